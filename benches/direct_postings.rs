@@ -0,0 +1,146 @@
+// Copyright 2019 Zhizhesihai (Beijing) Technology Limited.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Looks up primary keys in a segment of 10M unique ids, with the terms held
+//! in memory by `DirectPostingsFormat` and read from the terms index by the
+//! default format, and prints the heap taken by the terms held in memory.
+
+#![feature(test)]
+
+extern crate rucene;
+extern crate test;
+
+use rucene::core::codec::{
+    CodecEnum, DirectPostingsFormat, Lucene62Codec, PerFieldDocValuesFormat,
+    PerFieldPostingsFormat, PostingsFormatEnum, DEFAULT_MAX_RAM_BYTES_PER_FIELD,
+};
+use rucene::core::doc::{Store, StringField};
+use rucene::core::index::index_writer_config::IndexWriterConfig;
+use rucene::core::index::merge_policy::TieredMergePolicy;
+use rucene::core::index::merge_scheduler::SerialMergeScheduler;
+use rucene::core::index::{IndexReader, IndexWriter, LeafReader, StandardDirectoryReader};
+use rucene::core::index::{TermIterator, Terms};
+use rucene::core::search::{DocIterator, NO_MORE_DOCS};
+use rucene::core::store::RAMDirectory;
+
+use std::sync::Arc;
+use test::Bencher;
+
+type Reader =
+    StandardDirectoryReader<RAMDirectory, CodecEnum, SerialMergeScheduler, TieredMergePolicy>;
+
+const KEYS: u64 = 10_000_000;
+const LOOKUPS: u64 = 1000;
+
+// ids of 10 bytes, not in index order
+fn id(i: u64) -> String {
+    format!("{:010}", i.wrapping_mul(7919) % 10_000_000_000)
+}
+
+// half of the looked up keys are missing
+fn lookups() -> Vec<(String, bool)> {
+    let mut state = 1_442_695_040_888_963_407u64;
+    (0..LOOKUPS)
+        .map(|_| {
+            state = state
+                .wrapping_mul(6_364_136_223_846_793_005)
+                .wrapping_add(1_442_695_040_888_963_407);
+            let i = (state >> 33) % (KEYS * 2);
+            (id(i), i < KEYS)
+        })
+        .collect()
+}
+
+fn open_reader(direct: bool) -> Arc<Reader> {
+    let mut postings_format = PerFieldPostingsFormat::default();
+    if direct {
+        postings_format.set_field_format(
+            "id",
+            PostingsFormatEnum::Direct(DirectPostingsFormat::default()),
+        );
+    }
+    let codec =
+        Lucene62Codec::with_per_field_formats(postings_format, PerFieldDocValuesFormat::default());
+    let config = IndexWriterConfig::new(
+        Arc::new(CodecEnum::Lucene62(codec)),
+        SerialMergeScheduler::default(),
+        TieredMergePolicy::default(),
+    );
+    let dir = Arc::new(RAMDirectory::new());
+    let writer = IndexWriter::new(Arc::clone(&dir), Arc::new(config)).unwrap();
+    for i in 0..KEYS {
+        writer
+            .add_document(vec![StringField::new("id", &id(i), Store::No)])
+            .unwrap();
+    }
+    writer.force_merge(1, true).unwrap();
+    writer.commit().unwrap();
+    writer.close().unwrap();
+    Arc::new(Reader::open(dir).unwrap())
+}
+
+// the doc of each key, or -1 if it is missing
+fn lookup(reader: &Reader, keys: &[(String, bool)]) -> Vec<i32> {
+    let leaves = reader.leaves();
+    let terms = leaves[0].reader.terms("id").unwrap().unwrap();
+    let mut iter = terms.iterator().unwrap();
+    keys.iter()
+        .map(|(key, _)| {
+            if !iter.seek_exact(key.as_bytes()).unwrap() {
+                return -1;
+            }
+            let mut postings = iter.postings().unwrap();
+            let doc = postings.next_doc().unwrap();
+            assert_eq!(postings.next_doc().unwrap(), NO_MORE_DOCS);
+            doc
+        })
+        .collect()
+}
+
+fn bench_lookup(b: &mut Bencher, direct: bool) {
+    let reader = open_reader(direct);
+    let leaves = reader.leaves();
+    assert_eq!(leaves.len(), 1);
+    let terms = leaves[0].reader.terms("id").unwrap().unwrap();
+    match terms.direct_terms() {
+        Some(direct_terms) => {
+            assert!(direct);
+            assert_eq!(direct_terms.len() as u64, KEYS);
+            eprintln!(
+                "terms held in memory: {} bytes, {} per key, budget {} bytes",
+                direct_terms.ram_bytes_used(),
+                direct_terms.ram_bytes_used() as u64 / KEYS,
+                DEFAULT_MAX_RAM_BYTES_PER_FIELD
+            );
+        }
+        None => assert!(!direct, "the terms are over the default budget"),
+    }
+
+    let keys = lookups();
+    let docs = lookup(&reader, &keys);
+    for ((_, found), doc) in keys.iter().zip(&docs) {
+        assert_eq!(*found, *doc >= 0);
+    }
+
+    b.iter(|| test::black_box(lookup(&reader, &keys)));
+}
+
+#[bench]
+fn bench_lookup_terms_index(b: &mut Bencher) {
+    bench_lookup(b, false);
+}
+
+#[bench]
+fn bench_lookup_direct_terms(b: &mut Bencher) {
+    bench_lookup(b, true);
+}
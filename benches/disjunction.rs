@@ -0,0 +1,177 @@
+// Copyright 2019 Zhizhesihai (Beijing) Technology Limited.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Iterates the union of 50 postings lists over 1M docs with the
+//! `DisiPriorityQueue` of the disjunction scorers and with a `BinaryHeap`,
+//! and searches the top 10 hits of a 50 terms disjunction.
+
+#![feature(test)]
+
+extern crate rucene;
+extern crate test;
+
+use rucene::core::codec::CodecEnum;
+use rucene::core::doc::{Field, FieldType};
+use rucene::core::index::index_writer_config::IndexWriterConfig;
+use rucene::core::index::merge_policy::TieredMergePolicy;
+use rucene::core::index::merge_scheduler::SerialMergeScheduler;
+use rucene::core::index::{IndexOptions, IndexWriter, StandardDirectoryReader, Term};
+use rucene::core::search::boolean_query::BooleanQuery;
+use rucene::core::search::collector::TopDocsCollector;
+use rucene::core::search::disi::{DisiPriorityQueue, DisiWrapper};
+use rucene::core::search::searcher::{DefaultIndexSearcher, IndexSearcher};
+use rucene::core::search::term_query::TermQuery;
+use rucene::core::search::{DocIterator, Query, NO_MORE_DOCS};
+use rucene::core::store::RAMDirectory;
+use rucene::core::util::doc_id_set::IntArrayDocIterator;
+use rucene::core::util::{DocId, VariantValue};
+
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::sync::Arc;
+use test::Bencher;
+
+type Reader =
+    StandardDirectoryReader<RAMDirectory, CodecEnum, SerialMergeScheduler, TieredMergePolicy>;
+
+const DOCS: u32 = 1_000_000;
+const TERMS: u32 = 50;
+
+// doc `i` has the term `t{j}` if a hash of `(i, j)` falls under a threshold
+// that makes the first terms far more frequent than the last ones
+fn has_term(doc: u32, term: u32) -> bool {
+    let hash = (u64::from(doc) << 8 | u64::from(term))
+        .wrapping_mul(6_364_136_223_846_793_005)
+        .wrapping_add(1_442_695_040_888_963_407);
+    (hash >> 40) % u64::from(2 * (term + 1)) == 0
+}
+
+fn postings() -> Vec<Arc<Vec<DocId>>> {
+    (0..TERMS)
+        .map(|term| {
+            let mut docs: Vec<DocId> = (0..DOCS)
+                .filter(|&doc| has_term(doc, term))
+                .map(|doc| doc as DocId)
+                .collect();
+            docs.push(NO_MORE_DOCS);
+            Arc::new(docs)
+        })
+        .collect()
+}
+
+fn iterators(postings: &[Arc<Vec<DocId>>]) -> Vec<IntArrayDocIterator> {
+    postings
+        .iter()
+        .map(|docs| IntArrayDocIterator::new(Arc::clone(docs), docs.len() - 1))
+        .collect()
+}
+
+// returns the number of docs and of postings of the union
+fn disi_union(postings: &[Arc<Vec<DocId>>]) -> (usize, usize) {
+    let mut queue = DisiPriorityQueue::new(iterators(postings));
+    let (mut docs, mut matches) = (0, 0);
+    while queue.peek().doc() < 0 {
+        queue.peek_mut().next_doc().unwrap();
+    }
+    loop {
+        let doc = queue.peek().doc();
+        if doc == NO_MORE_DOCS {
+            break;
+        }
+        docs += 1;
+        let mut w: &DisiWrapper<_> = queue.top_list();
+        matches += 1;
+        while let Some(next) = w.next_scorer() {
+            matches += 1;
+            w = next;
+        }
+        while queue.peek().doc() == doc {
+            queue.peek_mut().next_doc().unwrap();
+        }
+    }
+    (docs, matches)
+}
+
+fn binary_heap_union(postings: &[Arc<Vec<DocId>>]) -> (usize, usize) {
+    let mut iterators = iterators(postings);
+    let mut heap = BinaryHeap::with_capacity(iterators.len());
+    for (i, iterator) in iterators.iter_mut().enumerate() {
+        heap.push(Reverse((iterator.next().unwrap(), i)));
+    }
+    let (mut docs, mut matches) = (0, 0);
+    let mut last = -1;
+    while let Some(Reverse((doc, i))) = heap.pop() {
+        if doc == NO_MORE_DOCS {
+            break;
+        }
+        if doc != last {
+            docs += 1;
+            last = doc;
+        }
+        matches += 1;
+        heap.push(Reverse((iterators[i].next().unwrap(), i)));
+    }
+    (docs, matches)
+}
+
+#[bench]
+fn bench_union_disi_priority_queue(b: &mut Bencher) {
+    let postings = postings();
+    assert_eq!(disi_union(&postings), binary_heap_union(&postings));
+    b.iter(|| test::black_box(disi_union(&postings)));
+}
+
+#[bench]
+fn bench_union_binary_heap(b: &mut Bencher) {
+    let postings = postings();
+    b.iter(|| test::black_box(binary_heap_union(&postings)));
+}
+
+#[bench]
+fn bench_disjunction_search(b: &mut Bencher) {
+    let dir = Arc::new(RAMDirectory::new());
+    let writer =
+        IndexWriter::new(Arc::clone(&dir), Arc::new(IndexWriterConfig::default())).unwrap();
+    let mut field_type = FieldType::default();
+    field_type.index_options = IndexOptions::DocsAndFreqs;
+    for doc in 0..DOCS {
+        let words: Vec<String> = (0..TERMS)
+            .filter(|&term| has_term(doc, term))
+            .map(|term| format!("t{}", term))
+            .collect();
+        let body = Field::new(
+            "body".to_string(),
+            field_type.clone(),
+            Some(VariantValue::VString(words.join(" "))),
+            None,
+        );
+        writer.add_document(vec![body]).unwrap();
+    }
+    writer.commit().unwrap();
+    writer.close().unwrap();
+    let searcher = DefaultIndexSearcher::new(Arc::new(Reader::open(dir).unwrap()));
+
+    let shoulds = (0..TERMS)
+        .map(|term| {
+            let term = Term::new("body".to_string(), format!("t{}", term).into_bytes());
+            Box::new(TermQuery::new(term, 1.0, None)) as Box<dyn Query<CodecEnum>>
+        })
+        .collect();
+    let query = BooleanQuery::build(vec![], shoulds, vec![]).unwrap();
+
+    b.iter(|| {
+        let mut collector = TopDocsCollector::new(10);
+        searcher.search(query.as_ref(), &mut collector).unwrap();
+        test::black_box(collector.top_docs())
+    });
+}
@@ -0,0 +1,148 @@
+// Copyright 2019 Zhizhesihai (Beijing) Technology Limited.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Searches a 20 clauses boolean query over 10 segments with and without a
+//! `ScorerArena`, and prints the allocations of a query for both.
+
+#![feature(test)]
+
+extern crate rucene;
+extern crate test;
+
+use rucene::core::codec::CodecEnum;
+use rucene::core::doc::{Field, FieldType};
+use rucene::core::index::index_writer_config::IndexWriterConfig;
+use rucene::core::index::merge_policy::TieredMergePolicy;
+use rucene::core::index::merge_scheduler::SerialMergeScheduler;
+use rucene::core::index::{IndexOptions, IndexReader, IndexWriter, StandardDirectoryReader, Term};
+use rucene::core::search::boolean_query::BooleanQuery;
+use rucene::core::search::collector::TopDocsCollector;
+use rucene::core::search::scorer_arena::ScorerArena;
+use rucene::core::search::searcher::{DefaultIndexSearcher, IndexSearcher};
+use rucene::core::search::term_query::TermQuery;
+use rucene::core::search::Query;
+use rucene::core::store::RAMDirectory;
+use rucene::core::util::VariantValue;
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use test::Bencher;
+
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+type Reader =
+    StandardDirectoryReader<RAMDirectory, CodecEnum, SerialMergeScheduler, TieredMergePolicy>;
+
+const SEGMENTS: usize = 10;
+const DOCS_PER_SEGMENT: usize = 1000;
+const CLAUSES: usize = 20;
+
+// doc `i` has the word `w{j}` if `j + 2` divides `i`
+fn open_reader() -> Arc<Reader> {
+    let dir = Arc::new(RAMDirectory::new());
+    let mut conf = IndexWriterConfig::default();
+    // keep the segments apart
+    conf.merge_policy.set_segments_per_tier(50.0).unwrap();
+    let writer = IndexWriter::new(Arc::clone(&dir), Arc::new(conf)).unwrap();
+    let mut field_type = FieldType::default();
+    field_type.index_options = IndexOptions::DocsAndFreqs;
+    for segment in 0..SEGMENTS {
+        for i in 0..DOCS_PER_SEGMENT {
+            let doc = segment * DOCS_PER_SEGMENT + i;
+            let words: Vec<String> = (0..CLAUSES)
+                .filter(|j| doc % (j + 2) == 0)
+                .map(|j| format!("w{}", j))
+                .collect();
+            let body = Field::new(
+                "body".to_string(),
+                field_type.clone(),
+                Some(VariantValue::VString(words.join(" "))),
+                None,
+            );
+            writer.add_document(vec![body]).unwrap();
+        }
+        writer.commit().unwrap();
+    }
+    writer.close().unwrap();
+    Arc::new(Reader::open(dir).unwrap())
+}
+
+fn query() -> Box<dyn Query<CodecEnum>> {
+    let shoulds = (0..CLAUSES)
+        .map(|j| {
+            let term = Term::new("body".to_string(), format!("w{}", j).into_bytes());
+            Box::new(TermQuery::new(term, 1.0, None)) as Box<dyn Query<CodecEnum>>
+        })
+        .collect();
+    BooleanQuery::build(vec![], shoulds, vec![]).unwrap()
+}
+
+fn search<S: IndexSearcher<CodecEnum>>(searcher: &S, query: &dyn Query<CodecEnum>) {
+    let mut collector = TopDocsCollector::new(10);
+    searcher.search(query, &mut collector).unwrap();
+    test::black_box(collector.top_docs());
+}
+
+fn bench_search(b: &mut Bencher, arena: Option<Arc<ScorerArena>>) {
+    let reader = open_reader();
+    assert_eq!(reader.leaves().len(), SEGMENTS);
+    let mut searcher = DefaultIndexSearcher::new(reader);
+    let with_arena = arena.is_some();
+    if let Some(arena) = arena {
+        searcher.set_scorer_arena(arena);
+    }
+    let query = query();
+    // warm the term contexts and the arena up
+    search(&searcher, query.as_ref());
+
+    let queries = 100;
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    for _ in 0..queries {
+        search(&searcher, query.as_ref());
+    }
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed) - before;
+    eprintln!(
+        "allocations per query {} scorer arena: {}",
+        if with_arena { "with" } else { "without" },
+        allocations / queries
+    );
+
+    b.iter(|| search(&searcher, query.as_ref()));
+}
+
+#[bench]
+fn bench_boolean_without_arena(b: &mut Bencher) {
+    bench_search(b, None);
+}
+
+#[bench]
+fn bench_boolean_with_arena(b: &mut Bencher) {
+    bench_search(b, Some(Arc::new(ScorerArena::new())));
+}
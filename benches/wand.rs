@@ -0,0 +1,145 @@
+// Copyright 2019 Zhizhesihai (Beijing) Technology Limited.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Searches the top 10 hits of a disjunction over 1M docs, counting all the
+//! hits and skipping the ones that can't compete, and prints the hits scored
+//! by both.
+
+#![feature(test)]
+
+extern crate rucene;
+extern crate test;
+
+use rucene::core::codec::CodecEnum;
+use rucene::core::doc::{Field, FieldType};
+use rucene::core::index::index_writer_config::IndexWriterConfig;
+use rucene::core::index::merge_policy::TieredMergePolicy;
+use rucene::core::index::merge_scheduler::SerialMergeScheduler;
+use rucene::core::index::{IndexOptions, IndexWriter, StandardDirectoryReader, Term};
+use rucene::core::search::boolean_query::BooleanQuery;
+use rucene::core::search::collector::TopDocsCollector;
+use rucene::core::search::searcher::{DefaultIndexSearcher, IndexSearcher};
+use rucene::core::search::term_query::TermQuery;
+use rucene::core::search::top_docs::TopDocs;
+use rucene::core::search::Query;
+use rucene::core::store::RAMDirectory;
+use rucene::core::util::VariantValue;
+
+use std::sync::Arc;
+use test::Bencher;
+
+type Reader =
+    StandardDirectoryReader<RAMDirectory, CodecEnum, SerialMergeScheduler, TieredMergePolicy>;
+
+const DOCS: u64 = 1_000_000;
+const WORDS: u64 = 1000;
+const TOP_N: usize = 10;
+
+// the words of a doc, the first words are far more frequent than the last ones
+fn words(doc: u64) -> String {
+    let mut state = doc.wrapping_mul(6_364_136_223_846_793_005) ^ 1_442_695_040_888_963_407;
+    let mut next = || {
+        state = state
+            .wrapping_mul(6_364_136_223_846_793_005)
+            .wrapping_add(1_442_695_040_888_963_407);
+        state >> 33
+    };
+    let len = 5 + next() % 20;
+    (0..len)
+        .map(|_| {
+            let r = next() % WORDS;
+            format!("w{}", r * r / WORDS)
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn open_reader() -> Arc<Reader> {
+    let dir = Arc::new(RAMDirectory::new());
+    let writer =
+        IndexWriter::new(Arc::clone(&dir), Arc::new(IndexWriterConfig::default())).unwrap();
+    let mut field_type = FieldType::default();
+    field_type.index_options = IndexOptions::DocsAndFreqs;
+    for doc in 0..DOCS {
+        let body = Field::new(
+            "body".to_string(),
+            field_type.clone(),
+            Some(VariantValue::VString(words(doc))),
+            None,
+        );
+        writer.add_document(vec![body]).unwrap();
+    }
+    writer.commit().unwrap();
+    writer.close().unwrap();
+    Arc::new(Reader::open(dir).unwrap())
+}
+
+// frequent and rare words
+fn query() -> Box<dyn Query<CodecEnum>> {
+    let shoulds = [0, 3, 50, 400, 900]
+        .iter()
+        .map(|w| {
+            let term = Term::new("body".to_string(), format!("w{}", w).into_bytes());
+            Box::new(TermQuery::new(term, 1.0, None)) as Box<dyn Query<CodecEnum>>
+        })
+        .collect();
+    BooleanQuery::build(vec![], shoulds, vec![]).unwrap()
+}
+
+fn search<S: IndexSearcher<CodecEnum>>(
+    searcher: &S,
+    query: &dyn Query<CodecEnum>,
+    skip: bool,
+) -> TopDocs {
+    let mut collector = TopDocsCollector::new(TOP_N);
+    if skip {
+        collector = collector.with_total_hits_threshold(TOP_N);
+    }
+    searcher.search(query, &mut collector).unwrap();
+    collector.top_docs()
+}
+
+fn hits(top_docs: &TopDocs) -> Vec<(i32, f32)> {
+    top_docs
+        .score_docs()
+        .iter()
+        .map(|d| (d.doc_id(), d.score()))
+        .collect()
+}
+
+fn bench_search(b: &mut Bencher, skip: bool) {
+    let searcher = DefaultIndexSearcher::new(open_reader());
+    let query = query();
+
+    let exhaustive = search(&searcher, query.as_ref(), false);
+    let top_docs = search(&searcher, query.as_ref(), skip);
+    assert_eq!(hits(&top_docs), hits(&exhaustive));
+    eprintln!(
+        "hits collected {} skipping: {} of {}",
+        if skip { "with" } else { "without" },
+        top_docs.total_hits(),
+        exhaustive.total_hits()
+    );
+
+    b.iter(|| test::black_box(search(&searcher, query.as_ref(), skip)));
+}
+
+#[bench]
+fn bench_disjunction_exhaustive(b: &mut Bencher) {
+    bench_search(b, false);
+}
+
+#[bench]
+fn bench_disjunction_skipping(b: &mut Bencher) {
+    bench_search(b, true);
+}
@@ -0,0 +1,688 @@
+// Copyright 2019 Zhizhesihai (Beijing) Technology Limited.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use core::analysis::keyword_tokenizer::{self, KeywordTokenizer};
+use core::analysis::standard_tokenizer::{StandardTokenizer, DEFAULT_MAX_TOKEN_LENGTH};
+use core::analysis::whitespace_tokenizer::WhitespaceTokenizer;
+use core::analysis::{
+    CharFilter, CharFilterChain, LowerCaseFilter, StopFilter, TokenStream, ENGLISH_STOP_WORDS,
+};
+
+use error::ErrorKind::IllegalArgument;
+use error::Result;
+
+use std::collections::{HashMap, HashSet};
+use std::io::Cursor;
+use std::sync::Arc;
+
+/// An `Analyzer` builds the `TokenStream`s the `IndexWriter` inverts the
+/// values of tokenized fields with.
+pub trait Analyzer: Send + Sync {
+    /// Returns the token stream of `value` indexed into `field`.
+    fn token_stream(&self, field: &str, value: &str) -> Result<Box<dyn TokenStream>>;
+
+    /// Position gap added between the values of a multi-valued field, so that
+    /// phrase queries do not match across values.
+    fn position_increment_gap(&self, _field: &str) -> i32 {
+        0
+    }
+
+    /// Offset gap added between the values of a multi-valued field.
+    fn offset_gap(&self, _field: &str) -> usize {
+        1
+    }
+}
+
+/// Splits the values at whitespaces, see `WhitespaceTokenizer`.
+#[derive(Debug, Default)]
+pub struct WhitespaceAnalyzer;
+
+impl Analyzer for WhitespaceAnalyzer {
+    fn token_stream(&self, _field: &str, value: &str) -> Result<Box<dyn TokenStream>> {
+        Ok(Box::new(WhitespaceTokenizer::new(Box::new(Cursor::new(
+            value.to_string().into_bytes(),
+        )))))
+    }
+}
+
+/// Indexes each value as a single term, see `KeywordTokenizer`.
+#[derive(Debug)]
+pub struct KeywordAnalyzer {
+    max_token_length: usize,
+}
+
+impl Default for KeywordAnalyzer {
+    fn default() -> Self {
+        KeywordAnalyzer {
+            max_token_length: keyword_tokenizer::DEFAULT_MAX_TOKEN_LENGTH,
+        }
+    }
+}
+
+impl KeywordAnalyzer {
+    pub fn new() -> KeywordAnalyzer {
+        KeywordAnalyzer::default()
+    }
+
+    /// Fails the values longer than `max_token_length` bytes.
+    pub fn set_max_token_length(&mut self, max_token_length: usize) {
+        self.max_token_length = max_token_length;
+    }
+}
+
+impl Analyzer for KeywordAnalyzer {
+    fn token_stream(&self, _field: &str, value: &str) -> Result<Box<dyn TokenStream>> {
+        Ok(Box::new(KeywordTokenizer::with_max_token_length(
+            value,
+            self.max_token_length,
+        )))
+    }
+}
+
+/// Splits the values at the word boundaries of `StandardTokenizer`, then
+/// lowercases the terms and removes the stop words, the English ones by
+/// default.
+#[derive(Debug)]
+pub struct StandardAnalyzer {
+    stop_words: Arc<HashSet<String>>,
+    max_token_length: usize,
+}
+
+impl Default for StandardAnalyzer {
+    fn default() -> Self {
+        StandardAnalyzer::with_stop_words(ENGLISH_STOP_WORDS.iter().map(|w| w.to_string()))
+    }
+}
+
+impl StandardAnalyzer {
+    pub fn new() -> StandardAnalyzer {
+        StandardAnalyzer::default()
+    }
+
+    /// Removes the lowercased `stop_words` rather than the English ones.
+    pub fn with_stop_words<I: IntoIterator<Item = String>>(stop_words: I) -> StandardAnalyzer {
+        StandardAnalyzer {
+            stop_words: Arc::new(stop_words.into_iter().collect()),
+            max_token_length: DEFAULT_MAX_TOKEN_LENGTH,
+        }
+    }
+
+    /// Splits the words longer than `max_token_length` chars.
+    pub fn set_max_token_length(&mut self, max_token_length: usize) {
+        self.max_token_length = max_token_length;
+    }
+}
+
+impl Analyzer for StandardAnalyzer {
+    fn token_stream(&self, _field: &str, value: &str) -> Result<Box<dyn TokenStream>> {
+        let tokenizer = StandardTokenizer::with_max_token_length(value, self.max_token_length);
+        let lowercased = LowerCaseFilter::new(Box::new(tokenizer));
+        Ok(Box::new(StopFilter::new(
+            Box::new(lowercased),
+            Arc::clone(&self.stop_words),
+        )))
+    }
+}
+
+type CharFilterFactory = Box<dyn Fn(&str) -> Box<dyn CharFilter> + Send + Sync>;
+type TokenFilterFactory = Box<dyn Fn(Box<dyn TokenStream>) -> Box<dyn TokenStream> + Send + Sync>;
+
+enum TokenizerFactory {
+    Text(Box<dyn Fn(&str) -> Box<dyn TokenStream> + Send + Sync>),
+    // a tokenizer reading the text of the char filters
+    CharFiltered(Box<dyn Fn(Box<dyn CharFilter>) -> Box<dyn TokenStream> + Send + Sync>),
+}
+
+/// An analyzer chaining a tokenizer and token filters, built with
+/// `CustomAnalyzer::builder()`. The tokenizer is created from the value with
+/// a closure such as `|text| Box::new(StandardTokenizer::new(text))`, then
+/// each filter wraps the stream of the previous one.
+///
+/// Char filters, such as `HTMLStripCharFilter`, may transform the value
+/// before its tokenization, the tokenizer being then created from their
+/// output with `char_filtered_tokenizer`, e.g.
+/// `|input| Box::new(StandardTokenizer::with_char_filter(input, 255))`, so
+/// that the offsets of the tokens point into the original value.
+pub struct CustomAnalyzer {
+    char_filters: Vec<CharFilterFactory>,
+    tokenizer: TokenizerFactory,
+    filters: Vec<TokenFilterFactory>,
+    position_increment_gap: i32,
+    offset_gap: usize,
+}
+
+impl CustomAnalyzer {
+    pub fn builder() -> CustomAnalyzerBuilder {
+        CustomAnalyzerBuilder {
+            char_filters: Vec::new(),
+            tokenizer: None,
+            filters: Vec::new(),
+            position_increment_gap: 0,
+            offset_gap: 1,
+        }
+    }
+}
+
+impl Analyzer for CustomAnalyzer {
+    fn token_stream(&self, _field: &str, value: &str) -> Result<Box<dyn TokenStream>> {
+        let mut stream = match self.tokenizer {
+            TokenizerFactory::Text(ref tokenizer) => tokenizer(value),
+            TokenizerFactory::CharFiltered(ref tokenizer) => {
+                let mut chain = CharFilterChain::new(value);
+                for char_filter in &self.char_filters {
+                    chain.add(|text| char_filter(text));
+                }
+                tokenizer(Box::new(chain))
+            }
+        };
+        for filter in &self.filters {
+            stream = filter(stream);
+        }
+        Ok(stream)
+    }
+
+    fn position_increment_gap(&self, _field: &str) -> i32 {
+        self.position_increment_gap
+    }
+
+    fn offset_gap(&self, _field: &str) -> usize {
+        self.offset_gap
+    }
+}
+
+pub struct CustomAnalyzerBuilder {
+    char_filters: Vec<CharFilterFactory>,
+    tokenizer: Option<TokenizerFactory>,
+    filters: Vec<TokenFilterFactory>,
+    position_increment_gap: i32,
+    offset_gap: usize,
+}
+
+impl CustomAnalyzerBuilder {
+    /// Splits the values with the tokenizers created by `tokenizer`.
+    pub fn tokenizer<F>(mut self, tokenizer: F) -> Self
+    where
+        F: Fn(&str) -> Box<dyn TokenStream> + Send + Sync + 'static,
+    {
+        self.tokenizer = Some(TokenizerFactory::Text(Box::new(tokenizer)));
+        self
+    }
+
+    /// Appends the char filter of the values created by `char_filter`, each
+    /// char filter reading the text of the previous one.
+    pub fn char_filter<F>(mut self, char_filter: F) -> Self
+    where
+        F: Fn(&str) -> Box<dyn CharFilter> + Send + Sync + 'static,
+    {
+        self.char_filters.push(Box::new(char_filter));
+        self
+    }
+
+    /// Splits the output of the char filters with the tokenizers created by
+    /// `tokenizer`, which correct the offsets of their tokens with it. The
+    /// `with_char_filter` constructors of `StandardTokenizer`,
+    /// `WhitespaceTokenizer` and `KeywordTokenizer` create such tokenizers.
+    pub fn char_filtered_tokenizer<F>(mut self, tokenizer: F) -> Self
+    where
+        F: Fn(Box<dyn CharFilter>) -> Box<dyn TokenStream> + Send + Sync + 'static,
+    {
+        self.tokenizer = Some(TokenizerFactory::CharFiltered(Box::new(tokenizer)));
+        self
+    }
+
+    /// Appends the filter wrapping its input token stream created by
+    /// `filter`, the filters are applied in the order they were added.
+    pub fn filter<F>(mut self, filter: F) -> Self
+    where
+        F: Fn(Box<dyn TokenStream>) -> Box<dyn TokenStream> + Send + Sync + 'static,
+    {
+        self.filters.push(Box::new(filter));
+        self
+    }
+
+    pub fn position_increment_gap(mut self, gap: i32) -> Self {
+        self.position_increment_gap = gap;
+        self
+    }
+
+    pub fn offset_gap(mut self, gap: usize) -> Self {
+        self.offset_gap = gap;
+        self
+    }
+
+    pub fn build(self) -> Result<CustomAnalyzer> {
+        let tokenizer = match self.tokenizer {
+            Some(tokenizer) => tokenizer,
+            None => bail!(IllegalArgument(
+                "a custom analyzer needs a tokenizer".into()
+            )),
+        };
+        if let TokenizerFactory::Text(_) = tokenizer {
+            if !self.char_filters.is_empty() {
+                bail!(IllegalArgument(
+                    "char filters need a char_filtered_tokenizer".into()
+                ));
+            }
+        }
+        Ok(CustomAnalyzer {
+            char_filters: self.char_filters,
+            tokenizer,
+            filters: self.filters,
+            position_increment_gap: self.position_increment_gap,
+            offset_gap: self.offset_gap,
+        })
+    }
+}
+
+/// Analyzes each field with the analyzer registered for it, falling back to
+/// a default analyzer for the other fields.
+///
+/// Both the `IndexWriter` and the `QueryStringQueryBuilder` pick the analyzer
+/// by field name, so the same wrapper should be given to both.
+pub struct PerFieldAnalyzerWrapper {
+    default: Arc<dyn Analyzer>,
+    fields: HashMap<String, Arc<dyn Analyzer>>,
+    position_increment_gap: Option<i32>,
+}
+
+impl PerFieldAnalyzerWrapper {
+    pub fn new(default: Arc<dyn Analyzer>) -> PerFieldAnalyzerWrapper {
+        Self::with_analyzers(default, HashMap::new())
+    }
+
+    pub fn with_analyzers(
+        default: Arc<dyn Analyzer>,
+        fields: HashMap<String, Arc<dyn Analyzer>>,
+    ) -> PerFieldAnalyzerWrapper {
+        PerFieldAnalyzerWrapper {
+            default,
+            fields,
+            position_increment_gap: None,
+        }
+    }
+
+    pub fn add_field(&mut self, field: &str, analyzer: Arc<dyn Analyzer>) {
+        self.fields.insert(field.to_string(), analyzer);
+    }
+
+    /// Adds `gap` positions between the values of every multi-valued field
+    /// rather than the gap of their analyzer.
+    pub fn set_position_increment_gap(&mut self, gap: i32) {
+        self.position_increment_gap = Some(gap);
+    }
+
+    fn analyzer(&self, field: &str) -> &dyn Analyzer {
+        self.fields.get(field).unwrap_or(&self.default).as_ref()
+    }
+}
+
+impl Analyzer for PerFieldAnalyzerWrapper {
+    fn token_stream(&self, field: &str, value: &str) -> Result<Box<dyn TokenStream>> {
+        self.analyzer(field).token_stream(field, value)
+    }
+
+    fn position_increment_gap(&self, field: &str) -> i32 {
+        match self.position_increment_gap {
+            Some(gap) => gap,
+            None => self.analyzer(field).position_increment_gap(field),
+        }
+    }
+
+    fn offset_gap(&self, field: &str) -> usize {
+        self.analyzer(field).offset_gap(field)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::analysis::char_filter::tests::HTML;
+    use core::analysis::{
+        ASCIIFoldingFilter, EdgeNGramTokenFilter, HTMLStripCharFilter, MappingCharFilter,
+        NormalizeCharMap,
+    };
+    use core::doc::{Store, TextField};
+    use core::index::index_writer_config::IndexWriterConfig;
+    use core::index::tests::open_test_reader_with;
+    use core::index::Term;
+    use core::search::collector::TopDocsCollector;
+    use core::search::searcher::{DefaultIndexSearcher, IndexSearcher};
+    use core::search::term_query::TermQuery;
+    use core::util::DocId;
+
+    struct GapAnalyzer;
+
+    impl Analyzer for GapAnalyzer {
+        fn token_stream(&self, field: &str, value: &str) -> Result<Box<dyn TokenStream>> {
+            WhitespaceAnalyzer.token_stream(field, value)
+        }
+
+        fn position_increment_gap(&self, _field: &str) -> i32 {
+            100
+        }
+    }
+
+    fn tokens(analyzer: &dyn Analyzer, value: &str) -> Vec<(String, usize, usize)> {
+        let mut stream = analyzer.token_stream("body", value).unwrap();
+        stream.reset().unwrap();
+        let mut tokens = vec![];
+        while stream.increment_token().unwrap() {
+            assert_eq!(stream.position_attribute_mut().get_position_increment(), 1);
+            let term = stream.term_bytes_attribute().get_bytes_ref();
+            let offsets = stream.offset_attribute();
+            tokens.push((
+                String::from_utf8(term.bytes().to_vec()).unwrap(),
+                offsets.start_offset(),
+                offsets.end_offset(),
+            ));
+        }
+        stream.end().unwrap();
+        assert_eq!(
+            stream.offset_attribute().end_offset(),
+            value.chars().count()
+        );
+        tokens
+    }
+
+    #[test]
+    fn test_whitespace_analyzer() {
+        let analyzer = WhitespaceAnalyzer;
+        assert_eq!(
+            tokens(&analyzer, " quick  brown\tfox "),
+            vec![
+                ("quick".to_string(), 1, 6),
+                ("brown".to_string(), 8, 13),
+                ("fox".to_string(), 14, 17),
+            ]
+        );
+        assert_eq!(
+            tokens(&analyzer, "quick fox"),
+            vec![("quick".to_string(), 0, 5), ("fox".to_string(), 6, 9)]
+        );
+        assert!(tokens(&analyzer, "   ").is_empty());
+
+        // tokens spanning the io buffer
+        let long: Vec<String> = (0..2000).map(|i| format!("t{}", i)).collect();
+        let value = long.join(" ");
+        let long_tokens = tokens(&analyzer, &value);
+        assert_eq!(long_tokens.len(), long.len());
+        let mut start = 0;
+        for (term, (token, token_start, token_end)) in long.iter().zip(long_tokens) {
+            assert_eq!(&token, term);
+            assert_eq!((token_start, token_end), (start, start + term.len()));
+            start += term.len() + 1;
+        }
+    }
+
+    // the terms with their positions and offsets
+    fn analyze(analyzer: &dyn Analyzer, value: &str) -> Vec<(String, i32, usize, usize)> {
+        let mut stream = analyzer.token_stream("body", value).unwrap();
+        stream.reset().unwrap();
+        let mut tokens = vec![];
+        let mut position = -1;
+        while stream.increment_token().unwrap() {
+            position += stream.position_attribute_mut().get_position_increment() as i32;
+            let term = stream.term_bytes_attribute().get_bytes_ref();
+            let offsets = stream.offset_attribute();
+            tokens.push((
+                String::from_utf8(term.bytes().to_vec()).unwrap(),
+                position,
+                offsets.start_offset(),
+                offsets.end_offset(),
+            ));
+        }
+        stream.end().unwrap();
+        tokens
+    }
+
+    fn token(term: &str, position: i32, start: usize, end: usize) -> (String, i32, usize, usize) {
+        (term.to_string(), position, start, end)
+    }
+
+    #[test]
+    fn test_standard_analyzer() {
+        let analyzer = StandardAnalyzer::new();
+        assert_eq!(
+            analyze(&analyzer, "The Quick FOX and the lazy Dog's İstanbul"),
+            vec![
+                token("quick", 1, 4, 9),
+                token("fox", 2, 10, 13),
+                token("lazy", 5, 22, 26),
+                token("dog's", 6, 27, 32),
+                token("i\u{307}stanbul", 7, 33, 41),
+            ]
+        );
+
+        // the offsets count the chars of multi-byte text
+        assert_eq!(
+            analyze(&analyzer, "Größe: 東京 ÉTÉ"),
+            vec![
+                token("größe", 0, 0, 5),
+                token("東", 1, 7, 8),
+                token("京", 2, 8, 9),
+                token("été", 3, 10, 13),
+            ]
+        );
+
+        // the trailing stop words move the end of the stream
+        let mut stream = analyzer.token_stream("body", "fox in the").unwrap();
+        stream.reset().unwrap();
+        assert!(stream.increment_token().unwrap());
+        assert!(!stream.increment_token().unwrap());
+        stream.end().unwrap();
+        assert_eq!(stream.position_attribute_mut().get_position_increment(), 2);
+        assert_eq!(stream.offset_attribute().end_offset(), 10);
+
+        let analyzer = StandardAnalyzer::with_stop_words(vec!["fox".to_string()]);
+        assert_eq!(analyze(&analyzer, "the fox"), vec![token("the", 0, 0, 3)]);
+    }
+
+    #[test]
+    fn test_per_field_analyzer() {
+        let mut analyzer = PerFieldAnalyzerWrapper::new(Arc::new(WhitespaceAnalyzer));
+        analyzer.add_field("title", Arc::new(GapAnalyzer));
+        analyzer.add_field("id", Arc::new(KeywordAnalyzer::new()));
+        assert_eq!(analyzer.position_increment_gap("title"), 100);
+        assert_eq!(analyzer.position_increment_gap("body"), 0);
+        assert_eq!(analyzer.offset_gap("title"), 1);
+        assert_eq!(tokens(&analyzer, "a b").len(), 2);
+        let mut stream = analyzer.token_stream("id", "a b").unwrap();
+        stream.reset().unwrap();
+        assert!(stream.increment_token().unwrap());
+        assert!(!stream.increment_token().unwrap());
+
+        analyzer.set_position_increment_gap(10);
+        assert_eq!(analyzer.position_increment_gap("title"), 10);
+        assert_eq!(analyzer.position_increment_gap("body"), 10);
+    }
+
+    #[test]
+    fn test_keyword_analyzer() {
+        let mut analyzer = KeywordAnalyzer::new();
+        assert_eq!(
+            analyze(&analyzer, "New York-1"),
+            vec![token("New York-1", 0, 0, 10)]
+        );
+        assert!(analyze(&analyzer, "").is_empty());
+
+        analyzer.set_max_token_length(4);
+        let mut stream = analyzer.token_stream("body", "New York").unwrap();
+        stream.reset().unwrap();
+        assert!(stream.increment_token().is_err());
+    }
+
+    fn autocomplete_analyzer() -> CustomAnalyzer {
+        CustomAnalyzer::builder()
+            .tokenizer(|text| Box::new(StandardTokenizer::new(text)))
+            .filter(|input| Box::new(LowerCaseFilter::new(input)))
+            .filter(|input| Box::new(ASCIIFoldingFilter::new(input)))
+            .filter(|input| Box::new(EdgeNGramTokenFilter::new(input, 1, 10, true)))
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_custom_analyzer() {
+        assert!(CustomAnalyzer::builder().build().is_err());
+
+        let analyzer = autocomplete_analyzer();
+        assert_eq!(
+            analyze(&analyzer, "Crème"),
+            vec![
+                token("c", 0, 0, 5),
+                token("cr", 0, 0, 5),
+                token("cre", 0, 0, 5),
+                token("crem", 0, 0, 5),
+                token("creme", 0, 0, 5),
+            ]
+        );
+
+        let mut conf = IndexWriterConfig::default();
+        conf.analyzer = Arc::new(analyzer);
+        let docs = ["Crème brûlée", "Cake"]
+            .iter()
+            .map(|title| vec![TextField::new("title", title, Store::No)])
+            .collect();
+        let searcher = DefaultIndexSearcher::new(open_test_reader_with(conf, vec![docs]));
+        let search = |term: &str| -> Vec<DocId> {
+            let query = TermQuery::new(
+                Term::new("title".to_string(), term.as_bytes().to_vec()),
+                1.0,
+                None,
+            );
+            let mut collector = TopDocsCollector::new(10);
+            searcher.search(&query, &mut collector).unwrap();
+            let mut docs: Vec<DocId> = collector
+                .top_docs()
+                .score_docs()
+                .iter()
+                .map(|d| d.doc_id())
+                .collect();
+            docs.sort();
+            docs
+        };
+        assert_eq!(search("cre"), vec![0]);
+        assert_eq!(search("brul"), vec![0]);
+        assert_eq!(search("c"), vec![0, 1]);
+        assert!(search("crè").is_empty());
+        assert!(search("cremes").is_empty());
+    }
+
+    // surrounds the chars from `start` to `end` of `text` with `<em>` tags
+    fn highlight(text: &str, start: usize, end: usize) -> String {
+        let chars: Vec<char> = text.chars().collect();
+        format!(
+            "{}<em>{}</em>{}",
+            chars[..start].iter().collect::<String>(),
+            chars[start..end].iter().collect::<String>(),
+            chars[end..].iter().collect::<String>()
+        )
+    }
+
+    #[test]
+    fn test_char_filters() {
+        assert!(CustomAnalyzer::builder()
+            .char_filter(|text| Box::new(HTMLStripCharFilter::new(text)))
+            .tokenizer(|text| Box::new(StandardTokenizer::new(text)))
+            .build()
+            .is_err());
+
+        let analyzer = CustomAnalyzer::builder()
+            .char_filter(|text| Box::new(HTMLStripCharFilter::new(text)))
+            .char_filtered_tokenizer(|input| {
+                Box::new(StandardTokenizer::with_char_filter(
+                    input,
+                    DEFAULT_MAX_TOKEN_LENGTH,
+                ))
+            })
+            .filter(|input| Box::new(LowerCaseFilter::new(input)))
+            .build()
+            .unwrap();
+        let html_tokens = tokens(&analyzer, HTML);
+        let expected = vec![
+            ("café".to_string(), 3, 14),
+            ("au".to_string(), 18, 20),
+            ("lait".to_string(), 25, 29),
+            ("crème".to_string(), 41, 51),
+            ("done".to_string(), 104, 108),
+        ];
+        assert_eq!(html_tokens, expected);
+
+        // the highlights cover the entities but not the tags around the terms
+        let highlights: Vec<String> = html_tokens
+            .iter()
+            .map(|&(_, start, end)| highlight(HTML, start, end))
+            .collect();
+        assert_eq!(
+            highlights[0],
+            HTML.replacen("Caf&eacute;", "<em>Caf&eacute;</em>", 1)
+        );
+        assert_eq!(
+            highlights[1],
+            HTML.replacen("<b>au</b>", "<b><em>au</em></b>", 1)
+        );
+        assert_eq!(
+            highlights[3],
+            HTML.replacen("cr&#232;me", "<em>cr&#232;me</em>", 1)
+        );
+
+        // the char filters are chained
+        let mut builder = NormalizeCharMap::builder();
+        builder.add("ß", "ss").unwrap();
+        let map = Arc::new(builder.build());
+        let analyzer = CustomAnalyzer::builder()
+            .char_filter(|text| Box::new(HTMLStripCharFilter::new(text)))
+            .char_filter(move |text| Box::new(MappingCharFilter::new(text, &map)))
+            .char_filtered_tokenizer(|input| {
+                Box::new(StandardTokenizer::with_char_filter(
+                    input,
+                    DEFAULT_MAX_TOKEN_LENGTH,
+                ))
+            })
+            .build()
+            .unwrap();
+        assert_eq!(
+            tokens(&analyzer, "<p>Stra&szlig;e</p>"),
+            vec![("Strasse".to_string(), 3, 15)]
+        );
+
+        // the whitespace and keyword tokenizers also read char filters
+        let analyzer = CustomAnalyzer::builder()
+            .char_filter(|text| Box::new(HTMLStripCharFilter::new(text)))
+            .char_filtered_tokenizer(|input| Box::new(WhitespaceTokenizer::with_char_filter(input)))
+            .filter(|input| Box::new(LowerCaseFilter::new(input)))
+            .build()
+            .unwrap();
+        // the entity `&amp;` is a word of its own for the whitespace tokenizer
+        let words: Vec<(String, usize, usize)> = tokens(&analyzer, HTML)
+            .into_iter()
+            .filter(|t| t.0 != "&")
+            .collect();
+        assert_eq!(words, expected);
+
+        let analyzer = CustomAnalyzer::builder()
+            .char_filter(|text| Box::new(HTMLStripCharFilter::new(text)))
+            .char_filtered_tokenizer(|input| {
+                Box::new(KeywordTokenizer::with_char_filter(
+                    input,
+                    keyword_tokenizer::DEFAULT_MAX_TOKEN_LENGTH,
+                ))
+            })
+            .build()
+            .unwrap();
+        assert_eq!(
+            tokens(&analyzer, "<b>New York</b>"),
+            vec![("New York".to_string(), 3, 11)]
+        );
+    }
+}
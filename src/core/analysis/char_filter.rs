@@ -0,0 +1,674 @@
+// Copyright 2019 Zhizhesihai (Beijing) Technology Limited.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use error::ErrorKind::IllegalArgument;
+use error::Result;
+
+use std::collections::{HashMap, HashSet};
+use std::fmt::Debug;
+
+/// Transforms a text before its tokenization, e.g. to strip its markup or to
+/// normalize some chars, keeping the offsets of the original text.
+///
+/// A tokenizer reading the filtered text, see
+/// `StandardTokenizer::with_char_filter`, corrects the offsets of its tokens
+/// so that they point into the original text, e.g. to highlight it.
+pub trait CharFilter: Debug {
+    /// The filtered text.
+    fn text(&self) -> &str;
+
+    /// The char offset in the original text of the char offset `current` of
+    /// the filtered text, e.g. the start of a token.
+    fn correct_offset(&self, current: usize) -> usize;
+
+    /// Same as `correct_offset` for the end of a token, which doesn't cover
+    /// the text removed right after it, such as a closing tag.
+    fn correct_end_offset(&self, current: usize) -> usize {
+        self.correct_offset(current)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Correction {
+    offset: usize,
+    diff: isize,
+    removal: bool,
+}
+
+/// The corrections of the offsets of the text of a char filter: from each
+/// corrected offset on, the offsets of the original text are moved by the
+/// cumulated difference of the lengths of the replaced texts and their
+/// replacements.
+#[derive(Debug, Default, Clone)]
+pub struct OffsetCorrections {
+    corrections: Vec<Correction>,
+}
+
+impl OffsetCorrections {
+    /// Moves the offsets from `offset` on by `diff`, `offset` being the end
+    /// of the replacement of some chars.
+    pub fn add_replacement(&mut self, offset: usize, diff: isize) {
+        self.add(offset, diff, false)
+    }
+
+    /// Moves the offsets from `offset` on by `diff`, some chars being removed
+    /// at `offset`. A token ending there doesn't cover them.
+    pub fn add_removal(&mut self, offset: usize, diff: isize) {
+        self.add(offset, diff, true)
+    }
+
+    fn add(&mut self, offset: usize, diff: isize, removal: bool) {
+        if let Some(last) = self.corrections.last() {
+            debug_assert!(offset >= last.offset);
+            if last.diff == diff {
+                return;
+            }
+        } else if diff == 0 {
+            return;
+        }
+        self.corrections.push(Correction {
+            offset,
+            diff,
+            removal,
+        });
+    }
+
+    // the number of corrections before `offset`
+    fn count_before(&self, offset: usize) -> usize {
+        let (mut low, mut high) = (0, self.corrections.len());
+        while low < high {
+            let mid = (low + high) / 2;
+            if self.corrections[mid].offset < offset {
+                low = mid + 1;
+            } else {
+                high = mid;
+            }
+        }
+        low
+    }
+
+    fn diff_before(&self, count: usize) -> isize {
+        if count > 0 {
+            self.corrections[count - 1].diff
+        } else {
+            0
+        }
+    }
+
+    pub fn correct(&self, current: usize) -> usize {
+        let count = self.count_before(current + 1);
+        (current as isize + self.diff_before(count)) as usize
+    }
+
+    pub fn correct_end(&self, current: usize) -> usize {
+        let count = self.count_before(current);
+        // a replacement ending at `current` belongs to the token, a removal
+        // there doesn't
+        let diff = match self.corrections.get(count) {
+            Some(c) if c.offset == current && !c.removal => c.diff,
+            _ => self.diff_before(count),
+        };
+        (current as isize + diff) as usize
+    }
+}
+
+// the text of a char filter being built, with its offset corrections
+#[derive(Default)]
+struct FilteredText {
+    text: String,
+    // the length of the text in chars
+    len: usize,
+    corrections: OffsetCorrections,
+}
+
+impl FilteredText {
+    fn push(&mut self, c: char) {
+        self.text.push(c);
+        self.len += 1;
+    }
+
+    // replaces the original chars from `start` to `end` by `replacement`, the
+    // chars of a longer replacement beyond the replaced ones being at the end
+    // of them
+    fn replace(&mut self, start: usize, end: usize, replacement: &str) {
+        for (i, c) in replacement.chars().enumerate() {
+            if i >= end - start {
+                let diff = end as isize - self.len as isize;
+                self.corrections.add_replacement(self.len, diff);
+            }
+            self.push(c);
+        }
+        let diff = end as isize - self.len as isize;
+        if replacement.is_empty() {
+            self.corrections.add_removal(self.len, diff);
+        } else {
+            self.corrections.add_replacement(self.len, diff);
+        }
+    }
+}
+
+/// Chains char filters, each one filtering the text of the previous one, the
+/// offsets being corrected back through all of them.
+#[derive(Debug)]
+pub struct CharFilterChain {
+    text: String,
+    filters: Vec<Box<dyn CharFilter>>,
+}
+
+impl CharFilterChain {
+    pub fn new(text: &str) -> CharFilterChain {
+        CharFilterChain {
+            text: text.to_string(),
+            filters: Vec::new(),
+        }
+    }
+
+    /// Filters the current text with the char filter created by `filter`.
+    pub fn add<F: FnOnce(&str) -> Box<dyn CharFilter>>(&mut self, filter: F) {
+        let filtered = filter(self.text());
+        self.filters.push(filtered);
+    }
+}
+
+impl CharFilter for CharFilterChain {
+    fn text(&self) -> &str {
+        match self.filters.last() {
+            Some(filter) => filter.text(),
+            None => &self.text,
+        }
+    }
+
+    fn correct_offset(&self, current: usize) -> usize {
+        self.filters
+            .iter()
+            .rev()
+            .fold(current, |offset, filter| filter.correct_offset(offset))
+    }
+
+    fn correct_end_offset(&self, current: usize) -> usize {
+        self.filters
+            .iter()
+            .rev()
+            .fold(current, |offset, filter| filter.correct_end_offset(offset))
+    }
+}
+
+// the elements starting a new line, their tags are replaced by a line break
+// so that the words around them aren't joined
+const BLOCK_TAGS: &[&str] = &[
+    "address",
+    "article",
+    "aside",
+    "blockquote",
+    "br",
+    "dd",
+    "div",
+    "dl",
+    "dt",
+    "fieldset",
+    "figcaption",
+    "figure",
+    "footer",
+    "form",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "header",
+    "hr",
+    "li",
+    "main",
+    "nav",
+    "ol",
+    "p",
+    "pre",
+    "section",
+    "table",
+    "tbody",
+    "td",
+    "tfoot",
+    "th",
+    "thead",
+    "title",
+    "tr",
+    "ul",
+];
+
+// the elements removed with their content
+const HIDDEN_TAGS: &[&str] = &["script", "style"];
+
+// the named character references, their capitalized names give the upper
+// case chars, e.g. "Eacute"
+const ENTITIES: &[(&str, char)] = &[
+    ("amp", '&'),
+    ("lt", '<'),
+    ("gt", '>'),
+    ("quot", '"'),
+    ("apos", '\''),
+    ("nbsp", '\u{a0}'),
+    ("copy", '©'),
+    ("reg", '®'),
+    ("trade", '™'),
+    ("hellip", '…'),
+    ("mdash", '—'),
+    ("ndash", '–'),
+    ("lsquo", '‘'),
+    ("rsquo", '’'),
+    ("ldquo", '“'),
+    ("rdquo", '”'),
+    ("laquo", '«'),
+    ("raquo", '»'),
+    ("euro", '€'),
+    ("cent", '¢'),
+    ("pound", '£'),
+    ("yen", '¥'),
+    ("sect", '§'),
+    ("deg", '°'),
+    ("middot", '·'),
+    ("times", '×'),
+    ("divide", '÷'),
+    ("aacute", 'á'),
+    ("agrave", 'à'),
+    ("acirc", 'â'),
+    ("atilde", 'ã'),
+    ("auml", 'ä'),
+    ("aring", 'å'),
+    ("aelig", 'æ'),
+    ("ccedil", 'ç'),
+    ("eacute", 'é'),
+    ("egrave", 'è'),
+    ("ecirc", 'ê'),
+    ("euml", 'ë'),
+    ("iacute", 'í'),
+    ("igrave", 'ì'),
+    ("icirc", 'î'),
+    ("iuml", 'ï'),
+    ("ntilde", 'ñ'),
+    ("oacute", 'ó'),
+    ("ograve", 'ò'),
+    ("ocirc", 'ô'),
+    ("otilde", 'õ'),
+    ("ouml", 'ö'),
+    ("oslash", 'ø'),
+    ("uacute", 'ú'),
+    ("ugrave", 'ù'),
+    ("ucirc", 'û'),
+    ("uuml", 'ü'),
+    ("yacute", 'ý'),
+    ("yuml", 'ÿ'),
+    ("szlig", 'ß'),
+];
+
+fn entity(name: &str) -> Option<char> {
+    if let Some(&(_, c)) = ENTITIES.iter().find(|e| e.0 == name) {
+        return Some(c);
+    }
+    let lower = name.to_lowercase();
+    let c = ENTITIES.iter().find(|e| e.0 == lower)?.1;
+    let mut upper = c.to_uppercase();
+    match (upper.next(), upper.next()) {
+        (Some(u), None) => Some(u),
+        _ => None,
+    }
+}
+
+/// Strips the markup of an HTML text: the tags, the comments, the
+/// declarations and the processing instructions are removed, as the
+/// scripts and the styles with their content, and the character references
+/// such as `&eacute;` or `&#233;` are replaced by their chars.
+///
+/// The tags of the block elements, such as `<p>` or `<br>`, are replaced by a
+/// line break so that the words around them stay apart. A `<` or a `&`
+/// starting no markup is kept as is.
+#[derive(Debug)]
+pub struct HTMLStripCharFilter {
+    text: String,
+    corrections: OffsetCorrections,
+}
+
+impl HTMLStripCharFilter {
+    pub fn new(html: &str) -> HTMLStripCharFilter {
+        Self::with_escaped_tags(html, &HashSet::new())
+    }
+
+    /// Keeps the tags named in `escaped_tags`, in lower case, e.g. to strip
+    /// all the tags but `<b>` and `</b>`.
+    pub fn with_escaped_tags(html: &str, escaped_tags: &HashSet<String>) -> HTMLStripCharFilter {
+        let mut stripper = HTMLStripper {
+            chars: html.chars().collect(),
+            escaped_tags,
+            filtered: FilteredText::default(),
+        };
+        stripper.strip();
+        HTMLStripCharFilter {
+            text: stripper.filtered.text,
+            corrections: stripper.filtered.corrections,
+        }
+    }
+}
+
+impl CharFilter for HTMLStripCharFilter {
+    fn text(&self) -> &str {
+        &self.text
+    }
+
+    fn correct_offset(&self, current: usize) -> usize {
+        self.corrections.correct(current)
+    }
+
+    fn correct_end_offset(&self, current: usize) -> usize {
+        self.corrections.correct_end(current)
+    }
+}
+
+struct HTMLStripper<'a> {
+    chars: Vec<char>,
+    escaped_tags: &'a HashSet<String>,
+    filtered: FilteredText,
+}
+
+impl<'a> HTMLStripper<'a> {
+    fn strip(&mut self) {
+        let mut i = 0;
+        while i < self.chars.len() {
+            let end = match self.chars[i] {
+                '<' => self.markup(i),
+                '&' => self.character_reference(i),
+                _ => None,
+            };
+            match end {
+                Some(end) => i = end,
+                None => {
+                    let c = self.chars[i];
+                    self.filtered.push(c);
+                    i += 1;
+                }
+            }
+        }
+    }
+
+    fn starts_with(&self, at: usize, prefix: &str) -> bool {
+        let mut i = at;
+        for p in prefix.chars() {
+            if i == self.chars.len() || !self.chars[i].eq_ignore_ascii_case(&p) {
+                return false;
+            }
+            i += 1;
+        }
+        true
+    }
+
+    // the index of the first `pattern` from `from`, ignoring the case
+    fn find(&self, from: usize, pattern: &str) -> Option<usize> {
+        (from..self.chars.len()).find(|&i| self.starts_with(i, pattern))
+    }
+
+    // strips the markup starting at `start`, returns its end or `None` when
+    // the `<` starts none
+    fn markup(&mut self, start: usize) -> Option<usize> {
+        if self.starts_with(start, "<!--") {
+            let end = self.find(start + 4, "-->")? + 3;
+            self.filtered.replace(start, end, "");
+            return Some(end);
+        }
+        if self.starts_with(start, "<![CDATA[") {
+            let content_end = self.find(start + 9, "]]>")?;
+            self.filtered.replace(start, start + 9, "");
+            for i in start + 9..content_end {
+                let c = self.chars[i];
+                self.filtered.push(c);
+            }
+            self.filtered.replace(content_end, content_end + 3, "");
+            return Some(content_end + 3);
+        }
+        if self.starts_with(start, "<!") || self.starts_with(start, "<?") {
+            let end = self.find(start + 2, ">")? + 1;
+            self.filtered.replace(start, end, "");
+            return Some(end);
+        }
+
+        let closing = self.starts_with(start, "</");
+        let name_start = if closing { start + 2 } else { start + 1 };
+        if name_start == self.chars.len() || !self.chars[name_start].is_ascii_alphabetic() {
+            return None;
+        }
+        let mut i = name_start;
+        while i < self.chars.len()
+            && (self.chars[i].is_ascii_alphanumeric()
+                || self.chars[i] == '-'
+                || self.chars[i] == ':')
+        {
+            i += 1;
+        }
+        let name: String = self.chars[name_start..i]
+            .iter()
+            .map(|c| c.to_ascii_lowercase())
+            .collect();
+        // the attributes, their quoted values may contain a '>'
+        let mut quote = None;
+        while i < self.chars.len() {
+            match (self.chars[i], quote) {
+                ('>', None) => break,
+                (c @ '"', None) | (c @ '\'', None) => quote = Some(c),
+                (c, Some(q)) if c == q => quote = None,
+                _ => {}
+            }
+            i += 1;
+        }
+        if i == self.chars.len() {
+            return None;
+        }
+        let mut end = i + 1;
+
+        if self.escaped_tags.contains(&name) {
+            for i in start..end {
+                let c = self.chars[i];
+                self.filtered.push(c);
+            }
+        } else if !closing && HIDDEN_TAGS.contains(&name.as_str()) {
+            let closing_tag = format!("</{}", name);
+            end = match self.find(end, &closing_tag) {
+                Some(close) => self.find(close, ">").map_or(self.chars.len(), |e| e + 1),
+                None => self.chars.len(),
+            };
+            self.filtered.replace(start, end, "\n");
+        } else if BLOCK_TAGS.contains(&name.as_str()) || HIDDEN_TAGS.contains(&name.as_str()) {
+            self.filtered.replace(start, end, "\n");
+        } else {
+            self.filtered.replace(start, end, "");
+        }
+        Some(end)
+    }
+
+    // replaces the character reference starting at `start`, returns its end
+    // or `None` when the `&` starts none
+    fn character_reference(&mut self, start: usize) -> Option<usize> {
+        let semicolon =
+            (start + 1..self.chars.len().min(start + 12)).find(|&i| self.chars[i] == ';')?;
+        let reference: String = self.chars[start + 1..semicolon].iter().collect();
+        let c = if reference.starts_with("#x") || reference.starts_with("#X") {
+            u32::from_str_radix(&reference[2..], 16).ok()
+        } else if reference.starts_with('#') {
+            reference[1..].parse::<u32>().ok()
+        } else {
+            entity(&reference).map(u32::from)
+        };
+        let c = ::std::char::from_u32(c?)?;
+        let mut replacement = [0u8; 4];
+        self.filtered
+            .replace(start, semicolon + 1, c.encode_utf8(&mut replacement));
+        Some(semicolon + 1)
+    }
+}
+
+/// Builds the `NormalizeCharMap` of a `MappingCharFilter`.
+#[derive(Debug, Default)]
+pub struct NormalizeCharMapBuilder {
+    mappings: HashMap<Vec<char>, String>,
+}
+
+impl NormalizeCharMapBuilder {
+    /// Replaces the chars of `matched` by `replacement`, which may be empty.
+    pub fn add(&mut self, matched: &str, replacement: &str) -> Result<()> {
+        if matched.is_empty() {
+            bail!(IllegalArgument("a char mapping can't match nothing".into()));
+        }
+        let matched: Vec<char> = matched.chars().collect();
+        if self.mappings.contains_key(&matched) {
+            bail!(IllegalArgument(format!(
+                "the chars {:?} are already mapped",
+                matched.iter().collect::<String>()
+            )));
+        }
+        self.mappings.insert(matched, replacement.to_string());
+        Ok(())
+    }
+
+    pub fn build(self) -> NormalizeCharMap {
+        let max_match_len = self.mappings.keys().map(|m| m.len()).max().unwrap_or(0);
+        NormalizeCharMap {
+            mappings: self.mappings,
+            max_match_len,
+        }
+    }
+}
+
+/// The replacements of the chars matched by a `MappingCharFilter`.
+#[derive(Debug)]
+pub struct NormalizeCharMap {
+    mappings: HashMap<Vec<char>, String>,
+    max_match_len: usize,
+}
+
+impl NormalizeCharMap {
+    pub fn builder() -> NormalizeCharMapBuilder {
+        NormalizeCharMapBuilder::default()
+    }
+}
+
+/// Replaces the chars of a text matched by a `NormalizeCharMap`, the longest
+/// match winning at each char, e.g. to turn "ß" into "ss" or to drop some
+/// punctuation.
+#[derive(Debug)]
+pub struct MappingCharFilter {
+    text: String,
+    corrections: OffsetCorrections,
+}
+
+impl MappingCharFilter {
+    pub fn new(text: &str, map: &NormalizeCharMap) -> MappingCharFilter {
+        let chars: Vec<char> = text.chars().collect();
+        let mut filtered = FilteredText::default();
+        let mut i = 0;
+        'chars: while i < chars.len() {
+            for len in (1..=map.max_match_len.min(chars.len() - i)).rev() {
+                if let Some(replacement) = map.mappings.get(&chars[i..i + len]) {
+                    filtered.replace(i, i + len, replacement);
+                    i += len;
+                    continue 'chars;
+                }
+            }
+            filtered.push(chars[i]);
+            i += 1;
+        }
+        MappingCharFilter {
+            text: filtered.text,
+            corrections: filtered.corrections,
+        }
+    }
+}
+
+impl CharFilter for MappingCharFilter {
+    fn text(&self) -> &str {
+        &self.text
+    }
+
+    fn correct_offset(&self, current: usize) -> usize {
+        self.corrections.correct(current)
+    }
+
+    fn correct_end_offset(&self, current: usize) -> usize {
+        self.corrections.correct_end(current)
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    /// An HTML text with tags, entities, a script and a comment.
+    pub const HTML: &str = "<p>Caf&eacute; <b>au</b> lait&nbsp;&amp; cr&#232;me</p><script>var x \
+                            = \"<b>\";</script><!-- note --><br/>Done";
+
+    #[test]
+    fn test_html_strip_char_filter() {
+        let filter = HTMLStripCharFilter::new(HTML);
+        assert_eq!(filter.text(), "\nCafé au lait\u{a0}& crème\n\n\nDone");
+        // "Café"
+        assert_eq!(filter.correct_offset(1), 3);
+        assert_eq!(filter.correct_end_offset(5), 14);
+        // "au", without its tags
+        assert_eq!(filter.correct_offset(6), 18);
+        assert_eq!(filter.correct_end_offset(8), 20);
+        // "crème"
+        assert_eq!(filter.correct_offset(16), 41);
+        assert_eq!(filter.correct_end_offset(21), 51);
+        // "Done", and the end of the text
+        assert_eq!(filter.correct_offset(24), 104);
+        assert_eq!(filter.correct_offset(28), HTML.chars().count());
+
+        let escaped: HashSet<String> = vec!["b".to_string()].into_iter().collect();
+        let filter = HTMLStripCharFilter::with_escaped_tags(
+            "<b>bold</b> <i>it</i> <![CDATA[a<b]]> 1 < 2 &unknown; &#xe9;",
+            &escaped,
+        );
+        assert_eq!(filter.text(), "<b>bold</b> it a<b 1 < 2 &unknown; é");
+        assert_eq!(filter.correct_offset(12), 15);
+    }
+
+    #[test]
+    fn test_mapping_char_filter() {
+        let mut builder = NormalizeCharMap::builder();
+        builder.add("ph", "f").unwrap();
+        builder.add("ß", "ss").unwrap();
+        builder.add("&", " and ").unwrap();
+        assert!(builder.add("", "x").is_err());
+        assert!(builder.add("ph", "v").is_err());
+        let map = builder.build();
+
+        let filter = MappingCharFilter::new("phone & Straße", &map);
+        assert_eq!(filter.text(), "fone  and  Strasse");
+        // "fone"
+        assert_eq!(filter.correct_offset(0), 0);
+        assert_eq!(filter.correct_end_offset(4), 5);
+        // "Strasse"
+        assert_eq!(filter.correct_offset(11), 8);
+        assert_eq!(filter.correct_end_offset(18), 14);
+    }
+
+    #[test]
+    fn test_char_filter_chain() {
+        let mut builder = NormalizeCharMap::builder();
+        builder.add("ß", "ss").unwrap();
+        let map = builder.build();
+        let mut chain = CharFilterChain::new("<p>Stra&szlig;e</p>");
+        assert_eq!(chain.text(), "<p>Stra&szlig;e</p>");
+        chain.add(|text| Box::new(HTMLStripCharFilter::new(text)));
+        chain.add(|text| Box::new(MappingCharFilter::new(text, &map)));
+        assert_eq!(chain.text(), "\nStrasse\n");
+        assert_eq!(chain.correct_offset(1), 3);
+        assert_eq!(chain.correct_end_offset(8), 15);
+    }
+}
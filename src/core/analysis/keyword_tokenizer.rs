@@ -0,0 +1,185 @@
+// Copyright 2019 Zhizhesihai (Beijing) Technology Limited.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use core::analysis::{CharFilter, TokenStream};
+use core::attribute::TermToBytesRefAttribute;
+use core::attribute::{CharTermAttribute, OffsetAttribute, PositionIncrementAttribute};
+use core::util::byte_block_pool::ByteBlockPool;
+
+use error::ErrorKind::IllegalArgument;
+use error::Result;
+
+/// The longest token accepted by default, in bytes: the longest term the
+/// `IndexWriter` can index.
+pub const DEFAULT_MAX_TOKEN_LENGTH: usize = ByteBlockPool::BYTE_BLOCK_SIZE - 2;
+
+/// Emits the whole text as a single token, for the values such as ids or
+/// tags which must not be split.
+///
+/// Empty texts have no token. A text longer than the max token length fails
+/// the stream rather than being truncated, so that two long values never
+/// become the same term.
+///
+/// Reading the text of a char filter, the offsets of the token point into
+/// the text before it.
+#[derive(Debug)]
+pub struct KeywordTokenizer {
+    text: String,
+    char_filter: Option<Box<dyn CharFilter>>,
+    done: bool,
+    max_token_length: usize,
+    term_attr: CharTermAttribute,
+    offset_attr: OffsetAttribute,
+    position_attr: PositionIncrementAttribute,
+}
+
+impl KeywordTokenizer {
+    pub fn new(text: &str) -> KeywordTokenizer {
+        Self::with_max_token_length(text, DEFAULT_MAX_TOKEN_LENGTH)
+    }
+
+    /// Fails on the texts longer than `max_token_length` bytes.
+    pub fn with_max_token_length(text: &str, max_token_length: usize) -> KeywordTokenizer {
+        assert!(max_token_length > 0);
+        KeywordTokenizer {
+            text: text.to_string(),
+            char_filter: None,
+            done: false,
+            max_token_length,
+            term_attr: CharTermAttribute::new(),
+            offset_attr: OffsetAttribute::new(),
+            position_attr: PositionIncrementAttribute::new(),
+        }
+    }
+
+    /// Emits the text of `char_filter` as a single token, its offsets
+    /// pointing into the text it filtered.
+    pub fn with_char_filter(
+        char_filter: Box<dyn CharFilter>,
+        max_token_length: usize,
+    ) -> KeywordTokenizer {
+        let mut tokenizer = Self::with_max_token_length(char_filter.text(), max_token_length);
+        tokenizer.char_filter = Some(char_filter);
+        tokenizer
+    }
+
+    fn correct_offset(&self, offset: usize) -> usize {
+        match self.char_filter {
+            Some(ref char_filter) => char_filter.correct_offset(offset),
+            None => offset,
+        }
+    }
+
+    fn correct_end_offset(&self, offset: usize) -> usize {
+        match self.char_filter {
+            Some(ref char_filter) => char_filter.correct_end_offset(offset),
+            None => offset,
+        }
+    }
+}
+
+impl TokenStream for KeywordTokenizer {
+    fn increment_token(&mut self) -> Result<bool> {
+        self.clear_attributes();
+        if self.done || self.text.is_empty() {
+            return Ok(false);
+        }
+        if self.text.len() > self.max_token_length {
+            bail!(IllegalArgument(format!(
+                "keyword token of {} bytes is longer than the max token length {}",
+                self.text.len(),
+                self.max_token_length
+            )));
+        }
+        self.done = true;
+        self.term_attr.append(&self.text);
+        let start_offset = self.correct_offset(0);
+        let end_offset = self.correct_end_offset(self.text.chars().count());
+        self.offset_attr.set_offset(start_offset, end_offset)?;
+        Ok(true)
+    }
+
+    fn end(&mut self) -> Result<()> {
+        self.end_attributes();
+        let final_offset = self.correct_offset(self.text.chars().count());
+        self.offset_attr.set_offset(final_offset, final_offset)
+    }
+
+    fn reset(&mut self) -> Result<()> {
+        self.done = false;
+        Ok(())
+    }
+
+    fn offset_attribute_mut(&mut self) -> &mut OffsetAttribute {
+        &mut self.offset_attr
+    }
+
+    fn offset_attribute(&self) -> &OffsetAttribute {
+        &self.offset_attr
+    }
+
+    fn position_attribute_mut(&mut self) -> &mut PositionIncrementAttribute {
+        &mut self.position_attr
+    }
+
+    fn term_bytes_attribute_mut(&mut self) -> &mut TermToBytesRefAttribute {
+        &mut self.term_attr
+    }
+
+    fn term_bytes_attribute(&self) -> &TermToBytesRefAttribute {
+        &self.term_attr
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokens(tokenizer: &mut KeywordTokenizer) -> Result<Vec<(String, usize, usize)>> {
+        tokenizer.reset()?;
+        let mut tokens = vec![];
+        while tokenizer.increment_token()? {
+            let term = tokenizer.term_bytes_attribute().get_bytes_ref();
+            let offsets = tokenizer.offset_attribute();
+            tokens.push((
+                String::from_utf8(term.bytes().to_vec()).unwrap(),
+                offsets.start_offset(),
+                offsets.end_offset(),
+            ));
+        }
+        tokenizer.end()?;
+        Ok(tokens)
+    }
+
+    #[test]
+    fn test_keyword_tokenizer() {
+        let mut tokenizer = KeywordTokenizer::new("New York, ÉTÉ");
+        let expected = vec![("New York, ÉTÉ".to_string(), 0, 13)];
+        assert_eq!(tokens(&mut tokenizer).unwrap(), expected);
+        assert_eq!(tokenizer.offset_attribute().end_offset(), 13);
+        // the stream can be consumed again after a reset
+        assert_eq!(tokens(&mut tokenizer).unwrap(), expected);
+
+        assert!(tokens(&mut KeywordTokenizer::new("")).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_max_token_length() {
+        let mut tokenizer = KeywordTokenizer::with_max_token_length("abcd", 4);
+        assert_eq!(tokens(&mut tokenizer).unwrap().len(), 1);
+
+        // the length counts bytes
+        let mut tokenizer = KeywordTokenizer::with_max_token_length("abcé", 4);
+        assert!(tokens(&mut tokenizer).is_err());
+    }
+}
@@ -11,8 +11,22 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+mod char_filter;
+pub use self::char_filter::*;
+
 mod token_stream;
 pub use self::token_stream::TokenStream;
 
+mod analyzer;
+pub use self::analyzer::*;
+
+mod token_filter;
+pub use self::token_filter::*;
+
+mod synonym;
+pub use self::synonym::*;
+
 mod char_buffer;
+pub mod keyword_tokenizer;
+pub mod standard_tokenizer;
 pub mod whitespace_tokenizer;
@@ -0,0 +1,419 @@
+// Copyright 2019 Zhizhesihai (Beijing) Technology Limited.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use core::analysis::{CharFilter, TokenStream};
+use core::attribute::TermToBytesRefAttribute;
+use core::attribute::{CharTermAttribute, OffsetAttribute, PositionIncrementAttribute};
+
+use error::Result;
+
+/// The longest token emitted by default, longer words are split.
+pub const DEFAULT_MAX_TOKEN_LENGTH: usize = 255;
+
+/// The word break property of a char, see Unicode Standard Annex #29.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum WordBreak {
+    ALetter,
+    Numeric,
+    Katakana,
+    Hiragana,
+    Ideographic,
+    MidLetter,
+    MidNum,
+    MidNumLet,
+    SingleQuote,
+    ExtendNumLet,
+    // Extend and Format, they stick to the char before them
+    Extend,
+    Other,
+}
+
+fn word_break(c: char) -> WordBreak {
+    match c as u32 {
+        0x200C
+        | 0x200D
+        | 0x00AD
+        | 0x0600..=0x0605
+        | 0x061C
+        | 0x06DD
+        | 0x070F
+        | 0x200E
+        | 0x200F
+        | 0x202A..=0x202E
+        | 0x2060..=0x2064
+        | 0xFEFF => WordBreak::Extend,
+        0x0300..=0x036F
+        | 0x0483..=0x0489
+        | 0x0591..=0x05BD
+        | 0x05BF
+        | 0x05C1
+        | 0x05C2
+        | 0x05C4
+        | 0x05C5
+        | 0x05C7
+        | 0x0610..=0x061A
+        | 0x064B..=0x065F
+        | 0x0670
+        | 0x1AB0..=0x1AFF
+        | 0x1DC0..=0x1DFF
+        | 0x20D0..=0x20FF
+        | 0xFE00..=0xFE0F
+        | 0xFE20..=0xFE2F => WordBreak::Extend,
+        // the viramas and the other marks of the indic and southeast asian
+        // scripts which aren't alphabetic
+        0x0900..=0x0DFF | 0x0E00..=0x0EFF | 0x1000..=0x109F
+            if !c.is_alphanumeric() && !is_brahmic_punctuation(c) =>
+        {
+            WordBreak::Extend
+        }
+        0x3031..=0x3035
+        | 0x309B
+        | 0x309C
+        | 0x30A0..=0x30FA
+        | 0x30FC..=0x30FF
+        | 0x31F0..=0x31FF
+        | 0x32D0..=0x32FE
+        | 0x3300..=0x3357
+        | 0xFF66..=0xFF9D => WordBreak::Katakana,
+        0x3041..=0x3096 | 0x309D..=0x309F => WordBreak::Hiragana,
+        0x3005..=0x3007
+        | 0x3021..=0x3029
+        | 0x3038..=0x303B
+        | 0x3400..=0x4DBF
+        | 0x4E00..=0x9FFF
+        | 0xF900..=0xFAFF
+        | 0x20000..=0x2FFFF => WordBreak::Ideographic,
+        0x003A | 0x00B7 | 0x0387 | 0x05F4 | 0x2027 | 0xFE13 | 0xFE55 | 0xFF1A => {
+            WordBreak::MidLetter
+        }
+        0x002E | 0x2018 | 0x2019 | 0x2024 | 0xFE52 | 0xFF07 | 0xFF0E => WordBreak::MidNumLet,
+        0x0027 => WordBreak::SingleQuote,
+        0x002C | 0x003B | 0x037E | 0x0589 | 0x060C | 0x060D | 0x066C | 0x07F8 | 0x2044 | 0xFE10
+        | 0xFE14 | 0xFE50 | 0xFE54 | 0xFF0C | 0xFF1B => WordBreak::MidNum,
+        0x005F | 0x202F | 0x203F | 0x2040 | 0x2054 | 0xFE33 | 0xFE34 | 0xFE4D..=0xFE4F | 0xFF3F => {
+            WordBreak::ExtendNumLet
+        }
+        _ if c.is_numeric() => WordBreak::Numeric,
+        _ if c.is_alphabetic() => WordBreak::ALetter,
+        _ => WordBreak::Other,
+    }
+}
+
+fn is_brahmic_punctuation(c: char) -> bool {
+    match c as u32 {
+        0x0964 | 0x0965 | 0x0970 | 0x0DF4 | 0x0E2F | 0x0E4F | 0x0E5A | 0x0E5B | 0x104A | 0x104B => {
+            true
+        }
+        _ => false,
+    }
+}
+
+// whether no word boundary is allowed between `prev` and `next` (WB5, WB8 to
+// WB10, WB13 to WB13b)
+fn joins(prev: WordBreak, next: WordBreak) -> bool {
+    use self::WordBreak::*;
+    match (prev, next) {
+        (ALetter, ALetter) | (ALetter, Numeric) | (Numeric, Numeric) | (Numeric, ALetter) => true,
+        (Katakana, Katakana) => true,
+        (ALetter, ExtendNumLet) | (Numeric, ExtendNumLet) | (Katakana, ExtendNumLet) => true,
+        (ExtendNumLet, ExtendNumLet)
+        | (ExtendNumLet, ALetter)
+        | (ExtendNumLet, Numeric)
+        | (ExtendNumLet, Katakana) => true,
+        _ => false,
+    }
+}
+
+// whether `mid` doesn't break a word between `prev` and `next` (WB6, WB7,
+// WB11 and WB12)
+fn joins_across(prev: WordBreak, mid: WordBreak, next: WordBreak) -> bool {
+    use self::WordBreak::*;
+    match (prev, mid, next) {
+        (ALetter, MidLetter, ALetter)
+        | (ALetter, MidNumLet, ALetter)
+        | (ALetter, SingleQuote, ALetter) => true,
+        (Numeric, MidNum, Numeric)
+        | (Numeric, MidNumLet, Numeric)
+        | (Numeric, SingleQuote, Numeric) => true,
+        _ => false,
+    }
+}
+
+/// A tokenizer splitting text at the word boundaries of the Unicode Text
+/// Segmentation algorithm (Unicode Standard Annex #29).
+///
+/// The tokens are the words made of letters, numbers or katakana, such as
+/// "can't", "3.14" or "foo_bar", and every single ideograph or hiragana, the
+/// punctuation and the symbols in between are dropped. The word break
+/// property of a char is approximated from its general category for the
+/// scripts the annex doesn't list, so some rare marks may split a word.
+///
+/// Like the other tokenizers, the offsets of the tokens count chars rather
+/// than bytes. Reading the text of a char filter, they are corrected to point
+/// into the text before it.
+#[derive(Debug)]
+pub struct StandardTokenizer {
+    chars: Vec<char>,
+    char_filter: Option<Box<dyn CharFilter>>,
+    // the index of the next char to read
+    index: usize,
+    max_token_length: usize,
+    term_attr: CharTermAttribute,
+    offset_attr: OffsetAttribute,
+    position_attr: PositionIncrementAttribute,
+}
+
+impl StandardTokenizer {
+    pub fn new(text: &str) -> StandardTokenizer {
+        Self::with_max_token_length(text, DEFAULT_MAX_TOKEN_LENGTH)
+    }
+
+    pub fn with_max_token_length(text: &str, max_token_length: usize) -> StandardTokenizer {
+        assert!(max_token_length > 0);
+        StandardTokenizer {
+            chars: text.chars().collect(),
+            char_filter: None,
+            index: 0,
+            max_token_length,
+            term_attr: CharTermAttribute::new(),
+            offset_attr: OffsetAttribute::new(),
+            position_attr: PositionIncrementAttribute::new(),
+        }
+    }
+
+    /// Tokenizes the text of `char_filter`, the offsets of the tokens pointing
+    /// into the text it filtered, e.g. the HTML stripped of its tags.
+    pub fn with_char_filter(
+        char_filter: Box<dyn CharFilter>,
+        max_token_length: usize,
+    ) -> StandardTokenizer {
+        let mut tokenizer = Self::with_max_token_length(char_filter.text(), max_token_length);
+        tokenizer.char_filter = Some(char_filter);
+        tokenizer
+    }
+
+    fn correct_offset(&self, offset: usize) -> usize {
+        match self.char_filter {
+            Some(ref char_filter) => char_filter.correct_offset(offset),
+            None => offset,
+        }
+    }
+
+    fn correct_end_offset(&self, offset: usize) -> usize {
+        match self.char_filter {
+            Some(ref char_filter) => char_filter.correct_end_offset(offset),
+            None => offset,
+        }
+    }
+
+    fn class_at(&self, index: usize) -> WordBreak {
+        word_break(self.chars[index])
+    }
+
+    // skips the Extend and Format chars from `index`
+    fn skip_extend(&self, mut index: usize) -> usize {
+        while index < self.chars.len() && self.class_at(index) == WordBreak::Extend {
+            index += 1;
+        }
+        index
+    }
+
+    // returns the end of the word starting at `start`
+    fn word_end(&self, start: usize) -> usize {
+        let mut prev = self.class_at(start);
+        let mut end = self.skip_extend(start + 1);
+        if prev == WordBreak::Ideographic || prev == WordBreak::Hiragana {
+            return end;
+        }
+        while end < self.chars.len() {
+            let next = self.class_at(end);
+            if joins(prev, next) {
+                prev = next;
+                end = self.skip_extend(end + 1);
+                continue;
+            }
+            let after = self.skip_extend(end + 1);
+            if after < self.chars.len() {
+                let next_next = self.class_at(after);
+                if joins_across(prev, next, next_next) {
+                    prev = next_next;
+                    end = self.skip_extend(after + 1);
+                    continue;
+                }
+            }
+            break;
+        }
+        end
+    }
+}
+
+fn starts_word(class: WordBreak) -> bool {
+    match class {
+        WordBreak::ALetter
+        | WordBreak::Numeric
+        | WordBreak::Katakana
+        | WordBreak::Hiragana
+        | WordBreak::Ideographic => true,
+        _ => false,
+    }
+}
+
+impl TokenStream for StandardTokenizer {
+    fn increment_token(&mut self) -> Result<bool> {
+        self.clear_attributes();
+        while self.index < self.chars.len() && !starts_word(self.class_at(self.index)) {
+            self.index += 1;
+        }
+        if self.index == self.chars.len() {
+            return Ok(false);
+        }
+        let start = self.index;
+        let mut end = self.word_end(start);
+        if end - start > self.max_token_length {
+            end = start + self.max_token_length;
+        }
+        for &c in &self.chars[start..end] {
+            self.term_attr.push_char(c);
+        }
+        let start_offset = self.correct_offset(start);
+        let end_offset = self.correct_end_offset(end);
+        self.offset_attr.set_offset(start_offset, end_offset)?;
+        self.index = end;
+        Ok(true)
+    }
+
+    fn end(&mut self) -> Result<()> {
+        self.end_attributes();
+        let final_offset = self.correct_offset(self.chars.len());
+        self.offset_attr.set_offset(final_offset, final_offset)
+    }
+
+    fn reset(&mut self) -> Result<()> {
+        self.index = 0;
+        Ok(())
+    }
+
+    fn offset_attribute_mut(&mut self) -> &mut OffsetAttribute {
+        &mut self.offset_attr
+    }
+
+    fn offset_attribute(&self) -> &OffsetAttribute {
+        &self.offset_attr
+    }
+
+    fn position_attribute_mut(&mut self) -> &mut PositionIncrementAttribute {
+        &mut self.position_attr
+    }
+
+    fn term_bytes_attribute_mut(&mut self) -> &mut TermToBytesRefAttribute {
+        &mut self.term_attr
+    }
+
+    fn term_bytes_attribute(&self) -> &TermToBytesRefAttribute {
+        &self.term_attr
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokens(tokenizer: &mut StandardTokenizer) -> Vec<(String, usize, usize)> {
+        tokenizer.reset().unwrap();
+        let mut tokens = vec![];
+        while tokenizer.increment_token().unwrap() {
+            assert_eq!(
+                tokenizer.position_attribute_mut().get_position_increment(),
+                1
+            );
+            let term = tokenizer.term_bytes_attribute().get_bytes_ref();
+            let offsets = tokenizer.offset_attribute();
+            tokens.push((
+                String::from_utf8(term.bytes().to_vec()).unwrap(),
+                offsets.start_offset(),
+                offsets.end_offset(),
+            ));
+        }
+        tokenizer.end().unwrap();
+        tokens
+    }
+
+    fn token(term: &str, start: usize, end: usize) -> (String, usize, usize) {
+        (term.to_string(), start, end)
+    }
+
+    #[test]
+    fn test_word_boundaries() {
+        let text =
+            "Wörld's 3.14 naïve_café, U.S.A. 1,000日本語テスト ひらがな नमस्ते—end.";
+        let mut tokenizer = StandardTokenizer::new(text);
+        assert_eq!(
+            tokens(&mut tokenizer),
+            vec![
+                token("Wörld's", 0, 7),
+                token("3.14", 8, 12),
+                token("naïve_café", 13, 23),
+                token("U.S.A", 25, 30),
+                token("1,000", 32, 37),
+                token("日", 37, 38),
+                token("本", 38, 39),
+                token("語", 39, 40),
+                token("テスト", 40, 43),
+                token("ひ", 44, 45),
+                token("ら", 45, 46),
+                token("が", 46, 47),
+                token("な", 47, 48),
+                token("नमस्ते", 49, 55),
+                token("end", 56, 59),
+            ]
+        );
+        assert_eq!(tokenizer.offset_attribute().end_offset(), 60);
+
+        // the stream can be consumed again after a reset
+        assert_eq!(tokens(&mut tokenizer).len(), 15);
+    }
+
+    #[test]
+    fn test_punctuation_between_words() {
+        let mut tokenizer = StandardTokenizer::new("'quoted' a.b. 1.a x:y 2:3 3..4 --");
+        assert_eq!(
+            tokens(&mut tokenizer),
+            vec![
+                token("quoted", 1, 7),
+                token("a.b", 9, 12),
+                token("1", 14, 15),
+                token("a", 16, 17),
+                token("x:y", 18, 21),
+                token("2", 22, 23),
+                token("3", 24, 25),
+                token("3", 26, 27),
+                token("4", 29, 30),
+            ]
+        );
+        assert!(tokens(&mut StandardTokenizer::new(" ,.;- ")).is_empty());
+    }
+
+    #[test]
+    fn test_max_token_length() {
+        let mut tokenizer = StandardTokenizer::with_max_token_length("abcdefgh ij", 3);
+        assert_eq!(
+            tokens(&mut tokenizer),
+            vec![
+                token("abc", 0, 3),
+                token("def", 3, 6),
+                token("gh", 6, 8),
+                token("ij", 9, 11),
+            ]
+        );
+    }
+}
@@ -0,0 +1,889 @@
+// Copyright 2019 Zhizhesihai (Beijing) Technology Limited.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use core::analysis::TokenStream;
+use core::attribute::{CharTermAttribute, OffsetAttribute, PositionIncrementAttribute};
+use core::attribute::{PositionLengthAttribute, TermToBytesRefAttribute};
+use core::util::fst::fst_builder::FstBuilder;
+use core::util::fst::{ByteSequenceOutput, ByteSequenceOutputFactory};
+use core::util::fst::{InputType, OutputFactory, FST};
+use core::util::ints_ref::{to_ints_ref, IntsRefBuilder};
+
+use error::ErrorKind::IllegalArgument;
+use error::Result;
+
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+use std::fmt;
+use std::str;
+use std::sync::Arc;
+
+/// Separates the words of an input in the FST of a `SynonymMap`.
+pub const WORD_SEPARATOR: u8 = 0;
+
+/// Builds a `SynonymMap` from rules mapping the words of an input to the
+/// words of a synonym.
+#[derive(Debug, Default)]
+pub struct SynonymMapBuilder {
+    dedup: bool,
+    // the rules of each input, keyed by its words joined by
+    // `WORD_SEPARATOR` so that they are in the order of the FST
+    rules: BTreeMap<Vec<u8>, Rule>,
+}
+
+#[derive(Debug, Default)]
+struct Rule {
+    outputs: Vec<Vec<Vec<u8>>>,
+    keep_original: bool,
+}
+
+impl SynonymMapBuilder {
+    /// With `dedup`, a synonym added twice to an input is only emitted once.
+    pub fn new(dedup: bool) -> SynonymMapBuilder {
+        SynonymMapBuilder {
+            dedup,
+            rules: BTreeMap::new(),
+        }
+    }
+
+    /// Adds the synonym `output` of `input`, both of one or more words
+    /// separated by whitespace, e.g. `add("ny", "new york", true)`.
+    ///
+    /// The input words are matched against the terms as they come out of
+    /// the filters before the `SynonymGraphFilter`, e.g. lowercased. With
+    /// `keep_original` the matched tokens are kept along the synonyms, which
+    /// is the case as soon as one of the rules of an input asks for it.
+    pub fn add(&mut self, input: &str, output: &str, keep_original: bool) -> Result<()> {
+        let input_words = Self::words(input)?;
+        let output_words = Self::words(output)?;
+        let mut key = Vec::with_capacity(input.len());
+        for (i, word) in input_words.into_iter().enumerate() {
+            if i > 0 {
+                key.push(WORD_SEPARATOR);
+            }
+            key.extend(word);
+        }
+        let rule = self.rules.entry(key).or_insert_with(Rule::default);
+        if !self.dedup || !rule.outputs.contains(&output_words) {
+            rule.outputs.push(output_words);
+        }
+        rule.keep_original |= keep_original;
+        Ok(())
+    }
+
+    fn words(text: &str) -> Result<Vec<Vec<u8>>> {
+        if text.contains(WORD_SEPARATOR as char) {
+            bail!(IllegalArgument(format!(
+                "synonym {:?} contains the word separator",
+                text
+            )));
+        }
+        let words: Vec<Vec<u8>> = text
+            .split_whitespace()
+            .map(|w| w.as_bytes().to_vec())
+            .collect();
+        if words.is_empty() {
+            bail!(IllegalArgument(format!("synonym '{}' has no word", text)));
+        }
+        Ok(words)
+    }
+
+    pub fn build(self) -> Result<SynonymMap> {
+        let mut outputs = Vec::new();
+        let mut ords = HashMap::new();
+        let mut builder = FstBuilder::new(InputType::Byte1, ByteSequenceOutputFactory::new());
+        builder.init();
+        let mut scratch = IntsRefBuilder::new();
+        for (input, rule) in self.rules {
+            // whether to keep the original, then the ords of the synonyms
+            let mut output = Vec::with_capacity(1 + 4 * rule.outputs.len());
+            output.push(rule.keep_original as u8);
+            for words in rule.outputs {
+                let next_ord = outputs.len() as u32;
+                let ord = *ords.entry(words.clone()).or_insert(next_ord);
+                if ord == next_ord {
+                    outputs.push(words);
+                }
+                output.extend_from_slice(&ord.to_be_bytes());
+            }
+            builder.add(
+                to_ints_ref(&input, &mut scratch),
+                ByteSequenceOutput::new(output),
+            )?;
+        }
+        Ok(SynonymMap {
+            fst: builder.finish()?,
+            outputs,
+        })
+    }
+}
+
+/// The synonyms of the inputs of one or more words, looked up by the
+/// `SynonymGraphFilter`, see `SynonymMapBuilder`.
+pub struct SynonymMap {
+    // the inputs, mapped to whether their tokens are kept and to the ords of
+    // their synonyms
+    fst: Option<FST<ByteSequenceOutputFactory>>,
+    // the words of each synonym, by ord
+    outputs: Vec<Vec<Vec<u8>>>,
+}
+
+impl SynonymMap {
+    pub fn builder() -> SynonymMapBuilder {
+        SynonymMapBuilder::new(true)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fst.is_none()
+    }
+
+    // the length of the longest input starting at the first token, with its
+    // FST output, or `None` without any
+    fn longest_match(
+        &self,
+        tokens: &mut dyn FnMut(usize) -> Result<Option<Vec<u8>>>,
+    ) -> Result<Option<(usize, ByteSequenceOutput)>> {
+        let fst = match self.fst {
+            Some(ref fst) => fst,
+            None => return Ok(None),
+        };
+        let outputs = fst.outputs();
+        let mut reader = fst.bytes_reader();
+        let mut arc = fst.root_arc();
+        let mut output = outputs.empty();
+        let mut longest = None;
+        let mut i = 0;
+        while let Some(term) = tokens(i)? {
+            if i > 0 {
+                match fst.find_target_arc(i32::from(WORD_SEPARATOR), &arc, &mut reader)? {
+                    Some(next) => arc = next,
+                    None => break,
+                }
+                if let Some(ref out) = arc.output {
+                    output = outputs.add(&output, out);
+                }
+            }
+            let mut matched = true;
+            for &b in &term {
+                match fst.find_target_arc(i32::from(b), &arc, &mut reader)? {
+                    Some(next) => arc = next,
+                    None => {
+                        matched = false;
+                        break;
+                    }
+                }
+                if let Some(ref out) = arc.output {
+                    output = outputs.add(&output, out);
+                }
+            }
+            if !matched {
+                break;
+            }
+            i += 1;
+            if arc.is_final() {
+                let full = match arc.next_final_output {
+                    Some(ref out) => outputs.add(&output, out),
+                    None => output.clone(),
+                };
+                longest = Some((i, full));
+            }
+        }
+        Ok(longest)
+    }
+
+    // whether the matched tokens are kept, and the words of the synonyms
+    fn decode(&self, output: &ByteSequenceOutput) -> (bool, Vec<&[Vec<u8>]>) {
+        let bytes = output.inner();
+        let synonyms = bytes[1..]
+            .chunks(4)
+            .map(|ord| {
+                let ord = u32::from_be_bytes([ord[0], ord[1], ord[2], ord[3]]);
+                self.outputs[ord as usize].as_slice()
+            })
+            .collect();
+        (bytes[0] != 0, synonyms)
+    }
+}
+
+impl fmt::Debug for SynonymMap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SynonymMap")
+            .field("outputs", &self.outputs.len())
+            .finish()
+    }
+}
+
+// a token of a graph, from the node of its `start` position to the one of its
+// `end` position
+#[derive(Debug, Clone)]
+struct GraphToken {
+    term: Vec<u8>,
+    start: i32,
+    end: i32,
+    start_offset: usize,
+    end_offset: usize,
+}
+
+// reads the next token of `input`, starting after the token at `position`
+fn read_token(input: &mut dyn TokenStream, position: i32) -> Result<Option<GraphToken>> {
+    if !input.increment_token()? {
+        return Ok(None);
+    }
+    let start = position + input.position_attribute_mut().get_position_increment() as i32;
+    let length = input
+        .position_length_attribute()
+        .map_or(1, |attr| attr.get_position_length() as i32);
+    let term = input
+        .term_bytes_attribute()
+        .get_bytes_ref()
+        .bytes()
+        .to_vec();
+    let offsets = input.offset_attribute();
+    Ok(Some(GraphToken {
+        term,
+        start,
+        end: start + length,
+        start_offset: offsets.start_offset(),
+        end_offset: offsets.end_offset(),
+    }))
+}
+
+/// Adds the synonyms of a `SynonymMap` to a token stream, matching the
+/// longest input at each token.
+///
+/// The synonyms of several words make a token graph: the words of each
+/// synonym, and of the matched input when it is kept, follow their own path
+/// from the position of the first matched token to the one after the last,
+/// the tokens spanning more than one position telling it with their position
+/// length. For "ny" mapped to "new york", "new" and "ny" are at the same
+/// position, "ny" spanning two, and "york" is at the next one.
+///
+/// The synonyms have the offsets of the whole matched input, the kept input
+/// tokens their own. The input can't already be a graph.
+///
+/// The query parser builds a query per path of such graphs. An index keeps a
+/// single position per token, so the index writer flattens them with a
+/// `FlattenGraphFilter`.
+#[derive(Debug)]
+pub struct SynonymGraphFilter {
+    input: Box<dyn TokenStream>,
+    map: Arc<SynonymMap>,
+    term_attr: CharTermAttribute,
+    offset_attr: OffsetAttribute,
+    position_attr: PositionIncrementAttribute,
+    position_length_attr: PositionLengthAttribute,
+    // the input tokens read ahead to match the inputs of several words
+    lookahead: VecDeque<GraphToken>,
+    input_done: bool,
+    // the position of the last token read
+    input_position: i32,
+    // the tokens to emit, at their output nodes
+    pending: VecDeque<GraphToken>,
+    // how far the output nodes are from the input positions, for the nodes
+    // added by the synonyms
+    shift: i32,
+    // the node of the last token emitted
+    last_node: i32,
+}
+
+impl SynonymGraphFilter {
+    pub fn new(input: Box<dyn TokenStream>, map: Arc<SynonymMap>) -> SynonymGraphFilter {
+        SynonymGraphFilter {
+            input,
+            map,
+            term_attr: CharTermAttribute::new(),
+            offset_attr: OffsetAttribute::new(),
+            position_attr: PositionIncrementAttribute::new(),
+            position_length_attr: PositionLengthAttribute::new(),
+            lookahead: VecDeque::new(),
+            input_done: false,
+            input_position: -1,
+            pending: VecDeque::new(),
+            shift: 0,
+            last_node: -1,
+        }
+    }
+
+    fn read_input(&mut self) -> Result<bool> {
+        if self.input_done {
+            return Ok(false);
+        }
+        match read_token(self.input.as_mut(), self.input_position)? {
+            Some(token) => {
+                if token.end - token.start > 1 {
+                    bail!(IllegalArgument(
+                        "SynonymGraphFilter can't consume a token graph".into()
+                    ));
+                }
+                self.input_position = token.start;
+                self.lookahead.push_back(token);
+                Ok(true)
+            }
+            None => {
+                self.input_done = true;
+                Ok(false)
+            }
+        }
+    }
+
+    // the term of the `i`th token read ahead, when it follows the previous
+    // one so that they may be the words of an input
+    fn lookahead_term(&mut self, i: usize) -> Result<Option<Vec<u8>>> {
+        if i == self.lookahead.len() && !self.read_input()? {
+            return Ok(None);
+        }
+        let token = &self.lookahead[i];
+        if i > 0 && token.start != self.lookahead[i - 1].start + 1 {
+            return Ok(None);
+        }
+        Ok(Some(token.term.clone()))
+    }
+
+    // queues the first token read ahead, with the synonyms of the longest
+    // input it starts
+    fn process(&mut self) -> Result<()> {
+        let map = Arc::clone(&self.map);
+        let longest = map.longest_match(&mut |i| self.lookahead_term(i))?;
+        let (len, output) = match longest {
+            Some(longest) => longest,
+            None => {
+                let mut token = self.lookahead.pop_front().unwrap();
+                token.start += self.shift;
+                token.end += self.shift;
+                self.pending.push_back(token);
+                return Ok(());
+            }
+        };
+        let matched: Vec<GraphToken> = self.lookahead.drain(..len).collect();
+        let (keep_original, synonyms) = map.decode(&output);
+        let mut paths: Vec<Vec<(&[u8], usize, usize)>> = synonyms
+            .iter()
+            .map(|words| {
+                let (start_offset, end_offset) =
+                    (matched[0].start_offset, matched[len - 1].end_offset);
+                words
+                    .iter()
+                    .map(|w| (w.as_slice(), start_offset, end_offset))
+                    .collect()
+            })
+            .collect();
+        if keep_original {
+            paths.push(
+                matched
+                    .iter()
+                    .map(|t| (t.term.as_slice(), t.start_offset, t.end_offset))
+                    .collect(),
+            );
+        }
+
+        // the paths share the first and the last node, the nodes within a
+        // path are its own
+        let start = matched[0].start + self.shift;
+        let inner_nodes: usize = paths.iter().map(|p| p.len() - 1).sum();
+        let end = start + inner_nodes as i32 + 1;
+        let mut next_node = start + 1;
+        let mut tokens = Vec::with_capacity(inner_nodes + paths.len());
+        for path in paths {
+            let mut from = start;
+            for (i, (term, start_offset, end_offset)) in path.iter().enumerate() {
+                let to = if i + 1 == path.len() {
+                    end
+                } else {
+                    next_node += 1;
+                    next_node - 1
+                };
+                tokens.push(GraphToken {
+                    term: term.to_vec(),
+                    start: from,
+                    end: to,
+                    start_offset: *start_offset,
+                    end_offset: *end_offset,
+                });
+                from = to;
+            }
+        }
+        tokens.sort_by_key(|t| t.start);
+        self.pending.extend(tokens);
+        self.shift = end - (matched[len - 1].start + 1);
+        Ok(())
+    }
+}
+
+impl TokenStream for SynonymGraphFilter {
+    fn increment_token(&mut self) -> Result<bool> {
+        if self.pending.is_empty() {
+            if self.lookahead.is_empty() && !self.read_input()? {
+                return Ok(false);
+            }
+            self.process()?;
+        }
+        let token = self.pending.pop_front().unwrap();
+        self.clear_attributes();
+        self.term_attr.append(str::from_utf8(&token.term)?);
+        self.offset_attr
+            .set_offset(token.start_offset, token.end_offset)?;
+        self.position_attr
+            .set_position_increment((token.start - self.last_node) as u32);
+        self.position_length_attr
+            .set_position_length((token.end - token.start) as u32);
+        self.last_node = token.start;
+        Ok(true)
+    }
+
+    fn end(&mut self) -> Result<()> {
+        self.input.end()?;
+        self.end_attributes();
+        // the end position of the input, moved by the synonyms
+        let increment = self.input.position_attribute_mut().get_position_increment() as i32;
+        let end = self.input_position + increment + self.shift;
+        self.position_attr
+            .set_position_increment((end - self.last_node).max(0) as u32);
+        let offsets = self.input.offset_attribute();
+        let (start_offset, end_offset) = (offsets.start_offset(), offsets.end_offset());
+        self.offset_attr.set_offset(start_offset, end_offset)
+    }
+
+    fn reset(&mut self) -> Result<()> {
+        self.lookahead.clear();
+        self.input_done = false;
+        self.input_position = -1;
+        self.pending.clear();
+        self.shift = 0;
+        self.last_node = -1;
+        self.input.reset()
+    }
+
+    fn offset_attribute_mut(&mut self) -> &mut OffsetAttribute {
+        &mut self.offset_attr
+    }
+
+    fn offset_attribute(&self) -> &OffsetAttribute {
+        &self.offset_attr
+    }
+
+    fn position_attribute_mut(&mut self) -> &mut PositionIncrementAttribute {
+        &mut self.position_attr
+    }
+
+    fn position_length_attribute_mut(&mut self) -> Option<&mut PositionLengthAttribute> {
+        Some(&mut self.position_length_attr)
+    }
+
+    fn position_length_attribute(&self) -> Option<&PositionLengthAttribute> {
+        Some(&self.position_length_attr)
+    }
+
+    fn term_bytes_attribute_mut(&mut self) -> &mut TermToBytesRefAttribute {
+        &mut self.term_attr
+    }
+
+    fn term_bytes_attribute(&self) -> &TermToBytesRefAttribute {
+        &self.term_attr
+    }
+}
+
+/// Flattens a token graph, e.g. of a `SynonymGraphFilter`, into tokens at a
+/// single position each, as the index keeps them.
+///
+/// Each node of the graph is moved to the position at the end of the longest
+/// path leading to it, so that the paths meet again where they did in the
+/// graph: for "usa" mapped to "united states" and "united states of america",
+/// both "states" are at the position after "united", "of" and "america" at
+/// the two next ones, and the next token after "america". A phrase of a
+/// shorter path may then not match, e.g. "united states" followed by the next
+/// token. The tokens at a position share its first start offset, so that the
+/// offsets don't go backwards.
+///
+/// Being flat, the output has no position length.
+#[derive(Debug)]
+pub struct FlattenGraphFilter {
+    input: Box<dyn TokenStream>,
+    term_attr: CharTermAttribute,
+    offset_attr: OffsetAttribute,
+    position_attr: PositionIncrementAttribute,
+    input_done: bool,
+    // the position of the last token read
+    input_position: i32,
+    // the tokens read of a part of the graph no token skips over
+    segment: Vec<GraphToken>,
+    segment_end: i32,
+    // the token read after the segment
+    next: Option<GraphToken>,
+    // the last node of the segments flattened so far, before and after
+    last_input_node: i32,
+    last_output_node: i32,
+    // the flattened tokens to emit, at their positions
+    pending: VecDeque<GraphToken>,
+    last_position: i32,
+    last_start_offset: usize,
+}
+
+impl FlattenGraphFilter {
+    pub fn new(input: Box<dyn TokenStream>) -> FlattenGraphFilter {
+        FlattenGraphFilter {
+            input,
+            term_attr: CharTermAttribute::new(),
+            offset_attr: OffsetAttribute::new(),
+            position_attr: PositionIncrementAttribute::new(),
+            input_done: false,
+            input_position: -1,
+            segment: Vec::new(),
+            segment_end: -1,
+            next: None,
+            last_input_node: -1,
+            last_output_node: -1,
+            pending: VecDeque::new(),
+            last_position: -1,
+            last_start_offset: 0,
+        }
+    }
+
+    fn read_input(&mut self) -> Result<Option<GraphToken>> {
+        if let Some(token) = self.next.take() {
+            return Ok(Some(token));
+        }
+        if self.input_done {
+            return Ok(None);
+        }
+        let token = read_token(self.input.as_mut(), self.input_position)?;
+        match token {
+            Some(ref token) => self.input_position = token.start,
+            None => self.input_done = true,
+        }
+        Ok(token)
+    }
+
+    // the output position of an input node
+    fn output_node(&self, node: i32) -> i32 {
+        self.last_output_node + (node - self.last_input_node)
+    }
+
+    // reads the tokens up to the next one starting after the end of all the
+    // previous ones, and flattens them
+    fn flatten_segment(&mut self) -> Result<()> {
+        while let Some(token) = self.read_input()? {
+            if !self.segment.is_empty() && token.start >= self.segment_end {
+                self.next = Some(token);
+                break;
+            }
+            self.segment_end = self.segment_end.max(token.end);
+            self.segment.push(token);
+        }
+        if self.segment.is_empty() {
+            return Ok(());
+        }
+
+        let mut incoming: BTreeMap<i32, Vec<i32>> = BTreeMap::new();
+        let mut nodes = BTreeSet::new();
+        for token in &self.segment {
+            nodes.insert(token.start);
+            nodes.insert(token.end);
+            incoming.entry(token.end).or_default().push(token.start);
+        }
+        let mut positions: HashMap<i32, i32> = HashMap::new();
+        let mut previous: Option<(i32, i32)> = None;
+        for &node in &nodes {
+            // a node reached by no token, the first one or one after a hole,
+            // keeps its distance to the previous node
+            let position = match incoming.get(&node) {
+                Some(starts) => starts.iter().map(|s| positions[s] + 1).max().unwrap(),
+                None => match previous {
+                    Some((node_before, position)) => position + (node - node_before),
+                    None => self.output_node(node),
+                },
+            };
+            positions.insert(node, position);
+            previous = Some((node, position));
+        }
+        self.last_output_node = positions[&self.segment_end];
+        self.last_input_node = self.segment_end;
+
+        let mut tokens: Vec<GraphToken> = self
+            .segment
+            .drain(..)
+            .map(|mut t| {
+                t.start = positions[&t.start];
+                t.end = positions[&t.end];
+                t
+            })
+            .collect();
+        tokens.sort_by_key(|t| t.start);
+        let mut start_offsets: HashMap<i32, usize> = HashMap::new();
+        for token in &mut tokens {
+            let last_start_offset = self.last_start_offset;
+            let start_offset = *start_offsets
+                .entry(token.start)
+                .or_insert_with(|| token.start_offset.max(last_start_offset));
+            token.start_offset = start_offset;
+            token.end_offset = token.end_offset.max(start_offset);
+            self.last_start_offset = start_offset;
+        }
+        self.pending.extend(tokens);
+        Ok(())
+    }
+}
+
+impl TokenStream for FlattenGraphFilter {
+    fn increment_token(&mut self) -> Result<bool> {
+        if self.pending.is_empty() {
+            self.flatten_segment()?;
+        }
+        let token = match self.pending.pop_front() {
+            Some(token) => token,
+            None => return Ok(false),
+        };
+        self.clear_attributes();
+        self.term_attr.append(str::from_utf8(&token.term)?);
+        self.offset_attr
+            .set_offset(token.start_offset, token.end_offset)?;
+        self.position_attr
+            .set_position_increment((token.start - self.last_position) as u32);
+        self.last_position = token.start;
+        Ok(true)
+    }
+
+    fn end(&mut self) -> Result<()> {
+        self.input.end()?;
+        self.end_attributes();
+        let increment = self.input.position_attribute_mut().get_position_increment() as i32;
+        let end = self.output_node(self.input_position + increment);
+        self.position_attr
+            .set_position_increment((end - self.last_position).max(0) as u32);
+        let offsets = self.input.offset_attribute();
+        let (start_offset, end_offset) = (offsets.start_offset(), offsets.end_offset());
+        self.offset_attr.set_offset(start_offset, end_offset)
+    }
+
+    fn reset(&mut self) -> Result<()> {
+        self.input_done = false;
+        self.input_position = -1;
+        self.segment.clear();
+        self.segment_end = -1;
+        self.next = None;
+        self.last_input_node = -1;
+        self.last_output_node = -1;
+        self.pending.clear();
+        self.last_position = -1;
+        self.last_start_offset = 0;
+        self.input.reset()
+    }
+
+    fn offset_attribute_mut(&mut self) -> &mut OffsetAttribute {
+        &mut self.offset_attr
+    }
+
+    fn offset_attribute(&self) -> &OffsetAttribute {
+        &self.offset_attr
+    }
+
+    fn position_attribute_mut(&mut self) -> &mut PositionIncrementAttribute {
+        &mut self.position_attr
+    }
+
+    fn term_bytes_attribute_mut(&mut self) -> &mut TermToBytesRefAttribute {
+        &mut self.term_attr
+    }
+
+    fn term_bytes_attribute(&self) -> &TermToBytesRefAttribute {
+        &self.term_attr
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::analysis::standard_tokenizer::StandardTokenizer;
+    use core::analysis::LowerCaseFilter;
+
+    // the terms with their position increments and lengths, and offsets
+    fn tokens(stream: &mut dyn TokenStream) -> Vec<(String, u32, u32, usize, usize)> {
+        stream.reset().unwrap();
+        let mut tokens = vec![];
+        while stream.increment_token().unwrap() {
+            let increment = stream.position_attribute_mut().get_position_increment();
+            let length = stream
+                .position_length_attribute()
+                .map_or(1, |attr| attr.get_position_length());
+            let term = stream.term_bytes_attribute().get_bytes_ref();
+            let offsets = stream.offset_attribute();
+            tokens.push((
+                String::from_utf8(term.bytes().to_vec()).unwrap(),
+                increment,
+                length,
+                offsets.start_offset(),
+                offsets.end_offset(),
+            ));
+        }
+        stream.end().unwrap();
+        tokens
+    }
+
+    fn token(
+        term: &str,
+        increment: u32,
+        length: u32,
+        start: usize,
+        end: usize,
+    ) -> (String, u32, u32, usize, usize) {
+        (term.to_string(), increment, length, start, end)
+    }
+
+    fn synonyms(text: &str, map: &Arc<SynonymMap>) -> Box<dyn TokenStream> {
+        let tokenizer = Box::new(StandardTokenizer::new(text));
+        Box::new(SynonymGraphFilter::new(
+            Box::new(LowerCaseFilter::new(tokenizer)),
+            Arc::clone(map),
+        ))
+    }
+
+    fn synonym_map(rules: &[(&str, &str, bool)]) -> Arc<SynonymMap> {
+        let mut builder = SynonymMap::builder();
+        for &(input, output, keep_original) in rules {
+            builder.add(input, output, keep_original).unwrap();
+        }
+        Arc::new(builder.build().unwrap())
+    }
+
+    #[test]
+    fn test_synonym_map_builder() {
+        let mut builder = SynonymMap::builder();
+        assert!(builder.add(" ", "a", true).is_err());
+        assert!(builder.add("a", "", true).is_err());
+        assert!(builder.add("a\0b", "c", true).is_err());
+        assert!(builder.build().unwrap().is_empty());
+
+        // deduplicated synonyms
+        let map = synonym_map(&[("a", "b", false), ("a", "b", false), ("a", "c", false)]);
+        assert_eq!(
+            tokens(synonyms("a", &map).as_mut()),
+            vec![token("b", 1, 1, 0, 1), token("c", 0, 1, 0, 1)]
+        );
+    }
+
+    #[test]
+    fn test_single_word_synonyms() {
+        let map = synonym_map(&[("fast", "quick", true), ("car", "auto", false)]);
+        assert_eq!(
+            tokens(synonyms("A fast red car", &map).as_mut()),
+            vec![
+                token("a", 1, 1, 0, 1),
+                token("quick", 1, 1, 2, 6),
+                token("fast", 0, 1, 2, 6),
+                token("red", 1, 1, 7, 10),
+                token("auto", 1, 1, 11, 14),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_multi_word_synonyms() {
+        let map = synonym_map(&[
+            ("ny", "new york", true),
+            ("new york", "ny", true),
+            ("new", "fresh", false),
+        ]);
+        // the synonyms have the offsets of the input they replace
+        assert_eq!(
+            tokens(synonyms("I love NY pizza", &map).as_mut()),
+            vec![
+                token("i", 1, 1, 0, 1),
+                token("love", 1, 1, 2, 6),
+                token("new", 1, 1, 7, 9),
+                token("ny", 0, 2, 7, 9),
+                token("york", 1, 1, 7, 9),
+                token("pizza", 1, 1, 10, 15),
+            ]
+        );
+        // the longest input wins
+        assert_eq!(
+            tokens(synonyms("new york city", &map).as_mut()),
+            vec![
+                token("ny", 1, 2, 0, 8),
+                token("new", 0, 1, 0, 3),
+                token("york", 1, 1, 4, 8),
+                token("city", 1, 1, 9, 13),
+            ]
+        );
+        assert_eq!(
+            tokens(synonyms("new yorker", &map).as_mut()),
+            vec![token("fresh", 1, 1, 0, 3), token("yorker", 1, 1, 4, 10)]
+        );
+
+        // each synonym has its own path
+        let map = synonym_map(&[
+            ("usa", "united states", true),
+            ("usa", "united states of america", true),
+        ]);
+        let usa = vec![
+            token("united", 1, 1, 0, 3),
+            token("united", 0, 2, 0, 3),
+            token("usa", 0, 5, 0, 3),
+            token("states", 1, 4, 0, 3),
+            token("states", 1, 1, 0, 3),
+            token("of", 1, 1, 0, 3),
+            token("america", 1, 1, 0, 3),
+            token("today", 1, 1, 4, 9),
+        ];
+        assert_eq!(tokens(synonyms("USA today", &map).as_mut()), usa);
+        // the stream is reusable
+        let mut stream = synonyms("USA today", &map);
+        assert_eq!(tokens(stream.as_mut()), usa);
+        assert_eq!(stream.offset_attribute().end_offset(), 9);
+        assert_eq!(tokens(stream.as_mut()), usa);
+    }
+
+    #[test]
+    fn test_flatten_graph() {
+        let map = synonym_map(&[
+            ("ny", "new york", true),
+            ("usa", "united states", true),
+            ("usa", "united states of america", true),
+        ]);
+        let flatten = |text: &str| {
+            let mut flattened = FlattenGraphFilter::new(synonyms(text, &map));
+            assert!(flattened.position_length_attribute().is_none());
+            tokens(&mut flattened)
+        };
+        assert_eq!(
+            flatten("NY pizza"),
+            vec![
+                token("new", 1, 1, 0, 2),
+                token("ny", 0, 1, 0, 2),
+                token("york", 1, 1, 0, 2),
+                token("pizza", 1, 1, 3, 8),
+            ]
+        );
+        // the paths meet again at the end of the longest one
+        assert_eq!(
+            flatten("USA today"),
+            vec![
+                token("united", 1, 1, 0, 3),
+                token("united", 0, 1, 0, 3),
+                token("usa", 0, 1, 0, 3),
+                token("states", 1, 1, 0, 3),
+                token("states", 0, 1, 0, 3),
+                token("of", 1, 1, 0, 3),
+                token("america", 1, 1, 0, 3),
+                token("today", 1, 1, 4, 9),
+            ]
+        );
+        // a flat stream is left as is
+        assert_eq!(
+            flatten("the quick fox"),
+            vec![
+                token("the", 1, 1, 0, 3),
+                token("quick", 1, 1, 4, 9),
+                token("fox", 1, 1, 10, 13),
+            ]
+        );
+    }
+}
@@ -0,0 +1,608 @@
+// Copyright 2019 Zhizhesihai (Beijing) Technology Limited.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use core::analysis::TokenStream;
+use core::attribute::{CharTermAttribute, OffsetAttribute, PositionIncrementAttribute};
+use core::attribute::{PayloadAttribute, PositionLengthAttribute, TermToBytesRefAttribute};
+
+use error::Result;
+
+use std::collections::HashSet;
+use std::str;
+use std::sync::Arc;
+
+/// The stop words removed by `StandardAnalyzer` by default.
+pub const ENGLISH_STOP_WORDS: [&str; 33] = [
+    "a", "an", "and", "are", "as", "at", "be", "but", "by", "for", "if", "in", "into", "is", "it",
+    "no", "not", "of", "on", "or", "such", "that", "the", "their", "then", "there", "these",
+    "they", "this", "to", "was", "will", "with",
+];
+
+/// Lowercases the terms of a token stream.
+#[derive(Debug)]
+pub struct LowerCaseFilter {
+    input: Box<dyn TokenStream>,
+    term_attr: CharTermAttribute,
+}
+
+impl LowerCaseFilter {
+    pub fn new(input: Box<dyn TokenStream>) -> LowerCaseFilter {
+        LowerCaseFilter {
+            input,
+            term_attr: CharTermAttribute::new(),
+        }
+    }
+}
+
+impl TokenStream for LowerCaseFilter {
+    fn increment_token(&mut self) -> Result<bool> {
+        if !self.input.increment_token()? {
+            return Ok(false);
+        }
+        self.term_attr.clear();
+        let term = self.input.term_bytes_attribute().get_bytes_ref();
+        for c in str::from_utf8(term.bytes())?.chars() {
+            for lower in c.to_lowercase() {
+                self.term_attr.push_char(lower);
+            }
+        }
+        Ok(true)
+    }
+
+    fn end(&mut self) -> Result<()> {
+        self.term_attr.end();
+        self.input.end()
+    }
+
+    fn reset(&mut self) -> Result<()> {
+        self.input.reset()
+    }
+
+    fn offset_attribute_mut(&mut self) -> &mut OffsetAttribute {
+        self.input.offset_attribute_mut()
+    }
+
+    fn offset_attribute(&self) -> &OffsetAttribute {
+        self.input.offset_attribute()
+    }
+
+    fn position_attribute_mut(&mut self) -> &mut PositionIncrementAttribute {
+        self.input.position_attribute_mut()
+    }
+
+    fn position_length_attribute_mut(&mut self) -> Option<&mut PositionLengthAttribute> {
+        self.input.position_length_attribute_mut()
+    }
+
+    fn position_length_attribute(&self) -> Option<&PositionLengthAttribute> {
+        self.input.position_length_attribute()
+    }
+
+    fn payload_attribute_mut(&mut self) -> Option<&mut PayloadAttribute> {
+        self.input.payload_attribute_mut()
+    }
+
+    fn payload_attribute(&self) -> Option<&PayloadAttribute> {
+        self.input.payload_attribute()
+    }
+
+    fn term_bytes_attribute_mut(&mut self) -> &mut TermToBytesRefAttribute {
+        &mut self.term_attr
+    }
+
+    fn term_bytes_attribute(&self) -> &TermToBytesRefAttribute {
+        &self.term_attr
+    }
+}
+
+/// Removes the stop words from a token stream.
+///
+/// The positions of the removed tokens are kept: the position increment of
+/// the token following stop words, or of the end of the stream, is increased
+/// by theirs so that phrase queries don't match across them.
+#[derive(Debug)]
+pub struct StopFilter {
+    input: Box<dyn TokenStream>,
+    stop_words: Arc<HashSet<String>>,
+    skipped_positions: u32,
+}
+
+impl StopFilter {
+    pub fn new(input: Box<dyn TokenStream>, stop_words: Arc<HashSet<String>>) -> StopFilter {
+        StopFilter {
+            input,
+            stop_words,
+            skipped_positions: 0,
+        }
+    }
+
+    fn is_stop_word(&self) -> bool {
+        let term = self.input.term_bytes_attribute().get_bytes_ref();
+        match str::from_utf8(term.bytes()) {
+            Ok(term) => self.stop_words.contains(term),
+            Err(_) => false,
+        }
+    }
+
+    // adds the positions of the stop words removed before the current token
+    fn apply_skipped_positions(&mut self) {
+        if self.skipped_positions > 0 {
+            let position_attr = self.input.position_attribute_mut();
+            let increment = position_attr.get_position_increment();
+            position_attr.set_position_increment(increment + self.skipped_positions);
+            self.skipped_positions = 0;
+        }
+    }
+}
+
+impl TokenStream for StopFilter {
+    fn increment_token(&mut self) -> Result<bool> {
+        while self.input.increment_token()? {
+            if !self.is_stop_word() {
+                self.apply_skipped_positions();
+                return Ok(true);
+            }
+            self.skipped_positions += self.input.position_attribute_mut().get_position_increment();
+        }
+        Ok(false)
+    }
+
+    fn end(&mut self) -> Result<()> {
+        self.input.end()?;
+        self.apply_skipped_positions();
+        Ok(())
+    }
+
+    fn reset(&mut self) -> Result<()> {
+        self.skipped_positions = 0;
+        self.input.reset()
+    }
+
+    fn offset_attribute_mut(&mut self) -> &mut OffsetAttribute {
+        self.input.offset_attribute_mut()
+    }
+
+    fn offset_attribute(&self) -> &OffsetAttribute {
+        self.input.offset_attribute()
+    }
+
+    fn position_attribute_mut(&mut self) -> &mut PositionIncrementAttribute {
+        self.input.position_attribute_mut()
+    }
+
+    fn position_length_attribute_mut(&mut self) -> Option<&mut PositionLengthAttribute> {
+        self.input.position_length_attribute_mut()
+    }
+
+    fn position_length_attribute(&self) -> Option<&PositionLengthAttribute> {
+        self.input.position_length_attribute()
+    }
+
+    fn payload_attribute_mut(&mut self) -> Option<&mut PayloadAttribute> {
+        self.input.payload_attribute_mut()
+    }
+
+    fn payload_attribute(&self) -> Option<&PayloadAttribute> {
+        self.input.payload_attribute()
+    }
+
+    fn term_bytes_attribute_mut(&mut self) -> &mut TermToBytesRefAttribute {
+        self.input.term_bytes_attribute_mut()
+    }
+
+    fn term_bytes_attribute(&self) -> &TermToBytesRefAttribute {
+        self.input.term_bytes_attribute()
+    }
+}
+
+// the ASCII equivalent of the Latin chars with diacritics, ligatures and
+// the other letters without an ASCII decomposition
+fn fold_to_ascii(c: char) -> Option<&'static str> {
+    let folded = match c {
+        'À' | 'Á' | 'Â' | 'Ã' | 'Ä' | 'Å' | 'Ā' | 'Ă' | 'Ą' => "A",
+        'à' | 'á' | 'â' | 'ã' | 'ä' | 'å' | 'ā' | 'ă' | 'ą' => "a",
+        'Æ' => "AE",
+        'æ' => "ae",
+        'Ç' | 'Ć' | 'Ĉ' | 'Ċ' | 'Č' => "C",
+        'ç' | 'ć' | 'ĉ' | 'ċ' | 'č' => "c",
+        'Ð' | 'Ď' | 'Đ' => "D",
+        'ð' | 'ď' | 'đ' => "d",
+        'È' | 'É' | 'Ê' | 'Ë' | 'Ē' | 'Ĕ' | 'Ė' | 'Ę' | 'Ě' => "E",
+        'è' | 'é' | 'ê' | 'ë' | 'ē' | 'ĕ' | 'ė' | 'ę' | 'ě' => "e",
+        'ƒ' => "f",
+        'Ĝ' | 'Ğ' | 'Ġ' | 'Ģ' => "G",
+        'ĝ' | 'ğ' | 'ġ' | 'ģ' => "g",
+        'Ĥ' | 'Ħ' => "H",
+        'ĥ' | 'ħ' => "h",
+        'Ì' | 'Í' | 'Î' | 'Ï' | 'Ĩ' | 'Ī' | 'Ĭ' | 'Į' | 'İ' => "I",
+        'ì' | 'í' | 'î' | 'ï' | 'ĩ' | 'ī' | 'ĭ' | 'į' | 'ı' => "i",
+        'Ĳ' => "IJ",
+        'ĳ' => "ij",
+        'Ĵ' => "J",
+        'ĵ' => "j",
+        'Ķ' => "K",
+        'ķ' => "k",
+        'ĸ' => "q",
+        'Ĺ' | 'Ļ' | 'Ľ' | 'Ŀ' | 'Ł' => "L",
+        'ĺ' | 'ļ' | 'ľ' | 'ŀ' | 'ł' => "l",
+        'Ñ' | 'Ń' | 'Ņ' | 'Ň' | 'Ŋ' => "N",
+        'ñ' | 'ń' | 'ņ' | 'ň' | 'ŋ' => "n",
+        'ŉ' => "'n",
+        'Ò' | 'Ó' | 'Ô' | 'Õ' | 'Ö' | 'Ø' | 'Ō' | 'Ŏ' | 'Ő' => "O",
+        'ò' | 'ó' | 'ô' | 'õ' | 'ö' | 'ø' | 'ō' | 'ŏ' | 'ő' => "o",
+        'Œ' => "OE",
+        'œ' => "oe",
+        'Ŕ' | 'Ŗ' | 'Ř' => "R",
+        'ŕ' | 'ŗ' | 'ř' => "r",
+        'Ś' | 'Ŝ' | 'Ş' | 'Š' => "S",
+        'ś' | 'ŝ' | 'ş' | 'š' | 'ſ' => "s",
+        'ß' => "ss",
+        'Ţ' | 'Ť' | 'Ŧ' => "T",
+        'ţ' | 'ť' | 'ŧ' => "t",
+        'Þ' => "TH",
+        'þ' => "th",
+        'Ù' | 'Ú' | 'Û' | 'Ü' | 'Ũ' | 'Ū' | 'Ŭ' | 'Ů' | 'Ű' | 'Ų' => "U",
+        'ù' | 'ú' | 'û' | 'ü' | 'ũ' | 'ū' | 'ŭ' | 'ů' | 'ű' | 'ų' => "u",
+        'Ŵ' => "W",
+        'ŵ' => "w",
+        'Ý' | 'Ŷ' | 'Ÿ' => "Y",
+        'ý' | 'ÿ' | 'ŷ' => "y",
+        'Ź' | 'Ż' | 'Ž' => "Z",
+        'ź' | 'ż' | 'ž' => "z",
+        _ => return None,
+    };
+    Some(folded)
+}
+
+/// Folds the Latin chars with diacritics of the terms into their ASCII
+/// equivalent, "Crème brûlée" into "Creme brulee".
+///
+/// Only the terms change, the offsets still point at the original text.
+#[derive(Debug)]
+pub struct ASCIIFoldingFilter {
+    input: Box<dyn TokenStream>,
+    term_attr: CharTermAttribute,
+}
+
+impl ASCIIFoldingFilter {
+    pub fn new(input: Box<dyn TokenStream>) -> ASCIIFoldingFilter {
+        ASCIIFoldingFilter {
+            input,
+            term_attr: CharTermAttribute::new(),
+        }
+    }
+}
+
+impl TokenStream for ASCIIFoldingFilter {
+    fn increment_token(&mut self) -> Result<bool> {
+        if !self.input.increment_token()? {
+            return Ok(false);
+        }
+        self.term_attr.clear();
+        let term = self.input.term_bytes_attribute().get_bytes_ref();
+        for c in str::from_utf8(term.bytes())?.chars() {
+            match fold_to_ascii(c) {
+                Some(folded) => self.term_attr.append(folded),
+                None => self.term_attr.push_char(c),
+            }
+        }
+        Ok(true)
+    }
+
+    fn end(&mut self) -> Result<()> {
+        self.term_attr.end();
+        self.input.end()
+    }
+
+    fn reset(&mut self) -> Result<()> {
+        self.input.reset()
+    }
+
+    fn offset_attribute_mut(&mut self) -> &mut OffsetAttribute {
+        self.input.offset_attribute_mut()
+    }
+
+    fn offset_attribute(&self) -> &OffsetAttribute {
+        self.input.offset_attribute()
+    }
+
+    fn position_attribute_mut(&mut self) -> &mut PositionIncrementAttribute {
+        self.input.position_attribute_mut()
+    }
+
+    fn position_length_attribute_mut(&mut self) -> Option<&mut PositionLengthAttribute> {
+        self.input.position_length_attribute_mut()
+    }
+
+    fn position_length_attribute(&self) -> Option<&PositionLengthAttribute> {
+        self.input.position_length_attribute()
+    }
+
+    fn payload_attribute_mut(&mut self) -> Option<&mut PayloadAttribute> {
+        self.input.payload_attribute_mut()
+    }
+
+    fn payload_attribute(&self) -> Option<&PayloadAttribute> {
+        self.input.payload_attribute()
+    }
+
+    fn term_bytes_attribute_mut(&mut self) -> &mut TermToBytesRefAttribute {
+        &mut self.term_attr
+    }
+
+    fn term_bytes_attribute(&self) -> &TermToBytesRefAttribute {
+        &self.term_attr
+    }
+}
+
+/// Replaces each token with its prefixes of `min_gram` to `max_gram` chars,
+/// to match the words by their beginning with term queries.
+///
+/// The prefixes of a token share its offsets and its position: all but the
+/// first have a position increment of 0. With `preserve_original` the
+/// tokens shorter than `min_gram` or longer than `max_gram` chars are also
+/// kept whole, after their prefixes. The positions of the tokens dropped
+/// for being too short are added to the next token.
+#[derive(Debug)]
+pub struct EdgeNGramTokenFilter {
+    input: Box<dyn TokenStream>,
+    min_gram: usize,
+    max_gram: usize,
+    preserve_original: bool,
+    term_attr: CharTermAttribute,
+    // the chars of the current input token
+    chars: Vec<char>,
+    // the length of the next prefix to emit, no prefix is left once it is
+    // greater than `last_gram_len`
+    gram_len: usize,
+    last_gram_len: usize,
+    pending_original: bool,
+    // whether a token was emitted at the position of the current input token
+    emitted: bool,
+    // the positions of the input tokens too short to have a prefix
+    skipped_positions: u32,
+}
+
+impl EdgeNGramTokenFilter {
+    pub fn new(
+        input: Box<dyn TokenStream>,
+        min_gram: usize,
+        max_gram: usize,
+        preserve_original: bool,
+    ) -> EdgeNGramTokenFilter {
+        assert!(min_gram > 0 && min_gram <= max_gram);
+        EdgeNGramTokenFilter {
+            input,
+            min_gram,
+            max_gram,
+            preserve_original,
+            term_attr: CharTermAttribute::new(),
+            chars: Vec::new(),
+            gram_len: 1,
+            last_gram_len: 0,
+            pending_original: false,
+            emitted: false,
+            skipped_positions: 0,
+        }
+    }
+
+    // sets the term to the first `len` chars of the input token
+    fn emit(&mut self, len: usize) {
+        self.term_attr.clear();
+        for &c in &self.chars[..len] {
+            self.term_attr.push_char(c);
+        }
+        let position_attr = self.input.position_attribute_mut();
+        if self.emitted {
+            position_attr.set_position_increment(0);
+        } else if self.skipped_positions > 0 {
+            let increment = position_attr.get_position_increment();
+            position_attr.set_position_increment(increment + self.skipped_positions);
+            self.skipped_positions = 0;
+        }
+        self.emitted = true;
+    }
+}
+
+impl TokenStream for EdgeNGramTokenFilter {
+    fn increment_token(&mut self) -> Result<bool> {
+        loop {
+            if self.gram_len <= self.last_gram_len {
+                let len = self.gram_len;
+                self.emit(len);
+                self.gram_len += 1;
+                return Ok(true);
+            }
+            if self.pending_original {
+                let len = self.chars.len();
+                self.emit(len);
+                self.pending_original = false;
+                return Ok(true);
+            }
+            if !self.input.increment_token()? {
+                return Ok(false);
+            }
+            self.chars.clear();
+            let term = self.input.term_bytes_attribute().get_bytes_ref();
+            self.chars.extend(str::from_utf8(term.bytes())?.chars());
+            let len = self.chars.len();
+            self.gram_len = self.min_gram;
+            self.last_gram_len = self.max_gram.min(len);
+            self.pending_original =
+                self.preserve_original && (len < self.min_gram || len > self.max_gram);
+            self.emitted = false;
+            if self.gram_len > self.last_gram_len && !self.pending_original {
+                self.skipped_positions +=
+                    self.input.position_attribute_mut().get_position_increment();
+            }
+        }
+    }
+
+    fn end(&mut self) -> Result<()> {
+        self.term_attr.end();
+        self.input.end()?;
+        let position_attr = self.input.position_attribute_mut();
+        let increment = position_attr.get_position_increment();
+        position_attr.set_position_increment(increment + self.skipped_positions);
+        self.skipped_positions = 0;
+        Ok(())
+    }
+
+    fn reset(&mut self) -> Result<()> {
+        self.skipped_positions = 0;
+        self.gram_len = 1;
+        self.last_gram_len = 0;
+        self.pending_original = false;
+        self.chars.clear();
+        self.input.reset()
+    }
+
+    fn offset_attribute_mut(&mut self) -> &mut OffsetAttribute {
+        self.input.offset_attribute_mut()
+    }
+
+    fn offset_attribute(&self) -> &OffsetAttribute {
+        self.input.offset_attribute()
+    }
+
+    fn position_attribute_mut(&mut self) -> &mut PositionIncrementAttribute {
+        self.input.position_attribute_mut()
+    }
+
+    fn position_length_attribute_mut(&mut self) -> Option<&mut PositionLengthAttribute> {
+        self.input.position_length_attribute_mut()
+    }
+
+    fn position_length_attribute(&self) -> Option<&PositionLengthAttribute> {
+        self.input.position_length_attribute()
+    }
+
+    fn payload_attribute_mut(&mut self) -> Option<&mut PayloadAttribute> {
+        self.input.payload_attribute_mut()
+    }
+
+    fn payload_attribute(&self) -> Option<&PayloadAttribute> {
+        self.input.payload_attribute()
+    }
+
+    fn term_bytes_attribute_mut(&mut self) -> &mut TermToBytesRefAttribute {
+        &mut self.term_attr
+    }
+
+    fn term_bytes_attribute(&self) -> &TermToBytesRefAttribute {
+        &self.term_attr
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::analysis::standard_tokenizer::StandardTokenizer;
+
+    // the terms with their position increments and offsets
+    fn tokens(mut stream: Box<dyn TokenStream>) -> Vec<(String, u32, usize, usize)> {
+        stream.reset().unwrap();
+        let mut tokens = vec![];
+        while stream.increment_token().unwrap() {
+            let increment = stream.position_attribute_mut().get_position_increment();
+            let term = stream.term_bytes_attribute().get_bytes_ref();
+            let offsets = stream.offset_attribute();
+            tokens.push((
+                String::from_utf8(term.bytes().to_vec()).unwrap(),
+                increment,
+                offsets.start_offset(),
+                offsets.end_offset(),
+            ));
+        }
+        stream.end().unwrap();
+        tokens
+    }
+
+    fn token(term: &str, increment: u32, start: usize, end: usize) -> (String, u32, usize, usize) {
+        (term.to_string(), increment, start, end)
+    }
+
+    fn edge_ngrams(
+        text: &str,
+        min_gram: usize,
+        max_gram: usize,
+        preserve: bool,
+    ) -> Box<dyn TokenStream> {
+        let tokenizer = Box::new(StandardTokenizer::new(text));
+        let folded = Box::new(ASCIIFoldingFilter::new(Box::new(LowerCaseFilter::new(
+            tokenizer,
+        ))));
+        Box::new(EdgeNGramTokenFilter::new(
+            folded, min_gram, max_gram, preserve,
+        ))
+    }
+
+    #[test]
+    fn test_ascii_folding_filter() {
+        let tokenizer = StandardTokenizer::new("Crème brûlée, Ærø straße naïve");
+        assert_eq!(
+            tokens(Box::new(ASCIIFoldingFilter::new(Box::new(tokenizer)))),
+            vec![
+                token("Creme", 1, 0, 5),
+                token("brulee", 1, 6, 12),
+                token("AEro", 1, 14, 17),
+                token("strasse", 1, 18, 24),
+                token("naive", 1, 25, 30),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_edge_ngram_filter() {
+        assert_eq!(
+            tokens(edge_ngrams("Crème ab", 1, 3, true)),
+            vec![
+                token("c", 1, 0, 5),
+                token("cr", 0, 0, 5),
+                token("cre", 0, 0, 5),
+                token("creme", 0, 0, 5),
+                token("a", 1, 6, 8),
+                token("ab", 0, 6, 8),
+            ]
+        );
+        assert_eq!(
+            tokens(edge_ngrams("Crème ab", 1, 3, false)),
+            vec![
+                token("c", 1, 0, 5),
+                token("cr", 0, 0, 5),
+                token("cre", 0, 0, 5),
+                token("a", 1, 6, 8),
+                token("ab", 0, 6, 8),
+            ]
+        );
+
+        // the too short tokens keep their position
+        assert_eq!(
+            tokens(edge_ngrams("a bc d", 2, 2, false)),
+            vec![token("bc", 2, 2, 4)]
+        );
+        let mut stream = edge_ngrams("a bc d", 2, 2, false);
+        stream.reset().unwrap();
+        while stream.increment_token().unwrap() {}
+        stream.end().unwrap();
+        assert_eq!(stream.position_attribute_mut().get_position_increment(), 1);
+        assert_eq!(
+            tokens(edge_ngrams("a bc d", 2, 2, true)),
+            vec![
+                token("a", 1, 0, 1),
+                token("bc", 1, 2, 4),
+                token("d", 1, 5, 6),
+            ]
+        );
+    }
+}
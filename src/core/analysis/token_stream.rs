@@ -13,6 +13,7 @@
 
 use std::fmt::Debug;
 
+use core::attribute::PositionLengthAttribute;
 use core::attribute::TermToBytesRefAttribute;
 use core::attribute::{OffsetAttribute, PayloadAttribute, PositionIncrementAttribute};
 
@@ -129,6 +130,9 @@ pub trait TokenStream: Debug {
     fn clear_attributes(&mut self) {
         self.offset_attribute_mut().clear();
         self.position_attribute_mut().clear();
+        if let Some(ref mut attr) = self.position_length_attribute_mut() {
+            attr.clear();
+        }
         if let Some(ref mut attr) = self.payload_attribute_mut() {
             attr.clear();
         }
@@ -138,6 +142,9 @@ pub trait TokenStream: Debug {
     fn end_attributes(&mut self) {
         self.offset_attribute_mut().end();
         self.position_attribute_mut().end();
+        if let Some(ref mut attr) = self.position_length_attribute_mut() {
+            attr.end();
+        }
         if let Some(ref mut attr) = self.payload_attribute_mut() {
             attr.end();
         }
@@ -150,6 +157,17 @@ pub trait TokenStream: Debug {
 
     fn position_attribute_mut(&mut self) -> &mut PositionIncrementAttribute;
 
+    /// The position lengths of a stream that may produce a token graph, e.g.
+    /// with a `SynonymGraphFilter`. Without it, every token spans a single
+    /// position.
+    fn position_length_attribute_mut(&mut self) -> Option<&mut PositionLengthAttribute> {
+        None
+    }
+
+    fn position_length_attribute(&self) -> Option<&PositionLengthAttribute> {
+        None
+    }
+
     fn payload_attribute_mut(&mut self) -> Option<&mut PayloadAttribute> {
         None
     }
@@ -12,7 +12,7 @@
 // limitations under the License.
 
 use core::analysis::char_buffer::CharacterBuffer;
-use core::analysis::TokenStream;
+use core::analysis::{CharFilter, TokenStream};
 use core::attribute::PositionIncrementAttribute;
 use core::attribute::TermToBytesRefAttribute;
 use core::attribute::{CharTermAttribute, OffsetAttribute};
@@ -20,16 +20,18 @@ use core::attribute::{CharTermAttribute, OffsetAttribute};
 use error::Result;
 
 use std::fmt;
-use std::io::Read;
+use std::io::{Cursor, Read};
 
 const MAX_WORD_LEN: usize = 255;
-#[allow(dead_code)]
 const IO_BUFFER_SIZE: usize = 4096;
 
 /// A tokenizer that divides text at whitespace characters as defined by
 /// {@link Character#isWhitespace(int)}.  Note: That definition explicitly excludes the
 /// non-breaking space. Adjacent sequences of non-Whitespace characters form tokens.
 ///
+/// Reading the text of a char filter, the offsets of the tokens point into
+/// the text before it.
+///
 /// @see UnicodeWhitespaceTokenizer
 pub struct WhitespaceTokenizer {
     offset: usize,
@@ -38,8 +40,10 @@ pub struct WhitespaceTokenizer {
     final_offset: usize,
     term_attr: CharTermAttribute,
     offset_attr: OffsetAttribute,
+    position_attr: PositionIncrementAttribute,
     io_buffer: CharacterBuffer,
     reader: Box<dyn Read>,
+    char_filter: Option<Box<dyn CharFilter>>,
 }
 
 impl fmt::Debug for WhitespaceTokenizer {
@@ -51,7 +55,9 @@ impl fmt::Debug for WhitespaceTokenizer {
             .field("final_offset", &self.final_offset)
             .field("term_attr", &self.term_attr)
             .field("offset_attr", &self.offset_attr)
+            .field("position_attr", &self.position_attr)
             .field("io_buffer", &self.io_buffer)
+            .field("char_filter", &self.char_filter)
             .finish()
     }
 }
@@ -65,11 +71,22 @@ impl WhitespaceTokenizer {
             final_offset: 0,
             term_attr: CharTermAttribute::new(),
             offset_attr: OffsetAttribute::new(),
-            io_buffer: CharacterBuffer::new(vec![], 0, 0),
+            position_attr: PositionIncrementAttribute::new(),
+            io_buffer: CharacterBuffer::new(vec!['\0'; IO_BUFFER_SIZE], 0, 0),
             reader,
+            char_filter: None,
         }
     }
 
+    /// Tokenizes the text of `char_filter`, the offsets of the tokens pointing
+    /// into the text it filtered.
+    pub fn with_char_filter(char_filter: Box<dyn CharFilter>) -> Self {
+        let text = char_filter.text().as_bytes().to_vec();
+        let mut tokenizer = Self::new(Box::new(Cursor::new(text)));
+        tokenizer.char_filter = Some(char_filter);
+        tokenizer
+    }
+
     pub fn is_token_char(&self, c: char) -> bool {
         !c.is_whitespace()
     }
@@ -85,10 +102,21 @@ impl WhitespaceTokenizer {
     fn clear_attributes(&mut self) {
         self.term_attr.clear();
         self.offset_attr.clear();
+        self.position_attr.clear();
     }
 
     fn correct_offset(&self, offset: usize) -> usize {
-        offset
+        match self.char_filter {
+            Some(ref char_filter) => char_filter.correct_offset(offset),
+            None => offset,
+        }
+    }
+
+    fn correct_end_offset(&self, offset: usize) -> usize {
+        match self.char_filter {
+            Some(ref char_filter) => char_filter.correct_end_offset(offset),
+            None => offset,
+        }
     }
 }
 
@@ -116,13 +144,14 @@ impl TokenStream for WhitespaceTokenizer {
             }
 
             let cur_char = self.io_buffer.char_at(self.buffer_index);
+            self.buffer_index += 1;
             if self.is_token_char(cur_char) {
                 if length == 0 {
                     debug_assert_eq!(start, -1);
-                    start = (self.offset + self.buffer_index) as isize;
-                    end = start;
+                    // `buffer_index` is already past the char
+                    start = (self.offset + self.buffer_index - 1) as isize;
                 }
-                end += 1;
+                end = (self.offset + self.buffer_index) as isize;
                 length += cur_char.len_utf8();
                 self.term_attr.push_char(cur_char);
                 if self.term_attr.char_cnt >= MAX_WORD_LEN {
@@ -135,16 +164,18 @@ impl TokenStream for WhitespaceTokenizer {
 
         assert_ne!(start, -1);
         let final_start = self.correct_offset(start as usize);
-        let final_end = self.correct_offset(end as usize);
+        let final_end = self.correct_end_offset(end as usize);
         self.final_offset = final_end;
         self.offset_attr.set_offset(final_start, final_end)?;
         Ok(true)
     }
 
     fn end(&mut self) -> Result<()> {
-        self.offset_attr.end();
         self.term_attr.end();
-        Ok(())
+        self.position_attr.end();
+        // set final offset
+        self.offset_attr
+            .set_offset(self.final_offset, self.final_offset)
     }
 
     fn reset(&mut self) -> Result<()> {
@@ -165,7 +196,7 @@ impl TokenStream for WhitespaceTokenizer {
     }
 
     fn position_attribute_mut(&mut self) -> &mut PositionIncrementAttribute {
-        unimplemented!()
+        &mut self.position_attr
     }
 
     fn term_bytes_attribute_mut(&mut self) -> &mut TermToBytesRefAttribute {
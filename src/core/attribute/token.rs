@@ -135,6 +135,43 @@ impl PositionIncrementAttribute {
     }
 }
 
+/// The number of positions a token spans, more than 1 for the tokens of a
+/// token graph skipping over other ones, e.g. a synonym of several words.
+#[derive(Debug, Clone, Copy)]
+pub struct PositionLengthAttribute {
+    position_length: u32,
+}
+
+impl Default for PositionLengthAttribute {
+    fn default() -> Self {
+        PositionLengthAttribute::new()
+    }
+}
+
+impl PositionLengthAttribute {
+    #[inline]
+    pub fn new() -> PositionLengthAttribute {
+        PositionLengthAttribute { position_length: 1 }
+    }
+
+    pub fn set_position_length(&mut self, position_length: u32) {
+        debug_assert!(position_length > 0);
+        self.position_length = position_length;
+    }
+
+    pub fn get_position_length(&self) -> u32 {
+        self.position_length
+    }
+
+    pub fn clear(&mut self) {
+        self.position_length = 1
+    }
+
+    pub fn end(&mut self) {
+        self.position_length = 1
+    }
+}
+
 #[derive(Debug)]
 pub struct PayloadAttribute {
     payload: Vec<u8>,
@@ -265,6 +302,7 @@ impl TermToBytesRefAttribute for CharTermAttribute {
 
     fn clear(&mut self) {
         self.term_length = 0;
+        self.char_cnt = 0;
     }
 
     fn end(&mut self) {
@@ -0,0 +1,471 @@
+// Copyright 2019 Zhizhesihai (Beijing) Technology Limited.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use core::store::IndexInput;
+use error::ErrorKind::CorruptIndex;
+use error::Result;
+
+use std::sync::mpsc::{self, Receiver, SyncSender, TryRecvError};
+use std::thread::{self, JoinHandle};
+
+/// The raw bytes of one block of the terms dictionary, as read by
+/// `SegmentTermsIterFrame::load_block`.
+///
+/// The suffix, stats and metadata sections are copied one after the other in
+/// a single buffer that the frame readers slice. The buffer is reused by the
+/// next read, so it only allocates when a block is larger than all the
+/// previous ones.
+#[derive(Default)]
+pub(crate) struct TermsBlockBytes {
+    pub fp: i64,
+    pub fp_end: i64,
+    // entry count << 1 | is last in floor
+    pub code: i32,
+    // suffix bytes length << 1 | is leaf block
+    pub suffix_code: i32,
+    // the sections one after the other
+    buffer: Vec<u8>,
+    // the suffix, stats and metadata ranges in `buffer`
+    sections: [(usize, usize); 3],
+}
+
+impl TermsBlockBytes {
+    /// Reads the block starting at `fp`. The lengths are checked against the
+    /// file length, because speculative reads may start at arbitrary bytes.
+    pub fn read(&mut self, input: &mut dyn IndexInput, fp: i64) -> Result<()> {
+        input.seek(fp)?;
+        self.fp = fp;
+        self.code = input.read_vint()?;
+        self.suffix_code = input.read_vint()?;
+        self.buffer.clear();
+        let num_bytes = self.suffix_code as u32 >> 1;
+        self.sections[0] = self.read_section(input, num_bytes)?;
+        let num_bytes = input.read_vint()? as u32;
+        self.sections[1] = self.read_section(input, num_bytes)?;
+        let num_bytes = input.read_vint()? as u32;
+        self.sections[2] = self.read_section(input, num_bytes)?;
+        self.fp_end = input.file_pointer();
+        Ok(())
+    }
+
+    // appends the next `num_bytes` bytes to the buffer and returns their range
+    fn read_section(
+        &mut self,
+        input: &mut dyn IndexInput,
+        num_bytes: u32,
+    ) -> Result<(usize, usize)> {
+        let fp = input.file_pointer();
+        let remaining = input.len() as i64 - fp;
+        if i64::from(num_bytes) > remaining {
+            bail!(CorruptIndex(format!(
+                "block of {} bytes exceeds the {} remaining bytes of {}",
+                num_bytes,
+                remaining,
+                input.name()
+            )));
+        }
+        let num_bytes = num_bytes as usize;
+        let from = self.buffer.len();
+        self.buffer.resize(from + num_bytes, 0);
+        input.read_bytes(&mut self.buffer, from, num_bytes)?;
+        Ok((from, from + num_bytes))
+    }
+
+    fn section(&self, i: usize) -> &[u8] {
+        let (from, to) = self.sections[i];
+        &self.buffer[from..to]
+    }
+
+    pub fn suffix_bytes(&self) -> &[u8] {
+        self.section(0)
+    }
+
+    pub fn stat_bytes(&self) -> &[u8] {
+        self.section(1)
+    }
+
+    pub fn meta_bytes(&self) -> &[u8] {
+        self.section(2)
+    }
+}
+
+type PrefetchRequest = (i64, TermsBlockBytes);
+type PrefetchResponse = (TermsBlockBytes, Result<()>);
+
+/// Double-buffered read-ahead of terms blocks for exhaustive enumerations.
+///
+/// While the current block is decoded, the block that follows it in the file is
+/// read into a standby buffer by a background thread, so that the next
+/// `load_block` doesn't wait for the IO if it asks for that block. At most one
+/// read is outstanding; blocks that weren't prefetched are read as usual.
+pub(crate) struct BlockPrefetcher {
+    requests: Option<SyncSender<PrefetchRequest>>,
+    responses: Receiver<PrefetchResponse>,
+    // file pointer of the outstanding read
+    pending: Option<i64>,
+    spare: Option<TermsBlockBytes>,
+    worker: Option<JoinHandle<()>>,
+    hits: u64,
+    misses: u64,
+}
+
+impl BlockPrefetcher {
+    pub fn new(mut input: Box<dyn IndexInput>) -> Result<BlockPrefetcher> {
+        let (requests, worker_requests) = mpsc::sync_channel::<PrefetchRequest>(1);
+        let (worker_responses, responses) = mpsc::sync_channel::<PrefetchResponse>(1);
+        let worker = thread::Builder::new()
+            .name("terms-block-prefetch".into())
+            .spawn(move || {
+                for (fp, mut block) in worker_requests {
+                    let res = block.read(input.as_mut(), fp);
+                    if worker_responses.send((block, res)).is_err() {
+                        break;
+                    }
+                }
+            })?;
+        Ok(BlockPrefetcher {
+            requests: Some(requests),
+            responses,
+            pending: None,
+            spare: None,
+            worker: Some(worker),
+            hits: 0,
+            misses: 0,
+        })
+    }
+
+    /// Starts reading the block at `fp` in the background, unless a read is
+    /// already in flight.
+    pub fn prefetch(&mut self, fp: i64) {
+        if self.pending.is_some() {
+            match self.responses.try_recv() {
+                Ok((block, _)) => {
+                    // nobody asked for it
+                    self.pending = None;
+                    self.spare = Some(block);
+                }
+                Err(TryRecvError::Empty) => return,
+                Err(TryRecvError::Disconnected) => {
+                    self.pending = None;
+                    return;
+                }
+            }
+        }
+        let block = self.spare.take().unwrap_or_default();
+        if let Some(ref requests) = self.requests {
+            if requests.send((fp, block)).is_ok() {
+                self.pending = Some(fp);
+            }
+        }
+    }
+
+    /// Returns the block at `fp` if it was prefetched, waiting for the read to
+    /// complete if needed.
+    pub fn take(&mut self, fp: i64) -> Option<TermsBlockBytes> {
+        if self.pending != Some(fp) {
+            self.misses += 1;
+            return None;
+        }
+        self.pending = None;
+        match self.responses.recv() {
+            Ok((block, Ok(()))) => {
+                self.hits += 1;
+                Some(block)
+            }
+            Ok((block, Err(e))) => {
+                debug!("prefetch of terms block at {} failed: {:?}", fp, e);
+                self.misses += 1;
+                self.spare = Some(block);
+                None
+            }
+            Err(_) => {
+                self.misses += 1;
+                None
+            }
+        }
+    }
+
+    /// Gives back the buffers of a consumed block, to be reused by the next read.
+    pub fn recycle(&mut self, block: TermsBlockBytes) {
+        self.spare = Some(block);
+    }
+
+    /// Returns the number of blocks served from the read-ahead buffer, and the
+    /// number of blocks that had to be read synchronously.
+    pub fn stats(&self) -> (u64, u64) {
+        (self.hits, self.misses)
+    }
+}
+
+impl Drop for BlockPrefetcher {
+    fn drop(&mut self) {
+        let (hits, misses) = self.stats();
+        debug!("terms block prefetch: {} hits, {} misses", hits, misses);
+        // closing the channel stops the worker once the current read is done
+        self.requests = None;
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::store::{
+        ByteArrayDataInput, DataInput, DataOutput, Directory, IOContext, IndexOutput, RAMDirectory,
+        RandomAccessInput,
+    };
+
+    use std::io::{self, Read};
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    // a clock only moving when the simulated IO or decoding advance it
+    #[derive(Default)]
+    struct ManualClock {
+        nanos: AtomicU64,
+    }
+
+    impl ManualClock {
+        fn advance(&self, duration: Duration) {
+            self.nanos
+                .fetch_add(duration.as_nanos() as u64, Ordering::AcqRel);
+        }
+
+        fn elapsed(&self) -> Duration {
+            Duration::from_nanos(self.nanos.load(Ordering::Acquire))
+        }
+    }
+
+    // simulates the latency of slow storage on every seek, on the clock of
+    // the thread reading the input
+    struct SlowIndexInput {
+        input: Box<dyn IndexInput>,
+        latency: Duration,
+        clock: Arc<ManualClock>,
+    }
+
+    impl Read for SlowIndexInput {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.input.read(buf)
+        }
+    }
+
+    impl DataInput for SlowIndexInput {
+        fn read_byte(&mut self) -> Result<u8> {
+            self.input.read_byte()
+        }
+
+        fn read_bytes(&mut self, b: &mut [u8], offset: usize, length: usize) -> Result<()> {
+            self.input.read_bytes(b, offset, length)
+        }
+    }
+
+    impl IndexInput for SlowIndexInput {
+        fn clone(&self) -> Result<Box<dyn IndexInput>> {
+            Ok(Box::new(SlowIndexInput {
+                input: self.input.clone()?,
+                latency: self.latency,
+                clock: Arc::clone(&self.clock),
+            }))
+        }
+
+        fn file_pointer(&self) -> i64 {
+            self.input.file_pointer()
+        }
+
+        fn seek(&mut self, pos: i64) -> Result<()> {
+            self.clock.advance(self.latency);
+            self.input.seek(pos)
+        }
+
+        fn len(&self) -> u64 {
+            self.input.len()
+        }
+
+        fn name(&self) -> &str {
+            self.input.name()
+        }
+
+        fn random_access_slice(
+            &self,
+            offset: i64,
+            length: i64,
+        ) -> Result<Box<dyn RandomAccessInput>> {
+            self.input.random_access_slice(offset, length)
+        }
+    }
+
+    const NUM_BLOCKS: usize = 40;
+
+    fn write_blocks(dir: &RAMDirectory) -> Vec<i64> {
+        let mut fps = Vec::with_capacity(NUM_BLOCKS);
+        let mut out = dir.create_output("_0.tim", &IOContext::Default).unwrap();
+        for i in 0..NUM_BLOCKS {
+            fps.push(out.file_pointer());
+            let len = 10 + i * 7;
+            out.write_vint((i as i32) << 1 | 1).unwrap();
+            out.write_vint((len as i32) << 1).unwrap();
+            out.write_bytes(&vec![i as u8; len], 0, len).unwrap();
+            out.write_vint(3).unwrap();
+            out.write_bytes(&[1, 2, 3], 0, 3).unwrap();
+            out.write_vint(i as i32).unwrap();
+            out.write_bytes(&vec![0xff; i], 0, i).unwrap();
+        }
+        // some trailing bytes that aren't a block
+        out.write_vint(0x7fff_ffff).unwrap();
+        fps
+    }
+
+    // reads all the blocks in file order, simulating decoding work per block
+    fn enumerate(
+        input: &mut dyn IndexInput,
+        mut prefetcher: Option<&mut BlockPrefetcher>,
+        clock: &ManualClock,
+        work: Duration,
+    ) -> Vec<(i64, i32, Vec<u8>, Vec<u8>)> {
+        let mut blocks = vec![];
+        let mut fp = 0;
+        while (fp as u64) < input.len() {
+            let block = match prefetcher.as_mut().and_then(|p| p.take(fp)) {
+                Some(block) => block,
+                None => {
+                    let mut block = TermsBlockBytes::default();
+                    if block.read(input, fp).is_err() {
+                        break;
+                    }
+                    block
+                }
+            };
+            if let Some(ref mut p) = prefetcher {
+                p.prefetch(block.fp_end);
+            }
+            clock.advance(work);
+            fp = block.fp_end;
+            blocks.push((
+                block.fp,
+                block.code,
+                block.suffix_bytes().to_vec(),
+                block.meta_bytes().to_vec(),
+            ));
+            if let Some(ref mut p) = prefetcher {
+                p.recycle(block);
+            }
+        }
+        blocks
+    }
+
+    #[test]
+    fn test_prefetch_moves_reads_off_the_calling_thread() {
+        let dir = RAMDirectory::new();
+        let fps = write_blocks(&dir);
+        let latency = Duration::from_millis(4);
+        let work = Duration::from_millis(3);
+        let open = |clock: &Arc<ManualClock>| -> Box<dyn IndexInput> {
+            Box::new(SlowIndexInput {
+                input: dir.open_input("_0.tim", &IOContext::READ).unwrap(),
+                latency,
+                clock: Arc::clone(clock),
+            })
+        };
+
+        let clock = Arc::new(ManualClock::default());
+        let mut input = open(&clock);
+        let expected = enumerate(input.as_mut(), None, &clock, work);
+        assert_eq!(expected.len(), NUM_BLOCKS);
+        assert_eq!(expected.iter().map(|b| b.0).collect::<Vec<_>>(), fps);
+        // every block and the garbage at the end were read in place
+        let blocks = NUM_BLOCKS as u32;
+        assert_eq!(clock.elapsed(), latency * (blocks + 1) + work * blocks);
+
+        let clock = Arc::new(ManualClock::default());
+        let worker_clock = Arc::new(ManualClock::default());
+        let mut input = open(&clock);
+        let mut prefetcher = BlockPrefetcher::new(open(&worker_clock)).unwrap();
+        let blocks_read = enumerate(input.as_mut(), Some(&mut prefetcher), &clock, work);
+        assert!(blocks_read == expected);
+        // only the first block and the garbage at the end were read in place,
+        // the other reads overlapped with the decoding
+        assert_eq!(prefetcher.stats(), (NUM_BLOCKS as u64 - 1, 2));
+        assert_eq!(clock.elapsed(), latency * 2 + work * blocks);
+        drop(prefetcher);
+        assert_eq!(worker_clock.elapsed(), latency * blocks);
+    }
+
+    #[test]
+    fn test_block_sections() {
+        let dir = RAMDirectory::new();
+        let fps = write_blocks(&dir);
+        let mut input = dir.open_input("_0.tim", &IOContext::READ).unwrap();
+
+        let mut block = TermsBlockBytes::default();
+        for (i, &fp) in fps.iter().enumerate() {
+            block.read(input.as_mut(), fp).unwrap();
+            assert_eq!(block.code, (i as i32) << 1 | 1);
+            assert_eq!(block.suffix_bytes(), &vec![i as u8; 10 + i * 7][..]);
+            assert_eq!(block.stat_bytes(), &[1, 2, 3]);
+            assert_eq!(block.meta_bytes(), &vec![0xff; i][..]);
+            assert_eq!(block.fp_end, input.file_pointer());
+        }
+
+        // the buffer is reused once large enough
+        let (ptr, capacity) = (block.buffer.as_ptr(), block.buffer.capacity());
+        for &fp in &fps {
+            block.read(input.as_mut(), fp).unwrap();
+        }
+        assert_eq!(block.buffer.as_ptr(), ptr);
+        assert_eq!(block.buffer.capacity(), capacity);
+
+        // the frame readers move independently over the sections
+        let mut suffixes = ByteArrayDataInput::new(block.suffix_bytes());
+        let mut stats = ByteArrayDataInput::new(block.stat_bytes());
+        assert_eq!(stats.read_byte().unwrap(), 1);
+        suffixes.skip_bytes(5).unwrap();
+        assert_eq!(stats.read_byte().unwrap(), 2);
+        assert_eq!(suffixes.position(), 5);
+        suffixes.rewind();
+        stats.rewind();
+        assert_eq!(stats.read_byte().unwrap(), 1);
+        assert_eq!(suffixes.read_byte().unwrap(), NUM_BLOCKS as u8 - 1);
+    }
+
+    #[test]
+    fn test_prefetch_miss_falls_back() {
+        let dir = RAMDirectory::new();
+        let fps = write_blocks(&dir);
+        let input = dir.open_input("_0.tim", &IOContext::READ).unwrap();
+        let mut prefetcher = BlockPrefetcher::new(input).unwrap();
+
+        prefetcher.prefetch(fps[3]);
+        // a seek elsewhere doesn't consume the read-ahead
+        assert!(prefetcher.take(fps[7]).is_none());
+        // the stale read is dropped by the next request once it completed
+        while prefetcher.pending != Some(fps[8]) {
+            prefetcher.prefetch(fps[8]);
+            thread::yield_now();
+        }
+        let block = prefetcher.take(fps[8]).unwrap();
+        assert_eq!(block.code, 8 << 1 | 1);
+        assert_eq!(block.fp_end, fps[9]);
+
+        // speculative reads of bytes that aren't a block fail quietly
+        let last = *fps.last().unwrap();
+        prefetcher.recycle(block);
+        prefetcher.prefetch(last);
+        let block = prefetcher.take(last).unwrap();
+        prefetcher.prefetch(block.fp_end);
+        assert!(prefetcher.take(block.fp_end).is_none());
+    }
+}
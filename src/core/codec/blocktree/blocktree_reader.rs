@@ -21,8 +21,11 @@ use std::ops::DerefMut;
 use std::string::ToString;
 use std::sync::Arc;
 
+use core::codec::blocktree::block_prefetcher::{BlockPrefetcher, TermsBlockBytes};
+use core::codec::blocktree::direct_terms::DirectTerms;
 use core::codec::blocktree::term_iter_frame::SegmentTermsIterFrame;
 use core::codec::blocktree::MAX_LONGS_SIZE;
+use core::codec::codec_util::ControlFlow;
 use core::codec::lucene50::Lucene50PostingIterEnum;
 use core::codec::{
     codec_util, BlockTermState, Codec, FieldsProducer, Lucene50PostingsReader,
@@ -34,9 +37,11 @@ use core::index::{IndexOptions, SegmentReadState};
 use core::index::{SeekStatus, TermIterator, Terms};
 use core::store::{ByteArrayDataInput, DataInput, Directory, IndexInput};
 use core::util::bit_util::UnsignedShift;
+use core::util::byte_block_pool::ByteBlockPool;
+use core::util::cache::LruCache;
 use core::util::fst::{
-    Arc as FSTArc, ByteSequenceOutput, ByteSequenceOutputFactory, DirectionalBytesReader,
-    FSTBytesReader, OutputFactory, FST,
+    Arc as FSTArc, ByteSequenceOutput, ByteSequenceOutputFactory, FSTBytesReader, OutputFactory,
+    FST,
 };
 use error::{
     ErrorKind::{CorruptIndex, IllegalState, UnsupportedOperation},
@@ -48,6 +53,9 @@ const OUTPUT_FLAGS_NUM_BITS: usize = 2;
 pub const OUTPUT_FLAGS_IS_FLOOR: i64 = 0x1;
 pub const OUTPUT_FLAGS_HAS_TERMS: i64 = 0x2;
 
+// the longest term the `IndexWriter` accepts
+const MAX_TERM_LENGTH: usize = ByteBlockPool::BYTE_BLOCK_SIZE - 2;
+
 /// Extension of terms file
 pub const TERMS_EXTENSION: &str = "tim";
 pub const TERMS_CODEC_NAME: &str = "BlockTreeTermsDict";
@@ -131,12 +139,22 @@ pub struct BlockTreeTermsReader {
     version: i32,
 
     any_auto_prefix_terms: bool,
+
+    /// Whether every terms block is checked when it is loaded.
+    verify_blocks: bool,
 }
 
 impl BlockTreeTermsReader {
+    /// Opens the terms dictionary of a segment.
+    ///
+    /// With `verify_blocks`, the structure of each block is checked when it
+    /// is loaded, so that a corrupt block is reported as `CorruptIndex` instead
+    /// of failing in a later scan of its terms. This walks all the entries of
+    /// the block once.
     pub fn new<D: Directory, DW: Directory, C: Codec>(
         postings_reader: Lucene50PostingsReader,
         state: &SegmentReadState<'_, D, DW, C>,
+        verify_blocks: bool,
     ) -> Result<BlockTreeTermsReader> {
         let segment = Arc::new(state.segment_info.name.clone());
         let terms_name = segment_file_name(&segment, &state.segment_suffix, TERMS_EXTENSION);
@@ -195,10 +213,9 @@ impl BlockTreeTermsReader {
         Self::seek_dir(terms_in.as_mut(), 0)?;
         Self::seek_dir(index_in.as_mut(), 0)?;
 
-        let num_fields = terms_in.read_vint()?;
-        if num_fields < 0 {
-            bail!(CorruptIndex(format!("invalid num_fields: {}", num_fields)));
-        }
+        let num_fields = terms_in.read_vint_bounded(state.field_infos.by_number.len() as i32)?;
+        // no root code is longer than the terms file
+        let max_root_code_len = terms_in.len().min(i32::max_value() as u64) as i32;
 
         let readers_terms_in = Arc::from(terms_in.clone()?);
         let mut terms_reader = BlockTreeTermsReader {
@@ -210,6 +227,7 @@ impl BlockTreeTermsReader {
             any_auto_prefix_terms,
             dir_offset: 0,
             index_dir_offset: 0,
+            verify_blocks,
         };
 
         let fields = {
@@ -224,13 +242,7 @@ impl BlockTreeTermsReader {
                         field
                     )));
                 }
-                let num_bytes = terms_in.read_vint()?;
-                if num_bytes < 0 {
-                    bail!(CorruptIndex(format!(
-                        "invalid root_code for field number: {}, num_bytes={}",
-                        field, num_bytes
-                    )));
-                }
+                let num_bytes = terms_in.read_vint_bounded(max_root_code_len)?;
                 let mut root_code = vec![0 as u8; num_bytes as usize];
                 terms_in.read_exact(&mut root_code)?;
                 let field_info = state.field_infos.by_number.get(&(field as u32));
@@ -244,13 +256,7 @@ impl BlockTreeTermsReader {
                 };
                 let sum_doc_freq = terms_in.read_vlong()?;
                 let doc_count = terms_in.read_vint()?;
-                let longs_size = terms_in.read_vint()?;
-                if longs_size < 0 {
-                    bail!(CorruptIndex(format!(
-                        "invalid longs_size for field: {}, longs_size={}",
-                        field_info.name, longs_size
-                    )));
-                }
+                let longs_size = terms_in.read_vint_bounded(MAX_LONGS_SIZE as i32)?;
                 let min_term = Self::read_bytes(terms_in.deref_mut())?;
                 let max_term = Self::read_bytes(terms_in.deref_mut())?;
                 if doc_count < 0 || doc_count > state.segment_info.max_doc {
@@ -282,7 +288,7 @@ impl BlockTreeTermsReader {
                     )));
                 }
                 let terms_in = Arc::from(terms_in.clone()?);
-                let mut reader = Arc::new(FieldReader::new(
+                let reader = Arc::new(FieldReader::new(
                     terms_reader.clone_without_fields(),
                     field_info.clone(),
                     num_terms,
@@ -307,6 +313,45 @@ impl BlockTreeTermsReader {
         Ok(terms_reader)
     }
 
+    /// Loads the terms of each field in memory, see `DirectTerms`, unless they
+    /// would take more than `max_ram_bytes_per_field` of heap. These fields
+    /// keep seeking through the terms index.
+    ///
+    /// Must be called before any of the fields is shared.
+    pub fn load_direct_terms(&mut self, max_ram_bytes_per_field: usize) -> Result<()> {
+        for (name, field) in &mut self.fields {
+            let direct_terms = DirectTerms::load(field, max_ram_bytes_per_field)?;
+            match Arc::get_mut(field) {
+                Some(field) => field.direct_terms = direct_terms.map(Arc::new),
+                None => bail!(IllegalState(format!(
+                    "field {} of segment {} is already in use",
+                    name, self.segment
+                ))),
+            }
+        }
+        Ok(())
+    }
+
+    /// Keeps the states of the last `max_terms_per_field` terms found by an
+    /// exact seek on each field, so that seeking them again needs no IO.
+    ///
+    /// Must be called before any of the fields is shared.
+    pub fn cache_term_states(&mut self, max_terms_per_field: usize) -> Result<()> {
+        for (name, field) in &mut self.fields {
+            match Arc::get_mut(field) {
+                Some(field) => {
+                    field.term_states =
+                        Some(Arc::new(LruCache::with_max_entries(max_terms_per_field)))
+                }
+                None => bail!(IllegalState(format!(
+                    "field {} of segment {} is already in use",
+                    name, self.segment
+                ))),
+            }
+        }
+        Ok(())
+    }
+
     fn clone_without_fields(&self) -> BlockTreeTermsReader {
         BlockTreeTermsReader {
             terms_in: Arc::clone(&self.terms_in),
@@ -317,11 +362,12 @@ impl BlockTreeTermsReader {
             any_auto_prefix_terms: self.any_auto_prefix_terms,
             dir_offset: self.dir_offset,
             index_dir_offset: self.index_dir_offset,
+            verify_blocks: self.verify_blocks,
         }
     }
 
     fn read_bytes(input: &mut dyn IndexInput) -> Result<Vec<u8>> {
-        let len = input.read_vint()? as usize;
+        let len = input.read_vint_bounded(MAX_TERM_LENGTH as i32)? as usize;
         let mut vec = vec![0 as u8; len];
         input.read_exact(&mut vec)?;
         Ok(vec)
@@ -356,9 +402,24 @@ impl BlockTreeTermsReader {
         self.any_auto_prefix_terms
     }
 
+    pub fn verify_blocks(&self) -> bool {
+        self.verify_blocks
+    }
+
     pub fn keys(&self) -> Keys<String, FieldReaderRef> {
         self.fields.keys()
     }
+
+    /// Verifies the checksums of the whole terms dict and postings files, which
+    /// `check_integrity` skips as too costly, reporting the progress of each file
+    /// to `progress`.
+    pub fn check_integrity_with(
+        &self,
+        progress: &mut dyn FnMut(u64, u64) -> ControlFlow<()>,
+    ) -> Result<()> {
+        codec_util::checksum_entire_file_with(self.terms_in.as_ref(), progress)?;
+        self.postings_reader.check_integrity_with(progress)
+    }
 }
 
 impl FieldsProducer for BlockTreeTermsReader {
@@ -402,6 +463,10 @@ pub struct FieldReader {
     terms_in: IndexInputRef,
     postings_reader: Lucene50PostingsReaderRef,
     pub parent: BlockTreeTermsReader,
+    // the terms held in memory, see `BlockTreeTermsReader::load_direct_terms`
+    direct_terms: Option<Arc<DirectTerms>>,
+    // see `BlockTreeTermsReader::cache_term_states`
+    term_states: Option<Arc<LruCache<Vec<u8>, BlockTermState>>>,
 }
 
 pub type FieldReaderRef = Arc<FieldReader>;
@@ -424,7 +489,6 @@ impl FieldReader {
         terms_in: IndexInputRef,
         postings_reader: Lucene50PostingsReaderRef,
     ) -> Result<FieldReader> {
-        debug_assert!(longs_size <= MAX_LONGS_SIZE);
         let mut root_block_fp = root_code.as_slice().read_vlong()? as usize;
         root_block_fp >>= OUTPUT_FLAGS_NUM_BITS;
         let root_block_fp = root_block_fp as i64;
@@ -454,6 +518,8 @@ impl FieldReader {
             terms_in,
             postings_reader,
             parent,
+            direct_terms: None,
+            term_states: None,
         })
     }
 
@@ -477,6 +543,17 @@ impl FieldReader {
     pub fn index(&self) -> &FSTRef {
         self.index.as_ref().unwrap()
     }
+
+    /// Returns the terms of this field held in memory, if they were loaded.
+    pub fn direct_terms(&self) -> Option<&DirectTerms> {
+        self.direct_terms.as_ref().map(Arc::as_ref)
+    }
+
+    /// Returns the states of the terms recently found by an exact seek, if
+    /// they are cached.
+    pub fn term_states(&self) -> Option<&LruCache<Vec<u8>, BlockTermState>> {
+        self.term_states.as_ref().map(Arc::as_ref)
+    }
 }
 
 impl<'a> Terms for FieldReader {
@@ -900,6 +977,8 @@ impl ToString for Stats {
 
 pub struct SegmentTermIterator {
     iter: Box<SegmentTermIteratorInner>,
+    direct_terms: Option<Arc<DirectTerms>>,
+    term_states: Option<Arc<LruCache<Vec<u8>, BlockTermState>>>,
 }
 
 impl SegmentTermIterator {
@@ -915,7 +994,22 @@ impl SegmentTermIterator {
             postings_reader,
             field_info,
         ));
-        Self { iter }
+        let direct_terms = field_reader.direct_terms.clone();
+        let term_states = field_reader.term_states.clone();
+        Self {
+            iter,
+            direct_terms,
+            term_states,
+        }
+    }
+
+    /// Reads the block that follows the current one in the background while
+    /// the current block is decoded, which hides the IO latency of exhaustive
+    /// enumerations with `next` on slow storage. Seeks don't use the read-ahead.
+    ///
+    /// Disabled by default, as it costs a thread per iterator.
+    pub fn enable_prefetch(&mut self) -> Result<()> {
+        self.iter.enable_prefetch()
     }
 }
 
@@ -930,6 +1024,26 @@ impl TermIterator for SegmentTermIterator {
 
     #[inline]
     fn seek_exact(&mut self, text: &[u8]) -> Result<bool> {
+        if let Some(ref direct_terms) = self.direct_terms {
+            return match direct_terms.get(text)? {
+                Some(state) => {
+                    self.iter.seek_exact_state(text, &state)?;
+                    Ok(true)
+                }
+                None => Ok(false),
+            };
+        }
+        if let Some(ref term_states) = self.term_states {
+            if let Some(state) = term_states.get(text) {
+                self.iter.seek_exact_state(text, &state)?;
+                return Ok(true);
+            }
+            if !self.iter.seek_exact(text)? {
+                return Ok(false);
+            }
+            term_states.put(text.to_vec(), self.iter.term_state()?);
+            return Ok(true);
+        }
         self.iter.seek_exact(text)
     }
 
@@ -1013,10 +1127,11 @@ pub(crate) struct SegmentTermIteratorInner {
 
     pub term: Vec<u8>,
     pub term_len: usize,
-}
 
-// used for empty fst reader
-const EMPTY_BYTES: [u8; 0] = [];
+    // read-ahead of the next block, only used by `next`
+    prefetcher: Option<BlockPrefetcher>,
+    enumerating: bool,
+}
 
 impl SegmentTermIteratorInner {
     fn new(
@@ -1036,7 +1151,7 @@ impl SegmentTermIteratorInner {
         } else {
             // NOTE: fst reader is always used when self.fr.index is Some,
             // so this will be safe because it will never be used
-            FSTBytesReader::Directional(DirectionalBytesReader::new(&EMPTY_BYTES, false))
+            FSTBytesReader::empty()
         };
 
         SegmentTermIteratorInner {
@@ -1057,6 +1172,8 @@ impl SegmentTermIteratorInner {
             eof: false,
             fst_reader,
             arcs,
+            prefetcher: None,
+            enumerating: false,
         }
     }
 
@@ -1083,6 +1200,34 @@ impl SegmentTermIteratorInner {
         Ok(())
     }
 
+    fn enable_prefetch(&mut self) -> Result<()> {
+        if self.prefetcher.is_none() {
+            self.prefetcher = Some(BlockPrefetcher::new((*self.terms_in).clone()?)?);
+        }
+        Ok(())
+    }
+
+    pub(crate) fn take_prefetched_block(&mut self, fp: i64) -> Option<TermsBlockBytes> {
+        if !self.enumerating {
+            return None;
+        }
+        self.prefetcher.as_mut().and_then(|p| p.take(fp))
+    }
+
+    pub(crate) fn recycle_block(&mut self, block: TermsBlockBytes) {
+        if let Some(ref mut prefetcher) = self.prefetcher {
+            prefetcher.recycle(block);
+        }
+    }
+
+    pub(crate) fn prefetch_block(&mut self, fp: i64) {
+        if self.enumerating {
+            if let Some(ref mut prefetcher) = self.prefetcher {
+                prefetcher.prefetch(fp);
+            }
+        }
+    }
+
     fn compute_block_stats(&mut self) -> Result<Stats> {
         let mut stats = Stats::new(
             &self.field_reader().parent.segment,
@@ -1207,11 +1352,14 @@ impl SegmentTermIteratorInner {
         let idx = (1 + self.current_frame_ord) as usize;
         let ord = self.get_frame(idx);
         self.stack[ord].arc = arc;
-        if self.stack[ord].fp_orig == fp && self.stack[ord].next_ent != -1 {
+        // the prefix only differs if a corrupt block points to another block
+        if self.stack[ord].fp_orig == fp
+            && self.stack[ord].next_ent != -1
+            && self.stack[ord].prefix == length
+        {
             if self.stack[ord].ord > self.target_before_current_length as isize {
                 self.stack[ord].rewind();
             }
-            debug_assert_eq!(length, self.stack[ord].prefix);
         } else {
             let frame = &mut self.stack[ord];
             frame.next_ent = -1;
@@ -1301,6 +1449,7 @@ impl TermIterator for SegmentTermIteratorInner {
             let result = self.seek_exact(&term)?;
             debug_assert!(result);
         }
+        self.enumerating = true;
 
         // Pop finished blocks:
         debug_assert!(self.current_frame_ord >= 0);
@@ -1359,6 +1508,7 @@ impl TermIterator for SegmentTermIteratorInner {
     }
 
     fn seek_exact(&mut self, target: &[u8]) -> Result<bool> {
+        self.enumerating = false;
         if self.term.len() < target.len() {
             self.term.resize(target.len(), 0);
         }
@@ -1548,6 +1698,7 @@ impl TermIterator for SegmentTermIteratorInner {
     }
 
     fn seek_ceil(&mut self, target: &[u8]) -> Result<SeekStatus> {
+        self.enumerating = false;
         if self.field_reader().index.is_none() {
             bail!(IllegalState("terms index was not loaded".into()));
         }
@@ -1752,6 +1903,7 @@ impl TermIterator for SegmentTermIteratorInner {
     }
 
     fn seek_exact_state(&mut self, text: &[u8], state: &Self::TermState) -> Result<()> {
+        self.enumerating = false;
         self.clear_eof();
         if text != self.term() || !self.term_exists {
             self.current_frame_ord = self.static_frame.ord;
@@ -1810,3 +1962,279 @@ impl TermIterator for SegmentTermIteratorInner {
         Ok(self.current_frame().state.clone())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    extern crate rand;
+
+    use super::*;
+    use core::codec::{
+        CodecEnum, FieldInfosFormat, Lucene50PostingsFormat, Lucene62Codec,
+        PerFieldDocValuesFormat, PerFieldFieldsReader, PerFieldPostingsFormat, PostingsFormat,
+        PostingsFormatEnum, TERMS_CODEC,
+    };
+    use core::doc::{Store, StringField, TextField};
+    use core::index::index_writer_config::IndexWriterConfig;
+    use core::index::merge_policy::TieredMergePolicy;
+    use core::index::merge_scheduler::SerialMergeScheduler;
+    use core::index::{IndexWriter, SegmentInfos};
+    use core::search::DocIterator;
+    use core::store::{DataOutput, IOContext, MmapDirectory, NativeFSLockFactory, RAMDirectory};
+    use core::util::tests::seeded_rng;
+    use error::Error;
+
+    use self::rand::Rng;
+
+    use std::fs;
+    use std::path::PathBuf;
+
+    const NUM_DOCS: usize = 2000;
+
+    // small enough for the term blocks to cross the chunks of the mappings
+    const MMAP_CHUNK_SIZE: u32 = 1024;
+
+    // terms sharing prefixes of various lengths, so that there are leaf,
+    // non-leaf and floor blocks, and a term in every doc
+    fn build_index<D: Directory + Send + Sync + 'static>(dir: Arc<D>) -> Arc<D> {
+        let mut config: IndexWriterConfig<CodecEnum, SerialMergeScheduler, TieredMergePolicy> =
+            IndexWriterConfig::default();
+        config.use_compound_file = false;
+        let writer = IndexWriter::new(Arc::clone(&dir), Arc::new(config)).unwrap();
+        for i in 0..NUM_DOCS {
+            let id = format!("{}{:x}", "ab".repeat(i % 3), i * 7919);
+            writer
+                .add_document(vec![
+                    StringField::new("id", &id, Store::No),
+                    TextField::new("body", &format!("{} common t{}", id, i % 300), Store::No),
+                ])
+                .unwrap();
+        }
+        writer.commit().unwrap();
+        writer.close().unwrap();
+        dir
+    }
+
+    fn open_terms<D: Directory>(
+        dir: &Arc<D>,
+        infos: &SegmentInfos<D, CodecEnum>,
+        suffix: &str,
+    ) -> Result<BlockTreeTermsReader> {
+        let si = &infos.segments[0].info;
+        let field_infos =
+            si.codec()
+                .field_infos_format()
+                .read(dir.as_ref(), si, "", &IOContext::READ)?;
+        let state = SegmentReadState::new(
+            Arc::clone(dir),
+            si,
+            Arc::new(field_infos),
+            &IOContext::READ,
+            suffix.to_string(),
+        );
+        BlockTreeTermsReader::new(Lucene50PostingsReader::open(&state)?, &state, true)
+    }
+
+    // opens the postings of the segment through a codec verifying the blocks
+    fn open_verified_fields<D: Directory>(
+        dir: &Arc<D>,
+        infos: &SegmentInfos<D, CodecEnum>,
+    ) -> Result<Arc<PerFieldFieldsReader>> {
+        let mut format = Lucene50PostingsFormat::default();
+        format.set_verify_blocks(true);
+        let codec = Lucene62Codec::with_per_field_formats(
+            PerFieldPostingsFormat::new(PostingsFormatEnum::Lucene50(format)),
+            PerFieldDocValuesFormat::default(),
+        );
+        let si = &infos.segments[0].info;
+        let field_infos =
+            si.codec()
+                .field_infos_format()
+                .read(dir.as_ref(), si, "", &IOContext::READ)?;
+        let state = SegmentReadState::new(
+            Arc::clone(dir),
+            si,
+            Arc::new(field_infos),
+            &IOContext::READ,
+            String::new(),
+        );
+        codec.postings_format().fields_producer(&state)
+    }
+
+    // enumerates and seeks all terms, returns the number of terms
+    fn visit_terms<F: Fields<Terms = FieldReaderRef>>(
+        fields: &F,
+        field: &str,
+        max_terms: usize,
+    ) -> Result<usize> {
+        let terms = fields.terms(field)?.unwrap();
+        let mut iter = terms.iterator()?;
+        let mut all_terms = vec![];
+        while let Some(term) = iter.next()? {
+            iter.doc_freq()?;
+            iter.total_term_freq()?;
+            all_terms.push(term);
+            assert!(all_terms.len() <= max_terms, "endless enumeration");
+        }
+        let mut iter = terms.iterator()?;
+        for term in all_terms.iter().step_by(7) {
+            if iter.seek_exact(term)? {
+                iter.doc_freq()?;
+            }
+            let mut next = term.clone();
+            next.push(b'0');
+            iter.seek_ceil(&next)?;
+        }
+        Ok(all_terms.len())
+    }
+
+    fn assert_corrupt(e: &Error) {
+        match e.kind() {
+            CorruptIndex(_) => {}
+            _ => panic!("expected CorruptIndex, got {:?}", e),
+        }
+    }
+
+    // the name of the terms dict file and the segment suffix of the postings
+    fn terms_file<D: Directory>(
+        dir: &Arc<D>,
+        infos: &SegmentInfos<D, CodecEnum>,
+    ) -> (String, String) {
+        let name = dir
+            .list_all()
+            .unwrap()
+            .into_iter()
+            .find(|f| f.ends_with(".tim"))
+            .unwrap();
+        // _0_Lucene50_0.tim
+        let suffix = name[infos.segments[0].info.name.len() + 1..name.len() - 4].to_string();
+        (name, suffix)
+    }
+
+    // an empty `MmapDirectory` in the temp dir, mapping the files by chunks
+    // of `MMAP_CHUNK_SIZE`
+    fn mmap_directory(name: &str) -> (PathBuf, Arc<MmapDirectory<NativeFSLockFactory>>) {
+        let path = ::std::env::temp_dir().join(name);
+        let _ = fs::remove_dir_all(&path);
+        let dir =
+            MmapDirectory::new(&path, NativeFSLockFactory::default(), MMAP_CHUNK_SIZE).unwrap();
+        (path, Arc::new(dir))
+    }
+
+    fn check_cache_term_states<D: Directory + Send + Sync + 'static>(dir: Arc<D>) {
+        let dir = build_index(dir);
+        let infos = SegmentInfos::read_latest_commit(&dir).unwrap();
+        let (_, suffix) = terms_file(&dir, &infos);
+        let expected = open_terms(&dir, &infos, &suffix).unwrap();
+        let mut cached = open_terms(&dir, &infos, &suffix).unwrap();
+        cached.cache_term_states(1024).unwrap();
+
+        let expected_terms = expected.terms("id").unwrap().unwrap();
+        assert!(expected_terms.term_states().is_none());
+        let cached_terms = cached.terms("id").unwrap().unwrap();
+        let mut expected_iter = expected_terms.iterator().unwrap();
+        for _ in 0..2 {
+            let mut iter = cached_terms.iterator().unwrap();
+            for i in 0..10 {
+                let id = format!("{}{:x}", "ab".repeat(i % 3), i * 7919);
+                assert!(iter.seek_exact(id.as_bytes()).unwrap());
+                assert!(expected_iter.seek_exact(id.as_bytes()).unwrap());
+                assert_eq!(iter.term().unwrap(), id.as_bytes());
+                assert_eq!(iter.doc_freq().unwrap(), expected_iter.doc_freq().unwrap());
+                assert_eq!(
+                    iter.postings().unwrap().next().unwrap(),
+                    expected_iter.postings().unwrap().next().unwrap()
+                );
+                // enumeration goes on from a cached term
+                assert_eq!(iter.next().unwrap(), expected_iter.next().unwrap());
+            }
+            assert!(!iter.seek_exact(b"missing").unwrap());
+        }
+
+        // the second round found every state in the cache
+        let term_states = cached_terms.term_states().unwrap();
+        assert_eq!(term_states.len(), 10);
+        assert_eq!(term_states.hit_count(), 10);
+        assert_eq!(term_states.miss_count(), 12);
+    }
+
+    #[test]
+    fn test_cache_term_states() {
+        check_cache_term_states(Arc::new(RAMDirectory::new()));
+    }
+
+    #[test]
+    fn test_cache_term_states_mmap() {
+        let (path, dir) = mmap_directory("rucene_test_blocktree_cache_term_states");
+        check_cache_term_states(dir);
+        fs::remove_dir_all(&path).unwrap();
+    }
+
+    fn check_verify_blocks<D: Directory + Send + Sync + 'static>(dir: Arc<D>) {
+        let dir = build_index(dir);
+        let infos = SegmentInfos::read_latest_commit(&dir).unwrap();
+        let (name, suffix) = terms_file(&dir, &infos);
+
+        // the setting of the format configured in the codec reaches the reader
+        let fields = open_verified_fields(&dir, &infos).unwrap();
+        assert!(fields.terms("id").unwrap().unwrap().parent.verify_blocks());
+        let num_ids = visit_terms(fields.as_ref(), "id", NUM_DOCS).unwrap();
+        assert_eq!(num_ids, NUM_DOCS);
+        let num_words = visit_terms(fields.as_ref(), "body", 2 * NUM_DOCS).unwrap();
+        assert!(num_words > NUM_DOCS);
+        drop(fields);
+
+        let mut input = dir.open_input(&name, &IOContext::READ).unwrap();
+        let mut clean = vec![0u8; input.len() as usize];
+        input.read_bytes(&mut clean, 0, clean.len()).unwrap();
+        // the blocks are between the headers of the terms dict and postings
+        // writer, followed by the 128 block size, and the fields directory
+        let blocks_start = codec_util::index_header_length(TERMS_CODEC_NAME, &suffix)
+            + codec_util::index_header_length(TERMS_CODEC, &suffix)
+            + 2;
+        input
+            .seek(input.len() as i64 - codec_util::footer_length() as i64 - 8)
+            .unwrap();
+        let blocks_end = input.read_long().unwrap() as usize;
+        assert!(blocks_start < blocks_end);
+
+        let mut rng = seeded_rng();
+        for _ in 0..200 {
+            let mut bytes = clean.clone();
+            for _ in 0..rng.gen_range(1, 4) {
+                let pos = rng.gen_range(blocks_start, blocks_end);
+                bytes[pos] ^= 1 << rng.gen_range(0, 8);
+            }
+            dir.delete_file(&name).unwrap();
+            {
+                let mut out = dir.create_output(&name, &IOContext::Default).unwrap();
+                out.write_bytes(&bytes, 0, bytes.len()).unwrap();
+            }
+
+            // a flipped byte of a suffix or a stat may still be valid, but
+            // nothing may panic or loop, and all the errors are corruptions
+            let fields = open_verified_fields(&dir, &infos).unwrap();
+            if let Err(e) = visit_terms(fields.as_ref(), "id", 10 * num_ids) {
+                assert_corrupt(&e);
+            }
+            if let Err(e) = visit_terms(fields.as_ref(), "body", 10 * num_words) {
+                assert_corrupt(&e);
+            }
+        }
+    }
+
+    #[test]
+    fn test_verify_blocks() {
+        check_verify_blocks(Arc::new(RAMDirectory::new()));
+    }
+
+    #[test]
+    fn test_verify_blocks_mmap() {
+        let (path, dir) = mmap_directory("rucene_test_blocktree_verify_blocks");
+        check_verify_blocks(Arc::clone(&dir));
+        // the terms dict was read through several chunks
+        let infos: SegmentInfos<_, CodecEnum> = SegmentInfos::read_latest_commit(&dir).unwrap();
+        let (name, _) = terms_file(&dir, &infos);
+        assert!(dir.file_length(&name).unwrap() > 4 * i64::from(MMAP_CHUNK_SIZE));
+        fs::remove_dir_all(&path).unwrap();
+    }
+}
@@ -0,0 +1,164 @@
+// Copyright 2019 Zhizhesihai (Beijing) Technology Limited.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use core::codec::blocktree::FieldReader;
+use core::codec::BlockTermState;
+use core::index::{TermIterator, Terms};
+use core::store::{DataInput, DataOutput};
+
+use error::Result;
+
+use std::mem;
+use std::u32;
+
+// smallest possible cost of a term: its length, an empty term and eleven
+// one byte state fields, plus its hash slot
+const MIN_TERM_BYTES: usize = 12 + mem::size_of::<u32>();
+
+/// All the terms of a field with their states, held in memory.
+///
+/// An exact seek is then a hash lookup instead of a walk of the terms index
+/// and a scan of a terms block. The postings of the terms found in a single
+/// doc are pulsed into their state, so these terms are resolved without any
+/// IO at all, which is the common case of primary key fields.
+///
+/// The terms and their states are packed in one buffer, each state field as
+/// a zig-zag vlong, and an open addressing table maps the hash of a term to
+/// its offset in the buffer. A term of 10 bytes with its state takes about
+/// 40 bytes, and the table 4 to 8 more.
+pub struct DirectTerms {
+    // for each term: its length as a vint, its bytes, then its state
+    bytes: Vec<u8>,
+    // offset + 1 of a term in `bytes`, 0 for an empty slot
+    slots: Vec<u32>,
+    len: usize,
+}
+
+impl DirectTerms {
+    /// Loads the terms of `field`, or returns `None` if they would take more
+    /// than `max_ram_bytes` of heap.
+    pub fn load(field: &FieldReader, max_ram_bytes: usize) -> Result<Option<DirectTerms>> {
+        let num_terms = field.size()?.max(0) as usize;
+        if num_terms.saturating_mul(MIN_TERM_BYTES) > max_ram_bytes {
+            return Ok(None);
+        }
+        // keeps the load factor at most 3/4
+        let capacity = (num_terms + num_terms / 3 + 1).next_power_of_two();
+        let slots_bytes = capacity * mem::size_of::<u32>();
+        if slots_bytes > max_ram_bytes {
+            return Ok(None);
+        }
+        let max_buffer_bytes = (max_ram_bytes - slots_bytes).min(u32::MAX as usize - 1);
+
+        let mut direct_terms = DirectTerms {
+            bytes: Vec::new(),
+            slots: vec![0; capacity],
+            len: 0,
+        };
+        let mut iter = field.iterator()?;
+        while let Some(term) = iter.next()? {
+            let offset = direct_terms.bytes.len();
+            direct_terms.bytes.write_vint(term.len() as i32)?;
+            direct_terms.bytes.extend_from_slice(&term);
+            write_state(&mut direct_terms.bytes, &iter.term_state()?)?;
+            if direct_terms.bytes.len() > max_buffer_bytes {
+                return Ok(None);
+            }
+            direct_terms.insert(&term, offset);
+        }
+        direct_terms.bytes.shrink_to_fit();
+        Ok(Some(direct_terms))
+    }
+
+    fn insert(&mut self, term: &[u8], offset: usize) {
+        let mask = self.slots.len() - 1;
+        let mut slot = hash(term) & mask;
+        while self.slots[slot] != 0 {
+            slot = (slot + 1) & mask;
+        }
+        self.slots[slot] = offset as u32 + 1;
+        self.len += 1;
+    }
+
+    /// Returns the state of `term`, if the field has it.
+    pub fn get(&self, term: &[u8]) -> Result<Option<BlockTermState>> {
+        let mask = self.slots.len() - 1;
+        let mut slot = hash(term) & mask;
+        loop {
+            let offset = self.slots[slot] as usize;
+            if offset == 0 {
+                return Ok(None);
+            }
+            let mut input = &self.bytes[offset - 1..];
+            let len = input.read_vint()? as usize;
+            if &input[..len] == term {
+                input = &input[len..];
+                return Ok(Some(read_state(&mut input)?));
+            }
+            slot = (slot + 1) & mask;
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn ram_bytes_used(&self) -> usize {
+        self.bytes.capacity() + self.slots.capacity() * mem::size_of::<u32>()
+    }
+}
+
+// FNV-1a
+#[inline]
+fn hash(term: &[u8]) -> usize {
+    let mut hash = 0xcbf2_9ce4_8422_2325u64;
+    for &b in term {
+        hash ^= u64::from(b);
+        hash = hash.wrapping_mul(0x100_0000_01b3);
+    }
+    hash as usize
+}
+
+fn write_state(output: &mut Vec<u8>, state: &BlockTermState) -> Result<()> {
+    output.write_zlong(state.ord)?;
+    output.write_zint(state.doc_freq)?;
+    output.write_zlong(state.total_term_freq)?;
+    output.write_zint(state.term_block_ord)?;
+    output.write_zlong(state.block_file_pointer)?;
+    output.write_zlong(state.doc_start_fp)?;
+    output.write_zlong(state.pos_start_fp)?;
+    output.write_zlong(state.pay_start_fp)?;
+    output.write_zlong(state.skip_offset)?;
+    output.write_zlong(state.last_pos_block_offset)?;
+    output.write_zint(state.singleton_doc_id)
+}
+
+fn read_state(input: &mut &[u8]) -> Result<BlockTermState> {
+    let mut state = BlockTermState::new();
+    state.ord = input.read_zlong()?;
+    state.doc_freq = input.read_zint()?;
+    state.total_term_freq = input.read_zlong()?;
+    state.term_block_ord = input.read_zint()?;
+    state.block_file_pointer = input.read_zlong()?;
+    state.doc_start_fp = input.read_zlong()?;
+    state.pos_start_fp = input.read_zlong()?;
+    state.pay_start_fp = input.read_zlong()?;
+    state.skip_offset = input.read_zlong()?;
+    state.last_pos_block_offset = input.read_zlong()?;
+    state.singleton_doc_id = input.read_zint()?;
+    Ok(state)
+}
@@ -17,6 +17,11 @@ pub use self::blocktree_reader::*;
 mod blocktree_writer;
 pub use self::blocktree_writer::*;
 
+mod direct_terms;
+pub use self::direct_terms::*;
+
 mod term_iter_frame;
 
+mod block_prefetcher;
+
 const MAX_LONGS_SIZE: usize = 3;
@@ -11,6 +11,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use core::codec::blocktree::block_prefetcher::TermsBlockBytes;
 use core::codec::blocktree::MAX_LONGS_SIZE;
 use core::codec::{lucene50_decode_term, BlockTermState, SegmentTermIteratorInner};
 use core::index::{IndexOptions, SeekStatus};
@@ -19,9 +20,11 @@ use core::util::bit_util::UnsignedShift;
 use core::util::fst::{Arc, ByteSequenceOutput};
 use core::util::BytesRef;
 
-use error::Result;
+use error::ErrorKind::CorruptIndex;
+use error::{Error, Result};
 
 use std::cmp::Ordering;
+use std::mem;
 use std::ptr;
 
 pub(crate) struct SegmentTermsIterFrame {
@@ -35,9 +38,10 @@ pub(crate) struct SegmentTermsIterFrame {
     pub fp: i64,
     pub fp_orig: i64,
     pub fp_end: i64,
-    suffix_bytes: Vec<u8>,
+    // the bytes of the loaded block, sliced by the suffixes, stats and
+    // metadata readers
+    block: TermsBlockBytes,
     pub suffixes_reader: ByteArrayDataInput<BytesRef>,
-    stat_bytes: Vec<u8>,
     pub stats_reader: ByteArrayDataInput<BytesRef>,
     floor_data: Vec<u8>,
     floor_data_reader: ByteArrayDataInput<BytesRef>,
@@ -63,7 +67,6 @@ pub(crate) struct SegmentTermsIterFrame {
     pub state: BlockTermState,
     // metadata bufffer, holding monotonic values
     longs: [i64; MAX_LONGS_SIZE],
-    bytes: Vec<u8>,
     bytes_reader: ByteArrayDataInput<BytesRef>,
     // NOTE: this is pointer to parent, because of set is always Boxed,
     // thus this pointer will be safe
@@ -85,9 +88,8 @@ impl Default for SegmentTermsIterFrame {
             fp: 0,
             fp_orig: 0,
             fp_end: 0,
-            suffix_bytes: vec![0; 128],
+            block: TermsBlockBytes::default(),
             suffixes_reader: ByteArrayDataInput::new(BytesRef::default()),
-            stat_bytes: vec![0; 64],
             stats_reader: ByteArrayDataInput::new(BytesRef::default()),
             floor_data: vec![0; 32],
             floor_data_reader: ByteArrayDataInput::new(BytesRef::default()),
@@ -102,7 +104,6 @@ impl Default for SegmentTermsIterFrame {
             metadata_upto: 0,
             state: BlockTermState::new(),
             longs: [0; MAX_LONGS_SIZE],
-            bytes: vec![0; 32],
             bytes_reader: ByteArrayDataInput::new(BytesRef::default()),
             ste: ptr::null_mut(),
             start_byte_pos: 0,
@@ -149,6 +150,49 @@ impl SegmentTermsIterFrame {
             .reset(BytesRef::new(&self.floor_data));
         self.num_follow_floor_blocks = self.floor_data_reader.read_vint()?;
         self.next_floor_label = self.floor_data_reader.read_byte()? as i32 & 0xff;
+        if self.terms_iter().field_reader().parent.verify_blocks() {
+            if let Err(e) = self.check_floor_data() {
+                bail!(CorruptIndex(format!(
+                    "invalid floor data of field {}: {}",
+                    self.terms_iter().field_reader().field_info().name,
+                    e
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    // the follow blocks are after the first one, their labels increasing
+    fn check_floor_data(&self) -> Result<()> {
+        if self.num_follow_floor_blocks <= 0 {
+            bail!(CorruptIndex(format!(
+                "{} follow floor blocks",
+                self.num_follow_floor_blocks
+            )));
+        }
+        let mut reader = &self.floor_data[self.floor_data_reader.position()..];
+        let mut last_fp = 0;
+        let mut last_label = self.next_floor_label;
+        for i in 0..self.num_follow_floor_blocks {
+            let fp = reader.read_vlong()?.unsigned_shift(1);
+            if fp <= last_fp {
+                bail!(CorruptIndex(format!(
+                    "follow floor block {} at {} isn't after {}",
+                    i, fp, last_fp
+                )));
+            }
+            last_fp = fp;
+            if i + 1 < self.num_follow_floor_blocks {
+                let label = reader.read_byte()? as i32;
+                if label <= last_label {
+                    bail!(CorruptIndex(format!(
+                        "floor label {} isn't greater than {}",
+                        label, last_label
+                    )));
+                }
+                last_label = label;
+            }
+        }
         Ok(())
     }
 
@@ -185,12 +229,35 @@ impl SegmentTermsIterFrame {
             // Already loaded
             return Ok(());
         }
-        self.terms_iter().input.as_mut().unwrap().seek(self.fp)?;
-        let mut code = self.terms_iter().input.as_mut().unwrap().read_vint()?;
-        self.ent_count = code.unsigned_shift(1);
-        debug_assert!(self.ent_count > 0);
-        self.is_last_in_floor = (code & 1) != 0;
+        let verify = self.terms_iter().field_reader().parent.verify_blocks();
+        match self.terms_iter().take_prefetched_block(self.fp) {
+            Some(mut block) => {
+                mem::swap(&mut self.block, &mut block);
+                self.terms_iter().recycle_block(block);
+            }
+            None => {
+                let input = unsafe { (*self.ste).input.as_mut().unwrap() };
+                if let Err(e) = self.block.read(input.as_mut(), self.fp) {
+                    if verify {
+                        return Err(self.block_corruption(&e));
+                    }
+                    return Err(e);
+                }
+            }
+        }
+        self.fp_end = self.block.fp_end;
+        let code = (self.block.code, self.block.suffix_code);
+        self.ent_count = code.0.unsigned_shift(1);
+        self.is_last_in_floor = (code.0 & 1) != 0;
+        self.is_leaf_block = (code.1 & 1) != 0;
+        if verify {
+            // the block stays unloaded
+            if let Err(e) = self.check_block() {
+                return Err(self.block_corruption(&e));
+            }
+        }
 
+        debug_assert!(self.ent_count > 0);
         debug_assert!(self.arc.is_none() || (self.is_floor || self.is_last_in_floor));
 
         // TODO: if suffixes were stored in random-access
@@ -199,31 +266,12 @@ impl SegmentTermsIterFrame {
         // we could have simple array of offsets
 
         // term suffixes:
-        code = self.terms_iter().input.as_mut().unwrap().read_vint()?;
-        self.is_leaf_block = (code & 1) != 0;
-        let num_bytes = code.unsigned_shift(1) as usize;
-        self.suffix_bytes.resize(num_bytes, 0);
-        unsafe {
-            (*self.ste)
-                .input
-                .as_mut()
-                .unwrap()
-                .read_bytes(&mut self.suffix_bytes, 0, num_bytes)?;
-        }
         self.suffixes_reader
-            .reset(BytesRef::new(&self.suffix_bytes));
+            .reset(BytesRef::new(self.block.suffix_bytes()));
 
         // stats
-        let num_bytes = self.terms_iter().input.as_mut().unwrap().read_vint()? as usize;
-        self.stat_bytes.resize(num_bytes, 0);
-        unsafe {
-            (*self.ste)
-                .input
-                .as_mut()
-                .unwrap()
-                .read_bytes(&mut self.stat_bytes, 0, num_bytes)?;
-        }
-        self.stats_reader.reset(BytesRef::new(&self.stat_bytes));
+        self.stats_reader
+            .reset(BytesRef::new(self.block.stat_bytes()));
         self.metadata_upto = 0;
 
         self.state.term_block_ord = 0;
@@ -233,23 +281,143 @@ impl SegmentTermsIterFrame {
         // TODO: we could skip this if !hasTerms; but
         // that's rare so won't help much
         // metadata
-        let num_bytes = self.terms_iter().input.as_mut().unwrap().read_vint()? as usize;
-        self.bytes.resize(num_bytes, 0);
-        unsafe {
-            (*self.ste)
-                .input
-                .as_mut()
-                .unwrap()
-                .read_bytes(&mut self.bytes, 0, num_bytes)?;
-        }
-        self.bytes_reader.reset(BytesRef::new(&self.bytes));
-
-        // Sub-blocks of a single floor block are always
-        // written one after another -- tail recurse:
-        self.fp_end = self.terms_iter().input.as_ref().unwrap().file_pointer();
+        self.bytes_reader
+            .reset(BytesRef::new(self.block.meta_bytes()));
+
+        // the next block is likely the one that follows in the file
+        self.terms_iter().prefetch_block(self.fp_end);
+        Ok(())
+    }
+
+    // Walks all the entries of the loaded block, their stats and metadata, so
+    // that the scans of the block can't run past its sections.
+    fn check_block(&self) -> Result<()> {
+        if self.ent_count <= 0 {
+            bail!(CorruptIndex(format!(
+                "invalid entry count {}",
+                self.ent_count
+            )));
+        }
+        if self.arc.is_some() && !self.is_floor && !self.is_last_in_floor {
+            bail!(CorruptIndex(
+                "the block of a non floor index entry isn't last in floor".to_owned()
+            ));
+        }
+        // sub-blocks, with all their floor blocks, are written before their parent
+        if self.ord > 0 {
+            let parent_fp = self.terms_iter().stack[self.ord as usize - 1].fp_orig;
+            if self.fp >= parent_fp {
+                bail!(CorruptIndex(format!(
+                    "the block isn't before its parent block at {}",
+                    parent_fp
+                )));
+            }
+        }
+
+        // the slices are advanced by the reads, which fail at their end
+        let mut suffixes = self.block.suffix_bytes();
+        let max_len = suffixes.len() as i32;
+        let mut num_terms = 0;
+        for _ in 0..self.ent_count {
+            if self.is_leaf_block {
+                let suffix = suffixes.read_vint_bounded(max_len)?;
+                skip_checked(&mut suffixes, suffix as usize)?;
+                num_terms += 1;
+                continue;
+            }
+            let code = suffixes.read_vint()?;
+            let (suffix, flag) = if self.version_auto_prefix {
+                (code.unsigned_shift(2), code & 3)
+            } else {
+                (code.unsigned_shift(1), code & 1)
+            };
+            skip_checked(&mut suffixes, suffix as usize)?;
+            match flag {
+                0 => num_terms += 1,
+                1 => {
+                    let sub_code = suffixes.read_vlong()?;
+                    if sub_code <= 0 || sub_code > self.fp {
+                        bail!(CorruptIndex(format!(
+                            "sub-block code {} points out of the file",
+                            sub_code
+                        )));
+                    }
+                }
+                _ => {
+                    // a floor prefix term
+                    suffixes.read_byte()?;
+                    num_terms += 1;
+                }
+            }
+        }
+        if !suffixes.is_empty() {
+            bail!(CorruptIndex(format!(
+                "{} suffix bytes left after {} entries",
+                suffixes.len(),
+                self.ent_count
+            )));
+        }
+
+        let field_reader = self.terms_iter().field_reader();
+        let field_info = field_reader.field_info();
+        let mut stats = self.block.stat_bytes();
+        let mut metadata = self.block.meta_bytes();
+        let mut state = BlockTermState::new();
+        let mut fps = [0i64; MAX_LONGS_SIZE];
+        let deltas = [0i64; MAX_LONGS_SIZE];
+        for _ in 0..num_terms {
+            state.doc_freq = stats.read_vint()?;
+            if state.doc_freq <= 0 {
+                bail!(CorruptIndex(format!("invalid doc_freq {}", state.doc_freq)));
+            }
+            state.total_term_freq = if field_info.index_options != IndexOptions::Docs {
+                let extra = stats.read_vlong()?;
+                match i64::from(state.doc_freq).checked_add(extra) {
+                    Some(total_term_freq) => total_term_freq,
+                    None => bail!(CorruptIndex(format!(
+                        "invalid total_term_freq delta {}",
+                        extra
+                    ))),
+                }
+            } else {
+                -1
+            };
+            for fp in fps.iter_mut().take(field_reader.longs_size) {
+                let delta = metadata.read_vlong()?;
+                *fp = match fp.checked_add(delta) {
+                    Some(fp) => fp,
+                    None => bail!(CorruptIndex(format!(
+                        "invalid file pointer delta {}",
+                        delta
+                    ))),
+                };
+            }
+            // only the bytes read matter, the file pointers are checked above
+            lucene50_decode_term(&deltas, &mut metadata, field_info, &mut state, true)?;
+        }
+        if !stats.is_empty() || !metadata.is_empty() {
+            bail!(CorruptIndex(format!(
+                "{} stats bytes and {} metadata bytes left after {} terms",
+                stats.len(),
+                metadata.len(),
+                num_terms
+            )));
+        }
         Ok(())
     }
 
+    fn block_corruption(&self, e: &Error) -> Error {
+        let prefix = &self.terms_iter().term[..self.prefix.min(self.terms_iter().term.len())];
+        CorruptIndex(format!(
+            "corrupt block at {} of field {} with prefix {:?}: {}",
+            self.fp,
+            self.terms_iter().field_reader().field_info().name,
+            String::from_utf8_lossy(prefix),
+            e
+        ))
+        .into()
+    }
+
     pub fn rewind(&mut self) {
         self.fp = self.fp_orig;
         self.next_ent = -1;
@@ -275,7 +443,8 @@ impl SegmentTermsIterFrame {
     pub fn next_leaf(&mut self) -> Result<()> {
         debug_assert!(self.next_ent != -1 && self.next_ent < self.ent_count);
         self.next_ent += 1;
-        self.suffix = self.suffixes_reader.read_vint()? as usize;
+        let max_len = self.suffixes_reader.length() as i32;
+        self.suffix = self.suffixes_reader.read_vint_bounded(max_len)? as usize;
         self.start_byte_pos = self.suffixes_reader.position();
         self.terms_iter().resize_term(self.prefix + self.suffix);
         self.suffixes_reader.read_bytes(
@@ -305,6 +474,7 @@ impl SegmentTermsIterFrame {
             let code = self.suffixes_reader.read_vint()?;
             if !self.version_auto_prefix {
                 self.suffix = code.unsigned_shift(1) as usize;
+                self.check_suffix_len()?;
                 self.start_byte_pos = self.suffixes_reader.position();
                 self.terms_iter().resize_term(self.prefix + self.suffix);
                 self.suffixes_reader.read_bytes(
@@ -327,6 +497,7 @@ impl SegmentTermsIterFrame {
                 }
             } else {
                 self.suffix = code.unsigned_shift(2) as usize;
+                self.check_suffix_len()?;
                 self.start_byte_pos = self.suffixes_reader.position();
                 self.terms_iter().resize_term(self.prefix + self.suffix);
                 self.suffixes_reader.read_bytes(
@@ -361,6 +532,18 @@ impl SegmentTermsIterFrame {
         }
     }
 
+    // a corrupt suffix length must not grow the term beyond the block
+    fn check_suffix_len(&self) -> Result<()> {
+        let remaining = self.suffixes_reader.length() - self.suffixes_reader.position();
+        if self.suffix > remaining {
+            bail!(CorruptIndex(format!(
+                "suffix of {} bytes exceeds the {} bytes left in the block at {}",
+                self.suffix, remaining, self.fp
+            )));
+        }
+        Ok(())
+    }
+
     // TODO: make this array'd so we can do bin search?
     // likely not worth it?  need to measure how many
     // floor blocks we "typically" get
@@ -461,7 +644,12 @@ impl SegmentTermsIterFrame {
         debug_assert!(sub_fp < self.fp);
         let target_sub_code = self.fp - sub_fp;
         loop {
-            debug_assert!(self.next_ent < self.ent_count);
+            if self.next_ent == self.ent_count {
+                bail!(CorruptIndex(format!(
+                    "sub-block at {} not found in the block at {}",
+                    sub_fp, self.fp
+                )));
+            }
             self.next_ent += 1;
             let code = self.suffixes_reader.read_vint()?;
             if !self.version_auto_prefix {
@@ -526,7 +714,8 @@ impl SegmentTermsIterFrame {
         'next_term: loop {
             self.next_ent += 1;
 
-            self.suffix = self.suffixes_reader.read_vint()? as usize;
+            let max_len = self.suffixes_reader.length() as i32;
+            self.suffix = self.suffixes_reader.read_vint_bounded(max_len)? as usize;
 
             let term_len = self.prefix + self.suffix;
             self.start_byte_pos = self.suffixes_reader.position();
@@ -540,7 +729,7 @@ impl SegmentTermsIterFrame {
             let mut byte_pos = self.start_byte_pos;
             loop {
                 let (cmp, stop) = if target_pos < target_limit {
-                    let c = self.suffix_bytes[byte_pos].cmp(&target[target_pos]);
+                    let c = self.block.suffix_bytes()[byte_pos].cmp(&target[target_pos]);
                     byte_pos += 1;
                     target_pos += 1;
                     (c, false)
@@ -667,7 +856,7 @@ impl SegmentTermsIterFrame {
             let mut byte_pos = self.start_byte_pos;
             loop {
                 let (cmp, stop) = if target_pos < target_limit {
-                    let c = self.suffix_bytes[byte_pos].cmp(&target[target_pos]);
+                    let c = self.block.suffix_bytes()[byte_pos].cmp(&target[target_pos]);
                     byte_pos += 1;
                     target_pos += 1;
                     (c, false)
@@ -713,7 +902,12 @@ impl SegmentTermsIterFrame {
                     // This cannot be a sub-block because we
                     // would have followed the index to this
                     // sub-block from the start:
-                    debug_assert!(self.terms_iter().term_exists);
+                    if !self.terms_iter().term_exists {
+                        bail!(CorruptIndex(format!(
+                            "a sub-block of the block at {} matches the term",
+                            self.fp
+                        )));
+                    }
                     self.fill_term();
                     return Ok(SeekStatus::Found);
                 }
@@ -742,7 +936,20 @@ impl SegmentTermsIterFrame {
         let term_length = self.prefix + self.suffix;
         self.terms_iter().resize_term(term_length);
         self.terms_iter().term[self.prefix..].copy_from_slice(
-            &self.suffix_bytes[self.start_byte_pos..self.start_byte_pos + self.suffix],
+            &self.block.suffix_bytes()[self.start_byte_pos..self.start_byte_pos + self.suffix],
         );
     }
 }
+
+// skips `count` bytes of a block section checked by `check_block`
+fn skip_checked(section: &mut &[u8], count: usize) -> Result<()> {
+    if count > section.len() {
+        bail!(CorruptIndex(format!(
+            "{} bytes to skip exceed the {} bytes left",
+            count,
+            section.len()
+        )));
+    }
+    *section = &section[count..];
+    Ok(())
+}
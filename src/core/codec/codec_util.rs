@@ -16,9 +16,9 @@ use core::store::{DataInput, DataOutput, IndexInput, IndexOutput};
 
 use core::util::string_util::id2str;
 use core::util::string_util::ID_LENGTH;
-use error::ErrorKind::{CorruptIndex, IllegalArgument, IllegalState};
-use error::Result;
-use std::io::Read;
+use error::ErrorKind::{Cancelled, CorruptIndex, IllegalArgument, IllegalState};
+use error::ErrorKind::{IndexFormatTooNew, IndexFormatTooOld};
+use error::{Error, Result};
 
 pub const CODEC_MAGIC: i32 = 0x3FD7_6C17;
 pub const FOOTER_MAGIC: i32 = !CODEC_MAGIC;
@@ -104,13 +104,74 @@ pub fn check_header_no_magic<T: DataInput + ?Sized>(
         )));
     }
     let actual_ver = data_input.read_int()?;
-    if actual_ver < min_ver || actual_ver > max_ver {
-        bail!(CorruptIndex(format!(
-            "index format either too new or too old: {} <= {} <= {} doesn't hold",
-            min_ver, actual_ver, max_ver
+    check_format_version(codec, actual_ver, min_ver, max_ver)?;
+    Ok(actual_ver)
+}
+
+/// Checks that the format version of `codec` is in the supported range,
+/// telling too old formats apart from too new ones.
+pub fn check_format_version(
+    codec: &str,
+    actual_ver: i32,
+    min_ver: i32,
+    max_ver: i32,
+) -> Result<()> {
+    if actual_ver < min_ver {
+        bail!(IndexFormatTooOld(format!(
+            "format version {} of '{}' is not supported, the oldest supported version is {}. This \
+             index was written by a too old release, upgrade it with the IndexUpgrader of an \
+             older release or reindex",
+            actual_ver, codec, min_ver
         )));
     }
-    Ok(actual_ver)
+    if actual_ver > max_ver {
+        bail!(IndexFormatTooNew(format!(
+            "format version {} of '{}' is not supported, the newest supported version is {}. This \
+             index was written by a newer release, upgrade rucene to read it",
+            actual_ver, codec, max_ver
+        )));
+    }
+    Ok(())
+}
+
+/// Returns true if the error is an `IndexFormatTooOld` or `IndexFormatTooNew` error.
+pub fn is_format_version_error(error: &Error) -> bool {
+    match error.kind() {
+        IndexFormatTooOld(_) | IndexFormatTooNew(_) => true,
+        _ => false,
+    }
+}
+
+/// Reads the codec name and format version of the header at the start of
+/// `input`, or returns `None` if the file doesn't start with a codec header.
+pub fn read_header_version<T: IndexInput + ?Sized>(input: &mut T) -> Result<Option<(String, i32)>> {
+    if input.len() < 4 {
+        return Ok(None);
+    }
+    input.seek(0)?;
+    if input.read_int()? != CODEC_MAGIC {
+        return Ok(None);
+    }
+    let codec = input.read_string()?;
+    let version = input.read_int()?;
+    Ok(Some((codec, version)))
+}
+
+/// Returns the format version the current writers write the files of `codec`
+/// with, or `None` if the versions of `codec` aren't tracked.
+///
+/// Only the terms dictionary, the postings and the norms are tracked.
+pub fn current_format_version(codec: &str) -> Option<i32> {
+    use core::codec::blocktree::{self, TERMS_CODEC_NAME, TERMS_INDEX_CODEC_NAME};
+    use core::codec::lucene50::{self, DOC_CODEC, PAY_CODEC, POS_CODEC, TERMS_CODEC};
+    use core::codec::lucene53::{self, DATA_CODEC, METADATA_CODEC};
+
+    match codec {
+        TERMS_CODEC_NAME | TERMS_INDEX_CODEC_NAME => Some(blocktree::VERSION_CURRENT),
+        TERMS_CODEC | DOC_CODEC | POS_CODEC | PAY_CODEC => Some(lucene50::POSTINGS_VERSION_CURRENT),
+        DATA_CODEC | METADATA_CODEC => Some(lucene53::VERSION_CURRENT),
+        _ => None,
+    }
 }
 
 pub fn check_index_header<T: DataInput + ?Sized>(
@@ -300,22 +361,48 @@ pub fn check_checksum<T: IndexInput + ?Sized>(input: &mut T, actual_checksum: i6
     Ok(())
 }
 
+/// Tells a long running operation whether to go on, returned by its progress
+/// callback. A local copy of `std::ops::ControlFlow`, which this toolchain
+/// doesn't have yet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ControlFlow<B, C = ()> {
+    Continue(C),
+    Break(B),
+}
+
+/// Number of bytes `checksum_entire_file_with` reads between two progress reports.
+pub const CHECKSUM_PROGRESS_INTERVAL: u64 = 1024 * 1024;
+
 pub fn checksum_entire_file<T: IndexInput + ?Sized>(input: &T) -> Result<i64> {
+    checksum_entire_file_with(input, &mut |_, _| ControlFlow::Continue(()))
+}
+
+/// Same as `checksum_entire_file`, but calls `progress` with the number of bytes
+/// read so far and the file length every `CHECKSUM_PROGRESS_INTERVAL` bytes, and
+/// once more when the whole file is verified.
+///
+/// Fails with `Cancelled` as soon as `progress` returns `ControlFlow::Break`,
+/// without reading any further.
+pub fn checksum_entire_file_with<T: IndexInput + ?Sized>(
+    input: &T,
+    progress: &mut dyn FnMut(u64, u64) -> ControlFlow<()>,
+) -> Result<i64> {
     let mut index = input.clone()?;
     index.seek(0)?;
     let mut checksum = BufferedChecksumIndexInput::new(index);
-    let mut len = checksum.len();
+    let total = checksum.len();
     let mut pos = checksum.file_pointer() as u64;
-    if len < footer_length() as u64 {
+    if total < footer_length() as u64 {
         bail!(CorruptIndex(format!(
             "misplaced codec footer (file truncated?): length={} but footerLength=={}",
-            checksum.len(),
+            total,
             footer_length()
         )));
     }
     const BUFSIZ: u64 = 1024 * 64;
     let mut buffer = [0u8; BUFSIZ as usize];
-    len -= footer_length() as u64;
+    let len = total - footer_length() as u64;
+    let mut next_report = CHECKSUM_PROGRESS_INTERVAL;
 
     while pos < len {
         let size = if len - pos < BUFSIZ {
@@ -323,11 +410,199 @@ pub fn checksum_entire_file<T: IndexInput + ?Sized>(input: &T) -> Result<i64> {
         } else {
             BUFSIZ
         };
-        pos += checksum.read(&mut buffer[0..size as usize])? as u64;
+        checksum.read_bytes(&mut buffer, 0, size as usize)?;
+        pos += size;
+        if pos >= next_report {
+            report_progress(progress, pos, total)?;
+            next_report = pos + CHECKSUM_PROGRESS_INTERVAL;
+        }
     }
 
     validate_footer(&mut checksum)?;
     let actual = checksum.checksum();
     check_checksum(&mut checksum, actual)?;
+    report_progress(progress, total, total)?;
     Ok(actual)
 }
+
+fn report_progress(
+    progress: &mut dyn FnMut(u64, u64) -> ControlFlow<()>,
+    done: u64,
+    total: u64,
+) -> Result<()> {
+    if let ControlFlow::Break(()) = progress(done, total) {
+        bail!(Cancelled(format!(
+            "checksum verification cancelled after {} of {} bytes",
+            done, total
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::store::{BufferedChecksumIndexOutput, Directory, IOContext, RAMDirectory};
+    use core::store::{ByteArrayDataInput, RAMOutputStream, RandomAccessInput};
+
+    const CODEC: &str = "TestCodec";
+    const ID: [u8; ID_LENGTH] = [7u8; ID_LENGTH];
+
+    fn write_file(dir: &RAMDirectory, name: &str, data: &[u8]) {
+        let output = dir.create_output(name, &IOContext::Default).unwrap();
+        let mut output = BufferedChecksumIndexOutput::new(output);
+        output.write_bytes(data, 0, data.len()).unwrap();
+    }
+
+    fn verify_file(dir: &RAMDirectory, name: &str) -> Result<i64> {
+        let mut input = dir.open_checksum_input(name, &IOContext::READ)?;
+        check_index_header(&mut input, CODEC, 0, 1, &ID, "sfx")?;
+        if input.read_long()? != 42 || input.read_string()? != "payload" {
+            bail!(CorruptIndex("unexpected content".into()));
+        }
+        check_footer(&mut input)
+    }
+
+    #[test]
+    fn test_header_footer_round_trip() {
+        let dir = RAMDirectory::new();
+        {
+            let output = dir.create_output("test", &IOContext::Default).unwrap();
+            let mut output = BufferedChecksumIndexOutput::new(output);
+            write_index_header(&mut output, CODEC, 1, &ID, "sfx").unwrap();
+            output.write_long(42).unwrap();
+            output.write_string("payload").unwrap();
+            write_footer(&mut output).unwrap();
+        }
+        let checksum = verify_file(&dir, "test").unwrap();
+
+        let mut input = dir.open_input("test", &IOContext::READ).unwrap();
+        assert_eq!(retrieve_checksum(input.as_mut()).unwrap(), checksum);
+        assert_eq!(checksum_entire_file(input.as_ref()).unwrap(), checksum);
+
+        let length = input.len() as usize;
+        assert_eq!(
+            length,
+            index_header_length(CODEC, "sfx") + 8 + 8 + footer_length()
+        );
+        let slice = input.random_access_slice(0, length as i64).unwrap();
+        let data: Vec<u8> = (0..length)
+            .map(|i| slice.read_byte(i as i64).unwrap())
+            .collect();
+
+        // tampering any single byte must fail the verification
+        for i in 0..length {
+            let mut tampered = data.clone();
+            tampered[i] ^= 0x20;
+            let name = format!("tampered_{}", i);
+            write_file(&dir, &name, &tampered);
+            assert!(verify_file(&dir, &name).is_err(), "byte {} not verified", i);
+        }
+    }
+
+    #[test]
+    fn test_checksum_entire_file_progress() {
+        let dir = RAMDirectory::new();
+        let mb = CHECKSUM_PROGRESS_INTERVAL as usize;
+        {
+            let output = dir.create_output("big", &IOContext::Default).unwrap();
+            let mut output = BufferedChecksumIndexOutput::new(output);
+            write_header(&mut output, CODEC, 1).unwrap();
+            let data = vec![0x5au8; mb / 2];
+            for _ in 0..7 {
+                output.write_bytes(&data, 0, data.len()).unwrap();
+            }
+            write_footer(&mut output).unwrap();
+        }
+        let input = dir.open_input("big", &IOContext::READ).unwrap();
+        let total = input.len();
+
+        let mut reports = vec![];
+        let checksum = checksum_entire_file_with(input.as_ref(), &mut |done, len| {
+            reports.push((done, len));
+            ControlFlow::Continue(())
+        })
+        .unwrap();
+        assert_eq!(checksum, checksum_entire_file(input.as_ref()).unwrap());
+        let mb = mb as u64;
+        assert_eq!(
+            reports,
+            vec![
+                (mb, total),
+                (2 * mb, total),
+                (3 * mb, total),
+                (total, total)
+            ]
+        );
+
+        let mut reports = vec![];
+        let err = checksum_entire_file_with(input.as_ref(), &mut |done, _| {
+            reports.push(done);
+            ControlFlow::Break(())
+        })
+        .unwrap_err();
+        match err.kind() {
+            Cancelled(_) => {}
+            e => panic!("unexpected error: {:?}", e),
+        }
+        // nothing was read after the first report
+        assert_eq!(reports, vec![mb]);
+    }
+
+    #[test]
+    fn test_header_version_classification() {
+        let mut output = RAMOutputStream::new(false);
+        write_header(&mut output, CODEC, 99).unwrap();
+        write_header(&mut output, CODEC, -3).unwrap();
+        write_header(&mut output, CODEC, 1).unwrap();
+        let length = output.file_pointer() as usize;
+        let mut data = vec![0u8; length];
+        output.write_to_buf(&mut data).unwrap();
+        let mut input = ByteArrayDataInput::new(data);
+
+        let err = check_header(&mut input, CODEC, 0, 1).unwrap_err();
+        assert!(is_format_version_error(&err));
+        match err.kind() {
+            IndexFormatTooNew(msg) => {
+                assert!(msg.contains("format version 99 of 'TestCodec'"), "{}", msg);
+                assert!(msg.contains("newest supported version is 1"), "{}", msg);
+            }
+            e => panic!("unexpected error: {:?}", e),
+        }
+
+        let err = check_header(&mut input, CODEC, 0, 1).unwrap_err();
+        match err.kind() {
+            IndexFormatTooOld(msg) => {
+                assert!(msg.contains("format version -3 of 'TestCodec'"), "{}", msg);
+                assert!(msg.contains("oldest supported version is 0"), "{}", msg);
+                assert!(msg.contains("IndexUpgrader"), "{}", msg);
+            }
+            e => panic!("unexpected error: {:?}", e),
+        }
+
+        assert_eq!(check_header(&mut input, CODEC, 0, 1).unwrap(), 1);
+
+        // a wrong codec name is corruption, not a version problem
+        let mut output = RAMOutputStream::new(false);
+        write_header(&mut output, "Other", 99).unwrap();
+        let mut data = vec![0u8; output.file_pointer() as usize];
+        output.write_to_buf(&mut data).unwrap();
+        let err = check_header(&mut ByteArrayDataInput::new(data), CODEC, 0, 1).unwrap_err();
+        assert!(!is_format_version_error(&err));
+    }
+
+    #[test]
+    fn test_footer_requires_checksum() {
+        // the output doesn't compute a checksum itself, the wrapper does
+        let mut output = RAMOutputStream::new(false);
+        assert!(write_footer(&mut output).is_err());
+
+        let mut output = BufferedChecksumIndexOutput::new(RAMOutputStream::new(false));
+        write_header(&mut output, CODEC, 0).unwrap();
+        write_footer(&mut output).unwrap();
+        assert_eq!(
+            output.file_pointer() as usize,
+            9 + CODEC.len() + footer_length()
+        );
+    }
+}
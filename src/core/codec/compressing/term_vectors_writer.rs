@@ -1092,7 +1092,10 @@ impl<O: IndexOutput> TermVectorsWriter for CompressingTermVectorsWriter<O> {
                             } else {
                                 None
                             };
-                        self.add_all_doc_vectors(vectors.as_ref(), merge_state)?;
+                        self.add_all_doc_vectors(
+                            vectors.as_ref(),
+                            merge_state.merge_field_infos.as_ref().unwrap(),
+                        )?;
                         doc_count += 1;
                     }
                 }
@@ -18,14 +18,15 @@ use core::codec::{
 use core::index::doc_id_merger::{
     doc_id_merger_of, DocIdMerger, DocIdMergerEnum, DocIdMergerSub, DocIdMergerSubBase,
 };
+use core::index::merge::MergedFields;
 use core::index::{
-    AcceptStatus, BinaryDocValuesRef, DocValuesTermIterator, DocValuesType, EmptyBinaryDocValues,
-    EmptyNumericDocValues, EmptySortedDocValues, EmptySortedNumericDocValues,
+    AcceptStatus, BinaryDocValuesContext, BinaryDocValuesRef, DocValuesTermIterator, DocValuesType,
+    EmptyBinaryDocValues, EmptyNumericDocValues, EmptySortedDocValues, EmptySortedNumericDocValues,
     EmptySortedSetDocValues, FieldInfo, Fields, FilteredTermIterBase, FilteredTermIterator,
-    LiveDocsDocMap, MappedMultiFields, MergeState, MultiFields, NumericDocValues,
-    NumericDocValuesContext, NumericDocValuesRef, OrdTermState, OrdinalMap, ReaderSlice,
-    SeekStatus, SortedDocValues, SortedDocValuesRef, SortedNumericDocValuesContext,
-    SortedNumericDocValuesRef, SortedSetDocValuesRef, TermIterator, NO_MORE_ORDS,
+    LiveDocsDocMap, MergeState, NumericDocValues, NumericDocValuesContext, NumericDocValuesRef,
+    OrdTermState, OrdinalMap, SeekStatus, SortedDocValues, SortedDocValuesRef,
+    SortedNumericDocValuesContext, SortedNumericDocValuesRef, SortedSetDocValuesRef, TermIterator,
+    NO_MORE_ORDS,
 };
 use core::search::posting_iterator::EmptyPostingIterator;
 use core::search::NO_MORE_DOCS;
@@ -38,7 +39,6 @@ use core::util::{BitsRef, BytesRef, DocId, LongValues, MatchNoBits, ReusableIter
 use error::Result;
 
 use std::ptr;
-use std::rc::Rc;
 use std::sync::Arc;
 
 /// Abstract API that consumes terms, doc, freq, prox, offset and
@@ -72,27 +72,18 @@ pub trait FieldsConsumer {
 
     /// Merges in the fields from the readers in
     /// <code>mergeState</code>. The default implementation skips
-    /// and maps around deleted documents, and calls {@link #write(Fields)}.
+    /// and maps around deleted documents with `MergedFields`, and calls
+    /// {@link #write(Fields)}.
     /// Implementations can override this method for more sophisticated
     /// merging (bulk-byte copying, etc).
     fn merge<D: Directory, C: Codec>(&mut self, merge_state: &mut MergeState<D, C>) -> Result<()> {
-        let mut fields = vec![];
-        let mut slices = vec![];
-
-        let mut doc_base = 0;
-        let fields_producers = merge_state.fields_producers.clone();
-        // let fields_producers = mem::replace(&mut merge_state.fields_producers,
-        // Vec::with_capacity(0));
-        for (i, f) in fields_producers.into_iter().enumerate() {
+        for f in &merge_state.fields_producers {
             f.check_integrity()?;
-            let max_doc = merge_state.max_docs[i];
-            slices.push(ReaderSlice::new(doc_base, max_doc, i));
-            fields.push(f);
-            doc_base += max_doc;
         }
-
-        let fields = MultiFields::new(fields, slices);
-        let merged_fields = MappedMultiFields::new(merge_state, fields);
+        let merged_fields = MergedFields::new(
+            merge_state.fields_producers.clone(),
+            merge_state.doc_maps.clone(),
+        )?;
         self.write(&merged_fields)
     }
 }
@@ -132,10 +123,12 @@ pub trait DocValuesConsumer {
         values: &mut impl ReusableIterator<Item = Result<Numeric>>,
     ) -> Result<()>;
 
+    /// Writes the binary values of a field, one per doc, `None` for the docs
+    /// without a value, which are not the same as the docs with an empty value.
     fn add_binary_field(
         &mut self,
         field_info: &FieldInfo,
-        values: &mut impl ReusableIterator<Item = Result<BytesRef>>,
+        values: &mut impl ReusableIterator<Item = Result<Option<BytesRef>>>,
     ) -> Result<()>;
 
     fn add_sorted_field(
@@ -689,7 +682,7 @@ struct BinaryDocValuesMergeIter<'a, D: Directory + 'static, C: Codec> {
     docs_with_field: &'a [BitsRef],
     doc_id_merger: DocIdMergerEnum<BinaryDocValuesSub>,
     next_value: Vec<u8>,
-    next_ref: BytesRef,
+    next_ref: Option<BytesRef>,
     next_is_set: bool,
 }
 
@@ -715,7 +708,7 @@ impl<'a, D: Directory + 'static, C: Codec> BinaryDocValuesMergeIter<'a, D, C> {
             docs_with_field,
             doc_id_merger,
             next_value: vec![],
-            next_ref: BytesRef::default(),
+            next_ref: None,
             next_is_set: false,
         })
     }
@@ -726,12 +719,13 @@ impl<'a, D: Directory + 'static, C: Codec> BinaryDocValuesMergeIter<'a, D, C> {
     fn set_next(&mut self) -> Result<bool> {
         if let Some(sub) = self.doc_id_merger.next()? {
             self.next_is_set = true;
+            self.next_value.clear();
             if sub.docs_with_field.get(sub.doc_id as usize)? {
-                self.next_value = sub.values.get(sub.doc_id)?;
-                self.next_ref = BytesRef::new(&self.next_value);
+                let value = sub.values.get_with_ctx(&mut sub.ctx, sub.doc_id)?;
+                self.next_value.extend_from_slice(value);
+                self.next_ref = Some(BytesRef::new(&self.next_value));
             } else {
-                self.next_value.clear();
-                self.next_ref = BytesRef::default();
+                self.next_ref = None;
             };
             Ok(true)
         } else {
@@ -741,9 +735,9 @@ impl<'a, D: Directory + 'static, C: Codec> BinaryDocValuesMergeIter<'a, D, C> {
 }
 
 impl<'a, D: Directory + 'static, C: Codec> Iterator for BinaryDocValuesMergeIter<'a, D, C> {
-    type Item = Result<BytesRef>;
+    type Item = Result<Option<BytesRef>>;
 
-    fn next(&mut self) -> Option<Result<BytesRef>> {
+    fn next(&mut self) -> Option<Result<Option<BytesRef>>> {
         match self.has_next() {
             Err(e) => Some(Err(e)),
             Ok(true) => {
@@ -771,12 +765,13 @@ impl<'a, D: Directory + 'static, C: Codec> ReusableIterator for BinaryDocValuesM
         self.doc_id_merger = doc_id_merger;
         self.next_is_set = false;
         self.next_value.clear();
-        self.next_ref = BytesRef::default();
+        self.next_ref = None;
     }
 }
 
 struct BinaryDocValuesSub {
     values: BinaryDocValuesRef,
+    ctx: BinaryDocValuesContext,
     docs_with_field: BitsRef,
     doc_id: i32,
     max_doc: i32,
@@ -793,6 +788,7 @@ impl BinaryDocValuesSub {
         let base = DocIdMergerSubBase::new(doc_map);
         BinaryDocValuesSub {
             values,
+            ctx: BinaryDocValuesContext::default(),
             docs_with_field,
             doc_id: -1,
             max_doc,
@@ -958,7 +954,7 @@ struct SortedDocValuesSub {
     values: SortedDocValuesRef,
     doc_id: DocId,
     max_doc: i32,
-    map: Rc<dyn LongValues>,
+    map: Arc<dyn LongValues>,
     base: DocIdMergerSubBase,
 }
 
@@ -967,7 +963,7 @@ impl SortedDocValuesSub {
         doc_map: Arc<LiveDocsDocMap>,
         values: SortedDocValuesRef,
         max_doc: i32,
-        map: Rc<dyn LongValues>,
+        map: Arc<dyn LongValues>,
     ) -> Self {
         let base = DocIdMergerSubBase::new(doc_map);
         SortedDocValuesSub {
@@ -1249,7 +1245,7 @@ struct SortedSetDocValuesSub {
     values: SortedSetDocValuesRef,
     doc_id: DocId,
     max_doc: i32,
-    map: Rc<dyn LongValues>,
+    map: Arc<dyn LongValues>,
     base: DocIdMergerSubBase,
 }
 
@@ -1258,7 +1254,7 @@ impl SortedSetDocValuesSub {
         doc_map: Arc<LiveDocsDocMap>,
         values: SortedSetDocValuesRef,
         max_doc: i32,
-        map: Rc<dyn LongValues>,
+        map: Arc<dyn LongValues>,
     ) -> Self {
         let base = DocIdMergerSubBase::new(doc_map);
         SortedSetDocValuesSub {
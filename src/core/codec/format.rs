@@ -90,8 +90,10 @@ pub trait PostingsFormat {
     fn name(&self) -> &str;
 }
 
+#[derive(Copy, Clone, Eq, PartialEq)]
 pub enum PostingsFormatEnum {
     Lucene50(Lucene50PostingsFormat),
+    Direct(DirectPostingsFormat),
 }
 
 impl PostingsFormat for PostingsFormatEnum {
@@ -105,6 +107,9 @@ impl PostingsFormat for PostingsFormatEnum {
             PostingsFormatEnum::Lucene50(f) => {
                 Ok(FieldsProducerEnum::Lucene50(f.fields_producer(state)?))
             }
+            PostingsFormatEnum::Direct(f) => {
+                Ok(FieldsProducerEnum::Lucene50(f.fields_producer(state)?))
+            }
         }
     }
 
@@ -114,12 +119,14 @@ impl PostingsFormat for PostingsFormatEnum {
     ) -> Result<FieldsConsumerEnum<D, DW, C>> {
         match self {
             PostingsFormatEnum::Lucene50(f) => f.fields_consumer(state),
+            PostingsFormatEnum::Direct(f) => f.fields_consumer(state),
         }
     }
 
     fn name(&self) -> &str {
         match self {
             PostingsFormatEnum::Lucene50(f) => f.name(),
+            PostingsFormatEnum::Direct(f) => f.name(),
         }
     }
 }
@@ -129,8 +136,9 @@ pub fn postings_format_for_name(name: &str) -> Result<PostingsFormatEnum> {
         "Lucene50" => Ok(PostingsFormatEnum::Lucene50(
             Lucene50PostingsFormat::default(),
         )),
+        "Direct" => Ok(PostingsFormatEnum::Direct(DirectPostingsFormat::default())),
         _ => bail!(IllegalArgument(format!(
-            "Invalid postings format: {}",
+            "unknown postings format {:?}, the known formats are: Lucene50, Direct",
             name
         ))),
     }
@@ -265,6 +273,7 @@ pub trait DocValuesFormat {
     ) -> Result<DocValuesConsumerEnum<D, DW, C>>;
 }
 
+#[derive(Clone)]
 pub enum DocValuesFormatEnum {
     Lucene54(Lucene54DocValuesFormat),
     PerField(PerFieldDocValuesFormat),
@@ -319,7 +328,7 @@ impl<D: Directory, DW: Directory, C: Codec> DocValuesConsumer for DocValuesConsu
     fn add_binary_field(
         &mut self,
         field_info: &FieldInfo,
-        values: &mut impl ReusableIterator<Item = Result<BytesRef>>,
+        values: &mut impl ReusableIterator<Item = Result<Option<BytesRef>>>,
     ) -> Result<()> {
         match self {
             DocValuesConsumerEnum::Lucene54(d) => d.add_binary_field(field_info, values),
@@ -474,7 +483,10 @@ pub fn doc_values_format_for_name(format: &str) -> Result<DocValuesFormatEnum> {
         "Lucene54" => Ok(DocValuesFormatEnum::Lucene54(
             Lucene54DocValuesFormat::default(),
         )),
-        _ => unimplemented!(),
+        _ => bail!(IllegalArgument(format!(
+            "unknown doc values format {:?}, the known formats are: Lucene54",
+            format
+        ))),
     }
 }
 
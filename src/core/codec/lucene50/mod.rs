@@ -19,8 +19,10 @@ mod posting_format;
 mod posting_reader;
 mod posting_writer;
 
-pub use self::posting_format::Lucene50PostingsFormat;
+pub use self::posting_format::{Lucene50PostingsFormat, DEFAULT_TERM_STATES_CACHE_SIZE};
 pub use self::posting_reader::*;
+// `VERSION_CURRENT` is also defined by the compound format
+pub use self::posting_reader::VERSION_CURRENT as POSTINGS_VERSION_CURRENT;
 pub use self::posting_writer::*;
 
 mod stored_fields;
@@ -22,11 +22,13 @@ use error::Result;
 
 use std::fmt;
 
-#[derive(Hash, Eq, Ord, PartialEq, PartialOrd)]
+#[derive(Copy, Clone, Hash, Eq, Ord, PartialEq, PartialOrd)]
 pub struct Lucene50PostingsFormat {
     name: &'static str,
     min_term_block_size: usize,
     max_term_block_size: usize,
+    verify_blocks: bool,
+    term_states_cache_size: usize,
 }
 
 /// Fixed packed block size, number of integers encoded in
@@ -37,6 +39,10 @@ pub const BLOCK_SIZE: i32 = 128;
 const DEFAULT_MIN_BLOCK_SIZE: usize = 25;
 const DEFAULT_MAX_BLOCK_SIZE: usize = 48;
 
+/// The number of term states cached per field by the readers, see
+/// `BlockTreeTermsReader::cache_term_states`.
+pub const DEFAULT_TERM_STATES_CACHE_SIZE: usize = 1024;
+
 impl fmt::Display for Lucene50PostingsFormat {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "{}(blocksize={})", self.name, BLOCK_SIZE)
@@ -58,8 +64,26 @@ impl Lucene50PostingsFormat {
             name: "Lucene50",
             min_term_block_size,
             max_term_block_size,
+            verify_blocks: false,
+            term_states_cache_size: DEFAULT_TERM_STATES_CACHE_SIZE,
         }
     }
+
+    /// Checks each terms block when it is loaded by the readers of this
+    /// format, see `BlockTreeTermsReader::new`.
+    ///
+    /// To verify the blocks of an index, give a `PerFieldPostingsFormat` using
+    /// this format to `Lucene62Codec::with_per_field_formats`. The per-field
+    /// format reads the "Lucene50" fields of the segments of that codec with it.
+    pub fn set_verify_blocks(&mut self, verify_blocks: bool) {
+        self.verify_blocks = verify_blocks;
+    }
+
+    /// Caches the states of `size` terms per field in the readers of this
+    /// format, none if `size` is 0.
+    pub fn set_term_states_cache_size(&mut self, size: usize) {
+        self.term_states_cache_size = size;
+    }
 }
 
 impl PostingsFormat for Lucene50PostingsFormat {
@@ -68,8 +92,12 @@ impl PostingsFormat for Lucene50PostingsFormat {
         &self,
         state: &SegmentReadState<'a, D, DW, C>,
     ) -> Result<Self::FieldsProducer> {
-        let reader = Lucene50PostingsReader::open(&state)?;
-        BlockTreeTermsReader::new(reader, state)
+        let postings_reader = Lucene50PostingsReader::open(&state)?;
+        let mut reader = BlockTreeTermsReader::new(postings_reader, state, self.verify_blocks)?;
+        if self.term_states_cache_size > 0 {
+            reader.cache_term_states(self.term_states_cache_size)?;
+        }
+        Ok(reader)
     }
 
     fn fields_consumer<D: Directory, DW: Directory, C: Codec>(
@@ -11,11 +11,12 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use core::codec::codec_util::{self, ControlFlow};
 use core::codec::lucene50::posting_format::BLOCK_SIZE;
 use core::codec::lucene50::skip_reader::*;
 use core::codec::lucene50::util::*;
 use core::codec::BlockTermState;
-use core::codec::{codec_util, Codec};
+use core::codec::Codec;
 use core::index::FieldInfo;
 use core::index::{segment_file_name, SegmentReadState};
 use core::search::posting_iterator::*;
@@ -238,6 +239,22 @@ impl Lucene50PostingsReader {
         //        }
         Ok(())
     }
+
+    /// Verifies the checksums of the whole postings files, reporting the
+    /// progress of each file to `progress`.
+    pub fn check_integrity_with(
+        &self,
+        progress: &mut dyn FnMut(u64, u64) -> ControlFlow<()>,
+    ) -> Result<()> {
+        codec_util::checksum_entire_file_with(self.doc_in.as_ref(), progress)?;
+        if let Some(ref pos_in) = self.pos_in {
+            codec_util::checksum_entire_file_with(pos_in.as_ref(), progress)?;
+        }
+        if let Some(ref pay_in) = self.pay_in {
+            codec_util::checksum_entire_file_with(pay_in.as_ref(), progress)?;
+        }
+        Ok(())
+    }
 }
 
 pub type Lucene50PostingsReaderRef = Arc<Lucene50PostingsReader>;
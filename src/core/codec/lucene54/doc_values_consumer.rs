@@ -363,7 +363,7 @@ impl<O: IndexOutput> Lucene54DocValuesConsumer<O> {
 
     fn write_missing_bitset_bytes(
         &mut self,
-        values: &mut impl ReusableIterator<Item = Result<BytesRef>>,
+        values: &mut impl ReusableIterator<Item = Result<Option<BytesRef>>>,
     ) -> Result<()> {
         let mut bits = 0u8;
         let mut count = 0i32;
@@ -376,7 +376,7 @@ impl<O: IndexOutput> Lucene54DocValuesConsumer<O> {
                 bits = 0;
             }
 
-            if v.len() > 0 {
+            if v.is_some() {
                 bits |= 1 << (count & 7);
             }
             count += 1;
@@ -430,6 +430,100 @@ impl<O: IndexOutput> Lucene54DocValuesConsumer<O> {
         Ok(doc_id)
     }
 
+    /// writes the values contiguously, addressed by their length if not all of
+    /// the same length, and the docs with a value if some have none.
+    fn write_binary_field(
+        &mut self,
+        field_info: &FieldInfo,
+        values: &mut impl ReusableIterator<Item = Result<Option<BytesRef>>>,
+    ) -> Result<()> {
+        // write the byte[] data
+        self.meta.write_vint(field_info.number as i32)?;
+        self.meta.write_byte(Lucene54DocValuesFormat::BINARY)?;
+        let mut min_length = i32::max_value();
+        let mut max_length = i32::min_value();
+        let start_fp = self.data.file_pointer();
+        let mut count = 0i64;
+        let mut missing_count = 0i64;
+
+        loop {
+            let v = match values.next() {
+                None => {
+                    break;
+                }
+                Some(r) => r?,
+            };
+            let length = match v {
+                Some(v) => {
+                    self.data.write_bytes(v.bytes(), 0, v.len())?;
+                    v.len() as i32
+                }
+                None => {
+                    missing_count += 1;
+                    0
+                }
+            };
+
+            min_length = length.min(min_length);
+            max_length = length.max(max_length);
+
+            count += 1;
+        }
+
+        let v = if min_length == max_length {
+            Lucene54DocValuesFormat::BINARY_FIXED_UNCOMPRESSED
+        } else {
+            Lucene54DocValuesFormat::BINARY_VARIABLE_UNCOMPRESSED
+        };
+        self.meta.write_vint(v)?;
+        if missing_count == 0 {
+            self.meta
+                .write_long(Lucene54DocValuesFormat::ALL_LIVE as i64)?;
+        } else if missing_count == count {
+            self.meta
+                .write_long(Lucene54DocValuesFormat::ALL_MISSING as i64)?;
+        } else {
+            self.meta.write_long(self.data.file_pointer())?;
+            values.reset();
+            self.write_missing_bitset_bytes(values)?;
+        }
+
+        self.meta.write_vint(min_length)?;
+        self.meta.write_vint(max_length)?;
+        self.meta.write_vlong(count)?;
+        self.meta.write_long(start_fp)?;
+
+        // if minLength == maxLength, it's a fixed-length byte[], we are done (the addresses are
+        // implicit) otherwise, we need to record the length fields...
+
+        if min_length != max_length {
+            self.meta.write_long(self.data.file_pointer())?;
+            self.meta
+                .write_vint(Lucene54DocValuesFormat::DIRECT_MONOTONIC_BLOCK_SHIFT)?;
+
+            {
+                let mut writer = DirectMonotonicWriter::get_instance(
+                    &mut self.meta,
+                    &mut self.data,
+                    count + 1,
+                    Lucene54DocValuesFormat::DIRECT_MONOTONIC_BLOCK_SHIFT,
+                )?;
+                let mut addr = 0;
+                writer.add(addr)?;
+                values.reset();
+                for v in values {
+                    if let Some(v) = v? {
+                        addr += v.len() as i64;
+                    }
+                    writer.add(addr)?;
+                }
+                writer.finish()?;
+            }
+            self.meta.write_long(self.data.file_pointer())?;
+        }
+        Ok(())
+    }
+
     fn add_terms_dict(
         &mut self,
         field_info: &FieldInfo,
@@ -475,11 +569,17 @@ impl<O: IndexOutput> Lucene54DocValuesConsumer<O> {
         {
             // no index needed: not very compressible, direct addressing by mult
             values.reset();
-            self.add_binary_field(field_info, values)?;
+            self.write_binary_field(
+                field_info,
+                &mut ReusableIterMap::new(values, |v: Result<BytesRef>| v.map(Some)),
+            )?;
         } else if (num_values as i32) < Lucene54DocValuesFormat::REVERSE_INTERVAL_COUNT {
             // low cardinality: waste a few KB of ram, but can't really use fancy index etc
             values.reset();
-            self.add_binary_field(field_info, values)?;
+            self.write_binary_field(
+                field_info,
+                &mut ReusableIterMap::new(values, |v: Result<BytesRef>| v.map(Some)),
+            )?;
         } else {
             debug_assert!(num_values > 0); // we don't have to handle the empty case
                                            // header
@@ -802,17 +902,13 @@ impl<O: IndexOutput> DocValuesConsumer for Lucene54DocValuesConsumer<O> {
     fn add_binary_field(
         &mut self,
         field_info: &FieldInfo,
-        values: &mut impl ReusableIterator<Item = Result<BytesRef>>,
+        values: &mut impl ReusableIterator<Item = Result<Option<BytesRef>>>,
     ) -> Result<()> {
-        // write the byte[] data
-        self.meta.write_vint(field_info.number as i32)?;
-        self.meta.write_byte(Lucene54DocValuesFormat::BINARY)?;
-        let mut min_length = i32::max_value();
-        let mut max_length = i32::min_value();
-        let start_fp = self.data.file_pointer();
+        // strictly increasing values are written as a terms dict, which
+        // prefix-compresses them per block if they share enough bytes
+        let mut sorted = true;
         let mut count = 0i64;
-        let mut missing_count = 0i64;
-
+        let mut previous_value: Vec<u8> = vec![];
         loop {
             let v = match values.next() {
                 None => {
@@ -820,73 +916,30 @@ impl<O: IndexOutput> DocValuesConsumer for Lucene54DocValuesConsumer<O> {
                 }
                 Some(r) => r?,
             };
-            let length = v.len() as i32;
-            if length == 0 {
-                missing_count += 1;
-            }
-
-            min_length = length.min(min_length);
-            max_length = length.max(max_length);
-            if length > 0 {
-                self.data.write_bytes(v.bytes(), 0, length as usize)?;
+            match v {
+                Some(v) if count == 0 || v.bytes() > previous_value.as_slice() => {
+                    previous_value.clear();
+                    previous_value.extend_from_slice(v.bytes());
+                }
+                _ => {
+                    sorted = false;
+                    break;
+                }
             }
-
             count += 1;
         }
 
-        let v = if min_length == max_length {
-            Lucene54DocValuesFormat::BINARY_FIXED_UNCOMPRESSED
-        } else {
-            Lucene54DocValuesFormat::BINARY_VARIABLE_UNCOMPRESSED
-        };
-        self.meta.write_vint(v)?;
-        if missing_count == 0 {
-            self.meta
-                .write_long(Lucene54DocValuesFormat::ALL_LIVE as i64)?;
-        } else if missing_count == count {
-            self.meta
-                .write_long(Lucene54DocValuesFormat::ALL_MISSING as i64)?;
+        values.reset();
+        if sorted && count > 0 {
+            self.add_terms_dict(
+                field_info,
+                &mut ReusableIterMap::new(values, |v: Result<Option<BytesRef>>| {
+                    v.map(Option::unwrap)
+                }),
+            )
         } else {
-            self.meta.write_long(self.data.file_pointer())?;
-            values.reset();
-            self.write_missing_bitset_bytes(values)?;
-        }
-
-        self.meta.write_vint(min_length)?;
-        self.meta.write_vint(max_length)?;
-        self.meta.write_vlong(count)?;
-        self.meta.write_long(start_fp)?;
-
-        // if minLength == maxLength, it's a fixed-length byte[], we are done (the addresses are
-        // implicit) otherwise, we need to record the length fields...
-
-        if min_length != max_length {
-            self.meta.write_long(self.data.file_pointer())?;
-            self.meta
-                .write_vint(Lucene54DocValuesFormat::DIRECT_MONOTONIC_BLOCK_SHIFT)?;
-
-            {
-                let mut writer = DirectMonotonicWriter::get_instance(
-                    &mut self.meta,
-                    &mut self.data,
-                    count + 1,
-                    Lucene54DocValuesFormat::DIRECT_MONOTONIC_BLOCK_SHIFT,
-                )?;
-                let mut addr = 0;
-                writer.add(addr)?;
-                values.reset();
-                for v in values {
-                    let v = v?;
-                    if v.len() > 0 {
-                        addr += v.len() as i64;
-                    }
-                    writer.add(addr)?;
-                }
-                writer.finish()?;
-            }
-            self.meta.write_long(self.data.file_pointer())?;
+            self.write_binary_field(field_info, values)
         }
-        Ok(())
     }
 
     fn add_sorted_field(
@@ -1072,6 +1125,42 @@ where
     }
 }
 
+// maps the values of binary fields between the terms dict, where all the docs
+// have a value, and the plain binary writer, which takes the docs without one
+struct ReusableIterMap<'a, I: 'a, F> {
+    iter: &'a mut dyn ReusableIterator<Item = I>,
+    f: F,
+}
+
+impl<'a, I, O, F> ReusableIterMap<'a, I, F>
+where
+    F: FnMut(I) -> O,
+{
+    pub fn new(iter: &'a mut dyn ReusableIterator<Item = I>, f: F) -> ReusableIterMap<'a, I, F> {
+        ReusableIterMap { iter, f }
+    }
+}
+
+impl<'a, I, O, F> ReusableIterator for ReusableIterMap<'a, I, F>
+where
+    F: FnMut(I) -> O,
+{
+    fn reset(&mut self) {
+        self.iter.reset();
+    }
+}
+
+impl<'a, I, O, F> Iterator for ReusableIterMap<'a, I, F>
+where
+    F: FnMut(I) -> O,
+{
+    type Item = O;
+
+    fn next(&mut self) -> Option<O> {
+        self.iter.next().map(&mut self.f)
+    }
+}
+
 fn hash_vec(longs: &[i64]) -> u64 {
     let mut hasher = DefaultHasher::default();
     longs.hash(&mut hasher);
@@ -161,6 +161,16 @@ impl PointValues for Lucene60PointsReader {
             .unwrap_or(Ok(()))
     }
 
+    fn estimate_point_count(
+        &self,
+        field_name: &str,
+        visitor: &mut impl IntersectVisitor,
+    ) -> Result<i64> {
+        self.bkd_reader(field_name)?
+            .map(|reader| reader.estimate_point_count(visitor))
+            .unwrap_or(Ok(0))
+    }
+
     fn min_packed_value(&self, field_name: &str) -> Result<Vec<u8>> {
         // Schema ghost corner case!  This field did index points in the past, but
         // now all docs having this point field were deleted in this segment:
@@ -148,76 +148,72 @@ impl<D: Directory, DW: Directory, C: Codec> PointsWriter for Lucene60PointsWrite
             .values()
         {
             if field_info.point_dimension_count > 0 {
-                if field_info.point_dimension_count == 1 {
-                    let mut single_value_per_doc = false;
-                    // Worst case total maximum size (if none of the points are deleted):
-                    let mut total_max_size = 0;
-                    for i in 0..merge_state.points_readers.len() {
-                        if let Some(reader) = &merge_state.points_readers[i] {
-                            if let Some(reader_field_info) =
-                                merge_state.fields_infos[i].field_info_by_name(&field_info.name)
-                            {
-                                if reader_field_info.point_dimension_count > 0 {
-                                    let max_size = reader.size(&field_info.name)?;
-                                    total_max_size += max_size;
-                                    single_value_per_doc &=
-                                        max_size == reader.doc_count(&field_info.name)? as i64;
-                                }
+                let mut single_value_per_doc = false;
+                // Worst case total maximum size (if none of the points are deleted):
+                let mut total_max_size = 0;
+                for i in 0..merge_state.points_readers.len() {
+                    if let Some(reader) = &merge_state.points_readers[i] {
+                        if let Some(reader_field_info) =
+                            merge_state.fields_infos[i].field_info_by_name(&field_info.name)
+                        {
+                            if reader_field_info.point_dimension_count > 0 {
+                                let max_size = reader.size(&field_info.name)?;
+                                total_max_size += max_size;
+                                single_value_per_doc &=
+                                    max_size == reader.doc_count(&field_info.name)? as i64;
                             }
                         }
                     }
+                }
 
-                    // Optimize the 1D case to use BKDWriter.merge, which does a single merge sort
-                    // of the already sorted incoming segments, instead of
-                    // trying to sort all points again as if we were simply
-                    // reindexing them:
-                    let mut writer = BKDWriter::new(
-                        self.write_state.segment_info.max_doc,
-                        Arc::clone(&self.write_state.directory),
-                        &self.write_state.segment_info.name,
-                        field_info.point_dimension_count as usize,
-                        field_info.point_num_bytes as usize,
-                        self.max_points_in_leaf_node,
-                        self.max_mb_sort_in_heap,
-                        total_max_size,
-                        single_value_per_doc,
-                    )?;
-                    let mut bkd_readers = vec![];
-                    let mut doc_maps: Vec<&LiveDocsDocMap> = vec![];
-                    for i in 0..merge_state.points_readers.len() {
-                        if let Some(reader) = &merge_state.points_readers[i] {
-                            // we confirmed this up above
-                            let reader60: &Lucene60PointsReader = reader
-                                .as_any()
-                                .downcast_ref::<Lucene60PointsReader>()
-                                .unwrap();
+                // BKDWriter.merge reads the leaf blocks of the incoming segments directly,
+                // and in the 1D case does a single merge sort of their already sorted
+                // points, instead of trying to sort all points again as if we were
+                // simply reindexing them:
+                let mut writer = BKDWriter::new(
+                    self.write_state.segment_info.max_doc,
+                    Arc::clone(&self.write_state.directory),
+                    &self.write_state.segment_info.name,
+                    field_info.point_dimension_count as usize,
+                    field_info.point_num_bytes as usize,
+                    self.max_points_in_leaf_node,
+                    self.max_mb_sort_in_heap,
+                    total_max_size,
+                    single_value_per_doc,
+                )?;
+                let mut bkd_readers = vec![];
+                let mut doc_maps: Vec<&LiveDocsDocMap> = vec![];
+                for i in 0..merge_state.points_readers.len() {
+                    if let Some(reader) = &merge_state.points_readers[i] {
+                        // we confirmed this up above
+                        let reader60: &Lucene60PointsReader = reader
+                            .as_any()
+                            .downcast_ref::<Lucene60PointsReader>()
+                            .unwrap();
 
-                            // NOTE: we cannot just use the merged fieldInfo.number (instead of
-                            // resolving to this reader's FieldInfo as
-                            // we do below) because field numbers can easily be different
-                            // when addIndexes(Directory...) copies over segments from another
-                            // index:
-                            if let Some(reader_field_info) =
-                                merge_state.fields_infos[i].field_info_by_name(&field_info.name)
-                            {
-                                if reader_field_info.point_dimension_count > 0 {
-                                    if let Some(bkd_reader) =
-                                        reader60.readers.get(&(reader_field_info.number as i32))
-                                    {
-                                        bkd_readers.push(bkd_reader);
-                                        doc_maps.push(merge_state.doc_maps[i].as_ref());
-                                    }
+                        // NOTE: we cannot just use the merged fieldInfo.number (instead of
+                        // resolving to this reader's FieldInfo as
+                        // we do below) because field numbers can easily be different
+                        // when addIndexes(Directory...) copies over segments from another
+                        // index:
+                        if let Some(reader_field_info) =
+                            merge_state.fields_infos[i].field_info_by_name(&field_info.name)
+                        {
+                            if reader_field_info.point_dimension_count > 0 {
+                                if let Some(bkd_reader) =
+                                    reader60.readers.get(&(reader_field_info.number as i32))
+                                {
+                                    bkd_readers.push(bkd_reader);
+                                    doc_maps.push(merge_state.doc_maps[i].as_ref());
                                 }
                             }
                         }
                     }
+                }
 
-                    let fp = writer.merge(&mut self.data_out, doc_maps, bkd_readers)?;
-                    if fp != -1 {
-                        self.index_fps.insert(field_info.name.clone(), fp);
-                    }
-                } else {
-                    self.merge_one_field(merge_state, field_info)?;
+                let fp = writer.merge(&mut self.data_out, doc_maps, bkd_readers)?;
+                if fp != -1 {
+                    self.index_fps.insert(field_info.name.clone(), fp);
                 }
             }
         }
@@ -62,6 +62,23 @@ impl Default for Lucene62Codec {
     }
 }
 
+impl Lucene62Codec {
+    /// Creates a codec choosing the postings and doc values format of each
+    /// field with `postings_format` and `doc_values_format`. The chosen
+    /// formats are recorded in the field infos, so the segments can be read
+    /// back by the default codec.
+    pub fn with_per_field_formats(
+        postings_format: PerFieldPostingsFormat,
+        doc_values_format: PerFieldDocValuesFormat,
+    ) -> Lucene62Codec {
+        Lucene62Codec {
+            postings_format,
+            doc_values_format,
+            ..Default::default()
+        }
+    }
+}
+
 impl Codec for Lucene62Codec {
     type FieldsProducer = Arc<PerFieldFieldsReader>;
     type PostingFmt = PerFieldPostingsFormat;
@@ -80,11 +97,11 @@ impl Codec for Lucene62Codec {
     }
 
     fn postings_format(&self) -> Self::PostingFmt {
-        self.postings_format
+        self.postings_format.clone()
     }
 
     fn doc_values_format(&self) -> Self::DVFmt {
-        self.doc_values_format
+        self.doc_values_format.clone()
     }
 
     fn stored_fields_format(&self) -> Self::StoredFmt {
@@ -0,0 +1,311 @@
+// Copyright 2019 Zhizhesihai (Beijing) Technology Limited.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use core::codec::blocktree::BlockTreeTermsReader;
+use core::codec::format::PostingsFormat;
+use core::codec::lucene50::{Lucene50PostingsFormat, Lucene50PostingsReader};
+use core::codec::{Codec, FieldsConsumerEnum};
+use core::index::{SegmentReadState, SegmentWriteState};
+use core::store::Directory;
+
+use error::Result;
+
+use std::fmt;
+
+/// Default heap budget of the terms of a field, 512MB.
+///
+/// A key of 10 bytes takes about 45 bytes with its state and hash slot, see
+/// `DirectTerms`, so the budget holds about 10M such keys.
+pub const DEFAULT_MAX_RAM_BYTES_PER_FIELD: usize = 512 * 1024 * 1024;
+
+/// Postings format holding the terms of each field in memory.
+///
+/// The files are written as with `Lucene50PostingsFormat`, but the reader
+/// loads all the terms of a field with their states when the segment is
+/// opened, so an exact seek is a hash lookup, and the postings of a term
+/// found in a single doc need no IO, see `DirectTerms`. This suits primary
+/// key fields, set it for them with `PerFieldPostingsFormat::set_field_format`.
+///
+/// The fields whose terms would take more than `max_ram_bytes_per_field` of
+/// heap are read from the terms index, as with `Lucene50PostingsFormat`.
+#[derive(Copy, Clone, Eq, PartialEq)]
+pub struct DirectPostingsFormat {
+    max_ram_bytes_per_field: usize,
+}
+
+impl Default for DirectPostingsFormat {
+    fn default() -> DirectPostingsFormat {
+        Self::with_max_ram_bytes(DEFAULT_MAX_RAM_BYTES_PER_FIELD)
+    }
+}
+
+impl DirectPostingsFormat {
+    pub fn with_max_ram_bytes(max_ram_bytes_per_field: usize) -> DirectPostingsFormat {
+        DirectPostingsFormat {
+            max_ram_bytes_per_field,
+        }
+    }
+
+    pub fn max_ram_bytes_per_field(&self) -> usize {
+        self.max_ram_bytes_per_field
+    }
+}
+
+impl fmt::Display for DirectPostingsFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Direct(max_ram_bytes_per_field={})",
+            self.max_ram_bytes_per_field
+        )
+    }
+}
+
+impl PostingsFormat for DirectPostingsFormat {
+    type FieldsProducer = BlockTreeTermsReader;
+    fn fields_producer<'a, D: Directory, DW: Directory, C: Codec>(
+        &self,
+        state: &SegmentReadState<'a, D, DW, C>,
+    ) -> Result<Self::FieldsProducer> {
+        let postings_reader = Lucene50PostingsReader::open(&state)?;
+        let mut reader = BlockTreeTermsReader::new(postings_reader, state, false)?;
+        reader.load_direct_terms(self.max_ram_bytes_per_field)?;
+        Ok(reader)
+    }
+
+    fn fields_consumer<D: Directory, DW: Directory, C: Codec>(
+        &self,
+        state: &SegmentWriteState<D, DW, C>,
+    ) -> Result<FieldsConsumerEnum<D, DW, C>> {
+        Lucene50PostingsFormat::default().fields_consumer(state)
+    }
+
+    fn name(&self) -> &str {
+        "Direct"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::codec::{
+        CodecEnum, FieldInfosFormat, FieldsProducer, Lucene62Codec, PerFieldDocValuesFormat,
+        PerFieldPostingsFormat, PostingsFormatEnum, PER_FIELD_POSTING_FORMAT_KEY,
+    };
+    use core::doc::{Store, StringField, TextField};
+    use core::index::index_writer_config::IndexWriterConfig;
+    use core::index::merge_policy::TieredMergePolicy;
+    use core::index::merge_scheduler::SerialMergeScheduler;
+    use core::index::{Fields, IndexReader, IndexWriter, LeafReader, SegmentInfos};
+    use core::index::{StandardDirectoryReader, Term, TermIterator, Terms};
+    use core::search::searcher::{DefaultIndexSearcher, IndexSearcher};
+    use core::search::term_query::TermQuery;
+    use core::search::{DocIterator, NO_MORE_DOCS};
+    use core::store::{IOContext, RAMDirectory};
+    use core::util::tests::seeded_rng;
+
+    use rand::Rng;
+
+    const NUM_DOCS: usize = 1000;
+
+    fn id(i: usize) -> String {
+        format!("{:x}", i * 7919)
+    }
+
+    fn build_index() -> Arc<RAMDirectory> {
+        build_index_of(NUM_DOCS, id)
+    }
+
+    // "id" is written with the direct format, "body" with the default one
+    fn build_index_of(num_docs: usize, id: fn(usize) -> String) -> Arc<RAMDirectory> {
+        let mut postings_format = PerFieldPostingsFormat::default();
+        postings_format.set_field_format(
+            "id",
+            PostingsFormatEnum::Direct(DirectPostingsFormat::default()),
+        );
+        let codec = Lucene62Codec::with_per_field_formats(
+            postings_format,
+            PerFieldDocValuesFormat::default(),
+        );
+        let mut config = IndexWriterConfig::new(
+            Arc::new(CodecEnum::Lucene62(codec)),
+            SerialMergeScheduler::default(),
+            TieredMergePolicy::default(),
+        );
+        config.use_compound_file = false;
+
+        let dir = Arc::new(RAMDirectory::new());
+        let writer = IndexWriter::new(Arc::clone(&dir), Arc::new(config)).unwrap();
+        for i in 0..num_docs {
+            writer
+                .add_document(vec![
+                    StringField::new("id", &id(i), Store::No),
+                    TextField::new("body", &format!("common t{}", i % 30), Store::No),
+                ])
+                .unwrap();
+        }
+        writer.commit().unwrap();
+        writer.close().unwrap();
+        dir
+    }
+
+    fn open_terms<F: PostingsFormat<FieldsProducer = BlockTreeTermsReader>>(
+        dir: &Arc<RAMDirectory>,
+        format: &F,
+    ) -> BlockTreeTermsReader {
+        let infos: SegmentInfos<RAMDirectory, CodecEnum> =
+            SegmentInfos::read_latest_commit(dir).unwrap();
+        let si = &infos.segments[0].info;
+        let field_infos = si
+            .codec()
+            .field_infos_format()
+            .read(dir.as_ref(), si, "", &IOContext::READ)
+            .unwrap();
+        let state = SegmentReadState::new(
+            Arc::clone(dir),
+            si,
+            Arc::new(field_infos),
+            &IOContext::READ,
+            "Direct_0".to_string(),
+        );
+        format.fields_producer(&state).unwrap()
+    }
+
+    fn docs(iter: &mut impl TermIterator) -> Vec<i32> {
+        let mut postings = iter.postings().unwrap();
+        let mut docs = vec![];
+        loop {
+            let doc = postings.next_doc().unwrap();
+            if doc == NO_MORE_DOCS {
+                return docs;
+            }
+            docs.push(doc);
+        }
+    }
+
+    // seeks the same terms, found or not, in both readers
+    fn assert_same_results(expected: &BlockTreeTermsReader, actual: &BlockTreeTermsReader) {
+        let expected = expected.terms("id").unwrap().unwrap();
+        let actual = actual.terms("id").unwrap().unwrap();
+        let mut expected_iter = expected.iterator().unwrap();
+        let mut actual_iter = actual.iterator().unwrap();
+        let mut rng = seeded_rng();
+        for _ in 0..500 {
+            let i = rng.gen_range(0, NUM_DOCS * 2);
+            let term = id(i).into_bytes();
+            let found = expected_iter.seek_exact(&term).unwrap();
+            assert_eq!(found, i < NUM_DOCS);
+            assert_eq!(actual_iter.seek_exact(&term).unwrap(), found);
+            if found {
+                assert_eq!(actual_iter.term().unwrap(), term.as_slice());
+                assert_eq!(
+                    actual_iter.doc_freq().unwrap(),
+                    expected_iter.doc_freq().unwrap()
+                );
+                assert_eq!(
+                    actual_iter.total_term_freq().unwrap(),
+                    expected_iter.total_term_freq().unwrap()
+                );
+                assert_eq!(docs(&mut actual_iter), docs(&mut expected_iter));
+            }
+        }
+
+        // enumeration goes on from a term found in memory
+        assert!(actual_iter.seek_exact(id(0).as_bytes()).unwrap());
+        assert!(expected_iter.seek_exact(id(0).as_bytes()).unwrap());
+        for _ in 0..10 {
+            assert_eq!(actual_iter.next().unwrap(), expected_iter.next().unwrap());
+        }
+    }
+
+    #[test]
+    fn test_direct_terms() {
+        let dir = build_index();
+        let block_tree = open_terms(&dir, &Lucene50PostingsFormat::default());
+        assert!(block_tree
+            .terms("id")
+            .unwrap()
+            .unwrap()
+            .direct_terms()
+            .is_none());
+
+        let direct = open_terms(&dir, &DirectPostingsFormat::default());
+        {
+            let terms = direct.terms("id").unwrap().unwrap();
+            let direct_terms = terms.direct_terms().unwrap();
+            assert_eq!(direct_terms.len(), NUM_DOCS);
+            assert!(direct_terms.ram_bytes_used() > 0);
+        }
+        assert_same_results(&block_tree, &direct);
+
+        // over budget, the field is read from the terms index
+        let degraded = open_terms(&dir, &DirectPostingsFormat::with_max_ram_bytes(1024));
+        assert!(degraded
+            .terms("id")
+            .unwrap()
+            .unwrap()
+            .direct_terms()
+            .is_none());
+        assert_same_results(&block_tree, &degraded);
+        degraded.check_integrity().unwrap();
+    }
+
+    #[test]
+    fn test_direct_terms_size() {
+        // keys of 10 bytes, the default budget must hold 10M of them
+        const NUM_KEYS: usize = 20_000;
+        let dir = build_index_of(NUM_KEYS, |i| format!("{:010}", i * 7919));
+        let direct = open_terms(&dir, &DirectPostingsFormat::default());
+        let terms = direct.terms("id").unwrap().unwrap();
+        let direct_terms = terms.direct_terms().unwrap();
+        assert_eq!(direct_terms.len(), NUM_KEYS);
+        let bytes_per_key = direct_terms.ram_bytes_used() / NUM_KEYS;
+        assert!(
+            bytes_per_key <= DEFAULT_MAX_RAM_BYTES_PER_FIELD / 10_000_000,
+            "{} bytes per key",
+            bytes_per_key
+        );
+    }
+
+    #[test]
+    fn test_per_field_direct() {
+        let dir = build_index();
+        let reader =
+            StandardDirectoryReader::<_, CodecEnum, SerialMergeScheduler, TieredMergePolicy>::open(
+                Arc::clone(&dir),
+            )
+            .unwrap();
+        for leaf in reader.leaves() {
+            let id_info = leaf.reader.field_infos().field_info_by_name("id").unwrap();
+            assert_eq!(
+                id_info.attribute(PER_FIELD_POSTING_FORMAT_KEY),
+                Some("Direct".to_string())
+            );
+            let id_terms = leaf.reader.terms("id").unwrap().unwrap();
+            assert!(id_terms.direct_terms().is_some());
+            let body_terms = leaf.reader.terms("body").unwrap().unwrap();
+            assert!(body_terms.direct_terms().is_none());
+        }
+
+        let searcher = DefaultIndexSearcher::new(Arc::new(reader));
+        let count = |field: &str, value: &str| {
+            let term = Term::new(field.to_string(), value.as_bytes().to_vec());
+            searcher.count(&TermQuery::new(term, 1.0, None)).unwrap()
+        };
+        for i in 0..NUM_DOCS {
+            assert_eq!(count("id", &id(i)), 1);
+        }
+        assert_eq!(count("id", "missing"), 0);
+        assert_eq!(count("body", "common"), NUM_DOCS);
+    }
+}
@@ -51,6 +51,10 @@ mod lucene62;
 
 pub use self::lucene62::*;
 
+mod memory;
+
+pub use self::memory::*;
+
 mod reader;
 
 pub use self::reader::*;
@@ -56,8 +56,42 @@ fn get_full_segment_suffix(outer_segment_suffix: &str, segment_suffix: String) -
     }
 }
 
-#[derive(Default, Clone, Copy)]
-pub struct PerFieldDocValuesFormat;
+#[derive(Clone)]
+pub struct PerFieldDocValuesFormat {
+    default_format: Box<DocValuesFormatEnum>,
+    field_formats: Arc<HashMap<String, DocValuesFormatEnum>>,
+}
+
+impl Default for PerFieldDocValuesFormat {
+    fn default() -> Self {
+        Self::new(DocValuesFormatEnum::Lucene54(
+            Lucene54DocValuesFormat::default(),
+        ))
+    }
+}
+
+impl PerFieldDocValuesFormat {
+    /// Creates a format writing the doc values of all the fields with
+    /// `default_format`.
+    pub fn new(default_format: DocValuesFormatEnum) -> Self {
+        PerFieldDocValuesFormat {
+            default_format: Box::new(default_format),
+            field_formats: Arc::new(HashMap::new()),
+        }
+    }
+
+    /// Writes the doc values of `field` with `format` instead of the default one.
+    pub fn set_field_format(&mut self, field: &str, format: DocValuesFormatEnum) {
+        Arc::make_mut(&mut self.field_formats).insert(field.to_string(), format);
+    }
+
+    /// Returns the format the doc values of `field` are written with.
+    pub fn doc_values_format_for_field(&self, field: &str) -> &DocValuesFormatEnum {
+        self.field_formats
+            .get(field)
+            .unwrap_or(self.default_format.as_ref())
+    }
+}
 
 impl DocValuesFormat for PerFieldDocValuesFormat {
     fn name(&self) -> &str {
@@ -77,6 +111,7 @@ impl DocValuesFormat for PerFieldDocValuesFormat {
         state: &SegmentWriteState<D, DW, C>,
     ) -> Result<DocValuesConsumerEnum<D, DW, C>> {
         Ok(DocValuesConsumerEnum::PerField(DocValuesFieldsWriter::new(
+            self.clone(),
             state,
         )))
     }
@@ -108,7 +143,13 @@ impl DocValuesFieldsReader {
                         PER_FIELD_VALUE_SUFFIX_KEY, name
                     ))),
                     Some(suffix) => {
-                        let dv_format = format::doc_values_format_for_name(format)?;
+                        let dv_format = match format::doc_values_format_for_name(format) {
+                            Ok(dv_format) => dv_format,
+                            Err(e) => bail!(IllegalArgument(format!(
+                                "can't read field {} of segment {}: {}",
+                                name, state.segment_info.name, e
+                            ))),
+                        };
                         let segment_suffix = get_full_segment_suffix(
                             &state.segment_suffix,
                             get_suffix(format, suffix),
@@ -223,14 +264,16 @@ struct ConsumerAndSuffix<D: Directory, DW: Directory, C: Codec> {
 }
 
 pub struct DocValuesFieldsWriter<D: Directory, DW: Directory, C: Codec> {
+    format: PerFieldDocValuesFormat,
     formats: HashMap<String, ConsumerAndSuffix<D, DW, C>>,
     suffixes: HashMap<String, i32>,
     segment_write_state: SegmentWriteState<D, DW, C>,
 }
 
 impl<D: Directory, DW: Directory, C: Codec> DocValuesFieldsWriter<D, DW, C> {
-    fn new(state: &SegmentWriteState<D, DW, C>) -> Self {
+    fn new(format: PerFieldDocValuesFormat, state: &SegmentWriteState<D, DW, C>) -> Self {
         DocValuesFieldsWriter {
+            format,
             formats: HashMap::new(),
             suffixes: HashMap::new(),
             segment_write_state: state.clone(),
@@ -246,10 +289,7 @@ impl<D: Directory, DW: Directory, C: Codec> DocValuesFieldsWriter<D, DW, C> {
             }
         }
         if format.is_none() {
-            // TODO hard code for `PerFieldDocValuesFormat.getDocValuesFormatForField`
-            format = Some(DocValuesFormatEnum::Lucene54(
-                Lucene54DocValuesFormat::default(),
-            ));
+            format = Some(self.format.doc_values_format_for_field(&field.name).clone());
         }
 
         let format = format.unwrap();
@@ -306,15 +346,16 @@ impl<D: Directory, DW: Directory, C: Codec> DocValuesFieldsWriter<D, DW, C> {
             self.segment_write_state.segment_suffix = old_suffix;
         }
 
-        if let Some(p) = field.put_attribute(
+        let prev = field.put_attribute(
             PER_FIELD_VALUE_SUFFIX_KEY.to_string(),
             format!("{}", suffix.unwrap()),
-        ) {
+        );
+        if field.dv_gen == -1 && prev.is_some() {
             bail!(IllegalState(format!(
                 "found existing value for {}, field={}, old={}, new={}",
                 PER_FIELD_VALUE_SUFFIX_KEY,
                 field.name,
-                p,
+                prev.unwrap(),
                 suffix.unwrap()
             )));
         }
@@ -338,7 +379,7 @@ impl<D: Directory, DW: Directory, C: Codec> DocValuesConsumer for DocValuesField
     fn add_binary_field(
         &mut self,
         field_info: &FieldInfo,
-        values: &mut impl ReusableIterator<Item = Result<BytesRef>>,
+        values: &mut impl ReusableIterator<Item = Result<Option<BytesRef>>>,
     ) -> Result<()> {
         self.get_instance(field_info)?
             .add_binary_field(field_info, values)
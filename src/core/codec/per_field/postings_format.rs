@@ -11,21 +11,22 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::collections::BTreeMap;
 use std::collections::HashMap;
-use std::collections::{BTreeMap, BTreeSet};
 use std::fmt;
 use std::mem;
 use std::sync::Arc;
 
 use core::codec::blocktree::FieldReaderRef;
 use core::codec::consumer::FieldsConsumerEnum;
-use core::codec::format::{postings_format_for_name, PostingsFormat};
+use core::codec::format::{postings_format_for_name, PostingsFormat, PostingsFormatEnum};
 use core::codec::lucene50::Lucene50PostingsFormat;
 use core::codec::producer::FieldsProducerEnum;
 use core::codec::{Codec, FieldsConsumer, FieldsProducer};
 use core::index::Fields;
 use core::index::{IndexOptions, SegmentReadState, SegmentWriteState};
 use core::store::Directory;
+use error::ErrorKind::{IllegalArgument, IllegalState};
 use error::Result;
 
 /// Name of this {@link PostingsFormat}. */
@@ -37,6 +38,10 @@ pub const PER_FIELD_POSTING_FORMAT_KEY: &str = "PerFieldPostingsFormat.format";
 /// segment suffix name for each field. */
 pub const PER_FIELD_POSTING_SUFFIX_KEY: &str = "PerFieldPostingsFormat.suffix";
 
+// the format and suffix of the indexed fields without format attributes
+const DEFAULT_POSTINGS_FORMAT: &str = "Lucene50";
+const DEFAULT_POSTINGS_SUFFIX: &str = "0";
+
 fn get_suffix(format: &str, suffix: &str) -> String {
     format!("{}_{}", format, suffix)
 }
@@ -54,12 +59,51 @@ fn get_suffix(format: &str, suffix: &str) -> String {
 /// filenames would look like <tt>_1_Lucene40_0.prx</tt>.
 /// @see ServiceLoader
 /// @lucene.experimental
-#[derive(Copy, Clone)]
-pub struct PerFieldPostingsFormat;
+#[derive(Clone)]
+pub struct PerFieldPostingsFormat {
+    default_format: PostingsFormatEnum,
+    field_formats: Arc<HashMap<String, PostingsFormatEnum>>,
+}
 
 impl Default for PerFieldPostingsFormat {
     fn default() -> PerFieldPostingsFormat {
-        PerFieldPostingsFormat {}
+        Self::new(PostingsFormatEnum::Lucene50(
+            Lucene50PostingsFormat::default(),
+        ))
+    }
+}
+
+impl PerFieldPostingsFormat {
+    /// Creates a format writing all the fields with `default_format`.
+    pub fn new(default_format: PostingsFormatEnum) -> PerFieldPostingsFormat {
+        PerFieldPostingsFormat {
+            default_format,
+            field_formats: Arc::new(HashMap::new()),
+        }
+    }
+
+    /// Writes the postings of `field` with `format` instead of the default one.
+    pub fn set_field_format(&mut self, field: &str, format: PostingsFormatEnum) {
+        Arc::make_mut(&mut self.field_formats).insert(field.to_string(), format);
+    }
+
+    /// Returns the format the postings of `field` are written with.
+    pub fn postings_format_for_field(&self, field: &str) -> &PostingsFormatEnum {
+        self.field_formats
+            .get(field)
+            .unwrap_or(&self.default_format)
+    }
+
+    // the formats configured here take precedence over the defaults of their
+    // name, so that their settings also apply to the segments they read
+    fn postings_format_for_name(&self, name: &str) -> Result<PostingsFormatEnum> {
+        if self.default_format.name() == name {
+            return Ok(self.default_format);
+        }
+        match self.field_formats.values().find(|f| f.name() == name) {
+            Some(format) => Ok(*format),
+            None => postings_format_for_name(name),
+        }
     }
 }
 
@@ -69,7 +113,7 @@ impl PostingsFormat for PerFieldPostingsFormat {
         &self,
         state: &SegmentReadState<'_, D, DW, C>,
     ) -> Result<Self::FieldsProducer> {
-        Ok(Arc::new(PerFieldFieldsReader::new(state)?))
+        Ok(Arc::new(PerFieldFieldsReader::new(self, state)?))
     }
 
     fn fields_consumer<D: Directory, DW: Directory, C: Codec>(
@@ -77,6 +121,7 @@ impl PostingsFormat for PerFieldPostingsFormat {
         state: &SegmentWriteState<D, DW, C>,
     ) -> Result<FieldsConsumerEnum<D, DW, C>> {
         Ok(FieldsConsumerEnum::PerField(PerFieldFieldsWriter::new(
+            self.clone(),
             state,
         )))
     }
@@ -87,50 +132,57 @@ impl PostingsFormat for PerFieldPostingsFormat {
 }
 
 pub struct PerFieldFieldsReader {
-    fields: BTreeMap<String, FieldsProducerEnum>,
+    fields: BTreeMap<String, Arc<FieldsProducerEnum>>,
+    // the producers by segment suffix, shared by the fields of the same format
+    producers: BTreeMap<String, Arc<FieldsProducerEnum>>,
     segment: String,
 }
 
 impl PerFieldFieldsReader {
     fn new<D: Directory, DW: Directory, C: Codec>(
+        per_field_format: &PerFieldPostingsFormat,
         state: &SegmentReadState<'_, D, DW, C>,
     ) -> Result<PerFieldFieldsReader> {
         let mut fields = BTreeMap::new();
-        let mut formats = HashMap::new();
+        let mut producers = BTreeMap::new();
         for (name, info) in &state.field_infos.by_name {
             if let IndexOptions::Null = info.index_options {
                 continue;
             }
-            if let Some(format) = info
-                .attributes
-                .read()
-                .unwrap()
-                .get(PER_FIELD_POSTING_FORMAT_KEY)
-            {
-                if let Some(suffix) = info
-                    .attributes
-                    .read()
-                    .unwrap()
-                    .get(PER_FIELD_POSTING_SUFFIX_KEY)
-                {
-                    if !formats.contains_key(suffix) {
-                        formats.insert(suffix.clone(), postings_format_for_name(format)?);
-                    }
-                    let postings_format = formats.get(suffix).unwrap();
-                    let suffix = get_suffix(format, suffix);
-                    let state = SegmentReadState::with_suffix(state, &suffix);
-                    fields.insert(name.clone(), postings_format.fields_producer(&state)?);
-                } else {
-                    bail!(
-                        "Illegal State: missing attribute: {} for field {}",
-                        PER_FIELD_POSTING_SUFFIX_KEY,
-                        name
-                    );
-                }
+            let attributes = info.attributes.read().unwrap();
+            // the fields without a format were written with the default one,
+            // as `FieldInfos` used to record for every indexed field
+            let (format, suffix) = match attributes.get(PER_FIELD_POSTING_FORMAT_KEY) {
+                Some(format) => match attributes.get(PER_FIELD_POSTING_SUFFIX_KEY) {
+                    Some(suffix) => (format.as_str(), suffix.as_str()),
+                    None => bail!(IllegalState(format!(
+                        "missing attribute: {} for field {}",
+                        PER_FIELD_POSTING_SUFFIX_KEY, name
+                    ))),
+                },
+                None => (DEFAULT_POSTINGS_FORMAT, DEFAULT_POSTINGS_SUFFIX),
+            };
+            let suffix = get_suffix(format, suffix);
+            if !producers.contains_key(&suffix) {
+                let postings_format = match per_field_format.postings_format_for_name(format) {
+                    Ok(postings_format) => postings_format,
+                    Err(e) => bail!(IllegalArgument(format!(
+                        "can't read field {} of segment {}: {}",
+                        name, state.segment_info.name, e
+                    ))),
+                };
+                let state = SegmentReadState::with_suffix(state, &suffix);
+                let producer = postings_format.fields_producer(&state)?;
+                producers.insert(suffix.clone(), Arc::new(producer));
             }
+            fields.insert(name.clone(), Arc::clone(&producers[&suffix]));
         }
         let segment = state.segment_info.name.clone();
-        Ok(PerFieldFieldsReader { fields, segment })
+        Ok(PerFieldFieldsReader {
+            fields,
+            producers,
+            segment,
+        })
     }
 
     fn terms_impl(&self, field: &str) -> Result<Option<FieldReaderRef>> {
@@ -153,7 +205,7 @@ impl fmt::Display for PerFieldFieldsReader {
 
 impl FieldsProducer for PerFieldFieldsReader {
     fn check_integrity(&self) -> Result<()> {
-        for producer in self.fields.values() {
+        for producer in self.producers.values() {
             producer.check_integrity()?;
         }
         Ok(())
@@ -173,20 +225,35 @@ impl Fields for PerFieldFieldsReader {
     }
 }
 
-#[allow(dead_code)]
-struct FieldsGroup<D: Directory, DW: Directory, C: Codec> {
-    fields: BTreeSet<String>,
-    suffix: usize,
-    state: SegmentWriteState<D, DW, C>,
+// the fields written with the same format, a view of all the fields
+struct FieldsGroup<'a, F: Fields> {
+    fields: &'a F,
+    names: Vec<String>,
+}
+
+impl<'a, F: Fields> Fields for FieldsGroup<'a, F> {
+    type Terms = F::Terms;
+    fn fields(&self) -> Vec<String> {
+        self.names.clone()
+    }
+    fn terms(&self, field: &str) -> Result<Option<Self::Terms>> {
+        debug_assert!(self.names.iter().any(|name| name == field));
+        self.fields.terms(field)
+    }
+    fn size(&self) -> usize {
+        self.names.len()
+    }
 }
 
 pub struct PerFieldFieldsWriter<D: Directory, DW: Directory, C: Codec> {
+    format: PerFieldPostingsFormat,
     write_state: SegmentWriteState<D, DW, C>,
 }
 
 impl<D: Directory, DW: Directory, C: Codec> PerFieldFieldsWriter<D, DW, C> {
-    pub fn new(write_state: &SegmentWriteState<D, DW, C>) -> Self {
+    pub fn new(format: PerFieldPostingsFormat, write_state: &SegmentWriteState<D, DW, C>) -> Self {
         PerFieldFieldsWriter {
+            format,
             write_state: write_state.clone(),
         }
     }
@@ -198,24 +265,257 @@ impl<D: Directory, DW: Directory, C: Codec> PerFieldFieldsWriter<D, DW, C> {
 }
 
 impl<D: Directory, DW: Directory, C: Codec> FieldsConsumer for PerFieldFieldsWriter<D, DW, C> {
-    // TODO this is only one postings format currently (lucene50).
-    // And we assume that we always use one format all the time.
-    // so we won't implement this like lucene
-    // when the format changes, it's easy to change the hard code then.
     fn write(&mut self, fields: &impl Fields) -> Result<()> {
-        // this is only one format, so suffix is always "0"
-        let segment_suffix =
-            self.get_full_segment_suffix(&self.write_state.segment_suffix, "Lucene50_0".into());
-        // always use lucene50, so just hard code it.
-        let format = Lucene50PostingsFormat::default();
+        // group the fields by format, keeping their order
+        let mut groups: Vec<(PostingsFormatEnum, Vec<String>)> = vec![];
+        for field in fields.fields() {
+            let format = *self.format.postings_format_for_field(&field);
+            match groups.iter_mut().find(|(f, _)| *f == format) {
+                Some((_, names)) => names.push(field),
+                None => groups.push((format, vec![field])),
+            }
+        }
 
-        let old_suffix = mem::replace(&mut self.write_state.segment_suffix, segment_suffix);
+        // each group is written to its own files, suffixed by the format name
+        // and a number telling apart the groups of formats with the same name
+        let mut suffixes: HashMap<String, usize> = HashMap::new();
+        for (format, names) in groups {
+            let format_name = format.name().to_string();
+            let suffix = {
+                let next = suffixes.entry(format_name.clone()).or_insert(0);
+                *next += 1;
+                (*next - 1).to_string()
+            };
+            for name in &names {
+                let info = match self.write_state.field_infos.field_info_by_name(name) {
+                    Some(info) => info,
+                    None => bail!(IllegalState(format!("missing field info for {}", name))),
+                };
+                info.put_attribute(
+                    PER_FIELD_POSTING_FORMAT_KEY.to_string(),
+                    format_name.clone(),
+                );
+                info.put_attribute(PER_FIELD_POSTING_SUFFIX_KEY.to_string(), suffix.clone());
+            }
 
-        let mut consumer = format.fields_consumer(&self.write_state)?;
-        consumer.write(fields)?;
+            let segment_suffix = self.get_full_segment_suffix(
+                &self.write_state.segment_suffix,
+                get_suffix(&format_name, &suffix),
+            );
+            let old_suffix = mem::replace(&mut self.write_state.segment_suffix, segment_suffix);
 
-        self.write_state.segment_suffix = old_suffix;
+            let mut consumer = format.fields_consumer(&self.write_state)?;
+            consumer.write(&FieldsGroup { fields, names })?;
+
+            self.write_state.segment_suffix = old_suffix;
+        }
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::codec::{CodecEnum, DocValuesFormat, FieldInfosFormat, Lucene62Codec};
+    use core::codec::{PerFieldDocValuesFormat, PER_FIELD_VALUE_FORMAT_KEY};
+    use core::doc::{NumericDocValuesField, Store, StringField};
+    use core::index::index_writer_config::IndexWriterConfig;
+    use core::index::merge_policy::TieredMergePolicy;
+    use core::index::merge_scheduler::SerialMergeScheduler;
+    use core::index::{FieldInfos, Fieldable, IndexReader, IndexWriter, NumericDocValues};
+    use core::index::{SegmentInfos, StandardDirectoryReader, Term, TermIterator, Terms};
+    use core::search::searcher::{DefaultIndexSearcher, IndexSearcher};
+    use core::search::term_query::TermQuery;
+    use core::store::{IOContext, RAMDirectory};
+
+    const NUM_DOCS: usize = 100;
+
+    // "id" is written with smaller terms blocks than the other fields, by a
+    // second instance of the Lucene50 format, in two segments merged into one
+    fn build_index() -> Arc<RAMDirectory> {
+        let mut postings_format = PerFieldPostingsFormat::default();
+        postings_format.set_field_format(
+            "id",
+            PostingsFormatEnum::Lucene50(Lucene50PostingsFormat::with_block_size(10, 20)),
+        );
+        let codec = Lucene62Codec::with_per_field_formats(
+            postings_format,
+            PerFieldDocValuesFormat::default(),
+        );
+        let mut config = IndexWriterConfig::new(
+            Arc::new(CodecEnum::Lucene62(codec)),
+            SerialMergeScheduler::default(),
+            TieredMergePolicy::default(),
+        );
+        config.use_compound_file = false;
+
+        let dir = Arc::new(RAMDirectory::new());
+        let writer = IndexWriter::new(Arc::clone(&dir), Arc::new(config)).unwrap();
+        for i in 0..NUM_DOCS {
+            let doc: Vec<Box<dyn Fieldable>> = vec![
+                Box::new(StringField::new("id", &i.to_string(), Store::No)),
+                Box::new(StringField::new("parity", &(i % 2).to_string(), Store::No)),
+                Box::new(NumericDocValuesField::new("price", i as i64)),
+            ];
+            writer.add_document(doc).unwrap();
+            if i == NUM_DOCS / 2 {
+                writer.commit().unwrap();
+            }
+        }
+        writer.commit().unwrap();
+        writer.force_merge(1, true).unwrap();
+        writer.commit().unwrap();
+        writer.close().unwrap();
+        dir
+    }
+
+    fn read_field_infos(
+        dir: &Arc<RAMDirectory>,
+        infos: &SegmentInfos<RAMDirectory, CodecEnum>,
+    ) -> FieldInfos {
+        let si = &infos.segments[0].info;
+        si.codec()
+            .field_infos_format()
+            .read(dir.as_ref(), si, "", &IOContext::READ)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_per_field_formats() {
+        let dir = build_index();
+        let infos: SegmentInfos<RAMDirectory, CodecEnum> =
+            SegmentInfos::read_latest_commit(&dir).unwrap();
+        assert_eq!(infos.segments.len(), 1);
+        let segment = infos.segments[0].info.name.clone();
+
+        // the fields are grouped by format in field order, the groups of the
+        // same format name are told apart by their suffix
+        let field_infos = read_field_infos(&dir, &infos);
+        let format_of = |field: &str| {
+            let info = field_infos.field_info_by_name(field).unwrap();
+            (
+                info.attribute(PER_FIELD_POSTING_FORMAT_KEY),
+                info.attribute(PER_FIELD_POSTING_SUFFIX_KEY),
+            )
+        };
+        let lucene50 = Some("Lucene50".to_string());
+        assert_eq!(format_of("id"), (lucene50.clone(), Some("0".to_string())));
+        assert_eq!(format_of("parity"), (lucene50, Some("1".to_string())));
+        assert_eq!(format_of("price"), (None, None));
+        let files = dir.list_all().unwrap();
+        for suffix in &["Lucene50_0", "Lucene50_1"] {
+            let file = format!("{}_{}.tim", segment, suffix);
+            assert!(files.contains(&file), "missing {}", file);
+        }
+
+        let reader =
+            StandardDirectoryReader::<_, CodecEnum, SerialMergeScheduler, TieredMergePolicy>::open(
+                Arc::clone(&dir),
+            )
+            .unwrap();
+        let mut price_sum = 0;
+        for leaf in reader.leaves() {
+            let prices = leaf.reader.get_numeric_doc_values("price").unwrap();
+            for doc in 0..leaf.reader.max_doc() {
+                price_sum += prices.get(doc).unwrap();
+            }
+        }
+        assert_eq!(price_sum, (NUM_DOCS * (NUM_DOCS - 1) / 2) as i64);
+
+        let searcher = DefaultIndexSearcher::new(Arc::new(reader));
+        for (field, value, count) in &[
+            ("id", "42", 1),
+            ("id", "99", 1),
+            ("id", "100", 0),
+            ("parity", "0", 50),
+            ("parity", "1", 50),
+        ] {
+            let term = Term::new(field.to_string(), value.as_bytes().to_vec());
+            let query = TermQuery::new(term, 1.0, None);
+            assert_eq!(searcher.count(&query).unwrap(), *count);
+        }
+    }
+
+    #[test]
+    fn test_missing_format_attribute() {
+        let dir = build_index();
+        let infos: SegmentInfos<RAMDirectory, CodecEnum> =
+            SegmentInfos::read_latest_commit(&dir).unwrap();
+
+        // "id" is written at the default suffix
+        let field_infos = read_field_infos(&dir, &infos);
+        {
+            let info = field_infos.field_info_by_name("id").unwrap();
+            let mut attributes = info.attributes.write().unwrap();
+            attributes.remove(PER_FIELD_POSTING_FORMAT_KEY);
+            attributes.remove(PER_FIELD_POSTING_SUFFIX_KEY);
+        }
+        let state = SegmentReadState::new(
+            Arc::clone(&dir),
+            &infos.segments[0].info,
+            Arc::new(field_infos),
+            &IOContext::READ,
+            String::new(),
+        );
+        let fields = PerFieldPostingsFormat::default()
+            .fields_producer(&state)
+            .unwrap();
+        let mut terms = fields.terms("id").unwrap().unwrap().iterator().unwrap();
+        assert!(terms.seek_exact(b"42").unwrap());
+        assert_eq!(terms.doc_freq().unwrap(), 1);
+    }
+
+    #[test]
+    fn test_unknown_format() {
+        let dir = build_index();
+        let infos: SegmentInfos<RAMDirectory, CodecEnum> =
+            SegmentInfos::read_latest_commit(&dir).unwrap();
+        let si = &infos.segments[0].info;
+
+        let field_infos = read_field_infos(&dir, &infos);
+        field_infos
+            .field_info_by_name("parity")
+            .unwrap()
+            .put_attribute(
+                PER_FIELD_POSTING_FORMAT_KEY.to_string(),
+                "Pulsing41".to_string(),
+            );
+        let state = SegmentReadState::new(
+            Arc::clone(&dir),
+            si,
+            Arc::new(field_infos),
+            &IOContext::READ,
+            String::new(),
+        );
+        match PerFieldPostingsFormat::default().fields_producer(&state) {
+            Ok(_) => panic!("the unknown postings format was not detected"),
+            Err(e) => {
+                let message = e.to_string();
+                assert!(message.contains("parity"), "{}", message);
+                assert!(message.contains("Pulsing41"), "{}", message);
+            }
+        }
+
+        let field_infos = read_field_infos(&dir, &infos);
+        field_infos
+            .field_info_by_name("price")
+            .unwrap()
+            .put_attribute(PER_FIELD_VALUE_FORMAT_KEY.to_string(), "Disk45".to_string());
+        let state = SegmentReadState::new(
+            Arc::clone(&dir),
+            si,
+            Arc::new(field_infos),
+            &IOContext::READ,
+            String::new(),
+        );
+        match PerFieldDocValuesFormat::default().fields_producer(&state) {
+            Ok(_) => panic!("the unknown doc values format was not detected"),
+            Err(e) => {
+                let message = e.to_string();
+                assert!(message.contains("price"), "{}", message);
+                assert!(message.contains("Disk45"), "{}", message);
+            }
+        }
+    }
+}
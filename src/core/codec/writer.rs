@@ -11,7 +11,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use core::analysis::TokenStream;
+use core::analysis::{Analyzer, TokenStream};
 use core::codec::{
     BlockTermState, Codec, CodecPointsReader, CompressingStoredFieldsWriter,
     CompressingTermVectorsWriter, MutablePointsReader, PointsReader, StoredFieldsReader,
@@ -71,6 +71,17 @@ impl<P: PointsReader, MP: MutablePointsReader> PointValues for PointsReaderEnum<
         }
     }
 
+    fn estimate_point_count(
+        &self,
+        field_name: &str,
+        visitor: &mut impl IntersectVisitor,
+    ) -> Result<i64> {
+        match self {
+            PointsReaderEnum::Simple(s) => s.estimate_point_count(field_name, visitor),
+            PointsReaderEnum::Mutable(m) => m.estimate_point_count(field_name, visitor),
+        }
+    }
+
     fn min_packed_value(&self, field_name: &str) -> Result<Vec<u8>> {
         match self {
             PointsReaderEnum::Simple(s) => s.min_packed_value(field_name),
@@ -509,10 +520,10 @@ pub trait TermVectorsWriter {
 
     /// Safe (but, slowish) default method to write every
     ///  vector field in the document.
-    fn add_all_doc_vectors<D: Directory, C: Codec>(
+    fn add_all_doc_vectors(
         &mut self,
         vectors: Option<&impl Fields>,
-        merge_state: &MergeState<D, C>,
+        field_infos: &FieldInfos,
     ) -> Result<()> {
         if let Some(vectors) = vectors {
             let num_fields = vectors.size();
@@ -523,12 +534,7 @@ pub trait TermVectorsWriter {
             let mut field_count = 0;
             for field_name in vectors.fields() {
                 field_count += 1;
-                let field_info = merge_state
-                    .merge_field_infos
-                    .as_ref()
-                    .unwrap()
-                    .field_info_by_name(&field_name)
-                    .unwrap();
+                let field_info = field_infos.field_info_by_name(&field_name).unwrap();
                 debug_assert!(last_field_name.is_empty() || field_name > last_field_name);
                 last_field_name = field_name;
                 let terms_opt = vectors.terms(&last_field_name)?;
@@ -627,7 +633,10 @@ pub fn merge_term_vectors<D: Directory, C: Codec, T: TermVectorsWriter>(
             } else {
                 None
             };
-            writer.add_all_doc_vectors(vectors.as_ref(), merge_state)?;
+            writer.add_all_doc_vectors(
+                vectors.as_ref(),
+                merge_state.merge_field_infos.as_ref().unwrap(),
+            )?;
             doc_count += 1;
         } else {
             break;
@@ -765,13 +774,13 @@ impl<O: IndexOutput> TermVectorsWriter for TermVectorsWriterEnum<O> {
         }
     }
 
-    fn add_all_doc_vectors<D: Directory, C: Codec>(
+    fn add_all_doc_vectors(
         &mut self,
         vectors: Option<&impl Fields>,
-        merge_state: &MergeState<D, C>,
+        field_infos: &FieldInfos,
     ) -> Result<()> {
         match self {
-            TermVectorsWriterEnum::Compressing(w) => w.add_all_doc_vectors(vectors, merge_state),
+            TermVectorsWriterEnum::Compressing(w) => w.add_all_doc_vectors(vectors, field_infos),
         }
     }
 }
@@ -980,6 +989,17 @@ impl<S: StoredFieldsWriter> MergeVisitor<S> {
         }
     }
 
+    /// A visitor copying the fields of a reader sharing the field numbers of
+    /// `fields_writer`.
+    pub fn without_remapping(fields_writer: &mut S) -> Self {
+        MergeVisitor {
+            value: None,
+            current_field: ptr::null(),
+            remapper: None,
+            fields_writer,
+        }
+    }
+
     fn reset(&mut self, field: &FieldInfo) {
         if let Some(ref remapper) = self.remapper {
             self.current_field = remapper.field_info_by_name(&field.name).unwrap();
@@ -1050,7 +1070,7 @@ impl<S: StoredFieldsWriter> Fieldable for MergeVisitor<S> {
         debug_assert!(self.value.is_some());
         self.value.as_ref()
     }
-    fn token_stream(&mut self) -> Result<Box<dyn TokenStream>> {
+    fn token_stream(&mut self, _analyzer: &dyn Analyzer) -> Result<Box<dyn TokenStream>> {
         unreachable!()
     }
 
@@ -13,7 +13,7 @@
 
 use std::ops::Deref;
 
-use core::analysis::TokenStream;
+use core::analysis::{Analyzer, TokenStream};
 use core::doc::{BinaryTokenStream, Field, FieldType, BINARY_DOC_VALUES_FIELD_TYPE};
 use core::index::Fieldable;
 use core::util::{BytesRef, Numeric, VariantValue};
@@ -54,7 +54,7 @@ impl Fieldable for BinaryDocValuesField {
         self.field.fields_data()
     }
 
-    fn token_stream(&mut self) -> Result<Box<dyn TokenStream>> {
+    fn token_stream(&mut self, _analyzer: &dyn Analyzer) -> Result<Box<dyn TokenStream>> {
         if let VariantValue::Binary(ref v) = self.fields_data().unwrap() {
             Ok(Box::new(BinaryTokenStream::new(BytesRef::new(v.as_ref()))))
         } else {
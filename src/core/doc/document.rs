@@ -13,6 +13,7 @@
 
 use core::doc::stored_field::StoredField;
 use core::index::Fieldable;
+use core::util::VariantValue;
 
 #[derive(Debug)]
 pub struct Document {
@@ -32,4 +33,184 @@ impl Document {
     pub fn remove_field(&mut self, name: &str) {
         self.fields.retain(|ref v| v.field.name() != name);
     }
+
+    /// Returns the first stored value of the field `name`, with the type it
+    /// was indexed with.
+    pub fn get(&self, name: &str) -> Option<&VariantValue> {
+        self.fields
+            .iter()
+            .find(|f| f.field.name() == name)
+            .and_then(|f| f.field.fields_data())
+    }
+
+    /// Returns the stored values of the field `name`, in the order they were
+    /// added to the document.
+    pub fn get_values(&self, name: &str) -> Vec<&VariantValue> {
+        self.fields
+            .iter()
+            .filter(|f| f.field.name() == name)
+            .filter_map(|f| f.field.fields_data())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::codec::CodecEnum;
+    use core::doc::{
+        DoublePoint, IntPoint, LongPoint, NumericDocValuesField, SortedSetDocValuesField, Store,
+        StringField, TextField,
+    };
+    use core::index::tests::{open_test_reader, TestReader};
+    use core::index::{IndexReader, Term, NO_MORE_ORDS};
+    use core::search::collector::{TopDocsCollector, TopFieldCollector};
+    use core::search::match_all::MatchAllDocsQuery;
+    use core::search::searcher::{DefaultIndexSearcher, IndexSearcher};
+    use core::search::sort::Sort;
+    use core::search::sort_field::{SimpleSortField, SortField, SortFieldType};
+    use core::search::term_query::TermQuery;
+    use core::search::Query;
+    use core::util::DocId;
+
+    use std::sync::Arc;
+
+    const TITLES: [&str; 3] = ["quick brown fox", "lazy dog", "quick dog"];
+    const RANKS: [i64; 3] = [30, 10, 20];
+
+    // every field type in each doc
+    fn build_index() -> Arc<TestReader> {
+        let docs = (0..3).map(|i| -> Vec<Box<dyn Fieldable>> {
+            vec![
+                Box::new(StringField::new("id", &format!("doc{}", i), Store::Yes)),
+                Box::new(TextField::new("title", TITLES[i], Store::Yes)),
+                Box::new(TextField::new("body", "not stored", Store::No)),
+                Box::new(NumericDocValuesField::new("rank", RANKS[i])),
+                Box::new(SortedSetDocValuesField::new("tags", b"common")),
+                Box::new(SortedSetDocValuesField::new(
+                    "tags",
+                    format!("tag{}", i).as_bytes(),
+                )),
+                Box::new(StoredField::new_bytes("payload", vec![i as u8, 0xff])),
+                Box::new(StoredField::new_long("count", -(1 << 40) + i as i64)),
+                Box::new(StoredField::new_double("weight", i as f64 + 0.25)),
+                Box::new(LongPoint::new("price", &[100 * i as i64]).unwrap()),
+                Box::new(IntPoint::new("size", &[i as i32]).unwrap()),
+                Box::new(DoublePoint::new("location", &[i as f64, -(i as f64)]).unwrap()),
+            ]
+        });
+        open_test_reader(vec![docs.collect()])
+    }
+
+    fn matching_docs<S: IndexSearcher<CodecEnum>>(
+        searcher: &S,
+        query: &dyn Query<CodecEnum>,
+    ) -> Vec<DocId> {
+        let mut collector = TopDocsCollector::new(10);
+        searcher.search(query, &mut collector).unwrap();
+        let mut docs: Vec<DocId> = collector
+            .top_docs()
+            .score_docs()
+            .iter()
+            .map(|d| d.doc_id())
+            .collect();
+        docs.sort();
+        docs
+    }
+
+    fn term_query(field: &str, text: &str) -> TermQuery {
+        TermQuery::new(
+            Term::new(field.to_string(), text.as_bytes().to_vec()),
+            1.0,
+            None,
+        )
+    }
+
+    #[test]
+    fn test_index_every_field_type() {
+        let reader = build_index();
+        let searcher = DefaultIndexSearcher::new(Arc::clone(&reader));
+
+        // terms
+        assert_eq!(matching_docs(&searcher, &term_query("id", "doc1")), vec![1]);
+        assert!(matching_docs(&searcher, &term_query("id", "doc")).is_empty());
+        assert_eq!(
+            matching_docs(&searcher, &term_query("title", "quick")),
+            vec![0, 2]
+        );
+        assert_eq!(
+            matching_docs(&searcher, &term_query("body", "stored")),
+            vec![0, 1, 2]
+        );
+
+        // points
+        let query = LongPoint::new_range_query("price".to_string(), 50, 250).unwrap();
+        assert_eq!(matching_docs(&searcher, query.as_ref()), vec![1, 2]);
+        let query = IntPoint::new_exact_query("size".to_string(), 0).unwrap();
+        assert_eq!(matching_docs(&searcher, query.as_ref()), vec![0]);
+        let query =
+            DoublePoint::new_multi_range_query("location".to_string(), &[0.5, -3.0], &[3.0, -1.5])
+                .unwrap();
+        assert_eq!(matching_docs(&searcher, query.as_ref()), vec![2]);
+
+        // doc values
+        let field = SimpleSortField::new("rank".to_string(), SortFieldType::Long, false);
+        let sort = Sort::new(vec![SortField::Simple(field)]);
+        let mut collector = TopFieldCollector::new(sort, 10, false).unwrap();
+        searcher.search(&MatchAllDocsQuery, &mut collector).unwrap();
+        let ranked: Vec<DocId> = collector
+            .top_docs()
+            .score_docs()
+            .iter()
+            .map(|d| d.doc_id())
+            .collect();
+        assert_eq!(ranked, vec![1, 2, 0]);
+
+        let leaves = reader.leaves();
+        let tags = leaves[0].reader.get_sorted_set_doc_values("tags").unwrap();
+        let mut ctx = tags.set_document(1).unwrap();
+        let mut values = vec![];
+        loop {
+            let ord = tags.next_ord(&mut ctx).unwrap();
+            if ord == NO_MORE_ORDS {
+                break;
+            }
+            values.push(tags.lookup_ord(ord).unwrap());
+        }
+        assert_eq!(values, vec![b"common".to_vec(), b"tag1".to_vec()]);
+
+        // stored values
+        for i in 0..3 {
+            let doc = reader.document(i as DocId, &[]).unwrap();
+            assert_eq!(
+                doc.get("id").and_then(|v| v.get_string()),
+                Some(format!("doc{}", i).as_str())
+            );
+            assert_eq!(
+                doc.get("title").and_then(|v| v.get_string()),
+                Some(TITLES[i])
+            );
+            assert_eq!(
+                doc.get("payload").and_then(|v| v.get_binary()),
+                Some(&[i as u8, 0xff][..])
+            );
+            assert_eq!(
+                doc.get("count").and_then(|v| v.get_long()),
+                Some(-(1 << 40) + i as i64)
+            );
+            assert_eq!(
+                doc.get("weight").and_then(|v| v.get_double()),
+                Some(i as f64 + 0.25)
+            );
+            for name in &["body", "rank", "tags", "price", "size", "location"] {
+                assert!(doc.get(name).is_none(), "{} is not stored", name);
+            }
+            assert_eq!(doc.get_values("id").len(), 1);
+        }
+    }
+
+    #[test]
+    fn test_empty_point() {
+        assert!(LongPoint::new("price", &[]).is_err());
+    }
 }
@@ -11,7 +11,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use core::analysis::TokenStream;
+use core::analysis::{Analyzer, TokenStream};
 use core::attribute::{BytesTermAttribute, PayloadAttribute, TermToBytesRefAttribute};
 use core::attribute::{CharTermAttribute, OffsetAttribute, PositionIncrementAttribute};
 use core::doc::FieldType;
@@ -23,6 +23,14 @@ use error::{ErrorKind, Result};
 use std::cmp::Ordering;
 use std::collections::HashSet;
 
+/// Whether the value of a field is stored, to be loaded with the document of
+/// a hit.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Store {
+    Yes,
+    No,
+}
+
 #[derive(Debug)]
 pub struct Field {
     name: String,
@@ -85,7 +93,7 @@ impl Fieldable for Field {
     }
 
     // TODO currently this function should only be called once per doc field
-    fn token_stream(&mut self) -> Result<Box<dyn TokenStream>> {
+    fn token_stream(&mut self, analyzer: &dyn Analyzer) -> Result<Box<dyn TokenStream>> {
         debug_assert_ne!(self.field_type.index_options, IndexOptions::Null);
 
         if !self.field_type.tokenized {
@@ -106,11 +114,16 @@ impl Fieldable for Field {
             }
         }
 
-        if self.token_stream.is_some() {
-            Ok(self.token_stream.take().unwrap())
-        } else {
-            // TODO currently not support analyzer
-            unimplemented!()
+        if let Some(token_stream) = self.token_stream.take() {
+            return Ok(token_stream);
+        }
+
+        match self.fields_data {
+            Some(VariantValue::VString(ref s)) => analyzer.token_stream(&self.name, s),
+            _ => bail!(ErrorKind::IllegalArgument(format!(
+                "field '{}' is tokenized but has neither a token stream nor a String value",
+                self.name
+            ))),
         }
     }
 
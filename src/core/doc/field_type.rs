@@ -258,3 +258,59 @@ pub const SORTED_SET_DOC_VALUES_FIELD_TYPE: FieldType = FieldType {
     dimension_count: 0,
     dimension_num_bytes: 0,
 };
+
+pub const TEXT_FIELD_TYPE_STORED: FieldType = FieldType {
+    stored: true,
+    tokenized: true,
+    store_term_vectors: false,
+    store_term_vector_offsets: false,
+    store_term_vector_positions: false,
+    store_term_vector_payloads: false,
+    omit_norms: false,
+    index_options: IndexOptions::DocsAndFreqsAndPositions,
+    doc_values_type: DocValuesType::Null,
+    dimension_count: 0,
+    dimension_num_bytes: 0,
+};
+
+pub const TEXT_FIELD_TYPE_NOT_STORED: FieldType = FieldType {
+    stored: false,
+    tokenized: true,
+    store_term_vectors: false,
+    store_term_vector_offsets: false,
+    store_term_vector_positions: false,
+    store_term_vector_payloads: false,
+    omit_norms: false,
+    index_options: IndexOptions::DocsAndFreqsAndPositions,
+    doc_values_type: DocValuesType::Null,
+    dimension_count: 0,
+    dimension_num_bytes: 0,
+};
+
+pub const STRING_FIELD_TYPE_STORED: FieldType = FieldType {
+    stored: true,
+    tokenized: false,
+    store_term_vectors: false,
+    store_term_vector_offsets: false,
+    store_term_vector_positions: false,
+    store_term_vector_payloads: false,
+    omit_norms: true,
+    index_options: IndexOptions::Docs,
+    doc_values_type: DocValuesType::Null,
+    dimension_count: 0,
+    dimension_num_bytes: 0,
+};
+
+pub const STRING_FIELD_TYPE_NOT_STORED: FieldType = FieldType {
+    stored: false,
+    tokenized: false,
+    store_term_vectors: false,
+    store_term_vector_offsets: false,
+    store_term_vector_positions: false,
+    store_term_vector_payloads: false,
+    omit_norms: true,
+    index_options: IndexOptions::Docs,
+    doc_values_type: DocValuesType::Null,
+    dimension_count: 0,
+    dimension_num_bytes: 0,
+};
@@ -17,6 +17,12 @@ pub use self::field_type::*;
 mod field;
 pub use self::field::*;
 
+mod text_field;
+pub use self::text_field::*;
+
+mod string_field;
+pub use self::string_field::*;
+
 mod numeric_doc_values_field;
 pub use self::numeric_doc_values_field::*;
 
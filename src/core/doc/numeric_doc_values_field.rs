@@ -11,10 +11,13 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use core::analysis::TokenStream;
+use core::analysis::{Analyzer, TokenStream};
+use core::codec::Codec;
 use core::doc::NUMERIC_DOC_VALUES_FIELD_TYPE;
 use core::doc::{Field, FieldType};
 use core::index::Fieldable;
+use core::search::doc_values_range::NumericDocValuesRangeQuery;
+use core::search::Query;
 use core::util::{Numeric, VariantValue};
 
 use error::Result;
@@ -38,6 +41,23 @@ impl NumericDocValuesField {
     pub fn numeric_value(&self) -> i64 {
         self.field.fields_data().unwrap().get_long().unwrap()
     }
+
+    /// Matches the docs whose value is `value`, by checking the value of
+    /// every doc. Slow unless it only verifies the docs of other clauses, see
+    /// `IndexOrDocValuesQuery`.
+    pub fn new_slow_exact_query<C: Codec>(field: String, value: i64) -> Box<dyn Query<C>> {
+        Box::new(NumericDocValuesRangeQuery::new_exact(field, value))
+    }
+
+    /// Matches the docs whose value is between `lower` and `upper`
+    /// (inclusive), with the same caveat as `new_slow_exact_query`.
+    pub fn new_slow_range_query<C: Codec>(
+        field: String,
+        lower: i64,
+        upper: i64,
+    ) -> Box<dyn Query<C>> {
+        Box::new(NumericDocValuesRangeQuery::new(field, lower, upper))
+    }
 }
 
 impl Fieldable for NumericDocValuesField {
@@ -57,7 +77,7 @@ impl Fieldable for NumericDocValuesField {
         self.field.fields_data()
     }
 
-    fn token_stream(&mut self) -> Result<Box<dyn TokenStream>> {
+    fn token_stream(&mut self, _analyzer: &dyn Analyzer) -> Result<Box<dyn TokenStream>> {
         unreachable!()
     }
 
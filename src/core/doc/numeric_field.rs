@@ -12,14 +12,32 @@
 // limitations under the License.
 
 use core::codec::Codec;
-use core::search::point_range::{PointRangeQuery, PointValueType};
+use core::doc::{Field, FieldType};
+use core::search::point_range::PointRangeQuery;
 use core::search::Query;
 use core::util::numeric;
 
-use error::Result;
+use error::{ErrorKind::IllegalArgument, Result};
 
 use num_traits::float::Float;
 
+// a field indexing the `packed` point, which has `num_dims` dimensions
+fn point_field(name: &str, packed: Vec<u8>, num_dims: usize) -> Result<Field> {
+    let mut field_type = FieldType::default();
+    field_type.set_dimensions(num_dims as u32, (packed.len() / num_dims) as u32)?;
+    Ok(Field::new_bytes(name.to_string(), packed, field_type))
+}
+
+fn check_point<T>(name: &str, point: &[T]) -> Result<()> {
+    if point.is_empty() {
+        bail!(IllegalArgument(format!(
+            "point of field '{}' must have at least one dimension",
+            name
+        )));
+    }
+    Ok(())
+}
+
 /// An indexed {@code float} field for fast range filters.  If you also
 /// need to store the value, you should add a separate {@link StoredField} instance.
 ///
@@ -31,6 +49,12 @@ use num_traits::float::Float;
 pub struct FloatPoint;
 
 impl FloatPoint {
+    /// Creates a field indexing the n-dimensional `point`.
+    pub fn new(name: &str, point: &[f32]) -> Result<Field> {
+        check_point(name, point)?;
+        point_field(name, FloatPoint::pack(point), point.len())
+    }
+
     pub fn next_up(f: f32) -> f32 {
         let mut int_value = f32::to_bits(f);
         if int_value == 0x8000_0000u32 {
@@ -96,12 +120,8 @@ impl FloatPoint {
         lower: &[f32],
         upper: &[f32],
     ) -> Result<Box<dyn Query<C>>> {
-        Ok(Box::new(PointRangeQuery::new(
-            field,
-            FloatPoint::pack(lower),
-            FloatPoint::pack(upper),
-            lower.len(),
-            PointValueType::Float,
+        Ok(Box::new(PointRangeQuery::new_float_range(
+            field, lower, upper,
         )?))
     }
 
@@ -113,7 +133,7 @@ impl FloatPoint {
         numeric::sortable_int2float(numeric::sortable_bytes2int(value))
     }
 
-    fn pack(point: &[f32]) -> Vec<u8> {
+    pub fn pack(point: &[f32]) -> Vec<u8> {
         assert!(!point.is_empty());
         let mut packed = vec![0u8; point.len() * 4];
         for dim in 0..point.len() {
@@ -126,6 +146,12 @@ impl FloatPoint {
 pub struct DoublePoint;
 
 impl DoublePoint {
+    /// Creates a field indexing the n-dimensional `point`.
+    pub fn new(name: &str, point: &[f64]) -> Result<Field> {
+        check_point(name, point)?;
+        point_field(name, DoublePoint::pack(point), point.len())
+    }
+
     pub fn next_up(d: f64) -> f64 {
         let mut bits = f64::to_bits(d);
         if bits == 0x8000_0000_0000_0000u64 {
@@ -204,12 +230,8 @@ impl DoublePoint {
         lower: &[f64],
         upper: &[f64],
     ) -> Result<Box<dyn Query<C>>> {
-        Ok(Box::new(PointRangeQuery::new(
-            field,
-            DoublePoint::pack(lower),
-            DoublePoint::pack(upper),
-            lower.len(),
-            PointValueType::Double,
+        Ok(Box::new(PointRangeQuery::new_double_range(
+            field, lower, upper,
         )?))
     }
 }
@@ -217,6 +239,12 @@ impl DoublePoint {
 pub struct IntPoint;
 
 impl IntPoint {
+    /// Creates a field indexing the n-dimensional `point`.
+    pub fn new(name: &str, point: &[i32]) -> Result<Field> {
+        check_point(name, point)?;
+        point_field(name, IntPoint::pack(point), point.len())
+    }
+
     pub fn pack(point: &[i32]) -> Vec<u8> {
         assert!(!point.is_empty());
         let mut packed = vec![0u8; point.len() * 4];
@@ -251,12 +279,8 @@ impl IntPoint {
         lower: &[i32],
         upper: &[i32],
     ) -> Result<Box<dyn Query<C>>> {
-        Ok(Box::new(PointRangeQuery::new(
-            field,
-            IntPoint::pack(lower),
-            IntPoint::pack(upper),
-            lower.len(),
-            PointValueType::Integer,
+        Ok(Box::new(PointRangeQuery::new_int_range(
+            field, lower, upper,
         )?))
     }
 }
@@ -264,6 +288,12 @@ impl IntPoint {
 pub struct LongPoint;
 
 impl LongPoint {
+    /// Creates a field indexing the n-dimensional `point`.
+    pub fn new(name: &str, point: &[i64]) -> Result<Field> {
+        check_point(name, point)?;
+        point_field(name, LongPoint::pack(point), point.len())
+    }
+
     pub fn pack(point: &[i64]) -> Vec<u8> {
         assert!(!point.is_empty());
         let mut packed = vec![0u8; point.len() * 8];
@@ -298,12 +328,8 @@ impl LongPoint {
         lower: &[i64],
         upper: &[i64],
     ) -> Result<Box<dyn Query<C>>> {
-        Ok(Box::new(PointRangeQuery::new(
-            field,
-            LongPoint::pack(lower),
-            LongPoint::pack(upper),
-            lower.len(),
-            PointValueType::Long,
+        Ok(Box::new(PointRangeQuery::new_long_range(
+            field, lower, upper,
         )?))
     }
 }
@@ -13,7 +13,7 @@
 
 use std::ops::Deref;
 
-use core::analysis::TokenStream;
+use core::analysis::{Analyzer, TokenStream};
 use core::doc::SORTED_NUMERIC_DOC_VALUES_FIELD_TYPE;
 use core::doc::{Field, FieldType};
 use core::index::Fieldable;
@@ -62,8 +62,8 @@ impl Fieldable for SortedNumericDocValuesField {
         self.field.fields_data()
     }
 
-    fn token_stream(&mut self) -> Result<Box<dyn TokenStream>> {
-        self.field.token_stream()
+    fn token_stream(&mut self, analyzer: &dyn Analyzer) -> Result<Box<dyn TokenStream>> {
+        self.field.token_stream(analyzer)
     }
 
     fn binary_value(&self) -> Option<&[u8]> {
@@ -11,7 +11,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use core::analysis::TokenStream;
+use core::analysis::{Analyzer, TokenStream};
 use core::doc::{Field, FieldType, SORTED_SET_DOC_VALUES_FIELD_TYPE};
 use core::index::Fieldable;
 use core::util::{Numeric, VariantValue};
@@ -59,8 +59,8 @@ impl Fieldable for SortedSetDocValuesField {
         self.field.fields_data()
     }
 
-    fn token_stream(&mut self) -> Result<Box<dyn TokenStream>> {
-        self.field.token_stream()
+    fn token_stream(&mut self, analyzer: &dyn Analyzer) -> Result<Box<dyn TokenStream>> {
+        self.field.token_stream(analyzer)
     }
 
     fn binary_value(&self) -> Option<&[u8]> {
@@ -11,12 +11,15 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use core::index::{DocValuesType, IndexOptions};
-use core::util::VariantValue;
+use core::analysis::{Analyzer, TokenStream};
+use core::index::{DocValuesType, Fieldable, IndexOptions};
+use core::util::{Numeric, VariantValue};
 
 use core::doc::Field;
 use core::doc::FieldType;
 
+use error::Result;
+
 lazy_static! {
     pub static ref STORE_FIELD_TYPE: FieldType = {
         let mut field_type = FieldType::default();
@@ -63,4 +66,62 @@ impl StoredField {
             }
         }
     }
+
+    pub fn new_bytes(name: &str, value: Vec<u8>) -> StoredField {
+        StoredField::new(name, None, VariantValue::Binary(value))
+    }
+
+    pub fn new_str(name: &str, value: &str) -> StoredField {
+        StoredField::new(name, None, VariantValue::VString(value.to_string()))
+    }
+
+    pub fn new_int(name: &str, value: i32) -> StoredField {
+        StoredField::new(name, None, VariantValue::Int(value))
+    }
+
+    pub fn new_long(name: &str, value: i64) -> StoredField {
+        StoredField::new(name, None, VariantValue::Long(value))
+    }
+
+    pub fn new_float(name: &str, value: f32) -> StoredField {
+        StoredField::new(name, None, VariantValue::Float(value))
+    }
+
+    pub fn new_double(name: &str, value: f64) -> StoredField {
+        StoredField::new(name, None, VariantValue::Double(value))
+    }
+}
+
+impl Fieldable for StoredField {
+    fn name(&self) -> &str {
+        self.field.name()
+    }
+
+    fn field_type(&self) -> &FieldType {
+        self.field.field_type()
+    }
+
+    fn boost(&self) -> f32 {
+        self.field.boost()
+    }
+
+    fn fields_data(&self) -> Option<&VariantValue> {
+        self.field.fields_data()
+    }
+
+    fn token_stream(&mut self, analyzer: &dyn Analyzer) -> Result<Box<dyn TokenStream>> {
+        self.field.token_stream(analyzer)
+    }
+
+    fn binary_value(&self) -> Option<&[u8]> {
+        self.field.binary_value()
+    }
+
+    fn string_value(&self) -> Option<&str> {
+        self.field.string_value()
+    }
+
+    fn numeric_value(&self) -> Option<Numeric> {
+        self.field.numeric_value()
+    }
 }
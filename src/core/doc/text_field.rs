@@ -0,0 +1,34 @@
+// Copyright 2019 Zhizhesihai (Beijing) Technology Limited.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use core::doc::{Field, Store, TEXT_FIELD_TYPE_NOT_STORED, TEXT_FIELD_TYPE_STORED};
+use core::util::VariantValue;
+
+/// A field whose value is analyzed into terms, indexed with their frequencies
+/// and positions, for full-text search.
+pub struct TextField;
+
+impl TextField {
+    pub fn new(name: &str, value: &str, store: Store) -> Field {
+        let field_type = match store {
+            Store::Yes => TEXT_FIELD_TYPE_STORED,
+            Store::No => TEXT_FIELD_TYPE_NOT_STORED,
+        };
+        Field::new(
+            name.to_string(),
+            field_type,
+            Some(VariantValue::VString(value.to_string())),
+            None,
+        )
+    }
+}
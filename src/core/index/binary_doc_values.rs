@@ -21,10 +21,75 @@ use core::util::DocId;
 use core::util::LongValues;
 use error::Result;
 
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
 
+// identifies the doc values owning the state cached in a context
+static NEXT_CONTEXT_OWNER: AtomicUsize = AtomicUsize::new(1);
+
+fn next_context_owner() -> usize {
+    NEXT_CONTEXT_OWNER.fetch_add(1, Ordering::Relaxed)
+}
+
+/// Reusable state of `BinaryDocValues::get_with_ctx`.
+///
+/// Holds the input or the term iterator the values are read from and the
+/// scratch buffer the value is read into, so sequential reads neither clone
+/// the input nor allocate. The state is cached for the doc values that
+/// filled it, and dropped when the context is used with other doc values.
+#[derive(Default)]
+pub struct BinaryDocValuesContext {
+    owner: usize,
+    input: Option<Box<dyn IndexInput>>,
+    term_iterator: Option<CompressedBinaryTermIterator>,
+    buffer: Vec<u8>,
+}
+
+impl BinaryDocValuesContext {
+    // drops the state cached for other doc values than `owner`
+    fn bind(&mut self, owner: usize) {
+        if self.owner != owner {
+            self.owner = owner;
+            self.input = None;
+            self.term_iterator = None;
+        }
+    }
+
+    // reads `length` bytes at `offset` of `data` into the buffer
+    fn read(
+        &mut self,
+        owner: usize,
+        data: &dyn IndexInput,
+        offset: i64,
+        length: usize,
+    ) -> Result<&[u8]> {
+        self.bind(owner);
+        if self.input.is_none() {
+            self.input = Some(IndexInput::clone(data)?);
+        }
+        let input = self.input.as_mut().unwrap();
+        input.seek(offset)?;
+        self.buffer.resize(length, 0u8);
+        input.read_bytes(&mut self.buffer, 0, length)?;
+        Ok(&self.buffer)
+    }
+}
+
 pub trait BinaryDocValues: Send + Sync {
     fn get(&self, doc_id: DocId) -> Result<Vec<u8>>;
+
+    /// Returns the value of `doc_id`, read into the scratch buffer of `ctx`.
+    ///
+    /// The value of a doc without one is empty, see `LeafReader::get_docs_with_field`
+    /// to tell it from an empty value.
+    fn get_with_ctx<'a>(
+        &self,
+        ctx: &'a mut BinaryDocValuesContext,
+        doc_id: DocId,
+    ) -> Result<&'a [u8]> {
+        ctx.buffer = self.get(doc_id)?;
+        Ok(&ctx.buffer)
+    }
 }
 
 pub type BinaryDocValuesRef = Arc<dyn BinaryDocValues>;
@@ -35,6 +100,14 @@ impl BinaryDocValues for EmptyBinaryDocValues {
     fn get(&self, _doc_id: DocId) -> Result<Vec<u8>> {
         Ok(Vec::with_capacity(0))
     }
+
+    fn get_with_ctx<'a>(
+        &self,
+        _ctx: &'a mut BinaryDocValuesContext,
+        _doc_id: DocId,
+    ) -> Result<&'a [u8]> {
+        Ok(&[])
+    }
 }
 
 pub trait LongBinaryDocValues: BinaryDocValues {
@@ -44,22 +117,34 @@ pub trait LongBinaryDocValues: BinaryDocValues {
 pub struct FixedBinaryDocValues {
     data: Box<dyn IndexInput>,
     buffer_len: usize,
+    ctx_owner: usize,
 }
 
 impl FixedBinaryDocValues {
     pub fn new(data: Box<dyn IndexInput>, buffer_len: usize) -> Self {
-        FixedBinaryDocValues { data, buffer_len }
+        FixedBinaryDocValues {
+            data,
+            buffer_len,
+            ctx_owner: next_context_owner(),
+        }
+    }
+
+    fn get64_with_ctx<'a>(&self, ctx: &'a mut BinaryDocValuesContext, id: i64) -> Result<&'a [u8]> {
+        let length = self.buffer_len;
+        ctx.read(
+            self.ctx_owner,
+            self.data.as_ref(),
+            id * length as i64,
+            length,
+        )
     }
 }
 
 impl LongBinaryDocValues for FixedBinaryDocValues {
     fn get64(&self, id: i64) -> Result<Vec<u8>> {
-        let length = self.buffer_len;
-        let mut data = self.data.as_ref().clone()?;
-        data.seek(id * length as i64)?;
-        let mut buffer = vec![0u8; length];
-        data.read_bytes(&mut buffer, 0, length)?;
-        Ok(buffer)
+        let mut ctx = BinaryDocValuesContext::default();
+        self.get64_with_ctx(&mut ctx, id)?;
+        Ok(ctx.buffer)
     }
 }
 
@@ -67,29 +152,44 @@ impl BinaryDocValues for FixedBinaryDocValues {
     fn get(&self, doc_id: DocId) -> Result<Vec<u8>> {
         FixedBinaryDocValues::get64(self, i64::from(doc_id))
     }
+
+    fn get_with_ctx<'a>(
+        &self,
+        ctx: &'a mut BinaryDocValuesContext,
+        doc_id: DocId,
+    ) -> Result<&'a [u8]> {
+        self.get64_with_ctx(ctx, i64::from(doc_id))
+    }
 }
 
 pub struct VariableBinaryDocValues<T: LongValues> {
     addresses: T,
     data: Box<dyn IndexInput>,
+    ctx_owner: usize,
 }
 
 impl<T: LongValues> VariableBinaryDocValues<T> {
     pub fn new(addresses: T, data: Box<dyn IndexInput>, _length: usize) -> Self {
-        VariableBinaryDocValues { addresses, data }
+        VariableBinaryDocValues {
+            addresses,
+            data,
+            ctx_owner: next_context_owner(),
+        }
     }
-}
 
-impl<T: LongValues> LongBinaryDocValues for VariableBinaryDocValues<T> {
-    fn get64(&self, id: i64) -> Result<Vec<u8>> {
+    fn get64_with_ctx<'a>(&self, ctx: &'a mut BinaryDocValuesContext, id: i64) -> Result<&'a [u8]> {
         let start_address = self.addresses.get64(id)?;
         let end_address = self.addresses.get64(id + 1)?;
         let length = (end_address - start_address) as usize;
-        let mut data = self.data.as_ref().clone()?;
-        data.seek(start_address)?;
-        let mut buffer = vec![0u8; length];
-        data.read_bytes(&mut buffer, 0, length)?;
-        Ok(buffer)
+        ctx.read(self.ctx_owner, self.data.as_ref(), start_address, length)
+    }
+}
+
+impl<T: LongValues> LongBinaryDocValues for VariableBinaryDocValues<T> {
+    fn get64(&self, id: i64) -> Result<Vec<u8>> {
+        let mut ctx = BinaryDocValuesContext::default();
+        self.get64_with_ctx(&mut ctx, id)?;
+        Ok(ctx.buffer)
     }
 }
 
@@ -97,6 +197,14 @@ impl<T: LongValues> BinaryDocValues for VariableBinaryDocValues<T> {
     fn get(&self, doc_id: DocId) -> Result<Vec<u8>> {
         VariableBinaryDocValues::get64(self, i64::from(doc_id))
     }
+
+    fn get_with_ctx<'a>(
+        &self,
+        ctx: &'a mut BinaryDocValuesContext,
+        doc_id: DocId,
+    ) -> Result<&'a [u8]> {
+        self.get64_with_ctx(ctx, i64::from(doc_id))
+    }
 }
 
 pub struct CompressedBinaryDocValues {
@@ -107,6 +215,7 @@ pub struct CompressedBinaryDocValues {
     data: Box<dyn IndexInput>,
     reverse_index: ReverseTermsIndexRef,
     addresses: MonotonicBlockPackedReaderRef,
+    ctx_owner: usize,
 }
 
 impl CompressedBinaryDocValues {
@@ -129,6 +238,7 @@ impl CompressedBinaryDocValues {
             data,
             reverse_index,
             addresses,
+            ctx_owner: next_context_owner(),
         };
         Ok(dv)
     }
@@ -172,9 +282,209 @@ impl BinaryDocValues for CompressedBinaryDocValues {
     fn get(&self, doc_id: DocId) -> Result<Vec<u8>> {
         CompressedBinaryDocValues::get64(self, i64::from(doc_id))
     }
+
+    fn get_with_ctx<'a>(
+        &self,
+        ctx: &'a mut BinaryDocValuesContext,
+        doc_id: DocId,
+    ) -> Result<&'a [u8]> {
+        ctx.bind(self.ctx_owner);
+        if ctx.term_iterator.is_none() {
+            ctx.term_iterator = Some(self.get_term_iterator()?);
+        }
+        let term_iterator = ctx.term_iterator.as_mut().unwrap();
+        term_iterator.seek_exact_ord(i64::from(doc_id))?;
+        term_iterator.term()
+    }
 }
 
 pub enum BoxedBinaryDocValuesEnum {
     General(Box<dyn LongBinaryDocValues>),
     Compressed(CompressedBinaryDocValues),
 }
+
+#[cfg(test)]
+mod tests {
+    extern crate rand;
+
+    use super::*;
+    use core::doc::{Field, Store, StringField};
+    use core::doc::{BINARY_DOC_VALUES_FIELD_TYPE, NUMERIC_DOC_VALUES_FIELD_TYPE};
+    use core::index::index_writer_config::IndexWriterConfig;
+    use core::index::tests::{TestReader, TestWriter};
+    use core::index::{IndexReader, IndexWriter, LeafReader};
+    use core::store::RAMDirectory;
+    use core::util::tests::seeded_rng;
+    use core::util::VariantValue;
+
+    use self::rand::Rng;
+
+    use std::collections::HashMap;
+
+    type Values = HashMap<i64, Option<Vec<u8>>>;
+
+    // the field is left out of the doc if `value` is None
+    fn doc(id: i64, value: &Option<Vec<u8>>) -> Vec<Field> {
+        let mut doc = vec![
+            StringField::new("key", &id.to_string(), Store::No),
+            Field::new(
+                "id".to_string(),
+                NUMERIC_DOC_VALUES_FIELD_TYPE,
+                Some(VariantValue::Long(id)),
+                None,
+            ),
+        ];
+        if let Some(value) = value {
+            doc.push(Field::new(
+                "payload".to_string(),
+                BINARY_DOC_VALUES_FIELD_TYPE,
+                Some(VariantValue::from(value.as_slice())),
+                None,
+            ));
+        }
+        doc
+    }
+
+    // each element of `segments` is committed as a segment, the ids go on
+    // from one to the next
+    fn build_index(segments: &[Vec<Option<Vec<u8>>>]) -> (TestWriter, Arc<RAMDirectory>, Values) {
+        let dir = Arc::new(RAMDirectory::new());
+        let writer =
+            IndexWriter::new(Arc::clone(&dir), Arc::new(IndexWriterConfig::default())).unwrap();
+        let mut expected = HashMap::new();
+        for values in segments {
+            for value in values {
+                let id = expected.len() as i64;
+                writer.add_document(doc(id, value)).unwrap();
+                expected.insert(id, value.clone());
+            }
+            writer.commit().unwrap();
+        }
+        (writer, dir, expected)
+    }
+
+    // reads the values of the live docs by id, with both `get` and `get_with_ctx`,
+    // one context is used for all the segments
+    fn read_values(dir: &Arc<RAMDirectory>) -> Values {
+        let reader = TestReader::open(Arc::clone(dir)).unwrap();
+        let mut values = HashMap::new();
+        let mut ctx = BinaryDocValuesContext::default();
+        for leaf in reader.leaves() {
+            let ids = leaf.reader.get_numeric_doc_values("id").unwrap();
+            let dv = leaf.reader.get_binary_doc_values("payload").unwrap();
+            let docs_with_field = leaf.reader.get_docs_with_field("payload").unwrap();
+            let live_docs = leaf.reader.live_docs();
+            for doc in 0..leaf.reader.max_doc() {
+                if !live_docs.get(doc as usize).unwrap() {
+                    continue;
+                }
+                let value = dv.get_with_ctx(&mut ctx, doc).unwrap().to_vec();
+                assert_eq!(dv.get(doc).unwrap(), value);
+                let value = if docs_with_field.get(doc as usize).unwrap() {
+                    Some(value)
+                } else {
+                    assert!(value.is_empty());
+                    None
+                };
+                values.insert(ids.get(doc).unwrap(), value);
+            }
+        }
+        values
+    }
+
+    fn random_bytes(rng: &mut impl Rng, len: usize) -> Vec<u8> {
+        (0..len).map(|_| rng.gen()).collect()
+    }
+
+    #[test]
+    fn test_binary_doc_values_round_trip() {
+        let mut rng = seeded_rng();
+        // variable length values up to 32KB, some empty or missing
+        let mut values = vec![
+            Some(random_bytes(&mut rng, 32 * 1024)),
+            Some(vec![]),
+            None,
+            Some(random_bytes(&mut rng, 1)),
+        ];
+        for i in 0..500 {
+            let value = match rng.gen_range(0, 10) {
+                0 => None,
+                1 => Some(vec![]),
+                2 => {
+                    let len = rng.gen_range(0, 32 * 1024 + 1);
+                    Some(random_bytes(&mut rng, len))
+                }
+                _ => {
+                    let len = rng.gen_range(0, 100);
+                    Some(random_bytes(&mut rng, len))
+                }
+            };
+            values.push(if i % 100 == 0 { None } else { value });
+        }
+        let segments = vec![
+            values,
+            // fixed length
+            (0..200).map(|_| Some(random_bytes(&mut rng, 8))).collect(),
+            // fixed length, some missing
+            (0..200)
+                .map(|i| {
+                    if i % 7 == 0 {
+                        None
+                    } else {
+                        Some(random_bytes(&mut rng, 8))
+                    }
+                })
+                .collect(),
+            // empty values are not missing ones
+            (0..50)
+                .map(|i| if i % 2 == 0 { None } else { Some(vec![]) })
+                .collect(),
+            (0..50).map(|_| Some(vec![])).collect(),
+            // sorted values are prefix compressed
+            (0..5000)
+                .map(|i| Some(format!("shared prefix {:08}", i).into_bytes()))
+                .collect(),
+        ];
+        let (writer, dir, expected) = build_index(&segments);
+        writer.close().unwrap();
+        assert_eq!(read_values(&dir), expected);
+    }
+
+    #[test]
+    fn test_merge_binary_doc_values() {
+        let mut rng = seeded_rng();
+        let mut segments = vec![];
+        for _ in 0..3 {
+            segments.push(
+                (0..300)
+                    .map(|_| match rng.gen_range(0, 5) {
+                        0 => None,
+                        _ => {
+                            let len = rng.gen_range(0, 300);
+                            Some(random_bytes(&mut rng, len))
+                        }
+                    })
+                    .collect(),
+            );
+        }
+        let (writer, dir, mut expected) = build_index(&segments);
+
+        // the deleted docs are dropped by the merge, which shifts the doc ids
+        // of the docs after them
+        for id in (0..900).filter(|id| id % 11 == 0) {
+            writer
+                .delete_documents_by_term("key", id.to_string().as_bytes())
+                .unwrap();
+            expected.remove(&id);
+        }
+        writer.commit().unwrap();
+        writer.force_merge(1, true).unwrap();
+        writer.commit().unwrap();
+        writer.close().unwrap();
+
+        let reader = TestReader::open(Arc::clone(&dir)).unwrap();
+        assert_eq!(reader.leaves().len(), 1);
+        assert_eq!(reader.num_docs() as usize, expected.len());
+        assert_eq!(read_values(&dir), expected);
+    }
+}
@@ -16,6 +16,7 @@ use core::index::index_writer::{ReaderPool, ReadersAndUpdates};
 use core::index::merge_policy::MergePolicy;
 use core::index::prefix_code_terms::{FieldTermIter, FieldTermIterator};
 use core::index::prefix_code_terms::{PrefixCodedTerms, PrefixCodedTermsBuilder};
+use core::index::sorter::PackedLongDocMap;
 use core::index::term::SeekStatus;
 use core::index::{Fields, TermIterator, Terms};
 use core::index::{IndexReader, LeafReader};
@@ -53,6 +54,39 @@ pub const BYTES_PER_DEL_TERM: usize = 6 * mem::size_of::<usize>();
 /// entry costs.
 pub const BYTES_PER_DEL_QUERY_IN_HASH: usize = 4 * mem::size_of::<usize>() + 28;
 
+/// Rough logic: the update is an entry of a Vec, it holds a Term and a String
+/// (2 * 3 * usize) plus the field and term bytes, an i64 value and an i32 limit.
+pub const BYTES_PER_NUMERIC_UPDATE: usize =
+    6 * mem::size_of::<usize>() + mem::size_of::<i64>() + mem::size_of::<DocId>();
+
+/// An update of the numeric doc values `field` of the documents containing
+/// `term`, it only applies to the documents before `doc_id_upto`.
+#[derive(Clone, Debug)]
+pub struct NumericDocValuesUpdate {
+    pub term: Term,
+    pub field: String,
+    pub value: i64,
+    pub doc_id_upto: DocId,
+}
+
+impl NumericDocValuesUpdate {
+    pub fn new(term: Term, field: String, value: i64, doc_id_upto: DocId) -> Self {
+        NumericDocValuesUpdate {
+            term,
+            field,
+            value,
+            doc_id_upto,
+        }
+    }
+
+    fn bytes_used(&self) -> usize {
+        BYTES_PER_NUMERIC_UPDATE
+            + self.term.field.capacity()
+            + self.term.bytes.capacity()
+            + self.field.capacity()
+    }
+}
+
 /// Holds buffered deletes and updates, by docID, term or query for a
 /// single segment. This is used to hold buffered pending
 /// deletes and updates against the to-be-flushed segment.  Once the
@@ -64,21 +98,15 @@ pub const BYTES_PER_DEL_QUERY_IN_HASH: usize = 4 * mem::size_of::<usize>() + 28;
 /// DocumentsWriterDeleteQueue
 pub struct BufferedUpdates<C: Codec> {
     pub num_term_deletes: AtomicUsize,
-    // num_numeric_updates: AtomicIsize,
+    pub num_numeric_updates: AtomicUsize,
     // num_binary_updates: AtomicIsize,
     pub deleted_terms: HashMap<Term, DocId>,
     // the key is string represent of query, query is share by multi-thread
     pub deleted_queries: HashMap<String, (Arc<dyn Query<C>>, DocId)>,
     pub deleted_doc_ids: Vec<i32>,
-    // Map<dvField,Map<updateTerm,NumericUpdate>>
-    // For each field we keep an ordered list of NumericUpdates, key'd by the
-    // update Term. LinkedHashMap guarantees we will later traverse the map in
-    // insertion order (so that if two terms affect the same document, the last
-    // one that came in wins), and helps us detect faster if the same Term is
-    // used to update the same field multiple times (so we later traverse it
-    // only once).
-    // numeric_updates: HashMap<String, HashMap<Term, NumericDocValuesUpdate>>,
-    //
+    // The numeric doc values updates in insertion order, so that if two terms
+    // affect the same document, the last one that came in wins.
+    pub numeric_updates: Vec<NumericDocValuesUpdate>,
     // Map<dvField,Map<updateTerm,BinaryUpdate>>
     // For each field we keep an ordered list of BinaryUpdates, key'd by the
     // update Term. LinkedHashMap guarantees we will later traverse the map in
@@ -96,9 +124,11 @@ impl<C: Codec> BufferedUpdates<C> {
     pub fn new(name: String) -> Self {
         BufferedUpdates {
             num_term_deletes: AtomicUsize::new(0),
+            num_numeric_updates: AtomicUsize::new(0),
             deleted_terms: HashMap::new(),
             deleted_queries: HashMap::new(),
             deleted_doc_ids: vec![],
+            numeric_updates: vec![],
             bytes_used: AtomicUsize::new(0),
             segment_name: name,
         }
@@ -152,11 +182,27 @@ impl<C: Codec> BufferedUpdates<C> {
         }
     }
 
+    pub fn add_numeric_update(
+        &mut self,
+        term: Term,
+        field: String,
+        value: i64,
+        doc_id_upto: DocId,
+    ) {
+        let update = NumericDocValuesUpdate::new(term, field, value, doc_id_upto);
+        self.bytes_used
+            .fetch_add(update.bytes_used(), Ordering::AcqRel);
+        self.numeric_updates.push(update);
+        self.num_numeric_updates.fetch_add(1, Ordering::AcqRel);
+    }
+
     pub fn clear(&mut self) {
         self.deleted_terms.clear();
         self.deleted_queries.clear();
         self.deleted_doc_ids.clear();
+        self.numeric_updates.clear();
         self.num_term_deletes.store(0, Ordering::Release);
+        self.num_numeric_updates.store(0, Ordering::Release);
         self.bytes_used.store(0, Ordering::Release);
     }
 
@@ -164,6 +210,7 @@ impl<C: Codec> BufferedUpdates<C> {
         !self.deleted_terms.is_empty()
             || !self.deleted_doc_ids.is_empty()
             || !self.deleted_queries.is_empty()
+            || !self.numeric_updates.is_empty()
     }
 }
 
@@ -178,13 +225,17 @@ pub struct FrozenBufferedUpdates<C: Codec> {
     terms: Arc<PrefixCodedTerms>,
     // Parallel array of deleted query, and the doc_id_upto for each
     query_and_limits: Vec<(Arc<dyn Query<C>>, DocId)>,
+    numeric_updates: Arc<Vec<NumericDocValuesUpdate>>,
     pub bytes_used: usize,
     pub num_term_deletes: usize,
     pub gen: u64,
     // assigned by BufferedUpdatesStream once pushed
     // set to true iff this frozen packet represents a segment private delete.
-    // in that case is should only have queries
+    // in that case is should only have queries and doc values updates
     is_segment_private: bool,
+    // the sort of a segment sorted on flush, the doc id limits of its segment
+    // private packet are doc ids before the sort
+    sort_map: Option<Arc<PackedLongDocMap>>,
 }
 
 impl<C: Codec> fmt::Display for FrozenBufferedUpdates<C> {
@@ -199,6 +250,9 @@ impl<C: Codec> fmt::Display for FrozenBufferedUpdates<C> {
         if !self.query_and_limits.is_empty() {
             write!(f, " {} deleted queries", self.query_and_limits.len())?;
         }
+        if !self.numeric_updates.is_empty() {
+            write!(f, " {} numeric updates", self.numeric_updates.len())?;
+        }
         if self.bytes_used > 0 {
             write!(f, " bytes_used={}", self.bytes_used)?;
         }
@@ -227,23 +281,35 @@ impl<C: Codec> FrozenBufferedUpdates<C> {
             .drain()
             .map(|(_key, value)| value)
             .collect();
+        let numeric_updates: Vec<_> = deletes.numeric_updates.drain(..).collect();
+        let numeric_updates_bytes: usize = numeric_updates.iter().map(|u| u.bytes_used()).sum();
 
         // TODO if a Term affects multiple fields, we could keep the updates key'd by Term
         // so that it maps to all fields it affects, sorted by their docUpto, and traverse
         // that Term only once, applying the update to all fields that still need to be
         // updated.
-        let bytes_used = terms.ram_bytes_used() + query_and_limits.len() * BYTES_PER_DEL_QUERY;
+        let bytes_used = terms.ram_bytes_used()
+            + query_and_limits.len() * BYTES_PER_DEL_QUERY
+            + numeric_updates_bytes;
         FrozenBufferedUpdates {
             terms: Arc::new(terms),
             query_and_limits,
+            numeric_updates: Arc::new(numeric_updates),
             bytes_used,
             num_term_deletes: deletes.num_term_deletes.load(Ordering::Acquire),
             gen: u64::max_value(),
             // used as a sentinel of invalid
             is_segment_private,
+            sort_map: None,
         }
     }
 
+    /// Sets the sort of the flushed segment of a segment private packet.
+    pub fn set_sort_map(&mut self, sort_map: Arc<PackedLongDocMap>) {
+        debug_assert!(self.is_segment_private);
+        self.sort_map = Some(sort_map);
+    }
+
     pub fn set_del_gen(&mut self, gen: u64) {
         assert_eq!(self.gen, u64::max_value());
         self.gen = gen;
@@ -251,7 +317,7 @@ impl<C: Codec> FrozenBufferedUpdates<C> {
     }
 
     pub fn any(&self) -> bool {
-        self.terms.size > 0 || self.query_and_limits.len() > 0
+        self.terms.size > 0 || self.query_and_limits.len() > 0 || !self.numeric_updates.is_empty()
     }
 }
 
@@ -460,6 +526,11 @@ impl<C: Codec> BufferedUpdatesStream<C> {
                         updates[del_idx - 1].query_and_limits.iter(),
                         seg_state,
                     )?;
+                    Self::apply_numeric_updates(
+                        updates[del_idx - 1].numeric_updates.iter(),
+                        seg_state,
+                        updates[del_idx - 1].sort_map.as_ref(),
+                    )?;
 
                     // ... then coalesced deletes/updates, so that if there is an update
                     // that appears in both, the coalesced updates (carried from
@@ -470,6 +541,11 @@ impl<C: Codec> BufferedUpdatesStream<C> {
                             seg_state,
                         )?;
                     }
+                    Self::apply_numeric_updates(
+                        coalesce_updates.numeric_updates(),
+                        seg_state,
+                        None,
+                    )?;
 
                     total_del_count += del_count;
 
@@ -494,6 +570,11 @@ impl<C: Codec> BufferedUpdatesStream<C> {
                                 seg_state,
                             )?;
                         }
+                        Self::apply_numeric_updates(
+                            coalesce_updates.numeric_updates(),
+                            seg_state,
+                            None,
+                        )?;
 
                         total_del_count += del_count;
                     }
@@ -568,6 +649,29 @@ impl<C: Codec> BufferedUpdatesStream<C> {
         Ok(del_count)
     }
 
+    /// Resolves the numeric doc values updates to the live docs of the segment,
+    /// in order so that the last update of a document wins.
+    fn apply_numeric_updates<'a, D, MS, MP>(
+        updates: impl Iterator<Item = &'a NumericDocValuesUpdate>,
+        seg_state: &mut SegmentState<D, C, MS, MP>,
+        sort_map: Option<&Arc<PackedLongDocMap>>,
+    ) -> Result<()>
+    where
+        D: Directory + Send + Sync + 'static,
+        MS: MergeScheduler,
+        MP: MergePolicy,
+    {
+        for update in updates {
+            if seg_state
+                .rld
+                .update_numeric(update, sort_map.map(|m| m.as_ref()))?
+            {
+                seg_state.any_updates = true;
+            }
+        }
+        Ok(())
+    }
+
     /// Merge sorts the deleted terms and all segments to resolve terms to doc_ids for deletion.
     fn apply_term_deletes<D, MS, MP>(
         &mut self,
@@ -776,12 +880,14 @@ impl<C: Codec> BufferedUpdatesStream<C> {
     {
         let mut first_err = Ok(ApplyDeletesResult::new(false, 0, vec![]));
         let mut total_del_count = 0;
+        let mut any_updates = false;
         let mut all_deleted = vec![];
 
         for seg_state in seg_states {
             if success {
                 total_del_count +=
                     seg_state.rld.pending_delete_count() - seg_state.start_del_count as u32;
+                any_updates |= seg_state.any_updates;
                 seg_state.rld.info.set_buffered_deletes_gen(gen);
                 let full_del_count =
                     seg_state.rld.info.del_count() + seg_state.rld.pending_delete_count() as i32;
@@ -806,7 +912,7 @@ impl<C: Codec> BufferedUpdatesStream<C> {
         );
 
         Ok(ApplyDeletesResult::new(
-            total_del_count > 0,
+            total_del_count > 0 || any_updates,
             gen,
             all_deleted,
         ))
@@ -886,7 +992,7 @@ impl<C: Codec> BufferedUpdatesStream<C> {
 }
 
 pub struct ApplyDeletesResult<D: Directory, C: Codec> {
-    // True if any actual deletes took place:
+    // True if any actual deletes or doc values updates took place:
     pub any_deletes: bool,
     // Current gen, for the merged segment:
     pub gen: i64,
@@ -917,6 +1023,7 @@ struct SegmentState<
     postings: Option<CodecPostingIterator<C>>,
     term: Option<Vec<u8>>,
     any: bool,
+    any_updates: bool,
 }
 
 impl<D, C, MS, MP> SegmentState<D, C, MS, MP>
@@ -940,6 +1047,7 @@ where
             postings: None,
             term: None,
             any: false,
+            any_updates: false,
         })
     }
 
@@ -1034,6 +1142,8 @@ where
 struct CoalescedUpdates<C: Codec> {
     queries: HashMap<String, (Arc<dyn Query<C>>, DocId)>,
     terms: Vec<Arc<PrefixCodedTerms>>,
+    // the numeric updates of the packets, newest first
+    numeric_updates: Vec<Arc<Vec<NumericDocValuesUpdate>>>,
     total_term_count: usize,
 }
 
@@ -1042,6 +1152,7 @@ impl<C: Codec> Default for CoalescedUpdates<C> {
         CoalescedUpdates {
             queries: HashMap::new(),
             terms: vec![],
+            numeric_updates: vec![],
             total_term_count: 0,
         }
     }
@@ -1056,6 +1167,9 @@ impl<C: Codec> CoalescedUpdates<C> {
             self.queries
                 .insert(query.to_string(), (Arc::clone(query), i32::max_value()));
         }
+        if !up.numeric_updates.is_empty() {
+            self.numeric_updates.push(Arc::clone(&up.numeric_updates));
+        }
     }
 
     fn term_iterator(&self) -> Result<FieldTermIterator> {
@@ -1071,7 +1185,15 @@ impl<C: Codec> CoalescedUpdates<C> {
         !self.queries.is_empty()
     }
 
+    /// The numeric updates of all the packets, oldest first.
+    pub fn numeric_updates(&self) -> impl Iterator<Item = &NumericDocValuesUpdate> {
+        self.numeric_updates
+            .iter()
+            .rev()
+            .flat_map(|updates| updates.iter())
+    }
+
     pub fn any(&self) -> bool {
-        self.queries.len() > 0 || self.terms.len() > 0
+        self.queries.len() > 0 || self.terms.len() > 0 || !self.numeric_updates.is_empty()
     }
 }
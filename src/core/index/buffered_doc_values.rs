@@ -0,0 +1,711 @@
+// Copyright 2019 Zhizhesihai (Beijing) Technology Limited.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use core::codec::{DocValuesConsumer, NormsConsumer};
+use core::index::sorter::{PackedLongDocMap, Sorter, SorterDocComparator, SorterDocMap};
+use core::index::{DocValuesType, FieldInfo};
+use core::search::sort::Sort;
+use core::search::sort_field::{
+    SortField, SortFieldMissingValue, SortFieldType, SortedNumericSelectorType,
+};
+use core::util::numeric::{sortable_double_bits, sortable_float_bits};
+use core::util::{BytesRef, DocId, Numeric, ReusableIterator};
+
+use error::{ErrorKind::IllegalArgument, Result};
+
+use std::borrow::Cow;
+use std::cmp::Ordering;
+
+/// The doc values of a segment flushed with an index sort.
+///
+/// The doc values writers are flushed into it in doc id order, the sort map
+/// of the segment is computed from the buffered values of the sort fields, and
+/// all the values are then written in the sorted order.
+pub struct BufferedDocValues {
+    max_doc: DocId,
+    fields: Vec<BufferedField>,
+}
+
+struct BufferedField {
+    field_info: FieldInfo,
+    values: BufferedValues,
+}
+
+// the values as the doc values writers pass them to a `DocValuesConsumer`,
+// with the bytes copied as they belong to the writers
+enum BufferedValues {
+    Numeric(Vec<Numeric>),
+    Binary(Vec<Option<Vec<u8>>>),
+    Sorted {
+        values: Vec<Vec<u8>>,
+        doc_to_ord: Vec<Numeric>,
+    },
+    SortedNumeric {
+        values: Vec<Numeric>,
+        doc_to_value_count: Vec<u32>,
+    },
+    SortedSet {
+        values: Vec<Vec<u8>>,
+        doc_to_ord_count: Vec<u32>,
+        ords: Vec<Numeric>,
+    },
+}
+
+impl BufferedValues {
+    fn doc_values_type(&self) -> DocValuesType {
+        match self {
+            BufferedValues::Numeric(_) => DocValuesType::Numeric,
+            BufferedValues::Binary(_) => DocValuesType::Binary,
+            BufferedValues::Sorted { .. } => DocValuesType::Sorted,
+            BufferedValues::SortedNumeric { .. } => DocValuesType::SortedNumeric,
+            BufferedValues::SortedSet { .. } => DocValuesType::SortedSet,
+        }
+    }
+}
+
+impl BufferedDocValues {
+    pub fn new(max_doc: DocId) -> Self {
+        BufferedDocValues {
+            max_doc,
+            fields: vec![],
+        }
+    }
+
+    /// Computes the permutation of the buffered docs following `sort`, `None`
+    /// if they are already sorted.
+    pub fn sort(&self, sort: &Sort) -> Result<Option<PackedLongDocMap>> {
+        let mut comparator = SortKeysComparator {
+            keys: Vec::with_capacity(sort.get_sort().len()),
+        };
+        for sort_field in sort.get_sort() {
+            comparator
+                .keys
+                .push((self.sort_keys(sort_field)?, sort_field.is_reverse()));
+        }
+        Sorter::sort(self.max_doc, &mut comparator)
+    }
+
+    // the values the docs are compared by for a sort field, as the
+    // `MultiSorter` compares the docs of the sorted segments
+    fn sort_keys(&self, sort_field: &SortField) -> Result<SortKeys> {
+        let field_type = Sorter::sort_field_type(sort_field);
+        let values = self
+            .fields
+            .iter()
+            .find(|f| f.field_info.name == sort_field.field())
+            .map(|f| &f.values);
+        let raw_values: Vec<Option<i64>> = match (sort_field, values) {
+            (_, None) if field_type == SortFieldType::String => {
+                return Ok(SortKeys::Long(vec![0; self.max_doc as usize]));
+            }
+            (_, None) => vec![None; self.max_doc as usize],
+            (SortField::Simple(_), Some(BufferedValues::Sorted { doc_to_ord, .. }))
+                if field_type == SortFieldType::String =>
+            {
+                // the ords follow the order of the values
+                let missing_ord = match sort_field.string_missing() {
+                    SortFieldMissingValue::StringFirst => -1,
+                    SortFieldMissingValue::StringLast => i64::max_value(),
+                };
+                let ords = doc_to_ord
+                    .iter()
+                    .map(|ord| match ord.long_value() {
+                        ord if ord < 0 => missing_ord,
+                        ord => ord,
+                    })
+                    .collect();
+                return Ok(SortKeys::Long(ords));
+            }
+            (SortField::Simple(_), Some(BufferedValues::Numeric(values)))
+                if field_type != SortFieldType::String =>
+            {
+                values
+                    .iter()
+                    .map(|v| match v {
+                        Numeric::Null => None,
+                        v => Some(v.long_value()),
+                    })
+                    .collect()
+            }
+            (
+                SortField::SortedNumeric(s),
+                Some(BufferedValues::SortedNumeric {
+                    values,
+                    doc_to_value_count,
+                }),
+            ) => {
+                // the values of a doc are sorted
+                let mut selected = Vec::with_capacity(doc_to_value_count.len());
+                let mut upto = 0;
+                for &count in doc_to_value_count {
+                    let count = count as usize;
+                    if count == 0 {
+                        selected.push(None);
+                        continue;
+                    }
+                    let value = match s.selector() {
+                        SortedNumericSelectorType::Min => values[upto].long_value(),
+                        SortedNumericSelectorType::Max => values[upto + count - 1].long_value(),
+                    };
+                    upto += count;
+                    selected.push(Some(match field_type {
+                        SortFieldType::Float => i64::from(sortable_float_bits(value as i32)),
+                        SortFieldType::Double => sortable_double_bits(value),
+                        _ => value,
+                    }));
+                }
+                selected
+            }
+            (_, Some(values)) => bail!(IllegalArgument(format!(
+                "index sort field '{}' of type {:?} can't sort {:?} doc values",
+                sort_field.field(),
+                field_type,
+                values.doc_values_type()
+            ))),
+        };
+
+        let missing_value = sort_field.missing_value();
+        Ok(match field_type {
+            SortFieldType::Long | SortFieldType::Int => {
+                let missing = match missing_value {
+                    Some(m) if field_type == SortFieldType::Long => m.get_long().unwrap_or(0),
+                    Some(m) => m.get_int().map_or(0, i64::from),
+                    None => 0,
+                };
+                SortKeys::Long(raw_values.iter().map(|v| v.unwrap_or(missing)).collect())
+            }
+            SortFieldType::Float => {
+                let missing = missing_value.and_then(|m| m.get_float()).unwrap_or(0.0);
+                SortKeys::Double(
+                    raw_values
+                        .iter()
+                        .map(|v| f64::from(v.map_or(missing, |v| f32::from_bits(v as u32))))
+                        .collect(),
+                )
+            }
+            SortFieldType::Double => {
+                let missing = missing_value.and_then(|m| m.get_double()).unwrap_or(0.0);
+                SortKeys::Double(
+                    raw_values
+                        .iter()
+                        .map(|v| v.map_or(missing, |v| f64::from_bits(v as u64)))
+                        .collect(),
+                )
+            }
+            _ => bail!(IllegalArgument(format!(
+                "invalid index sort field '{}' of type {:?}",
+                sort_field.field(),
+                field_type
+            ))),
+        })
+    }
+
+    /// Writes the buffered values to `consumer` in the order of `sort_map`, or
+    /// in doc id order without it.
+    pub fn write(
+        &self,
+        consumer: &mut impl DocValuesConsumer,
+        sort_map: Option<&PackedLongDocMap>,
+    ) -> Result<()> {
+        for field in &self.fields {
+            let field_info = &field.field_info;
+            match &field.values {
+                BufferedValues::Numeric(values) => {
+                    let values = permute(values, sort_map);
+                    let mut values = BufferedIter::new(&values, |v: &Numeric| *v);
+                    consumer.add_numeric_field(field_info, &mut values)?;
+                }
+                BufferedValues::Binary(values) => {
+                    let values = permute(values, sort_map);
+                    let mut values = BufferedIter::new(&values, |v: &Option<Vec<u8>>| {
+                        v.as_ref().map(|b| BytesRef::new(b))
+                    });
+                    consumer.add_binary_field(field_info, &mut values)?;
+                }
+                BufferedValues::Sorted { values, doc_to_ord } => {
+                    let doc_to_ord = permute(doc_to_ord, sort_map);
+                    consumer.add_sorted_field(
+                        field_info,
+                        &mut BufferedIter::new(values, |v: &Vec<u8>| BytesRef::new(v)),
+                        &mut BufferedIter::new(&doc_to_ord, |ord: &Numeric| *ord),
+                    )?;
+                }
+                BufferedValues::SortedNumeric {
+                    values,
+                    doc_to_value_count,
+                } => {
+                    let (values, counts) = permute_multi(values, doc_to_value_count, sort_map);
+                    consumer.add_sorted_numeric_field(
+                        field_info,
+                        &mut BufferedIter::new(&values, |v: &Numeric| *v),
+                        &mut BufferedIter::new(&counts, |c: &u32| *c),
+                    )?;
+                }
+                BufferedValues::SortedSet {
+                    values,
+                    doc_to_ord_count,
+                    ords,
+                } => {
+                    let (ords, counts) = permute_multi(ords, doc_to_ord_count, sort_map);
+                    consumer.add_sorted_set_field(
+                        field_info,
+                        &mut BufferedIter::new(values, |v: &Vec<u8>| BytesRef::new(v)),
+                        &mut BufferedIter::new(&counts, |c: &u32| *c),
+                        &mut BufferedIter::new(&ords, |ord: &Numeric| *ord),
+                    )?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn add(&mut self, field_info: &FieldInfo, values: BufferedValues) {
+        self.fields.push(BufferedField {
+            field_info: field_info.clone(),
+            values,
+        });
+    }
+}
+
+fn copy_bytes(values: &mut impl ReusableIterator<Item = Result<BytesRef>>) -> Result<Vec<Vec<u8>>> {
+    values.map(|v| v.map(|b| b.bytes().to_vec())).collect()
+}
+
+impl DocValuesConsumer for BufferedDocValues {
+    fn add_numeric_field(
+        &mut self,
+        field_info: &FieldInfo,
+        values: &mut impl ReusableIterator<Item = Result<Numeric>>,
+    ) -> Result<()> {
+        let values = values.collect::<Result<Vec<_>>>()?;
+        debug_assert_eq!(values.len(), self.max_doc as usize);
+        self.add(field_info, BufferedValues::Numeric(values));
+        Ok(())
+    }
+
+    fn add_binary_field(
+        &mut self,
+        field_info: &FieldInfo,
+        values: &mut impl ReusableIterator<Item = Result<Option<BytesRef>>>,
+    ) -> Result<()> {
+        let values = values
+            .map(|v| v.map(|b| b.map(|b| b.bytes().to_vec())))
+            .collect::<Result<Vec<_>>>()?;
+        debug_assert_eq!(values.len(), self.max_doc as usize);
+        self.add(field_info, BufferedValues::Binary(values));
+        Ok(())
+    }
+
+    fn add_sorted_field(
+        &mut self,
+        field_info: &FieldInfo,
+        values: &mut impl ReusableIterator<Item = Result<BytesRef>>,
+        doc_to_ord: &mut impl ReusableIterator<Item = Result<Numeric>>,
+    ) -> Result<()> {
+        let values = copy_bytes(values)?;
+        let doc_to_ord = doc_to_ord.collect::<Result<Vec<_>>>()?;
+        debug_assert_eq!(doc_to_ord.len(), self.max_doc as usize);
+        self.add(field_info, BufferedValues::Sorted { values, doc_to_ord });
+        Ok(())
+    }
+
+    fn add_sorted_numeric_field(
+        &mut self,
+        field_info: &FieldInfo,
+        values: &mut impl ReusableIterator<Item = Result<Numeric>>,
+        doc_to_value_count: &mut impl ReusableIterator<Item = Result<u32>>,
+    ) -> Result<()> {
+        let values = values.collect::<Result<Vec<_>>>()?;
+        let doc_to_value_count = doc_to_value_count.collect::<Result<Vec<_>>>()?;
+        debug_assert_eq!(doc_to_value_count.len(), self.max_doc as usize);
+        self.add(
+            field_info,
+            BufferedValues::SortedNumeric {
+                values,
+                doc_to_value_count,
+            },
+        );
+        Ok(())
+    }
+
+    fn add_sorted_set_field(
+        &mut self,
+        field_info: &FieldInfo,
+        values: &mut impl ReusableIterator<Item = Result<BytesRef>>,
+        doc_to_ord_count: &mut impl ReusableIterator<Item = Result<u32>>,
+        ords: &mut impl ReusableIterator<Item = Result<Numeric>>,
+    ) -> Result<()> {
+        let values = copy_bytes(values)?;
+        let doc_to_ord_count = doc_to_ord_count.collect::<Result<Vec<_>>>()?;
+        let ords = ords.collect::<Result<Vec<_>>>()?;
+        debug_assert_eq!(doc_to_ord_count.len(), self.max_doc as usize);
+        self.add(
+            field_info,
+            BufferedValues::SortedSet {
+                values,
+                doc_to_ord_count,
+                ords,
+            },
+        );
+        Ok(())
+    }
+}
+
+/// Writes the norms of a segment flushed with an index sort in the sorted
+/// order.
+pub struct SortingNormsConsumer<'a, N: NormsConsumer> {
+    consumer: &'a mut N,
+    sort_map: &'a PackedLongDocMap,
+}
+
+impl<'a, N: NormsConsumer> SortingNormsConsumer<'a, N> {
+    pub fn new(consumer: &'a mut N, sort_map: &'a PackedLongDocMap) -> Self {
+        SortingNormsConsumer { consumer, sort_map }
+    }
+}
+
+impl<'a, N: NormsConsumer> NormsConsumer for SortingNormsConsumer<'a, N> {
+    fn add_norms_field(
+        &mut self,
+        field_info: &FieldInfo,
+        values: &mut impl ReusableIterator<Item = Result<Numeric>>,
+    ) -> Result<()> {
+        let values = values.collect::<Result<Vec<_>>>()?;
+        let values = permute(&values, Some(self.sort_map));
+        self.consumer.add_norms_field(
+            field_info,
+            &mut BufferedIter::new(&values, |v: &Numeric| *v),
+        )
+    }
+}
+
+// the values of the docs in the order of `sort_map`
+fn permute<'a, T: Clone>(values: &'a [T], sort_map: Option<&PackedLongDocMap>) -> Cow<'a, [T]> {
+    match sort_map {
+        Some(sort_map) => {
+            debug_assert_eq!(values.len(), sort_map.len());
+            let values = (0..values.len())
+                .map(|doc| values[sort_map.new_to_old(doc as DocId) as usize].clone())
+                .collect();
+            Cow::Owned(values)
+        }
+        None => Cow::Borrowed(values),
+    }
+}
+
+// the values of the docs in the order of `sort_map`, where doc `i` has the
+// `counts[i]` values following the ones of the previous docs
+fn permute_multi<'a, T: Clone>(
+    values: &'a [T],
+    counts: &'a [u32],
+    sort_map: Option<&PackedLongDocMap>,
+) -> (Cow<'a, [T]>, Cow<'a, [u32]>) {
+    let sort_map = match sort_map {
+        Some(sort_map) => sort_map,
+        None => return (Cow::Borrowed(values), Cow::Borrowed(counts)),
+    };
+    let mut starts = Vec::with_capacity(counts.len());
+    let mut start = 0;
+    for &count in counts {
+        starts.push(start);
+        start += count as usize;
+    }
+    let mut sorted_values = Vec::with_capacity(values.len());
+    let mut sorted_counts = Vec::with_capacity(counts.len());
+    for doc in 0..counts.len() {
+        let old = sort_map.new_to_old(doc as DocId) as usize;
+        let count = counts[old];
+        sorted_values.extend_from_slice(&values[starts[old]..starts[old] + count as usize]);
+        sorted_counts.push(count);
+    }
+    (Cow::Owned(sorted_values), Cow::Owned(sorted_counts))
+}
+
+// iterates over the buffered values as a consumer expects them
+struct BufferedIter<'a, T: 'a, F> {
+    values: &'a [T],
+    upto: usize,
+    map: F,
+}
+
+impl<'a, T: 'a, F> BufferedIter<'a, T, F> {
+    fn new(values: &'a [T], map: F) -> Self {
+        BufferedIter {
+            values,
+            upto: 0,
+            map,
+        }
+    }
+}
+
+impl<'a, T: 'a, R, F: Fn(&'a T) -> R> Iterator for BufferedIter<'a, T, F> {
+    type Item = Result<R>;
+
+    fn next(&mut self) -> Option<Result<R>> {
+        if self.upto < self.values.len() {
+            self.upto += 1;
+            Some(Ok((self.map)(&self.values[self.upto - 1])))
+        } else {
+            None
+        }
+    }
+}
+
+impl<'a, T: 'a, R, F: Fn(&'a T) -> R> ReusableIterator for BufferedIter<'a, T, F> {
+    fn reset(&mut self) {
+        self.upto = 0;
+    }
+}
+
+enum SortKeys {
+    Long(Vec<i64>),
+    Double(Vec<f64>),
+}
+
+struct SortKeysComparator {
+    keys: Vec<(SortKeys, bool)>,
+}
+
+impl SorterDocComparator for SortKeysComparator {
+    fn compare(&mut self, doc1: DocId, doc2: DocId) -> Result<Ordering> {
+        let (doc1, doc2) = (doc1 as usize, doc2 as usize);
+        for (keys, reverse) in &self.keys {
+            let cmp = match keys {
+                SortKeys::Long(keys) => keys[doc1].cmp(&keys[doc2]),
+                SortKeys::Double(keys) => keys[doc1]
+                    .partial_cmp(&keys[doc2])
+                    .unwrap_or(Ordering::Equal),
+            };
+            if cmp != Ordering::Equal {
+                return Ok(if *reverse { cmp.reverse() } else { cmp });
+            }
+        }
+        Ok(doc1.cmp(&doc2))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::index::IndexOptions;
+    use core::search::sort_field::{SimpleSortField, SortedNumericSortField};
+
+    fn field_info(name: &str, number: u32) -> FieldInfo {
+        FieldInfo::new(
+            name.to_string(),
+            number,
+            false,
+            true,
+            false,
+            IndexOptions::Null,
+            DocValuesType::Null,
+            0,
+            Default::default(),
+            0,
+            0,
+        )
+        .unwrap()
+    }
+
+    fn new_to_old(sort_map: &PackedLongDocMap) -> Vec<DocId> {
+        (0..sort_map.len() as DocId)
+            .map(|doc| sort_map.new_to_old(doc))
+            .collect()
+    }
+
+    fn simple(field: &str, field_type: SortFieldType, reverse: bool) -> SortField {
+        SortField::Simple(SimpleSortField::new(field.to_string(), field_type, reverse))
+    }
+
+    fn buffered() -> BufferedDocValues {
+        let mut buffered = BufferedDocValues::new(4);
+        let words: Vec<&[u8]> = vec![&b"a"[..], &b"b"[..], &b"c"[..]];
+        let values: Vec<Option<BytesRef>> = vec![
+            Some(BytesRef::new(words[2])),
+            None,
+            Some(BytesRef::new(words[0])),
+            Some(BytesRef::new(words[1])),
+        ];
+        let ords = vec![2, -1, 0, 1];
+        buffered
+            .add_sorted_field(
+                &field_info("word", 0),
+                &mut BufferedIter::new(&words, |w: &&[u8]| BytesRef::new(w)),
+                &mut BufferedIter::new(&ords, |o: &i32| Numeric::Int(*o)),
+            )
+            .unwrap();
+        buffered
+            .add_binary_field(
+                &field_info("payload", 1),
+                &mut BufferedIter::new(&values, |v: &Option<BytesRef>| *v),
+            )
+            .unwrap();
+        let ranks = vec![
+            Numeric::Long(5),
+            Numeric::Long(-3),
+            Numeric::Null,
+            Numeric::Long(5),
+        ];
+        buffered
+            .add_numeric_field(
+                &field_info("rank", 2),
+                &mut BufferedIter::new(&ranks, |v: &Numeric| *v),
+            )
+            .unwrap();
+        let values = vec![
+            Numeric::Long(1),
+            Numeric::Long(9),
+            Numeric::Long(4),
+            Numeric::Long(2),
+            Numeric::Long(3),
+        ];
+        let counts = vec![2u32, 1, 0, 2];
+        buffered
+            .add_sorted_numeric_field(
+                &field_info("tags", 3),
+                &mut BufferedIter::new(&values, |v: &Numeric| *v),
+                &mut BufferedIter::new(&counts, |c: &u32| *c),
+            )
+            .unwrap();
+        buffered
+    }
+
+    #[test]
+    fn test_sort_by_sorted_values() {
+        let buffered = buffered();
+        let sort = Sort::new(vec![simple("word", SortFieldType::String, false)]);
+        let sort_map = buffered.sort(&sort).unwrap().unwrap();
+        // the doc without a word sorts first
+        assert_eq!(new_to_old(&sort_map), vec![1, 2, 3, 0]);
+
+        let mut field = simple("word", SortFieldType::String, true);
+        if let SortField::Simple(ref mut s) = field {
+            s.set_string_missing(SortFieldMissingValue::StringLast);
+        }
+        let sort_map = buffered.sort(&Sort::new(vec![field])).unwrap().unwrap();
+        assert_eq!(new_to_old(&sort_map), vec![1, 0, 3, 2]);
+    }
+
+    #[test]
+    fn test_sort_by_numeric_values() {
+        let buffered = buffered();
+        // ties are broken by the doc ids
+        let sort = Sort::new(vec![simple("rank", SortFieldType::Long, true)]);
+        let sort_map = buffered.sort(&sort).unwrap().unwrap();
+        assert_eq!(new_to_old(&sort_map), vec![0, 3, 2, 1]);
+
+        let sort = Sort::new(vec![SortField::SortedNumeric(SortedNumericSortField::new(
+            "tags".to_string(),
+            SortFieldType::Long,
+            false,
+            SortedNumericSelectorType::Max,
+        ))]);
+        let sort_map = buffered.sort(&sort).unwrap().unwrap();
+        assert_eq!(new_to_old(&sort_map), vec![2, 3, 1, 0]);
+
+        // the values of a field missing from all the docs are all equal
+        let sort = Sort::new(vec![simple("missing", SortFieldType::Int, false)]);
+        assert!(buffered.sort(&sort).unwrap().is_none());
+
+        let sort = Sort::new(vec![simple("payload", SortFieldType::Long, false)]);
+        assert!(buffered.sort(&sort).is_err());
+    }
+
+    #[derive(Default)]
+    struct Collected {
+        binary: Vec<Option<Vec<u8>>>,
+        counts: Vec<u32>,
+        values: Vec<i64>,
+    }
+
+    impl DocValuesConsumer for Collected {
+        fn add_numeric_field(
+            &mut self,
+            _field_info: &FieldInfo,
+            _values: &mut impl ReusableIterator<Item = Result<Numeric>>,
+        ) -> Result<()> {
+            Ok(())
+        }
+
+        fn add_binary_field(
+            &mut self,
+            _field_info: &FieldInfo,
+            values: &mut impl ReusableIterator<Item = Result<Option<BytesRef>>>,
+        ) -> Result<()> {
+            for v in values {
+                self.binary.push(v?.map(|b| b.bytes().to_vec()));
+            }
+            Ok(())
+        }
+
+        fn add_sorted_field(
+            &mut self,
+            _field_info: &FieldInfo,
+            _values: &mut impl ReusableIterator<Item = Result<BytesRef>>,
+            _doc_to_ord: &mut impl ReusableIterator<Item = Result<Numeric>>,
+        ) -> Result<()> {
+            Ok(())
+        }
+
+        fn add_sorted_numeric_field(
+            &mut self,
+            _field_info: &FieldInfo,
+            values: &mut impl ReusableIterator<Item = Result<Numeric>>,
+            doc_to_value_count: &mut impl ReusableIterator<Item = Result<u32>>,
+        ) -> Result<()> {
+            for v in values {
+                self.values.push(v?.long_value());
+            }
+            for c in doc_to_value_count {
+                self.counts.push(c?);
+            }
+            Ok(())
+        }
+
+        fn add_sorted_set_field(
+            &mut self,
+            _field_info: &FieldInfo,
+            _values: &mut impl ReusableIterator<Item = Result<BytesRef>>,
+            _doc_to_ord_count: &mut impl ReusableIterator<Item = Result<u32>>,
+            _ords: &mut impl ReusableIterator<Item = Result<Numeric>>,
+        ) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_write_in_sorted_order() {
+        let buffered = buffered();
+        let sort = Sort::new(vec![simple("word", SortFieldType::String, false)]);
+        let sort_map = buffered.sort(&sort).unwrap().unwrap();
+
+        let mut collected = Collected::default();
+        buffered.write(&mut collected, Some(&sort_map)).unwrap();
+        assert_eq!(
+            collected.binary,
+            vec![
+                None,
+                Some(b"a".to_vec()),
+                Some(b"b".to_vec()),
+                Some(b"c".to_vec())
+            ]
+        );
+        assert_eq!(collected.counts, vec![1, 0, 2, 2]);
+        assert_eq!(collected.values, vec![4, 2, 3, 1, 9]);
+
+        let mut collected = Collected::default();
+        buffered.write(&mut collected, None).unwrap();
+        assert_eq!(collected.counts, vec![2, 1, 0, 2]);
+        assert_eq!(collected.values, vec![1, 9, 4, 2, 3]);
+    }
+}
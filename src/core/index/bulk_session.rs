@@ -0,0 +1,445 @@
+// Copyright 2019 Zhizhesihai (Beijing) Technology Limited.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use core::codec::Codec;
+use core::index::merge_policy::MergePolicy;
+use core::index::merge_scheduler::MergeScheduler;
+use core::index::{Fieldable, IndexWriter};
+use core::store::Directory;
+
+use error::ErrorKind::IllegalState;
+use error::Result;
+
+use std::cmp::max;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// How often blocked producers and the idle flusher re-check the RAM used by
+/// the buffered documents, in case a flush finished without notifying them.
+const RECHECK_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Holds the thresholds of a `BulkSession`.
+#[derive(Clone, Debug)]
+pub struct BulkSessionConfig {
+    /// Producers block while the buffered documents of the writer, including
+    /// the ones pending or being flushed, use more than this many bytes.
+    pub max_buffered_bytes: u64,
+    /// The session flushes the most RAM consuming in-memory segment in the
+    /// background once the buffered documents use more than this many bytes.
+    pub flush_trigger_bytes: u64,
+    /// Producers also block while this many segments are being flushed.
+    pub max_flushing_segments: usize,
+}
+
+impl BulkSessionConfig {
+    /// Blocks the producers above `max_buffered_bytes`, flushing in the
+    /// background from half of it.
+    pub fn new(max_buffered_bytes: u64) -> BulkSessionConfig {
+        BulkSessionConfig {
+            max_buffered_bytes,
+            flush_trigger_bytes: max_buffered_bytes / 2,
+            max_flushing_segments: 4,
+        }
+    }
+}
+
+impl Default for BulkSessionConfig {
+    fn default() -> Self {
+        BulkSessionConfig::new(64 * 1024 * 1024)
+    }
+}
+
+/// The outcome of `BulkSession::try_add`.
+pub enum TryAdd<F: Fieldable> {
+    /// The document was added, with this sequence number.
+    Added(u64),
+    /// The buffered documents use too much RAM, the document is handed back.
+    WouldBlock(Vec<F>),
+}
+
+/// What a `BulkSession` did, returned by `BulkSession::finish`.
+#[derive(Clone, Debug, Default)]
+pub struct BulkSummary {
+    pub docs_indexed: u64,
+    /// The segments flushed while the session was open, by the session or by
+    /// the writer itself.
+    pub segments_created: u64,
+    /// The size of these segments.
+    pub bytes_written: u64,
+    /// The time the producers spent blocked, summed over all of them.
+    pub blocked_time: Duration,
+    /// The most RAM used by the buffered documents after adding a document.
+    pub peak_buffered_bytes: u64,
+}
+
+struct BulkState {
+    docs_indexed: u64,
+    blocked_time: Duration,
+    peak_buffered_bytes: u64,
+    flush_requested: bool,
+    finished: bool,
+    flusher_failed: bool,
+}
+
+struct BulkShared {
+    state: Mutex<BulkState>,
+    cond: Condvar,
+}
+
+/// Feeds documents to an `IndexWriter`, keeping the RAM used by the buffered
+/// documents bounded.
+///
+/// `add` blocks while the buffered documents use more than
+/// `BulkSessionConfig::max_buffered_bytes`, or while too many segments are
+/// being flushed. A flusher thread owned by the session flushes the most RAM
+/// consuming in-memory segment whenever they use more than
+/// `BulkSessionConfig::flush_trigger_bytes`, so the producers don't have to
+/// wait for the flush policy of the writer.
+///
+/// The session can be shared by any number of producer threads, each of them
+/// indexing into its own DWPT as with `IndexWriter::add_document`.
+pub struct BulkSession<
+    D: Directory + Send + Sync + 'static,
+    C: Codec,
+    MS: MergeScheduler,
+    MP: MergePolicy,
+> {
+    writer: IndexWriter<D, C, MS, MP>,
+    config: BulkSessionConfig,
+    shared: Arc<BulkShared>,
+    flusher: Option<JoinHandle<Result<()>>>,
+    flushed_at_start: (u64, u64),
+}
+
+impl<D, C, MS, MP> BulkSession<D, C, MS, MP>
+where
+    D: Directory + Send + Sync + 'static,
+    C: Codec,
+    MS: MergeScheduler,
+    MP: MergePolicy,
+{
+    pub(crate) fn new(
+        writer: IndexWriter<D, C, MS, MP>,
+        config: BulkSessionConfig,
+    ) -> Result<BulkSession<D, C, MS, MP>> {
+        if config.flush_trigger_bytes > config.max_buffered_bytes
+            || config.max_flushing_segments == 0
+        {
+            bail!(IllegalState(format!(
+                "invalid bulk session config {:?}, the flush trigger can't exceed the max \
+                 buffered bytes and at least one segment must be allowed to flush",
+                config
+            )));
+        }
+        let shared = Arc::new(BulkShared {
+            state: Mutex::new(BulkState {
+                docs_indexed: 0,
+                blocked_time: Duration::default(),
+                peak_buffered_bytes: 0,
+                flush_requested: false,
+                finished: false,
+                flusher_failed: false,
+            }),
+            cond: Condvar::new(),
+        });
+        let flusher = {
+            let writer = writer.clone();
+            let shared = Arc::clone(&shared);
+            let flush_trigger_bytes = config.flush_trigger_bytes;
+            thread::Builder::new()
+                .name("Rucene Bulk Flush Thread".into())
+                .spawn(move || Self::run_flusher(&writer, &shared, flush_trigger_bytes))
+                .expect("failed to spawn thread")
+        };
+        let flushed_at_start = writer.flushed_segment_stats();
+        Ok(BulkSession {
+            writer,
+            config,
+            shared,
+            flusher: Some(flusher),
+            flushed_at_start,
+        })
+    }
+
+    fn run_flusher(
+        writer: &IndexWriter<D, C, MS, MP>,
+        shared: &BulkShared,
+        flush_trigger_bytes: u64,
+    ) -> Result<()> {
+        loop {
+            {
+                let mut state = shared.state.lock()?;
+                while !state.finished
+                    && !state.flush_requested
+                    && writer.doc_writer().net_bytes() <= flush_trigger_bytes
+                {
+                    state = shared.cond.wait_timeout(state, RECHECK_INTERVAL)?.0;
+                }
+                if state.finished {
+                    return Ok(());
+                }
+                state.flush_requested = false;
+            }
+            while writer.doc_writer().net_bytes() > flush_trigger_bytes {
+                match writer.flush_next_buffer() {
+                    Ok(true) => shared.cond.notify_all(),
+                    Ok(false) => break,
+                    Err(e) => {
+                        error!("bulk session flush failed by '{:?}'", e);
+                        shared.state.lock()?.flusher_failed = true;
+                        shared.cond.notify_all();
+                        return Err(e);
+                    }
+                }
+            }
+            shared.cond.notify_all();
+        }
+    }
+
+    fn has_room(&self) -> bool {
+        let doc_writer = self.writer.doc_writer();
+        doc_writer.net_bytes() <= self.config.max_buffered_bytes
+            && doc_writer.num_flushing_writers() < self.config.max_flushing_segments
+    }
+
+    fn check_flusher(&self, state: &MutexGuard<BulkState>) -> Result<()> {
+        if state.flusher_failed {
+            bail!(IllegalState(
+                "the bulk session flusher failed, see BulkSession::finish".into()
+            ));
+        }
+        Ok(())
+    }
+
+    /// Adds a document, blocking until the buffered documents use less RAM
+    /// than the session allows.
+    pub fn add<F: Fieldable>(&self, doc: Vec<F>) -> Result<u64> {
+        {
+            let mut state = self.shared.state.lock()?;
+            let mut blocked_since = None;
+            loop {
+                self.check_flusher(&state)?;
+                if self.has_room() {
+                    break;
+                }
+                if blocked_since.is_none() {
+                    blocked_since = Some(Instant::now());
+                }
+                state.flush_requested = true;
+                self.shared.cond.notify_all();
+                state = self.shared.cond.wait_timeout(state, RECHECK_INTERVAL)?.0;
+            }
+            if let Some(since) = blocked_since {
+                state.blocked_time += since.elapsed();
+            }
+        }
+        self.do_add(doc)
+    }
+
+    /// Adds a document unless the buffered documents use too much RAM, in
+    /// which case the document is handed back.
+    pub fn try_add<F: Fieldable>(&self, doc: Vec<F>) -> Result<TryAdd<F>> {
+        {
+            let mut state = self.shared.state.lock()?;
+            self.check_flusher(&state)?;
+            if !self.has_room() {
+                state.flush_requested = true;
+                self.shared.cond.notify_all();
+                return Ok(TryAdd::WouldBlock(doc));
+            }
+        }
+        Ok(TryAdd::Added(self.do_add(doc)?))
+    }
+
+    fn do_add<F: Fieldable>(&self, doc: Vec<F>) -> Result<u64> {
+        let seq_no = self.writer.add_document(doc)?;
+        let buffered_bytes = self.writer.doc_writer().net_bytes();
+        let mut state = self.shared.state.lock()?;
+        state.docs_indexed += 1;
+        state.peak_buffered_bytes = max(state.peak_buffered_bytes, buffered_bytes);
+        if buffered_bytes > self.config.flush_trigger_bytes && !state.flush_requested {
+            state.flush_requested = true;
+            self.shared.cond.notify_all();
+        }
+        Ok(seq_no)
+    }
+
+    /// Stops the flusher and flushes all the buffered documents, the caller
+    /// still has to commit them.
+    pub fn finish(mut self) -> Result<BulkSummary> {
+        self.stop_flusher()?;
+        self.writer.flush()?;
+
+        let state = self.shared.state.lock()?;
+        let (segments, bytes) = self.writer.flushed_segment_stats();
+        Ok(BulkSummary {
+            docs_indexed: state.docs_indexed,
+            segments_created: segments - self.flushed_at_start.0,
+            bytes_written: bytes - self.flushed_at_start.1,
+            blocked_time: state.blocked_time,
+            peak_buffered_bytes: state.peak_buffered_bytes,
+        })
+    }
+
+    fn stop_flusher(&mut self) -> Result<()> {
+        if let Some(flusher) = self.flusher.take() {
+            self.shared.state.lock()?.finished = true;
+            self.shared.cond.notify_all();
+            match flusher.join() {
+                Ok(res) => res?,
+                Err(e) => bail!(IllegalState(format!(
+                    "bulk session flusher panicked: {:?}",
+                    e
+                ))),
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<D, C, MS, MP> Drop for BulkSession<D, C, MS, MP>
+where
+    D: Directory + Send + Sync + 'static,
+    C: Codec,
+    MS: MergeScheduler,
+    MP: MergePolicy,
+{
+    fn drop(&mut self) {
+        if let Err(e) = self.stop_flusher() {
+            error!("stop bulk session flusher failed by '{:?}'", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::doc::{Field, FieldType};
+    use core::index::index_writer_config::IndexWriterConfig;
+    use core::index::tests::TestReader;
+    use core::index::{IndexOptions, IndexReader, Term};
+    use core::search::searcher::{DefaultIndexSearcher, IndexSearcher};
+    use core::search::term_query::TermQuery;
+    use core::store::RAMDirectory;
+    use core::util::VariantValue;
+
+    fn doc(id: &str, body: String) -> Vec<Field> {
+        let mut id_type = FieldType::default();
+        id_type.tokenized = false;
+        id_type.index_options = IndexOptions::Docs;
+        let mut body_type = FieldType::default();
+        body_type.index_options = IndexOptions::DocsAndFreqsAndPositions;
+        vec![
+            Field::new(
+                "id".to_string(),
+                id_type,
+                Some(VariantValue::VString(id.to_string())),
+                None,
+            ),
+            Field::new(
+                "body".to_string(),
+                body_type,
+                Some(VariantValue::VString(body)),
+                None,
+            ),
+        ]
+    }
+
+    fn count(reader: TestReader, field: &str, value: &str) -> i32 {
+        let searcher = DefaultIndexSearcher::new(Arc::new(reader));
+        let term = Term::new(field.to_string(), value.as_bytes().to_vec());
+        searcher.count(&TermQuery::new(term, 1.0, None)).unwrap()
+    }
+
+    #[test]
+    fn test_bounded_buffered_bytes_with_four_producers() {
+        let dir = Arc::new(RAMDirectory::new());
+        // the default 16MB RAM buffer of the writer would never flush here
+        let writer =
+            IndexWriter::new(Arc::clone(&dir), Arc::new(IndexWriterConfig::default())).unwrap();
+        let max_buffered_bytes = 1024 * 1024;
+        let config = BulkSessionConfig::new(max_buffered_bytes);
+        let session = Arc::new(writer.bulk_session(config).unwrap());
+
+        let threads: Vec<_> = (0..4)
+            .map(|t| {
+                let session = Arc::clone(&session);
+                thread::spawn(move || {
+                    for i in 0..2_000 {
+                        let words: Vec<String> = (0..20)
+                            .map(|w| format!("w{}", (i * 31 + w) % 5_000))
+                            .collect();
+                        let body = format!("producer{} {}", t, words.join(" "));
+                        session.add(doc(&format!("{}_{}", t, i), body)).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for t in threads {
+            t.join().unwrap();
+        }
+
+        let session = Arc::try_unwrap(session).ok().unwrap();
+        let summary = session.finish().unwrap();
+        assert_eq!(summary.docs_indexed, 8_000);
+        assert!(summary.segments_created > 4, "{:?}", summary);
+        assert!(summary.bytes_written > 0);
+        // a document may be added by every producer past the limit
+        assert!(
+            summary.peak_buffered_bytes <= 2 * max_buffered_bytes,
+            "{:?}",
+            summary
+        );
+        assert_eq!(writer.doc_writer().net_bytes(), 0);
+
+        writer.commit().unwrap();
+        writer.close().unwrap();
+        let reader = TestReader::open(dir).unwrap();
+        assert_eq!(reader.num_docs(), 8_000);
+        assert_eq!(count(reader, "body", "producer3"), 2_000);
+    }
+
+    #[test]
+    fn test_try_add_hands_back_documents() {
+        let dir = Arc::new(RAMDirectory::new());
+        let writer =
+            IndexWriter::new(Arc::clone(&dir), Arc::new(IndexWriterConfig::default())).unwrap();
+        // any buffered document exceeds the limit
+        let session = writer.bulk_session(BulkSessionConfig::new(1)).unwrap();
+
+        let mut would_block = 0;
+        for i in 0..50 {
+            let mut doc = doc(&i.to_string(), format!("body {}", i));
+            loop {
+                match session.try_add(doc).unwrap() {
+                    TryAdd::Added(_) => break,
+                    TryAdd::WouldBlock(d) => {
+                        would_block += 1;
+                        doc = d;
+                        thread::sleep(Duration::from_millis(1));
+                    }
+                }
+            }
+        }
+        assert!(would_block > 0);
+
+        let summary = session.finish().unwrap();
+        assert_eq!(summary.docs_indexed, 50);
+        assert_eq!(summary.blocked_time, Duration::default());
+        writer.commit().unwrap();
+        writer.close().unwrap();
+        let reader = TestReader::open(dir).unwrap();
+        assert_eq!(count(reader, "body", "body"), 50);
+    }
+}
@@ -142,3 +142,63 @@ impl io::Read for ByteSliceReader {
 }
 
 impl DataInput for ByteSliceReader {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::util::byte_block_pool::DirectAllocator;
+    use core::util::tests::seeded_rng;
+
+    use rand::Rng;
+    use std::io::Read;
+
+    // writes a byte at the offset `upto` of a slice the way the terms hash
+    // does, moving to a new slice at the end of the current one
+    fn write_byte(pool: &mut ByteBlockPool, upto: &mut usize, b: u8) {
+        let mut buffer_index = *upto >> ByteBlockPool::BYTE_BLOCK_SHIFT;
+        let mut offset = *upto & ByteBlockPool::BYTE_BLOCK_MASK;
+        if pool.buffers[buffer_index][offset] != 0 {
+            // End of slice; allocate a new one
+            offset = pool.alloc_slice(buffer_index, offset);
+            buffer_index = pool.buffer_upto as usize;
+            *upto = (offset as isize + pool.byte_offset) as usize;
+        }
+        pool.buffers[buffer_index][offset] = b;
+        *upto += 1;
+    }
+
+    #[test]
+    fn test_interleaved_slices() {
+        let mut rng = seeded_rng();
+        let mut pool = ByteBlockPool::new(Box::new(DirectAllocator::default()));
+        for &num_streams in &[1, 3, 50, 1000] {
+            let mut starts = Vec::with_capacity(num_streams);
+            let mut ends = Vec::with_capacity(num_streams);
+            let mut values = vec![Vec::new(); num_streams];
+            for _ in 0..num_streams {
+                let upto = pool.new_slice(ByteBlockPool::FIRST_LEVEL_SIZE);
+                let start = (upto as isize + pool.byte_offset) as usize;
+                starts.push(start);
+                ends.push(start);
+            }
+            for _ in 0..num_streams * 300 {
+                let stream = rng.gen_range(0, num_streams);
+                let b: u8 = rng.gen();
+                write_byte(&mut pool, &mut ends[stream], b);
+                values[stream].push(b);
+            }
+
+            let mut reader = ByteSliceReader::default();
+            for stream in 0..num_streams {
+                reader.init(&pool, starts[stream], ends[stream]);
+                let mut read = vec![0u8; values[stream].len()];
+                reader.read_exact(&mut read).unwrap();
+                assert_eq!(read, values[stream]);
+                assert!(reader.eof());
+            }
+
+            // the slices rely on the buffers being zero filled
+            pool.reset(true, true);
+        }
+    }
+}
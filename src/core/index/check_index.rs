@@ -0,0 +1,825 @@
+// Copyright 2019 Zhizhesihai (Beijing) Technology Limited.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Basic tool to check the health of an index, like Lucene's `CheckIndex`.
+//!
+//! Every segment of the latest commit is checked on its own: the checksums of
+//! all its files are verified, then (unless only the checksums are asked for)
+//! the segment is opened, which validates the headers of every producer, and
+//! all the terms, postings, norms and doc values are read back and cross
+//! checked against the statistics recorded by the codec.
+//!
+//! With `CheckIndexOptions::exorcise`, a new commit is then written without
+//! the segments having problems, so the rest of the index stays usable. The
+//! documents of these segments are lost, but their files are left on disk.
+
+use core::codec::codec_util::{self, ControlFlow};
+use core::codec::Codec;
+use core::index::INDEX_WRITE_LOCK_NAME;
+use core::index::NO_MORE_ORDS;
+use core::index::{get_segment_file_name, DocValuesType, Fields, LeafReader, NumericDocValues};
+use core::index::{BinaryDocValues, SortedDocValues, SortedNumericDocValues, SortedSetDocValues};
+use core::index::{SegmentCommitInfo, SegmentInfos, SegmentReader, TermIterator, Terms};
+use core::search::posting_iterator::PostingIteratorFlags;
+use core::search::{DocIterator, NO_MORE_DOCS};
+use core::store::{Directory, IOContext};
+use core::util::bit_set::{BitSet, FixedBitSet, ImmutableBitSet};
+use core::util::DocId;
+
+use error::ErrorKind::{Cancelled, CorruptIndex, IllegalState};
+use error::Result;
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Options of `check_index`.
+#[derive(Clone, Debug, Default)]
+pub struct CheckIndexOptions {
+    /// Only verify the checksums of the segment files (fast), instead of also
+    /// decoding and enumerating their content (slow).
+    pub checksums_only: bool,
+    /// Record the `Terms::stats` of every field in the segment status.
+    pub field_stats: bool,
+    /// Write a new segments_N without the segments having problems. Refused if
+    /// the index is locked for writing.
+    pub exorcise: bool,
+}
+
+/// Result of `check_index`.
+#[derive(Debug)]
+pub struct CheckIndexStatus {
+    /// The commit that was checked.
+    pub segments_file_name: String,
+    pub segments: Vec<CheckIndexSegmentStatus>,
+    /// The commit written without the segments having problems, if exorcised.
+    pub exorcised_segments_file_name: Option<String>,
+    /// Number of live documents of the exorcised segments.
+    pub lost_doc_count: i32,
+    pub elapsed: Duration,
+}
+
+impl CheckIndexStatus {
+    /// Returns true if no problem was found in any segment.
+    pub fn is_clean(&self) -> bool {
+        self.segments.iter().all(|s| s.is_clean())
+    }
+}
+
+impl fmt::Display for CheckIndexStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "{}: {} segments checked in {:?}",
+            self.segments_file_name,
+            self.segments.len(),
+            self.elapsed
+        )?;
+        for segment in &self.segments {
+            writeln!(f, "  {}", segment)?;
+        }
+        if let Some(ref name) = self.exorcised_segments_file_name {
+            writeln!(
+                f,
+                "Wrote {} without the broken segments, {} documents were lost.",
+                name, self.lost_doc_count
+            )?;
+        }
+        if self.is_clean() {
+            write!(f, "No problems were detected with this index.")
+        } else {
+            write!(f, "WARNING: problems were detected with this index!")
+        }
+    }
+}
+
+/// Result of checking one segment.
+#[derive(Debug)]
+pub struct CheckIndexSegmentStatus {
+    pub name: String,
+    pub max_doc: i32,
+    pub del_count: i32,
+    pub num_files: usize,
+    /// Number of terms enumerated over all the fields, 0 if only the
+    /// checksums were verified.
+    pub term_count: u64,
+    /// Problems found per field, the segment could still be opened.
+    pub fields_with_issues: BTreeMap<String, Vec<String>>,
+    /// `Terms::stats` per field, if asked for.
+    pub field_stats: BTreeMap<String, String>,
+    /// Error that stopped checking the segment, e.g. a checksum mismatch or a
+    /// producer that couldn't be opened.
+    pub error: Option<String>,
+    pub elapsed: Duration,
+}
+
+impl CheckIndexSegmentStatus {
+    pub fn is_clean(&self) -> bool {
+        self.error.is_none() && self.fields_with_issues.is_empty()
+    }
+
+    fn add_issue(&mut self, field: &str, issue: String) {
+        self.fields_with_issues
+            .entry(field.to_string())
+            .or_insert_with(Vec::new)
+            .push(issue);
+    }
+}
+
+impl fmt::Display for CheckIndexSegmentStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}: max_doc={}, del_count={}, files={}, terms={}, took {:?}",
+            self.name, self.max_doc, self.del_count, self.num_files, self.term_count, self.elapsed
+        )?;
+        if let Some(ref error) = self.error {
+            write!(f, ", FAILED: {}", error)?;
+        }
+        for (field, issues) in &self.fields_with_issues {
+            write!(f, ", field '{}': {}", field, issues.join("; "))?;
+        }
+        Ok(())
+    }
+}
+
+/// Checks every segment of the latest commit of `directory`.
+///
+/// Problems found in a segment are reported in its status and don't stop the
+/// check, an error is only returned if the commit itself can't be read, or
+/// if it can't be exorcised.
+pub fn check_index<D: Directory + 'static, C: Codec>(
+    directory: &Arc<D>,
+    options: &CheckIndexOptions,
+) -> Result<CheckIndexStatus> {
+    check_index_with::<D, C>(directory, options, &mut |_, _| ControlFlow::Continue(()))
+}
+
+/// Same as `check_index`, but reports the progress of the checksum
+/// verification to `progress`, with the bytes verified so far and the total
+/// length of the files of the commit, see
+/// `codec_util::checksum_entire_file_with`.
+///
+/// Fails with `Cancelled` as soon as `progress` returns `ControlFlow::Break`,
+/// without checking the remaining segments and without exorcising any.
+pub fn check_index_with<D: Directory + 'static, C: Codec>(
+    directory: &Arc<D>,
+    options: &CheckIndexOptions,
+    progress: &mut dyn FnMut(u64, u64) -> ControlFlow<()>,
+) -> Result<CheckIndexStatus> {
+    let start = Instant::now();
+    // held until the new commit is written
+    let _write_lock = if options.exorcise {
+        match directory.obtain_lock(INDEX_WRITE_LOCK_NAME) {
+            Ok(lock) => Some(lock),
+            Err(e) => bail!(IllegalState(format!(
+                "refusing to exorcise segments of an index locked for writing: {}",
+                e
+            ))),
+        }
+    } else {
+        None
+    };
+
+    let segments_file_name = get_segment_file_name(directory.as_ref())?;
+    let infos: SegmentInfos<D, C> = SegmentInfos::read_commit(directory, &segments_file_name)?;
+
+    // the missing files are reported by the check of their segment
+    let mut total = 0;
+    for si in &infos.segments {
+        for file in si.files() {
+            total += si.info.directory.file_length(&file).unwrap_or(0) as u64;
+        }
+    }
+    let mut checksums = ChecksumProgress {
+        progress,
+        done: 0,
+        total,
+    };
+    let mut segments = Vec::with_capacity(infos.segments.len());
+    for si in &infos.segments {
+        segments.push(check_segment(si, options, &mut checksums)?);
+    }
+    let mut status = CheckIndexStatus {
+        segments_file_name,
+        segments,
+        exorcised_segments_file_name: None,
+        lost_doc_count: 0,
+        elapsed: Duration::default(),
+    };
+    if options.exorcise && !status.is_clean() {
+        exorcise(directory, infos, &mut status)?;
+    }
+    status.elapsed = start.elapsed();
+    Ok(status)
+}
+
+fn exorcise<D: Directory + 'static, C: Codec>(
+    directory: &Arc<D>,
+    mut infos: SegmentInfos<D, C>,
+    status: &mut CheckIndexStatus,
+) -> Result<()> {
+    let broken: Vec<String> = status
+        .segments
+        .iter()
+        .filter(|s| !s.is_clean())
+        .map(|s| s.name.clone())
+        .collect();
+    let lost_doc_count: i32 = status
+        .segments
+        .iter()
+        .filter(|s| !s.is_clean())
+        .map(|s| s.max_doc - s.del_count)
+        .sum();
+    warn!(
+        "exorcising segments {:?} of {}: {} documents will be lost",
+        broken, status.segments_file_name, lost_doc_count
+    );
+
+    // the next generation is written, with the same counter and user data
+    infos.segments.retain(|si| !broken.contains(&si.info.name));
+    infos.changed();
+    infos.prepare_commit(directory.as_ref())?;
+    let segments_file_name = infos.finish_commit(directory.as_ref())?;
+    warn!(
+        "wrote {}, {} documents were lost",
+        segments_file_name, lost_doc_count
+    );
+
+    status.exorcised_segments_file_name = Some(segments_file_name);
+    status.lost_doc_count = lost_doc_count;
+    Ok(())
+}
+
+// the progress of the checksum verification over all the files of a commit
+struct ChecksumProgress<'a> {
+    progress: &'a mut dyn FnMut(u64, u64) -> ControlFlow<()>,
+    // the length of the files already verified
+    done: u64,
+    total: u64,
+}
+
+// checks a segment, only failing if the check was cancelled, the problems of
+// the segment are reported in its status
+fn check_segment<D: Directory + 'static, C: Codec>(
+    si: &Arc<SegmentCommitInfo<D, C>>,
+    options: &CheckIndexOptions,
+    checksums: &mut ChecksumProgress,
+) -> Result<CheckIndexSegmentStatus> {
+    let start = Instant::now();
+    let mut files: Vec<String> = si.files().into_iter().collect();
+    files.sort();
+    let mut status = CheckIndexSegmentStatus {
+        name: si.info.name.clone(),
+        max_doc: si.info.max_doc(),
+        del_count: si.del_count(),
+        num_files: files.len(),
+        term_count: 0,
+        fields_with_issues: BTreeMap::new(),
+        field_stats: BTreeMap::new(),
+        error: None,
+        elapsed: Duration::default(),
+    };
+
+    let res = check_checksums(si.info.directory.as_ref(), &files, checksums).and_then(|()| {
+        if options.checksums_only {
+            return Ok(());
+        }
+        let reader = SegmentReader::open(si, &IOContext::READ)?;
+        check_postings(&reader, options, &mut status)?;
+        check_norms_and_doc_values(&reader, &mut status)
+    });
+    if let Err(e) = res {
+        if let Cancelled(_) = e.kind() {
+            return Err(e);
+        }
+        status.error = Some(e.to_string());
+    }
+    status.elapsed = start.elapsed();
+    Ok(status)
+}
+
+fn check_checksums<D: Directory>(
+    directory: &D,
+    files: &[String],
+    checksums: &mut ChecksumProgress,
+) -> Result<()> {
+    for file in files {
+        let input = directory.open_input(file, &IOContext::READ_ONCE)?;
+        let (done, total) = (checksums.done, checksums.total);
+        let progress = &mut *checksums.progress;
+        let res = codec_util::checksum_entire_file_with(input.as_ref(), &mut |read, _| {
+            progress(done + read, total)
+        });
+        if let Err(e) = res {
+            if let Cancelled(_) = e.kind() {
+                return Err(e);
+            }
+            bail!(CorruptIndex(format!("checksum of {} failed: {}", file, e)));
+        }
+        checksums.done += input.len();
+    }
+    Ok(())
+}
+
+fn check_postings<D: Directory + 'static, C: Codec>(
+    reader: &SegmentReader<D, C>,
+    options: &CheckIndexOptions,
+    status: &mut CheckIndexSegmentStatus,
+) -> Result<()> {
+    let max_doc = reader.max_doc();
+    let fields = reader.postings_reader()?;
+    for field in fields.fields() {
+        let terms = match fields.terms(&field)? {
+            Some(terms) => terms,
+            None => continue,
+        };
+        if options.field_stats {
+            status.field_stats.insert(field.clone(), terms.stats()?);
+        }
+
+        let has_freqs = terms.has_freqs()?;
+        let flags = if has_freqs {
+            PostingIteratorFlags::FREQS
+        } else {
+            PostingIteratorFlags::NONE
+        };
+        let mut docs_seen = FixedBitSet::new(max_doc as usize);
+        let mut term_count = 0i64;
+        let mut sum_doc_freq = 0i64;
+        let mut sum_total_term_freq = 0i64;
+        let mut last_term: Option<Vec<u8>> = None;
+
+        let mut iter = terms.iterator()?;
+        'terms: while let Some(term) = iter.next()? {
+            if let Some(ref last) = last_term {
+                if *last >= term {
+                    status.add_issue(&field, format!("terms out of order: {:?}", term));
+                    break;
+                }
+            }
+            term_count += 1;
+            let doc_freq = iter.doc_freq()?;
+            let total_term_freq = iter.total_term_freq()?;
+
+            let mut postings = iter.postings_with_flags(flags)?;
+            let mut docs = 0;
+            let mut freqs = 0i64;
+            let mut last_doc: DocId = -1;
+            loop {
+                let doc = postings.next()?;
+                if doc == NO_MORE_DOCS {
+                    break;
+                }
+                if doc <= last_doc || doc >= max_doc {
+                    status.add_issue(
+                        &field,
+                        format!("term {:?}: doc {} out of order or bounds", term, doc),
+                    );
+                    break 'terms;
+                }
+                if has_freqs {
+                    let freq = postings.freq()?;
+                    if freq <= 0 {
+                        status.add_issue(&field, format!("term {:?}: freq {}", term, freq));
+                        break 'terms;
+                    }
+                    freqs += i64::from(freq);
+                }
+                docs_seen.set(doc as usize);
+                docs += 1;
+                last_doc = doc;
+            }
+
+            if docs != doc_freq {
+                status.add_issue(
+                    &field,
+                    format!("term {:?}: doc_freq={} but {} docs", term, doc_freq, docs),
+                );
+            }
+            if has_freqs && freqs != total_term_freq {
+                status.add_issue(
+                    &field,
+                    format!(
+                        "term {:?}: total_term_freq={} but freqs sum to {}",
+                        term, total_term_freq, freqs
+                    ),
+                );
+            }
+            sum_doc_freq += i64::from(doc_freq);
+            sum_total_term_freq += if has_freqs {
+                total_term_freq
+            } else {
+                i64::from(doc_freq)
+            };
+            last_term = Some(term);
+        }
+        status.term_count += term_count as u64;
+        if status.fields_with_issues.contains_key(&field) {
+            // the sums are meaningless
+            continue;
+        }
+
+        let size = terms.size()?;
+        if size != -1 && size != term_count {
+            status.add_issue(&field, format!("size={} but {} terms", size, term_count));
+        }
+        let expected = terms.sum_doc_freq()?;
+        if expected != -1 && expected != sum_doc_freq {
+            status.add_issue(
+                &field,
+                format!(
+                    "sum_doc_freq={} but doc_freqs sum to {}",
+                    expected, sum_doc_freq
+                ),
+            );
+        }
+        let expected = terms.sum_total_term_freq()?;
+        if expected != -1 && expected != sum_total_term_freq {
+            status.add_issue(
+                &field,
+                format!(
+                    "sum_total_term_freq={} but total_term_freqs sum to {}",
+                    expected, sum_total_term_freq
+                ),
+            );
+        }
+        let expected = terms.doc_count()?;
+        if expected != -1 && expected as usize != docs_seen.cardinality() {
+            status.add_issue(
+                &field,
+                format!(
+                    "doc_count={} but {} docs have terms",
+                    expected,
+                    docs_seen.cardinality()
+                ),
+            );
+        }
+        if i64::from(expected) > sum_doc_freq {
+            status.add_issue(
+                &field,
+                format!("doc_count={} > sum_doc_freq={}", expected, sum_doc_freq),
+            );
+        }
+    }
+    Ok(())
+}
+
+fn check_norms_and_doc_values<D: Directory + 'static, C: Codec>(
+    reader: &SegmentReader<D, C>,
+    status: &mut CheckIndexSegmentStatus,
+) -> Result<()> {
+    let max_doc = reader.max_doc();
+    let mut field_infos: Vec<_> = reader.field_infos().by_name.values().collect();
+    field_infos.sort_by_key(|fi| fi.number);
+
+    for fi in field_infos {
+        let field = fi.name.as_str();
+        if fi.has_norms() {
+            match reader.norm_values(field)? {
+                Some(norms) => {
+                    for doc in 0..max_doc {
+                        norms.get(doc)?;
+                    }
+                }
+                None => status.add_issue(field, "norms are missing".to_string()),
+            }
+        }
+
+        match fi.doc_values_type {
+            DocValuesType::Null => {}
+            DocValuesType::Numeric => {
+                let values = reader.get_numeric_doc_values(field)?;
+                for doc in 0..max_doc {
+                    values.get(doc)?;
+                }
+            }
+            DocValuesType::Binary => {
+                let values = reader.get_binary_doc_values(field)?;
+                for doc in 0..max_doc {
+                    values.get(doc)?;
+                }
+            }
+            DocValuesType::Sorted => {
+                let values = reader.get_sorted_doc_values(field)?;
+                let value_count = values.get_value_count() as i32;
+                for doc in 0..max_doc {
+                    let ord = values.get_ord(doc)?;
+                    if ord < -1 || ord >= value_count {
+                        status.add_issue(field, format!("doc {}: ord {} out of bounds", doc, ord));
+                        break;
+                    }
+                }
+                for ord in 0..value_count {
+                    values.lookup_ord(ord)?;
+                }
+            }
+            DocValuesType::SortedNumeric => {
+                let values = reader.get_sorted_numeric_doc_values(field)?;
+                for doc in 0..max_doc {
+                    let ctx = values.set_document(None, doc)?;
+                    let mut last = ::std::i64::MIN;
+                    for i in 0..values.count(&ctx) {
+                        let value = values.value_at(&ctx, i)?;
+                        if value < last {
+                            status.add_issue(field, format!("doc {}: values out of order", doc));
+                            break;
+                        }
+                        last = value;
+                    }
+                }
+            }
+            DocValuesType::SortedSet => {
+                let values = reader.get_sorted_set_doc_values(field)?;
+                let value_count = values.get_value_count() as i64;
+                'docs: for doc in 0..max_doc {
+                    let mut ctx = values.set_document(doc)?;
+                    let mut last = -1;
+                    loop {
+                        let ord = values.next_ord(&mut ctx)?;
+                        if ord == NO_MORE_ORDS {
+                            break;
+                        }
+                        if ord <= last || ord >= value_count {
+                            status.add_issue(
+                                field,
+                                format!("doc {}: ord {} out of order or bounds", doc, ord),
+                            );
+                            break 'docs;
+                        }
+                        last = ord;
+                    }
+                }
+                for ord in 0..value_count {
+                    values.lookup_ord(ord)?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::codec::CodecEnum;
+    use core::doc::{Field, FieldType, NumericDocValuesField};
+    use core::index::index_writer_config::IndexWriterConfig;
+    use core::index::merge_policy::TieredMergePolicy;
+    use core::index::merge_scheduler::SerialMergeScheduler;
+    use core::index::{Fieldable, IndexOptions, IndexReader, IndexWriter};
+    use core::index::{StandardDirectoryReader, Term};
+    use core::search::searcher::{DefaultIndexSearcher, IndexSearcher};
+    use core::search::term_query::TermQuery;
+    use core::store::{DataInput, DataOutput, IndexInput, RAMDirectory};
+    use core::util::VariantValue;
+
+    use std::collections::HashMap;
+
+    fn keyword(name: &str, value: &str) -> Box<dyn Fieldable> {
+        let mut field_type = FieldType::default();
+        field_type.tokenized = false;
+        field_type.index_options = IndexOptions::DocsAndFreqs;
+        Box::new(Field::new(
+            name.to_string(),
+            field_type,
+            Some(VariantValue::VString(value.to_string())),
+            None,
+        ))
+    }
+
+    fn build_index() -> Arc<RAMDirectory> {
+        let dir = Arc::new(RAMDirectory::new());
+        let mut conf = IndexWriterConfig::default();
+        conf.use_compound_file = false;
+        let writer = IndexWriter::new(Arc::clone(&dir), Arc::new(conf)).unwrap();
+        for i in 0..10 {
+            let doc = vec![
+                keyword("id", &i.to_string()),
+                keyword("parity", if i % 2 == 0 { "even" } else { "odd" }),
+                keyword("parity", "any"),
+                Box::new(NumericDocValuesField::new("rank", i)),
+            ];
+            writer.add_document(doc).unwrap();
+        }
+        writer.commit().unwrap();
+        writer.close().unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_check_healthy_index() {
+        let dir = build_index();
+        let options = CheckIndexOptions {
+            checksums_only: false,
+            field_stats: true,
+            exorcise: false,
+        };
+        let status = check_index::<_, CodecEnum>(&dir, &options).unwrap();
+        assert!(status.is_clean(), "{}", status);
+        assert_eq!(status.segments.len(), 1);
+        let segment = &status.segments[0];
+        assert_eq!(segment.max_doc, 10);
+        // 10 ids plus even, odd and any
+        assert_eq!(segment.term_count, 13);
+        assert!(segment.field_stats.contains_key("id"));
+        assert!(segment.field_stats.contains_key("parity"));
+
+        let options = CheckIndexOptions {
+            checksums_only: true,
+            field_stats: false,
+            exorcise: false,
+        };
+        let status = check_index::<_, CodecEnum>(&dir, &options).unwrap();
+        assert!(status.is_clean(), "{}", status);
+        assert_eq!(status.segments[0].term_count, 0);
+        assert!(status.segments[0].field_stats.is_empty());
+    }
+
+    #[test]
+    fn test_check_truncated_terms_file() {
+        let dir = build_index();
+        let tim = dir
+            .list_all()
+            .unwrap()
+            .into_iter()
+            .find(|f| f.ends_with(".tim"))
+            .unwrap();
+        let mut input = dir.open_input(&tim, &IOContext::READ).unwrap();
+        let len = input.len() as usize - 8;
+        let mut data = vec![0u8; len];
+        input.read_bytes(&mut data, 0, len).unwrap();
+        drop(input);
+        dir.delete_file(&tim).unwrap();
+        let mut output = dir.create_output(&tim, &IOContext::Default).unwrap();
+        output.write_bytes(&data, 0, data.len()).unwrap();
+        drop(output);
+
+        for checksums_only in &[true, false] {
+            let options = CheckIndexOptions {
+                checksums_only: *checksums_only,
+                field_stats: false,
+                exorcise: false,
+            };
+            let status = check_index::<_, CodecEnum>(&dir, &options).unwrap();
+            assert!(!status.is_clean());
+            let error = status.segments[0].error.as_ref().unwrap();
+            assert!(error.contains(&tim), "{}", error);
+            assert!(status.to_string().contains("FAILED"));
+        }
+    }
+
+    // corrupts the terms file of `segment` without changing its length
+    fn corrupt_terms(dir: &RAMDirectory, segment: &str) {
+        let tim = dir
+            .list_all()
+            .unwrap()
+            .into_iter()
+            .find(|f| {
+                f.ends_with(".tim")
+                    && (f.starts_with(&format!("{}_", segment))
+                        || f.starts_with(&format!("{}.", segment)))
+            })
+            .unwrap();
+        let mut input = dir.open_input(&tim, &IOContext::READ).unwrap();
+        let len = input.len() as usize;
+        let mut data = vec![0u8; len];
+        input.read_bytes(&mut data, 0, len).unwrap();
+        drop(input);
+        data[len / 2] ^= 0xFF;
+        dir.delete_file(&tim).unwrap();
+        let mut output = dir.create_output(&tim, &IOContext::Default).unwrap();
+        output.write_bytes(&data, 0, len).unwrap();
+    }
+
+    #[test]
+    fn test_exorcise_corrupt_segment() {
+        let dir = Arc::new(RAMDirectory::new());
+        let mut conf = IndexWriterConfig::default();
+        conf.use_compound_file = false;
+        let conf = Arc::new(conf);
+        let writer = IndexWriter::new(Arc::clone(&dir), Arc::clone(&conf)).unwrap();
+        let mut user_data = HashMap::new();
+        user_data.insert("source".to_string(), "test".to_string());
+        writer.set_live_commit_data(user_data.clone()).unwrap();
+        for (i, num_docs) in [2, 3, 4].iter().enumerate() {
+            for _ in 0..*num_docs {
+                writer
+                    .add_document(vec![keyword("segment", &i.to_string())])
+                    .unwrap();
+            }
+            writer.commit().unwrap();
+        }
+
+        // refused while a writer holds the lock
+        let options = CheckIndexOptions {
+            exorcise: true,
+            ..Default::default()
+        };
+        assert!(check_index::<_, CodecEnum>(&dir, &options).is_err());
+        writer.close().unwrap();
+
+        let infos: SegmentInfos<RAMDirectory, CodecEnum> =
+            SegmentInfos::read_latest_commit(&dir).unwrap();
+        assert_eq!(infos.segments.len(), 3);
+        assert_eq!(infos.user_data, user_data);
+        let corrupt = infos.segments[1].info.name.clone();
+        let generation = infos.generation;
+        let counter = infos.counter;
+        corrupt_terms(&dir, &corrupt);
+        let files_before = dir.list_all().unwrap();
+
+        let status = check_index::<_, CodecEnum>(&dir, &options).unwrap();
+        assert!(!status.is_clean());
+        assert_eq!(status.lost_doc_count, 3);
+        let exorcised = status.exorcised_segments_file_name.unwrap();
+        assert_eq!(exorcised, format!("segments_{}", generation + 1));
+
+        let infos: SegmentInfos<RAMDirectory, CodecEnum> =
+            SegmentInfos::read_latest_commit(&dir).unwrap();
+        assert_eq!(infos.generation, generation + 1);
+        // the new commit keeps the counter and the user data
+        assert_eq!(infos.counter, counter);
+        assert_eq!(infos.user_data, user_data);
+        let names: Vec<&str> = infos
+            .segments
+            .iter()
+            .map(|s| s.info.name.as_str())
+            .collect();
+        assert_eq!(names.len(), 2);
+        assert!(!names.contains(&corrupt.as_str()));
+        // the files of the corrupt segment are still there
+        let files_after = dir.list_all().unwrap();
+        for file in &files_before {
+            assert!(files_after.contains(file), "{} was deleted", file);
+        }
+
+        let reader =
+            StandardDirectoryReader::<_, CodecEnum, SerialMergeScheduler, TieredMergePolicy>::open(
+                Arc::clone(&dir),
+            )
+            .unwrap();
+        assert_eq!(reader.num_docs(), 6);
+        let searcher = DefaultIndexSearcher::new(Arc::new(reader));
+        for (segment, count) in &[("0", 2), ("1", 0), ("2", 4)] {
+            let term = Term::new("segment".to_string(), segment.as_bytes().to_vec());
+            let query = TermQuery::new(term, 1.0, None);
+            assert_eq!(searcher.count(&query).unwrap(), *count);
+        }
+
+        // the exorcised index is clean
+        let status = check_index::<_, CodecEnum>(&dir, &options).unwrap();
+        assert!(status.is_clean(), "{}", status);
+        assert!(status.exorcised_segments_file_name.is_none());
+    }
+
+    #[test]
+    fn test_check_index_progress_and_cancel() {
+        let dir = build_index();
+        let options = CheckIndexOptions::default();
+        let total: u64 = dir
+            .list_all()
+            .unwrap()
+            .iter()
+            .filter(|f| !f.starts_with("segments") && f.as_str() != INDEX_WRITE_LOCK_NAME)
+            .map(|f| dir.file_length(f).unwrap() as u64)
+            .sum();
+
+        let mut reports = vec![];
+        let status = check_index_with::<_, CodecEnum>(&dir, &options, &mut |done, len| {
+            reports.push((done, len));
+            ControlFlow::Continue(())
+        })
+        .unwrap();
+        assert!(status.is_clean(), "{}", status);
+        // at least one report per file, the bytes done only grow
+        assert!(reports.len() >= status.segments[0].num_files);
+        assert!(reports.windows(2).all(|w| w[0].0 < w[1].0));
+        assert!(reports.iter().all(|r| r.1 == total));
+        assert_eq!(*reports.last().unwrap(), (total, total));
+
+        // cancelled at the first report, nothing else is read
+        let mut reports = vec![];
+        let err = check_index_with::<_, CodecEnum>(&dir, &options, &mut |done, _| {
+            reports.push(done);
+            ControlFlow::Break(())
+        })
+        .unwrap_err();
+        match err.kind() {
+            Cancelled(_) => {}
+            e => panic!("unexpected error: {:?}", e),
+        }
+        assert_eq!(reports.len(), 1);
+    }
+}
@@ -0,0 +1,701 @@
+// Copyright 2019 Zhizhesihai (Beijing) Technology Limited.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Duplicate document detection at index time.
+//!
+//! The values of the fields listed in `IndexWriterConfig::dedup_fields` are
+//! normalized and hashed into a stable 128-bit content hash, which is added to
+//! every document as the indexed keyword + binary doc values field
+//! `DEDUP_HASH_FIELD`. `IndexWriter::add_document_dedup` looks the hash up in
+//! the current view of the index and applies a `DedupPolicy` when a duplicate
+//! is found.
+//!
+//! The view is an NRT snapshot of the index, plus the documents written by
+//! `add_document_dedup` since the snapshot was opened, kept in memory by their
+//! hash. The snapshot is only reopened, which flushes the writer, when other
+//! writes happened meanwhile or after `MAX_PENDING_DEDUP_DOCS` documents.
+
+use core::codec::Codec;
+use core::doc::{DocumentStoredFieldVisitor, Field, FieldType, NumericDocValuesField};
+use core::index::merge_policy::MergePolicy;
+use core::index::merge_scheduler::MergeScheduler;
+use core::index::StandardDirectoryReader;
+use core::index::{DocValuesType, Fieldable, IndexOptions, IndexReader, IndexWriter, LeafReader};
+use core::index::{Term, TermIterator, Terms};
+use core::search::posting_iterator::PostingIteratorFlags;
+use core::search::{DocIterator, NO_MORE_DOCS};
+use core::store::Directory;
+use core::util::VariantValue;
+
+use error::ErrorKind::IllegalState;
+use error::Result;
+
+use fasthash::murmur3;
+
+use std::collections::HashMap;
+
+/// Name of the field holding the content hash of the dedup fields.
+pub const DEDUP_HASH_FIELD: &str = "_dedup_hash";
+
+/// Name of the numeric doc values field holding the version of documents
+/// added with `DedupPolicy::Version`.
+pub const DEDUP_VERSION_FIELD: &str = "_dedup_version";
+
+/// Length in bytes of the content hash.
+pub const DEDUP_HASH_LENGTH: usize = 16;
+
+/// The documents `add_document_dedup` keeps in memory before it reopens its
+/// snapshot of the index.
+pub const MAX_PENDING_DEDUP_DOCS: usize = 65_536;
+
+type ContentHash = [u8; DEDUP_HASH_LENGTH];
+
+// the normalized values of the stored dedup fields, by field name
+type StoredValues = Vec<(String, Vec<Vec<u8>>)>;
+
+/// What `IndexWriter::add_document_dedup` does when the incoming document is a
+/// duplicate of a live document in the index.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DedupPolicy {
+    /// Keep the existing documents and drop the incoming one.
+    Skip,
+    /// Replace the existing documents with the incoming one.
+    Overwrite,
+    /// Keep the existing documents and add the incoming one with the next version
+    /// in `DEDUP_VERSION_FIELD`.
+    Version,
+}
+
+/// The outcome of `IndexWriter::add_document_dedup`, with the sequence number of
+/// the write operation if any.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DedupResult {
+    /// No duplicate was found and the document was added.
+    Added(u64),
+    /// A duplicate was found and the document was dropped.
+    Skipped,
+    /// The duplicates were deleted and the document was added.
+    Overwritten(u64),
+    /// The document was added as a new version of its duplicates.
+    Versioned(u64, i64),
+}
+
+/// Computes the content hash of the given fields of a document.
+///
+/// Fields are hashed in name order, values of the same field in the order they
+/// appear in the document, so the hash doesn't depend on the field order of
+/// the document. Fields not present in the document hash the same as fields
+/// without values.
+pub fn content_hash<F: Fieldable>(doc: &[F], fields: &[String]) -> ContentHash {
+    let mut buffer = Vec::new();
+    for (name, values) in normalized_fields(doc, fields, false) {
+        buffer.extend_from_slice(name.as_bytes());
+        buffer.push(0u8);
+        buffer.extend_from_slice(&(values.len() as u32).to_be_bytes());
+        for value in values {
+            buffer.extend_from_slice(&(value.len() as u32).to_be_bytes());
+            buffer.extend(value);
+        }
+    }
+    murmur3::hash128(&buffer).to_be_bytes()
+}
+
+/// Returns the normalized values of the given fields, sorted by field name.
+fn normalized_fields<F: Fieldable>(
+    doc: &[F],
+    fields: &[String],
+    stored_only: bool,
+) -> StoredValues {
+    let mut names = fields.to_vec();
+    names.sort();
+    names.dedup();
+    names
+        .into_iter()
+        .map(|name| {
+            let values = doc
+                .iter()
+                .filter(|f| f.name() == name && (!stored_only || f.field_type().stored))
+                .filter_map(|f| f.fields_data().and_then(normalize))
+                .collect();
+            (name, values)
+        })
+        .collect()
+}
+
+/// Normalizes a field value, text is lower cased with the white spaces collapsed,
+/// numbers are tagged by their kind (integer or floating point) so that the same
+/// number hashes the same whatever the width of the type it is stored in.
+fn normalize(value: &VariantValue) -> Option<Vec<u8>> {
+    let mut normalized = Vec::new();
+    match value {
+        VariantValue::VString(s) => {
+            normalized.push(b's');
+            let text: Vec<String> = s.split_whitespace().map(|w| w.to_lowercase()).collect();
+            normalized.extend_from_slice(text.join(" ").as_bytes());
+        }
+        VariantValue::Binary(b) => {
+            normalized.push(b'b');
+            normalized.extend_from_slice(b);
+        }
+        VariantValue::Bool(v) => {
+            normalized.push(b'z');
+            normalized.push(*v as u8);
+        }
+        VariantValue::Char(c) => {
+            normalized.push(b's');
+            normalized.extend(c.to_lowercase().to_string().into_bytes());
+        }
+        VariantValue::Short(v) => integer(&mut normalized, i64::from(*v)),
+        VariantValue::Int(v) => integer(&mut normalized, i64::from(*v)),
+        VariantValue::Long(v) => integer(&mut normalized, *v),
+        VariantValue::Float(v) => float(&mut normalized, f64::from(*v)),
+        VariantValue::Double(v) => float(&mut normalized, *v),
+        VariantValue::Map(_) => {
+            return None;
+        }
+    }
+    Some(normalized)
+}
+
+fn integer(normalized: &mut Vec<u8>, v: i64) {
+    normalized.push(b'i');
+    normalized.extend_from_slice(&v.to_be_bytes());
+}
+
+fn float(normalized: &mut Vec<u8>, v: f64) {
+    normalized.push(b'f');
+    // -0.0 and 0.0 are the same value
+    let v = if v == 0.0 { 0.0 } else { v };
+    normalized.extend_from_slice(&v.to_bits().to_be_bytes());
+}
+
+// Only the fields that are stored for both documents are compared, the hash
+// is trusted for the others.
+fn same_stored_values(a: &StoredValues, b: &StoredValues) -> bool {
+    a.iter().zip(b).all(|(a, b)| {
+        debug_assert_eq!(a.0, b.0);
+        a.1.is_empty() || b.1.is_empty() || a.1 == b.1
+    })
+}
+
+fn hash_field(hash: &[u8]) -> Field {
+    let mut field_type = FieldType::default();
+    field_type.tokenized = false;
+    field_type.omit_norms = true;
+    field_type.index_options = IndexOptions::Docs;
+    field_type.doc_values_type = DocValuesType::Binary;
+    Field::new_bytes(DEDUP_HASH_FIELD.to_string(), hash.to_vec(), field_type)
+}
+
+#[derive(Default)]
+struct Duplicates {
+    // live docs with the same hash and the same stored values
+    confirmed: usize,
+    // live docs with the same hash but different stored values
+    collisions: usize,
+    max_version: Option<i64>,
+}
+
+/// Looks up the live documents having the given content hash, and compares
+/// their stored values of the dedup fields with `expected` to rule out hash
+/// collisions. Dedup fields that aren't stored can't be compared, the hash is
+/// trusted for them.
+fn find_duplicates<R: IndexReader + ?Sized>(
+    reader: &R,
+    hash: &[u8],
+    fields: &[String],
+    expected: &StoredValues,
+) -> Result<Duplicates> {
+    let mut duplicates = Duplicates::default();
+    for leaf in reader.leaves() {
+        let leaf_reader = leaf.reader;
+        let terms = match leaf_reader.terms(DEDUP_HASH_FIELD)? {
+            Some(terms) => terms,
+            None => continue,
+        };
+        let mut iter = terms.iterator()?;
+        if !iter.seek_exact(hash)? {
+            continue;
+        }
+        let mut postings = iter.postings_with_flags(PostingIteratorFlags::NONE)?;
+        let live_docs = leaf_reader.live_docs();
+        let versions = if leaf_reader.field_info(DEDUP_VERSION_FIELD).is_some() {
+            Some(leaf_reader.get_numeric_doc_values(DEDUP_VERSION_FIELD)?)
+        } else {
+            None
+        };
+
+        loop {
+            let doc = postings.next()?;
+            if doc == NO_MORE_DOCS {
+                break;
+            }
+            if !live_docs.get(doc as usize)? {
+                continue;
+            }
+
+            let mut visitor = DocumentStoredFieldVisitor::new(fields);
+            leaf_reader.document(doc, &mut visitor)?;
+            let stored: Vec<Field> = visitor.fields.into_iter().map(|f| f.field).collect();
+            let actual = normalized_fields(&stored, fields, true);
+            if !same_stored_values(&actual, expected) {
+                duplicates.collisions += 1;
+                continue;
+            }
+
+            duplicates.confirmed += 1;
+            if let Some(ref versions) = versions {
+                let version = versions.get(doc)?;
+                duplicates.max_version =
+                    Some(duplicates.max_version.map_or(version, |v| v.max(version)));
+            }
+        }
+    }
+    Ok(duplicates)
+}
+
+struct PendingDoc {
+    values: StoredValues,
+    version: Option<i64>,
+}
+
+#[derive(Default)]
+struct PendingDocs {
+    // the docs of the snapshot with the hash were deleted by an overwrite
+    hides_snapshot: bool,
+    docs: Vec<PendingDoc>,
+}
+
+/// The view of the index `add_document_dedup` looks the duplicates up in, kept
+/// by the writer between the calls.
+pub(crate) struct DedupState<D, C, MS, MP>
+where
+    D: Directory + Send + Sync + 'static,
+    C: Codec,
+    MS: MergeScheduler,
+    MP: MergePolicy,
+{
+    snapshot: Option<StandardDirectoryReader<D, C, MS, MP>>,
+    // the last sequence number of the writer the view covers
+    seq_no: u64,
+    // the docs written since the snapshot was opened
+    pending: HashMap<ContentHash, PendingDocs>,
+    pending_docs: usize,
+}
+
+impl<D, C, MS, MP> Default for DedupState<D, C, MS, MP>
+where
+    D: Directory + Send + Sync + 'static,
+    C: Codec,
+    MS: MergeScheduler,
+    MP: MergePolicy,
+{
+    fn default() -> Self {
+        DedupState {
+            snapshot: None,
+            seq_no: 0,
+            pending: HashMap::new(),
+            pending_docs: 0,
+        }
+    }
+}
+
+impl<D, C, MS, MP> DedupState<D, C, MS, MP>
+where
+    D: Directory + Send + Sync + 'static,
+    C: Codec,
+    MS: MergeScheduler,
+    MP: MergePolicy,
+{
+    /// Forgets the view, returning the snapshot for the caller to drop it
+    /// outside of the lock. The snapshot holds a reference to the writer.
+    pub(crate) fn release(&mut self) -> Option<StandardDirectoryReader<D, C, MS, MP>> {
+        self.pending.clear();
+        self.pending_docs = 0;
+        self.snapshot.take()
+    }
+
+    // reopens the snapshot if the writer changed otherwise than through
+    // `add_document_dedup` since it was opened
+    fn refresh(&mut self, writer: &IndexWriter<D, C, MS, MP>) -> Result<()> {
+        let last_seq_no = writer.doc_writer().delete_queue.last_sequence_number();
+        if self.snapshot.is_some()
+            && self.seq_no == last_seq_no
+            && self.pending_docs < MAX_PENDING_DEDUP_DOCS
+        {
+            return Ok(());
+        }
+        // release the files of the old snapshot first
+        self.release();
+        self.snapshot = Some(writer.get_reader(true, false)?);
+        self.seq_no = writer.doc_writer().delete_queue.last_sequence_number();
+        Ok(())
+    }
+
+    fn find_duplicates(
+        &self,
+        hash: &ContentHash,
+        fields: &[String],
+        expected: &StoredValues,
+    ) -> Result<Duplicates> {
+        let pending = self.pending.get(hash);
+        let mut duplicates = match self.snapshot {
+            Some(ref snapshot) if !pending.map_or(false, |p| p.hides_snapshot) => {
+                find_duplicates(snapshot, hash, fields, expected)?
+            }
+            _ => Duplicates::default(),
+        };
+        for doc in pending.iter().flat_map(|p| &p.docs) {
+            if !same_stored_values(&doc.values, expected) {
+                duplicates.collisions += 1;
+                continue;
+            }
+            duplicates.confirmed += 1;
+            if let Some(version) = doc.version {
+                duplicates.max_version =
+                    Some(duplicates.max_version.map_or(version, |v| v.max(version)));
+            }
+        }
+        Ok(duplicates)
+    }
+
+    // records the doc written with sequence number `seq_no`, an overwrite
+    // deleted the other docs with its hash
+    fn add_pending(&mut self, hash: ContentHash, doc: PendingDoc, overwrite: bool, seq_no: u64) {
+        let pending = self
+            .pending
+            .entry(hash)
+            .or_insert_with(PendingDocs::default);
+        if overwrite {
+            pending.hides_snapshot = true;
+            pending.docs.clear();
+        }
+        pending.docs.push(doc);
+        self.pending_docs += 1;
+        // another write got a sequence number since the view was refreshed,
+        // the next call reopens the snapshot to see it
+        if seq_no == self.seq_no + 1 {
+            self.seq_no = seq_no;
+        }
+    }
+}
+
+pub(crate) fn add_document_dedup<D, C, MS, MP, F>(
+    writer: &IndexWriter<D, C, MS, MP>,
+    doc: Vec<F>,
+    policy: DedupPolicy,
+) -> Result<DedupResult>
+where
+    D: Directory + Send + Sync + 'static,
+    C: Codec,
+    MS: MergeScheduler,
+    MP: MergePolicy,
+    F: Fieldable + 'static,
+{
+    let fields = &writer.config().dedup_fields;
+    if fields.is_empty() {
+        bail!(IllegalState(
+            "add_document_dedup requires IndexWriterConfig::dedup_fields".into()
+        ));
+    }
+    if doc
+        .iter()
+        .any(|f| f.name() == DEDUP_HASH_FIELD || f.name() == DEDUP_VERSION_FIELD)
+    {
+        bail!(IllegalState(format!(
+            "fields {} and {} are reserved for dedup",
+            DEDUP_HASH_FIELD, DEDUP_VERSION_FIELD
+        )));
+    }
+
+    let hash = content_hash(&doc, fields);
+    let expected = normalized_fields(&doc, fields, true);
+    // the lookup and the write of concurrent calls don't interleave
+    let mut state = writer.dedup_state().lock()?;
+    state.refresh(writer)?;
+    let duplicates = state.find_duplicates(&hash, fields, &expected)?;
+
+    let mut new_doc: Vec<Box<dyn Fieldable>> = Vec::with_capacity(doc.len() + 2);
+    for field in doc {
+        new_doc.push(Box::new(field));
+    }
+    new_doc.push(Box::new(hash_field(&hash)));
+
+    let mut pending = PendingDoc {
+        values: expected,
+        version: None,
+    };
+    if duplicates.confirmed == 0 {
+        if policy == DedupPolicy::Version {
+            new_doc.push(Box::new(NumericDocValuesField::new(DEDUP_VERSION_FIELD, 0)));
+            pending.version = Some(0);
+        }
+        let seq_no = writer.add_document(new_doc)?;
+        state.add_pending(hash, pending, false, seq_no);
+        return Ok(DedupResult::Added(seq_no));
+    }
+
+    match policy {
+        DedupPolicy::Skip => Ok(DedupResult::Skipped),
+        DedupPolicy::Overwrite => {
+            if duplicates.collisions > 0 {
+                // deleting by the hash term would also delete the colliding documents
+                bail!(IllegalState(format!(
+                    "content hash collides with {} different documents, can't overwrite",
+                    duplicates.collisions
+                )));
+            }
+            let term = Term::new(DEDUP_HASH_FIELD.to_string(), hash.to_vec());
+            let seq_no = writer.update_document(new_doc, Some(term))?;
+            state.add_pending(hash, pending, true, seq_no);
+            Ok(DedupResult::Overwritten(seq_no))
+        }
+        DedupPolicy::Version => {
+            let version = duplicates
+                .max_version
+                .map_or(duplicates.confirmed as i64, |v| v + 1);
+            new_doc.push(Box::new(NumericDocValuesField::new(
+                DEDUP_VERSION_FIELD,
+                version,
+            )));
+            let seq_no = writer.add_document(new_doc)?;
+            pending.version = Some(version);
+            state.add_pending(hash, pending, false, seq_no);
+            Ok(DedupResult::Versioned(seq_no, version))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::doc::{Store, StoredField, StringField, STORE_FIELD_TYPE};
+    use core::index::index_writer_config::IndexWriterConfig;
+    use core::index::tests::{TestReader, TestWriter};
+    use core::store::RAMDirectory;
+
+    use std::sync::Arc;
+
+    fn text(name: &str, value: &str) -> Field {
+        Field::new(
+            name.to_string(),
+            STORE_FIELD_TYPE.clone(),
+            Some(VariantValue::VString(value.to_string())),
+            None,
+        )
+    }
+
+    #[test]
+    fn test_content_hash_is_normalized() {
+        let fields = vec!["title".to_string(), "year".to_string()];
+        let doc = vec![
+            text("title", "Hello  World"),
+            StoredField::new("year", None, VariantValue::Int(2019)).field,
+            text("body", "ignored"),
+        ];
+        let hash = content_hash(&doc, &fields);
+
+        let same = vec![
+            StoredField::new("year", None, VariantValue::Long(2019)).field,
+            text("title", " hello world "),
+        ];
+        assert_eq!(content_hash(&same, &fields), hash);
+        let reversed = vec!["year".to_string(), "title".to_string()];
+        assert_eq!(content_hash(&doc, &reversed), hash);
+
+        let near = vec![
+            text("title", "Hello World!"),
+            StoredField::new("year", None, VariantValue::Int(2019)).field,
+        ];
+        assert_ne!(content_hash(&near, &fields), hash);
+        let float = vec![
+            text("title", "Hello World"),
+            StoredField::new("year", None, VariantValue::Double(2019.0)).field,
+        ];
+        assert_ne!(content_hash(&float, &fields), hash);
+
+        // values can't move between fields
+        let moved = vec![text("title", ""), text("year", "hello world")];
+        let swapped = vec![text("title", "hello world"), text("year", "")];
+        assert_ne!(
+            content_hash(&moved, &fields),
+            content_hash(&swapped, &fields)
+        );
+    }
+
+    #[test]
+    fn test_normalized_fields_stored_only() {
+        let fields = vec!["b".to_string(), "a".to_string()];
+        let mut field_type = FieldType::default();
+        field_type.index_options = IndexOptions::Docs;
+        let indexed_only = Field::new(
+            "b".to_string(),
+            field_type,
+            Some(VariantValue::Long(1)),
+            None,
+        );
+        let doc = vec![text("a", "X"), indexed_only];
+
+        let all = normalized_fields(&doc, &fields, false);
+        assert_eq!(all[0], ("a".to_string(), vec![b"sx".to_vec()]));
+        assert_eq!(all[1].1.len(), 1);
+        let stored = normalized_fields(&doc, &fields, true);
+        assert_eq!(stored[0], all[0]);
+        assert!(stored[1].1.is_empty());
+    }
+
+    fn dedup_writer() -> (Arc<RAMDirectory>, TestWriter) {
+        let dir = Arc::new(RAMDirectory::new());
+        let mut conf = IndexWriterConfig::default();
+        conf.dedup_fields = vec!["title".to_string()];
+        let writer = IndexWriter::new(Arc::clone(&dir), Arc::new(conf)).unwrap();
+        (dir, writer)
+    }
+
+    fn doc(id: &str, title: &str) -> Vec<Field> {
+        vec![StringField::new("id", id, Store::Yes), text("title", title)]
+    }
+
+    // the ids of the live docs of the last commit, with their dedup version
+    fn live_docs(dir: &Arc<RAMDirectory>) -> Vec<(String, Option<i64>)> {
+        let reader = TestReader::open(Arc::clone(dir)).unwrap();
+        let fields = vec!["id".to_string()];
+        let mut docs = vec![];
+        for leaf in reader.leaves() {
+            let live_docs = leaf.reader.live_docs();
+            let versions = if leaf.reader.field_info(DEDUP_VERSION_FIELD).is_some() {
+                Some(
+                    leaf.reader
+                        .get_numeric_doc_values(DEDUP_VERSION_FIELD)
+                        .unwrap(),
+                )
+            } else {
+                None
+            };
+            for doc in 0..leaf.reader.max_doc() {
+                if !live_docs.get(doc as usize).unwrap() {
+                    continue;
+                }
+                let mut visitor = DocumentStoredFieldVisitor::new(&fields);
+                leaf.reader.document(doc, &mut visitor).unwrap();
+                let id = match visitor.fields[0].field.fields_data() {
+                    Some(VariantValue::VString(id)) => id.clone(),
+                    v => panic!("unexpected id {:?}", v),
+                };
+                let version = versions.as_ref().map(|v| v.get(doc).unwrap());
+                docs.push((id, version));
+            }
+        }
+        docs.sort();
+        docs
+    }
+
+    fn ids(ids: &[&str]) -> Vec<(String, Option<i64>)> {
+        ids.iter().map(|id| (id.to_string(), None)).collect()
+    }
+
+    #[test]
+    fn test_dedup_skip() {
+        let (dir, writer) = dedup_writer();
+        let policy = DedupPolicy::Skip;
+        match writer.add_document_dedup(doc("1", "Hello World"), policy) {
+            Ok(DedupResult::Added(_)) => {}
+            r => panic!("unexpected result {:?}", r),
+        }
+        let result = writer.add_document_dedup(doc("2", " hello  world"), policy);
+        assert_eq!(result.unwrap(), DedupResult::Skipped);
+        // near duplicates are added
+        match writer.add_document_dedup(doc("3", "Hello World!"), policy) {
+            Ok(DedupResult::Added(_)) => {}
+            r => panic!("unexpected result {:?}", r),
+        }
+        match writer.add_document_dedup(doc("4", "Goodbye"), policy) {
+            Ok(DedupResult::Added(_)) => {}
+            r => panic!("unexpected result {:?}", r),
+        }
+        let result = writer.add_document_dedup(doc("5", "goodbye"), policy);
+        assert_eq!(result.unwrap(), DedupResult::Skipped);
+        // the duplicates were found without flushing the added docs
+        assert_eq!(writer.flushed_segment_stats().0, 0);
+
+        // the view sees the writes that didn't go through dedup
+        writer.delete_documents_by_term("id", b"1").unwrap();
+        match writer.add_document_dedup(doc("6", "hello world"), policy) {
+            Ok(DedupResult::Added(_)) => {}
+            r => panic!("unexpected result {:?}", r),
+        }
+        writer.commit().unwrap();
+        assert_eq!(live_docs(&dir), ids(&["3", "4", "6"]));
+        writer.close().unwrap();
+    }
+
+    #[test]
+    fn test_dedup_overwrite() {
+        let (dir, writer) = dedup_writer();
+        let policy = DedupPolicy::Overwrite;
+        writer.add_document_dedup(doc("1", "a"), policy).unwrap();
+        writer.add_document_dedup(doc("2", "b"), policy).unwrap();
+        match writer.add_document_dedup(doc("3", "A"), policy) {
+            Ok(DedupResult::Overwritten(_)) => {}
+            r => panic!("unexpected result {:?}", r),
+        }
+        assert_eq!(writer.flushed_segment_stats().0, 0);
+        writer.commit().unwrap();
+        assert_eq!(live_docs(&dir), ids(&["2", "3"]));
+
+        // the committed doc is found in a new snapshot
+        match writer.add_document_dedup(doc("4", "a "), policy) {
+            Ok(DedupResult::Overwritten(_)) => {}
+            r => panic!("unexpected result {:?}", r),
+        }
+        match writer.add_document_dedup(doc("5", "a!"), policy) {
+            Ok(DedupResult::Added(_)) => {}
+            r => panic!("unexpected result {:?}", r),
+        }
+        writer.commit().unwrap();
+        assert_eq!(live_docs(&dir), ids(&["2", "4", "5"]));
+        writer.close().unwrap();
+    }
+
+    #[test]
+    fn test_dedup_version() {
+        let (dir, writer) = dedup_writer();
+        let policy = DedupPolicy::Version;
+        match writer.add_document_dedup(doc("1", "a"), policy) {
+            Ok(DedupResult::Added(_)) => {}
+            r => panic!("unexpected result {:?}", r),
+        }
+        for (id, version) in &[("2", 1), ("3", 2)] {
+            match writer.add_document_dedup(doc(id, "A"), policy) {
+                Ok(DedupResult::Versioned(_, v)) => assert_eq!(v, *version),
+                r => panic!("unexpected result {:?}", r),
+            }
+        }
+        writer.add_document_dedup(doc("4", "b"), policy).unwrap();
+        assert_eq!(writer.flushed_segment_stats().0, 0);
+        writer.commit().unwrap();
+
+        match writer.add_document_dedup(doc("5", "a"), policy) {
+            Ok(DedupResult::Versioned(_, v)) => assert_eq!(v, 3),
+            r => panic!("unexpected result {:?}", r),
+        }
+        writer.commit().unwrap();
+        let expected: Vec<(String, Option<i64>)> =
+            [("1", 0), ("2", 1), ("3", 2), ("4", 0), ("5", 3)]
+                .iter()
+                .map(|(id, v)| (id.to_string(), Some(*v)))
+                .collect();
+        assert_eq!(live_docs(&dir), expected);
+        writer.close().unwrap();
+    }
+}
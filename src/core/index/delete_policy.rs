@@ -11,10 +11,14 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use core::index::index_commit::IndexCommit;
-use core::store::Directory;
+use core::index::index_commit::IndexCommitPoint;
+
+use error::ErrorKind::{IllegalArgument, IllegalState};
 use error::Result;
 
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+
 /// Expert: policy for deletion of stale `IndexCommit index commits`.
 ///
 /// Implement this interface, and pass it to one
@@ -41,10 +45,9 @@ use error::Result;
 /// href="http://issues.apache.org/jira/browse/LUCENE-710">LUCENE-710</a>
 /// for details.
 ///
-/// Implementers of sub-classes should make sure that `#clone()`
-/// returns an independent instance able to work with any other `IndexWriter`
-/// or `Directory` instance.
-pub trait IndexDeletionPolicy {
+/// The policy is set with `IndexWriterConfig::index_deletion_policy`, it may
+/// be shared with the application, as `SnapshotDeletionPolicy` is.
+pub trait IndexDeletionPolicy: Send + Sync {
     /// This is called once when a writer is first
     /// instantiated to give the policy a chance to remove old
     /// commit points.
@@ -65,7 +68,7 @@ pub trait IndexDeletionPolicy {
     ///  sorted by age (the 0th one is the oldest commit).
     ///  Note that for a new index this method is invoked with
     ///  an empty list.
-    fn on_init<D: Directory>(&self, commits: Vec<&mut IndexCommit<D>>) -> Result<()>;
+    fn on_init(&self, commits: Vec<&mut dyn IndexCommitPoint>) -> Result<()>;
 
     /// This is called each time the writer completed a commit.
     /// This gives the policy a chance to remove old commit points
@@ -86,18 +89,18 @@ pub trait IndexDeletionPolicy {
     ///  
     /// @param commits List of `IndexCommit`,
     ///  sorted by age (the 0th one is the oldest commit).
-    fn on_commit<D: Directory>(&self, commits: Vec<&mut IndexCommit<D>>) -> Result<()>;
+    fn on_commit(&self, commits: Vec<&mut dyn IndexCommitPoint>) -> Result<()>;
 }
 
 #[derive(Default)]
 pub struct KeepOnlyLastCommitDeletionPolicy;
 
 impl IndexDeletionPolicy for KeepOnlyLastCommitDeletionPolicy {
-    fn on_init<D: Directory>(&self, commits: Vec<&mut IndexCommit<D>>) -> Result<()> {
+    fn on_init(&self, commits: Vec<&mut dyn IndexCommitPoint>) -> Result<()> {
         self.on_commit(commits)
     }
 
-    fn on_commit<D: Directory>(&self, mut commits: Vec<&mut IndexCommit<D>>) -> Result<()> {
+    fn on_commit(&self, mut commits: Vec<&mut dyn IndexCommitPoint>) -> Result<()> {
         commits.pop();
         for commit in commits {
             commit.delete()?;
@@ -105,3 +108,228 @@ impl IndexDeletionPolicy for KeepOnlyLastCommitDeletionPolicy {
         Ok(())
     }
 }
+
+/// An `IndexDeletionPolicy` that wraps any other policy and adds the ability
+/// to hold onto the most recent commit, e.g. while it is backed up, even
+/// after the writer made newer commits.
+///
+/// `#snapshot` protects the files of the last commit from deletion until the
+/// snapshot is passed to `#release`. A commit can be snapshotted several
+/// times, it's kept until every snapshot of it is released. The files of the
+/// released commits are deleted on the next commit of the writer, as decided
+/// by the wrapped policy.
+///
+/// The snapshots are held in memory only, they don't survive a restart of
+/// the writer.
+pub struct SnapshotDeletionPolicy {
+    primary: Arc<dyn IndexDeletionPolicy>,
+    state: Mutex<SnapshotState>,
+}
+
+struct SnapshotState {
+    // generation -> the snapshotted commit and its number of snapshots
+    snapshots: HashMap<i64, (Arc<CommitSnapshot>, u32)>,
+    last_commit: Option<Arc<CommitSnapshot>>,
+}
+
+impl SnapshotDeletionPolicy {
+    pub fn new(primary: Arc<dyn IndexDeletionPolicy>) -> SnapshotDeletionPolicy {
+        SnapshotDeletionPolicy {
+            primary,
+            state: Mutex::new(SnapshotState {
+                snapshots: HashMap::new(),
+                last_commit: None,
+            }),
+        }
+    }
+
+    /// Snapshots the last commit, its files won't be deleted until `#release`
+    /// is called with the returned commit.
+    ///
+    /// Fails if the writer hasn't seen a commit yet, this is the case of a
+    /// writer that created a new index and didn't commit.
+    pub fn snapshot(&self) -> Result<Arc<CommitSnapshot>> {
+        let mut state = self.state.lock()?;
+        let commit = match state.last_commit {
+            Some(ref commit) => Arc::clone(commit),
+            None => bail!(IllegalState("no index commit to snapshot".into())),
+        };
+        state
+            .snapshots
+            .entry(commit.generation)
+            .or_insert_with(|| (Arc::clone(&commit), 0))
+            .1 += 1;
+        Ok(commit)
+    }
+
+    /// Releases a snapshot returned by `#snapshot`.
+    pub fn release(&self, commit: &dyn IndexCommitPoint) -> Result<()> {
+        let generation = commit.generation();
+        let mut state = self.state.lock()?;
+        let released = match state.snapshots.get_mut(&generation) {
+            Some(snapshot) => {
+                snapshot.1 -= 1;
+                snapshot.1 == 0
+            }
+            None => bail!(IllegalArgument(format!(
+                "commit generation {} is not snapshotted",
+                generation
+            ))),
+        };
+        if released {
+            state.snapshots.remove(&generation);
+        }
+        Ok(())
+    }
+
+    /// The commits currently snapshotted.
+    pub fn snapshots(&self) -> Vec<Arc<CommitSnapshot>> {
+        let state = self.state.lock().unwrap();
+        state
+            .snapshots
+            .values()
+            .map(|(commit, _)| Arc::clone(commit))
+            .collect()
+    }
+
+    /// The total number of snapshots, a commit counts as many times as it was
+    /// snapshotted.
+    pub fn snapshot_count(&self) -> usize {
+        let state = self.state.lock().unwrap();
+        state
+            .snapshots
+            .values()
+            .map(|(_, count)| *count as usize)
+            .sum()
+    }
+
+    fn on_commits(&self, commits: Vec<&mut dyn IndexCommitPoint>, init: bool) -> Result<()> {
+        let mut state = self.state.lock()?;
+        if let Some(commit) = commits.last() {
+            state.last_commit = Some(Arc::new(CommitSnapshot::new(&**commit)?));
+        }
+        let mut wrapped: Vec<ProtectedCommit> = commits
+            .into_iter()
+            .map(|commit| {
+                let protected = state.snapshots.contains_key(&commit.generation());
+                ProtectedCommit { commit, protected }
+            })
+            .collect();
+        let commits: Vec<&mut dyn IndexCommitPoint> = wrapped
+            .iter_mut()
+            .map(|c| c as &mut dyn IndexCommitPoint)
+            .collect();
+        if init {
+            self.primary.on_init(commits)
+        } else {
+            self.primary.on_commit(commits)
+        }
+    }
+}
+
+impl IndexDeletionPolicy for SnapshotDeletionPolicy {
+    fn on_init(&self, commits: Vec<&mut dyn IndexCommitPoint>) -> Result<()> {
+        self.on_commits(commits, true)
+    }
+
+    fn on_commit(&self, commits: Vec<&mut dyn IndexCommitPoint>) -> Result<()> {
+        self.on_commits(commits, false)
+    }
+}
+
+/// A commit point given to the primary policy of a `SnapshotDeletionPolicy`,
+/// which ignores the deletion of the snapshotted commits.
+struct ProtectedCommit<'a> {
+    commit: &'a mut dyn IndexCommitPoint,
+    protected: bool,
+}
+
+impl<'a> IndexCommitPoint for ProtectedCommit<'a> {
+    fn segments_file_name(&self) -> &str {
+        self.commit.segments_file_name()
+    }
+
+    fn file_names(&self) -> Result<&HashSet<String>> {
+        self.commit.file_names()
+    }
+
+    fn delete(&mut self) -> Result<()> {
+        if !self.protected {
+            self.commit.delete()?;
+        }
+        Ok(())
+    }
+
+    fn is_deleted(&self) -> bool {
+        self.commit.is_deleted()
+    }
+
+    fn segment_count(&self) -> usize {
+        self.commit.segment_count()
+    }
+
+    fn generation(&self) -> i64 {
+        self.commit.generation()
+    }
+
+    fn user_data(&self) -> &HashMap<String, String> {
+        self.commit.user_data()
+    }
+}
+
+/// A commit held by a `SnapshotDeletionPolicy`.
+///
+/// A backup copies its `#file_names` with `Directory::open_input`, and
+/// `StandardDirectoryReader::open_commit` opens a reader on it.
+#[derive(Debug)]
+pub struct CommitSnapshot {
+    segments_file_name: String,
+    file_names: HashSet<String>,
+    generation: i64,
+    segment_count: usize,
+    user_data: HashMap<String, String>,
+}
+
+impl CommitSnapshot {
+    fn new(commit: &dyn IndexCommitPoint) -> Result<CommitSnapshot> {
+        Ok(CommitSnapshot {
+            segments_file_name: commit.segments_file_name().to_string(),
+            file_names: commit.file_names()?.clone(),
+            generation: commit.generation(),
+            segment_count: commit.segment_count(),
+            user_data: commit.user_data().clone(),
+        })
+    }
+}
+
+impl IndexCommitPoint for CommitSnapshot {
+    fn segments_file_name(&self) -> &str {
+        &self.segments_file_name
+    }
+
+    fn file_names(&self) -> Result<&HashSet<String>> {
+        Ok(&self.file_names)
+    }
+
+    fn delete(&mut self) -> Result<()> {
+        bail!(IllegalState(
+            "a commit snapshot can't be deleted, release it instead".into()
+        ))
+    }
+
+    fn is_deleted(&self) -> bool {
+        false
+    }
+
+    fn segment_count(&self) -> usize {
+        self.segment_count
+    }
+
+    fn generation(&self) -> i64 {
+        self.generation
+    }
+
+    fn user_data(&self) -> &HashMap<String, String> {
+        &self.user_data
+    }
+}
@@ -11,24 +11,29 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use core::codec::{Codec, CodecTVFields};
+use core::codec::{codec_util, Codec, CodecTVFields};
 use core::doc::{Document, DocumentStoredFieldVisitor};
-use core::index::index_commit::IndexCommit;
+use core::index::index_commit::{IndexCommit, IndexCommitPoint};
 use core::index::index_writer::IndexWriter;
 use core::index::leaf_reader::{LeafReaderContext, SearchLeafReader};
 use core::index::merge_policy::MergePolicy;
 use core::index::merge_scheduler::MergeScheduler;
 use core::index::INDEX_FILE_SEGMENTS;
-use core::index::{get_segment_file_name, run_with_find_segment_file, SegmentInfos};
-use core::index::{IndexReader, LeafReader, SegmentReader};
+use core::index::{
+    get_segment_file_name, run_with_find_segment_file, segments_format_error, term_statistics,
+    SegmentInfos, Term,
+};
+use core::index::{IndexReader, LeafReader, OrdinalMap, OrdinalMapCache, SegmentReader};
 use core::store::{Directory, IOContext};
 use core::util::DocId;
 
 use error::{
-    ErrorKind::{IllegalArgument, IllegalState},
+    ErrorKind::{AlreadyClosed, IllegalArgument, IllegalState},
     Result,
 };
 
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Mutex;
 use std::{collections::HashMap, fmt, sync::Arc};
 
 ///
@@ -58,6 +63,16 @@ pub fn index_exist<D: Directory>(directory: &D) -> Result<bool> {
         .any(|f| f.starts_with(&prefix)))
 }
 
+/// A reader on the segments of an index.
+///
+/// The `SegmentReader`s are shared through `Arc` by the readers opened from
+/// each other (see `#open_if_changed`) and by the pool of the `IndexWriter`,
+/// the files of a segment stay open until its last reader is dropped.
+/// The reader is ref counted: `#close` releases the reference of its owner
+/// and is idempotent, readers built on top of it (e.g. a `MultiReader`) keep
+/// it open with `#inc_ref` until they `#dec_ref` it. When the last reference
+/// is released, what the reader holds on the index is released and its
+/// methods fail with `AlreadyClosed`.
 pub struct StandardDirectoryReader<
     D: Directory + Send + Sync + 'static,
     C: Codec,
@@ -73,8 +88,39 @@ pub struct StandardDirectoryReader<
     apply_all_deletes: bool,
     write_all_deletes: bool,
     writer: Option<IndexWriter<D, C, MS, MP>>,
+    // the reference of the owner, plus one for each `inc_ref`
+    ref_count: AtomicUsize,
+    // whether the owner released its reference
+    closed: AtomicBool,
+    // memoized doc freq and total term freq, scorers ask for the same terms
+    term_statistics: Mutex<HashMap<Term, (i32, i64)>>,
+    ordinal_maps: OrdinalMapCache,
+}
+
+// The segment readers are `Send + Sync`, the memoized statistics and ordinal
+// maps are behind their locks and the reference count is atomic.
+unsafe impl<D, C, MS, MP> Send for StandardDirectoryReader<D, C, MS, MP>
+where
+    D: Directory + Send + Sync + 'static,
+    C: Codec,
+    MS: MergeScheduler,
+    MP: MergePolicy,
+{
+}
+
+unsafe impl<D, C, MS, MP> Sync for StandardDirectoryReader<D, C, MS, MP>
+where
+    D: Directory + Send + Sync + 'static,
+    C: Codec,
+    MS: MergeScheduler,
+    MP: MergePolicy,
+{
 }
 
+/// The number of terms whose statistics are memoized by a reader, the cache
+/// is cleared when it's full.
+const TERM_STATISTICS_CACHE_SIZE: usize = 1024;
+
 impl<D, C, MS, MP> StandardDirectoryReader<D, C, MS, MP>
 where
     D: Directory + Send + Sync + 'static,
@@ -86,9 +132,20 @@ where
         let segment_file_name = get_segment_file_name(directory.as_ref())?;
         let segment_infos = SegmentInfos::read_commit(&directory, &segment_file_name)?;
         let mut readers = Vec::with_capacity(segment_infos.segments.len());
+        let mut format_failures = Vec::new();
         for seg_info in &segment_infos.segments {
-            let s = SegmentReader::open(seg_info, &IOContext::READ)?;
-            readers.push(Arc::new(s));
+            match SegmentReader::open(seg_info, &IOContext::READ) {
+                Ok(s) => readers.push(Arc::new(s)),
+                Err(e) => {
+                    if !codec_util::is_format_version_error(&e) {
+                        return Err(e);
+                    }
+                    format_failures.push((seg_info.info.name.clone(), e));
+                }
+            }
+        }
+        if !format_failures.is_empty() {
+            return Err(segments_format_error(format_failures));
         }
         Ok(Self::new(
             directory,
@@ -100,6 +157,26 @@ where
         ))
     }
 
+    /// Open a reader on the given commit of the index in `directory`, e.g. a
+    /// commit held by a `SnapshotDeletionPolicy`.
+    pub fn open_commit(directory: Arc<D>, commit: &dyn IndexCommitPoint) -> Result<Self> {
+        let infos = SegmentInfos::read_commit(&directory, commit.segments_file_name())?;
+        Self::open_by_readers(directory, infos, &[])
+    }
+
+    /// Open a near real-time reader from `writer`, it sees the documents
+    /// flushed or buffered by the writer without committing them. If
+    /// `apply_all_deletes` is false the buffered deletes may not be visible.
+    ///
+    /// Reopen it with `#open_if_changed`, the segments without new deletes
+    /// are shared with the reopened reader.
+    pub fn open_with_writer(
+        writer: &IndexWriter<D, C, MS, MP>,
+        apply_all_deletes: bool,
+    ) -> Result<Self> {
+        writer.get_reader(apply_all_deletes, false)
+    }
+
     /// Used by near real-time searcher
     pub fn open_by_writer(
         writer: IndexWriter<D, C, MS, MP>,
@@ -124,7 +201,7 @@ where
             let reader = rld.get_readonly_clone(&IOContext::READ)?;
             if reader.num_docs() > 0 || writer.keep_fully_deleted_segments() {
                 // Steal the ref:
-                readers.push(Arc::new(reader));
+                readers.push(reader);
                 infos_upto += 1;
             } else {
                 segment_infos.segments.remove(infos_upto);
@@ -237,6 +314,10 @@ where
             writer,
             apply_all_deletes,
             write_all_deletes,
+            ref_count: AtomicUsize::new(1),
+            closed: AtomicBool::new(false),
+            term_statistics: Mutex::new(HashMap::new()),
+            ordinal_maps: OrdinalMapCache::default(),
         }
     }
 
@@ -248,18 +329,33 @@ where
         self.writer.clone()
     }
 
+    pub(crate) fn segment_readers(&self) -> &[Arc<SegmentReader<D, C>>] {
+        &self.readers
+    }
+
     pub fn version(&self) -> i64 {
         self.segment_infos.version
     }
 
     pub fn open_if_changed(&self, commit: Option<&IndexCommit<D>>) -> Result<Option<Self>> {
+        self.ensure_open()?;
         // If we were obtained by writer.getReader(), re-ask the
         // writer to get a new reader.
-        if self.writer.is_some() {
-            self.open_from_writer(commit)
+        let reader = if self.writer.is_some() {
+            self.open_from_writer(commit)?
         } else {
-            self.do_open_no_writer(commit)
+            self.do_open_no_writer(commit)?
+        };
+        if let Some(ref reader) = reader {
+            // the segments are shared, so are the ordinal maps built on them
+            reader.ordinal_maps.inherit(&self.ordinal_maps, reader)?;
         }
+        Ok(reader)
+    }
+
+    /// Returns an estimate of the heap used by the cached `OrdinalMap`s.
+    pub fn ordinal_maps_ram_bytes_used(&self) -> Result<usize> {
+        self.ordinal_maps.ram_bytes_used()
     }
 
     fn open_from_writer(&self, commit: Option<&IndexCommit<D>>) -> Result<Option<Self>> {
@@ -302,7 +398,93 @@ where
         Ok(Some(self.open_from_commit(commit)?))
     }
 
+    /// Releases the reference of the owner of this reader, it's closed once
+    /// the references taken by `#inc_ref` are released too. Closing a closed
+    /// reader does nothing.
+    pub fn close(&self) -> Result<()> {
+        if self.closed.swap(true, Ordering::AcqRel) {
+            return Ok(());
+        }
+        self.dec_ref()
+    }
+
+    /// Takes a reference on this reader, which stays open until it's released
+    /// by `#dec_ref`. Fails with `AlreadyClosed` if the reader is closed.
+    pub fn inc_ref(&self) -> Result<()> {
+        let mut count = self.ref_count.load(Ordering::Acquire);
+        loop {
+            if count == 0 {
+                bail!(AlreadyClosed("this IndexReader is closed".into()));
+            }
+            match self.ref_count.compare_exchange_weak(
+                count,
+                count + 1,
+                Ordering::AcqRel,
+                Ordering::Acquire,
+            ) {
+                Ok(_) => return Ok(()),
+                Err(current) => count = current,
+            }
+        }
+    }
+
+    /// Releases a reference taken by `#inc_ref`, the last one closes the
+    /// reader: the commit it's opened on isn't protected from the writer
+    /// anymore. The segment readers are released when the last reader sharing
+    /// them is dropped.
+    pub fn dec_ref(&self) -> Result<()> {
+        let mut count = self.ref_count.load(Ordering::Acquire);
+        loop {
+            if count == 0 {
+                bail!(AlreadyClosed("this IndexReader is closed".into()));
+            }
+            match self.ref_count.compare_exchange_weak(
+                count,
+                count - 1,
+                Ordering::AcqRel,
+                Ordering::Acquire,
+            ) {
+                Ok(_) => break,
+                Err(current) => count = current,
+            }
+        }
+        if count == 1 {
+            self.do_close()?;
+        }
+        Ok(())
+    }
+
+    pub fn ref_count(&self) -> usize {
+        self.ref_count.load(Ordering::Acquire)
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.ref_count() == 0
+    }
+
+    fn do_close(&self) -> Result<()> {
+        if let Some(ref writer) = self.writer {
+            writer.dec_ref_deleter(&self.segment_infos)?;
+        }
+        Ok(())
+    }
+
+    fn cached_term_statistics(&self, term: &Term) -> Result<(i32, i64)> {
+        self.ensure_open()?;
+        if let Some(stats) = self.term_statistics.lock()?.get(term) {
+            return Ok(*stats);
+        }
+        let stats = term_statistics(self, term)?;
+        let mut cache = self.term_statistics.lock()?;
+        if cache.len() >= TERM_STATISTICS_CACHE_SIZE {
+            cache.clear();
+        }
+        cache.insert(term.clone(), stats);
+        Ok(stats)
+    }
+
     pub fn is_current(&self) -> Result<bool> {
+        self.ensure_open()?;
         match &self.writer {
             Some(writer) if !writer.is_closed() => Ok(writer.nrt_is_current(&self.segment_infos)),
             _ => {
@@ -327,7 +509,19 @@ where
     MP: MergePolicy,
 {
     type Codec = C;
+    fn ensure_open(&self) -> Result<()> {
+        if self.is_closed() {
+            bail!(AlreadyClosed("this IndexReader is closed".into()));
+        }
+        Ok(())
+    }
+
+    // a closed reader has no leaves, the methods that can fail check
+    // `ensure_open` instead
     fn leaves(&self) -> Vec<LeafReaderContext<'_, C>> {
+        if self.ensure_open().is_err() {
+            return vec![];
+        }
         self.readers
             .iter()
             .enumerate()
@@ -338,6 +532,7 @@ where
     }
 
     fn term_vector(&self, doc_id: DocId) -> Result<Option<CodecTVFields<C>>> {
+        self.ensure_open()?;
         if doc_id < 0 || doc_id > self.max_doc {
             bail!(IllegalArgument(format!("invalid doc id: {}", doc_id)));
         }
@@ -350,6 +545,7 @@ where
     }
 
     fn document(&self, doc_id: DocId, fields_load: &[String]) -> Result<Document> {
+        self.ensure_open()?;
         if doc_id < 0 || doc_id > self.max_doc {
             bail!(IllegalArgument(format!(
                 "doc_id {} invalid: [max_doc={}]",
@@ -378,13 +574,26 @@ where
         self.num_docs
     }
 
-    fn refresh(&self) -> Result<Option<Box<dyn IndexReader<Codec = C>>>> {
+    fn refresh(&self) -> Result<Option<Box<dyn IndexReader<Codec = C> + Send + Sync>>> {
         if let Some(reader) = self.open_if_changed(None)? {
             Ok(Some(Box::new(reader)))
         } else {
             Ok(None)
         }
     }
+
+    fn doc_freq(&self, term: &Term) -> Result<i32> {
+        Ok(self.cached_term_statistics(term)?.0)
+    }
+
+    fn total_term_freq(&self, term: &Term) -> Result<i64> {
+        Ok(self.cached_term_statistics(term)?.1)
+    }
+
+    fn ordinal_map(&self, field: &str) -> Result<Option<Arc<OrdinalMap>>> {
+        self.ensure_open()?;
+        self.ordinal_maps.get_or_build(self, field)
+    }
 }
 
 impl<D, C, MS, MP> fmt::Debug for StandardDirectoryReader<D, C, MS, MP>
@@ -429,13 +638,175 @@ where
     MP: MergePolicy,
 {
     fn drop(&mut self) {
-        if let Some(ref writer) = self.writer {
-            if let Err(e) = writer.dec_ref_deleter(&self.segment_infos) {
-                error!(
-                    "StandardDirectoryReader drop failed by dec_ref_deleter: {:?}",
-                    e
-                );
+        // nobody can release the references left once the reader is dropped
+        if self.ref_count.swap(0, Ordering::AcqRel) == 0 {
+            return;
+        }
+        if let Err(e) = self.do_close() {
+            error!(
+                "StandardDirectoryReader drop failed by dec_ref_deleter: {:?}",
+                e
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::codec::CodecEnum;
+    use core::doc::{Field, FieldType};
+    use core::index::index_writer_config::IndexWriterConfig;
+    use core::index::merge_policy::TieredMergePolicy;
+    use core::index::merge_scheduler::SerialMergeScheduler;
+    use core::index::{IndexOptions, LeafReader, TermIterator, Terms};
+    use core::search::searcher::{DefaultIndexSearcher, IndexSearcher};
+    use core::search::term_query::TermQuery;
+    use core::store::tests::CountingDirectory;
+    use core::util::VariantValue;
+    use error::ErrorKind::AlreadyClosed;
+
+    type TestReader = StandardDirectoryReader<
+        CountingDirectory,
+        CodecEnum,
+        SerialMergeScheduler,
+        TieredMergePolicy,
+    >;
+
+    fn keyword(name: &str, value: &str) -> Field {
+        let mut field_type = FieldType::default();
+        field_type.tokenized = false;
+        field_type.index_options = IndexOptions::DocsAndFreqs;
+        Field::new(
+            name.to_string(),
+            field_type,
+            Some(VariantValue::VString(value.to_string())),
+            None,
+        )
+    }
+
+    fn count(reader: &Arc<TestReader>, value: &str) -> i32 {
+        let searcher = DefaultIndexSearcher::new(Arc::clone(reader));
+        let term = Term::new("id".to_string(), value.as_bytes().to_vec());
+        searcher.count(&TermQuery::new(term, 1.0, None)).unwrap()
+    }
+
+    #[test]
+    fn test_closing_a_reader_keeps_shared_segments_open() {
+        let dir = Arc::new(CountingDirectory::new());
+        let writer =
+            IndexWriter::new(Arc::clone(&dir), Arc::new(IndexWriterConfig::default())).unwrap();
+        writer.add_document(vec![keyword("id", "0")]).unwrap();
+        writer.commit().unwrap();
+        let first = Arc::new(TestReader::open(Arc::clone(&dir)).unwrap());
+        writer.add_document(vec![keyword("id", "1")]).unwrap();
+        writer.commit().unwrap();
+        writer.close().unwrap();
+
+        let second = Arc::new(first.open_if_changed(None).unwrap().unwrap());
+        assert!(Arc::ptr_eq(
+            &first.segment_readers()[0],
+            &second.segment_readers()[0]
+        ));
+        let shared_inputs = dir.open_inputs();
+        assert!(shared_inputs > 0);
+
+        first.close().unwrap();
+        first.close().unwrap();
+        // the shared segments aren't reachable through the closed reader
+        assert!(first.leaves().is_empty());
+        match first.document(0, &[]) {
+            Err(e) => match e.kind() {
+                AlreadyClosed(_) => {}
+                _ => panic!("expected AlreadyClosed, got {:?}", e),
+            },
+            Ok(_) => panic!("a closed reader loaded a document"),
+        }
+        assert!(first.open_if_changed(None).is_err());
+        let searcher = DefaultIndexSearcher::new(Arc::clone(&first));
+        let term = Term::new("id".to_string(), b"0".to_vec());
+        match searcher.count(&TermQuery::new(term, 1.0, None)) {
+            Err(e) => match e.kind() {
+                AlreadyClosed(_) => {}
+                _ => panic!("expected AlreadyClosed, got {:?}", e),
+            },
+            Ok(_) => panic!("a closed reader served a search"),
+        }
+        drop(searcher);
+        drop(first);
+
+        // the segment shared with the closed reader still serves queries
+        assert_eq!(dir.open_inputs(), shared_inputs);
+        assert_eq!(count(&second, "0"), 1);
+        assert_eq!(count(&second, "1"), 1);
+
+        drop(second);
+        assert_eq!(dir.open_inputs(), 0);
+    }
+
+    #[test]
+    fn test_term_statistics_sum_the_segments() {
+        let dir = Arc::new(CountingDirectory::new());
+        let writer =
+            IndexWriter::new(Arc::clone(&dir), Arc::new(IndexWriterConfig::default())).unwrap();
+        for segment in 0..3 {
+            for i in 0..10 {
+                let color = if i % 3 == 0 { "red" } else { "blue" };
+                let doc = vec![
+                    keyword("id", &format!("{}_{}", segment, i)),
+                    keyword("color", color),
+                ];
+                writer.add_document(doc).unwrap();
+            }
+            if segment == 2 {
+                writer
+                    .add_document(vec![keyword("color", "green")])
+                    .unwrap();
             }
+            writer.commit().unwrap();
+        }
+        writer.delete_documents_by_term("color", b"red").unwrap();
+        writer.commit().unwrap();
+        writer.close().unwrap();
+
+        let reader = TestReader::open(dir).unwrap();
+        assert!(reader.leaves().len() > 1);
+        assert!(reader.has_deletions());
+        for value in &["red", "blue", "green", "yellow", "aaa"] {
+            let term = Term::new("color".to_string(), value.as_bytes().to_vec());
+            let (mut doc_freq, mut total_term_freq) = (0, 0);
+            for leaf in reader.leaves() {
+                if let Some(terms) = leaf.reader.terms("color").unwrap() {
+                    let mut terms_enum = terms.iterator().unwrap();
+                    if terms_enum.seek_exact(value.as_bytes()).unwrap() {
+                        doc_freq += terms_enum.doc_freq().unwrap();
+                        total_term_freq += terms_enum.total_term_freq().unwrap();
+                    }
+                }
+            }
+            assert_eq!(reader.doc_freq(&term).unwrap(), doc_freq);
+            assert_eq!(reader.total_term_freq(&term).unwrap(), total_term_freq);
+            // memoized
+            assert_eq!(reader.doc_freq(&term).unwrap(), doc_freq);
+        }
+        // deleted documents are counted, like in the segment statistics
+        let red = Term::new("color".to_string(), b"red".to_vec());
+        assert_eq!(reader.doc_freq(&red).unwrap(), 12);
+        let green = Term::new("color".to_string(), b"green".to_vec());
+        assert_eq!(reader.doc_freq(&green).unwrap(), 1);
+
+        let stats = reader.collection_statistics("color").unwrap();
+        assert_eq!(stats.max_doc, i64::from(reader.max_doc()));
+        let (mut doc_count, mut sum_doc_freq) = (0, 0);
+        for leaf in reader.leaves() {
+            let terms = leaf.reader.terms("color").unwrap().unwrap();
+            doc_count += i64::from(terms.doc_count().unwrap());
+            sum_doc_freq += terms.sum_doc_freq().unwrap();
         }
+        assert_eq!(stats.doc_count, 31);
+        assert_eq!(stats.doc_count, doc_count);
+        assert_eq!(stats.sum_doc_freq, sum_doc_freq);
+        let missing = reader.collection_statistics("size").unwrap();
+        assert_eq!(missing.doc_count, 0);
     }
 }
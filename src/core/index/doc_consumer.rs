@@ -11,12 +11,14 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use core::analysis::TokenStream;
+use core::analysis::{FlattenGraphFilter, TokenStream};
 use core::codec::{
-    Codec, DocValuesFormat, FieldInfosFormat, NormsFormat, PointsFormat, PointsWriter,
-    StoredFieldsFormat, StoredFieldsWriter, StoredFieldsWriterEnum,
+    Codec, DocValuesFormat, FieldInfosFormat, MergeVisitor, NormsFormat, PointsFormat,
+    PointsWriter, StoredFieldsFormat, StoredFieldsReader, StoredFieldsWriter,
+    StoredFieldsWriterEnum,
 };
 use core::doc::FieldType;
+use core::index::buffered_doc_values::{BufferedDocValues, SortingNormsConsumer};
 use core::index::doc_values_type::DocValuesType;
 use core::index::doc_values_writer::BinaryDocValuesWriter;
 use core::index::doc_values_writer::NumericDocValuesWriter;
@@ -28,6 +30,7 @@ use core::index::index_writer;
 use core::index::merge_policy::MergePolicy;
 use core::index::norm_values_writer::NormValuesWriter;
 use core::index::point_values_writer::PointValuesWriter;
+use core::index::sorter::{PackedLongDocMap, SorterDocMap};
 use core::index::term_vector::TermVectorsConsumer;
 use core::index::terms_hash::{FreqProxTermsWriter, TermsHash};
 use core::index::terms_hash_per_field::{FreqProxTermsWriterPerField, TermsHashPerField};
@@ -39,7 +42,7 @@ use core::index::{
 use core::store::{Directory, IOContext};
 use core::util::{BytesRef, Counter, DocId, VariantValue};
 
-use core::search::bm25_similarity::BM25Similarity;
+use core::search::norm_encoding::{NormEncoding, NORM_ENCODING_ATTRIBUTE};
 
 use error::{
     ErrorKind::{IllegalArgument, UnsupportedOperation},
@@ -51,6 +54,7 @@ use std::borrow::Cow;
 use std::cmp::Ordering;
 use std::collections::BTreeMap;
 use std::ptr;
+use std::sync::Arc;
 
 const MAX_FIELD_COUNT: usize = 65536;
 
@@ -61,7 +65,12 @@ pub trait DocConsumer<D: Directory, C: Codec> {
         doc: &mut [F],
     ) -> Result<()>;
 
-    fn flush<DW>(&mut self, state: &mut SegmentWriteState<D, DW, C>) -> Result<()>
+    /// Writes the segment, returns how the docs were reordered to follow the
+    /// index sort, `None` if they kept their order.
+    fn flush<DW>(
+        &mut self,
+        state: &mut SegmentWriteState<D, DW, C>,
+    ) -> Result<Option<Arc<PackedLongDocMap>>>
     where
         DW: Directory + 'static;
 
@@ -164,7 +173,11 @@ where
     }
 
     /// Writes all buffered points.
-    fn write_points<DW: Directory>(&mut self, state: &SegmentWriteState<D, DW, C>) -> Result<()> {
+    fn write_points<DW: Directory>(
+        &mut self,
+        state: &SegmentWriteState<D, DW, C>,
+        sort_map: Option<&Arc<PackedLongDocMap>>,
+    ) -> Result<()> {
         let mut points_writer = None;
         for per_field in &mut self.field_hash {
             if per_field.point_values_writer.is_some() {
@@ -179,11 +192,11 @@ where
                             .fields_writer(state)?,
                     );
                 }
-                per_field
-                    .point_values_writer
-                    .as_mut()
-                    .unwrap()
-                    .flush(state, points_writer.as_mut().unwrap())?;
+                let point_values_writer = per_field.point_values_writer.as_mut().unwrap();
+                if let Some(sort_map) = sort_map {
+                    point_values_writer.sort_doc_ids(sort_map.as_ref());
+                }
+                point_values_writer.flush(state, points_writer.as_mut().unwrap())?;
                 per_field.point_values_writer = None;
             } else {
                 debug_assert_eq!(per_field.field_info().point_dimension_count, 0);
@@ -196,11 +209,17 @@ where
     }
 
     /// Writes all buffered doc values (called from {@link #flush}).
+    ///
+    /// With an index sort, the values are buffered to sort the docs by them
+    /// first, and written in the sorted order. Returns the sort of the docs,
+    /// `None` if they are already in order.
     fn write_doc_values<DW: Directory>(
         &mut self,
         state: &SegmentWriteState<D, DW, C>,
-    ) -> Result<()> {
+    ) -> Result<Option<Arc<PackedLongDocMap>>> {
         let max_doc = state.segment_info.max_doc;
+        let index_sort = state.segment_info.index_sort.as_ref();
+        let mut buffered = index_sort.map(|_| BufferedDocValues::new(max_doc));
         let mut dv_consumer = None;
         for per_field in &mut self.field_hash {
             if per_field.doc_values_writer.is_some() {
@@ -219,11 +238,11 @@ where
                     .as_mut()
                     .unwrap()
                     .finish(max_doc);
-                per_field
-                    .doc_values_writer
-                    .as_mut()
-                    .unwrap()
-                    .flush(state, dv_consumer.as_mut().unwrap())?;
+                let doc_values_writer = per_field.doc_values_writer.as_mut().unwrap();
+                match buffered {
+                    Some(ref mut buffered) => doc_values_writer.flush(state, buffered)?,
+                    None => doc_values_writer.flush(state, dv_consumer.as_mut().unwrap())?,
+                }
                 per_field.doc_values_writer = None;
             } else {
                 debug_assert_eq!(per_field.field_info().doc_values_type, DocValuesType::Null);
@@ -231,7 +250,14 @@ where
         }
 
         debug_assert_eq!(state.field_infos.has_doc_values, dv_consumer.is_some());
-        Ok(())
+        let mut sort_map = None;
+        if let (Some(sort), Some(buffered)) = (index_sort, buffered) {
+            sort_map = buffered.sort(sort)?.map(Arc::new);
+            if let Some(ref mut consumer) = dv_consumer {
+                buffered.write(consumer, sort_map.as_ref().map(Arc::as_ref))?;
+            }
+        }
+        Ok(sort_map)
     }
 
     /// Catch up for all docs before us that had no stored fields,
@@ -270,13 +296,19 @@ where
 
     fn init_stored_fields_writer(&mut self) -> Result<()> {
         if self.stored_fields_writer.is_none() {
+            let doc_writer = self.doc_writer();
+            // with an index sort, the stored fields are sorted on flush
+            let segment_info = match doc_writer.unsorted_segment_info {
+                Some(ref mut unsorted) => unsorted,
+                None => &mut doc_writer.segment_info,
+            };
             self.stored_fields_writer = Some(
                 self.doc_writer()
                     .codec()
                     .stored_fields_format()
                     .fields_writer(
                         self.doc_writer().directory.clone(),
-                        &mut self.doc_writer().segment_info,
+                        segment_info,
                         &IOContext::Default,
                     )?,
             );
@@ -285,9 +317,41 @@ where
         Ok(())
     }
 
+    /// Copies the stored fields written to the unsorted segment to the flushed
+    /// segment, in the order of the index sort.
+    fn sort_stored_fields<DW: Directory>(
+        &mut self,
+        state: &SegmentWriteState<D, DW, C>,
+        sort_map: Option<&Arc<PackedLongDocMap>>,
+    ) -> Result<()> {
+        let doc_writer = self.doc_writer();
+        let format = doc_writer.codec().stored_fields_format();
+        let max_doc = state.segment_info.max_doc();
+        let mut reader = format.fields_reader(
+            doc_writer.directory.as_ref(),
+            doc_writer.unsorted_segment_info.as_ref().unwrap(),
+            Arc::new(state.field_infos.clone()),
+            &IOContext::READ,
+        )?;
+        let mut writer = format.fields_writer(
+            doc_writer.directory.clone(),
+            &mut doc_writer.segment_info,
+            &IOContext::Default,
+        )?;
+        let mut visitor = MergeVisitor::without_remapping(&mut writer);
+        for doc in 0..max_doc {
+            let old_doc = sort_map.map_or(doc, |m| m.new_to_old(doc));
+            writer.start_document()?;
+            reader.visit_document_mut(old_doc, &mut visitor)?;
+            writer.finish_document()?;
+        }
+        writer.finish(&state.field_infos, max_doc as usize)
+    }
+
     fn write_norms<D1: Directory, D2: Directory, C1: Codec>(
         &mut self,
         state: &SegmentWriteState<D1, D2, C1>,
+        sort_map: Option<&Arc<PackedLongDocMap>>,
     ) -> Result<()> {
         let max_doc = state.segment_info.max_doc;
         if state.field_infos.has_norms {
@@ -300,11 +364,21 @@ where
                     if fi.omit_norms == false && fi.index_options != IndexOptions::Null {
                         debug_assert!(pf.norms.is_some());
                         if pf.norms.is_some() {
-                            pf.norms.as_mut().unwrap().finish(max_doc);
-                            pf.norms
-                                .as_mut()
-                                .unwrap()
-                                .flush(state, &mut norms_consumer)?;
+                            let norms = pf.norms.as_mut().unwrap();
+                            norms.finish(max_doc);
+                            match sort_map {
+                                Some(sort_map) => norms.flush(
+                                    state,
+                                    &mut SortingNormsConsumer::new(&mut norms_consumer, sort_map),
+                                )?,
+                                None => norms.flush(state, &mut norms_consumer)?,
+                            }
+                            if let Some(fi) = state.field_infos.field_info_by_name(name) {
+                                fi.put_attribute(
+                                    NORM_ENCODING_ATTRIBUTE.to_string(),
+                                    pf.norm_encoding.name.to_string(),
+                                );
+                            }
                         }
                     }
                 }
@@ -438,6 +512,10 @@ where
         // Make sure we have a PerField allocated
         if idx == self.field_hash.len() {
             // First time we are seeing this field in this segment
+            let norm_encoding = self
+                .doc_writer()
+                .index_writer_config
+                .field_norm_encoding(name);
             let mut fi = self.field_infos.get_or_add(name)?;
             // Messy: must set this here because e.g. FreqProxTermsWriterPerField looks at the
             // initial IndexOptions to decide what arrays it must create).  Then, we
@@ -445,7 +523,7 @@ where
             // the index options:
             fi.set_index_options(field_type.index_options);
 
-            let fp = PerField::new(&mut fi, invert, &mut self.terms_hash);
+            let fp = PerField::new(&mut fi, invert, norm_encoding, &mut self.terms_hash);
             self.field_hash.push(fp);
 
             if let Some(fp) = self.field_hash.last_mut() {
@@ -668,20 +746,24 @@ where
         res
     }
 
-    fn flush<DW: Directory>(&mut self, state: &mut SegmentWriteState<D, DW, C>) -> Result<()> {
+    fn flush<DW: Directory>(
+        &mut self,
+        state: &mut SegmentWriteState<D, DW, C>,
+    ) -> Result<Option<Arc<PackedLongDocMap>>> {
         debug_assert!(self.inited);
         // NOTE: caller (DocumentsWriterPerThread) handles
         // aborting on any exception from this method
         let max_doc = state.segment_info.max_doc();
-        self.write_norms(state)?;
 
         for per_field in &mut self.field_hash {
             let field_info = self.field_infos.get_or_add(&per_field.invert_state.name)?;
             per_field.reset_field_info_ptr(field_info);
         }
 
-        self.write_doc_values(state)?;
-        self.write_points(state)?;
+        // the index sort is computed from the doc values, so they go first
+        let sort_map = self.write_doc_values(state)?;
+        self.write_norms(state, sort_map.as_ref())?;
+        self.write_points(state, sort_map.as_ref())?;
 
         // it's possible all docs hit non-aborting exceptions...
         self.init_stored_fields_writer()?;
@@ -691,6 +773,9 @@ where
             .unwrap()
             .finish(&state.field_infos, max_doc as usize)?;
         self.stored_fields_writer = None;
+        if self.doc_writer().unsorted_segment_info.is_some() {
+            self.sort_stored_fields(state, sort_map.as_ref())?;
+        }
 
         {
             let mut fields_to_flush = BTreeMap::new();
@@ -714,7 +799,8 @@ where
             // FreqProxTermsWriter does this with
             // FieldInfo.storePayload.
 
-            self.terms_hash.flush(fields_to_flush, state)?;
+            self.terms_hash
+                .flush(fields_to_flush, state, sort_map.as_ref())?;
         }
 
         let codec = self.doc_writer().codec();
@@ -724,10 +810,14 @@ where
             "",
             &state.field_infos,
             &IOContext::Default,
-        )
+        )?;
+        Ok(sort_map)
     }
 
     fn abort(&mut self) -> Result<()> {
+        // close the stored fields files, so that the aborted segment's files
+        // can be deleted
+        self.stored_fields_writer = None;
         let res = self.terms_hash.abort();
         self.field_hash.clear();
         res
@@ -738,6 +828,7 @@ pub struct PerField<T: TermsHashPerField> {
     name: String,
     field_info: *mut FieldInfo,
     // similarity: Similarity,
+    norm_encoding: NormEncoding,
     invert_state: FieldInvertState,
     pub term_hash_per_field: Option<T>,
     // Non-null if this field ever had doc values in this segment:
@@ -755,6 +846,7 @@ impl<T: TermsHashPerField> PerField<T> {
     fn new<D: Directory, C: Codec, TH: TermsHash<D, C, PerField = T>>(
         field_info: &mut FieldInfo,
         invert: bool,
+        norm_encoding: NormEncoding,
         terms_hash: &mut TH,
     ) -> Self {
         let term_hash_per_field: Option<T> = None;
@@ -762,6 +854,7 @@ impl<T: TermsHashPerField> PerField<T> {
         let mut per_field = PerField {
             name: field_info.name.clone(),
             field_info,
+            norm_encoding,
             invert_state,
             term_hash_per_field,
             doc_values_writer: None,
@@ -808,10 +901,10 @@ impl<T: TermsHashPerField> PerField<T> {
         if self.field_info().omit_norms == false && self.invert_state.length != 0 {
             debug_assert!(self.norms.is_some());
             let doc_id = doc_state.doc_id;
-            self.norms
-                .as_mut()
-                .unwrap()
-                .add_value(doc_id, BM25Similarity::compute_norm(&self.invert_state));
+            self.norms.as_mut().unwrap().add_value(
+                doc_id,
+                (self.norm_encoding.compute_norm)(&self.invert_state),
+            );
         }
 
         self.term_hash_per_field
@@ -868,7 +961,7 @@ impl<T: TermsHashPerField> PerField<T> {
                 .omit_norms = true;
         }
 
-        // let analyzed = field.field_type().tokenized() && doc_state.analyzer.is_some();
+        let analyzed = field.field_type().tokenized;
         // only bother checking offsets if something will consume them.
         // TODO: after we fix analyzers, also check if termVectorOffsets will be indexed.
         let check_offset = index_options == IndexOptions::DocsAndFreqsAndPositionsAndOffsets;
@@ -877,7 +970,25 @@ impl<T: TermsHashPerField> PerField<T> {
         // write the field name to the infostream when we fail. We expect some caller to
         // eventually deal with the real exception, so we don't want any 'catch' clauses,
         // but rather a finally that takes note of the problem.
-        let mut token_stream: Box<dyn TokenStream> = field.token_stream()?;
+        let analyzer = Arc::clone(&index_chain.doc_writer().index_writer_config.analyzer);
+        let mut token_stream: Box<dyn TokenStream> = field.token_stream(analyzer.as_ref())?;
+        if token_stream.position_length_attribute().is_some() {
+            // the index keeps a single position per token, a token graph like
+            // the one of multi-word synonyms has to be flattened
+            if index_chain
+                .doc_writer()
+                .index_writer()
+                .graph_flattened(field.name())
+            {
+                warn!(
+                    "flattening the token graph of field '{}' to index it, the positions of the \
+                     paths that aren't the longest are lost: add a FlattenGraphFilter at the end \
+                     of the index analyzer to keep this behavior without the warning",
+                    field.name()
+                );
+            }
+            token_stream = Box::new(FlattenGraphFilter::new(token_stream));
+        }
         token_stream.reset()?;
 
         self.term_hash_per_field
@@ -965,10 +1076,10 @@ impl<T: TermsHashPerField> PerField<T> {
             .get_position_increment() as i32;
         self.invert_state.offset += token_stream.offset_attribute_mut().end_offset();
 
-        //        if analyzed {
-        //            self.invert_state.position += doc_state.analyzer.get_position_increment_gap();
-        //            self.invert_state.offset += doc_state.analyzer.get_offset_gap();
-        //        }
+        if analyzed {
+            self.invert_state.position += analyzer.position_increment_gap(field.name());
+            self.invert_state.offset += analyzer.offset_gap(field.name());
+        }
 
         self.invert_state.boost *= field.boost();
 
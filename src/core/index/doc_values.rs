@@ -13,11 +13,11 @@
 
 use core::codec::Codec;
 use core::index::{
-    BinaryDocValuesRef, MultiTermIterator, NumericDocValues, NumericDocValuesContext,
-    NumericDocValuesRef, ReaderSlice, SearchLeafReader, SingletonSortedNumericDocValues,
-    SingletonSortedSetDocValues, SortedDocValues, SortedDocValuesRef, SortedNumericDocValues,
-    SortedNumericDocValuesRef, SortedSetDocValues, SortedSetDocValuesRef, TermIterator,
-    TermIteratorIndex, NO_MORE_ORDS,
+    BinaryDocValuesRef, DocValuesTermIterator, DocValuesType, IndexReader, MultiTermIterator,
+    NumericDocValues, NumericDocValuesContext, NumericDocValuesRef, ReaderSlice, SearchLeafReader,
+    SingletonSortedNumericDocValues, SingletonSortedSetDocValues, SortedDocValues,
+    SortedDocValuesRef, SortedNumericDocValues, SortedNumericDocValuesRef, SortedSetDocValues,
+    SortedSetDocValuesRef, TermIterator, TermIteratorIndex, NO_MORE_ORDS,
 };
 use core::util::bit_util::BitsRequired;
 use core::util::packed::{
@@ -28,10 +28,12 @@ use core::util::{
     Bits, BitsContext, BitsRef, DocId, IdentityLongValues, LongValues, LongValuesContext,
 };
 
+use error::ErrorKind::IllegalArgument;
 use error::Result;
 
-use std::rc::Rc;
-use std::sync::Arc;
+use std::collections::HashMap;
+use std::mem;
+use std::sync::{Arc, Mutex};
 
 pub struct DocValues;
 
@@ -172,9 +174,10 @@ pub struct OrdinalMap {
     // globalOrd -> first segment container
     first_segments: PackedLongValues,
     // for every segment, segmentOrd -> globalOrd
-    segment_to_global_ords: Vec<Rc<LongValues>>,
+    segment_to_global_ords: Vec<Arc<dyn LongValues>>,
     // the map from/to segment ids
     segment_map: SegmentMap,
+    ram_bytes_used: usize,
 }
 
 impl OrdinalMap {
@@ -261,15 +264,18 @@ impl OrdinalMap {
 
         let first_segments = first_segments_builder.build();
         let global_ord_deltas = global_ord_deltas_builder.build();
+        let mut ram_bytes_used = first_segments.ram_bytes_used_estimate()
+            + global_ord_deltas.ram_bytes_used_estimate()
+            + segment_map.ram_bytes_used();
 
-        let mut segment_to_global_ords: Vec<Rc<LongValues>> = Vec::with_capacity(subs.len());
+        let mut segment_to_global_ords: Vec<Arc<dyn LongValues>> = Vec::with_capacity(subs.len());
         let mut i = 0;
         for mut d in ord_deltas {
             let deltas = d.build();
             if ord_delta_bits[i] == 0 {
                 // segment ords perfectly match global ordinals
                 // likely in case of low cardinalities and large segments
-                segment_to_global_ords.push(Rc::new(IdentityLongValues {}));
+                segment_to_global_ords.push(Arc::new(IdentityLongValues {}));
             } else {
                 let bits_required = if ord_delta_bits[i] < 0 {
                     64
@@ -296,12 +302,14 @@ impl OrdinalMap {
                         cnt += 1;
                     }
                     debug_assert_eq!(cnt as i64, size);
-                    segment_to_global_ords.push(Rc::new(MutableAsLongValues {
+                    ram_bytes_used += new_deltas.ram_bytes_used_estimate();
+                    segment_to_global_ords.push(Arc::new(MutableAsLongValues {
                         mutable: new_deltas,
                     }));
                 } else {
+                    ram_bytes_used += deltas.ram_bytes_used_estimate();
                     segment_to_global_ords
-                        .push(Rc::new(PackedLongValuesWrapper { values: deltas }));
+                        .push(Arc::new(PackedLongValuesWrapper { values: deltas }));
                 }
             }
             i += 1;
@@ -311,9 +319,55 @@ impl OrdinalMap {
             first_segments,
             segment_to_global_ords,
             segment_map,
+            ram_bytes_used,
         })
     }
 
+    /// Builds the map of the sorted (set) doc values of `field` over the leaves
+    /// of `reader`, `None` if no leaf has any. The leaves without the field map
+    /// no ord.
+    pub fn build_for_field<R: IndexReader + ?Sized>(
+        reader: &R,
+        field: &str,
+    ) -> Result<Option<OrdinalMap>> {
+        let leaves = reader.leaves();
+        let mut subs = Vec::with_capacity(leaves.len());
+        let mut weights = Vec::with_capacity(leaves.len());
+        let mut has_values = false;
+        for leaf in &leaves {
+            let dv_type = leaf
+                .reader
+                .field_info(field)
+                .map_or(DocValuesType::Null, |fi| fi.doc_values_type);
+            match dv_type {
+                DocValuesType::Sorted => {
+                    let values = leaf.reader.get_sorted_doc_values(field)?;
+                    subs.push(Some(values.term_iterator()?));
+                    weights.push(values.get_value_count());
+                    has_values = true;
+                }
+                DocValuesType::SortedSet => {
+                    let values = leaf.reader.get_sorted_set_doc_values(field)?;
+                    subs.push(Some(values.term_iterator()?));
+                    weights.push(values.get_value_count());
+                    has_values = true;
+                }
+                DocValuesType::Null => {
+                    subs.push(Some(DocValuesTermIterator::empty()));
+                    weights.push(0);
+                }
+                _ => bail!(IllegalArgument(format!(
+                    "field '{}' has {:?} doc values, not sorted ones",
+                    field, dv_type
+                ))),
+            }
+        }
+        if !has_values {
+            return Ok(None);
+        }
+        Self::build(subs, weights, COMPACT).map(Some)
+    }
+
     pub fn value_count(&self) -> i64 {
         self.global_ord_deltas.size()
     }
@@ -328,9 +382,103 @@ impl OrdinalMap {
         global_ord - self.global_ord_deltas.get64(global_ord).unwrap()
     }
 
-    pub fn get_global_ords(&self, index: usize) -> Rc<LongValues> {
+    pub fn get_global_ords(&self, index: usize) -> Arc<dyn LongValues> {
+        let i = self.segment_map.old_to_new(index as i32) as usize;
+        Arc::clone(&self.segment_to_global_ords[i])
+    }
+
+    /// Returns the global ord of the ord `segment_ord` of segment `index`.
+    pub fn global_ord(&self, index: usize, segment_ord: i64) -> Result<i64> {
         let i = self.segment_map.old_to_new(index as i32) as usize;
-        Rc::clone(&self.segment_to_global_ords[i])
+        self.segment_to_global_ords[i].get64(segment_ord)
+    }
+
+    /// Returns an estimate of the heap used by the map.
+    pub fn ram_bytes_used(&self) -> usize {
+        self.ram_bytes_used
+    }
+}
+
+// the map of a field with the core cache keys of the leaves it was built on,
+// `None` until it is built
+type OrdinalMapEntry = Mutex<Option<(Vec<String>, Option<Arc<OrdinalMap>>)>>;
+
+/// The `OrdinalMap`s of the fields of a top level reader, built on first use.
+///
+/// The map of a field is kept with the core cache keys of the leaves it was
+/// built on, so a reopened reader inherits the maps of the fields whose
+/// segments didn't change, see `#inherit`. Otherwise the map is rebuilt from
+/// all the segments.
+#[derive(Default)]
+pub struct OrdinalMapCache {
+    maps: Mutex<HashMap<String, Arc<OrdinalMapEntry>>>,
+}
+
+impl OrdinalMapCache {
+    /// Returns the map of `field` over the leaves of `reader`, building and
+    /// caching it if it isn't cached yet.
+    pub fn get_or_build<R: IndexReader + ?Sized>(
+        &self,
+        reader: &R,
+        field: &str,
+    ) -> Result<Option<Arc<OrdinalMap>>> {
+        let core_keys = Self::core_keys(reader);
+        let entry = Arc::clone(
+            self.maps
+                .lock()?
+                .entry(field.to_string())
+                .or_insert_with(Arc::default),
+        );
+        // only the entry of the field is locked while building, so the map is
+        // built once without blocking the other fields
+        let mut cached = entry.lock()?;
+        if let Some((ref keys, ref map)) = *cached {
+            if *keys == core_keys {
+                return Ok(map.clone());
+            }
+        }
+        let map = OrdinalMap::build_for_field(reader, field)?.map(Arc::new);
+        *cached = Some((core_keys, map.clone()));
+        Ok(map)
+    }
+
+    /// Takes over the maps of `other` built on the same segments as the
+    /// leaves of `reader`.
+    pub fn inherit<R: IndexReader + ?Sized>(
+        &self,
+        other: &OrdinalMapCache,
+        reader: &R,
+    ) -> Result<()> {
+        let core_keys = Self::core_keys(reader);
+        let mut inherited = HashMap::new();
+        for (field, entry) in other.maps.lock()?.iter() {
+            if let Some((ref keys, _)) = *entry.lock()? {
+                if *keys == core_keys {
+                    inherited.insert(field.clone(), Arc::clone(entry));
+                }
+            }
+        }
+        self.maps.lock()?.extend(inherited);
+        Ok(())
+    }
+
+    /// Returns an estimate of the heap used by the cached maps.
+    pub fn ram_bytes_used(&self) -> Result<usize> {
+        let mut ram_bytes_used = 0;
+        for entry in self.maps.lock()?.values() {
+            if let Some((_, Some(ref map))) = *entry.lock()? {
+                ram_bytes_used += map.ram_bytes_used();
+            }
+        }
+        Ok(ram_bytes_used)
+    }
+
+    fn core_keys<R: IndexReader + ?Sized>(reader: &R) -> Vec<String> {
+        reader
+            .leaves()
+            .iter()
+            .map(|leaf| leaf.reader.core_cache_key().to_string())
+            .collect()
     }
 }
 
@@ -358,6 +506,10 @@ impl SegmentMap {
         self.old_to_new[segment as usize]
     }
 
+    fn ram_bytes_used(&self) -> usize {
+        (self.new_to_old.len() + self.old_to_new.len()) * mem::size_of::<i32>()
+    }
+
     fn map(weights: &[usize]) -> Vec<i32> {
         let mut new_to_old: Vec<i32> = (0..weights.len() as i32).collect();
         new_to_old.sort_by(|i, j| weights[*j as usize].cmp(&weights[*i as usize]));
@@ -422,3 +574,183 @@ impl NumericDocValues for PackedLongValuesWrapper {
         Ok((self.get64(doc_id as i64)?, ctx))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    extern crate rand;
+
+    use super::*;
+    use core::codec::CodecEnum;
+    use core::doc::{SortedSetDocValuesField, Store, StringField};
+    use core::index::index_writer_config::IndexWriterConfig;
+    use core::index::merge_policy::TieredMergePolicy;
+    use core::index::merge_scheduler::SerialMergeScheduler;
+    use core::index::{Fieldable, IndexWriter, StandardDirectoryReader};
+    use core::store::RAMDirectory;
+    use core::util::tests::seeded_rng;
+
+    use self::rand::Rng;
+
+    use std::collections::BTreeSet;
+
+    type Reader =
+        StandardDirectoryReader<RAMDirectory, CodecEnum, SerialMergeScheduler, TieredMergePolicy>;
+    type Writer = IndexWriter<RAMDirectory, CodecEnum, SerialMergeScheduler, TieredMergePolicy>;
+
+    fn writer() -> Writer {
+        let dir = Arc::new(RAMDirectory::new());
+        IndexWriter::new(dir, Arc::new(IndexWriterConfig::default())).unwrap()
+    }
+
+    // adds the docs of `ids` with a few values of `terms` each, or without
+    // the field if `terms` is empty
+    fn add_docs(writer: &Writer, ids: ::std::ops::Range<usize>, terms: &[String]) {
+        let mut rng = seeded_rng();
+        for id in ids {
+            let mut doc: Vec<Box<dyn Fieldable>> =
+                vec![Box::new(StringField::new("id", &id.to_string(), Store::No))];
+            if !terms.is_empty() {
+                for _ in 0..rng.gen_range(1, 4) {
+                    let term = &terms[rng.gen_range(0, terms.len())];
+                    doc.push(Box::new(SortedSetDocValuesField::new(
+                        "color",
+                        term.as_bytes(),
+                    )));
+                }
+            }
+            writer.add_document(doc).unwrap();
+        }
+    }
+
+    fn terms(prefix: &str, range: ::std::ops::Range<usize>) -> Vec<String> {
+        range.map(|i| format!("{}{:04}", prefix, i)).collect()
+    }
+
+    // checks the map against the terms of the segments
+    fn check_ordinal_map(reader: &Reader, map: &OrdinalMap) {
+        let leaves = reader.leaves();
+        let mut values = Vec::with_capacity(leaves.len());
+        let mut all_terms = BTreeSet::new();
+        for leaf in &leaves {
+            let dv = match leaf.reader.field_info("color") {
+                Some(_) => leaf.reader.get_sorted_set_doc_values("color").unwrap(),
+                None => {
+                    values.push(None);
+                    continue;
+                }
+            };
+            for ord in 0..dv.get_value_count() as i64 {
+                all_terms.insert(dv.lookup_ord(ord).unwrap());
+            }
+            values.push(Some(dv));
+        }
+        let all_terms: Vec<Vec<u8>> = all_terms.into_iter().collect();
+        assert_eq!(map.value_count() as usize, all_terms.len());
+
+        // the global ords are the ords of the terms over all the segments
+        for (i, dv) in values.iter().enumerate() {
+            if let Some(dv) = dv {
+                let global_ords = map.get_global_ords(i);
+                for ord in 0..dv.get_value_count() as i64 {
+                    let global_ord = map.global_ord(i, ord).unwrap();
+                    assert_eq!(global_ords.get64(ord).unwrap(), global_ord);
+                    assert_eq!(dv.lookup_ord(ord).unwrap(), all_terms[global_ord as usize]);
+                }
+            }
+        }
+        // and the reverse lookup finds them back in a segment
+        for (global_ord, term) in all_terms.iter().enumerate() {
+            let global_ord = global_ord as i64;
+            let segment = map.first_segment_number(global_ord) as usize;
+            let ord = map.first_segment_ord(global_ord);
+            let dv = values[segment].as_ref().unwrap();
+            assert_eq!(&dv.lookup_ord(ord).unwrap(), term);
+            assert_eq!(map.global_ord(segment, ord).unwrap(), global_ord);
+        }
+        assert!(map.ram_bytes_used() > 0);
+    }
+
+    #[test]
+    fn test_ordinal_map_consistency() {
+        let writer = writer();
+        // overlapping segments, a disjoint one, and one without the field
+        add_docs(&writer, 0..200, &terms("a", 0..300));
+        writer.get_reader(true, false).unwrap();
+        add_docs(&writer, 200..300, &terms("a", 150..400));
+        writer.get_reader(true, false).unwrap();
+        add_docs(&writer, 300..350, &terms("b", 0..50));
+        writer.get_reader(true, false).unwrap();
+        add_docs(&writer, 350..360, &[]);
+        writer.get_reader(true, false).unwrap();
+        add_docs(&writer, 360..1000, &terms("a", 0..1000));
+
+        let reader = writer.get_reader(true, false).unwrap();
+        assert_eq!(reader.leaves().len(), 5);
+        let map = reader.ordinal_map("color").unwrap().unwrap();
+        check_ordinal_map(&reader, &map);
+        assert!(reader.ordinal_map("id").is_err());
+        assert!(reader.ordinal_map("missing").unwrap().is_none());
+
+        // a single segment maps its ords to themselves
+        writer.force_merge(1, true).unwrap();
+        let merged = writer.get_reader(true, false).unwrap();
+        let merged_map = merged.ordinal_map("color").unwrap().unwrap();
+        assert_eq!(merged_map.value_count(), map.value_count());
+        check_ordinal_map(&merged, &merged_map);
+    }
+
+    #[test]
+    fn test_ordinal_map_cache() {
+        let writer = writer();
+        add_docs(&writer, 0..100, &terms("a", 0..50));
+        writer.get_reader(true, false).unwrap();
+        add_docs(&writer, 100..200, &terms("a", 25..75));
+        let reader = writer.get_reader(true, false).unwrap();
+
+        let map = reader.ordinal_map("color").unwrap().unwrap();
+        assert!(Arc::ptr_eq(
+            &map,
+            &reader.ordinal_map("color").unwrap().unwrap()
+        ));
+        assert_eq!(
+            reader.ordinal_maps_ram_bytes_used().unwrap(),
+            map.ram_bytes_used()
+        );
+
+        // deletes leave the segment cores, and so the map, as they are
+        writer.delete_documents_by_term("id", b"3").unwrap();
+        let reopened = reader.open_if_changed(None).unwrap().unwrap();
+        assert_eq!(reopened.num_docs(), 199);
+        assert!(Arc::ptr_eq(
+            &map,
+            &reopened.ordinal_map("color").unwrap().unwrap()
+        ));
+
+        // a new segment gets the map rebuilt
+        add_docs(&writer, 200..300, &terms("b", 0..10));
+        let reopened = reopened.open_if_changed(None).unwrap().unwrap();
+        let rebuilt = reopened.ordinal_map("color").unwrap().unwrap();
+        assert!(!Arc::ptr_eq(&map, &rebuilt));
+        assert_eq!(rebuilt.value_count(), map.value_count() + 10);
+        check_ordinal_map(&reopened, &rebuilt);
+    }
+
+    #[test]
+    fn test_ordinal_map_cache_per_field() {
+        let writer = writer();
+        add_docs(&writer, 0..100, &terms("a", 0..50));
+        writer.get_reader(true, false).unwrap();
+        add_docs(&writer, 100..200, &terms("a", 25..75));
+        let reader = writer.get_reader(true, false).unwrap();
+
+        let cache = OrdinalMapCache::default();
+        let map = cache.get_or_build(&reader, "color").unwrap().unwrap();
+        {
+            // a map being built only holds the lock of its own field
+            let entry = Arc::clone(&cache.maps.lock().unwrap()["color"]);
+            let _building = entry.lock().unwrap();
+            assert!(cache.get_or_build(&reader, "missing").unwrap().is_none());
+        }
+        assert_eq!(cache.ram_bytes_used().unwrap(), map.ram_bytes_used());
+    }
+}
@@ -141,12 +141,6 @@ impl BinaryDocValuesWriter {
                 self.field_info.name
             )));
         }
-        if value.len() == 0 {
-            bail!(IllegalArgument(format!(
-                "field={}: null value not allowed",
-                self.field_info.name
-            )));
-        }
         if value.len() > MAX_ARRAY_LENGTH {
             bail!(IllegalArgument(format!(
                 "DocValuesField {} is too large, must be <={}",
@@ -1715,13 +1709,14 @@ impl<'a> BinaryBytesIterator<'a> {
 }
 
 impl<'a> Iterator for BinaryBytesIterator<'a> {
-    type Item = Result<BytesRef>;
+    type Item = Result<Option<BytesRef>>;
 
-    fn next(&mut self) -> Option<Result<BytesRef>> {
+    fn next(&mut self) -> Option<Result<Option<BytesRef>>> {
         if !self.has_next() {
             return None;
         }
 
+        let mut has_value = false;
         if self.upto < self.size {
             let length = self.lengths_iter.next().unwrap() as usize;
             self.value.resize(length, 0u8);
@@ -1735,17 +1730,18 @@ impl<'a> Iterator for BinaryBytesIterator<'a> {
                 Err(e) => {
                     return Some(Err(e));
                 }
-                Ok(false) => {
-                    self.value.clear();
+                Ok(v) => {
+                    has_value = v;
                 }
-                Ok(true) => {}
             }
-        } else {
-            self.value.clear();
         }
 
         self.upto += 1;
-        Some(Ok(BytesRef::new(&self.value)))
+        if has_value {
+            Some(Ok(Some(BytesRef::new(&self.value))))
+        } else {
+            Some(Ok(None))
+        }
     }
 }
 
@@ -12,7 +12,7 @@
 // limitations under the License.
 
 use core::codec::Codec;
-use core::index::doc_writer_delete_queue::DocumentsWriterDeleteQueue;
+use core::index::doc_writer_delete_queue::{DeleteNode, DocumentsWriterDeleteQueue};
 use core::index::doc_writer_flush_queue::DocumentsWriterFlushQueue;
 use core::index::flush_control::DocumentsWriterFlushControl;
 use core::index::flush_policy::FlushByRamOrCountsPolicy;
@@ -199,7 +199,7 @@ where
         &self,
         docs: Vec<Vec<F>>,
         // analyzer: Analyzer,
-        del_term: Option<Term>,
+        del_node: Option<DeleteNode<C>>,
     ) -> Result<(u64, bool)> {
         debug_assert!(self.inited);
         let has_events = self.pre_update()?;
@@ -216,7 +216,7 @@ where
                 }
             };
             let per_thread_mut = per_thread.thread_state_mut(&l);
-            self.do_update_documents(per_thread_mut, docs, del_term)?
+            self.do_update_documents(per_thread_mut, docs, del_node)?
         };
 
         self.per_thread_pool.release(per_thread);
@@ -230,9 +230,9 @@ where
         per_thread: &mut ThreadState<D, C, MS, MP>,
         docs: Vec<Vec<F>>,
         // analyzer: Analyzer,
-        del_term: Option<Term>,
+        del_node: Option<DeleteNode<C>>,
     ) -> Result<(u64, Option<DocumentsWriterPerThread<D, C, MS, MP>>)> {
-        let is_update = del_term.is_some();
+        let is_update = del_node.is_some();
 
         // This must happen after we've pulled the ThreadState because IW.close
         // waits for all ThreadStates to be released:
@@ -241,7 +241,7 @@ where
         debug_assert!(per_thread.inited());
         let dwpt_num_docs = per_thread.dwpt().num_docs_in_ram;
 
-        let res = per_thread.dwpt_mut().update_documents(docs, del_term);
+        let res = per_thread.dwpt_mut().update_documents(docs, del_node);
         let num_docs_in_ram = if res.is_err() {
             // TODO, we should only deal with AbortException here instead of
             // all errors
@@ -278,7 +278,7 @@ where
         &self,
         doc: Vec<F>,
         // analyzer: Analyzer,
-        del_term: Option<Term>,
+        del_node: Option<DeleteNode<C>>,
     ) -> Result<(u64, bool)> {
         debug_assert!(self.inited);
         let mut has_event = self.pre_update()?;
@@ -295,7 +295,7 @@ where
                 }
             };
             let per_thread_mut = per_thread.thread_state_mut(&guard);
-            self.do_update_document(per_thread_mut, doc, del_term)?
+            self.do_update_document(per_thread_mut, doc, del_node)?
         };
         self.per_thread_pool.release(per_thread);
 
@@ -309,9 +309,9 @@ where
         per_thread: &mut ThreadState<D, C, MS, MP>,
         doc: Vec<F>,
         // analyzer: Analyzer,
-        del_term: Option<Term>,
+        del_node: Option<DeleteNode<C>>,
     ) -> Result<(u64, Option<DocumentsWriterPerThread<D, C, MS, MP>>)> {
-        let is_update = del_term.is_some();
+        let is_update = del_node.is_some();
 
         // This must happen after we've pulled the ThreadState because IW.close
         // waits for all ThreadStates to be released:
@@ -320,7 +320,7 @@ where
         debug_assert!(per_thread.inited());
 
         let dwpt_num_docs = per_thread.dwpt().num_docs_in_ram;
-        let res = per_thread.dwpt_mut().update_document(doc, del_term);
+        let res = per_thread.dwpt_mut().update_document(doc, del_node);
         let num_docs_in_ram = if res.is_err() {
             // TODO, we should only deal with AbortException here instead of
             // all errors
@@ -447,6 +447,50 @@ where
         Ok((seq_no, applyed))
     }
 
+    pub fn update_numeric_doc_value(
+        &self,
+        term: Term,
+        field: String,
+        value: i64,
+    ) -> Result<(u64, bool)> {
+        debug_assert!(self.inited);
+        let l = self.lock.lock()?;
+        let doc_writer_mut = unsafe { self.doc_writer_mut(&l) };
+        let seq_no = self.delete_queue.add_numeric_update(term, field, value)?;
+        doc_writer_mut.flush_control.do_on_delete();
+
+        let applyed = self.apply_all_deletes_local()?;
+        doc_writer_mut.last_seq_no = max(self.last_seq_no, seq_no);
+        Ok((seq_no, applyed))
+    }
+
+    /// Flushes the next pending DWPT, or else the most RAM consuming one.
+    /// Returns false if there was no buffered document to flush.
+    pub fn flush_one_dwpt(&self) -> Result<bool> {
+        self.ensure_open()?;
+        let dwpt = match self.flush_control.next_pending_flush() {
+            Some(dwpt) => Some(dwpt),
+            None => self.flush_control.checkout_largest_non_pending_writer(),
+        };
+        match dwpt {
+            Some(dwpt) => {
+                self.do_flush(dwpt)?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Returns the bytes used by the buffered documents, including the ones
+    /// pending or being flushed.
+    pub fn net_bytes(&self) -> u64 {
+        self.flush_control.net_bytes()
+    }
+
+    pub fn num_flushing_writers(&self) -> usize {
+        self.flush_control.num_flushing_writers()
+    }
+
     pub fn num_docs(&self) -> u32 {
         self.num_docs_in_ram.load(Ordering::Acquire)
     }
@@ -683,14 +727,27 @@ where
         };
 
         let mut anything_flushed = false;
+        // a failed flush only discards the docs of its own DWPT, the pending
+        // ones are still flushed, and the first failure is returned after
+        let mut flush_error = None;
         loop {
             // Help out with flushing:
             if let Some(flushing_dwpt) = self.flush_control.next_pending_flush() {
-                anything_flushed |= self.do_flush(flushing_dwpt)?;
+                match self.do_flush(flushing_dwpt) {
+                    Ok(flushed) => anything_flushed |= flushed,
+                    Err(e) => {
+                        if flush_error.is_none() {
+                            flush_error = Some(e);
+                        }
+                    }
+                }
             } else {
                 break;
             }
         }
+        if let Some(e) = flush_error {
+            return Err(e);
+        }
         // If a concurrent flush is still in flight wait for it
         self.flush_control.wait_for_flush()?;
         if !anything_flushed && flushing_queue.any_changes() {
@@ -709,18 +766,22 @@ where
         Ok((anything_flushed, seq_no))
     }
 
-    pub fn finish_full_flush(&self, success: bool) {
+    /// Ends a full flush, aborting the DWPTs still pending if it failed.
+    /// Returns the number of buffered docs aborted that way.
+    pub fn finish_full_flush(&self, success: bool) -> u32 {
         debug!(
             "DW - {:?} finish full flush, success={}",
             thread::current().name(),
             success
         );
-        if success {
+        let aborted_docs = if success {
             self.flush_control.finish_full_flush();
+            0
         } else {
-            self.flush_control.abort_full_flushes();
-        }
+            self.flush_control.abort_full_flushes()
+        };
         self.pending_changes_in_current_full_flush.write(false);
+        aborted_docs
     }
 
     pub fn close(&mut self) {
@@ -133,11 +133,23 @@ impl<C: Codec> DocumentsWriterDeleteQueue<C> {
         Ok(seq_no)
     }
 
-    /// invariant for document update
-    pub fn add_term_to_slice(&self, term: Term, slice: &mut DeleteSlice<C>) -> Result<u64> {
-        let del_node = Arc::new(DeleteListNode::new(DeleteNode::Term(term)));
+    /// Buffers an update of the numeric doc values `field` to `value` for the
+    /// documents containing `term`.
+    pub fn add_numeric_update(&self, term: Term, field: String, value: i64) -> Result<u64> {
+        let node = Arc::new(DeleteListNode::new(DeleteNode::NumericUpdate(
+            term, field, value,
+        )));
+        let seq_no = self.add_node(node)?;
+        self.try_apply_global_slice()?;
+        Ok(seq_no)
+    }
+
+    /// invariant for document update, `node` is the delete term or the doc
+    /// values update of the updated documents
+    pub fn add_to_slice(&self, node: DeleteNode<C>, slice: &mut DeleteSlice<C>) -> Result<u64> {
+        let del_node = Arc::new(DeleteListNode::new(node));
         let seq_no = self.add_node(Arc::clone(&del_node))?;
-        // this is an update request where the term is the updated documents
+        // this is an update request where the node is the updated documents
         // delTerm. in that case we need to guarantee that this insert is atomic
         // with regards to the given delete slice. This means if two threads try to
         // update the same document with in turn the same delTerm one of them must
@@ -276,10 +288,11 @@ impl<C: Codec> DocumentsWriterDeleteQueue<C> {
     }
 }
 
-enum DeleteNode<C: Codec> {
+pub enum DeleteNode<C: Codec> {
     Term(Term),
     TermArray(Vec<Term>),
     QueryArray(Vec<Arc<dyn Query<C>>>),
+    NumericUpdate(Term, String, i64),
     None,
     // used for sentinel head
 }
@@ -300,6 +313,14 @@ impl<C: Codec> DeleteNode<C> {
                     buffered_deletes.add_query(Arc::clone(q), doc_id_upto);
                 }
             }
+            DeleteNode::NumericUpdate(term, field, value) => {
+                buffered_deletes.add_numeric_update(
+                    term.clone(),
+                    field.clone(),
+                    *value,
+                    doc_id_upto,
+                );
+            }
             DeleteNode::None => {
                 unreachable!();
             }
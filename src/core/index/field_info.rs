@@ -28,10 +28,18 @@ use serde::{Serialize, Serializer};
 
 // use core::attribute::{OffsetAttribute, PayloadAttribute, PositionIncrementAttribute};
 use core::codec::{PER_FIELD_POSTING_FORMAT_KEY, PER_FIELD_POSTING_SUFFIX_KEY};
+use core::codec::{PER_FIELD_VALUE_FORMAT_KEY, PER_FIELD_VALUE_SUFFIX_KEY};
 use core::index::point_values;
 use core::index::term::*;
 use core::index::{DocValuesType, IndexOptions};
 
+const PER_FIELD_FORMAT_KEYS: [&str; 4] = [
+    PER_FIELD_POSTING_FORMAT_KEY,
+    PER_FIELD_POSTING_SUFFIX_KEY,
+    PER_FIELD_VALUE_FORMAT_KEY,
+    PER_FIELD_VALUE_SUFFIX_KEY,
+];
+
 fn variant_eq<T>(a: &T, b: &T) -> bool {
     discriminant(a) == discriminant(b)
 }
@@ -431,18 +439,6 @@ impl FieldInfos {
         let mut by_name: HashMap<String, FieldInfoRef> = HashMap::new();
         let mut max_number = 0;
 
-        let mut infos = infos;
-        for info in &mut infos {
-            if info.index_options != IndexOptions::Null {
-                // force put attr...
-                info.put_attribute(
-                    PER_FIELD_POSTING_FORMAT_KEY.to_string(),
-                    "Lucene50".to_string(),
-                );
-                info.put_attribute(PER_FIELD_POSTING_SUFFIX_KEY.to_string(), "0".to_string());
-            }
-        }
-
         for info in infos {
             let info = Arc::new(info);
             let number = info.number;
@@ -579,7 +575,18 @@ impl<T: AsRef<FieldNumbers>> FieldInfosBuilder<T> {
             fi.doc_values_type,
             fi.point_dimension_count,
             fi.point_num_bytes,
-        )
+        )?;
+        // keep the attributes, e.g. the norm encoding, when merging, but not
+        // the formats: they are recorded again when the field is written
+        let attributes = fi.attributes.read().unwrap().clone();
+        if let Some(field_info) = self.by_name.get(&fi.name) {
+            for (key, value) in attributes {
+                if !PER_FIELD_FORMAT_KEYS.contains(&key.as_str()) {
+                    field_info.put_attribute(key, value);
+                }
+            }
+        }
+        Ok(())
     }
 
     fn add_or_update_internal(
@@ -690,6 +697,11 @@ impl FieldNumbers {
         )
     }
 
+    /// Returns true if the field exists and has doc values of `dv_type`.
+    pub fn contains(&self, field_name: &str, dv_type: DocValuesType) -> bool {
+        self.inner.lock().unwrap().contains(field_name, dv_type)
+    }
+
     pub fn clear(&self) {
         self.inner.lock().unwrap().clear();
     }
@@ -895,16 +907,11 @@ impl FieldNumbersInner {
         Ok(())
     }
 
-    // TODO used for doc values update
-    #[allow(dead_code)]
     /// return true if the field_name exists in the map and is of the type of dv_type
     fn contains(&self, field_name: &str, dv_type: DocValuesType) -> bool {
         // used by IndexWriter.updateNumericDocValue
-        if !self.name_to_number.contains_key(field_name) {
-            false
-        } else {
-            self.doc_values_type[field_name] == dv_type
-        }
+        self.name_to_number.contains_key(field_name)
+            && self.doc_values_type.get(field_name) == Some(&dv_type)
     }
 
     pub fn clear(&mut self) {
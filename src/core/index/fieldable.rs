@@ -11,7 +11,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use core::analysis::TokenStream;
+use core::analysis::{Analyzer, TokenStream};
 use core::doc::FieldType;
 use core::util::{Numeric, VariantValue};
 
@@ -22,7 +22,7 @@ pub trait Fieldable {
     fn field_type(&self) -> &FieldType;
     fn boost(&self) -> f32;
     fn fields_data(&self) -> Option<&VariantValue>;
-    fn token_stream(&mut self) -> Result<Box<dyn TokenStream>>;
+    fn token_stream(&mut self, analyzer: &dyn Analyzer) -> Result<Box<dyn TokenStream>>;
     fn binary_value(&self) -> Option<&[u8]>;
     fn string_value(&self) -> Option<&str>;
     fn numeric_value(&self) -> Option<Numeric>;
@@ -41,8 +41,8 @@ impl<T: Fieldable + ?Sized> Fieldable for Box<T> {
     fn fields_data(&self) -> Option<&VariantValue> {
         (**self).fields_data()
     }
-    fn token_stream(&mut self) -> Result<Box<dyn TokenStream>> {
-        (**self).token_stream()
+    fn token_stream(&mut self, analyzer: &dyn Analyzer) -> Result<Box<dyn TokenStream>> {
+        (**self).token_stream(analyzer)
     }
     fn binary_value(&self) -> Option<&[u8]> {
         (**self).binary_value()
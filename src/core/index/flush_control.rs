@@ -314,12 +314,16 @@ impl<D: Directory + Send + Sync + 'static, C: Codec, MS: MergeScheduler, MP: Mer
         self.flush_deletes.store(true, Ordering::Release)
     }
 
-    pub fn abort_pending_flushes(&self) {
+    /// Aborts the DWPTs waiting for a flush, returns the number of docs they
+    /// buffered.
+    pub fn abort_pending_flushes(&self) -> u32 {
+        let mut aborted_docs = 0;
         let l = self.lock.lock().unwrap();
         let control_mut = unsafe { self.flush_control_mut(&l) };
 
         let flush_queue = mem::replace(&mut control_mut.flush_queue, VecDeque::with_capacity(0));
         for mut dwpt in flush_queue {
+            aborted_docs += dwpt.num_docs_in_ram;
             self.documents_writer()
                 .subtract_flushed_num_docs(dwpt.num_docs_in_ram);
             dwpt.abort();
@@ -332,6 +336,7 @@ impl<D: Directory + Send + Sync + 'static, C: Codec, MS: MergeScheduler, MP: Mer
                 blocked_flush.dwpt.segment_info.name.clone(),
                 blocked_flush.bytes,
             );
+            aborted_docs += blocked_flush.dwpt.num_docs_in_ram;
             self.documents_writer()
                 .subtract_flushed_num_docs(blocked_flush.dwpt.num_docs_in_ram);
             blocked_flush.dwpt.abort();
@@ -340,6 +345,7 @@ impl<D: Directory + Send + Sync + 'static, C: Codec, MS: MergeScheduler, MP: Mer
 
         control_mut.flush_queue.clear();
         self.update_stall_state();
+        aborted_docs
     }
 
     pub fn is_full_flush(&self) -> bool {
@@ -438,6 +444,19 @@ impl<D: Directory + Send + Sync + 'static, C: Codec, MS: MergeScheduler, MP: Mer
         Ok(())
     }
 
+    /// Returns the bytes used by the DWPTs, both the active ones and the ones
+    /// pending or being flushed.
+    pub fn net_bytes(&self) -> u64 {
+        let _l = self.lock.lock().unwrap();
+        self.active_bytes + self.flush_bytes
+    }
+
+    /// Returns the number of DWPTs checked out for flushing.
+    pub fn num_flushing_writers(&self) -> usize {
+        let l = self.lock.lock().unwrap();
+        self.num_flushing_dwpt(&l)
+    }
+
     pub fn num_queued_flushes(&self) -> usize {
         let _l = self.lock.lock().unwrap();
         self.flush_queue.len()
@@ -493,6 +512,32 @@ impl<D: Directory + Send + Sync + 'static, C: Codec, MS: MergeScheduler, MP: Mer
         None
     }
 
+    /// Marks the most RAM consuming non-pending DWPT as flush pending and
+    /// checks it out for flushing. If its thread state is busy indexing it is
+    /// only marked, the indexing thread will flush it after its document.
+    pub fn checkout_largest_non_pending_writer(
+        &self,
+    ) -> Option<DocumentsWriterPerThread<D, C, MS, MP>> {
+        let guard = self.lock.lock().unwrap();
+        let pool = self.per_thread_pool();
+        let mut max_ram_so_far = 0;
+        let mut largest = None;
+        for i in 0..pool.active_thread_state_count() {
+            let state = pool.get_thread_state(i);
+            if !state.flush_pending() && state.bytes_used() > max_ram_so_far {
+                max_ram_so_far = state.bytes_used();
+                largest = Some(state);
+            }
+        }
+        if let Some(state) = largest {
+            let flush_control_mut = unsafe { self.flush_control_mut(&guard) };
+            flush_control_mut.set_flush_pending(&state, &guard);
+            self.try_checkout_for_flush(&state, &guard)
+        } else {
+            None
+        }
+    }
+
     // TODO, actually we didn't lock the state, this should be done by the caller
     pub fn obtain_and_lock(&self) -> Result<Arc<ThreadState<D, C, MS, MP>>> {
         let per_thread = self.per_thread_pool().get_and_lock()?;
@@ -634,9 +679,10 @@ impl<D: Directory + Send + Sync + 'static, C: Codec, MS: MergeScheduler, MP: Mer
         self.update_stall_state();
     }
 
-    pub fn abort_full_flushes(&self) {
-        self.abort_pending_flushes();
+    pub fn abort_full_flushes(&self) -> u32 {
+        let aborted_docs = self.abort_pending_flushes();
         self.full_flush.store(false, Ordering::Release);
+        aborted_docs
     }
 
     fn add_flushable_state(&self, per_therad: &mut ThreadState<D, C, MS, MP>) {
@@ -31,16 +31,26 @@ use std::collections::{HashMap, HashSet};
 /// later index commit point would have a larger N.
 // TODO: this is now a poor name, because this class also represents a
 // point-in-time view from an NRT reader
-pub trait IndexCommit<D> {
+pub trait IndexCommit<D>: IndexCommitPoint {
+    /// Return the `Directory` for the index
+    fn directory(&self) -> &D;
+
+    // package-private API for IndexWriter to init from a commit-point pulled from
+    // an NRT or non-NRT reader.
+    //    fn reader(&self) -> Option<&StandardDirectoryReader> {
+    //        None
+    //    }
+}
+
+/// The part of an `IndexCommit` that doesn't depend on the `Directory` of
+/// the index, this is what the `IndexDeletionPolicy` sees of a commit.
+pub trait IndexCommitPoint {
     /// Get the segments file (`segments_N`) associated with this commit point
     fn segments_file_name(&self) -> &str;
 
     /// Returns all index files referenced by this commit point.
     fn file_names(&self) -> Result<&HashSet<String>>;
 
-    /// Return the `Directory` for the index
-    fn directory(&self) -> &D;
-
     /// Delete this commit point.  This only applies when using
     /// the commit point in the context of IndexWriter's
     /// IndexDeletionPolicy.
@@ -68,10 +78,4 @@ pub trait IndexCommit<D> {
     /// `IndexWriter::set_live_commit_data()` for this commit.
     /// Map is (String -> String)
     fn user_data(&self) -> &HashMap<String, String>;
-
-    // package-private API for IndexWriter to init from a commit-point pulled from
-    // an NRT or non-NRT reader.
-    //    fn reader(&self) -> Option<&StandardDirectoryReader> {
-    //        None
-    //    }
 }
@@ -12,8 +12,8 @@
 // limitations under the License.
 
 use core::codec::Codec;
-use core::index::delete_policy::{IndexDeletionPolicy, KeepOnlyLastCommitDeletionPolicy};
-use core::index::index_commit::IndexCommit;
+use core::index::delete_policy::IndexDeletionPolicy;
+use core::index::index_commit::{IndexCommit, IndexCommitPoint};
 use core::index::index_writer::INDEX_WRITE_LOCK_NAME;
 use core::index::{
     generation_from_segments_file_name, parse_generation, parse_segment_name, SegmentInfos,
@@ -82,7 +82,7 @@ pub struct IndexFileDeleter<D: Directory, C: Codec> {
     commits_to_delete: Vec<CommitPoint<D>>,
     directory_orig: Arc<D>,
     directory: Arc<LockValidatingDirectoryWrapper<D>>,
-    policy: KeepOnlyLastCommitDeletionPolicy,
+    policy: Arc<dyn IndexDeletionPolicy>,
     pub starting_commit_deleted: bool,
     last_segment_infos: Option<SegmentInfos<D, C>>,
     inited: bool,
@@ -92,7 +92,7 @@ impl<D: Directory, C: Codec> IndexFileDeleter<D, C> {
     pub fn new(
         directory_orig: Arc<D>,
         directory: Arc<LockValidatingDirectoryWrapper<D>>,
-        // policy: Box<IndexDeletionPolicy>,
+        policy: Arc<dyn IndexDeletionPolicy>,
     ) -> Self {
         IndexFileDeleter {
             ref_counts: Arc::new(RwLock::new(HashMap::new())),
@@ -101,7 +101,7 @@ impl<D: Directory, C: Codec> IndexFileDeleter<D, C> {
             commits_to_delete: vec![],
             directory_orig,
             directory,
-            policy: KeepOnlyLastCommitDeletionPolicy {},
+            policy,
             starting_commit_deleted: false,
             last_segment_infos: None,
             inited: false,
@@ -223,7 +223,8 @@ impl<D: Directory, C: Codec> IndexFileDeleter<D, C> {
         // Finally, give policy a chance to remove things on
         // startup:
         {
-            let mut commits: Vec<&mut IndexCommit<D>> = Vec::with_capacity(self.commits.len());
+            let mut commits: Vec<&mut dyn IndexCommitPoint> =
+                Vec::with_capacity(self.commits.len());
             for i in &mut self.commits {
                 commits.push(i);
             }
@@ -352,7 +353,8 @@ impl<D: Directory, C: Codec> IndexFileDeleter<D, C> {
 
             // Tell policy so it can remove commits:
             {
-                let mut commits: Vec<&mut IndexCommit<D>> = Vec::with_capacity(self.commits.len());
+                let mut commits: Vec<&mut dyn IndexCommitPoint> =
+                    Vec::with_capacity(self.commits.len());
                 for i in &mut self.commits {
                     i.commits_to_delete = &mut self.commits_to_delete;
                     commits.push(i);
@@ -652,13 +654,19 @@ impl<D: Directory> CommitPoint<D> {
             directory_orig,
             commits_to_delete,
             generation: segment_infos.generation,
-            user_data: HashMap::new(),
+            user_data: segment_infos.user_data.clone(),
             segment_count: segment_infos.len(),
         }
     }
 }
 
 impl<D: Directory> IndexCommit<D> for CommitPoint<D> {
+    fn directory(&self) -> &D {
+        self.directory_orig.as_ref()
+    }
+}
+
+impl<D: Directory> IndexCommitPoint for CommitPoint<D> {
     fn segments_file_name(&self) -> &str {
         &self.segments_file_name
     }
@@ -667,10 +675,6 @@ impl<D: Directory> IndexCommit<D> for CommitPoint<D> {
         Ok(&self.files)
     }
 
-    fn directory(&self) -> &D {
-        self.directory_orig.as_ref()
-    }
-
     fn delete(&mut self) -> Result<()> {
         if !self.deleted {
             self.deleted = true;
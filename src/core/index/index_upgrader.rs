@@ -0,0 +1,370 @@
+// Copyright 2019 Zhizhesihai (Beijing) Technology Limited.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Upgrades an index to the current version, like Lucene's `IndexUpgrader`.
+//!
+//! The segments of the latest commit written by an older version, or with
+//! files in an older format, are merged one by one with the current codec,
+//! see `UpgradeIndexMergePolicy`, so they can still be read once the support
+//! of their format is dropped. The deleted documents of these segments are
+//! dropped on the way, the segments already current are left as they are.
+
+use core::codec::{codec_util, Codec, CompoundFormat};
+use core::index::index_writer_config::{IndexWriterConfig, OpenMode};
+use core::index::merge_policy::{MergePolicy, UpgradeIndexMergePolicy};
+use core::index::merge_scheduler::MergeScheduler;
+use core::index::{IndexWriter, SegmentInfo, SegmentInfos};
+use core::store::{Directory, IOContext};
+use core::util::{Version, VERSION_LATEST};
+
+use error::Result;
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::Arc;
+
+/// Reads the format versions of the files of a segment from their codec
+/// headers, looking into the compound file if the segment has one.
+///
+/// The versions are keyed by codec name, see
+/// `codec_util::current_format_version` for the codecs tracked. The oldest
+/// version is kept if several files have the same codec.
+pub fn segment_format_versions<D: Directory, C: Codec>(
+    info: &SegmentInfo<D, C>,
+) -> Result<BTreeMap<String, i32>> {
+    let mut versions = BTreeMap::new();
+    if info.is_compound_file() {
+        let cfs = info.codec().compound_format().get_compound_reader(
+            Arc::clone(&info.directory),
+            info,
+            &IOContext::READ,
+        )?;
+        read_format_versions(&cfs, &cfs.list_all()?, &mut versions)?;
+    } else {
+        let files: Vec<String> = info.files().iter().cloned().collect();
+        read_format_versions(info.directory.as_ref(), &files, &mut versions)?;
+    }
+    Ok(versions)
+}
+
+fn read_format_versions<D: Directory>(
+    dir: &D,
+    files: &[String],
+    versions: &mut BTreeMap<String, i32>,
+) -> Result<()> {
+    for file in files {
+        let mut input = dir.open_input(file, &IOContext::READ)?;
+        if let Some((codec, version)) = codec_util::read_header_version(input.as_mut())? {
+            if codec_util::current_format_version(&codec).is_some() {
+                let oldest = versions.entry(codec).or_insert(version);
+                *oldest = (*oldest).min(version);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Returns true if none of the format versions read by
+/// `segment_format_versions` is older than the current one.
+pub fn is_format_current(versions: &BTreeMap<String, i32>) -> bool {
+    versions.iter().all(|(codec, &version)| {
+        codec_util::current_format_version(codec).map_or(true, |current| version >= current)
+    })
+}
+
+/// The versions of a segment of a commit.
+#[derive(Clone, Debug)]
+pub struct SegmentVersion {
+    pub name: String,
+    /// The version that wrote the segment.
+    pub version: Version,
+    /// The format version of the files of the segment by codec name.
+    pub format_versions: BTreeMap<String, i32>,
+    pub max_doc: i32,
+    pub del_count: i32,
+}
+
+impl SegmentVersion {
+    fn read<D: Directory, C: Codec>(infos: &SegmentInfos<D, C>) -> Result<Vec<SegmentVersion>> {
+        infos
+            .segments
+            .iter()
+            .map(|s| {
+                Ok(SegmentVersion {
+                    name: s.info.name.clone(),
+                    version: s.info.version,
+                    format_versions: segment_format_versions(&s.info)?,
+                    max_doc: s.info.max_doc,
+                    del_count: s.del_count(),
+                })
+            })
+            .collect()
+    }
+
+    pub fn is_current(&self) -> bool {
+        self.version >= VERSION_LATEST && is_format_current(&self.format_versions)
+    }
+}
+
+impl fmt::Display for SegmentVersion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}: version={}, formats={:?}, max_doc={}, del_count={}",
+            self.name,
+            self.version.to_string(),
+            self.format_versions,
+            self.max_doc,
+            self.del_count
+        )
+    }
+}
+
+/// Result of `IndexUpgrader::upgrade`.
+#[derive(Debug)]
+pub struct IndexUpgradeStatus {
+    /// The segments of the commit before the upgrade.
+    pub before: Vec<SegmentVersion>,
+    /// The segments of the commit written by the upgrade.
+    pub after: Vec<SegmentVersion>,
+}
+
+impl IndexUpgradeStatus {
+    /// Returns the segments that were rewritten.
+    pub fn upgraded_segments(&self) -> Vec<&SegmentVersion> {
+        self.before
+            .iter()
+            .filter(|s| !self.after.iter().any(|a| a.name == s.name))
+            .collect()
+    }
+
+    /// Returns true if all the segments are at the current version.
+    pub fn is_current(&self) -> bool {
+        self.after.iter().all(SegmentVersion::is_current)
+    }
+}
+
+impl fmt::Display for IndexUpgradeStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "before upgrade, {} segments:", self.before.len())?;
+        for segment in &self.before {
+            writeln!(f, "  {}", segment)?;
+        }
+        writeln!(f, "after upgrade, {} segments:", self.after.len())?;
+        for segment in &self.after {
+            writeln!(f, "  {}", segment)?;
+        }
+        Ok(())
+    }
+}
+
+/// Rewrites the segments of an index written by older versions with the
+/// current codec.
+///
+/// The index must exist, and no other writer may be open on it.
+pub struct IndexUpgrader<D, C, MS, MP>
+where
+    D: Directory + Send + Sync + 'static,
+    C: Codec,
+    MS: MergeScheduler,
+    MP: MergePolicy,
+{
+    directory: Arc<D>,
+    config: Arc<IndexWriterConfig<C, MS, UpgradeIndexMergePolicy<MP>>>,
+}
+
+impl<D, C, MS, MP> IndexUpgrader<D, C, MS, MP>
+where
+    D: Directory + Send + Sync + 'static,
+    C: Codec,
+    MS: MergeScheduler,
+    MP: MergePolicy,
+{
+    /// The merge policy of `config` is wrapped in an `UpgradeIndexMergePolicy`
+    /// and the index is opened in `OpenMode::Append`.
+    pub fn new(directory: Arc<D>, mut config: IndexWriterConfig<C, MS, MP>) -> Self {
+        config.open_mode = OpenMode::Append;
+        IndexUpgrader {
+            directory,
+            config: Arc::new(config.map_merge_policy(UpgradeIndexMergePolicy::new)),
+        }
+    }
+
+    pub fn upgrade(&self) -> Result<IndexUpgradeStatus> {
+        let infos: SegmentInfos<D, C> = SegmentInfos::read_latest_commit(&self.directory)?;
+        let before = SegmentVersion::read(&infos)?;
+
+        let writer = IndexWriter::new(Arc::clone(&self.directory), Arc::clone(&self.config))?;
+        let res = writer.force_merge(1, true).and_then(|_| writer.commit());
+        if let Err(e) = res {
+            let _ = writer.rollback();
+            return Err(e);
+        }
+        writer.close()?;
+
+        let infos: SegmentInfos<D, C> = SegmentInfos::read_latest_commit(&self.directory)?;
+        let after = SegmentVersion::read(&infos)?;
+        Ok(IndexUpgradeStatus { before, after })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::codec::{CodecEnum, TERMS_CODEC_NAME, TERMS_INDEX_CODEC_NAME};
+    use core::doc::{Store, StringField};
+    use core::index::merge_policy::TieredMergePolicy;
+    use core::index::merge_scheduler::SerialMergeScheduler;
+    use core::index::{IndexReader, StandardDirectoryReader, Term};
+    use core::search::searcher::{DefaultIndexSearcher, IndexSearcher};
+    use core::search::term_query::TermQuery;
+    use core::store::{DataInput, DataOutput, RAMDirectory};
+
+    type Config = IndexWriterConfig<CodecEnum, SerialMergeScheduler, TieredMergePolicy>;
+
+    // three segments of 10 docs, the second one with a deleted doc
+    fn build_index() -> Arc<RAMDirectory> {
+        let dir = Arc::new(RAMDirectory::new());
+        let mut config = Config::default();
+        config.use_compound_file = false;
+        let writer = IndexWriter::new(Arc::clone(&dir), Arc::new(config)).unwrap();
+        for segment in 0..3 {
+            for i in 0..10 {
+                writer
+                    .add_document(vec![
+                        StringField::new("id", &format!("{}-{}", segment, i), Store::Yes),
+                        StringField::new("segment", &segment.to_string(), Store::No),
+                        StringField::new("parity", &(i % 2).to_string(), Store::No),
+                    ])
+                    .unwrap();
+            }
+            writer.commit().unwrap();
+        }
+        writer.delete_documents_by_term("id", b"1-3").unwrap();
+        writer.commit().unwrap();
+        writer.close().unwrap();
+        dir
+    }
+
+    // rewrites the codec headers of the terms dictionary of a segment with
+    // format version 0, the version of Lucene 5.0 and 5.1. The blocks of that
+    // version are encoded like the current ones, so the files are read as a
+    // 5.1 terms dictionary; no older writer is around to write a whole index
+    fn downgrade_terms_dict(dir: &Arc<RAMDirectory>, segment: &str) {
+        let prefix = format!("{}_", segment);
+        for name in dir.list_all().unwrap() {
+            if !name.starts_with(&prefix) || !(name.ends_with(".tim") || name.ends_with(".tip")) {
+                continue;
+            }
+            let mut input = dir.open_input(&name, &IOContext::READ).unwrap();
+            let (codec, version) = codec_util::read_header_version(input.as_mut())
+                .unwrap()
+                .unwrap();
+            assert_eq!(Some(version), codec_util::current_format_version(&codec));
+            let mut bytes = vec![0u8; input.len() as usize];
+            input.seek(0).unwrap();
+            input.read_bytes(&mut bytes, 0, bytes.len()).unwrap();
+            // after the magic and the codec name
+            let pos = 4 + 1 + codec.len();
+            bytes[pos..pos + 4].copy_from_slice(&0i32.to_be_bytes());
+
+            dir.delete_file(&name).unwrap();
+            let mut out = dir.create_output(&name, &IOContext::Default).unwrap();
+            let len = bytes.len() - codec_util::footer_length();
+            out.write_bytes(&bytes, 0, len).unwrap();
+            codec_util::write_footer(&mut out).unwrap();
+        }
+    }
+
+    fn counts(dir: &Arc<RAMDirectory>) -> Vec<i32> {
+        let reader =
+            StandardDirectoryReader::<_, CodecEnum, SerialMergeScheduler, TieredMergePolicy>::open(
+                Arc::clone(dir),
+            )
+            .unwrap();
+        let mut counts = vec![reader.num_docs()];
+        let searcher = DefaultIndexSearcher::new(Arc::new(reader));
+        for (field, value) in &[
+            ("segment", "0"),
+            ("segment", "1"),
+            ("segment", "2"),
+            ("parity", "0"),
+            ("parity", "1"),
+            ("id", "1-3"),
+            ("id", "1-4"),
+        ] {
+            let term = Term::new(field.to_string(), value.as_bytes().to_vec());
+            let query = TermQuery::new(term, 1.0, None);
+            counts.push(searcher.count(&query).unwrap());
+        }
+        counts
+    }
+
+    #[test]
+    fn test_upgrade_old_segments() {
+        let dir = build_index();
+        let infos: SegmentInfos<RAMDirectory, CodecEnum> =
+            SegmentInfos::read_latest_commit(&dir).unwrap();
+        downgrade_terms_dict(&dir, &infos.segments[0].info.name);
+        downgrade_terms_dict(&dir, &infos.segments[1].info.name);
+        // the old terms dictionaries are read through their version 0 path
+        let expected = counts(&dir);
+        assert_eq!(expected, vec![29, 10, 9, 10, 15, 14, 0, 1]);
+
+        let upgrader = IndexUpgrader::new(Arc::clone(&dir), Config::default());
+        let status = upgrader.upgrade().unwrap();
+        assert_eq!(status.before.len(), 3);
+        // the segment infos don't tell the old segments apart, the headers do
+        for segment in &status.before[..2] {
+            assert_eq!(segment.version, VERSION_LATEST);
+            assert_eq!(segment.format_versions[TERMS_CODEC_NAME], 0);
+            assert_eq!(segment.format_versions[TERMS_INDEX_CODEC_NAME], 0);
+            assert!(!segment.is_current());
+        }
+        assert_eq!(status.before[1].del_count, 1);
+        assert!(status.before[2].is_current());
+        assert!(status.is_current(), "{}", status);
+        for segment in &status.after {
+            assert_eq!(
+                Some(segment.format_versions[TERMS_CODEC_NAME]),
+                codec_util::current_format_version(TERMS_CODEC_NAME)
+            );
+        }
+
+        let upgraded: Vec<&str> = status
+            .upgraded_segments()
+            .iter()
+            .map(|s| s.name.as_str())
+            .collect();
+        assert_eq!(
+            upgraded,
+            vec![
+                status.before[0].name.as_str(),
+                status.before[1].name.as_str()
+            ]
+        );
+        // the segments are rewritten one by one, in place, and the current
+        // one is left alone
+        assert_eq!(status.after.len(), 3);
+        assert_eq!(status.after[0].max_doc, 10);
+        assert_eq!(status.after[1].max_doc, 9);
+        assert_eq!(status.after[1].del_count, 0);
+        assert_eq!(status.after[2].name, status.before[2].name);
+        assert_eq!(counts(&dir), expected);
+
+        // nothing left to upgrade
+        let status = upgrader.upgrade().unwrap();
+        assert!(status.upgraded_segments().is_empty());
+        assert_eq!(counts(&dir), expected);
+    }
+}
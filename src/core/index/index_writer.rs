@@ -12,10 +12,14 @@
 // limitations under the License.
 
 use core::codec::{Codec, CompoundFormat, FieldInfosFormat, LiveDocsFormat, SegmentInfoFormat};
+use core::codec::{DocValuesConsumer, DocValuesFormat};
 use core::index::bufferd_updates::BufferedUpdatesStream;
-use core::index::bufferd_updates::FrozenBufferedUpdates;
+use core::index::bufferd_updates::{FrozenBufferedUpdates, NumericDocValuesUpdate};
+use core::index::bulk_session::{BulkSession, BulkSessionConfig};
+use core::index::dedup::{self, DedupPolicy, DedupResult, DedupState};
 use core::index::directory_reader::index_exist;
 use core::index::doc_writer::{DocumentsWriter, Event};
+use core::index::doc_writer_delete_queue::DeleteNode;
 use core::index::index_file_deleter::IndexFileDeleter;
 use core::index::index_writer_config::{IndexWriterConfig, OpenMode};
 use core::index::merge_policy::{MergePolicy, MergeSpecification, MergerTrigger};
@@ -23,14 +27,20 @@ use core::index::merge_policy::{OneMerge, OneMergeRunningInfo};
 use core::index::merge_scheduler::MergeScheduler;
 use core::index::merge_state::{DocMap, MergeState};
 use core::index::segment_merger::SegmentMerger;
+use core::index::soft_deletes::soft_deletes_live_docs;
+use core::index::sorter::{PackedLongDocMap, Sorter, SorterDocMap};
 use core::index::thread_doc_writer::FlushedSegment;
 use core::index::{
-    file_name_from_generation, get_last_commit_segments_filename, FieldInfos, FieldNumbers,
-    FieldNumbersRef, Fieldable, LeafReader, SegmentCommitInfo, SegmentInfo, SegmentInfos,
-    SegmentReader, StandardDirectoryReader, Term, INDEX_FILE_PENDING_SEGMENTS,
+    file_name_from_generation, get_last_commit_segments_filename, DocValuesType, FieldInfo,
+    FieldInfos, FieldNumbers, FieldNumbersRef, Fieldable, Fields, IndexOptions, LeafReader,
+    NumericDocValues, SegmentCommitInfo, SegmentInfo, SegmentInfos, SegmentReader,
+    SegmentWriteState, StandardDirectoryReader, Term, TermIterator, Terms,
+    INDEX_FILE_PENDING_SEGMENTS,
 };
 use core::search::match_all::MATCH_ALL;
-use core::search::Query;
+use core::search::posting_iterator::PostingIteratorFlags;
+use core::search::sort_field::SortFieldType;
+use core::search::{DocIterator, Query, NO_MORE_DOCS};
 use core::store::{
     Directory, FlushInfo, IOContext, IndexInput, Lock, LockValidatingDirectoryWrapper,
     RateLimitIndexOutput, RateLimiter, TrackingDirectoryWrapper,
@@ -38,13 +48,13 @@ use core::store::{
 use core::util::bits::{Bits, BitsRef};
 use core::util::io::delete_file_ignoring_error;
 use core::util::string_util::random_id;
-use core::util::{to_base36, DerefWrapper, DocId, VERSION_LATEST};
+use core::util::{to_base36, DerefWrapper, DocId, Numeric, ReusableIterator, VERSION_LATEST};
 
-use core::index::ErrorKind::MergeAborted;
+use core::index::ErrorKind::{FlushFailed, MergeAborted};
 use error::ErrorKind::{AlreadyClosed, IllegalArgument, IllegalState, Index, RuntimeError};
 use error::{Error, Result};
 
-use std::collections::{HashMap, HashSet, VecDeque};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
 use std::fmt;
 use std::mem;
 use std::ops::Deref;
@@ -193,6 +203,12 @@ where
         &self.writer.config
     }
 
+    /// The merge scheduler of the writer, a clone of the one of the config,
+    /// so the settings changed on the config's scheduler don't apply to it.
+    pub fn merge_scheduler(&self) -> &MS {
+        &self.writer.merge_scheduler
+    }
+
     #[inline]
     pub fn max_doc(&self) -> u32 {
         // self.ensure_open(true);
@@ -217,6 +233,7 @@ where
     }
 
     pub fn close(&self) -> Result<()> {
+        self.release_dedup_snapshot();
         IndexWriterInner::close(self)
     }
 
@@ -230,6 +247,7 @@ where
     /// call to `#prepareCommit`.
     /// @throws IOException if there is a low-level IO error
     pub fn rollback(&self) -> Result<()> {
+        self.release_dedup_snapshot();
         self.writer.rollback()
     }
 
@@ -277,10 +295,12 @@ where
     /// containing <code>term</code> and then adding the new
     /// document.  The delete and then add are atomic as seen
     /// by a reader on the same index (flush may happen only after
-    /// the add).
+    /// the add): no commit or NRT reader sees both the old and the
+    /// new document, or neither of them.
     ///
     /// @return The <a href="#sequence_number">sequence number</a>
-    /// for this operation
+    /// for this operation, greater than the one of every operation
+    /// of this writer before it
     ///
     /// @param term the term to identify the document(s) to be
     /// deleted
@@ -288,7 +308,58 @@ where
     /// @throws CorruptIndexException if the index is corrupt
     /// @throws IOException if there is a low-level IO error
     pub fn update_document<F: Fieldable>(&self, doc: Vec<F>, term: Option<Term>) -> Result<u64> {
-        IndexWriterInner::update_document(self, doc, term)
+        IndexWriterInner::update_document(self, doc, term.map(DeleteNode::Term))
+    }
+
+    /// Replaces the document(s) containing `term` with `doc`, like
+    /// `#update_document`, but soft deletes them: instead of being deleted,
+    /// the old documents get a value of 1 for the numeric doc values field
+    /// `IndexWriterConfig::soft_deletes_field`, and `doc` stays live.
+    ///
+    /// The old documents stay in the index, e.g. to retain recently deleted
+    /// documents for replication, while searches through a
+    /// `SoftDeletesDirectoryReader` skip them. The soft delete and the add are
+    /// atomic as in `#update_document`.
+    pub fn soft_update_document<F: Fieldable>(
+        &self,
+        doc: Vec<F>,
+        term: Option<Term>,
+    ) -> Result<u64> {
+        let field = match self.writer.config.soft_deletes_field() {
+            Some(field) => field,
+            None => bail!(IllegalState(
+                "soft deletes require IndexWriterConfig::soft_deletes_field".into()
+            )),
+        };
+        let soft_delete = term.map(|t| DeleteNode::NumericUpdate(t, field.to_string(), 1));
+        IndexWriterInner::update_document(self, doc, soft_delete)
+    }
+
+    /// Updates the `NumericDocValues` of `field` to `value` for the
+    /// document(s) containing `term`. The field must already exist in the
+    /// index as a numeric doc values field.
+    ///
+    /// The documents are not reindexed: the update is buffered like a delete,
+    /// then the new values of the field are written as a new generation of its
+    /// doc values on commit, NRT reopen or merge, and the older generations are
+    /// deleted once no commit uses them.
+    pub fn update_numeric_doc_value(&self, term: Term, field: &str, value: i64) -> Result<u64> {
+        IndexWriterInner::update_numeric_doc_value(self, term, field, value)
+    }
+
+    /// Adds a document unless a duplicate of it is already in the index.
+    ///
+    /// The content hash of the `IndexWriterConfig::dedup_fields` of the document
+    /// is looked up in the current view of the index, and `policy` decides
+    /// what to do with the document if a duplicate is found. Concurrent calls
+    /// are serialized, but the other writes of the writer aren't: a document
+    /// written by another thread during the call may be missed by the lookup.
+    pub fn add_document_dedup<F: Fieldable + 'static>(
+        &self,
+        doc: Vec<F>,
+        policy: DedupPolicy,
+    ) -> Result<DedupResult> {
+        dedup::add_document_dedup(self, doc, policy)
     }
 
     /// Atomically adds a block of documents with sequentially
@@ -306,10 +377,8 @@ where
     /// documents to an existing block will require you the reindex
     /// the entire block.
     ///
-    /// However it's possible that in the future Lucene may
-    /// merge more aggressively re-order documents (for example,
-    /// perhaps to obtain better index compression), in which case
-    /// you may need to fully re-index your documents at that time.
+    /// Blocks can't be added to an index with an index sort, as
+    /// sorting the flushed and merged segments would split them.
     ///
     /// See `#addDocument(Iterable)` for details on
     /// index and IndexWriter state after an Exception, and
@@ -350,7 +419,7 @@ where
         docs: Vec<Vec<F>>,
         term: Option<Term>,
     ) -> Result<u64> {
-        IndexWriterInner::update_documents(self, docs, term)
+        IndexWriterInner::update_documents(self, docs, term.map(DeleteNode::Term))
     }
 
     /// Deletes the document(s) containing any of the
@@ -368,6 +437,12 @@ where
         IndexWriterInner::delete_documents_by_terms(self, terms)
     }
 
+    /// Deletes the document(s) having `term` in `field`, see
+    /// `#delete_documents_by_terms`.
+    pub fn delete_documents_by_term(&self, field: &str, term: &[u8]) -> Result<u64> {
+        self.delete_documents_by_terms(vec![Term::new(field.to_string(), term.to_vec())])
+    }
+
     /// Deletes the document(s) matching any of the provided queries.
     /// All given deletes are applied and flushed atomically at the same time.
     ///
@@ -382,6 +457,11 @@ where
         IndexWriterInner::delete_documents_by_queries(self, queries)
     }
 
+    /// Deletes the document(s) matching `query`, see `#delete_documents_by_queries`.
+    pub fn delete_documents_by_query(&self, query: Arc<dyn Query<C>>) -> Result<u64> {
+        self.delete_documents_by_queries(vec![query])
+    }
+
     /// Delete all documents in the index.
     ///
     /// This method will drop all buffered documents and will remove all segments
@@ -486,10 +566,70 @@ where
         self.writer.has_uncommitted_changes()
     }
 
+    /// Commits all the pending changes, making them visible to the readers
+    /// opening the index and durable.
+    ///
+    /// The commit doesn't wait for the running merges, it commits the segments
+    /// they merge, unless `IndexWriterConfig::merge_on_commit` is set.
+    ///
+    /// If `#prepare_commit` was called, this only completes the prepared
+    /// commit, the changes made since then are not committed.
     pub fn commit(&self) -> Result<i64> {
         IndexWriterInner::commit(self)
     }
 
+    /// Expert: prepares the first phase of a two-phase commit. It flushes the
+    /// buffered documents and deletes, writes a new pending_segments_N file and
+    /// syncs all the files referenced by the commit, but the commit is not
+    /// visible to the readers until `#commit` is called.
+    ///
+    /// Call `#commit` to finish the commit, or `#rollback` to abort it, before
+    /// the writer can be closed. If the process crashes in between, the index
+    /// opens at the previous commit.
+    ///
+    /// Returns the sequence number of the last operation in the commit, or -1
+    /// if there was nothing to commit.
+    pub fn prepare_commit(&self) -> Result<i64> {
+        IndexWriterInner::prepare_commit(self)
+    }
+
+    /// Sets the user data stored with the next commit, in
+    /// `SegmentInfos::user_data`. It must be set before `#prepare_commit` to be
+    /// part of the prepared commit.
+    pub fn set_live_commit_data(&self, data: HashMap<String, String>) -> Result<()> {
+        self.writer.set_live_commit_data(data)
+    }
+
+    /// Returns the user data stored with the next commit, this is the data of
+    /// the last commit until `#set_live_commit_data` is called.
+    pub fn live_commit_data(&self) -> Result<HashMap<String, String>> {
+        let _l = self.writer.lock.lock()?;
+        Ok(self.writer.segment_infos.user_data.clone())
+    }
+
+    /// Moves all the buffered documents to new segments in the directory,
+    /// without committing them.
+    pub fn flush(&self) -> Result<()> {
+        IndexWriterInner::flush(self, true, true)
+    }
+
+    /// Flushes the next in-memory segment pending for flush, or else the most
+    /// RAM consuming one, without stopping the other indexing threads.
+    ///
+    /// Returns false if there were no buffered documents to flush.
+    pub fn flush_next_buffer(&self) -> Result<bool> {
+        self.writer.ensure_open(true)?;
+        let flushed = self.writer.doc_writer.flush_one_dwpt()?;
+        IndexWriterInner::process_events(self, true, false)?;
+        Ok(flushed)
+    }
+
+    /// Starts a bulk indexing session, whose producers block while the
+    /// buffered documents use more RAM than allowed by `config`.
+    pub fn bulk_session(&self, config: BulkSessionConfig) -> Result<BulkSession<D, C, MS, MP>> {
+        BulkSession::new(self.clone(), config)
+    }
+
     pub fn is_open(&self) -> bool {
         self.writer.is_open()
     }
@@ -525,6 +665,35 @@ where
         Self { writer }
     }
 
+    #[inline]
+    pub(crate) fn dedup_state(&self) -> &Mutex<DedupState<D, C, MS, MP>> {
+        &self.writer.dedup_state
+    }
+
+    // the snapshot of `add_document_dedup` holds a reference to the writer,
+    // which must be dropped for the writer to be closed
+    fn release_dedup_snapshot(&self) {
+        let snapshot = match self.writer.dedup_state.lock() {
+            Ok(mut state) => state.release(),
+            Err(_) => None,
+        };
+        drop(snapshot);
+    }
+
+    #[inline]
+    pub(crate) fn doc_writer(&self) -> &DocumentsWriter<D, C, MS, MP> {
+        &self.writer.doc_writer
+    }
+
+    /// Returns the number of segments flushed by this writer and their size
+    /// in bytes.
+    pub(crate) fn flushed_segment_stats(&self) -> (u64, u64) {
+        (
+            self.writer.flushed_segments.load(Ordering::Acquire),
+            self.writer.flushed_bytes.load(Ordering::Acquire),
+        )
+    }
+
     #[inline]
     pub(crate) fn reader_pool(&self) -> &ReaderPool<D, C, MS, MP> {
         &self.writer.reader_pool
@@ -599,6 +768,12 @@ where
         self.writer.num_deleted_docs(info)
     }
 
+    /// The number of documents a merge of `info` would reclaim, the deleted
+    /// ones plus the soft deleted ones.
+    pub(crate) fn num_deletes_to_merge(&self, info: &Arc<SegmentCommitInfo<D, C>>) -> u32 {
+        self.writer.num_deletes_to_merge(info)
+    }
+
     /// Record that the files referenced by this `SegmentInfos` are still in use.
     pub(crate) fn inc_ref_deleter(&self, segment_infos: &SegmentInfos<D, C>) -> Result<()> {
         self.writer.inc_ref_deleter(segment_infos)
@@ -629,6 +804,10 @@ where
     MP: MergePolicy,
 {
     fn drop(&mut self) {
+        // the other reference may be the one of the dedup snapshot
+        if Arc::strong_count(&self.writer) == 2 {
+            self.release_dedup_snapshot();
+        }
         // this is the last reference, actual drop
         if Arc::strong_count(&self.writer) == 1 {
             if self.writer.config.commit_on_close {
@@ -650,6 +829,16 @@ pub(crate) struct IndexWriterInner<
     MS: MergeScheduler,
     MP: MergePolicy,
 > {
+    // Lock ordering, a thread holding one of these locks only takes the ones
+    // after it: `commit_lock` -> `full_flush_lock` -> `lock` -> `updates_stream_lock`
+    // and the reader pool locks. `segment_infos_lock` only guards the segment
+    // name counter and is taken alone.
+    //
+    // `lock` guards the segment infos and the merge registration (pending and
+    // running merges), it is only held for short bookkeeping steps. No lock is
+    // held while a merge reads and writes the segments, so flushes, NRT
+    // refreshes and commits go on during merges, publishing the segments being
+    // merged until the merge commits.
     lock: Arc<Mutex<()>>,
     cond: Condvar,
     // original use directory
@@ -696,9 +885,15 @@ pub(crate) struct IndexWriterInner<
 
     flush_count: AtomicU32,
     flush_deletes_count: AtomicU32,
+    flushed_segments: AtomicU64,
+    flushed_bytes: AtomicU64,
     reader_pool: ReaderPool<D, C, MS, MP>,
     updates_stream_lock: Mutex<()>,
     buffered_updates_stream: BufferedUpdatesStream<C>,
+    // the view of the index of `add_document_dedup`
+    dedup_state: Mutex<DedupState<D, C, MS, MP>>,
+    // the fields whose token graph was flattened, to warn once per field
+    graph_flattened_fields: Mutex<HashSet<String>>,
 
     // This is a "write once" variable (like the organic dye
     // on a DVD-R that may or may not be heated by a laser and
@@ -879,7 +1074,7 @@ where
         let mut deleter = IndexFileDeleter::new(
             Arc::clone(&d),
             Arc::clone(&directory),
-            // conf.index_deletion_policy(),
+            conf.index_deletion_policy(),
         );
         deleter.init(&files, &mut segment_infos, initial_index_exists, false)?;
 
@@ -927,9 +1122,13 @@ where
             stop_merges: false,
             flush_count: AtomicU32::new(0),
             flush_deletes_count: AtomicU32::new(0),
+            flushed_segments: AtomicU64::new(0),
+            flushed_bytes: AtomicU64::new(0),
             reader_pool: ReaderPool::new(),
             updates_stream_lock: Mutex::new(()),
             buffered_updates_stream,
+            dedup_state: Mutex::new(DedupState::default()),
+            graph_flattened_fields: Mutex::new(HashSet::new()),
             pool_readers: AtomicBool::new(pool_readers),
             config: conf,
             pending_num_docs: Arc::new(pending_num_docs),
@@ -941,6 +1140,17 @@ where
         })
     }
 
+    /// Records that the token graph of `field` is flattened to index it,
+    /// returns whether it's the first time for this writer.
+    pub(crate) fn graph_flattened(&self, field: &str) -> bool {
+        let mut fields = self.graph_flattened_fields.lock().unwrap();
+        if fields.contains(field) {
+            return false;
+        }
+        fields.insert(field.to_string());
+        true
+    }
+
     unsafe fn writer_mut(&self, _l: &MutexGuard<()>) -> &mut IndexWriterInner<D, C, MS, MP> {
         let writer =
             self as *const IndexWriterInner<D, C, MS, MP> as *mut IndexWriterInner<D, C, MS, MP>;
@@ -1000,6 +1210,20 @@ where
         Ok(reader)
     }
 
+    // the docs of the DWPTs aborted by a failed full flush are reported with
+    // the error of the flush
+    fn pending_flushes_aborted(err: Error, aborted_docs: u32) -> Error {
+        if aborted_docs == 0 {
+            return err;
+        }
+        let desc = format!(
+            "{}, the {} buffered docs of the pending flushes were aborted",
+            err, aborted_docs
+        );
+        error!("IW - {}", &desc);
+        Error::with_chain(err, FlushFailed(desc))
+    }
+
     fn do_get_reader(
         index_writer: &IndexWriter<D, C, MS, MP>,
         apply_all_deletes: bool,
@@ -1015,7 +1239,10 @@ where
             any_changes,
         );
         // Done: finish the full flush!
-        index_writer.writer.doc_writer.finish_full_flush(true);
+        let aborted_docs = index_writer
+            .writer
+            .doc_writer
+            .finish_full_flush(res.is_ok());
         match res {
             Ok(reader) => {
                 Self::process_events(index_writer, false, true)?;
@@ -1024,7 +1251,7 @@ where
             }
             Err(e) => {
                 error!("IW - hit error during NRT reader: {:?}", e);
-                Err(e)
+                Err(Self::pending_flushes_aborted(e, aborted_docs))
             }
         }
     }
@@ -1060,6 +1287,12 @@ where
                     .writer
                     .reader_pool
                     .commit(&index_writer.writer.segment_infos)?;
+            } else {
+                // the doc values updates are only visible once written
+                *any_changes |= index_writer
+                    .writer
+                    .reader_pool
+                    .write_doc_values_updates(&index_writer.writer.segment_infos)?;
             }
 
             let r = StandardDirectoryReader::open_by_writer(
@@ -1078,14 +1311,30 @@ where
         Ok(reader)
     }
 
-    /// Confirms that the incoming index sort (if any) matches the existing index
-    /// sort (if any). This is unfortunately just best effort, because it could
-    /// be the old index only has flushed segments.
+    /// Confirms that the incoming index sort (if any) only sorts by numeric or
+    /// sorted doc values and matches the existing index sort (if any). This is
+    /// unfortunately just best effort, because it could be the old index only
+    /// has segments written without the index sort.
     fn validate_index_sort<MS1: MergeScheduler, MP1: MergePolicy>(
         config: &IndexWriterConfig<C, MS1, MP1>,
         segment_infos: &SegmentInfos<D, C>,
     ) -> Result<()> {
         if let Some(index_sort) = config.index_sort() {
+            for field in index_sort.get_sort() {
+                match Sorter::sort_field_type(field) {
+                    SortFieldType::Long
+                    | SortFieldType::Int
+                    | SortFieldType::Double
+                    | SortFieldType::Float
+                    | SortFieldType::String => {}
+                    field_type => bail!(IllegalArgument(format!(
+                        "invalid index sort field '{}' of type {:?}, the index can only be sorted \
+                         by numeric or sorted doc values",
+                        field.field(),
+                        field_type
+                    ))),
+                }
+            }
             for info in &segment_infos.segments {
                 if let Some(segment_sort) = info.info.index_sort() {
                     if segment_sort != index_sort {
@@ -1203,7 +1452,12 @@ where
         new_segment: FlushedSegment<D, C>,
         global_packet: Option<FrozenBufferedUpdates<C>>,
     ) -> Result<()> {
+        let size = new_segment.segment_info.size_in_bytes();
         let res = self.do_publish_flushed_segment(new_segment, global_packet);
+        if res.is_ok() {
+            self.flushed_segments.fetch_add(1, Ordering::AcqRel);
+            self.flushed_bytes.fetch_add(size as u64, Ordering::AcqRel);
+        }
         self.flush_count.fetch_add(1, Ordering::AcqRel);
         self.do_after_flush();
         res
@@ -1578,6 +1832,47 @@ where
         del_count
     }
 
+    fn num_deletes_to_merge(&self, info: &Arc<SegmentCommitInfo<D, C>>) -> u32 {
+        let del_count = self.num_deleted_docs(info);
+        let field = match self.config.soft_deletes_field() {
+            Some(field) => field,
+            None => {
+                return del_count;
+            }
+        };
+        match self.num_soft_deleted_docs(info, field) {
+            Ok(soft_del_count) => del_count + soft_del_count,
+            Err(e) => {
+                warn!(
+                    "IW - count soft deletes of segment {} failed by '{:?}'",
+                    &info.info.name, e
+                );
+                del_count
+            }
+        }
+    }
+
+    // the count is cached by the segment until its deletes or doc values change,
+    // the merge policy asks for it on every find_merges
+    fn num_soft_deleted_docs(
+        &self,
+        info: &Arc<SegmentCommitInfo<D, C>>,
+        field: &str,
+    ) -> Result<u32> {
+        let del_count = self.num_deleted_docs(info) as i32;
+        if let Some(count) = info.soft_delete_count(del_count) {
+            return Ok(count);
+        }
+        let rld = self.reader_pool.get_or_create(info)?;
+        let res = rld.soft_delete_count(field);
+        if let Ok(count) = res {
+            // before the release, which may write the pending changes
+            info.set_soft_delete_count(del_count, count);
+        }
+        self.reader_pool.release(&rld, false)?;
+        res
+    }
+
     fn change_count(&self) -> u64 {
         self.change_count.load(Ordering::Acquire)
     }
@@ -1778,6 +2073,12 @@ where
     fn commit(index_writer: &IndexWriter<D, C, MS, MP>) -> Result<i64> {
         debug!("IW - commit: start");
 
+        if index_writer.writer.config.merge_on_commit {
+            debug!("IW - commit: wait for merges");
+            Self::flush(index_writer, true, true)?;
+            Self::wait_for_merges(index_writer)?;
+        }
+
         let mut do_maybe_merge = false;
         let seq_no: i64;
         {
@@ -1805,6 +2106,38 @@ where
         Ok(seq_no)
     }
 
+    fn prepare_commit(index_writer: &IndexWriter<D, C, MS, MP>) -> Result<i64> {
+        index_writer.writer.ensure_open(true)?;
+        debug!("IW - prepare commit: start");
+
+        let mut do_maybe_merge = false;
+        let seq_no = {
+            let l = index_writer.writer.commit_lock.lock()?;
+            let writer = unsafe { index_writer.writer.writer_mut(&l) };
+            let seq_no = writer.prepare_commit_internal(&mut do_maybe_merge, index_writer, &l)?;
+            index_writer
+                .writer
+                .pending_seq_no
+                .store(seq_no, Ordering::Release);
+            seq_no
+        };
+
+        if do_maybe_merge {
+            Self::maybe_merge(index_writer, MergerTrigger::FullFlush, None)?;
+        }
+        Ok(seq_no)
+    }
+
+    fn set_live_commit_data(&self, data: HashMap<String, String>) -> Result<()> {
+        let l = self.lock.lock()?;
+        let writer = unsafe { self.writer_mut(&l) };
+        writer.segment_infos.user_data = data;
+        // so the next commit writes the new data, even if nothing else changed
+        writer.segment_infos.changed();
+        self.change_count.fetch_add(1, Ordering::AcqRel);
+        Ok(())
+    }
+
     // _l is self.commit_lock
     fn prepare_commit_internal(
         &mut self,
@@ -1852,14 +2185,14 @@ where
                 debug!("IW - hit error during perpare commit");
             }
             // Done: finish the full flush
-            self.doc_writer.finish_full_flush(flush_success);
+            let aborted_docs = self.doc_writer.finish_full_flush(flush_success);
             self.do_after_flush();
             match res {
                 Ok(infos) => {
                     to_commit = infos;
                 }
                 Err(e) => {
-                    return Err(e);
+                    return Err(Self::pending_flushes_aborted(e, aborted_docs));
                 }
             }
         }
@@ -1919,9 +2252,6 @@ where
             self.segment_infos.changed();
         }
 
-        // TODO currently not support SegmentInfos#user_data
-        // if self.commit_user_data.is_some() {}
-
         // Must clone the segmentInfos while we still
         // hold fullFlushLock and while sync'd so that
         // no partial changes (eg a delete w/o
@@ -2286,14 +2616,20 @@ where
     fn update_documents<F: Fieldable>(
         index_writer: &IndexWriter<D, C, MS, MP>,
         docs: Vec<Vec<F>>,
-        term: Option<Term>,
+        del_node: Option<DeleteNode<C>>,
     ) -> Result<u64> {
         index_writer.writer.ensure_open(true)?;
+        // sorting the segments would split the block
+        if docs.len() > 1 && index_writer.writer.config.index_sort().is_some() {
+            bail!(IllegalArgument(
+                "blocks of documents can't be added to an index with an index sort".into()
+            ));
+        }
 
         let (seq_no, changed) = index_writer
             .writer
             .doc_writer
-            .update_documents(docs, term)?;
+            .update_documents(docs, del_node)?;
         if changed {
             Self::process_events(index_writer, false, false)?;
         }
@@ -2347,10 +2683,13 @@ where
     fn update_document<F: Fieldable>(
         index_writer: &IndexWriter<D, C, MS, MP>,
         doc: Vec<F>,
-        term: Option<Term>,
+        del_node: Option<DeleteNode<C>>,
     ) -> Result<u64> {
         index_writer.writer.ensure_open(true)?;
-        let (seq_no, changed) = index_writer.writer.doc_writer.update_document(doc, term)?;
+        let (seq_no, changed) = index_writer
+            .writer
+            .doc_writer
+            .update_document(doc, del_node)?;
         if changed {
             Self::process_events(index_writer, false, false)?;
         }
@@ -2376,9 +2715,33 @@ where
     ///           if the index is corrupt
     /// @throws IOException
     ///           if there is a low-level IO error
-    #[allow(dead_code)]
-    fn update_numeric_doc_value(&mut self, _field: &str, _value: i64, _term: Term) -> Result<u64> {
-        unimplemented!()
+    fn update_numeric_doc_value(
+        index_writer: &IndexWriter<D, C, MS, MP>,
+        term: Term,
+        field: &str,
+        value: i64,
+    ) -> Result<u64> {
+        index_writer.writer.ensure_open(true)?;
+        if !index_writer
+            .writer
+            .global_field_numbers
+            .contains(field, DocValuesType::Numeric)
+        {
+            bail!(IllegalArgument(format!(
+                "can only update existing numeric-docvalues fields, '{}' is not one",
+                field
+            )));
+        }
+
+        let (seq_no, changed) = index_writer.writer.doc_writer.update_numeric_doc_value(
+            term,
+            field.to_string(),
+            value,
+        )?;
+        if changed {
+            Self::process_events(index_writer, true, false)?;
+        }
+        Ok(seq_no)
     }
 
     pub fn new_segment_name(&self) -> String {
@@ -2424,12 +2787,15 @@ where
             .compound_format()
             .write(directory, info, context);
         if let Err(err) = res {
-            // Safe: these files must exist
-            if let Err(e) = self.delete_new_files(&directory.create_files()) {
-                error!(
-                    "clean up files when create compound file failed, error occur: {:?}",
-                    e
-                );
+            // Safe: these files must exist. Deletes through the tracking directory
+            // so that the files it tracks are the files left in the directory
+            for file in directory.create_files() {
+                if let Err(e) = directory.delete_file(&file) {
+                    error!(
+                        "clean up files when create compound file failed, error occur: {:?}",
+                        e
+                    );
+                }
             }
             return Err(err);
         }
@@ -2709,6 +3075,11 @@ where
 
         Self::merge_middle(index_writer, merge)?;
         // self.merge_success();
+        let stats = merge.io_stats();
+        debug!(
+            "IW - merge {} wrote {} bytes, paused {:?}, stopped {:?}",
+            merge.id, stats.bytes_written, stats.paused, stats.stopped
+        );
         Ok(())
     }
 
@@ -3124,6 +3495,24 @@ where
                     }
                 }
             }
+
+            // Carry over the doc values updates the merge didn't see, the
+            // docs deleted before the merge started are gone:
+            for (field, updates) in &inner.merging_numeric_updates {
+                for (j, value) in updates {
+                    let doc_id =
+                        merge_state.doc_maps[i].get(merge_state.leaf_doc_maps[i].get(*j)?)?;
+                    if doc_id < 0 {
+                        continue;
+                    }
+                    holder.init(&self.reader_pool, merge, false)?;
+                    holder
+                        .merged_deletes_and_updates
+                        .as_ref()
+                        .unwrap()
+                        .add_numeric_update(field, doc_id, *value)?;
+                }
+            }
         }
 
         merge
@@ -3315,10 +3704,9 @@ where
     ) -> Result<()> {
         if drop {
             rld.drop_changes();
+        } else {
+            rld.drop_merging_updates();
         }
-        //            else {
-        //                rld.drop_merging_updates();
-        //            }
         // rld.release(reader);
         self.reader_pool.release(&rld, true)?;
         if drop {
@@ -3575,6 +3963,22 @@ where
         Ok(())
     }
 
+    /// Writes the pending doc values updates of the segments of `infos`,
+    /// so a NRT reader sees them; returns true if any were written.
+    pub fn write_doc_values_updates(&self, infos: &SegmentInfos<D, C>) -> Result<bool> {
+        let l = self.lock.lock()?;
+        let mut any = false;
+        for info in &infos.segments {
+            if let Some(rld) = self.reader_map.lock()?.get(&info.info.name) {
+                any |= rld.write_field_updates(&self.writer().directory)?;
+            }
+        }
+        if any {
+            self.check_point_no_sis(&l)?;
+        }
+        Ok(any)
+    }
+
     /// Obtain a ReadersAndLiveDocs instance from the reader_pool.
     pub fn get(
         &self,
@@ -3751,26 +4155,61 @@ where
         guard.init_writable_live_docs(&self.info)
     }
 
+    /// Resolves `update` to the live documents of the segment, returns true
+    /// if there were any. `sort_map` is the sort of a segment sorted on flush,
+    /// the doc id limit of its segment private updates is unsorted.
+    pub fn update_numeric(
+        &self,
+        update: &NumericDocValuesUpdate,
+        sort_map: Option<&PackedLongDocMap>,
+    ) -> Result<bool> {
+        let mut guard = self.inner.lock()?;
+        guard.update_numeric(&self.info, update, sort_map)
+    }
+
+    fn add_numeric_update(&self, field: &str, doc_id: DocId, value: i64) -> Result<()> {
+        let mut guard = self.inner.lock()?;
+        guard.add_numeric_update(field, doc_id, value);
+        Ok(())
+    }
+
     // Commit live docs (writes new _X_N.del files) and field updates (writes new
     // _X_N updates files) to the directory; returns true if it wrote any file
     // and false if there were no new deletes or updates to write:
     pub fn write_live_docs<D1: Directory>(&self, dir: &Arc<D1>) -> Result<bool> {
         let mut guard = self.inner.lock()?;
-        guard.write_live_docs(&self.info, dir)
+        let wrote_updates = guard.write_field_updates(&self.info, dir)?;
+        let wrote_live_docs = guard.write_live_docs(&self.info, dir)?;
+        Ok(wrote_live_docs || wrote_updates)
+    }
+
+    // Writes the pending field updates (new _X_N updates files) to the
+    // directory, returns false if there were none.
+    pub fn write_field_updates<D1: Directory>(&self, dir: &Arc<D1>) -> Result<bool> {
+        let mut guard = self.inner.lock()?;
+        guard.write_field_updates(&self.info, dir)
     }
 
     pub fn drop_readers(&self) -> Result<()> {
         let mut guard = self.inner.lock()?;
         guard.reader = None;
+        guard.nrt_reader = None;
         self.dec_ref();
         Ok(())
     }
 
-    pub fn get_readonly_clone(&self, context: &IOContext) -> Result<SegmentReader<D, C>> {
+    pub fn get_readonly_clone(&self, context: &IOContext) -> Result<Arc<SegmentReader<D, C>>> {
         let mut guard = self.inner.lock()?;
         guard.get_readonly_clone(&self.info, context)
     }
 
+    /// The number of live documents of the segment having a value for the
+    /// soft-deletes `field`.
+    pub fn soft_delete_count(&self, field: &str) -> Result<u32> {
+        let mut guard = self.inner.lock()?;
+        guard.soft_delete_count(&self.info, field)
+    }
+
     pub fn test_doc_id(&self, doc_id: usize) -> Result<bool> {
         let guard = self.inner.lock().unwrap();
         debug_assert!(guard.live_docs.is_some());
@@ -3799,6 +4238,12 @@ where
         // discard them on the sub-readers:
         let mut guard = self.inner.lock().unwrap();
         guard.pending_delete_count = 0;
+        guard.pending_numeric_updates.clear();
+        guard.drop_merging_updates();
+    }
+
+    pub fn drop_merging_updates(&self) {
+        let mut guard = self.inner.lock().unwrap();
         guard.drop_merging_updates();
     }
 
@@ -3813,6 +4258,9 @@ where
         self.create_reader_if_not_exist(context)?;
         let mut guard = self.inner.lock()?;
         guard.is_merging = true;
+        // the merge reader doesn't see the pending updates, they are carried
+        // over onto the merged segment with the ones done while merging
+        guard.merging_numeric_updates = guard.pending_numeric_updates.clone();
         Ok(Arc::clone(guard.reader.as_ref().unwrap()))
     }
 }
@@ -3824,8 +4272,8 @@ where
     MS: MergeScheduler,
     MP: MergePolicy,
 {
-    // used for process DocValues update which is not currently implemented
-    _writer: Weak<IndexWriterInner<D, C, MS, MP>>,
+    // used to number the fields which are updated but don't exist in the segment
+    writer: Weak<IndexWriterInner<D, C, MS, MP>>,
     // Set once (null, and then maybe set, and never set again):
     reader: Option<Arc<SegmentReader<D, C>>>,
     // Holds the current shared (readable and writable)
@@ -3846,7 +4294,13 @@ where
     // That way, when the segment is done merging, IndexWriter can apply the
     // updates on the merged segment too.
     is_merging: bool,
-    // merging_dv_updates: HashMap<String, DocValuesFieldUpdates>,
+    // The numeric doc values updates not written yet, by field and doc
+    pending_numeric_updates: BTreeMap<String, BTreeMap<DocId, i64>>,
+    // The numeric doc values updates since the segment started merging
+    merging_numeric_updates: BTreeMap<String, BTreeMap<DocId, i64>>,
+    // The last reader handed to an NRT reader, it's shared by the following
+    // NRT readers as long as the segment has no new deletes.
+    nrt_reader: Option<Arc<SegmentReader<D, C>>>,
 }
 
 impl<D, C, MS, MP> ReadersAndUpdatesInner<D, C, MS, MP>
@@ -3858,12 +4312,15 @@ where
 {
     fn new(writer: Weak<IndexWriterInner<D, C, MS, MP>>) -> Self {
         ReadersAndUpdatesInner {
-            _writer: writer,
+            writer,
             reader: None,
             live_docs: None,
             pending_delete_count: 0,
             live_docs_shared: true,
             is_merging: false,
+            pending_numeric_updates: BTreeMap::new(),
+            merging_numeric_updates: BTreeMap::new(),
+            nrt_reader: None,
         }
     }
 
@@ -3876,12 +4333,15 @@ where
         let pending_delete_count = reader.num_deleted_docs();
         debug_assert!(pending_delete_count >= 0);
         ReadersAndUpdatesInner {
-            _writer: writer,
+            writer,
             reader: Some(Arc::new(reader)),
             live_docs: Some(live_docs),
             pending_delete_count: pending_delete_count as u32,
             live_docs_shared: true,
             is_merging: false,
+            pending_numeric_updates: BTreeMap::new(),
+            merging_numeric_updates: BTreeMap::new(),
+            nrt_reader: None,
         }
     }
 
@@ -3953,13 +4413,79 @@ where
         Ok(did_deleted)
     }
 
+    pub fn update_numeric(
+        &mut self,
+        info: &Arc<SegmentCommitInfo<D, C>>,
+        update: &NumericDocValuesUpdate,
+        sort_map: Option<&PackedLongDocMap>,
+    ) -> Result<bool> {
+        self.create_reader_if_not_exist(info, &IOContext::READ)?;
+        let reader = Arc::clone(self.reader());
+        let term = &update.term;
+        let terms = match reader.fields()?.terms(&term.field)? {
+            Some(terms) => terms,
+            None => {
+                return Ok(false);
+            }
+        };
+        let mut terms_iter = terms.iterator()?;
+        if !terms_iter.seek_exact(&term.bytes)? {
+            return Ok(false);
+        }
+        let mut postings = terms_iter.postings_with_flags(PostingIteratorFlags::NONE)?;
+        let mut any = false;
+        loop {
+            let doc_id = postings.next()?;
+            if doc_id == NO_MORE_DOCS {
+                break;
+            }
+            let limit_doc = sort_map.map_or(doc_id, |m| m.new_to_old(doc_id));
+            if limit_doc < update.doc_id_upto && self.live_docs().get(doc_id as usize)? {
+                self.add_numeric_update(&update.field, doc_id, update.value);
+                any = true;
+            }
+        }
+        Ok(any)
+    }
+
+    fn add_numeric_update(&mut self, field: &str, doc_id: DocId, value: i64) {
+        self.pending_numeric_updates
+            .entry(field.to_string())
+            .or_insert_with(BTreeMap::new)
+            .insert(doc_id, value);
+        if self.is_merging {
+            self.merging_numeric_updates
+                .entry(field.to_string())
+                .or_insert_with(BTreeMap::new)
+                .insert(doc_id, value);
+        }
+    }
+
+    fn soft_delete_count(
+        &mut self,
+        info: &Arc<SegmentCommitInfo<D, C>>,
+        field: &str,
+    ) -> Result<u32> {
+        self.create_reader_if_not_exist(info, &IOContext::READ)?;
+        let count =
+            match soft_deletes_live_docs(self.reader().as_ref(), self.live_docs().as_ref(), field)?
+            {
+                Some((_, num_soft_deleted)) => num_soft_deleted as u32,
+                None => 0,
+            };
+        Ok(count)
+    }
+
     // Returns a ref to a clone. NOTE: you should decRef()
     // the reader when you're done (ie do not call close())
+    //
+    // The clone is shared with the previous call if the segment has no new
+    // deletes since, so unchanged segments are shared by the NRT readers.
     pub fn get_readonly_clone(
         &mut self,
         info: &Arc<SegmentCommitInfo<D, C>>,
         context: &IOContext,
-    ) -> Result<SegmentReader<D, C>> {
+    ) -> Result<Arc<SegmentReader<D, C>>> {
         if self.reader.is_none() {
             self.create_reader_if_not_exist(info, context)?;
             // self.reader.as_mut().unwrap().dec_ref();
@@ -3976,13 +4502,21 @@ where
             // self.reader.as_mut().unwrap().inc_ref();
             Arc::clone(live_docs)
         };
+        let num_docs = info.info.max_doc - info.del_count() - self.pending_delete_count as i32;
+        if let Some(ref nrt_reader) = self.nrt_reader {
+            if Arc::ptr_eq(&nrt_reader.live_docs, &live_docs) && nrt_reader.num_docs() == num_docs {
+                return Ok(Arc::clone(nrt_reader));
+            }
+        }
         let reader = self.reader.as_ref().unwrap();
-        SegmentReader::build(
+        let reader = Arc::new(SegmentReader::build(
             Arc::clone(info),
             live_docs,
-            info.info.max_doc - info.del_count() - self.pending_delete_count as i32,
+            num_docs,
             Arc::clone(&reader.core),
-        )
+        )?);
+        self.nrt_reader = Some(Arc::clone(&reader));
+        Ok(reader)
     }
 
     pub fn init_writable_live_docs(&mut self, info: &Arc<SegmentCommitInfo<D, C>>) -> Result<()> {
@@ -4065,17 +4599,15 @@ where
     }
 
     fn drop_merging_updates(&mut self) {
-        // self.merging_dv_updates.clear();
+        self.merging_numeric_updates.clear();
         self.is_merging = false;
     }
 
-    // TODO used for doc values update
-    #[allow(dead_code)]
-    fn write_field_infos_gen<F: FieldInfosFormat>(
+    fn write_field_infos_gen<DW: Directory, F: FieldInfosFormat>(
         &self,
         info: &Arc<SegmentCommitInfo<D, C>>,
         field_infos: &FieldInfos,
-        dir: &Arc<D>,
+        dir: &DW,
         infos_format: &F,
     ) -> Result<HashSet<String>> {
         let next_field_infos_gen = info.next_field_infos_gen();
@@ -4089,7 +4621,7 @@ where
             est_infos_size as u64,
         ));
         // separately also track which files were created for this gen
-        let tracking_dir = TrackingDirectoryWrapper::new(dir.as_ref());
+        let tracking_dir = TrackingDirectoryWrapper::new(dir);
         infos_format.write(
             &tracking_dir,
             &info.info,
@@ -4097,13 +4629,216 @@ where
             field_infos,
             &infos_context,
         )?;
-        info.advance_del_gen();
         Ok(tracking_dir.get_create_files())
     }
 
-    // Writes field updates (new _X_N updates files) to the directory
-    pub fn write_field_updates<DW: Directory>(&self, _dir: &DW) -> Result<()> {
-        unreachable!()
+    // Writes field updates (new _X_N updates files) to the directory; returns
+    // false if there were no updates to write.
+    //
+    // Each updated field is written whole as a new DocValues generation, and
+    // a new FieldInfos generation records the generation of each field.
+    pub fn write_field_updates<D1: Directory>(
+        &mut self,
+        info: &Arc<SegmentCommitInfo<D, C>>,
+        dir: &Arc<D1>,
+    ) -> Result<bool> {
+        if self.pending_numeric_updates.is_empty() {
+            return Ok(false);
+        }
+        self.create_reader_if_not_exist(info, &IOContext::READ)?;
+
+        // Do this so we can delete any created files on exceptions;
+        // this saves all codecs from having to do it:
+        let tracking_dir = Arc::new(TrackingDirectoryWrapper::new(Arc::clone(dir)));
+        let (dv_updates_files, field_infos_files) =
+            match self.do_write_field_updates(info, &tracking_dir) {
+                Ok(files) => files,
+                Err(e) => {
+                    // Advance only the next write gens so that a 2nd attempt
+                    // to write will write to new files
+                    info.advance_next_write_doc_values_gen();
+                    info.advance_next_write_field_infos_gen();
+                    // Delete any partially created file(s):
+                    for file_name in &tracking_dir.get_create_files() {
+                        delete_file_ignoring_error(tracking_dir.as_ref(), file_name);
+                    }
+                    return Err(e);
+                }
+            };
+
+        info.advance_doc_values_gen();
+        info.set_doc_values_updates_files(dv_updates_files);
+        info.set_field_infos_files(field_infos_files);
+        info.advance_field_infos_gen();
+        self.pending_numeric_updates.clear();
+
+        // reopen the reader over the new generations, sharing its core
+        let reader = {
+            let reader = self.reader();
+            SegmentReader::build_from(
+                Arc::clone(info),
+                reader.as_ref(),
+                reader.live_docs(),
+                reader.num_docs(),
+                reader.is_nrt,
+            )?
+        };
+        self.reader = Some(Arc::new(reader));
+        self.nrt_reader = None;
+        Ok(true)
+    }
+
+    // Returns the DocValues files of each updated field and the FieldInfos files
+    #[allow(clippy::type_complexity)]
+    fn do_write_field_updates<D1: Directory>(
+        &self,
+        info: &Arc<SegmentCommitInfo<D, C>>,
+        dir: &Arc<TrackingDirectoryWrapper<D1, Arc<D1>>>,
+    ) -> Result<(HashMap<i32, HashSet<String>>, HashSet<String>)> {
+        let reader = self.reader();
+        let codec = info.info.codec();
+        let max_doc = info.info.max_doc();
+        let gen = info.next_write_doc_values_gen();
+        let segment_suffix = to_base36(gen as u64);
+
+        let mut dv_updates_files = HashMap::new();
+        let mut updated_infos = Vec::with_capacity(self.pending_numeric_updates.len());
+        for (field, updates) in &self.pending_numeric_updates {
+            let mut values = vec![Numeric::Null; max_doc as usize];
+            let field_info = match reader.field_infos.field_info_by_name(field) {
+                Some(fi) => {
+                    match fi.doc_values_type {
+                        DocValuesType::Numeric => {
+                            let current = reader.get_numeric_doc_values(field)?;
+                            let docs_with_field = reader.get_docs_with_field(field)?;
+                            for (doc_id, value) in values.iter_mut().enumerate() {
+                                if docs_with_field.get(doc_id)? {
+                                    *value = Numeric::Long(current.get(doc_id as DocId)?);
+                                }
+                            }
+                        }
+                        DocValuesType::Null => {}
+                        dv_type => bail!(IllegalArgument(format!(
+                            "cannot update field '{}' with {:?} doc values",
+                            field, dv_type
+                        ))),
+                    }
+                    // a copy, the attributes of the new generation are set on it
+                    FieldInfo::new(
+                        fi.name.clone(),
+                        fi.number,
+                        fi.has_store_term_vector,
+                        fi.omit_norms,
+                        fi.has_store_payloads,
+                        fi.index_options,
+                        DocValuesType::Numeric,
+                        gen,
+                        fi.attributes.read()?.clone(),
+                        fi.point_dimension_count,
+                        fi.point_num_bytes,
+                    )?
+                }
+                None => {
+                    // the field never existed in this segment
+                    let writer = match self.writer.upgrade() {
+                        Some(writer) => writer,
+                        None => bail!(AlreadyClosed("this IndexWriter is closed".into())),
+                    };
+                    let number = writer.global_field_numbers.add_or_get(
+                        field,
+                        0,
+                        DocValuesType::Numeric,
+                        0,
+                        0,
+                    )?;
+                    FieldInfo::new(
+                        field.clone(),
+                        number,
+                        false,
+                        false,
+                        false,
+                        IndexOptions::Null,
+                        DocValuesType::Numeric,
+                        gen,
+                        HashMap::new(),
+                        0,
+                        0,
+                    )?
+                }
+            };
+            for (doc_id, value) in updates {
+                values[*doc_id as usize] = Numeric::Long(*value);
+            }
+
+            // separately track the files of each field
+            let field_dir = Arc::new(TrackingDirectoryWrapper::new(Arc::clone(dir)));
+            {
+                let context = IOContext::Flush(FlushInfo::new(max_doc as u32, 8 * max_doc as u64));
+                let state = SegmentWriteState::new(
+                    Arc::clone(&field_dir),
+                    info.info.clone(),
+                    FieldInfos::new(vec![field_info.clone()])?,
+                    None,
+                    context,
+                    segment_suffix.clone(),
+                );
+                let mut consumer = codec.doc_values_format().fields_consumer(&state)?;
+                consumer
+                    .add_numeric_field(&field_info, &mut UpdatedNumericValuesIter::new(&values))?;
+            }
+            dv_updates_files.insert(field_info.number as i32, field_dir.get_create_files());
+            updated_infos.push(field_info);
+        }
+
+        // the FieldInfos of the new generation: the fields not updated now
+        // keep their previous generation
+        let mut infos: Vec<FieldInfo> = reader
+            .field_infos
+            .by_number
+            .values()
+            .filter(|fi| !self.pending_numeric_updates.contains_key(&fi.name))
+            .map(|fi| fi.as_ref().clone())
+            .collect();
+        infos.extend(updated_infos);
+        let field_infos = FieldInfos::new(infos)?;
+        let field_infos_files = self.write_field_infos_gen(
+            info,
+            &field_infos,
+            dir.as_ref(),
+            &codec.field_infos_format(),
+        )?;
+        Ok((dv_updates_files, field_infos_files))
+    }
+}
+
+// The values of an updated numeric field, by doc
+struct UpdatedNumericValuesIter<'a> {
+    values: &'a [Numeric],
+    upto: usize,
+}
+
+impl<'a> UpdatedNumericValuesIter<'a> {
+    fn new(values: &'a [Numeric]) -> Self {
+        UpdatedNumericValuesIter { values, upto: 0 }
+    }
+}
+
+impl<'a> Iterator for UpdatedNumericValuesIter<'a> {
+    type Item = Result<Numeric>;
+
+    fn next(&mut self) -> Option<Result<Numeric>> {
+        if self.upto < self.values.len() {
+            self.upto += 1;
+            Some(Ok(self.values[self.upto - 1]))
+        } else {
+            None
+        }
+    }
+}
+
+impl<'a> ReusableIterator for UpdatedNumericValuesIter<'a> {
+    fn reset(&mut self) {
+        self.upto = 0;
     }
 }
 
@@ -4257,3 +4992,1379 @@ where
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::codec::CodecEnum;
+    use core::doc::{
+        Field, FieldType, NUMERIC_DOC_VALUES_FIELD_TYPE, SORTED_DOC_VALUES_FIELD_TYPE,
+    };
+    use core::index::delete_policy::{
+        IndexDeletionPolicy, KeepOnlyLastCommitDeletionPolicy, SnapshotDeletionPolicy,
+    };
+    use core::index::index_commit::IndexCommitPoint;
+    use core::index::merge_policy::TieredMergePolicy;
+    use core::index::merge_scheduler::SerialMergeScheduler;
+    use core::index::segment_file_name;
+    use core::index::tests::{write_test_index, TestReader};
+    use core::index::{
+        Fields, IndexOptions, IndexReader, NumericDocValues, SoftDeletesDirectoryReader,
+        TermIterator, Terms,
+    };
+    use core::search::bm25_similarity::BM25Similarity;
+    use core::search::searcher::{DefaultIndexSearcher, IndexSearcher};
+    use core::search::sort::Sort;
+    use core::search::sort_field::{SimpleSortField, SortField};
+    use core::search::term_query::TermQuery;
+    use core::store::{
+        DataOutput, FSDirectory, IndexOutput, NativeFSLockFactory, RAMDirectory, RAMIndexOutput,
+        RAMLock,
+    };
+    use core::util::VariantValue;
+    use error::ErrorKind::LockObtainFailed;
+
+    use std::io::{self, Write};
+    use std::sync::mpsc;
+
+    #[test]
+    fn test_second_writer_fails_to_obtain_write_lock() {
+        let path = ::std::env::temp_dir().join("rucene_test_index_writer_lock");
+        let _ = ::std::fs::remove_dir_all(&path);
+        let dir = Arc::new(FSDirectory::new(&path, NativeFSLockFactory::default()).unwrap());
+        let conf = Arc::new(IndexWriterConfig::default());
+
+        let writer = IndexWriter::new(Arc::clone(&dir), Arc::clone(&conf)).unwrap();
+        match IndexWriter::new(Arc::clone(&dir), Arc::clone(&conf)) {
+            Err(e) => match e.kind() {
+                LockObtainFailed(_) => {}
+                _ => panic!("expected LockObtainFailed, got {:?}", e),
+            },
+            Ok(_) => panic!("two writers opened the same index"),
+        }
+
+        writer.close().unwrap();
+        drop(writer);
+        let writer = IndexWriter::new(dir, conf).unwrap();
+        writer.close().unwrap();
+        drop(writer);
+        ::std::fs::remove_dir_all(&path).unwrap();
+    }
+
+    #[test]
+    fn test_graph_flattened_once_per_field() {
+        let dir = Arc::new(RAMDirectory::new());
+        let writer = IndexWriter::new(dir, Arc::new(IndexWriterConfig::default())).unwrap();
+        assert!(writer.writer.graph_flattened("title"));
+        assert!(!writer.writer.graph_flattened("title"));
+        assert!(writer.writer.graph_flattened("body"));
+
+        // another writer warns again
+        let dir = Arc::new(RAMDirectory::new());
+        let other = IndexWriter::new(dir, Arc::new(IndexWriterConfig::default())).unwrap();
+        assert!(other.writer.graph_flattened("title"));
+    }
+
+    #[test]
+    fn test_add_documents_and_search_flushed_segments() {
+        let dir = Arc::new(RAMDirectory::new());
+        let mut conf = IndexWriterConfig::default();
+        // small enough for the buffered documents to be flushed several times
+        conf.ram_buffer_size_mb = Some(0.25);
+        let writer = IndexWriter::new(Arc::clone(&dir), Arc::new(conf)).unwrap();
+
+        let mut body_type = FieldType::default();
+        body_type.index_options = IndexOptions::DocsAndFreqsAndPositions;
+        let mut id_type = FieldType::default();
+        id_type.stored = true;
+        let num_docs = 10_000;
+        for i in 0..num_docs {
+            // "id{i}", the parity and i % 5 times "x"
+            let parity = if i % 2 == 0 { "even" } else { "odd" };
+            let body = format!("id{} {} {}", i, parity, "x ".repeat(i % 5));
+            let doc = vec![
+                Field::new(
+                    "body".to_string(),
+                    body_type.clone(),
+                    Some(VariantValue::VString(body)),
+                    None,
+                ),
+                Field::new(
+                    "id".to_string(),
+                    id_type.clone(),
+                    Some(VariantValue::VString(i.to_string())),
+                    None,
+                ),
+            ];
+            writer.add_document(doc).unwrap();
+        }
+        writer.commit().unwrap();
+        writer.close().unwrap();
+
+        let reader = TestReader::open(dir).unwrap();
+        assert_eq!(reader.num_docs(), num_docs as i32);
+        assert!(reader.leaves().len() > 1);
+
+        let searcher = DefaultIndexSearcher::new(Arc::new(reader));
+        let count = |term: &str| {
+            let term = Term::new("body".to_string(), term.as_bytes().to_vec());
+            searcher.count(&TermQuery::new(term, 1.0, None)).unwrap()
+        };
+        assert_eq!(count("even"), 5_000);
+        assert_eq!(count("odd"), 5_000);
+        assert_eq!(count("x"), 8_000);
+        assert_eq!(count("id4242"), 1);
+        assert_eq!(count("4242"), 0);
+
+        let reader = searcher.reader();
+        let fields = vec!["id".to_string()];
+        for leaf in reader.leaves() {
+            let norms = leaf.reader.norm_values("body").unwrap().unwrap();
+            for doc in 0..leaf.reader.max_doc() {
+                let stored = reader.document(leaf.doc_base + doc, &fields).unwrap();
+                assert_eq!(stored.fields.len(), 1);
+                let i: usize = stored.fields[0]
+                    .field
+                    .string_value()
+                    .unwrap()
+                    .parse()
+                    .unwrap();
+                let norm = (norms.get(doc).unwrap() & 0xFF) as u8;
+                let length = 2 + (i % 5) as i32;
+                assert_eq!(norm, BM25Similarity::encode_norm_value(1.0, length));
+            }
+        }
+    }
+
+    fn keyword(name: &str, value: &str) -> Field {
+        let mut field_type = FieldType::default();
+        field_type.tokenized = false;
+        field_type.index_options = IndexOptions::DocsAndFreqs;
+        Field::new(
+            name.to_string(),
+            field_type,
+            Some(VariantValue::VString(value.to_string())),
+            None,
+        )
+    }
+
+    fn count(reader: &Arc<TestReader>, field: &str, value: &str) -> i32 {
+        let searcher = DefaultIndexSearcher::new(Arc::clone(reader));
+        let term = Term::new(field.to_string(), value.as_bytes().to_vec());
+        searcher.count(&TermQuery::new(term, 1.0, None)).unwrap()
+    }
+
+    #[test]
+    fn test_delete_then_add_same_id() {
+        let reader = write_test_index(IndexWriterConfig::default(), |writer| {
+            for i in 0..10 {
+                let doc = vec![keyword("id", &i.to_string()), keyword("version", "1")];
+                writer.add_document(doc).unwrap();
+            }
+            writer.commit().unwrap();
+
+            // replaces a committed doc
+            writer.delete_documents_by_term("id", b"3").unwrap();
+            writer
+                .add_document(vec![keyword("id", "3"), keyword("version", "2")])
+                .unwrap();
+            // deletes a doc of the in-memory segment
+            writer
+                .add_document(vec![keyword("id", "10"), keyword("version", "1")])
+                .unwrap();
+            writer.delete_documents_by_term("id", b"10").unwrap();
+            writer.commit().unwrap();
+        });
+        assert_eq!(reader.num_docs(), 10);
+        assert_eq!(reader.max_doc(), 12);
+        assert_eq!(count(&reader, "id", "3"), 1);
+        assert_eq!(count(&reader, "version", "2"), 1);
+        assert_eq!(count(&reader, "version", "1"), 9);
+        assert_eq!(count(&reader, "id", "10"), 0);
+    }
+
+    #[test]
+    fn test_delete_matching_no_docs() {
+        let reader = write_test_index(IndexWriterConfig::default(), |writer| {
+            for i in 0..10 {
+                writer
+                    .add_document(vec![keyword("id", &i.to_string())])
+                    .unwrap();
+            }
+            writer.delete_documents_by_term("id", b"missing").unwrap();
+            writer.commit().unwrap();
+            writer.delete_documents_by_term("id", b"10").unwrap();
+            let query = TermQuery::new(Term::new("other".to_string(), b"0".to_vec()), 1.0, None);
+            writer.delete_documents_by_query(Arc::new(query)).unwrap();
+            writer.commit().unwrap();
+        });
+        assert_eq!(reader.num_docs(), 10);
+        assert_eq!(reader.max_doc(), 10);
+        assert!(!reader.has_deletions());
+    }
+
+    #[test]
+    fn test_concurrent_add_and_delete() {
+        let reader = write_test_index(IndexWriterConfig::default(), |writer| {
+            let threads: Vec<_> = (0..4)
+                .map(|t| {
+                    let writer = writer.clone();
+                    ::std::thread::spawn(move || {
+                        for i in 0..250 {
+                            let id = format!("{}_{}", t, i);
+                            let parity = if i % 2 == 0 { "even" } else { "odd" };
+                            writer
+                                .add_document(vec![keyword("id", &id), keyword("parity", parity)])
+                                .unwrap();
+                            if i % 2 == 1 {
+                                // deletes the even doc added just before by this thread
+                                let id = format!("{}_{}", t, i - 1);
+                                writer
+                                    .delete_documents_by_term("id", id.as_bytes())
+                                    .unwrap();
+                            }
+                        }
+                    })
+                })
+                .collect();
+            for thread in threads {
+                thread.join().unwrap();
+            }
+            // deletes nothing more, all the even docs are already deleted
+            let query =
+                TermQuery::new(Term::new("parity".to_string(), b"even".to_vec()), 1.0, None);
+            writer.delete_documents_by_query(Arc::new(query)).unwrap();
+            writer.commit().unwrap();
+        });
+        assert_eq!(reader.num_docs(), 500);
+        assert!(reader.max_doc() >= 500 && reader.max_doc() <= 1000);
+        assert_eq!(count(&reader, "parity", "even"), 0);
+        assert_eq!(count(&reader, "parity", "odd"), 500);
+        assert_eq!(count(&reader, "id", "2_249"), 1);
+        assert_eq!(count(&reader, "id", "2_248"), 0);
+    }
+
+    #[test]
+    fn test_update_same_key_across_flushes() {
+        let mut conf = IndexWriterConfig::default();
+        conf.max_buffered_docs = Some(64);
+        let reader = write_test_index(conf, |writer| {
+            let mut last_seq_no = 0;
+            for i in 0..1000 {
+                let doc = vec![keyword("id", "key"), keyword("version", &i.to_string())];
+                let term = Term::new("id".to_string(), b"key".to_vec());
+                let seq_no = writer.update_document(doc, Some(term)).unwrap();
+                assert!(seq_no > last_seq_no);
+                last_seq_no = seq_no;
+
+                if i % 97 == 0 {
+                    let reader = Arc::new(writer.get_reader(true, false).unwrap());
+                    assert_eq!(reader.num_docs(), 1);
+                    assert_eq!(count(&reader, "version", &i.to_string()), 1);
+                }
+                if i % 250 == 0 {
+                    writer.commit().unwrap();
+                }
+            }
+            let seq_no = writer.delete_documents_by_term("id", b"missing").unwrap();
+            assert!(seq_no > last_seq_no);
+            writer.commit().unwrap();
+        });
+        assert!(reader.max_doc() > 64);
+        assert_eq!(reader.num_docs(), 1);
+        assert_eq!(count(&reader, "id", "key"), 1);
+        assert_eq!(count(&reader, "version", "999"), 1);
+    }
+
+    #[test]
+    fn test_concurrent_updates_keep_one_version() {
+        let mut conf = IndexWriterConfig::default();
+        conf.max_buffered_docs = Some(32);
+        let reader = write_test_index(conf, |writer| {
+            writer
+                .add_document(vec![keyword("id", "key"), keyword("writer", "init")])
+                .unwrap();
+
+            let threads: Vec<_> = (0..4)
+                .map(|t| {
+                    let writer = writer.clone();
+                    ::std::thread::spawn(move || {
+                        for _ in 0..250 {
+                            let doc = vec![keyword("id", "key"), keyword("writer", &t.to_string())];
+                            let term = Term::new("id".to_string(), b"key".to_vec());
+                            writer.update_document(doc, Some(term)).unwrap();
+                        }
+                    })
+                })
+                .collect();
+            // no NRT reader sees both the old and new versions, or neither
+            for _ in 0..20 {
+                let reader = Arc::new(writer.get_reader(true, false).unwrap());
+                assert_eq!(count(&reader, "id", "key"), 1);
+            }
+            for thread in threads {
+                thread.join().unwrap();
+            }
+            writer.commit().unwrap();
+        });
+        assert_eq!(reader.num_docs(), 1);
+        assert_eq!(count(&reader, "writer", "init"), 0);
+    }
+
+    // blocks the merges writing their files until it is opened
+    struct GatedMergeDirectory {
+        dir: RAMDirectory,
+        open: Mutex<bool>,
+        cond: Condvar,
+        merge_started: AtomicBool,
+    }
+
+    impl GatedMergeDirectory {
+        fn new() -> GatedMergeDirectory {
+            GatedMergeDirectory {
+                dir: RAMDirectory::new(),
+                open: Mutex::new(false),
+                cond: Condvar::new(),
+                merge_started: AtomicBool::new(false),
+            }
+        }
+
+        fn open_gate(&self) {
+            *self.open.lock().unwrap() = true;
+            self.cond.notify_all();
+        }
+
+        fn wait_merge_started(&self) {
+            let start = SystemTime::now();
+            while !self.merge_started.load(Ordering::Acquire) {
+                assert!(start.elapsed().unwrap() < Duration::from_secs(30));
+                ::std::thread::sleep(Duration::from_millis(1));
+            }
+        }
+    }
+
+    impl fmt::Display for GatedMergeDirectory {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "GatedMergeDirectory({})", self.dir)
+        }
+    }
+
+    impl Directory for GatedMergeDirectory {
+        type LK = RAMLock;
+        type IndexOutput = RAMIndexOutput;
+        type TempOutput = RAMIndexOutput;
+
+        fn list_all(&self) -> Result<Vec<String>> {
+            self.dir.list_all()
+        }
+
+        fn file_length(&self, name: &str) -> Result<i64> {
+            self.dir.file_length(name)
+        }
+
+        fn create_output(&self, name: &str, ctx: &IOContext) -> Result<Self::IndexOutput> {
+            if let IOContext::Merge(_) = *ctx {
+                self.merge_started.store(true, Ordering::Release);
+                let mut open = self.open.lock()?;
+                while !*open {
+                    open = self.cond.wait(open)?;
+                }
+            }
+            self.dir.create_output(name, ctx)
+        }
+
+        fn open_input(&self, name: &str, ctx: &IOContext) -> Result<Box<dyn IndexInput>> {
+            self.dir.open_input(name, ctx)
+        }
+
+        fn obtain_lock(&self, name: &str) -> Result<Self::LK> {
+            self.dir.obtain_lock(name)
+        }
+
+        fn create_temp_output(
+            &self,
+            prefix: &str,
+            suffix: &str,
+            ctx: &IOContext,
+        ) -> Result<Self::TempOutput> {
+            self.dir.create_temp_output(prefix, suffix, ctx)
+        }
+
+        fn delete_file(&self, name: &str) -> Result<()> {
+            self.dir.delete_file(name)
+        }
+
+        fn sync(&self, names: &HashSet<String>) -> Result<()> {
+            self.dir.sync(names)
+        }
+
+        fn sync_meta_data(&self) -> Result<()> {
+            self.dir.sync_meta_data()
+        }
+
+        fn rename(&self, source: &str, dest: &str) -> Result<()> {
+            self.dir.rename(source, dest)
+        }
+    }
+
+    type GatedWriter =
+        IndexWriter<GatedMergeDirectory, CodecEnum, SerialMergeScheduler, TieredMergePolicy>;
+
+    // four committed segments of ten docs
+    fn gated_writer(merge_on_commit: bool) -> (Arc<GatedMergeDirectory>, GatedWriter) {
+        let dir = Arc::new(GatedMergeDirectory::new());
+        let mut conf = IndexWriterConfig::default();
+        conf.merge_on_commit = merge_on_commit;
+        let writer = IndexWriter::new(Arc::clone(&dir), Arc::new(conf)).unwrap();
+        for i in 0..40 {
+            writer
+                .add_document(vec![keyword("id", &i.to_string()), keyword("kind", "old")])
+                .unwrap();
+            if i % 10 == 9 {
+                writer.commit().unwrap();
+            }
+        }
+        (dir, writer)
+    }
+
+    fn spawn_force_merge(writer: &GatedWriter) -> mpsc::Receiver<()> {
+        let (tx, rx) = mpsc::channel();
+        let writer = writer.clone();
+        ::std::thread::spawn(move || {
+            writer.force_merge(1, true).unwrap();
+            tx.send(()).unwrap();
+        });
+        rx
+    }
+
+    #[test]
+    fn test_refresh_and_commit_during_merge() {
+        let (dir, writer) = gated_writer(false);
+        let merged = spawn_force_merge(&writer);
+        dir.wait_merge_started();
+
+        // indexes, refreshes, searches and commits while the merge is blocked
+        let (tx, refreshed) = mpsc::channel();
+        let indexer = writer.clone();
+        ::std::thread::spawn(move || {
+            let mut max_latency = Duration::default();
+            for i in 0..20 {
+                let id = format!("new{}", i);
+                indexer
+                    .add_document(vec![keyword("id", &id), keyword("kind", "new")])
+                    .unwrap();
+                let start = SystemTime::now();
+                let reader = Arc::new(indexer.get_reader(true, false).unwrap());
+                max_latency = max_latency.max(start.elapsed().unwrap());
+                let searcher = DefaultIndexSearcher::new(reader);
+                let term = Term::new("kind".to_string(), b"new".to_vec());
+                let count = searcher.count(&TermQuery::new(term, 1.0, None)).unwrap();
+                assert_eq!(count, i + 1);
+                if i % 5 == 4 {
+                    indexer.commit().unwrap();
+                }
+            }
+            tx.send(max_latency).unwrap();
+        });
+        let max_latency = refreshed
+            .recv_timeout(Duration::from_secs(60))
+            .expect("refreshes blocked by the running merge");
+        assert!(max_latency < Duration::from_secs(10));
+        assert!(merged.try_recv().is_err());
+
+        dir.open_gate();
+        merged
+            .recv_timeout(Duration::from_secs(60))
+            .expect("force merge didn't finish");
+        writer.commit().unwrap();
+        let reader = Arc::new(writer.get_reader(true, false).unwrap());
+        assert_eq!(reader.num_docs(), 60);
+        let searcher = DefaultIndexSearcher::new(reader);
+        let term = Term::new("kind".to_string(), b"old".to_vec());
+        assert_eq!(
+            searcher.count(&TermQuery::new(term, 1.0, None)).unwrap(),
+            40
+        );
+        writer.close().unwrap();
+    }
+
+    #[test]
+    fn test_merge_on_commit_waits_for_merges() {
+        let (dir, writer) = gated_writer(true);
+        let merged = spawn_force_merge(&writer);
+        dir.wait_merge_started();
+
+        let (tx, committed) = mpsc::channel();
+        let committer = writer.clone();
+        ::std::thread::spawn(move || {
+            committer.commit().unwrap();
+            tx.send(()).unwrap();
+        });
+        assert!(committed.recv_timeout(Duration::from_millis(200)).is_err());
+
+        dir.open_gate();
+        committed
+            .recv_timeout(Duration::from_secs(60))
+            .expect("commit didn't finish after the merge");
+        merged
+            .recv_timeout(Duration::from_secs(60))
+            .expect("force merge didn't finish");
+        let infos: SegmentInfos<GatedMergeDirectory, CodecEnum> =
+            SegmentInfos::read_latest_commit(&dir).unwrap();
+        assert_eq!(infos.segments.len(), 1);
+        assert_eq!(infos.segments[0].info.max_doc(), 40);
+        writer.close().unwrap();
+    }
+
+    // fails the writes to the files with the given extension once armed
+    struct FaultyDirectory {
+        dir: RAMDirectory,
+        failing_ext: Arc<Mutex<Option<&'static str>>>,
+    }
+
+    impl FaultyDirectory {
+        fn new() -> FaultyDirectory {
+            FaultyDirectory {
+                dir: RAMDirectory::new(),
+                failing_ext: Arc::new(Mutex::new(None)),
+            }
+        }
+
+        fn fail_writes(&self, ext: Option<&'static str>) {
+            *self.failing_ext.lock().unwrap() = ext;
+        }
+    }
+
+    struct FaultyOutput {
+        output: RAMIndexOutput,
+        failing_ext: Arc<Mutex<Option<&'static str>>>,
+    }
+
+    impl Write for FaultyOutput {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if let Some(ext) = *self.failing_ext.lock().unwrap() {
+                if self.output.name().ends_with(ext) {
+                    return Err(io::Error::new(
+                        io::ErrorKind::Other,
+                        format!("injected failure writing '{}'", self.output.name()),
+                    ));
+                }
+            }
+            self.output.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.output.flush()
+        }
+    }
+
+    impl DataOutput for FaultyOutput {}
+
+    impl IndexOutput for FaultyOutput {
+        fn name(&self) -> &str {
+            self.output.name()
+        }
+
+        fn file_pointer(&self) -> i64 {
+            self.output.file_pointer()
+        }
+
+        fn checksum(&self) -> Result<i64> {
+            self.output.checksum()
+        }
+    }
+
+    impl fmt::Display for FaultyDirectory {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "FaultyDirectory({})", self.dir)
+        }
+    }
+
+    impl Directory for FaultyDirectory {
+        type LK = RAMLock;
+        type IndexOutput = FaultyOutput;
+        type TempOutput = FaultyOutput;
+
+        fn list_all(&self) -> Result<Vec<String>> {
+            self.dir.list_all()
+        }
+
+        fn file_length(&self, name: &str) -> Result<i64> {
+            self.dir.file_length(name)
+        }
+
+        fn create_output(&self, name: &str, ctx: &IOContext) -> Result<Self::IndexOutput> {
+            Ok(FaultyOutput {
+                output: self.dir.create_output(name, ctx)?,
+                failing_ext: Arc::clone(&self.failing_ext),
+            })
+        }
+
+        fn open_input(&self, name: &str, ctx: &IOContext) -> Result<Box<dyn IndexInput>> {
+            self.dir.open_input(name, ctx)
+        }
+
+        fn obtain_lock(&self, name: &str) -> Result<Self::LK> {
+            self.dir.obtain_lock(name)
+        }
+
+        fn create_temp_output(
+            &self,
+            prefix: &str,
+            suffix: &str,
+            ctx: &IOContext,
+        ) -> Result<Self::TempOutput> {
+            Ok(FaultyOutput {
+                output: self.dir.create_temp_output(prefix, suffix, ctx)?,
+                failing_ext: Arc::clone(&self.failing_ext),
+            })
+        }
+
+        fn delete_file(&self, name: &str) -> Result<()> {
+            self.dir.delete_file(name)
+        }
+
+        fn sync(&self, names: &HashSet<String>) -> Result<()> {
+            self.dir.sync(names)
+        }
+
+        fn sync_meta_data(&self) -> Result<()> {
+            self.dir.sync_meta_data()
+        }
+
+        fn rename(&self, source: &str, dest: &str) -> Result<()> {
+            self.dir.rename(source, dest)
+        }
+    }
+
+    // norms, doc values, stored fields and postings for every doc
+    fn faulty_doc(i: usize) -> Vec<Field> {
+        let mut stored_type = FieldType::default();
+        stored_type.stored = true;
+        vec![
+            keyword("id", &i.to_string()),
+            Field::new(
+                "stored".to_string(),
+                stored_type,
+                Some(VariantValue::VString(format!("doc {}", i))),
+                None,
+            ),
+            Field::new(
+                "num".to_string(),
+                NUMERIC_DOC_VALUES_FIELD_TYPE,
+                Some(VariantValue::Long(i as i64)),
+                None,
+            ),
+        ]
+    }
+
+    fn assert_flush_failed(err: &Error, ext: &str) {
+        match err.kind() {
+            Index(FlushFailed(desc)) => {
+                assert!(desc.contains("injected failure"), "{}", desc);
+                assert!(desc.contains("deleted all"), "{}", desc);
+                assert!(desc.contains("with 10 buffered docs discarded"), "{}", desc);
+            }
+            _ => panic!("expected FlushFailed for {}, got {:?}", ext, err),
+        }
+        // the original error is kept as the cause
+        assert!(err.iter().nth(1).is_some());
+    }
+
+    #[test]
+    fn test_failed_flush_leaves_no_partial_segment() {
+        // norms, doc values, stored fields, postings, terms dictionary, field infos,
+        // compound file and segment info
+        for ext in &[
+            ".nvd", ".dvd", ".fdt", ".doc", ".tim", ".fnm", ".cfs", ".si",
+        ] {
+            let dir = Arc::new(FaultyDirectory::new());
+            let writer =
+                IndexWriter::new(Arc::clone(&dir), Arc::new(IndexWriterConfig::default())).unwrap();
+            for i in 0..10 {
+                writer.add_document(faulty_doc(i)).unwrap();
+            }
+            writer.commit().unwrap();
+            let committed_files = dir.list_all().unwrap();
+
+            // armed once the docs are buffered, the stored fields are written on add
+            for i in 10..20 {
+                writer.add_document(faulty_doc(i)).unwrap();
+            }
+            dir.fail_writes(Some(*ext));
+            match writer.get_reader(true, false) {
+                Ok(_) => panic!("NRT reader opened while {} writes fail", ext),
+                Err(e) => assert_flush_failed(&e, ext),
+            }
+            assert_eq!(dir.list_all().unwrap(), committed_files);
+
+            dir.fail_writes(None);
+            for i in 20..30 {
+                writer.add_document(faulty_doc(i)).unwrap();
+            }
+            dir.fail_writes(Some(*ext));
+            match writer.commit() {
+                Ok(_) => panic!("committed while {} writes fail", ext),
+                Err(e) => assert_flush_failed(&e, ext),
+            }
+            assert_eq!(dir.list_all().unwrap(), committed_files);
+            let infos: SegmentInfos<FaultyDirectory, CodecEnum> =
+                SegmentInfos::read_latest_commit(&dir).unwrap();
+            assert_eq!(infos.segments.len(), 1);
+            assert_eq!(infos.segments[0].info.max_doc(), 10);
+
+            // the discarded docs are gone, the writer keeps indexing
+            dir.fail_writes(None);
+            for i in 30..40 {
+                writer.add_document(faulty_doc(i)).unwrap();
+            }
+            let reader = Arc::new(writer.get_reader(true, false).unwrap());
+            assert_eq!(reader.num_docs(), 20);
+            let searcher = DefaultIndexSearcher::new(reader);
+            for (id, expected) in &[("5", 1), ("15", 0), ("25", 0), ("35", 1)] {
+                let term = Term::new("id".to_string(), id.as_bytes().to_vec());
+                let count = searcher.count(&TermQuery::new(term, 1.0, None)).unwrap();
+                assert_eq!(count, *expected, "id {} after {} failures", id, ext);
+            }
+            writer.commit().unwrap();
+            writer.close().unwrap();
+            let infos: SegmentInfos<FaultyDirectory, CodecEnum> =
+                SegmentInfos::read_latest_commit(&dir).unwrap();
+            let max_doc: i32 = infos.segments.iter().map(|s| s.info.max_doc()).sum();
+            assert_eq!(max_doc, 20);
+        }
+    }
+
+    #[test]
+    fn test_index_sort_by_long_descending() {
+        let dir = Arc::new(RAMDirectory::new());
+        let sort = Sort::new(vec![SortField::Simple(SimpleSortField::new(
+            "rank".to_string(),
+            SortFieldType::Long,
+            true,
+        ))]);
+        let mut conf = IndexWriterConfig::default();
+        conf.max_buffered_docs = Some(50);
+        conf.index_sort = Some(sort.clone());
+        let writer = IndexWriter::new(Arc::clone(&dir), Arc::new(conf)).unwrap();
+
+        let mut id_type = FieldType::default();
+        id_type.stored = true;
+        let rank = |i: usize| ((i * 7919) % 500) as i64;
+        for i in 0..500 {
+            let parity = if rank(i) % 2 == 0 { "even" } else { "odd" };
+            let doc = vec![
+                keyword("id", &i.to_string()),
+                keyword("parity", parity),
+                Field::new(
+                    "stored_id".to_string(),
+                    id_type.clone(),
+                    Some(VariantValue::VString(i.to_string())),
+                    None,
+                ),
+                Field::new(
+                    "rank".to_string(),
+                    NUMERIC_DOC_VALUES_FIELD_TYPE,
+                    Some(VariantValue::Long(rank(i))),
+                    None,
+                ),
+            ];
+            writer.add_document(doc).unwrap();
+            if i == 20 {
+                // both deletes hit documents that are still buffered
+                writer.delete_documents_by_term("id", b"3").unwrap();
+                let term = Term::new("id".to_string(), b"7".to_vec());
+                let query = TermQuery::new(term, 1.0, None);
+                writer.delete_documents_by_query(Arc::new(query)).unwrap();
+            }
+        }
+        writer.commit().unwrap();
+
+        let check_sorted = |reader: &TestReader| {
+            let fields = vec!["stored_id".to_string()];
+            for leaf in reader.leaves() {
+                assert_eq!(leaf.reader.index_sort(), Some(&sort));
+                let ranks = leaf.reader.get_numeric_doc_values("rank").unwrap();
+                let live_docs = leaf.reader.live_docs();
+                let mut last = i64::max_value();
+                for doc in 0..leaf.reader.max_doc() {
+                    let value = ranks.get(doc).unwrap();
+                    assert!(value <= last, "{} after {}", value, last);
+                    last = value;
+                    if live_docs.get(doc as usize).unwrap() {
+                        let stored = reader.document(leaf.doc_base + doc, &fields).unwrap();
+                        let i: usize = stored.fields[0]
+                            .field
+                            .string_value()
+                            .unwrap()
+                            .parse()
+                            .unwrap();
+                        assert_eq!(value, rank(i));
+                    }
+                }
+            }
+        };
+
+        let reader = Arc::new(TestReader::open(Arc::clone(&dir)).unwrap());
+        assert_eq!(reader.num_docs(), 498);
+        assert!(reader.leaves().len() > 1);
+        check_sorted(&reader);
+        // ids 3 and 7 both have odd ranks
+        assert_eq!(count(&reader, "parity", "even"), 250);
+        assert_eq!(count(&reader, "parity", "odd"), 248);
+        assert_eq!(count(&reader, "id", "3"), 0);
+        assert_eq!(count(&reader, "id", "7"), 0);
+
+        writer.force_merge(1, true).unwrap();
+        writer.commit().unwrap();
+        writer.close().unwrap();
+
+        let reader = TestReader::open(dir).unwrap();
+        assert_eq!(reader.num_docs(), 498);
+        assert_eq!(reader.leaves().len(), 1);
+        check_sorted(&reader);
+    }
+
+    #[test]
+    fn test_index_sort_by_sorted_doc_values() {
+        let dir = Arc::new(RAMDirectory::new());
+        let sort = Sort::new(vec![SortField::Simple(SimpleSortField::new(
+            "name".to_string(),
+            SortFieldType::String,
+            false,
+        ))]);
+        let mut conf = IndexWriterConfig::default();
+        conf.max_buffered_docs = Some(50);
+        conf.index_sort = Some(sort.clone());
+        let writer = IndexWriter::new(Arc::clone(&dir), Arc::new(conf)).unwrap();
+
+        let mut id_type = FieldType::default();
+        id_type.stored = true;
+        id_type.tokenized = false;
+        id_type.index_options = IndexOptions::Docs;
+        id_type.store_term_vectors = true;
+        // every 13th doc has no name, they sort first
+        let name = |i: usize| format!("n{:03}", (i * 7919) % 300);
+        for i in 0..300 {
+            let mut doc = vec![
+                keyword("id", &i.to_string()),
+                Field::new(
+                    "stored_id".to_string(),
+                    id_type.clone(),
+                    Some(VariantValue::VString(i.to_string())),
+                    None,
+                ),
+            ];
+            if i % 13 != 0 {
+                doc.push(Field::new(
+                    "name".to_string(),
+                    SORTED_DOC_VALUES_FIELD_TYPE,
+                    Some(VariantValue::from(name(i).as_bytes())),
+                    None,
+                ));
+            }
+            writer.add_document(doc).unwrap();
+            if i == 30 {
+                // resolved against the sorted doc ids of the flushed segment
+                let term = Term::new("id".to_string(), b"5".to_vec());
+                let query = TermQuery::new(term, 1.0, None);
+                writer.delete_documents_by_query(Arc::new(query)).unwrap();
+            }
+        }
+        writer.commit().unwrap();
+
+        let check_sorted = |reader: &TestReader| {
+            let fields = vec!["stored_id".to_string()];
+            for leaf in reader.leaves() {
+                assert_eq!(leaf.reader.index_sort(), Some(&sort));
+                let names = leaf.reader.get_sorted_doc_values("name").unwrap();
+                let live_docs = leaf.reader.live_docs();
+                let mut last: Option<Vec<u8>> = None;
+                for doc in 0..leaf.reader.max_doc() {
+                    let ord = names.get_ord(doc).unwrap();
+                    let value = if ord < 0 {
+                        assert!(last.is_none(), "missing name after {:?}", last);
+                        None
+                    } else {
+                        let value = names.lookup_ord(ord).unwrap();
+                        if let Some(ref last) = last {
+                            assert!(&value >= last);
+                        }
+                        last = Some(value.clone());
+                        Some(value)
+                    };
+                    if live_docs.get(doc as usize).unwrap() {
+                        let stored = reader.document(leaf.doc_base + doc, &fields).unwrap();
+                        let id = stored.fields[0].field.string_value().unwrap().to_string();
+                        let i: usize = id.parse().unwrap();
+                        if i % 13 == 0 {
+                            assert!(value.is_none());
+                        } else {
+                            assert_eq!(value, Some(name(i).into_bytes()));
+                        }
+                        // the term vectors follow the sorted docs too
+                        let vectors = leaf.reader.term_vector(doc).unwrap().unwrap();
+                        let terms = vectors.terms("stored_id").unwrap().unwrap();
+                        let mut terms = terms.iterator().unwrap();
+                        assert_eq!(terms.next().unwrap(), Some(id.into_bytes()));
+                    }
+                }
+            }
+        };
+
+        let reader = Arc::new(TestReader::open(Arc::clone(&dir)).unwrap());
+        assert_eq!(reader.num_docs(), 299);
+        assert!(reader.leaves().len() > 1);
+        check_sorted(&reader);
+        assert_eq!(count(&reader, "id", "5"), 0);
+        assert_eq!(count(&reader, "id", "35"), 1);
+        // no unsorted stored fields or term vectors are left behind
+        for file in dir.list_all().unwrap() {
+            assert!(!file.contains("_unsorted"), "{}", file);
+        }
+
+        writer.force_merge(1, true).unwrap();
+        writer.commit().unwrap();
+        writer.close().unwrap();
+
+        let reader = TestReader::open(dir).unwrap();
+        assert_eq!(reader.num_docs(), 299);
+        assert_eq!(reader.leaves().len(), 1);
+        check_sorted(&reader);
+    }
+
+    #[test]
+    fn test_soft_deletes() {
+        let dir = Arc::new(RAMDirectory::new());
+        let mut conf = IndexWriterConfig::default();
+        conf.set_soft_deletes_field("soft_deletes");
+        let writer = IndexWriter::new(Arc::clone(&dir), Arc::new(conf)).unwrap();
+        let doc = |i: i32| vec![keyword("id", &i.to_string()), keyword("body", "value")];
+        for i in 0..100 {
+            writer.add_document(doc(i)).unwrap();
+        }
+        writer.commit().unwrap();
+        for i in (0..100).step_by(2) {
+            let term = Term::new("id".to_string(), i.to_string().into_bytes());
+            writer.soft_update_document(doc(i), Some(term)).unwrap();
+        }
+        // the new documents are soft deleted when they are updated again
+        for i in (0..10).step_by(2) {
+            let term = Term::new("id".to_string(), i.to_string().into_bytes());
+            writer.soft_update_document(doc(i), Some(term)).unwrap();
+        }
+        writer.commit().unwrap();
+        let info = Arc::clone(&writer.writer.segment_infos.segments[0]);
+        assert_eq!(writer.num_deletes_to_merge(&info), 50);
+        // cached until the segment changes
+        assert_eq!(info.soft_delete_count(0), Some(50));
+        let info = Arc::clone(&writer.writer.segment_infos.segments[1]);
+        assert_eq!(info.info.max_doc(), 55);
+        assert_eq!(writer.num_deletes_to_merge(&info), 5);
+
+        writer.force_merge(1, true).unwrap();
+        writer.commit().unwrap();
+
+        // the merge keeps the soft deleted documents, as reclaimable ones
+        let info = Arc::clone(&writer.writer.segment_infos.segments[0]);
+        assert_eq!(info.info.max_doc(), 155);
+        assert_eq!(writer.num_deleted_docs(&info), 0);
+        assert_eq!(writer.num_deletes_to_merge(&info), 55);
+
+        let reader = TestReader::open(Arc::clone(&dir)).unwrap();
+        assert_eq!(reader.num_docs(), 155);
+        let reader = SoftDeletesDirectoryReader::new(reader, "soft_deletes").unwrap();
+        assert_eq!(reader.num_docs(), 100);
+        let searcher = DefaultIndexSearcher::new(Arc::new(reader));
+        let count_live = |field: &str, value: &str| {
+            let term = Term::new(field.to_string(), value.as_bytes().to_vec());
+            searcher.count(&TermQuery::new(term, 1.0, None)).unwrap()
+        };
+        // the updated documents are replaced by their new version
+        assert_eq!(count_live("body", "value"), 100);
+        assert_eq!(count_live("id", "4"), 1);
+        assert_eq!(count_live("id", "5"), 1);
+
+        // the soft deleted documents are still in the index
+        let raw = Arc::new(TestReader::open(dir).unwrap());
+        assert_eq!(count(&raw, "body", "value"), 155);
+        assert_eq!(count(&raw, "id", "4"), 3);
+        assert_eq!(count(&raw, "id", "14"), 2);
+    }
+
+    #[test]
+    fn test_prepared_commit_is_visible_after_commit() {
+        let dir = Arc::new(RAMDirectory::new());
+        let writer =
+            IndexWriter::new(Arc::clone(&dir), Arc::new(IndexWriterConfig::default())).unwrap();
+        writer.add_document(vec![keyword("id", "0")]).unwrap();
+        writer.commit().unwrap();
+
+        writer.add_document(vec![keyword("id", "1")]).unwrap();
+        let mut data = HashMap::new();
+        data.insert("txn".to_string(), "42".to_string());
+        writer.set_live_commit_data(data.clone()).unwrap();
+        assert!(writer.prepare_commit().unwrap() > 0);
+        let files = dir.list_all().unwrap();
+        assert!(files
+            .iter()
+            .any(|f| f.starts_with(INDEX_FILE_PENDING_SEGMENTS)));
+        let reader = TestReader::open(Arc::clone(&dir)).unwrap();
+        assert_eq!(reader.num_docs(), 1);
+
+        // changes after the prepare are not part of the commit
+        writer.add_document(vec![keyword("id", "2")]).unwrap();
+        writer.commit().unwrap();
+        let reader = TestReader::open(Arc::clone(&dir)).unwrap();
+        assert_eq!(reader.num_docs(), 2);
+        let infos: SegmentInfos<RAMDirectory, CodecEnum> =
+            SegmentInfos::read_latest_commit(&dir).unwrap();
+        assert_eq!(infos.user_data, data);
+        assert_eq!(writer.live_commit_data().unwrap(), data);
+
+        writer.commit().unwrap();
+        writer.close().unwrap();
+        let reader = TestReader::open(dir).unwrap();
+        assert_eq!(reader.num_docs(), 3);
+    }
+
+    #[test]
+    fn test_prepared_commit_is_lost_on_crash() {
+        let dir = Arc::new(RAMDirectory::new());
+        let writer =
+            IndexWriter::new(Arc::clone(&dir), Arc::new(IndexWriterConfig::default())).unwrap();
+        writer.add_document(vec![keyword("id", "0")]).unwrap();
+        writer.commit().unwrap();
+        writer.add_document(vec![keyword("id", "1")]).unwrap();
+        writer.prepare_commit().unwrap();
+        // a writer with a prepared commit can't be closed, dropping it leaves
+        // the index as a crash would
+        drop(writer);
+
+        let reader = Arc::new(TestReader::open(dir).unwrap());
+        assert_eq!(reader.num_docs(), 1);
+        assert_eq!(count(&reader, "id", "1"), 0);
+    }
+
+    #[test]
+    fn test_rollback_keeps_last_commit() {
+        let dir = Arc::new(RAMDirectory::new());
+        let conf = Arc::new(IndexWriterConfig::default());
+        let writer = IndexWriter::new(Arc::clone(&dir), Arc::clone(&conf)).unwrap();
+        writer.add_document(vec![keyword("id", "0")]).unwrap();
+        writer.add_document(vec![keyword("id", "1")]).unwrap();
+        writer.commit().unwrap();
+
+        writer.add_document(vec![keyword("id", "2")]).unwrap();
+        writer.delete_documents_by_term("id", b"0").unwrap();
+        writer.flush().unwrap();
+        writer.add_document(vec![keyword("id", "3")]).unwrap();
+        writer.rollback().unwrap();
+        assert!(!writer.is_open());
+
+        let reader = Arc::new(TestReader::open(Arc::clone(&dir)).unwrap());
+        assert_eq!(reader.num_docs(), 2);
+        assert_eq!(count(&reader, "id", "0"), 1);
+        assert_eq!(count(&reader, "id", "2"), 0);
+
+        // a rollback aborts a prepared commit too
+        let writer = IndexWriter::new(Arc::clone(&dir), conf).unwrap();
+        writer.add_document(vec![keyword("id", "4")]).unwrap();
+        writer.prepare_commit().unwrap();
+        writer.rollback().unwrap();
+        let files = dir.list_all().unwrap();
+        assert!(!files
+            .iter()
+            .any(|f| f.starts_with(INDEX_FILE_PENDING_SEGMENTS)));
+        let reader = TestReader::open(dir).unwrap();
+        assert_eq!(reader.num_docs(), 2);
+    }
+
+    #[test]
+    fn test_snapshot_keeps_commit_files() {
+        let dir = Arc::new(RAMDirectory::new());
+        let policy = Arc::new(SnapshotDeletionPolicy::new(Arc::new(
+            KeepOnlyLastCommitDeletionPolicy,
+        )));
+        let mut conf = IndexWriterConfig::default();
+        conf.index_deletion_policy = Arc::clone(&policy) as Arc<dyn IndexDeletionPolicy>;
+        let writer = IndexWriter::new(Arc::clone(&dir), Arc::new(conf)).unwrap();
+        assert!(policy.snapshot().is_err());
+
+        writer.add_document(vec![keyword("id", "0")]).unwrap();
+        writer.commit().unwrap();
+        let snapshot = policy.snapshot().unwrap();
+        assert_eq!(policy.snapshot_count(), 1);
+
+        writer.add_document(vec![keyword("id", "1")]).unwrap();
+        writer.commit().unwrap();
+        let files = dir.list_all().unwrap();
+        for file in snapshot.file_names().unwrap() {
+            assert!(files.contains(file), "{} was deleted", file);
+        }
+        let reader = TestReader::open_commit(Arc::clone(&dir), snapshot.as_ref()).unwrap();
+        assert_eq!(reader.num_docs(), 1);
+        drop(reader);
+
+        policy.release(snapshot.as_ref()).unwrap();
+        assert!(policy.release(snapshot.as_ref()).is_err());
+        assert_eq!(policy.snapshot_count(), 0);
+        writer.add_document(vec![keyword("id", "2")]).unwrap();
+        writer.commit().unwrap();
+        let files = dir.list_all().unwrap();
+        assert!(!files.contains(&snapshot.segments_file_name().to_string()));
+        writer.close().unwrap();
+
+        let reader = TestReader::open(dir).unwrap();
+        assert_eq!(reader.num_docs(), 3);
+    }
+
+    #[test]
+    fn test_nrt_reader_shares_unchanged_segments() {
+        let dir = Arc::new(RAMDirectory::new());
+        let writer =
+            IndexWriter::new(Arc::clone(&dir), Arc::new(IndexWriterConfig::default())).unwrap();
+        writer.add_document(vec![keyword("id", "0")]).unwrap();
+        writer.add_document(vec![keyword("id", "1")]).unwrap();
+        let reader = Arc::new(TestReader::open_with_writer(&writer, true).unwrap());
+        assert_eq!(count(&reader, "id", "1"), 1);
+        assert!(reader.open_if_changed(None).unwrap().is_none());
+
+        // an added document is searchable right after the reopen
+        writer.add_document(vec![keyword("id", "2")]).unwrap();
+        let reopened = Arc::new(reader.open_if_changed(None).unwrap().unwrap());
+        assert_eq!(reopened.num_docs(), 3);
+        assert_eq!(count(&reopened, "id", "2"), 1);
+        assert_eq!(reopened.segment_readers().len(), 2);
+        assert!(Arc::ptr_eq(
+            &reader.segment_readers()[0],
+            &reopened.segment_readers()[0]
+        ));
+        // nothing was committed
+        assert!(TestReader::open(Arc::clone(&dir)).is_err());
+
+        // a deleted segment gets a new reader, the other one is shared
+        writer.delete_documents_by_term("id", b"0").unwrap();
+        let deleted = Arc::new(reopened.open_if_changed(None).unwrap().unwrap());
+        assert_eq!(deleted.num_docs(), 2);
+        assert_eq!(count(&deleted, "id", "0"), 0);
+        assert!(!Arc::ptr_eq(
+            &reopened.segment_readers()[0],
+            &deleted.segment_readers()[0]
+        ));
+        assert!(Arc::ptr_eq(
+            &reopened.segment_readers()[1],
+            &deleted.segment_readers()[1]
+        ));
+        writer.close().unwrap();
+    }
+
+    fn value_doc(i: i64) -> Vec<Field> {
+        vec![
+            keyword("id", &i.to_string()),
+            Field::new(
+                "value".to_string(),
+                NUMERIC_DOC_VALUES_FIELD_TYPE,
+                Some(VariantValue::Long(i)),
+                None,
+            ),
+        ]
+    }
+
+    fn id_term(i: i64) -> Term {
+        Term::new("id".to_string(), i.to_string().into_bytes())
+    }
+
+    // the values of the "value" field of all the documents, in doc id order
+    fn doc_values(reader: &TestReader) -> Vec<i64> {
+        let mut values = vec![];
+        for leaf in reader.leaves() {
+            let dv = leaf.reader.get_numeric_doc_values("value").unwrap();
+            for doc in 0..leaf.reader.max_doc() {
+                values.push(dv.get(doc).unwrap());
+            }
+        }
+        values
+    }
+
+    #[test]
+    fn test_update_numeric_doc_values() {
+        let dir = Arc::new(RAMDirectory::new());
+        let writer =
+            IndexWriter::new(Arc::clone(&dir), Arc::new(IndexWriterConfig::default())).unwrap();
+        for i in 0..10 {
+            writer.add_document(value_doc(i)).unwrap();
+        }
+        writer.commit().unwrap();
+        let segment = writer.writer.segment_infos.segments[0].info.name.clone();
+        let field_infos_file =
+            |gen: i64| segment_file_name(&segment, &to_base36(gen as u64), "fnm");
+
+        let mut expected: Vec<i64> = (0..10).collect();
+        for gen in 1..4 {
+            // the last update of a document wins
+            writer
+                .update_numeric_doc_value(id_term(3), "value", 100 * gen)
+                .unwrap();
+            writer
+                .update_numeric_doc_value(id_term(5), "value", -gen)
+                .unwrap();
+            writer
+                .update_numeric_doc_value(id_term(3), "value", 100 * gen + 1)
+                .unwrap();
+            writer.commit().unwrap();
+            expected[3] = 100 * gen + 1;
+            expected[5] = -gen;
+            assert_eq!(
+                doc_values(&TestReader::open(Arc::clone(&dir)).unwrap()),
+                expected
+            );
+            assert_eq!(
+                writer.writer.segment_infos.segments[0].field_infos_gen(),
+                gen
+            );
+
+            // the previous generation is deleted with the previous commit
+            let files = dir.list_all().unwrap();
+            assert!(files.contains(&field_infos_file(gen)));
+            if gen > 1 {
+                let prev = to_base36(gen as u64 - 1);
+                assert!(!files.contains(&field_infos_file(gen - 1)));
+                assert!(!files
+                    .iter()
+                    .any(|f| f.starts_with(&format!("{}_{}_", segment, prev))));
+            }
+        }
+
+        // a NRT reader sees the updates without a commit
+        let reader = TestReader::open_with_writer(&writer, true).unwrap();
+        writer
+            .update_numeric_doc_value(id_term(0), "value", 1000)
+            .unwrap();
+        let reopened = reader.open_if_changed(None).unwrap().unwrap();
+        expected[0] = 1000;
+        assert_eq!(doc_values(&reopened), expected);
+        writer.commit().unwrap();
+        writer.close().unwrap();
+
+        // the generations are read back by a new writer
+        let writer =
+            IndexWriter::new(Arc::clone(&dir), Arc::new(IndexWriterConfig::default())).unwrap();
+        writer
+            .update_numeric_doc_value(id_term(7), "value", 70)
+            .unwrap();
+        writer.commit().unwrap();
+        expected[7] = 70;
+        assert_eq!(
+            doc_values(&TestReader::open(Arc::clone(&dir)).unwrap()),
+            expected
+        );
+
+        // only existing numeric doc values fields can be updated
+        assert!(writer
+            .update_numeric_doc_value(id_term(1), "id", 1)
+            .is_err());
+        assert!(writer
+            .update_numeric_doc_value(id_term(1), "missing", 1)
+            .is_err());
+        writer.close().unwrap();
+    }
+
+    #[test]
+    fn test_buffered_numeric_doc_values_updates() {
+        let dir = Arc::new(RAMDirectory::new());
+        let mut conf = IndexWriterConfig::default();
+        conf.reader_pooling = false;
+        let writer = IndexWriter::new(Arc::clone(&dir), Arc::new(conf)).unwrap();
+        for i in 0..5 {
+            writer.add_document(value_doc(i)).unwrap();
+        }
+        writer.commit().unwrap();
+
+        // the updates are buffered along with the documents, they apply to the
+        // committed segment and to the buffered documents
+        for i in 5..10 {
+            writer.add_document(value_doc(i)).unwrap();
+            writer
+                .update_numeric_doc_value(id_term(i - 5), "value", 10 * i)
+                .unwrap();
+            writer
+                .update_numeric_doc_value(id_term(i), "value", -i)
+                .unwrap();
+        }
+        // an update doesn't apply to the documents added after it
+        writer
+            .update_numeric_doc_value(id_term(10), "value", 1000)
+            .unwrap();
+        writer.add_document(value_doc(10)).unwrap();
+        assert_eq!(writer.writer.segment_infos.segments.len(), 1);
+
+        writer.commit().unwrap();
+        assert_eq!(writer.writer.segment_infos.segments.len(), 2);
+        assert!(!writer.writer.pool_readers());
+        assert_eq!(
+            doc_values(&TestReader::open(Arc::clone(&dir)).unwrap()),
+            vec![50, 60, 70, 80, 90, -5, -6, -7, -8, -9, 10]
+        );
+        writer.close().unwrap();
+    }
+
+    #[test]
+    fn test_merge_doc_values_updates() {
+        let dir = Arc::new(RAMDirectory::new());
+        let writer =
+            IndexWriter::new(Arc::clone(&dir), Arc::new(IndexWriterConfig::default())).unwrap();
+        for i in 0..10 {
+            writer.add_document(value_doc(i)).unwrap();
+            if i == 4 {
+                writer.commit().unwrap();
+            }
+        }
+        writer.commit().unwrap();
+        writer
+            .update_numeric_doc_value(id_term(2), "value", 20)
+            .unwrap();
+        writer
+            .update_numeric_doc_value(id_term(8), "value", 80)
+            .unwrap();
+        writer.commit().unwrap();
+        assert_eq!(writer.writer.segment_infos.segments.len(), 2);
+        assert!(writer.writer.segment_infos.segments[0].has_field_updates());
+
+        // the merge collapses the generations
+        writer.force_merge(1, true).unwrap();
+        writer.commit().unwrap();
+        let mut expected: Vec<i64> = (0..10).collect();
+        expected[2] = 20;
+        expected[8] = 80;
+        assert_eq!(writer.writer.segment_infos.segments.len(), 1);
+        assert!(!writer.writer.segment_infos.segments[0].has_field_updates());
+        assert_eq!(
+            doc_values(&TestReader::open(Arc::clone(&dir)).unwrap()),
+            expected
+        );
+
+        // the updates not written yet are carried over onto the merged segment
+        for i in 10..15 {
+            writer.add_document(value_doc(i)).unwrap();
+        }
+        writer.commit().unwrap();
+        writer
+            .update_numeric_doc_value(id_term(3), "value", 30)
+            .unwrap();
+        writer
+            .update_numeric_doc_value(id_term(12), "value", 120)
+            .unwrap();
+        writer.force_merge(1, true).unwrap();
+        writer.commit().unwrap();
+        expected.extend(10..15);
+        expected[3] = 30;
+        expected[12] = 120;
+        assert_eq!(writer.writer.segment_infos.segments.len(), 1);
+        assert_eq!(
+            doc_values(&TestReader::open(Arc::clone(&dir)).unwrap()),
+            expected
+        );
+        writer.close().unwrap();
+    }
+}
@@ -11,13 +11,17 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use core::analysis::{Analyzer, WhitespaceAnalyzer};
 use core::codec::{Codec, CodecEnum, Lucene62Codec};
-use core::index::delete_policy::KeepOnlyLastCommitDeletionPolicy;
+use core::index::delete_policy::{IndexDeletionPolicy, KeepOnlyLastCommitDeletionPolicy};
 use core::index::merge_policy::{MergePolicy, TieredMergePolicy};
 use core::index::merge_scheduler::MergeScheduler;
 use core::index::merge_scheduler::SerialMergeScheduler;
+use core::search::bm25_similarity::BM25_NORM_ENCODING;
+use core::search::norm_encoding::NormEncoding;
 use core::search::sort::Sort;
 
+use std::collections::HashMap;
 use std::sync::Arc;
 
 /// Holds all the configuration that is used to create an {@link IndexWriter}.
@@ -48,6 +52,29 @@ pub struct IndexWriterConfig<C: Codec, MS: MergeScheduler, MP: MergePolicy> {
     pub per_thread_hard_limit_mb: u32,
     pub codec: Arc<C>,
     pub commit_on_close: bool,
+    /// Fields whose values are hashed to detect duplicate documents in
+    /// `IndexWriter::add_document_dedup`.
+    pub dedup_fields: Vec<String>,
+    /// How the norms of the indexed fields are encoded, this must match the
+    /// similarity searching them. Recorded per field so the searcher can check it.
+    pub norm_encoding: NormEncoding,
+    /// Overrides `norm_encoding` for the fields searched by other similarities,
+    /// see `PerFieldSimilarityWrapper`.
+    pub field_norm_encodings: HashMap<String, NormEncoding>,
+    /// Analyzes the values of the tokenized fields without a preset token stream.
+    pub analyzer: Arc<dyn Analyzer>,
+    /// If true, `IndexWriter::commit` waits for the pending and running merges,
+    /// so the commit includes the merged segments. Otherwise commits never wait
+    /// for merges.
+    pub merge_on_commit: bool,
+    /// The numeric doc values field marking the soft deleted documents, see
+    /// `IndexWriter::soft_update_document`. Soft deleted documents are kept in
+    /// the index but count as deleted for the merge policy.
+    pub soft_deletes_field: Option<String>,
+    /// Decides which commits are deleted when the writer commits, the default
+    /// keeps only the last commit. Wrap it in a `SnapshotDeletionPolicy` to
+    /// hold onto commits while they are backed up.
+    pub index_deletion_policy: Arc<dyn IndexDeletionPolicy>,
     // pub similarity: Box<Similarity>,
 }
 
@@ -55,7 +82,7 @@ impl Default for IndexWriterConfig<CodecEnum, SerialMergeScheduler, TieredMergeP
     fn default() -> Self {
         Self::new(
             Arc::new(CodecEnum::Lucene62(Lucene62Codec::default())),
-            SerialMergeScheduler {},
+            SerialMergeScheduler::default(),
             TieredMergePolicy::default(),
         )
     }
@@ -77,6 +104,13 @@ impl<C: Codec, MS: MergeScheduler, MP: MergePolicy> IndexWriterConfig<C, MS, MP>
             per_thread_hard_limit_mb: DEFAULT_RAM_PER_THREAD_HARD_LIMIT_MB,
             codec,
             commit_on_close: true,
+            dedup_fields: vec![],
+            norm_encoding: BM25_NORM_ENCODING,
+            field_norm_encodings: HashMap::new(),
+            analyzer: Arc::new(WhitespaceAnalyzer),
+            merge_on_commit: false,
+            soft_deletes_field: None,
+            index_deletion_policy: Arc::new(KeepOnlyLastCommitDeletionPolicy),
             // similarity: Box::new(BM25Similarity::default()),
         }
     }
@@ -100,6 +134,14 @@ impl<C: Codec, MS: MergeScheduler, MP: MergePolicy> IndexWriterConfig<C, MS, MP>
         }
     }
 
+    /// Returns how the norms of `field` are encoded.
+    pub fn field_norm_encoding(&self, field: &str) -> NormEncoding {
+        self.field_norm_encodings
+            .get(field)
+            .cloned()
+            .unwrap_or(self.norm_encoding)
+    }
+
     pub fn max_buffered_delete_terms(&self) -> u32 {
         self.max_buffered_delete_terms.unwrap_or(0)
     }
@@ -124,6 +166,36 @@ impl<C: Codec, MS: MergeScheduler, MP: MergePolicy> IndexWriterConfig<C, MS, MP>
         &self.merge_policy
     }
 
+    /// Returns this config with the merge policy replaced by `f` of the
+    /// current one, which may be of another type, e.g. to wrap it.
+    pub fn map_merge_policy<P, F>(self, f: F) -> IndexWriterConfig<C, MS, P>
+    where
+        P: MergePolicy,
+        F: FnOnce(MP) -> P,
+    {
+        IndexWriterConfig {
+            ram_buffer_size_mb: self.ram_buffer_size_mb,
+            use_compound_file: self.use_compound_file,
+            max_buffered_delete_terms: self.max_buffered_delete_terms,
+            max_buffered_docs: self.max_buffered_docs,
+            merge_policy: f(self.merge_policy),
+            merge_scheduler: self.merge_scheduler,
+            index_sort: self.index_sort,
+            reader_pooling: self.reader_pooling,
+            open_mode: self.open_mode,
+            per_thread_hard_limit_mb: self.per_thread_hard_limit_mb,
+            codec: self.codec,
+            commit_on_close: self.commit_on_close,
+            dedup_fields: self.dedup_fields,
+            norm_encoding: self.norm_encoding,
+            field_norm_encodings: self.field_norm_encodings,
+            analyzer: self.analyzer,
+            merge_on_commit: self.merge_on_commit,
+            soft_deletes_field: self.soft_deletes_field,
+            index_deletion_policy: self.index_deletion_policy,
+        }
+    }
+
     pub fn index_sort(&self) -> Option<&Sort> {
         self.index_sort.as_ref()
     }
@@ -132,8 +204,8 @@ impl<C: Codec, MS: MergeScheduler, MP: MergePolicy> IndexWriterConfig<C, MS, MP>
         self.per_thread_hard_limit_mb as u64 * 1024 * 1024
     }
 
-    pub fn index_deletion_policy(&self) -> KeepOnlyLastCommitDeletionPolicy {
-        KeepOnlyLastCommitDeletionPolicy::default()
+    pub fn index_deletion_policy(&self) -> Arc<dyn IndexDeletionPolicy> {
+        Arc::clone(&self.index_deletion_policy)
     }
 
     pub fn merge_scheduler(&self) -> MS {
@@ -144,6 +216,14 @@ impl<C: Codec, MS: MergeScheduler, MP: MergePolicy> IndexWriterConfig<C, MS, MP>
         self.codec.as_ref()
     }
 
+    pub fn soft_deletes_field(&self) -> Option<&str> {
+        self.soft_deletes_field.as_ref().map(String::as_str)
+    }
+
+    pub fn set_soft_deletes_field(&mut self, field: &str) {
+        self.soft_deletes_field = Some(field.to_string());
+    }
+
     // pub fn similarity(&self) -> &Similarity {
     //     self.similarity.as_ref()
     // }
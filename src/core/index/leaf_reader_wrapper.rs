@@ -19,7 +19,7 @@ use core::codec::{
 use core::index::sorter::{PackedLongDocMap, SorterDocMap};
 use core::index::SegmentReader;
 use core::index::StoredFieldVisitor;
-use core::index::{BinaryDocValues, BinaryDocValuesRef};
+use core::index::{BinaryDocValues, BinaryDocValuesContext, BinaryDocValuesRef};
 use core::index::{DocValuesTermIterator, LeafReader};
 use core::index::{FieldInfo, FieldInfos, Fields, IndexOptions};
 use core::index::{IntersectVisitor, PointValues, Relation};
@@ -1061,6 +1061,15 @@ impl BinaryDocValues for SortingBinaryDocValues {
     fn get(&self, doc_id: i32) -> Result<Vec<u8>> {
         self.doc_values.get(self.doc_map.new_to_old(doc_id))
     }
+
+    fn get_with_ctx<'a>(
+        &self,
+        ctx: &'a mut BinaryDocValuesContext,
+        doc_id: i32,
+    ) -> Result<&'a [u8]> {
+        self.doc_values
+            .get_with_ctx(ctx, self.doc_map.new_to_old(doc_id))
+    }
 }
 
 pub struct SortingNumericDocValues<T: AsRef<NumericDocValues> + Send + Sync> {
@@ -1172,6 +1181,15 @@ impl<P: PointValues + 'static> PointValues for SortingPointValues<P> {
         self.point_values.intersect(field_name, &mut sort_visitor)
     }
 
+    fn estimate_point_count(
+        &self,
+        field_name: &str,
+        visitor: &mut impl IntersectVisitor,
+    ) -> Result<i64> {
+        // no doc is visited, the doc map does not matter
+        self.point_values.estimate_point_count(field_name, visitor)
+    }
+
     fn min_packed_value(&self, field_name: &str) -> Result<Vec<u8>> {
         self.point_values.min_packed_value(field_name)
     }
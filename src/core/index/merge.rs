@@ -0,0 +1,916 @@
+// Copyright 2019 Zhizhesihai (Beijing) Technology Limited.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! N-way merge of the terms and postings of a field of several segments.
+//!
+//! `merge_terms` pushes the merged terms to a `MergedTermsConsumer`, while
+//! `MergedFields` exposes them as the `Fields` the postings formats write,
+//! which is how the terms of the segments are merged by `SegmentMerger`.
+
+use core::index::{DocMap, Fields, SeekStatus, TermIterator, Terms};
+use core::search::posting_iterator::{PostingIterator, PostingIteratorFlags};
+use core::search::{DocIterator, Payload, NO_MORE_DOCS};
+use core::util::bit_set::{BitSet, FixedBitSet, ImmutableBitSet};
+use core::util::DocId;
+
+use error::ErrorKind::{IllegalArgument, UnsupportedOperation};
+use error::Result;
+
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::collections::{BTreeSet, BinaryHeap};
+use std::sync::Arc;
+
+/// A doc of the merged postings of a term.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MergedDoc {
+    /// The doc id in the merged segment.
+    pub doc: DocId,
+    /// The term frequency, 1 if the field doesn't index frequencies.
+    pub freq: i32,
+    /// The positions, empty if the field doesn't index positions.
+    pub positions: Vec<i32>,
+    /// The start and end offsets of the positions, empty if the field doesn't
+    /// index offsets.
+    pub offsets: Vec<(i32, i32)>,
+    /// The payloads of the positions, empty if no segment indexes payloads.
+    pub payloads: Vec<Payload>,
+}
+
+/// The postings of a term in all the merged segments, ordered by merged doc id.
+#[derive(Debug, Default)]
+pub struct MergedPostings {
+    pub docs: Vec<MergedDoc>,
+    has_freqs: bool,
+}
+
+impl MergedPostings {
+    pub fn doc_freq(&self) -> i32 {
+        self.docs.len() as i32
+    }
+
+    /// The sum of the frequencies, -1 if the field doesn't index frequencies.
+    pub fn total_term_freq(&self) -> i64 {
+        if self.has_freqs {
+            self.docs.iter().map(|d| i64::from(d.freq)).sum()
+        } else {
+            -1
+        }
+    }
+}
+
+/// The statistics of the merged field, as they come out of the `Terms` of the
+/// merged segment.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MergedFieldStats {
+    /// Number of terms with at least one live doc.
+    pub size: i64,
+    pub sum_doc_freq: i64,
+    /// -1 if the field doesn't index frequencies.
+    pub sum_total_term_freq: i64,
+    pub doc_count: i32,
+    pub min: Option<Vec<u8>>,
+    pub max: Option<Vec<u8>>,
+}
+
+/// Receives the merged terms of a field, in term order.
+pub trait MergedTermsConsumer {
+    /// Called for every term having at least one live doc in a segment.
+    fn write_term(&mut self, term: &[u8], postings: &MergedPostings) -> Result<()>;
+
+    /// Called once all the terms were written.
+    fn finish(&mut self, stats: &MergedFieldStats) -> Result<()>;
+}
+
+// the current term of the terms of a segment, the queue pops the smallest
+// term first, of the first segment on ties
+struct SegmentTerms<T: TermIterator> {
+    term: Vec<u8>,
+    index: usize,
+    iterator: T,
+}
+
+impl<T: TermIterator> Ord for SegmentTerms<T> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .term
+            .cmp(&self.term)
+            .then_with(|| other.index.cmp(&self.index))
+    }
+}
+
+impl<T: TermIterator> PartialOrd for SegmentTerms<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T: TermIterator> PartialEq for SegmentTerms<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<T: TermIterator> Eq for SegmentTerms<T> {}
+
+// What the merged postings hold: frequencies, positions and offsets if all
+// the segments having the field index them, payloads if any of them does.
+#[derive(Clone, Copy, Debug)]
+struct MergedFeatures {
+    freqs: bool,
+    positions: bool,
+    offsets: bool,
+    payloads: bool,
+}
+
+impl MergedFeatures {
+    fn new<T: Terms>(terms: &[Option<T>]) -> Result<MergedFeatures> {
+        let mut features = MergedFeatures {
+            freqs: true,
+            positions: true,
+            offsets: true,
+            payloads: false,
+        };
+        for sub in terms.iter().filter_map(Option::as_ref) {
+            features.freqs &= sub.has_freqs()?;
+            features.positions &= sub.has_positions()?;
+            features.offsets &= sub.has_offsets()?;
+            features.payloads |= sub.has_payloads()?;
+        }
+        features.positions &= features.freqs;
+        features.offsets &= features.positions;
+        features.payloads &= features.positions;
+        Ok(features)
+    }
+
+    fn flags(self) -> u16 {
+        let mut flags = PostingIteratorFlags::NONE;
+        if self.freqs {
+            flags |= PostingIteratorFlags::FREQS;
+        }
+        if self.positions {
+            flags |= PostingIteratorFlags::POSITIONS;
+        }
+        if self.offsets {
+            flags |= PostingIteratorFlags::OFFSETS;
+        }
+        if self.payloads {
+            flags |= PostingIteratorFlags::PAYLOADS;
+        }
+        flags
+    }
+}
+
+// The n-way merge of the terms of a field, one merged term at a time.
+struct TermMerger<T: TermIterator> {
+    queue: BinaryHeap<SegmentTerms<T>>,
+    // the segments positioned on the current term
+    top: Vec<SegmentTerms<T>>,
+    features: MergedFeatures,
+    term: Vec<u8>,
+    postings: Arc<MergedPostings>,
+    stats: MergedFieldStats,
+    docs_seen: FixedBitSet,
+}
+
+impl<T: TermIterator> TermMerger<T> {
+    fn new<S: Terms<Iterator = T>>(terms: &[Option<S>]) -> Result<TermMerger<T>> {
+        let features = MergedFeatures::new(terms)?;
+        let mut queue = BinaryHeap::with_capacity(terms.len());
+        for (index, sub) in terms.iter().enumerate() {
+            if let Some(sub) = sub {
+                let mut iterator = sub.iterator()?;
+                if let Some(term) = iterator.next()? {
+                    queue.push(SegmentTerms {
+                        term,
+                        index,
+                        iterator,
+                    });
+                }
+            }
+        }
+        let mut stats = MergedFieldStats::default();
+        if !features.freqs {
+            stats.sum_total_term_freq = -1;
+        }
+        Ok(TermMerger {
+            queue,
+            top: Vec::with_capacity(terms.len()),
+            features,
+            term: vec![],
+            postings: Arc::new(MergedPostings {
+                docs: vec![],
+                has_freqs: features.freqs,
+            }),
+            stats,
+            docs_seen: FixedBitSet::new(64),
+        })
+    }
+
+    // moves to the next term having a doc left, `doc_maps[i]` mapping the
+    // docs of the i-th segment, returns false once all the terms were merged
+    fn next_term<M: DocMap>(&mut self, doc_maps: &[M]) -> Result<bool> {
+        loop {
+            for mut sub in self.top.drain(..) {
+                if let Some(term) = sub.iterator.next()? {
+                    sub.term = term;
+                    self.queue.push(sub);
+                }
+            }
+            match self.queue.pop() {
+                Some(first) => self.top.push(first),
+                None => return Ok(false),
+            }
+            while self
+                .queue
+                .peek()
+                .map_or(false, |s| s.term == self.top[0].term)
+            {
+                self.top.push(self.queue.pop().unwrap());
+            }
+
+            // the postings of the previous term may still be read
+            if Arc::get_mut(&mut self.postings).is_none() {
+                self.postings = Arc::new(MergedPostings {
+                    docs: vec![],
+                    has_freqs: self.features.freqs,
+                });
+            }
+            let postings = Arc::get_mut(&mut self.postings).unwrap();
+            postings.docs.clear();
+            let features = self.features;
+            for sub in &mut self.top {
+                let doc_map = &doc_maps[sub.index];
+                let mut iter = sub.iterator.postings_with_flags(features.flags())?;
+                loop {
+                    let doc = iter.next()?;
+                    if doc == NO_MORE_DOCS {
+                        break;
+                    }
+                    let merged_doc = doc_map.get(doc)?;
+                    if merged_doc < 0 {
+                        continue;
+                    }
+                    let freq = if features.freqs { iter.freq()? } else { 1 };
+                    let mut merged = MergedDoc {
+                        doc: merged_doc,
+                        freq,
+                        positions: vec![],
+                        offsets: vec![],
+                        payloads: vec![],
+                    };
+                    if features.positions {
+                        for _ in 0..freq {
+                            merged.positions.push(iter.next_position()?);
+                            if features.offsets {
+                                merged
+                                    .offsets
+                                    .push((iter.start_offset()?, iter.end_offset()?));
+                            }
+                            if features.payloads {
+                                merged.payloads.push(iter.payload()?);
+                            }
+                        }
+                    }
+                    postings.docs.push(merged);
+                }
+            }
+            if postings.docs.is_empty() {
+                continue;
+            }
+
+            // the docs of a segment may interleave with the ones of the other
+            // segments if the merged segment is sorted
+            postings.docs.sort_by_key(|d| d.doc);
+            for d in &postings.docs {
+                self.docs_seen.ensure_capacity(d.doc as usize + 1);
+                self.docs_seen.set(d.doc as usize);
+            }
+            self.term.clear();
+            self.term.extend_from_slice(&self.top[0].term);
+
+            self.stats.size += 1;
+            self.stats.sum_doc_freq += i64::from(postings.doc_freq());
+            if features.freqs {
+                self.stats.sum_total_term_freq += postings.total_term_freq();
+            }
+            if self.stats.min.is_none() {
+                self.stats.min = Some(self.term.clone());
+            }
+            return Ok(true);
+        }
+    }
+
+    // the statistics of the merged terms, once all of them were merged
+    fn stats(&self) -> MergedFieldStats {
+        let mut stats = self.stats.clone();
+        if stats.size > 0 {
+            stats.max = Some(self.term.clone());
+        }
+        stats.doc_count = self.docs_seen.cardinality() as i32;
+        stats
+    }
+}
+
+/// Merges the terms of `field` of several segments, `terms[i]` being the
+/// terms of the i-th segment if it has the field, and feeds them term by term
+/// to `consumer`.
+///
+/// The doc ids of the i-th segment are mapped to the merged segment with
+/// `doc_maps[i]`, the docs it maps to -1 (the deleted docs) are dropped, so
+/// are the terms without any doc left. Frequencies, positions and offsets are
+/// only merged if all the segments having the field index them, payloads if
+/// any of them does.
+pub fn merge_terms<T, M, W>(
+    field: &str,
+    terms: &[Option<T>],
+    doc_maps: &[M],
+    consumer: &mut W,
+) -> Result<()>
+where
+    T: Terms,
+    M: DocMap,
+    W: MergedTermsConsumer,
+{
+    if terms.len() != doc_maps.len() {
+        bail!(IllegalArgument(format!(
+            "field '{}' has terms for {} segments but {} doc maps",
+            field,
+            terms.len(),
+            doc_maps.len()
+        )));
+    }
+
+    let mut merger = TermMerger::new(terms)?;
+    while merger.next_term(doc_maps)? {
+        consumer.write_term(&merger.term, &merger.postings)?;
+    }
+    consumer.finish(&merger.stats())
+}
+
+/// The fields of the segments of a merge, the terms of every field being
+/// merged as by `merge_terms`. The postings formats write the merged segment
+/// from it.
+///
+/// Only the iteration over the terms and their postings is supported, the
+/// statistics are computed by the writer.
+pub struct MergedFields<F: Fields, M: DocMap> {
+    fields: Vec<F>,
+    doc_maps: Arc<Vec<M>>,
+}
+
+impl<F: Fields, M: DocMap> MergedFields<F, M> {
+    /// `doc_maps[i]` maps the docs of `fields[i]` to the merged segment.
+    pub fn new(fields: Vec<F>, doc_maps: Vec<M>) -> Result<MergedFields<F, M>> {
+        if fields.len() != doc_maps.len() {
+            bail!(IllegalArgument(format!(
+                "{} segments to merge but {} doc maps",
+                fields.len(),
+                doc_maps.len()
+            )));
+        }
+        Ok(MergedFields {
+            fields,
+            doc_maps: Arc::new(doc_maps),
+        })
+    }
+}
+
+impl<F: Fields, M: DocMap + 'static> Fields for MergedFields<F, M> {
+    type Terms = MergedTerms<F::Terms, M>;
+
+    fn fields(&self) -> Vec<String> {
+        let fields: BTreeSet<String> = self.fields.iter().flat_map(|f| f.fields()).collect();
+        fields.into_iter().collect()
+    }
+
+    fn terms(&self, field: &str) -> Result<Option<Self::Terms>> {
+        let mut terms = Vec::with_capacity(self.fields.len());
+        for f in &self.fields {
+            terms.push(f.terms(field)?);
+        }
+        if terms.iter().all(Option::is_none) {
+            return Ok(None);
+        }
+        Ok(Some(MergedTerms {
+            terms,
+            doc_maps: Arc::clone(&self.doc_maps),
+        }))
+    }
+
+    fn size(&self) -> usize {
+        self.fields().len()
+    }
+}
+
+/// The merged terms of a field, see `MergedFields`.
+pub struct MergedTerms<T: Terms, M: DocMap> {
+    terms: Vec<Option<T>>,
+    doc_maps: Arc<Vec<M>>,
+}
+
+impl<T: Terms, M: DocMap + 'static> Terms for MergedTerms<T, M> {
+    type Iterator = MergedTermIterator<T::Iterator, M>;
+
+    fn iterator(&self) -> Result<Self::Iterator> {
+        Ok(MergedTermIterator {
+            merger: TermMerger::new(&self.terms)?,
+            doc_maps: Arc::clone(&self.doc_maps),
+        })
+    }
+
+    fn size(&self) -> Result<i64> {
+        bail!(UnsupportedOperation(Cow::Borrowed("")))
+    }
+
+    fn sum_total_term_freq(&self) -> Result<i64> {
+        bail!(UnsupportedOperation(Cow::Borrowed("")))
+    }
+
+    fn sum_doc_freq(&self) -> Result<i64> {
+        bail!(UnsupportedOperation(Cow::Borrowed("")))
+    }
+
+    fn doc_count(&self) -> Result<i32> {
+        bail!(UnsupportedOperation(Cow::Borrowed("")))
+    }
+
+    fn has_freqs(&self) -> Result<bool> {
+        Ok(MergedFeatures::new(&self.terms)?.freqs)
+    }
+
+    fn has_offsets(&self) -> Result<bool> {
+        Ok(MergedFeatures::new(&self.terms)?.offsets)
+    }
+
+    fn has_positions(&self) -> Result<bool> {
+        Ok(MergedFeatures::new(&self.terms)?.positions)
+    }
+
+    fn has_payloads(&self) -> Result<bool> {
+        Ok(MergedFeatures::new(&self.terms)?.payloads)
+    }
+}
+
+/// Iterates over the merged terms of a field, see `MergedFields`. It can't
+/// seek.
+pub struct MergedTermIterator<T: TermIterator, M: DocMap> {
+    merger: TermMerger<T>,
+    doc_maps: Arc<Vec<M>>,
+}
+
+impl<T: TermIterator, M: DocMap + 'static> TermIterator for MergedTermIterator<T, M> {
+    type Postings = MergedPostingIterator;
+    type TermState = T::TermState;
+
+    fn next(&mut self) -> Result<Option<Vec<u8>>> {
+        if self.merger.next_term(&self.doc_maps)? {
+            Ok(Some(self.merger.term.clone()))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn seek_ceil(&mut self, _text: &[u8]) -> Result<SeekStatus> {
+        bail!(UnsupportedOperation(Cow::Borrowed("")))
+    }
+
+    fn seek_exact_ord(&mut self, _ord: i64) -> Result<()> {
+        bail!(UnsupportedOperation(Cow::Borrowed("")))
+    }
+
+    fn term(&self) -> Result<&[u8]> {
+        Ok(&self.merger.term)
+    }
+
+    fn ord(&self) -> Result<i64> {
+        bail!(UnsupportedOperation(Cow::Borrowed("")))
+    }
+
+    fn doc_freq(&mut self) -> Result<i32> {
+        Ok(self.merger.postings.doc_freq())
+    }
+
+    fn total_term_freq(&mut self) -> Result<i64> {
+        Ok(self.merger.postings.total_term_freq())
+    }
+
+    fn postings_with_flags(&mut self, _flags: u16) -> Result<Self::Postings> {
+        Ok(MergedPostingIterator {
+            postings: Arc::clone(&self.merger.postings),
+            upto: 0,
+            doc: -1,
+            position: 0,
+        })
+    }
+}
+
+/// Iterates over the merged postings of a term.
+pub struct MergedPostingIterator {
+    postings: Arc<MergedPostings>,
+    // the index of the next doc
+    upto: usize,
+    doc: DocId,
+    // the index of the next position of the current doc
+    position: usize,
+}
+
+impl MergedPostingIterator {
+    fn current(&self) -> &MergedDoc {
+        &self.postings.docs[self.upto - 1]
+    }
+}
+
+impl DocIterator for MergedPostingIterator {
+    fn doc_id(&self) -> DocId {
+        self.doc
+    }
+
+    fn next(&mut self) -> Result<DocId> {
+        self.doc = match self.postings.docs.get(self.upto) {
+            Some(d) => {
+                self.upto += 1;
+                self.position = 0;
+                d.doc
+            }
+            None => NO_MORE_DOCS,
+        };
+        Ok(self.doc)
+    }
+
+    fn advance(&mut self, target: DocId) -> Result<DocId> {
+        self.slow_advance(target)
+    }
+
+    fn cost(&self) -> usize {
+        self.postings.docs.len()
+    }
+}
+
+impl PostingIterator for MergedPostingIterator {
+    fn freq(&self) -> Result<i32> {
+        Ok(self.current().freq)
+    }
+
+    fn next_position(&mut self) -> Result<i32> {
+        let position = self
+            .current()
+            .positions
+            .get(self.position)
+            .cloned()
+            .unwrap_or(-1);
+        self.position += 1;
+        Ok(position)
+    }
+
+    fn start_offset(&self) -> Result<i32> {
+        let offsets = &self.current().offsets;
+        Ok(self
+            .position
+            .checked_sub(1)
+            .and_then(|i| offsets.get(i))
+            .map_or(-1, |o| o.0))
+    }
+
+    fn end_offset(&self) -> Result<i32> {
+        let offsets = &self.current().offsets;
+        Ok(self
+            .position
+            .checked_sub(1)
+            .and_then(|i| offsets.get(i))
+            .map_or(-1, |o| o.1))
+    }
+
+    fn payload(&self) -> Result<Payload> {
+        let payloads = &self.current().payloads;
+        Ok(self
+            .position
+            .checked_sub(1)
+            .and_then(|i| payloads.get(i))
+            .cloned()
+            .unwrap_or_default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::doc::{Field, FieldType};
+    use core::index::index_writer_config::IndexWriterConfig;
+    use core::index::tests::{open_test_reader, write_test_index, TestReader, TestWriter};
+    use core::index::{Fieldable, IndexOptions, IndexReader, LeafReader};
+    use core::util::{Bits, VariantValue};
+
+    use std::sync::Arc;
+
+    type TermPostings = Vec<(Vec<u8>, Vec<MergedDoc>)>;
+
+    struct VecDocMap(Vec<DocId>);
+
+    impl DocMap for VecDocMap {
+        fn get(&self, doc_id: DocId) -> Result<DocId> {
+            Ok(self.0[doc_id as usize])
+        }
+    }
+
+    #[derive(Default)]
+    struct CollectingConsumer {
+        terms: TermPostings,
+        stats: Option<MergedFieldStats>,
+    }
+
+    impl MergedTermsConsumer for CollectingConsumer {
+        fn write_term(&mut self, term: &[u8], postings: &MergedPostings) -> Result<()> {
+            assert!(self.stats.is_none());
+            self.terms.push((term.to_vec(), postings.docs.clone()));
+            Ok(())
+        }
+
+        fn finish(&mut self, stats: &MergedFieldStats) -> Result<()> {
+            self.stats = Some(stats.clone());
+            Ok(())
+        }
+    }
+
+    fn field(name: &str, value: &str, index_options: IndexOptions) -> Box<dyn Fieldable> {
+        let mut field_type = FieldType::default();
+        field_type.tokenized = index_options != IndexOptions::Docs;
+        field_type.index_options = index_options;
+        Box::new(Field::new(
+            name.to_string(),
+            field_type,
+            Some(VariantValue::VString(value.to_string())),
+            None,
+        ))
+    }
+
+    fn doc(i: i32) -> Vec<Box<dyn Fieldable>> {
+        let mut body = format!("w{} v{} common", i % 3, i % 5);
+        if i % 4 == 0 {
+            body.push_str(" common");
+        }
+        if i >= 20 {
+            body.push_str(" tail");
+        }
+        vec![
+            field("id", &format!("{:02}", i), IndexOptions::Docs),
+            field(
+                "body",
+                &body,
+                IndexOptions::DocsAndFreqsAndPositionsAndOffsets,
+            ),
+        ]
+    }
+
+    fn is_deleted(i: i32) -> bool {
+        i % 7 == 3
+    }
+
+    // three segments of ten docs, some of them deleted
+    fn build_segments(writer: &TestWriter) {
+        for i in 0..30 {
+            writer.add_document(doc(i)).unwrap();
+            if i % 10 == 9 {
+                writer.commit().unwrap();
+            }
+        }
+        for i in (0..30).filter(|i| is_deleted(*i)) {
+            let id = format!("{:02}", i);
+            writer
+                .delete_documents_by_term("id", id.as_bytes())
+                .unwrap();
+        }
+        writer.commit().unwrap();
+    }
+
+    // the live docs of the segments, in a single segment
+    fn build_expected() -> Arc<TestReader> {
+        open_test_reader(vec![(0..30).filter(|i| !is_deleted(*i)).map(doc).collect()])
+    }
+
+    fn collect_postings<T: Terms>(terms: &T) -> TermPostings {
+        let has_freqs = terms.has_freqs().unwrap();
+        let has_positions = terms.has_positions().unwrap();
+        let has_offsets = terms.has_offsets().unwrap();
+        let has_payloads = terms.has_payloads().unwrap();
+        let mut collected = vec![];
+        let mut iter = terms.iterator().unwrap();
+        while let Some(term) = iter.next().unwrap() {
+            let mut postings = iter.postings_with_flags(PostingIteratorFlags::ALL).unwrap();
+            let mut docs = vec![];
+            loop {
+                let doc = postings.next().unwrap();
+                if doc == NO_MORE_DOCS {
+                    break;
+                }
+                let freq = if has_freqs {
+                    postings.freq().unwrap()
+                } else {
+                    1
+                };
+                let mut merged = MergedDoc {
+                    doc,
+                    freq,
+                    positions: vec![],
+                    offsets: vec![],
+                    payloads: vec![],
+                };
+                if has_positions {
+                    for _ in 0..freq {
+                        merged.positions.push(postings.next_position().unwrap());
+                        if has_offsets {
+                            merged.offsets.push((
+                                postings.start_offset().unwrap(),
+                                postings.end_offset().unwrap(),
+                            ));
+                        }
+                        if has_payloads {
+                            merged.payloads.push(postings.payload().unwrap());
+                        }
+                    }
+                }
+                docs.push(merged);
+            }
+            collected.push((term, docs));
+        }
+        collected
+    }
+
+    fn collect<T: Terms>(terms: &T) -> (TermPostings, MergedFieldStats) {
+        let stats = MergedFieldStats {
+            size: terms.size().unwrap(),
+            sum_doc_freq: terms.sum_doc_freq().unwrap(),
+            sum_total_term_freq: terms.sum_total_term_freq().unwrap(),
+            doc_count: terms.doc_count().unwrap(),
+            min: terms.min().unwrap(),
+            max: terms.max().unwrap(),
+        };
+        (collect_postings(terms), stats)
+    }
+
+    #[test]
+    fn test_merge_terms_matches_single_segment() {
+        let reader = write_test_index(IndexWriterConfig::default(), build_segments);
+        let leaves = reader.leaves();
+        assert_eq!(leaves.len(), 3);
+
+        let mut doc_maps = vec![];
+        let mut next_doc = 0;
+        for leaf in &leaves {
+            let live_docs = leaf.reader.live_docs();
+            let mut doc_map = vec![];
+            for doc in 0..leaf.reader.max_doc() {
+                if live_docs.get(doc as usize).unwrap() {
+                    doc_map.push(next_doc);
+                    next_doc += 1;
+                } else {
+                    doc_map.push(-1);
+                }
+            }
+            doc_maps.push(VecDocMap(doc_map));
+        }
+
+        let expected = build_expected();
+        let expected_leaves = expected.leaves();
+        assert_eq!(expected_leaves.len(), 1);
+        for name in &["id", "body"] {
+            let terms: Vec<_> = leaves
+                .iter()
+                .map(|leaf| leaf.reader.terms(name).unwrap())
+                .collect();
+            let mut consumer = CollectingConsumer::default();
+            merge_terms(name, &terms, &doc_maps, &mut consumer).unwrap();
+
+            let expected_terms = expected_leaves[0].reader.terms(name).unwrap().unwrap();
+            let (expected_postings, expected_stats) = collect(&expected_terms);
+            assert_eq!(consumer.terms, expected_postings);
+            assert_eq!(consumer.stats.unwrap(), expected_stats);
+        }
+
+        // the ids of the deleted docs are gone, "tail" only lives in the last
+        // segment
+        let mut consumer = CollectingConsumer::default();
+        let terms: Vec<_> = leaves
+            .iter()
+            .map(|leaf| leaf.reader.terms("id").unwrap())
+            .collect();
+        merge_terms("id", &terms, &doc_maps, &mut consumer).unwrap();
+        assert!(consumer.terms.iter().all(|(t, _)| t != b"03" && t != b"24"));
+        let stats = consumer.stats.unwrap();
+        assert_eq!(stats.size, 26);
+        assert_eq!(stats.sum_total_term_freq, -1);
+        assert_eq!(stats.min, Some(b"00".to_vec()));
+        assert_eq!(stats.max, Some(b"29".to_vec()));
+    }
+
+    #[test]
+    fn test_merged_fields_match_merge_terms() {
+        let reader = write_test_index(IndexWriterConfig::default(), build_segments);
+        let leaves = reader.leaves();
+
+        let mut next_doc = 0;
+        let doc_maps: Vec<_> = leaves
+            .iter()
+            .map(|leaf| {
+                let live_docs = leaf.reader.live_docs();
+                let doc_map = (0..leaf.reader.max_doc())
+                    .map(|doc| {
+                        if live_docs.get(doc as usize).unwrap() {
+                            next_doc += 1;
+                            next_doc - 1
+                        } else {
+                            -1
+                        }
+                    })
+                    .collect();
+                Arc::new(VecDocMap(doc_map))
+            })
+            .collect();
+        let fields: Vec<_> = leaves
+            .iter()
+            .map(|leaf| leaf.reader.postings_reader().unwrap())
+            .collect();
+        let merged = MergedFields::new(fields, doc_maps.clone()).unwrap();
+        assert_eq!(merged.fields(), vec!["body".to_string(), "id".to_string()]);
+        assert!(merged.terms("missing").unwrap().is_none());
+
+        for name in &["id", "body"] {
+            let terms = merged.terms(name).unwrap().unwrap();
+            assert!(terms.has_offsets().unwrap());
+            assert!(!terms.has_payloads().unwrap());
+            assert!(terms.size().is_err());
+
+            let sub_terms: Vec<_> = leaves
+                .iter()
+                .map(|leaf| leaf.reader.terms(name).unwrap())
+                .collect();
+            let mut consumer = CollectingConsumer::default();
+            merge_terms(name, &sub_terms, &doc_maps, &mut consumer).unwrap();
+            assert_eq!(collect_postings(&terms), consumer.terms);
+        }
+
+        let fields: Vec<_> = leaves
+            .iter()
+            .map(|leaf| leaf.reader.postings_reader().unwrap())
+            .collect();
+        assert!(MergedFields::new(fields, doc_maps[1..].to_vec()).is_err());
+    }
+
+    #[test]
+    fn test_merge_terms_missing_field_and_mismatch() {
+        let reader = write_test_index(IndexWriterConfig::default(), build_segments);
+        let leaves = reader.leaves();
+
+        let mut terms: Vec<_> = leaves
+            .iter()
+            .map(|leaf| leaf.reader.terms("body").unwrap())
+            .collect();
+        terms[0] = None;
+        terms[1] = None;
+        // all the docs of the last segment have "tail", the deleted ones too
+        // as nothing is mapped to -1
+        let doc_maps: Vec<_> = leaves
+            .iter()
+            .map(|leaf| VecDocMap((0..leaf.reader.max_doc()).collect()))
+            .collect();
+        let mut consumer = CollectingConsumer::default();
+        merge_terms("body", &terms, &doc_maps, &mut consumer).unwrap();
+        let stats = consumer.stats.unwrap();
+        assert_eq!(stats.doc_count, 10);
+        let tail = consumer.terms.iter().find(|(t, _)| t == b"tail").unwrap();
+        assert_eq!(tail.1.len(), 10);
+
+        let mut consumer = CollectingConsumer::default();
+        assert!(merge_terms("body", &terms, &doc_maps[1..], &mut consumer).is_err());
+        assert!(consumer.stats.is_none());
+    }
+
+    #[test]
+    fn test_force_merge_matches_single_segment() {
+        let reader = write_test_index(IndexWriterConfig::default(), |writer| {
+            build_segments(writer);
+            writer.force_merge(1, true).unwrap();
+            writer.commit().unwrap();
+        });
+        let leaves = reader.leaves();
+        assert_eq!(leaves.len(), 1);
+
+        let expected = build_expected();
+        let expected_leaves = expected.leaves();
+        for name in &["id", "body"] {
+            let merged = leaves[0].reader.terms(name).unwrap().unwrap();
+            let expected_terms = expected_leaves[0].reader.terms(name).unwrap().unwrap();
+            assert_eq!(collect(&merged), collect(&expected_terms));
+        }
+    }
+}
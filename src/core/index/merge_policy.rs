@@ -13,11 +13,13 @@
 
 use core::codec::Codec;
 use core::index::index_writer::IndexWriter;
-use core::index::merge_rate_limiter::MergeRateLimiter;
+use core::index::merge_rate_limiter::{MergeIOStats, MergeRateLimiter};
 use core::index::merge_scheduler::MergeScheduler;
-use core::index::{SegmentCommitInfo, SegmentInfos, SegmentReader};
+use core::index::{
+    is_format_current, segment_format_versions, SegmentCommitInfo, SegmentInfos, SegmentReader,
+};
 use core::store::{Directory, MergeInfo};
-use core::util::{ptr_eq, Volatile};
+use core::util::{ptr_eq, Volatile, VERSION_LATEST};
 
 use error::{
     ErrorKind::{IllegalArgument, RuntimeError},
@@ -26,7 +28,7 @@ use error::{
 
 use std::cell::Cell;
 use std::cmp::Ordering;
-use std::collections::{HashMap, HashSet};
+use std::collections::HashMap;
 use std::f64;
 use std::hash::{Hash, Hasher};
 use std::sync::Arc;
@@ -319,6 +321,11 @@ impl<D: Directory, C: Codec> OneMerge<D, C> {
         }
     }
 
+    /// Returns the IO statistics of this merge so far.
+    pub fn io_stats(&self) -> MergeIOStats {
+        self.rate_limiter.io_stats()
+    }
+
     pub fn store_merge_info(&self) -> MergeInfo {
         MergeInfo::new(
             self.total_max_doc,
@@ -391,7 +398,11 @@ unsafe impl Sync for OneMergeScheduleInfo {}
 /// {@link #setMaxMergedSegmentMB}, then the policy will
 /// merge fewer segments (down to 1 at once, if that one has
 /// deletions) to keep the segment size under budget.
-///     
+///
+/// Once the index is within budget, the segments having more
+/// than `deletes_pct_allowed` percent of deleted docs, including
+/// the too large ones, are still merged to reclaim their deletes.
+///
 /// NOTE: this policy freely merges non-adjacent
 /// segments; if this is a problem, use {@link
 /// LogMergePolicy}.
@@ -410,8 +421,9 @@ pub struct TieredMergePolicy {
     max_merge_at_once: u32,
     max_merged_segment_bytes: u64,
     max_merge_at_once_explicit: u32,
-    floor_segment_bytes: u32,
+    floor_segment_bytes: u64,
     segs_per_tier: f64,
+    deletes_pct_allowed: f64,
     force_merge_deletes_pct_allowed: f64,
     reclaim_deletes_weight: f64,
 }
@@ -426,12 +438,22 @@ impl Default for TieredMergePolicy {
             max_merge_at_once_explicit: 30,
             floor_segment_bytes: 2 * 1024 * 1024,
             segs_per_tier: 10.0,
+            deletes_pct_allowed: 33.0,
             force_merge_deletes_pct_allowed: 10.0,
             reclaim_deletes_weight: 2.0,
         }
     }
 }
 
+fn mb_to_bytes(mb: f64) -> u64 {
+    let bytes = mb * 1024.0 * 1024.0;
+    if bytes > i64::max_value() as f64 {
+        i64::max_value() as u64
+    } else {
+        bytes as u64
+    }
+}
+
 impl TieredMergePolicy {
     pub fn set_max_merge_at_once(&mut self, v: u32) -> Result<()> {
         if v < 2 {
@@ -455,20 +477,64 @@ impl TieredMergePolicy {
         Ok(())
     }
 
-    pub fn set_max_merged_segment_mb(&mut self, mut v: f64) -> Result<()> {
+    pub fn set_max_merged_segment_mb(&mut self, v: f64) -> Result<()> {
         if v < 0.0 {
             bail!(IllegalArgument(format!(
                 "max_merged_segment_bytes must be >= 0, got {}",
                 v
             )));
         }
-        v *= 1024.0 * 1024.0;
-        let max_bytes = if v > i64::max_value() as f64 {
-            i64::max_value() as u64
-        } else {
-            v as u64
-        };
-        self.max_merged_segment_bytes = max_bytes;
+        self.max_merged_segment_bytes = mb_to_bytes(v);
+        Ok(())
+    }
+
+    /// Sets the allowed number of segments per tier. Smaller values mean
+    /// more merging but fewer segments.
+    pub fn set_segments_per_tier(&mut self, v: f64) -> Result<()> {
+        if v < 2.0 {
+            bail!(IllegalArgument(format!(
+                "segments_per_tier must be >= 2.0, got {}",
+                v
+            )));
+        }
+        self.segs_per_tier = v;
+        Ok(())
+    }
+
+    /// Segments smaller than this are "rounded up" to this size, so that
+    /// the tiny flushed segments are merged aggressively.
+    pub fn set_floor_segment_mb(&mut self, v: f64) -> Result<()> {
+        if v <= 0.0 {
+            bail!(IllegalArgument(format!(
+                "floor_segment_mb must be > 0.0, got {}",
+                v
+            )));
+        }
+        self.floor_segment_bytes = mb_to_bytes(v);
+        Ok(())
+    }
+
+    /// Sets the percentage of deleted docs a segment may have before it is
+    /// merged even when the index is within its segment budget.
+    pub fn set_deletes_pct_allowed(&mut self, v: f64) -> Result<()> {
+        if v < 0.0 || v > 100.0 {
+            bail!(IllegalArgument(format!(
+                "deletes_pct_allowed must be in [0.0, 100.0], got {}",
+                v
+            )));
+        }
+        self.deletes_pct_allowed = v;
+        Ok(())
+    }
+
+    pub fn set_force_merge_deletes_pct_allowed(&mut self, v: f64) -> Result<()> {
+        if v < 0.0 || v > 100.0 {
+            bail!(IllegalArgument(format!(
+                "force_merge_deletes_pct_allowed must be in [0.0, 100.0], got {}",
+                v
+            )));
+        }
+        self.force_merge_deletes_pct_allowed = v;
         Ok(())
     }
 
@@ -476,70 +542,30 @@ impl TieredMergePolicy {
         bytes.max(self.floor_segment_bytes as i64)
     }
 
-    /// Expert: scores one merge; subclasses can override.
-    fn score<D, C, MS, MP>(
+    fn segment_stats<D, C, MS, MP>(
         &self,
-        candidate: &[&Arc<SegmentCommitInfo<D, C>>],
-        hit_too_large: bool,
-        _merging_bytes: i64,
+        info: &Arc<SegmentCommitInfo<D, C>>,
         writer: &IndexWriter<D, C, MS, MP>,
-    ) -> MergeScore
+    ) -> SegmentMergeStats
     where
         D: Directory + Send + Sync + 'static,
         C: Codec,
         MS: MergeScheduler,
         MP: MergePolicy,
     {
-        let mut total_before_merge_bytes = 0;
-        let mut total_after_merge_bytes = 0;
-        let mut total_after_merge_bytes_floored = 0;
-        for info in candidate {
-            let seg_bytes = self.size(info.as_ref(), writer);
-            total_after_merge_bytes = seg_bytes;
-            total_after_merge_bytes_floored += self.floor_size(seg_bytes);
-            total_before_merge_bytes += info.size_in_bytes();
+        SegmentMergeStats {
+            name: info.info.name.clone(),
+            size_in_bytes: info.size_in_bytes(),
+            max_doc: info.info.max_doc,
+            del_count: writer.num_deletes_to_merge(info) as i32,
+            merging: writer.merging_segments().contains(&info.info.name),
         }
-
-        // Roughly measure "skew" of the merge, i.e. how
-        // "balanced" the merge is (whether the segments are
-        // about the same size), which can range from
-        // 1.0/numSegsBeingMerged (good) to 1.0 (poor). Heavily
-        // lopsided merges (skew near 1.0) is no good; it means
-        // O(N^2) merge cost over time:
-        let skew = if hit_too_large {
-            // Pretend the merge has perfect skew; skew doesn't
-            // matter in this case because this merge will not
-            // "cascade" and so it cannot lead to N^2 merge cost
-            // over time:
-            1.0 / self.max_merge_at_once as f64
-        } else {
-            self.floor_size(self.size(candidate[0].as_ref(), writer)) as f64
-                / total_after_merge_bytes_floored as f64
-        };
-
-        // Strongly favor merges with less skew (smaller
-        // mergeScore is better):
-        let mut merge_score = skew;
-
-        // Gently favor smaller merges over bigger ones.  We
-        // don't want to make this exponent too large else we
-        // can end up doing poor merges of small segments in
-        // order to avoid the large merges:
-        merge_score *= f64::powf(total_after_merge_bytes as f64, 0.05);
-
-        // Strongly favor merges that reclaim deletes:
-        let non_del_ratio = total_after_merge_bytes as f64 / total_before_merge_bytes as f64;
-        merge_score *= f64::powf(non_del_ratio, self.reclaim_deletes_weight);
-
-        MergeScore::new(merge_score, skew, non_del_ratio)
     }
-}
 
-impl MergePolicy for TieredMergePolicy {
-    fn find_merges<D, C, MS, MP>(
+    fn merge_specification<D, C, MS, MP>(
         &self,
-        _merge_trigger: MergerTrigger,
-        segment_infos: &SegmentInfos<D, C>,
+        infos: &[&Arc<SegmentCommitInfo<D, C>>],
+        merges: Vec<Vec<usize>>,
         writer: &IndexWriter<D, C, MS, MP>,
     ) -> Result<Option<MergeSpecification<D, C>>>
     where
@@ -548,68 +574,86 @@ impl MergePolicy for TieredMergePolicy {
         MS: MergeScheduler,
         MP: MergePolicy,
     {
-        if segment_infos.len() == 0 {
+        if merges.is_empty() {
             return Ok(None);
         }
+        let mut spec = MergeSpecification::default();
+        for merge in merges {
+            let segments = merge.into_iter().map(|i| Arc::clone(infos[i])).collect();
+            let merge = OneMerge::new(segments, writer.next_merge_id())?;
+            debug!("add merge={:?}", &merge.segments);
+            spec.add(merge);
+        }
+        Ok(Some(spec))
+    }
 
-        let merging = &writer.merging_segments();
-        let mut to_be_merged = HashSet::new();
+    // indices of the segments sorted by decreasing size, then by name
+    fn sort_by_size_descending(segments: &[SegmentMergeStats], indices: &mut [usize]) {
+        indices.sort_by(|&a, &b| {
+            segments[b]
+                .size()
+                .cmp(&segments[a].size())
+                .then_with(|| segments[a].name.cmp(&segments[b].name))
+        });
+    }
 
-        let mut infos_sorted = segment_infos.segments.clone();
-        {
-            let comparator = SegmentByteSizeDescending::new(writer, self);
-            infos_sorted.sort_by(|o1, o2| comparator.compare(o1.as_ref(), o2.as_ref()));
+    /// Picks the natural merges of `segments`, returning the indices of the
+    /// segments of every merge.
+    ///
+    /// While the index has more segments than its budget, the least-cost
+    /// merge of at most `max_merge_at_once` segments is picked. Then the
+    /// segments with too many deletes are merged to reclaim them.
+    pub fn select_merges(&self, segments: &[SegmentMergeStats]) -> Vec<Vec<usize>> {
+        let mut merges = vec![];
+        if segments.is_empty() {
+            return merges;
         }
 
-        // Compute total index bytes & print details about the index
+        let mut sorted: Vec<usize> = (0..segments.len()).collect();
+        Self::sort_by_size_descending(segments, &mut sorted);
+
+        // Compute total index bytes
         let mut total_index_bytes = 0;
         let mut min_segment_bytes = i64::max_value();
-        let mut info_seg_bytes = vec![];
-        let mut info_max_docs = vec![];
-        for info in &infos_sorted {
-            let seg_bytes = self.size(info.as_ref(), writer);
+        for &i in &sorted {
+            let seg_bytes = segments[i].size();
             min_segment_bytes = seg_bytes.min(min_segment_bytes);
-            // Accumulate total byte size
             total_index_bytes += seg_bytes;
-            info_seg_bytes.push(seg_bytes);
-            info_max_docs.push(info.info.max_doc);
         }
 
         // If we have too-large segments, grace them out of the max_segment_count
-        let mut too_big_count = 0;
-        while too_big_count < infos_sorted.len() {
-            let seg_bytes = info_seg_bytes[too_big_count];
+        let mut graced = vec![false; segments.len()];
+        for &i in &sorted {
+            let seg_bytes = segments[i].size();
             if seg_bytes < self.max_merged_segment_bytes as i64 / 2 {
                 break;
             }
             total_index_bytes -= seg_bytes;
-            too_big_count += 1;
+            graced[i] = true;
         }
 
+        // Group the other segments in tiers, each tier starting with a segment
+        // `segs_per_tier` times smaller than the first one of the tier above.
+        // The tiers that are not full, except the smallest one, are graced out
+        // too, they don't need merging yet.
+        let mut tiers: Vec<Vec<usize>> = vec![];
         let mut last_seg_bytes = i64::max_value();
-        let mut last_level_infos = vec![];
-        let mut info_levels = vec![];
-        let mut i = too_big_count;
-        while i < infos_sorted.len() {
-            let seg_bytes = info_seg_bytes[i];
+        for &i in sorted.iter().filter(|&&i| !graced[i]) {
+            let seg_bytes = segments[i].size();
             if last_seg_bytes >= seg_bytes * self.segs_per_tier as i64 {
-                if last_level_infos.len() > 0 {
-                    info_levels.push(last_level_infos);
-                    last_level_infos = vec![];
-                }
-
+                tiers.push(vec![]);
                 last_seg_bytes = seg_bytes;
             }
-
-            last_level_infos.push(seg_bytes);
-            i += 1;
+            tiers.last_mut().unwrap().push(i);
         }
-
-        for info_level in &info_levels {
-            if info_level.len() < self.segs_per_tier as usize {
-                for _i in 0..info_level.len() {
-                    total_index_bytes -= info_seg_bytes[too_big_count];
-                    too_big_count += 1;
+        if !tiers.is_empty() {
+            let smallest = tiers.len() - 1;
+            for tier in &tiers[..smallest] {
+                if tier.len() < self.segs_per_tier as usize {
+                    for &i in tier {
+                        total_index_bytes -= segments[i].size();
+                        graced[i] = true;
+                    }
                 }
             }
         }
@@ -628,12 +672,12 @@ impl MergePolicy for TieredMergePolicy {
             }
             allowed_seg_count += self.segs_per_tier;
             bytes_left -= (self.segs_per_tier * level_size as f64) as i64;
-            level_size *= self.max_merge_at_once as i64;
+            level_size *= i64::from(self.max_merge_at_once);
         }
+        let allowed_seg_count = allowed_seg_count as usize;
 
-        let allowed_seg_count_int = allowed_seg_count as u32;
-        let mut spec = MergeSpecification::default();
-
+        let mut to_be_merged = vec![false; segments.len()];
+        let max_merge_at_once = self.max_merge_at_once as usize;
         // Cycle to possibly select more than one merge:
         loop {
             let mut merging_bytes = 0;
@@ -642,127 +686,271 @@ impl MergePolicy for TieredMergePolicy {
             // not already being merged and not already picked (by
             // prior iteration of this loop) for merging:
             let mut eligible = vec![];
-            for info in &infos_sorted[too_big_count..] {
-                if merging.contains(&info.info.name) {
-                    merging_bytes += self.size(info.as_ref(), writer);
-                } else if !to_be_merged.contains(info) {
-                    eligible.push(info);
+            for &i in sorted.iter().filter(|&&i| !graced[i]) {
+                if segments[i].merging {
+                    merging_bytes += segments[i].size();
+                } else if !to_be_merged[i] {
+                    eligible.push(i);
                 }
             }
 
             let max_merge_is_running = merging_bytes >= self.max_merged_segment_bytes as i64;
 
-            if eligible.is_empty() {
-                if spec.merges.is_empty() {
-                    return Ok(None);
-                } else {
-                    return Ok(Some(spec));
-                }
+            if eligible.len() <= allowed_seg_count || eligible.len() < max_merge_at_once {
+                break;
             }
 
-            if eligible.len() > allowed_seg_count_int as usize {
-                // OK we are over budget -- find best merge!
-                let mut best_score = MergeScore::new(f64::INFINITY, 0.0, 0.0);
-                let mut best = vec![];
-                let mut best_too_large = false;
-                let mut best_merge_bytes = 0;
-
-                // Consider all merge starts:
-                if eligible.len() >= self.max_merge_at_once as usize {
-                    let mut start_idx = 0;
-                    while start_idx <= eligible.len() - self.max_merge_at_once as usize {
-                        let mut total_after_merge_bytes = 0;
-                        let mut candidate = vec![];
-                        let mut hit_too_large = false;
-                        let mut idx = start_idx;
-                        while idx < eligible.len()
-                            && candidate.len() < self.max_merge_at_once as usize
-                        {
-                            let info = eligible[idx];
-                            let seg_bytes = self.size(info, writer);
-
-                            if total_after_merge_bytes + seg_bytes
-                                > self.max_merged_segment_bytes as i64
-                            {
-                                hit_too_large = true;
-                            // NOTE: we continue, so that we can try
-                            // "packing" smaller segments into this merge
-                            // to see if we can get closer to the max
-                            // size; this in general is not perfect since
-                            // this is really "bin packing" and we'd have
-                            // to try different permutations.
-                            } else {
-                                candidate.push(info);
-                                total_after_merge_bytes += seg_bytes;
-                            }
-
-                            idx += 1;
-                        }
-
-                        // We should never see an empty candidate: we iterated over maxMergeAtOnce
-                        // segments, and already pre - excluded the too - large segments:
-                        debug_assert!(!candidate.is_empty());
-
-                        let score = self.score(&candidate, hit_too_large, merging_bytes, writer);
-                        debug!(
-                            "maybe={:?}, score={} {}, too_large={} size={} MB",
-                            &candidate,
-                            score.score(),
-                            score.explanation(),
-                            hit_too_large,
-                            (total_after_merge_bytes as f64) / 1024.0 / 1024.0
-                        );
-                        // If we are already running a max sized merge
-                        // (maxMergeIsRunning), don't allow another max
-                        // sized merge to kick off:
-                        if score.score() < best_score.score()
-                            && (!hit_too_large || !max_merge_is_running)
-                        {
-                            best = candidate;
-                            best_score = score;
-                            best_too_large = hit_too_large;
-                            best_merge_bytes = total_after_merge_bytes;
-                        }
-
-                        start_idx += 1;
+            // OK we are over budget -- find best merge!
+            let mut best_score = MergeScore::new(f64::INFINITY, 0.0, 0.0);
+            let mut best = vec![];
+
+            // Consider all merge starts:
+            for start_idx in 0..=eligible.len() - max_merge_at_once {
+                let mut total_after_merge_bytes = 0;
+                let mut candidate = vec![];
+                let mut hit_too_large = false;
+                for &i in &eligible[start_idx..] {
+                    if candidate.len() == max_merge_at_once {
+                        break;
                     }
-                }
-
-                if !best.is_empty() {
-                    let mut segments = Vec::with_capacity(best.len());
-                    for s in best {
-                        segments.push(Arc::clone(s));
-                    }
-                    let merge = OneMerge::new(segments, writer.next_merge_id())?;
-                    for info in &merge.segments {
-                        to_be_merged.insert(Arc::clone(info));
-                    }
-                    debug!(
-                        "add merge={:?} size={} MB, score={} {}, {}",
-                        &merge.segments,
-                        (best_merge_bytes as f64) / 1024.0 / 1024.0,
-                        best_score.score(),
-                        best_score.explanation(),
-                        if best_too_large { "[max merge]" } else { "" }
-                    );
-                    spec.add(merge);
-                } else {
-                    if spec.merges.is_empty() {
-                        return Ok(None);
+                    let seg_bytes = segments[i].size();
+                    if total_after_merge_bytes + seg_bytes > self.max_merged_segment_bytes as i64 {
+                        hit_too_large = true;
+                    // NOTE: we continue, so that we can try
+                    // "packing" smaller segments into this merge
+                    // to see if we can get closer to the max
+                    // size; this in general is not perfect since
+                    // this is really "bin packing" and we'd have
+                    // to try different permutations.
                     } else {
-                        return Ok(Some(spec));
+                        candidate.push(i);
+                        total_after_merge_bytes += seg_bytes;
                     }
                 }
-            } else {
-                if spec.merges.is_empty() {
-                    return Ok(None);
-                } else {
-                    return Ok(Some(spec));
+
+                // We should never see an empty candidate: we iterated over maxMergeAtOnce
+                // segments, and already pre - excluded the too - large segments:
+                debug_assert!(!candidate.is_empty());
+
+                let score = self.score(segments, &candidate, hit_too_large);
+                debug!(
+                    "maybe={:?}, score={} {}, too_large={} size={} MB",
+                    candidate
+                        .iter()
+                        .map(|&i| &segments[i].name)
+                        .collect::<Vec<_>>(),
+                    score.score(),
+                    score.explanation(),
+                    hit_too_large,
+                    (total_after_merge_bytes as f64) / 1024.0 / 1024.0
+                );
+                // If we are already running a max sized merge
+                // (maxMergeIsRunning), don't allow another max
+                // sized merge to kick off:
+                if score.score() < best_score.score() && (!hit_too_large || !max_merge_is_running) {
+                    best = candidate;
+                    best_score = score;
                 }
             }
+
+            if best.is_empty() {
+                break;
+            }
+            for &i in &best {
+                to_be_merged[i] = true;
+            }
+            merges.push(best);
+        }
+
+        self.select_deletes_merges(segments, &mut to_be_merged, &mut merges);
+        merges
+    }
+
+    // Merges the segments with more than `deletes_pct_allowed` deletes that
+    // are not merged yet, the ones with the most deletes first.
+    fn select_deletes_merges(
+        &self,
+        segments: &[SegmentMergeStats],
+        to_be_merged: &mut [bool],
+        merges: &mut Vec<Vec<usize>>,
+    ) {
+        let mut candidates: Vec<usize> = (0..segments.len())
+            .filter(|&i| {
+                !segments[i].merging
+                    && !to_be_merged[i]
+                    && segments[i].pct_deletes() > self.deletes_pct_allowed
+            })
+            .collect();
+        candidates.sort_by(|&a, &b| {
+            segments[b]
+                .pct_deletes()
+                .partial_cmp(&segments[a].pct_deletes())
+                .unwrap_or(Ordering::Equal)
+                .then_with(|| segments[a].name.cmp(&segments[b].name))
+        });
+
+        for i in 0..candidates.len() {
+            let first = candidates[i];
+            if to_be_merged[first] {
+                continue;
+            }
+            // a too large segment is merged alone, rewriting it without its deletes
+            let mut merge = vec![first];
+            let mut merge_bytes = segments[first].size();
+            to_be_merged[first] = true;
+            for &j in &candidates[i + 1..] {
+                if merge.len() == self.max_merge_at_once as usize {
+                    break;
+                }
+                let seg_bytes = segments[j].size();
+                if !to_be_merged[j]
+                    && merge_bytes + seg_bytes <= self.max_merged_segment_bytes as i64
+                {
+                    merge.push(j);
+                    merge_bytes += seg_bytes;
+                    to_be_merged[j] = true;
+                }
+            }
+            merges.push(merge);
         }
     }
 
+    /// Picks the merges reducing `segments` to at most `max_segment_count`
+    /// segments, merging at most `max_merge_at_once_explicit` segments at once.
+    ///
+    /// Waits for the running merges of these segments before the final merge.
+    pub fn select_forced_merges(
+        &self,
+        segments: &[SegmentMergeStats],
+        max_segment_count: u32,
+    ) -> Vec<Vec<usize>> {
+        let mut merges = vec![];
+        let max_segment_count = max_segment_count.max(1) as usize;
+        let mut eligible: Vec<usize> = (0..segments.len())
+            .filter(|&i| !segments[i].merging)
+            .collect();
+        let force_merge_running = eligible.len() < segments.len();
+
+        if eligible.is_empty() || (max_segment_count > 1 && eligible.len() <= max_segment_count) {
+            return merges;
+        }
+        Self::sort_by_size_descending(segments, &mut eligible);
+
+        let max_merge_at_once = self.max_merge_at_once_explicit as usize;
+        let mut end = eligible.len();
+
+        // Do full merges, first, backwards:
+        while end >= max_merge_at_once + max_segment_count - 1 {
+            merges.push(eligible[end - max_merge_at_once..end].to_vec());
+            end -= max_merge_at_once;
+        }
+
+        if merges.is_empty() && !force_merge_running {
+            // Do final merge
+            let num_to_merge = end + 1 - max_segment_count;
+            merges.push(eligible[end - num_to_merge..end].to_vec());
+        }
+        merges
+    }
+
+    /// Picks the merges of the segments with more than
+    /// `force_merge_deletes_pct_allowed` deletes.
+    pub fn select_forced_deletes_merges(&self, segments: &[SegmentMergeStats]) -> Vec<Vec<usize>> {
+        let mut eligible: Vec<usize> = (0..segments.len())
+            .filter(|&i| {
+                !segments[i].merging
+                    && segments[i].pct_deletes() > self.force_merge_deletes_pct_allowed
+            })
+            .collect();
+        Self::sort_by_size_descending(segments, &mut eligible);
+
+        // Don't enforce max merged size here: app is explicitly
+        // calling forceMergeDeletes, and knows this may take a
+        // long time / produce big segments (like forceMerge):
+        eligible
+            .chunks(self.max_merge_at_once_explicit as usize)
+            .map(|merge| merge.to_vec())
+            .collect()
+    }
+
+    /// Expert: scores one merge; subclasses can override.
+    fn score(
+        &self,
+        segments: &[SegmentMergeStats],
+        candidate: &[usize],
+        hit_too_large: bool,
+    ) -> MergeScore {
+        let mut total_before_merge_bytes = 0;
+        let mut total_after_merge_bytes = 0;
+        let mut total_after_merge_bytes_floored = 0;
+        for &i in candidate {
+            let seg_bytes = segments[i].size();
+            total_after_merge_bytes += seg_bytes;
+            total_after_merge_bytes_floored += self.floor_size(seg_bytes);
+            total_before_merge_bytes += segments[i].size_in_bytes;
+        }
+
+        // Roughly measure "skew" of the merge, i.e. how
+        // "balanced" the merge is (whether the segments are
+        // about the same size), which can range from
+        // 1.0/numSegsBeingMerged (good) to 1.0 (poor). Heavily
+        // lopsided merges (skew near 1.0) is no good; it means
+        // O(N^2) merge cost over time:
+        let skew = if hit_too_large {
+            // Pretend the merge has perfect skew; skew doesn't
+            // matter in this case because this merge will not
+            // "cascade" and so it cannot lead to N^2 merge cost
+            // over time:
+            1.0 / f64::from(self.max_merge_at_once)
+        } else {
+            self.floor_size(segments[candidate[0]].size()) as f64
+                / total_after_merge_bytes_floored as f64
+        };
+
+        // Strongly favor merges with less skew (smaller
+        // mergeScore is better):
+        let mut merge_score = skew;
+
+        // Gently favor smaller merges over bigger ones.  We
+        // don't want to make this exponent too large else we
+        // can end up doing poor merges of small segments in
+        // order to avoid the large merges:
+        merge_score *= f64::powf(total_after_merge_bytes as f64, 0.05);
+
+        // Strongly favor merges that reclaim deletes:
+        let non_del_ratio = if total_before_merge_bytes > 0 {
+            total_after_merge_bytes as f64 / total_before_merge_bytes as f64
+        } else {
+            1.0
+        };
+        merge_score *= f64::powf(non_del_ratio, self.reclaim_deletes_weight);
+
+        MergeScore::new(merge_score, skew, non_del_ratio)
+    }
+}
+
+impl MergePolicy for TieredMergePolicy {
+    fn find_merges<D, C, MS, MP>(
+        &self,
+        _merge_trigger: MergerTrigger,
+        segment_infos: &SegmentInfos<D, C>,
+        writer: &IndexWriter<D, C, MS, MP>,
+    ) -> Result<Option<MergeSpecification<D, C>>>
+    where
+        D: Directory + Send + Sync + 'static,
+        C: Codec,
+        MS: MergeScheduler,
+        MP: MergePolicy,
+    {
+        let infos: Vec<_> = segment_infos.segments.iter().collect();
+        let stats: Vec<_> = infos
+            .iter()
+            .map(|info| self.segment_stats(info, writer))
+            .collect();
+        let merges = self.select_merges(&stats);
+        self.merge_specification(&infos, merges, writer)
+    }
+
     fn find_forced_merges<D, C, MS, MP>(
         &self,
         segment_infos: &SegmentInfos<D, C>,
@@ -776,71 +964,30 @@ impl MergePolicy for TieredMergePolicy {
         MS: MergeScheduler,
         MP: MergePolicy,
     {
-        let mut eligible = vec![];
-        let mut force_merge_running = false;
-        let merging = &writer.merging_segments();
+        let mut infos = vec![];
+        let mut stats = vec![];
         let mut segment_is_original = false;
-
         for info in &segment_infos.segments {
             if let Some(is_original) = segments_to_merge.get(info) {
                 segment_is_original = *is_original;
-                if !merging.contains(&info.info.name) {
-                    eligible.push(info);
-                } else {
-                    force_merge_running = true;
-                }
+                infos.push(info);
+                stats.push(self.segment_stats(info, writer));
             }
         }
 
-        if eligible.is_empty() {
-            return Ok(None);
-        }
-
-        if (max_segment_count > 1 && eligible.len() <= max_segment_count as usize)
-            || (max_segment_count == 1
-                && eligible.len() == 1
+        // a lone segment is only rewritten when it isn't merged yet
+        let mut eligible = stats.iter().enumerate().filter(|(_, s)| !s.merging);
+        if let (Some((i, _)), None) = (eligible.next(), eligible.next()) {
+            if max_segment_count == 1
                 && (!segment_is_original
-                    || self.is_merged(segment_infos, eligible[0].as_ref(), writer)))
-        {
-            return Ok(None);
-        }
-
-        {
-            let comparator = SegmentByteSizeDescending::new(writer, self);
-            eligible.sort_by(|s1, s2| comparator.compare(s1.as_ref(), s2.as_ref()));
-        }
-
-        let mut end = eligible.len();
-        let mut spec = MergeSpecification::default();
-
-        // Do full merges, first, backwards:
-        while end >= (self.max_merge_at_once_explicit + max_segment_count - 1) as usize {
-            let mut segments = vec![];
-            for i in end - self.max_merge_at_once_explicit as usize..end {
-                segments.push(Arc::clone(eligible[i]));
-            }
-            let merge = OneMerge::new(segments, writer.next_merge_id())?;
-            spec.add(merge);
-            end -= self.max_merge_at_once_explicit as usize;
-        }
-
-        if spec.merges.is_empty() && !force_merge_running {
-            // Do final merge
-            let num_to_merge = end + 1 - max_segment_count as usize;
-            let mut segments = vec![];
-            for i in end - num_to_merge..end {
-                segments.push(Arc::clone(eligible[i]));
+                    || self.is_merged(segment_infos, infos[i].as_ref(), writer))
+            {
+                return Ok(None);
             }
-            let merge = OneMerge::new(segments, writer.next_merge_id())?;
-            spec = MergeSpecification::default();
-            spec.add(merge);
         }
 
-        if spec.merges.is_empty() {
-            return Ok(None);
-        } else {
-            return Ok(Some(spec));
-        }
+        let merges = self.select_forced_merges(&stats, max_segment_count);
+        self.merge_specification(&infos, merges, writer)
     }
 
     fn find_forced_deletes_mergers<D, C, MS, MP>(
@@ -854,100 +1001,158 @@ impl MergePolicy for TieredMergePolicy {
         MS: MergeScheduler,
         MP: MergePolicy,
     {
-        let mut eligible = vec![];
-        let merging = &writer.merging_segments();
-
-        for info in &segments_infos.segments {
-            let pct_deletes =
-                100.0 * writer.num_deleted_docs(info.as_ref()) as f64 / info.info.max_doc as f64;
-            if pct_deletes > self.force_merge_deletes_pct_allowed
-                && !merging.contains(&info.info.name)
-            {
-                eligible.push(info);
-            }
-        }
+        let infos: Vec<_> = segments_infos.segments.iter().collect();
+        let stats: Vec<_> = infos
+            .iter()
+            .map(|info| self.segment_stats(info, writer))
+            .collect();
+        let merges = self.select_forced_deletes_merges(&stats);
+        self.merge_specification(&infos, merges, writer)
+    }
 
-        if eligible.is_empty() {
-            return Ok(None);
-        }
+    fn max_cfs_segment_size(&self) -> u64 {
+        self.max_cfs_segment_size
+    }
 
-        {
-            let comparator = SegmentByteSizeDescending::new(writer, self);
-            eligible.sort_by(|s1, s2| comparator.compare(s1.as_ref(), s2.as_ref()));
-        }
+    fn no_cfs_ratio(&self) -> f64 {
+        self.no_cfs_ratio
+    }
+}
 
-        let mut start = 0;
-        let mut spec = MergeSpecification::default();
+/// Wraps another merge policy to upgrade the segments written by older
+/// versions, see `IndexUpgrader`.
+///
+/// The forced merges only rewrite the segments whose version is older than
+/// `VERSION_LATEST` or whose files have an older format version in their
+/// codec headers, each in its own merge so that they keep their place in
+/// the index, the current segments are left alone. The natural merges are
+/// left to the wrapped policy.
+pub struct UpgradeIndexMergePolicy<P: MergePolicy> {
+    base: P,
+}
 
-        while start < eligible.len() {
-            // Don't enforce max merged size here: app is explicitly
-            // calling forceMergeDeletes, and knows this may take a
-            // long time / produce big segments (like forceMerge):
-            let end = eligible
-                .len()
-                .min(start + self.max_merge_at_once_explicit as usize);
-            let mut segments = vec![];
-            for i in start..end {
-                segments.push(Arc::clone(eligible[i]));
+impl<P: MergePolicy> UpgradeIndexMergePolicy<P> {
+    pub fn new(base: P) -> Self {
+        UpgradeIndexMergePolicy { base }
+    }
+
+    pub fn base(&self) -> &P {
+        &self.base
+    }
+
+    /// Returns true if the segment was written by an older version, or if one
+    /// of its files has an older format, and must be upgraded.
+    pub fn should_upgrade_segment<D: Directory, C: Codec>(
+        info: &SegmentCommitInfo<D, C>,
+    ) -> Result<bool> {
+        Ok(info.info.version < VERSION_LATEST
+            || !is_format_current(&segment_format_versions(&info.info)?))
+    }
+}
+
+impl<P: MergePolicy> MergePolicy for UpgradeIndexMergePolicy<P> {
+    fn find_merges<D, C, MS, MP>(
+        &self,
+        merge_trigger: MergerTrigger,
+        segment_infos: &SegmentInfos<D, C>,
+        writer: &IndexWriter<D, C, MS, MP>,
+    ) -> Result<Option<MergeSpecification<D, C>>>
+    where
+        D: Directory + Send + Sync + 'static,
+        C: Codec,
+        MS: MergeScheduler,
+        MP: MergePolicy,
+    {
+        self.base.find_merges(merge_trigger, segment_infos, writer)
+    }
+
+    fn find_forced_merges<D, C, MS, MP>(
+        &self,
+        segment_infos: &SegmentInfos<D, C>,
+        _max_segment_count: u32,
+        segments_to_merge: &HashMap<Arc<SegmentCommitInfo<D, C>>, bool>,
+        writer: &IndexWriter<D, C, MS, MP>,
+    ) -> Result<Option<MergeSpecification<D, C>>>
+    where
+        D: Directory + Send + Sync + 'static,
+        C: Codec,
+        MS: MergeScheduler,
+        MP: MergePolicy,
+    {
+        let mut spec = MergeSpecification::default();
+        for info in &segment_infos.segments {
+            if segments_to_merge.contains_key(info)
+                && !writer.merging_segments().contains(&info.info.name)
+                && Self::should_upgrade_segment(info)?
+            {
+                // a singleton merge rewrites the segment with the current
+                // codec and drops its deleted docs
+                spec.add(OneMerge::new(
+                    vec![Arc::clone(info)],
+                    writer.next_merge_id(),
+                )?);
             }
-            let merge = OneMerge::new(segments, writer.next_merge_id())?;
-            spec.add(merge);
-            start = end;
         }
         if spec.merges.is_empty() {
-            return Ok(None);
+            Ok(None)
         } else {
-            return Ok(Some(spec));
+            Ok(Some(spec))
         }
     }
 
+    fn find_forced_deletes_mergers<D, C, MS, MP>(
+        &self,
+        segments_infos: &SegmentInfos<D, C>,
+        writer: &IndexWriter<D, C, MS, MP>,
+    ) -> Result<Option<MergeSpecification<D, C>>>
+    where
+        D: Directory + Send + Sync + 'static,
+        C: Codec,
+        MS: MergeScheduler,
+        MP: MergePolicy,
+    {
+        self.base
+            .find_forced_deletes_mergers(segments_infos, writer)
+    }
+
     fn max_cfs_segment_size(&self) -> u64 {
-        self.max_cfs_segment_size
+        self.base.max_cfs_segment_size()
     }
 
     fn no_cfs_ratio(&self) -> f64 {
-        self.no_cfs_ratio
+        self.base.no_cfs_ratio()
     }
 }
 
-struct SegmentByteSizeDescending<
-    'a,
-    D: Directory + Send + Sync + 'static,
-    C: Codec,
-    MS: MergeScheduler,
-    MP: MergePolicy,
-> {
-    writer: &'a IndexWriter<D, C, MS, MP>,
-    policy: &'a TieredMergePolicy,
+/// What the `TieredMergePolicy` knows of a segment when picking merges.
+#[derive(Clone, Debug)]
+pub struct SegmentMergeStats {
+    pub name: String,
+    /// Size of the files of the segment.
+    pub size_in_bytes: i64,
+    pub max_doc: i32,
+    /// Deleted docs, including the ones pending in the writer.
+    pub del_count: i32,
+    /// Whether the segment is already being merged.
+    pub merging: bool,
 }
 
-impl<'a, D, C, MS, MP> SegmentByteSizeDescending<'a, D, C, MS, MP>
-where
-    D: Directory + Send + Sync + 'static,
-    C: Codec,
-    MS: MergeScheduler,
-    MP: MergePolicy,
-{
-    fn new(writer: &'a IndexWriter<D, C, MS, MP>, policy: &'a TieredMergePolicy) -> Self {
-        SegmentByteSizeDescending { writer, policy }
+impl SegmentMergeStats {
+    /// Percentage of deleted docs of the segment.
+    pub fn pct_deletes(&self) -> f64 {
+        if self.max_doc <= 0 {
+            0.0
+        } else {
+            100.0 * f64::from(self.del_count) / f64::from(self.max_doc)
+        }
     }
-}
 
-impl<'a, D, C, MS, MP> SegmentByteSizeDescending<'a, D, C, MS, MP>
-where
-    D: Directory + Send + Sync + 'static,
-    C: Codec,
-    MS: MergeScheduler,
-    MP: MergePolicy,
-{
-    fn compare(&self, o1: &SegmentCommitInfo<D, C>, o2: &SegmentCommitInfo<D, C>) -> Ordering {
-        let sz1 = self.policy.size(o1, self.writer);
-        let sz2 = self.policy.size(o2, self.writer);
-        let cmp = sz2.cmp(&sz1);
-        if cmp != Ordering::Equal {
-            cmp
+    /// Size of the segment pro-rated by its percentage of live docs.
+    pub fn size(&self) -> i64 {
+        if self.max_doc <= 0 {
+            self.size_in_bytes
         } else {
-            o1.info.name.cmp(&o2.info.name)
+            (self.size_in_bytes as f64 * (1.0 - self.pct_deletes() / 100.0)) as i64
         }
     }
 }
@@ -977,3 +1182,171 @@ impl MergeScore {
         format!("skew {} non_del_ratio: {}", self.skew, self.non_del_ratio)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MB: i64 = 1024 * 1024;
+
+    fn segment(name: &str, size_in_bytes: i64, max_doc: i32, del_count: i32) -> SegmentMergeStats {
+        SegmentMergeStats {
+            name: name.to_string(),
+            size_in_bytes,
+            max_doc,
+            del_count,
+            merging: false,
+        }
+    }
+
+    fn segments(prefix: &str, count: usize, size_in_bytes: i64) -> Vec<SegmentMergeStats> {
+        (0..count)
+            .map(|i| segment(&format!("{}{:03}", prefix, i), size_in_bytes, 1000, 0))
+            .collect()
+    }
+
+    fn names(stats: &[SegmentMergeStats], merge: &[usize]) -> Vec<String> {
+        let mut names: Vec<_> = merge.iter().map(|&i| stats[i].name.clone()).collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn test_merges_over_budget() {
+        let mut policy = TieredMergePolicy::default();
+        let stats = segments("_", 30, MB);
+        let merges = policy.select_merges(&stats);
+        assert_eq!(merges.len(), 2);
+        assert!(merges.iter().all(|m| m.len() == 10));
+        assert!(policy.select_merges(&segments("_", 8, MB)).is_empty());
+
+        policy.set_max_merge_at_once(5).unwrap();
+        let merges = policy.select_merges(&stats);
+        assert_eq!(merges.len(), 4);
+        assert!(merges.iter().all(|m| m.len() == 5));
+
+        // every segment is picked at most once
+        let mut picked: Vec<_> = merges.iter().flat_map(|m| m.iter().cloned()).collect();
+        picked.sort();
+        picked.dedup();
+        assert_eq!(picked.len(), 20);
+    }
+
+    #[test]
+    fn test_partial_tiers_are_graced() {
+        let policy = TieredMergePolicy::default();
+
+        let mut stats = segments("a", 5, 100 * MB);
+        stats.extend(segments("b", 9, MB));
+        assert!(policy.select_merges(&stats).is_empty());
+
+        stats.extend(segments("c", 20, MB));
+        let merges = policy.select_merges(&stats);
+        assert!(!merges.is_empty());
+        for merge in &merges {
+            assert!(merge.iter().all(|&i| stats[i].size_in_bytes == MB));
+        }
+    }
+
+    #[test]
+    fn test_merging_segments_are_skipped() {
+        let policy = TieredMergePolicy::default();
+        let mut stats = segments("_", 30, MB);
+        for s in &mut stats[..10] {
+            s.merging = true;
+        }
+        let merges = policy.select_merges(&stats);
+        assert_eq!(merges.len(), 1);
+        assert!(merges[0].iter().all(|&i| !stats[i].merging));
+    }
+
+    #[test]
+    fn test_reclaim_deletes() {
+        let mut policy = TieredMergePolicy::default();
+        let mut stats = segments("_", 3, 100 * MB);
+        stats.push(segment("del_50", 100 * MB, 1000, 500));
+        stats.push(segment("del_20", 100 * MB, 1000, 200));
+        stats.push(segment("huge_40", 3 * 1024 * MB, 1000, 400));
+
+        let merges = policy.select_merges(&stats);
+        let merged: Vec<_> = merges.iter().flat_map(|m| names(&stats, m)).collect();
+        assert!(merged.contains(&"del_50".to_string()));
+        assert!(merged.contains(&"huge_40".to_string()));
+        assert!(!merged.contains(&"del_20".to_string()));
+        assert!(merges
+            .iter()
+            .all(|m| m.iter().map(|&i| stats[i].size()).sum::<i64>() <= 5 * 1024 * MB));
+
+        policy.set_deletes_pct_allowed(10.0).unwrap();
+        let merges = policy.select_merges(&stats);
+        let merged: Vec<_> = merges.iter().flat_map(|m| names(&stats, m)).collect();
+        assert!(merged.contains(&"del_20".to_string()));
+
+        policy.set_deletes_pct_allowed(60.0).unwrap();
+        assert!(policy.select_merges(&stats).is_empty());
+    }
+
+    #[test]
+    fn test_prefer_merges_reclaiming_deletes() {
+        let policy = TieredMergePolicy::default();
+        let mut stats = segments("_", 20, 10 * MB);
+        stats.push(segment("_deletes", 10 * MB, 1000, 200));
+        let merges = policy.select_merges(&stats);
+        assert_eq!(merges.len(), 1);
+        assert!(names(&stats, &merges[0]).contains(&"_deletes".to_string()));
+    }
+
+    #[test]
+    fn test_forced_merges() {
+        let policy = TieredMergePolicy::default();
+
+        let stats = segments("_", 25, MB);
+        let merges = policy.select_forced_merges(&stats, 1);
+        assert_eq!(merges.len(), 1);
+        assert_eq!(merges[0].len(), 25);
+
+        assert!(policy.select_forced_merges(&stats, 25).is_empty());
+
+        let mut stats: Vec<_> = (0..25)
+            .map(|i| segment(&format!("_{:03}", i), (i + 1) * MB, 1000, 0))
+            .collect();
+        let merges = policy.select_forced_merges(&stats, 5);
+        assert_eq!(merges.len(), 1);
+        assert_eq!(merges[0].len(), 21);
+        // the 4 largest segments are left alone
+        assert!(merges[0].iter().all(|&i| stats[i].size_in_bytes <= 21 * MB));
+
+        // no final merge while a forced merge is running
+        stats[0].merging = true;
+        assert!(policy.select_forced_merges(&stats, 5).is_empty());
+
+        let stats = segments("_", 70, MB);
+        let merges = policy.select_forced_merges(&stats, 1);
+        assert_eq!(merges.len(), 2);
+        assert!(merges.iter().all(|m| m.len() == 30));
+    }
+
+    #[test]
+    fn test_forced_deletes_merges() {
+        let policy = TieredMergePolicy::default();
+        let mut stats = segments("_", 40, MB);
+        for s in &mut stats[..35] {
+            s.del_count = 200;
+        }
+        let merges = policy.select_forced_deletes_merges(&stats);
+        assert_eq!(merges.len(), 2);
+        assert_eq!(merges[0].len(), 30);
+        assert_eq!(merges[1].len(), 5);
+    }
+
+    #[test]
+    fn test_invalid_settings() {
+        let mut policy = TieredMergePolicy::default();
+        assert!(policy.set_segments_per_tier(1.5).is_err());
+        assert!(policy.set_floor_segment_mb(0.0).is_err());
+        assert!(policy.set_deletes_pct_allowed(101.0).is_err());
+        assert!(policy.set_deletes_pct_allowed(-1.0).is_err());
+        assert!(policy.set_segments_per_tier(5.0).is_ok());
+        assert!(policy.set_floor_segment_mb(0.5).is_ok());
+    }
+}
@@ -51,19 +51,18 @@ enum PauseResult {
 
 impl MergeRateLimiter {
     pub fn new() -> Self {
-        let limiter = MergeRateLimiter {
+        // not rate limited until the merge scheduler sets a rate
+        MergeRateLimiter {
             total_bytes_written: AtomicU64::new(0),
-            mb_per_sec: Volatile::new(0.0),
+            mb_per_sec: Volatile::new(f64::INFINITY),
             last_time: Volatile::new(SystemTime::now()),
-            min_pause_check_bytes: Volatile::new(0),
+            min_pause_check_bytes: Volatile::new(MAX_MIN_PAUSE_CHECK_BYTES),
             abort: AtomicBool::new(false),
             total_paused_dur: Volatile::new(Duration::default()),
             total_stopped_dur: Volatile::new(Duration::default()),
             lock: Mutex::new(()),
             cond: Condvar::new(),
-        };
-        limiter.set_mb_per_sec(f64::INFINITY);
-        limiter
+        }
     }
 
     fn maybe_pause(&self, bytes: u64, cur_ns: SystemTime) -> Result<PauseResult> {
@@ -124,17 +123,53 @@ impl MergeRateLimiter {
     pub fn aborted(&self) -> bool {
         self.abort.load(Ordering::Acquire)
     }
+
+    /// Total bytes written by the merge so far.
+    pub fn total_bytes_written(&self) -> u64 {
+        self.total_bytes_written.load(Ordering::Acquire)
+    }
+
+    /// Total time the merge was paused to enforce the rate limit.
+    pub fn total_paused_dur(&self) -> Duration {
+        self.total_paused_dur.read()
+    }
+
+    /// Total time the merge was stopped because the rate was set to 0.
+    pub fn total_stopped_dur(&self) -> Duration {
+        self.total_stopped_dur.read()
+    }
+
+    pub fn io_stats(&self) -> MergeIOStats {
+        MergeIOStats {
+            bytes_written: self.total_bytes_written(),
+            paused: self.total_paused_dur(),
+            stopped: self.total_stopped_dur(),
+        }
+    }
+}
+
+/// IO statistics of a merge, as tracked by its `MergeRateLimiter`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MergeIOStats {
+    pub bytes_written: u64,
+    pub paused: Duration,
+    pub stopped: Duration,
 }
 
 const MIN_PAUSE_CHECK_MSEC: i32 = 25;
 
+const MAX_MIN_PAUSE_CHECK_BYTES: u64 = 64 * 1024 * 1024;
+
 impl RateLimiter for MergeRateLimiter {
-    fn set_mb_per_sec(&self, mb_per_sec: f64) {
-        let _g = self.lock.lock().unwrap();
+    fn set_mb_per_sec(&self, mb_per_sec: f64) -> Result<()> {
         // 0.0 is allowed: it means the merge is paused
-        if mb_per_sec < 0.0 {
-            panic!("mb_per_sec must be position; got: {}", mb_per_sec);
+        if mb_per_sec.is_nan() || mb_per_sec < 0.0 {
+            bail!(ErrorKind::IllegalArgument(format!(
+                "mb_per_sec must be >= 0; got: {}",
+                mb_per_sec
+            )));
         }
+        let _g = self.lock.lock()?;
 
         self.mb_per_sec.write(mb_per_sec);
         // NOTE: java Double.POSITIVE_INFINITY cast to long is long.MAX_VALUE,
@@ -147,8 +182,9 @@ impl RateLimiter for MergeRateLimiter {
             check_value as u64
         };
         self.min_pause_check_bytes
-            .write(::std::cmp::min(64 * 1024 * 1024, check_bytes));
+            .write(::std::cmp::min(MAX_MIN_PAUSE_CHECK_BYTES, check_bytes));
         self.cond.notify_one();
+        Ok(())
     }
 
     fn mb_per_sec(&self) -> f64 {
@@ -183,7 +219,7 @@ impl RateLimiter for MergeRateLimiter {
                 self.total_stopped_dur.write(stopped_dur + dur);
             } else {
                 debug_assert_eq!(result, PauseResult::Paused);
-                let total_paused_dur = self.total_stopped_dur.read();
+                let total_paused_dur = self.total_paused_dur.read();
                 self.total_paused_dur.write(total_paused_dur + dur);
             }
             paused += dur;
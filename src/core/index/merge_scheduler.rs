@@ -21,15 +21,11 @@ use error::{Error, ErrorKind, Result};
 
 use num_cpus;
 
-use std::cmp::Ordering;
 use std::f64;
-use std::sync::{Arc, Condvar, Mutex, MutexGuard, Weak};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, TryLockError};
 use std::thread::{self, ThreadId};
 use std::time::{Duration, SystemTime};
 
-// use std::cmp::Ordering;
-// use std::thread::{self, ThreadId};
-
 /// Expert: `IndexWriter` uses an instance
 /// implementing this interface to execute the merges
 /// selected by a `MergePolicy`.  The default
@@ -52,8 +48,24 @@ pub trait MergeScheduler: Send + Sync + Clone + 'static {
     fn close(&self) -> Result<()>;
 }
 
-#[derive(Copy, Clone)]
-pub struct SerialMergeScheduler;
+/// A `MergeScheduler` that runs the merges one at a time, in the thread
+/// calling it.
+///
+/// Only one thread runs merges at a time: a thread calling `merge` while
+/// another one is merging returns at once, the merging thread picks up the
+/// merges registered meanwhile. So flushes, NRT refreshes and commits never
+/// wait for a running merge, they only run the merges when no one else does.
+#[derive(Default)]
+pub struct SerialMergeScheduler {
+    merging: Arc<Mutex<()>>,
+}
+
+impl Clone for SerialMergeScheduler {
+    fn clone(&self) -> Self {
+        // each writer gets its own merging thread
+        SerialMergeScheduler::default()
+    }
+}
 
 impl MergeScheduler for SerialMergeScheduler {
     fn merge<D, C, MP>(
@@ -68,9 +80,19 @@ impl MergeScheduler for SerialMergeScheduler {
         MP: MergePolicy,
     {
         loop {
-            if let Some(ref mut merge) = writer.next_merge() {
-                writer.merge(merge)?;
-            } else {
+            {
+                let _guard = match self.merging.try_lock() {
+                    Ok(guard) => guard,
+                    Err(TryLockError::WouldBlock) => return Ok(()),
+                    Err(TryLockError::Poisoned(e)) => e.into_inner(),
+                };
+                while let Some(ref mut merge) = writer.next_merge() {
+                    writer.merge(merge)?;
+                }
+            }
+            // a merge registered after the last `next_merge` but before the guard
+            // was released was left by the threads failing to get the guard
+            if !writer.has_pending_merges() {
                 break;
             }
         }
@@ -82,61 +104,36 @@ impl MergeScheduler for SerialMergeScheduler {
     }
 }
 
-struct ThreadSentinel;
+/// A merge run by the merge threads of a `ConcurrentMergeScheduler`.
+trait MergeJob: Send {
+    fn schedule_info(&self) -> OneMergeScheduleInfo;
+
+    fn run(self: Box<Self>);
+}
 
 struct MergeTaskInfo {
     merge: OneMergeScheduleInfo,
     thread_id: ThreadId,
-    live_sentinel: Weak<ThreadSentinel>,
-}
-
-impl MergeTaskInfo {
-    fn thread_alive(&self) -> bool {
-        self.live_sentinel.upgrade().is_some()
-    }
-}
-
-impl Eq for MergeTaskInfo {}
-
-impl PartialEq for MergeTaskInfo {
-    fn eq(&self, other: &Self) -> bool {
-        self.merge.id == other.merge.id
-    }
-}
-
-impl PartialOrd for MergeTaskInfo {
-    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        // reverse order
-        other
-            .merge
-            .estimated_merge_bytes
-            .read()
-            .partial_cmp(&self.merge.estimated_merge_bytes.read())
-    }
-}
-
-impl Ord for MergeTaskInfo {
-    fn cmp(&self, other: &Self) -> Ordering {
-        // reverse order
-        other
-            .merge
-            .estimated_merge_bytes
-            .read()
-            .cmp(&self.merge.estimated_merge_bytes.read())
-    }
 }
 
-/// A `MergeScheduler` that runs each merge using a separate thread.
+/// A `MergeScheduler` that runs the merges in background threads.
 ///
-/// Specify the max number of threads that may run at once, and the maximum number
-/// of simultaneous merges with *max_merge_thread*
+/// The merges are queued and run by at most *max_thread_count* merge threads,
+/// the smallest queued merge first. The merge threads exit once the queue is
+/// empty.
 ///
-/// If the number of merges exceeds the max number of threads then the largest merges
-/// are paused until one of the smaller merges completes.
+/// If more than *max_merge_count* merges are queued or running, or if they
+/// would write more than *max_pending_merge_mb*, the threads producing new
+/// segments are stalled until merging catches up.
 ///
-/// If more than *max_merge_count* merges are requested then this class will forcefully
-/// throttle the incoming threads by pausing until one more more merges complete.
-#[derive(Clone)]
+/// Closing the scheduler cancels the queued merges and waits for the running
+/// ones.
+///
+/// A clone gets its own queue and merge threads, with a copy of the settings
+/// at the time of the clone: changing the settings of one doesn't change the
+/// other. As every `IndexWriter` clones the scheduler of its config, tune the
+/// scheduler before creating the writer, or through
+/// `IndexWriter::merge_scheduler` afterwards.
 pub struct ConcurrentMergeScheduler {
     inner: Arc<ConcurrentMergeSchedulerInner>,
 }
@@ -148,27 +145,162 @@ impl Default for ConcurrentMergeScheduler {
     }
 }
 
+impl Clone for ConcurrentMergeScheduler {
+    fn clone(&self) -> Self {
+        // each writer gets its own merge threads, and a copy of the settings
+        let state = self.inner.state.lock().unwrap();
+        let mut settings = SchedulerState::new(state.max_thread_count);
+        settings.max_merge_count = state.max_merge_count;
+        settings.max_pending_merge_bytes = state.max_pending_merge_bytes;
+        settings.do_auto_io_throttle = state.do_auto_io_throttle;
+        settings.force_merge_mb_per_sec = state.force_merge_mb_per_sec;
+        Self {
+            inner: Arc::new(ConcurrentMergeSchedulerInner::new(settings)),
+        }
+    }
+}
+
 impl ConcurrentMergeScheduler {
     pub fn new(max_thread_count: usize) -> Self {
         if max_thread_count == 0 {
             panic!("max thread count must not be 0");
         }
         Self {
-            inner: Arc::new(ConcurrentMergeSchedulerInner::new(max_thread_count)),
+            inner: Arc::new(ConcurrentMergeSchedulerInner::new(SchedulerState::new(
+                max_thread_count,
+            ))),
+        }
+    }
+
+    /// Sets the max number of queued and running merges before the threads
+    /// producing segments are stalled, and the max number of merge threads.
+    pub fn set_max_merges_and_threads(
+        &self,
+        max_merge_count: usize,
+        max_thread_count: usize,
+    ) -> Result<()> {
+        if max_thread_count == 0 {
+            bail!(ErrorKind::IllegalArgument(
+                "max_thread_count should be at least 1".into()
+            ));
+        }
+        if max_merge_count < max_thread_count {
+            bail!(ErrorKind::IllegalArgument(format!(
+                "max_merge_count should be at least max_thread_count {}, got {}",
+                max_thread_count, max_merge_count
+            )));
+        }
+        let mut state = self.inner.state.lock().unwrap();
+        state.max_merge_count = max_merge_count;
+        state.max_thread_count = max_thread_count;
+        self.inner.cond.notify_all();
+        Ok(())
+    }
+
+    /// Sets the max number of MB the queued and running merges may write
+    /// before the threads producing segments are stalled.
+    pub fn set_max_pending_merge_mb(&self, mb: f64) -> Result<()> {
+        if mb <= 0.0 {
+            bail!(ErrorKind::IllegalArgument(format!(
+                "max_pending_merge_mb must be > 0.0, got {}",
+                mb
+            )));
+        }
+        self.inner.state.lock().unwrap().max_pending_merge_bytes = mb_to_bytes(mb);
+        self.inner.cond.notify_all();
+        Ok(())
+    }
+
+    /// Turns on or off the dynamic IO throttling of the big merges.
+    pub fn set_auto_io_throttle(&self, enabled: bool) {
+        let mut state = self.inner.state.lock().unwrap();
+        state.do_auto_io_throttle = enabled;
+        state.update_merge_rates();
+    }
+
+    /// Sets the IO rate limit of the forced merges.
+    pub fn set_force_merge_mb_per_sec(&self, mb_per_sec: f64) -> Result<()> {
+        if mb_per_sec <= 0.0 {
+            bail!(ErrorKind::IllegalArgument(format!(
+                "force_merge_mb_per_sec must be > 0.0, got {}",
+                mb_per_sec
+            )));
+        }
+        let mut state = self.inner.state.lock().unwrap();
+        state.force_merge_mb_per_sec = mb_per_sec;
+        state.update_merge_rates();
+        Ok(())
+    }
+
+    pub fn max_thread_count(&self) -> usize {
+        self.inner.state.lock().unwrap().max_thread_count
+    }
+
+    pub fn max_merge_count(&self) -> usize {
+        self.inner.state.lock().unwrap().max_merge_count
+    }
+
+    // Queues the merge returned by `next_merge`, if any, once merging has
+    // caught up. Returns false if there was no merge to queue.
+    fn add_merge<F>(&self, next_merge: F) -> bool
+    where
+        F: FnOnce() -> Option<Box<dyn MergeJob>>,
+    {
+        let state = self.inner.state.lock().unwrap();
+        let mut state = self.inner.maybe_stall(state);
+        if let Some(job) = next_merge() {
+            if state.closed {
+                job.schedule_info().rate_limiter.set_abort();
+            } else {
+                state.update_io_throttle(&job.schedule_info());
+            }
+            state.queue.push(job);
+            if state.merge_thread_count < state.max_thread_count {
+                self.spawn_merge_thread(&mut state);
+            }
+            true
+        } else {
+            false
         }
     }
+
+    fn spawn_merge_thread(&self, state: &mut SchedulerState) {
+        let inner = Arc::clone(&self.inner);
+        thread::Builder::new()
+            .name(format!(
+                "Rucene Merge Thread #{}",
+                state.spawned_thread_count
+            ))
+            .spawn(move || inner.run_merges())
+            .expect("failed to spawn thread");
+        state.spawned_thread_count += 1;
+        state.merge_thread_count += 1;
+    }
 }
 
 struct ConcurrentMergeSchedulerInner {
-    lock: Mutex<()>,
+    state: Mutex<SchedulerState>,
+    // notified when a merge is done or the limits change, for the stalled
+    // threads and the ones waiting for the merges
     cond: Condvar,
+}
+
+// The merges and the settings of a `ConcurrentMergeScheduler`, all read and
+// written under the lock of its inner.
+struct SchedulerState {
+    // merges waiting for a merge thread
+    queue: Vec<Box<dyn MergeJob>>,
+    // merges being run by the merge threads
     merge_tasks: Vec<MergeTaskInfo>,
     max_merge_count: usize,
     max_thread_count: usize,
+    max_pending_merge_bytes: u64,
     merge_thread_count: usize,
+    spawned_thread_count: usize,
     target_mb_per_sec: f64,
     do_auto_io_throttle: bool,
     force_merge_mb_per_sec: f64,
+    closed: bool,
 }
 
 // Floor for IO write rate limit (we will never go any lower than this)
@@ -178,129 +310,90 @@ const MIN_MERGE_MB_PER_SEC: f64 = 5.0;
 const MAX_MERGE_MB_PER_SEC: f64 = 10240.0;
 
 // Initial value for IO write rate limit when do_auto_io_throttle is true
-#[allow(dead_code)]
 const START_MB_PER_SEC: f64 = 20.0;
 
-// Merges below this size are not counted in the maxThreadCount, i.e. they can freely run in
-// their own thread (up until maxMergeCount).
+// Merges below this size are not IO throttled.
 const MIN_BIG_MERGE_MB: f64 = 50.0;
 
-impl ConcurrentMergeSchedulerInner {
+// Default max size the queued and running merges may write before the
+// threads producing segments are stalled.
+const DEFAULT_MAX_PENDING_MERGE_MB: f64 = 10240.0;
+
+impl SchedulerState {
     fn new(max_thread_count: usize) -> Self {
-        ConcurrentMergeSchedulerInner {
-            lock: Mutex::new(()),
-            cond: Condvar::new(),
+        SchedulerState {
+            queue: vec![],
             merge_tasks: vec![],
             max_merge_count: max_thread_count + 5,
             max_thread_count,
+            max_pending_merge_bytes: mb_to_bytes(DEFAULT_MAX_PENDING_MERGE_MB),
             merge_thread_count: 0,
+            spawned_thread_count: 0,
             target_mb_per_sec: START_MB_PER_SEC,
             do_auto_io_throttle: true,
             force_merge_mb_per_sec: f64::INFINITY,
+            closed: false,
         }
     }
 
-    unsafe fn scheduler_mut(&self, _guard: &MutexGuard<()>) -> &mut ConcurrentMergeSchedulerInner {
-        let scheduler =
-            self as *const ConcurrentMergeSchedulerInner as *mut ConcurrentMergeSchedulerInner;
-        &mut *scheduler
-    }
-
-    fn maybe_stall<'a, D, C, MP>(
-        &self,
-        writer: &IndexWriter<D, C, ConcurrentMergeScheduler, MP>,
-        guard: MutexGuard<'a, ()>,
-    ) -> (bool, MutexGuard<'a, ()>)
-    where
-        D: Directory + Send + Sync + 'static,
-        C: Codec,
-        MP: MergePolicy,
-    {
+    fn is_merge_thread(&self) -> bool {
         let thread_id = thread::current().id();
-        let mut guard = guard;
-        while writer.has_pending_merges() && self.merge_thread_count() >= self.max_merge_count {
-            // This means merging has fallen too far behind: we
-            // have already created maxMergeCount threads, and
-            // now there's at least one more merge pending.
-            // Note that only maxThreadCount of
-            // those created merge threads will actually be
-            // running; the rest will be paused (see
-            // updateMergeThreads).  We stall this producer
-            // thread to prevent creation of new segments,
-            // until merging has caught up:
-            if self.merge_tasks.iter().any(|t| t.thread_id == thread_id) {
-                // Never stall a merge thread since this blocks the thread from
-                // finishing and calling updateMergeThreads, and blocking it
-                // accomplishes nothing anyway (it's not really a segment producer):
-                return (false, guard);
-            }
+        self.merge_tasks.iter().any(|t| t.thread_id == thread_id)
+    }
 
-            // Defensively wait for only .25 seconds in case we are missing a .notify/All somewhere:
-            let (g, _) = self
-                .cond
-                .wait_timeout(guard, Duration::from_millis(25))
-                .unwrap();
-            guard = g;
-        }
-        (true, guard)
+    fn pending_merge_bytes(&self) -> u64 {
+        let queued: u64 = self
+            .queue
+            .iter()
+            .map(|j| j.schedule_info().estimated_merge_bytes.read())
+            .sum();
+        let running: u64 = self
+            .merge_tasks
+            .iter()
+            .map(|t| t.merge.estimated_merge_bytes.read())
+            .sum();
+        queued + running
     }
 
-    fn update_merge_threads(&mut self) {
-        let mut active_tasks: Vec<_> = self.merge_tasks.iter().collect();
-        active_tasks.sort();
+    fn falling_behind(&self) -> bool {
+        self.queue.len() + self.merge_tasks.len() >= self.max_merge_count
+            || self.pending_merge_bytes() > self.max_pending_merge_bytes
+    }
 
-        let tasks_count = active_tasks.len();
+    fn next_job(&mut self) -> Option<Box<dyn MergeJob>> {
+        let idx = (0..self.queue.len())
+            .min_by_key(|&i| self.queue[i].schedule_info().estimated_merge_bytes.read())?;
+        Some(self.queue.remove(idx))
+    }
 
-        let mut big_merge_count = 0;
-        for i in 0..tasks_count {
-            if active_tasks[tasks_count - 1 - i]
-                .merge
-                .estimated_merge_bytes
-                .read() as f64
-                > MIN_BIG_MERGE_MB * 1024.0 * 1024.0
-            {
-                big_merge_count = tasks_count - i;
-                break;
-            }
+    fn merge_mb_per_sec(&self, merge: &OneMergeScheduleInfo) -> f64 {
+        if merge.max_num_segments.get().is_some() {
+            self.force_merge_mb_per_sec
+        } else if !self.do_auto_io_throttle
+            || bytes_to_mb(merge.estimated_merge_bytes.read()) < MIN_BIG_MERGE_MB
+        {
+            // Don't rate limit small merges:
+            f64::INFINITY
+        } else {
+            self.target_mb_per_sec
         }
+    }
 
-        for (idx, task) in active_tasks.iter().enumerate() {
-            // pause the thread if max_thread_count is smaller than the number of merge threads.
-            let do_pause = idx + self.max_thread_count < big_merge_count;
-
-            let new_mb_per_sec = if do_pause {
-                0.0
-            } else if task.merge.max_num_segments.get().is_some() {
-                self.force_merge_mb_per_sec
-            } else if !self.do_auto_io_throttle {
-                f64::INFINITY
-            } else if (task.merge.estimated_merge_bytes.read() as f64)
-                < MIN_BIG_MERGE_MB * 1024.0 * 1024.0
-            {
-                // Don't rate limit small merges:
-                f64::INFINITY
-            } else {
-                self.target_mb_per_sec
-            };
-
-            task.merge.rate_limiter.set_mb_per_sec(new_mb_per_sec);
+    // the rates of the scheduler are never negative, so setting them can't fail
+    fn set_merge_rate(&self, merge: &OneMergeScheduleInfo) {
+        let mb_per_sec = self.merge_mb_per_sec(merge);
+        if let Err(e) = merge.rate_limiter.set_mb_per_sec(mb_per_sec) {
+            error!("failed to set the merge rate to {}: {:?}", mb_per_sec, e);
         }
     }
 
-    fn merge_thread_count(&self) -> usize {
-        let current_thread = thread::current().id();
-        self.merge_tasks
-            .iter()
-            .filter(|t| {
-                t.thread_id != current_thread && t.thread_alive() && !t.merge.rate_limiter.aborted()
-            })
-            .count()
+    fn update_merge_rates(&self) {
+        for task in &self.merge_tasks {
+            self.set_merge_rate(&task.merge);
+        }
     }
 
-    fn update_io_throttle<D: Directory + Send + Sync + 'static, C: Codec>(
-        &mut self,
-        new_merge: &OneMerge<D, C>,
-    ) {
+    fn update_io_throttle(&mut self, new_merge: &OneMergeScheduleInfo) {
         if !self.do_auto_io_throttle {
             return;
         }
@@ -321,9 +414,8 @@ impl ConcurrentMergeSchedulerInner {
 
         let mut cur_back_log = false;
         if !new_back_log {
-            if self.merge_tasks.len() > self.max_thread_count {
-                // If there are already more than the maximum merge threads allowed, count that as
-                // backlog:
+            if !self.queue.is_empty() {
+                // If merges are already waiting for a merge thread, count that as backlog:
                 cur_back_log = true;
             } else {
                 // Now see if any still-running merges are backlog'd:
@@ -347,12 +439,8 @@ impl ConcurrentMergeSchedulerInner {
             self.target_mb_per_sec = MIN_MERGE_MB_PER_SEC.max(self.target_mb_per_sec / 1.1);
         }
 
-        let rate = if new_merge.max_num_segments.get().is_some() {
-            self.force_merge_mb_per_sec
-        } else {
-            self.target_mb_per_sec
-        };
-        new_merge.rate_limiter.set_mb_per_sec(rate);
+        self.set_merge_rate(new_merge);
+        self.update_merge_rates();
     }
 
     fn is_back_log(&self, now: SystemTime, merge_id: u32, estimated_merge_bytes: u64) -> bool {
@@ -379,6 +467,97 @@ impl ConcurrentMergeSchedulerInner {
     }
 }
 
+impl ConcurrentMergeSchedulerInner {
+    fn new(state: SchedulerState) -> Self {
+        ConcurrentMergeSchedulerInner {
+            state: Mutex::new(state),
+            cond: Condvar::new(),
+        }
+    }
+
+    fn maybe_stall<'a>(
+        &self,
+        state: MutexGuard<'a, SchedulerState>,
+    ) -> MutexGuard<'a, SchedulerState> {
+        let mut state = state;
+        while !state.closed && state.falling_behind() {
+            // This means merging has fallen too far behind: we stall this
+            // producer thread to prevent creation of new segments, until
+            // merging has caught up.
+            if state.is_merge_thread() {
+                // Never stall a merge thread since this blocks the thread from
+                // finishing its merge, and blocking it accomplishes nothing anyway
+                // (it's not really a segment producer):
+                break;
+            }
+
+            // Defensively wait for only .25 seconds in case we are missing a .notify/All somewhere:
+            let (s, _) = self
+                .cond
+                .wait_timeout(state, Duration::from_millis(250))
+                .unwrap();
+            state = s;
+        }
+        state
+    }
+
+    // Body of the merge threads: runs the queued merges, the smallest first,
+    // until the queue is empty.
+    fn run_merges(&self) {
+        let thread_id = thread::current().id();
+        loop {
+            let job = {
+                let mut state = self.state.lock().unwrap();
+                match state.next_job() {
+                    Some(job) => {
+                        state.merge_tasks.push(MergeTaskInfo {
+                            merge: job.schedule_info(),
+                            thread_id,
+                        });
+                        state.update_merge_rates();
+                        job
+                    }
+                    None => {
+                        state.merge_thread_count -= 1;
+                        self.cond.notify_all();
+                        return;
+                    }
+                }
+            };
+
+            job.run();
+
+            let mut state = self.state.lock().unwrap();
+            state.merge_tasks.retain(|t| t.thread_id != thread_id);
+            // In case we had stalled indexing, we can now wake up
+            // and possibly unstall:
+            self.cond.notify_all();
+        }
+    }
+
+    // Waits for the queued merges and the ones run by other threads.
+    fn wait_for_merges<'a>(
+        &self,
+        state: MutexGuard<'a, SchedulerState>,
+    ) -> MutexGuard<'a, SchedulerState> {
+        let thread_id = thread::current().id();
+        // a merge thread closing its writer can't wait for the queue, it
+        // might be the one merge thread left
+        let merge_thread = state.is_merge_thread();
+        let mut state = state;
+        while (!merge_thread && !state.queue.is_empty())
+            || state.merge_tasks.iter().any(|t| t.thread_id != thread_id)
+        {
+            let (s, _) = self
+                .cond
+                .wait_timeout(state, Duration::from_millis(250))
+                .unwrap();
+            state = s;
+        }
+        state
+    }
+}
+
 impl MergeScheduler for ConcurrentMergeScheduler {
     fn merge<D, C, MP>(
         &self,
@@ -391,69 +570,43 @@ impl MergeScheduler for ConcurrentMergeScheduler {
         C: Codec,
         MP: MergePolicy,
     {
-        let mut guard = self.inner.lock.lock().unwrap();
-        let scheduler = unsafe { self.inner.scheduler_mut(&guard) };
-
         if trigger == MergerTrigger::Closing {
+            let mut state = self.inner.state.lock().unwrap();
             // Disable throttling on close:
-            scheduler.target_mb_per_sec = MAX_MERGE_MB_PER_SEC;
-            scheduler.update_merge_threads();
+            state.target_mb_per_sec = MAX_MERGE_MB_PER_SEC;
+            state.update_merge_rates();
         }
 
-        // First, quickly run through the newly proposed merges
-        // and add any orthogonal merges (ie a merge not
-        // involving segments already pending to be merged) to
-        // the queue.  If we are way behind on merging, many of
-        // these newly proposed merges will likely already be
-        // registered.
-
-        loop {
-            let (valid, g) = scheduler.maybe_stall(writer, guard);
-            guard = g;
-            if !valid {
+        // Queue the newly proposed merges. If we are way behind on merging,
+        // the calling thread is stalled until the merge threads catch up.
+        while writer.has_pending_merges() {
+            let queued = self.add_merge(|| {
+                writer.next_merge().map(|merge| {
+                    Box::new(WriterMergeJob {
+                        index_writer: writer.clone(),
+                        merge_scheduler: Self {
+                            inner: Arc::clone(&self.inner),
+                        },
+                        merge,
+                    }) as Box<dyn MergeJob>
+                })
+            });
+            if !queued {
                 break;
             }
-
-            if let Some(merge) = writer.next_merge() {
-                scheduler.update_io_throttle(&merge);
-
-                let sentinel = Arc::new(ThreadSentinel);
-                let live_sentinel = Arc::downgrade(&sentinel);
-                let merge_thread = MergeThread {
-                    index_writer: writer.clone(),
-                    merge_scheduler: self.clone(),
-                    _live_sentinel: sentinel,
-                };
-                let merge_info = merge.schedule_info();
-                let handler = thread::Builder::new()
-                    .name(format!(
-                        "Rucene Merge Thread #{}",
-                        scheduler.merge_thread_count
-                    ))
-                    .spawn(move || {
-                        merge_thread.merge(merge);
-                    })
-                    .expect("failed to spawn thread");
-                scheduler.merge_thread_count += 1;
-
-                let merge_task = MergeTaskInfo {
-                    merge: merge_info,
-                    thread_id: handler.thread().id(),
-                    live_sentinel,
-                };
-                scheduler.merge_tasks.push(merge_task);
-                scheduler.update_merge_threads();
-            } else {
-                return Ok(());
-            }
         }
         Ok(())
     }
 
     fn close(&self) -> Result<()> {
-        // do nothing here
-        // this is safe because each MergeThread contain a IndexWriter make sure that
-        // IndexWrite live long enough before all the threads finish running
+        let mut state = self.inner.state.lock().unwrap();
+        state.closed = true;
+        // the queued merges are cancelled: the merge threads only hand them
+        // back to their writer
+        for job in &state.queue {
+            job.schedule_info().rate_limiter.set_abort();
+        }
+        let _state = self.inner.wait_for_merges(state);
         Ok(())
     }
 }
@@ -462,40 +615,49 @@ fn bytes_to_mb(bytes: u64) -> f64 {
     bytes as f64 / 1024.0 / 1024.0
 }
 
-struct MergeThread<D: Directory + Send + Sync + 'static, C: Codec, MP: MergePolicy> {
+fn mb_to_bytes(mb: f64) -> u64 {
+    (mb * 1024.0 * 1024.0) as u64
+}
+
+struct WriterMergeJob<D: Directory + Send + Sync + 'static, C: Codec, MP: MergePolicy> {
     index_writer: IndexWriter<D, C, ConcurrentMergeScheduler, MP>,
-    // a sentinel object used by MergeTaskInfo to determine whether target thread is alive
-    _live_sentinel: Arc<ThreadSentinel>,
     merge_scheduler: ConcurrentMergeScheduler,
+    merge: OneMerge<D, C>,
 }
 
-impl<D: Directory + Send + Sync + 'static, C: Codec, MP: MergePolicy> MergeThread<D, C, MP> {
-    fn merge(&self, mut one_merge: OneMerge<D, C>) {
-        match self.do_merge(&mut one_merge) {
+impl<D, C, MP> MergeJob for WriterMergeJob<D, C, MP>
+where
+    D: Directory + Send + Sync + 'static,
+    C: Codec,
+    MP: MergePolicy,
+{
+    fn schedule_info(&self) -> OneMergeScheduleInfo {
+        self.merge.schedule_info()
+    }
+
+    fn run(self: Box<Self>) {
+        let mut job = *self;
+        match job.do_merge() {
             Err(Error(ErrorKind::Index(index::ErrorKind::MergeAborted(_)), _)) => {
                 // OK to ignore
             }
             Err(e) => {
-                error!(
-                    "merge {:?} failed: {:?}, thread will abort immediately",
-                    one_merge.segments, e
-                );
+                // the writer already recorded the error as its tragedy
+                error!("merge {:?} failed: {:?}", job.merge.segments, e);
             }
             Ok(()) => {}
         }
-        let l = self.merge_scheduler.inner.lock.lock().unwrap();
-        let scheduler_mut = unsafe { self.merge_scheduler.inner.scheduler_mut(&l) };
-        scheduler_mut
-            .merge_tasks
-            .drain_filter(|t| t.merge.id == one_merge.id);
-        scheduler_mut.update_merge_threads();
-        // In case we had stalled indexing, we can now wake up
-        // and possibly unstall:
-        scheduler_mut.cond.notify_all();
     }
+}
 
-    fn do_merge(&self, merge: &mut OneMerge<D, C>) -> Result<()> {
-        self.index_writer.merge(merge)?;
+impl<D, C, MP> WriterMergeJob<D, C, MP>
+where
+    D: Directory + Send + Sync + 'static,
+    C: Codec,
+    MP: MergePolicy,
+{
+    fn do_merge(&mut self) -> Result<()> {
+        self.index_writer.merge(&mut self.merge)?;
 
         // Let CMS run new merges if necessary
         match self
@@ -508,3 +670,249 @@ impl<D: Directory + Send + Sync + 'static, C: Codec, MP: MergePolicy> MergeThrea
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::codec::{CodecEnum, Lucene62Codec};
+    use core::doc::{Field, FieldType};
+    use core::index::index_writer_config::IndexWriterConfig;
+    use core::index::merge_policy::TieredMergePolicy;
+    use core::index::merge_rate_limiter::MergeRateLimiter;
+    use core::index::{IndexOptions, IndexReader, StandardDirectoryReader};
+    use core::store::RAMDirectory;
+    use core::util::{VariantValue, Volatile};
+
+    use std::cell::Cell;
+    use std::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
+    use std::time::Instant;
+
+    type TestReader = StandardDirectoryReader<
+        RAMDirectory,
+        CodecEnum,
+        ConcurrentMergeScheduler,
+        TieredMergePolicy,
+    >;
+
+    #[derive(Default)]
+    struct Counters {
+        running: AtomicUsize,
+        max_running: AtomicUsize,
+        done: AtomicUsize,
+        cancelled: AtomicUsize,
+    }
+
+    // sleeps instead of merging
+    struct SlowMerge {
+        info: OneMergeScheduleInfo,
+        duration: Duration,
+        counters: Arc<Counters>,
+    }
+
+    impl MergeJob for SlowMerge {
+        fn schedule_info(&self) -> OneMergeScheduleInfo {
+            OneMergeScheduleInfo {
+                id: self.info.id,
+                max_num_segments: Arc::clone(&self.info.max_num_segments),
+                estimated_merge_bytes: Arc::clone(&self.info.estimated_merge_bytes),
+                rate_limiter: Arc::clone(&self.info.rate_limiter),
+                merge_start_time: Arc::clone(&self.info.merge_start_time),
+            }
+        }
+
+        fn run(self: Box<Self>) {
+            if self.info.rate_limiter.aborted() {
+                self.counters.cancelled.fetch_add(1, AtomicOrdering::SeqCst);
+                return;
+            }
+            let running = self.counters.running.fetch_add(1, AtomicOrdering::SeqCst) + 1;
+            let mut max_running = self.counters.max_running.load(AtomicOrdering::SeqCst);
+            while running > max_running {
+                match self.counters.max_running.compare_exchange(
+                    max_running,
+                    running,
+                    AtomicOrdering::SeqCst,
+                    AtomicOrdering::SeqCst,
+                ) {
+                    Ok(_) => break,
+                    Err(current) => max_running = current,
+                }
+            }
+            thread::sleep(self.duration);
+            self.counters.running.fetch_sub(1, AtomicOrdering::SeqCst);
+            self.counters.done.fetch_add(1, AtomicOrdering::SeqCst);
+        }
+    }
+
+    fn add_slow_merge(
+        scheduler: &ConcurrentMergeScheduler,
+        id: u32,
+        estimated_merge_bytes: u64,
+        millis: u64,
+        counters: &Arc<Counters>,
+    ) {
+        let merge = SlowMerge {
+            info: OneMergeScheduleInfo {
+                id,
+                max_num_segments: Arc::new(Cell::new(None)),
+                estimated_merge_bytes: Arc::new(Volatile::new(estimated_merge_bytes)),
+                rate_limiter: Arc::new(MergeRateLimiter::new()),
+                merge_start_time: Arc::new(Volatile::new(None)),
+            },
+            duration: Duration::from_millis(millis),
+            counters: Arc::clone(counters),
+        };
+        assert!(scheduler.add_merge(|| Some(Box::new(merge) as Box<dyn MergeJob>)));
+    }
+
+    fn pending_merge_count(scheduler: &ConcurrentMergeScheduler) -> usize {
+        let state = scheduler.inner.state.lock().unwrap();
+        state.queue.len() + state.merge_tasks.len()
+    }
+
+    fn wait_for_merges(scheduler: &ConcurrentMergeScheduler) {
+        let state = scheduler.inner.state.lock().unwrap();
+        let _state = scheduler.inner.wait_for_merges(state);
+    }
+
+    #[test]
+    fn test_merge_thread_limit() {
+        let scheduler = ConcurrentMergeScheduler::new(2);
+        scheduler.set_max_merges_and_threads(20, 2).unwrap();
+        let counters = Arc::new(Counters::default());
+        for id in 0..8 {
+            add_slow_merge(&scheduler, id, 1024, 30, &counters);
+        }
+        wait_for_merges(&scheduler);
+
+        assert_eq!(counters.done.load(AtomicOrdering::SeqCst), 8);
+        assert!(counters.max_running.load(AtomicOrdering::SeqCst) <= 2);
+        assert_eq!(pending_merge_count(&scheduler), 0);
+        // the merge threads exit once the queue is empty
+        let start = Instant::now();
+        while scheduler.inner.state.lock().unwrap().merge_thread_count > 0 {
+            assert!(start.elapsed() < Duration::from_secs(10));
+            thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    #[test]
+    fn test_stall_on_merge_count() {
+        let scheduler = ConcurrentMergeScheduler::new(1);
+        scheduler.set_max_merges_and_threads(2, 1).unwrap();
+        let counters = Arc::new(Counters::default());
+
+        let start = Instant::now();
+        for id in 0..4 {
+            add_slow_merge(&scheduler, id, 1024, 100, &counters);
+            assert!(pending_merge_count(&scheduler) <= 2);
+        }
+        // the third merge waited for the first one
+        assert!(start.elapsed() >= Duration::from_millis(100));
+        assert!(counters.done.load(AtomicOrdering::SeqCst) >= 1);
+
+        scheduler.close().unwrap();
+        assert_eq!(counters.running.load(AtomicOrdering::SeqCst), 0);
+    }
+
+    #[test]
+    fn test_stall_on_pending_merge_bytes() {
+        let scheduler = ConcurrentMergeScheduler::new(1);
+        scheduler.set_max_merges_and_threads(10, 1).unwrap();
+        scheduler.set_max_pending_merge_mb(1.0).unwrap();
+        assert!(scheduler.set_max_pending_merge_mb(0.0).is_err());
+        let counters = Arc::new(Counters::default());
+
+        let start = Instant::now();
+        for id in 0..3 {
+            add_slow_merge(&scheduler, id, 1024 * 1024, 50, &counters);
+        }
+        // 2MB were pending when the third merge was added
+        assert!(start.elapsed() >= Duration::from_millis(50));
+
+        wait_for_merges(&scheduler);
+        assert_eq!(counters.done.load(AtomicOrdering::SeqCst), 3);
+    }
+
+    #[test]
+    fn test_close_cancels_queued_merges() {
+        let scheduler = ConcurrentMergeScheduler::new(1);
+        let counters = Arc::new(Counters::default());
+        for id in 0..5 {
+            add_slow_merge(&scheduler, id, 1024, 50, &counters);
+        }
+        scheduler.close().unwrap();
+
+        assert_eq!(counters.running.load(AtomicOrdering::SeqCst), 0);
+        assert_eq!(pending_merge_count(&scheduler), 0);
+        let done = counters.done.load(AtomicOrdering::SeqCst);
+        assert!(done <= 1);
+        assert_eq!(done + counters.cancelled.load(AtomicOrdering::SeqCst), 5);
+
+        // merges scheduled once closed are cancelled too
+        add_slow_merge(&scheduler, 5, 1024, 50, &counters);
+        scheduler.close().unwrap();
+        assert_eq!(counters.done.load(AtomicOrdering::SeqCst), done);
+        assert_eq!(counters.cancelled.load(AtomicOrdering::SeqCst), 6 - done);
+    }
+
+    #[test]
+    fn test_invalid_limits() {
+        let scheduler = ConcurrentMergeScheduler::new(2);
+        assert!(scheduler.set_max_merges_and_threads(4, 0).is_err());
+        assert!(scheduler.set_max_merges_and_threads(1, 2).is_err());
+        assert!(scheduler.set_force_merge_mb_per_sec(0.0).is_err());
+        scheduler.set_max_merges_and_threads(6, 3).unwrap();
+        assert_eq!(scheduler.max_merge_count(), 6);
+        assert_eq!(scheduler.max_thread_count(), 3);
+
+        // the clone copies the settings, then goes its own way
+        let cloned = scheduler.clone();
+        assert_eq!(cloned.max_merge_count(), 6);
+        assert!(!Arc::ptr_eq(&cloned.inner, &scheduler.inner));
+        scheduler.set_max_merges_and_threads(8, 4).unwrap();
+        assert_eq!(cloned.max_merge_count(), 6);
+    }
+
+    #[test]
+    fn test_index_with_concurrent_merges() {
+        let dir = Arc::new(RAMDirectory::new());
+        let scheduler = ConcurrentMergeScheduler::new(2);
+        let mut policy = TieredMergePolicy::default();
+        policy.set_max_merge_at_once(2).unwrap();
+        let mut conf = IndexWriterConfig::new(
+            Arc::new(CodecEnum::Lucene62(Lucene62Codec::default())),
+            scheduler,
+            policy,
+        );
+        conf.max_buffered_docs = Some(10);
+        let conf = Arc::new(conf);
+        let writer = IndexWriter::new(Arc::clone(&dir), Arc::clone(&conf)).unwrap();
+        // the writer runs the merges with its own clone of the scheduler
+        writer
+            .merge_scheduler()
+            .set_max_merges_and_threads(4, 2)
+            .unwrap();
+        assert_eq!(writer.merge_scheduler().max_merge_count(), 4);
+        assert_ne!(conf.merge_scheduler().max_merge_count(), 4);
+
+        let mut field_type = FieldType::default();
+        field_type.index_options = IndexOptions::Docs;
+        for i in 0..500 {
+            let doc = vec![Field::new(
+                "id".to_string(),
+                field_type.clone(),
+                Some(VariantValue::VString(i.to_string())),
+                None,
+            )];
+            writer.add_document(doc).unwrap();
+        }
+        writer.commit().unwrap();
+        writer.close().unwrap();
+
+        let reader = TestReader::open(dir).unwrap();
+        assert_eq!(reader.num_docs(), 500);
+        // 50 flushed segments got merged
+        assert!(reader.leaves().len() < 50);
+    }
+}
@@ -190,12 +190,8 @@ impl<D: Directory + 'static, C: Codec> MergeState<D, C> {
                     ReaderWrapperEnum::Segment(leaf)
                 }
             } else {
-                // TODO: fix IW to also sort when flushing?  It's somewhat tricky because of stored
-                // fields and term vectors, which write "live" to their index files
-                // on each indexed document:
-
-                // This segment was written by flush, so documents are not yet sorted, so we sort
-                // them now:
+                // This segment was written without the index sort (e.g. by a writer configured
+                // without it), so documents are not yet sorted, so we sort them now:
                 let sort_doc_map = sorter.sort_leaf_reader(&leaf.leaf_context())?;
                 if let Some(sort_doc_map) = sort_doc_map {
                     *needs_index_sort = true;
@@ -994,6 +990,12 @@ pub enum DocMapEnum {
     Sorted(DocMapBySortDocMap),
 }
 
+impl<M: DocMap + ?Sized> DocMap for Arc<M> {
+    fn get(&self, doc_id: DocId) -> Result<DocId> {
+        (**self).get(doc_id)
+    }
+}
+
 impl DocMap for DocMapEnum {
     fn get(&self, doc_id: i32) -> Result<DocId> {
         match self {
@@ -83,10 +83,30 @@ mod directory_reader;
 
 pub use self::directory_reader::*;
 
+mod multi_reader;
+
+pub use self::multi_reader::*;
+
+mod soft_deletes;
+
+pub use self::soft_deletes::*;
+
 mod segment;
 
 pub use self::segment::*;
 
+mod dedup;
+
+pub use self::dedup::*;
+
+mod check_index;
+
+pub use self::check_index::*;
+
+mod index_upgrader;
+
+pub use self::index_upgrader::*;
+
 mod point_values;
 
 pub use self::point_values::*;
@@ -104,6 +124,10 @@ mod term;
 pub use self::term::TermState;
 pub use self::term::*;
 
+mod term_stream;
+
+pub use self::term_stream::*;
+
 mod fieldable;
 
 pub use self::fieldable::*;
@@ -141,8 +165,10 @@ pub use self::doc_values_term_iterator::DocValuesTermIterator;
 pub mod doc_id_merger;
 
 mod bufferd_updates;
+mod buffered_doc_values;
+pub mod bulk_session;
 mod byte_slice_reader;
-mod delete_policy;
+pub mod delete_policy;
 mod doc_consumer;
 mod doc_values_term_iterator;
 mod doc_writer;
@@ -150,10 +176,11 @@ mod doc_writer_delete_queue;
 mod doc_writer_flush_queue;
 mod flush_control;
 mod flush_policy;
-mod index_commit;
+pub mod index_commit;
 mod index_file_deleter;
 pub mod index_writer_config;
 mod leaf_reader_wrapper;
+pub mod merge;
 pub mod merge_policy;
 mod merge_rate_limiter;
 pub mod merge_scheduler;
@@ -166,6 +193,8 @@ mod terms_hash;
 mod terms_hash_per_field;
 mod thread_doc_writer;
 
+pub use self::merge_rate_limiter::MergeIOStats;
+
 use serde::ser::SerializeStruct;
 use serde::{Serialize, Serializer};
 use std::cmp::Ordering;
@@ -174,7 +203,7 @@ use std::fmt;
 use std::hash::{Hash, Hasher};
 use std::result;
 use std::sync::atomic::{AtomicBool, AtomicI32, AtomicI64, Ordering as AtomicOrdering};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex, RwLock};
 
 use regex::Regex;
 
@@ -182,6 +211,7 @@ use core::codec::{Codec, CodecTVFields, FieldsProducer, LiveDocsFormat};
 use core::doc::Document;
 use core::index::bufferd_updates::BufferedUpdates;
 use core::search::sort::Sort;
+use core::search::statistics::CollectionStatistics;
 use core::store::{Directory, IOContext};
 use core::util::bit_set::FixedBitSet;
 use core::util::string_util::ID_LENGTH;
@@ -202,6 +232,11 @@ error_chain! {
             description(desc)
             display("merge is aborted: {}", desc)
         }
+
+        FlushFailed(desc: String) {
+            description(desc)
+            display("flush failed: {}", desc)
+        }
     }
 }
 
@@ -210,7 +245,7 @@ pub const INDEX_FILE_SEGMENTS: &str = "segments";
 pub const INDEX_FILE_PENDING_SEGMENTS: &str = "pending_segments";
 pub const INDEX_FILE_OLD_SEGMENT_GEN: &str = "segments.gen";
 
-const CODEC_FILE_PATTERN: &str = r"_[a-z0-9]+(_.*)?\..*";
+const CODEC_FILE_PATTERN: &str = r"^_[a-z0-9]+(_.*)?\..*$";
 
 pub fn matches_extension(filename: &str, ext: &str) -> bool {
     filename.ends_with(ext)
@@ -314,6 +349,10 @@ pub trait IndexReader {
     fn document(&self, doc_id: DocId, fields: &[String]) -> Result<Document>;
     fn max_doc(&self) -> i32;
     fn num_docs(&self) -> i32;
+    /// Fails with `AlreadyClosed` if the reader has been closed.
+    fn ensure_open(&self) -> Result<()> {
+        Ok(())
+    }
     fn num_deleted_docs(&self) -> i32 {
         self.max_doc() - self.num_docs()
     }
@@ -343,9 +382,96 @@ pub trait IndexReader {
     }
 
     // used for refresh
-    fn refresh(&self) -> Result<Option<Box<dyn IndexReader<Codec = Self::Codec>>>> {
+    fn refresh(&self) -> Result<Option<Box<dyn IndexReader<Codec = Self::Codec> + Send + Sync>>> {
         Ok(None)
     }
+
+    /// Returns the number of documents containing `term` in all the leaves.
+    /// Like the other term statistics, this counts the deleted documents.
+    fn doc_freq(&self, term: &Term) -> Result<i32> {
+        Ok(term_statistics(self, term)?.0)
+    }
+
+    /// Returns the number of occurrences of `term` in all the leaves, or -1 if
+    /// a leaf doesn't record the term frequencies of the field.
+    fn total_term_freq(&self, term: &Term) -> Result<i64> {
+        Ok(term_statistics(self, term)?.1)
+    }
+
+    /// Returns the statistics of `field` summed over the leaves, a statistic is
+    /// -1 if a leaf doesn't record it.
+    fn collection_statistics(&self, field: &str) -> Result<CollectionStatistics> {
+        let mut doc_count = 0i64;
+        let mut sum_total_term_freq = 0i64;
+        let mut sum_doc_freq = 0i64;
+        for leaf in self.leaves() {
+            if let Some(terms) = leaf.reader.terms(field)? {
+                doc_count = sum_statistic(doc_count, i64::from(terms.doc_count()?));
+                sum_total_term_freq =
+                    sum_statistic(sum_total_term_freq, terms.sum_total_term_freq()?);
+                sum_doc_freq = sum_statistic(sum_doc_freq, terms.sum_doc_freq()?);
+            }
+        }
+        Ok(CollectionStatistics::new(
+            field.to_string(),
+            i64::from(self.max_doc()),
+            doc_count,
+            sum_total_term_freq,
+            sum_doc_freq,
+        ))
+    }
+
+    /// Returns the `OrdinalMap` of the sorted (set) doc values of `field` over
+    /// the leaves, `None` if no leaf has any. Building it reads all the terms
+    /// of the field, the readers opened on an index cache it.
+    fn ordinal_map(&self, field: &str) -> Result<Option<Arc<OrdinalMap>>> {
+        Ok(OrdinalMap::build_for_field(self, field)?.map(Arc::new))
+    }
+}
+
+/// Sums the doc freq and the total term freq of `term` over the leaves of
+/// `reader`, the leaves whose terms range doesn't contain `term` are skipped
+/// without seeking their terms dictionary.
+pub fn term_statistics<IR: IndexReader + ?Sized>(reader: &IR, term: &Term) -> Result<(i32, i64)> {
+    let mut doc_freq = 0;
+    let mut total_term_freq = 0i64;
+    for leaf in reader.leaves() {
+        if let Some(terms) = leaf.reader.terms(&term.field)? {
+            if !terms_may_contain(&terms, &term.bytes)? {
+                continue;
+            }
+            let mut terms_enum = terms.iterator()?;
+            if terms_enum.seek_exact(&term.bytes)? {
+                doc_freq += terms_enum.doc_freq()?;
+                total_term_freq = sum_statistic(total_term_freq, terms_enum.total_term_freq()?);
+            }
+        }
+    }
+    Ok((doc_freq, total_term_freq))
+}
+
+// false if `term` is out of the [min, max] range of `terms`
+fn terms_may_contain<T: Terms>(terms: &T, term: &[u8]) -> Result<bool> {
+    if let Some(min) = terms.min()? {
+        if term < min.as_slice() {
+            return Ok(false);
+        }
+    }
+    if let Some(max) = terms.max()? {
+        if term > max.as_slice() {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+// sums two statistics, -1 if any of them isn't recorded
+fn sum_statistic(sum: i64, value: i64) -> i64 {
+    if sum >= 0 && value >= 0 {
+        sum + value
+    } else {
+        -1
+    }
 }
 
 pub const SEGMENT_USE_COMPOUND_YES: u8 = 0x01;
@@ -642,6 +768,9 @@ impl<S: TermState> TermContext<S> {
     {
         for reader in reader.leaves() {
             if let Some(terms) = reader.reader.terms(&term.field)? {
+                if !terms_may_contain(&terms, &term.bytes)? {
+                    continue;
+                }
                 let mut terms_enum = terms.iterator()?;
                 if terms_enum.seek_exact(&term.bytes)? {
                     // TODO add TermStates if someone need it
@@ -705,21 +834,24 @@ pub struct SegmentCommitInfo<D: Directory, C: Codec> {
     /// write
     pub next_write_field_infos_gen: AtomicI64,
     /// Generation number of the DocValues (-1 if there are no updates)
-    pub doc_values_gen: i64,
+    doc_values_gen: AtomicI64,
     /// Normally 1+dvGen, unless an exception was hit on last attempt to
     /// write
     pub next_write_doc_values_gen: AtomicI64,
     /// Track the per-field DocValues update files
-    pub dv_updates_files: HashMap<i32, HashSet<String>>,
+    dv_updates_files: RwLock<HashMap<i32, HashSet<String>>>,
     /// TODO should we add .files() to FieldInfosFormat, like we have on
     /// LiveDocsFormat?
     /// track the fieldInfos update files
-    pub field_infos_files: HashSet<String>,
+    field_infos_files: RwLock<HashSet<String>>,
 
     pub size_in_bytes: AtomicI64,
     // NOTE: only used in-RAM by IW to track buffered deletes;
     // this is never written to/read from the Directory
     pub buffered_deletes_gen: AtomicI64,
+    // NOTE: only used in-RAM by IW, the number of soft deleted docs along with
+    // the del count, del gen and doc values gen it was counted at
+    soft_delete_count: Mutex<Option<(i32, i64, i64, u32)>>,
 }
 
 impl<D: Directory, C: Codec> Hash for SegmentCommitInfo<D, C> {
@@ -750,16 +882,17 @@ impl<D: Directory, C: Codec> SegmentCommitInfo<D, C> {
             next_write_del_gen: AtomicI64::new(if del_gen == -1 { 1i64 } else { del_gen + 1 }),
             field_infos_gen: AtomicI64::new(field_infos_gen),
             next_write_field_infos_gen: AtomicI64::new(field_info_gen),
-            doc_values_gen,
+            doc_values_gen: AtomicI64::new(doc_values_gen),
             next_write_doc_values_gen: AtomicI64::new(if doc_values_gen == -1 {
                 1
             } else {
                 doc_values_gen + 1
             }),
-            dv_updates_files,
-            field_infos_files,
+            dv_updates_files: RwLock::new(dv_updates_files),
+            field_infos_files: RwLock::new(field_infos_files),
             size_in_bytes: AtomicI64::new(-1),
             buffered_deletes_gen: AtomicI64::new(0),
+            soft_delete_count: Mutex::new(None),
         }
     }
 
@@ -776,14 +909,14 @@ impl<D: Directory, C: Codec> SegmentCommitInfo<D, C> {
         self.info.codec().live_docs_format().files(self, &mut files);
 
         // must separately add any field updates files
-        for (_, fs) in &self.dv_updates_files {
+        for fs in self.dv_updates_files.read().unwrap().values() {
             for f in fs {
                 files.insert(f.clone());
             }
         }
 
         // must separately add field_infos files
-        for f in &self.field_infos_files {
+        for f in self.field_infos_files.read().unwrap().iter() {
             files.insert(f.clone());
         }
 
@@ -833,6 +966,52 @@ impl<D: Directory, C: Codec> SegmentCommitInfo<D, C> {
             .store(gen, AtomicOrdering::Release);
     }
 
+    pub fn advance_next_write_field_infos_gen(&self) {
+        self.next_write_field_infos_gen
+            .fetch_add(1, AtomicOrdering::AcqRel);
+    }
+
+    pub fn doc_values_gen(&self) -> i64 {
+        self.doc_values_gen.load(AtomicOrdering::Acquire)
+    }
+
+    pub fn advance_doc_values_gen(&self) {
+        self.doc_values_gen
+            .store(self.next_write_doc_values_gen(), AtomicOrdering::Release);
+        self.next_write_doc_values_gen
+            .store(self.doc_values_gen() + 1, AtomicOrdering::Release);
+        self.size_in_bytes.store(-1, AtomicOrdering::Release);
+    }
+
+    pub fn advance_next_write_doc_values_gen(&self) {
+        self.next_write_doc_values_gen
+            .fetch_add(1, AtomicOrdering::AcqRel);
+    }
+
+    /// Returns the per-field DocValues update files, by field number.
+    pub fn doc_values_updates_files(&self) -> HashMap<i32, HashSet<String>> {
+        self.dv_updates_files.read().unwrap().clone()
+    }
+
+    /// Sets the DocValues update files of the given fields, replacing the
+    /// files of their previous generation.
+    pub fn set_doc_values_updates_files(&self, files: HashMap<i32, HashSet<String>>) {
+        let mut dv_updates_files = self.dv_updates_files.write().unwrap();
+        for (field, fs) in files {
+            dv_updates_files.insert(field, fs);
+        }
+        self.size_in_bytes.store(-1, AtomicOrdering::Release);
+    }
+
+    pub fn field_infos_files(&self) -> HashSet<String> {
+        self.field_infos_files.read().unwrap().clone()
+    }
+
+    pub fn set_field_infos_files(&self, files: HashSet<String>) {
+        *self.field_infos_files.write().unwrap() = files;
+        self.size_in_bytes.store(-1, AtomicOrdering::Release);
+    }
+
     pub fn advance_field_infos_gen(&self) {
         self.field_infos_gen
             .store(self.next_field_infos_gen(), AtomicOrdering::Release);
@@ -901,6 +1080,26 @@ impl<D: Directory, C: Codec> SegmentCommitInfo<D, C> {
         self.buffered_deletes_gen.store(v, AtomicOrdering::Release);
         self.size_in_bytes.store(-1, AtomicOrdering::Release);
     }
+
+    /// The number of soft deleted docs set by `set_soft_delete_count`, unless
+    /// the deletes or the doc values of the segment changed since.
+    pub fn soft_delete_count(&self, del_count: i32) -> Option<u32> {
+        match *self.soft_delete_count.lock().unwrap() {
+            Some((cached_del_count, del_gen, doc_values_gen, count))
+                if cached_del_count == del_count
+                    && del_gen == self.del_gen()
+                    && doc_values_gen == self.doc_values_gen() =>
+            {
+                Some(count)
+            }
+            _ => None,
+        }
+    }
+
+    pub fn set_soft_delete_count(&self, del_count: i32, count: u32) {
+        *self.soft_delete_count.lock().unwrap() =
+            Some((del_count, self.del_gen(), self.doc_values_gen(), count));
+    }
 }
 
 impl<D: Directory, C: Codec> Clone for SegmentCommitInfo<D, C> {
@@ -910,9 +1109,9 @@ impl<D: Directory, C: Codec> Clone for SegmentCommitInfo<D, C> {
             self.del_count(),
             self.del_gen(),
             self.field_infos_gen(),
-            self.doc_values_gen,
-            self.dv_updates_files.clone(),
-            self.field_infos_files.clone(),
+            self.doc_values_gen(),
+            self.doc_values_updates_files(),
+            self.field_infos_files(),
         );
         // Not clear that we need to carry over nextWriteDelGen
         // (i.e. do we ever clone after a failed write and
@@ -955,13 +1154,13 @@ impl<D: Directory, C: Codec> Serialize for SegmentCommitInfo<D, C> {
                 .next_write_field_infos_gen
                 .load(AtomicOrdering::Acquire),
         )?;
-        s.serialize_field("doc_values_gen", &self.doc_values_gen)?;
+        s.serialize_field("doc_values_gen", &self.doc_values_gen())?;
         s.serialize_field(
             "next_write_doc_values_gen",
             &self.next_write_doc_values_gen(),
         )?;
-        s.serialize_field("dv_updates_files", &self.dv_updates_files)?;
-        s.serialize_field("field_infos_files", &self.field_infos_files)?;
+        s.serialize_field("dv_updates_files", &self.doc_values_updates_files())?;
+        s.serialize_field("field_infos_files", &self.field_infos_files())?;
         s.serialize_field("size_in_bytes", &self.size_in_bytes())?;
         s.end()
     }
@@ -1070,7 +1269,7 @@ impl<D: Directory, DW: Directory, C: Codec> SegmentWriteState<D, DW, C> {
             if parts.len() == 2 {
                 true
             } else if parts.len() == 1 {
-                segment_suffix.parse::<i64>().is_ok()
+                i64::from_str_radix(segment_suffix, 36).is_ok()
             } else {
                 false // invalid
             }
@@ -1157,16 +1356,62 @@ impl<'a, D: Directory, DW: Directory, C: Codec> SegmentReadState<'a, D, DW, C> {
 #[cfg(test)]
 pub mod tests {
     use std::collections::HashMap;
+    use std::sync::Arc;
 
     use super::*;
     use core::codec::tests::TestCodec;
     use core::codec::*;
+    use core::index::index_writer_config::IndexWriterConfig;
+    use core::index::merge_policy::TieredMergePolicy;
+    use core::index::merge_scheduler::SerialMergeScheduler;
     use core::search::bm25_similarity::BM25Similarity;
+    use core::store::RAMDirectory;
     use core::util::external::deferred::Deferred;
     use core::util::*;
 
     use core::codec::FieldsProducerRef;
 
+    pub type TestReader =
+        StandardDirectoryReader<RAMDirectory, CodecEnum, SerialMergeScheduler, TieredMergePolicy>;
+    pub type TestWriter =
+        IndexWriter<RAMDirectory, CodecEnum, SerialMergeScheduler, TieredMergePolicy>;
+    pub type TestWriterConfig =
+        IndexWriterConfig<CodecEnum, SerialMergeScheduler, TieredMergePolicy>;
+
+    /// Opens a reader on a RAM index with one committed segment per entry of
+    /// `segments`, each holding the documents of that segment.
+    pub fn open_test_reader<F: Fieldable>(segments: Vec<Vec<Vec<F>>>) -> Arc<TestReader> {
+        open_test_reader_with(IndexWriterConfig::default(), segments)
+    }
+
+    /// Same as `open_test_reader`, with the given writer config.
+    pub fn open_test_reader_with<F: Fieldable>(
+        config: TestWriterConfig,
+        segments: Vec<Vec<Vec<F>>>,
+    ) -> Arc<TestReader> {
+        write_test_index(config, |writer| {
+            for docs in segments {
+                for doc in docs {
+                    writer.add_document(doc).unwrap();
+                }
+                writer.commit().unwrap();
+            }
+        })
+    }
+
+    /// Runs `write` against a writer on a new RAM index, then closes the
+    /// writer and opens a reader on the last commit.
+    pub fn write_test_index<W: FnOnce(&TestWriter)>(
+        config: TestWriterConfig,
+        write: W,
+    ) -> Arc<TestReader> {
+        let dir = Arc::new(RAMDirectory::new());
+        let writer = IndexWriter::new(Arc::clone(&dir), Arc::new(config)).unwrap();
+        write(&writer);
+        writer.close().unwrap();
+        Arc::new(TestReader::open(dir).unwrap())
+    }
+
     pub struct MockNumericValues {
         num: HashMap<i32, u8>,
     }
@@ -0,0 +1,472 @@
+// Copyright 2019 Zhizhesihai (Beijing) Technology Limited.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use core::codec::{Codec, CodecTVFields};
+use core::doc::Document;
+use core::index::leaf_reader::LeafReaderContext;
+use core::index::merge_policy::MergePolicy;
+use core::index::merge_scheduler::MergeScheduler;
+use core::index::{sum_statistic, IndexReader, StandardDirectoryReader, Term};
+use core::index::{OrdinalMap, OrdinalMapCache};
+use core::store::Directory;
+use core::util::DocId;
+
+use error::{
+    ErrorKind::{AlreadyClosed, IllegalArgument},
+    Result,
+};
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// Reads several indexes opened on their own as a single index.
+///
+/// The leaves of the sub readers are concatenated, the doc ids of a sub reader
+/// are rebased after the `max_doc` of the ones before it, so that a searcher
+/// on this reader sees one index whose term and collection statistics are
+/// summed over all of them.
+///
+/// A `MultiReader` takes a reference on each of its sub readers (see
+/// `StandardDirectoryReader#inc_ref`) and releases it when it's closed, so the
+/// sub readers stay open while it's searched even if their owners close them.
+/// To hand a sub reader over, close it once the `MultiReader` is built: it's
+/// closed along with the `MultiReader`.
+pub struct MultiReader<D, C, MS, MP>
+where
+    D: Directory + Send + Sync + 'static,
+    C: Codec,
+    MS: MergeScheduler,
+    MP: MergePolicy,
+{
+    sub_readers: Vec<Arc<StandardDirectoryReader<D, C, MS, MP>>>,
+    // the doc base of every sub reader, then the max doc
+    starts: Vec<DocId>,
+    num_docs: i32,
+    closed: AtomicBool,
+    // the maps over the leaves of all the sub readers
+    ordinal_maps: OrdinalMapCache,
+}
+
+impl<D, C, MS, MP> MultiReader<D, C, MS, MP>
+where
+    D: Directory + Send + Sync + 'static,
+    C: Codec,
+    MS: MergeScheduler,
+    MP: MergePolicy,
+{
+    /// Fails with `AlreadyClosed` if one of the sub readers is closed.
+    pub fn new(sub_readers: Vec<Arc<StandardDirectoryReader<D, C, MS, MP>>>) -> Result<Self> {
+        let mut starts = Vec::with_capacity(sub_readers.len() + 1);
+        let mut max_doc = 0i64;
+        let mut num_docs = 0;
+        for reader in &sub_readers {
+            starts.push(max_doc as DocId);
+            max_doc += i64::from(reader.max_doc());
+            if max_doc > i64::from(DocId::max_value()) {
+                bail!(IllegalArgument(format!(
+                    "too many documents: the sub readers hold more than {}",
+                    DocId::max_value()
+                )));
+            }
+            num_docs += reader.num_docs();
+        }
+        starts.push(max_doc as DocId);
+
+        for (i, reader) in sub_readers.iter().enumerate() {
+            if let Err(e) = reader.inc_ref() {
+                for taken in &sub_readers[..i] {
+                    taken.dec_ref()?;
+                }
+                return Err(e);
+            }
+        }
+
+        Ok(MultiReader {
+            sub_readers,
+            starts,
+            num_docs,
+            closed: AtomicBool::new(false),
+            ordinal_maps: OrdinalMapCache::default(),
+        })
+    }
+
+    pub fn sub_readers(&self) -> &[Arc<StandardDirectoryReader<D, C, MS, MP>>] {
+        &self.sub_readers
+    }
+
+    /// Closes this reader and releases its references on the sub readers, the
+    /// ones already closed by their owners are closed too. Closing a closed
+    /// reader does nothing.
+    pub fn close(&self) -> Result<()> {
+        if self.closed.swap(true, Ordering::AcqRel) {
+            return Ok(());
+        }
+        let mut result = Ok(());
+        for reader in &self.sub_readers {
+            // release all of them, the first failure is returned
+            if let Err(e) = reader.dec_ref() {
+                if result.is_ok() {
+                    result = Err(e);
+                }
+            }
+        }
+        result
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::Acquire)
+    }
+
+    // the sub reader holding `doc_id`, along with the doc id in it
+    fn sub_reader_for_doc(&self, doc_id: DocId) -> Result<(usize, DocId)> {
+        let max_doc = self.max_doc();
+        if doc_id < 0 || doc_id >= max_doc {
+            bail!(IllegalArgument(format!(
+                "doc_id {} invalid: [max_doc={}]",
+                doc_id, max_doc
+            )));
+        }
+        // the last sub reader starting at or before `doc_id`, the empty ones
+        // start where the next one does
+        let mut lo = 0;
+        let mut hi = self.sub_readers.len();
+        while hi - lo > 1 {
+            let mid = (lo + hi) >> 1;
+            if self.starts[mid] <= doc_id {
+                lo = mid;
+            } else {
+                hi = mid;
+            }
+        }
+        Ok((lo, doc_id - self.starts[lo]))
+    }
+}
+
+impl<D, C, MS, MP> IndexReader for MultiReader<D, C, MS, MP>
+where
+    D: Directory + Send + Sync + 'static,
+    C: Codec,
+    MS: MergeScheduler,
+    MP: MergePolicy,
+{
+    type Codec = C;
+    fn ensure_open(&self) -> Result<()> {
+        if self.is_closed() {
+            bail!(AlreadyClosed("this IndexReader is closed".into()));
+        }
+        Ok(())
+    }
+
+    // a closed reader has no leaves, the methods that can fail check
+    // `ensure_open` instead
+    fn leaves(&self) -> Vec<LeafReaderContext<'_, C>> {
+        if self.ensure_open().is_err() {
+            return vec![];
+        }
+        let mut leaves = vec![];
+        for (i, reader) in self.sub_readers.iter().enumerate() {
+            for leaf in reader.leaves() {
+                let ord = leaves.len();
+                leaves.push(LeafReaderContext::new(
+                    self,
+                    leaf.reader,
+                    ord,
+                    self.starts[i] + leaf.doc_base,
+                ));
+            }
+        }
+        leaves
+    }
+
+    fn term_vector(&self, doc_id: DocId) -> Result<Option<CodecTVFields<C>>> {
+        self.ensure_open()?;
+        let (i, doc) = self.sub_reader_for_doc(doc_id)?;
+        self.sub_readers[i].term_vector(doc)
+    }
+
+    fn document(&self, doc_id: DocId, fields_load: &[String]) -> Result<Document> {
+        self.ensure_open()?;
+        let (i, doc) = self.sub_reader_for_doc(doc_id)?;
+        self.sub_readers[i].document(doc, fields_load)
+    }
+
+    fn max_doc(&self) -> i32 {
+        self.starts[self.sub_readers.len()]
+    }
+
+    fn num_docs(&self) -> i32 {
+        self.num_docs
+    }
+
+    // the sub readers memoize the statistics of their terms
+    fn doc_freq(&self, term: &Term) -> Result<i32> {
+        let mut doc_freq = 0;
+        for reader in &self.sub_readers {
+            doc_freq += reader.doc_freq(term)?;
+        }
+        Ok(doc_freq)
+    }
+
+    fn total_term_freq(&self, term: &Term) -> Result<i64> {
+        let mut total_term_freq = 0i64;
+        for reader in &self.sub_readers {
+            total_term_freq = sum_statistic(total_term_freq, reader.total_term_freq(term)?);
+        }
+        Ok(total_term_freq)
+    }
+
+    fn ordinal_map(&self, field: &str) -> Result<Option<Arc<OrdinalMap>>> {
+        self.ensure_open()?;
+        self.ordinal_maps.get_or_build(self, field)
+    }
+}
+
+impl<D, C, MS, MP> AsRef<IndexReader<Codec = C>> for MultiReader<D, C, MS, MP>
+where
+    D: Directory + Send + Sync + 'static,
+    C: Codec,
+    MS: MergeScheduler,
+    MP: MergePolicy,
+{
+    fn as_ref(&self) -> &(IndexReader<Codec = C> + 'static) {
+        self
+    }
+}
+
+impl<D, C, MS, MP> Drop for MultiReader<D, C, MS, MP>
+where
+    D: Directory + Send + Sync + 'static,
+    C: Codec,
+    MS: MergeScheduler,
+    MP: MergePolicy,
+{
+    fn drop(&mut self) {
+        if let Err(e) = self.close() {
+            error!(
+                "MultiReader drop failed to release the sub readers: {:?}",
+                e
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::codec::CodecEnum;
+    use core::doc::{Field, FieldType};
+    use core::index::index_writer_config::IndexWriterConfig;
+    use core::index::merge_policy::TieredMergePolicy;
+    use core::index::merge_scheduler::SerialMergeScheduler;
+    use core::index::tests::{write_test_index, TestReader};
+    use core::index::{Fieldable, IndexOptions};
+    use core::search::collector::TopDocsCollector;
+    use core::search::searcher::{DefaultIndexSearcher, IndexSearcher};
+    use core::search::term_query::TermQuery;
+    use core::store::RAMDirectory;
+    use core::util::VariantValue;
+
+    use std::ops::Range;
+
+    type TestMultiReader =
+        MultiReader<RAMDirectory, CodecEnum, SerialMergeScheduler, TieredMergePolicy>;
+
+    const GROUPS: [Range<usize>; 3] = [0..4, 4..9, 9..12];
+    const DELETED: &str = "5";
+
+    fn document(i: usize) -> Vec<Field> {
+        let mut id_type = FieldType::default();
+        id_type.stored = true;
+        id_type.tokenized = false;
+        id_type.index_options = IndexOptions::DocsAndFreqs;
+        let mut body_type = FieldType::default();
+        body_type.stored = true;
+        body_type.omit_norms = false;
+        body_type.index_options = IndexOptions::DocsAndFreqs;
+
+        let mut body = vec!["apple"; i % 3 + 1];
+        if i % 2 == 0 {
+            body.push("banana");
+        }
+        if i % 4 == 0 {
+            body.push("cherry");
+        }
+        vec![
+            Field::new(
+                "id".to_string(),
+                id_type,
+                Some(VariantValue::VString(i.to_string())),
+                None,
+            ),
+            Field::new(
+                "body".to_string(),
+                body_type,
+                Some(VariantValue::VString(body.join(" "))),
+                None,
+            ),
+        ]
+    }
+
+    // one segment for each group of documents
+    fn open_index(groups: &[Range<usize>]) -> Arc<TestReader> {
+        write_test_index(IndexWriterConfig::default(), |writer| {
+            for group in groups {
+                for i in group.clone() {
+                    writer.add_document(document(i)).unwrap();
+                }
+                writer
+                    .delete_documents_by_term("id", DELETED.as_bytes())
+                    .unwrap();
+                writer.commit().unwrap();
+            }
+        })
+    }
+
+    fn open_multi_reader() -> TestMultiReader {
+        let sub_readers = GROUPS.iter().map(|g| open_index(&[g.clone()])).collect();
+        MultiReader::new(sub_readers).unwrap()
+    }
+
+    fn stored_values<R: IndexReader + ?Sized>(reader: &R, doc: DocId) -> Vec<String> {
+        let fields = ["id".to_string(), "body".to_string()];
+        reader
+            .document(doc, &fields)
+            .unwrap()
+            .fields
+            .iter()
+            .map(|f| {
+                f.field
+                    .fields_data()
+                    .unwrap()
+                    .get_string()
+                    .unwrap()
+                    .to_string()
+            })
+            .collect()
+    }
+
+    fn search<S: IndexSearcher<CodecEnum>>(searcher: &S, text: &str) -> Vec<(DocId, f32)> {
+        let query = TermQuery::new(
+            Term::new("body".to_string(), text.as_bytes().to_vec()),
+            1.0,
+            None,
+        );
+        let mut collector = TopDocsCollector::new(20);
+        searcher.search(&query, &mut collector).unwrap();
+        collector
+            .top_docs()
+            .score_docs()
+            .iter()
+            .map(|d| (d.doc_id(), d.score()))
+            .collect()
+    }
+
+    #[test]
+    fn test_same_as_merged_index() {
+        let merged = open_index(&GROUPS);
+        let multi = Arc::new(open_multi_reader());
+
+        assert_eq!(multi.leaves().len(), 3);
+        assert_eq!(multi.max_doc(), 12);
+        assert_eq!(multi.num_docs(), 11);
+        assert_eq!(multi.max_doc(), merged.max_doc());
+        assert_eq!(multi.num_docs(), merged.num_docs());
+        for (leaf, expected) in multi.leaves().iter().zip(merged.leaves()) {
+            assert_eq!(leaf.doc_base, expected.doc_base);
+        }
+
+        for text in &["apple", "banana", "cherry", "durian"] {
+            let term = Term::new("body".to_string(), text.as_bytes().to_vec());
+            assert_eq!(
+                multi.doc_freq(&term).unwrap(),
+                merged.doc_freq(&term).unwrap()
+            );
+            assert_eq!(
+                multi.total_term_freq(&term).unwrap(),
+                merged.total_term_freq(&term).unwrap()
+            );
+        }
+        let stats = multi.collection_statistics("body").unwrap();
+        let expected = merged.collection_statistics("body").unwrap();
+        assert_eq!(stats.max_doc, expected.max_doc);
+        assert_eq!(stats.doc_count, expected.doc_count);
+        assert_eq!(stats.sum_total_term_freq, expected.sum_total_term_freq);
+        assert_eq!(stats.sum_doc_freq, expected.sum_doc_freq);
+
+        let searcher = DefaultIndexSearcher::new(Arc::clone(&multi));
+        let merged_searcher = DefaultIndexSearcher::new(Arc::clone(&merged));
+        for text in &["apple", "banana", "cherry"] {
+            let hits = search(&searcher, text);
+            assert!(!hits.is_empty());
+            assert_eq!(hits, search(&merged_searcher, text));
+            for &(doc, _) in &hits {
+                assert_ne!(stored_values(&*multi, doc)[0], DELETED);
+            }
+        }
+
+        for doc in 0..multi.max_doc() {
+            let values = stored_values(&*multi, doc);
+            assert_eq!(values[0], doc.to_string());
+            assert_eq!(values, stored_values(&*merged, doc));
+        }
+        assert!(multi.document(multi.max_doc(), &[]).is_err());
+    }
+
+    #[test]
+    fn test_empty_sub_reader() {
+        let sub_readers = vec![
+            open_index(&[0..3]),
+            open_index(&[0..0]),
+            open_index(&[3..5]),
+        ];
+        let multi = MultiReader::new(sub_readers).unwrap();
+        assert_eq!(multi.max_doc(), 5);
+        for doc in 0..5 {
+            assert_eq!(stored_values(&multi, doc)[0], doc.to_string());
+        }
+    }
+
+    #[test]
+    fn test_close_sub_readers() {
+        let sub_readers: Vec<_> = GROUPS.iter().map(|g| open_index(&[g.clone()])).collect();
+
+        // the sub readers shared with their owners stay open
+        let multi = MultiReader::new(sub_readers.clone()).unwrap();
+        assert!(sub_readers.iter().all(|r| r.ref_count() == 2));
+        multi.close().unwrap();
+        multi.close().unwrap();
+        assert!(multi.document(0, &[]).is_err());
+        assert!(multi.leaves().is_empty());
+        assert!(sub_readers.iter().all(|r| r.ref_count() == 1));
+        drop(multi);
+        assert_eq!(stored_values(&*sub_readers[0], 0)[0], "0");
+
+        // the sub readers handed over are closed along with the multi reader
+        let multi = MultiReader::new(sub_readers.clone()).unwrap();
+        for reader in &sub_readers {
+            reader.close().unwrap();
+        }
+        assert!(sub_readers.iter().all(|r| !r.is_closed()));
+        assert_eq!(stored_values(&multi, 4)[0], "4");
+        drop(multi);
+        assert!(sub_readers.iter().all(|r| r.is_closed()));
+        match sub_readers[0].document(0, &[]) {
+            Err(e) => match e.kind() {
+                AlreadyClosed(_) => {}
+                _ => panic!("expected AlreadyClosed, got {:?}", e),
+            },
+            Ok(_) => panic!("a closed sub reader loaded a document"),
+        }
+        assert!(MultiReader::new(sub_readers).is_err());
+    }
+}
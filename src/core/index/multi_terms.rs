@@ -144,11 +144,27 @@ impl<T: Terms> Terms for MultiTerms<T> {
     }
 
     fn min(&self) -> Result<Option<Vec<u8>>> {
-        unimplemented!();
+        let mut min: Option<Vec<u8>> = None;
+        for terms in &self.subs {
+            if let Some(term) = terms.min()? {
+                if min.as_ref().map_or(true, |m| term < *m) {
+                    min = Some(term);
+                }
+            }
+        }
+        Ok(min)
     }
 
     fn max(&self) -> Result<Option<Vec<u8>>> {
-        unimplemented!();
+        let mut max: Option<Vec<u8>> = None;
+        for terms in &self.subs {
+            if let Some(term) = terms.max()? {
+                if max.as_ref().map_or(true, |m| term > *m) {
+                    max = Some(term);
+                }
+            }
+        }
+        Ok(max)
     }
 
     fn stats(&self) -> Result<String> {
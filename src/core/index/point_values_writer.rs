@@ -16,6 +16,7 @@ use core::codec::{
 };
 use core::index::merge_policy::MergePolicy;
 use core::index::merge_scheduler::MergeScheduler;
+use core::index::sorter::SorterDocMap;
 use core::index::thread_doc_writer::DocumentsWriterPerThread;
 use core::index::FieldInfo;
 use core::index::IntersectVisitor;
@@ -90,6 +91,13 @@ impl PointValuesWriter {
         Ok(())
     }
 
+    /// Moves the buffered points to the doc ids of the sorted segment.
+    pub fn sort_doc_ids(&mut self, sort_map: &impl SorterDocMap) {
+        for doc_id in &mut self.doc_ids {
+            *doc_id = sort_map.old_to_new(*doc_id);
+        }
+    }
+
     pub fn flush<D: Directory, C: Codec, DW: Directory, W: PointsWriter>(
         &mut self,
         _state: &SegmentWriteState<D, DW, C>,
@@ -24,7 +24,7 @@ use core::codec::{
     PointsFormat, PostingsFormat, SegmentInfoFormat, StoredFieldsFormat, TermVectorsFormat,
     CODEC_MAGIC,
 };
-use core::index::index_commit::IndexCommit;
+use core::index::index_commit::{IndexCommit, IndexCommitPoint};
 use core::index::merge_policy::OneMerge;
 use core::index::{
     file_name_from_generation, FieldInfos, SegmentCommitInfo, SegmentInfo, SegmentReadState,
@@ -38,7 +38,8 @@ use core::util::ptr_eq;
 use core::util::string_util::{id2str, random_id, ID_LENGTH};
 use core::util::{to_base36, Version, VERSION_LATEST};
 use error::ErrorKind::{IOError, IllegalState, NumError};
-use error::Result;
+use error::ErrorKind::{IndexFormatTooNew, IndexFormatTooOld};
+use error::{Error, Result};
 
 /// The file format version for the segments_N codec header, since 5.0+
 const SEGMENT_VERSION_50: i32 = 4;
@@ -51,6 +52,9 @@ const SEGMENT_VERSION_53: i32 = 6;
 
 const SEGMENT_VERSION_CURRENT: i32 = SEGMENT_VERSION_53;
 
+/// Segments written by an older major version can't be read.
+const MIN_SUPPORTED_MAJOR_VERSION: i32 = 5;
+
 /// A collection of segmentInfo objects with methods for operating on those
 /// segments in relation to the file system.
 ///
@@ -79,6 +83,8 @@ pub struct SegmentInfos<D: Directory, C: Codec> {
     pub lucene_version: Option<Version>,
     /// Version of the oldest segment in the index, or null if there are no segments.
     pub min_seg_version: Option<Version>,
+    /// Opaque map of strings written with the commit.
+    pub user_data: HashMap<String, String>,
     // Only true after prepareCommit has been called and
     // before finishCommit is called
     pending_commit: bool,
@@ -95,6 +101,7 @@ impl<D: Directory, C: Codec> Default for SegmentInfos<D, C> {
             id: [0u8; ID_LENGTH],
             lucene_version: None,
             min_seg_version: None,
+            user_data: HashMap::new(),
             pending_commit: false,
         }
     }
@@ -121,6 +128,7 @@ impl<D: Directory, C: Codec> SegmentInfos<D, C> {
             id,
             lucene_version,
             min_seg_version,
+            user_data: HashMap::new(),
             pending_commit: false,
         }
     }
@@ -281,12 +289,18 @@ impl<D: Directory, C: Codec> SegmentInfos<D, C> {
             }
             output.write_int(del_count)?;
             output.write_long(commit.field_infos_gen())?;
-            output.write_long(commit.doc_values_gen)?;
-            output.write_set_of_strings(&commit.field_infos_files)?;
-            // TODO add doc_values_updates_files
-            output.write_int(0)?;
+            output.write_long(commit.doc_values_gen())?;
+            output.write_set_of_strings(&commit.field_infos_files())?;
+            let dv_updates_files = commit.doc_values_updates_files();
+            output.write_int(dv_updates_files.len() as i32)?;
+            let mut fields: Vec<_> = dv_updates_files.keys().cloned().collect();
+            fields.sort();
+            for field in fields {
+                output.write_int(field)?;
+                output.write_set_of_strings(&dv_updates_files[&field])?;
+            }
         }
-        output.write_map_of_strings(&HashMap::with_capacity(0))?;
+        output.write_map_of_strings(&self.user_data)?;
         codec_util::write_footer(output)
     }
 
@@ -315,8 +329,22 @@ impl<D: Directory, C: Codec> SegmentInfos<D, C> {
             file_name_from_generation(INDEX_FILE_PENDING_SEGMENTS, "", self.generation as u64);
         let dest = file_name_from_generation(INDEX_FILE_SEGMENTS, "", self.generation as u64);
 
+        // the directory entries of the new files must be durable before
+        // segments_N refers to them
+        if let Err(e) = dir.sync_meta_data() {
+            self.rollback_commit(dir);
+            return Err(e);
+        }
         if let Err(e) = self.rename(dir, &src, &dest) {
             self.rollback_commit(dir);
+            // the rename may have happened before syncing failed, but the
+            // previous commit must stay the latest one
+            if let Err(err) = dir.delete_file(&dest) {
+                debug!(
+                    "SegmentInfos: finish_commit delete file '{}' failed by '{:?}'",
+                    &dest, err
+                );
+            }
             return Err(e);
         }
 
@@ -486,8 +514,33 @@ impl<D: Directory, C: Codec> SegmentInfos<D, C> {
             None
         };
 
+        if let Some(ref v) = lucene_version {
+            if v.major > VERSION_LATEST.major {
+                bail!(IndexFormatTooNew(format!(
+                    "commit {} was written by version {}, the newest supported version is {}. \
+                     Upgrade rucene to read this index",
+                    generation,
+                    v.to_string(),
+                    VERSION_LATEST.to_string()
+                )));
+            }
+        }
+        if let Some(ref v) = min_seg_ver {
+            if v.major < MIN_SUPPORTED_MAJOR_VERSION {
+                bail!(IndexFormatTooOld(format!(
+                    "commit {} contains segments written by version {}, the oldest supported \
+                     major version is {}. Upgrade the index with the IndexUpgrader of an older \
+                     release or reindex",
+                    generation,
+                    v.to_string(),
+                    MIN_SUPPORTED_MAJOR_VERSION
+                )));
+            }
+        }
+
         // let mut total_docs = 0;
         let mut segments = Vec::new();
+        let mut format_failures = Vec::new();
         for _sge in 0..num_segs {
             let seg_name = input.read_string()?;
             let has_id = input.read_byte()?;
@@ -497,24 +550,26 @@ impl<D: Directory, C: Codec> SegmentInfos<D, C> {
             let mut segment_id = [0; ID_LENGTH];
             input.read_bytes(&mut segment_id, 0, ID_LENGTH)?;
             let codec: Arc<C> = Arc::new(read_codec(input, format < SEGMENT_VERSION_53)?);
-            let mut info = codec.segment_info_format().read(
+            // keep reading the other segments on version errors, so that all the
+            // segments that can't be read are reported at once
+            let info = match codec.segment_info_format().read(
                 directory,
                 seg_name.as_ref(),
                 segment_id,
                 &IOContext::READ,
-            )?;
-            info.set_codec(codec);
+            ) {
+                Ok(info) => Some(info),
+                Err(e) => {
+                    if !codec_util::is_format_version_error(&e) {
+                        return Err(e);
+                    }
+                    format_failures.push((seg_name.clone(), e));
+                    None
+                }
+            };
             // total_docs += info.max_doc();
             let del_gen = input.read_long()?;
             let del_count = input.read_int()?;
-            if del_count < 0 || del_count > info.max_doc() {
-                return Err(format!(
-                    "invalid deletion count: {} vs maxDoc={}",
-                    del_count,
-                    info.max_doc()
-                )
-                .into());
-            }
             let field_infos_gen = input.read_long()?;
             let dv_gen = input.read_long()?;
             let field_infos_files = input.read_set_of_strings()?;
@@ -528,6 +583,19 @@ impl<D: Directory, C: Codec> SegmentInfos<D, C> {
                 }
                 map
             };
+            let mut info = match info {
+                Some(info) => info,
+                None => continue,
+            };
+            if del_count < 0 || del_count > info.max_doc() {
+                return Err(format!(
+                    "invalid deletion count: {} vs maxDoc={}",
+                    del_count,
+                    info.max_doc()
+                )
+                .into());
+            }
+            info.set_codec(codec);
             let si_per_commit = SegmentCommitInfo::new(
                 info,
                 del_count,
@@ -543,9 +611,12 @@ impl<D: Directory, C: Codec> SegmentInfos<D, C> {
                 // TODO check version
             }
         }
-        let _user_data = input.read_map_of_strings();
+        let user_data = input.read_map_of_strings()?;
+        if !format_failures.is_empty() {
+            return Err(segments_format_error(format_failures));
+        }
 
-        Ok(SegmentInfos::new(
+        let mut infos = SegmentInfos::new(
             counter as i32,
             version,
             generation,
@@ -554,7 +625,9 @@ impl<D: Directory, C: Codec> SegmentInfos<D, C> {
             id,
             lucene_version,
             min_seg_ver,
-        ))
+        );
+        infos.user_data = user_data;
+        Ok(infos)
     }
 
     pub fn read_latest_commit(directory: &Arc<D>) -> Result<Self> {
@@ -582,6 +655,7 @@ impl<D: Directory, C: Codec> Clone for SegmentInfos<D, C> {
             id,
             lucene_version: self.lucene_version.clone(),
             min_seg_version: self.min_seg_version.clone(),
+            user_data: self.user_data.clone(),
             pending_commit: self.pending_commit,
         }
     }
@@ -631,6 +705,29 @@ pub fn generation_from_segments_file_name(file_name: &str) -> Result<i64> {
     }
 }
 
+/// Combines the format version errors of several segments into a single error,
+/// which is `IndexFormatTooNew` if any of the segments is too new.
+pub(crate) fn segments_format_error(failures: Vec<(String, Error)>) -> Error {
+    let too_new = failures.iter().any(|(_, e)| match e.kind() {
+        IndexFormatTooNew(_) => true,
+        _ => false,
+    });
+    let details: Vec<String> = failures
+        .iter()
+        .map(|(name, e)| format!("{}: {}", name, e))
+        .collect();
+    let msg = format!(
+        "{} segment(s) can't be read: [{}]",
+        failures.len(),
+        details.join("; ")
+    );
+    if too_new {
+        IndexFormatTooNew(msg).into()
+    } else {
+        IndexFormatTooOld(msg).into()
+    }
+}
+
 fn read_codec<T: Codec>(input: &mut dyn IndexInput, _unsupported_allowed: bool) -> Result<T> {
     let name = input.read_string()?;
     T::try_from(name)
@@ -1028,3 +1125,168 @@ impl<D: Directory> fmt::Display for CfsDirectory<D> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::codec::CodecEnum;
+    use core::store::{RAMDirectory, RAMIndexOutput, RAMLock};
+
+    use std::sync::atomic::{AtomicBool, Ordering as AtomicOrdering};
+
+    // fails `sync` or `sync_meta_data` on demand
+    struct FaultyDirectory {
+        dir: RAMDirectory,
+        fail_sync: AtomicBool,
+        fail_sync_meta_data: AtomicBool,
+    }
+
+    impl FaultyDirectory {
+        fn new() -> FaultyDirectory {
+            FaultyDirectory {
+                dir: RAMDirectory::new(),
+                fail_sync: AtomicBool::new(false),
+                fail_sync_meta_data: AtomicBool::new(false),
+            }
+        }
+    }
+
+    impl fmt::Display for FaultyDirectory {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "FaultyDirectory({})", self.dir)
+        }
+    }
+
+    impl Directory for FaultyDirectory {
+        type LK = RAMLock;
+        type IndexOutput = RAMIndexOutput;
+        type TempOutput = RAMIndexOutput;
+
+        fn list_all(&self) -> Result<Vec<String>> {
+            self.dir.list_all()
+        }
+
+        fn file_length(&self, name: &str) -> Result<i64> {
+            self.dir.file_length(name)
+        }
+
+        fn create_output(&self, name: &str, ctx: &IOContext) -> Result<Self::IndexOutput> {
+            self.dir.create_output(name, ctx)
+        }
+
+        fn open_input(&self, name: &str, ctx: &IOContext) -> Result<Box<dyn IndexInput>> {
+            self.dir.open_input(name, ctx)
+        }
+
+        fn obtain_lock(&self, name: &str) -> Result<Self::LK> {
+            self.dir.obtain_lock(name)
+        }
+
+        fn create_temp_output(
+            &self,
+            prefix: &str,
+            suffix: &str,
+            ctx: &IOContext,
+        ) -> Result<Self::TempOutput> {
+            self.dir.create_temp_output(prefix, suffix, ctx)
+        }
+
+        fn delete_file(&self, name: &str) -> Result<()> {
+            self.dir.delete_file(name)
+        }
+
+        fn sync(&self, names: &HashSet<String>) -> Result<()> {
+            if self.fail_sync.load(AtomicOrdering::Acquire) {
+                bail!(IOError(format!("injected sync failure: {:?}", names)));
+            }
+            self.dir.sync(names)
+        }
+
+        fn sync_meta_data(&self) -> Result<()> {
+            if self.fail_sync_meta_data.load(AtomicOrdering::Acquire) {
+                bail!(IOError("injected sync_meta_data failure".into()));
+            }
+            self.dir.sync_meta_data()
+        }
+
+        fn rename(&self, source: &str, dest: &str) -> Result<()> {
+            self.dir.rename(source, dest)
+        }
+    }
+
+    type Infos = SegmentInfos<FaultyDirectory, CodecEnum>;
+
+    fn commit(infos: &mut Infos, dir: &Arc<FaultyDirectory>) -> Result<String> {
+        infos.prepare_commit(dir.as_ref())?;
+        infos.finish_commit(dir.as_ref())
+    }
+
+    #[test]
+    fn test_failed_sync_keeps_previous_commit() {
+        let dir = Arc::new(FaultyDirectory::new());
+        let mut infos = Infos::default();
+        infos.version = 1;
+        assert_eq!(commit(&mut infos, &dir).unwrap(), "segments_1");
+
+        dir.fail_sync.store(true, AtomicOrdering::Release);
+        infos.version = 2;
+        assert!(commit(&mut infos, &dir).is_err());
+        dir.fail_sync.store(false, AtomicOrdering::Release);
+
+        dir.fail_sync_meta_data.store(true, AtomicOrdering::Release);
+        infos.version = 3;
+        assert!(commit(&mut infos, &dir).is_err());
+        dir.fail_sync_meta_data
+            .store(false, AtomicOrdering::Release);
+
+        // no partial commit is left behind
+        let mut files = dir.list_all().unwrap();
+        files.sort();
+        assert_eq!(files, vec!["segments_1".to_string()]);
+        let latest = Infos::read_latest_commit(&dir).unwrap();
+        assert_eq!(latest.generation, 1);
+        assert_eq!(latest.version, 1);
+
+        infos.version = 4;
+        assert_eq!(commit(&mut infos, &dir).unwrap(), "segments_4");
+        let latest = Infos::read_latest_commit(&dir).unwrap();
+        assert_eq!(latest.version, 4);
+    }
+
+    #[test]
+    fn test_segments_format_error() {
+        let failures = vec![
+            (
+                "_0".to_string(),
+                IndexFormatTooOld("version 3".into()).into(),
+            ),
+            (
+                "_1".to_string(),
+                IndexFormatTooOld("version 2".into()).into(),
+            ),
+        ];
+        let err = segments_format_error(failures);
+        match err.kind() {
+            IndexFormatTooOld(msg) => {
+                assert!(msg.starts_with("2 segment(s)"));
+                assert!(msg.contains("_0: ") && msg.contains("_1: "));
+            }
+            _ => panic!("expected IndexFormatTooOld"),
+        }
+
+        let failures = vec![
+            (
+                "_0".to_string(),
+                IndexFormatTooOld("version 3".into()).into(),
+            ),
+            (
+                "_2".to_string(),
+                IndexFormatTooNew("version 9".into()).into(),
+            ),
+        ];
+        match segments_format_error(failures).kind() {
+            IndexFormatTooNew(msg) => assert!(msg.contains("_2: ")),
+            _ => panic!("expected IndexFormatTooNew"),
+        }
+    }
+}
@@ -12,13 +12,16 @@
 // limitations under the License.
 
 use core::codec::{Codec, DocValuesFormat, DocValuesProducer};
-use core::index::FieldInfos;
+use core::index::{BinaryDocValues, NumericDocValues, SortedDocValues};
+use core::index::{DocValuesType, FieldInfo, FieldInfos};
 use core::index::{SegmentCommitInfo, SegmentReadState};
+use core::index::{SortedNumericDocValues, SortedSetDocValues};
 use core::store::{Directory, IOContext};
-use core::util::to_base36;
-use error::ErrorKind::IllegalState;
+use core::util::{to_base36, BitsRef};
+use error::ErrorKind::{IllegalArgument, IllegalState};
 use error::Result;
 
+use std::collections::HashMap;
 use std::sync::Arc;
 
 /// Manage the `DocValuesProducer` held by `SegmentReader`.
@@ -67,3 +70,112 @@ impl SegmentDocValues {
         }
     }
 }
+
+/// The `DocValuesProducer` of a segment with DocValues updates.
+///
+/// Each updated field is read from the files of its own generation, the
+/// other fields from the files written with the segment.
+pub struct SegmentDocValuesProducer {
+    producers: Vec<Arc<dyn DocValuesProducer>>,
+    // field name -> index of its producer in `producers`
+    fields: HashMap<String, usize>,
+}
+
+impl SegmentDocValuesProducer {
+    /// `dir` holds the files written with the segment, which are read with
+    /// `core_infos`, the updates are always outside of the compound file.
+    pub fn new<D: Directory, DW: Directory, C: Codec>(
+        si: &SegmentCommitInfo<D, C>,
+        dir: Arc<DW>,
+        core_infos: &Arc<FieldInfos>,
+        field_infos: &FieldInfos,
+    ) -> Result<SegmentDocValuesProducer> {
+        let mut producers: Vec<Arc<dyn DocValuesProducer>> = vec![];
+        let mut fields = HashMap::new();
+        let mut base = None;
+        for fi in field_infos.by_number.values() {
+            if fi.doc_values_type == DocValuesType::Null {
+                continue;
+            }
+            let idx = if fi.dv_gen == -1 {
+                if base.is_none() {
+                    let producer = SegmentDocValues::get_doc_values_producer(
+                        -1,
+                        si,
+                        Arc::clone(&dir),
+                        Arc::clone(core_infos),
+                    )?;
+                    producers.push(Arc::from(producer));
+                    base = Some(producers.len() - 1);
+                }
+                base.unwrap()
+            } else {
+                let infos = FieldInfos::new(vec![fi.as_ref().clone()])?;
+                let producer = SegmentDocValues::get_doc_values_producer(
+                    fi.dv_gen,
+                    si,
+                    Arc::clone(&dir),
+                    Arc::new(infos),
+                )?;
+                producers.push(Arc::from(producer));
+                producers.len() - 1
+            };
+            fields.insert(fi.name.clone(), idx);
+        }
+        Ok(SegmentDocValuesProducer { producers, fields })
+    }
+
+    fn producer(&self, field: &FieldInfo) -> Result<&dyn DocValuesProducer> {
+        match self.fields.get(&field.name) {
+            Some(idx) => Ok(self.producers[*idx].as_ref()),
+            None => bail!(IllegalArgument(format!(
+                "field '{}' has no doc values",
+                field.name
+            ))),
+        }
+    }
+}
+
+impl DocValuesProducer for SegmentDocValuesProducer {
+    fn get_numeric(&self, field_info: &FieldInfo) -> Result<Arc<dyn NumericDocValues>> {
+        self.producer(field_info)?.get_numeric(field_info)
+    }
+
+    fn get_binary(&self, field_info: &FieldInfo) -> Result<Arc<dyn BinaryDocValues>> {
+        self.producer(field_info)?.get_binary(field_info)
+    }
+
+    fn get_sorted(&self, field: &FieldInfo) -> Result<Arc<dyn SortedDocValues>> {
+        self.producer(field)?.get_sorted(field)
+    }
+
+    fn get_sorted_numeric(&self, field: &FieldInfo) -> Result<Arc<dyn SortedNumericDocValues>> {
+        self.producer(field)?.get_sorted_numeric(field)
+    }
+
+    fn get_sorted_set(&self, field: &FieldInfo) -> Result<Arc<dyn SortedSetDocValues>> {
+        self.producer(field)?.get_sorted_set(field)
+    }
+
+    fn get_docs_with_field(&self, field: &FieldInfo) -> Result<BitsRef> {
+        self.producer(field)?.get_docs_with_field(field)
+    }
+
+    fn check_integrity(&self) -> Result<()> {
+        for producer in &self.producers {
+            producer.check_integrity()?;
+        }
+        Ok(())
+    }
+
+    fn get_merge_instance(&self) -> Result<Box<dyn DocValuesProducer>> {
+        let mut producers = Vec::with_capacity(self.producers.len());
+        for producer in &self.producers {
+            producers.push(Arc::from(producer.get_merge_instance()?));
+        }
+        Ok(Box::new(SegmentDocValuesProducer {
+            producers,
+            fields: self.fields.clone(),
+        }))
+    }
+}
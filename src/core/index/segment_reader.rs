@@ -29,8 +29,8 @@ use core::{
     index::{
         leaf_reader::LeafReaderContext, BinaryDocValuesRef, CfsDirectory, DocValuesType, FieldInfo,
         FieldInfos, IndexReader, LeafReader, NumericDocValues, NumericDocValuesRef,
-        SegmentCommitInfo, SegmentCoreReaders, SegmentDocValues, SortedDocValuesRef,
-        SortedNumericDocValuesRef, SortedSetDocValuesRef, StoredFieldVisitor,
+        SegmentCommitInfo, SegmentCoreReaders, SegmentDocValues, SegmentDocValuesProducer,
+        SortedDocValuesRef, SortedNumericDocValuesRef, SortedSetDocValuesRef, StoredFieldVisitor,
     },
     search::sort::Sort,
     store::IOContext,
@@ -61,6 +61,8 @@ pub struct SegmentReader<D: Directory, C: Codec> {
     doc_values_local: CachedThreadLocal<RefCell<HashMap<String, DocValuesRefEnum>>>,
 }
 
+unsafe impl<D: Directory + Send + Sync + 'static, C: Codec> Send for SegmentReader<D, C> {}
+
 unsafe impl<D: Directory + Send + Sync + 'static, C: Codec> Sync for SegmentReader<D, C> {}
 
 /// IndexReader implementation over a single segment.
@@ -197,19 +199,7 @@ impl<D: Directory + 'static, C: Codec> SegmentReader<D, C> {
         let core = Arc::new(SegmentCoreReaders::new(&si.info.directory, &si.info, ctx)?);
         let codec = si.info.codec();
         let num_docs = si.info.max_doc() - si.del_count();
-        let field_infos = if !si.has_field_updates() {
-            Arc::clone(&core.core_field_infos)
-        } else {
-            let fis_format = codec.field_infos_format();
-            let segment_suffix = format!("{}", si.field_infos_gen());
-            let field_infos = fis_format.read(
-                si.info.directory.as_ref(),
-                &si.info,
-                &segment_suffix,
-                &IOContext::READ_ONCE,
-            )?;
-            Arc::new(field_infos)
-        };
+        let field_infos = Self::init_field_infos(si.as_ref(), core.as_ref())?;
 
         let live_docs = if si.has_deletions() {
             codec.live_docs_format().read_live_docs(
@@ -255,59 +245,47 @@ impl<D: Directory + 'static, C: Codec> SegmentReader<D, C> {
         field_infos: Arc<FieldInfos>,
     ) -> Result<ThreadLocalDocValueProducer> {
         // initDocValuesProducer: init most recent DocValues for the current commit
-        let dir = match core.cfs_reader {
-            Some(ref d) => Arc::clone(d),
-            None => Arc::new(CfsDirectory::Raw(Arc::clone(&si.info.directory))),
-        };
-
-        let doc_values_producer = if !field_infos.has_doc_values {
-            ThreadLocal::new()
-        } else if si.has_field_updates() {
-            unimplemented!()
-        } else {
-            // simple case, no DocValues updates
-            let dv_producer =
-                SegmentDocValues::get_doc_values_producer(-1_i64, &si, dir, field_infos)?;
-
-            let doc_values_producer = ThreadLocal::new();
+        let doc_values_producer = ThreadLocal::new();
+        if field_infos.has_doc_values {
+            let dv_producer = Self::open_doc_values_producer(core, si, field_infos)?;
             doc_values_producer.get_or(|| Box::new(Arc::from(dv_producer)));
-            doc_values_producer
-        };
+        }
         Ok(doc_values_producer)
     }
 
     fn init_local_doc_values_producer(&self) -> Result<()> {
-        if self.field_infos.has_doc_values {
-            if self.si.has_field_updates() {
-                unimplemented!()
-            } else {
-                if self.doc_values_producer.get().is_some() {
-                    return Ok(());
-                }
-
-                let dv_producer = if let Some(ref cfs_dir) = self.core.cfs_reader {
-                    SegmentDocValues::get_doc_values_producer(
-                        -1_i64,
-                        &self.si,
-                        Arc::clone(cfs_dir),
-                        Arc::clone(&self.field_infos),
-                    )?
-                } else {
-                    SegmentDocValues::get_doc_values_producer(
-                        -1_i64,
-                        &self.si,
-                        Arc::clone(&self.si.info.directory),
-                        Arc::clone(&self.field_infos),
-                    )?
-                };
-
-                self.doc_values_producer
-                    .get_or(|| Box::new(Arc::from(dv_producer)));
-            }
+        if self.field_infos.has_doc_values && self.doc_values_producer.get().is_none() {
+            let dv_producer = Self::open_doc_values_producer(
+                self.core.as_ref(),
+                self.si.as_ref(),
+                Arc::clone(&self.field_infos),
+            )?;
+            self.doc_values_producer
+                .get_or(|| Box::new(Arc::from(dv_producer)));
         }
         Ok(())
     }
 
+    fn open_doc_values_producer(
+        core: &SegmentCoreReaders<D, C>,
+        si: &SegmentCommitInfo<D, C>,
+        field_infos: Arc<FieldInfos>,
+    ) -> Result<Box<dyn DocValuesProducer>> {
+        let dir = match core.cfs_reader {
+            Some(ref d) => Arc::clone(d),
+            None => Arc::new(CfsDirectory::Raw(Arc::clone(&si.info.directory))),
+        };
+        if si.has_field_updates() {
+            // the updated fields are read from their own generation
+            let producer =
+                SegmentDocValuesProducer::new(si, dir, &core.core_field_infos, &field_infos)?;
+            Ok(Box::new(producer))
+        } else {
+            // simple case, no DocValues updates
+            SegmentDocValues::get_doc_values_producer(-1_i64, si, dir, field_infos)
+        }
+    }
+
     fn init_field_infos<C1: Codec>(
         si: &SegmentCommitInfo<D, C1>,
         core: &SegmentCoreReaders<D, C1>,
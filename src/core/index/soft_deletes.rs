@@ -0,0 +1,205 @@
+// Copyright 2019 Zhizhesihai (Beijing) Technology Limited.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use core::codec::{Codec, CodecTVFields};
+use core::doc::Document;
+use core::index::leaf_reader::{LeafReaderContext, SearchLeafReader};
+use core::index::merge_policy::MergePolicy;
+use core::index::merge_scheduler::MergeScheduler;
+use core::index::{DocValuesType, IndexReader, LeafReader, OrdinalMap};
+use core::index::{SegmentReader, StandardDirectoryReader};
+use core::store::Directory;
+use core::util::bit_set::{BitSet, FixedBitSet};
+use core::util::{Bits, DocId};
+
+use error::Result;
+
+use std::sync::Arc;
+
+/// Removes the documents having a value for the soft-deletes `field` from
+/// `live_docs`, returning the resulting live docs of the segment along with the
+/// number of live documents that were soft deleted.
+///
+/// Returns `None` if the segment has no doc values for `field`.
+pub(crate) fn soft_deletes_live_docs<D: Directory + 'static, C: Codec>(
+    reader: &SegmentReader<D, C>,
+    live_docs: &dyn Bits,
+    field: &str,
+) -> Result<Option<(FixedBitSet, i32)>> {
+    match reader.field_info(field) {
+        Some(fi) if fi.doc_values_type != DocValuesType::Null => {}
+        _ => {
+            return Ok(None);
+        }
+    }
+    let soft_deleted = reader.get_docs_with_field(field)?;
+    let max_doc = reader.max_docs() as usize;
+    let mut bits = FixedBitSet::new(max_doc);
+    let mut num_soft_deleted = 0;
+    for doc in 0..max_doc {
+        if live_docs.get(doc)? {
+            if soft_deleted.get(doc)? {
+                num_soft_deleted += 1;
+            } else {
+                bits.set(doc);
+            }
+        }
+    }
+    Ok(Some((bits, num_soft_deleted)))
+}
+
+/// A `StandardDirectoryReader` hiding the soft deleted documents.
+///
+/// A document is soft deleted when it has a value for the soft-deletes doc
+/// values field, see `IndexWriterConfig::soft_deletes_field`. The live docs
+/// of every segment of this reader are the hard live docs of the segment
+/// minus its soft deleted documents, so searches skip them as they skip the
+/// documents deleted by the writer, while the wrapped reader still sees them.
+pub struct SoftDeletesDirectoryReader<
+    D: Directory + Send + Sync + 'static,
+    C: Codec,
+    MS: MergeScheduler,
+    MP: MergePolicy,
+> {
+    reader: StandardDirectoryReader<D, C, MS, MP>,
+    field: String,
+    readers: Vec<Arc<SegmentReader<D, C>>>,
+    starts: Vec<i32>,
+    num_docs: i32,
+}
+
+impl<D, C, MS, MP> SoftDeletesDirectoryReader<D, C, MS, MP>
+where
+    D: Directory + Send + Sync + 'static,
+    C: Codec,
+    MS: MergeScheduler,
+    MP: MergePolicy,
+{
+    pub fn new(reader: StandardDirectoryReader<D, C, MS, MP>, field: &str) -> Result<Self> {
+        let segment_readers = reader.segment_readers();
+        let mut readers = Vec::with_capacity(segment_readers.len());
+        let mut starts = Vec::with_capacity(segment_readers.len() + 1);
+        let mut max_doc = 0;
+        let mut num_docs = 0;
+        for sr in segment_readers {
+            starts.push(max_doc);
+            max_doc += sr.max_docs();
+            let sr = match soft_deletes_live_docs(sr.as_ref(), sr.live_docs.as_ref(), field)? {
+                Some((live_docs, num_soft_deleted)) if num_soft_deleted > 0 => {
+                    Arc::new(SegmentReader::build_from(
+                        Arc::clone(&sr.si),
+                        sr.as_ref(),
+                        Arc::new(live_docs),
+                        sr.num_docs() - num_soft_deleted,
+                        sr.is_nrt,
+                    )?)
+                }
+                _ => Arc::clone(sr),
+            };
+            num_docs += sr.num_docs();
+            readers.push(sr);
+        }
+        starts.push(max_doc);
+
+        Ok(SoftDeletesDirectoryReader {
+            reader,
+            field: field.to_string(),
+            readers,
+            starts,
+            num_docs,
+        })
+    }
+
+    pub fn soft_deletes_field(&self) -> &str {
+        &self.field
+    }
+
+    /// The wrapped reader, which doesn't ignore the soft deleted documents.
+    pub fn raw_reader(&self) -> &StandardDirectoryReader<D, C, MS, MP> {
+        &self.reader
+    }
+
+    pub fn into_raw_reader(self) -> StandardDirectoryReader<D, C, MS, MP> {
+        self.reader
+    }
+}
+
+impl<D, C, MS, MP> IndexReader for SoftDeletesDirectoryReader<D, C, MS, MP>
+where
+    D: Directory + Send + Sync + 'static,
+    C: Codec,
+    MS: MergeScheduler,
+    MP: MergePolicy,
+{
+    type Codec = C;
+    fn ensure_open(&self) -> Result<()> {
+        self.reader.ensure_open()
+    }
+
+    fn leaves(&self) -> Vec<LeafReaderContext<'_, C>> {
+        if self.ensure_open().is_err() {
+            return vec![];
+        }
+        self.readers
+            .iter()
+            .enumerate()
+            .map(|(i, r)| {
+                LeafReaderContext::new(self, r.as_ref() as &SearchLeafReader<C>, i, self.starts[i])
+            })
+            .collect()
+    }
+
+    fn term_vector(&self, doc_id: DocId) -> Result<Option<CodecTVFields<C>>> {
+        self.reader.term_vector(doc_id)
+    }
+
+    fn document(&self, doc_id: DocId, fields_load: &[String]) -> Result<Document> {
+        self.reader.document(doc_id, fields_load)
+    }
+
+    fn max_doc(&self) -> i32 {
+        self.reader.max_doc()
+    }
+
+    fn num_docs(&self) -> i32 {
+        self.num_docs
+    }
+
+    fn ordinal_map(&self, field: &str) -> Result<Option<Arc<OrdinalMap>>> {
+        // the same segments as the wrapped reader, whose maps are cached
+        self.reader.ordinal_map(field)
+    }
+
+    fn refresh(&self) -> Result<Option<Box<dyn IndexReader<Codec = C> + Send + Sync>>> {
+        if let Some(reader) = self.reader.open_if_changed(None)? {
+            Ok(Some(Box::new(SoftDeletesDirectoryReader::new(
+                reader,
+                &self.field,
+            )?)))
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+impl<D, C, MS, MP> AsRef<IndexReader<Codec = C>> for SoftDeletesDirectoryReader<D, C, MS, MP>
+where
+    D: Directory + Send + Sync + 'static,
+    C: Codec,
+    MS: MergeScheduler,
+    MP: MergePolicy,
+{
+    fn as_ref(&self) -> &(IndexReader<Codec = C> + 'static) {
+        self
+    }
+}
@@ -13,10 +13,14 @@
 
 use core::codec::Codec;
 use core::index::merge_state::{LiveDocsDocMap, ReaderWrapperEnum};
-use core::index::{LeafReader, LeafReaderContext, NumericDocValues, NumericDocValuesRef};
+use core::index::{
+    LeafReader, LeafReaderContext, NumericDocValues, NumericDocValuesRef, SortedDocValuesRef,
+};
 use core::search::field_comparator::{ComparatorValue, FieldComparator, FieldComparatorEnum};
 use core::search::sort::Sort;
-use core::search::sort_field::{SortField, SortFieldType, SortedNumericSelector};
+use core::search::sort_field::{
+    SortField, SortFieldMissingValue, SortFieldType, SortedNumericSelector,
+};
 use core::util::packed::{
     PackedLongValues, PackedLongValuesBuilder, PackedLongValuesBuilderType, DEFAULT_PAGE_SIZE,
 };
@@ -63,7 +67,7 @@ impl Sorter {
     }
 
     /// Computes the old-to-new permutation over the given comparator.
-    fn sort(
+    pub(crate) fn sort(
         max_doc: DocId,
         comparator: &mut impl SorterDocComparator,
     ) -> Result<Option<PackedLongDocMap>> {
@@ -221,7 +225,7 @@ pub trait SorterDocMap {
 }
 
 /// a comparator of doc IDs
-trait SorterDocComparator {
+pub(crate) trait SorterDocComparator {
     fn compare(&mut self, doc1: DocId, doc2: DocId) -> Result<Ordering>;
 }
 
@@ -340,7 +344,16 @@ impl MultiSorter {
         let reverse = sort_field.is_reverse();
         let field_type = Sorter::sort_field_type(sort_field);
         match field_type {
-            SortFieldType::String => unimplemented!(),
+            SortFieldType::String => {
+                let mut values = Vec::with_capacity(readers.len());
+                for reader in readers {
+                    values.push(reader.get_sorted_doc_values(sort_field.field())?);
+                }
+                let missing_last = sort_field.string_missing() == SortFieldMissingValue::StringLast;
+                Ok(CrossReaderComparatorEnum::String(
+                    StringCrossReaderComparator::new(values, missing_last, reverse),
+                ))
+            }
             SortFieldType::Long | SortFieldType::Int => {
                 let mut values = Vec::with_capacity(readers.len());
                 let mut docs_with_fields = Vec::with_capacity(readers.len());
@@ -391,6 +404,7 @@ impl MultiSorter {
                         docs_with_fields,
                         values,
                         missing_value,
+                        field_type == SortFieldType::Float,
                         reverse,
                     ),
                 ))
@@ -468,6 +482,7 @@ impl<'a> PartialOrd for LeafAndDocId<'a> {
 }
 
 enum CrossReaderComparatorEnum {
+    String(StringCrossReaderComparator),
     Long(LongCrossReaderComparator),
     Double(DoubleCrossReaderComparator),
 }
@@ -481,6 +496,9 @@ impl CrossReaderComparator for CrossReaderComparatorEnum {
         doc_id2: DocId,
     ) -> Result<Ordering> {
         match self {
+            CrossReaderComparatorEnum::String(s) => {
+                s.compare(reader_index1, doc_id1, reader_index2, doc_id2)
+            }
             CrossReaderComparatorEnum::Long(l) => {
                 l.compare(reader_index1, doc_id1, reader_index2, doc_id2)
             }
@@ -501,6 +519,54 @@ trait CrossReaderComparator {
     ) -> Result<Ordering>;
 }
 
+struct StringCrossReaderComparator {
+    values: Vec<SortedDocValuesRef>,
+    missing_last: bool,
+    reverse: bool,
+}
+
+impl StringCrossReaderComparator {
+    fn new(values: Vec<SortedDocValuesRef>, missing_last: bool, reverse: bool) -> Self {
+        StringCrossReaderComparator {
+            values,
+            missing_last,
+            reverse,
+        }
+    }
+}
+
+impl CrossReaderComparator for StringCrossReaderComparator {
+    fn compare(
+        &self,
+        idx1: usize,
+        doc_id1: DocId,
+        idx2: usize,
+        doc_id2: DocId,
+    ) -> Result<Ordering> {
+        let ord1 = self.values[idx1].get_ord(doc_id1)?;
+        let ord2 = self.values[idx2].get_ord(doc_id2)?;
+        let res = match (ord1 < 0, ord2 < 0) {
+            (true, true) => Ordering::Equal,
+            (true, false) if self.missing_last => Ordering::Greater,
+            (true, false) => Ordering::Less,
+            (false, true) if self.missing_last => Ordering::Less,
+            (false, true) => Ordering::Greater,
+            // the ords of a segment follow the order of its values
+            (false, false) if idx1 == idx2 => ord1.cmp(&ord2),
+            (false, false) => {
+                let value1 = self.values[idx1].lookup_ord(ord1)?;
+                let value2 = self.values[idx2].lookup_ord(ord2)?;
+                value1.cmp(&value2)
+            }
+        };
+        if self.reverse {
+            Ok(res.reverse())
+        } else {
+            Ok(res)
+        }
+    }
+}
+
 struct LongCrossReaderComparator {
     docs_with_fields: Vec<BitsRef>,
     values: Vec<NumericDocValuesRef>,
@@ -555,6 +621,8 @@ struct DoubleCrossReaderComparator {
     docs_with_fields: Vec<BitsRef>,
     values: Vec<NumericDocValuesRef>,
     missing_value: f64,
+    // the values are the bits of floats rather than of doubles
+    float_values: bool,
     reverse: bool,
 }
 
@@ -563,15 +631,30 @@ impl DoubleCrossReaderComparator {
         docs_with_fields: Vec<BitsRef>,
         values: Vec<NumericDocValuesRef>,
         missing_value: f64,
+        float_values: bool,
         reverse: bool,
     ) -> Self {
         DoubleCrossReaderComparator {
             docs_with_fields,
             values,
             missing_value,
+            float_values,
             reverse,
         }
     }
+
+    fn value(&self, idx: usize, doc_id: DocId) -> Result<f64> {
+        if self.docs_with_fields[idx].get(doc_id as usize)? {
+            let bits = self.values[idx].get(doc_id)?;
+            if self.float_values {
+                Ok(f64::from(f32::from_bits(bits as u32)))
+            } else {
+                Ok(f64::from_bits(bits as u64))
+            }
+        } else {
+            Ok(self.missing_value)
+        }
+    }
 }
 
 impl CrossReaderComparator for DoubleCrossReaderComparator {
@@ -582,16 +665,8 @@ impl CrossReaderComparator for DoubleCrossReaderComparator {
         idx2: usize,
         doc_id2: DocId,
     ) -> Result<Ordering> {
-        let value1 = if self.docs_with_fields[idx1].get(doc_id1 as usize)? {
-            f64::from_bits(self.values[idx1].get(doc_id1)? as u64)
-        } else {
-            self.missing_value
-        };
-        let value2 = if self.docs_with_fields[idx2].get(doc_id2 as usize)? {
-            f64::from_bits(self.values[idx2].get(doc_id2)? as u64)
-        } else {
-            self.missing_value
-        };
+        let value1 = self.value(idx1, doc_id1)?;
+        let value2 = self.value(idx2, doc_id2)?;
         let res = value1.partial_cmp(&value2).unwrap();
         if self.reverse {
             Ok(res.reverse())
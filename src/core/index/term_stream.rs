@@ -0,0 +1,418 @@
+// Copyright 2019 Zhizhesihai (Beijing) Technology Limited.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use core::index::{SeekStatus, TermIterator};
+use error::ErrorKind::IllegalState;
+use error::Result;
+
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+
+/// Adapts a `TermIterator` to `std::iter::Iterator`, so that terms can be consumed
+/// with the standard iterator combinators instead of `while let` loops.
+///
+/// Errors of the underlying iterator are returned as items, after which the
+/// stream ends.
+pub struct TermStream<T: TermIterator> {
+    iter: Rc<RefCell<T>>,
+    // bumped every time the underlying iterator moves, so that stale
+    // `TermEntry`s can't read the stats of another term
+    position: Rc<Cell<u64>>,
+    // term the iterator is positioned on by `seek` but not returned yet
+    pending: Option<Vec<u8>>,
+    error: Option<::error::Error>,
+    done: bool,
+}
+
+/// Extension trait to build a `TermStream` from any `TermIterator`.
+pub trait TermIteratorExt: TermIterator + Sized {
+    fn into_stream(self) -> TermStream<Self> {
+        TermStream::new(self)
+    }
+}
+
+impl<T: TermIterator> TermIteratorExt for T {}
+
+impl<T: TermIterator> TermStream<T> {
+    pub fn new(iter: T) -> TermStream<T> {
+        TermStream {
+            iter: Rc::new(RefCell::new(iter)),
+            position: Rc::new(Cell::new(0)),
+            pending: None,
+            error: None,
+            done: false,
+        }
+    }
+
+    /// Positions the stream so that the next term returned is the smallest
+    /// term greater than or equal to `from`.
+    pub fn seek(mut self, from: &[u8]) -> TermStream<T> {
+        self.pending = None;
+        self.done = false;
+        self.position.set(self.position.get() + 1);
+        let result = {
+            let mut iter = self.iter.borrow_mut();
+            iter.seek_ceil(from).and_then(|status| match status {
+                SeekStatus::End => Ok(None),
+                _ => iter.term().map(|t| Some(t.to_vec())),
+            })
+        };
+        match result {
+            Ok(Some(term)) => self.pending = Some(term),
+            Ok(None) => self.done = true,
+            Err(e) => self.error = Some(e),
+        }
+        self
+    }
+
+    /// Returns the terms while they start with `prefix`, errors are passed through.
+    pub fn take_while_prefix(self, prefix: &[u8]) -> impl Iterator<Item = Result<TermEntry<T>>> {
+        let prefix = prefix.to_vec();
+        self.take_while(move |entry| match entry {
+            Ok(entry) => entry.term().starts_with(&prefix),
+            Err(_) => true,
+        })
+    }
+
+    fn entry(&self, term: Vec<u8>) -> TermEntry<T> {
+        TermEntry {
+            term,
+            position: self.position.get(),
+            iter: Rc::clone(&self.iter),
+            current: Rc::clone(&self.position),
+        }
+    }
+}
+
+impl<T: TermIterator> Iterator for TermStream<T> {
+    type Item = Result<TermEntry<T>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(e) = self.error.take() {
+            self.done = true;
+            return Some(Err(e));
+        }
+        if self.done {
+            return None;
+        }
+        if let Some(term) = self.pending.take() {
+            return Some(Ok(self.entry(term)));
+        }
+
+        self.position.set(self.position.get() + 1);
+        let next = self.iter.borrow_mut().next();
+        match next {
+            Ok(Some(term)) => Some(Ok(self.entry(term))),
+            Ok(None) => {
+                self.done = true;
+                None
+            }
+            Err(e) => {
+                self.done = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+/// A term returned by `TermStream`.
+///
+/// The term statistics are only decoded when asked for, and only while the
+/// stream is still positioned on this term.
+pub struct TermEntry<T: TermIterator> {
+    term: Vec<u8>,
+    position: u64,
+    iter: Rc<RefCell<T>>,
+    current: Rc<Cell<u64>>,
+}
+
+impl<T: TermIterator> TermEntry<T> {
+    pub fn term(&self) -> &[u8] {
+        &self.term
+    }
+
+    pub fn into_term(self) -> Vec<u8> {
+        self.term
+    }
+
+    /// Returns the number of documents containing this term.
+    pub fn doc_freq(&self) -> Result<i32> {
+        self.ensure_current()?;
+        self.iter.borrow_mut().doc_freq()
+    }
+
+    /// Returns the total number of occurrences of this term, or -1 if the codec
+    /// doesn't support this measure.
+    pub fn total_term_freq(&self) -> Result<i64> {
+        self.ensure_current()?;
+        self.iter.borrow_mut().total_term_freq()
+    }
+
+    fn ensure_current(&self) -> Result<()> {
+        if self.position != self.current.get() {
+            bail!(IllegalState(format!(
+                "term {:?} is no longer the current term of the stream",
+                self.term
+            )));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::doc::{Field, FieldType};
+    use core::index::tests::{open_test_reader, TestReader};
+    use core::index::{IndexOptions, IndexReader, LeafReader};
+    use core::index::{Terms, UnreachableTermState};
+    use core::search::posting_iterator::EmptyPostingIterator;
+    use core::util::VariantValue;
+    use error::ErrorKind::IllegalArgument;
+
+    use std::sync::Arc;
+
+    struct MockTermIterator {
+        terms: Vec<(Vec<u8>, i32)>,
+        // index of the current term, terms.len() is unpositioned
+        current: usize,
+        stats_reads: Rc<Cell<usize>>,
+        fail_at: Option<usize>,
+    }
+
+    impl MockTermIterator {
+        fn new(terms: &[&str], stats_reads: Rc<Cell<usize>>) -> MockTermIterator {
+            let terms = terms
+                .iter()
+                .enumerate()
+                .map(|(i, t)| (t.as_bytes().to_vec(), i as i32 + 1))
+                .collect::<Vec<_>>();
+            let current = terms.len();
+            MockTermIterator {
+                terms,
+                current,
+                stats_reads,
+                fail_at: None,
+            }
+        }
+    }
+
+    impl TermIterator for MockTermIterator {
+        type Postings = EmptyPostingIterator;
+        type TermState = UnreachableTermState;
+
+        fn next(&mut self) -> Result<Option<Vec<u8>>> {
+            self.current = if self.current == self.terms.len() {
+                0
+            } else {
+                self.current + 1
+            };
+            if Some(self.current) == self.fail_at {
+                bail!(IllegalArgument("broken term".into()));
+            }
+            Ok(self.terms.get(self.current).map(|t| t.0.clone()))
+        }
+
+        fn seek_ceil(&mut self, text: &[u8]) -> Result<SeekStatus> {
+            match self.terms.iter().position(|t| t.0.as_slice() >= text) {
+                Some(i) => {
+                    self.current = i;
+                    if self.terms[i].0.as_slice() == text {
+                        Ok(SeekStatus::Found)
+                    } else {
+                        Ok(SeekStatus::NotFound)
+                    }
+                }
+                None => {
+                    self.current = self.terms.len();
+                    Ok(SeekStatus::End)
+                }
+            }
+        }
+
+        fn seek_exact_ord(&mut self, ord: i64) -> Result<()> {
+            self.current = ord as usize;
+            Ok(())
+        }
+
+        fn term(&self) -> Result<&[u8]> {
+            Ok(&self.terms[self.current].0)
+        }
+
+        fn ord(&self) -> Result<i64> {
+            Ok(self.current as i64)
+        }
+
+        fn doc_freq(&mut self) -> Result<i32> {
+            self.stats_reads.set(self.stats_reads.get() + 1);
+            Ok(self.terms[self.current].1)
+        }
+
+        fn total_term_freq(&mut self) -> Result<i64> {
+            self.stats_reads.set(self.stats_reads.get() + 1);
+            Ok(i64::from(self.terms[self.current].1) * 2)
+        }
+
+        fn postings_with_flags(&mut self, _flags: u16) -> Result<Self::Postings> {
+            Ok(EmptyPostingIterator::default())
+        }
+    }
+
+    const TERMS: [&str; 6] = ["apple", "apricot", "banana", "blueberry", "cherry", "date"];
+
+    #[test]
+    fn test_term_stream_matches_manual_loop() {
+        let reads = Rc::new(Cell::new(0));
+        let mut manual = vec![];
+        let mut iter = MockTermIterator::new(&TERMS, Rc::clone(&reads));
+        while let Some(term) = iter.next().unwrap() {
+            manual.push(term);
+        }
+
+        let streamed: Vec<Vec<u8>> = MockTermIterator::new(&TERMS, Rc::clone(&reads))
+            .into_stream()
+            .map(|e| e.unwrap().into_term())
+            .collect();
+        assert_eq!(streamed, manual);
+        // no stats were decoded
+        assert_eq!(reads.get(), 0);
+
+        let frequent: Vec<Vec<u8>> = MockTermIterator::new(&TERMS, Rc::clone(&reads))
+            .into_stream()
+            .filter(|e| e.as_ref().unwrap().doc_freq().unwrap() % 2 == 0)
+            .take(2)
+            .map(|e| e.unwrap().into_term())
+            .collect();
+        assert_eq!(frequent, vec![b"apricot".to_vec(), b"blueberry".to_vec()]);
+        assert_eq!(reads.get(), 4);
+    }
+
+    #[test]
+    fn test_term_stream_seek_and_prefix() {
+        let reads = Rc::new(Cell::new(0));
+        let terms: Vec<Vec<u8>> = MockTermIterator::new(&TERMS, Rc::clone(&reads))
+            .into_stream()
+            .seek(b"b")
+            .take_while_prefix(b"b")
+            .map(|e| e.unwrap().into_term())
+            .collect();
+        assert_eq!(terms, vec![b"banana".to_vec(), b"blueberry".to_vec()]);
+
+        let mut stream = MockTermIterator::new(&TERMS, Rc::clone(&reads))
+            .into_stream()
+            .seek(b"cherry");
+        let cherry = stream.next().unwrap().unwrap();
+        assert_eq!(cherry.term(), b"cherry");
+        assert_eq!(cherry.total_term_freq().unwrap(), 10);
+        let date = stream.next().unwrap().unwrap();
+        // the stream moved on
+        assert!(cherry.doc_freq().is_err());
+        assert_eq!(date.doc_freq().unwrap(), 6);
+        assert!(stream.next().is_none());
+
+        let mut stream = MockTermIterator::new(&TERMS, reads)
+            .into_stream()
+            .seek(b"z");
+        assert!(stream.next().is_none());
+    }
+
+    #[test]
+    fn test_term_stream_surfaces_errors() {
+        let mut iter = MockTermIterator::new(&TERMS, Rc::new(Cell::new(0)));
+        iter.fail_at = Some(2);
+        let items: Vec<Result<TermEntry<MockTermIterator>>> = iter.into_stream().collect();
+        assert_eq!(items.len(), 3);
+        assert!(items[0].is_ok());
+        assert!(items[1].is_ok());
+        assert!(items[2].is_err());
+    }
+
+    // doc `i` has the terms `TERMS[i..]`, each one `i + 1` times
+    fn open_reader() -> Arc<TestReader> {
+        let mut field_type = FieldType::default();
+        field_type.index_options = IndexOptions::DocsAndFreqs;
+        let docs = (0..TERMS.len())
+            .map(|i| {
+                let words: Vec<&str> = TERMS[i..]
+                    .iter()
+                    .flat_map(|t| ::std::iter::repeat(*t).take(i + 1))
+                    .collect();
+                vec![Field::new(
+                    "body".to_string(),
+                    field_type.clone(),
+                    Some(VariantValue::VString(words.join(" "))),
+                    None,
+                )]
+            })
+            .collect();
+        open_test_reader(vec![docs])
+    }
+
+    #[test]
+    fn test_term_stream_over_index_terms() {
+        let reader = open_reader();
+        let leaves = reader.leaves();
+        assert_eq!(leaves.len(), 1);
+        let terms = leaves[0].reader.terms("body").unwrap().unwrap();
+
+        let mut manual = vec![];
+        let mut iter = terms.iterator().unwrap();
+        while let Some(term) = iter.next().unwrap() {
+            manual.push((
+                term,
+                iter.doc_freq().unwrap(),
+                iter.total_term_freq().unwrap(),
+            ));
+        }
+        assert_eq!(manual.len(), TERMS.len());
+
+        let streamed: Vec<(Vec<u8>, i32, i64)> = terms
+            .iterator()
+            .unwrap()
+            .into_stream()
+            .map(|e| {
+                let e = e.unwrap();
+                let doc_freq = e.doc_freq().unwrap();
+                let total_term_freq = e.total_term_freq().unwrap();
+                (e.into_term(), doc_freq, total_term_freq)
+            })
+            .collect();
+        assert_eq!(streamed, manual);
+        // `date` is in every doc, i + 1 times in doc i
+        assert_eq!(streamed[5], (b"date".to_vec(), 6, 21));
+
+        let b_terms: Vec<Vec<u8>> = terms
+            .iterator()
+            .unwrap()
+            .into_stream()
+            .seek(b"b")
+            .take_while_prefix(b"b")
+            .map(|e| e.unwrap().into_term())
+            .collect();
+        assert_eq!(b_terms, vec![b"banana".to_vec(), b"blueberry".to_vec()]);
+
+        let mut stream = terms.iterator().unwrap().into_stream().seek(b"cherry");
+        let cherry = stream.next().unwrap().unwrap();
+        assert_eq!(cherry.doc_freq().unwrap(), 5);
+        stream.next().unwrap().unwrap();
+        assert!(cherry.doc_freq().is_err());
+        assert!(stream.next().is_none());
+        assert!(terms
+            .iterator()
+            .unwrap()
+            .into_stream()
+            .seek(b"z")
+            .next()
+            .is_none());
+    }
+}
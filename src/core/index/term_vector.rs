@@ -12,17 +12,20 @@
 // limitations under the License.
 
 use core::analysis::TokenStream;
-use core::codec::{Codec, TermVectorsFormat, TermVectorsWriter, TermVectorsWriterEnum};
+use core::codec::{
+    Codec, TermVectorsFormat, TermVectorsReader, TermVectorsWriter, TermVectorsWriterEnum,
+};
 use core::index::byte_slice_reader::ByteSliceReader;
 use core::index::merge_policy::MergePolicy;
 use core::index::merge_scheduler::MergeScheduler;
 use core::index::postings_array::{ParallelPostingsArray, PostingsArray};
+use core::index::sorter::{PackedLongDocMap, SorterDocMap};
 use core::index::terms_hash::{TermsHash, TermsHashBase};
 use core::index::terms_hash_per_field::{TermsHashPerField, TermsHashPerFieldBase};
 use core::index::thread_doc_writer::DocumentsWriterPerThread;
 use core::index::{
     FieldInfo, FieldInfosBuilder, FieldInvertState, FieldNumbersRef, Fieldable, IndexOptions,
-    SegmentWriteState,
+    SegmentInfo, SegmentWriteState,
 };
 use core::store::{Directory, FlushInfo, IOContext};
 use core::util::DocId;
@@ -32,6 +35,7 @@ use error::{ErrorKind, Result};
 use std::cmp::Ordering;
 use std::collections::BTreeMap;
 use std::ptr;
+use std::sync::Arc;
 
 pub struct TermVectorsConsumer<
     D: Directory + Send + Sync + 'static,
@@ -111,9 +115,14 @@ where
                 doc_writer.num_docs_in_ram,
                 doc_writer.bytes_used() as u64,
             ));
+            // with an index sort, the vectors are sorted on flush
+            let segment_info = doc_writer
+                .unsorted_segment_info
+                .as_ref()
+                .unwrap_or(&doc_writer.segment_info);
             self.writer = Some(doc_writer.codec().term_vectors_format().tv_writer(
                 &*doc_writer.directory,
-                &doc_writer.segment_info,
+                segment_info,
                 &context,
             )?);
             self.last_doc_id = 0;
@@ -141,6 +150,7 @@ where
         &mut self,
         _field_to_flush: BTreeMap<&str, &TermVectorsConsumerPerField<D, C, MS, MP>>,
         state: &mut SegmentWriteState<D, DW, C>,
+        sort_map: Option<&Arc<PackedLongDocMap>>,
     ) -> Result<()> {
         if self.writer.is_some() {
             let num_docs = state.segment_info.max_doc;
@@ -151,10 +161,46 @@ where
                 .as_mut()
                 .unwrap()
                 .finish(&state.field_infos, num_docs as usize)?;
+            let doc_writer = unsafe { &*self.doc_writer };
+            if let Some(ref unsorted) = doc_writer.unsorted_segment_info {
+                self.writer = None;
+                self.sort_term_vectors(state, unsorted, sort_map)?;
+            }
         }
         Ok(())
     }
 
+    /// Copies the vectors written to `unsorted` to the flushed segment, in the
+    /// order of the index sort.
+    fn sort_term_vectors<DW: Directory>(
+        &self,
+        state: &SegmentWriteState<D, DW, C>,
+        unsorted: &SegmentInfo<D, C>,
+        sort_map: Option<&Arc<PackedLongDocMap>>,
+    ) -> Result<()> {
+        let doc_writer = unsafe { &*self.doc_writer };
+        let format = doc_writer.codec().term_vectors_format();
+        let num_docs = state.segment_info.max_doc;
+        let reader = format.tv_reader(
+            &*doc_writer.directory,
+            unsorted,
+            Arc::new(state.field_infos.clone()),
+            &IOContext::READ,
+        )?;
+        let context = IOContext::Flush(FlushInfo::new(
+            num_docs as u32,
+            doc_writer.bytes_used() as u64,
+        ));
+        let mut writer =
+            format.tv_writer(&*doc_writer.directory, &doc_writer.segment_info, &context)?;
+        for doc in 0..num_docs {
+            let old_doc = sort_map.map_or(doc, |m| m.new_to_old(doc));
+            let vectors = reader.get(old_doc)?;
+            writer.add_all_doc_vectors(vectors.as_ref(), &state.field_infos)?;
+        }
+        writer.finish(&state.field_infos, num_docs as usize)
+    }
+
     fn reset_field(&mut self) {
         self.per_fields.truncate(0); // don't hang onto stuff from previous doc
         self.num_vector_fields = 0;
@@ -198,8 +244,9 @@ where
         &mut self,
         field_to_flush: BTreeMap<&str, &TermVectorsConsumerPerField<D, C, MS, MP>>,
         state: &mut SegmentWriteState<D, DW, C>,
+        sort_map: Option<&Arc<PackedLongDocMap>>,
     ) -> Result<()> {
-        let res = self.do_flush(field_to_flush, state);
+        let res = self.do_flush(field_to_flush, state, sort_map);
         self.writer = None;
         self.last_doc_id = 0;
         self.has_vectors = false;
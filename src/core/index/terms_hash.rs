@@ -13,13 +13,15 @@
 
 use core::codec::{Codec, FieldsConsumer, PostingsFormat};
 use core::index::byte_slice_reader::ByteSliceReader;
+use core::index::merge::MergedFields;
 use core::index::merge_policy::MergePolicy;
 use core::index::merge_scheduler::MergeScheduler;
+use core::index::sorter::PackedLongDocMap;
 use core::index::term::{SeekStatus, TermIterator, Terms, UnreachableTermState};
 use core::index::term_vector::TermVectorsConsumer;
 use core::index::terms_hash_per_field::{FreqProxTermsWriterPerField, TermsHashPerField};
 use core::index::thread_doc_writer::DocumentsWriterPerThread;
-use core::index::SegmentWriteState;
+use core::index::{DocMapBySortDocMap, SegmentWriteState};
 use core::index::{
     FieldInfo, FieldInfosBuilder, FieldInvertState, FieldNumbersRef, Fields, IndexOptions,
 };
@@ -35,6 +37,7 @@ use std::borrow::Cow;
 use std::cmp::Ordering;
 use std::collections::BTreeMap;
 use std::ptr;
+use std::sync::Arc;
 
 use error::{ErrorKind, Result};
 
@@ -126,6 +129,7 @@ pub trait TermsHash<D: Directory, C: Codec> {
         &mut self,
         field_to_flush: BTreeMap<&str, &Self::PerField>,
         state: &mut SegmentWriteState<D, DW, C>,
+        sort_map: Option<&Arc<PackedLongDocMap>>,
     ) -> Result<()>;
 
     fn abort(&mut self) -> Result<()> {
@@ -271,12 +275,14 @@ where
         &mut self,
         field_to_flush: BTreeMap<&str, &Self::PerField>,
         state: &mut SegmentWriteState<D, DW, C>,
+        sort_map: Option<&Arc<PackedLongDocMap>>,
     ) -> Result<()> {
         let mut next_child_fields = BTreeMap::new();
         for (k, v) in &field_to_flush {
             next_child_fields.insert(*k, &(*v).next_per_field);
         }
-        self.next_terms_hash.flush(next_child_fields, state)?;
+        self.next_terms_hash
+            .flush(next_child_fields, state, sort_map)?;
 
         // Gather all fields that saw any positions:
         let mut all_fields = Vec::with_capacity(field_to_flush.len());
@@ -301,6 +307,7 @@ where
             all_fields.sort();
             let fields = FreqProxFields::new(all_fields);
 
+            // the delete terms limits are unsorted doc ids
             apply_deletes(state, &fields)?;
 
             let mut consumer = state
@@ -309,7 +316,13 @@ where
                 .postings_format()
                 .fields_consumer(state)?;
 
-            consumer.write(&fields)
+            match sort_map {
+                Some(sort_map) => {
+                    let doc_map = DocMapBySortDocMap::new(Arc::clone(sort_map));
+                    consumer.write(&MergedFields::new(vec![fields], vec![doc_map])?)
+                }
+                None => consumer.write(&fields),
+            }
         } else {
             Ok(())
         }
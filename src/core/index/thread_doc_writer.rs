@@ -16,19 +16,27 @@ use core::{
     index::{
         bufferd_updates::{self, BufferedUpdates, FrozenBufferedUpdates},
         doc_consumer::{DefaultIndexingChain, DocConsumer},
-        doc_writer_delete_queue::{DeleteSlice, DocumentsWriterDeleteQueue},
+        doc_writer_delete_queue::{DeleteNode, DeleteSlice, DocumentsWriterDeleteQueue},
         index_writer::{IndexWriterInner, INDEX_MAX_DOCS},
         index_writer_config::IndexWriterConfig,
         merge_policy::MergePolicy,
         merge_scheduler::MergeScheduler,
-        FieldInfos, FieldInfosBuilder, FieldNumbers, FieldNumbersRef, Fieldable, SegmentCommitInfo,
-        SegmentInfo, SegmentWriteState, Term,
+        sorter::{PackedLongDocMap, SorterDocMap},
+        ErrorKind::FlushFailed,
+        FieldInfos, FieldInfosBuilder, FieldNumbers, FieldNumbersRef, Fieldable, IndexReader,
+        SegmentCommitInfo, SegmentInfo, SegmentReader, SegmentWriteState,
+    },
+    search::{
+        query_cache::{NoCacheQueryCache, QueryCache},
+        searcher::{DefaultIndexSearcher, IndexSearcher},
+        DocIterator, NO_MORE_DOCS,
     },
     store::{
         Directory, FlushInfo, IOContext, LockValidatingDirectoryWrapper, TrackingDirectoryWrapper,
     },
     util::{
-        bit_set::BitSet,
+        bit_set::{BitSet, FixedBitSet},
+        bits::Bits,
         byte_block_pool::DirectTrackingAllocator,
         int_block_pool::{IntAllocator, INT_BLOCK_SIZE},
         string_util::random_id,
@@ -43,7 +51,7 @@ use std::time::SystemTime;
 
 use core::util::Volatile;
 use error::ErrorKind::IllegalArgument;
-use error::Result;
+use error::{Error, Result};
 
 pub struct DocState {
     // analyzer: Analyzer,  // TODO, current Analyzer is not implemented
@@ -84,6 +92,10 @@ pub struct DocumentsWriterPerThread<
     pending_updates: BufferedUpdates<C>,
     pub segment_info: SegmentInfo<D, C>,
     // current segment we are working on
+    /// With an index sort, the stored fields and the term vectors are written
+    /// to this segment in the order the docs are added, and rewritten to
+    /// `segment_info` in the sorted order on flush.
+    pub unsorted_segment_info: Option<SegmentInfo<D, C>>,
     aborted: bool,
     // true if we aborted
     pub num_docs_in_ram: u32,
@@ -93,7 +105,7 @@ pub struct DocumentsWriterPerThread<
     pub byte_block_allocator: DirectTrackingAllocator,
     pub int_block_allocator: Box<dyn IntAllocator>,
     pending_num_docs: Arc<AtomicI64>,
-    index_writer_config: Arc<IndexWriterConfig<C, MS, MP>>,
+    pub index_writer_config: Arc<IndexWriterConfig<C, MS, MP>>,
     // enable_test_points: bool,
     index_writer: Weak<IndexWriterInner<D, C, MS, MP>>,
     pub files_to_delete: HashSet<String>,
@@ -128,7 +140,7 @@ where
             HashMap::new(),
             random_id(),
             HashMap::new(),
-            None,
+            index_writer_config.index_sort().cloned(),
         )?;
         let delete_slice = delete_queue.new_slice();
         let doc_state = DocState::new();
@@ -141,6 +153,7 @@ where
             bytes_used: Counter::new(false),
             pending_updates: BufferedUpdates::new(segment_name),
             segment_info,
+            unsorted_segment_info: None,
             aborted: false,
             num_docs_in_ram: 0,
             delete_queue,
@@ -164,13 +177,19 @@ where
             self.bytes_used.shallow_copy()
         }));
         self.segment_info.set_codec(codec);
+        if self.segment_info.index_sort.is_some() {
+            let mut unsorted = self.segment_info.clone();
+            unsorted.name = format!("{}_unsorted", &self.segment_info.name);
+            unsorted.index_sort = None;
+            self.unsorted_segment_info = Some(unsorted);
+        }
         let consumer = DefaultIndexingChain::new(self, field_infos);
         self.consumer = consumer;
         self.consumer.init();
         self.inited = true;
     }
 
-    fn index_writer(&self) -> Arc<IndexWriterInner<D, C, MS, MP>> {
+    pub(crate) fn index_writer(&self) -> Arc<IndexWriterInner<D, C, MS, MP>> {
         self.index_writer.upgrade().unwrap()
     }
 
@@ -198,7 +217,7 @@ where
     pub fn update_document<F: Fieldable>(
         &mut self,
         mut doc: Vec<F>,
-        del_term: Option<Term>,
+        del_node: Option<DeleteNode<C>>,
     ) -> Result<u64> {
         // debug_assert!(self.inited);
         self.reserve_one_doc()?;
@@ -224,19 +243,19 @@ where
             self.num_docs_in_ram += 1;
             res?;
         }
-        self.finish_document(del_term)
+        self.finish_document(del_node)
     }
 
     pub fn update_documents<F: Fieldable>(
         &mut self,
         docs: Vec<Vec<F>>,
-        del_term: Option<Term>,
+        del_node: Option<DeleteNode<C>>,
     ) -> Result<u64> {
         // debug_assert!(self.inited);
         let mut doc_count = 0;
         let mut all_docs_indexed = false;
 
-        let res = self.do_update_documents(docs, del_term, &mut doc_count, &mut all_docs_indexed);
+        let res = self.do_update_documents(docs, del_node, &mut doc_count, &mut all_docs_indexed);
         if !all_docs_indexed && !self.aborted {
             // the iterator threw an exception that is not aborting
             // go and mark all docs from this block as deleted
@@ -254,7 +273,7 @@ where
     fn do_update_documents<F: Fieldable>(
         &mut self,
         docs: Vec<Vec<F>>,
-        del_term: Option<Term>,
+        del_node: Option<DeleteNode<C>>,
         doc_count: &mut i32,
         all_docs_indexed: &mut bool,
     ) -> Result<u64> {
@@ -288,10 +307,10 @@ where
         // Apply delTerm only after all indexing has
         // succeeded, but apply it only to docs prior to when
         // this batch started:
-        let seq_no = if let Some(del_term) = del_term {
+        let seq_no = if let Some(del_node) = del_node {
             let seq = self
                 .delete_queue
-                .add_term_to_slice(del_term, &mut self.delete_slice)?;
+                .add_to_slice(del_node, &mut self.delete_slice)?;
             self.delete_slice.apply(
                 &mut self.pending_updates,
                 self.num_docs_in_ram as i32 - *doc_count,
@@ -327,7 +346,7 @@ where
         // confounding exception).
     }
 
-    fn finish_document(&mut self, del_term: Option<Term>) -> Result<u64> {
+    fn finish_document(&mut self, del_node: Option<DeleteNode<C>>) -> Result<u64> {
         // here we actually finish the document in two steps:
         // 1. push the delete into the queue and update our slice
         // 2. increment the DWPT private document id.
@@ -336,12 +355,12 @@ where
         // occurred since we updated the slice the last time.
         let mut apply_slice = self.num_docs_in_ram > 0;
         let seq_no: u64;
-        if let Some(del_term) = del_term {
+        if let Some(del_node) = del_node {
             seq_no = self
                 .delete_queue
-                .add_term_to_slice(del_term, &mut self.delete_slice)?
+                .add_to_slice(del_node, &mut self.delete_slice)?
 
-        // debug_assert!(self.delete_slice.is_tail_item(del_term));
+        // debug_assert!(self.delete_slice.is_tail_item(del_node));
         } else {
             let (seq, apply) = self.delete_queue.update_slice(&mut self.delete_slice);
             seq_no = seq;
@@ -382,6 +401,9 @@ where
         debug_assert!(self.delete_slice.is_empty());
 
         self.segment_info.max_doc = self.num_docs_in_ram as i32;
+        if let Some(ref mut unsorted) = self.unsorted_segment_info {
+            unsorted.max_doc = self.num_docs_in_ram as i32;
+        }
         let ctx = IOContext::Flush(FlushInfo::new(
             self.num_docs_in_ram,
             self.bytes_used() as u64,
@@ -424,11 +446,53 @@ where
             "DWPT: flush postings as segment '{}' num_docs={}",
             &flush_state.segment_info.name, self.num_docs_in_ram
         );
-        let res = self.do_flush(flush_state);
-        if res.is_err() {
-            self.abort();
+        match self.do_flush(flush_state) {
+            Ok(segment) => Ok(segment),
+            Err(e) => Err(self.discard_failed_flush(e)),
         }
-        res
+    }
+
+    /// Deletes every file written for the segment after a failed flush.
+    ///
+    /// The `SegmentInfo` of a flushed segment is only handed to the writer once all
+    /// of its files are written, so the partial segment is never referenced by a
+    /// commit or an NRT reader. The buffered docs are discarded: the returned error
+    /// chains the original one and reports how many docs were lost and the cleanup
+    /// outcome, a failed cleanup included.
+    fn discard_failed_flush(&mut self, err: Error) -> Error {
+        let num_docs = self.num_docs_in_ram;
+        // closes the outputs still open, so that their files can be deleted
+        self.abort();
+
+        let files = self.directory.create_files();
+        let mut undeleted = Vec::new();
+        for file in &files {
+            if let Err(e) = self.directory.delete_file(file) {
+                warn!(
+                    "DWPT: failed to delete '{}' of the failed flush: {:?}",
+                    file, e
+                );
+                undeleted.push(file.clone());
+            }
+        }
+        // nothing left for the FlushFailed and DeleteNewFiles events to delete
+        self.files_to_delete.clear();
+
+        let mut cleanup = if undeleted.is_empty() {
+            format!("deleted all {} written files", files.len())
+        } else {
+            undeleted.sort();
+            format!("failed to delete {:?}", undeleted)
+        };
+        if let Err(e) = self.segment_info.set_files(&HashSet::new()) {
+            cleanup.push_str(&format!(", failed to reset the segment files: {}", e));
+        }
+        let desc = format!(
+            "segment '{}' with {} buffered docs discarded after '{}', {}",
+            &self.segment_info.name, num_docs, err, cleanup
+        );
+        error!("DWPT: {}", &desc);
+        Error::with_chain(err, FlushFailed(desc))
     }
 
     fn do_flush<DW: Directory + 'static>(
@@ -442,10 +506,31 @@ where
         self.consumer.reset_doc_writer(doc_writer);
         self.consumer.init();
 
-        self.consumer.flush(&mut flush_state)?;
+        let sort_map = self.consumer.flush(&mut flush_state)?;
         self.pending_updates.deleted_terms.clear();
+        if let Some(ref unsorted) = self.unsorted_segment_info {
+            // the unsorted stored fields and term vectors were copied to the
+            // segment in the sorted order
+            for file in self.directory.create_files() {
+                if file.starts_with(&unsorted.name) {
+                    self.directory.delete_file(&file)?;
+                }
+            }
+        }
         self.segment_info
             .set_files(&self.directory.create_files())?;
+        if let Some(ref sort_map) = sort_map {
+            if !flush_state.live_docs.is_empty() {
+                let max_doc = self.segment_info.max_doc() as usize;
+                let mut live_docs = FixedBitSet::new(max_doc);
+                for doc in 0..max_doc {
+                    if flush_state.live_docs.get(doc)? {
+                        live_docs.set(sort_map.old_to_new(doc as DocId) as usize);
+                    }
+                }
+                flush_state.live_docs = live_docs;
+            }
+        }
         let segment_info_per_commit = SegmentCommitInfo::new(
             self.segment_info.clone(),
             0,
@@ -455,9 +540,30 @@ where
             HashMap::new(),
             HashSet::new(),
         );
+        if !self.pending_updates.deleted_queries.is_empty() && sort_map.is_some() {
+            // the doc id limits of the delete queries are unsorted doc ids, the
+            // queries are resolved here on the flushed segment
+            let max_doc = self.segment_info.max_doc() as usize;
+            if flush_state.live_docs.is_empty() {
+                flush_state.live_docs = FixedBitSet::new(max_doc);
+                flush_state.live_docs.batch_set(0, max_doc);
+            }
+            let reader = Arc::new(SegmentReader::open(
+                &Arc::new(segment_info_per_commit.clone()),
+                &IOContext::READ,
+            )?);
+            flush_state.del_count_on_flush += self.apply_query_deletes(
+                &reader,
+                sort_map.as_ref().unwrap(),
+                &mut flush_state.live_docs,
+            )?;
+            self.pending_updates.deleted_queries.clear();
+        }
 
         let mut fs = {
-            let segment_deletes = if self.pending_updates.deleted_queries.is_empty() {
+            let segment_deletes = if self.pending_updates.deleted_queries.is_empty()
+                && self.pending_updates.numeric_updates.is_empty()
+            {
                 self.pending_updates.clear();
                 None
             } else {
@@ -467,6 +573,7 @@ where
                 Arc::new(segment_info_per_commit),
                 flush_state.field_infos,
                 segment_deletes,
+                sort_map,
                 Arc::new(flush_state.live_docs),
                 flush_state.del_count_on_flush,
             )
@@ -480,6 +587,38 @@ where
         Ok(Some(fs))
     }
 
+    /// Clears the docs matching the buffered delete queries, up to the doc id
+    /// limit of each query, returns the number of newly deleted docs. The limits
+    /// are doc ids of the docs before they were sorted by `sort_map`.
+    fn apply_query_deletes(
+        &self,
+        reader: &Arc<SegmentReader<D, C>>,
+        sort_map: &PackedLongDocMap,
+        live_docs: &mut FixedBitSet,
+    ) -> Result<u32> {
+        let mut searcher = DefaultIndexSearcher::new(Arc::clone(reader));
+        let query_cache: Arc<dyn QueryCache<C>> = Arc::new(NoCacheQueryCache::new());
+        searcher.set_query_cache(query_cache);
+        let leaf = searcher.reader().leaves().remove(0);
+        let mut del_count = 0;
+        for (query, limit) in self.pending_updates.deleted_queries.values() {
+            let weight = searcher.create_normalized_weight(query.as_ref(), false)?;
+            if let Some(mut scorer) = weight.create_scorer(&leaf)? {
+                loop {
+                    let doc = scorer.next()?;
+                    if doc == NO_MORE_DOCS {
+                        break;
+                    }
+                    if sort_map.new_to_old(doc) < *limit && live_docs.get(doc as usize)? {
+                        live_docs.clear(doc as usize);
+                        del_count += 1;
+                    }
+                }
+            }
+        }
+        Ok(del_count)
+    }
+
     fn seal_flushed_segment(&mut self, flushed_segment: &mut FlushedSegment<D, C>) -> Result<()> {
         // set_diagnostics(&mut flushed_segment.segment_info.info, index_writer::SOURCE_FLUSH);
 
@@ -578,13 +717,18 @@ impl<D: Directory, C: Codec> FlushedSegment<D, C> {
         segment_info: Arc<SegmentCommitInfo<D, C>>,
         field_infos: FieldInfos,
         buffered_updates: Option<&mut BufferedUpdates<C>>,
+        sort_map: Option<Arc<PackedLongDocMap>>,
         live_docs: BitsRef,
         del_count: u32,
     ) -> Self {
         let mut segment_updates = None;
         if let Some(b) = buffered_updates {
             if b.any() {
-                segment_updates = Some(FrozenBufferedUpdates::new(b, true));
+                let mut updates = FrozenBufferedUpdates::new(b, true);
+                if let Some(sort_map) = sort_map {
+                    updates.set_sort_map(sort_map);
+                }
+                segment_updates = Some(updates);
             }
         }
         FlushedSegment {
@@ -0,0 +1,896 @@
+// Copyright 2019 Zhizhesihai (Beijing) Technology Limited.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Joins of nested documents.
+//!
+//! The children of a parent doc are indexed with `IndexWriter::add_documents`
+//! as a block of docs followed by the parent, so the children of a parent are
+//! the docs between the previous parent and itself. A `BitSetProducer` tells
+//! the parent docs of a segment apart.
+
+use core::codec::Codec;
+use core::index::{LeafReader, LeafReaderContext};
+use core::search::explanation::Explanation;
+use core::search::join::ScoreMode;
+use core::search::searcher::SearchPlanBuilder;
+use core::search::term_query::TermQuery;
+use core::search::{DocIterator, Query, Scorer, Weight, NO_MORE_DOCS};
+use core::util::bit_set::{BitSet, FixedBitSet, ImmutableBitSet};
+use core::util::external::deferred::Deferred;
+use core::util::{Bits, DocId};
+
+use error::ErrorKind::IllegalState;
+use error::Result;
+
+use std::collections::HashMap;
+use std::f32;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+pub const TO_PARENT_BLOCK_JOIN: &str = "to_parent_block_join";
+pub const TO_CHILD_BLOCK_JOIN: &str = "to_child_block_join";
+
+/// Produces the bit set of the docs of a segment matching a filter, like the
+/// parent docs of a block join.
+pub trait BitSetProducer<C: Codec>: fmt::Display {
+    /// Returns the docs of the segment of `reader`, `None` if no doc matches.
+    fn get_bit_set(
+        &self,
+        searcher: &dyn SearchPlanBuilder<C>,
+        reader: &LeafReaderContext<'_, C>,
+    ) -> Result<Option<Arc<FixedBitSet>>>;
+}
+
+/// Produces the docs matching a query, ignoring the deleted docs.
+///
+/// The bit sets are cached per segment core until the core is dropped.
+pub struct QueryBitSetProducer<C: Codec> {
+    query: Box<dyn Query<C>>,
+    cache: Arc<Mutex<HashMap<String, Option<Arc<FixedBitSet>>>>>,
+}
+
+impl<C: Codec> QueryBitSetProducer<C> {
+    pub fn new(query: Box<dyn Query<C>>) -> QueryBitSetProducer<C> {
+        QueryBitSetProducer {
+            query,
+            cache: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    pub fn query(&self) -> &dyn Query<C> {
+        self.query.as_ref()
+    }
+}
+
+impl<C: Codec> BitSetProducer<C> for QueryBitSetProducer<C> {
+    fn get_bit_set(
+        &self,
+        searcher: &dyn SearchPlanBuilder<C>,
+        reader: &LeafReaderContext<'_, C>,
+    ) -> Result<Option<Arc<FixedBitSet>>> {
+        let key = reader.reader.core_cache_key();
+        if let Some(bits) = self.cache.lock()?.get(key) {
+            return Ok(bits.clone());
+        }
+
+        let weight = searcher.create_normalized_weight(self.query.as_ref(), false)?;
+        let bits = match weight.create_scorer(reader)? {
+            Some(mut scorer) => {
+                let mut bits = FixedBitSet::new(reader.reader.max_doc() as usize);
+                loop {
+                    let doc = scorer.next()?;
+                    if doc == NO_MORE_DOCS {
+                        break;
+                    }
+                    bits.set(doc as usize);
+                }
+                Some(Arc::new(bits))
+            }
+            None => None,
+        };
+
+        let mut cache = self.cache.lock()?;
+        if !cache.contains_key(key) {
+            cache.insert(key.to_string(), bits.clone());
+            let core_key = key.to_string();
+            let cache = Arc::clone(&self.cache);
+            reader.reader.add_core_drop_listener(Deferred::new(move || {
+                cache.lock().unwrap().remove(&core_key);
+            }));
+        }
+        Ok(bits)
+    }
+}
+
+impl<C: Codec> fmt::Display for QueryBitSetProducer<C> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "QueryBitSetProducer({})", &self.query)
+    }
+}
+
+// segment core key -> the parent docs of the segment
+type ParentBitSets = HashMap<String, Option<Arc<FixedBitSet>>>;
+
+fn parent_bit_sets<C: Codec>(
+    parents_filter: &dyn BitSetProducer<C>,
+    searcher: &dyn SearchPlanBuilder<C>,
+) -> Result<ParentBitSets> {
+    let mut bit_sets = HashMap::new();
+    for leaf in searcher.leaves() {
+        let bits = parents_filter.get_bit_set(searcher, &leaf)?;
+        bit_sets.insert(leaf.reader.core_cache_key().to_string(), bits);
+    }
+    Ok(bit_sets)
+}
+
+fn leaf_parents<C: Codec>(
+    parents: &ParentBitSets,
+    reader: &LeafReaderContext<'_, C>,
+) -> Result<Option<Arc<FixedBitSet>>> {
+    match parents.get(reader.reader.core_cache_key()) {
+        Some(bits) => Ok(bits.clone()),
+        None => bail!(IllegalState(
+            "the block join weight is used on another reader than the one it was created with"
+                .into()
+        )),
+    }
+}
+
+/// Matches the parent docs of the child docs matching a query.
+///
+/// The score of a parent aggregates the scores of its matching children with
+/// the score mode. The child query must not match parent docs.
+pub struct ToParentBlockJoinQuery<C: Codec> {
+    child_query: Box<dyn Query<C>>,
+    parents_filter: Arc<dyn BitSetProducer<C>>,
+    score_mode: ScoreMode,
+}
+
+impl<C: Codec> ToParentBlockJoinQuery<C> {
+    pub fn new(
+        child_query: Box<dyn Query<C>>,
+        parents_filter: Arc<dyn BitSetProducer<C>>,
+        score_mode: ScoreMode,
+    ) -> ToParentBlockJoinQuery<C> {
+        ToParentBlockJoinQuery {
+            child_query,
+            parents_filter,
+            score_mode,
+        }
+    }
+
+    pub fn child_query(&self) -> &dyn Query<C> {
+        self.child_query.as_ref()
+    }
+
+    pub fn score_mode(&self) -> ScoreMode {
+        self.score_mode
+    }
+}
+
+impl<C: Codec> Query<C> for ToParentBlockJoinQuery<C> {
+    fn create_weight(
+        &self,
+        searcher: &dyn SearchPlanBuilder<C>,
+        needs_scores: bool,
+    ) -> Result<Box<dyn Weight<C>>> {
+        let needs_scores = needs_scores && self.score_mode != ScoreMode::None;
+        let child_weight = self.child_query.create_weight(searcher, needs_scores)?;
+        let parents = parent_bit_sets(self.parents_filter.as_ref(), searcher)?;
+        Ok(Box::new(ToParentBlockJoinWeight {
+            child_weight,
+            parents,
+            score_mode: self.score_mode,
+            needs_scores,
+        }))
+    }
+
+    fn extract_terms(&self) -> Vec<TermQuery> {
+        self.child_query.extract_terms()
+    }
+
+    fn query_type(&self) -> &'static str {
+        TO_PARENT_BLOCK_JOIN
+    }
+
+    fn as_any(&self) -> &::std::any::Any {
+        self
+    }
+}
+
+impl<C: Codec> fmt::Display for ToParentBlockJoinQuery<C> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "ToParentBlockJoinQuery(child: {}, parents: {}, score_mode: {:?})",
+            &self.child_query, &self.parents_filter, self.score_mode
+        )
+    }
+}
+
+struct ToParentBlockJoinWeight<C: Codec> {
+    child_weight: Box<dyn Weight<C>>,
+    parents: ParentBitSets,
+    score_mode: ScoreMode,
+    needs_scores: bool,
+}
+
+impl<C: Codec> ToParentBlockJoinWeight<C> {
+    fn block_join_scorer(
+        &self,
+        reader: &LeafReaderContext<'_, C>,
+    ) -> Result<Option<ToParentBlockJoinScorer>> {
+        let parents = match leaf_parents(&self.parents, reader)? {
+            Some(parents) => parents,
+            None => return Ok(None),
+        };
+        match self.child_weight.create_scorer(reader)? {
+            Some(child_scorer) => Ok(Some(ToParentBlockJoinScorer {
+                child_scorer,
+                parents,
+                score_mode: self.score_mode,
+                needs_scores: self.needs_scores,
+                parent_doc: -1,
+                score: 0f32,
+                child_count: 0,
+            })),
+            None => Ok(None),
+        }
+    }
+}
+
+impl<C: Codec> Weight<C> for ToParentBlockJoinWeight<C> {
+    fn create_scorer(
+        &self,
+        leaf_reader: &LeafReaderContext<'_, C>,
+    ) -> Result<Option<Box<dyn Scorer>>> {
+        match self.block_join_scorer(leaf_reader)? {
+            Some(scorer) => Ok(Some(Box::new(scorer))),
+            None => Ok(None),
+        }
+    }
+
+    fn query_type(&self) -> &'static str {
+        TO_PARENT_BLOCK_JOIN
+    }
+
+    fn normalize(&mut self, norm: f32, boost: f32) {
+        self.child_weight.normalize(norm, boost)
+    }
+
+    fn value_for_normalization(&self) -> f32 {
+        self.child_weight.value_for_normalization()
+    }
+
+    fn needs_scores(&self) -> bool {
+        self.needs_scores
+    }
+
+    fn explain(&self, reader: &LeafReaderContext<'_, C>, doc: DocId) -> Result<Explanation> {
+        if let Some(mut scorer) = self.block_join_scorer(reader)? {
+            if scorer.advance(doc)? == doc {
+                return Ok(Explanation::new(
+                    true,
+                    scorer.score()?,
+                    format!(
+                        "score of {} matching child docs, with score mode {:?}",
+                        scorer.child_count, self.score_mode
+                    ),
+                    vec![],
+                ));
+            }
+        }
+        Ok(Explanation::new(
+            false,
+            0f32,
+            format!("{} doesn't match id {}", self, doc),
+            vec![],
+        ))
+    }
+}
+
+impl<C: Codec> fmt::Display for ToParentBlockJoinWeight<C> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "ToParentBlockJoinWeight(child: {}, score_mode: {:?})",
+            &self.child_weight, self.score_mode
+        )
+    }
+}
+
+/// Iterates the parents of the matching children, aggregating the scores of
+/// the children while moving past them.
+struct ToParentBlockJoinScorer {
+    child_scorer: Box<dyn Scorer>,
+    parents: Arc<FixedBitSet>,
+    score_mode: ScoreMode,
+    needs_scores: bool,
+    parent_doc: DocId,
+    score: f32,
+    child_count: u32,
+}
+
+impl ToParentBlockJoinScorer {
+    // moves to the parent of the current child and past its children
+    fn next_parent(&mut self) -> Result<DocId> {
+        let mut child_doc = self.child_scorer.doc_id();
+        if child_doc == -1 {
+            child_doc = self.child_scorer.next()?;
+        }
+        if child_doc == NO_MORE_DOCS {
+            self.parent_doc = NO_MORE_DOCS;
+            return Ok(NO_MORE_DOCS);
+        }
+        let parent_doc = self.parents.next_set_bit(child_doc as usize);
+        if parent_doc == NO_MORE_DOCS {
+            // the docs after the last parent have no parent
+            self.parent_doc = NO_MORE_DOCS;
+            return Ok(NO_MORE_DOCS);
+        }
+
+        let (mut total, mut max, mut count) = (0f32, f32::MIN, 0u32);
+        while child_doc < parent_doc {
+            if self.needs_scores {
+                let score = self.child_scorer.score()?;
+                total += score;
+                max = max.max(score);
+            }
+            count += 1;
+            child_doc = self.child_scorer.next()?;
+        }
+        if child_doc == parent_doc {
+            bail!(IllegalState(format!(
+                "the child query matches the parent doc {}",
+                parent_doc
+            )));
+        }
+
+        self.score = match self.score_mode {
+            _ if !self.needs_scores => 1f32,
+            ScoreMode::None => 1f32,
+            ScoreMode::Avg => total / count as f32,
+            ScoreMode::Max => max,
+            ScoreMode::Total => total,
+        };
+        self.child_count = count;
+        self.parent_doc = parent_doc;
+        Ok(parent_doc)
+    }
+}
+
+impl Scorer for ToParentBlockJoinScorer {
+    fn score(&mut self) -> Result<f32> {
+        Ok(self.score)
+    }
+}
+
+impl DocIterator for ToParentBlockJoinScorer {
+    fn doc_id(&self) -> DocId {
+        self.parent_doc
+    }
+
+    fn next(&mut self) -> Result<DocId> {
+        self.next_parent()
+    }
+
+    fn advance(&mut self, target: DocId) -> Result<DocId> {
+        if target as usize >= self.parents.len() {
+            self.parent_doc = NO_MORE_DOCS;
+            return Ok(NO_MORE_DOCS);
+        }
+        if target > 0 {
+            // the children of the parents before the target don't matter
+            let prev_parent = self.parents.prev_set_bit(target as usize - 1);
+            if prev_parent >= self.child_scorer.doc_id() {
+                self.child_scorer.advance(prev_parent + 1)?;
+            }
+        }
+        self.next_parent()
+    }
+
+    fn cost(&self) -> usize {
+        self.child_scorer.cost()
+    }
+}
+
+/// Matches the child docs of the parent docs matching a query, with the
+/// scores of their parents.
+///
+/// The parent query must only match parent docs.
+pub struct ToChildBlockJoinQuery<C: Codec> {
+    parent_query: Box<dyn Query<C>>,
+    parents_filter: Arc<dyn BitSetProducer<C>>,
+}
+
+impl<C: Codec> ToChildBlockJoinQuery<C> {
+    pub fn new(
+        parent_query: Box<dyn Query<C>>,
+        parents_filter: Arc<dyn BitSetProducer<C>>,
+    ) -> ToChildBlockJoinQuery<C> {
+        ToChildBlockJoinQuery {
+            parent_query,
+            parents_filter,
+        }
+    }
+
+    pub fn parent_query(&self) -> &dyn Query<C> {
+        self.parent_query.as_ref()
+    }
+}
+
+impl<C: Codec> Query<C> for ToChildBlockJoinQuery<C> {
+    fn create_weight(
+        &self,
+        searcher: &dyn SearchPlanBuilder<C>,
+        needs_scores: bool,
+    ) -> Result<Box<dyn Weight<C>>> {
+        let parent_weight = self.parent_query.create_weight(searcher, needs_scores)?;
+        let parents = parent_bit_sets(self.parents_filter.as_ref(), searcher)?;
+        Ok(Box::new(ToChildBlockJoinWeight {
+            parent_weight,
+            parents,
+        }))
+    }
+
+    fn extract_terms(&self) -> Vec<TermQuery> {
+        self.parent_query.extract_terms()
+    }
+
+    fn query_type(&self) -> &'static str {
+        TO_CHILD_BLOCK_JOIN
+    }
+
+    fn as_any(&self) -> &::std::any::Any {
+        self
+    }
+}
+
+impl<C: Codec> fmt::Display for ToChildBlockJoinQuery<C> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "ToChildBlockJoinQuery(parent: {}, parents: {})",
+            &self.parent_query, &self.parents_filter
+        )
+    }
+}
+
+struct ToChildBlockJoinWeight<C: Codec> {
+    parent_weight: Box<dyn Weight<C>>,
+    parents: ParentBitSets,
+}
+
+impl<C: Codec> Weight<C> for ToChildBlockJoinWeight<C> {
+    fn create_scorer(
+        &self,
+        leaf_reader: &LeafReaderContext<'_, C>,
+    ) -> Result<Option<Box<dyn Scorer>>> {
+        let parents = match leaf_parents(&self.parents, leaf_reader)? {
+            Some(parents) => parents,
+            None => return Ok(None),
+        };
+        match self.parent_weight.create_scorer(leaf_reader)? {
+            Some(parent_scorer) => Ok(Some(Box::new(ToChildBlockJoinScorer {
+                parent_scorer,
+                parents,
+                child_doc: -1,
+                parent_doc: 0,
+            }))),
+            None => Ok(None),
+        }
+    }
+
+    fn query_type(&self) -> &'static str {
+        TO_CHILD_BLOCK_JOIN
+    }
+
+    fn normalize(&mut self, norm: f32, boost: f32) {
+        self.parent_weight.normalize(norm, boost)
+    }
+
+    fn value_for_normalization(&self) -> f32 {
+        self.parent_weight.value_for_normalization()
+    }
+
+    fn needs_scores(&self) -> bool {
+        self.parent_weight.needs_scores()
+    }
+
+    fn explain(&self, reader: &LeafReaderContext<'_, C>, doc: DocId) -> Result<Explanation> {
+        if let Some(mut scorer) = self.create_scorer(reader)? {
+            if scorer.advance(doc)? == doc {
+                let parents = leaf_parents(&self.parents, reader)?.unwrap();
+                let parent_doc = parents.next_set_bit(doc as usize);
+                let explanation = self.parent_weight.explain(reader, parent_doc)?;
+                return Ok(Explanation::new(
+                    true,
+                    explanation.value(),
+                    format!("score of the parent doc {}", parent_doc + reader.doc_base),
+                    vec![explanation],
+                ));
+            }
+        }
+        Ok(Explanation::new(
+            false,
+            0f32,
+            format!("{} doesn't match id {}", self, doc),
+            vec![],
+        ))
+    }
+}
+
+impl<C: Codec> fmt::Display for ToChildBlockJoinWeight<C> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "ToChildBlockJoinWeight(parent: {})", &self.parent_weight)
+    }
+}
+
+/// Iterates the children of the matching parents.
+struct ToChildBlockJoinScorer {
+    parent_scorer: Box<dyn Scorer>,
+    parents: Arc<FixedBitSet>,
+    child_doc: DocId,
+    // the parent of the current child, the children of a parent are the docs
+    // between the previous parent and itself
+    parent_doc: DocId,
+}
+
+impl ToChildBlockJoinScorer {
+    // moves the parent scorer from `parent_doc` to the first parent having
+    // children, and returns its first child
+    fn to_parent_with_children(&mut self, mut parent_doc: DocId) -> Result<DocId> {
+        loop {
+            if parent_doc == NO_MORE_DOCS {
+                self.parent_doc = NO_MORE_DOCS;
+                return Ok(NO_MORE_DOCS);
+            }
+            if !self.parents.get(parent_doc as usize)? {
+                bail!(IllegalState(format!(
+                    "the parent query matches the doc {} which isn't a parent",
+                    parent_doc
+                )));
+            }
+            let first_child = if parent_doc == 0 {
+                0
+            } else {
+                self.parents.prev_set_bit(parent_doc as usize - 1) + 1
+            };
+            if first_child < parent_doc {
+                self.parent_doc = parent_doc;
+                return Ok(first_child);
+            }
+            parent_doc = self.parent_scorer.next()?;
+        }
+    }
+}
+
+impl Scorer for ToChildBlockJoinScorer {
+    fn score(&mut self) -> Result<f32> {
+        self.parent_scorer.score()
+    }
+}
+
+impl DocIterator for ToChildBlockJoinScorer {
+    fn doc_id(&self) -> DocId {
+        self.child_doc
+    }
+
+    fn next(&mut self) -> Result<DocId> {
+        if self.parent_doc == NO_MORE_DOCS {
+            return Ok(NO_MORE_DOCS);
+        }
+        if self.child_doc + 1 < self.parent_doc {
+            self.child_doc += 1;
+        } else {
+            let parent_doc = self.parent_scorer.next()?;
+            self.child_doc = self.to_parent_with_children(parent_doc)?;
+        }
+        Ok(self.child_doc)
+    }
+
+    fn advance(&mut self, target: DocId) -> Result<DocId> {
+        if self.parent_doc == NO_MORE_DOCS {
+            return Ok(NO_MORE_DOCS);
+        }
+        if target < self.parent_doc {
+            self.child_doc = target;
+            return Ok(target);
+        }
+        if target as usize >= self.parents.len() {
+            self.child_doc = NO_MORE_DOCS;
+            self.parent_doc = NO_MORE_DOCS;
+            return Ok(NO_MORE_DOCS);
+        }
+        let parent_doc = self.parent_scorer.advance(target + 1)?;
+        let first_child = self.to_parent_with_children(parent_doc)?;
+        // the first child of the next parent may be before the target if
+        // the target is the first child or the parent itself
+        self.child_doc = first_child.max(target);
+        Ok(self.child_doc)
+    }
+
+    fn cost(&self) -> usize {
+        self.parent_scorer.cost()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::codec::CodecEnum;
+    use core::doc::{Field, Store, StringField, TextField};
+    use core::index::index_writer_config::IndexWriterConfig;
+    use core::index::tests::{write_test_index, TestReader};
+    use core::index::{IndexReader, IndexWriter, Term};
+    use core::search::collector::top_docs::TopDocsCollector;
+    use core::search::searcher::{DefaultIndexSearcher, IndexSearcher};
+    use core::search::sort::Sort;
+    use core::search::sort_field::{SimpleSortField, SortField, SortFieldType};
+    use core::store::RAMDirectory;
+
+    use std::collections::BTreeMap;
+
+    // products with the colors of their offers, every product is indexed as
+    // a block of its offers followed by the product:
+    //
+    // segment 0: 0 red, 1 blue, 2 a, 3 b, 4 red, 5 c
+    // segment 1: 6 blue, 7 blue, 8 green, 9 d, 10 red, 11 e
+    const SEGMENTS: &[&[(&str, &[&str])]] = &[
+        &[("a", &["red", "blue"]), ("b", &[]), ("c", &["red"])],
+        &[("d", &["blue", "blue", "green"]), ("e", &["red"])],
+    ];
+
+    const NUM_DOCS: DocId = 12;
+
+    fn block(name: &str, colors: &[&str]) -> Vec<Vec<Field>> {
+        let mut docs: Vec<Vec<Field>> = colors
+            .iter()
+            .map(|color| {
+                vec![
+                    StringField::new("type", "offer", Store::No),
+                    TextField::new("color", color, Store::No),
+                ]
+            })
+            .collect();
+        docs.push(vec![
+            StringField::new("type", "product", Store::No),
+            StringField::new("name", name, Store::No),
+        ]);
+        docs
+    }
+
+    fn build_index(force_merge: bool) -> Arc<TestReader> {
+        write_test_index(IndexWriterConfig::default(), |writer| {
+            for segment in SEGMENTS {
+                for &(name, colors) in segment.iter() {
+                    writer.add_documents(block(name, colors)).unwrap();
+                }
+                writer.commit().unwrap();
+            }
+            if force_merge {
+                writer.force_merge(1, true).unwrap();
+                writer.commit().unwrap();
+            }
+        })
+    }
+
+    fn term_query(field: &str, value: &str) -> Box<dyn Query<CodecEnum>> {
+        Box::new(TermQuery::new(
+            Term::new(field.to_string(), value.as_bytes().to_vec()),
+            1.0,
+            None,
+        ))
+    }
+
+    fn products() -> Arc<dyn BitSetProducer<CodecEnum>> {
+        Arc::new(QueryBitSetProducer::new(term_query("type", "product")))
+    }
+
+    fn hits<S: IndexSearcher<CodecEnum>>(
+        searcher: &S,
+        query: &dyn Query<CodecEnum>,
+    ) -> BTreeMap<DocId, f32> {
+        let mut collector = TopDocsCollector::new(100);
+        searcher.search(query, &mut collector).unwrap();
+        collector
+            .top_docs()
+            .score_docs()
+            .iter()
+            .map(|d| (d.doc_id(), d.score()))
+            .collect()
+    }
+
+    fn parent_of(doc: DocId, parents: &[DocId]) -> DocId {
+        *parents.iter().find(|&&parent| parent > doc).unwrap()
+    }
+
+    #[test]
+    fn test_to_parent_block_join() {
+        let searcher = DefaultIndexSearcher::new(build_index(false));
+        let parents: Vec<DocId> = hits(&searcher, term_query("type", "product").as_ref())
+            .keys()
+            .cloned()
+            .collect();
+        assert_eq!(parents, vec![2, 3, 5, 9, 11]);
+
+        let colors: &[(&str, &[DocId])] = &[
+            ("red", &[2, 5, 11]),
+            ("blue", &[2, 9]),
+            ("green", &[9]),
+            ("yellow", &[]),
+        ];
+        for &(color, expected_docs) in colors {
+            let child_hits = hits(&searcher, term_query("color", color).as_ref());
+            for &score_mode in &[
+                ScoreMode::None,
+                ScoreMode::Avg,
+                ScoreMode::Max,
+                ScoreMode::Total,
+            ] {
+                let query =
+                    ToParentBlockJoinQuery::new(term_query("color", color), products(), score_mode);
+                let joined = hits(&searcher, &query);
+                assert_eq!(
+                    joined.keys().cloned().collect::<Vec<_>>(),
+                    expected_docs,
+                    "{} {:?}",
+                    color,
+                    score_mode
+                );
+
+                for (&parent, &score) in &joined {
+                    let scores: Vec<f32> = child_hits
+                        .iter()
+                        .filter(|&(&child, _)| parent_of(child, &parents) == parent)
+                        .map(|(_, &score)| score)
+                        .collect();
+                    let expected = match score_mode {
+                        ScoreMode::None => 1f32,
+                        ScoreMode::Avg => scores.iter().sum::<f32>() / scores.len() as f32,
+                        ScoreMode::Max => scores.iter().cloned().fold(f32::MIN, f32::max),
+                        ScoreMode::Total => scores.iter().sum(),
+                    };
+                    assert!((score - expected).abs() < 1e-5);
+                }
+            }
+        }
+
+        // the child query must not match parents
+        let query =
+            ToParentBlockJoinQuery::new(term_query("type", "product"), products(), ScoreMode::Max);
+        let mut collector = TopDocsCollector::new(100);
+        assert!(searcher.search(&query, &mut collector).is_err());
+    }
+
+    #[test]
+    fn test_to_child_block_join() {
+        let searcher = DefaultIndexSearcher::new(build_index(false));
+        let names: &[(&str, &[DocId])] = &[
+            ("a", &[0, 1]),
+            ("b", &[]),
+            ("c", &[4]),
+            ("d", &[6, 7, 8]),
+            ("e", &[10]),
+        ];
+        for &(name, expected_docs) in names {
+            let parent_score = hits(&searcher, term_query("name", name).as_ref())
+                .values()
+                .next()
+                .cloned()
+                .unwrap();
+            let query = ToChildBlockJoinQuery::new(term_query("name", name), products());
+            let joined = hits(&searcher, &query);
+            assert_eq!(joined.keys().cloned().collect::<Vec<_>>(), expected_docs);
+            for score in joined.values() {
+                assert!((score - parent_score).abs() < 1e-5);
+            }
+        }
+
+        let query = ToChildBlockJoinQuery::new(term_query("type", "product"), products());
+        let joined = hits(&searcher, &query);
+        assert_eq!(
+            joined.keys().cloned().collect::<Vec<_>>(),
+            vec![0, 1, 4, 6, 7, 8, 10]
+        );
+
+        // the parent query must only match parents
+        let query = ToChildBlockJoinQuery::new(term_query("color", "red"), products());
+        let mut collector = TopDocsCollector::new(100);
+        assert!(searcher.search(&query, &mut collector).is_err());
+    }
+
+    #[test]
+    fn test_block_join_advance() {
+        let searcher = DefaultIndexSearcher::new(build_index(false));
+        let leaves = searcher.reader().leaves();
+        assert_eq!(leaves.len(), 2);
+        let scorer = |query: &dyn Query<CodecEnum>, leaf: usize| {
+            let weight = searcher.create_normalized_weight(query, false).unwrap();
+            weight.create_scorer(&leaves[leaf]).unwrap().unwrap()
+        };
+
+        let red =
+            ToParentBlockJoinQuery::new(term_query("color", "red"), products(), ScoreMode::None);
+        let mut parents = scorer(&red, 0);
+        assert_eq!(parents.advance(3).unwrap(), 5);
+        assert_eq!(parents.next().unwrap(), NO_MORE_DOCS);
+        let mut parents = scorer(&red, 1);
+        assert_eq!(parents.advance(0).unwrap(), 5);
+
+        let blue =
+            ToParentBlockJoinQuery::new(term_query("color", "blue"), products(), ScoreMode::None);
+        let mut parents = scorer(&blue, 0);
+        assert_eq!(parents.advance(3).unwrap(), NO_MORE_DOCS);
+        let mut parents = scorer(&blue, 1);
+        assert_eq!(parents.next().unwrap(), 3);
+        assert_eq!(parents.next().unwrap(), NO_MORE_DOCS);
+
+        let offers = ToChildBlockJoinQuery::new(term_query("type", "product"), products());
+        let mut children = scorer(&offers, 0);
+        // the product "b" at 3 has no offers
+        assert_eq!(children.advance(2).unwrap(), 4);
+        assert_eq!(children.next().unwrap(), NO_MORE_DOCS);
+        let mut children = scorer(&offers, 1);
+        assert_eq!(children.advance(1).unwrap(), 1);
+        assert_eq!(children.next().unwrap(), 2);
+        assert_eq!(children.next().unwrap(), 4);
+        assert_eq!(children.advance(5).unwrap(), NO_MORE_DOCS);
+    }
+
+    #[test]
+    fn test_merge_keeps_blocks() {
+        let searcher = DefaultIndexSearcher::new(build_index(true));
+        assert_eq!(searcher.reader().leaves().len(), 1);
+        assert_eq!(searcher.reader().max_doc(), NUM_DOCS);
+
+        let named = |names: &[&str]| {
+            let mut docs: Vec<DocId> = names
+                .iter()
+                .flat_map(|name| hits(&searcher, term_query("name", name).as_ref()).into_iter())
+                .map(|(doc, _)| doc)
+                .collect();
+            docs.sort();
+            docs
+        };
+        let query =
+            ToParentBlockJoinQuery::new(term_query("color", "red"), products(), ScoreMode::Max);
+        assert_eq!(
+            hits(&searcher, &query).keys().cloned().collect::<Vec<_>>(),
+            named(&["a", "c", "e"])
+        );
+        let query =
+            ToParentBlockJoinQuery::new(term_query("color", "green"), products(), ScoreMode::Max);
+        assert_eq!(
+            hits(&searcher, &query).keys().cloned().collect::<Vec<_>>(),
+            named(&["d"])
+        );
+        let query = ToChildBlockJoinQuery::new(term_query("name", "b"), products());
+        assert!(hits(&searcher, &query).is_empty());
+    }
+
+    #[test]
+    fn test_index_sort_rejects_blocks() {
+        let dir = Arc::new(RAMDirectory::new());
+        let mut conf = IndexWriterConfig::default();
+        conf.index_sort = Some(Sort::new(vec![SortField::Simple(SimpleSortField::new(
+            "rank".to_string(),
+            SortFieldType::Long,
+            false,
+        ))]));
+        let writer = IndexWriter::new(Arc::clone(&dir), Arc::new(conf)).unwrap();
+        assert!(writer.add_documents(block("a", &["red"])).is_err());
+        assert!(writer.add_documents(block("b", &[])).is_ok());
+        writer.rollback().unwrap();
+    }
+}
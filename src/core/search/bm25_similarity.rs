@@ -12,6 +12,7 @@
 // limitations under the License.
 
 use error::Result;
+use std::f32;
 use std::fmt;
 use std::sync::Arc;
 
@@ -19,8 +20,9 @@ use core::codec::Codec;
 use core::index::field_info::FieldInvertState;
 use core::index::{NumericDocValues, SearchLeafReader};
 use core::search::explanation::Explanation;
+use core::search::norm_encoding::{NormEncoding, NormRemap};
 use core::search::statistics::{CollectionStatistics, TermStatistics};
-use core::search::{SimScorer, SimWeight, Similarity};
+use core::search::{SimScorer, SimWeight, Similarity, SimilarityProducer};
 use core::util::small_float::SmallFloat;
 use core::util::{DocId, KeyedContext};
 
@@ -38,14 +40,48 @@ lazy_static! {
         norm_table[0] = 1f32 / norm_table[255];
         norm_table
     };
+    static ref LENGTH_TABLE: [f32; 256] = {
+        let mut length_table: [f32; 256] = [0f32; 256];
+        for (i, length) in length_table.iter_mut().enumerate() {
+            *length = SmallFloat::byte4_to_int(i as u8) as f32;
+        }
+        length_table
+    };
 }
 
 pub const DEFAULT_BM25_K1: f32 = 1.2;
 pub const DEFAULT_BM25_B: f32 = 0.75;
 
+/// Norm encoding of `BM25Similarity`, used at index time by default.
+pub const BM25_NORM_ENCODING: NormEncoding = NormEncoding {
+    name: "BM25Similarity",
+    compute_norm: BM25Similarity::compute_norm,
+    decode_length: decode_length,
+};
+
+/// Norm encoding of `BM25Similarity` recording the exact lengths of the
+/// short fields, at the cost of ignoring the index time boosts: the number of
+/// terms is encoded with `SmallFloat::int_to_byte4` rather than the inverse
+/// of its square root with `SmallFloat::float_to_byte315`.
+pub const BM25_LENGTH_NORM_ENCODING: NormEncoding = NormEncoding {
+    name: "BM25Similarity.length",
+    compute_norm: BM25Similarity::compute_length_norm,
+    decode_length: decode_int_length,
+};
+
+fn decode_length(norm: u8) -> f32 {
+    NORM_TABLE[norm as usize]
+}
+
+fn decode_int_length(norm: u8) -> f32 {
+    LENGTH_TABLE[norm as usize]
+}
+
 pub struct BM25Similarity {
     k1: f32,
     b: f32,
+    norm_encoding: NormEncoding,
+    norm_remap: Option<NormRemap>,
 }
 
 impl Default for BM25Similarity {
@@ -56,7 +92,19 @@ impl Default for BM25Similarity {
 
 impl BM25Similarity {
     pub fn new(k1: f32, b: f32) -> BM25Similarity {
-        BM25Similarity { k1, b }
+        BM25Similarity::with_norm_encoding(k1, b, BM25_NORM_ENCODING)
+    }
+
+    /// Creates a `BM25Similarity` decoding the norms with `norm_encoding`,
+    /// which should be the one the norms were computed with at index time,
+    /// e.g. `BM25_LENGTH_NORM_ENCODING`.
+    pub fn with_norm_encoding(k1: f32, b: f32, norm_encoding: NormEncoding) -> BM25Similarity {
+        BM25Similarity {
+            k1,
+            b,
+            norm_encoding,
+            norm_remap: None,
+        }
     }
 
     fn sloppy_freq(distance: i32) -> f32 {
@@ -88,9 +136,25 @@ impl BM25Similarity {
         SmallFloat::float_to_byte315(boost / (field_length as f32).sqrt())
     }
 
-    #[inline]
-    fn decode_norm_value(b: usize) -> f32 {
-        NORM_TABLE[b]
+    /// Computes the norm of `BM25_LENGTH_NORM_ENCODING`.
+    pub fn compute_length_norm(state: &FieldInvertState) -> i64 {
+        let num_terms = state.length - state.num_overlap;
+        i64::from(SmallFloat::int_to_byte4(num_terms))
+    }
+
+    // the length normalization factor of each norm, the norms being decoded
+    // with the norm encoding of this similarity after being remapped
+    fn norm_cache(&self, avgdl: f32) -> [f32; 256] {
+        let mut cache: [f32; 256] = [0f32; 256];
+        for (i, c) in cache.iter_mut().enumerate() {
+            let norm = match self.norm_remap {
+                Some(ref remap) => remap.map(i as u8),
+                None => i as u8,
+            };
+            let length = (self.norm_encoding.decode_length)(norm);
+            *c = self.k1 * ((1.0 - self.b) + self.b * (length / avgdl));
+        }
+        cache
     }
 
     fn idf(term_stats: &[TermStatistics], collection_stats: &CollectionStatistics) -> f32 {
@@ -155,11 +219,7 @@ impl<C: Codec> Similarity<C> for BM25Similarity {
         let avgdl = BM25Similarity::avg_field_length(&collection_stats);
         let idf = BM25Similarity::idf(&term_stats, &collection_stats);
         let field = collection_stats.field.clone();
-        let mut cache: [f32; 256] = [0f32; 256];
-        for (i, c) in cache.iter_mut().enumerate() {
-            *c = self.k1
-                * ((1.0 - self.b) + self.b * (BM25Similarity::decode_norm_value(i) / avgdl));
-        }
+        let cache = self.norm_cache(avgdl);
 
         Box::new(BM25SimWeight::new(
             self.k1,
@@ -170,8 +230,18 @@ impl<C: Codec> Similarity<C> for BM25Similarity {
             self.idf_explain(collection_stats, term_stats),
             BM25Similarity::avg_field_length(collection_stats),
             boost,
+            self.norm_encoding,
+            self.norm_remap.clone(),
         ))
     }
+
+    fn norm_encoding(&self) -> Option<NormEncoding> {
+        Some(self.norm_encoding)
+    }
+
+    fn set_norm_remap(&mut self, remap: NormRemap) {
+        self.norm_remap = Some(remap);
+    }
 }
 
 impl fmt::Display for BM25Similarity {
@@ -180,6 +250,48 @@ impl fmt::Display for BM25Similarity {
     }
 }
 
+/// Creates `BM25Similarity` with the same parameters for every field.
+#[derive(Clone, Copy)]
+pub struct BM25SimilarityProducer {
+    k1: f32,
+    b: f32,
+    norm_encoding: NormEncoding,
+}
+
+impl BM25SimilarityProducer {
+    pub fn new(k1: f32, b: f32) -> BM25SimilarityProducer {
+        BM25SimilarityProducer::with_norm_encoding(k1, b, BM25_NORM_ENCODING)
+    }
+
+    pub fn with_norm_encoding(
+        k1: f32,
+        b: f32,
+        norm_encoding: NormEncoding,
+    ) -> BM25SimilarityProducer {
+        BM25SimilarityProducer {
+            k1,
+            b,
+            norm_encoding,
+        }
+    }
+}
+
+impl Default for BM25SimilarityProducer {
+    fn default() -> Self {
+        BM25SimilarityProducer::new(DEFAULT_BM25_K1, DEFAULT_BM25_B)
+    }
+}
+
+impl<C: Codec> SimilarityProducer<C> for BM25SimilarityProducer {
+    fn create(&self, _field: &str) -> Box<dyn Similarity<C>> {
+        Box::new(BM25Similarity::with_norm_encoding(
+            self.k1,
+            self.b,
+            self.norm_encoding,
+        ))
+    }
+}
+
 pub struct BM25SimScorer {
     k1: f32,
     weight: f32,
@@ -217,6 +329,15 @@ impl SimScorer for BM25SimScorer {
     fn compute_slop_factor(&self, distance: i32) -> f32 {
         BM25Similarity::sloppy_freq(distance)
     }
+
+    // freq / (freq + norm) is below 1
+    fn max_score(&self) -> f32 {
+        if self.weight >= 0.0 {
+            self.weight * (self.k1 + 1.0)
+        } else {
+            f32::INFINITY
+        }
+    }
 }
 
 pub struct BM25SimWeight {
@@ -230,9 +351,12 @@ pub struct BM25SimWeight {
     weight: f32,
     idf_explanation: Explanation,
     avg_dl: f32,
+    norm_encoding: NormEncoding,
+    norm_remap: Option<NormRemap>,
 }
 
 impl BM25SimWeight {
+    #[allow(too_many_arguments)]
     fn new(
         k1: f32,
         b: f32,
@@ -242,6 +366,8 @@ impl BM25SimWeight {
         idf_explanation: Explanation,
         avg_dl: f32,
         boost: f32,
+        norm_encoding: NormEncoding,
+        norm_remap: Option<NormRemap>,
     ) -> BM25SimWeight {
         let mut weight = BM25SimWeight {
             k1,
@@ -253,6 +379,8 @@ impl BM25SimWeight {
             weight: 0.0,
             idf_explanation,
             avg_dl,
+            norm_encoding,
+            norm_remap,
         };
         weight.do_normalize(boost);
         weight
@@ -277,7 +405,12 @@ impl BM25SimWeight {
 
         match norms {
             Some(n) => {
-                let doc_len = NORM_TABLE[n.get(doc)? as usize];
+                let norm = (n.get(doc)? & 0xFF) as u8;
+                let (norm, encoding) = match self.norm_remap {
+                    Some(ref remap) => (remap.map(norm), remap.source),
+                    None => (norm, self.norm_encoding.name),
+                };
+                let doc_len = (self.norm_encoding.decode_length)(norm);
                 subs.push(Explanation::new(
                     true,
                     self.b,
@@ -293,7 +426,7 @@ impl BM25SimWeight {
                 subs.push(Explanation::new(
                     true,
                     doc_len,
-                    "fieldLength".to_string(),
+                    format!("fieldLength, from the norm encoded by {}", encoding),
                     vec![],
                 ));
 
@@ -391,17 +524,23 @@ impl<C: Codec> SimWeight<C> for BM25SimWeight {
 mod tests {
     use super::*;
     use core::index::tests::MockLeafReader;
+    use core::index::NumericDocValuesContext;
 
     // copy from Lucene TestBM25Similarity
     #[test]
     fn test_sane_norm_values() {
         for i in 0..256 {
-            let len = BM25Similarity::decode_norm_value(i);
+            let len = decode_length(i as u8);
             assert!(len >= 0f32);
             assert!(!len.is_nan());
             assert!(!len.is_infinite());
             if i > 0 {
-                assert!(len < BM25Similarity::decode_norm_value(i - 1));
+                assert!(len < decode_length(i as u8 - 1));
+            }
+
+            let len = decode_int_length(i as u8);
+            if i > 0 {
+                assert!(len > decode_int_length(i as u8 - 1));
             }
         }
     }
@@ -459,4 +598,67 @@ mod tests {
 
         assert!(score1 > score2);
     }
+
+    struct CorpusNorms(Vec<u8>);
+
+    impl NumericDocValues for CorpusNorms {
+        fn get_with_ctx(
+            &self,
+            ctx: NumericDocValuesContext,
+            doc_id: DocId,
+        ) -> Result<(i64, NumericDocValuesContext)> {
+            Ok((i64::from(self.0[doc_id as usize]), ctx))
+        }
+    }
+
+    // scores a term occurring twice in a document of 3 terms and once in
+    // documents of 10, 25 and 100 terms, against the expected field lengths
+    fn check_corpus_scores(encoding: NormEncoding, expected_lengths: &[f64]) {
+        let lengths = [3, 10, 25, 100];
+        let freqs = [2.0f32, 1.0, 1.0, 1.0];
+        let norms = lengths
+            .iter()
+            .map(|&length| {
+                let state = FieldInvertState::new("body".to_string(), 0, length, 0, 0, 1.0);
+                (encoding.compute_norm)(&state) as u8
+            })
+            .collect();
+
+        let sim = BM25Similarity::with_norm_encoding(1.2, 0.75, encoding);
+        // 10 documents of 27.6 terms on average, the term occurring in 4
+        let collection_stats = CollectionStatistics::new("body".to_string(), 10, 10, 276, -1);
+        let term_stats = vec![TermStatistics::new(Vec::new(), 4, 5)];
+        let avgdl = BM25Similarity::avg_field_length(&collection_stats);
+        let idf = BM25Similarity::idf(&term_stats, &collection_stats);
+        assert!((f64::from(idf) - (1.0f64 + 6.5 / 4.5).ln()).abs() < 1e-6);
+
+        let mut scorer = BM25SimScorer {
+            k1: 1.2,
+            weight: idf,
+            cache: Arc::new(sim.norm_cache(avgdl)),
+            norms: Some(Box::new(CorpusNorms(norms))),
+        };
+        for doc in 0..lengths.len() {
+            let freq = f64::from(freqs[doc]);
+            let tf_norm = freq * 2.2 / (freq + 1.2 * (0.25 + 0.75 * expected_lengths[doc] / 27.6));
+            let expected = f64::from(idf) * tf_norm;
+            let score = f64::from(scorer.score(doc as DocId, freqs[doc]).unwrap());
+            assert!(
+                (score - expected).abs() < 1e-5,
+                "doc {}: {} != {}",
+                doc,
+                score,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn test_corpus_scores() {
+        // the lengths decoded by Lucene 6, the inverse square roots of the
+        // lengths keeping 3 bits of mantissa
+        check_corpus_scores(BM25_NORM_ENCODING, &[4.0, 10.24, 28.444_445, 113.777_78]);
+        // the lengths decoded by Lucene 7, the short ones being exact
+        check_corpus_scores(BM25_LENGTH_NORM_ENCODING, &[3.0, 10.0, 25.0, 96.0]);
+    }
 }
@@ -13,6 +13,7 @@
 
 use std::any::Any;
 use std::fmt;
+use std::sync::Arc;
 
 use core::codec::Codec;
 use core::index::LeafReaderContext;
@@ -20,17 +21,22 @@ use core::search::conjunction::ConjunctionScorer;
 use core::search::disjunction::DisjunctionSumScorer;
 use core::search::explanation::Explanation;
 use core::search::match_all::ConstantScoreQuery;
+use core::search::match_no_docs::MatchNoDocsQuery;
+use core::search::req_excl::ReqExclScorer;
 use core::search::req_opt::ReqOptScorer;
+use core::search::scorer_arena::{PooledVec, ScorerArena};
 use core::search::searcher::SearchPlanBuilder;
 use core::search::term_query::TermQuery;
+use core::search::wand::WANDScorer;
 use core::search::{Query, Scorer, Weight};
 use core::util::DocId;
-use error::{ErrorKind::IllegalArgument, Result};
+use error::Result;
 
 pub struct BooleanQuery<C: Codec> {
     must_queries: Vec<Box<dyn Query<C>>>,
     should_queries: Vec<Box<dyn Query<C>>>,
     filter_queries: Vec<Box<dyn Query<C>>>,
+    must_not_queries: Vec<Box<dyn Query<C>>>,
     minimum_should_match: i32,
 }
 
@@ -41,17 +47,28 @@ impl<C: Codec> BooleanQuery<C> {
         musts: Vec<Box<dyn Query<C>>>,
         shoulds: Vec<Box<dyn Query<C>>>,
         filters: Vec<Box<dyn Query<C>>>,
+    ) -> Result<Box<dyn Query<C>>> {
+        Self::build_with_must_nots(musts, shoulds, filters, vec![])
+    }
+
+    /// Like `build`, excluding the docs matching any of `must_nots`.
+    ///
+    /// A query with only `must_nots` matches no doc, and one without any
+    /// clause is built as a `MatchNoDocsQuery`.
+    pub fn build_with_must_nots(
+        musts: Vec<Box<dyn Query<C>>>,
+        shoulds: Vec<Box<dyn Query<C>>>,
+        filters: Vec<Box<dyn Query<C>>>,
+        must_nots: Vec<Box<dyn Query<C>>>,
     ) -> Result<Box<dyn Query<C>>> {
         let minimum_should_match = if musts.is_empty() { 1 } else { 0 };
         let mut musts = musts;
         let mut shoulds = shoulds;
         let mut filters = filters;
-        if musts.len() + shoulds.len() + filters.len() == 0 {
-            bail!(IllegalArgument(
-                "boolean query should at least contain one inner query!".into()
-            ));
+        if musts.len() + shoulds.len() + filters.len() + must_nots.len() == 0 {
+            return Ok(Box::new(MatchNoDocsQuery::new("empty BooleanQuery")));
         }
-        if musts.len() + shoulds.len() + filters.len() == 1 {
+        if must_nots.is_empty() && musts.len() + shoulds.len() + filters.len() == 1 {
             let query = if musts.len() == 1 {
                 musts.remove(0)
             } else if shoulds.len() == 1 {
@@ -65,10 +82,33 @@ impl<C: Codec> BooleanQuery<C> {
             must_queries: musts,
             should_queries: shoulds,
             filter_queries: filters,
+            must_not_queries: must_nots,
             minimum_should_match,
         }))
     }
 
+    pub fn must_queries(&self) -> &[Box<dyn Query<C>>] {
+        &self.must_queries
+    }
+
+    pub fn should_queries(&self) -> &[Box<dyn Query<C>>] {
+        &self.should_queries
+    }
+
+    pub fn filter_queries(&self) -> &[Box<dyn Query<C>>] {
+        &self.filter_queries
+    }
+
+    pub fn must_not_queries(&self) -> &[Box<dyn Query<C>>] {
+        &self.must_not_queries
+    }
+
+    /// The number of should clauses a doc has to match, 0 when there are must
+    /// clauses.
+    pub fn minimum_should_match(&self) -> i32 {
+        self.minimum_should_match
+    }
+
     fn queries_to_str(&self, queries: &[Box<dyn Query<C>>]) -> String {
         let query_strs: Vec<String> = queries.iter().map(|q| format!("{}", q)).collect();
         query_strs.join(", ")
@@ -93,12 +133,17 @@ impl<C: Codec> Query<C> for BooleanQuery<C> {
         for q in &self.should_queries {
             should_weights.push(searcher.create_weight(q.as_ref(), needs_scores)?);
         }
+        let mut must_not_weights = Vec::with_capacity(self.must_not_queries.len());
+        for q in &self.must_not_queries {
+            must_not_weights.push(searcher.create_weight(q.as_ref(), false)?);
+        }
 
-        Ok(Box::new(BooleanWeight::new(
-            must_weights,
-            should_weights,
-            needs_scores,
-        )))
+        let mut weight =
+            BooleanWeight::new(must_weights, should_weights, must_not_weights, needs_scores);
+        if let Some(arena) = searcher.scorer_arena() {
+            weight.set_scorer_arena(Arc::clone(arena));
+        }
+        Ok(Box::new(weight))
     }
 
     fn extract_terms(&self) -> Vec<TermQuery> {
@@ -135,32 +180,69 @@ impl<C: Codec> fmt::Display for BooleanQuery<C> {
         let filters_str = self.queries_to_str(&self.filter_queries);
         write!(
             f,
-            "BooleanQuery(must: [{}], should: [{}], filters: [{}], match: {})",
-            must_str, should_str, filters_str, self.minimum_should_match
-        )
+            "BooleanQuery(must: [{}], should: [{}], filters: [{}], ",
+            must_str, should_str, filters_str
+        )?;
+        if !self.must_not_queries.is_empty() {
+            let must_not_str = self.queries_to_str(&self.must_not_queries);
+            write!(f, "must_not: [{}], ", must_not_str)?;
+        }
+        write!(f, "match: {})", self.minimum_should_match)
     }
 }
 
 pub struct BooleanWeight<C: Codec> {
     must_weights: Vec<Box<dyn Weight<C>>>,
     should_weights: Vec<Box<dyn Weight<C>>>,
+    must_not_weights: Vec<Box<dyn Weight<C>>>,
     #[allow(dead_code)]
     minimum_should_match: i32,
     needs_scores: bool,
+    scorer_arena: Option<Arc<ScorerArena>>,
 }
 
 impl<C: Codec> BooleanWeight<C> {
     pub fn new(
         musts: Vec<Box<dyn Weight<C>>>,
         shoulds: Vec<Box<dyn Weight<C>>>,
+        must_nots: Vec<Box<dyn Weight<C>>>,
         needs_scores: bool,
     ) -> BooleanWeight<C> {
         let minimum_should_match = if musts.is_empty() { 1 } else { 0 };
         BooleanWeight {
             must_weights: musts,
             should_weights: shoulds,
+            must_not_weights: must_nots,
             minimum_should_match,
             needs_scores,
+            scorer_arena: None,
+        }
+    }
+
+    /// Takes the buffers of the scorers from `arena`.
+    pub fn set_scorer_arena(&mut self, arena: Arc<ScorerArena>) {
+        self.scorer_arena = Some(arena);
+    }
+
+    fn scorer_list(&self, capacity: usize) -> PooledVec<Box<dyn Scorer>> {
+        match self.scorer_arena {
+            Some(ref arena) => arena.scorers(capacity),
+            None => Vec::with_capacity(capacity).into(),
+        }
+    }
+
+    // the disjunction of at least one scorer
+    fn disjunction(&self, mut scorers: PooledVec<Box<dyn Scorer>>) -> Box<dyn Scorer> {
+        if scorers.len() == 1 {
+            scorers.remove(0)
+        } else if let Some(ref arena) = self.scorer_arena {
+            Box::new(arena.disjunction_sum(scorers))
+        } else {
+            Box::new(DisjunctionSumScorer::with_buffers(
+                scorers,
+                PooledVec::default(),
+                PooledVec::default(),
+            ))
         }
     }
 
@@ -176,16 +258,29 @@ impl<C: Codec> Weight<C> for BooleanWeight<C> {
         leaf_reader: &LeafReaderContext<'_, C>,
     ) -> Result<Option<Box<dyn Scorer>>> {
         let must_scorer: Option<Box<dyn Scorer>> = if !self.must_weights.is_empty() {
-            let mut scorers = vec![];
+            let mut suppliers = Vec::with_capacity(self.must_weights.len());
             for weight in &self.must_weights {
-                if let Some(scorer) = weight.create_scorer(leaf_reader)? {
-                    scorers.push(scorer);
+                if let Some(supplier) = weight.scorer_supplier(leaf_reader)? {
+                    suppliers.push(supplier);
                 } else {
                     return Ok(None);
                 }
             }
+            // the cheapest clause leads the conjunction, the others may pick
+            // how they iterate knowing it
+            let lead_cost = suppliers.iter().map(|s| s.cost()).min().unwrap();
+            let mut scorers = self.scorer_list(suppliers.len());
+            for supplier in suppliers {
+                scorers.push(supplier.get(lead_cost)?);
+            }
             if scorers.len() > 1 {
-                Some(Box::new(ConjunctionScorer::new(scorers)))
+                Some(match self.scorer_arena {
+                    Some(ref arena) => Box::new(arena.conjunction(scorers)),
+                    None => Box::new(ConjunctionScorer::with_buffers(
+                        scorers,
+                        PooledVec::default(),
+                    )),
+                })
             } else {
                 Some(scorers.remove(0))
             }
@@ -193,31 +288,58 @@ impl<C: Codec> Weight<C> for BooleanWeight<C> {
             None
         };
         let should_scorer: Option<Box<dyn Scorer>> = {
-            let mut scorers = vec![];
+            let mut scorers = self.scorer_list(self.should_weights.len());
             for weight in &self.should_weights {
                 if let Some(scorer) = weight.create_scorer(leaf_reader)? {
                     scorers.push(scorer);
                 }
             }
-            match scorers.len() {
-                0 => None,
-                1 => Some(scorers.remove(0)),
-                _ => Some(Box::new(DisjunctionSumScorer::new(scorers))),
+            if scorers.is_empty() {
+                None
+            } else if self.needs_scores
+                && must_scorer.is_none()
+                && scorers.len() > 1
+                && WANDScorer::can_skip(&scorers)
+            {
+                // the docs that can't compete are skipped once the collector
+                // tells the minimum competitive score
+                Some(Box::new(WANDScorer::new(scorers)))
+            } else {
+                Some(self.disjunction(scorers))
+            }
+        };
+
+        let must_not_scorer: Option<Box<dyn Scorer>> = {
+            let mut scorers = self.scorer_list(self.must_not_weights.len());
+            for weight in &self.must_not_weights {
+                if let Some(scorer) = weight.create_scorer(leaf_reader)? {
+                    scorers.push(scorer);
+                }
+            }
+            if scorers.is_empty() {
+                None
+            } else {
+                Some(self.disjunction(scorers))
             }
         };
 
-        if let Some(must) = must_scorer {
+        let scorer: Box<dyn Scorer> = if let Some(must) = must_scorer {
             if let Some(should) = should_scorer {
-                Ok(Some(Box::new(ReqOptScorer::new(must, should))))
+                Box::new(ReqOptScorer::new(must, should))
             } else {
-                Ok(Some(must))
+                must
             }
         } else {
             if let Some(should) = should_scorer {
-                Ok(Some(should))
+                should
             } else {
-                Ok(None)
+                return Ok(None);
             }
+        };
+        if let Some(must_not) = must_not_scorer {
+            Ok(Some(Box::new(ReqExclScorer::new(scorer, must_not))))
+        } else {
+            Ok(Some(scorer))
         }
     }
 
@@ -290,6 +412,19 @@ impl<C: Codec> Weight<C> for BooleanWeight<C> {
             }
         }
 
+        for w in &self.must_not_weights {
+            let e = w.explain(reader, doc)?;
+            if e.is_match() {
+                fail = true;
+                subs.push(Explanation::new(
+                    false,
+                    0.0f32,
+                    format!("match on prohibited clause ({})", w),
+                    vec![e],
+                ));
+            }
+        }
+
         if fail {
             Ok(Explanation::new(
                 false,
@@ -345,10 +480,12 @@ impl<C: Codec> fmt::Display for BooleanWeight<C> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let must_str = self.weights_to_str(&self.must_weights);
         let should_str = self.weights_to_str(&self.should_weights);
+        let must_not_str = self.weights_to_str(&self.must_not_weights);
         write!(
             f,
-            "BooleanWeight(must: [{}], should: [{}], min match: {}, needs score: {})",
-            must_str, should_str, self.minimum_should_match, self.needs_scores
+            "BooleanWeight(must: [{}], should: [{}], must_not: [{}], min match: {}, needs score: \
+             {})",
+            must_str, should_str, must_not_str, self.minimum_should_match, self.needs_scores
         )
     }
 }
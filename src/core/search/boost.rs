@@ -20,7 +20,7 @@ use core::index::LeafReaderContext;
 use core::search::explanation::Explanation;
 use core::search::searcher::SearchPlanBuilder;
 use core::search::term_query::TermQuery;
-use core::search::{Query, Scorer, Weight};
+use core::search::{Query, Scorer, ScorerSupplier, Weight};
 use core::util::DocId;
 
 use error::Result;
@@ -40,6 +40,14 @@ impl<C: Codec> BoostQuery<C> {
             Box::new(BoostQuery { query, boost })
         }
     }
+
+    pub fn query(&self) -> &dyn Query<C> {
+        self.query.as_ref()
+    }
+
+    pub fn boost(&self) -> f32 {
+        self.boost
+    }
 }
 
 impl<C: Codec> Query<C> for BoostQuery<C> {
@@ -98,6 +106,17 @@ impl<C: Codec> Weight<C> for BoostWeight<C> {
         self.weight.create_scorer(leaf_reader)
     }
 
+    fn scorer_supplier<'a>(
+        &'a self,
+        leaf_reader: &'a LeafReaderContext<'_, C>,
+    ) -> Result<Option<Box<dyn ScorerSupplier + 'a>>> {
+        self.weight.scorer_supplier(leaf_reader)
+    }
+
+    fn count(&self, leaf_reader: &LeafReaderContext<'_, C>) -> Result<Option<usize>> {
+        self.weight.count(leaf_reader)
+    }
+
     fn query_type(&self) -> &'static str {
         BOOST_QUERY
     }
@@ -119,7 +138,17 @@ impl<C: Codec> Weight<C> for BoostWeight<C> {
     }
 
     fn explain(&self, reader: &LeafReaderContext<'_, C>, doc: DocId) -> Result<Explanation> {
-        self.weight.explain(reader, doc)
+        // the boost is already part of the score of the wrapped weight
+        let explanation = self.weight.explain(reader, doc)?;
+        if !explanation.is_match() {
+            return Ok(explanation);
+        }
+        Ok(Explanation::new(
+            true,
+            explanation.value(),
+            format!("boosted by {}, of:", self.boost),
+            vec![explanation],
+        ))
     }
 }
 
@@ -17,6 +17,7 @@ use std::sync::Mutex;
 use core::codec::Codec;
 use core::search::match_all::{CONSTANT, MATCH_ALL};
 use core::search::point_range::POINT_RANGE;
+use core::search::term_in_set::TERM_IN_SET;
 use core::search::term_query::TERM;
 use core::search::Weight;
 
@@ -69,6 +70,8 @@ const SENTINEL: u32 = u32::max_value();
 /// filters in order to decide on which filters are worth caching.
 pub struct UsageTrackingQueryCachingPolicy {
     recently_used_filters: Mutex<FrequencyTrackingRingBuffer>,
+    // overrides the frequencies depending on the cost of the queries
+    min_frequency: Option<u32>,
 }
 
 impl UsageTrackingQueryCachingPolicy {
@@ -77,12 +80,20 @@ impl UsageTrackingQueryCachingPolicy {
             Mutex::new(FrequencyTrackingRingBuffer::new(history_size, SENTINEL));
         UsageTrackingQueryCachingPolicy {
             recently_used_filters,
+            min_frequency: None,
         }
     }
 
+    /// Caches the queries used at least `min_frequency` times in the history,
+    /// whatever their cost.
+    pub fn with_min_frequency(mut self, min_frequency: u32) -> UsageTrackingQueryCachingPolicy {
+        self.min_frequency = Some(min_frequency);
+        self
+    }
+
     fn is_costly<C: Codec>(w: &dyn Weight<C>) -> bool {
-        // TODO currently only PointRangeQuery is costly
-        w.actual_query_type() == POINT_RANGE
+        let query_type = w.actual_query_type();
+        query_type == POINT_RANGE || query_type == TERM_IN_SET
     }
 
     fn is_cheap<C: Codec>(w: &dyn Weight<C>) -> bool {
@@ -90,7 +101,9 @@ impl UsageTrackingQueryCachingPolicy {
     }
 
     fn cache_min_frequency<C: Codec>(&self, w: &dyn Weight<C>) -> u32 {
-        if Self::is_costly(w) {
+        if let Some(min_frequency) = self.min_frequency {
+            min_frequency
+        } else if Self::is_costly(w) {
             2
         } else if Self::is_cheap(w) {
             20
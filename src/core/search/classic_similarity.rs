@@ -0,0 +1,331 @@
+// Copyright 2019 Zhizhesihai (Beijing) Technology Limited.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use error::Result;
+use std::fmt;
+
+use core::codec::Codec;
+use core::index::field_info::FieldInvertState;
+use core::index::{NumericDocValues, SearchLeafReader};
+use core::search::explanation::Explanation;
+use core::search::norm_encoding::{NormEncoding, NormRemap};
+use core::search::statistics::{CollectionStatistics, TermStatistics};
+use core::search::{SimScorer, SimWeight, Similarity, SimilarityProducer};
+use core::util::small_float::SmallFloat;
+use core::util::{DocId, KeyedContext};
+
+lazy_static! {
+    static ref NORM_TABLE: [f32; 256] = {
+        let mut norm_table: [f32; 256] = [0f32; 256];
+        for (i, norm) in norm_table.iter_mut().enumerate() {
+            *norm = SmallFloat::byte315_to_float(i as u8);
+        }
+        norm_table
+    };
+}
+
+/// Norm encoding of `ClassicSimilarity`.
+pub const CLASSIC_NORM_ENCODING: NormEncoding = NormEncoding {
+    name: "ClassicSimilarity",
+    compute_norm: ClassicSimilarity::compute_norm,
+    decode_length: decode_length,
+};
+
+fn decode_length(norm: u8) -> f32 {
+    // the length of the empty norm is taken the same way as by BM25Similarity
+    let f = if norm == 0 {
+        1.0 / NORM_TABLE[255]
+    } else {
+        NORM_TABLE[norm as usize]
+    };
+    1.0 / (f * f)
+}
+
+/// The vector space model scoring of Lucene's `ClassicSimilarity`: the score of a
+/// document is `sqrt(freq) * idf^2 * boost * fieldNorm`, where the field norm is
+/// `1 / sqrt(fieldLength)`.
+#[derive(Default)]
+pub struct ClassicSimilarity {
+    norm_remap: Option<NormRemap>,
+}
+
+impl ClassicSimilarity {
+    pub fn new() -> ClassicSimilarity {
+        Self::default()
+    }
+
+    pub fn compute_norm(state: &FieldInvertState) -> i64 {
+        let num_terms = state.length - state.num_overlap;
+        let norm = state.boost / (num_terms as f32).sqrt();
+        i64::from(SmallFloat::float_to_byte315(norm))
+    }
+
+    fn tf(freq: f32) -> f32 {
+        freq.sqrt()
+    }
+
+    fn idf(doc_freq: i64, doc_count: i64) -> f32 {
+        (((doc_count + 1) as f64 / (doc_freq + 1) as f64).ln() + 1.0) as f32
+    }
+
+    fn idf_explain(
+        collection_stats: &CollectionStatistics,
+        term_stats: &[TermStatistics],
+    ) -> Explanation {
+        let doc_count = if collection_stats.doc_count == -1 {
+            collection_stats.max_doc
+        } else {
+            collection_stats.doc_count
+        };
+        let mut idf_total = 0f32;
+        let mut details = Vec::with_capacity(term_stats.len());
+        for stat in term_stats {
+            let idf = ClassicSimilarity::idf(stat.doc_freq, doc_count);
+            idf_total += idf;
+            details.push(Explanation::new(
+                true,
+                idf,
+                "idf, computed as log((docCount + 1) / (docFreq + 1)) + 1 from:".to_string(),
+                vec![
+                    Explanation::new(true, stat.doc_freq as f32, "docFreq".to_string(), vec![]),
+                    Explanation::new(true, doc_count as f32, "docCount".to_string(), vec![]),
+                ],
+            ));
+        }
+
+        Explanation::new(true, idf_total, "idf(), sum of:".to_string(), details)
+    }
+}
+
+impl<C: Codec> Similarity<C> for ClassicSimilarity {
+    fn compute_weight(
+        &self,
+        collection_stats: &CollectionStatistics,
+        term_stats: &[TermStatistics],
+        _context: Option<&KeyedContext>,
+        boost: f32,
+    ) -> Box<dyn SimWeight<C>> {
+        let idf = ClassicSimilarity::idf_explain(collection_stats, term_stats);
+        let mut weight = ClassicSimWeight {
+            field: collection_stats.field.clone(),
+            idf,
+            boost: 1.0,
+            query_norm: 1.0,
+            query_weight: 0.0,
+            value: 0.0,
+            norm_remap: self.norm_remap.clone(),
+        };
+        weight.do_normalize(1.0, boost);
+        Box::new(weight)
+    }
+
+    fn query_norm(&self, value_for_normalization: f32, _context: Option<&KeyedContext>) -> f32 {
+        1.0 / value_for_normalization.sqrt()
+    }
+
+    fn norm_encoding(&self) -> Option<NormEncoding> {
+        Some(CLASSIC_NORM_ENCODING)
+    }
+
+    fn set_norm_remap(&mut self, remap: NormRemap) {
+        self.norm_remap = Some(remap);
+    }
+}
+
+impl fmt::Display for ClassicSimilarity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "ClassicSimilarity")
+    }
+}
+
+/// Creates `ClassicSimilarity` for every field.
+#[derive(Clone, Copy, Default)]
+pub struct ClassicSimilarityProducer;
+
+impl<C: Codec> SimilarityProducer<C> for ClassicSimilarityProducer {
+    fn create(&self, _field: &str) -> Box<dyn Similarity<C>> {
+        Box::new(ClassicSimilarity::new())
+    }
+}
+
+pub struct ClassicSimWeight {
+    field: String,
+    idf: Explanation,
+    boost: f32,
+    query_norm: f32,
+    query_weight: f32,
+    value: f32,
+    norm_remap: Option<NormRemap>,
+}
+
+impl ClassicSimWeight {
+    fn do_normalize(&mut self, query_norm: f32, boost: f32) {
+        self.boost = boost;
+        self.query_norm = query_norm;
+        self.query_weight = query_norm * boost * self.idf.value();
+        self.value = self.query_weight * self.idf.value();
+    }
+
+    fn explain_field_norm(
+        &self,
+        doc: DocId,
+        norms: Option<Box<dyn NumericDocValues>>,
+    ) -> Result<Explanation> {
+        match norms {
+            Some(n) => {
+                let norm = (n.get(doc)? & 0xFF) as u8;
+                let (norm, encoding) = match self.norm_remap {
+                    Some(ref remap) => (remap.map(norm), remap.source),
+                    None => (norm, CLASSIC_NORM_ENCODING.name),
+                };
+                Ok(Explanation::new(
+                    true,
+                    NORM_TABLE[norm as usize],
+                    format!(
+                        "fieldNorm(doc={}), from the norm encoded by {}",
+                        doc, encoding
+                    ),
+                    vec![],
+                ))
+            }
+            None => Ok(Explanation::new(
+                true,
+                1.0,
+                format!("fieldNorm(doc={}), norms omitted for field", doc),
+                vec![],
+            )),
+        }
+    }
+}
+
+impl<C: Codec> SimWeight<C> for ClassicSimWeight {
+    fn get_value_for_normalization(&self) -> f32 {
+        self.query_weight * self.query_weight
+    }
+
+    fn normalize(&mut self, query_norm: f32, boost: f32) {
+        self.do_normalize(query_norm, boost)
+    }
+
+    fn sim_scorer(&self, reader: &SearchLeafReader<C>) -> Result<Box<dyn SimScorer>> {
+        Ok(Box::new(ClassicSimScorer {
+            weight: self.value,
+            norms: reader.norm_values(&self.field)?,
+            norm_remap: self.norm_remap.clone(),
+        }))
+    }
+
+    fn explain(
+        &self,
+        reader: &SearchLeafReader<C>,
+        doc: DocId,
+        freq: Explanation,
+    ) -> Result<Explanation> {
+        let freq_string = freq.to_string(0);
+        let tf = Explanation::new(
+            true,
+            ClassicSimilarity::tf(freq.value()),
+            format!("tf(freq={}), with freq of:", freq_string),
+            vec![freq],
+        );
+        let field_norm = self.explain_field_norm(doc, reader.norm_values(&self.field)?)?;
+        let field_weight = Explanation::new(
+            true,
+            tf.value() * self.idf.value() * field_norm.value(),
+            format!("fieldWeight in {}, product of:", doc),
+            vec![tf, self.idf.clone(), field_norm],
+        );
+
+        let mut query_subs = Vec::with_capacity(3);
+        if (self.boost - 1.0).abs() > ::std::f32::EPSILON {
+            query_subs.push(Explanation::new(
+                true,
+                self.boost,
+                "boost".to_string(),
+                vec![],
+            ));
+        }
+        query_subs.push(self.idf.clone());
+        query_subs.push(Explanation::new(
+            true,
+            self.query_norm,
+            "queryNorm".to_string(),
+            vec![],
+        ));
+        let query_weight = Explanation::new(
+            true,
+            self.query_weight,
+            "queryWeight, product of:".to_string(),
+            query_subs,
+        );
+
+        Ok(Explanation::new(
+            true,
+            query_weight.value() * field_weight.value(),
+            format!("score(doc={},freq={}), product of:", doc, freq_string),
+            vec![query_weight, field_weight],
+        ))
+    }
+}
+
+pub struct ClassicSimScorer {
+    weight: f32,
+    norms: Option<Box<dyn NumericDocValues>>,
+    norm_remap: Option<NormRemap>,
+}
+
+impl SimScorer for ClassicSimScorer {
+    fn score(&mut self, doc: DocId, freq: f32) -> Result<f32> {
+        let raw = ClassicSimilarity::tf(freq) * self.weight;
+        match self.norms {
+            Some(ref norms) => {
+                let mut norm = (norms.get(doc)? & 0xFF) as u8;
+                if let Some(ref remap) = self.norm_remap {
+                    norm = remap.map(norm);
+                }
+                Ok(raw * NORM_TABLE[norm as usize])
+            }
+            None => Ok(raw),
+        }
+    }
+
+    fn compute_slop_factor(&self, distance: i32) -> f32 {
+        1.0 / (distance as f32 + 1.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::index::tests::MockLeafReader;
+    use core::search::bm25_similarity::BM25Similarity;
+
+    #[test]
+    fn test_classic_similarity() {
+        let collection_stats = CollectionStatistics::new(String::from("world"), 32, 31, 120, -1);
+        let term_stats = vec![TermStatistics::new(Vec::new(), 3, -1)];
+        let sim = ClassicSimilarity::new();
+        let sim_weight = sim.compute_weight(&collection_stats, &term_stats, None, 2.0);
+        let idf = (32f32 / 4f32).ln() + 1.0;
+        assert!((sim_weight.get_value_for_normalization() - (2.0 * idf).powi(2)).abs() < 1e-4);
+
+        let leaf_reader = MockLeafReader::new(1);
+        let mut sim_scorer = sim_weight.sim_scorer(&leaf_reader).unwrap();
+        // doc 1 is 120 terms long
+        let norm = NORM_TABLE[BM25Similarity::encode_norm_value(1.0, 120) as usize];
+        let score = sim_scorer.score(1, 4.0).unwrap();
+        assert!((score - 2.0 * 2.0 * idf * idf * norm).abs() < 1e-4);
+        // shorter fields score higher
+        assert!(score > sim_scorer.score(2, 4.0).unwrap());
+    }
+}
@@ -13,21 +13,38 @@
 
 use core::codec::Codec;
 use core::index::LeafReaderContext;
-use core::search::collector::{Collector, ParallelLeafCollector, SearchCollector};
+use core::search::collector::multi::CombinedScorer;
+use core::search::collector::{Collector, ParallelLeafCollector, ScoreMode, SearchCollector};
 use core::search::Scorer;
 use core::util::DocId;
 use error::Result;
 
+use std::f32;
+
 /// ChainCollector makes it possible to collect on more than one collector in sequence.
+///
+/// Chains nest to collect with more than two collectors, `MultiCollector`
+/// collects with several collectors of the same type. The scores are
+/// computed once if any of the collectors needs them, see `ScoreMode::combine`.
 pub struct ChainedCollector<A, B> {
     first: A,
     second: B,
+    // the min competitive scores of the collectors on the current leaf
+    min_scores: [f32; 2],
 }
 
 impl<A, B> ChainedCollector<A, B> {
     /// Constructor
     pub fn new(first: A, second: B) -> ChainedCollector<A, B> {
-        ChainedCollector { first, second }
+        ChainedCollector {
+            first,
+            second,
+            min_scores: [f32::NEG_INFINITY; 2],
+        }
+    }
+
+    pub fn into_inner(self) -> (A, B) {
+        (self.first, self.second)
     }
 }
 
@@ -39,6 +56,7 @@ where
     type LC = ChainedCollector<A::LC, B::LC>;
 
     fn set_next_reader<C: Codec>(&mut self, reader: &LeafReaderContext<'_, C>) -> Result<()> {
+        self.min_scores = [f32::NEG_INFINITY; 2];
         self.first.set_next_reader(reader)?;
         self.second.set_next_reader(reader)
     }
@@ -51,10 +69,10 @@ where
         &mut self,
         reader: &LeafReaderContext<'_, C>,
     ) -> Result<ChainedCollector<A::LC, B::LC>> {
-        Ok(ChainedCollector {
-            first: self.first.leaf_collector(reader)?,
-            second: self.second.leaf_collector(reader)?,
-        })
+        Ok(ChainedCollector::new(
+            self.first.leaf_collector(reader)?,
+            self.second.leaf_collector(reader)?,
+        ))
     }
 
     fn finish_parallel(&mut self) -> Result<()> {
@@ -62,6 +80,20 @@ where
         self.second.finish_parallel()?;
         self.first.finish_parallel()
     }
+
+    fn min_competitive_score(&self) -> Option<f32> {
+        if self.score_mode().is_exhaustive() {
+            return None;
+        }
+        // docs can only be skipped if both collectors would drop them
+        match (
+            self.first.min_competitive_score(),
+            self.second.min_competitive_score(),
+        ) {
+            (Some(first), Some(second)) => Some(first.min(second)),
+            _ => None,
+        }
+    }
 }
 
 impl<A, B> Collector for ChainedCollector<A, B>
@@ -69,13 +101,16 @@ where
     A: Collector,
     B: Collector,
 {
-    fn needs_scores(&self) -> bool {
-        self.first.needs_scores() || self.second.needs_scores()
+    fn score_mode(&self) -> ScoreMode {
+        self.first.score_mode().combine(self.second.score_mode())
     }
 
     fn collect<S: Scorer + ?Sized>(&mut self, doc: DocId, scorer: &mut S) -> Result<()> {
-        self.first.collect(doc, scorer)?;
-        self.second.collect(doc, scorer)
+        let score_mode = self.score_mode();
+        let mut scorer = CombinedScorer::new(scorer, score_mode, &mut self.min_scores);
+        self.first.collect(doc, &mut scorer)?;
+        scorer.collector = 1;
+        self.second.collect(doc, &mut scorer)
     }
 }
 
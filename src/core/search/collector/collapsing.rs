@@ -0,0 +1,656 @@
+// Copyright 2019 Zhizhesihai (Beijing) Technology Limited.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use core::codec::Codec;
+use core::index::{
+    DocValuesType, LeafReaderContext, NumericDocValuesRef, SearchLeafReader, SortedDocValuesRef,
+};
+use core::search::collector::top_field::compare_field_docs;
+use core::search::collector::{
+    Collector, LeafSender, ParallelChannel, ParallelLeafCollector, ScoreMode, SearchCollector,
+};
+use core::search::field_comparator::{ComparatorValue, FieldComparator, FieldComparatorEnum};
+use core::search::sort::Sort;
+use core::search::sort_field::{SortField, SortFieldType};
+use core::search::top_docs::{CollapseTopFieldDocs, FieldDoc, ScoreDocHit, TopDocs};
+use core::search::Scorer;
+use core::util::{BitsRef, DocId, VariantValue};
+use error::{
+    ErrorKind::{IllegalArgument, IllegalState},
+    Result,
+};
+
+use std::cmp::Ordering;
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::f32;
+use std::hash::Hash;
+use std::mem;
+
+/// What a `CollapsingTopDocsCollector` does with the hits without a value
+/// for the collapse field.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CollapseMissing {
+    /// The hits without a value are collapsed into a group of their own,
+    /// whose collapse value is `None`.
+    Group,
+    /// The hits without a value are left out of the results.
+    Exclude,
+}
+
+// the group of a hit: the value of the collapse field, the same in all the
+// segments
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+enum GroupKey {
+    Missing,
+    Bytes(Vec<u8>),
+    Long(i64),
+}
+
+impl GroupKey {
+    fn into_value(self) -> Option<VariantValue> {
+        match self {
+            GroupKey::Missing => None,
+            GroupKey::Bytes(bytes) => Some(VariantValue::Binary(bytes)),
+            GroupKey::Long(value) => Some(VariantValue::Long(value)),
+        }
+    }
+}
+
+// the group of a hit in a segment, the sorted values are grouped by segment
+// ord so that their bytes are only looked up once per group
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+enum LeafKey {
+    Missing,
+    Ord(i32),
+    Long(i64),
+}
+
+// the doc values of the collapse field in a segment
+enum GroupValues {
+    Sorted(SortedDocValuesRef),
+    Numeric(NumericDocValuesRef, BitsRef),
+    Missing,
+}
+
+impl GroupValues {
+    fn open<C: Codec>(reader: &SearchLeafReader<C>, field: &str) -> Result<GroupValues> {
+        let values = match reader.field_info(field).map(|fi| fi.doc_values_type) {
+            Some(DocValuesType::Sorted) => {
+                GroupValues::Sorted(reader.get_sorted_doc_values(field)?)
+            }
+            Some(DocValuesType::Numeric) => GroupValues::Numeric(
+                reader.get_numeric_doc_values(field)?,
+                reader.get_docs_with_field(field)?,
+            ),
+            None | Some(DocValuesType::Null) => GroupValues::Missing,
+            Some(doc_values_type) => bail!(IllegalArgument(format!(
+                "can't collapse on '{}', it has {:?} doc values rather than sorted or numeric ones",
+                field, doc_values_type
+            ))),
+        };
+        Ok(values)
+    }
+
+    fn key(&self, doc: DocId) -> Result<LeafKey> {
+        match self {
+            GroupValues::Sorted(values) => {
+                let ord = values.get_ord(doc)?;
+                if ord < 0 {
+                    Ok(LeafKey::Missing)
+                } else {
+                    Ok(LeafKey::Ord(ord))
+                }
+            }
+            GroupValues::Numeric(values, docs_with_field) => {
+                if docs_with_field.get(doc as usize)? {
+                    Ok(LeafKey::Long(values.get(doc)?))
+                } else {
+                    Ok(LeafKey::Missing)
+                }
+            }
+            GroupValues::Missing => Ok(LeafKey::Missing),
+        }
+    }
+
+    fn group_key(&self, key: LeafKey) -> Result<GroupKey> {
+        match (key, self) {
+            (LeafKey::Ord(ord), GroupValues::Sorted(values)) => {
+                Ok(GroupKey::Bytes(values.lookup_ord(ord)?))
+            }
+            (LeafKey::Ord(_), _) => unreachable!(),
+            (LeafKey::Long(value), _) => Ok(GroupKey::Long(value)),
+            (LeafKey::Missing, _) => Ok(GroupKey::Missing),
+        }
+    }
+}
+
+// the best hit of every group, with the counts of the hits they were picked from
+struct Groups<K> {
+    best: HashMap<K, FieldDoc>,
+    total_hits: usize,
+    max_score: f32,
+}
+
+impl<K: Eq + Hash> Groups<K> {
+    fn new() -> Groups<K> {
+        Groups {
+            best: HashMap::new(),
+            total_hits: 0,
+            max_score: f32::NAN,
+        }
+    }
+
+    // keeps `hit` if it beats the best hit of its group
+    fn add(&mut self, fields: &[SortField], key: K, hit: FieldDoc) {
+        match self.best.entry(key) {
+            Entry::Vacant(e) => {
+                e.insert(hit);
+            }
+            Entry::Occupied(mut e) => {
+                if compare_field_docs(fields, &hit, e.get()) == Ordering::Less {
+                    e.insert(hit);
+                }
+            }
+        }
+    }
+
+    fn add_groups(&mut self, fields: &[SortField], groups: Groups<K>) {
+        self.total_hits += groups.total_hits;
+        if self.max_score.is_nan() || groups.max_score > self.max_score {
+            self.max_score = groups.max_score;
+        }
+        for (key, hit) in groups.best {
+            self.add(fields, key, hit);
+        }
+    }
+}
+
+// the collection of the groups of a segment
+struct LeafGroups {
+    fields: Vec<SortField>,
+    // with a single slot, to read the sort values of the hits
+    comparators: Vec<FieldComparatorEnum>,
+    values: GroupValues,
+    missing: CollapseMissing,
+    needs_scores: bool,
+    doc_base: DocId,
+    groups: Groups<LeafKey>,
+}
+
+impl LeafGroups {
+    fn new<C: Codec>(
+        collector: &CollapsingTopDocsCollector,
+        reader: &LeafReaderContext<'_, C>,
+    ) -> Result<LeafGroups> {
+        let fields = collector.sort.get_sort().to_vec();
+        let mut comparators = Vec::with_capacity(fields.len());
+        for field in &fields {
+            let mut comparator = field.get_comparator(1, field.missing_value());
+            comparator.get_information_from_reader(reader)?;
+            comparators.push(comparator);
+        }
+        Ok(LeafGroups {
+            fields,
+            comparators,
+            values: GroupValues::open(reader.reader, &collector.field)?,
+            missing: collector.missing,
+            needs_scores: collector.needs_scores,
+            doc_base: reader.doc_base,
+            groups: Groups::new(),
+        })
+    }
+
+    fn collect<S: Scorer + ?Sized>(&mut self, doc: DocId, scorer: &mut S) -> Result<()> {
+        self.groups.total_hits += 1;
+        let score = if self.needs_scores {
+            scorer.score()?
+        } else {
+            f32::NAN
+        };
+        if self.needs_scores && (self.groups.max_score.is_nan() || score > self.groups.max_score) {
+            self.groups.max_score = score;
+        }
+
+        let key = self.values.key(doc)?;
+        if key == LeafKey::Missing && self.missing == CollapseMissing::Exclude {
+            return Ok(());
+        }
+        let mut sort_values = Vec::with_capacity(self.comparators.len());
+        for comparator in &mut self.comparators {
+            let value = if comparator.get_type() == SortFieldType::Score {
+                ComparatorValue::Score(score)
+            } else {
+                ComparatorValue::Doc(doc)
+            };
+            comparator.copy(0, value)?;
+            sort_values.push(comparator.value(0));
+        }
+        let hit = FieldDoc::new(doc + self.doc_base, score, sort_values);
+        self.groups.add(&self.fields, key, hit);
+        Ok(())
+    }
+
+    // the groups collected so far, keyed by their values
+    fn take_groups(&mut self) -> Result<Groups<GroupKey>> {
+        let leaf_groups = mem::replace(&mut self.groups, Groups::new());
+        let mut best = HashMap::with_capacity(leaf_groups.best.len());
+        for (key, hit) in leaf_groups.best {
+            best.insert(self.values.group_key(key)?, hit);
+        }
+        Ok(Groups {
+            best,
+            total_hits: leaf_groups.total_hits,
+            max_score: leaf_groups.max_score,
+        })
+    }
+}
+
+/// Collapses the hits by the value of a field, keeping the best hit of every
+/// group by a `Sort`, and collects the top `n` groups as
+/// `TopDocs::Collapse`, e.g. the best product of every seller. The ties are
+/// broken by doc id. Use a `Sort` by `SortField::new_score()` to keep the
+/// most relevant hit of every group.
+///
+/// The value is read from the sorted or numeric doc values of the field, the
+/// groups of different segments are merged by the bytes or the value. The
+/// hits without a value form a group of their own unless they're excluded
+/// with `CollapseMissing::Exclude`.
+pub struct CollapsingTopDocsCollector {
+    field: String,
+    n: usize,
+    sort: Sort,
+    missing: CollapseMissing,
+    needs_scores: bool,
+    groups: Groups<GroupKey>,
+    // the leaf being collected by `collect`
+    cur_leaf: Option<LeafGroups>,
+    // the groups of the leaves collected in parallel
+    channel: ParallelChannel<Result<Groups<GroupKey>>>,
+}
+
+impl CollapsingTopDocsCollector {
+    pub fn new(field: &str, n: usize, sort: Sort) -> Result<CollapsingTopDocsCollector> {
+        if n == 0 {
+            bail!(IllegalArgument("n must be > 0".into()));
+        }
+        let needs_scores = sort.needs_scores();
+        Ok(CollapsingTopDocsCollector {
+            field: field.to_string(),
+            n,
+            sort,
+            missing: CollapseMissing::Group,
+            needs_scores,
+            groups: Groups::new(),
+            cur_leaf: None,
+            channel: ParallelChannel::default(),
+        })
+    }
+
+    pub fn with_missing(mut self, missing: CollapseMissing) -> CollapsingTopDocsCollector {
+        self.missing = missing;
+        self
+    }
+
+    /// Returns the best hit of the top `n` groups, the best group first, with
+    /// the collapse value of every hit.
+    pub fn top_docs(&mut self) -> Result<TopDocs> {
+        self.finish_leaf()?;
+        let fields = self.sort.get_sort();
+        let mut hits: Vec<_> = self.groups.best.drain().collect();
+        hits.sort_by(|(_, h1), (_, h2)| compare_field_docs(fields, h1, h2));
+        let total_groups = hits.len();
+        hits.truncate(self.n);
+
+        let mut score_docs = Vec::with_capacity(hits.len());
+        let mut collapse_values = Vec::with_capacity(hits.len());
+        for (key, hit) in hits {
+            score_docs.push(ScoreDocHit::Field(hit));
+            collapse_values.push(key.into_value());
+        }
+        Ok(TopDocs::Collapse(CollapseTopFieldDocs::new(
+            self.field.clone(),
+            self.groups.total_hits,
+            total_groups,
+            score_docs,
+            fields.to_vec(),
+            collapse_values,
+            self.groups.max_score,
+        )))
+    }
+
+    fn finish_leaf(&mut self) -> Result<()> {
+        if let Some(mut leaf) = self.cur_leaf.take() {
+            let groups = leaf.take_groups()?;
+            self.groups.add_groups(self.sort.get_sort(), groups);
+        }
+        Ok(())
+    }
+}
+
+impl SearchCollector for CollapsingTopDocsCollector {
+    type LC = CollapsingLeafCollector;
+
+    fn set_next_reader<C: Codec>(&mut self, reader: &LeafReaderContext<'_, C>) -> Result<()> {
+        self.finish_leaf()?;
+        self.cur_leaf = Some(LeafGroups::new(self, reader)?);
+        Ok(())
+    }
+
+    fn support_parallel(&self) -> bool {
+        true
+    }
+
+    fn leaf_collector<C: Codec>(
+        &mut self,
+        reader: &LeafReaderContext<'_, C>,
+    ) -> Result<CollapsingLeafCollector> {
+        Ok(CollapsingLeafCollector {
+            leaf: LeafGroups::new(self, reader)?,
+            channel: self.channel.sender(),
+        })
+    }
+
+    fn finish_parallel(&mut self) -> Result<()> {
+        for groups in self.channel.receive() {
+            self.groups.add_groups(self.sort.get_sort(), groups?);
+        }
+        Ok(())
+    }
+}
+
+impl Collector for CollapsingTopDocsCollector {
+    fn score_mode(&self) -> ScoreMode {
+        if self.needs_scores {
+            ScoreMode::Complete
+        } else {
+            ScoreMode::CompleteNoScores
+        }
+    }
+
+    fn collect<S: Scorer + ?Sized>(&mut self, doc: DocId, scorer: &mut S) -> Result<()> {
+        match self.cur_leaf {
+            Some(ref mut leaf) => leaf.collect(doc, scorer),
+            None => bail!(IllegalState("collect called before set_next_reader".into())),
+        }
+    }
+}
+
+pub struct CollapsingLeafCollector {
+    leaf: LeafGroups,
+    channel: LeafSender<Result<Groups<GroupKey>>>,
+}
+
+impl ParallelLeafCollector for CollapsingLeafCollector {
+    fn finish_leaf(&mut self) -> Result<()> {
+        let groups = self.leaf.take_groups();
+        self.channel.send(groups)
+    }
+}
+
+impl Collector for CollapsingLeafCollector {
+    fn score_mode(&self) -> ScoreMode {
+        if self.leaf.needs_scores {
+            ScoreMode::Complete
+        } else {
+            ScoreMode::CompleteNoScores
+        }
+    }
+
+    fn collect<S: Scorer + ?Sized>(&mut self, doc: DocId, scorer: &mut S) -> Result<()> {
+        self.leaf.collect(doc, scorer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::doc::{Field, NumericDocValuesField, SORTED_DOC_VALUES_FIELD_TYPE};
+    use core::index::tests::{open_test_reader, TestReader};
+    use core::index::{Fieldable, IndexReader};
+    use core::search::sort_field::SimpleSortField;
+    use core::search::tests::create_mock_scorer;
+    use core::search::{DocIterator, NO_MORE_DOCS};
+
+    use std::collections::HashSet;
+    use std::sync::Arc;
+
+    const SEGMENT_DOCS: DocId = 20;
+
+    // most docs are sold by "big", a few docs have no seller and the last
+    // segment has no seller at all
+    fn seller(doc: DocId) -> Option<String> {
+        if doc >= 3 * SEGMENT_DOCS || doc % 13 == 7 {
+            None
+        } else if doc % 6 != 0 {
+            Some("big".to_string())
+        } else {
+            Some(format!("small{}", doc / 6 % 3))
+        }
+    }
+
+    fn price(doc: DocId) -> i64 {
+        i64::from(doc * 7 % 23)
+    }
+
+    fn shop(doc: DocId) -> i64 {
+        i64::from(doc % 5) - 2
+    }
+
+    fn document(doc: DocId) -> Vec<Box<dyn Fieldable>> {
+        let mut fields: Vec<Box<dyn Fieldable>> = vec![
+            Box::new(NumericDocValuesField::new("price", price(doc))),
+            Box::new(NumericDocValuesField::new("shop", shop(doc))),
+        ];
+        if let Some(seller) = seller(doc) {
+            fields.push(Box::new(Field::new(
+                "seller".to_string(),
+                SORTED_DOC_VALUES_FIELD_TYPE,
+                Some(VariantValue::from(seller.as_bytes())),
+                None,
+            )));
+        }
+        fields
+    }
+
+    fn build_index() -> Arc<TestReader> {
+        let segments = (0..4)
+            .map(|segment| {
+                (segment * SEGMENT_DOCS..(segment + 1) * SEGMENT_DOCS)
+                    .map(document)
+                    .collect()
+            })
+            .collect();
+        open_test_reader(segments)
+    }
+
+    fn price_sort() -> Sort {
+        let field = SimpleSortField::new("price".to_string(), SortFieldType::Long, true);
+        Sort::new(vec![SortField::Simple(field)])
+    }
+
+    fn collect(reader: &TestReader, collector: &mut CollapsingTopDocsCollector, parallel: bool) {
+        for leaf in reader.leaves() {
+            let mut scorer = create_mock_scorer((0..leaf.reader.max_doc()).collect());
+            if parallel {
+                let mut leaf_collector = collector.leaf_collector(&leaf).unwrap();
+                loop {
+                    let doc = scorer.next().unwrap();
+                    if doc == NO_MORE_DOCS {
+                        break;
+                    }
+                    leaf_collector.collect(doc, &mut scorer).unwrap();
+                }
+                leaf_collector.finish_leaf().unwrap();
+            } else {
+                collector.set_next_reader(&leaf).unwrap();
+                loop {
+                    let doc = scorer.next().unwrap();
+                    if doc == NO_MORE_DOCS {
+                        break;
+                    }
+                    collector.collect(doc, &mut scorer).unwrap();
+                }
+            }
+        }
+        if parallel {
+            collector.finish_parallel().unwrap();
+        }
+    }
+
+    // the best doc of every group and its collapse value, the best group
+    // first, where `better` orders the docs
+    fn expected_groups<K, F, B>(key: F, better: B) -> Vec<(DocId, Option<VariantValue>)>
+    where
+        K: Eq + Hash,
+        F: Fn(DocId) -> Option<(K, Option<VariantValue>)>,
+        B: Fn(DocId, DocId) -> Ordering,
+    {
+        let mut best: HashMap<K, (DocId, Option<VariantValue>)> = HashMap::new();
+        for doc in 0..4 * SEGMENT_DOCS {
+            if let Some((key, value)) = key(doc) {
+                let entry = best.entry(key).or_insert((doc, value));
+                if better(doc, entry.0) == Ordering::Less {
+                    entry.0 = doc;
+                }
+            }
+        }
+        let mut groups: Vec<_> = best.into_iter().map(|(_, v)| v).collect();
+        groups.sort_by(|g1, g2| better(g1.0, g2.0));
+        groups
+    }
+
+    fn hits(top_docs: &TopDocs) -> Vec<(DocId, Option<VariantValue>)> {
+        match top_docs {
+            TopDocs::Collapse(c) => {
+                assert_eq!(c.score_docs.len(), c.collapse_values.len());
+                let hits: Vec<_> = c
+                    .score_docs
+                    .iter()
+                    .map(ScoreDocHit::doc_id)
+                    .zip(c.collapse_values.iter().cloned())
+                    .collect();
+                // every group appears once
+                let values: HashSet<_> = c
+                    .collapse_values
+                    .iter()
+                    .map(|v| format!("{:?}", v))
+                    .collect();
+                assert_eq!(values.len(), hits.len());
+                hits
+            }
+            _ => panic!("expected collapse top docs"),
+        }
+    }
+
+    fn total_groups(top_docs: &TopDocs) -> usize {
+        match top_docs {
+            TopDocs::Collapse(c) => c.total_groups,
+            _ => panic!("expected collapse top docs"),
+        }
+    }
+
+    #[test]
+    fn test_collapse_on_sorted_values() {
+        let reader = build_index();
+        assert_eq!(reader.leaves().len(), 4);
+        let by_price = |d1: DocId, d2: DocId| price(d2).cmp(&price(d1)).then(d1.cmp(&d2));
+
+        for &missing in &[CollapseMissing::Group, CollapseMissing::Exclude] {
+            let expected = expected_groups(
+                |doc| match seller(doc) {
+                    Some(seller) => Some((
+                        Some(seller.clone()),
+                        Some(VariantValue::Binary(seller.into_bytes())),
+                    )),
+                    None if missing == CollapseMissing::Group => Some((None, None)),
+                    None => None,
+                },
+                by_price,
+            );
+            for &parallel in &[false, true] {
+                for &n in &[3, 100] {
+                    let mut collector = CollapsingTopDocsCollector::new("seller", n, price_sort())
+                        .unwrap()
+                        .with_missing(missing);
+                    collect(&reader, &mut collector, parallel);
+                    let top_docs = collector.top_docs().unwrap();
+                    assert_eq!(top_docs.total_hits(), 80);
+                    assert_eq!(total_groups(&top_docs), expected.len());
+                    let n = n.min(expected.len());
+                    assert_eq!(hits(&top_docs), &expected[..n]);
+                }
+            }
+        }
+
+        // "big" has most of the docs, the other groups still get their best doc
+        let big_docs = (0..80).filter(|&d| seller(d) == Some("big".into())).count();
+        assert!(big_docs > 40);
+        let mut collector = CollapsingTopDocsCollector::new("seller", 100, price_sort()).unwrap();
+        collect(&reader, &mut collector, false);
+        let top_docs = collector.top_docs().unwrap();
+        assert_eq!(total_groups(&top_docs), 5);
+        let big = Some(VariantValue::Binary(b"big".to_vec()));
+        assert_eq!(hits(&top_docs).iter().filter(|(_, v)| *v == big).count(), 1);
+    }
+
+    #[test]
+    fn test_collapse_on_numeric_values_by_score() {
+        let reader = build_index();
+        // the mock scores are the doc ids in the segment
+        let by_score = |d1: DocId, d2: DocId| {
+            (d2 % SEGMENT_DOCS)
+                .cmp(&(d1 % SEGMENT_DOCS))
+                .then(d1.cmp(&d2))
+        };
+        let expected = expected_groups(
+            |doc| Some((shop(doc), Some(VariantValue::Long(shop(doc))))),
+            by_score,
+        );
+        assert_eq!(expected.len(), 5);
+        for &parallel in &[false, true] {
+            let sort = Sort::new(vec![SortField::new_score()]);
+            let mut collector = CollapsingTopDocsCollector::new("shop", 10, sort).unwrap();
+            assert!(collector.needs_scores());
+            collect(&reader, &mut collector, parallel);
+            let top_docs = collector.top_docs().unwrap();
+            assert_eq!(hits(&top_docs), expected);
+            match top_docs {
+                TopDocs::Collapse(c) => {
+                    assert!((c.max_score() - (SEGMENT_DOCS - 1) as f32).abs() < f32::EPSILON)
+                }
+                _ => unreachable!(),
+            }
+        }
+    }
+
+    #[test]
+    fn test_collapse_on_missing_field() {
+        let reader = build_index();
+        let mut collector = CollapsingTopDocsCollector::new("color", 10, price_sort()).unwrap();
+        collect(&reader, &mut collector, false);
+        let top_docs = collector.top_docs().unwrap();
+        assert_eq!(total_groups(&top_docs), 1);
+        assert_eq!(hits(&top_docs)[0].1, None);
+
+        let mut collector = CollapsingTopDocsCollector::new("color", 10, price_sort())
+            .unwrap()
+            .with_missing(CollapseMissing::Exclude);
+        collect(&reader, &mut collector, true);
+        let top_docs = collector.top_docs().unwrap();
+        assert_eq!(top_docs.total_hits(), 80);
+        assert_eq!(total_groups(&top_docs), 0);
+
+        assert!(CollapsingTopDocsCollector::new("seller", 0, price_sort()).is_err());
+    }
+}
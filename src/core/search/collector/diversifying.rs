@@ -0,0 +1,582 @@
+// Copyright 2019 Zhizhesihai (Beijing) Technology Limited.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use core::codec::Codec;
+use core::index::{
+    DocValuesType, LeafReaderContext, NumericDocValuesRef, SearchLeafReader, SortedDocValuesRef,
+};
+use core::search::collector::top_field::compare_field_docs;
+use core::search::collector::{
+    Collector, LeafSender, ParallelChannel, ParallelLeafCollector, ScoreMode, SearchCollector,
+};
+use core::search::field_comparator::{ComparatorValue, FieldComparator, FieldComparatorEnum};
+use core::search::sort::Sort;
+use core::search::sort_field::SortFieldType;
+use core::search::top_docs::{FieldDoc, ScoreDocHit, TopDocs, TopFieldDocs};
+use core::search::Scorer;
+use core::util::{BitsRef, DocId};
+use error::{
+    ErrorKind::{IllegalArgument, IllegalState},
+    Result,
+};
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::f32;
+use std::sync::Arc;
+
+/// The value of the diversifying key of a hit. Hits of different segments
+/// have the same key if they have the same value.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum DiversifyKey {
+    Bytes(Vec<u8>),
+    Long(i64),
+}
+
+// the doc values of the key field in a segment
+enum KeyValues {
+    Sorted(SortedDocValuesRef),
+    Numeric(NumericDocValuesRef, BitsRef),
+    Missing,
+}
+
+impl KeyValues {
+    fn open<C: Codec>(reader: &SearchLeafReader<C>, field: &str) -> Result<KeyValues> {
+        let values = match reader.field_info(field).map(|fi| fi.doc_values_type) {
+            Some(DocValuesType::Sorted) => KeyValues::Sorted(reader.get_sorted_doc_values(field)?),
+            Some(DocValuesType::Numeric) => KeyValues::Numeric(
+                reader.get_numeric_doc_values(field)?,
+                reader.get_docs_with_field(field)?,
+            ),
+            _ => KeyValues::Missing,
+        };
+        Ok(values)
+    }
+
+    fn key(&self, doc: DocId) -> Result<Option<DiversifyKey>> {
+        match self {
+            KeyValues::Sorted(values) => {
+                let ord = values.get_ord(doc)?;
+                if ord < 0 {
+                    Ok(None)
+                } else {
+                    Ok(Some(DiversifyKey::Bytes(values.lookup_ord(ord)?)))
+                }
+            }
+            KeyValues::Numeric(values, docs_with_field) => {
+                if docs_with_field.get(doc as usize)? {
+                    Ok(Some(DiversifyKey::Long(values.get(doc)?)))
+                } else {
+                    Ok(None)
+                }
+            }
+            KeyValues::Missing => Ok(None),
+        }
+    }
+}
+
+// the sort values and the key of the hits of a segment
+struct LeafValues {
+    // with a single slot, to read the sort values of the hits
+    comparators: Vec<FieldComparatorEnum>,
+    values: KeyValues,
+    needs_scores: bool,
+    doc_base: DocId,
+}
+
+impl LeafValues {
+    fn new<C: Codec>(
+        collector: &DiversifyingTopDocsCollector,
+        reader: &LeafReaderContext<'_, C>,
+    ) -> Result<LeafValues> {
+        let fields = collector.sort.get_sort();
+        let mut comparators = Vec::with_capacity(fields.len());
+        for field in fields {
+            let mut comparator = field.get_comparator(1, field.missing_value());
+            comparator.get_information_from_reader(reader)?;
+            comparators.push(comparator);
+        }
+        Ok(LeafValues {
+            comparators,
+            values: KeyValues::open(reader.reader, &collector.key_field)?,
+            needs_scores: collector.needs_scores,
+            doc_base: reader.doc_base,
+        })
+    }
+
+    // the hit of `doc` with its sort values and a global doc id
+    fn hit<S: Scorer + ?Sized>(&mut self, doc: DocId, scorer: &mut S) -> Result<FieldDoc> {
+        let score = if self.needs_scores {
+            scorer.score()?
+        } else {
+            f32::NAN
+        };
+        let mut sort_values = Vec::with_capacity(self.comparators.len());
+        for comparator in &mut self.comparators {
+            let value = if comparator.get_type() == SortFieldType::Score {
+                ComparatorValue::Score(score)
+            } else {
+                ComparatorValue::Doc(doc)
+            };
+            comparator.copy(0, value)?;
+            sort_values.push(comparator.value(0));
+        }
+        Ok(FieldDoc::new(doc + self.doc_base, score, sort_values))
+    }
+}
+
+/// Collects the top `n` hits by a `Sort`, keeping at most `max_per_key` hits
+/// per value of a key field, e.g. at most two hits per seller in the first
+/// page. Use a `Sort` by `SortField::new_score()` to keep the most relevant
+/// hits. The ties are broken by doc id.
+///
+/// The key is read from the sorted or numeric doc values of `key_field`; the
+/// hits without a value aren't limited. A new hit takes the place of the worst
+/// hit of its key if the key already has `max_per_key` hits, else of the
+/// worst hit overall, so the best hits of every key are kept.
+pub struct DiversifyingTopDocsCollector {
+    sort: Sort,
+    n: usize,
+    key_field: String,
+    max_per_key: usize,
+    needs_scores: bool,
+    // the kept hits, the best first, with their interned keys, `None` for the
+    // hits without a key
+    hits: Vec<(FieldDoc, Option<usize>)>,
+    // the number of kept hits of every interned key
+    key_counts: Vec<usize>,
+    keys: HashMap<DiversifyKey, usize>,
+    total_hits: usize,
+    max_score: f32,
+    // the leaf being collected by `collect`
+    cur_leaf: Option<LeafValues>,
+    // segment ord -> interned key of the current segment
+    cur_ords: HashMap<i32, usize>,
+    channel: ParallelChannel<(FieldDoc, Option<DiversifyKey>)>,
+}
+
+impl DiversifyingTopDocsCollector {
+    pub fn new(
+        sort: Sort,
+        n: usize,
+        key_field: &str,
+        max_per_key: usize,
+    ) -> Result<DiversifyingTopDocsCollector> {
+        if n == 0 {
+            bail!(IllegalArgument("n must be > 0".into()));
+        }
+        if max_per_key == 0 {
+            bail!(IllegalArgument("max_per_key must be > 0".into()));
+        }
+        let needs_scores = sort.needs_scores();
+        Ok(DiversifyingTopDocsCollector {
+            sort,
+            n,
+            key_field: key_field.to_string(),
+            max_per_key,
+            needs_scores,
+            hits: Vec::with_capacity(n),
+            key_counts: vec![],
+            keys: HashMap::new(),
+            total_hits: 0,
+            max_score: f32::NAN,
+            cur_leaf: None,
+            cur_ords: HashMap::new(),
+            channel: ParallelChannel::default(),
+        })
+    }
+
+    /// Returns the top docs that were collected by this collector, the best
+    /// first, with their sort values.
+    pub fn top_docs(&mut self) -> TopDocs {
+        let score_docs = self
+            .hits
+            .iter()
+            .map(|(hit, _)| ScoreDocHit::Field(hit.clone()))
+            .collect();
+        TopDocs::Field(TopFieldDocs::new(
+            self.total_hits,
+            score_docs,
+            self.sort.get_sort().to_vec(),
+            self.max_score,
+        ))
+    }
+
+    fn count_hit(&mut self, hit: &FieldDoc) {
+        self.total_hits += 1;
+        if self.needs_scores && (self.max_score.is_nan() || hit.score > self.max_score) {
+            self.max_score = hit.score;
+        }
+    }
+
+    // false if the hit can't make it into the kept hits, whatever its key
+    fn competitive(&self, hit: &FieldDoc) -> bool {
+        match self.hits.last() {
+            Some((worst, _)) if self.hits.len() >= self.n => {
+                compare_field_docs(self.sort.get_sort(), hit, worst) == Ordering::Less
+            }
+            _ => true,
+        }
+    }
+
+    fn intern(&mut self, key: DiversifyKey) -> usize {
+        let key_counts = &mut self.key_counts;
+        *self.keys.entry(key).or_insert_with(|| {
+            key_counts.push(0);
+            key_counts.len() - 1
+        })
+    }
+
+    fn current_key(&mut self, doc: DocId) -> Result<Option<usize>> {
+        let (sorted, key) = match self.cur_leaf {
+            Some(ref leaf) => match leaf.values {
+                KeyValues::Sorted(ref values) => (Some(Arc::clone(values)), None),
+                ref values => (None, values.key(doc)?),
+            },
+            None => return Ok(None),
+        };
+        if let Some(sorted) = sorted {
+            let ord = sorted.get_ord(doc)?;
+            if ord < 0 {
+                return Ok(None);
+            }
+            if let Some(&key) = self.cur_ords.get(&ord) {
+                return Ok(Some(key));
+            }
+            let key = self.intern(DiversifyKey::Bytes(sorted.lookup_ord(ord)?));
+            self.cur_ords.insert(ord, key);
+            return Ok(Some(key));
+        }
+        Ok(key.map(|key| self.intern(key)))
+    }
+
+    fn remove(&mut self, index: usize) {
+        let (_, key) = self.hits.remove(index);
+        if let Some(key) = key {
+            self.key_counts[key] -= 1;
+        }
+    }
+
+    // whether `hit` is better than the kept hit at `index`
+    fn better(&self, hit: &FieldDoc, index: usize) -> bool {
+        compare_field_docs(self.sort.get_sort(), hit, &self.hits[index].0) == Ordering::Less
+    }
+
+    fn add_hit(&mut self, hit: FieldDoc, key: Option<usize>) {
+        if let Some(key) = key {
+            if self.key_counts[key] >= self.max_per_key {
+                // the key is full: the hit can only replace the worst hit of its key
+                let worst = self
+                    .hits
+                    .iter()
+                    .rposition(|(_, k)| *k == Some(key))
+                    .unwrap();
+                if !self.better(&hit, worst) {
+                    return;
+                }
+                self.remove(worst);
+            }
+        }
+        if self.hits.len() >= self.n {
+            let worst = self.hits.len() - 1;
+            if !self.better(&hit, worst) {
+                return;
+            }
+            self.remove(worst);
+        }
+        let fields = self.sort.get_sort();
+        let index = match self
+            .hits
+            .binary_search_by(|(h, _)| compare_field_docs(fields, h, &hit))
+        {
+            Ok(index) | Err(index) => index,
+        };
+        self.hits.insert(index, (hit, key));
+        if let Some(key) = key {
+            self.key_counts[key] += 1;
+        }
+    }
+}
+
+impl SearchCollector for DiversifyingTopDocsCollector {
+    type LC = DiversifyingLeafCollector;
+
+    fn set_next_reader<C: Codec>(&mut self, reader: &LeafReaderContext<'_, C>) -> Result<()> {
+        self.cur_leaf = Some(LeafValues::new(self, reader)?);
+        self.cur_ords.clear();
+        Ok(())
+    }
+
+    fn support_parallel(&self) -> bool {
+        true
+    }
+
+    fn leaf_collector<C: Codec>(
+        &mut self,
+        reader: &LeafReaderContext<'_, C>,
+    ) -> Result<DiversifyingLeafCollector> {
+        Ok(DiversifyingLeafCollector {
+            leaf: LeafValues::new(self, reader)?,
+            channel: self.channel.sender(),
+        })
+    }
+
+    fn finish_parallel(&mut self) -> Result<()> {
+        for (hit, key) in self.channel.receive() {
+            self.count_hit(&hit);
+            if self.competitive(&hit) {
+                let key = key.map(|key| self.intern(key));
+                self.add_hit(hit, key);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Collector for DiversifyingTopDocsCollector {
+    fn score_mode(&self) -> ScoreMode {
+        if self.needs_scores {
+            ScoreMode::Complete
+        } else {
+            ScoreMode::CompleteNoScores
+        }
+    }
+
+    fn collect<S: Scorer + ?Sized>(&mut self, doc: DocId, scorer: &mut S) -> Result<()> {
+        let hit = match self.cur_leaf {
+            Some(ref mut leaf) => leaf.hit(doc, scorer)?,
+            None => bail!(IllegalState("collect called before set_next_reader".into())),
+        };
+        self.count_hit(&hit);
+        // skip reading the key of the hits worse than all the kept ones
+        if self.competitive(&hit) {
+            let key = self.current_key(doc)?;
+            self.add_hit(hit, key);
+        }
+        Ok(())
+    }
+}
+
+pub struct DiversifyingLeafCollector {
+    leaf: LeafValues,
+    channel: LeafSender<(FieldDoc, Option<DiversifyKey>)>,
+}
+
+impl ParallelLeafCollector for DiversifyingLeafCollector {
+    fn finish_leaf(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl Collector for DiversifyingLeafCollector {
+    fn score_mode(&self) -> ScoreMode {
+        if self.leaf.needs_scores {
+            ScoreMode::Complete
+        } else {
+            ScoreMode::CompleteNoScores
+        }
+    }
+
+    fn collect<S: Scorer + ?Sized>(&mut self, doc: DocId, scorer: &mut S) -> Result<()> {
+        let hit = self.leaf.hit(doc, scorer)?;
+        let key = self.leaf.values.key(doc)?;
+        self.channel.send((hit, key))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::doc::{Field, NumericDocValuesField, SORTED_DOC_VALUES_FIELD_TYPE};
+    use core::index::tests::{open_test_reader, TestReader};
+    use core::index::{Fieldable, IndexReader};
+    use core::search::sort_field::{SimpleSortField, SortField};
+    use core::search::tests::create_mock_scorer;
+    use core::search::{DocIterator, NO_MORE_DOCS};
+    use core::util::VariantValue;
+
+    fn seller(name: &str) -> Box<dyn Fieldable> {
+        Box::new(Field::new(
+            "seller".to_string(),
+            SORTED_DOC_VALUES_FIELD_TYPE,
+            Some(VariantValue::from(name.as_bytes())),
+            None,
+        ))
+    }
+
+    fn document(segment: i64, i: i64) -> Vec<Box<dyn Fieldable>> {
+        let mut doc =
+            vec![Box::new(NumericDocValuesField::new("brand", i % 3)) as Box<dyn Fieldable>];
+        if i % 2 == 0 || i % 7 == 1 {
+            doc.push(seller("big"));
+        } else if i != 19 {
+            doc.push(seller(&format!("small{}", (i + segment) % 3)));
+        }
+        doc
+    }
+
+    // Two segments of 20 docs where seller "big" has most of the docs, and
+    // all the even docs; "brand" is a numeric key with 3 values.
+    fn build_index() -> Arc<TestReader> {
+        let segments = (0..2)
+            .map(|segment| (0..20).map(|i| document(segment, i)).collect())
+            .collect();
+        open_test_reader(segments)
+    }
+
+    // collects every doc of every segment, scored by its id in the segment
+    fn collect_all(reader: &TestReader, collector: &mut DiversifyingTopDocsCollector) {
+        collect(reader, collector, false)
+    }
+
+    fn collect(reader: &TestReader, collector: &mut DiversifyingTopDocsCollector, parallel: bool) {
+        for leaf in reader.leaves() {
+            let mut scorer = create_mock_scorer((0..leaf.reader.max_doc()).collect());
+            if parallel {
+                let mut leaf_collector = collector.leaf_collector(&leaf).unwrap();
+                loop {
+                    let doc = scorer.next().unwrap();
+                    if doc == NO_MORE_DOCS {
+                        break;
+                    }
+                    leaf_collector.collect(doc, &mut scorer).unwrap();
+                }
+                leaf_collector.finish_leaf().unwrap();
+            } else {
+                collector.set_next_reader(&leaf).unwrap();
+                loop {
+                    let doc = scorer.next().unwrap();
+                    if doc == NO_MORE_DOCS {
+                        break;
+                    }
+                    collector.collect(doc, &mut scorer).unwrap();
+                }
+            }
+        }
+        if parallel {
+            collector.finish_parallel().unwrap();
+        }
+    }
+
+    fn score_sort() -> Sort {
+        Sort::new(vec![SortField::new_score()])
+    }
+
+    fn seller_of(reader: &TestReader, doc: DocId) -> Option<Vec<u8>> {
+        let leaves = reader.leaves();
+        let leaf = &leaves[if doc < 20 { 0 } else { 1 }];
+        let values = leaf.reader.get_sorted_doc_values("seller").unwrap();
+        let ord = values.get_ord(doc - leaf.doc_base).unwrap();
+        if ord < 0 {
+            None
+        } else {
+            Some(values.lookup_ord(ord).unwrap())
+        }
+    }
+
+    #[test]
+    fn test_max_hits_per_sorted_key() {
+        let reader = build_index();
+        assert_eq!(reader.leaves().len(), 2);
+
+        let mut collector =
+            DiversifyingTopDocsCollector::new(score_sort(), 6, "seller", 2).unwrap();
+        collect_all(&reader, &mut collector);
+        let top_docs = collector.top_docs();
+        assert_eq!(top_docs.total_hits(), 40);
+
+        let hits = top_docs.score_docs();
+        assert_eq!(hits.len(), 6);
+        // scores don't increase
+        for pair in hits.windows(2) {
+            assert!(pair[0].score() >= pair[1].score());
+        }
+        let mut per_seller = HashMap::new();
+        for hit in hits {
+            *per_seller
+                .entry(seller_of(&reader, hit.doc_id()))
+                .or_insert(0) += 1;
+        }
+        assert!(per_seller.values().all(|&count| count <= 2));
+        // the docs without a seller aren't limited, the best one got in
+        assert!(hits.iter().any(|hit| hit.doc_id() == 39));
+
+        // the best hits of "big" are its docs scoring 18 in both segments
+        let big: Vec<_> = hits
+            .iter()
+            .filter(|hit| seller_of(&reader, hit.doc_id()) == Some(b"big".to_vec()))
+            .map(|hit| hit.doc_id())
+            .collect();
+        assert_eq!(big, vec![18, 38]);
+    }
+
+    #[test]
+    fn test_max_hits_per_numeric_key() {
+        let reader = build_index();
+        let mut collector =
+            DiversifyingTopDocsCollector::new(score_sort(), 10, "brand", 1).unwrap();
+        collect_all(&reader, &mut collector);
+        let top_docs = collector.top_docs();
+
+        // only one hit per brand, the best of each brand
+        let docs: Vec<_> = top_docs.score_docs().iter().map(|d| d.doc_id()).collect();
+        assert_eq!(docs, vec![19, 18, 17]);
+    }
+
+    #[test]
+    fn test_without_key_field() {
+        let reader = build_index();
+        let mut collector =
+            DiversifyingTopDocsCollector::new(score_sort(), 3, "missing", 1).unwrap();
+        collect_all(&reader, &mut collector);
+
+        let top_docs = collector.top_docs();
+        let docs: Vec<_> = top_docs.score_docs().iter().map(|d| d.doc_id()).collect();
+        // ties are broken by doc id
+        assert_eq!(docs, vec![19, 39, 18]);
+    }
+
+    #[test]
+    fn test_max_hits_per_key_by_field_sort() {
+        let reader = build_index();
+        // by brand, the ties broken by doc id
+        let field = SimpleSortField::new("brand".to_string(), SortFieldType::Long, false);
+        let sort = Sort::new(vec![SortField::Simple(field)]);
+        for parallel in &[false, true] {
+            let mut collector =
+                DiversifyingTopDocsCollector::new(sort.clone(), 5, "seller", 2).unwrap();
+            assert_eq!(collector.score_mode(), ScoreMode::CompleteNoScores);
+            collect(&reader, &mut collector, *parallel);
+            let top_docs = collector.top_docs();
+            assert_eq!(top_docs.total_hits(), 40);
+
+            // the docs of brand 0 are 0, 3, .., 18 then 20, 23, ..: "big" is
+            // full after 0 and 6, "small0" after 3 and 9, the next doc with
+            // another seller is 23 of "small1"
+            let docs: Vec<_> = top_docs.score_docs().iter().map(|d| d.doc_id()).collect();
+            assert_eq!(docs, vec![0, 3, 6, 9, 23]);
+            for hit in top_docs.score_docs() {
+                match hit {
+                    ScoreDocHit::Field(f) => assert_eq!(f.fields, vec![VariantValue::Long(0)]),
+                    ScoreDocHit::Score(_) => panic!("hits without sort values"),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_invalid_arguments() {
+        assert!(DiversifyingTopDocsCollector::new(score_sort(), 0, "seller", 1).is_err());
+        assert!(DiversifyingTopDocsCollector::new(score_sort(), 10, "seller", 0).is_err());
+    }
+}
@@ -14,7 +14,7 @@
 use core::codec::Codec;
 use core::index::LeafReaderContext;
 use core::search::collector;
-use core::search::collector::{Collector, ParallelLeafCollector, SearchCollector};
+use core::search::collector::{Collector, ParallelLeafCollector, ScoreMode, SearchCollector};
 use core::search::Scorer;
 use core::util::DocId;
 use error::{ErrorKind, Result};
@@ -70,8 +70,8 @@ impl SearchCollector for EarlyTerminatingSortingCollector {
 }
 
 impl Collector for EarlyTerminatingSortingCollector {
-    fn needs_scores(&self) -> bool {
-        false
+    fn score_mode(&self) -> ScoreMode {
+        ScoreMode::TopDocs
     }
 
     fn collect<S: Scorer + ?Sized>(&mut self, _doc: DocId, _scorer: &mut S) -> Result<()> {
@@ -113,8 +113,8 @@ impl ParallelLeafCollector for EarlyTerminatingLeafCollector {
 }
 
 impl Collector for EarlyTerminatingLeafCollector {
-    fn needs_scores(&self) -> bool {
-        false
+    fn score_mode(&self) -> ScoreMode {
+        ScoreMode::TopDocs
     }
 
     fn collect<S: Scorer + ?Sized>(&mut self, _doc: i32, _scorer: &mut S) -> Result<()> {
@@ -0,0 +1,185 @@
+// Copyright 2019 Zhizhesihai (Beijing) Technology Limited.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use core::codec::Codec;
+use core::index::LeafReaderContext;
+use core::search::collector::{Collector, ParallelLeafCollector, ScoreMode, SearchCollector};
+use core::search::{DocIterator, Scorer};
+use core::util::DocId;
+use error::Result;
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/// Wraps another collector and drops the hits scoring less than an absolute
+/// minimum score before they reach it.
+///
+/// The threshold always applies to the score, even if the wrapped collector
+/// doesn't need scores (e.g. it sorts by a field), so the hits are always scored.
+pub struct MinScoreCollector<T> {
+    collector: T,
+    min_score: f32,
+    // shared with the leaf collectors of a parallel search
+    suppressed: Arc<AtomicUsize>,
+}
+
+impl<T> MinScoreCollector<T> {
+    pub fn new(collector: T, min_score: f32) -> MinScoreCollector<T> {
+        debug_assert!(!min_score.is_nan());
+        MinScoreCollector {
+            collector,
+            min_score,
+            suppressed: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// Returns the number of hits dropped for scoring less than the minimum
+    /// score. Docs the scorers skipped because of the threshold aren't counted.
+    pub fn suppressed_hits(&self) -> usize {
+        self.suppressed.load(Ordering::Acquire)
+    }
+
+    pub fn into_inner(self) -> T {
+        self.collector
+    }
+}
+
+impl<T: SearchCollector> SearchCollector for MinScoreCollector<T> {
+    type LC = MinScoreCollector<T::LC>;
+
+    fn set_next_reader<C: Codec>(&mut self, reader: &LeafReaderContext<'_, C>) -> Result<()> {
+        self.collector.set_next_reader(reader)
+    }
+
+    fn support_parallel(&self) -> bool {
+        self.collector.support_parallel()
+    }
+
+    fn leaf_collector<C: Codec>(
+        &mut self,
+        reader: &LeafReaderContext<'_, C>,
+    ) -> Result<MinScoreCollector<T::LC>> {
+        Ok(MinScoreCollector {
+            collector: self.collector.leaf_collector(reader)?,
+            min_score: self.min_score,
+            suppressed: Arc::clone(&self.suppressed),
+        })
+    }
+
+    fn finish_parallel(&mut self) -> Result<()> {
+        self.collector.finish_parallel()
+    }
+
+    fn min_competitive_score(&self) -> Option<f32> {
+        match self.collector.min_competitive_score() {
+            Some(score) if score > self.min_score => Some(score),
+            _ => Some(self.min_score),
+        }
+    }
+}
+
+impl<T: ParallelLeafCollector> ParallelLeafCollector for MinScoreCollector<T> {
+    fn finish_leaf(&mut self) -> Result<()> {
+        self.collector.finish_leaf()
+    }
+}
+
+impl<T: Collector> Collector for MinScoreCollector<T> {
+    fn score_mode(&self) -> ScoreMode {
+        ScoreMode::TopScores
+    }
+
+    fn collect<S: Scorer + ?Sized>(&mut self, doc: DocId, scorer: &mut S) -> Result<()> {
+        let score = scorer.score()?;
+        if score < self.min_score {
+            self.suppressed.fetch_add(1, Ordering::AcqRel);
+            return Ok(());
+        }
+        self.collector
+            .collect(doc, &mut ScoreCachingScorer { scorer, score })
+    }
+}
+
+// avoids scoring the collected doc again in the wrapped collector
+struct ScoreCachingScorer<'a, S: Scorer + ?Sized + 'a> {
+    scorer: &'a mut S,
+    score: f32,
+}
+
+impl<'a, S: Scorer + ?Sized + 'a> Scorer for ScoreCachingScorer<'a, S> {
+    fn score(&mut self) -> Result<f32> {
+        Ok(self.score)
+    }
+
+    fn set_min_competitive_score(&mut self, min_score: f32) -> Result<()> {
+        self.scorer.set_min_competitive_score(min_score)
+    }
+
+    fn max_score(&self) -> f32 {
+        self.scorer.max_score()
+    }
+}
+
+impl<'a, S: Scorer + ?Sized + 'a> DocIterator for ScoreCachingScorer<'a, S> {
+    fn doc_id(&self) -> DocId {
+        self.scorer.doc_id()
+    }
+
+    fn next(&mut self) -> Result<DocId> {
+        self.scorer.next()
+    }
+
+    fn advance(&mut self, target: DocId) -> Result<DocId> {
+        self.scorer.advance(target)
+    }
+
+    fn cost(&self) -> usize {
+        self.scorer.cost()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::index::tests::*;
+    use core::index::IndexReader;
+    use core::search::collector::TopDocsCollector;
+    use core::search::tests::*;
+    use core::search::NO_MORE_DOCS;
+
+    #[test]
+    fn test_min_score_collector() {
+        let leaf_reader = MockLeafReader::new(0);
+        let index_reader = MockIndexReader::new(vec![leaf_reader]);
+        let leaves = index_reader.leaves();
+
+        let mut collector = MinScoreCollector::new(TopDocsCollector::new(10), 3.0);
+        assert_eq!(collector.min_competitive_score(), Some(3.0));
+        collector.set_next_reader(&leaves[0]).unwrap();
+        // the mock scorer scores docs with their id
+        let mut scorer = create_mock_scorer(vec![1, 2, 3, 4, 5]);
+        loop {
+            let doc = scorer.next().unwrap();
+            if doc == NO_MORE_DOCS {
+                break;
+            }
+            collector.collect(doc, &mut scorer).unwrap();
+        }
+
+        assert_eq!(collector.suppressed_hits(), 2);
+        let top_docs = collector.into_inner().top_docs();
+        assert_eq!(top_docs.total_hits(), 3);
+        let docs: Vec<DocId> = top_docs.score_docs().iter().map(|d| d.doc_id()).collect();
+        assert_eq!(docs, vec![5, 4, 3]);
+    }
+}
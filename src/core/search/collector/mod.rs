@@ -11,6 +11,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use error::ErrorKind::IllegalState;
 use error::Result;
 
 use core::codec::Codec;
@@ -18,18 +19,38 @@ use core::index::LeafReaderContext;
 use core::search::Scorer;
 use core::util::DocId;
 
+use crossbeam::channel::{unbounded, Receiver, Sender};
+
 pub mod top_docs;
 pub use self::top_docs::TopDocsCollector;
 
+mod top_field;
+pub use self::top_field::TopFieldCollector;
+
 mod early_terminating;
 pub use self::early_terminating::EarlyTerminatingSortingCollector;
 
 mod timeout;
 pub use self::timeout::TimeoutCollector;
 
+mod time_limiting;
+pub use self::time_limiting::TimeLimitingCollector;
+
 mod chain;
 pub use self::chain::ChainedCollector;
 
+mod multi;
+pub use self::multi::MultiCollector;
+
+mod min_score;
+pub use self::min_score::MinScoreCollector;
+
+mod diversifying;
+pub use self::diversifying::{DiversifyKey, DiversifyingTopDocsCollector};
+
+mod collapsing;
+pub use self::collapsing::{CollapseMissing, CollapsingLeafCollector, CollapsingTopDocsCollector};
+
 error_chain! {
     types {
         Error, ErrorKind, ResultExt;
@@ -44,6 +65,13 @@ error_chain! {
         CollectionFailed {
             description("Collection failed")
         }
+        TimeExceeded(elapsed: u64, allowed: u64, last_doc: DocId) {
+            description("Time exceeded")
+            display(
+                "Elapsed time: {}, exceeded allowed search time: {} ticks, last doc: {}",
+                elapsed, allowed, last_doc
+            )
+        }
     }
 }
 
@@ -78,6 +106,12 @@ pub trait SearchCollector: Collector {
     fn leaf_collector<C: Codec>(&mut self, reader: &LeafReaderContext<'_, C>) -> Result<Self::LC>;
 
     fn finish_parallel(&mut self) -> Result<()>;
+
+    /// The minimum score of the hits this collector keeps, which is passed to
+    /// the scorers before collecting so that they may skip lower scoring docs.
+    fn min_competitive_score(&self) -> Option<f32> {
+        None
+    }
 }
 
 impl<'a, T: SearchCollector + 'a> SearchCollector for &'a mut T {
@@ -98,12 +132,69 @@ impl<'a, T: SearchCollector + 'a> SearchCollector for &'a mut T {
     fn finish_parallel(&mut self) -> Result<()> {
         (**self).finish_parallel()
     }
+
+    fn min_competitive_score(&self) -> Option<f32> {
+        (**self).min_competitive_score()
+    }
+}
+
+/// How a collector uses the scores of the hits, which the searcher passes on
+/// to the weights so that they skip what isn't needed, e.g. loading the norms
+/// when the scores aren't.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ScoreMode {
+    /// Every hit is collected with its score.
+    Complete,
+    /// Every hit is collected, its score is never asked.
+    CompleteNoScores,
+    /// Only the top scoring hits are kept, the docs scoring less than
+    /// `SearchCollector::min_competitive_score` may be skipped.
+    TopScores,
+    /// Only the top hits by another order than the score are kept, and the
+    /// scores aren't needed, e.g. when sorting by a field.
+    TopDocs,
+}
+
+impl ScoreMode {
+    pub fn needs_scores(self) -> bool {
+        match self {
+            ScoreMode::Complete | ScoreMode::TopScores => true,
+            ScoreMode::CompleteNoScores | ScoreMode::TopDocs => false,
+        }
+    }
+
+    /// Whether every hit has to be collected. The searcher doesn't pass the
+    /// min competitive scores on to the scorers of an exhaustive collection,
+    /// nor do the combined collections, e.g. top docs collected along with
+    /// facet counts.
+    pub fn is_exhaustive(self) -> bool {
+        match self {
+            ScoreMode::Complete | ScoreMode::CompleteNoScores => true,
+            ScoreMode::TopScores | ScoreMode::TopDocs => false,
+        }
+    }
+
+    /// The mode of collecting the same hits with two collectors.
+    pub fn combine(self, other: ScoreMode) -> ScoreMode {
+        if self == other {
+            self
+        } else if self.needs_scores() || other.needs_scores() {
+            ScoreMode::Complete
+        } else {
+            ScoreMode::CompleteNoScores
+        }
+    }
 }
 
 pub trait Collector {
+    /// How this collector uses the scores, see `ScoreMode`.
+    fn score_mode(&self) -> ScoreMode;
+
     /// Indicates if document scores are needed by this collector.
     /// return `true` if scores are needed.
-    fn needs_scores(&self) -> bool;
+    fn needs_scores(&self) -> bool {
+        self.score_mode().needs_scores()
+    }
 
     /// Called once for every document matching a query, with the unbased document
     /// number.
@@ -119,8 +210,8 @@ pub trait Collector {
 }
 
 impl<'a, T: Collector + 'a> Collector for &'a mut T {
-    fn needs_scores(&self) -> bool {
-        (**self).needs_scores()
+    fn score_mode(&self) -> ScoreMode {
+        (**self).score_mode()
     }
 
     fn collect<S: Scorer + ?Sized>(&mut self, doc: i32, scorer: &mut S) -> Result<()> {
@@ -131,3 +222,49 @@ impl<'a, T: Collector + 'a> Collector for &'a mut T {
 pub trait ParallelLeafCollector: Collector + Send + 'static {
     fn finish_leaf(&mut self) -> Result<()>;
 }
+
+/// The channel on which the `ParallelLeafCollector`s of a `SearchCollector`
+/// send what they collected, opened by the first leaf collector.
+pub struct ParallelChannel<T> {
+    channel: Option<(Sender<T>, Receiver<T>)>,
+}
+
+impl<T> Default for ParallelChannel<T> {
+    fn default() -> Self {
+        ParallelChannel { channel: None }
+    }
+}
+
+impl<T> ParallelChannel<T> {
+    /// Returns the sender of a new leaf collector.
+    pub fn sender(&mut self) -> LeafSender<T> {
+        if self.channel.is_none() {
+            self.channel = Some(unbounded());
+        }
+        LeafSender(self.channel.as_ref().unwrap().0.clone())
+    }
+
+    /// Returns what the leaf collectors sent, the iteration ends once they are
+    /// all dropped. Nothing is received if no leaf collector was created, which
+    /// happens when no leaf has a scorer.
+    pub fn receive(&mut self) -> impl Iterator<Item = T> {
+        // the channel is closed by dropping the sender
+        let receiver = self.channel.take().map(|(_, receiver)| receiver);
+        receiver.into_iter().flat_map(|receiver| receiver.into_iter())
+    }
+}
+
+/// Sends what a `ParallelLeafCollector` collected to its `SearchCollector`.
+pub struct LeafSender<T>(Sender<T>);
+
+impl<T> LeafSender<T> {
+    pub fn send(&self, value: T) -> Result<()> {
+        self.0.send(value).map_err(|e| {
+            IllegalState(format!(
+                "channel unexpected closed before search complete with err: {:?}",
+                e
+            ))
+            .into()
+        })
+    }
+}
@@ -0,0 +1,329 @@
+// Copyright 2019 Zhizhesihai (Beijing) Technology Limited.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use core::codec::Codec;
+use core::index::LeafReaderContext;
+use core::search::collector;
+use core::search::collector::{Collector, ParallelLeafCollector, ScoreMode, SearchCollector};
+use core::search::{DocIterator, Scorer};
+use core::util::DocId;
+use error::{Error, ErrorKind, Result};
+
+use std::f32;
+
+/// Collects the hits with several collectors of the same type, e.g. the
+/// facet collectors of several fields. Collectors of different types are
+/// combined with `ChainedCollector`.
+///
+/// The score of a hit is computed once for all the collectors, see
+/// `ScoreMode::combine`. A collector terminating the collection of a leaf
+/// doesn't collect the following docs of the leaf, the others still do.
+pub struct MultiCollector<T> {
+    collectors: Vec<T>,
+    // the collectors done with the current leaf
+    terminated: Vec<bool>,
+    // the min competitive scores of the collectors on the current leaf
+    min_scores: Vec<f32>,
+    score_mode: ScoreMode,
+}
+
+impl<T: Collector> MultiCollector<T> {
+    pub fn new(collectors: Vec<T>) -> MultiCollector<T> {
+        let score_mode = collectors
+            .iter()
+            .map(|c| c.score_mode())
+            .fold(None, |mode: Option<ScoreMode>, m| {
+                Some(mode.map_or(m, |mode| mode.combine(m)))
+            })
+            .unwrap_or(ScoreMode::CompleteNoScores);
+        Self::with_score_mode(collectors, score_mode)
+    }
+
+    fn with_score_mode(collectors: Vec<T>, score_mode: ScoreMode) -> MultiCollector<T> {
+        let len = collectors.len();
+        MultiCollector {
+            collectors,
+            terminated: vec![false; len],
+            min_scores: vec![f32::NEG_INFINITY; len],
+            score_mode,
+        }
+    }
+}
+
+impl<T> MultiCollector<T> {
+    pub fn collectors(&self) -> &[T] {
+        &self.collectors
+    }
+
+    pub fn into_inner(self) -> Vec<T> {
+        self.collectors
+    }
+}
+
+impl<T: SearchCollector> SearchCollector for MultiCollector<T> {
+    type LC = MultiCollector<T::LC>;
+
+    fn set_next_reader<C: Codec>(&mut self, reader: &LeafReaderContext<'_, C>) -> Result<()> {
+        for collector in &mut self.collectors {
+            collector.set_next_reader(reader)?;
+        }
+        for terminated in &mut self.terminated {
+            *terminated = false;
+        }
+        for min_score in &mut self.min_scores {
+            *min_score = f32::NEG_INFINITY;
+        }
+        Ok(())
+    }
+
+    fn support_parallel(&self) -> bool {
+        self.collectors.iter().all(|c| c.support_parallel())
+    }
+
+    fn leaf_collector<C: Codec>(
+        &mut self,
+        reader: &LeafReaderContext<'_, C>,
+    ) -> Result<MultiCollector<T::LC>> {
+        let mut collectors = Vec::with_capacity(self.collectors.len());
+        for collector in &mut self.collectors {
+            collectors.push(collector.leaf_collector(reader)?);
+        }
+        Ok(MultiCollector::with_score_mode(collectors, self.score_mode))
+    }
+
+    fn finish_parallel(&mut self) -> Result<()> {
+        for collector in &mut self.collectors {
+            collector.finish_parallel()?;
+        }
+        Ok(())
+    }
+
+    fn min_competitive_score(&self) -> Option<f32> {
+        if self.score_mode.is_exhaustive() {
+            return None;
+        }
+        // docs can only be skipped if all the collectors would drop them
+        let mut min = f32::INFINITY;
+        for collector in &self.collectors {
+            min = min.min(collector.min_competitive_score()?);
+        }
+        Some(min)
+    }
+}
+
+impl<T: Collector> Collector for MultiCollector<T> {
+    fn score_mode(&self) -> ScoreMode {
+        self.score_mode
+    }
+
+    fn collect<S: Scorer + ?Sized>(&mut self, doc: DocId, scorer: &mut S) -> Result<()> {
+        let mut scorer = CombinedScorer::new(scorer, self.score_mode, &mut self.min_scores);
+        let mut collected = false;
+        for (i, collector) in self.collectors.iter_mut().enumerate() {
+            if self.terminated[i] {
+                continue;
+            }
+            scorer.collector = i;
+            match collector.collect(doc, &mut scorer) {
+                Ok(()) => collected = true,
+                Err(Error(
+                    ErrorKind::Collector(collector::ErrorKind::LeafCollectionTerminated),
+                    _,
+                )) => {
+                    self.terminated[i] = true;
+                    // the collector no longer holds the other ones back
+                    scorer.set_collector_min_score(f32::INFINITY)?;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        if !collected {
+            bail!(ErrorKind::Collector(
+                collector::ErrorKind::LeafCollectionTerminated,
+            ))
+        }
+        Ok(())
+    }
+}
+
+impl<T: ParallelLeafCollector> ParallelLeafCollector for MultiCollector<T> {
+    fn finish_leaf(&mut self) -> Result<()> {
+        for collector in &mut self.collectors {
+            collector.finish_leaf()?;
+        }
+        Ok(())
+    }
+}
+
+/// The scorer the collectors of a combined collection get for a hit: its
+/// score is computed once for all of them. The min competitive scores are
+/// ignored when the combined collection is exhaustive, otherwise the lowest
+/// of the scores of all the collectors is passed on.
+pub(crate) struct CombinedScorer<'a, S: Scorer + ?Sized + 'a> {
+    scorer: &'a mut S,
+    score: Option<f32>,
+    // the min competitive score per collector, `None` if no doc may be skipped
+    min_scores: Option<&'a mut [f32]>,
+    /// The collector the hit is passed to.
+    pub collector: usize,
+}
+
+impl<'a, S: Scorer + ?Sized + 'a> CombinedScorer<'a, S> {
+    pub fn new(
+        scorer: &'a mut S,
+        score_mode: ScoreMode,
+        min_scores: &'a mut [f32],
+    ) -> CombinedScorer<'a, S> {
+        let min_scores = if score_mode.is_exhaustive() {
+            None
+        } else {
+            Some(min_scores)
+        };
+        CombinedScorer {
+            scorer,
+            score: None,
+            min_scores,
+            collector: 0,
+        }
+    }
+
+    fn set_collector_min_score(&mut self, min_score: f32) -> Result<()> {
+        if let Some(ref mut min_scores) = self.min_scores {
+            let before = lowest(min_scores);
+            min_scores[self.collector] = min_score;
+            let after = lowest(min_scores);
+            if after > before {
+                self.scorer.set_min_competitive_score(after)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn lowest(scores: &[f32]) -> f32 {
+    scores.iter().cloned().fold(f32::INFINITY, f32::min)
+}
+
+impl<'a, S: Scorer + ?Sized + 'a> Scorer for CombinedScorer<'a, S> {
+    fn score(&mut self) -> Result<f32> {
+        if let Some(score) = self.score {
+            return Ok(score);
+        }
+        let score = self.scorer.score()?;
+        self.score = Some(score);
+        Ok(score)
+    }
+
+    fn set_min_competitive_score(&mut self, min_score: f32) -> Result<()> {
+        self.set_collector_min_score(min_score)
+    }
+
+    fn max_score(&self) -> f32 {
+        self.scorer.max_score()
+    }
+}
+
+impl<'a, S: Scorer + ?Sized + 'a> DocIterator for CombinedScorer<'a, S> {
+    fn doc_id(&self) -> DocId {
+        self.scorer.doc_id()
+    }
+
+    fn next(&mut self) -> Result<DocId> {
+        self.scorer.next()
+    }
+
+    fn advance(&mut self, target: DocId) -> Result<DocId> {
+        self.scorer.advance(target)
+    }
+
+    fn cost(&self) -> usize {
+        self.scorer.cost()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::doc::FloatPoint;
+    use core::index::tests::*;
+    use core::index::IndexReader;
+    use core::search::collector::{ChainedCollector, TopDocsCollector};
+    use core::search::searcher::TotalHitCountCollector;
+    use core::search::tests::*;
+    use core::search::NO_MORE_DOCS;
+
+    // collects the docs of a leaf, the docs score as their ids
+    fn collect<T: SearchCollector>(collector: &mut T, docs: Vec<DocId>) -> RecordingScorer {
+        let index_reader = MockIndexReader::new(vec![MockLeafReader::new(0)]);
+        let leaves = index_reader.leaves();
+        collector.set_next_reader(&leaves[0]).unwrap();
+        let mut scorer = RecordingScorer::new(docs);
+        loop {
+            let doc = scorer.next().unwrap();
+            if doc == NO_MORE_DOCS {
+                break;
+            }
+            match collector.collect(doc, &mut scorer) {
+                Err(Error(
+                    ErrorKind::Collector(collector::ErrorKind::LeafCollectionTerminated),
+                    _,
+                )) => break,
+                result => result.unwrap(),
+            }
+        }
+        scorer
+    }
+
+    fn hit_ids(collector: &mut TopDocsCollector) -> Vec<DocId> {
+        let top_docs = collector.top_docs();
+        top_docs.score_docs().iter().map(|d| d.doc_id()).collect()
+    }
+
+    #[test]
+    fn test_multi_collector() {
+        let mut collector = MultiCollector::new(vec![
+            TopDocsCollector::new(2).with_total_hits_threshold(0),
+            TopDocsCollector::new(3).with_total_hits_threshold(0),
+        ]);
+        assert_eq!(collector.score_mode(), ScoreMode::TopScores);
+        let scorer = collect(&mut collector, vec![1, 2, 3, 4, 5]);
+        // every hit is scored once, and the docs are only skipped once both
+        // collectors would drop them
+        assert_eq!(scorer.scores, 5);
+        assert_eq!(
+            scorer.thresholds,
+            vec![
+                FloatPoint::next_up(1.0),
+                FloatPoint::next_up(2.0),
+                FloatPoint::next_up(3.0),
+            ]
+        );
+        let mut collectors = collector.into_inner();
+        assert_eq!(hit_ids(&mut collectors[0]), vec![5, 4]);
+        assert_eq!(hit_ids(&mut collectors[1]), vec![5, 4, 3]);
+    }
+
+    #[test]
+    fn test_exhaustive_combination() {
+        // counting every hit, the top docs can't let the scorer skip docs
+        let mut collector = ChainedCollector::new(
+            TopDocsCollector::new(2).with_total_hits_threshold(0),
+            TotalHitCountCollector::new(),
+        );
+        assert_eq!(collector.score_mode(), ScoreMode::Complete);
+        assert_eq!(collector.min_competitive_score(), None);
+        let scorer = collect(&mut collector, vec![1, 2, 3, 4, 5]);
+        assert!(scorer.thresholds.is_empty());
+        assert_eq!(scorer.scores, 5);
+    }
+}
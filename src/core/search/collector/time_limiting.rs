@@ -0,0 +1,179 @@
+// Copyright 2019 Zhizhesihai (Beijing) Technology Limited.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use core::codec::Codec;
+use core::index::LeafReaderContext;
+use core::search::collector;
+use core::search::collector::{Collector, ParallelLeafCollector, ScoreMode, SearchCollector};
+use core::search::query_timeout::{QueryTimeout, DEFAULT_CHECK_INTERVAL};
+use core::search::Scorer;
+use core::util::DocId;
+use error::{ErrorKind, Result};
+
+/// Wraps another collector and stops the search once a `QueryTimeout` is
+/// cancelled, e.g. when its deadline has passed.
+///
+/// The timeout is checked every `check_interval` docs, before collecting
+/// the doc. Once cancelled, the collection fails with `TimeExceeded`, and the
+/// wrapped collector keeps the hits collected before, see `into_inner`.
+/// With a parallel search, the hits of the leaves not finished in time are
+/// dropped.
+pub struct TimeLimitingCollector<T> {
+    collector: T,
+    timeout: QueryTimeout,
+    check_interval: usize,
+    doc_base: DocId,
+    collected: usize,
+}
+
+impl<T> TimeLimitingCollector<T> {
+    pub fn new(collector: T, timeout: QueryTimeout) -> TimeLimitingCollector<T> {
+        TimeLimitingCollector {
+            collector,
+            timeout,
+            check_interval: DEFAULT_CHECK_INTERVAL,
+            doc_base: 0,
+            collected: 0,
+        }
+    }
+
+    /// Checks the timeout every `check_interval` docs, 1 to check it before
+    /// every doc.
+    pub fn with_check_interval(mut self, check_interval: usize) -> TimeLimitingCollector<T> {
+        debug_assert!(check_interval > 0);
+        self.check_interval = check_interval.max(1);
+        self
+    }
+
+    pub fn timeout(&self) -> &QueryTimeout {
+        &self.timeout
+    }
+
+    /// Whether the search was stopped for the timeout.
+    pub fn is_timed_out(&self) -> bool {
+        self.timeout.is_cancelled()
+    }
+
+    pub fn into_inner(self) -> T {
+        self.collector
+    }
+}
+
+impl<T: SearchCollector> SearchCollector for TimeLimitingCollector<T> {
+    type LC = TimeLimitingCollector<T::LC>;
+
+    fn set_next_reader<C: Codec>(&mut self, reader: &LeafReaderContext<'_, C>) -> Result<()> {
+        self.doc_base = reader.doc_base;
+        self.collector.set_next_reader(reader)
+    }
+
+    fn support_parallel(&self) -> bool {
+        self.collector.support_parallel()
+    }
+
+    fn leaf_collector<C: Codec>(
+        &mut self,
+        reader: &LeafReaderContext<'_, C>,
+    ) -> Result<TimeLimitingCollector<T::LC>> {
+        Ok(TimeLimitingCollector {
+            collector: self.collector.leaf_collector(reader)?,
+            timeout: self.timeout.clone(),
+            check_interval: self.check_interval,
+            doc_base: reader.doc_base,
+            collected: 0,
+        })
+    }
+
+    fn finish_parallel(&mut self) -> Result<()> {
+        self.collector.finish_parallel()
+    }
+
+    fn min_competitive_score(&self) -> Option<f32> {
+        self.collector.min_competitive_score()
+    }
+}
+
+impl<T: ParallelLeafCollector> ParallelLeafCollector for TimeLimitingCollector<T> {
+    fn finish_leaf(&mut self) -> Result<()> {
+        self.collector.finish_leaf()
+    }
+}
+
+impl<T: Collector> Collector for TimeLimitingCollector<T> {
+    fn score_mode(&self) -> ScoreMode {
+        self.collector.score_mode()
+    }
+
+    fn collect<S: Scorer + ?Sized>(&mut self, doc: DocId, scorer: &mut S) -> Result<()> {
+        if self.collected % self.check_interval == 0 && self.timeout.is_cancelled() {
+            bail!(ErrorKind::Collector(collector::ErrorKind::TimeExceeded(
+                self.timeout.elapsed(),
+                self.timeout.allowed().unwrap_or(0),
+                self.doc_base + doc,
+            )))
+        }
+        self.collected += 1;
+        self.collector.collect(doc, scorer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::index::tests::*;
+    use core::index::IndexReader;
+    use core::search::collector::TopDocsCollector;
+    use core::search::query_timeout::{Counter, ManualCounter};
+    use core::search::tests::*;
+    use core::search::{DocIterator, NO_MORE_DOCS};
+    use error::Error;
+
+    use std::sync::Arc;
+
+    #[test]
+    fn test_time_limiting_collector() {
+        let leaf_reader = MockLeafReader::new(0);
+        let index_reader = MockIndexReader::new(vec![leaf_reader]);
+        let leaves = index_reader.leaves();
+
+        // a tick per doc scored, the deadline passes at the sixth doc
+        let counter = Arc::new(ManualCounter::default());
+        let timeout = QueryTimeout::with_counter(Arc::clone(&counter) as Arc<dyn Counter>, 5);
+        let mut collector =
+            TimeLimitingCollector::new(TopDocsCollector::new(10), timeout).with_check_interval(1);
+        collector.set_next_reader(&leaves[0]).unwrap();
+        let mut scorer = create_mock_scorer((1..=10).collect());
+        let result = loop {
+            let doc = scorer.next().unwrap();
+            if doc == NO_MORE_DOCS {
+                break Ok(());
+            }
+            if let Err(e) = collector.collect(doc, &mut scorer) {
+                break Err(e);
+            }
+            counter.add_and_get(1);
+        };
+        match result {
+            Err(Error(ErrorKind::Collector(collector::ErrorKind::TimeExceeded(5, 5, 6)), _)) => {}
+            other => panic!("expected the time to be exceeded, got {:?}", other),
+        }
+        assert!(collector.is_timed_out());
+
+        // the hits collected before the deadline are kept
+        let top_docs = collector.into_inner().top_docs();
+        assert_eq!(top_docs.total_hits(), 5);
+        let mut docs: Vec<DocId> = top_docs.score_docs().iter().map(|d| d.doc_id()).collect();
+        docs.sort();
+        assert_eq!(docs, vec![1, 2, 3, 4, 5]);
+    }
+}
@@ -14,32 +14,47 @@
 use core::codec::Codec;
 use core::index::LeafReaderContext;
 use core::search::collector;
-use core::search::collector::{Collector, ParallelLeafCollector, SearchCollector};
+use core::search::collector::{Collector, ParallelLeafCollector, ScoreMode, SearchCollector};
+use core::search::query_timeout::QueryTimeout;
 use core::search::Scorer;
 use core::util::DocId;
 use error::{ErrorKind, Result};
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
 use std::time::{Duration, SystemTime};
 
+/// Collects nothing and stops the collection with `CollectionTerminated`
+/// once a `QueryTimeout` is cancelled, to be chained with the collectors of
+/// a search. `TimeLimitingCollector` wraps a collector instead, and keeps
+/// track of where the search stopped.
 pub struct TimeoutCollector {
-    timeout_duration: Duration,
-    start_time: SystemTime,
-    pub timeout: Arc<AtomicBool>,
+    timeout: QueryTimeout,
 }
 
 impl TimeoutCollector {
+    /// Times out once `timeout_duration` has elapsed from `start_time`.
     pub fn new(timeout_duration: Duration, start_time: SystemTime) -> TimeoutCollector {
-        TimeoutCollector {
-            timeout_duration,
-            start_time,
-            timeout: Arc::new(AtomicBool::new(false)),
-        }
+        let elapsed = SystemTime::now()
+            .duration_since(start_time)
+            .unwrap_or_else(|_| Duration::from_secs(0));
+        let remaining = if timeout_duration > elapsed {
+            timeout_duration - elapsed
+        } else {
+            Duration::from_secs(0)
+        };
+        Self::with_query_timeout(QueryTimeout::with_timeout(remaining))
+    }
+
+    pub fn with_query_timeout(timeout: QueryTimeout) -> TimeoutCollector {
+        TimeoutCollector { timeout }
+    }
+
+    /// Whether the collection was stopped for the timeout.
+    pub fn is_timed_out(&self) -> bool {
+        self.timeout.is_cancelled()
     }
 }
 
 impl SearchCollector for TimeoutCollector {
-    type LC = TimeoutLeafCollector;
+    type LC = TimeoutCollector;
 
     fn set_next_reader<C: Codec>(&mut self, _reader: &LeafReaderContext<'_, C>) -> Result<()> {
         Ok(())
@@ -52,12 +67,8 @@ impl SearchCollector for TimeoutCollector {
     fn leaf_collector<C: Codec>(
         &mut self,
         _reader: &LeafReaderContext<'_, C>,
-    ) -> Result<TimeoutLeafCollector> {
-        Ok(TimeoutLeafCollector::new(
-            self.timeout_duration,
-            self.start_time,
-            Arc::clone(&self.timeout),
-        ))
+    ) -> Result<TimeoutCollector> {
+        Ok(TimeoutCollector::with_query_timeout(self.timeout.clone()))
     }
 
     fn finish_parallel(&mut self) -> Result<()> {
@@ -66,14 +77,12 @@ impl SearchCollector for TimeoutCollector {
 }
 
 impl Collector for TimeoutCollector {
-    fn needs_scores(&self) -> bool {
-        false
+    fn score_mode(&self) -> ScoreMode {
+        ScoreMode::CompleteNoScores
     }
 
     fn collect<S: Scorer + ?Sized>(&mut self, _doc: DocId, _scorer: &mut S) -> Result<()> {
-        let now = SystemTime::now();
-        if self.start_time < now && now.duration_since(self.start_time)? >= self.timeout_duration {
-            self.timeout.store(true, Ordering::Release);
+        if self.timeout.is_cancelled() {
             bail!(ErrorKind::Collector(
                 collector::ErrorKind::CollectionTerminated,
             ))
@@ -82,45 +91,42 @@ impl Collector for TimeoutCollector {
     }
 }
 
-pub struct TimeoutLeafCollector {
-    timeout_duration: Duration,
-    start_time: SystemTime,
-    timeout: Arc<AtomicBool>,
-}
-
-impl TimeoutLeafCollector {
-    pub fn new(
-        timeout_duration: Duration,
-        start_time: SystemTime,
-        timeout: Arc<AtomicBool>,
-    ) -> TimeoutLeafCollector {
-        TimeoutLeafCollector {
-            timeout_duration,
-            start_time,
-            timeout,
-        }
+impl ParallelLeafCollector for TimeoutCollector {
+    fn finish_leaf(&mut self) -> Result<()> {
+        Ok(())
     }
 }
 
-impl Collector for TimeoutLeafCollector {
-    fn needs_scores(&self) -> bool {
-        false
-    }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::search::query_timeout::{Counter, ManualCounter};
+    use core::search::tests::*;
+    use error::Error;
+    use std::sync::Arc;
 
-    fn collect<S: Scorer + ?Sized>(&mut self, _doc: i32, _scorer: &mut S) -> Result<()> {
-        let now = SystemTime::now();
-        if self.start_time < now && now.duration_since(self.start_time)? >= self.timeout_duration {
-            self.timeout.store(true, Ordering::Release);
-            bail!(ErrorKind::Collector(
-                collector::ErrorKind::CollectionTerminated,
-            ))
+    #[test]
+    fn test_timeout_collector() {
+        let counter = Arc::new(ManualCounter::default());
+        let timeout = QueryTimeout::with_counter(Arc::clone(&counter) as Arc<dyn Counter>, 2);
+        let mut collector = TimeoutCollector::with_query_timeout(timeout);
+        let mut scorer = create_mock_scorer(vec![1, 2, 3]);
+        collector.collect(1, &mut scorer).unwrap();
+        counter.add_and_get(1);
+        collector.collect(2, &mut scorer).unwrap();
+        assert!(!collector.is_timed_out());
+
+        counter.add_and_get(1);
+        match collector.collect(3, &mut scorer) {
+            Err(Error(ErrorKind::Collector(collector::ErrorKind::CollectionTerminated), _)) => {}
+            other => panic!("expected the collection to stop, got {:?}", other),
         }
-        Ok(())
-    }
-}
+        assert!(collector.is_timed_out());
 
-impl ParallelLeafCollector for TimeoutLeafCollector {
-    fn finish_leaf(&mut self) -> Result<()> {
-        Ok(())
+        let collector = TimeoutCollector::new(Duration::from_secs(60), SystemTime::now());
+        assert!(!collector.is_timed_out());
+        let start = SystemTime::now() - Duration::from_secs(2);
+        let collector = TimeoutCollector::new(Duration::from_secs(1), start);
+        assert!(collector.is_timed_out());
     }
 }
@@ -11,22 +11,36 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::collections::BinaryHeap;
+use std::cmp::Ordering;
 use std::f32;
 use std::usize;
 
 use core::codec::Codec;
+use core::doc::FloatPoint;
 use core::index::LeafReaderContext;
-use core::search::collector::{Collector, ParallelLeafCollector, SearchCollector};
-use core::search::top_docs::{ScoreDoc, ScoreDocHit, TopDocs, TopScoreDocs};
+use core::search::collector::{
+    Collector, LeafSender, ParallelChannel, ParallelLeafCollector, ScoreMode, SearchCollector,
+};
+use core::search::top_docs::{ScoreDoc, ScoreDocHit, TopDocs, TopScoreDocs, TotalHitsRelation};
 use core::search::Scorer;
+use core::util::priority_queue::PriorityQueue;
 use core::util::DocId;
-use error::{ErrorKind::IllegalState, Result};
+use error::Result;
 
-use crossbeam::channel::{unbounded, Receiver, Sender};
+type ScoreDocPriorityQueue = PriorityQueue<ScoreDoc, fn(&ScoreDoc, &ScoreDoc) -> bool>;
 
-type ScoreDocPriorityQueue = BinaryHeap<ScoreDoc>;
+// the worst hits at the top of the queue
+fn worse_hit(a: &ScoreDoc, b: &ScoreDoc) -> bool {
+    a.cmp(b) == Ordering::Greater
+}
 
+/// Collects the top scoring hits, the hits with the same score are ordered by
+/// doc id whatever the order the leaves are collected in.
+///
+/// Past the total hits threshold, the score of the worst hit kept is passed to
+/// the scorer as the minimum competitive score once the queue is full, so that
+/// it may skip the docs that can't make it to the top hits. The leaves
+/// collected in parallel aren't pruned.
 pub struct TopDocsCollector {
     /// The priority queue which holds the top documents. Note that different
     /// implementations of PriorityQueue give different meaning to 'top documents'.
@@ -39,24 +53,50 @@ pub struct TopDocsCollector {
     /// The total number of documents that the collector encountered.
     total_hits: usize,
 
+    /// The number of hits counted exactly, over it `total_hits` is a lower bound.
+    total_hits_threshold: usize,
+
+    /// The last hit of the previous page, only the hits after it are collected.
+    after: Option<ScoreDoc>,
+
+    // the minimum competitive score passed to the scorer of the current leaf
+    min_competitive_score: f32,
+
     cur_doc_base: DocId,
 
-    // TODO used for parallel collect, maybe should be move the new struct for parallel search
-    channel: Option<(Sender<ScoreDoc>, Receiver<ScoreDoc>)>,
+    channel: ParallelChannel<ScoreDoc>,
 }
 
 impl TopDocsCollector {
     pub fn new(estimated_hits: usize) -> TopDocsCollector {
-        let pq = ScoreDocPriorityQueue::with_capacity(estimated_hits);
+        let pq = ScoreDocPriorityQueue::new(estimated_hits, worse_hit);
         TopDocsCollector {
             pq,
             estimated_hits,
             total_hits: 0,
+            total_hits_threshold: usize::MAX,
+            after: None,
+            min_competitive_score: f32::NEG_INFINITY,
             cur_doc_base: 0,
-            channel: None,
+            channel: ParallelChannel::default(),
         }
     }
 
+    /// Collects the page of hits that sort after `after`, the last hit of the
+    /// previous page.
+    pub fn with_after(mut self, after: ScoreDoc) -> TopDocsCollector {
+        self.after = Some(after);
+        self
+    }
+
+    /// Only counts the hits exactly up to `threshold`, the total hits are
+    /// reported as a lower bound past it and the scorers may skip the docs
+    /// that can't compete.
+    pub fn with_total_hits_threshold(mut self, threshold: usize) -> TopDocsCollector {
+        self.total_hits_threshold = threshold;
+        self
+    }
+
     /// Returns the top docs that were collected by this collector.
     pub fn top_docs(&mut self) -> TopDocs {
         let size = self.total_hits.min(self.pq.len());
@@ -67,7 +107,11 @@ impl TopDocsCollector {
         }
 
         score_docs.reverse();
-        TopDocs::Score(TopScoreDocs::new(self.total_hits, score_docs))
+        let mut top_docs = TopScoreDocs::new(self.total_hits, score_docs);
+        if self.total_hits > self.total_hits_threshold {
+            top_docs.total_hits_relation = TotalHitsRelation::GreaterThanOrEqualTo;
+        }
+        TopDocs::Score(top_docs)
     }
 
     fn add_doc(&mut self, doc_id: DocId, score: f32) {
@@ -75,16 +119,30 @@ impl TopDocsCollector {
 
         self.total_hits += 1;
 
-        let at_capacity = self.pq.len() == self.estimated_hits;
-
-        if !at_capacity {
-            let score_doc = ScoreDoc::new(doc_id, score);
-            self.pq.push(score_doc);
-        } else if let Some(mut doc) = self.pq.peek_mut() {
-            if doc.score < score {
-                doc.reset(doc_id, score);
+        let score_doc = ScoreDoc::new(doc_id, score);
+        if let Some(ref after) = self.after {
+            // collected in a previous page
+            if score_doc <= *after {
+                return;
             }
         }
+
+        if self.pq.len() < self.estimated_hits {
+            self.pq.add(score_doc);
+        } else if !self.pq.is_empty() && score_doc < *self.pq.top() {
+            // replace the worst hit in place and move it down
+            self.pq.top_mut().reset(doc_id, score);
+            self.pq.update_top();
+        }
+    }
+
+    // the score the next hits have to beat, if the hits may be skipped
+    fn competitive_score(&self) -> Option<f32> {
+        if self.total_hits <= self.total_hits_threshold || self.pq.len() < self.estimated_hits {
+            return None;
+        }
+        // the hits with the same score as the worst one have greater doc ids
+        self.pq.peek().map(|top| FloatPoint::next_up(top.score))
     }
 }
 
@@ -93,6 +151,7 @@ impl SearchCollector for TopDocsCollector {
 
     fn set_next_reader<C: Codec>(&mut self, reader: &LeafReaderContext<'_, C>) -> Result<()> {
         self.cur_doc_base = reader.doc_base;
+        self.min_competitive_score = f32::NEG_INFINITY;
 
         Ok(())
     }
@@ -105,33 +164,27 @@ impl SearchCollector for TopDocsCollector {
         &mut self,
         reader: &LeafReaderContext<'_, C>,
     ) -> Result<TopDocsLeafCollector> {
-        if self.channel.is_none() {
-            self.channel = Some(unbounded());
-        }
         Ok(TopDocsLeafCollector::new(
             reader.doc_base,
-            self.channel.as_ref().unwrap().0.clone(),
+            self.channel.sender(),
         ))
     }
 
     fn finish_parallel(&mut self) -> Result<()> {
-        let channel = self.channel.take();
-        // iff all the `weight.create_scorer(leaf_reader)` return None, the channel won't
-        // inited and thus stay None
-        if let Some((sender, receiver)) = channel {
-            drop(sender);
-            while let Ok(doc) = receiver.recv() {
-                self.add_doc(doc.doc, doc.score)
-            }
+        for doc in self.channel.receive() {
+            self.add_doc(doc.doc, doc.score);
         }
-
         Ok(())
     }
+
+    fn min_competitive_score(&self) -> Option<f32> {
+        self.competitive_score()
+    }
 }
 
 impl Collector for TopDocsCollector {
-    fn needs_scores(&self) -> bool {
-        true
+    fn score_mode(&self) -> ScoreMode {
+        ScoreMode::TopScores
     }
 
     fn collect<S: Scorer + ?Sized>(&mut self, doc: DocId, scorer: &mut S) -> Result<()> {
@@ -142,17 +195,23 @@ impl Collector for TopDocsCollector {
         let id = doc + self.cur_doc_base;
         self.add_doc(id, score);
 
+        if let Some(min_score) = self.competitive_score() {
+            if min_score > self.min_competitive_score {
+                scorer.set_min_competitive_score(min_score)?;
+                self.min_competitive_score = min_score;
+            }
+        }
         Ok(())
     }
 }
 
 pub struct TopDocsLeafCollector {
     doc_base: DocId,
-    channel: Sender<ScoreDoc>,
+    channel: LeafSender<ScoreDoc>,
 }
 
 impl TopDocsLeafCollector {
-    pub fn new(doc_base: DocId, channel: Sender<ScoreDoc>) -> TopDocsLeafCollector {
+    pub fn new(doc_base: DocId, channel: LeafSender<ScoreDoc>) -> TopDocsLeafCollector {
         TopDocsLeafCollector { doc_base, channel }
     }
 }
@@ -165,19 +224,13 @@ impl ParallelLeafCollector for TopDocsLeafCollector {
 }
 
 impl Collector for TopDocsLeafCollector {
-    fn needs_scores(&self) -> bool {
-        true
+    fn score_mode(&self) -> ScoreMode {
+        ScoreMode::TopScores
     }
 
     fn collect<S: Scorer + ?Sized>(&mut self, doc: i32, scorer: &mut S) -> Result<()> {
         let score_doc = ScoreDoc::new(doc + self.doc_base, scorer.score()?);
-        self.channel.send(score_doc).map_err(|e| {
-            IllegalState(format!(
-                "channel unexpected closed before search complete with err: {:?}",
-                e
-            ))
-            .into()
-        })
+        self.channel.send(score_doc)
     }
 }
 
@@ -220,4 +273,64 @@ mod tests {
         assert_eq!(score_docs[1].doc_id(), 3);
         assert_eq!(score_docs[2].doc_id(), 3);
     }
+
+    // collects the docs of two leaves, the docs score as their leaf doc ids
+    fn collect(collector: &mut TopDocsCollector, docs: &[DocId]) -> Vec<Vec<f32>> {
+        let index_reader =
+            MockIndexReader::new(vec![MockLeafReader::new(0), MockLeafReader::new(10)]);
+        let leaves = index_reader.leaves();
+        let mut thresholds = vec![];
+        for leaf in &leaves {
+            collector.set_next_reader(leaf).unwrap();
+            let mut scorer = RecordingScorer::new(docs.to_vec());
+            loop {
+                let doc = scorer.next().unwrap();
+                if doc == NO_MORE_DOCS {
+                    break;
+                }
+                collector.collect(doc, &mut scorer).unwrap();
+            }
+            thresholds.push(scorer.thresholds);
+        }
+        thresholds
+    }
+
+    fn hits(top_docs: &TopDocs) -> Vec<(DocId, f32)> {
+        top_docs
+            .score_docs()
+            .iter()
+            .map(|d| (d.doc_id(), d.score()))
+            .collect()
+    }
+
+    #[test]
+    fn test_min_competitive_score() {
+        let docs = vec![1, 3, 2, 5, 4, 6];
+
+        let mut collector = TopDocsCollector::new(3);
+        let thresholds = collect(&mut collector, &docs);
+        assert_eq!(thresholds, vec![vec![], vec![]]);
+        assert_eq!(collector.min_competitive_score(), None);
+        let exhaustive = collector.top_docs();
+        assert_eq!(exhaustive.total_hits_relation(), TotalHitsRelation::EqualTo);
+
+        let mut collector = TopDocsCollector::new(3).with_total_hits_threshold(4);
+        let thresholds = collect(&mut collector, &docs);
+        // published once the queue is full and the threshold crossed, then
+        // every time the worst hit gets better, again to the scorer of the
+        // next leaf
+        let above = FloatPoint::next_up;
+        assert_eq!(
+            thresholds,
+            vec![vec![above(3.0), above(4.0)], vec![above(4.0), above(5.0)]]
+        );
+        assert_eq!(collector.min_competitive_score(), Some(above(5.0)));
+        let top_docs = collector.top_docs();
+        assert_eq!(hits(&top_docs), hits(&exhaustive));
+        assert_eq!(top_docs.total_hits(), 12);
+        assert_eq!(
+            top_docs.total_hits_relation(),
+            TotalHitsRelation::GreaterThanOrEqualTo
+        );
+    }
 }
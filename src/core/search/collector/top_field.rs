@@ -0,0 +1,927 @@
+// Copyright 2019 Zhizhesihai (Beijing) Technology Limited.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::cmp::Ordering;
+use std::f32;
+use std::mem;
+use std::usize;
+
+use core::codec::Codec;
+use core::index::LeafReaderContext;
+use core::search::collector;
+use core::search::collector::{
+    Collector, LeafSender, ParallelChannel, ParallelLeafCollector, ScoreMode, SearchCollector,
+};
+use core::search::field_comparator::{ComparatorValue, FieldComparator, FieldComparatorEnum};
+use core::search::sort::Sort;
+use core::search::sort_field::{SortField, SortFieldType};
+use core::search::top_docs::{FieldDoc, ScoreDocHit, TopDocs, TopFieldDocs, TotalHitsRelation};
+use core::search::Scorer;
+use core::util::DocId;
+use error::{ErrorKind, ErrorKind::IllegalArgument, Result};
+
+// a hit of `FieldValueHitQueue`, its sort values are in `slot` of the comparators
+#[derive(Clone, Copy, Debug)]
+struct Entry {
+    slot: usize,
+    doc: DocId,
+    score: f32,
+}
+
+/// The best `size` hits by a `Sort`, the ties are broken by doc id.
+///
+/// The sort values of the hits are kept by the comparators of the sort fields,
+/// the heap is ordered by them with the worst hit on top, which is the bottom
+/// the new hits compete with.
+struct FieldValueHitQueue {
+    comparators: Vec<FieldComparatorEnum>,
+    reverse: Vec<bool>,
+    heap: Vec<Entry>,
+    size: usize,
+    // the doc id of the last hit of the previous page, whose sort values are
+    // the top values of the comparators
+    after_doc: Option<DocId>,
+    doc_base: DocId,
+}
+
+impl FieldValueHitQueue {
+    fn new(sort: &Sort, size: usize, after: Option<&FieldDoc>) -> Result<FieldValueHitQueue> {
+        let fields = sort.get_sort();
+        let mut comparators = Vec::with_capacity(fields.len());
+        for (i, field) in fields.iter().enumerate() {
+            let mut comparator = field.get_comparator(size, field.missing_value());
+            if let Some(after) = after {
+                comparator.set_top_value(&after.fields[i])?;
+            }
+            comparators.push(comparator);
+        }
+        Ok(FieldValueHitQueue {
+            comparators,
+            reverse: fields.iter().map(SortField::is_reverse).collect(),
+            heap: Vec::with_capacity(size),
+            size,
+            after_doc: after.map(|a| a.doc),
+            doc_base: 0,
+        })
+    }
+
+    fn set_next_reader<C: Codec>(&mut self, reader: &LeafReaderContext<'_, C>) -> Result<()> {
+        self.doc_base = reader.doc_base;
+        for comparator in &mut self.comparators {
+            comparator.get_information_from_reader(reader)?;
+        }
+        Ok(())
+    }
+
+    fn value(comparator: &FieldComparatorEnum, doc: DocId, score: f32) -> ComparatorValue {
+        if comparator.get_type() == SortFieldType::Score {
+            ComparatorValue::Score(score)
+        } else {
+            ComparatorValue::Doc(doc)
+        }
+    }
+
+    fn compare(&self, e1: &Entry, e2: &Entry) -> Ordering {
+        for (comparator, &reverse) in self.comparators.iter().zip(&self.reverse) {
+            let cmp = comparator.compare(e1.slot, e2.slot);
+            let cmp = if reverse { cmp.reverse() } else { cmp };
+            if cmp != Ordering::Equal {
+                return cmp;
+            }
+        }
+        e1.doc.cmp(&e2.doc)
+    }
+
+    // compares the bottom with a doc of the current reader, the doc is
+    // competitive if the bottom is greater
+    fn compare_bottom(&self, doc: DocId, score: f32) -> Result<Ordering> {
+        for (comparator, &reverse) in self.comparators.iter().zip(&self.reverse) {
+            let cmp = comparator.compare_bottom(Self::value(comparator, doc, score))?;
+            let cmp = if reverse { cmp.reverse() } else { cmp };
+            if cmp != Ordering::Equal {
+                return Ok(cmp);
+            }
+        }
+        // the docs are collected in order, a tie can't beat the bottom
+        Ok(Ordering::Less)
+    }
+
+    // whether a doc of the current reader sorts after the previous page
+    fn is_after(&self, doc: DocId, score: f32) -> Result<bool> {
+        let after_doc = match self.after_doc {
+            Some(after_doc) => after_doc,
+            None => return Ok(true),
+        };
+        for (comparator, &reverse) in self.comparators.iter().zip(&self.reverse) {
+            let cmp = comparator.compare_top(Self::value(comparator, doc, score))?;
+            let cmp = if reverse { cmp.reverse() } else { cmp };
+            if cmp != Ordering::Equal {
+                return Ok(cmp == Ordering::Less);
+            }
+        }
+        Ok(after_doc < doc + self.doc_base)
+    }
+
+    /// Adds a doc of the current reader if it's competitive, returns false if
+    /// the queue is full and the doc doesn't beat its bottom.
+    fn add(&mut self, doc: DocId, score: f32) -> Result<bool> {
+        if !self.is_after(doc, score)? {
+            return Ok(true);
+        }
+        if self.heap.len() == self.size {
+            if self.compare_bottom(doc, score)? != Ordering::Greater {
+                return Ok(false);
+            }
+            let slot = self.heap[0].slot;
+            self.copy(slot, doc, score)?;
+            self.heap[0] = Entry {
+                slot,
+                doc: doc + self.doc_base,
+                score,
+            };
+            self.down_heap();
+        } else {
+            let slot = self.heap.len();
+            self.copy(slot, doc, score)?;
+            self.heap.push(Entry {
+                slot,
+                doc: doc + self.doc_base,
+                score,
+            });
+            self.up_heap();
+        }
+        if self.heap.len() == self.size {
+            let bottom = self.heap[0].slot;
+            for comparator in &mut self.comparators {
+                comparator.set_bottom(bottom);
+            }
+        }
+        Ok(true)
+    }
+
+    fn copy(&mut self, slot: usize, doc: DocId, score: f32) -> Result<()> {
+        for comparator in &mut self.comparators {
+            let value = Self::value(comparator, doc, score);
+            comparator.copy(slot, value)?;
+        }
+        Ok(())
+    }
+
+    fn up_heap(&mut self) {
+        let mut i = self.heap.len() - 1;
+        while i > 0 {
+            let parent = (i - 1) / 2;
+            if self.compare(&self.heap[i], &self.heap[parent]) != Ordering::Greater {
+                break;
+            }
+            self.heap.swap(i, parent);
+            i = parent;
+        }
+    }
+
+    fn down_heap(&mut self) {
+        let mut i = 0;
+        loop {
+            let left = 2 * i + 1;
+            let right = left + 1;
+            let mut greatest = i;
+            if left < self.heap.len()
+                && self.compare(&self.heap[left], &self.heap[greatest]) == Ordering::Greater
+            {
+                greatest = left;
+            }
+            if right < self.heap.len()
+                && self.compare(&self.heap[right], &self.heap[greatest]) == Ordering::Greater
+            {
+                greatest = right;
+            }
+            if greatest == i {
+                break;
+            }
+            self.heap.swap(i, greatest);
+            i = greatest;
+        }
+    }
+
+    /// Takes the hits out of the queue, best first.
+    fn field_docs(&mut self) -> Vec<FieldDoc> {
+        let mut entries = mem::replace(&mut self.heap, Vec::with_capacity(self.size));
+        entries.sort_by(|e1, e2| self.compare(e1, e2));
+        entries
+            .into_iter()
+            .map(|e| {
+                let fields = self.comparators.iter().map(|c| c.value(e.slot)).collect();
+                FieldDoc::new(e.doc, e.score, fields)
+            })
+            .collect()
+    }
+}
+
+/// Compares two hits by their sort values for `fields`, the better hit
+/// first, the ties are broken by doc id. The doc ids must be global.
+pub(crate) fn compare_field_docs(fields: &[SortField], h1: &FieldDoc, h2: &FieldDoc) -> Ordering {
+    for (i, field) in fields.iter().enumerate() {
+        let cmp = field.compare_values(&h1.fields[i], &h2.fields[i]);
+        // higher scores sort first
+        let reverse = field.is_reverse() != (field.field_type() == SortFieldType::Score);
+        let cmp = if reverse { cmp.reverse() } else { cmp };
+        if cmp != Ordering::Equal {
+            return cmp;
+        }
+    }
+    h1.doc.cmp(&h2.doc)
+}
+
+/// Collects the top hits by a `Sort` as `FieldDoc`s holding the sort values
+/// of the hits, e.g. to sort by the numeric doc values of a field then by
+/// score. The ties are broken by doc id.
+///
+/// The scores of the hits are only computed if the sort needs them or if
+/// `track_scores` is set, they are `NaN` otherwise.
+///
+/// Past the total hits threshold, the collection of a leaf stops at the first
+/// hit that can't compete anymore if the leaf is sorted by the sort of the
+/// collector, i.e. by an index sort starting with it or by doc id.
+pub struct TopFieldCollector {
+    sort: Sort,
+    num_hits: usize,
+    track_scores: bool,
+    needs_scores: bool,
+    queue: FieldValueHitQueue,
+    after: Option<FieldDoc>,
+
+    /// The total number of documents that the collector encountered.
+    total_hits: usize,
+
+    /// The number of hits counted exactly, over it `total_hits` is a lower bound.
+    total_hits_threshold: usize,
+
+    // whether the current leaf is sorted by `sort`
+    can_early_terminate: bool,
+
+    max_score: f32,
+
+    // the hits of the leaves collected in parallel
+    channel: ParallelChannel<LeafHits>,
+    leaf_hits: Vec<FieldDoc>,
+}
+
+impl TopFieldCollector {
+    pub fn new(sort: Sort, num_hits: usize, track_scores: bool) -> Result<TopFieldCollector> {
+        if num_hits == 0 {
+            bail!(IllegalArgument("num_hits must be > 0".into()));
+        }
+        let queue = FieldValueHitQueue::new(&sort, num_hits, None)?;
+        let needs_scores = track_scores || sort.needs_scores();
+        Ok(TopFieldCollector {
+            sort,
+            num_hits,
+            track_scores,
+            needs_scores,
+            queue,
+            after: None,
+            total_hits: 0,
+            total_hits_threshold: usize::MAX,
+            can_early_terminate: false,
+            max_score: f32::NAN,
+            channel: ParallelChannel::default(),
+            leaf_hits: vec![],
+        })
+    }
+
+    /// Collects the page of hits that sort after `after`, the last hit of the
+    /// previous page.
+    pub fn with_after(mut self, after: FieldDoc) -> Result<TopFieldCollector> {
+        if after.fields.len() != self.sort.get_sort().len() {
+            bail!(IllegalArgument(format!(
+                "after has {} sort values, but the sort has {} fields",
+                after.fields.len(),
+                self.sort.get_sort().len()
+            )));
+        }
+        self.queue = FieldValueHitQueue::new(&self.sort, self.num_hits, Some(&after))?;
+        self.after = Some(after);
+        Ok(self)
+    }
+
+    /// Only counts the hits exactly up to `threshold`, the total hits are
+    /// reported as a lower bound past it and the collection of the sorted
+    /// leaves may terminate early.
+    pub fn with_total_hits_threshold(mut self, threshold: usize) -> TopFieldCollector {
+        self.total_hits_threshold = threshold;
+        self
+    }
+
+    /// Returns the top docs that were collected by this collector.
+    pub fn top_docs(&mut self) -> TopDocs {
+        let mut hits = self.queue.field_docs();
+        if !self.leaf_hits.is_empty() {
+            hits.append(&mut self.leaf_hits);
+            self.merge(&mut hits);
+        }
+        let score_docs = hits.into_iter().map(ScoreDocHit::Field).collect();
+        let mut top_docs = TopFieldDocs::new(
+            self.total_hits,
+            score_docs,
+            self.sort.get_sort().to_vec(),
+            self.max_score,
+        );
+        if self.total_hits > self.total_hits_threshold {
+            top_docs.total_hits_relation = TotalHitsRelation::GreaterThanOrEqualTo;
+        }
+        TopDocs::Field(top_docs)
+    }
+
+    // sorts the hits of several leaves by their sort values and keeps the
+    // best `num_hits`, the doc ids are already global
+    fn merge(&self, hits: &mut Vec<FieldDoc>) {
+        let fields = self.sort.get_sort();
+        hits.sort_by(|h1, h2| compare_field_docs(fields, h1, h2));
+        hits.truncate(self.num_hits);
+    }
+
+    // whether the docs of the leaf are in the order of `sort`, so that the
+    // docs after a hit that can't compete can't either
+    fn can_early_terminate<C: Codec>(sort: &Sort, reader: &LeafReaderContext<'_, C>) -> bool {
+        let fields = sort.get_sort();
+        if let Some(first) = fields.first() {
+            if first.field_type() == SortFieldType::Doc && !first.is_reverse() {
+                return true;
+            }
+        }
+        // the flushed segments are sorted without the missing values
+        match reader.reader.index_sort() {
+            Some(index_sort) => {
+                index_sort.get_sort().starts_with(fields)
+                    && fields.iter().all(|f| f.missing_value().is_none())
+            }
+            None => false,
+        }
+    }
+
+    fn add_leaf_hits(&mut self, mut leaf_hits: LeafHits) {
+        self.total_hits += leaf_hits.total_hits;
+        if self.max_score.is_nan() || leaf_hits.max_score > self.max_score {
+            self.max_score = leaf_hits.max_score;
+        }
+        self.leaf_hits.append(&mut leaf_hits.hits);
+    }
+}
+
+impl SearchCollector for TopFieldCollector {
+    type LC = TopFieldLeafCollector;
+
+    fn set_next_reader<C: Codec>(&mut self, reader: &LeafReaderContext<'_, C>) -> Result<()> {
+        self.can_early_terminate = Self::can_early_terminate(&self.sort, reader);
+        self.queue.set_next_reader(reader)
+    }
+
+    fn support_parallel(&self) -> bool {
+        true
+    }
+
+    fn leaf_collector<C: Codec>(
+        &mut self,
+        reader: &LeafReaderContext<'_, C>,
+    ) -> Result<TopFieldLeafCollector> {
+        let mut queue = FieldValueHitQueue::new(&self.sort, self.num_hits, self.after.as_ref())?;
+        queue.set_next_reader(reader)?;
+        Ok(TopFieldLeafCollector {
+            queue,
+            needs_scores: self.needs_scores,
+            track_scores: self.track_scores,
+            total_hits_threshold: self.total_hits_threshold,
+            can_early_terminate: Self::can_early_terminate(&self.sort, reader),
+            total_hits: 0,
+            max_score: f32::NAN,
+            channel: self.channel.sender(),
+        })
+    }
+
+    fn finish_parallel(&mut self) -> Result<()> {
+        for leaf_hits in self.channel.receive() {
+            self.add_leaf_hits(leaf_hits);
+        }
+        Ok(())
+    }
+}
+
+impl Collector for TopFieldCollector {
+    fn score_mode(&self) -> ScoreMode {
+        if self.needs_scores {
+            ScoreMode::Complete
+        } else {
+            ScoreMode::CompleteNoScores
+        }
+    }
+
+    fn collect<S: Scorer + ?Sized>(&mut self, doc: DocId, scorer: &mut S) -> Result<()> {
+        self.total_hits += 1;
+        let score = if self.needs_scores {
+            scorer.score()?
+        } else {
+            f32::NAN
+        };
+        if self.track_scores && (self.max_score.is_nan() || score > self.max_score) {
+            self.max_score = score;
+        }
+        if !self.queue.add(doc, score)?
+            && self.can_early_terminate
+            && self.total_hits > self.total_hits_threshold
+        {
+            bail!(ErrorKind::Collector(
+                collector::ErrorKind::LeafCollectionTerminated,
+            ))
+        }
+        Ok(())
+    }
+}
+
+// the top hits of a leaf collected in parallel
+struct LeafHits {
+    hits: Vec<FieldDoc>,
+    total_hits: usize,
+    max_score: f32,
+}
+
+pub struct TopFieldLeafCollector {
+    queue: FieldValueHitQueue,
+    needs_scores: bool,
+    track_scores: bool,
+    // compared to the hits of the leaf only
+    total_hits_threshold: usize,
+    can_early_terminate: bool,
+    total_hits: usize,
+    max_score: f32,
+    channel: LeafSender<LeafHits>,
+}
+
+impl ParallelLeafCollector for TopFieldLeafCollector {
+    fn finish_leaf(&mut self) -> Result<()> {
+        let hits = LeafHits {
+            hits: self.queue.field_docs(),
+            total_hits: self.total_hits,
+            max_score: self.max_score,
+        };
+        self.channel.send(hits)
+    }
+}
+
+impl Collector for TopFieldLeafCollector {
+    fn score_mode(&self) -> ScoreMode {
+        if self.needs_scores {
+            ScoreMode::Complete
+        } else {
+            ScoreMode::CompleteNoScores
+        }
+    }
+
+    fn collect<S: Scorer + ?Sized>(&mut self, doc: DocId, scorer: &mut S) -> Result<()> {
+        self.total_hits += 1;
+        let score = if self.needs_scores {
+            scorer.score()?
+        } else {
+            f32::NAN
+        };
+        if self.track_scores && (self.max_score.is_nan() || score > self.max_score) {
+            self.max_score = score;
+        }
+        if !self.queue.add(doc, score)?
+            && self.can_early_terminate
+            && self.total_hits > self.total_hits_threshold
+        {
+            bail!(ErrorKind::Collector(
+                collector::ErrorKind::LeafCollectionTerminated,
+            ))
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::doc::{Field, NumericDocValuesField, SORTED_DOC_VALUES_FIELD_TYPE};
+    use core::index::index_writer_config::IndexWriterConfig;
+    use core::index::tests::{open_test_reader, open_test_reader_with, TestReader};
+    use core::index::{Fieldable, IndexReader};
+    use core::search::collector::TopDocsCollector;
+    use core::search::match_all::MatchAllDocsQuery;
+    use core::search::searcher::{DefaultIndexSearcher, IndexSearcher};
+    use core::search::sort_field::SimpleSortField;
+    use core::search::tests::create_mock_scorer;
+    use core::search::top_docs::ScoreDoc;
+    use core::search::{DocIterator, NO_MORE_DOCS};
+    use core::util::VariantValue;
+
+    use std::sync::Arc;
+
+    const MISSING_PRICE: i64 = 100;
+
+    fn price(doc: DocId) -> Option<i64> {
+        if doc % 9 == 4 {
+            None
+        } else {
+            Some(i64::from(doc * 7 % 13))
+        }
+    }
+
+    fn name(doc: DocId) -> Option<&'static str> {
+        if doc % 11 == 5 {
+            None
+        } else {
+            Some(["b", "a", "c", "ab"][(doc + doc / 10) as usize % 4])
+        }
+    }
+
+    // three segments of 10 docs, with a few docs missing a price or a name
+    fn document(doc: DocId) -> Vec<Box<dyn Fieldable>> {
+        let mut fields: Vec<Box<dyn Fieldable>> = vec![];
+        if let Some(price) = price(doc) {
+            fields.push(Box::new(NumericDocValuesField::new("price", price)));
+        }
+        if let Some(name) = name(doc) {
+            fields.push(Box::new(Field::new(
+                "name".to_string(),
+                SORTED_DOC_VALUES_FIELD_TYPE,
+                Some(VariantValue::from(name.as_bytes())),
+                None,
+            )));
+        }
+        fields
+    }
+
+    fn build_index() -> Arc<TestReader> {
+        let segments = (0..3)
+            .map(|segment| (segment * 10..segment * 10 + 10).map(document).collect())
+            .collect();
+        open_test_reader(segments)
+    }
+
+    // the same docs in segments sorted by price, the missing prices indexed
+    fn build_sorted_index() -> Arc<TestReader> {
+        let mut conf = IndexWriterConfig::default();
+        conf.max_buffered_docs = Some(10);
+        conf.index_sort = Some(sorted_price_sort());
+        let docs = (0..30)
+            .map(|doc| {
+                let price = price(doc).unwrap_or(MISSING_PRICE);
+                vec![NumericDocValuesField::new("price", price)]
+            })
+            .collect();
+        open_test_reader_with(conf, vec![docs])
+    }
+
+    fn sorted_price_sort() -> Sort {
+        let field = SimpleSortField::new("price".to_string(), SortFieldType::Long, false);
+        Sort::new(vec![SortField::Simple(field)])
+    }
+
+    fn price_sort(reverse: bool) -> Sort {
+        let mut field = SimpleSortField::new("price".to_string(), SortFieldType::Long, reverse);
+        field.set_missing_value(Some(VariantValue::Long(MISSING_PRICE)));
+        Sort::new(vec![SortField::Simple(field)])
+    }
+
+    // the docs in price order, the ties by doc id
+    fn price_order(reverse: bool) -> Vec<DocId> {
+        let mut docs: Vec<DocId> = (0..30).collect();
+        docs.sort_by(|&d1, &d2| {
+            let p1 = price(d1).unwrap_or(MISSING_PRICE);
+            let p2 = price(d2).unwrap_or(MISSING_PRICE);
+            let cmp = if reverse { p2.cmp(&p1) } else { p1.cmp(&p2) };
+            cmp.then(d1.cmp(&d2))
+        });
+        docs
+    }
+
+    // collects every doc of every segment, scored by its id in the segment
+    fn collect_all<T: SearchCollector>(reader: &TestReader, collector: &mut T) {
+        for leaf in reader.leaves() {
+            collector.set_next_reader(&leaf).unwrap();
+            let mut scorer = create_mock_scorer((0..leaf.reader.max_doc()).collect());
+            loop {
+                let doc = scorer.next().unwrap();
+                if doc == NO_MORE_DOCS {
+                    break;
+                }
+                collector.collect(doc, &mut scorer).unwrap();
+            }
+        }
+    }
+
+    // same as `collect_all`, with a leaf collector per segment
+    fn collect_parallel<T: SearchCollector>(reader: &TestReader, collector: &mut T) {
+        for leaf in reader.leaves() {
+            let mut leaf_collector = collector.leaf_collector(&leaf).unwrap();
+            let mut scorer = create_mock_scorer((0..leaf.reader.max_doc()).collect());
+            loop {
+                let doc = scorer.next().unwrap();
+                if doc == NO_MORE_DOCS {
+                    break;
+                }
+                leaf_collector.collect(doc, &mut scorer).unwrap();
+            }
+            leaf_collector.finish_leaf().unwrap();
+        }
+        collector.finish_parallel().unwrap();
+    }
+
+    fn field_docs(top_docs: &TopDocs) -> Vec<FieldDoc> {
+        top_docs
+            .score_docs()
+            .iter()
+            .map(|hit| match hit {
+                ScoreDocHit::Field(f) => f.clone(),
+                ScoreDocHit::Score(_) => panic!("expected field docs"),
+            })
+            .collect()
+    }
+
+    fn doc_ids(hits: &[FieldDoc]) -> Vec<DocId> {
+        hits.iter().map(|hit| hit.doc).collect()
+    }
+
+    #[test]
+    fn test_sort_by_numeric_doc_values() {
+        let reader = build_index();
+        assert_eq!(reader.leaves().len(), 3);
+
+        for &reverse in &[false, true] {
+            let expected = price_order(reverse);
+            let mut collector = TopFieldCollector::new(price_sort(reverse), 10, false).unwrap();
+            collect_all(&reader, &mut collector);
+            let top_docs = collector.top_docs();
+            assert_eq!(top_docs.total_hits(), 30);
+            assert_eq!(top_docs.total_hits_relation(), TotalHitsRelation::EqualTo);
+
+            let hits = field_docs(&top_docs);
+            assert_eq!(doc_ids(&hits), &expected[..10]);
+            for hit in &hits {
+                let price = price(hit.doc).unwrap_or(MISSING_PRICE);
+                assert_eq!(hit.fields, vec![VariantValue::Long(price)]);
+                assert!(hit.score.is_nan());
+            }
+
+            // the hits of the leaves are merged in the same order
+            let mut collector = TopFieldCollector::new(price_sort(reverse), 10, false).unwrap();
+            collect_parallel(&reader, &mut collector);
+            let parallel_hits = field_docs(&collector.top_docs());
+            assert_eq!(doc_ids(&parallel_hits), doc_ids(&hits));
+        }
+    }
+
+    #[test]
+    fn test_search_after_pages() {
+        let reader = build_index();
+        for &reverse in &[false, true] {
+            for &parallel in &[false, true] {
+                let mut pages = vec![];
+                let mut after: Option<FieldDoc> = None;
+                loop {
+                    let mut collector =
+                        TopFieldCollector::new(price_sort(reverse), 7, false).unwrap();
+                    if let Some(after) = after.take() {
+                        collector = collector.with_after(after).unwrap();
+                    }
+                    if parallel {
+                        collect_parallel(&reader, &mut collector);
+                    } else {
+                        collect_all(&reader, &mut collector);
+                    }
+                    let top_docs = collector.top_docs();
+                    // every hit is counted, not only those of the page
+                    assert_eq!(top_docs.total_hits(), 30);
+                    let hits = field_docs(&top_docs);
+                    if hits.is_empty() {
+                        break;
+                    }
+                    after = hits.last().cloned();
+                    pages.push(doc_ids(&hits));
+                }
+
+                let sizes: Vec<usize> = pages.iter().map(Vec::len).collect();
+                assert_eq!(sizes, vec![7, 7, 7, 7, 2]);
+                let docs: Vec<DocId> = pages.concat();
+                assert_eq!(docs, price_order(reverse));
+            }
+        }
+
+        let collector = TopFieldCollector::new(price_sort(false), 7, false).unwrap();
+        assert!(collector
+            .with_after(FieldDoc::new(3, f32::NAN, vec![]))
+            .is_err());
+        assert!(TopFieldCollector::new(price_sort(false), 0, false).is_err());
+    }
+
+    #[test]
+    fn test_sort_by_string_then_score() {
+        let reader = build_index();
+        let name_field = SimpleSortField::new("name".to_string(), SortFieldType::String, false);
+        let sort = Sort::new(vec![SortField::Simple(name_field), SortField::new_score()]);
+        let mut collector = TopFieldCollector::new(sort, 12, true).unwrap();
+        collect_all(&reader, &mut collector);
+        let hits = field_docs(&collector.top_docs());
+
+        // the missing names sort first, then higher scores, which are the ids
+        // in the segment
+        let mut expected: Vec<DocId> = (0..30).collect();
+        expected.sort_by(|&d1, &d2| {
+            name(d1)
+                .unwrap_or("")
+                .cmp(name(d2).unwrap_or(""))
+                .then((d2 % 10).cmp(&(d1 % 10)))
+                .then(d1.cmp(&d2))
+        });
+        assert_eq!(doc_ids(&hits), &expected[..12]);
+        for hit in &hits {
+            let name = name(hit.doc).unwrap_or("");
+            assert_eq!(
+                hit.fields[0],
+                VariantValue::Binary(name.as_bytes().to_vec())
+            );
+            assert_eq!(hit.fields[1], VariantValue::Float((hit.doc % 10) as f32));
+            assert!((hit.score - (hit.doc % 10) as f32).abs() < ::std::f32::EPSILON);
+        }
+    }
+
+    #[test]
+    fn test_sort_by_string_price_score() {
+        let reader = build_index();
+        let sort = || Sort::parse("name:asc:last,price:long:desc:first,_score").unwrap();
+
+        // a stable sort of the docs in id order: the missing names last, the
+        // missing prices first, then higher prices and higher scores
+        let mut expected: Vec<DocId> = (0..30).collect();
+        expected.sort_by(|&d1, &d2| {
+            let (n1, n2) = (name(d1), name(d2));
+            n1.is_none()
+                .cmp(&n2.is_none())
+                .then(n1.cmp(&n2))
+                .then(price(d1).is_some().cmp(&price(d2).is_some()))
+                .then(price(d2).cmp(&price(d1)))
+                .then((d2 % 10).cmp(&(d1 % 10)))
+        });
+
+        let mut collector = TopFieldCollector::new(sort(), 12, true).unwrap();
+        collect_all(&reader, &mut collector);
+        let hits = field_docs(&collector.top_docs());
+        assert_eq!(doc_ids(&hits), &expected[..12]);
+        for hit in &hits {
+            let name = name(hit.doc).unwrap_or("");
+            let price = price(hit.doc).unwrap_or(i64::max_value());
+            assert_eq!(
+                hit.fields,
+                vec![
+                    VariantValue::Binary(name.as_bytes().to_vec()),
+                    VariantValue::Long(price),
+                    VariantValue::Float((hit.doc % 10) as f32),
+                ]
+            );
+        }
+
+        for &parallel in &[false, true] {
+            let mut docs = vec![];
+            let mut after: Option<FieldDoc> = None;
+            loop {
+                let mut collector = TopFieldCollector::new(sort(), 7, true).unwrap();
+                if let Some(after) = after.take() {
+                    collector = collector.with_after(after).unwrap();
+                }
+                if parallel {
+                    collect_parallel(&reader, &mut collector);
+                } else {
+                    collect_all(&reader, &mut collector);
+                }
+                let hits = field_docs(&collector.top_docs());
+                if hits.is_empty() {
+                    break;
+                }
+                after = hits.last().cloned();
+                docs.extend(doc_ids(&hits));
+            }
+            assert_eq!(docs, expected);
+        }
+    }
+
+    #[test]
+    fn test_total_hits_threshold() {
+        let reader = build_index();
+        let mut collector = TopFieldCollector::new(price_sort(false), 5, false)
+            .unwrap()
+            .with_total_hits_threshold(10);
+        collect_all(&reader, &mut collector);
+        let top_docs = collector.top_docs();
+        assert!(top_docs.total_hits() >= 10);
+        assert_eq!(
+            top_docs.total_hits_relation(),
+            TotalHitsRelation::GreaterThanOrEqualTo
+        );
+
+        let mut collector = TopDocsCollector::new(5).with_total_hits_threshold(30);
+        collect_all(&reader, &mut collector);
+        let top_docs = collector.top_docs();
+        assert_eq!(top_docs.total_hits(), 30);
+        assert_eq!(top_docs.total_hits_relation(), TotalHitsRelation::EqualTo);
+    }
+
+    #[test]
+    fn test_top_score_docs_pages() {
+        let reader = build_index();
+        // the scores tie across the segments, the ties are ordered by doc id
+        let mut expected: Vec<DocId> = (0..30).collect();
+        expected.sort_by(|&d1, &d2| (d2 % 10).cmp(&(d1 % 10)).then(d1.cmp(&d2)));
+
+        for &parallel in &[false, true] {
+            let mut docs = vec![];
+            let mut after: Option<ScoreDoc> = None;
+            loop {
+                let mut collector = TopDocsCollector::new(4);
+                if let Some(after) = after.take() {
+                    collector = collector.with_after(after);
+                }
+                if parallel {
+                    collect_parallel(&reader, &mut collector);
+                } else {
+                    collect_all(&reader, &mut collector);
+                }
+                let top_docs = collector.top_docs();
+                let hits = top_docs.score_docs();
+                if hits.is_empty() {
+                    break;
+                }
+                docs.extend(hits.iter().map(|hit| hit.doc_id()));
+                let last = hits.last().unwrap();
+                after = Some(ScoreDoc::new(last.doc_id(), last.score()));
+            }
+            assert_eq!(docs, expected);
+        }
+    }
+    #[test]
+    fn test_early_termination_by_index_sort() {
+        let reader = build_sorted_index();
+        assert_eq!(reader.leaves().len(), 3);
+        let searcher = DefaultIndexSearcher::new(Arc::clone(&reader));
+
+        let mut collector = TopFieldCollector::new(sorted_price_sort(), 5, false).unwrap();
+        searcher.search(&MatchAllDocsQuery, &mut collector).unwrap();
+        let exhaustive = collector.top_docs();
+        assert_eq!(exhaustive.total_hits(), 30);
+
+        let mut collector = TopFieldCollector::new(sorted_price_sort(), 5, false)
+            .unwrap()
+            .with_total_hits_threshold(5);
+        searcher.search(&MatchAllDocsQuery, &mut collector).unwrap();
+        let top_docs = collector.top_docs();
+        assert_eq!(field_docs(&top_docs), field_docs(&exhaustive));
+        assert!(top_docs.total_hits() < 30);
+        assert_eq!(
+            top_docs.total_hits_relation(),
+            TotalHitsRelation::GreaterThanOrEqualTo
+        );
+
+        // the leaves aren't sorted by the reversed sort
+        let mut collector = TopFieldCollector::new(price_sort(true), 5, false)
+            .unwrap()
+            .with_total_hits_threshold(5);
+        searcher.search(&MatchAllDocsQuery, &mut collector).unwrap();
+        assert_eq!(collector.top_docs().total_hits(), 30);
+    }
+
+    #[test]
+    fn test_early_termination_by_doc_id() {
+        let reader = build_index();
+        let searcher = DefaultIndexSearcher::new(Arc::clone(&reader));
+        let doc_field = SimpleSortField::new(String::new(), SortFieldType::Doc, false);
+        let sort = Sort::new(vec![SortField::Simple(doc_field)]);
+
+        let mut collector = TopFieldCollector::new(sort.clone(), 5, false)
+            .unwrap()
+            .with_total_hits_threshold(5);
+        searcher.search(&MatchAllDocsQuery, &mut collector).unwrap();
+        let top_docs = collector.top_docs();
+        assert_eq!(doc_ids(&field_docs(&top_docs)), vec![0, 1, 2, 3, 4]);
+        // the first doc that can't compete in every segment is counted
+        assert_eq!(top_docs.total_hits(), 8);
+        assert_eq!(
+            top_docs.total_hits_relation(),
+            TotalHitsRelation::GreaterThanOrEqualTo
+        );
+
+        let mut collector = TopFieldCollector::new(sort, 5, false).unwrap();
+        searcher.search(&MatchAllDocsQuery, &mut collector).unwrap();
+        let top_docs = collector.top_docs();
+        assert_eq!(doc_ids(&field_docs(&top_docs)), vec![0, 1, 2, 3, 4]);
+        assert_eq!(top_docs.total_hits(), 30);
+    }
+}
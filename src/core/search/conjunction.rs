@@ -11,46 +11,78 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use core::search::scorer_arena::PooledVec;
 use core::search::{two_phase_next, DocIterator, Scorer, NO_MORE_DOCS};
 use core::util::DocId;
 use error::Result;
 
+use std::cmp::Ordering;
+
 pub struct ConjunctionScorer<T: Scorer> {
     lead1: T,
     lead2: T,
-    others: Vec<T>,
-    support_two_phase: bool,
+    others: PooledVec<T>,
+    // the children supporting two phase iteration, as indexes in
+    // `[lead1, lead2, others..]`, by ascending match cost
+    two_phase_children: PooledVec<usize>,
     two_phase_match_cost: f32,
 }
 
 impl<T: Scorer> ConjunctionScorer<T> {
-    pub fn new(mut children: Vec<T>) -> ConjunctionScorer<T> {
+    pub fn new(children: Vec<T>) -> ConjunctionScorer<T> {
+        Self::with_buffers(children.into(), PooledVec::default())
+    }
+
+    /// Creates the conjunction of `children`, keeping the children after the
+    /// two leading ones in its buffer and the indexes of the two phase children
+    /// in `two_phase_children`, which must be empty.
+    pub fn with_buffers(
+        mut children: PooledVec<T>,
+        mut two_phase_children: PooledVec<usize>,
+    ) -> ConjunctionScorer<T> {
         assert!(children.len() >= 2);
+        debug_assert!(two_phase_children.is_empty());
 
         // Sort the scores the first time to allow the least cost DocIterator to
-        // lead the matching.
-        let support_two_phase = children.iter().any(|scorer| scorer.support_two_phase());
-        let two_phase_match_cost = if support_two_phase {
-            children.iter().map(|s| s.match_cost()).sum()
-        } else {
-            0f32
-        };
-        children.sort_by(|a, b| a.cost().cmp(&b.cost()));
-
-        let others = children.drain(2..).collect();
-
-        let lead2 = children.remove(1);
+        // lead the matching. The cost of a two phase child is the one of its
+        // approximation, at equal costs the children without verification
+        // lead.
+        children.sort_by_key(|child| (child.cost(), child.support_two_phase()));
+
+        // and verify the docs agreed on by all the approximations with the
+        // cheapest two phase children first
+        two_phase_children.extend((0..children.len()).filter(|&i| children[i].support_two_phase()));
+        two_phase_children.sort_by(|&a, &b| {
+            children[a]
+                .match_cost()
+                .partial_cmp(&children[b].match_cost())
+                .unwrap_or(Ordering::Equal)
+        });
+        let two_phase_match_cost = two_phase_children
+            .iter()
+            .map(|&i| children[i].match_cost())
+            .sum();
+
         let lead1 = children.remove(0);
+        let lead2 = children.remove(0);
 
         ConjunctionScorer {
             lead1,
             lead2,
-            others,
-            support_two_phase,
+            others: children,
+            two_phase_children,
             two_phase_match_cost,
         }
     }
 
+    fn child_mut(&mut self, index: usize) -> &mut T {
+        match index {
+            0 => &mut self.lead1,
+            1 => &mut self.lead2,
+            _ => &mut self.others[index - 2],
+        }
+    }
+
     fn skip_to_approx(&mut self, target: DocId) -> Result<DocId> {
         let mut doc = target;
 
@@ -104,7 +136,7 @@ impl<T: Scorer> Scorer for ConjunctionScorer<T> {
         Ok(score)
     }
     fn support_two_phase(&self) -> bool {
-        self.support_two_phase
+        !self.two_phase_children.is_empty()
     }
 }
 
@@ -128,20 +160,13 @@ impl<T: Scorer> DocIterator for ConjunctionScorer<T> {
     }
 
     fn matches(&mut self) -> Result<bool> {
-        if !self.support_two_phase {
-            Ok(true)
-        } else if !self.lead1.matches()? || !self.lead2.matches()? {
-            Ok(false)
-        } else {
-            let mut res = true;
-            for s in &mut self.others {
-                if !s.matches()? {
-                    res = false;
-                    break;
-                }
+        for i in 0..self.two_phase_children.len() {
+            let child = self.two_phase_children[i];
+            if !self.child_mut(child).matches()? {
+                return Ok(false);
             }
-            Ok(res)
         }
+        Ok(true)
     }
 
     fn match_cost(&self) -> f32 {
@@ -165,6 +190,8 @@ mod tests {
     use super::*;
     use core::search::tests::*;
 
+    use std::sync::atomic::Ordering as AtomicOrdering;
+
     #[test]
     fn test_mock_doc_iterator_next() {
         let mut it = MockDocIterator::new(vec![1, 2, 3, 4, 5]);
@@ -286,6 +313,35 @@ mod tests {
         assert_eq!(scorer.doc_id(), NO_MORE_DOCS);
     }
 
+    #[test]
+    fn test_conjunction_verification_order() {
+        let lead = create_mock_scorer(vec![2, 3, 4, 5, 6]);
+        let costly = MatchRecordingScorer::new(
+            create_mock_two_phase_scorer(vec![1, 2, 3, 4, 5, 6, 7], vec![5]),
+            100.0,
+        );
+        let cheap = MatchRecordingScorer::new(
+            create_mock_two_phase_scorer(vec![1, 2, 3, 4, 5, 6, 7], vec![2, 4]),
+            1.0,
+        );
+        let costly_docs = costly.verified_docs();
+        let cheap_docs = cheap.verified_docs();
+
+        let scorers: Vec<Box<dyn Scorer>> = vec![Box::new(costly), Box::new(lead), Box::new(cheap)];
+        let mut scorer = ConjunctionScorer::new(scorers);
+        assert!(scorer.support_two_phase());
+        assert!((scorer.match_cost() - 101.0).abs() < ::std::f32::EPSILON);
+
+        let mut docs = vec![];
+        while scorer.next().unwrap() != NO_MORE_DOCS {
+            docs.push(scorer.doc_id());
+        }
+        assert_eq!(docs, vec![3, 6]);
+        // the costly scorer only verifies the docs the cheap one accepts
+        assert_eq!(*cheap_docs.lock().unwrap(), vec![2, 3, 4, 5, 6]);
+        assert_eq!(*costly_docs.lock().unwrap(), vec![3, 5, 6]);
+    }
+
     fn create_conjunction_scorer() -> ConjunctionScorer<MockSimpleScorer<MockDocIterator>> {
         let s1 = create_mock_scorer(vec![1, 2, 3, 4, 5]);
         let s2 = create_mock_scorer(vec![2, 5]);
@@ -304,4 +360,44 @@ mod tests {
             vec![Box::new(s1), Box::new(s2), Box::new(s3), Box::new(s4)];
         ConjunctionScorer::new(scorers)
     }
+
+    #[test]
+    fn test_conjunction_lead_by_cost() {
+        let dense = MatchRecordingScorer::new(create_mock_scorer((0..20).collect()), 0.0);
+        let sparse = MatchRecordingScorer::new(create_mock_scorer(vec![3, 8, 15]), 0.0);
+        let medium = MatchRecordingScorer::new(create_mock_scorer(vec![1, 3, 5, 8, 13, 15]), 0.0);
+        let dense_calls = dense.next_calls();
+        let sparse_calls = sparse.next_calls();
+        let medium_calls = medium.next_calls();
+
+        let mut scorer = ConjunctionScorer::new(vec![dense, medium, sparse]);
+        assert_eq!(scorer.cost(), 3);
+        let mut docs = vec![];
+        while scorer.next().unwrap() != NO_MORE_DOCS {
+            docs.push(scorer.doc_id());
+        }
+        assert_eq!(docs, vec![3, 8, 15]);
+        // only the sparsest child is moved on by itself
+        assert_eq!(sparse_calls.load(AtomicOrdering::Relaxed), 4);
+        assert_eq!(medium_calls.load(AtomicOrdering::Relaxed), 0);
+        assert_eq!(dense_calls.load(AtomicOrdering::Relaxed), 0);
+
+        // at equal costs the child needing no verification leads
+        let two_phase = MatchRecordingScorer::new(
+            Box::new(create_mock_two_phase_scorer(vec![1, 2, 3, 4], vec![2])) as Box<dyn Scorer>,
+            1.0,
+        );
+        let exact = MatchRecordingScorer::new(
+            Box::new(create_mock_scorer(vec![1, 2, 3, 5])) as Box<dyn Scorer>,
+            0.0,
+        );
+        let two_phase_calls = two_phase.next_calls();
+        let exact_calls = exact.next_calls();
+        let mut scorer = ConjunctionScorer::new(vec![two_phase, exact]);
+        assert_eq!(scorer.next().unwrap(), 1);
+        assert_eq!(scorer.next().unwrap(), 3);
+        assert_eq!(scorer.next().unwrap(), NO_MORE_DOCS);
+        assert_eq!(two_phase_calls.load(AtomicOrdering::Relaxed), 0);
+        assert!(exact_calls.load(AtomicOrdering::Relaxed) > 0);
+    }
 }
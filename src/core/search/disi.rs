@@ -11,7 +11,9 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use core::search::scorer_arena::PooledVec;
 use core::search::DocIterator;
+use core::util::priority_queue::PriorityQueue;
 use core::util::DocId;
 use error::Result;
 
@@ -132,49 +134,50 @@ impl<T: DocIterator> PartialOrd for DisiWrapper<T> {
     }
 }
 
+type DocLessThan<T> = fn(&*mut DisiWrapper<T>, &*mut DisiWrapper<T>) -> bool;
+
+fn doc_less_than<T: DocIterator>(a: &*mut DisiWrapper<T>, b: &*mut DisiWrapper<T>) -> bool {
+    unsafe { (**a).doc() < (**b).doc() }
+}
+
+/// A `PriorityQueue` of `DisiWrapper`s keyed by their current doc, which can
+/// also link the wrappers on the least doc together, see `top_list`.
 pub struct DisiPriorityQueue<T: DocIterator> {
-    heap: Vec<*mut DisiWrapper<T>>,
-    size: isize,
-    _buffer: Vec<DisiWrapper<T>>,
+    queue: PriorityQueue<*mut DisiWrapper<T>, DocLessThan<T>, PooledVec<*mut DisiWrapper<T>>>,
+    _buffer: PooledVec<DisiWrapper<T>>,
 }
 
 unsafe impl<T: DocIterator> Send for DisiPriorityQueue<T> {}
 
 impl<T: DocIterator> DisiPriorityQueue<T> {
-    fn left_node(node: isize) -> isize {
-        ((node + 1) << 1) - 1
-    }
-
-    fn right_node(left_node: isize) -> isize {
-        left_node + 1
-    }
-
-    fn parent_node(node: isize) -> isize {
-        ((node + 1) >> 1) - 1
-    }
-
     pub fn new(children: Vec<T>) -> DisiPriorityQueue<T> {
-        let mut _buffer: Vec<DisiWrapper<T>> = children.into_iter().map(DisiWrapper::new).collect();
-        let children: Vec<*mut DisiWrapper<T>> = _buffer
-            .iter_mut()
-            .map(|x| x as *mut DisiWrapper<T>)
-            .collect();
+        Self::with_buffers(children.into(), PooledVec::default(), PooledVec::default())
+    }
+
+    /// Creates the queue of `children` in the empty buffers `wrappers` and `heap`.
+    pub fn with_buffers(
+        mut children: PooledVec<T>,
+        mut wrappers: PooledVec<DisiWrapper<T>>,
+        heap: PooledVec<*mut DisiWrapper<T>>,
+    ) -> DisiPriorityQueue<T> {
+        debug_assert!(wrappers.is_empty() && heap.is_empty());
+        let len = children.len();
+        wrappers.extend(children.drain(..).map(DisiWrapper::new));
         let mut queue = DisiPriorityQueue {
-            heap: vec![ptr::null_mut(); children.len()],
-            size: 0,
-            _buffer,
+            queue: PriorityQueue::with_heap(heap, len, doc_less_than::<T> as DocLessThan<T>),
+            _buffer: wrappers,
         };
 
-        for disi in children {
-            unsafe {
-                queue.do_push(disi);
-            }
+        // the wrappers don't move anymore, the heap points to them
+        for i in 0..len {
+            let disi = &mut queue._buffer[i] as *mut DisiWrapper<T>;
+            queue.queue.add(disi);
         }
         queue
     }
 
     pub fn size(&self) -> usize {
-        self.size as usize
+        self.queue.len()
     }
 
     /// Get the list of scorers which are on the current doc.
@@ -184,13 +187,13 @@ impl<T: DocIterator> DisiPriorityQueue<T> {
     }
 
     unsafe fn do_top_list(&self) -> *mut DisiWrapper<T> {
-        let heap = &self.heap;
-        let size = self.size;
+        let heap = self.queue.as_slice();
+        let size = heap.len();
         let mut list = heap[0];
         (*list).next = ptr::null_mut();
         if size >= 3 {
-            list = Self::top_list_to(list, heap, size, 1);
-            list = Self::top_list_to(list, heap, size, 2);
+            list = Self::top_list_to(list, heap, 1);
+            list = Self::top_list_to(list, heap, 2);
         } else if size == 2 && (*heap[1]).doc() == (*list).doc() {
             list = Self::prepend(heap[1], list);
         }
@@ -202,61 +205,39 @@ impl<T: DocIterator> DisiPriorityQueue<T> {
         w1
     }
 
+    // the heap order guarantees the sub trees of a wrapper on another doc
+    // than the top have no wrapper on the top doc either
     unsafe fn top_list_to(
         mut list: *mut DisiWrapper<T>,
         heap: &[*mut DisiWrapper<T>],
-        size: isize,
-        i: isize,
+        i: usize,
     ) -> *mut DisiWrapper<T> {
-        let w = heap[i as usize];
+        let w = heap[i];
         if (*w).doc() == (*list).doc() {
             list = Self::prepend(w, list);
-            let left = Self::left_node(i);
+            let left = (i << 1) + 1;
             let right = left + 1;
-            if right < size {
-                list = Self::top_list_to(list, heap, size, left);
-                list = Self::top_list_to(list, heap, size, right);
-            } else if left < size && (*heap[left as usize]).doc() == (*list).doc() {
-                list = Self::prepend(heap[left as usize], list);
+            if right < heap.len() {
+                list = Self::top_list_to(list, heap, left);
+                list = Self::top_list_to(list, heap, right);
+            } else if left < heap.len() && (*heap[left]).doc() == (*list).doc() {
+                list = Self::prepend(heap[left], list);
             }
         }
         list
     }
 
     pub fn push(&mut self, entry: &mut DisiWrapper<T>) {
-        unsafe {
-            self.do_push(entry as *mut DisiWrapper<T>);
-        }
-    }
-
-    unsafe fn do_push(&mut self, entry: *mut DisiWrapper<T>) -> *mut DisiWrapper<T> {
-        self.heap[self.size as usize] = entry;
-        let size = self.size;
-        self.up_heap(size);
-        self.size += 1;
-        self.heap[0]
+        self.queue.add(entry as *mut DisiWrapper<T>);
     }
 
     pub fn pop(&mut self) -> &'static mut DisiWrapper<T> {
-        unsafe { &mut *self.do_pop() }
-    }
-
-    unsafe fn do_pop(&mut self) -> *mut DisiWrapper<T> {
-        let result = self.heap[0];
-        self.size -= 1;
-        let size = self.size;
-        self.heap[0] = self.heap[size as usize];
-        self.heap[size as usize] = ptr::null_mut();
-        self.down_heap(size);
-        result
+        unsafe { &mut *self.queue.pop().unwrap() }
     }
 
     #[allow(mut_from_ref)]
     pub fn peek(&self) -> &mut DisiWrapper<T> {
-        unsafe {
-            let result = self.heap[0];
-            &mut *result
-        }
+        unsafe { &mut **self.queue.top() }
     }
 
     pub fn peek_mut(&mut self) -> PeekMut<T> {
@@ -268,68 +249,16 @@ impl<T: DocIterator> DisiPriorityQueue<T> {
     }
 
     pub fn update_top(&mut self) -> &mut DisiWrapper<T> {
-        unsafe { &mut *self.do_update_top() }
-    }
-
-    unsafe fn do_update_top(&mut self) -> *mut DisiWrapper<T> {
-        let size = self.size;
-        self.down_heap(size);
-        self.heap[0]
+        unsafe { &mut **self.queue.update_top() }
     }
 
     pub fn update_top_with(&mut self, top_replacement: &mut DisiWrapper<T>) -> &mut DisiWrapper<T> {
-        unsafe { &mut *self.do_update_top_with(top_replacement as *mut DisiWrapper<T>) }
-    }
-
-    unsafe fn do_update_top_with(
-        &mut self,
-        top_replacement: *mut DisiWrapper<T>,
-    ) -> *mut DisiWrapper<T> {
-        self.heap[0] = top_replacement;
+        *self.queue.top_mut() = top_replacement as *mut DisiWrapper<T>;
         self.update_top()
     }
 
-    unsafe fn up_heap(&mut self, mut i: isize) {
-        let node = self.heap[i as usize];
-        let node_doc = (*node).doc();
-        let mut j = Self::parent_node(i);
-        while j >= 0 && node_doc < (*self.heap[j as usize]).doc() {
-            self.heap[i as usize] = self.heap[j as usize];
-            i = j;
-            j = Self::parent_node(j);
-        }
-        self.heap[i as usize] = node;
-    }
-
-    unsafe fn down_heap(&mut self, size: isize) {
-        let mut i = 0;
-        let node = self.heap[0];
-        let mut j = Self::left_node(i);
-        if j < self.size {
-            let mut k = Self::right_node(j);
-            if k < size && (*self.heap[k as usize]).doc() < (*self.heap[j as usize]).doc() {
-                j = k;
-            }
-            if (*self.heap[j as usize]).doc() < (*node).doc() {
-                loop {
-                    self.heap[i as usize] = self.heap[j as usize];
-                    i = j;
-                    j = Self::left_node(i);
-                    k = Self::right_node(j);
-                    if k < size && (*self.heap[k as usize]).doc() < (*self.heap[j as usize]).doc() {
-                        j = k;
-                    }
-                    if j >= size || (*self.heap[j as usize]).doc() >= (*node).doc() {
-                        break;
-                    }
-                }
-                self.heap[i as usize] = node;
-            }
-        }
-    }
-
     pub fn is_empty(&self) -> bool {
-        self.size == 0
+        self.queue.is_empty()
     }
 }
 
@@ -385,13 +314,13 @@ impl<'a, T: 'a + DocIterator> Drop for PeekMut<'a, T> {
 impl<'a, T: DocIterator> Deref for PeekMut<'a, T> {
     type Target = DisiWrapper<T>;
     fn deref(&self) -> &DisiWrapper<T> {
-        unsafe { &*self.heap.heap[0] }
+        unsafe { &**self.heap.queue.top() }
     }
 }
 
 impl<'a, T: DocIterator> DerefMut for PeekMut<'a, T> {
     fn deref_mut(&mut self) -> &mut DisiWrapper<T> {
-        unsafe { &mut *self.heap.heap[0] }
+        unsafe { &mut **self.heap.queue.top_mut() }
     }
 }
 
@@ -403,3 +332,57 @@ impl<'a, T: DocIterator> PeekMut<'a, T> {
         value
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::search::NO_MORE_DOCS;
+    use core::util::doc_id_set::IntArrayDocIterator;
+
+    use std::sync::Arc;
+
+    fn iterator(docs: &[DocId]) -> IntArrayDocIterator {
+        let mut docs = docs.to_vec();
+        let length = docs.len();
+        docs.push(NO_MORE_DOCS);
+        IntArrayDocIterator::new(Arc::new(docs), length)
+    }
+
+    #[test]
+    fn test_top_list() {
+        let mut queue = DisiPriorityQueue::new(vec![
+            iterator(&[1, 4, 7]),
+            iterator(&[1, 2, 7]),
+            iterator(&[4, 7]),
+            iterator(&[7, 9]),
+            iterator(&[]),
+        ]);
+        assert_eq!(queue.size(), 5);
+
+        let mut union = vec![];
+        loop {
+            let doc = queue.peek().doc();
+            if doc == NO_MORE_DOCS {
+                break;
+            }
+            let mut matches = 1;
+            let mut w: &DisiWrapper<_> = queue.top_list();
+            while let Some(next) = w.next_scorer() {
+                assert_eq!(next.doc(), doc);
+                matches += 1;
+                w = next;
+            }
+            union.push((doc, matches));
+            while queue.peek().doc() == doc {
+                queue.peek_mut().next_doc().unwrap();
+            }
+        }
+        assert_eq!(union, vec![(-1, 5), (1, 2), (2, 1), (4, 2), (7, 4), (9, 1)]);
+
+        let top = PeekMut::pop(queue.peek_mut());
+        assert_eq!(top.doc(), NO_MORE_DOCS);
+        assert_eq!(queue.size(), 4);
+        queue.push(top);
+        assert_eq!(queue.size(), 5);
+    }
+}
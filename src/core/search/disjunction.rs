@@ -15,6 +15,7 @@ use core::codec::Codec;
 use core::index::LeafReaderContext;
 use core::search::disi::*;
 use core::search::explanation::Explanation;
+use core::search::scorer_arena::PooledVec;
 use core::search::searcher::SearchPlanBuilder;
 use core::search::term_query::TermQuery;
 use core::search::{two_phase_next, DocIterator, Query, Scorer, Weight};
@@ -34,6 +35,16 @@ pub struct DisjunctionSumScorer<T: Scorer> {
 
 impl<T: Scorer> DisjunctionSumScorer<T> {
     pub fn new(children: Vec<T>) -> DisjunctionSumScorer<T> {
+        Self::with_buffers(children.into(), PooledVec::default(), PooledVec::default())
+    }
+
+    /// Creates the disjunction of `children`, with its queue in the empty
+    /// buffers `wrappers` and `heap`.
+    pub fn with_buffers(
+        children: PooledVec<T>,
+        wrappers: PooledVec<DisiWrapper<T>>,
+        heap: PooledVec<*mut DisiWrapper<T>>,
+    ) -> DisjunctionSumScorer<T> {
         assert!(children.len() > 1);
 
         let cost = children.iter().map(|w| w.cost()).sum();
@@ -45,7 +56,7 @@ impl<T: Scorer> DisjunctionSumScorer<T> {
             0f32
         };
         DisjunctionSumScorer {
-            sub_scorers: DisiPriorityQueue::new(children),
+            sub_scorers: DisiPriorityQueue::with_buffers(children, wrappers, heap),
             cost,
             support_two_phase,
             two_phase_match_cost,
@@ -363,30 +374,27 @@ impl<C: Codec> Weight<C> for DisjunctionMaxWeight<C> {
                 matched = true;
                 sum += e.value();
                 max = e.value().max(max);
-                subs.push(e);
             }
+            subs.push(e);
         }
 
         if matched {
             let score = max + (sum - max) * self.tie_breaker_multiplier;
             let desc = if self.tie_breaker_multiplier == 0.0f32 {
-                "max of:"
+                "max of:".to_string()
             } else {
-                "max plus "
+                format!("max plus {} times others of:", self.tie_breaker_multiplier)
             };
+            // the clauses that don't match don't contribute to the score
+            subs.retain(|e| e.is_match());
 
-            Ok(Explanation::new(
-                true,
-                score,
-                format!("{} {} times others of:", desc, self.tie_breaker_multiplier),
-                subs,
-            ))
+            Ok(Explanation::new(true, score, desc, subs))
         } else {
             Ok(Explanation::new(
                 false,
                 0.0f32,
                 "No matching clause".to_string(),
-                vec![],
+                subs,
             ))
         }
     }
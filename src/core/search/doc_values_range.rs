@@ -0,0 +1,307 @@
+// Copyright 2019 Zhizhesihai (Beijing) Technology Limited.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use core::codec::Codec;
+use core::index::{DocValuesType, LeafReaderContext, NumericDocValuesRef};
+use core::search::explanation::Explanation;
+use core::search::match_all::{ConstantScore, ConstantScoreScorer};
+use core::search::searcher::SearchPlanBuilder;
+use core::search::term_query::TermQuery;
+use core::search::{DocIterator, Query, Scorer, Weight, NO_MORE_DOCS};
+use core::util::{BitsRef, DocId};
+
+use error::ErrorKind::IllegalArgument;
+use error::Result;
+
+use std::fmt;
+
+pub const NUMERIC_DOC_VALUES_RANGE: &str = "numeric_doc_values_range";
+
+/// Matches the docs whose numeric doc value is between `lower` and `upper`
+/// (inclusive), with a constant score.
+///
+/// The scorer checks the value of every doc of the segment, so this is slow
+/// as a lead iterator. It supports two phase iteration and is meant to verify
+/// the docs matched by other clauses, or to be paired with a points query in
+/// an `IndexOrDocValuesQuery`.
+#[derive(Clone, Debug)]
+pub struct NumericDocValuesRangeQuery {
+    field: String,
+    lower: i64,
+    upper: i64,
+}
+
+impl NumericDocValuesRangeQuery {
+    pub fn new(field: String, lower: i64, upper: i64) -> NumericDocValuesRangeQuery {
+        NumericDocValuesRangeQuery {
+            field,
+            lower,
+            upper,
+        }
+    }
+
+    /// Matches the docs whose value is `value`.
+    pub fn new_exact(field: String, value: i64) -> NumericDocValuesRangeQuery {
+        Self::new(field, value, value)
+    }
+
+    pub fn field(&self) -> &str {
+        &self.field
+    }
+
+    pub fn lower(&self) -> i64 {
+        self.lower
+    }
+
+    pub fn upper(&self) -> i64 {
+        self.upper
+    }
+}
+
+impl<C: Codec> Query<C> for NumericDocValuesRangeQuery {
+    fn create_weight(
+        &self,
+        _searcher: &dyn SearchPlanBuilder<C>,
+        _needs_scores: bool,
+    ) -> Result<Box<dyn Weight<C>>> {
+        Ok(Box::new(NumericDocValuesRangeWeight {
+            query: self.clone(),
+            score: ConstantScore::default(),
+        }))
+    }
+
+    fn extract_terms(&self) -> Vec<TermQuery> {
+        vec![]
+    }
+
+    fn query_type(&self) -> &'static str {
+        NUMERIC_DOC_VALUES_RANGE
+    }
+
+    fn as_any(&self) -> &::std::any::Any {
+        self
+    }
+}
+
+impl fmt::Display for NumericDocValuesRangeQuery {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "NumericDocValuesRangeQuery(field: {}, lower: {}, upper: {})",
+            &self.field, self.lower, self.upper
+        )
+    }
+}
+
+struct NumericDocValuesRangeWeight {
+    query: NumericDocValuesRangeQuery,
+    score: ConstantScore,
+}
+
+impl<C: Codec> Weight<C> for NumericDocValuesRangeWeight {
+    fn create_scorer(
+        &self,
+        leaf_reader_ctx: &LeafReaderContext<'_, C>,
+    ) -> Result<Option<Box<dyn Scorer>>> {
+        let reader = leaf_reader_ctx.reader;
+        match reader.field_info(&self.query.field) {
+            Some(fi) if fi.doc_values_type == DocValuesType::Numeric => {}
+            Some(fi) if fi.doc_values_type != DocValuesType::Null => {
+                bail!(IllegalArgument(format!(
+                    "field '{}' has doc values of type {:?}, not numeric",
+                    &self.query.field, fi.doc_values_type
+                )));
+            }
+            _ => return Ok(None),
+        }
+        if self.query.lower > self.query.upper {
+            return Ok(None);
+        }
+        let max_doc = reader.max_doc();
+        let iterator = DocValuesRangeIterator {
+            values: reader.get_numeric_doc_values(&self.query.field)?,
+            docs_with_field: reader.get_docs_with_field(&self.query.field)?,
+            lower: self.query.lower,
+            upper: self.query.upper,
+            doc: -1,
+            max_doc,
+        };
+        Ok(Some(Box::new(ConstantScoreScorer::new_two_phase(
+            self.score.score(),
+            iterator,
+            max_doc as usize,
+        ))))
+    }
+
+    fn query_type(&self) -> &'static str {
+        NUMERIC_DOC_VALUES_RANGE
+    }
+
+    fn normalize(&mut self, norm: f32, boost: f32) {
+        self.score.normalize(norm, boost);
+    }
+
+    fn value_for_normalization(&self) -> f32 {
+        self.score.value_for_normalization()
+    }
+
+    fn needs_scores(&self) -> bool {
+        false
+    }
+
+    fn explain(&self, reader: &LeafReaderContext<'_, C>, doc: DocId) -> Result<Explanation> {
+        self.score.explain(self, self.create_scorer(reader)?, doc)
+    }
+}
+
+impl fmt::Display for NumericDocValuesRangeWeight {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "NumericDocValuesRangeWeight({})", &self.query)
+    }
+}
+
+/// Approximates the matches with all the docs of the segment, and verifies
+/// the value of each.
+struct DocValuesRangeIterator {
+    values: NumericDocValuesRef,
+    docs_with_field: BitsRef,
+    lower: i64,
+    upper: i64,
+    doc: DocId,
+    max_doc: DocId,
+}
+
+impl DocIterator for DocValuesRangeIterator {
+    fn doc_id(&self) -> DocId {
+        self.doc
+    }
+
+    fn next(&mut self) -> Result<DocId> {
+        let target = self.doc + 1;
+        self.advance(target)
+    }
+
+    fn advance(&mut self, target: DocId) -> Result<DocId> {
+        let mut doc = self.approximate_advance(target)?;
+        while doc != NO_MORE_DOCS && !self.matches()? {
+            doc = self.approximate_next()?;
+        }
+        Ok(doc)
+    }
+
+    fn cost(&self) -> usize {
+        self.max_doc as usize
+    }
+
+    fn matches(&mut self) -> Result<bool> {
+        if !self.docs_with_field.get(self.doc as usize)? {
+            return Ok(false);
+        }
+        let value = self.values.get(self.doc)?;
+        Ok(value >= self.lower && value <= self.upper)
+    }
+
+    fn match_cost(&self) -> f32 {
+        // the docs with field bit and the value
+        2f32
+    }
+
+    fn approximate_next(&mut self) -> Result<DocId> {
+        let target = self.doc + 1;
+        self.approximate_advance(target)
+    }
+
+    fn approximate_advance(&mut self, target: DocId) -> Result<DocId> {
+        self.doc = if target >= self.max_doc {
+            NO_MORE_DOCS
+        } else {
+            target
+        };
+        Ok(self.doc)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::doc::{NumericDocValuesField, SortedNumericDocValuesField};
+    use core::index::tests::{open_test_reader, TestReader};
+    use core::index::{Fieldable, IndexReader};
+    use core::search::searcher::{DefaultIndexSearcher, IndexSearcher};
+
+    use std::sync::Arc;
+
+    // doc i has the value i * 10 - 50, every fifth doc has no value
+    fn open_reader() -> Arc<TestReader> {
+        let docs = (0..20i64)
+            .map(|i| {
+                let mut doc: Vec<Box<dyn Fieldable>> =
+                    vec![Box::new(SortedNumericDocValuesField::new("other", i))];
+                if i % 5 != 4 {
+                    doc.push(Box::new(NumericDocValuesField::new("value", i * 10 - 50)));
+                }
+                doc
+            })
+            .collect();
+        open_test_reader(vec![docs])
+    }
+
+    fn count(reader: &Arc<TestReader>, lower: i64, upper: i64) -> usize {
+        let searcher = DefaultIndexSearcher::new(Arc::clone(reader));
+        let query = NumericDocValuesRangeQuery::new("value".to_string(), lower, upper);
+        searcher.count(&query).unwrap()
+    }
+
+    #[test]
+    fn test_numeric_doc_values_range() {
+        let reader = open_reader();
+        assert_eq!(count(&reader, -50, 140), 16);
+        // 0 is the value of doc 5, not of the docs without value
+        assert_eq!(count(&reader, 0, 0), 1);
+        assert_eq!(count(&reader, -1, 1), 1);
+        assert_eq!(count(&reader, 0, 45), 4);
+        assert_eq!(count(&reader, 50, 0), 0);
+        assert_eq!(count(&reader, 1000, 2000), 0);
+
+        let searcher = DefaultIndexSearcher::new(Arc::clone(&reader));
+        let exact = NumericDocValuesRangeQuery::new_exact("value".to_string(), 20);
+        assert_eq!(searcher.count(&exact).unwrap(), 1);
+        let missing = NumericDocValuesRangeQuery::new("missing".to_string(), 0, 10);
+        assert_eq!(searcher.count(&missing).unwrap(), 0);
+        let wrong_type = NumericDocValuesRangeQuery::new("other".to_string(), 0, 10);
+        assert!(searcher.count(&wrong_type).is_err());
+    }
+
+    #[test]
+    fn test_two_phase() {
+        let reader = open_reader();
+        let searcher = DefaultIndexSearcher::new(Arc::clone(&reader));
+        let query = NumericDocValuesRangeQuery::new("value".to_string(), -10, 20);
+        let weight = searcher.create_weight(&query, true).unwrap();
+        let leaves = reader.leaves();
+        let mut scorer = weight.create_scorer(&leaves[0]).unwrap().unwrap();
+        assert!(scorer.support_two_phase());
+        assert!((scorer.match_cost() - 2.0).abs() < ::std::f32::EPSILON);
+        assert_eq!(scorer.cost(), 20);
+
+        // every doc is a candidate
+        assert_eq!(scorer.approximate_next().unwrap(), 0);
+        assert!(!scorer.matches().unwrap());
+        assert_eq!(scorer.approximate_advance(4).unwrap(), 4);
+        assert!(!scorer.matches().unwrap());
+        assert_eq!(scorer.next().unwrap(), 5);
+        assert_eq!(scorer.next().unwrap(), 6);
+        assert_eq!(scorer.next().unwrap(), 7);
+        assert_eq!(scorer.next().unwrap(), NO_MORE_DOCS);
+    }
+}
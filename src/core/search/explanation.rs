@@ -11,6 +11,10 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use error::Result;
+
+/// Describes the score computation of a document, as a tree of values with the
+/// descriptions of how they are computed from their details.
 #[derive(Serialize, Deserialize)]
 pub struct Explanation {
     is_match: bool,
@@ -76,6 +80,12 @@ impl Explanation {
         return buffer;
     }
 
+    /// Renders the tree as JSON, with the `is_match`, `value`, `description`
+    /// and `details` of every explanation.
+    pub fn to_json(&self) -> Result<String> {
+        Ok(::serde_json::to_string(self)?)
+    }
+
     pub fn clone(&self) -> Explanation {
         let mut details: Vec<Explanation> = vec![];
         for detail in &self.details {
@@ -89,3 +99,210 @@ impl Explanation {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::codec::CodecEnum;
+    use core::doc::{Field, FieldType};
+    use core::index::tests::{open_test_reader, TestReader};
+    use core::index::{IndexOptions, IndexReader, Term};
+    use core::search::boolean_query::BooleanQuery;
+    use core::search::collector::TopDocsCollector;
+    use core::search::disjunction::DisjunctionMaxQuery;
+    use core::search::phrase_query::PhraseQuery;
+    use core::search::searcher::{DefaultIndexSearcher, IndexSearcher};
+    use core::search::term_query::TermQuery;
+    use core::search::Query;
+    use core::util::{DocId, VariantValue};
+
+    use std::sync::Arc;
+
+    fn text(value: &str) -> Field {
+        let mut field_type = FieldType::default();
+        field_type.index_options = IndexOptions::DocsAndFreqsAndPositions;
+        Field::new(
+            "body".to_string(),
+            field_type,
+            Some(VariantValue::VString(value.to_string())),
+            None,
+        )
+    }
+
+    fn term(value: &str) -> Term {
+        Term::new("body".to_string(), value.as_bytes().to_vec())
+    }
+
+    fn term_query(value: &str) -> Box<dyn Query<CodecEnum>> {
+        Box::new(TermQuery::new(term(value), 1.0, None))
+    }
+
+    // two segments
+    fn open_reader() -> Arc<TestReader> {
+        let segments: &[&[&str]] = &[
+            &[
+                "the quick brown fox",
+                "the lazy dog",
+                "quick quick fox jumps over the brown dog",
+            ],
+            &["brown fox", "a fox that is quick and brown"],
+        ];
+        open_test_reader(
+            segments
+                .iter()
+                .map(|docs| docs.iter().map(|body| vec![text(body)]).collect())
+                .collect(),
+        )
+    }
+
+    // explains every doc of the index, checking the values against the scores
+    fn check_explanations<S: IndexSearcher<CodecEnum>>(
+        searcher: &S,
+        query: &dyn Query<CodecEnum>,
+        expected_docs: &[DocId],
+    ) -> Vec<Explanation> {
+        let mut collector = TopDocsCollector::new(10);
+        searcher.search(query, &mut collector).unwrap();
+        let top_docs = collector.top_docs();
+        let mut docs: Vec<DocId> = top_docs.score_docs().iter().map(|d| d.doc_id()).collect();
+        docs.sort();
+        assert_eq!(docs, expected_docs, "{}", query);
+
+        let mut explanations = vec![];
+        for doc in 0..5 {
+            let explanation = searcher.explain(query, doc).unwrap();
+            match top_docs.score_docs().iter().find(|d| d.doc_id() == doc) {
+                Some(hit) => {
+                    assert!(explanation.is_match(), "{}", explanation.to_string(0));
+                    let score = hit.score();
+                    assert!(
+                        (explanation.value() - score).abs() <= 1e-5 * score.max(1.0),
+                        "{} != {}\n{}",
+                        explanation.value(),
+                        score,
+                        explanation.to_string(0)
+                    );
+                }
+                None => {
+                    assert!(!explanation.is_match(), "{}", explanation.to_string(0));
+                    assert_eq!(explanation.value(), 0.0);
+                }
+            }
+            explanations.push(explanation);
+        }
+        explanations
+    }
+
+    #[test]
+    fn test_explain_term_query() {
+        let reader = open_reader();
+        let searcher = DefaultIndexSearcher::new(Arc::clone(&reader));
+        let explanations = check_explanations(&searcher, term_query("fox").as_ref(), &[0, 2, 3, 4]);
+
+        // the score of BM25 is the product of the idf and the tf norm
+        let score = &explanations[0].details()[0];
+        let factors: Vec<String> = score.details().iter().map(|e| e.description()).collect();
+        assert_eq!(factors.len(), 2);
+        assert!(factors[0].starts_with("idf"));
+        assert!(factors[1].starts_with("tfNorm"));
+        let product: f32 = score.details().iter().map(|e| e.value()).product();
+        assert!((product - explanations[0].value()).abs() < 1e-5);
+        assert_eq!(explanations[1].description(), "no matching term");
+    }
+
+    #[test]
+    fn test_explain_boolean_query() {
+        let reader = open_reader();
+        let searcher = DefaultIndexSearcher::new(Arc::clone(&reader));
+
+        let query = BooleanQuery::build(
+            vec![term_query("fox")],
+            vec![term_query("quick"), term_query("dog")],
+            vec![],
+        )
+        .unwrap();
+        let explanations = check_explanations(&searcher, query.as_ref(), &[0, 2, 3, 4]);
+        // doc 2 matches all the clauses, doc 3 only the required one
+        assert_eq!(explanations[2].details().len(), 3);
+        assert_eq!(explanations[3].details().len(), 1);
+        let sum: f32 = explanations[2].details().iter().map(|e| e.value()).sum();
+        assert!((sum - explanations[2].value()).abs() < 1e-5);
+
+        // the failing required clause is identified
+        let query = BooleanQuery::build(vec![term_query("fox"), term_query("dog")], vec![], vec![])
+            .unwrap();
+        let explanations = check_explanations(&searcher, query.as_ref(), &[2]);
+        let failures: Vec<&Explanation> = explanations[0]
+            .details()
+            .iter()
+            .filter(|e| !e.is_match())
+            .collect();
+        assert_eq!(failures.len(), 1);
+        assert!(failures[0]
+            .description()
+            .starts_with("no match on required clause (TermWeight(field: body, term: dog"));
+    }
+
+    #[test]
+    fn test_explain_disjunction_max_query() {
+        let reader = open_reader();
+        let searcher = DefaultIndexSearcher::new(Arc::clone(&reader));
+        for &tie_breaker in &[0.0, 0.3] {
+            let query = DisjunctionMaxQuery::build(
+                vec![term_query("quick"), term_query("lazy")],
+                tie_breaker,
+            )
+            .unwrap();
+            let explanations = check_explanations(&searcher, query.as_ref(), &[0, 1, 2, 4]);
+            let expected = if tie_breaker == 0.0 {
+                "max of:".to_string()
+            } else {
+                "max plus 0.3 times others of:".to_string()
+            };
+            assert_eq!(explanations[0].description(), expected);
+            assert_eq!(explanations[0].details().len(), 1);
+            // the clauses that don't match are explained
+            assert_eq!(explanations[3].details().len(), 2);
+        }
+    }
+
+    #[test]
+    fn test_explain_phrase_query() {
+        let reader = open_reader();
+        let searcher = DefaultIndexSearcher::new(Arc::clone(&reader));
+
+        let query = PhraseQuery::build(vec![term("brown"), term("fox")], 0, None, None).unwrap();
+        let explanations = check_explanations(&searcher, &query, &[0, 3]);
+        assert!(explanations[0].details()[0].details()[1].details()[0]
+            .description()
+            .starts_with("phraseFreq=1"));
+        assert_eq!(explanations[2].description(), "no matching phrase");
+
+        let query = PhraseQuery::build(vec![term("quick"), term("fox")], 2, None, None).unwrap();
+        check_explanations(&searcher, &query, &[0, 2]);
+    }
+
+    #[test]
+    fn test_explanation_to_json() {
+        let explanation = Explanation::new(
+            true,
+            2.0,
+            "product of:".to_string(),
+            vec![
+                Explanation::new(true, 4.0, "idf".to_string(), vec![]),
+                Explanation::new(true, 0.5, "tf".to_string(), vec![]),
+            ],
+        );
+        let json = explanation.to_json().unwrap();
+        let parsed: ::serde_json::Value = ::serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed["value"], 2.0);
+        assert_eq!(parsed["description"], "product of:");
+        assert_eq!(parsed["details"][1]["description"], "tf");
+        assert_eq!(parsed["details"][1]["is_match"], true);
+
+        assert_eq!(
+            explanation.to_string(0),
+            "2 = product of:\n  4 = idf\n  0.5 = tf\n"
+        );
+    }
+}
@@ -0,0 +1,932 @@
+// Copyright 2019 Zhizhesihai (Beijing) Technology Limited.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Facet counting over sorted (set) doc values.
+//!
+//! `SortedSetDocValuesFacetCounts` counts the values of the hits of a query
+//! with the global ords of an `OrdinalMap`, which is cached on the reader (see
+//! `IndexReader::ordinal_map`), so the facet requests on a reader share it.
+//!
+//! `FacetsState` builds the maps of the facet fields when `SearcherManager`
+//! refreshes, so the facet requests don't.
+
+use core::codec::Codec;
+use core::index::{DocValues, OrdinalMap, NO_MORE_ORDS};
+use core::index::{DocValuesType, IndexReader, LeafReader, LeafReaderContext, SearchLeafReader};
+use core::index::{SortedDocValuesRef, SortedSetDocValues, SortedSetDocValuesRef};
+use core::search::collector::{Collector, ParallelLeafCollector, ScoreMode, SearchCollector};
+use core::search::collector::{LeafSender, ParallelChannel};
+use core::search::match_all::MatchAllDocsQuery;
+use core::search::searcher::IndexSearcher;
+use core::search::{Query, Scorer};
+use core::util::{Bits, DocId, LongValues};
+
+use error::ErrorKind::{IllegalArgument, IllegalState};
+use error::Result;
+
+use std::mem;
+use std::sync::Arc;
+
+enum FacetValues {
+    Sorted(SortedDocValuesRef),
+    SortedSet(SortedSetDocValuesRef),
+}
+
+impl FacetValues {
+    fn open<C: Codec>(reader: &SearchLeafReader<C>, field: &str) -> Result<Option<FacetValues>> {
+        let values = match reader.field_info(field).map(|fi| fi.doc_values_type) {
+            Some(DocValuesType::Sorted) => {
+                FacetValues::Sorted(reader.get_sorted_doc_values(field)?)
+            }
+            Some(DocValuesType::SortedSet) => {
+                FacetValues::SortedSet(reader.get_sorted_set_doc_values(field)?)
+            }
+            _ => return Ok(None),
+        };
+        Ok(Some(values))
+    }
+}
+
+/// The facet fields of an index, whose `OrdinalMap`s `SearcherManager`
+/// builds on refresh, rather than the first facet request on a new reader.
+///
+/// The maps are cached on the reader, see `IndexReader::ordinal_map`, and a
+/// reopened reader keeps the maps of the fields whose segments didn't change.
+pub struct FacetsState {
+    fields: Vec<String>,
+}
+
+impl FacetsState {
+    pub fn new(fields: &[&str]) -> FacetsState {
+        FacetsState {
+            fields: fields.iter().map(|f| f.to_string()).collect(),
+        }
+    }
+
+    pub fn fields(&self) -> &[String] {
+        &self.fields
+    }
+
+    /// Builds the maps of the fields over `reader`, unless they are cached.
+    /// The ords of a single leaf are already global, it needs no map.
+    pub fn refresh<R: IndexReader + ?Sized>(&self, reader: &R) -> Result<()> {
+        if reader.leaves().len() <= 1 {
+            return Ok(());
+        }
+        for field in &self.fields {
+            reader.ordinal_map(field)?;
+        }
+        Ok(())
+    }
+
+    /// Counts the values of `field` over the live docs of `reader`, sorted by
+    /// value. `reader` needn't be the last refreshed one.
+    pub fn facet_counts<R: IndexReader + ?Sized>(
+        &self,
+        reader: &R,
+        field: &str,
+    ) -> Result<Vec<(Vec<u8>, u64)>> {
+        if !self.fields.iter().any(|f| f == field) {
+            bail!(IllegalArgument(format!("'{}' isn't a facet field", field)));
+        }
+        let state = SortedSetDocValuesReaderState::new(reader, field)?;
+        let counts = SortedSetDocValuesFacetCounts::count_all(&state, &reader.leaves())?;
+        // global ords are sorted by value
+        let mut facets = vec![];
+        for (global_ord, &count) in counts.counts.iter().enumerate() {
+            if count > 0 {
+                facets.push((state.lookup_global_ord(global_ord as i64)?, count as u64));
+            }
+        }
+        Ok(facets)
+    }
+}
+
+// the sorted set doc values of `field` in the segment, single-valued sorted
+// doc values being sets of one value, `None` if the segment has none
+fn sorted_set_values<C: Codec>(
+    reader: &SearchLeafReader<C>,
+    field: &str,
+) -> Result<Option<SortedSetDocValuesRef>> {
+    let values: SortedSetDocValuesRef = match FacetValues::open(reader, field)? {
+        Some(FacetValues::SortedSet(values)) => values,
+        Some(FacetValues::Sorted(values)) => {
+            Arc::new(DocValues::singleton_sorted_doc_values(values))
+        }
+        None => return Ok(None),
+    };
+    Ok(Some(values))
+}
+
+// adds one to the count of every segment ord of `doc`
+fn count_doc(values: &dyn SortedSetDocValues, doc: DocId, counts: &mut [i32]) -> Result<()> {
+    let mut ctx = values.set_document(doc)?;
+    loop {
+        let ord = values.next_ord(&mut ctx)?;
+        if ord == NO_MORE_ORDS {
+            return Ok(());
+        }
+        counts[ord as usize] += 1;
+    }
+}
+
+/// The global ords of the sorted (set) doc values of a field over all the
+/// segments of a reader, sorted by term.
+///
+/// The `OrdinalMap` of the field is the one of `IndexReader::ordinal_map`,
+/// which the readers opened on an index build once and cache, so a state
+/// built again on the same reader, or on a reader reopened without segment
+/// changes, doesn't read the terms again.
+pub struct SortedSetDocValuesReaderState {
+    field: String,
+    // the core cache keys of the leaves the state was built for
+    core_keys: Vec<String>,
+    // per leaf, `None` where no doc has the field
+    values: Vec<Option<SortedSetDocValuesRef>>,
+    // `None` with a single leaf, whose ords are the global ords
+    ordinal_map: Option<Arc<OrdinalMap>>,
+    value_count: usize,
+}
+
+impl SortedSetDocValuesReaderState {
+    pub fn new<R: IndexReader + ?Sized>(
+        reader: &R,
+        field: &str,
+    ) -> Result<SortedSetDocValuesReaderState> {
+        let leaves = reader.leaves();
+        let mut core_keys = Vec::with_capacity(leaves.len());
+        let mut values = Vec::with_capacity(leaves.len());
+        for leaf in &leaves {
+            core_keys.push(leaf.reader.core_cache_key().to_string());
+            values.push(sorted_set_values(leaf.reader, field)?);
+        }
+
+        let (ordinal_map, value_count) = if values.len() <= 1 {
+            let value_count = values
+                .first()
+                .and_then(|v| v.as_ref())
+                .map_or(0, |v| v.get_value_count());
+            (None, value_count)
+        } else if values.iter().all(Option::is_none) {
+            (None, 0)
+        } else {
+            match reader.ordinal_map(field)? {
+                Some(map) => {
+                    let value_count = map.value_count() as usize;
+                    (Some(map), value_count)
+                }
+                None => (None, 0),
+            }
+        };
+
+        Ok(SortedSetDocValuesReaderState {
+            field: field.to_string(),
+            core_keys,
+            values,
+            ordinal_map,
+            value_count,
+        })
+    }
+
+    pub fn field(&self) -> &str {
+        &self.field
+    }
+
+    /// Number of distinct values of the field in the reader.
+    pub fn value_count(&self) -> usize {
+        self.value_count
+    }
+
+    pub fn lookup_global_ord(&self, global_ord: i64) -> Result<Vec<u8>> {
+        let (segment, ord) = match self.ordinal_map {
+            Some(ref map) => (
+                map.first_segment_number(global_ord) as usize,
+                map.first_segment_ord(global_ord),
+            ),
+            None => (0, global_ord),
+        };
+        match self.values[segment] {
+            Some(ref values) => values.lookup_ord(ord),
+            None => bail!(IllegalState(format!(
+                "no value of '{}' in segment {}",
+                self.field, segment
+            ))),
+        }
+    }
+
+    // fails unless `leaves` are the ones the state was built for
+    fn check_leaves<C: Codec>(&self, leaves: &[LeafReaderContext<'_, C>]) -> Result<()> {
+        let same = leaves.len() == self.core_keys.len()
+            && leaves
+                .iter()
+                .zip(&self.core_keys)
+                .all(|(leaf, key)| leaf.reader.core_cache_key() == key);
+        if !same {
+            bail!(IllegalArgument(
+                "the searcher's reader isn't the one the facets state was built for".into()
+            ));
+        }
+        Ok(())
+    }
+
+    // adds the counts per segment ord of the leaf to the counts per global ord
+    fn add_segment_counts(
+        &self,
+        leaf: usize,
+        segment_counts: &[i32],
+        counts: &mut [i32],
+    ) -> Result<()> {
+        let value_count = self
+            .values
+            .get(leaf)
+            .and_then(|v| v.as_ref())
+            .map_or(0, |v| v.get_value_count());
+        if segment_counts.len() != value_count {
+            bail!(IllegalArgument(format!(
+                "{} counts for the {} values of '{}' in segment {}",
+                segment_counts.len(),
+                value_count,
+                self.field,
+                leaf
+            )));
+        }
+        match self.ordinal_map {
+            Some(ref map) => {
+                let global_ords = map.get_global_ords(leaf);
+                for (ord, &count) in segment_counts.iter().enumerate() {
+                    if count > 0 {
+                        counts[global_ords.get64(ord as i64)? as usize] += count;
+                    }
+                }
+            }
+            None => {
+                for (ord, &count) in segment_counts.iter().enumerate() {
+                    counts[ord] += count;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Collects the values of a sorted (set) doc values field of the hits,
+/// counted per segment ord in an array sized to the value count of the
+/// segment. `SortedSetDocValuesFacetCounts` maps them to global ords.
+pub struct SortedSetDocValuesFacetCollector {
+    field: String,
+    // leaf ord -> counts per segment ord
+    segment_counts: Vec<(usize, Vec<i32>)>,
+    // the values of the current leaf
+    values: Option<SortedSetDocValuesRef>,
+    channel: ParallelChannel<(usize, Vec<i32>)>,
+}
+
+impl SortedSetDocValuesFacetCollector {
+    pub fn new(field: &str) -> SortedSetDocValuesFacetCollector {
+        SortedSetDocValuesFacetCollector {
+            field: field.to_string(),
+            segment_counts: vec![],
+            values: None,
+            channel: ParallelChannel::default(),
+        }
+    }
+
+    pub fn field(&self) -> &str {
+        &self.field
+    }
+}
+
+impl SearchCollector for SortedSetDocValuesFacetCollector {
+    type LC = SortedSetDocValuesFacetLeafCollector;
+
+    fn set_next_reader<C: Codec>(&mut self, reader: &LeafReaderContext<'_, C>) -> Result<()> {
+        self.values = sorted_set_values(reader.reader, &self.field)?;
+        if let Some(ref values) = self.values {
+            self.segment_counts
+                .push((reader.ord, vec![0; values.get_value_count()]));
+        }
+        Ok(())
+    }
+
+    fn support_parallel(&self) -> bool {
+        true
+    }
+
+    fn leaf_collector<C: Codec>(
+        &mut self,
+        reader: &LeafReaderContext<'_, C>,
+    ) -> Result<SortedSetDocValuesFacetLeafCollector> {
+        let values = sorted_set_values(reader.reader, &self.field)?;
+        let counts = values
+            .as_ref()
+            .map_or(vec![], |v| vec![0; v.get_value_count()]);
+        Ok(SortedSetDocValuesFacetLeafCollector {
+            leaf: reader.ord,
+            values,
+            counts,
+            sender: self.channel.sender(),
+        })
+    }
+
+    fn finish_parallel(&mut self) -> Result<()> {
+        self.segment_counts.extend(self.channel.receive());
+        Ok(())
+    }
+}
+
+impl Collector for SortedSetDocValuesFacetCollector {
+    fn score_mode(&self) -> ScoreMode {
+        ScoreMode::CompleteNoScores
+    }
+
+    fn collect<S: Scorer + ?Sized>(&mut self, doc: DocId, _scorer: &mut S) -> Result<()> {
+        if let Some(ref values) = self.values {
+            let counts = &mut self.segment_counts.last_mut().unwrap().1;
+            count_doc(&**values, doc, counts)?;
+        }
+        Ok(())
+    }
+}
+
+pub struct SortedSetDocValuesFacetLeafCollector {
+    leaf: usize,
+    values: Option<SortedSetDocValuesRef>,
+    counts: Vec<i32>,
+    sender: LeafSender<(usize, Vec<i32>)>,
+}
+
+impl Collector for SortedSetDocValuesFacetLeafCollector {
+    fn score_mode(&self) -> ScoreMode {
+        ScoreMode::CompleteNoScores
+    }
+
+    fn collect<S: Scorer + ?Sized>(&mut self, doc: DocId, _scorer: &mut S) -> Result<()> {
+        if let Some(ref values) = self.values {
+            count_doc(&**values, doc, &mut self.counts)?;
+        }
+        Ok(())
+    }
+}
+
+impl ParallelLeafCollector for SortedSetDocValuesFacetLeafCollector {
+    fn finish_leaf(&mut self) -> Result<()> {
+        if self.values.is_none() {
+            return Ok(());
+        }
+        let counts = mem::replace(&mut self.counts, vec![]);
+        self.sender.send((self.leaf, counts))
+    }
+}
+
+/// The top values of a facet field with their counts.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FacetResult {
+    pub field: String,
+    /// Sum of the counts of all the values, a doc having several values is
+    /// counted once per value.
+    pub total_count: i32,
+    /// Number of values counted at least once.
+    pub child_count: usize,
+    /// The top values by decreasing count, then by value.
+    pub children: Vec<(Vec<u8>, i32)>,
+}
+
+/// Counts the values of a sorted (set) doc values field of the hits of a
+/// query, per global ord of a `SortedSetDocValuesReaderState`.
+pub struct SortedSetDocValuesFacetCounts<'a> {
+    state: &'a SortedSetDocValuesReaderState,
+    // global ord -> count
+    counts: Vec<i32>,
+}
+
+impl<'a> SortedSetDocValuesFacetCounts<'a> {
+    /// Counts the values of the hits of `query`, `searcher` must search the
+    /// reader `state` was built for. Counting the values of all the docs with
+    /// a `MatchAllDocsQuery` doesn't run the query.
+    pub fn new<C: Codec, S: IndexSearcher<C>>(
+        state: &'a SortedSetDocValuesReaderState,
+        searcher: &S,
+        query: &dyn Query<C>,
+    ) -> Result<SortedSetDocValuesFacetCounts<'a>> {
+        let leaves = searcher.reader().leaves();
+        state.check_leaves(&leaves)?;
+        if query.as_any().is::<MatchAllDocsQuery>() {
+            return Self::count_all(state, &leaves);
+        }
+        let mut collector = SortedSetDocValuesFacetCollector::new(state.field());
+        searcher.search(query, &mut collector)?;
+        Self::from_collector(state, collector)
+    }
+
+    /// Maps the counts of a collector which collected a search on the reader
+    /// `state` was built for.
+    pub fn from_collector(
+        state: &'a SortedSetDocValuesReaderState,
+        collector: SortedSetDocValuesFacetCollector,
+    ) -> Result<SortedSetDocValuesFacetCounts<'a>> {
+        if collector.field != state.field {
+            bail!(IllegalArgument(format!(
+                "collected '{}' rather than '{}'",
+                collector.field, state.field
+            )));
+        }
+        let mut counts = vec![0; state.value_count];
+        for (leaf, segment_counts) in &collector.segment_counts {
+            state.add_segment_counts(*leaf, segment_counts, &mut counts)?;
+        }
+        Ok(SortedSetDocValuesFacetCounts { state, counts })
+    }
+
+    fn count_all<C: Codec>(
+        state: &'a SortedSetDocValuesReaderState,
+        leaves: &[LeafReaderContext<'_, C>],
+    ) -> Result<SortedSetDocValuesFacetCounts<'a>> {
+        let mut counts = vec![0; state.value_count];
+        for leaf in leaves {
+            let values = match state.values[leaf.ord] {
+                Some(ref values) => values,
+                None => continue,
+            };
+            let live_docs = leaf.reader.live_docs();
+            let mut segment_counts = vec![0; values.get_value_count()];
+            for doc in 0..leaf.reader.max_doc() {
+                if live_docs.get(doc as usize)? {
+                    count_doc(&**values, doc, &mut segment_counts)?;
+                }
+            }
+            state.add_segment_counts(leaf.ord, &segment_counts, &mut counts)?;
+        }
+        Ok(SortedSetDocValuesFacetCounts { state, counts })
+    }
+
+    /// Returns the `n` values with the highest counts, ties broken by value.
+    pub fn top_children(&self, n: usize) -> Result<FacetResult> {
+        let mut total_count = 0;
+        let mut top = Vec::new();
+        for (global_ord, &count) in self.counts.iter().enumerate() {
+            if count > 0 {
+                total_count += count;
+                top.push((count, global_ord));
+            }
+        }
+        let child_count = top.len();
+        // global ords are sorted by value
+        top.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)));
+        top.truncate(n);
+
+        let mut children = Vec::with_capacity(top.len());
+        for (count, global_ord) in top {
+            children.push((self.state.lookup_global_ord(global_ord as i64)?, count));
+        }
+        Ok(FacetResult {
+            field: self.state.field.clone(),
+            total_count,
+            child_count,
+            children,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::codec::CodecEnum;
+    use core::doc::{SortedSetDocValuesField, Store, StringField, TextField};
+    use core::index::index_writer_config::IndexWriterConfig;
+    use core::index::merge_policy::TieredMergePolicy;
+    use core::index::merge_scheduler::SerialMergeScheduler;
+    use core::index::{Fieldable, IndexWriter, StandardDirectoryReader, Term};
+    use core::search::bm25_similarity::BM25Similarity;
+    use core::search::collector::{ChainedCollector, MultiCollector, TopDocsCollector};
+    use core::search::searcher::{DefaultIndexSearcher, DefaultSimilarityProducer, IndexSearcher};
+    use core::search::statistics::{CollectionStatistics, TermStatistics};
+    use core::search::term_query::TermQuery;
+    use core::search::{SearcherFactory, SearcherManager};
+    use core::search::{SimScorer, SimWeight, Similarity, SimilarityProducer};
+    use core::store::RAMDirectory;
+    use core::util::{KeyedContext, ReferenceManager, RefreshListener};
+
+    use std::collections::BTreeMap;
+    use std::fmt;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    type Reader =
+        StandardDirectoryReader<RAMDirectory, CodecEnum, SerialMergeScheduler, TieredMergePolicy>;
+    type Writer = IndexWriter<RAMDirectory, CodecEnum, SerialMergeScheduler, TieredMergePolicy>;
+
+    fn add_color_docs(writer: &Writer, colors: &[&[&str]]) {
+        for doc_colors in colors {
+            let doc: Vec<Box<dyn Fieldable>> = doc_colors
+                .iter()
+                .map(|c| {
+                    Box::new(SortedSetDocValuesField::new("color", c.as_bytes()))
+                        as Box<dyn Fieldable>
+                })
+                .collect();
+            writer.add_document(doc).unwrap();
+        }
+    }
+
+    fn counts(facets: &FacetsState, reader: &Reader) -> Vec<(String, u64)> {
+        facets
+            .facet_counts(reader, "color")
+            .unwrap()
+            .into_iter()
+            .map(|(term, count)| (String::from_utf8(term).unwrap(), count))
+            .collect()
+    }
+
+    fn writer() -> Writer {
+        let dir = Arc::new(RAMDirectory::new());
+        IndexWriter::new(dir, Arc::new(IndexWriterConfig::default())).unwrap()
+    }
+
+    #[test]
+    fn test_refresh() {
+        let writer = writer();
+        let facets = FacetsState::new(&["color"]);
+
+        add_color_docs(&writer, &[&["red", "blue"], &["red"], &["green"]]);
+        let reader = writer.get_reader(true, false).unwrap();
+        facets.refresh(&reader).unwrap();
+        assert_eq!(
+            counts(&facets, &reader),
+            vec![
+                ("blue".to_string(), 1),
+                ("green".to_string(), 1),
+                ("red".to_string(), 2),
+            ]
+        );
+        assert!(facets.facet_counts(&reader, "size").is_err());
+
+        let additions: &[&[&[&str]]] = &[
+            &[&["red"], &["yellow"]],
+            &[&["blue", "yellow"]],
+            &[&["purple"], &["red"], &["red", "purple"]],
+        ];
+        let mut reader = reader;
+        for docs in additions {
+            add_color_docs(&writer, docs);
+            reader = reader.open_if_changed(None).unwrap().unwrap();
+            facets.refresh(&reader).unwrap();
+            // the refresh built the map the facet requests use
+            let map = reader.ordinal_map("color").unwrap().unwrap();
+            let state = SortedSetDocValuesReaderState::new(&reader, "color").unwrap();
+            assert!(Arc::ptr_eq(&map, state.ordinal_map.as_ref().unwrap()));
+        }
+        let expected = vec![
+            ("blue".to_string(), 2),
+            ("green".to_string(), 1),
+            ("purple".to_string(), 2),
+            ("red".to_string(), 5),
+            ("yellow".to_string(), 2),
+        ];
+        assert_eq!(counts(&facets, &reader), expected);
+
+        writer.force_merge(1, true).unwrap();
+        let merged = writer.get_reader(true, false).unwrap();
+        facets.refresh(&merged).unwrap();
+        assert_eq!(counts(&facets, &merged), expected);
+    }
+
+    fn add_category_docs(writer: &Writer, docs: &[(&str, &[&str])]) {
+        for &(kind, categories) in docs {
+            let mut doc: Vec<Box<dyn Fieldable>> =
+                vec![Box::new(StringField::new("kind", kind, Store::Yes))];
+            for category in categories {
+                doc.push(Box::new(StringField::new("category", category, Store::Yes)));
+                doc.push(Box::new(SortedSetDocValuesField::new(
+                    "category",
+                    category.as_bytes(),
+                )));
+            }
+            writer.add_document(doc).unwrap();
+        }
+    }
+
+    // the category counts of the live docs of `kind`, read from the stored fields
+    fn brute_force_counts<R: IndexReader + ?Sized>(
+        reader: &R,
+        kind: Option<&str>,
+    ) -> BTreeMap<Vec<u8>, i32> {
+        let fields = vec!["kind".to_string(), "category".to_string()];
+        let mut counts = BTreeMap::new();
+        for leaf in reader.leaves() {
+            let live_docs = leaf.reader.live_docs();
+            for doc in 0..leaf.reader.max_doc() {
+                if !live_docs.get(doc as usize).unwrap() {
+                    continue;
+                }
+                let document = reader.document(leaf.doc_base + doc, &fields).unwrap();
+                if kind.is_some() && document.get("kind").and_then(|v| v.get_string()) != kind {
+                    continue;
+                }
+                for value in document.get_values("category") {
+                    let category = value.get_string().unwrap().as_bytes().to_vec();
+                    *counts.entry(category).or_insert(0) += 1;
+                }
+            }
+        }
+        counts
+    }
+
+    fn check_facet_counts<S: IndexSearcher<CodecEnum>>(
+        state: &SortedSetDocValuesReaderState,
+        searcher: &S,
+        query: &dyn Query<CodecEnum>,
+        kind: Option<&str>,
+    ) -> FacetResult {
+        let counts = SortedSetDocValuesFacetCounts::new(state, searcher, query).unwrap();
+        let result = counts.top_children(10).unwrap();
+        let expected = brute_force_counts(searcher.reader(), kind);
+        let actual: BTreeMap<Vec<u8>, i32> = result.children.iter().cloned().collect();
+        assert_eq!(actual, expected);
+        assert_eq!(result.child_count, expected.len());
+        assert_eq!(result.total_count, expected.values().sum::<i32>());
+        result
+    }
+
+    #[test]
+    fn test_sorted_set_facet_counts() {
+        let writer = writer();
+        add_category_docs(
+            &writer,
+            &[
+                ("book", &["fiction", "classic"]),
+                ("book", &["fiction"]),
+                ("film", &["drama"]),
+                ("book", &["history", "classic"]),
+            ],
+        );
+        writer.commit().unwrap();
+        // a segment without the field
+        add_category_docs(&writer, &[("book", &[]), ("film", &[])]);
+        writer.commit().unwrap();
+        add_category_docs(
+            &writer,
+            &[
+                ("book", &["poetry", "fiction"]),
+                ("film", &["drama", "classic"]),
+                ("book", &["classic"]),
+            ],
+        );
+        writer
+            .delete_documents_by_term("category", b"history")
+            .unwrap();
+        let reader = Arc::new(writer.get_reader(true, false).unwrap());
+        assert_eq!(reader.leaves().len(), 3);
+
+        let searcher = DefaultIndexSearcher::new(Arc::clone(&reader));
+        let state = SortedSetDocValuesReaderState::new(reader.as_ref(), "category").unwrap();
+        // the values of deleted docs have global ords too
+        assert_eq!(state.value_count(), 5);
+        // the map is built once per reader
+        let again = SortedSetDocValuesReaderState::new(reader.as_ref(), "category").unwrap();
+        assert!(Arc::ptr_eq(
+            state.ordinal_map.as_ref().unwrap(),
+            again.ordinal_map.as_ref().unwrap()
+        ));
+
+        let books = TermQuery::new(Term::new("kind".into(), b"book".to_vec()), 1.0, None);
+        let result = check_facet_counts(&state, &searcher, &books, Some("book"));
+        assert_eq!(
+            result.children,
+            vec![
+                (b"fiction".to_vec(), 3),
+                (b"classic".to_vec(), 2),
+                (b"poetry".to_vec(), 1),
+            ]
+        );
+        let counts = SortedSetDocValuesFacetCounts::new(&state, &searcher, &books).unwrap();
+        let top = counts.top_children(1).unwrap();
+        assert_eq!(top.children, vec![(b"fiction".to_vec(), 3)]);
+        assert_eq!(top.child_count, 3);
+        assert_eq!(top.total_count, 6);
+
+        // ties are broken by value
+        let result = check_facet_counts(&state, &searcher, &MatchAllDocsQuery, None);
+        assert_eq!(
+            result.children[..2].to_vec(),
+            vec![(b"classic".to_vec(), 3), (b"fiction".to_vec(), 3)]
+        );
+
+        // a state is only valid for the reader it was built for
+        add_category_docs(&writer, &[("film", &["drama"])]);
+        let newer = writer.get_reader(true, false).unwrap();
+        let newer_state = SortedSetDocValuesReaderState::new(&newer, "category").unwrap();
+        assert!(SortedSetDocValuesFacetCounts::new(&newer_state, &searcher, &books).is_err());
+
+        // a single segment needs no ordinal map
+        writer.force_merge(1, true).unwrap();
+        let merged = Arc::new(writer.get_reader(true, false).unwrap());
+        assert_eq!(merged.leaves().len(), 1);
+        let searcher = DefaultIndexSearcher::new(Arc::clone(&merged));
+        let state = SortedSetDocValuesReaderState::new(merged.as_ref(), "category").unwrap();
+        check_facet_counts(&state, &searcher, &books, Some("book"));
+        check_facet_counts(&state, &searcher, &MatchAllDocsQuery, None);
+    }
+
+    // counts the similarity scorers, each reading the norms of a segment
+    struct NormsCountingProducer {
+        sim_scorers: Arc<AtomicUsize>,
+    }
+
+    impl<C: Codec> SimilarityProducer<C> for NormsCountingProducer {
+        fn create(&self, _field: &str) -> Box<dyn Similarity<C>> {
+            Box::new(NormsCountingSimilarity {
+                sim_scorers: Arc::clone(&self.sim_scorers),
+            })
+        }
+    }
+
+    struct NormsCountingSimilarity {
+        sim_scorers: Arc<AtomicUsize>,
+    }
+
+    impl<C: Codec> Similarity<C> for NormsCountingSimilarity {
+        fn compute_weight(
+            &self,
+            collection_stats: &CollectionStatistics,
+            term_stats: &[TermStatistics],
+            context: Option<&KeyedContext>,
+            boost: f32,
+        ) -> Box<dyn SimWeight<C>> {
+            Box::new(NormsCountingSimWeight {
+                weight: Similarity::<C>::compute_weight(
+                    &BM25Similarity::default(),
+                    collection_stats,
+                    term_stats,
+                    context,
+                    boost,
+                ),
+                sim_scorers: Arc::clone(&self.sim_scorers),
+            })
+        }
+    }
+
+    impl fmt::Display for NormsCountingSimilarity {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "norms-counting")
+        }
+    }
+
+    struct NormsCountingSimWeight<C: Codec> {
+        weight: Box<dyn SimWeight<C>>,
+        sim_scorers: Arc<AtomicUsize>,
+    }
+
+    impl<C: Codec> SimWeight<C> for NormsCountingSimWeight<C> {
+        fn get_value_for_normalization(&self) -> f32 {
+            self.weight.get_value_for_normalization()
+        }
+
+        fn normalize(&mut self, query_norm: f32, boost: f32) {
+            self.weight.normalize(query_norm, boost)
+        }
+
+        fn sim_scorer(&self, reader: &SearchLeafReader<C>) -> Result<Box<dyn SimScorer>> {
+            self.sim_scorers.fetch_add(1, Ordering::Relaxed);
+            self.weight.sim_scorer(reader)
+        }
+    }
+
+    #[test]
+    fn test_facet_collection_reads_no_norms() {
+        let writer = writer();
+        for (i, category) in ["fiction", "drama", "fiction", "poetry"].iter().enumerate() {
+            let (body, format) = if i % 2 == 0 {
+                ("red fox", "paper")
+            } else {
+                ("quick red fox", "ebook")
+            };
+            let doc: Vec<Box<dyn Fieldable>> = vec![
+                Box::new(TextField::new("body", body, Store::No)),
+                Box::new(SortedSetDocValuesField::new(
+                    "category",
+                    category.as_bytes(),
+                )),
+                Box::new(SortedSetDocValuesField::new("format", format.as_bytes())),
+            ];
+            writer.add_document(doc).unwrap();
+        }
+        let reader = Arc::new(writer.get_reader(true, false).unwrap());
+        let sim_scorers = Arc::new(AtomicUsize::new(0));
+        let searcher = DefaultIndexSearcher::with_similarity(
+            Arc::clone(&reader),
+            NormsCountingProducer {
+                sim_scorers: Arc::clone(&sim_scorers),
+            },
+        );
+        let categories = SortedSetDocValuesReaderState::new(reader.as_ref(), "category").unwrap();
+        let formats = SortedSetDocValuesReaderState::new(reader.as_ref(), "format").unwrap();
+        let fox = TermQuery::new(Term::new("body".into(), b"fox".to_vec()), 1.0, None);
+        let category_counts = vec![
+            (b"fiction".to_vec(), 2),
+            (b"drama".to_vec(), 1),
+            (b"poetry".to_vec(), 1),
+        ];
+
+        // the facets of several fields are collected at once, without scores
+        let mut collector = MultiCollector::new(vec![
+            SortedSetDocValuesFacetCollector::new("category"),
+            SortedSetDocValuesFacetCollector::new("format"),
+        ]);
+        assert_eq!(collector.score_mode(), ScoreMode::CompleteNoScores);
+        searcher.search(&fox, &mut collector).unwrap();
+        assert_eq!(sim_scorers.load(Ordering::Relaxed), 0);
+        let mut collectors = collector.into_inner().into_iter();
+        let counts =
+            SortedSetDocValuesFacetCounts::from_collector(&categories, collectors.next().unwrap())
+                .unwrap();
+        assert_eq!(counts.top_children(10).unwrap().children, category_counts);
+        let counts =
+            SortedSetDocValuesFacetCounts::from_collector(&formats, collectors.next().unwrap())
+                .unwrap();
+        assert_eq!(
+            counts.top_children(10).unwrap().children,
+            vec![(b"ebook".to_vec(), 2), (b"paper".to_vec(), 2)]
+        );
+
+        // collected along with the top hit, the scores are needed, but the top
+        // docs can't let the scorer skip the docs the facets count
+        let mut collector = ChainedCollector::new(
+            TopDocsCollector::new(1).with_total_hits_threshold(0),
+            SortedSetDocValuesFacetCollector::new("category"),
+        );
+        assert_eq!(collector.score_mode(), ScoreMode::Complete);
+        assert_eq!(collector.min_competitive_score(), None);
+        searcher.search(&fox, &mut collector).unwrap();
+        assert!(sim_scorers.load(Ordering::Relaxed) > 0);
+        let (_, facets) = collector.into_inner();
+        let counts = SortedSetDocValuesFacetCounts::from_collector(&categories, facets).unwrap();
+        assert_eq!(counts.top_children(10).unwrap().children, category_counts);
+    }
+
+    struct Factory;
+
+    impl SearcherFactory<CodecEnum> for Factory {
+        type Searcher = DefaultIndexSearcher<
+            CodecEnum,
+            dyn IndexReader<Codec = CodecEnum> + Send + Sync,
+            Arc<dyn IndexReader<Codec = CodecEnum> + Send + Sync>,
+            DefaultSimilarityProducer,
+        >;
+
+        fn new_searcher(
+            &self,
+            reader: Arc<dyn IndexReader<Codec = CodecEnum> + Send + Sync>,
+        ) -> Result<Self::Searcher> {
+            Ok(DefaultIndexSearcher::new(reader))
+        }
+    }
+
+    struct NoListener;
+
+    impl RefreshListener for NoListener {
+        fn before_refresh(&self) -> Result<()> {
+            Ok(())
+        }
+
+        fn after_refresh(&self, _refreshed: bool) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_searcher_manager_facets() {
+        let writer = writer();
+        add_color_docs(&writer, &[&["red"], &["blue"]]);
+        let facets = Arc::new(FacetsState::new(&["color"]));
+        let manager: SearcherManager<CodecEnum, Arc<NoListener>, Factory> =
+            SearcherManager::with_facets(
+                writer.get_reader(true, false).unwrap(),
+                Factory,
+                None,
+                Arc::clone(&facets),
+            )
+            .unwrap();
+        assert_eq!(
+            manager.facets_state().unwrap().fields().to_vec(),
+            vec!["color".to_string()]
+        );
+
+        add_color_docs(&writer, &[&["red"]]);
+        writer.commit().unwrap();
+        manager.maybe_refresh().unwrap();
+
+        let searcher = manager.acquire().unwrap();
+        let counts = facets.facet_counts(searcher.reader(), "color").unwrap();
+        assert_eq!(counts, vec![(b"blue".to_vec(), 1), (b"red".to_vec(), 2)]);
+        manager.release(&searcher).unwrap();
+    }
+}
@@ -11,15 +11,20 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use core::index::{LeafReaderContext, NumericDocValuesRef, SearchLeafReader};
+use core::index::{
+    DocValuesType, EmptySortedDocValues, LeafReaderContext, NumericDocValuesRef, SearchLeafReader,
+    SortedDocValues, SortedDocValuesRef,
+};
 use core::search::sort_field::{SortFieldType, SortedWrapperDocValuesSource};
 use core::util::bits::BitsRef;
 use core::util::{DocId, VariantValue};
+use error::ErrorKind::IllegalArgument;
 use error::Result;
 
 use core::codec::Codec;
 use std::cmp::Ordering;
 use std::fmt;
+use std::sync::Arc;
 
 #[derive(Copy, Clone, Debug)]
 pub enum ComparatorValue {
@@ -107,6 +112,14 @@ pub trait FieldComparator: fmt::Display {
 
     fn compare_bottom(&self, value: ComparatorValue) -> Result<Ordering>;
 
+    /// Sets the value of the hit a paging search starts after, as returned by
+    /// `value` in the previous page.
+    fn set_top_value(&mut self, value: &VariantValue) -> Result<()>;
+
+    /// Compares the top value with the value of a doc of the current reader,
+    /// `Ordering::Less` if the doc sorts after the top value.
+    fn compare_top(&self, value: ComparatorValue) -> Result<Ordering>;
+
     fn copy(&mut self, slot: usize, value: ComparatorValue) -> Result<()>;
 
     fn get_information_from_reader<C: Codec>(
@@ -122,6 +135,7 @@ pub enum FieldComparatorEnum {
     Doc(DocComparator),
     NumericDV(NumericDocValuesComparator<DefaultDocValuesSource>),
     SortedNumericDV(NumericDocValuesComparator<SortedWrapperDocValuesSource>),
+    TermOrdVal(TermOrdValComparator),
 }
 
 impl FieldComparator for FieldComparatorEnum {
@@ -131,6 +145,7 @@ impl FieldComparator for FieldComparatorEnum {
             FieldComparatorEnum::Doc(c) => c.compare(slot1, slot2),
             FieldComparatorEnum::NumericDV(c) => c.compare(slot1, slot2),
             FieldComparatorEnum::SortedNumericDV(c) => c.compare(slot1, slot2),
+            FieldComparatorEnum::TermOrdVal(c) => c.compare(slot1, slot2),
         }
     }
 
@@ -140,6 +155,7 @@ impl FieldComparator for FieldComparatorEnum {
             FieldComparatorEnum::Doc(c) => c.value(slot),
             FieldComparatorEnum::NumericDV(c) => c.value(slot),
             FieldComparatorEnum::SortedNumericDV(c) => c.value(slot),
+            FieldComparatorEnum::TermOrdVal(c) => c.value(slot),
         }
     }
 
@@ -149,6 +165,7 @@ impl FieldComparator for FieldComparatorEnum {
             FieldComparatorEnum::Doc(c) => c.set_bottom(slot),
             FieldComparatorEnum::NumericDV(c) => c.set_bottom(slot),
             FieldComparatorEnum::SortedNumericDV(c) => c.set_bottom(slot),
+            FieldComparatorEnum::TermOrdVal(c) => c.set_bottom(slot),
         }
     }
 
@@ -158,6 +175,27 @@ impl FieldComparator for FieldComparatorEnum {
             FieldComparatorEnum::Doc(c) => c.compare_bottom(value),
             FieldComparatorEnum::NumericDV(c) => c.compare_bottom(value),
             FieldComparatorEnum::SortedNumericDV(c) => c.compare_bottom(value),
+            FieldComparatorEnum::TermOrdVal(c) => c.compare_bottom(value),
+        }
+    }
+
+    fn set_top_value(&mut self, value: &VariantValue) -> Result<()> {
+        match self {
+            FieldComparatorEnum::Score(c) => c.set_top_value(value),
+            FieldComparatorEnum::Doc(c) => c.set_top_value(value),
+            FieldComparatorEnum::NumericDV(c) => c.set_top_value(value),
+            FieldComparatorEnum::SortedNumericDV(c) => c.set_top_value(value),
+            FieldComparatorEnum::TermOrdVal(c) => c.set_top_value(value),
+        }
+    }
+
+    fn compare_top(&self, value: ComparatorValue) -> Result<Ordering> {
+        match self {
+            FieldComparatorEnum::Score(c) => c.compare_top(value),
+            FieldComparatorEnum::Doc(c) => c.compare_top(value),
+            FieldComparatorEnum::NumericDV(c) => c.compare_top(value),
+            FieldComparatorEnum::SortedNumericDV(c) => c.compare_top(value),
+            FieldComparatorEnum::TermOrdVal(c) => c.compare_top(value),
         }
     }
 
@@ -167,6 +205,7 @@ impl FieldComparator for FieldComparatorEnum {
             FieldComparatorEnum::Doc(c) => c.copy(slot, value),
             FieldComparatorEnum::NumericDV(c) => c.copy(slot, value),
             FieldComparatorEnum::SortedNumericDV(c) => c.copy(slot, value),
+            FieldComparatorEnum::TermOrdVal(c) => c.copy(slot, value),
         }
     }
 
@@ -179,6 +218,7 @@ impl FieldComparator for FieldComparatorEnum {
             FieldComparatorEnum::Doc(c) => c.get_information_from_reader(reader),
             FieldComparatorEnum::NumericDV(c) => c.get_information_from_reader(reader),
             FieldComparatorEnum::SortedNumericDV(c) => c.get_information_from_reader(reader),
+            FieldComparatorEnum::TermOrdVal(c) => c.get_information_from_reader(reader),
         }
     }
 
@@ -188,6 +228,7 @@ impl FieldComparator for FieldComparatorEnum {
             FieldComparatorEnum::Doc(c) => c.get_type(),
             FieldComparatorEnum::NumericDV(c) => c.get_type(),
             FieldComparatorEnum::SortedNumericDV(c) => c.get_type(),
+            FieldComparatorEnum::TermOrdVal(c) => c.get_type(),
         }
     }
 }
@@ -199,6 +240,7 @@ impl fmt::Display for FieldComparatorEnum {
             FieldComparatorEnum::Doc(c) => write!(f, "FieldComparatorEnum({})", c),
             FieldComparatorEnum::NumericDV(c) => write!(f, "FieldComparatorEnum({})", c),
             FieldComparatorEnum::SortedNumericDV(c) => write!(f, "FieldComparatorEnum({})", c),
+            FieldComparatorEnum::TermOrdVal(c) => write!(f, "FieldComparatorEnum({})", c),
         }
     }
 }
@@ -206,6 +248,7 @@ impl fmt::Display for FieldComparatorEnum {
 pub struct RelevanceComparator {
     scores: Vec<f32>,
     bottom: f32,
+    top: f32,
 }
 
 impl RelevanceComparator {
@@ -215,6 +258,7 @@ impl RelevanceComparator {
         RelevanceComparator {
             scores,
             bottom: 0f32,
+            top: 0f32,
         }
     }
 }
@@ -242,6 +286,27 @@ impl FieldComparator for RelevanceComparator {
             .unwrap_or(Ordering::Equal))
     }
 
+    fn set_top_value(&mut self, value: &VariantValue) -> Result<()> {
+        match value.get_float() {
+            Some(score) => {
+                self.top = score;
+                Ok(())
+            }
+            None => bail!(IllegalArgument(format!(
+                "top value of a score sort must be a float, got {:?}",
+                value
+            ))),
+        }
+    }
+
+    fn compare_top(&self, value: ComparatorValue) -> Result<Ordering> {
+        debug_assert!(value.is_score());
+        Ok(value
+            .score()
+            .partial_cmp(&self.top)
+            .unwrap_or(Ordering::Equal))
+    }
+
     fn copy(&mut self, slot: usize, value: ComparatorValue) -> Result<()> {
         debug_assert!(value.is_score());
         self.scores[slot] = value.score();
@@ -269,6 +334,7 @@ impl fmt::Display for RelevanceComparator {
 pub struct DocComparator {
     doc_ids: Vec<i32>,
     bottom: i32,
+    top: i32,
     doc_base: i32,
 }
 
@@ -282,6 +348,7 @@ impl DocComparator {
         DocComparator {
             doc_ids,
             bottom: 0,
+            top: 0,
             doc_base: 0,
         }
     }
@@ -302,7 +369,25 @@ impl FieldComparator for DocComparator {
 
     fn compare_bottom(&self, value: ComparatorValue) -> Result<Ordering> {
         debug_assert!(value.is_doc());
-        Ok(self.bottom.cmp(&value.doc()))
+        Ok(self.bottom.cmp(&(value.doc() + self.doc_base)))
+    }
+
+    fn set_top_value(&mut self, value: &VariantValue) -> Result<()> {
+        match value.get_int() {
+            Some(doc) => {
+                self.top = doc;
+                Ok(())
+            }
+            None => bail!(IllegalArgument(format!(
+                "top value of a doc sort must be an int, got {:?}",
+                value
+            ))),
+        }
+    }
+
+    fn compare_top(&self, value: ComparatorValue) -> Result<Ordering> {
+        debug_assert!(value.is_doc());
+        Ok(self.top.cmp(&(value.doc() + self.doc_base)))
     }
 
     fn copy(&mut self, slot: usize, value: ComparatorValue) -> Result<()> {
@@ -382,6 +467,17 @@ impl<T: DocValuesSource> NumericDocValuesComparator<T> {
         };
         Ok(value)
     }
+
+    // the value of the doc, or the missing value if it has none
+    fn doc_value(&self, doc_id: DocId) -> Result<VariantValue> {
+        let value = self.get_doc_value(doc_id)?;
+        if let Some(ref bits) = self.docs_with_fields {
+            if value.is_zero() && !bits.get(doc_id as usize)? {
+                return Ok(self.missing_value.as_ref().unwrap().clone());
+            }
+        }
+        Ok(value)
+    }
 }
 
 impl<T: DocValuesSource> FieldComparator for NumericDocValuesComparator<T> {
@@ -399,26 +495,22 @@ impl<T: DocValuesSource> FieldComparator for NumericDocValuesComparator<T> {
 
     fn compare_bottom(&self, value: ComparatorValue) -> Result<Ordering> {
         debug_assert!(value.is_doc());
-        let doc_id = value.doc();
-        let value = self.get_doc_value(doc_id)?;
-        if let Some(ref bits) = self.docs_with_fields {
-            if value.is_zero() && bits.get(doc_id as usize)? {
-                return Ok(self.bottom.cmp(self.missing_value.as_ref().unwrap()));
-            }
-        }
-        Ok(self.bottom.cmp(&value))
+        Ok(self.bottom.cmp(&self.doc_value(value.doc())?))
+    }
+
+    fn set_top_value(&mut self, value: &VariantValue) -> Result<()> {
+        self.top_value = value.clone();
+        Ok(())
+    }
+
+    fn compare_top(&self, value: ComparatorValue) -> Result<Ordering> {
+        debug_assert!(value.is_doc());
+        Ok(self.top_value.cmp(&self.doc_value(value.doc())?))
     }
 
     fn copy(&mut self, slot: usize, value: ComparatorValue) -> Result<()> {
         debug_assert!(value.is_doc());
-        let doc_id = value.doc();
-        let mut value = self.get_doc_value(doc_id)?;
-        if let Some(ref bits) = self.docs_with_fields {
-            if value.is_zero() && bits.get(doc_id as usize)? {
-                value = self.missing_value.as_ref().unwrap().clone();
-            }
-        }
-        self.values[slot] = value;
+        self.values[slot] = self.doc_value(value.doc())?;
         Ok(())
     }
 
@@ -455,6 +547,234 @@ impl<T: DocValuesSource> fmt::Display for NumericDocValuesComparator<T> {
     }
 }
 
+/// Sorts by the `SortedDocValues` of a field, comparing the ords of hits of
+/// the same segment and their terms otherwise.
+///
+/// The docs without a value sort first, or last if `missing_last` is set,
+/// and the empty term sorts with them: the sort value of both is an empty
+/// binary.
+pub struct TermOrdValComparator {
+    field: String,
+    missing_last: bool,
+    // the ord, the term and the reader generation of the hit in each slot,
+    // the ord of a missing value is `missing_ord`
+    ords: Vec<i32>,
+    values: Vec<Vec<u8>>,
+    readers: Vec<usize>,
+    current_reader: usize,
+    doc_values: Option<SortedDocValuesRef>,
+    // whether the first term of the current segment is the empty term
+    empty_first_term: bool,
+    bottom_slot: Option<usize>,
+    // the position of the bottom and top values in the current segment, see
+    // `segment_ord`; `None` if it couldn't be looked up
+    bottom_ord: Option<(i32, bool)>,
+    top_value: Vec<u8>,
+    top_ord: Option<(i32, bool)>,
+}
+
+impl TermOrdValComparator {
+    pub fn new(num_hits: usize, field: String, missing_last: bool) -> TermOrdValComparator {
+        let missing_ord = if missing_last { i32::max_value() } else { -1 };
+        TermOrdValComparator {
+            field,
+            missing_last,
+            ords: vec![missing_ord; num_hits],
+            values: vec![Vec::new(); num_hits],
+            readers: vec![0; num_hits],
+            current_reader: 0,
+            doc_values: None,
+            empty_first_term: false,
+            bottom_slot: None,
+            bottom_ord: None,
+            top_value: Vec::new(),
+            top_ord: None,
+        }
+    }
+
+    fn doc_values(&self) -> &SortedDocValuesRef {
+        self.doc_values.as_ref().unwrap()
+    }
+
+    fn missing_ord(&self) -> i32 {
+        if self.missing_last {
+            i32::max_value()
+        } else {
+            -1
+        }
+    }
+
+    // the ord of the doc in the current segment, `missing_ord` if it has no
+    // value or the empty term
+    fn doc_ord(&self, doc: DocId) -> Result<i32> {
+        let ord = self.doc_values().get_ord(doc)?;
+        if ord < 0 || (ord == 0 && self.empty_first_term) {
+            Ok(self.missing_ord())
+        } else {
+            Ok(ord)
+        }
+    }
+
+    // the ord of `value` in the current segment and true if it's a term of
+    // the segment, else the ord of the greatest term below it and false
+    fn segment_ord(&self, value: &[u8]) -> Result<(i32, bool)> {
+        if value.is_empty() {
+            return Ok((self.missing_ord(), true));
+        }
+        let ord = self.doc_values().lookup_term(value)?;
+        if ord >= 0 {
+            Ok((ord, true))
+        } else {
+            Ok((-ord - 2, false))
+        }
+    }
+
+    fn term(&self, ord: i32) -> Result<Vec<u8>> {
+        if ord == self.missing_ord() {
+            Ok(Vec::new())
+        } else {
+            self.doc_values().lookup_ord(ord)
+        }
+    }
+
+    fn compare_terms(&self, term1: &[u8], term2: &[u8]) -> Ordering {
+        compare_terms(term1, term2, self.missing_last)
+    }
+
+    // compares `value`, positioned at `value_ord` in the current segment, to
+    // the term of the doc with ord `doc_ord`
+    fn compare_ord(
+        &self,
+        value: &[u8],
+        value_ord: Option<(i32, bool)>,
+        doc_ord: i32,
+    ) -> Result<Ordering> {
+        match value_ord {
+            Some((ord, true)) => Ok(ord.cmp(&doc_ord)),
+            Some((ord, false)) => {
+                // the value is between the terms of `ord` and `ord + 1`
+                if ord >= doc_ord {
+                    Ok(Ordering::Greater)
+                } else {
+                    Ok(Ordering::Less)
+                }
+            }
+            None => Ok(self.compare_terms(value, &self.term(doc_ord)?)),
+        }
+    }
+}
+
+// compares two sort values of a string sort, the empty term standing for the
+// missing values
+pub(crate) fn compare_terms(term1: &[u8], term2: &[u8], missing_last: bool) -> Ordering {
+    if !missing_last {
+        return term1.cmp(term2);
+    }
+    match (term1.is_empty(), term2.is_empty()) {
+        (true, true) => Ordering::Equal,
+        (true, false) => Ordering::Greater,
+        (false, true) => Ordering::Less,
+        (false, false) => term1.cmp(term2),
+    }
+}
+
+impl FieldComparator for TermOrdValComparator {
+    fn compare(&self, slot1: usize, slot2: usize) -> Ordering {
+        if self.readers[slot1] == self.readers[slot2] {
+            self.ords[slot1].cmp(&self.ords[slot2])
+        } else {
+            self.compare_terms(&self.values[slot1], &self.values[slot2])
+        }
+    }
+
+    fn value(&self, slot: usize) -> VariantValue {
+        VariantValue::Binary(self.values[slot].clone())
+    }
+
+    fn set_bottom(&mut self, slot: usize) {
+        self.bottom_slot = Some(slot);
+        self.bottom_ord = if self.readers[slot] == self.current_reader {
+            Some((self.ords[slot], true))
+        } else {
+            self.segment_ord(&self.values[slot]).ok()
+        };
+    }
+
+    fn compare_bottom(&self, value: ComparatorValue) -> Result<Ordering> {
+        debug_assert!(value.is_doc());
+        let bottom = &self.values[self.bottom_slot.unwrap()];
+        let doc_ord = self.doc_ord(value.doc())?;
+        self.compare_ord(bottom, self.bottom_ord, doc_ord)
+    }
+
+    fn set_top_value(&mut self, value: &VariantValue) -> Result<()> {
+        self.top_value = match value {
+            VariantValue::Binary(b) => b.clone(),
+            VariantValue::VString(s) => s.as_bytes().to_vec(),
+            _ => bail!(IllegalArgument(format!(
+                "top value of a string sort must be binary or a string, got {:?}",
+                value
+            ))),
+        };
+        self.top_ord = None;
+        Ok(())
+    }
+
+    fn compare_top(&self, value: ComparatorValue) -> Result<Ordering> {
+        debug_assert!(value.is_doc());
+        let doc_ord = self.doc_ord(value.doc())?;
+        self.compare_ord(&self.top_value, self.top_ord, doc_ord)
+    }
+
+    fn copy(&mut self, slot: usize, value: ComparatorValue) -> Result<()> {
+        debug_assert!(value.is_doc());
+        let ord = self.doc_ord(value.doc())?;
+        self.values[slot] = self.term(ord)?;
+        self.ords[slot] = ord;
+        self.readers[slot] = self.current_reader;
+        Ok(())
+    }
+
+    fn get_information_from_reader<C: Codec>(
+        &mut self,
+        reader: &LeafReaderContext<'_, C>,
+    ) -> Result<()> {
+        let doc_values: SortedDocValuesRef = match reader
+            .reader
+            .field_info(&self.field)
+            .map(|fi| fi.doc_values_type)
+        {
+            Some(DocValuesType::Sorted) => reader.reader.get_sorted_doc_values(&self.field)?,
+            _ => Arc::new(EmptySortedDocValues),
+        };
+        self.empty_first_term =
+            doc_values.get_value_count() > 0 && doc_values.lookup_ord(0)?.is_empty();
+        self.doc_values = Some(doc_values);
+        self.current_reader += 1;
+        if let Some(slot) = self.bottom_slot {
+            self.bottom_ord = Some(self.segment_ord(&self.values[slot])?);
+        }
+        self.top_ord = Some(self.segment_ord(&self.top_value)?);
+        Ok(())
+    }
+
+    fn get_type(&self) -> SortFieldType {
+        SortFieldType::String
+    }
+}
+
+impl fmt::Display for TermOrdValComparator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "TermOrdValComparator(field: {}, bottom: {:?}, top: {:?})",
+            self.field,
+            self.bottom_slot.map(|slot| &self.values[slot]),
+            self.top_value
+        )
+    }
+}
+
 pub trait DocValuesSource {
     fn numeric_doc_values<C: Codec>(
         &self,
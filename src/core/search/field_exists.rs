@@ -0,0 +1,622 @@
+// Copyright 2019 Zhizhesihai (Beijing) Technology Limited.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use core::codec::Codec;
+use core::index::{DocValuesType, IndexOptions, LeafReader, LeafReaderContext};
+use core::index::{IntersectVisitor, NumericDocValues, PointValues, Relation};
+use core::index::{SearchLeafReader, TermIterator, Terms};
+use core::search::explanation::Explanation;
+use core::search::match_all::{AllDocsIterator, ConstantScore, ConstantScoreScorer};
+use core::search::posting_iterator::PostingIteratorFlags;
+use core::search::searcher::SearchPlanBuilder;
+use core::search::term_query::TermQuery;
+use core::search::{DocIdSet, DocIterator, Query, Scorer, Weight, NO_MORE_DOCS};
+use core::util::{BitsRef, DocId, DocIdSetBuilder};
+
+use error::ErrorKind::IllegalArgument;
+use error::Result;
+
+use std::fmt;
+
+pub const FIELD_EXISTS: &str = "field_exists";
+
+/// Which evidence a `FieldExistsQuery` finds the docs having the field of a
+/// segment with.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FieldExistsStrategy {
+    /// Docs with a non zero norm, for indexed fields not omitting norms.
+    Norms,
+    /// The docs with field of the doc values.
+    DocValues,
+    /// Every doc of the segment has a point of the field.
+    AllDocs,
+    /// Visits every point of the field.
+    Points,
+    /// Unions the postings of all the terms of the field.
+    Terms,
+}
+
+/// Matches the docs having a value in the field, with a constant score.
+///
+/// The field may be of any type except stored only, which has nothing to
+/// tell the docs having it apart at search time.
+#[derive(Clone, Debug)]
+pub struct FieldExistsQuery {
+    field: String,
+}
+
+impl FieldExistsQuery {
+    pub fn new(field: String) -> FieldExistsQuery {
+        FieldExistsQuery { field }
+    }
+
+    pub fn field(&self) -> &str {
+        &self.field
+    }
+
+    /// Returns the cheapest strategy for the segment of `reader`, or `None`
+    /// when the field is absent from the segment.
+    pub fn strategy<C: Codec>(
+        &self,
+        reader: &SearchLeafReader<C>,
+    ) -> Result<Option<FieldExistsStrategy>> {
+        let field_info = match reader.field_info(&self.field) {
+            Some(fi) => fi,
+            None => return Ok(None),
+        };
+        if field_info.has_norms() {
+            return Ok(Some(FieldExistsStrategy::Norms));
+        }
+        if field_info.doc_values_type != DocValuesType::Null {
+            return Ok(Some(FieldExistsStrategy::DocValues));
+        }
+        if field_info.point_dimension_count > 0 {
+            if let Some(values) = reader.point_values() {
+                return if values.doc_count(&self.field)? == reader.max_doc() {
+                    Ok(Some(FieldExistsStrategy::AllDocs))
+                } else {
+                    Ok(Some(FieldExistsStrategy::Points))
+                };
+            }
+        }
+        if field_info.index_options != IndexOptions::Null {
+            return Ok(Some(FieldExistsStrategy::Terms));
+        }
+        bail!(IllegalArgument(format!(
+            "field '{}' is neither indexed, nor has doc values or points, field existence is \
+             unsupported for stored only fields",
+            self.field
+        )))
+    }
+}
+
+impl<C: Codec> Query<C> for FieldExistsQuery {
+    fn create_weight(
+        &self,
+        _searcher: &dyn SearchPlanBuilder<C>,
+        _needs_scores: bool,
+    ) -> Result<Box<dyn Weight<C>>> {
+        Ok(Box::new(FieldExistsWeight {
+            query: self.clone(),
+            score: ConstantScore::default(),
+        }))
+    }
+
+    fn extract_terms(&self) -> Vec<TermQuery> {
+        vec![]
+    }
+
+    fn query_type(&self) -> &'static str {
+        FIELD_EXISTS
+    }
+
+    fn as_any(&self) -> &::std::any::Any {
+        self
+    }
+}
+
+impl fmt::Display for FieldExistsQuery {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "FieldExistsQuery(field: {})", &self.field)
+    }
+}
+
+struct FieldExistsWeight {
+    query: FieldExistsQuery,
+    score: ConstantScore,
+}
+
+impl FieldExistsWeight {
+    fn constant_scorer<T: DocIterator + 'static>(&self, iterator: T) -> Box<dyn Scorer> {
+        let cost = iterator.cost();
+        Box::new(ConstantScoreScorer::new(self.score.score(), iterator, cost))
+    }
+
+    fn points_scorer<C: Codec>(
+        &self,
+        reader: &SearchLeafReader<C>,
+    ) -> Result<Option<Box<dyn Scorer>>> {
+        let values = match reader.point_values() {
+            Some(values) => values,
+            None => return Ok(None),
+        };
+        let mut result =
+            DocIdSetBuilder::from_values(reader.max_doc(), &values, &self.query.field)?;
+        {
+            let mut visitor = AllPointsVisitor {
+                doc_id_set_builder: &mut result,
+            };
+            values.intersect(&self.query.field, &mut visitor)?;
+        }
+        Ok(result
+            .build()
+            .iterator()?
+            .map(|iterator| self.constant_scorer(iterator)))
+    }
+
+    fn terms_scorer<C: Codec>(
+        &self,
+        reader: &SearchLeafReader<C>,
+    ) -> Result<Option<Box<dyn Scorer>>> {
+        let terms = match reader.terms(&self.query.field)? {
+            Some(terms) => terms,
+            None => return Ok(None),
+        };
+        let mut result = DocIdSetBuilder::from_terms(reader.max_doc(), &terms)?;
+        let mut iter = terms.iterator()?;
+        while iter.next()?.is_some() {
+            let mut postings = iter.postings_with_flags(PostingIteratorFlags::NONE)?;
+            result.add(&mut postings)?;
+        }
+        Ok(result
+            .build()
+            .iterator()?
+            .map(|iterator| self.constant_scorer(iterator)))
+    }
+}
+
+impl<C: Codec> Weight<C> for FieldExistsWeight {
+    fn create_scorer(
+        &self,
+        leaf_reader_ctx: &LeafReaderContext<'_, C>,
+    ) -> Result<Option<Box<dyn Scorer>>> {
+        let reader = leaf_reader_ctx.reader;
+        let strategy = match self.query.strategy(reader)? {
+            Some(strategy) => strategy,
+            None => return Ok(None),
+        };
+        let max_doc = reader.max_doc();
+        match strategy {
+            FieldExistsStrategy::Norms => match reader.norm_values(&self.query.field)? {
+                Some(norms) => Ok(Some(self.constant_scorer(DocsWithFieldIterator::new(
+                    Evidence::Norms(norms),
+                    max_doc,
+                )))),
+                None => Ok(None),
+            },
+            FieldExistsStrategy::DocValues => {
+                let bits = reader.get_docs_with_field(&self.query.field)?;
+                Ok(Some(self.constant_scorer(DocsWithFieldIterator::new(
+                    Evidence::Bits(bits),
+                    max_doc,
+                ))))
+            }
+            FieldExistsStrategy::AllDocs => {
+                Ok(Some(self.constant_scorer(AllDocsIterator::new(max_doc))))
+            }
+            FieldExistsStrategy::Points => self.points_scorer(reader),
+            FieldExistsStrategy::Terms => self.terms_scorer(reader),
+        }
+    }
+
+    fn query_type(&self) -> &'static str {
+        FIELD_EXISTS
+    }
+
+    fn normalize(&mut self, norm: f32, boost: f32) {
+        self.score.normalize(norm, boost);
+    }
+
+    fn value_for_normalization(&self) -> f32 {
+        self.score.value_for_normalization()
+    }
+
+    fn needs_scores(&self) -> bool {
+        false
+    }
+
+    fn explain(&self, reader: &LeafReaderContext<'_, C>, doc: DocId) -> Result<Explanation> {
+        self.score.explain(self, self.create_scorer(reader)?, doc)
+    }
+}
+
+impl fmt::Display for FieldExistsWeight {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "FieldExistsWeight({})", &self.query)
+    }
+}
+
+pub const DOC_VALUES_FIELD_EXISTS: &str = "doc_values_field_exists";
+
+/// Matches the docs having doc values in the field, of any type, with a
+/// constant score.
+///
+/// Unlike `FieldExistsQuery` only the docs with field of the doc values are
+/// looked at, a segment without doc values for the field matches no doc.
+#[derive(Clone, Debug)]
+pub struct DocValuesFieldExistsQuery {
+    field: String,
+}
+
+impl DocValuesFieldExistsQuery {
+    pub fn new(field: String) -> DocValuesFieldExistsQuery {
+        DocValuesFieldExistsQuery { field }
+    }
+
+    pub fn field(&self) -> &str {
+        &self.field
+    }
+}
+
+impl<C: Codec> Query<C> for DocValuesFieldExistsQuery {
+    fn create_weight(
+        &self,
+        _searcher: &dyn SearchPlanBuilder<C>,
+        _needs_scores: bool,
+    ) -> Result<Box<dyn Weight<C>>> {
+        Ok(Box::new(DocValuesFieldExistsWeight {
+            query: self.clone(),
+            score: ConstantScore::default(),
+        }))
+    }
+
+    fn extract_terms(&self) -> Vec<TermQuery> {
+        vec![]
+    }
+
+    fn query_type(&self) -> &'static str {
+        DOC_VALUES_FIELD_EXISTS
+    }
+
+    fn as_any(&self) -> &::std::any::Any {
+        self
+    }
+}
+
+impl fmt::Display for DocValuesFieldExistsQuery {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "DocValuesFieldExistsQuery(field: {})", &self.field)
+    }
+}
+
+struct DocValuesFieldExistsWeight {
+    query: DocValuesFieldExistsQuery,
+    score: ConstantScore,
+}
+
+impl<C: Codec> Weight<C> for DocValuesFieldExistsWeight {
+    fn create_scorer(
+        &self,
+        leaf_reader_ctx: &LeafReaderContext<'_, C>,
+    ) -> Result<Option<Box<dyn Scorer>>> {
+        let reader = leaf_reader_ctx.reader;
+        match reader.field_info(&self.query.field) {
+            Some(fi) if fi.doc_values_type != DocValuesType::Null => {}
+            _ => return Ok(None),
+        }
+        let max_doc = reader.max_doc();
+        let bits = reader.get_docs_with_field(&self.query.field)?;
+        Ok(Some(Box::new(ConstantScoreScorer::new(
+            self.score.score(),
+            DocsWithFieldIterator::new(Evidence::Bits(bits), max_doc),
+            max_doc as usize,
+        ))))
+    }
+
+    fn query_type(&self) -> &'static str {
+        DOC_VALUES_FIELD_EXISTS
+    }
+
+    fn normalize(&mut self, norm: f32, boost: f32) {
+        self.score.normalize(norm, boost);
+    }
+
+    fn value_for_normalization(&self) -> f32 {
+        self.score.value_for_normalization()
+    }
+
+    fn needs_scores(&self) -> bool {
+        false
+    }
+
+    fn explain(&self, reader: &LeafReaderContext<'_, C>, doc: DocId) -> Result<Explanation> {
+        self.score.explain(self, self.create_scorer(reader)?, doc)
+    }
+}
+
+impl fmt::Display for DocValuesFieldExistsWeight {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "DocValuesFieldExistsWeight({})", &self.query)
+    }
+}
+
+/// Collects the docs of every point, all cells are inside the query.
+struct AllPointsVisitor<'a> {
+    doc_id_set_builder: &'a mut DocIdSetBuilder,
+}
+
+impl<'a> IntersectVisitor for AllPointsVisitor<'a> {
+    fn visit(&mut self, doc_id: DocId) -> Result<()> {
+        self.doc_id_set_builder.add_doc(doc_id);
+        Ok(())
+    }
+
+    fn visit_by_packed_value(&mut self, doc_id: DocId, _packed_value: &[u8]) -> Result<()> {
+        self.doc_id_set_builder.add_doc(doc_id);
+        Ok(())
+    }
+
+    fn compare(&self, _min_packed_value: &[u8], _max_packed_value: &[u8]) -> Relation {
+        Relation::CellInsideQuery
+    }
+
+    fn grow(&mut self, count: usize) {
+        self.doc_id_set_builder.grow(count);
+    }
+}
+
+enum Evidence {
+    // docs without the field have no norm, which reads as 0
+    Norms(Box<dyn NumericDocValues>),
+    Bits(BitsRef),
+}
+
+/// Iterates the docs the evidence tells having the field.
+struct DocsWithFieldIterator {
+    evidence: Evidence,
+    doc: DocId,
+    max_doc: DocId,
+}
+
+impl DocsWithFieldIterator {
+    fn new(evidence: Evidence, max_doc: DocId) -> DocsWithFieldIterator {
+        DocsWithFieldIterator {
+            evidence,
+            doc: -1,
+            max_doc,
+        }
+    }
+
+    fn matches_doc(&self, doc: DocId) -> Result<bool> {
+        match self.evidence {
+            Evidence::Norms(ref norms) => Ok(norms.get(doc)? != 0),
+            Evidence::Bits(ref bits) => bits.get(doc as usize),
+        }
+    }
+}
+
+impl DocIterator for DocsWithFieldIterator {
+    fn doc_id(&self) -> DocId {
+        self.doc
+    }
+
+    fn next(&mut self) -> Result<DocId> {
+        let target = self.doc + 1;
+        self.advance(target)
+    }
+
+    fn advance(&mut self, target: DocId) -> Result<DocId> {
+        let mut doc = target;
+        while doc < self.max_doc {
+            if self.matches_doc(doc)? {
+                self.doc = doc;
+                return Ok(doc);
+            }
+            doc += 1;
+        }
+        self.doc = NO_MORE_DOCS;
+        Ok(NO_MORE_DOCS)
+    }
+
+    fn cost(&self) -> usize {
+        self.max_doc as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::codec::CodecEnum;
+    use core::doc::{BinaryDocValuesField, SortedNumericDocValuesField, SortedSetDocValuesField};
+    use core::doc::{Field, FieldType, LongPoint, NumericDocValuesField, StoredField};
+    use core::index::index_writer_config::IndexWriterConfig;
+    use core::index::tests::{write_test_index, TestReader};
+    use core::index::{Fieldable, IndexReader};
+    use core::search::searcher::{DefaultIndexSearcher, IndexSearcher};
+    use core::util::VariantValue;
+
+    use std::sync::Arc;
+
+    fn text(name: &str, value: &str) -> Box<dyn Fieldable> {
+        let mut field_type = FieldType::default();
+        field_type.index_options = IndexOptions::DocsAndFreqsAndPositions;
+        Box::new(Field::new(
+            name.to_string(),
+            field_type,
+            Some(VariantValue::VString(value.to_string())),
+            None,
+        ))
+    }
+
+    fn keyword_without_norms(name: &str, value: &str) -> Box<dyn Fieldable> {
+        let mut field_type = FieldType::default();
+        field_type.tokenized = false;
+        field_type.omit_norms = true;
+        field_type.index_options = IndexOptions::Docs;
+        Box::new(Field::new(
+            name.to_string(),
+            field_type,
+            Some(VariantValue::VString(value.to_string())),
+            None,
+        ))
+    }
+
+    fn long_point(name: &str, value: i64) -> Box<dyn Fieldable> {
+        let mut field_type = FieldType::default();
+        field_type.set_dimensions(1, 8).unwrap();
+        Box::new(Field::new_bytes(
+            name.to_string(),
+            LongPoint::pack(&[value]),
+            field_type,
+        ))
+    }
+
+    // The first segment has every field, the second only "id" and sparse
+    // points.
+    fn build_index() -> Arc<TestReader> {
+        write_test_index(IndexWriterConfig::default(), |writer| {
+            for i in 0..30 {
+                let mut doc = vec![keyword_without_norms("id", &i.to_string())];
+                if i % 2 == 0 {
+                    doc.push(text("body", "quick brown fox"));
+                }
+                if i % 3 == 0 {
+                    doc.push(Box::new(NumericDocValuesField::new("price", i)));
+                }
+                if i % 5 == 0 {
+                    doc.push(keyword_without_norms("tag", "red"));
+                }
+                doc.push(long_point("timestamp", i));
+                doc.push(Box::new(
+                    StoredField::new("payload", None, VariantValue::VString("x".into())).field,
+                ));
+                writer.add_document(doc).unwrap();
+            }
+            writer.commit().unwrap();
+            for i in 30..50 {
+                let mut doc = vec![keyword_without_norms("id", &i.to_string())];
+                if i % 4 == 0 {
+                    doc.push(long_point("timestamp", i));
+                }
+                writer.add_document(doc).unwrap();
+            }
+            writer.commit().unwrap();
+        })
+    }
+
+    fn count(reader: &Arc<TestReader>, field: &str) -> Result<usize> {
+        let searcher = DefaultIndexSearcher::new(Arc::clone(reader));
+        searcher.count(&FieldExistsQuery::new(field.to_string()))
+    }
+
+    fn strategy(field: &str, reader: &SearchLeafReader<CodecEnum>) -> Option<FieldExistsStrategy> {
+        FieldExistsQuery::new(field.to_string())
+            .strategy(reader)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_strategy_per_segment() {
+        let reader = build_index();
+        let leaves = reader.leaves();
+        assert_eq!(leaves.len(), 2);
+        let (first, second) = (leaves[0].reader, leaves[1].reader);
+
+        assert_eq!(strategy("body", first), Some(FieldExistsStrategy::Norms));
+        assert_eq!(
+            strategy("price", first),
+            Some(FieldExistsStrategy::DocValues)
+        );
+        assert_eq!(
+            strategy("timestamp", first),
+            Some(FieldExistsStrategy::AllDocs)
+        );
+        assert_eq!(
+            strategy("timestamp", second),
+            Some(FieldExistsStrategy::Points)
+        );
+        assert_eq!(strategy("tag", first), Some(FieldExistsStrategy::Terms));
+        for field in &["body", "price", "tag", "payload", "missing"] {
+            assert_eq!(strategy(field, second), None);
+        }
+        assert!(FieldExistsQuery::new("payload".to_string())
+            .strategy(first)
+            .is_err());
+    }
+
+    #[test]
+    fn test_count_docs_with_field() {
+        let reader = build_index();
+        assert_eq!(count(&reader, "id").unwrap(), 50);
+        assert_eq!(count(&reader, "body").unwrap(), 15);
+        assert_eq!(count(&reader, "price").unwrap(), 10);
+        assert_eq!(count(&reader, "tag").unwrap(), 6);
+        assert_eq!(count(&reader, "timestamp").unwrap(), 30 + 5);
+        assert_eq!(count(&reader, "missing").unwrap(), 0);
+        assert!(count(&reader, "payload").is_err());
+    }
+
+    #[test]
+    fn test_doc_values_field_exists() {
+        let reader = write_test_index(IndexWriterConfig::default(), |writer| {
+            for i in 0..40i64 {
+                let mut doc = vec![keyword_without_norms("id", &i.to_string())];
+                if i % 2 == 0 {
+                    doc.push(Box::new(NumericDocValuesField::new("numeric", i)));
+                }
+                if i % 3 == 0 {
+                    doc.push(Box::new(BinaryDocValuesField::new("binary", b"x")));
+                }
+                if i % 5 == 0 {
+                    doc.push(Box::new(SortedSetDocValuesField::new("sorted_set", b"y")));
+                }
+                if i % 7 == 0 {
+                    doc.push(Box::new(SortedNumericDocValuesField::new(
+                        "sorted_numeric",
+                        i,
+                    )));
+                }
+                writer.add_document(doc).unwrap();
+                if i == 19 {
+                    writer.commit().unwrap();
+                }
+            }
+            // some deleted docs have values of the fields, some not
+            for id in &["0", "2", "9", "10", "21"] {
+                writer
+                    .delete_documents_by_term("id", id.as_bytes())
+                    .unwrap();
+            }
+            writer.commit().unwrap();
+        });
+        assert_eq!(reader.leaves().len(), 2);
+
+        let searcher = DefaultIndexSearcher::new(Arc::clone(&reader));
+        let count = |field: &str| {
+            searcher
+                .count(&DocValuesFieldExistsQuery::new(field.to_string()))
+                .unwrap()
+        };
+        assert_eq!(count("numeric"), 20 - 3);
+        assert_eq!(count("binary"), 14 - 3);
+        assert_eq!(count("sorted_set"), 8 - 2);
+        assert_eq!(count("sorted_numeric"), 6 - 2);
+        // indexed without doc values
+        assert_eq!(count("id"), 0);
+        assert_eq!(count("missing"), 0);
+
+        let query = DocValuesFieldExistsQuery::new("sorted_set".to_string());
+        assert!(searcher.explain(&query, 15).unwrap().is_match());
+        assert!(!searcher.explain(&query, 16).unwrap().is_match());
+    }
+}
@@ -0,0 +1,522 @@
+// Copyright 2019 Zhizhesihai (Beijing) Technology Limited.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use core::codec::Codec;
+use core::index::{LeafReaderContext, SeekStatus, Term, TermIterator, Terms};
+use core::search::boolean_query::BooleanWeight;
+use core::search::query_timeout::{QueryTimeout, DEFAULT_CHECK_INTERVAL};
+use core::search::searcher::SearchPlanBuilder;
+use core::search::statistics::{CollectionStatistics, TermStatistics};
+use core::search::term_query::{TermQuery, TermWeight};
+use core::search::{Query, Weight};
+use core::util::levenshtein::{DfaMatch, LevenshteinAutomata, MAXIMUM_SUPPORTED_DISTANCE};
+
+use error::ErrorKind::IllegalArgument;
+use error::Result;
+
+use std::cmp::{max, Ordering};
+use std::collections::{BinaryHeap, HashSet};
+use std::fmt;
+use std::str;
+
+pub const FUZZY: &str = "fuzzy";
+
+pub const DEFAULT_MAX_EDITS: u32 = MAXIMUM_SUPPORTED_DISTANCE;
+pub const DEFAULT_PREFIX_LENGTH: usize = 0;
+pub const DEFAULT_MAX_EXPANSIONS: usize = 50;
+pub const DEFAULT_TRANSPOSITIONS: bool = true;
+
+/// Matches the terms within `max_edits` edits of a term, the edit distance
+/// being computed over the unicode code points of the terms.
+///
+/// The weight looks up the matching terms of every segment and keeps the
+/// `max_expansions` ones with the highest boost, `1 - edits / length` with the
+/// length of the shorter of the two terms. They are then scored as the
+/// `SHOULD` clauses of a `BooleanQuery`, all with the statistics of the most
+/// frequent one so that rare misspellings don't outscore the common terms.
+#[derive(Clone, Debug)]
+pub struct FuzzyQuery {
+    term: Term,
+    max_edits: u32,
+    prefix_length: usize,
+    max_expansions: usize,
+    transpositions: bool,
+}
+
+impl FuzzyQuery {
+    /// `prefix_length` is the number of leading code points that must match
+    /// exactly.
+    pub fn new(
+        term: Term,
+        max_edits: u32,
+        prefix_length: usize,
+        max_expansions: usize,
+    ) -> Result<FuzzyQuery> {
+        if max_edits > MAXIMUM_SUPPORTED_DISTANCE {
+            bail!(IllegalArgument(format!(
+                "max_edits must be between 0 and {}, got {}",
+                MAXIMUM_SUPPORTED_DISTANCE, max_edits
+            )));
+        }
+        if max_expansions == 0 {
+            bail!(IllegalArgument("max_expansions must be positive".into()));
+        }
+        Ok(FuzzyQuery {
+            term,
+            max_edits,
+            prefix_length,
+            max_expansions,
+            transpositions: DEFAULT_TRANSPOSITIONS,
+        })
+    }
+
+    /// Whether swapping two adjacent code points counts as a single edit.
+    pub fn with_transpositions(mut self, transpositions: bool) -> FuzzyQuery {
+        self.transpositions = transpositions;
+        self
+    }
+
+    pub fn term(&self) -> &Term {
+        &self.term
+    }
+
+    pub fn max_edits(&self) -> u32 {
+        self.max_edits
+    }
+
+    pub fn prefix_length(&self) -> usize {
+        self.prefix_length
+    }
+
+    pub fn max_expansions(&self) -> usize {
+        self.max_expansions
+    }
+
+    /// Returns the terms the query expands to in `leaves` with their boosts,
+    /// the highest boost first.
+    pub fn top_terms<C: Codec>(
+        &self,
+        leaves: &[LeafReaderContext<'_, C>],
+    ) -> Result<Vec<(Vec<u8>, f32)>> {
+        self.collect_top_terms(leaves, None)
+    }
+
+    // enumerates the terms of the leaves, failing with `Cancelled` once
+    // `timeout` is cancelled
+    fn collect_top_terms<C: Codec>(
+        &self,
+        leaves: &[LeafReaderContext<'_, C>],
+        timeout: Option<&QueryTimeout>,
+    ) -> Result<Vec<(Vec<u8>, f32)>> {
+        let text: Vec<char> = self.term.text()?.chars().collect();
+        if self.max_edits == 0 || self.prefix_length >= text.len() {
+            // only the term itself can match
+            for leaf in leaves {
+                if let Some(terms) = leaf.reader.terms(&self.term.field)? {
+                    if terms.iterator()?.seek_exact(&self.term.bytes)? {
+                        return Ok(vec![(self.term.bytes.clone(), 1.0)]);
+                    }
+                }
+            }
+            return Ok(vec![]);
+        }
+
+        let prefix: String = text[..self.prefix_length].iter().collect();
+        let suffix: String = text[self.prefix_length..].iter().collect();
+        let automaton =
+            LevenshteinAutomata::new(&suffix, self.transpositions).to_automaton(self.max_edits)?;
+
+        let mut top_terms = TopTerms::new(self.max_expansions);
+        let mut enumerated = 0;
+        for leaf in leaves {
+            let terms = match leaf.reader.terms(&self.term.field)? {
+                Some(terms) => terms,
+                None => continue,
+            };
+            // seeks over the terms the automaton rejects to the next string it
+            // may accept, instead of running it on every term with the prefix
+            let mut iter = terms.iterator()?;
+            let mut seek_to = if prefix.is_empty() {
+                None
+            } else {
+                Some(prefix.as_bytes().to_vec())
+            };
+            loop {
+                let term = match seek_to.take() {
+                    Some(target) => match iter.seek_ceil(&target)? {
+                        SeekStatus::End => break,
+                        _ => iter.term()?.to_vec(),
+                    },
+                    None => match iter.next()? {
+                        Some(term) => term,
+                        None => break,
+                    },
+                };
+                if !term.starts_with(prefix.as_bytes()) {
+                    break;
+                }
+                if let Some(timeout) = timeout {
+                    if enumerated % DEFAULT_CHECK_INTERVAL == 0 {
+                        timeout.check()?;
+                    }
+                    enumerated += 1;
+                }
+                let candidate = match str::from_utf8(&term[prefix.len()..]) {
+                    Ok(candidate) => candidate,
+                    Err(_) => continue,
+                };
+                match automaton.intersect(candidate) {
+                    DfaMatch::Accepted(edits) => {
+                        let length = prefix.chars().count() + candidate.chars().count();
+                        let boost = if edits == 0 {
+                            1.0
+                        } else {
+                            1.0 - edits as f32 / max(1, length.min(text.len())) as f32
+                        };
+                        top_terms.insert(&term, boost);
+                    }
+                    DfaMatch::Rejected => {}
+                    DfaMatch::SeekTo(next) => {
+                        let mut target = prefix.as_bytes().to_vec();
+                        target.extend_from_slice(next.as_bytes());
+                        seek_to = Some(target);
+                    }
+                    DfaMatch::Exhausted => break,
+                }
+            }
+        }
+        Ok(top_terms.into_sorted_vec())
+    }
+}
+
+impl<C: Codec> Query<C> for FuzzyQuery {
+    fn create_weight(
+        &self,
+        searcher: &dyn SearchPlanBuilder<C>,
+        needs_scores: bool,
+    ) -> Result<Box<dyn Weight<C>>> {
+        let field = &self.term.field;
+        let top_terms = self.collect_top_terms(&searcher.leaves(), searcher.query_timeout())?;
+
+        let mut contexts = Vec::with_capacity(top_terms.len());
+        let mut max_doc_freq = 0;
+        let mut max_total_term_freq = 0i64;
+        for (bytes, boost) in top_terms {
+            let term = Term::new(field.clone(), bytes);
+            let context = searcher.term_state(&term)?;
+            max_doc_freq = max(max_doc_freq, context.doc_freq);
+            max_total_term_freq = if context.total_term_freq == -1 || max_total_term_freq == -1 {
+                -1
+            } else {
+                max(max_total_term_freq, context.total_term_freq)
+            };
+            contexts.push((term, boost, context));
+        }
+
+        let max_doc = i64::from(searcher.max_doc());
+        let collection_stats = if needs_scores {
+            searcher.collections_statistics(field)?
+        } else {
+            CollectionStatistics::new(field.clone(), max_doc, -1, -1, -1)
+        };
+        let mut weights: Vec<Box<dyn Weight<C>>> = Vec::with_capacity(contexts.len());
+        for (term, boost, context) in contexts {
+            // the blended statistics of the terms
+            let term_stats = if needs_scores {
+                TermStatistics::new(
+                    term.bytes.clone(),
+                    i64::from(max_doc_freq),
+                    max_total_term_freq,
+                )
+            } else {
+                TermStatistics::new(term.bytes.clone(), max_doc, -1)
+            };
+            let similarity = searcher.similarity(field, needs_scores);
+            let sim_weight =
+                similarity.compute_weight(&collection_stats, &[term_stats], None, boost);
+            weights.push(Box::new(TermWeight::new(
+                term,
+                context.term_states(),
+                boost,
+                similarity,
+                sim_weight,
+                needs_scores,
+            )));
+        }
+        Ok(Box::new(BooleanWeight::new(
+            vec![],
+            weights,
+            vec![],
+            needs_scores,
+        )))
+    }
+
+    fn extract_terms(&self) -> Vec<TermQuery> {
+        vec![]
+    }
+
+    fn query_type(&self) -> &'static str {
+        FUZZY
+    }
+
+    fn as_any(&self) -> &::std::any::Any {
+        self
+    }
+}
+
+impl fmt::Display for FuzzyQuery {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "FuzzyQuery(field: {}, term: {}, max_edits: {}, prefix_length: {}, max_expansions: {})",
+            &self.term.field,
+            String::from_utf8_lossy(&self.term.bytes),
+            self.max_edits,
+            self.prefix_length,
+            self.max_expansions
+        )
+    }
+}
+
+struct ScoreTerm {
+    term: Vec<u8>,
+    boost: f32,
+}
+
+// the greatest is the least competitive, of the lowest boost and then the
+// greatest term
+impl Ord for ScoreTerm {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .boost
+            .partial_cmp(&self.boost)
+            .unwrap_or(Ordering::Equal)
+            .then_with(|| self.term.cmp(&other.term))
+    }
+}
+
+impl PartialOrd for ScoreTerm {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for ScoreTerm {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for ScoreTerm {}
+
+/// The most competitive terms seen in any segment.
+struct TopTerms {
+    size: usize,
+    queue: BinaryHeap<ScoreTerm>,
+    terms: HashSet<Vec<u8>>,
+}
+
+impl TopTerms {
+    fn new(size: usize) -> TopTerms {
+        TopTerms {
+            size,
+            queue: BinaryHeap::with_capacity(size + 1),
+            terms: HashSet::with_capacity(size + 1),
+        }
+    }
+
+    fn insert(&mut self, term: &[u8], boost: f32) {
+        if self.terms.contains(term) {
+            return;
+        }
+        let score_term = ScoreTerm {
+            term: term.to_vec(),
+            boost,
+        };
+        if self.queue.len() == self.size {
+            if score_term >= *self.queue.peek().unwrap() {
+                return;
+            }
+            let evicted = self.queue.pop().unwrap();
+            self.terms.remove(&evicted.term);
+        }
+        self.terms.insert(score_term.term.clone());
+        self.queue.push(score_term);
+    }
+
+    fn into_sorted_vec(self) -> Vec<(Vec<u8>, f32)> {
+        self.queue
+            .into_sorted_vec()
+            .into_iter()
+            .map(|t| (t.term, t.boost))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::doc::{Field, FieldType};
+    use core::index::tests::{open_test_reader, TestReader};
+    use core::index::{IndexOptions, IndexReader};
+    use core::search::collector::TopDocsCollector;
+    use core::search::searcher::{DefaultIndexSearcher, IndexSearcher};
+    use core::util::VariantValue;
+
+    use std::sync::Arc;
+
+    fn keyword(value: &str) -> Field {
+        let mut field_type = FieldType::default();
+        field_type.tokenized = false;
+        field_type.index_options = IndexOptions::DocsAndFreqs;
+        Field::new(
+            "name".to_string(),
+            field_type,
+            Some(VariantValue::VString(value.to_string())),
+            None,
+        )
+    }
+
+    // two segments, "lucene" is in both
+    fn open_reader() -> Arc<TestReader> {
+        let segments: &[&[&str]] = &[
+            &["lucene", "lucena", "lcuene", "solr"],
+            &["lucen", "lucenexx", "luxxne", "lucene", "café"],
+        ];
+        let reader = open_test_reader(
+            segments
+                .iter()
+                .map(|names| names.iter().map(|name| vec![keyword(name)]).collect())
+                .collect(),
+        );
+        assert_eq!(reader.leaves().len(), 2);
+        reader
+    }
+
+    fn fuzzy(
+        text: &str,
+        max_edits: u32,
+        prefix_length: usize,
+        max_expansions: usize,
+    ) -> FuzzyQuery {
+        let term = Term::new("name".to_string(), text.as_bytes().to_vec());
+        FuzzyQuery::new(term, max_edits, prefix_length, max_expansions).unwrap()
+    }
+
+    fn top_terms(reader: &Arc<TestReader>, query: &FuzzyQuery) -> Vec<(String, f32)> {
+        query
+            .top_terms(&reader.leaves())
+            .unwrap()
+            .into_iter()
+            .map(|(term, boost)| (String::from_utf8(term).unwrap(), boost))
+            .collect()
+    }
+
+    fn terms(reader: &Arc<TestReader>, query: &FuzzyQuery) -> Vec<String> {
+        top_terms(reader, query).into_iter().map(|t| t.0).collect()
+    }
+
+    fn matching_docs(reader: &Arc<TestReader>, query: &FuzzyQuery) -> Vec<(i32, f32)> {
+        let searcher = DefaultIndexSearcher::new(Arc::clone(reader));
+        let mut collector = TopDocsCollector::new(10);
+        searcher.search(query, &mut collector).unwrap();
+        let mut docs: Vec<_> = collector
+            .top_docs()
+            .score_docs()
+            .iter()
+            .map(|d| (d.doc_id(), d.score()))
+            .collect();
+        docs.sort_by_key(|d| d.0);
+        docs
+    }
+
+    fn doc_ids(reader: &Arc<TestReader>, query: &FuzzyQuery) -> Vec<i32> {
+        matching_docs(reader, query)
+            .into_iter()
+            .map(|d| d.0)
+            .collect()
+    }
+
+    #[test]
+    fn test_fuzzy_query_distances() {
+        let reader = open_reader();
+
+        // "lucena", "lcuene" and "lucen" are one edit away
+        let query = fuzzy("lucene", 1, 0, DEFAULT_MAX_EXPANSIONS);
+        assert_eq!(doc_ids(&reader, &query), vec![0, 1, 2, 4, 7]);
+        let query = query.with_transpositions(false);
+        assert_eq!(doc_ids(&reader, &query), vec![0, 1, 4, 7]);
+
+        // "lucenexx" and "luxxne" are two edits away
+        let query = fuzzy("lucene", 2, 0, DEFAULT_MAX_EXPANSIONS);
+        assert_eq!(doc_ids(&reader, &query), vec![0, 1, 2, 4, 5, 6, 7]);
+        assert_eq!(
+            terms(&reader, &query),
+            vec!["lucene", "lcuene", "lucena", "lucen", "lucenexx", "luxxne"]
+        );
+        let boosts: Vec<f32> = top_terms(&reader, &query).iter().map(|t| t.1).collect();
+        let expected = [1.0, 5.0 / 6.0, 5.0 / 6.0, 4.0 / 5.0, 4.0 / 6.0, 4.0 / 6.0];
+        for (boost, expected) in boosts.iter().zip(expected.iter()) {
+            assert!((boost - expected).abs() < 1e-6);
+        }
+
+        // the terms share the statistics of "lucene", the docs of the same length
+        // score as the boosts of their terms
+        let scores = matching_docs(&reader, &query);
+        assert!((scores[0].1 - scores[6].1).abs() < 1e-6);
+        assert!((scores[2].1 / scores[0].1 - 5.0 / 6.0).abs() < 1e-4);
+        assert!((scores[4].1 / scores[0].1 - 4.0 / 6.0).abs() < 1e-4);
+
+        // distances are over code points
+        let query = fuzzy("cafe", 1, 0, DEFAULT_MAX_EXPANSIONS);
+        assert_eq!(
+            top_terms(&reader, &query),
+            vec![("café".to_string(), 0.75)]
+        );
+        assert_eq!(doc_ids(&reader, &query), vec![8]);
+        assert!(doc_ids(&reader, &fuzzy("soap", 1, 0, 10)).is_empty());
+    }
+
+    #[test]
+    fn test_fuzzy_query_expansions() {
+        let reader = open_reader();
+
+        let query = fuzzy("lucene", 2, 0, 2);
+        assert_eq!(terms(&reader, &query), vec!["lucene", "lcuene"]);
+        assert_eq!(doc_ids(&reader, &query), vec![0, 2, 7]);
+        let query = fuzzy("lucene", 2, 0, 3);
+        assert_eq!(doc_ids(&reader, &query), vec![0, 1, 2, 7]);
+
+        // "lcuene" doesn't start with "lu"
+        let query = fuzzy("lucene", 2, 2, DEFAULT_MAX_EXPANSIONS);
+        assert_eq!(
+            terms(&reader, &query),
+            vec!["lucene", "lucena", "lucen", "lucenexx", "luxxne"]
+        );
+
+        // only the term itself matches
+        for query in &[
+            fuzzy("lucene", 0, 0, DEFAULT_MAX_EXPANSIONS),
+            fuzzy("lucene", 2, 6, DEFAULT_MAX_EXPANSIONS),
+            fuzzy("lucene", 2, 10, DEFAULT_MAX_EXPANSIONS),
+        ] {
+            assert_eq!(top_terms(&reader, query), vec![("lucene".to_string(), 1.0)]);
+            assert_eq!(doc_ids(&reader, query), vec![0, 7]);
+        }
+        assert!(doc_ids(&reader, &fuzzy("lucenx", 0, 0, 10)).is_empty());
+
+        let term = Term::new("name".to_string(), b"lucene".to_vec());
+        assert!(FuzzyQuery::new(term.clone(), MAXIMUM_SUPPORTED_DISTANCE + 1, 0, 10).is_err());
+        assert!(FuzzyQuery::new(term, 1, 0, 0).is_err());
+    }
+}
@@ -0,0 +1,310 @@
+// Copyright 2019 Zhizhesihai (Beijing) Technology Limited.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use core::codec::Codec;
+use core::index::LeafReaderContext;
+use core::search::explanation::Explanation;
+use core::search::searcher::SearchPlanBuilder;
+use core::search::term_query::TermQuery;
+use core::search::{Query, Scorer, ScorerSupplier, Weight};
+use core::util::DocId;
+
+use error::Result;
+
+use std::fmt;
+
+pub const INDEX_OR_DOC_VALUES: &str = "index_or_doc_values";
+
+/// Matches the docs of two equivalent queries, one searching the index, e.g.
+/// a `PointRangeQuery`, and one verifying the doc values of each doc, e.g. a
+/// `NumericDocValuesRangeQuery`.
+///
+/// For each segment the cheaper one is used: the index query when it leads
+/// the search or matches few docs, the doc values query when it only has to
+/// verify the few docs matched by a much more selective clause of a
+/// conjunction.
+pub struct IndexOrDocValuesQuery<C: Codec> {
+    index_query: Box<dyn Query<C>>,
+    dv_query: Box<dyn Query<C>>,
+}
+
+impl<C: Codec> IndexOrDocValuesQuery<C> {
+    /// Both queries must match the same docs.
+    pub fn new(
+        index_query: Box<dyn Query<C>>,
+        dv_query: Box<dyn Query<C>>,
+    ) -> IndexOrDocValuesQuery<C> {
+        IndexOrDocValuesQuery {
+            index_query,
+            dv_query,
+        }
+    }
+
+    pub fn index_query(&self) -> &dyn Query<C> {
+        self.index_query.as_ref()
+    }
+
+    pub fn dv_query(&self) -> &dyn Query<C> {
+        self.dv_query.as_ref()
+    }
+}
+
+impl<C: Codec> Query<C> for IndexOrDocValuesQuery<C> {
+    fn create_weight(
+        &self,
+        searcher: &dyn SearchPlanBuilder<C>,
+        needs_scores: bool,
+    ) -> Result<Box<dyn Weight<C>>> {
+        Ok(Box::new(IndexOrDocValuesWeight {
+            index_weight: searcher.create_weight(self.index_query.as_ref(), needs_scores)?,
+            dv_weight: searcher.create_weight(self.dv_query.as_ref(), needs_scores)?,
+        }))
+    }
+
+    fn extract_terms(&self) -> Vec<TermQuery> {
+        self.index_query.extract_terms()
+    }
+
+    fn query_type(&self) -> &'static str {
+        INDEX_OR_DOC_VALUES
+    }
+
+    fn as_any(&self) -> &::std::any::Any {
+        self
+    }
+}
+
+impl<C: Codec> fmt::Display for IndexOrDocValuesQuery<C> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "IndexOrDocValuesQuery(index: {}, dv: {})",
+            &self.index_query, &self.dv_query
+        )
+    }
+}
+
+struct IndexOrDocValuesWeight<C: Codec> {
+    index_weight: Box<dyn Weight<C>>,
+    dv_weight: Box<dyn Weight<C>>,
+}
+
+impl<C: Codec> Weight<C> for IndexOrDocValuesWeight<C> {
+    fn create_scorer(
+        &self,
+        leaf_reader: &LeafReaderContext<'_, C>,
+    ) -> Result<Option<Box<dyn Scorer>>> {
+        // leading the search, the index is always the better choice
+        self.index_weight.create_scorer(leaf_reader)
+    }
+
+    fn scorer_supplier<'a>(
+        &'a self,
+        leaf_reader: &'a LeafReaderContext<'_, C>,
+    ) -> Result<Option<Box<dyn ScorerSupplier + 'a>>> {
+        let index_supplier = match self.index_weight.scorer_supplier(leaf_reader)? {
+            Some(supplier) => supplier,
+            None => return Ok(None),
+        };
+        let dv_supplier = match self.dv_weight.scorer_supplier(leaf_reader)? {
+            Some(supplier) => supplier,
+            None => return Ok(None),
+        };
+        Ok(Some(Box::new(IndexOrDocValuesScorerSupplier {
+            index_supplier,
+            dv_supplier,
+        })))
+    }
+
+    fn count(&self, leaf_reader: &LeafReaderContext<'_, C>) -> Result<Option<usize>> {
+        self.index_weight.count(leaf_reader)
+    }
+
+    fn query_type(&self) -> &'static str {
+        INDEX_OR_DOC_VALUES
+    }
+
+    fn actual_query_type(&self) -> &'static str {
+        self.index_weight.actual_query_type()
+    }
+
+    fn normalize(&mut self, norm: f32, boost: f32) {
+        self.index_weight.normalize(norm, boost);
+        self.dv_weight.normalize(norm, boost);
+    }
+
+    fn value_for_normalization(&self) -> f32 {
+        self.index_weight.value_for_normalization()
+    }
+
+    fn needs_scores(&self) -> bool {
+        self.index_weight.needs_scores()
+    }
+
+    fn explain(&self, reader: &LeafReaderContext<'_, C>, doc: DocId) -> Result<Explanation> {
+        self.index_weight.explain(reader, doc)
+    }
+}
+
+impl<C: Codec> fmt::Display for IndexOrDocValuesWeight<C> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "IndexOrDocValuesWeight(index: {}, dv: {})",
+            &self.index_weight, &self.dv_weight
+        )
+    }
+}
+
+struct IndexOrDocValuesScorerSupplier<'a> {
+    index_supplier: Box<dyn ScorerSupplier + 'a>,
+    dv_supplier: Box<dyn ScorerSupplier + 'a>,
+}
+
+impl<'a> ScorerSupplier for IndexOrDocValuesScorerSupplier<'a> {
+    fn get(self: Box<Self>, lead_cost: usize) -> Result<Box<dyn Scorer>> {
+        // At equal costs the doc values are worse than the index: they check
+        // every candidate while the index reads the matches in bulk, hence an
+        // arbitrary 8x penalty on the doc values.
+        let threshold = self.cost() >> 3;
+        if threshold <= lead_cost {
+            self.index_supplier.get(lead_cost)
+        } else {
+            self.dv_supplier.get(lead_cost)
+        }
+    }
+
+    fn cost(&self) -> usize {
+        self.index_supplier.cost()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::codec::CodecEnum;
+    use core::doc::{LongPoint, NumericDocValuesField, Store, StringField};
+    use core::index::index_writer_config::IndexWriterConfig;
+    use core::index::tests::{write_test_index, TestReader};
+    use core::index::{Fieldable, IndexReader, Term};
+    use core::search::boolean_query::BooleanQuery;
+    use core::search::doc_values_range::NumericDocValuesRangeQuery;
+    use core::search::point_range::PointRangeQuery;
+    use core::search::searcher::{DefaultIndexSearcher, IndexSearcher};
+
+    use std::sync::Arc;
+
+    const NUM_DOCS: i64 = 10_000;
+
+    // doc i has the value i, and one doc in a hundred is "rare"
+    fn open_reader() -> Arc<TestReader> {
+        write_test_index(IndexWriterConfig::default(), |writer| {
+            for i in 0..NUM_DOCS {
+                let tag = if i % 100 == 0 { "rare" } else { "common" };
+                let doc: Vec<Box<dyn Fieldable>> = vec![
+                    Box::new(StringField::new("tag", tag, Store::No)),
+                    Box::new(LongPoint::new("value", &[i]).unwrap()),
+                    Box::new(NumericDocValuesField::new("value", i)),
+                ];
+                writer.add_document(doc).unwrap();
+            }
+            writer.force_merge(1, true).unwrap();
+            writer.commit().unwrap();
+        })
+    }
+
+    fn range(lower: i64, upper: i64) -> IndexOrDocValuesQuery<CodecEnum> {
+        IndexOrDocValuesQuery::new(
+            Box::new(
+                PointRangeQuery::new_long_range("value".to_string(), &[lower], &[upper]).unwrap(),
+            ),
+            Box::new(NumericDocValuesRangeQuery::new(
+                "value".to_string(),
+                lower,
+                upper,
+            )),
+        )
+    }
+
+    // the docs that are "rare" and in the range
+    fn rare_in_range(range: Box<dyn Query<CodecEnum>>) -> Box<dyn Query<CodecEnum>> {
+        let tag = Term::new("tag".to_string(), b"rare".to_vec());
+        BooleanQuery::build(
+            vec![Box::new(TermQuery::new(tag, 1.0, None))],
+            vec![],
+            vec![range],
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_choice_per_lead_cost() {
+        let reader = open_reader();
+        let leaves = reader.leaves();
+        assert_eq!(leaves.len(), 1);
+        let searcher = DefaultIndexSearcher::new(Arc::clone(&reader));
+        // the rare docs lead a conjunction with a cost of 100
+        let lead_cost = 100;
+
+        // the range matches 90% of the docs, verifying the lead docs is cheaper
+        let broad = range(0, NUM_DOCS * 9 / 10 - 1);
+        let weight = searcher.create_weight(&broad, true).unwrap();
+        let supplier = weight.scorer_supplier(&leaves[0]).unwrap().unwrap();
+        assert!(supplier.cost() > (NUM_DOCS / 2) as usize);
+        assert!(supplier.get(lead_cost).unwrap().support_two_phase());
+        // leading, the points are read
+        let supplier = weight.scorer_supplier(&leaves[0]).unwrap().unwrap();
+        assert!(!supplier
+            .get(usize::max_value())
+            .unwrap()
+            .support_two_phase());
+
+        // the range matches 0.1% of the docs, the points are cheaper
+        let narrow = range(0, NUM_DOCS / 1000 - 1);
+        let weight = searcher.create_weight(&narrow, true).unwrap();
+        let supplier = weight.scorer_supplier(&leaves[0]).unwrap().unwrap();
+        assert!(supplier.cost() < (NUM_DOCS / 10) as usize);
+        assert!(!supplier.get(lead_cost).unwrap().support_two_phase());
+    }
+
+    #[test]
+    fn test_same_results() {
+        let reader = open_reader();
+        let searcher = DefaultIndexSearcher::new(Arc::clone(&reader));
+        for &(lower, upper) in &[
+            (0, NUM_DOCS * 9 / 10 - 1),
+            (0, NUM_DOCS / 1000 - 1),
+            (1, 99),
+        ] {
+            let points: Box<dyn Query<CodecEnum>> = Box::new(
+                PointRangeQuery::new_long_range("value".to_string(), &[lower], &[upper]).unwrap(),
+            );
+            let dv: Box<dyn Query<CodecEnum>> = Box::new(NumericDocValuesRangeQuery::new(
+                "value".to_string(),
+                lower,
+                upper,
+            ));
+            let expected_rare = (upper / 100 - (lower + 99) / 100 + 1) as i32;
+            let expected_all = (upper - lower + 1) as i32;
+
+            assert_eq!(searcher.count(&range(lower, upper)).unwrap(), expected_all);
+            let both: Box<dyn Query<CodecEnum>> = Box::new(range(lower, upper));
+            for query in vec![points, dv, both] {
+                assert_eq!(
+                    searcher.count(rare_in_range(query).as_ref()).unwrap(),
+                    expected_rare
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,1180 @@
+// Copyright 2019 Zhizhesihai (Beijing) Technology Limited.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Query time joins.
+//!
+//! `create_join_query` runs a query on the "from" side of the join, collects
+//! the sorted (set) doc values of the "from" field of its hits, and returns a
+//! `JoinQuery` matching the docs having one of these values in the "to"
+//! field, like a primary key joined with foreign keys.
+//!
+//! The values are collected per segment ord and, when the "to" field has
+//! sorted (set) doc values too, joined with the global ords of an
+//! `OrdinalMap` over both fields, so no value is read as bytes. Otherwise the
+//! distinct collected values are looked up and matched with the terms of the
+//! "to" field.
+
+use core::codec::Codec;
+use core::index::{DocValues, DocValuesTermIterator, OrdinalMap, NO_MORE_ORDS};
+use core::index::{DocValuesType, IndexReader, LeafReader, LeafReaderContext, SearchLeafReader};
+use core::index::{SortedSetDocValues, SortedSetDocValuesRef, TermIterator, Terms};
+use core::search::collector::{self, Collector, ParallelLeafCollector, SearchCollector};
+use core::search::collector::{LeafSender, ParallelChannel};
+use core::search::explanation::Explanation;
+use core::search::posting_iterator::PostingIteratorFlags;
+use core::search::searcher::{IndexSearcher, SearchPlanBuilder};
+use core::search::term_query::TermQuery;
+use core::search::{DocIterator, Query, Scorer, Weight, NO_MORE_DOCS};
+use core::util::bit_set::{BitSet, FixedBitSet, ImmutableBitSet};
+use core::util::packed_misc::COMPACT;
+use core::util::{Bits, DocId, LongValues};
+
+use error::ErrorKind::IllegalArgument;
+use error::Result;
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt;
+use std::mem;
+use std::sync::Arc;
+
+pub const JOIN: &str = "join";
+
+/// How the scores of the "from" docs having a value are aggregated into the
+/// score of the "to" docs having it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ScoreMode {
+    /// The joined docs have a constant score.
+    None,
+    /// The average score of the "from" docs.
+    Avg,
+    /// The max score of the "from" docs.
+    Max,
+    /// The sum of the scores of the "from" docs.
+    Total,
+}
+
+// the scores of the "from" docs having a value
+#[derive(Clone, Copy, Debug, Default)]
+struct JoinScore {
+    count: u32,
+    total: f32,
+    max: f32,
+}
+
+impl JoinScore {
+    fn add(&mut self, score: f32) {
+        if self.count == 0 || score > self.max {
+            self.max = score;
+        }
+        self.total += score;
+        self.count += 1;
+    }
+
+    fn merge(&mut self, other: &JoinScore) {
+        if other.count == 0 {
+            return;
+        }
+        if self.count == 0 || other.max > self.max {
+            self.max = other.max;
+        }
+        self.total += other.total;
+        self.count += other.count;
+    }
+
+    fn score(&self, score_mode: ScoreMode) -> f32 {
+        match score_mode {
+            ScoreMode::None => 1.0,
+            ScoreMode::Avg => self.total / self.count as f32,
+            ScoreMode::Max => self.max,
+            ScoreMode::Total => self.total,
+        }
+    }
+}
+
+/// Returns a query matching the docs whose `to_field` has one of the values
+/// of `from_field` in the hits of `from_query`.
+///
+/// `from_field` must have sorted doc values, or sorted set doc values if
+/// `multi_valued`. The query matches with the sorted (set) doc values of
+/// `to_field` if it has some in every segment having it, and with its
+/// indexed terms otherwise. A joined doc having several of the values is
+/// scored with the best of them.
+pub fn create_join_query<C: Codec, S: IndexSearcher<C>>(
+    from_field: &str,
+    multi_valued: bool,
+    to_field: &str,
+    from_query: &dyn Query<C>,
+    searcher: &S,
+    score_mode: ScoreMode,
+) -> Result<JoinQuery> {
+    let leaves = searcher.reader().leaves();
+    let mut collector = JoinValuesCollector::new(from_field, multi_valued, score_mode);
+    searcher.search(from_query, &mut collector)?;
+    let values = if use_global_ordinals(&leaves, from_field, to_field) {
+        JoinValues::Ordinals(Arc::new(GlobalOrdinalsJoin::build(
+            &leaves, from_field, to_field, collector,
+        )?))
+    } else {
+        JoinValues::Terms(Arc::new(collected_terms(&leaves, collector, score_mode)?))
+    };
+    Ok(JoinQuery {
+        from_field: from_field.to_string(),
+        to_field: to_field.to_string(),
+        score_mode,
+        values,
+    })
+}
+
+// the values of a field, `None` if the segment doesn't have the field
+fn join_values<C: Codec>(
+    reader: &SearchLeafReader<C>,
+    field: &str,
+    multi_valued: bool,
+) -> Result<Option<SortedSetDocValuesRef>> {
+    let values: SortedSetDocValuesRef = match reader.field_info(field).map(|fi| fi.doc_values_type)
+    {
+        Some(DocValuesType::Sorted) => Arc::new(DocValues::singleton_sorted_doc_values(
+            reader.get_sorted_doc_values(field)?,
+        )),
+        Some(DocValuesType::SortedSet) if multi_valued => {
+            reader.get_sorted_set_doc_values(field)?
+        }
+        Some(DocValuesType::SortedSet) => bail!(IllegalArgument(format!(
+            "field '{}' has several values per doc, it must be joined as multi valued",
+            field
+        ))),
+        Some(_) => bail!(IllegalArgument(format!(
+            "field '{}' has no sorted doc values to join on",
+            field
+        ))),
+        None => return Ok(None),
+    };
+    Ok(Some(values))
+}
+
+// true if both fields have sorted (set) doc values in every segment having
+// them, and at least one segment has the "to" field
+fn use_global_ordinals<C: Codec>(
+    leaves: &[LeafReaderContext<'_, C>],
+    from_field: &str,
+    to_field: &str,
+) -> bool {
+    let sorted = |leaf: &LeafReaderContext<'_, C>, field: &str| match leaf
+        .reader
+        .field_info(field)
+        .map(|fi| fi.doc_values_type)
+    {
+        Some(DocValuesType::Sorted) | Some(DocValuesType::SortedSet) | None => true,
+        _ => false,
+    };
+    leaves
+        .iter()
+        .all(|leaf| sorted(leaf, from_field) && sorted(leaf, to_field))
+        && leaves
+            .iter()
+            .any(|leaf| leaf.reader.field_info(to_field).is_some())
+}
+
+// the distinct collected values, sorted, with their scores
+fn collected_terms<C: Codec>(
+    leaves: &[LeafReaderContext<'_, C>],
+    collector: JoinValuesCollector,
+    score_mode: ScoreMode,
+) -> Result<Vec<(Vec<u8>, f32)>> {
+    let mut terms: HashMap<Vec<u8>, JoinScore> = HashMap::new();
+    for (leaf, scores) in collector.segment_scores {
+        let values = match join_values(leaves[leaf].reader, &collector.field, true)? {
+            Some(values) => values,
+            None => continue,
+        };
+        for (ord, score) in scores.iter().enumerate() {
+            if score.count > 0 {
+                let term = values.lookup_ord(ord as i64)?;
+                terms.entry(term).or_default().merge(score);
+            }
+        }
+    }
+    let mut terms: Vec<(Vec<u8>, f32)> = terms
+        .into_iter()
+        .map(|(term, score)| (term, score.score(score_mode)))
+        .collect();
+    terms.sort_by(|t1, t2| t1.0.cmp(&t2.0));
+    Ok(terms)
+}
+
+/// The collected values as global ords of the values of both fields.
+struct GlobalOrdinalsJoin {
+    core_keys: Vec<String>,
+    // the values of the "from" field in every segment, then the values of
+    // the "to" field in every segment
+    ordinal_map: OrdinalMap,
+    // global ord -> scores, the values not collected have a zero count
+    scores: Vec<JoinScore>,
+}
+
+impl GlobalOrdinalsJoin {
+    fn build<C: Codec>(
+        leaves: &[LeafReaderContext<'_, C>],
+        from_field: &str,
+        to_field: &str,
+        collector: JoinValuesCollector,
+    ) -> Result<GlobalOrdinalsJoin> {
+        let mut subs = Vec::with_capacity(leaves.len() * 2);
+        let mut weights = Vec::with_capacity(leaves.len() * 2);
+        for field in &[from_field, to_field] {
+            for leaf in leaves {
+                match join_values(leaf.reader, field, true)? {
+                    Some(values) => {
+                        subs.push(Some(values.term_iterator()?));
+                        weights.push(values.get_value_count());
+                    }
+                    None => {
+                        subs.push(Some(DocValuesTermIterator::empty()));
+                        weights.push(0);
+                    }
+                }
+            }
+        }
+        let ordinal_map = OrdinalMap::build(subs, weights, COMPACT)?;
+        let mut scores = vec![JoinScore::default(); ordinal_map.value_count() as usize];
+        for (leaf, segment_scores) in collector.segment_scores {
+            let global_ords = ordinal_map.get_global_ords(leaf);
+            for (ord, score) in segment_scores.iter().enumerate() {
+                if score.count > 0 {
+                    scores[global_ords.get64(ord as i64)? as usize].merge(score);
+                }
+            }
+        }
+        Ok(GlobalOrdinalsJoin {
+            core_keys: leaves
+                .iter()
+                .map(|leaf| leaf.reader.core_cache_key().to_string())
+                .collect(),
+            ordinal_map,
+            scores,
+        })
+    }
+}
+
+#[derive(Clone)]
+enum JoinValues {
+    // sorted by value
+    Terms(Arc<Vec<(Vec<u8>, f32)>>),
+    Ordinals(Arc<GlobalOrdinalsJoin>),
+}
+
+/// Matches the docs having one of the values collected by
+/// `create_join_query` in a field.
+///
+/// A query joining with global ords only matches the docs of the reader it
+/// was created with.
+#[derive(Clone)]
+pub struct JoinQuery {
+    from_field: String,
+    to_field: String,
+    score_mode: ScoreMode,
+    values: JoinValues,
+}
+
+impl JoinQuery {
+    pub fn from_field(&self) -> &str {
+        &self.from_field
+    }
+
+    pub fn to_field(&self) -> &str {
+        &self.to_field
+    }
+
+    pub fn score_mode(&self) -> ScoreMode {
+        self.score_mode
+    }
+
+    /// True if the query joins with the global ords of the doc values of the
+    /// fields rather than with the terms of the "to" field.
+    pub fn uses_global_ordinals(&self) -> bool {
+        match self.values {
+            JoinValues::Ordinals(_) => true,
+            JoinValues::Terms(_) => false,
+        }
+    }
+
+    /// Number of distinct values of the "from" field collected.
+    pub fn value_count(&self) -> usize {
+        match self.values {
+            JoinValues::Terms(ref terms) => terms.len(),
+            JoinValues::Ordinals(ref join) => join.scores.iter().filter(|s| s.count > 0).count(),
+        }
+    }
+}
+
+impl<C: Codec> Query<C> for JoinQuery {
+    fn create_weight(
+        &self,
+        _searcher: &dyn SearchPlanBuilder<C>,
+        needs_scores: bool,
+    ) -> Result<Box<dyn Weight<C>>> {
+        Ok(Box::new(JoinWeight {
+            query: self.clone(),
+            needs_scores: needs_scores && self.score_mode != ScoreMode::None,
+            boost: 1f32,
+            norm: 1f32,
+        }))
+    }
+
+    fn extract_terms(&self) -> Vec<TermQuery> {
+        vec![]
+    }
+
+    fn query_type(&self) -> &'static str {
+        JOIN
+    }
+
+    fn as_any(&self) -> &::std::any::Any {
+        self
+    }
+}
+
+impl fmt::Display for JoinQuery {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "JoinQuery(from: {}, to: {}, score_mode: {:?}, values: {})",
+            &self.from_field,
+            &self.to_field,
+            self.score_mode,
+            self.value_count()
+        )
+    }
+}
+
+struct JoinWeight {
+    query: JoinQuery,
+    needs_scores: bool,
+    boost: f32,
+    norm: f32,
+}
+
+impl JoinWeight {
+    fn terms_scorer<C: Codec>(
+        &self,
+        reader: &SearchLeafReader<C>,
+        terms: &[(Vec<u8>, f32)],
+    ) -> Result<Option<JoinScorer>> {
+        let field_terms = match reader.terms(&self.query.to_field)? {
+            Some(terms) => terms,
+            None => return Ok(None),
+        };
+        let mut scorer = JoinScorer::new(reader.max_doc(), self.needs_scores);
+        let mut iter = field_terms.iterator()?;
+        for &(ref term, score) in terms {
+            if iter.seek_exact(term)? {
+                let mut postings = iter.postings_with_flags(PostingIteratorFlags::NONE)?;
+                loop {
+                    let doc = postings.next()?;
+                    if doc == NO_MORE_DOCS {
+                        break;
+                    }
+                    scorer.add(doc, score)?;
+                }
+            }
+        }
+        scorer.finish();
+        Ok(Some(scorer))
+    }
+
+    fn global_ordinals_scorer<C: Codec>(
+        &self,
+        reader_ctx: &LeafReaderContext<'_, C>,
+        join: &GlobalOrdinalsJoin,
+    ) -> Result<Option<JoinScorer>> {
+        let leaf_count = join.core_keys.len();
+        if reader_ctx.ord >= leaf_count
+            || reader_ctx.reader.core_cache_key() != join.core_keys[reader_ctx.ord]
+        {
+            bail!(IllegalArgument(
+                "the join query is searched on another reader than the one it was created with"
+                    .into()
+            ));
+        }
+        let values = match join_values(reader_ctx.reader, &self.query.to_field, true)? {
+            Some(values) => values,
+            None => return Ok(None),
+        };
+        let global_ords = join
+            .ordinal_map
+            .get_global_ords(leaf_count + reader_ctx.ord);
+        let max_doc = reader_ctx.reader.max_doc();
+        let mut scorer = JoinScorer::new(max_doc, self.needs_scores);
+        for doc in 0..max_doc {
+            let mut ctx = values.set_document(doc)?;
+            loop {
+                let ord = values.next_ord(&mut ctx)?;
+                if ord == NO_MORE_ORDS {
+                    break;
+                }
+                let score = &join.scores[global_ords.get64(ord)? as usize];
+                if score.count > 0 {
+                    scorer.add(doc, score.score(self.query.score_mode))?;
+                }
+            }
+        }
+        scorer.finish();
+        Ok(Some(scorer))
+    }
+}
+
+impl<C: Codec> Weight<C> for JoinWeight {
+    fn create_scorer(
+        &self,
+        leaf_reader_ctx: &LeafReaderContext<'_, C>,
+    ) -> Result<Option<Box<dyn Scorer>>> {
+        let scorer = match self.query.values {
+            JoinValues::Terms(ref terms) => {
+                if terms.is_empty() {
+                    return Ok(None);
+                }
+                self.terms_scorer(leaf_reader_ctx.reader, terms)?
+            }
+            JoinValues::Ordinals(ref join) => self.global_ordinals_scorer(leaf_reader_ctx, join)?,
+        };
+        let mut scorer = match scorer {
+            Some(scorer) => scorer,
+            None => return Ok(None),
+        };
+        if scorer.cost == 0 {
+            return Ok(None);
+        }
+        scorer.weight = if self.needs_scores {
+            self.boost
+        } else {
+            self.boost * self.norm
+        };
+        Ok(Some(Box::new(scorer)))
+    }
+
+    fn query_type(&self) -> &'static str {
+        JOIN
+    }
+
+    fn normalize(&mut self, norm: f32, boost: f32) {
+        self.boost = boost;
+        self.norm = norm;
+    }
+
+    fn value_for_normalization(&self) -> f32 {
+        self.boost * self.boost
+    }
+
+    fn needs_scores(&self) -> bool {
+        self.needs_scores
+    }
+
+    fn explain(&self, reader: &LeafReaderContext<'_, C>, doc: DocId) -> Result<Explanation> {
+        if let Some(mut scorer) = self.create_scorer(reader)? {
+            if scorer.advance(doc)? == doc {
+                return Ok(Explanation::new(
+                    true,
+                    scorer.score()?,
+                    format!(
+                        "{}, joined with score mode {:?}",
+                        self, self.query.score_mode
+                    ),
+                    vec![],
+                ));
+            }
+        }
+        Ok(Explanation::new(
+            false,
+            0f32,
+            format!("{} doesn't match id {}", self, doc),
+            vec![],
+        ))
+    }
+}
+
+impl fmt::Display for JoinWeight {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "JoinWeight({})", &self.query)
+    }
+}
+
+// the joined docs of a segment with their best score
+enum JoinedDocs {
+    // (doc, score) in the order they were added, `JoinScorer::finish` sorts
+    // them and keeps the best score of every doc
+    Sparse(Vec<(DocId, f32)>),
+    // `scores` is empty if the scores aren't needed
+    Dense { docs: FixedBitSet, scores: Vec<f32> },
+}
+
+/// Iterates the joined docs of a segment with their best score.
+///
+/// The docs are kept in a list until they are more than 1/128th of the
+/// segment, then in a bit set and an array of scores sized to the segment,
+/// so a join matching few docs doesn't allocate per doc of the segment.
+struct JoinScorer {
+    docs: JoinedDocs,
+    needs_scores: bool,
+    weight: f32,
+    doc: DocId,
+    // the position of `doc` in the sparse docs
+    index: usize,
+    max_doc: DocId,
+    cost: usize,
+}
+
+impl JoinScorer {
+    fn new(max_doc: DocId, needs_scores: bool) -> JoinScorer {
+        JoinScorer {
+            docs: JoinedDocs::Sparse(vec![]),
+            needs_scores,
+            weight: 1f32,
+            doc: -1,
+            index: 0,
+            max_doc,
+            cost: 0,
+        }
+    }
+
+    fn add(&mut self, doc: DocId, score: f32) -> Result<()> {
+        match self.docs {
+            JoinedDocs::Sparse(ref mut hits) => {
+                hits.push((doc, score));
+                if hits.len() <= self.max_doc as usize >> 7 {
+                    return Ok(());
+                }
+            }
+            JoinedDocs::Dense {
+                ref mut docs,
+                ref mut scores,
+            } => {
+                if Self::add_dense(docs, scores, doc, score)? {
+                    self.cost += 1;
+                }
+                return Ok(());
+            }
+        }
+        self.densify()
+    }
+
+    // sets the bit of `doc` and keeps its best score, returns whether the doc
+    // wasn't there yet
+    fn add_dense(
+        docs: &mut FixedBitSet,
+        scores: &mut [f32],
+        doc: DocId,
+        score: f32,
+    ) -> Result<bool> {
+        let index = doc as usize;
+        if docs.get(index)? {
+            if !scores.is_empty() {
+                scores[index] = scores[index].max(score);
+            }
+            Ok(false)
+        } else {
+            docs.set(index);
+            if !scores.is_empty() {
+                scores[index] = score;
+            }
+            Ok(true)
+        }
+    }
+
+    fn densify(&mut self) -> Result<()> {
+        let max_doc = self.max_doc as usize;
+        let mut docs = FixedBitSet::new(max_doc);
+        let mut scores = if self.needs_scores {
+            vec![0f32; max_doc]
+        } else {
+            vec![]
+        };
+        self.cost = 0;
+        if let JoinedDocs::Sparse(ref hits) = self.docs {
+            for &(doc, score) in hits {
+                if Self::add_dense(&mut docs, &mut scores, doc, score)? {
+                    self.cost += 1;
+                }
+            }
+        }
+        self.docs = JoinedDocs::Dense { docs, scores };
+        Ok(())
+    }
+
+    // to be called once all the docs are added
+    fn finish(&mut self) {
+        if let JoinedDocs::Sparse(ref mut hits) = self.docs {
+            // the best score of a doc first
+            hits.sort_by(|a, b| {
+                a.0.cmp(&b.0)
+                    .then(b.1.partial_cmp(&a.1).unwrap_or(Ordering::Equal))
+            });
+            hits.dedup_by_key(|hit| hit.0);
+            self.cost = hits.len();
+        }
+    }
+}
+
+impl Scorer for JoinScorer {
+    fn score(&mut self) -> Result<f32> {
+        if !self.needs_scores {
+            return Ok(self.weight);
+        }
+        let score = match self.docs {
+            JoinedDocs::Sparse(ref hits) => hits[self.index].1,
+            JoinedDocs::Dense { ref scores, .. } => scores[self.doc as usize],
+        };
+        Ok(self.weight * score)
+    }
+}
+
+impl DocIterator for JoinScorer {
+    fn doc_id(&self) -> DocId {
+        self.doc
+    }
+
+    fn next(&mut self) -> Result<DocId> {
+        let target = self.doc + 1;
+        self.advance(target)
+    }
+
+    fn advance(&mut self, target: DocId) -> Result<DocId> {
+        self.doc = match self.docs {
+            JoinedDocs::Sparse(ref hits) => {
+                while self.index < hits.len() && hits[self.index].0 < target {
+                    self.index += 1;
+                }
+                hits.get(self.index).map_or(NO_MORE_DOCS, |hit| hit.0)
+            }
+            JoinedDocs::Dense { ref docs, .. } => {
+                if target >= self.max_doc {
+                    NO_MORE_DOCS
+                } else {
+                    docs.next_set_bit(target as usize)
+                }
+            }
+        };
+        Ok(self.doc)
+    }
+
+    fn cost(&self) -> usize {
+        self.cost
+    }
+}
+
+// adds the score of `doc` to the scores of its segment ords
+fn collect_doc(
+    values: &dyn SortedSetDocValues,
+    doc: DocId,
+    score: f32,
+    scores: &mut [JoinScore],
+) -> Result<()> {
+    let mut ctx = values.set_document(doc)?;
+    loop {
+        let ord = values.next_ord(&mut ctx)?;
+        if ord == NO_MORE_ORDS {
+            return Ok(());
+        }
+        scores[ord as usize].add(score);
+    }
+}
+
+/// Collects the scores of the values of the "from" field of the hits, per
+/// segment ord in an array sized to the value count of the segment.
+struct JoinValuesCollector {
+    field: String,
+    multi_valued: bool,
+    needs_scores: bool,
+    // leaf ord -> scores per segment ord
+    segment_scores: Vec<(usize, Vec<JoinScore>)>,
+    // the values of the current leaf
+    values: Option<SortedSetDocValuesRef>,
+    channel: ParallelChannel<(usize, Vec<JoinScore>)>,
+}
+
+impl JoinValuesCollector {
+    fn new(field: &str, multi_valued: bool, score_mode: ScoreMode) -> JoinValuesCollector {
+        JoinValuesCollector {
+            field: field.to_string(),
+            multi_valued,
+            needs_scores: score_mode != ScoreMode::None,
+            segment_scores: vec![],
+            values: None,
+            channel: ParallelChannel::default(),
+        }
+    }
+}
+
+impl SearchCollector for JoinValuesCollector {
+    type LC = JoinValuesLeafCollector;
+
+    fn set_next_reader<C: Codec>(&mut self, reader: &LeafReaderContext<'_, C>) -> Result<()> {
+        self.values = join_values(reader.reader, &self.field, self.multi_valued)?;
+        if let Some(ref values) = self.values {
+            self.segment_scores.push((
+                reader.ord,
+                vec![JoinScore::default(); values.get_value_count()],
+            ));
+        }
+        Ok(())
+    }
+
+    fn support_parallel(&self) -> bool {
+        true
+    }
+
+    fn leaf_collector<C: Codec>(
+        &mut self,
+        reader: &LeafReaderContext<'_, C>,
+    ) -> Result<JoinValuesLeafCollector> {
+        let values = join_values(reader.reader, &self.field, self.multi_valued)?;
+        let scores = values
+            .as_ref()
+            .map_or(vec![], |v| vec![JoinScore::default(); v.get_value_count()]);
+        Ok(JoinValuesLeafCollector {
+            leaf: reader.ord,
+            values,
+            needs_scores: self.needs_scores,
+            scores,
+            sender: self.channel.sender(),
+        })
+    }
+
+    fn finish_parallel(&mut self) -> Result<()> {
+        self.segment_scores.extend(self.channel.receive());
+        Ok(())
+    }
+}
+
+impl Collector for JoinValuesCollector {
+    fn score_mode(&self) -> collector::ScoreMode {
+        if self.needs_scores {
+            collector::ScoreMode::Complete
+        } else {
+            collector::ScoreMode::CompleteNoScores
+        }
+    }
+
+    fn collect<S: Scorer + ?Sized>(&mut self, doc: DocId, scorer: &mut S) -> Result<()> {
+        if let Some(ref values) = self.values {
+            let score = if self.needs_scores {
+                scorer.score()?
+            } else {
+                0f32
+            };
+            let scores = &mut self.segment_scores.last_mut().unwrap().1;
+            collect_doc(&**values, doc, score, scores)?;
+        }
+        Ok(())
+    }
+}
+
+struct JoinValuesLeafCollector {
+    leaf: usize,
+    values: Option<SortedSetDocValuesRef>,
+    needs_scores: bool,
+    scores: Vec<JoinScore>,
+    sender: LeafSender<(usize, Vec<JoinScore>)>,
+}
+
+impl Collector for JoinValuesLeafCollector {
+    fn score_mode(&self) -> collector::ScoreMode {
+        if self.needs_scores {
+            collector::ScoreMode::Complete
+        } else {
+            collector::ScoreMode::CompleteNoScores
+        }
+    }
+
+    fn collect<S: Scorer + ?Sized>(&mut self, doc: DocId, scorer: &mut S) -> Result<()> {
+        if let Some(ref values) = self.values {
+            let score = if self.needs_scores {
+                scorer.score()?
+            } else {
+                0f32
+            };
+            collect_doc(&**values, doc, score, &mut self.scores)?;
+        }
+        Ok(())
+    }
+}
+
+impl ParallelLeafCollector for JoinValuesLeafCollector {
+    fn finish_leaf(&mut self) -> Result<()> {
+        if self.values.is_none() {
+            return Ok(());
+        }
+        let scores = mem::replace(&mut self.scores, vec![]);
+        self.sender.send((self.leaf, scores))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::codec::CodecEnum;
+    use core::doc::SORTED_DOC_VALUES_FIELD_TYPE;
+    use core::doc::{Field, SortedSetDocValuesField, Store, StringField, TextField};
+    use core::index::tests::{open_test_reader, TestReader};
+    use core::index::{Fieldable, Term};
+    use core::search::collector::top_docs::TopDocsCollector;
+    use core::search::searcher::DefaultIndexSearcher;
+    use core::util::VariantValue;
+
+    use std::collections::BTreeMap;
+
+    enum TestDoc {
+        Customer(&'static str),
+        Product(&'static str),
+        // customer, items, products
+        Order(&'static str, &'static str, &'static [&'static str]),
+    }
+
+    use self::TestDoc::*;
+
+    // the order of "c9" has no customer
+    const SEGMENTS: &[&[TestDoc]] = &[
+        &[
+            Customer("c1"),
+            Customer("c2"),
+            Order("c1", "book book", &["p1", "p2"]),
+            Order("c1", "book", &["p2"]),
+        ],
+        &[
+            Customer("c3"),
+            Customer("c4"),
+            Order("c2", "book pen", &["p3"]),
+            Order("c3", "pen", &["p1", "p3"]),
+            Product("p1"),
+            Product("p2"),
+        ],
+        &[
+            Order("c2", "book book book", &["p2", "p4"]),
+            Product("p3"),
+            Product("p4"),
+            Order("c9", "pen", &[]),
+        ],
+    ];
+
+    fn doc(doc_id: DocId) -> &'static TestDoc {
+        SEGMENTS
+            .iter()
+            .flat_map(|segment| segment.iter())
+            .nth(doc_id as usize)
+            .unwrap()
+    }
+
+    // "id" and "customer" have sorted doc values, "products" has sorted set
+    // doc values and the "key" fields are only indexed
+    fn values(doc: &TestDoc, field: &str) -> Vec<&'static str> {
+        match (doc, field) {
+            (&Customer(id), "id") | (&Customer(id), "key") => vec![id],
+            (&Product(id), "id") | (&Product(id), "key") => vec![id],
+            (&Order(customer, _, _), "customer") | (&Order(customer, _, _), "customer_key") => {
+                vec![customer]
+            }
+            (&Order(_, _, products), "products") | (&Order(_, _, products), "product_keys") => {
+                products.to_vec()
+            }
+            _ => vec![],
+        }
+    }
+
+    fn sorted_field(name: &str, value: &str) -> Box<dyn Fieldable> {
+        Box::new(Field::new(
+            name.to_string(),
+            SORTED_DOC_VALUES_FIELD_TYPE,
+            Some(VariantValue::from(value.as_bytes())),
+            None,
+        ))
+    }
+
+    fn fields(doc: &TestDoc) -> Vec<Box<dyn Fieldable>> {
+        let mut fields: Vec<Box<dyn Fieldable>> = vec![];
+        match *doc {
+            Customer(id) | Product(id) => {
+                let kind = match *doc {
+                    Customer(_) => "customer",
+                    _ => "product",
+                };
+                fields.push(Box::new(StringField::new("type", kind, Store::No)));
+                fields.push(Box::new(StringField::new("id", id, Store::No)));
+                fields.push(sorted_field("id", id));
+                fields.push(Box::new(StringField::new("key", id, Store::No)));
+            }
+            Order(customer, items, products) => {
+                fields.push(Box::new(StringField::new("type", "order", Store::No)));
+                fields.push(Box::new(StringField::new("customer", customer, Store::No)));
+                fields.push(sorted_field("customer", customer));
+                fields.push(Box::new(StringField::new(
+                    "customer_key",
+                    customer,
+                    Store::No,
+                )));
+                fields.push(Box::new(TextField::new("items", items, Store::No)));
+                for product in products {
+                    fields.push(Box::new(StringField::new("products", product, Store::No)));
+                    fields.push(Box::new(SortedSetDocValuesField::new(
+                        "products",
+                        product.as_bytes(),
+                    )));
+                    fields.push(Box::new(StringField::new(
+                        "product_keys",
+                        product,
+                        Store::No,
+                    )));
+                }
+            }
+        }
+        fields
+    }
+
+    fn build_index() -> Arc<TestReader> {
+        let segments = SEGMENTS
+            .iter()
+            .map(|segment| segment.iter().map(fields).collect())
+            .collect();
+        open_test_reader(segments)
+    }
+
+    fn term_query(field: &str, value: &str) -> TermQuery {
+        TermQuery::new(
+            Term::new(field.to_string(), value.as_bytes().to_vec()),
+            1.0,
+            None,
+        )
+    }
+
+    fn hits<S: IndexSearcher<CodecEnum>>(
+        searcher: &S,
+        query: &dyn Query<CodecEnum>,
+    ) -> BTreeMap<DocId, f32> {
+        let mut collector = TopDocsCollector::new(100);
+        searcher.search(query, &mut collector).unwrap();
+        collector
+            .top_docs()
+            .score_docs()
+            .iter()
+            .map(|d| (d.doc_id(), d.score()))
+            .collect()
+    }
+
+    // joins the hits of the "from" query by brute force
+    fn expected_join(
+        from_hits: &BTreeMap<DocId, f32>,
+        from_field: &str,
+        to_field: &str,
+        score_mode: ScoreMode,
+    ) -> BTreeMap<DocId, f32> {
+        let mut scores: HashMap<&str, Vec<f32>> = HashMap::new();
+        for (&doc_id, &score) in from_hits {
+            for value in values(doc(doc_id), from_field) {
+                scores.entry(value).or_insert_with(Vec::new).push(score);
+            }
+        }
+        let num_docs = SEGMENTS.iter().map(|s| s.len()).sum::<usize>() as DocId;
+        let mut joined = BTreeMap::new();
+        for doc_id in 0..num_docs {
+            let mut best: Option<f32> = None;
+            for value in values(doc(doc_id), to_field) {
+                if let Some(scores) = scores.get(value) {
+                    let score = match score_mode {
+                        ScoreMode::None => 1.0,
+                        ScoreMode::Avg => scores.iter().sum::<f32>() / scores.len() as f32,
+                        ScoreMode::Max => scores.iter().cloned().fold(::std::f32::MIN, f32::max),
+                        ScoreMode::Total => scores.iter().sum(),
+                    };
+                    best = Some(best.map_or(score, |b| b.max(score)));
+                }
+            }
+            if let Some(score) = best {
+                joined.insert(doc_id, score);
+            }
+        }
+        joined
+    }
+
+    fn check_join<S: IndexSearcher<CodecEnum>>(
+        searcher: &S,
+        from_field: &str,
+        multi_valued: bool,
+        to_field: &str,
+        from_query: &TermQuery,
+        expected_docs: &[DocId],
+    ) {
+        let from_hits = hits(searcher, from_query);
+        for &score_mode in &[
+            ScoreMode::None,
+            ScoreMode::Avg,
+            ScoreMode::Max,
+            ScoreMode::Total,
+        ] {
+            let query = create_join_query(
+                from_field,
+                multi_valued,
+                to_field,
+                from_query,
+                searcher,
+                score_mode,
+            )
+            .unwrap();
+            assert_eq!(query.uses_global_ordinals(), !to_field.contains("key"));
+
+            let expected = expected_join(&from_hits, from_field, to_field, score_mode);
+            let joined = hits(searcher, &query);
+            assert_eq!(expected.keys().cloned().collect::<Vec<_>>(), expected_docs);
+            assert_eq!(joined.keys().cloned().collect::<Vec<_>>(), expected_docs);
+            if score_mode != ScoreMode::None {
+                for (doc_id, score) in &joined {
+                    assert!(
+                        (score - expected[doc_id]).abs() < 1e-5,
+                        "{:?} score of {}: {} != {}",
+                        score_mode,
+                        doc_id,
+                        score,
+                        expected[doc_id]
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_join_scorer_sparse_and_dense() {
+        // added out of doc order, every doc twice with different scores
+        let hits: Vec<(DocId, f32)> = (0..40)
+            .map(|i| ((i * 37) % 25 * 10, (i % 7) as f32))
+            .collect();
+        let mut expected: BTreeMap<DocId, f32> = BTreeMap::new();
+        for &(doc, score) in &hits {
+            let best = expected.entry(doc).or_insert(score);
+            *best = best.max(score);
+        }
+        // 40 docs are less than 1/128th of the first segment only
+        for &max_doc in &[10_000, 1000] {
+            let mut scorer = JoinScorer::new(max_doc, true);
+            for &(doc, score) in &hits {
+                scorer.add(doc, score).unwrap();
+            }
+            scorer.finish();
+            let sparse = match scorer.docs {
+                JoinedDocs::Sparse(_) => true,
+                JoinedDocs::Dense { .. } => false,
+            };
+            assert_eq!(sparse, max_doc == 10_000);
+            assert_eq!(scorer.cost(), expected.len());
+
+            let mut joined = vec![];
+            loop {
+                let doc = scorer.next().unwrap();
+                if doc == NO_MORE_DOCS {
+                    break;
+                }
+                joined.push((doc, scorer.score().unwrap()));
+            }
+            assert_eq!(joined, expected.clone().into_iter().collect::<Vec<_>>());
+        }
+    }
+
+    #[test]
+    fn test_join_orders_to_customers() {
+        let searcher = DefaultIndexSearcher::new(build_index());
+        for to_field in &["id", "key"] {
+            // c1 and c2 bought books
+            check_join(
+                &searcher,
+                "customer",
+                false,
+                to_field,
+                &term_query("items", "book"),
+                &[0, 1],
+            );
+            // c2 and c3 bought pens, the order of c9 joins nothing
+            check_join(
+                &searcher,
+                "customer",
+                false,
+                to_field,
+                &term_query("items", "pen"),
+                &[1, 4],
+            );
+        }
+    }
+
+    #[test]
+    fn test_join_customers_to_orders() {
+        let searcher = DefaultIndexSearcher::new(build_index());
+        for to_field in &["customer", "customer_key"] {
+            check_join(
+                &searcher,
+                "id",
+                false,
+                to_field,
+                &term_query("type", "customer"),
+                &[2, 3, 6, 7, 10],
+            );
+            check_join(
+                &searcher,
+                "id",
+                false,
+                to_field,
+                &term_query("id", "c2"),
+                &[6, 10],
+            );
+        }
+
+        let query = create_join_query(
+            "id",
+            false,
+            "customer",
+            &term_query("id", "c5"),
+            &searcher,
+            ScoreMode::Max,
+        )
+        .unwrap();
+        assert_eq!(query.value_count(), 0);
+        assert_eq!(searcher.count(&query).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_join_multi_valued() {
+        let searcher = DefaultIndexSearcher::new(build_index());
+        for to_field in &["id", "key"] {
+            // the pen orders have p3, and p1 and p3
+            check_join(
+                &searcher,
+                "products",
+                true,
+                to_field,
+                &term_query("items", "pen"),
+                &[8, 11],
+            );
+        }
+        for to_field in &["products", "product_keys"] {
+            check_join(
+                &searcher,
+                "id",
+                false,
+                to_field,
+                &term_query("id", "p2"),
+                &[2, 3, 10],
+            );
+        }
+
+        let pens = term_query("items", "pen");
+        assert!(
+            create_join_query("products", false, "id", &pens, &searcher, ScoreMode::Max).is_err()
+        );
+        assert!(create_join_query("items", false, "id", &pens, &searcher, ScoreMode::Max).is_err());
+    }
+}
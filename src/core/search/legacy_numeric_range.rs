@@ -0,0 +1,858 @@
+// Copyright 2019 Zhizhesihai (Beijing) Technology Limited.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use error::{ErrorKind, Result};
+use std::fmt;
+use std::str::FromStr;
+
+use core::codec::Codec;
+use core::index::{FieldInfo, IndexOptions, SeekStatus, TermIterator, Terms};
+use core::index::{LeafReader, LeafReaderContext};
+use core::search::explanation::Explanation;
+use core::search::match_all::ConstantScoreScorer;
+use core::search::posting_iterator::PostingIteratorFlags;
+use core::search::searcher::SearchPlanBuilder;
+use core::search::term_query::TermQuery;
+use core::search::{DocIdSet, DocIterator, Query, Scorer, Weight};
+use core::util::doc_id_set::DocIdSetEnum;
+use core::util::legacy_numeric_utils as legacy;
+use core::util::numeric::{double2sortable_long, float2sortable_int};
+use core::util::{DocId, DocIdSetBuilder};
+
+pub const LEGACY_NUMERIC_RANGE: &str = "legacy_numeric_range";
+
+/// Field attribute used to mark a field as indexed with legacy trie encoded
+/// numeric terms, the value is the `LegacyNumericType` of the field.
+pub const LEGACY_NUMERIC_TYPE_ATTRIBUTE: &str = "LegacyNumeric.type";
+
+/// Field attribute holding the precision step a legacy numeric field was indexed with.
+pub const LEGACY_NUMERIC_PRECISION_STEP_ATTRIBUTE: &str = "LegacyNumeric.precisionStep";
+
+/// Data type of the values of a legacy numeric field
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum LegacyNumericType {
+    Int,
+    Long,
+    Float,
+    Double,
+}
+
+impl LegacyNumericType {
+    /// size of the encoded value in bits
+    pub fn value_size(self) -> u32 {
+        match self {
+            LegacyNumericType::Int | LegacyNumericType::Float => 32,
+            LegacyNumericType::Long | LegacyNumericType::Double => 64,
+        }
+    }
+
+    pub fn default_precision_step(self) -> i32 {
+        match self {
+            LegacyNumericType::Int | LegacyNumericType::Float => legacy::PRECISION_STEP_DEFAULT_32,
+            LegacyNumericType::Long | LegacyNumericType::Double => legacy::PRECISION_STEP_DEFAULT,
+        }
+    }
+}
+
+impl fmt::Display for LegacyNumericType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let value = match *self {
+            LegacyNumericType::Int => "int",
+            LegacyNumericType::Long => "long",
+            LegacyNumericType::Float => "float",
+            LegacyNumericType::Double => "double",
+        };
+        write!(f, "{}", value)
+    }
+}
+
+impl FromStr for LegacyNumericType {
+    type Err = ::error::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "int" => Ok(LegacyNumericType::Int),
+            "long" => Ok(LegacyNumericType::Long),
+            "float" => Ok(LegacyNumericType::Float),
+            "double" => Ok(LegacyNumericType::Double),
+            _ => bail!(ErrorKind::IllegalArgument(format!(
+                "unknown legacy numeric type: {}",
+                s
+            ))),
+        }
+    }
+}
+
+/// Returns the legacy numeric type of the field if it was indexed with
+/// trie encoded terms instead of points, so query builders can choose
+/// between `LegacyNumericRangeQuery` and `PointRangeQuery`.
+///
+/// Fields that have points are never considered legacy. Otherwise the
+/// `LEGACY_NUMERIC_TYPE_ATTRIBUTE` attribute of the field is used.
+pub fn legacy_numeric_type(field_info: &FieldInfo) -> Option<LegacyNumericType> {
+    if field_info.point_dimension_count > 0 || field_info.index_options == IndexOptions::Null {
+        return None;
+    }
+    field_info
+        .attribute(LEGACY_NUMERIC_TYPE_ATTRIBUTE)
+        .and_then(|v| v.parse().ok())
+}
+
+/// Returns the precision step the field was indexed with, if recorded in the field attributes.
+pub fn legacy_numeric_precision_step(field_info: &FieldInfo) -> Option<i32> {
+    field_info
+        .attribute(LEGACY_NUMERIC_PRECISION_STEP_ATTRIBUTE)
+        .and_then(|v| v.parse().ok())
+        .filter(|&step| step > 0)
+}
+
+/// Detects legacy trie encoded terms by looking at the smallest term of the field,
+/// returns the size in bits of the encoded values (32 or 64).
+///
+/// This is used for indexes whose field infos carry no legacy numeric attributes.
+pub fn detect_legacy_numeric_terms(terms: &impl Terms) -> Result<Option<u32>> {
+    Ok(terms
+        .min()?
+        .and_then(|term| legacy::prefix_coded_value_size(&term)))
+}
+
+/// A query that matches numeric values within a specified range on a field
+/// that was indexed with the legacy trie encoding (one term per precision step).
+///
+/// The range is split into sub ranges of prefix coded terms for every
+/// precision step, so that only a small amount of terms need to be visited
+/// for each query.
+pub struct LegacyNumericRangeQuery {
+    field: String,
+    precision_step: i32,
+    numeric_type: LegacyNumericType,
+    min: Option<f64>,
+    max: Option<f64>,
+    min_inclusive: bool,
+    max_inclusive: bool,
+    // the prefix coded (lower, upper) bounds of every sub range, both inclusive
+    ranges: Vec<(Vec<u8>, Vec<u8>)>,
+}
+
+impl LegacyNumericRangeQuery {
+    pub fn new_long_range(
+        field: String,
+        precision_step: i32,
+        min: Option<i64>,
+        max: Option<i64>,
+        min_inclusive: bool,
+        max_inclusive: bool,
+    ) -> Result<LegacyNumericRangeQuery> {
+        Self::check_precision_step(precision_step)?;
+        let ranges = Self::long_ranges(precision_step, min, max, min_inclusive, max_inclusive);
+        Ok(LegacyNumericRangeQuery {
+            field,
+            precision_step,
+            numeric_type: LegacyNumericType::Long,
+            min: min.map(|v| v as f64),
+            max: max.map(|v| v as f64),
+            min_inclusive,
+            max_inclusive,
+            ranges,
+        })
+    }
+
+    pub fn new_int_range(
+        field: String,
+        precision_step: i32,
+        min: Option<i32>,
+        max: Option<i32>,
+        min_inclusive: bool,
+        max_inclusive: bool,
+    ) -> Result<LegacyNumericRangeQuery> {
+        Self::check_precision_step(precision_step)?;
+        let ranges = Self::int_ranges(precision_step, min, max, min_inclusive, max_inclusive);
+        Ok(LegacyNumericRangeQuery {
+            field,
+            precision_step,
+            numeric_type: LegacyNumericType::Int,
+            min: min.map(f64::from),
+            max: max.map(f64::from),
+            min_inclusive,
+            max_inclusive,
+            ranges,
+        })
+    }
+
+    pub fn new_double_range(
+        field: String,
+        precision_step: i32,
+        min: Option<f64>,
+        max: Option<f64>,
+        min_inclusive: bool,
+        max_inclusive: bool,
+    ) -> Result<LegacyNumericRangeQuery> {
+        Self::check_precision_step(precision_step)?;
+        // NaN can't be matched by a range, treat it as an open bound
+        let min = min.filter(|v| !v.is_nan());
+        let max = max.filter(|v| !v.is_nan());
+        let ranges = Self::long_ranges(
+            precision_step,
+            Some(double2sortable_long(
+                min.unwrap_or(::std::f64::NEG_INFINITY),
+            )),
+            Some(double2sortable_long(max.unwrap_or(::std::f64::INFINITY))),
+            min_inclusive || min.is_none(),
+            max_inclusive || max.is_none(),
+        );
+        Ok(LegacyNumericRangeQuery {
+            field,
+            precision_step,
+            numeric_type: LegacyNumericType::Double,
+            min,
+            max,
+            min_inclusive,
+            max_inclusive,
+            ranges,
+        })
+    }
+
+    pub fn new_float_range(
+        field: String,
+        precision_step: i32,
+        min: Option<f32>,
+        max: Option<f32>,
+        min_inclusive: bool,
+        max_inclusive: bool,
+    ) -> Result<LegacyNumericRangeQuery> {
+        Self::check_precision_step(precision_step)?;
+        let min = min.filter(|v| !v.is_nan());
+        let max = max.filter(|v| !v.is_nan());
+        let ranges = Self::int_ranges(
+            precision_step,
+            Some(float2sortable_int(min.unwrap_or(::std::f32::NEG_INFINITY))),
+            Some(float2sortable_int(max.unwrap_or(::std::f32::INFINITY))),
+            min_inclusive || min.is_none(),
+            max_inclusive || max.is_none(),
+        );
+        Ok(LegacyNumericRangeQuery {
+            field,
+            precision_step,
+            numeric_type: LegacyNumericType::Float,
+            min: min.map(f64::from),
+            max: max.map(f64::from),
+            min_inclusive,
+            max_inclusive,
+            ranges,
+        })
+    }
+
+    /// Create a range query for the given field, using the type and precision step
+    /// recorded in the field attributes. Returns `None` if the field is not a legacy
+    /// numeric field.
+    pub fn for_field(
+        field_info: &FieldInfo,
+        min: Option<f64>,
+        max: Option<f64>,
+        min_inclusive: bool,
+        max_inclusive: bool,
+    ) -> Result<Option<LegacyNumericRangeQuery>> {
+        let numeric_type = match legacy_numeric_type(field_info) {
+            Some(t) => t,
+            None => {
+                return Ok(None);
+            }
+        };
+        let step = legacy_numeric_precision_step(field_info)
+            .unwrap_or_else(|| numeric_type.default_precision_step());
+        let field = field_info.name.clone();
+        let query = match numeric_type {
+            LegacyNumericType::Int => {
+                let lowest = i64::from(i32::min_value());
+                let highest = i64::from(i32::max_value());
+                let (min, min_inclusive) = integer_lower_bound(min, min_inclusive, lowest, highest);
+                let (max, max_inclusive) = integer_upper_bound(max, max_inclusive, lowest, highest);
+                Self::new_int_range(
+                    field,
+                    step,
+                    min.map(|v| v as i32),
+                    max.map(|v| v as i32),
+                    min_inclusive,
+                    max_inclusive,
+                )?
+            }
+            LegacyNumericType::Long => {
+                let lowest = i64::min_value();
+                let highest = i64::max_value();
+                let (min, min_inclusive) = integer_lower_bound(min, min_inclusive, lowest, highest);
+                let (max, max_inclusive) = integer_upper_bound(max, max_inclusive, lowest, highest);
+                Self::new_long_range(field, step, min, max, min_inclusive, max_inclusive)?
+            }
+            LegacyNumericType::Float => {
+                let (min, min_inclusive) = float_bound(min, min_inclusive, true);
+                let (max, max_inclusive) = float_bound(max, max_inclusive, false);
+                Self::new_float_range(field, step, min, max, min_inclusive, max_inclusive)?
+            }
+            LegacyNumericType::Double => {
+                Self::new_double_range(field, step, min, max, min_inclusive, max_inclusive)?
+            }
+        };
+        Ok(Some(query))
+    }
+
+    fn check_precision_step(precision_step: i32) -> Result<()> {
+        if precision_step < 1 {
+            bail!(ErrorKind::IllegalArgument(
+                "precision_step must be >= 1".into()
+            ));
+        }
+        Ok(())
+    }
+
+    fn long_ranges(
+        precision_step: i32,
+        min: Option<i64>,
+        max: Option<i64>,
+        min_inclusive: bool,
+        max_inclusive: bool,
+    ) -> Vec<(Vec<u8>, Vec<u8>)> {
+        let mut ranges = vec![];
+        let mut min_bound = min.unwrap_or(i64::min_value());
+        if !min_inclusive && min.is_some() {
+            if min_bound == i64::max_value() {
+                return ranges;
+            }
+            min_bound += 1;
+        }
+        let mut max_bound = max.unwrap_or(i64::max_value());
+        if !max_inclusive && max.is_some() {
+            if max_bound == i64::min_value() {
+                return ranges;
+            }
+            max_bound -= 1;
+        }
+        legacy::split_long_range(precision_step, min_bound, max_bound, |lower, upper| {
+            ranges.push((lower, upper))
+        });
+        ranges.sort();
+        ranges
+    }
+
+    fn int_ranges(
+        precision_step: i32,
+        min: Option<i32>,
+        max: Option<i32>,
+        min_inclusive: bool,
+        max_inclusive: bool,
+    ) -> Vec<(Vec<u8>, Vec<u8>)> {
+        let mut ranges = vec![];
+        let mut min_bound = min.unwrap_or(i32::min_value());
+        if !min_inclusive && min.is_some() {
+            if min_bound == i32::max_value() {
+                return ranges;
+            }
+            min_bound += 1;
+        }
+        let mut max_bound = max.unwrap_or(i32::max_value());
+        if !max_inclusive && max.is_some() {
+            if max_bound == i32::min_value() {
+                return ranges;
+            }
+            max_bound -= 1;
+        }
+        legacy::split_int_range(precision_step, min_bound, max_bound, |lower, upper| {
+            ranges.push((lower, upper))
+        });
+        ranges.sort();
+        ranges
+    }
+
+    pub fn field(&self) -> &str {
+        &self.field
+    }
+
+    pub fn precision_step(&self) -> i32 {
+        self.precision_step
+    }
+
+    pub fn numeric_type(&self) -> LegacyNumericType {
+        self.numeric_type
+    }
+
+    /// The prefix coded (lower, upper) bounds of the sub ranges, both inclusive.
+    pub fn ranges(&self) -> &[(Vec<u8>, Vec<u8>)] {
+        &self.ranges
+    }
+}
+
+// Float to int `as` casts are undefined for out of range values, so the `f64`
+// bounds of `for_field` are compared against the limits of the target type
+// before they are converted. `-(lowest as f64)` is exactly `highest + 1`.
+
+/// Rounds a lower bound up to an integer in `[lowest, highest]`. A bound above
+/// `highest` becomes an exclusive `highest`, which matches nothing.
+fn integer_lower_bound(
+    value: Option<f64>,
+    inclusive: bool,
+    lowest: i64,
+    highest: i64,
+) -> (Option<i64>, bool) {
+    match value.filter(|v| !v.is_nan()) {
+        Some(v) => {
+            let ceil = v.ceil();
+            if ceil < lowest as f64 {
+                (None, true)
+            } else if ceil >= -(lowest as f64) {
+                (Some(highest), false)
+            } else {
+                (Some(ceil as i64), inclusive || ceil > v)
+            }
+        }
+        None => (None, inclusive),
+    }
+}
+
+/// Rounds an upper bound down to an integer in `[lowest, highest]`. A bound below
+/// `lowest` becomes an exclusive `lowest`, which matches nothing.
+fn integer_upper_bound(
+    value: Option<f64>,
+    inclusive: bool,
+    lowest: i64,
+    highest: i64,
+) -> (Option<i64>, bool) {
+    match value.filter(|v| !v.is_nan()) {
+        Some(v) => {
+            let floor = v.floor();
+            if floor >= -(lowest as f64) {
+                (None, true)
+            } else if floor < lowest as f64 {
+                (Some(lowest), false)
+            } else {
+                (Some(floor as i64), inclusive || floor < v)
+            }
+        }
+        None => (None, inclusive),
+    }
+}
+
+/// Rounds a bound to the nearest `f32` towards the inside of the range, up for
+/// lower bounds and down for upper bounds. Bounds that can't be represented
+/// exactly become inclusive.
+fn float_bound(value: Option<f64>, inclusive: bool, round_up: bool) -> (Option<f32>, bool) {
+    let v = match value.filter(|v| !v.is_nan()) {
+        Some(v) => v,
+        None => {
+            return (None, inclusive);
+        }
+    };
+    let mut bound = if v.is_infinite() {
+        v as f32
+    } else if v > f64::from(::std::f32::MAX) {
+        if round_up {
+            ::std::f32::INFINITY
+        } else {
+            ::std::f32::MAX
+        }
+    } else if v < f64::from(::std::f32::MIN) {
+        if round_up {
+            ::std::f32::MIN
+        } else {
+            ::std::f32::NEG_INFINITY
+        }
+    } else {
+        v as f32
+    };
+    if round_up && f64::from(bound) < v {
+        bound = next_up(bound);
+    } else if !round_up && f64::from(bound) > v {
+        bound = -next_up(-bound);
+    }
+    let inexact = if round_up {
+        f64::from(bound) > v
+    } else {
+        f64::from(bound) < v
+    };
+    (Some(bound), inclusive || inexact)
+}
+
+// the smallest `f32` greater than the finite `v`
+fn next_up(v: f32) -> f32 {
+    if v == 0.0 {
+        f32::from_bits(1)
+    } else if v > 0.0 {
+        f32::from_bits(v.to_bits() + 1)
+    } else {
+        f32::from_bits(v.to_bits() - 1)
+    }
+}
+
+impl<C: Codec> Query<C> for LegacyNumericRangeQuery {
+    fn create_weight(
+        &self,
+        _searcher: &dyn SearchPlanBuilder<C>,
+        _needs_scores: bool,
+    ) -> Result<Box<dyn Weight<C>>> {
+        Ok(Box::new(LegacyNumericRangeWeight {
+            description: self.to_string(),
+            field: self.field.clone(),
+            ranges: self.ranges.clone(),
+            weight: 0f32,
+            norm: 1f32,
+        }))
+    }
+
+    fn extract_terms(&self) -> Vec<TermQuery> {
+        vec![]
+    }
+
+    fn query_type(&self) -> &'static str {
+        LEGACY_NUMERIC_RANGE
+    }
+
+    fn as_any(&self) -> &::std::any::Any {
+        self
+    }
+}
+
+impl fmt::Display for LegacyNumericRangeQuery {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let format_bound = |v: Option<f64>| match v {
+            Some(v) => v.to_string(),
+            None => "*".to_string(),
+        };
+        write!(
+            f,
+            "LegacyNumericRangeQuery(field: {}, type: {}, precision_step: {}, range: {}{} TO {}{})",
+            &self.field,
+            self.numeric_type,
+            self.precision_step,
+            if self.min_inclusive { '[' } else { '{' },
+            format_bound(self.min),
+            format_bound(self.max),
+            if self.max_inclusive { ']' } else { '}' },
+        )
+    }
+}
+
+struct LegacyNumericRangeWeight {
+    description: String,
+    field: String,
+    ranges: Vec<(Vec<u8>, Vec<u8>)>,
+    weight: f32,
+    norm: f32,
+}
+
+impl LegacyNumericRangeWeight {
+    /// Visits the terms of every sub range and collect the matching docs.
+    fn build_matching_doc_set(&self, max_doc: DocId, terms: &impl Terms) -> Result<DocIdSetEnum> {
+        let mut result = DocIdSetBuilder::from_terms(max_doc, terms)?;
+        let mut iter = terms.iterator()?;
+        for &(ref lower, ref upper) in &self.ranges {
+            if iter.seek_ceil(lower)? == SeekStatus::End {
+                // the ranges are sorted, so all the remaining ones are beyond the last term
+                break;
+            }
+            loop {
+                if iter.term()? > upper.as_slice() {
+                    break;
+                }
+                let mut postings = iter.postings_with_flags(PostingIteratorFlags::NONE)?;
+                result.add(&mut postings)?;
+                if iter.next()?.is_none() {
+                    break;
+                }
+            }
+        }
+        Ok(result.build())
+    }
+}
+
+impl<C: Codec> Weight<C> for LegacyNumericRangeWeight {
+    fn create_scorer(
+        &self,
+        leaf_reader_ctx: &LeafReaderContext<'_, C>,
+    ) -> Result<Option<Box<dyn Scorer>>> {
+        if self.ranges.is_empty() {
+            return Ok(None);
+        }
+        let leaf_reader = leaf_reader_ctx.reader;
+        if let Some(terms) = leaf_reader.terms(&self.field)? {
+            let doc_set = self.build_matching_doc_set(leaf_reader.max_doc(), &terms)?;
+            if let Some(iterator) = doc_set.iterator()? {
+                let cost = iterator.cost();
+                return Ok(Some(Box::new(ConstantScoreScorer::new(
+                    self.weight,
+                    iterator,
+                    cost,
+                ))));
+            }
+        }
+        Ok(None)
+    }
+
+    fn query_type(&self) -> &'static str {
+        LEGACY_NUMERIC_RANGE
+    }
+
+    fn normalize(&mut self, norm: f32, boost: f32) {
+        self.weight = norm * boost;
+        self.norm = norm;
+    }
+
+    fn value_for_normalization(&self) -> f32 {
+        self.weight * self.weight
+    }
+
+    fn needs_scores(&self) -> bool {
+        false
+    }
+
+    fn explain(&self, reader: &LeafReaderContext<'_, C>, doc: DocId) -> Result<Explanation> {
+        let matched = match self.create_scorer(reader)? {
+            Some(mut scorer) => scorer.advance(doc)? == doc,
+            None => false,
+        };
+        if matched {
+            Ok(Explanation::new(
+                true,
+                self.weight,
+                format!("{}, product of:", self),
+                vec![
+                    Explanation::new(true, self.weight, "boost".to_string(), vec![]),
+                    Explanation::new(true, self.norm, "queryNorm".to_string(), vec![]),
+                ],
+            ))
+        } else {
+            Ok(Explanation::new(
+                false,
+                0f32,
+                format!("{} doesn't match id {}", self, doc),
+                vec![],
+            ))
+        }
+    }
+}
+
+impl fmt::Display for LegacyNumericRangeWeight {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "LegacyNumericRangeWeight({})", &self.description)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::doc::{Field, FieldType};
+    use core::index::tests::{open_test_reader, TestReader};
+    use core::search::collector::TopDocsCollector;
+    use core::search::searcher::{DefaultIndexSearcher, IndexSearcher};
+
+    use std::sync::Arc;
+
+    #[test]
+    fn test_empty_exclusive_ranges() {
+        let query =
+            LegacyNumericRangeQuery::new_long_range("f".into(), 4, Some(5), Some(5), false, true)
+                .unwrap();
+        assert!(query.ranges.is_empty());
+        let query = LegacyNumericRangeQuery::new_int_range(
+            "f".into(),
+            4,
+            Some(i32::max_value()),
+            None,
+            false,
+            true,
+        )
+        .unwrap();
+        assert!(query.ranges.is_empty());
+        assert!(
+            LegacyNumericRangeQuery::new_int_range("f".into(), 0, None, None, true, true).is_err()
+        );
+    }
+
+    #[test]
+    fn test_ranges_match_values() {
+        let query = LegacyNumericRangeQuery::new_double_range(
+            "f".into(),
+            legacy::PRECISION_STEP_DEFAULT,
+            Some(-1.5),
+            Some(1000.25),
+            true,
+            false,
+        )
+        .unwrap();
+        let matches = |v: f64| {
+            let sortable = double2sortable_long(v);
+            query.ranges.iter().any(|&(ref lower, ref upper)| {
+                let shift = legacy::prefix_coded_long_shift(lower).unwrap();
+                let term = legacy::long_to_prefix_coded(sortable, shift);
+                &term >= lower && &term <= upper
+            })
+        };
+        assert!(matches(-1.5));
+        assert!(matches(0.0));
+        assert!(matches(1000.0));
+        assert!(!matches(1000.25));
+        assert!(!matches(-1.6));
+        assert!(!matches(::std::f64::INFINITY));
+    }
+
+    #[test]
+    fn test_bounds_out_of_range() {
+        let lowest = i64::from(i32::min_value());
+        let highest = i64::from(i32::max_value());
+        assert_eq!(
+            integer_lower_bound(Some(1e12), true, lowest, highest),
+            (Some(highest), false)
+        );
+        assert_eq!(
+            integer_lower_bound(Some(-1e12), false, lowest, highest),
+            (None, true)
+        );
+        assert_eq!(
+            integer_lower_bound(Some(2.5), false, lowest, highest),
+            (Some(3), true)
+        );
+        assert_eq!(
+            integer_upper_bound(Some(-1e12), true, lowest, highest),
+            (Some(lowest), false)
+        );
+        assert_eq!(
+            integer_upper_bound(Some(2.5), false, lowest, highest),
+            (Some(2), true)
+        );
+        assert_eq!(
+            integer_upper_bound(Some(::std::f64::INFINITY), false, lowest, highest),
+            (None, true)
+        );
+        // 2^63 can't be cast to an i64
+        assert_eq!(
+            integer_upper_bound(Some(9.3e18), true, i64::min_value(), i64::max_value()),
+            (None, true)
+        );
+        assert_eq!(
+            integer_lower_bound(Some(9.3e18), true, i64::min_value(), i64::max_value()),
+            (Some(i64::max_value()), false)
+        );
+
+        assert_eq!(
+            float_bound(Some(1e300), true, true),
+            (Some(::std::f32::INFINITY), true)
+        );
+        assert_eq!(
+            float_bound(Some(1e300), true, false),
+            (Some(::std::f32::MAX), true)
+        );
+        assert_eq!(
+            float_bound(Some(-1e300), false, true),
+            (Some(::std::f32::MIN), true)
+        );
+        assert_eq!(
+            float_bound(Some(::std::f64::INFINITY), false, false),
+            (Some(::std::f32::INFINITY), false)
+        );
+        assert_eq!(float_bound(Some(1.5), false, true), (Some(1.5), false));
+        let (lower, inclusive) = float_bound(Some(0.1), false, true);
+        assert!(f64::from(lower.unwrap()) > 0.1 && inclusive);
+        let (upper, inclusive) = float_bound(Some(0.1), false, false);
+        assert!(f64::from(upper.unwrap()) < 0.1 && inclusive);
+        assert_eq!(next_up(upper.unwrap()), lower.unwrap());
+    }
+
+    // indexes every value the way Lucene's `LegacyLongField` does, as one prefix
+    // coded term for each precision step
+    fn index_legacy_longs(values: &[i64], precision_step: usize) -> Arc<TestReader> {
+        let mut field_type = FieldType::default();
+        field_type.tokenized = false;
+        field_type.omit_norms = true;
+        field_type.index_options = IndexOptions::Docs;
+        let docs: Vec<Vec<Field>> = values
+            .iter()
+            .map(|&value| {
+                (0..64)
+                    .step_by(precision_step)
+                    .map(|shift| {
+                        let term = legacy::long_to_prefix_coded(value, shift);
+                        Field::new_bytes("price".to_string(), term, field_type.clone())
+                    })
+                    .collect()
+            })
+            .collect();
+        open_test_reader(vec![docs])
+    }
+
+    #[test]
+    fn test_search_legacy_encoded_terms() {
+        // the full precision term of 0 as written by Lucene's LegacyNumericUtils
+        assert_eq!(
+            legacy::long_to_prefix_coded(0, 0),
+            vec![0x20, 0x01, 0, 0, 0, 0, 0, 0, 0, 0, 0]
+        );
+
+        let values = [
+            i64::min_value(),
+            -1_000_000,
+            -17,
+            0,
+            1,
+            255,
+            256,
+            65_535,
+            1 << 40,
+            i64::max_value(),
+        ];
+        let step = 8;
+        let reader = index_legacy_longs(&values, step as usize);
+        let searcher = DefaultIndexSearcher::new(reader);
+        let search = |query: LegacyNumericRangeQuery| -> Vec<i64> {
+            let mut collector = TopDocsCollector::new(values.len());
+            searcher.search(&query, &mut collector).unwrap();
+            let mut found: Vec<i64> = collector
+                .top_docs()
+                .score_docs()
+                .iter()
+                .map(|hit| values[hit.doc_id() as usize])
+                .collect();
+            found.sort();
+            found
+        };
+        let ranges = [
+            (None, None, true, true),
+            (Some(-17), Some(256), true, false),
+            (Some(-17), Some(256), false, true),
+            (Some(0), Some(0), true, true),
+            (Some(2), Some(254), true, true),
+            (Some(256), None, true, true),
+            (None, Some(i64::max_value()), true, false),
+        ];
+        for &(min, max, min_inclusive, max_inclusive) in &ranges {
+            let query = LegacyNumericRangeQuery::new_long_range(
+                "price".into(),
+                step,
+                min,
+                max,
+                min_inclusive,
+                max_inclusive,
+            )
+            .unwrap();
+            let expected: Vec<i64> = values
+                .iter()
+                .cloned()
+                .filter(|&v| match min {
+                    Some(min) => v > min || (min_inclusive && v == min),
+                    None => true,
+                })
+                .filter(|&v| match max {
+                    Some(max) => v < max || (max_inclusive && v == max),
+                    None => true,
+                })
+                .collect();
+            assert_eq!(search(query), expected, "{:?}", (min, max));
+        }
+    }
+}
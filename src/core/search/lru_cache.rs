@@ -34,7 +34,6 @@
 // SOFTWARE.
 
 //! A fixed-size cache with LRU expiration criteria.
-//!
 use std::borrow::Borrow;
 use std::collections::HashMap;
 use std::hash::Hash;
@@ -69,6 +68,19 @@ impl<K: Clone + Hash + Eq, V> LRUCache<K, V> {
         }
     }
 
+    /// Creates a new cache that can hold the specified number of elements,
+    /// without reserving room for them upfront.
+    pub fn new(cap: usize) -> Self {
+        LRUCache {
+            table: HashMap::new(),
+            entries: Vec::new(),
+            free_indexes: Vec::new(),
+            first: None,
+            last: None,
+            capacity: cap,
+        }
+    }
+
     /// Inserts a key-value pair into the cache and returns the previous value, if any.
     ///
     /// If there is no room in the cache the oldest item will be removed.
@@ -225,6 +237,31 @@ impl<K: Clone + Hash + Eq, V> LRUCache<K, V> {
             .and_then(move |i| entries[*i].value.as_mut())
     }
 
+    /// Retrieves a mutable reference to the item associated with `key` from the cache,
+    /// without promoting it.
+    ///
+    /// # Example
+    /// ```
+    /// use rucene::core::search::lru_cache::LRUCache;
+    ///
+    /// let mut cache: LRUCache<&str, _> = LRUCache::with_capacity(2);
+    /// cache.insert("foo", 1);
+    /// *cache.peek_mut(&"foo").unwrap() = 2;
+    /// cache.insert("bar", 2);
+    /// cache.insert("baz", 3);
+    /// assert!(!cache.contains_key(&"foo"));
+    /// ```
+    pub fn peek_mut<Q: ?Sized>(&mut self, key: &Q) -> Option<&mut V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq,
+    {
+        let entries = &mut self.entries;
+        self.table
+            .get(key)
+            .and_then(move |i| entries[*i].value.as_mut())
+    }
+
     /// Returns the number of elements currently in the cache.
     pub fn len(&self) -> usize {
         self.table.len()
@@ -246,6 +283,27 @@ impl<K: Clone + Hash + Eq, V> LRUCache<K, V> {
         self.table.is_empty()
     }
 
+    /// Returns the keys of the cache, the most recently used first.
+    ///
+    /// ```
+    /// use rucene::core::search::lru_cache::LRUCache;
+    ///
+    /// let mut cache = LRUCache::with_capacity(3);
+    /// cache.insert("foo", 1);
+    /// cache.insert("bar", 2);
+    /// cache.get(&"foo");
+    /// assert_eq!(cache.keys(), vec!["foo", "bar"]);
+    /// ```
+    pub fn keys(&self) -> Vec<K> {
+        let mut keys = Vec::with_capacity(self.len());
+        let mut next = self.first;
+        while let Some(i) = next {
+            keys.push(self.entries[i].key.clone());
+            next = self.entries[i].next;
+        }
+        keys
+    }
+
     /// Returns true if the cache is at full capacity. Any subsequent insertions of keys not
     /// already present will eject the oldest element from the cache.
     pub fn is_full(&self) -> bool {
@@ -349,6 +407,28 @@ impl<K: Clone + Hash + Eq, V> LRUCache<K, V> {
         }
         key
     }
+
+    /// Removes the oldest item in the cache and returns it.
+    ///
+    /// ```
+    /// use rucene::core::search::lru_cache::LRUCache;
+    ///
+    /// let mut cache = LRUCache::new(10);
+    /// cache.insert("foo", 1);
+    /// cache.insert("bar", 2);
+    /// cache.get(&"foo");
+    /// assert_eq!(cache.pop_last(), Some(("bar", 2)));
+    /// assert_eq!(cache.pop_last(), Some(("foo", 1)));
+    /// assert_eq!(cache.pop_last(), None);
+    /// ```
+    pub fn pop_last(&mut self) -> Option<(K, V)> {
+        let idx = self.last?;
+        self.remove_from_list(idx);
+        self.free_indexes.push(idx);
+        let entry = &mut self.entries[idx];
+        let (key, _) = self.table.remove_entry(&entry.key)?;
+        Some((key, entry.value.take().unwrap()))
+    }
 }
 
 #[cfg(test)]
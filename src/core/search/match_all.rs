@@ -13,9 +13,13 @@
 
 use core::codec::Codec;
 use core::index::LeafReaderContext;
+use core::search::boost::{BoostQuery, BoostWeight};
 use core::search::explanation::Explanation;
+use core::search::point_range::PointRangeQuery;
+use core::search::prefix_query::PrefixQuery;
 use core::search::searcher::SearchPlanBuilder;
 use core::search::term_query::TermQuery;
+use core::search::term_range_query::TermRangeQuery;
 use core::search::two_phase_next;
 use core::search::{DocIterator, Query, Scorer, Weight, NO_MORE_DOCS};
 use core::util::DocId;
@@ -24,6 +28,9 @@ use std::fmt;
 
 pub const MATCH_ALL: &str = "match_all";
 
+/// Matches every live doc with a constant score, the cost of its scorers is
+/// the `max_doc` of the segment.
+#[derive(Clone, Copy, Debug, Default)]
 pub struct MatchAllDocsQuery;
 
 impl<C: Codec> Query<C> for MatchAllDocsQuery {
@@ -36,7 +43,7 @@ impl<C: Codec> Query<C> for MatchAllDocsQuery {
     }
 
     fn extract_terms(&self) -> Vec<TermQuery> {
-        unimplemented!()
+        vec![]
     }
 
     fn query_type(&self) -> &'static str {
@@ -74,11 +81,15 @@ impl<C: Codec> Weight<C> for MatchAllDocsWeight {
         leaf_reader: &LeafReaderContext<'_, C>,
     ) -> Result<Option<Box<dyn Scorer>>> {
         let max_doc = leaf_reader.reader.max_doc();
-        Ok(Some(Box::new(ConstantScoreScorer {
-            score: self.weight,
-            iterator: AllDocsIterator::new(max_doc),
-            cost: max_doc as usize,
-        })))
+        Ok(Some(Box::new(ConstantScoreScorer::new(
+            self.weight,
+            AllDocsIterator::new(max_doc),
+            max_doc as usize,
+        ))))
+    }
+
+    fn count(&self, leaf_reader: &LeafReaderContext<'_, C>) -> Result<Option<usize>> {
+        Ok(Some(leaf_reader.reader.num_docs() as usize))
     }
 
     fn query_type(&self) -> &'static str {
@@ -121,6 +132,7 @@ pub struct ConstantScoreScorer<T: DocIterator> {
     score: f32,
     iterator: T,
     cost: usize,
+    support_two_phase: bool,
 }
 
 impl<T: DocIterator> ConstantScoreScorer<T> {
@@ -129,6 +141,17 @@ impl<T: DocIterator> ConstantScoreScorer<T> {
             score,
             iterator,
             cost,
+            support_two_phase: false,
+        }
+    }
+
+    /// The scorer of an iterator supporting two phase iteration.
+    pub fn new_two_phase(score: f32, iterator: T, cost: usize) -> ConstantScoreScorer<T> {
+        ConstantScoreScorer {
+            score,
+            iterator,
+            cost,
+            support_two_phase: true,
         }
     }
 }
@@ -137,6 +160,10 @@ impl<T: DocIterator> Scorer for ConstantScoreScorer<T> {
     fn score(&mut self) -> Result<f32> {
         Ok(self.score)
     }
+
+    fn support_two_phase(&self) -> bool {
+        self.support_two_phase
+    }
 }
 
 impl<T: DocIterator> DocIterator for ConstantScoreScorer<T> {
@@ -167,6 +194,14 @@ impl<T: DocIterator> DocIterator for ConstantScoreScorer<T> {
     fn approximate_advance(&mut self, target: i32) -> Result<DocId> {
         self.iterator.approximate_advance(target)
     }
+
+    fn matches(&mut self) -> Result<bool> {
+        self.iterator.matches()
+    }
+
+    fn match_cost(&self) -> f32 {
+        self.iterator.match_cost()
+    }
 }
 
 pub struct AllDocsIterator {
@@ -213,14 +248,20 @@ impl DocIterator for AllDocsIterator {
 
 pub const CONSTANT: &str = "constant";
 
+/// Wraps a query so that all its matches get the same score, the boost of the
+/// query.
 pub struct ConstantScoreQuery<C: Codec> {
     pub query: Box<dyn Query<C>>,
     boost: f32,
 }
 
 impl<C: Codec> ConstantScoreQuery<C> {
+    /// The matches score `1.0`.
     pub fn new(query: Box<dyn Query<C>>) -> ConstantScoreQuery<C> {
-        ConstantScoreQuery { query, boost: 0f32 }
+        ConstantScoreQuery {
+            query,
+            boost: 1.0f32,
+        }
     }
 
     pub fn with_boost(query: Box<dyn Query<C>>, boost: f32) -> ConstantScoreQuery<C> {
@@ -248,12 +289,32 @@ impl<C: Codec> Query<C> for ConstantScoreQuery<C> {
         searcher: &dyn SearchPlanBuilder<C>,
         needs_scores: bool,
     ) -> Result<Box<dyn Weight<C>>> {
-        let weight = searcher.create_weight(self.query.as_ref(), false)?;
-        if needs_scores {
-            Ok(Box::new(ConstantScoreWeight::new(weight, self.boost)))
-        } else {
-            Ok(weight)
+        // the scores of the wrapped queries are ignored
+        let mut query = self.query.as_ref();
+        loop {
+            if let Some(q) = query.as_any().downcast_ref::<ConstantScoreQuery<C>>() {
+                query = q.get_raw_query();
+            } else if let Some(q) = query.as_any().downcast_ref::<BoostQuery<C>>() {
+                query = q.query();
+            } else {
+                break;
+            }
+        }
+
+        if !needs_scores {
+            return searcher.create_weight(query, false);
         }
+        if is_constant_score(query) {
+            // the scorers of the query already score its boost
+            let mut weight = query.create_weight(searcher, false)?;
+            Weight::<C>::normalize(weight.as_mut(), 1.0f32, self.boost);
+            if (self.boost - 1.0f32).abs() <= ::std::f32::EPSILON {
+                return Ok(weight);
+            }
+            return Ok(Box::new(BoostWeight::new(weight, self.boost)));
+        }
+        let weight = searcher.create_weight(query, false)?;
+        Ok(Box::new(ConstantScoreWeight::new(weight, self.boost)))
     }
 
     fn extract_terms(&self) -> Vec<TermQuery> {
@@ -269,8 +330,84 @@ impl<C: Codec> Query<C> for ConstantScoreQuery<C> {
     }
 }
 
+// the queries whose scorers all score the normalized boost of their weight
+fn is_constant_score<C: Codec>(query: &dyn Query<C>) -> bool {
+    let query = query.as_any();
+    query.is::<MatchAllDocsQuery>()
+        || query.is::<PrefixQuery>()
+        || query.is::<TermRangeQuery>()
+        || query.is::<PointRangeQuery>()
+}
+
+/// The score of a weight whose scorers give every match the same score, the
+/// query norm times the boost, with the `Weight` methods computing it.
+#[derive(Copy, Clone, Debug)]
+pub struct ConstantScore {
+    weight: f32,
+    norm: f32,
+}
+
+impl Default for ConstantScore {
+    fn default() -> ConstantScore {
+        ConstantScore {
+            weight: 0f32,
+            norm: 1f32,
+        }
+    }
+}
+
+impl ConstantScore {
+    pub fn score(&self) -> f32 {
+        self.weight
+    }
+
+    pub fn normalize(&mut self, norm: f32, boost: f32) {
+        self.weight = norm * boost;
+        self.norm = norm;
+    }
+
+    pub fn value_for_normalization(&self) -> f32 {
+        self.weight * self.weight
+    }
+
+    /// Explains the score of `doc` for `weight`, which matches it if
+    /// `scorer` advances to it.
+    pub fn explain(
+        &self,
+        weight: &dyn fmt::Display,
+        scorer: Option<Box<dyn Scorer>>,
+        doc: DocId,
+    ) -> Result<Explanation> {
+        let matched = match scorer {
+            Some(mut scorer) => scorer.advance(doc)? == doc,
+            None => false,
+        };
+        if matched {
+            Ok(Explanation::new(
+                true,
+                self.weight,
+                format!("{}, product of:", weight),
+                vec![
+                    Explanation::new(true, self.weight, "boost".to_string(), vec![]),
+                    Explanation::new(true, self.norm, "queryNorm".to_string(), vec![]),
+                ],
+            ))
+        } else {
+            Ok(Explanation::new(
+                false,
+                0f32,
+                format!("{} doesn't match id {}", weight, doc),
+                vec![],
+            ))
+        }
+    }
+}
+
 pub struct ConstantScoreWeight<C: Codec> {
     sub_weight: Box<dyn Weight<C>>,
+    boost: f32,
+    // the boost of the enclosing queries
+    query_boost: f32,
     query_norm: f32,
     query_weight: f32,
 }
@@ -279,6 +416,8 @@ impl<C: Codec> ConstantScoreWeight<C> {
     pub fn new(sub_weight: Box<dyn Weight<C>>, boost: f32) -> ConstantScoreWeight<C> {
         ConstantScoreWeight {
             sub_weight,
+            boost,
+            query_boost: 1.0f32,
             query_weight: boost,
             query_norm: 1.0f32,
         }
@@ -289,22 +428,29 @@ impl<C: Codec> Weight<C> for ConstantScoreWeight<C> {
     fn create_scorer(&self, reader: &LeafReaderContext<'_, C>) -> Result<Option<Box<dyn Scorer>>> {
         if let Some(inner_scorer) = self.sub_weight.create_scorer(reader)? {
             let cost = inner_scorer.cost();
+            let support_two_phase = inner_scorer.support_two_phase();
             Ok(Some(Box::new(ConstantScoreScorer {
                 score: self.query_weight,
                 iterator: inner_scorer,
                 cost,
+                support_two_phase,
             })))
         } else {
             Ok(None)
         }
     }
 
+    fn count(&self, reader: &LeafReaderContext<'_, C>) -> Result<Option<usize>> {
+        self.sub_weight.count(reader)
+    }
+
     fn query_type(&self) -> &'static str {
         CONSTANT
     }
 
     fn normalize(&mut self, norm: f32, boost: f32) {
-        self.query_weight = norm * boost;
+        self.query_weight = norm * boost * self.boost;
+        self.query_boost = boost;
         self.query_norm = norm;
     }
 
@@ -333,7 +479,8 @@ impl<C: Codec> Weight<C> for ConstantScoreWeight<C> {
                 self.query_weight,
                 format!("{}, product of:", self.sub_weight),
                 vec![
-                    Explanation::new(true, self.query_weight, "boost".to_string(), vec![]),
+                    Explanation::new(true, self.boost, "boost".to_string(), vec![]),
+                    Explanation::new(true, self.query_boost, "queryBoost".to_string(), vec![]),
                     Explanation::new(true, self.query_norm, "queryNorm".to_string(), vec![]),
                 ],
             ))
@@ -357,3 +504,208 @@ impl<C: Codec> fmt::Display for ConstantScoreWeight<C> {
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::codec::CodecEnum;
+    use core::doc::{Field, FieldType};
+    use core::index::index_writer_config::IndexWriterConfig;
+    use core::index::tests::{open_test_reader, write_test_index, TestReader};
+    use core::index::{IndexOptions, IndexReader, Term};
+    use core::search::collector::TopDocsCollector;
+    use core::search::phrase_query::PhraseQuery;
+    use core::search::prefix_query::PREFIX;
+    use core::search::searcher::{DefaultIndexSearcher, IndexSearcher};
+    use core::util::VariantValue;
+
+    use std::sync::Arc;
+
+    fn text(value: &str) -> Field {
+        let mut field_type = FieldType::default();
+        field_type.index_options = IndexOptions::DocsAndFreqsAndPositions;
+        Field::new(
+            "body".to_string(),
+            field_type,
+            Some(VariantValue::VString(value.to_string())),
+            None,
+        )
+    }
+
+    fn term(value: &str) -> Box<dyn Query<CodecEnum>> {
+        let term = Term::new("body".to_string(), value.as_bytes().to_vec());
+        Box::new(TermQuery::new(term, 1.0, None))
+    }
+
+    fn constant(query: Box<dyn Query<CodecEnum>>, boost: f32) -> Box<dyn Query<CodecEnum>> {
+        Box::new(ConstantScoreQuery::with_boost(query, boost))
+    }
+
+    // two segments
+    fn open_reader() -> Arc<TestReader> {
+        let segments: &[&[&str]] = &[
+            &["apple banana", "apple apple cherry", "banana cherry"],
+            &["apple pie", "cherry pie apple", "durian"],
+        ];
+        open_test_reader(
+            segments
+                .iter()
+                .map(|docs| docs.iter().map(|body| vec![text(body)]).collect())
+                .collect(),
+        )
+    }
+
+    fn search<S: IndexSearcher<CodecEnum>>(
+        searcher: &S,
+        query: &dyn Query<CodecEnum>,
+    ) -> Vec<(DocId, f32)> {
+        let mut collector = TopDocsCollector::new(10);
+        searcher.search(query, &mut collector).unwrap();
+        let mut docs: Vec<_> = collector
+            .top_docs()
+            .score_docs()
+            .iter()
+            .map(|d| (d.doc_id(), d.score()))
+            .collect();
+        docs.sort_by_key(|d| d.0);
+        docs
+    }
+
+    fn doc_ids(hits: &[(DocId, f32)]) -> Vec<DocId> {
+        hits.iter().map(|h| h.0).collect()
+    }
+
+    fn assert_scores(hits: &[(DocId, f32)], score: f32) {
+        for hit in hits {
+            assert!((hit.1 - score).abs() < 1e-6, "{:?} != {}", hit, score);
+        }
+    }
+
+    #[test]
+    fn test_constant_score_query() {
+        let reader = open_reader();
+        let searcher = DefaultIndexSearcher::new(Arc::clone(&reader));
+        let apple = search(&searcher, term("apple").as_ref());
+        assert_eq!(doc_ids(&apple), vec![0, 1, 3, 4]);
+
+        let hits = search(&searcher, &ConstantScoreQuery::new(term("apple")));
+        assert_eq!(doc_ids(&hits), doc_ids(&apple));
+        assert_scores(&hits, 1.0);
+        let hits = search(&searcher, constant(term("apple"), 2.5).as_ref());
+        assert_eq!(doc_ids(&hits), doc_ids(&apple));
+        assert_scores(&hits, 2.5);
+
+        // the wrapped boosts are ignored
+        let query = constant(constant(term("apple"), 3.0), 2.0);
+        assert_scores(&search(&searcher, query.as_ref()), 2.0);
+        let query = constant(BoostQuery::build(term("apple"), 3.0), 2.0);
+        assert_scores(&search(&searcher, query.as_ref()), 2.0);
+
+        // the enclosing boosts multiply
+        let query = BoostQuery::build(constant(term("apple"), 1.5), 2.0);
+        assert_scores(&search(&searcher, query.as_ref()), 3.0);
+        let query = BoostQuery::build(
+            BoostQuery::build(Box::new(ConstantScoreQuery::new(term("apple"))), 2.0),
+            3.0,
+        );
+        let hits = search(&searcher, query.as_ref());
+        assert_eq!(doc_ids(&hits), doc_ids(&apple));
+        assert_scores(&hits, 6.0);
+
+        let explanation = searcher.explain(query.as_ref(), 3).unwrap();
+        assert!((explanation.value() - 6.0).abs() < 1e-6);
+        assert_eq!(explanation.description(), "boosted by 3, of:");
+        let explanation = &explanation.details()[0];
+        assert_eq!(explanation.description(), "boosted by 2, of:");
+        let boosts: Vec<f32> = explanation.details()[0]
+            .details()
+            .iter()
+            .map(|e| e.value())
+            .collect();
+        assert_eq!(boosts, vec![1.0, 6.0, 1.0]);
+    }
+
+    #[test]
+    fn test_constant_score_over_constant_scorers() {
+        let reader = open_reader();
+        let searcher = DefaultIndexSearcher::new(Arc::clone(&reader));
+        let prefix = || -> Box<dyn Query<CodecEnum>> {
+            Box::new(PrefixQuery::new("body".to_string(), b"ch".to_vec()))
+        };
+        let cherry = search(&searcher, prefix().as_ref());
+        assert_eq!(doc_ids(&cherry), vec![1, 2, 4]);
+
+        // the weight of the prefix query is used as is
+        for &boost in &[1.0, 4.0] {
+            let query = constant(prefix(), boost);
+            let weight = searcher.create_weight(query.as_ref(), true).unwrap();
+            assert_eq!(weight.actual_query_type(), PREFIX);
+            let hits = search(&searcher, query.as_ref());
+            assert_eq!(doc_ids(&hits), doc_ids(&cherry));
+            assert_scores(&hits, boost);
+        }
+        let query = BoostQuery::build(constant(prefix(), 4.0), 0.5);
+        assert_scores(&search(&searcher, query.as_ref()), 2.0);
+        let query = constant(Box::new(MatchAllDocsQuery), 1.5);
+        let hits = search(&searcher, query.as_ref());
+        assert_eq!(doc_ids(&hits), vec![0, 1, 2, 3, 4, 5]);
+        assert_scores(&hits, 1.5);
+    }
+
+    #[test]
+    fn test_constant_score_two_phase() {
+        let reader = open_reader();
+        let searcher = DefaultIndexSearcher::new(Arc::clone(&reader));
+        let phrase = || -> Box<dyn Query<CodecEnum>> {
+            let terms = vec![
+                Term::new("body".to_string(), b"pie".to_vec()),
+                Term::new("body".to_string(), b"apple".to_vec()),
+            ];
+            Box::new(PhraseQuery::build(terms, 0, None, None).unwrap())
+        };
+        assert_eq!(doc_ids(&search(&searcher, phrase().as_ref())), vec![4]);
+
+        let query = constant(phrase(), 2.0);
+        let hits = search(&searcher, query.as_ref());
+        assert_eq!(hits, vec![(4, 2.0)]);
+
+        let weight = searcher.create_weight(query.as_ref(), true).unwrap();
+        let leaves = reader.leaves();
+        let mut scorer = weight.create_scorer(&leaves[1]).unwrap().unwrap();
+        assert!(scorer.support_two_phase());
+        // "apple pie" and "cherry pie apple" contain both terms
+        assert_eq!(scorer.approximate_next().unwrap(), 0);
+        assert!(!scorer.matches().unwrap());
+        assert_eq!(scorer.approximate_next().unwrap(), 1);
+        assert!(scorer.matches().unwrap());
+        assert!((scorer.score().unwrap() - 2.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_match_all_live_docs() {
+        let reader = write_test_index(IndexWriterConfig::default(), |writer| {
+            for body in &["apple", "banana durian", "cherry", "durian"] {
+                writer.add_document(vec![text(body)]).unwrap();
+            }
+            writer.commit().unwrap();
+            writer.delete_documents_by_term("body", b"durian").unwrap();
+            writer.commit().unwrap();
+        });
+        assert_eq!(reader.max_doc(), 4);
+        assert_eq!(reader.num_docs(), 2);
+
+        let searcher = DefaultIndexSearcher::new(Arc::clone(&reader));
+        assert_eq!(searcher.count(&MatchAllDocsQuery).unwrap(), 2);
+        // collected, not counted from the reader
+        let hits = search(
+            &searcher,
+            constant(Box::new(MatchAllDocsQuery), 2.0).as_ref(),
+        );
+        assert_eq!(hits, vec![(0, 2.0), (2, 2.0)]);
+
+        let weight = searcher.create_weight(&MatchAllDocsQuery, true).unwrap();
+        let scorer = weight.create_scorer(&reader.leaves()[0]).unwrap().unwrap();
+        assert_eq!(scorer.cost(), 4);
+        assert!(!scorer.support_two_phase());
+    }
+}
@@ -0,0 +1,184 @@
+// Copyright 2019 Zhizhesihai (Beijing) Technology Limited.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use core::codec::Codec;
+use core::index::LeafReaderContext;
+use core::search::explanation::Explanation;
+use core::search::searcher::SearchPlanBuilder;
+use core::search::term_query::TermQuery;
+use core::search::{Query, Scorer, Weight};
+use core::util::DocId;
+
+use error::Result;
+
+use std::fmt;
+
+pub const MATCH_NO_DOCS: &str = "match_no_docs";
+
+/// Matches no doc, e.g. what a query that can't match anything is built as.
+///
+/// The reason is told by the explanations, to tell such a query apart from
+/// one that just doesn't match the explained doc.
+#[derive(Clone, Debug, Default)]
+pub struct MatchNoDocsQuery {
+    reason: String,
+}
+
+impl MatchNoDocsQuery {
+    pub fn new<S: Into<String>>(reason: S) -> MatchNoDocsQuery {
+        MatchNoDocsQuery {
+            reason: reason.into(),
+        }
+    }
+
+    pub fn reason(&self) -> &str {
+        &self.reason
+    }
+}
+
+impl<C: Codec> Query<C> for MatchNoDocsQuery {
+    fn create_weight(
+        &self,
+        _searcher: &dyn SearchPlanBuilder<C>,
+        _needs_scores: bool,
+    ) -> Result<Box<dyn Weight<C>>> {
+        Ok(Box::new(MatchNoDocsWeight {
+            reason: self.reason.clone(),
+        }))
+    }
+
+    fn extract_terms(&self) -> Vec<TermQuery> {
+        vec![]
+    }
+
+    fn query_type(&self) -> &'static str {
+        MATCH_NO_DOCS
+    }
+
+    fn as_any(&self) -> &::std::any::Any {
+        self
+    }
+}
+
+impl fmt::Display for MatchNoDocsQuery {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "MatchNoDocsQuery(reason: {})", &self.reason)
+    }
+}
+
+struct MatchNoDocsWeight {
+    reason: String,
+}
+
+impl<C: Codec> Weight<C> for MatchNoDocsWeight {
+    fn create_scorer(
+        &self,
+        _leaf_reader: &LeafReaderContext<'_, C>,
+    ) -> Result<Option<Box<dyn Scorer>>> {
+        Ok(None)
+    }
+
+    fn count(&self, _leaf_reader: &LeafReaderContext<'_, C>) -> Result<Option<usize>> {
+        Ok(Some(0))
+    }
+
+    fn query_type(&self) -> &'static str {
+        MATCH_NO_DOCS
+    }
+
+    fn normalize(&mut self, _norm: f32, _boost: f32) {}
+
+    fn value_for_normalization(&self) -> f32 {
+        0f32
+    }
+
+    fn needs_scores(&self) -> bool {
+        false
+    }
+
+    fn explain(&self, _reader: &LeafReaderContext<'_, C>, _doc: DocId) -> Result<Explanation> {
+        Ok(Explanation::new(
+            false,
+            0f32,
+            format!("no matching docs: {}", &self.reason),
+            vec![],
+        ))
+    }
+}
+
+impl fmt::Display for MatchNoDocsWeight {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "MatchNoDocsWeight(reason: {})", &self.reason)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::codec::CodecEnum;
+    use core::doc::{Store, StringField};
+    use core::index::tests::{open_test_reader, TestReader};
+    use core::index::Term;
+    use core::search::boolean_query::BooleanQuery;
+    use core::search::collector::TopDocsCollector;
+    use core::search::searcher::{DefaultIndexSearcher, IndexSearcher};
+
+    use std::sync::Arc;
+
+    fn open_reader() -> Arc<TestReader> {
+        let docs = ["a", "b", "a"]
+            .iter()
+            .map(|tag| vec![StringField::new("tag", tag, Store::No)])
+            .collect();
+        open_test_reader(vec![docs])
+    }
+
+    #[test]
+    fn test_match_no_docs() {
+        let reader = open_reader();
+        let searcher = DefaultIndexSearcher::new(reader);
+        let query = MatchNoDocsQuery::new("nothing to match");
+        assert_eq!(searcher.count(&query).unwrap(), 0);
+        let mut collector = TopDocsCollector::new(10);
+        searcher.search(&query, &mut collector).unwrap();
+        assert!(collector.top_docs().score_docs().is_empty());
+
+        let explanation = searcher.explain(&query, 0).unwrap();
+        assert!(!explanation.is_match());
+        assert_eq!(
+            explanation.description(),
+            "no matching docs: nothing to match"
+        );
+
+        // excluding nothing
+        let a = Term::new("tag".to_string(), b"a".to_vec());
+        let query = BooleanQuery::build_with_must_nots(
+            vec![Box::new(TermQuery::new(a, 1.0, None))],
+            vec![],
+            vec![],
+            vec![Box::new(MatchNoDocsQuery::new("no exclusion"))],
+        )
+        .unwrap();
+        assert_eq!(searcher.count(query.as_ref()).unwrap(), 2);
+    }
+
+    #[test]
+    fn test_empty_boolean_query() {
+        let reader = open_reader();
+        let searcher = DefaultIndexSearcher::new(reader);
+        let query = BooleanQuery::<CodecEnum>::build(vec![], vec![], vec![]).unwrap();
+        let no_docs = query.as_any().downcast_ref::<MatchNoDocsQuery>().unwrap();
+        assert_eq!(no_docs.reason(), "empty BooleanQuery");
+        assert_eq!(searcher.count(query.as_ref()).unwrap(), 0);
+    }
+}
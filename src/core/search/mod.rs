@@ -14,6 +14,7 @@
 use std::any::Any;
 use std::collections::hash_map::DefaultHasher;
 use std::collections::HashMap;
+use std::f32;
 use std::fmt;
 use std::fmt::Display;
 use std::hash::{Hash, Hasher};
@@ -22,6 +23,7 @@ use std::i32;
 use core::codec::Codec;
 use core::index::{LeafReaderContext, SearchLeafReader};
 use core::search::explanation::Explanation;
+use core::search::norm_encoding::{NormEncoding, NormRemap};
 use core::search::searcher::{IndexSearcher, SearchPlanBuilder};
 use core::search::statistics::CollectionStatistics;
 use core::search::statistics::TermStatistics;
@@ -35,6 +37,7 @@ pub mod conjunction;
 pub mod disjunction;
 pub mod filter_query;
 pub mod match_all;
+pub mod match_no_docs;
 pub mod min_score;
 pub mod point_range;
 pub mod posting_iterator;
@@ -42,27 +45,49 @@ pub mod spans;
 
 pub mod bulk_scorer;
 pub mod disi;
+pub mod facets;
 pub mod field_comparator;
+pub mod numeric_facets;
+pub mod req_excl;
 pub mod req_opt;
 pub mod rescorer;
 pub mod search_group;
 pub mod sort;
 pub mod sort_field;
+pub mod suggest;
 pub mod top_docs;
 pub mod util;
 
 // Queries
+pub mod block_join;
 pub mod boolean_query;
 pub mod boost;
+pub mod doc_values_range;
+pub mod field_exists;
+pub mod fuzzy_query;
+pub mod index_or_doc_values;
+pub mod join;
+pub mod legacy_numeric_range;
+pub mod multi_term_query;
 pub mod phrase_query;
+pub mod prefix_query;
+pub mod query_parser;
 pub mod query_string;
+pub mod term_in_set;
 pub mod term_query;
+pub mod term_range_query;
+pub mod wildcard_query;
 
 // Scorers
+pub mod scorer_arena;
 pub mod term_scorer;
+pub mod wand;
 
 // Similarities
 pub mod bm25_similarity;
+pub mod classic_similarity;
+pub mod norm_encoding;
+pub mod per_field_similarity;
 
 // IndexSearcher
 pub mod searcher;
@@ -71,7 +96,10 @@ pub mod searcher;
 pub mod cache_policy;
 pub mod explanation;
 pub mod lru_cache;
+pub mod profile;
 pub mod query_cache;
+pub mod query_timeout;
+pub mod selectivity;
 pub mod statistics;
 
 mod search_manager;
@@ -86,6 +114,18 @@ error_chain! {
         SearchFailed {
             description("Search failed")
         }
+
+        // a query string the `QueryParser` can't parse, at the given char
+        // position
+        QueryParse(position: usize, found: String, expected: Vec<String>) {
+            description("Query parse error")
+            display(
+                "Query parse error at char {}: found {}, expected one of: {}",
+                position,
+                found,
+                expected.join(", ")
+            )
+        }
     }
 }
 
@@ -233,6 +273,13 @@ pub trait Scorer: DocIterator {
     fn score(&mut self) -> Result<f32>;
 
     /// whether this scorer support *two phase iterator*, default to false
+    ///
+    /// A two phase scorer iterates over an approximation of its matches with
+    /// `approximate_next()` and `approximate_advance()`, and only verifies the
+    /// docs it is positioned on with `matches()`, at `match_cost()` per doc.
+    /// Scorers combining two phase children, e.g. conjunctions, should be two
+    /// phase themselves so that the costly verifications run last, on the
+    /// docs all the approximations agree on.
     fn support_two_phase(&self) -> bool {
         false
     }
@@ -244,6 +291,19 @@ pub trait Scorer: DocIterator {
     fn score_feature(&mut self) -> Result<Vec<FeatureResult>> {
         unimplemented!()
     }
+
+    /// Optional method: tells the scorer that documents scoring less than
+    /// `min_score` won't be collected, so that it may skip them without
+    /// scoring. Ignored by default.
+    fn set_min_competitive_score(&mut self, _min_score: f32) -> Result<()> {
+        Ok(())
+    }
+
+    /// Returns an upper bound of the scores of the documents, infinite if the
+    /// scorer can't tell.
+    fn max_score(&self) -> f32 {
+        f32::INFINITY
+    }
 }
 
 impl Scorer for Box<dyn Scorer> {
@@ -262,6 +322,14 @@ impl Scorer for Box<dyn Scorer> {
     fn score_feature(&mut self) -> Result<Vec<FeatureResult>> {
         (**self).score_feature()
     }
+
+    fn set_min_competitive_score(&mut self, min_score: f32) -> Result<()> {
+        (**self).set_min_competitive_score(min_score)
+    }
+
+    fn max_score(&self) -> f32 {
+        (**self).max_score()
+    }
 }
 
 impl DocIterator for Box<dyn Scorer> {
@@ -343,6 +411,29 @@ pub trait Query<C: Codec>: Display {
 pub trait Weight<C: Codec>: Display {
     fn create_scorer(&self, reader: &LeafReaderContext<'_, C>) -> Result<Option<Box<dyn Scorer>>>;
 
+    /// Returns a `ScorerSupplier` for the leaf of `reader`, or `None` if no
+    /// doc of the leaf matches.
+    ///
+    /// This lets the weight pick how to iterate its matches once the cost of
+    /// the iterator leading the search is known, e.g. in a conjunction. The
+    /// default creates the scorer right away.
+    fn scorer_supplier<'a>(
+        &'a self,
+        reader: &'a LeafReaderContext<'_, C>,
+    ) -> Result<Option<Box<dyn ScorerSupplier + 'a>>> {
+        Ok(self.create_scorer(reader)?.map(|scorer| {
+            Box::new(CreatedScorerSupplier::new(scorer)) as Box<dyn ScorerSupplier + 'a>
+        }))
+    }
+
+    /// Returns how many live docs of the leaf of `reader` match, when it is
+    /// known without iterating the matches, e.g. from the doc freq of a term
+    /// in a segment without deletions. Returns `None` when the matches have
+    /// to be counted.
+    fn count(&self, _reader: &LeafReaderContext<'_, C>) -> Result<Option<usize>> {
+        Ok(None)
+    }
+
     fn hash_code(&self) -> u32 {
         let key = format!("{}", self);
         let mut hasher = DefaultHasher::new();
@@ -371,7 +462,49 @@ pub trait Weight<C: Codec>: Display {
     }
 
     /// An explanation of the score computation for the named document.
-    fn explain(&self, reader: &LeafReaderContext<'_, C>, doc: DocId) -> Result<Explanation>;
+    ///
+    /// The weights that don't recompute their score explain every document as
+    /// not matching.
+    fn explain(&self, _reader: &LeafReaderContext<'_, C>, doc: DocId) -> Result<Explanation> {
+        Ok(Explanation::new(
+            false,
+            0.0f32,
+            format!("{} doesn't explain doc {}", self, doc),
+            vec![],
+        ))
+    }
+}
+
+/// Creates the `Scorer` of a leaf once the cost of the iterator leading the
+/// search is known, see `Weight::scorer_supplier`.
+pub trait ScorerSupplier {
+    /// Creates the scorer. `lead_cost` is the cost of the iterator leading
+    /// the search, `usize::max_value()` if the scorer leads the search itself.
+    fn get(self: Box<Self>, lead_cost: usize) -> Result<Box<dyn Scorer>>;
+
+    /// Estimates the cost of the scorer before it is created.
+    fn cost(&self) -> usize;
+}
+
+/// Supplies a scorer that is already created.
+pub struct CreatedScorerSupplier {
+    scorer: Box<dyn Scorer>,
+}
+
+impl CreatedScorerSupplier {
+    pub fn new(scorer: Box<dyn Scorer>) -> CreatedScorerSupplier {
+        CreatedScorerSupplier { scorer }
+    }
+}
+
+impl ScorerSupplier for CreatedScorerSupplier {
+    fn get(self: Box<Self>, _lead_cost: usize) -> Result<Box<dyn Scorer>> {
+        Ok(self.scorer)
+    }
+
+    fn cost(&self) -> usize {
+        self.scorer.cost()
+    }
 }
 
 pub trait BatchScorer {
@@ -469,6 +602,17 @@ pub trait Similarity<C: Codec>: Display {
     fn query_norm(&self, _value_for_normalization: f32, _context: Option<&KeyedContext>) -> f32 {
         1.0f32
     }
+
+    /// Returns how this similarity expects the norms to be encoded at index time,
+    /// or None if it can't tell.
+    fn norm_encoding(&self) -> Option<NormEncoding> {
+        None
+    }
+
+    /// Makes this similarity read norms encoded by another similarity through
+    /// `remap`. Only called on similarities returning a `norm_encoding`, which
+    /// must honor it.
+    fn set_norm_remap(&mut self, _remap: NormRemap) {}
 }
 
 pub trait SimScorer: Send {
@@ -481,6 +625,12 @@ pub trait SimScorer: Send {
     /// Computes the amount of a sloppy phrase match, based on an edit distance.
     fn compute_slop_factor(&self, distance: i32) -> f32;
 
+    /// Returns an upper bound of the score of any frequency, infinite if the
+    /// scores aren't bounded.
+    fn max_score(&self) -> f32 {
+        f32::INFINITY
+    }
+
     // Calculate a scoring factor based on the data in the payload.
     // fn compute_payload_factor(&self, doc: DocId, start: i32, end: i32, payload: &Payload);
 }
@@ -656,6 +806,9 @@ pub trait DocIdSet: Send + Sync {
 pub mod tests {
     use super::*;
 
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+
     pub struct MockDocIterator {
         doc_ids: Vec<DocId>,
         current_doc_id: DocId,
@@ -893,6 +1046,130 @@ pub mod tests {
         MockTwoPhaseScorer::new(all_docs, invalid_docs)
     }
 
+    /// Scores the docs with their ids as `MockSimpleScorer`, and records the
+    /// number of scores computed and the min competitive scores it is given.
+    pub struct RecordingScorer {
+        scorer: MockSimpleScorer<MockDocIterator>,
+        pub scores: usize,
+        pub thresholds: Vec<f32>,
+    }
+
+    impl RecordingScorer {
+        pub fn new(docs: Vec<DocId>) -> RecordingScorer {
+            RecordingScorer {
+                scorer: create_mock_scorer(docs),
+                scores: 0,
+                thresholds: vec![],
+            }
+        }
+    }
+
+    impl Scorer for RecordingScorer {
+        fn score(&mut self) -> Result<f32> {
+            self.scores += 1;
+            self.scorer.score()
+        }
+
+        fn set_min_competitive_score(&mut self, min_score: f32) -> Result<()> {
+            self.thresholds.push(min_score);
+            Ok(())
+        }
+    }
+
+    impl DocIterator for RecordingScorer {
+        fn doc_id(&self) -> DocId {
+            self.scorer.doc_id()
+        }
+
+        fn next(&mut self) -> Result<DocId> {
+            self.scorer.next()
+        }
+
+        fn advance(&mut self, target: DocId) -> Result<DocId> {
+            self.scorer.advance(target)
+        }
+
+        fn cost(&self) -> usize {
+            self.scorer.cost()
+        }
+    }
+
+    /// Records the docs verified by a two phase scorer, reporting `match_cost`
+    /// as its match cost, and counts the calls moving it on by itself.
+    pub struct MatchRecordingScorer<T: Scorer> {
+        scorer: T,
+        match_cost: f32,
+        verified_docs: Arc<Mutex<Vec<DocId>>>,
+        next_calls: Arc<AtomicUsize>,
+    }
+
+    impl<T: Scorer> MatchRecordingScorer<T> {
+        pub fn new(scorer: T, match_cost: f32) -> MatchRecordingScorer<T> {
+            MatchRecordingScorer {
+                scorer,
+                match_cost,
+                verified_docs: Arc::new(Mutex::new(vec![])),
+                next_calls: Arc::new(AtomicUsize::new(0)),
+            }
+        }
+
+        pub fn verified_docs(&self) -> Arc<Mutex<Vec<DocId>>> {
+            Arc::clone(&self.verified_docs)
+        }
+
+        pub fn next_calls(&self) -> Arc<AtomicUsize> {
+            Arc::clone(&self.next_calls)
+        }
+    }
+
+    impl<T: Scorer> Scorer for MatchRecordingScorer<T> {
+        fn score(&mut self) -> Result<f32> {
+            self.scorer.score()
+        }
+
+        fn support_two_phase(&self) -> bool {
+            self.scorer.support_two_phase()
+        }
+    }
+
+    impl<T: Scorer> DocIterator for MatchRecordingScorer<T> {
+        fn doc_id(&self) -> DocId {
+            self.scorer.doc_id()
+        }
+
+        fn next(&mut self) -> Result<DocId> {
+            self.approximate_next()?;
+            two_phase_next(self)
+        }
+
+        fn advance(&mut self, target: DocId) -> Result<DocId> {
+            self.approximate_advance(target)?;
+            two_phase_next(self)
+        }
+
+        fn cost(&self) -> usize {
+            self.scorer.cost()
+        }
+
+        fn matches(&mut self) -> Result<bool> {
+            self.verified_docs.lock()?.push(self.scorer.doc_id());
+            self.scorer.matches()
+        }
+
+        fn match_cost(&self) -> f32 {
+            self.match_cost
+        }
+
+        fn approximate_next(&mut self) -> Result<DocId> {
+            self.next_calls.fetch_add(1, Ordering::Relaxed);
+            self.scorer.approximate_next()
+        }
+
+        fn approximate_advance(&mut self, target: DocId) -> Result<DocId> {
+            self.scorer.approximate_advance(target)
+        }
+    }
+
     #[test]
     fn test_mock_two_phase_scorer() {
         let mut scorer =
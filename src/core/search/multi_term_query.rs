@@ -0,0 +1,415 @@
+// Copyright 2019 Zhizhesihai (Beijing) Technology Limited.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use core::codec::{Codec, CodecTermState};
+use core::index::{LeafReaderContext, SearchLeafReader, SeekStatus, TermIterator, Terms};
+use core::search::disjunction::DisjunctionSumScorer;
+use core::search::explanation::Explanation;
+use core::search::match_all::ConstantScoreScorer;
+use core::search::posting_iterator::PostingIteratorFlags;
+use core::search::query_timeout::{QueryTimeout, DEFAULT_CHECK_INTERVAL};
+use core::search::{DocIdSet, DocIterator, Scorer, Weight};
+use core::util::{DocId, DocIdSetBuilder};
+
+use error::Result;
+
+use std::fmt;
+use std::usize;
+
+/// Up to this many terms of a segment are unioned as a disjunction by
+/// `RewriteMethod::Auto`.
+pub const DEFAULT_TERM_COUNT_THRESHOLD: usize = 16;
+
+/// How a multi-term query turns the terms it matches in a segment into a
+/// constant score scorer.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum RewriteMethod {
+    /// Unions the postings of the terms like a `BooleanQuery` of `SHOULD`
+    /// term clauses would.
+    Boolean,
+    /// Collects the docs of the terms into a doc id set.
+    DocIdSet,
+    /// `Boolean` when at most this many terms match in the segment,
+    /// `DocIdSet` otherwise.
+    Auto(usize),
+}
+
+impl Default for RewriteMethod {
+    fn default() -> Self {
+        RewriteMethod::Auto(DEFAULT_TERM_COUNT_THRESHOLD)
+    }
+}
+
+/// Whether a term enumerated by a multi-term query matches.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum AcceptStatus {
+    Yes,
+    No,
+    /// The term and all the following terms don't match.
+    End,
+}
+
+/// The terms of a field matched by a multi-term query.
+///
+/// The terms are enumerated in order, starting from `lower_bound`, until
+/// `accept` returns `AcceptStatus::End`.
+pub trait TermFilter: fmt::Display + Send + Sync {
+    fn field(&self) -> &str;
+
+    /// The term to seek to before enumerating, `None` to start from the
+    /// smallest term of the field.
+    fn lower_bound(&self) -> Option<&[u8]>;
+
+    fn accept(&self, term: &[u8]) -> AcceptStatus;
+}
+
+/// The terms of a segment matched by a multi-term query.
+pub enum SegmentRewrite<C: Codec> {
+    /// The matching terms and their states.
+    Terms(Vec<(Vec<u8>, CodecTermState<C>)>),
+    /// The docs of the matching terms, once there are more of them than
+    /// the rewrite method unions as a disjunction.
+    DocIdSet(DocIdSetBuilder),
+}
+
+/// Enumerates the terms of `filter` in the segment of `reader`, returns
+/// `None` if no term matches.
+///
+/// The enumeration fails with `Cancelled` once `timeout` is cancelled.
+pub fn rewrite_segment<C: Codec, F: TermFilter + ?Sized>(
+    filter: &F,
+    reader: &SearchLeafReader<C>,
+    method: RewriteMethod,
+    timeout: Option<&QueryTimeout>,
+) -> Result<Option<SegmentRewrite<C>>> {
+    let terms = match reader.terms(filter.field())? {
+        Some(terms) => terms,
+        None => return Ok(None),
+    };
+    let max = terms.max()?;
+    let mut iter = terms.iterator()?;
+    let mut term = match filter.lower_bound() {
+        Some(lower) => {
+            // no need to seek into the terms dictionary past its last term
+            if let Some(ref max) = max {
+                if lower > max.as_slice() {
+                    return Ok(None);
+                }
+            }
+            match iter.seek_ceil(lower)? {
+                SeekStatus::End => return Ok(None),
+                _ => iter.term()?.to_vec(),
+            }
+        }
+        None => match iter.next()? {
+            Some(term) => term,
+            None => return Ok(None),
+        },
+    };
+
+    let threshold = match method {
+        RewriteMethod::Boolean => usize::MAX,
+        RewriteMethod::DocIdSet => 0,
+        RewriteMethod::Auto(threshold) => threshold,
+    };
+    let mut states = vec![];
+    let mut doc_id_set: Option<DocIdSetBuilder> = None;
+    let mut enumerated = 0;
+    loop {
+        if let Some(timeout) = timeout {
+            if enumerated % DEFAULT_CHECK_INTERVAL == 0 {
+                timeout.check()?;
+            }
+            enumerated += 1;
+        }
+        match filter.accept(&term) {
+            AcceptStatus::End => break,
+            AcceptStatus::No => {}
+            AcceptStatus::Yes => {
+                if doc_id_set.is_none() && states.len() >= threshold {
+                    // too many terms, add the docs of the collected ones with
+                    // another iterator to keep the position of this one
+                    let mut builder = DocIdSetBuilder::from_terms(reader.max_doc(), &terms)?;
+                    let mut seeker = terms.iterator()?;
+                    for (term, state) in states.drain(..) {
+                        seeker.seek_exact_state(&term, &state)?;
+                        let mut postings =
+                            seeker.postings_with_flags(PostingIteratorFlags::NONE)?;
+                        builder.add(&mut postings)?;
+                    }
+                    doc_id_set = Some(builder);
+                }
+                if let Some(ref mut builder) = doc_id_set {
+                    let mut postings = iter.postings_with_flags(PostingIteratorFlags::NONE)?;
+                    builder.add(&mut postings)?;
+                } else {
+                    let state = iter.term_state()?;
+                    states.push((term.clone(), state));
+                }
+            }
+        }
+        // the last term of the field, don't read the terms dictionary any further
+        if let Some(ref max) = max {
+            if term >= *max {
+                break;
+            }
+        }
+        term = match iter.next()? {
+            Some(term) => term,
+            None => break,
+        };
+    }
+
+    if let Some(builder) = doc_id_set {
+        Ok(Some(SegmentRewrite::DocIdSet(builder)))
+    } else if states.is_empty() {
+        Ok(None)
+    } else {
+        Ok(Some(SegmentRewrite::Terms(states)))
+    }
+}
+
+/// Matches the docs having any of the terms of a `TermFilter`, with a
+/// constant score.
+pub struct MultiTermWeight<F: TermFilter> {
+    filter: F,
+    rewrite_method: RewriteMethod,
+    query_type: &'static str,
+    timeout: Option<QueryTimeout>,
+    weight: f32,
+    norm: f32,
+}
+
+impl<F: TermFilter> MultiTermWeight<F> {
+    pub fn new(filter: F, rewrite_method: RewriteMethod, query_type: &'static str) -> Self {
+        MultiTermWeight {
+            filter,
+            rewrite_method,
+            query_type,
+            timeout: None,
+            weight: 0f32,
+            norm: 1f32,
+        }
+    }
+
+    /// Stops enumerating the terms of a segment once `timeout` is cancelled,
+    /// see `SearchPlanBuilder::query_timeout`.
+    pub fn with_timeout(mut self, timeout: Option<QueryTimeout>) -> Self {
+        self.timeout = timeout;
+        self
+    }
+}
+
+impl<C: Codec, F: TermFilter + 'static> Weight<C> for MultiTermWeight<F> {
+    fn create_scorer(
+        &self,
+        leaf_reader_ctx: &LeafReaderContext<'_, C>,
+    ) -> Result<Option<Box<dyn Scorer>>> {
+        let reader = leaf_reader_ctx.reader;
+        let timeout = self.timeout.as_ref();
+        match rewrite_segment(&self.filter, reader, self.rewrite_method, timeout)? {
+            Some(SegmentRewrite::Terms(states)) => {
+                let terms = match reader.terms(self.filter.field())? {
+                    Some(terms) => terms,
+                    None => return Ok(None),
+                };
+                let mut iter = terms.iterator()?;
+                let mut children = Vec::with_capacity(states.len());
+                let mut cost = 0;
+                for (term, state) in &states {
+                    iter.seek_exact_state(term, state)?;
+                    let postings = iter.postings_with_flags(PostingIteratorFlags::NONE)?;
+                    let postings_cost = postings.cost();
+                    cost += postings_cost;
+                    children.push(ConstantScoreScorer::new(
+                        self.weight,
+                        postings,
+                        postings_cost,
+                    ));
+                }
+                if children.len() == 1 {
+                    Ok(Some(Box::new(children.remove(0))))
+                } else {
+                    let disjunction = DisjunctionSumScorer::new(children);
+                    Ok(Some(Box::new(ConstantScoreScorer::new(
+                        self.weight,
+                        disjunction,
+                        cost,
+                    ))))
+                }
+            }
+            Some(SegmentRewrite::DocIdSet(mut builder)) => match builder.build().iterator()? {
+                Some(iterator) => {
+                    let cost = iterator.cost();
+                    Ok(Some(Box::new(ConstantScoreScorer::new(
+                        self.weight,
+                        iterator,
+                        cost,
+                    ))))
+                }
+                None => Ok(None),
+            },
+            None => Ok(None),
+        }
+    }
+
+    fn query_type(&self) -> &'static str {
+        self.query_type
+    }
+
+    fn normalize(&mut self, norm: f32, boost: f32) {
+        self.weight = norm * boost;
+        self.norm = norm;
+    }
+
+    fn value_for_normalization(&self) -> f32 {
+        self.weight * self.weight
+    }
+
+    fn needs_scores(&self) -> bool {
+        false
+    }
+
+    fn explain(&self, reader: &LeafReaderContext<'_, C>, doc: DocId) -> Result<Explanation> {
+        let matched = match self.create_scorer(reader)? {
+            Some(mut scorer) => scorer.advance(doc)? == doc,
+            None => false,
+        };
+        if matched {
+            Ok(Explanation::new(
+                true,
+                self.weight,
+                format!("{}, product of:", self),
+                vec![
+                    Explanation::new(true, self.weight, "boost".to_string(), vec![]),
+                    Explanation::new(true, self.norm, "queryNorm".to_string(), vec![]),
+                ],
+            ))
+        } else {
+            Ok(Explanation::new(
+                false,
+                0f32,
+                format!("{} doesn't match id {}", self, doc),
+                vec![],
+            ))
+        }
+    }
+}
+
+impl<F: TermFilter> fmt::Display for MultiTermWeight<F> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "MultiTermWeight({}, rewrite: {:?})",
+            &self.filter, self.rewrite_method
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::doc::{Field, FieldType};
+    use core::index::tests::{open_test_reader, TestReader};
+    use core::index::{IndexOptions, IndexReader};
+    use core::search::prefix_query::PrefixQuery;
+    use core::search::searcher::{DefaultIndexSearcher, IndexSearcher};
+    use core::util::VariantValue;
+
+    use std::sync::Arc;
+
+    // "p000" to "p199", one per doc
+    fn build_index() -> Arc<TestReader> {
+        let mut field_type = FieldType::default();
+        field_type.tokenized = false;
+        field_type.index_options = IndexOptions::Docs;
+        let docs = (0..200)
+            .map(|i| {
+                vec![Field::new(
+                    "id".to_string(),
+                    field_type.clone(),
+                    Some(VariantValue::VString(format!("p{:03}", i))),
+                    None,
+                )]
+            })
+            .collect();
+        open_test_reader(vec![docs])
+    }
+
+    fn prefix(prefix: &str) -> PrefixQuery {
+        PrefixQuery::new("id".to_string(), prefix.as_bytes().to_vec())
+    }
+
+    fn term_count(reader: &Arc<TestReader>, query: &PrefixQuery, method: RewriteMethod) -> usize {
+        let leaves = reader.leaves();
+        match rewrite_segment(query, leaves[0].reader, method, None).unwrap() {
+            Some(SegmentRewrite::Terms(terms)) => terms.len(),
+            Some(SegmentRewrite::DocIdSet(_)) => panic!("{} was rewritten to a doc id set", query),
+            None => 0,
+        }
+    }
+
+    fn is_doc_id_set(reader: &Arc<TestReader>, query: &PrefixQuery, method: RewriteMethod) -> bool {
+        let leaves = reader.leaves();
+        match rewrite_segment(query, leaves[0].reader, method, None).unwrap() {
+            Some(SegmentRewrite::DocIdSet(_)) => true,
+            _ => false,
+        }
+    }
+
+    #[test]
+    fn test_doc_id_set_rewrite_beyond_term_count_threshold() {
+        let reader = build_index();
+        let auto = RewriteMethod::default();
+
+        // 10 terms are unioned, 100 terms are collected into a doc id set
+        assert_eq!(term_count(&reader, &prefix("p01"), auto), 10);
+        assert_eq!(
+            term_count(&reader, &prefix("p1"), RewriteMethod::Auto(100)),
+            100
+        );
+        assert!(is_doc_id_set(&reader, &prefix("p1"), auto));
+        assert!(is_doc_id_set(
+            &reader,
+            &prefix("p1"),
+            RewriteMethod::Auto(99)
+        ));
+        assert_eq!(
+            term_count(&reader, &prefix("p1"), RewriteMethod::Boolean),
+            100
+        );
+        assert!(is_doc_id_set(
+            &reader,
+            &prefix("p01"),
+            RewriteMethod::DocIdSet
+        ));
+        assert_eq!(
+            term_count(&reader, &prefix("x"), RewriteMethod::DocIdSet),
+            0
+        );
+
+        // all the rewrites match the same docs
+        let searcher = DefaultIndexSearcher::new(Arc::clone(&reader));
+        for method in &[
+            RewriteMethod::Boolean,
+            RewriteMethod::DocIdSet,
+            RewriteMethod::Auto(16),
+            RewriteMethod::Auto(100),
+        ] {
+            for &(p, count) in &[("p01", 10), ("p1", 100), ("p", 200), ("p19", 10)] {
+                let query = prefix(p).with_rewrite_method(*method);
+                assert_eq!(searcher.count(&query).unwrap(), count, "{}", query);
+            }
+        }
+    }
+}
@@ -0,0 +1,171 @@
+// Copyright 2019 Zhizhesihai (Beijing) Technology Limited.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use core::index::field_info::FieldInvertState;
+use core::search::bm25_similarity::{BM25_LENGTH_NORM_ENCODING, BM25_NORM_ENCODING};
+use core::search::classic_similarity::CLASSIC_NORM_ENCODING;
+
+use std::fmt;
+use std::sync::Arc;
+
+/// Name of the field attribute recording which similarity encoded the norms
+/// of the field at index time.
+pub const NORM_ENCODING_ATTRIBUTE: &str = "norm_encoding";
+
+/// How a similarity encodes the length of a field into its one byte norm.
+#[derive(Clone, Copy)]
+pub struct NormEncoding {
+    /// Name of the similarity owning this encoding, recorded in the field
+    /// attributes at index time.
+    pub name: &'static str,
+    /// Computes the norm of a field at index time.
+    pub compute_norm: fn(&FieldInvertState) -> i64,
+    /// Decodes a norm into the (boosted) field length it stands for.
+    pub decode_length: fn(u8) -> f32,
+}
+
+impl NormEncoding {
+    /// Returns the known encoding named `name`.
+    pub fn for_name(name: &str) -> Option<NormEncoding> {
+        [
+            BM25_NORM_ENCODING,
+            BM25_LENGTH_NORM_ENCODING,
+            CLASSIC_NORM_ENCODING,
+        ]
+        .iter()
+        .find(|e| e.name == name)
+        .cloned()
+    }
+
+    /// Returns the remapping of the norms written with `source` to the norms
+    /// of this encoding decoding to the nearest field lengths.
+    pub fn remap_from(&self, source: &NormEncoding) -> NormRemap {
+        let mut lengths = [0f32; 256];
+        for (i, length) in lengths.iter_mut().enumerate() {
+            *length = (self.decode_length)(i as u8);
+        }
+        let mut table = [0u8; 256];
+        for (i, norm) in table.iter_mut().enumerate() {
+            let length = (source.decode_length)(i as u8);
+            let mut best = ::std::f32::INFINITY;
+            for (j, l) in lengths.iter().enumerate() {
+                let distance = (l - length).abs();
+                if distance < best {
+                    best = distance;
+                    *norm = j as u8;
+                }
+            }
+        }
+        NormRemap {
+            source: source.name,
+            table: Arc::new(table),
+        }
+    }
+}
+
+impl PartialEq for NormEncoding {
+    fn eq(&self, other: &NormEncoding) -> bool {
+        self.name == other.name
+    }
+}
+
+impl fmt::Debug for NormEncoding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "NormEncoding({})", self.name)
+    }
+}
+
+/// Maps the norms of a field encoded by another similarity to the norms the
+/// searching similarity expects.
+#[derive(Clone, Debug)]
+pub struct NormRemap {
+    /// Name of the similarity that encoded the norms at index time.
+    pub source: &'static str,
+    table: Arc<[u8; 256]>,
+}
+
+impl NormRemap {
+    #[inline]
+    pub fn map(&self, norm: u8) -> u8 {
+        self.table[norm as usize]
+    }
+}
+
+/// What the searcher does when the norms of a field were encoded by another
+/// similarity than the one it searches the field with.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NormMismatchPolicy {
+    /// Fail to create the searcher.
+    Fail,
+    /// Decode the norms as they are, the scores are knowingly off.
+    Ignore,
+    /// Remap the norms to the encoding of the searching similarity.
+    Remap,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn compute_norm(_state: &FieldInvertState) -> i64 {
+        1
+    }
+
+    fn decode_length(norm: u8) -> f32 {
+        f32::from(norm)
+    }
+
+    fn decode_double_length(norm: u8) -> f32 {
+        2.0 * f32::from(norm)
+    }
+
+    #[test]
+    fn test_remap_between_encodings() {
+        let bm25 = NormEncoding::for_name(BM25_NORM_ENCODING.name).unwrap();
+        let classic = NormEncoding::for_name(CLASSIC_NORM_ENCODING.name).unwrap();
+        assert_ne!(bm25, classic);
+        assert!(NormEncoding::for_name("unknown").is_none());
+
+        // both encode the lengths the same way
+        let remap = classic.remap_from(&bm25);
+        assert_eq!(remap.source, bm25.name);
+        for i in 0..256 {
+            assert_eq!(remap.map(i as u8), i as u8);
+        }
+
+        let plain = NormEncoding {
+            name: "plain",
+            compute_norm,
+            decode_length,
+        };
+        let double = NormEncoding {
+            name: "double",
+            compute_norm,
+            decode_length: decode_double_length,
+        };
+        // the exact short lengths map to the nearest lengths of Lucene 6
+        let length = NormEncoding::for_name(BM25_LENGTH_NORM_ENCODING.name).unwrap();
+        let remap = bm25.remap_from(&length);
+        assert_eq!(remap.map(1), 124);
+        assert_eq!(remap.map(3), 121);
+        assert_eq!(remap.map(4), 120);
+
+        let remap = plain.remap_from(&double);
+        assert_eq!(remap.map(3), 6);
+        assert_eq!(remap.map(127), 254);
+        assert_eq!(remap.map(200), 255);
+        let remap = double.remap_from(&plain);
+        assert_eq!(remap.map(6), 3);
+        assert_eq!(remap.map(255), 127);
+    }
+}
@@ -0,0 +1,680 @@
+// Copyright 2019 Zhizhesihai (Beijing) Technology Limited.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Facet counting over numeric doc values: the hits per range of values with
+//! `LongRangeFacetCounts` and per fixed-size bucket with `HistogramCollector`.
+
+use core::codec::Codec;
+use core::index::{DocValuesType, LeafReader, LeafReaderContext, NumericDocValuesRef};
+use core::index::{NumericDocValues, SearchLeafReader};
+use core::search::collector::{Collector, ParallelLeafCollector, ScoreMode, SearchCollector};
+use core::search::collector::{LeafSender, ParallelChannel};
+use core::search::Scorer;
+use core::util::{Bits, BitsRef, DocId};
+
+use error::ErrorKind::IllegalArgument;
+use error::Result;
+
+use std::collections::BTreeMap;
+use std::mem;
+use std::sync::Arc;
+
+// the numeric doc values of a field in a segment
+struct LeafValues {
+    // `None` if no doc of the segment has the field
+    values: Option<(NumericDocValuesRef, BitsRef)>,
+}
+
+impl LeafValues {
+    fn open<C: Codec>(reader: &SearchLeafReader<C>, field: &str) -> Result<LeafValues> {
+        let values = match reader.field_info(field).map(|fi| fi.doc_values_type) {
+            Some(DocValuesType::Numeric) => Some((
+                reader.get_numeric_doc_values(field)?,
+                reader.get_docs_with_field(field)?,
+            )),
+            None | Some(DocValuesType::Null) => None,
+            Some(doc_values_type) => bail!(IllegalArgument(format!(
+                "'{}' has {:?} doc values rather than numeric ones",
+                field, doc_values_type
+            ))),
+        };
+        Ok(LeafValues { values })
+    }
+
+    fn get(&self, doc: DocId) -> Result<Option<i64>> {
+        if let Some((ref values, ref docs_with_field)) = self.values {
+            if docs_with_field.get(doc as usize)? {
+                return Ok(Some(values.get(doc)?));
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// A labeled range of long values.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LongRange {
+    pub label: String,
+    pub min: i64,
+    pub min_inclusive: bool,
+    pub max: i64,
+    pub max_inclusive: bool,
+}
+
+impl LongRange {
+    pub fn new(
+        label: &str,
+        min: i64,
+        min_inclusive: bool,
+        max: i64,
+        max_inclusive: bool,
+    ) -> LongRange {
+        LongRange {
+            label: label.to_string(),
+            min,
+            min_inclusive,
+            max,
+            max_inclusive,
+        }
+    }
+
+    pub fn accept(&self, value: i64) -> bool {
+        let above_min = if self.min_inclusive {
+            value >= self.min
+        } else {
+            value > self.min
+        };
+        let below_max = if self.max_inclusive {
+            value <= self.max
+        } else {
+            value < self.max
+        };
+        above_min && below_max
+    }
+}
+
+// counts `doc` in every range accepting its value, or in the missing bucket
+// after the ranges if it has none and `count_missing`
+fn count_ranges(
+    ranges: &[LongRange],
+    values: &LeafValues,
+    doc: DocId,
+    count_missing: bool,
+    counts: &mut [i32],
+) -> Result<()> {
+    match values.get(doc)? {
+        Some(value) => {
+            for (i, range) in ranges.iter().enumerate() {
+                if range.accept(value) {
+                    counts[i] += 1;
+                }
+            }
+        }
+        None if count_missing => counts[ranges.len()] += 1,
+        None => {}
+    }
+    Ok(())
+}
+
+/// Counts the hits whose value of a numeric doc values field is in each of
+/// a list of ranges. The ranges may overlap, a hit is then counted in all the
+/// ranges its value is in.
+///
+/// The hits without a value are skipped unless a missing bucket is given.
+pub struct LongRangeFacetCounts {
+    field: String,
+    ranges: Arc<Vec<LongRange>>,
+    missing_label: Option<String>,
+    // per range, then for the missing bucket
+    counts: Vec<i32>,
+    // the values of the current leaf
+    values: Option<LeafValues>,
+    channel: ParallelChannel<Vec<i32>>,
+}
+
+impl LongRangeFacetCounts {
+    pub fn new(field: &str, ranges: &[LongRange]) -> LongRangeFacetCounts {
+        LongRangeFacetCounts {
+            field: field.to_string(),
+            ranges: Arc::new(ranges.to_vec()),
+            missing_label: None,
+            counts: vec![0; ranges.len() + 1],
+            values: None,
+            channel: ParallelChannel::default(),
+        }
+    }
+
+    /// Counts the hits without a value in a bucket labeled `label`.
+    pub fn with_missing_bucket(mut self, label: &str) -> LongRangeFacetCounts {
+        self.missing_label = Some(label.to_string());
+        self
+    }
+
+    pub fn field(&self) -> &str {
+        &self.field
+    }
+
+    /// Returns the label and the count of every range in the order they were
+    /// given, followed by the missing bucket if there is one.
+    pub fn counts(&self) -> Vec<(&str, i32)> {
+        let mut counts: Vec<(&str, i32)> = self
+            .ranges
+            .iter()
+            .zip(&self.counts)
+            .map(|(range, &count)| (range.label.as_str(), count))
+            .collect();
+        if let Some(ref label) = self.missing_label {
+            counts.push((label.as_str(), self.counts[self.ranges.len()]));
+        }
+        counts
+    }
+}
+
+impl SearchCollector for LongRangeFacetCounts {
+    type LC = LongRangeFacetLeafCollector;
+
+    fn set_next_reader<C: Codec>(&mut self, reader: &LeafReaderContext<'_, C>) -> Result<()> {
+        self.values = Some(LeafValues::open(reader.reader, &self.field)?);
+        Ok(())
+    }
+
+    fn support_parallel(&self) -> bool {
+        true
+    }
+
+    fn leaf_collector<C: Codec>(
+        &mut self,
+        reader: &LeafReaderContext<'_, C>,
+    ) -> Result<LongRangeFacetLeafCollector> {
+        Ok(LongRangeFacetLeafCollector {
+            ranges: Arc::clone(&self.ranges),
+            count_missing: self.missing_label.is_some(),
+            values: LeafValues::open(reader.reader, &self.field)?,
+            counts: vec![0; self.counts.len()],
+            sender: self.channel.sender(),
+        })
+    }
+
+    fn finish_parallel(&mut self) -> Result<()> {
+        for counts in self.channel.receive() {
+            for (total, count) in self.counts.iter_mut().zip(counts) {
+                *total += count;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Collector for LongRangeFacetCounts {
+    fn score_mode(&self) -> ScoreMode {
+        ScoreMode::CompleteNoScores
+    }
+
+    fn collect<S: Scorer + ?Sized>(&mut self, doc: DocId, _scorer: &mut S) -> Result<()> {
+        if let Some(ref values) = self.values {
+            let count_missing = self.missing_label.is_some();
+            count_ranges(&self.ranges, values, doc, count_missing, &mut self.counts)?;
+        }
+        Ok(())
+    }
+}
+
+pub struct LongRangeFacetLeafCollector {
+    ranges: Arc<Vec<LongRange>>,
+    count_missing: bool,
+    values: LeafValues,
+    counts: Vec<i32>,
+    sender: LeafSender<Vec<i32>>,
+}
+
+impl Collector for LongRangeFacetLeafCollector {
+    fn score_mode(&self) -> ScoreMode {
+        ScoreMode::CompleteNoScores
+    }
+
+    fn collect<S: Scorer + ?Sized>(&mut self, doc: DocId, _scorer: &mut S) -> Result<()> {
+        count_ranges(
+            &self.ranges,
+            &self.values,
+            doc,
+            self.count_missing,
+            &mut self.counts,
+        )
+    }
+}
+
+impl ParallelLeafCollector for LongRangeFacetLeafCollector {
+    fn finish_leaf(&mut self) -> Result<()> {
+        let counts = mem::replace(&mut self.counts, vec![]);
+        self.sender.send(counts)
+    }
+}
+
+// the histogram buckets of one or more leaves
+#[derive(Clone, Copy)]
+struct Buckets {
+    interval: i64,
+    offset: i64,
+    // the value the docs without one are counted as
+    missing_value: Option<i64>,
+}
+
+// the bucket of a value
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum BucketKey {
+    // the bucket starts below the lowest long
+    Underflow,
+    // the lowest value of the bucket
+    Key(i64),
+    // the bucket ends above the highest long
+    Overflow,
+}
+
+impl Buckets {
+    fn key(&self, value: i64) -> BucketKey {
+        let (interval, offset) = (i128::from(self.interval), i128::from(self.offset));
+        let shifted = i128::from(value) - offset;
+        let mut bucket = shifted / interval;
+        if shifted % interval < 0 {
+            bucket -= 1;
+        }
+        let start = bucket * interval + offset;
+        if start < i128::from(i64::min_value()) {
+            BucketKey::Underflow
+        } else if start + interval > i128::from(i64::max_value()) + 1 {
+            BucketKey::Overflow
+        } else {
+            BucketKey::Key(start as i64)
+        }
+    }
+
+    fn count(&self, values: &LeafValues, doc: DocId, counts: &mut BucketCounts) -> Result<()> {
+        if let Some(value) = values.get(doc)?.or(self.missing_value) {
+            match self.key(value) {
+                BucketKey::Underflow => counts.underflow += 1,
+                BucketKey::Key(key) => *counts.buckets.entry(key).or_insert(0) += 1,
+                BucketKey::Overflow => counts.overflow += 1,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+struct BucketCounts {
+    // bucket key -> count
+    buckets: BTreeMap<i64, i32>,
+    underflow: i32,
+    overflow: i32,
+}
+
+impl BucketCounts {
+    fn add(&mut self, other: BucketCounts) {
+        for (key, count) in other.buckets {
+            *self.buckets.entry(key).or_insert(0) += count;
+        }
+        self.underflow += other.underflow;
+        self.overflow += other.overflow;
+    }
+}
+
+/// Counts the hits per bucket of the values of a numeric doc values field,
+/// the buckets being `[offset + k * interval, offset + (k + 1) * interval)`
+/// for every integer `k`.
+///
+/// The hits without a value are skipped unless a missing value is given. The
+/// values whose bucket doesn't fit in the longs, which only happens near the
+/// lowest and the highest long, are counted in an underflow and an overflow
+/// bucket.
+pub struct HistogramCollector {
+    field: String,
+    buckets: Buckets,
+    counts: BucketCounts,
+    // the values of the current leaf
+    values: Option<LeafValues>,
+    channel: ParallelChannel<BucketCounts>,
+}
+
+impl HistogramCollector {
+    pub fn new(field: &str, interval: i64, offset: i64) -> Result<HistogramCollector> {
+        if interval <= 0 {
+            bail!(IllegalArgument(format!(
+                "the histogram interval must be positive, got {}",
+                interval
+            )));
+        }
+        Ok(HistogramCollector {
+            field: field.to_string(),
+            buckets: Buckets {
+                interval,
+                offset,
+                missing_value: None,
+            },
+            counts: BucketCounts::default(),
+            values: None,
+            channel: ParallelChannel::default(),
+        })
+    }
+
+    /// Counts the hits without a value as if they had `value`.
+    pub fn with_missing_value(mut self, value: i64) -> HistogramCollector {
+        self.buckets.missing_value = Some(value);
+        self
+    }
+
+    pub fn field(&self) -> &str {
+        &self.field
+    }
+
+    /// Returns the key, the lowest value, and the count of the buckets with
+    /// at least one hit, by increasing key.
+    pub fn buckets(&self) -> Vec<(i64, i32)> {
+        self.counts
+            .buckets
+            .iter()
+            .map(|(&key, &count)| (key, count))
+            .collect()
+    }
+
+    /// Number of hits whose bucket starts below the lowest long.
+    pub fn underflow(&self) -> i32 {
+        self.counts.underflow
+    }
+
+    /// Number of hits whose bucket ends above the highest long.
+    pub fn overflow(&self) -> i32 {
+        self.counts.overflow
+    }
+}
+
+impl SearchCollector for HistogramCollector {
+    type LC = HistogramLeafCollector;
+
+    fn set_next_reader<C: Codec>(&mut self, reader: &LeafReaderContext<'_, C>) -> Result<()> {
+        self.values = Some(LeafValues::open(reader.reader, &self.field)?);
+        Ok(())
+    }
+
+    fn support_parallel(&self) -> bool {
+        true
+    }
+
+    fn leaf_collector<C: Codec>(
+        &mut self,
+        reader: &LeafReaderContext<'_, C>,
+    ) -> Result<HistogramLeafCollector> {
+        Ok(HistogramLeafCollector {
+            buckets: self.buckets,
+            values: LeafValues::open(reader.reader, &self.field)?,
+            counts: BucketCounts::default(),
+            sender: self.channel.sender(),
+        })
+    }
+
+    fn finish_parallel(&mut self) -> Result<()> {
+        for counts in self.channel.receive() {
+            self.counts.add(counts);
+        }
+        Ok(())
+    }
+}
+
+impl Collector for HistogramCollector {
+    fn score_mode(&self) -> ScoreMode {
+        ScoreMode::CompleteNoScores
+    }
+
+    fn collect<S: Scorer + ?Sized>(&mut self, doc: DocId, _scorer: &mut S) -> Result<()> {
+        if let Some(ref values) = self.values {
+            self.buckets.count(values, doc, &mut self.counts)?;
+        }
+        Ok(())
+    }
+}
+
+pub struct HistogramLeafCollector {
+    buckets: Buckets,
+    values: LeafValues,
+    counts: BucketCounts,
+    sender: LeafSender<BucketCounts>,
+}
+
+impl Collector for HistogramLeafCollector {
+    fn score_mode(&self) -> ScoreMode {
+        ScoreMode::CompleteNoScores
+    }
+
+    fn collect<S: Scorer + ?Sized>(&mut self, doc: DocId, _scorer: &mut S) -> Result<()> {
+        self.buckets.count(&self.values, doc, &mut self.counts)
+    }
+}
+
+impl ParallelLeafCollector for HistogramLeafCollector {
+    fn finish_leaf(&mut self) -> Result<()> {
+        let counts = mem::replace(&mut self.counts, BucketCounts::default());
+        self.sender.send(counts)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::doc::{NumericDocValuesField, Store, StringField};
+    use core::index::tests::{open_test_reader, TestReader};
+    use core::index::{Fieldable, Term};
+    use core::search::match_all::MatchAllDocsQuery;
+    use core::search::searcher::{DefaultIndexSearcher, IndexSearcher};
+    use core::search::term_query::TermQuery;
+    use core::util::thread_pool::ThreadPoolBuilder;
+
+    // the kind and the price of every doc, in segments of 10 docs, the
+    // second segment has no price at all
+    fn docs() -> Vec<(&'static str, Option<i64>)> {
+        (0..60i64)
+            .map(|i| {
+                let kind = if i % 3 == 0 { "sale" } else { "stock" };
+                let price = if i / 10 == 1 || i % 7 == 0 {
+                    None
+                } else {
+                    Some((i * 37) % 101 - 50)
+                };
+                (kind, price)
+            })
+            .collect()
+    }
+
+    fn document(kind: &str, price: Option<i64>) -> Vec<Box<dyn Fieldable>> {
+        let mut doc: Vec<Box<dyn Fieldable>> =
+            vec![Box::new(StringField::new("kind", kind, Store::No))];
+        if let Some(price) = price {
+            doc.push(Box::new(NumericDocValuesField::new("price", price)));
+        }
+        doc
+    }
+
+    fn index() -> Arc<TestReader> {
+        let segments = docs()
+            .chunks(10)
+            .map(|docs| {
+                docs.iter()
+                    .map(|&(kind, price)| document(kind, price))
+                    .collect()
+            })
+            .collect();
+        open_test_reader(segments)
+    }
+
+    fn sale_prices() -> Vec<Option<i64>> {
+        docs()
+            .into_iter()
+            .filter(|&(kind, _)| kind == "sale")
+            .map(|(_, price)| price)
+            .collect()
+    }
+
+    fn sale_query() -> TermQuery {
+        TermQuery::new(Term::new("kind".into(), b"sale".to_vec()), 1.0, None)
+    }
+
+    fn ranges() -> Vec<LongRange> {
+        vec![
+            LongRange::new("negative", i64::min_value(), true, 0, false),
+            LongRange::new("small", -10, true, 10, true),
+            LongRange::new("positive", 0, false, i64::max_value(), true),
+            LongRange::new("empty", 5, false, 5, false),
+        ]
+    }
+
+    #[test]
+    fn test_long_range_facet_counts() {
+        let reader = index();
+        assert_eq!(reader.leaves().len(), 6);
+        let single = DefaultIndexSearcher::new(Arc::clone(&reader));
+        let pool = ThreadPoolBuilder::with_default_factory("search".into())
+            .thread_count(4)
+            .build();
+        let parallel = DefaultIndexSearcher::new(Arc::clone(&reader)).with_executor(Arc::new(pool));
+
+        let prices = sale_prices();
+        let mut expected: Vec<(&str, i32)> = ranges()
+            .iter()
+            .map(|r| {
+                let count = prices.iter().filter(|p| p.map_or(false, |p| r.accept(p)));
+                (r.label.as_str(), count.count() as i32)
+            })
+            .collect();
+        // the ranges overlap
+        assert!(expected.iter().map(|c| c.1).sum::<i32>() > prices.len() as i32);
+        assert_eq!(expected[3].1, 0);
+
+        let ranges = ranges();
+        let mut counts = LongRangeFacetCounts::new("price", &ranges);
+        single.search(&sale_query(), &mut counts).unwrap();
+        assert_eq!(counts.counts(), expected);
+
+        let missing = prices.iter().filter(|p| p.is_none()).count() as i32;
+        assert!(missing > 0);
+        expected.push(("missing", missing));
+        for _ in 0..3 {
+            let mut counts =
+                LongRangeFacetCounts::new("price", &ranges).with_missing_bucket("missing");
+            parallel.search(&sale_query(), &mut counts).unwrap();
+            assert_eq!(counts.counts(), expected);
+        }
+        let mut counts = LongRangeFacetCounts::new("price", &ranges).with_missing_bucket("missing");
+        single.search(&sale_query(), &mut counts).unwrap();
+        assert_eq!(counts.counts(), expected);
+    }
+
+    fn brute_force_histogram(
+        prices: &[Option<i64>],
+        interval: i64,
+        offset: i64,
+        missing_value: Option<i64>,
+    ) -> Vec<(i64, i32)> {
+        let mut buckets = BTreeMap::new();
+        for price in prices.iter().filter_map(|p| p.or(missing_value)) {
+            // the highest key not above the price
+            let mut key = offset;
+            while key > price {
+                key -= interval;
+            }
+            while key + interval <= price {
+                key += interval;
+            }
+            *buckets.entry(key).or_insert(0) += 1;
+        }
+        buckets.into_iter().collect()
+    }
+
+    #[test]
+    fn test_histogram_collector() {
+        let reader = index();
+        let single = DefaultIndexSearcher::new(Arc::clone(&reader));
+        let pool = ThreadPoolBuilder::with_default_factory("search".into())
+            .thread_count(4)
+            .build();
+        let parallel = DefaultIndexSearcher::new(Arc::clone(&reader)).with_executor(Arc::new(pool));
+        let prices = sale_prices();
+
+        for &(interval, offset) in &[(10, 0), (10, 3), (7, -5), (25, 112), (1, 0)] {
+            let expected = brute_force_histogram(&prices, interval, offset, None);
+            let mut histogram = HistogramCollector::new("price", interval, offset).unwrap();
+            single.search(&sale_query(), &mut histogram).unwrap();
+            assert_eq!(histogram.buckets(), expected);
+            // negative values have keys below them
+            assert!(histogram.buckets()[0].0 < 0);
+
+            let mut histogram = HistogramCollector::new("price", interval, offset)
+                .unwrap()
+                .with_missing_value(-1000);
+            parallel.search(&sale_query(), &mut histogram).unwrap();
+            let expected = brute_force_histogram(&prices, interval, offset, Some(-1000));
+            assert_eq!(histogram.buckets(), expected);
+        }
+
+        assert!(HistogramCollector::new("price", 0, 0).is_err());
+        let buckets = Buckets {
+            interval: 10,
+            offset: 3,
+            missing_value: None,
+        };
+        assert_eq!(buckets.key(3), BucketKey::Key(3));
+        assert_eq!(buckets.key(2), BucketKey::Key(-7));
+        assert_eq!(buckets.key(-7), BucketKey::Key(-7));
+        assert_eq!(buckets.key(-8), BucketKey::Key(-17));
+    }
+
+    #[test]
+    fn test_histogram_out_of_range_buckets() {
+        let buckets = Buckets {
+            interval: 10,
+            offset: 3,
+            missing_value: None,
+        };
+        let (min, max) = (i64::min_value(), i64::max_value());
+        // the lowest key is the first one not below the lowest long
+        let lowest = min + (3 - min % 10 + 10) % 10;
+        assert_eq!(lowest % 10, 3 - 10);
+        assert_eq!(buckets.key(min), BucketKey::Underflow);
+        assert_eq!(buckets.key(lowest - 1), BucketKey::Underflow);
+        assert_eq!(buckets.key(lowest), BucketKey::Key(lowest));
+        // the highest key is the last one whose bucket ends at most past the
+        // highest long
+        let highest = max - 9 - (max - 9 - 3) % 10;
+        assert_eq!(buckets.key(highest - 1), BucketKey::Key(highest - 10));
+        assert_eq!(buckets.key(highest), BucketKey::Key(highest));
+        assert_eq!(buckets.key(highest + 10), BucketKey::Overflow);
+        assert_eq!(buckets.key(max), BucketKey::Overflow);
+
+        // a bucket ending right past the highest long fits
+        let buckets = Buckets {
+            interval: 1 << 62,
+            offset: 0,
+            missing_value: None,
+        };
+        assert_eq!(buckets.key(min), BucketKey::Key(min));
+        assert_eq!(buckets.key(max), BucketKey::Key(1 << 62));
+
+        let docs = [min, min + 1, 0, 5, max - 1, max]
+            .iter()
+            .map(|&price| vec![NumericDocValuesField::new("price", price)])
+            .collect();
+        let searcher = DefaultIndexSearcher::new(open_test_reader(vec![docs]));
+        let mut histogram = HistogramCollector::new("price", 10, 3).unwrap();
+        searcher.search(&MatchAllDocsQuery, &mut histogram).unwrap();
+        assert_eq!(histogram.underflow(), 1);
+        assert_eq!(histogram.buckets(), vec![(min + 1, 1), (-7, 1), (3, 1)]);
+        assert_eq!(histogram.overflow(), 2);
+    }
+}
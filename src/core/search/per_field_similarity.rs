@@ -0,0 +1,177 @@
+// Copyright 2019 Zhizhesihai (Beijing) Technology Limited.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use core::codec::Codec;
+use core::search::{Similarity, SimilarityProducer};
+
+use std::collections::HashMap;
+
+/// Searches some fields with other similarities than the default one.
+///
+/// The norms of these fields must be encoded the way their similarity decodes
+/// them, see `IndexWriterConfig::field_norm_encodings`.
+pub struct PerFieldSimilarityWrapper<C: Codec> {
+    default: Box<dyn SimilarityProducer<C> + Send + Sync>,
+    fields: HashMap<String, Box<dyn SimilarityProducer<C> + Send + Sync>>,
+}
+
+impl<C: Codec> PerFieldSimilarityWrapper<C> {
+    pub fn new<P>(default: P) -> PerFieldSimilarityWrapper<C>
+    where
+        P: SimilarityProducer<C> + Send + Sync + 'static,
+    {
+        PerFieldSimilarityWrapper {
+            default: Box::new(default),
+            fields: HashMap::new(),
+        }
+    }
+
+    /// Searches `field` with the similarities created by `producer`.
+    pub fn set_similarity<P>(&mut self, field: String, producer: P)
+    where
+        P: SimilarityProducer<C> + Send + Sync + 'static,
+    {
+        self.fields.insert(field, Box::new(producer));
+    }
+
+    pub fn with_similarity<P>(mut self, field: String, producer: P) -> Self
+    where
+        P: SimilarityProducer<C> + Send + Sync + 'static,
+    {
+        self.set_similarity(field, producer);
+        self
+    }
+}
+
+impl<C: Codec> SimilarityProducer<C> for PerFieldSimilarityWrapper<C> {
+    fn create(&self, field: &str) -> Box<dyn Similarity<C>> {
+        match self.fields.get(field) {
+            Some(producer) => producer.create(field),
+            None => self.default.create(field),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::codec::CodecEnum;
+    use core::doc::{Field, FieldType};
+    use core::index::index_writer_config::IndexWriterConfig;
+    use core::index::tests::{open_test_reader_with, TestReader};
+    use core::index::{IndexOptions, Term};
+    use core::search::bm25_similarity::BM25SimilarityProducer;
+    use core::search::classic_similarity::{ClassicSimilarityProducer, CLASSIC_NORM_ENCODING};
+    use core::search::collector::TopDocsCollector;
+    use core::search::norm_encoding::NormMismatchPolicy;
+    use core::search::searcher::{DefaultIndexSearcher, DefaultSimilarityProducer, IndexSearcher};
+    use core::search::term_query::TermQuery;
+    use core::util::{DocId, VariantValue};
+
+    use std::sync::Arc;
+
+    // "title" and "body" have the same values, the norms of "body" are encoded
+    // by ClassicSimilarity
+    fn open_reader() -> Arc<TestReader> {
+        let mut conf = IndexWriterConfig::default();
+        conf.field_norm_encodings
+            .insert("body".to_string(), CLASSIC_NORM_ENCODING);
+        let mut field_type = FieldType::default();
+        field_type.omit_norms = false;
+        field_type.index_options = IndexOptions::DocsAndFreqs;
+        let long = vec!["z"; 16].join(" ");
+        let docs: Vec<Vec<Field>> = ["x", "x x x y", long.as_str()]
+            .iter()
+            .map(|value| {
+                ["title", "body"]
+                    .iter()
+                    .map(|name| {
+                        Field::new(
+                            name.to_string(),
+                            field_type.clone(),
+                            Some(VariantValue::VString(value.to_string())),
+                            None,
+                        )
+                    })
+                    .collect()
+            })
+            .collect();
+        open_test_reader_with(conf, vec![docs])
+    }
+
+    fn search<S: IndexSearcher<CodecEnum>>(searcher: &S, field: &str) -> Vec<(DocId, f32)> {
+        let query = TermQuery::new(Term::new(field.to_string(), b"x".to_vec()), 1.0, None);
+        let mut collector = TopDocsCollector::new(10);
+        searcher.search(&query, &mut collector).unwrap();
+        collector
+            .top_docs()
+            .score_docs()
+            .iter()
+            .map(|d| (d.doc_id(), d.score()))
+            .collect()
+    }
+
+    fn ranking(hits: &[(DocId, f32)]) -> Vec<DocId> {
+        hits.iter().map(|h| h.0).collect()
+    }
+
+    #[test]
+    fn test_per_field_similarity() {
+        let reader = open_reader();
+        let similarity = PerFieldSimilarityWrapper::new(DefaultSimilarityProducer)
+            .with_similarity("body".to_string(), ClassicSimilarityProducer);
+        let searcher = DefaultIndexSearcher::with_similarity_checked(
+            Arc::clone(&reader),
+            similarity,
+            NormMismatchPolicy::Fail,
+        )
+        .unwrap();
+
+        // BM25 saturates the frequency of "x" in the longer doc less than the
+        // vector space model
+        assert_eq!(ranking(&search(&searcher, "title")), vec![1, 0]);
+        assert_eq!(ranking(&search(&searcher, "body")), vec![0, 1]);
+
+        // the norms of "body" were not encoded by BM25
+        assert!(DefaultIndexSearcher::with_similarity_checked(
+            Arc::clone(&reader),
+            DefaultSimilarityProducer,
+            NormMismatchPolicy::Fail,
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_bm25_parameters() {
+        let reader = open_reader();
+        let idf = (1.0f64 + (3.0 - 2.0 + 0.5) / (2.0 + 0.5)).ln();
+        let mut similarity = PerFieldSimilarityWrapper::new(ClassicSimilarityProducer);
+        // without length normalization the score only depends on the frequency
+        similarity.set_similarity("title".to_string(), BM25SimilarityProducer::new(1.2, 0.0));
+        let searcher = DefaultIndexSearcher::with_similarity(Arc::clone(&reader), similarity);
+
+        let hits = search(&searcher, "title");
+        assert_eq!(ranking(&hits), vec![1, 0]);
+        for &(doc, score) in &hits {
+            let freq = if doc == 0 { 1.0 } else { 3.0 };
+            let expected = idf * freq * 2.2 / (freq + 1.2);
+            assert!(
+                (f64::from(score) - expected).abs() < 1e-5,
+                "{} != {}",
+                score,
+                expected
+            );
+        }
+        assert_eq!(ranking(&search(&searcher, "body")), vec![0, 1]);
+    }
+}
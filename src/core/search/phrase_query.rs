@@ -19,7 +19,7 @@ use std::collections::{HashMap, HashSet};
 use std::f32;
 use std::fmt;
 
-use core::codec::{Codec, CodecTermState};
+use core::codec::{Codec, CodecPostingIterator, CodecTermState};
 use core::index::{LeafReaderContext, Term, TermIterator, Terms};
 use core::search::conjunction::ConjunctionScorer;
 use core::search::explanation::Explanation;
@@ -122,11 +122,93 @@ impl PhraseQuery {
         Self::new(terms, positions, slop, ctx, ctxs)
     }
 
+    pub fn terms(&self) -> &[Term] {
+        &self.terms
+    }
+
+    /// The positions of the terms relative to the first one.
+    pub fn positions(&self) -> &[i32] {
+        &self.positions
+    }
+
+    pub fn slop(&self) -> i32 {
+        self.slop
+    }
+
     fn increment_positions(length: usize) -> Vec<i32> {
         (0..length as i32).collect()
     }
 }
 
+/// Builds a `PhraseQuery` term by term.
+///
+/// Terms may be added at explicit positions, which allows gaps in the phrase,
+/// e.g. a phrase "quick _ fox" matches "quick brown fox" as well as "quick red fox".
+#[derive(Default)]
+pub struct PhraseQueryBuilder {
+    slop: i32,
+    terms: Vec<Term>,
+    positions: Vec<i32>,
+}
+
+impl PhraseQueryBuilder {
+    pub fn new() -> PhraseQueryBuilder {
+        Default::default()
+    }
+
+    /// Set the number of other words permitted between words in the phrase.
+    pub fn set_slop(&mut self, slop: i32) -> &mut Self {
+        self.slop = slop;
+        self
+    }
+
+    /// Add a term right after the last added term.
+    pub fn append(&mut self, term: Term) -> Result<&mut Self> {
+        let position = self.positions.last().map(|p| *p + 1).unwrap_or(0);
+        self.add(term, position)
+    }
+
+    /// Add a term at the given position, positions must be added in
+    /// non-decreasing order and all terms must belong to the same field.
+    pub fn add(&mut self, term: Term, position: i32) -> Result<&mut Self> {
+        if position < 0 {
+            bail!(ErrorKind::IllegalArgument(format!(
+                "Positions must be >= 0, got {}",
+                position
+            )));
+        }
+        if let Some(last) = self.positions.last() {
+            if position < *last {
+                bail!(ErrorKind::IllegalArgument(format!(
+                    "Positions must be added in order, got {} after {}",
+                    position, last
+                )));
+            }
+        }
+        if let Some(first) = self.terms.first() {
+            if first.field != term.field {
+                bail!(ErrorKind::IllegalArgument(format!(
+                    "All terms must be on the same field, got {} and {}",
+                    first.field, term.field
+                )));
+            }
+        }
+        self.terms.push(term);
+        self.positions.push(position);
+        Ok(self)
+    }
+
+    pub fn build(&self) -> Result<PhraseQuery> {
+        PhraseQuery::new(
+            self.terms.clone(),
+            self.positions.clone(),
+            self.slop,
+            None,
+            None,
+        )
+    }
+}
+
 impl<C: Codec> Query<C> for PhraseQuery {
     fn create_weight(
         &self,
@@ -261,14 +343,13 @@ impl<C: Codec> PhraseWeight<C> {
         Ok(TERM_POSNS_SEEK_OPS_PER_DOC as f32
             + exp_occurrences_in_matching_doc * TERM_OPS_PER_POS as f32)
     }
-}
 
-impl<C: Codec> Weight<C> for PhraseWeight<C> {
-    fn create_scorer(
+    // the postings of the terms in the segment, `None` if one of them is missing
+    fn postings_freqs(
         &self,
         reader_context: &LeafReaderContext<'_, C>,
-    ) -> Result<Option<Box<dyn Scorer>>> {
-        debug_assert!(!self.terms.len() >= 2);
+    ) -> Result<Option<(Vec<PostingsAndFreq<CodecPostingIterator<C>>>, f32)>> {
+        debug_assert!(self.terms.len() >= 2);
 
         let mut postings_freqs = Vec::with_capacity(self.terms.len());
         let mut term_iter = if let Some(field_terms) = reader_context.reader.terms(&self.field)? {
@@ -302,6 +383,19 @@ impl<C: Codec> Weight<C> for PhraseWeight<C> {
                 &self.terms[i],
             ));
         }
+        Ok(Some((postings_freqs, total_match_cost)))
+    }
+}
+
+impl<C: Codec> Weight<C> for PhraseWeight<C> {
+    fn create_scorer(
+        &self,
+        reader_context: &LeafReaderContext<'_, C>,
+    ) -> Result<Option<Box<dyn Scorer>>> {
+        let (mut postings_freqs, total_match_cost) = match self.postings_freqs(reader_context)? {
+            Some(postings) => postings,
+            None => return Ok(None),
+        };
 
         let sim_scorer = self.sim_weight.sim_scorer(reader_context.reader)?;
         let scorer: Box<dyn Scorer> = if self.slop == 0 {
@@ -344,48 +438,9 @@ impl<C: Codec> Weight<C> for PhraseWeight<C> {
     }
 
     fn explain(&self, reader: &LeafReaderContext<'_, C>, doc: DocId) -> Result<Explanation> {
-        debug_assert!(!self.terms.len() >= 2);
-
-        let mut matched = true;
-        let mut postings_freqs = Vec::with_capacity(self.terms.len());
-        let mut term_iter = if let Some(field_terms) = reader.reader.terms(&self.field)? {
-            debug_assert!(
-                field_terms.has_positions()?,
-                format!(
-                    "field {} was indexed without position data; cannot run PhraseQuery \
-                     (phrase={:?})",
-                    self.field, self.terms
-                )
-            );
-            Some(field_terms.iterator()?)
-        } else {
-            matched = false;
-            None
-        };
-
-        let mut total_match_cost = 0f32;
-        for i in 0..self.terms.len() {
-            if let Some(state) = self.term_states[i].get(&reader.doc_base()) {
-                if let Some(ref mut term_iter) = term_iter {
-                    term_iter.seek_exact_state(self.terms[i].bytes.as_ref(), state)?;
-                    total_match_cost += self.term_positions_cost(term_iter)?;
-
-                    let postings =
-                        term_iter.postings_with_flags(PostingIteratorFlags::POSITIONS)?;
-                    postings_freqs.push(PostingsAndFreq::new(
-                        postings,
-                        self.positions[i],
-                        &self.terms[i],
-                    ));
-                }
-            } else {
-                matched = false;
-            }
-        }
-
-        if matched {
+        if let Some((mut postings_freqs, total_match_cost)) = self.postings_freqs(reader)? {
             let sim_scorer = self.sim_weight.sim_scorer(reader.reader)?;
-            if self.slop == 0 {
+            let freq = if self.slop == 0 {
                 postings_freqs.sort();
                 let mut scorer = ExactPhraseScorer::new(
                     postings_freqs,
@@ -393,19 +448,10 @@ impl<C: Codec> Weight<C> for PhraseWeight<C> {
                     self.needs_scores,
                     total_match_cost,
                 );
-
                 if scorer.advance(doc)? == doc {
-                    let freq = scorer.freq as f32;
-                    let freq_expl =
-                        Explanation::new(true, freq, format!("phraseFreq={}", freq), vec![]);
-                    let score_expl = self.sim_weight.explain(reader.reader, doc, freq_expl)?;
-
-                    return Ok(Explanation::new(
-                        true,
-                        score_expl.value(),
-                        format!("weight({} in {}), result of:", self, doc),
-                        vec![score_expl],
-                    ));
+                    Some(scorer.freq as f32)
+                } else {
+                    None
                 }
             } else {
                 let mut scorer = SloppyPhraseScorer::new(
@@ -415,27 +461,31 @@ impl<C: Codec> Weight<C> for PhraseWeight<C> {
                     self.needs_scores,
                     total_match_cost,
                 );
-
                 if scorer.advance(doc)? == doc {
-                    let freq = scorer.sloppy_freq;
-                    let freq_expl =
-                        Explanation::new(true, freq, format!("phraseFreq={}", freq), vec![]);
-                    let score_expl = self.sim_weight.explain(reader.reader, doc, freq_expl)?;
-
-                    return Ok(Explanation::new(
-                        true,
-                        score_expl.value(),
-                        format!("weight({} in {}), result of:", self, doc),
-                        vec![score_expl],
-                    ));
+                    Some(scorer.sloppy_freq)
+                } else {
+                    None
                 }
+            };
+
+            if let Some(freq) = freq {
+                let freq_expl =
+                    Explanation::new(true, freq, format!("phraseFreq={}", freq), vec![]);
+                let score_expl = self.sim_weight.explain(reader.reader, doc, freq_expl)?;
+
+                return Ok(Explanation::new(
+                    true,
+                    score_expl.value(),
+                    format!("weight({} in {}), result of:", self, doc),
+                    vec![score_expl],
+                ));
             }
         }
 
         Ok(Explanation::new(
             false,
             0.0f32,
-            "no matching term".to_string(),
+            "no matching phrase".to_string(),
             vec![],
         ))
     }
@@ -556,13 +606,12 @@ impl<T: PostingIterator + 'static> ExactPhraseScorer<T> {
         let mut postings_and_positions: Vec<PostingsAndPosition> =
             Vec::with_capacity(postings.len());
 
-        for (i, posing) in postings.into_iter().enumerate() {
-            let mut iterator = posing.postings;
+        for posting in postings {
+            // the conjunction reorders its children by cost, so the postings are
+            // boxed to keep the raw pointers below valid once they are moved
+            let mut iterator = Box::new(posting.postings);
+            postings_and_positions.push(PostingsAndPosition::new(iterator.as_mut(), posting.pos));
             iterators.push(PostingsIterAsScorer { iterator });
-            postings_and_positions.push(PostingsAndPosition::new(
-                &mut iterators[i].iterator,
-                posing.pos,
-            ));
         }
 
         let conjunction = ConjunctionScorer::new(iterators);
@@ -648,27 +697,6 @@ impl<T: PostingIterator + 'static> ExactPhraseScorer<T> {
         self.freq = freq;
         Ok(self.freq)
     }
-
-    pub fn matches(&mut self) -> Result<bool> {
-        Ok(self.phrase_freq()? > 0)
-    }
-
-    pub fn match_cost(&self) -> f32 {
-        self.match_cost
-    }
-
-    pub fn do_next(&mut self, doc_id: DocId) -> Result<DocId> {
-        let mut doc = doc_id;
-        loop {
-            if doc == NO_MORE_DOCS {
-                return Ok(NO_MORE_DOCS);
-            } else if self.matches()? {
-                return Ok(doc);
-            }
-
-            doc = self.conjunction.next()?;
-        }
-    }
 }
 
 impl<T: PostingIterator + 'static> Scorer for ExactPhraseScorer<T> {
@@ -677,6 +705,10 @@ impl<T: PostingIterator + 'static> Scorer for ExactPhraseScorer<T> {
         let freq = self.freq as f32;
         self.doc_scorer.score(doc_id, freq)
     }
+
+    fn support_two_phase(&self) -> bool {
+        true
+    }
 }
 
 impl<T: PostingIterator + 'static> DocIterator for ExactPhraseScorer<T> {
@@ -685,18 +717,34 @@ impl<T: PostingIterator + 'static> DocIterator for ExactPhraseScorer<T> {
     }
 
     fn next(&mut self) -> Result<DocId> {
-        let doc_id = self.conjunction.next()?;
-        self.do_next(doc_id)
+        self.approximate_next()?;
+        two_phase_next(self)
     }
 
     fn advance(&mut self, target: DocId) -> Result<DocId> {
-        let doc_id = self.conjunction.advance(target)?;
-        self.do_next(doc_id)
+        self.approximate_advance(target)?;
+        two_phase_next(self)
     }
 
     fn cost(&self) -> usize {
         self.conjunction.cost()
     }
+
+    fn matches(&mut self) -> Result<bool> {
+        Ok(self.phrase_freq()? > 0)
+    }
+
+    fn match_cost(&self) -> f32 {
+        self.match_cost
+    }
+
+    fn approximate_next(&mut self) -> Result<DocId> {
+        self.conjunction.next()
+    }
+
+    fn approximate_advance(&mut self, target: DocId) -> Result<DocId> {
+        self.conjunction.advance(target)
+    }
 }
 
 /// Position of a term in a document that takes into account the term offset within the phrase.
@@ -828,8 +876,10 @@ impl PartialEq for PPElement {
 impl Eq for PPElement {}
 
 // TODO a fake scorer struct used for `ConjunctionScorer`
+// the iterator is boxed so that its address is stable for the raw pointers
+// held by `PostingsAndPosition` and `PhrasePositions`
 struct PostingsIterAsScorer<T: PostingIterator> {
-    pub iterator: T,
+    pub iterator: Box<T>,
 }
 
 impl<T: PostingIterator> Scorer for PostingsIterAsScorer<T> {
@@ -897,14 +947,14 @@ impl<T: PostingIterator + 'static> SloppyPhraseScorer<T> {
         let mut doc_iterators = Vec::with_capacity(num_postings);
         let mut phrase_positions = Vec::with_capacity(num_postings);
         for (idx, posting) in postings.into_iter().enumerate() {
-            let mut iterator = posting.postings;
-            doc_iterators.push(PostingsIterAsScorer { iterator });
+            let mut iterator = Box::new(posting.postings);
             phrase_positions.push(PhrasePositions::new(
-                &mut doc_iterators[idx].iterator,
+                iterator.as_mut(),
                 posting.pos,
                 idx as i32,
-                posting.terms.clone(),
+                posting.terms,
             ));
+            doc_iterators.push(PostingsIterAsScorer { iterator });
         }
         let conjunction = ConjunctionScorer::new(doc_iterators);
         let pq = BinaryHeap::with_capacity(num_postings);
@@ -1477,3 +1527,225 @@ impl<T: PostingIterator + 'static> DocIterator for SloppyPhraseScorer<T> {
         self.conjunction.advance(target)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::codec::CodecEnum;
+    use core::doc::{Field, FieldType};
+    use core::index::tests::{open_test_reader, TestReader};
+    use core::index::{IndexOptions, IndexReader};
+    use core::search::collector::TopDocsCollector;
+    use core::search::conjunction::ConjunctionScorer;
+    use core::search::searcher::{DefaultIndexSearcher, IndexSearcher};
+    use core::search::term_query::TermQuery;
+    use core::search::tests::MatchRecordingScorer;
+    use core::util::VariantValue;
+
+    use std::sync::Arc;
+
+    fn text(value: &str) -> Field {
+        let mut field_type = FieldType::default();
+        field_type.index_options = IndexOptions::DocsAndFreqsAndPositions;
+        Field::new(
+            "body".to_string(),
+            field_type,
+            Some(VariantValue::VString(value.to_string())),
+            None,
+        )
+    }
+
+    fn term(value: &str) -> Term {
+        Term::new("body".to_string(), value.as_bytes().to_vec())
+    }
+
+    fn phrase(words: &[&str], slop: i32) -> PhraseQuery {
+        PhraseQuery::build(words.iter().map(|w| term(w)).collect(), slop, None, None).unwrap()
+    }
+
+    // three segments, the last one does not contain "fox"
+    fn open_reader() -> Arc<TestReader> {
+        let segments: &[&[&str]] = &[
+            &[
+                "the quick brown fox jumps",
+                "quick fox brown",
+                "brown quick fox",
+                "the quick red fox",
+            ],
+            &[
+                "a quick brown fox and a quick brown fox",
+                "fox brown quick",
+                "buffalo buffalo buffalo",
+            ],
+            &["quick brown dog"],
+        ];
+        let reader = open_test_reader(
+            segments
+                .iter()
+                .map(|docs| docs.iter().map(|body| vec![text(body)]).collect())
+                .collect(),
+        );
+        assert_eq!(reader.leaves().len(), 3);
+        reader
+    }
+
+    fn search<S: IndexSearcher<CodecEnum>>(searcher: &S, query: &PhraseQuery) -> Vec<(i32, f32)> {
+        let mut collector = TopDocsCollector::new(10);
+        searcher.search(query, &mut collector).unwrap();
+        let mut hits: Vec<_> = collector
+            .top_docs()
+            .score_docs()
+            .iter()
+            .map(|hit| (hit.doc_id(), hit.score()))
+            .collect();
+        hits.sort_by_key(|hit| hit.0);
+        hits
+    }
+
+    fn docs(hits: &[(i32, f32)]) -> Vec<i32> {
+        hits.iter().map(|hit| hit.0).collect()
+    }
+
+    #[test]
+    fn test_exact_phrase_query() {
+        let reader = open_reader();
+        let searcher = DefaultIndexSearcher::new(Arc::clone(&reader));
+
+        let query = phrase(&["quick", "brown", "fox"], 0);
+        let hits = search(&searcher, &query);
+        assert_eq!(docs(&hits), vec![0, 4]);
+        for &(doc, score) in &hits {
+            let explanation = searcher.explain(&query, doc).unwrap();
+            assert!((explanation.value() - score).abs() < 1e-5);
+        }
+
+        // "quick _ fox" leaves a gap for any word
+        let mut builder = PhraseQueryBuilder::new();
+        builder.add(term("quick"), 0).unwrap();
+        builder.add(term("fox"), 2).unwrap();
+        let gap = builder.build().unwrap();
+        assert_eq!(docs(&search(&searcher, &gap)), vec![0, 3, 4]);
+
+        // repeated terms are matched against different positions
+        assert_eq!(
+            docs(&search(&searcher, &phrase(&["buffalo", "buffalo"], 0))),
+            vec![6]
+        );
+        assert!(search(&searcher, &phrase(&["quick", "quick"], 0)).is_empty());
+    }
+
+    #[test]
+    fn test_sloppy_phrase_query() {
+        let reader = open_reader();
+        let searcher = DefaultIndexSearcher::new(Arc::clone(&reader));
+
+        // "quick fox brown" and "brown quick fox" are within two moves, "fox brown quick"
+        // needs four
+        let query = phrase(&["quick", "brown", "fox"], 2);
+        let hits = search(&searcher, &query);
+        assert_eq!(docs(&hits), vec![0, 1, 2, 4]);
+        // docs of the same length with the same edit distance score the same,
+        // less than the exact match
+        assert!((hits[1].1 - hits[2].1).abs() < 1e-5);
+        assert!(hits[0].1 > hits[1].1);
+        for &(doc, score) in &hits {
+            let explanation = searcher.explain(&query, doc).unwrap();
+            assert!((explanation.value() - score).abs() < 1e-5);
+        }
+
+        assert_eq!(
+            docs(&search(&searcher, &phrase(&["quick", "brown", "fox"], 4))),
+            vec![0, 1, 2, 4, 5]
+        );
+        assert_eq!(
+            docs(&search(&searcher, &phrase(&["buffalo", "buffalo"], 1))),
+            vec![6]
+        );
+    }
+
+    #[test]
+    fn test_phrase_scorer_two_phase() {
+        let reader = open_reader();
+        let searcher = DefaultIndexSearcher::new(Arc::clone(&reader));
+        let leaves = reader.leaves();
+
+        for &slop in &[0, 2] {
+            let query = phrase(&["quick", "brown", "fox"], slop);
+            let weight = searcher.create_weight(&query, true).unwrap();
+
+            // the approximation visits every doc containing all the terms
+            let mut scorer = weight.create_scorer(&leaves[0]).unwrap().unwrap();
+            assert!(scorer.support_two_phase());
+            assert_eq!(scorer.approximate_next().unwrap(), 0);
+            assert!(scorer.matches().unwrap());
+            assert_eq!(scorer.approximate_next().unwrap(), 1);
+            assert_eq!(scorer.matches().unwrap(), slop > 0);
+            assert_eq!(scorer.approximate_advance(2).unwrap(), 2);
+            assert_eq!(scorer.matches().unwrap(), slop > 0);
+            assert_eq!(scorer.approximate_next().unwrap(), NO_MORE_DOCS);
+
+            let mut scorer = weight.create_scorer(&leaves[1]).unwrap().unwrap();
+            assert_eq!(scorer.next().unwrap(), 0);
+            assert_eq!(scorer.next().unwrap(), NO_MORE_DOCS);
+
+            // "fox" does not occur in the last segment
+            assert!(weight.create_scorer(&leaves[2]).unwrap().is_none());
+        }
+    }
+
+    #[test]
+    fn test_phrase_verified_after_term() {
+        let reader = open_reader();
+        let searcher = DefaultIndexSearcher::new(Arc::clone(&reader));
+        let leaves = reader.leaves();
+        let phrase_weight = searcher
+            .create_weight(&phrase(&["quick", "brown"], 0), true)
+            .unwrap();
+        let term_weight = searcher
+            .create_weight(&TermQuery::new(term("the"), 1.0, None), true)
+            .unwrap();
+
+        // alone, the phrase decodes the positions of every doc with both terms
+        let mut scorer = MatchRecordingScorer::new(
+            phrase_weight.create_scorer(&leaves[0]).unwrap().unwrap(),
+            1.0,
+        );
+        let verified = scorer.verified_docs();
+        assert_eq!(scorer.next().unwrap(), 0);
+        assert_eq!(scorer.next().unwrap(), NO_MORE_DOCS);
+        assert_eq!(*verified.lock().unwrap(), vec![0, 1, 2]);
+
+        // along with "the", only the docs containing "the" as well
+        let phrase_scorer = MatchRecordingScorer::new(
+            phrase_weight.create_scorer(&leaves[0]).unwrap().unwrap(),
+            1.0,
+        );
+        let verified = phrase_scorer.verified_docs();
+        let term_scorer = term_weight.create_scorer(&leaves[0]).unwrap().unwrap();
+        assert!(!term_scorer.support_two_phase());
+        let scorers: Vec<Box<dyn Scorer>> = vec![Box::new(phrase_scorer), term_scorer];
+        let mut conjunction = ConjunctionScorer::new(scorers);
+        assert!(conjunction.support_two_phase());
+        assert_eq!(conjunction.next().unwrap(), 0);
+        assert_eq!(conjunction.next().unwrap(), NO_MORE_DOCS);
+        assert_eq!(*verified.lock().unwrap(), vec![0]);
+    }
+
+    #[test]
+    fn test_phrase_query_builder() {
+        let mut builder = PhraseQueryBuilder::new();
+        builder.set_slop(1);
+        builder.append(term("quick")).unwrap();
+        builder.append(term("fox")).unwrap();
+        assert!(builder.add(term("brown"), 0).is_err());
+        assert!(builder
+            .add(Term::new("title".to_string(), b"dog".to_vec()), 3)
+            .is_err());
+        builder.add(term("jumps"), 3).unwrap();
+        let query = builder.build().unwrap();
+        assert_eq!(query.positions, vec![0, 1, 3]);
+        assert_eq!(query.slop, 1);
+
+        assert!(PhraseQueryBuilder::new().build().is_err());
+    }
+}
@@ -13,16 +13,18 @@
 
 use error::{ErrorKind, Result};
 use std::fmt;
+use std::sync::Arc;
 
-use core::codec::Codec;
+use core::codec::{Codec, CodecPointsReader};
 use core::doc::{DoublePoint, FloatPoint, IntPoint, LongPoint};
 use core::index::{IntersectVisitor, PointValues, Relation};
-use core::index::{LeafReader, LeafReaderContext};
+use core::index::{LeafReader, LeafReaderContext, SearchLeafReader};
 use core::search::explanation::Explanation;
 use core::search::match_all::{AllDocsIterator, ConstantScoreScorer};
+use core::search::query_timeout::{QueryTimeout, DEFAULT_CHECK_INTERVAL};
 use core::search::searcher::SearchPlanBuilder;
 use core::search::term_query::TermQuery;
-use core::search::{DocIdSet, Query, Scorer, Weight};
+use core::search::{CreatedScorerSupplier, DocIdSet, Query, Scorer, ScorerSupplier, Weight};
 use core::search::{DocIterator, EmptyDocIterator};
 use core::util::doc_id_set::{DocIdSetDocIterEnum, DocIdSetEnum};
 use core::util::{DocId, DocIdSetBuilder};
@@ -33,6 +35,7 @@ pub enum PointValueType {
     Float,
     Double,
     Long,
+    Binary,
     /* Byte,
      * SmallFloat,
      * Short */
@@ -45,6 +48,7 @@ impl PointValueType {
             PointValueType::Double => DoublePoint::decode_dimension(bytes).to_string(),
             PointValueType::Integer => IntPoint::decode_dimension(bytes).to_string(),
             PointValueType::Long => LongPoint::decode_dimension(bytes).to_string(),
+            PointValueType::Binary => format!("{:?}", bytes),
         }
     }
 
@@ -67,6 +71,7 @@ impl fmt::Display for PointValueType {
             PointValueType::Float => "float",
             PointValueType::Double => "double",
             PointValueType::Long => "long",
+            PointValueType::Binary => "binary",
         };
         write!(f, "{}", value)
     }
@@ -114,6 +119,130 @@ impl PointRangeQuery {
             value_type,
         })
     }
+
+    /// Matches the docs with a long point between `lower` and `upper`
+    /// (inclusive) in every dimension.
+    pub fn new_long_range(field: String, lower: &[i64], upper: &[i64]) -> Result<PointRangeQuery> {
+        Self::check_dims(lower.len(), upper.len())?;
+        PointRangeQuery::new(
+            field,
+            LongPoint::pack(lower),
+            LongPoint::pack(upper),
+            lower.len(),
+            PointValueType::Long,
+        )
+    }
+
+    /// Matches the docs with an int point between `lower` and `upper`
+    /// (inclusive) in every dimension.
+    pub fn new_int_range(field: String, lower: &[i32], upper: &[i32]) -> Result<PointRangeQuery> {
+        Self::check_dims(lower.len(), upper.len())?;
+        PointRangeQuery::new(
+            field,
+            IntPoint::pack(lower),
+            IntPoint::pack(upper),
+            lower.len(),
+            PointValueType::Integer,
+        )
+    }
+
+    /// Matches the docs with a float point between `lower` and `upper`
+    /// (inclusive) in every dimension, `-0.0` being less than `0.0`.
+    pub fn new_float_range(field: String, lower: &[f32], upper: &[f32]) -> Result<PointRangeQuery> {
+        Self::check_dims(lower.len(), upper.len())?;
+        PointRangeQuery::new(
+            field,
+            FloatPoint::pack(lower),
+            FloatPoint::pack(upper),
+            lower.len(),
+            PointValueType::Float,
+        )
+    }
+
+    /// Matches the docs with a double point between `lower` and `upper`
+    /// (inclusive) in every dimension, `-0.0` being less than `0.0`.
+    pub fn new_double_range(
+        field: String,
+        lower: &[f64],
+        upper: &[f64],
+    ) -> Result<PointRangeQuery> {
+        Self::check_dims(lower.len(), upper.len())?;
+        PointRangeQuery::new(
+            field,
+            DoublePoint::pack(lower),
+            DoublePoint::pack(upper),
+            lower.len(),
+            PointValueType::Double,
+        )
+    }
+
+    /// Matches the docs with a binary point between `lower` and `upper`
+    /// (inclusive, compared as unsigned bytes) in every dimension. All the
+    /// dimensions must have the same number of bytes.
+    pub fn new_binary_range(
+        field: String,
+        lower: &[&[u8]],
+        upper: &[&[u8]],
+    ) -> Result<PointRangeQuery> {
+        Self::check_dims(lower.len(), upper.len())?;
+        let bytes_per_dim = lower[0].len();
+        if bytes_per_dim == 0 {
+            bail!(ErrorKind::IllegalArgument(
+                "binary point dimensions must not be empty".into()
+            ));
+        }
+        for value in lower.iter().chain(upper.iter()) {
+            if value.len() != bytes_per_dim {
+                bail!(ErrorKind::IllegalArgument(format!(
+                    "all dimensions must have {} bytes, got {}",
+                    bytes_per_dim,
+                    value.len()
+                )));
+            }
+        }
+        PointRangeQuery::new(
+            field,
+            lower.concat(),
+            upper.concat(),
+            lower.len(),
+            PointValueType::Binary,
+        )
+    }
+
+    fn check_dims(lower_dims: usize, upper_dims: usize) -> Result<()> {
+        if lower_dims == 0 {
+            bail!(ErrorKind::IllegalArgument(
+                "a point range needs at least one dimension".into()
+            ));
+        }
+        if lower_dims != upper_dims {
+            bail!(ErrorKind::IllegalArgument(format!(
+                "lower has {} dimensions but upper has {}",
+                lower_dims, upper_dims
+            )));
+        }
+        Ok(())
+    }
+
+    pub fn field(&self) -> &str {
+        &self.field
+    }
+
+    pub fn num_dims(&self) -> usize {
+        self.num_dims
+    }
+
+    pub fn bytes_per_dim(&self) -> usize {
+        self.bytes_per_dim
+    }
+
+    pub fn lower_point(&self) -> &[u8] {
+        &self.lower_point
+    }
+
+    pub fn upper_point(&self) -> &[u8] {
+        &self.upper_point
+    }
 }
 
 pub const POINT_RANGE: &str = "point_range";
@@ -121,17 +250,19 @@ pub const POINT_RANGE: &str = "point_range";
 impl<C: Codec> Query<C> for PointRangeQuery {
     fn create_weight(
         &self,
-        _searcher: &dyn SearchPlanBuilder<C>,
+        searcher: &dyn SearchPlanBuilder<C>,
         _needs_scores: bool,
     ) -> Result<Box<dyn Weight<C>>> {
-        Ok(Box::new(PointRangeWeight::new(
+        let mut weight = PointRangeWeight::new(
             self.field.clone(),
             self.num_dims,
             self.bytes_per_dim,
             self.lower_point.clone(),
             self.upper_point.clone(),
             self.value_type,
-        )))
+        );
+        weight.timeout = searcher.query_timeout().cloned();
+        Ok(Box::new(weight))
     }
 
     fn extract_terms(&self) -> Vec<TermQuery> {
@@ -172,6 +303,8 @@ struct PointRangeWeight {
     lower_point: Vec<u8>,
     upper_point: Vec<u8>,
     value_type: PointValueType,
+    // stops the intersection of the points once cancelled
+    timeout: Option<QueryTimeout>,
     weight: f32,
     norm: f32,
 }
@@ -192,11 +325,36 @@ impl PointRangeWeight {
             lower_point,
             upper_point,
             value_type,
+            timeout: None,
             weight: 0f32,
             norm: 1f32,
         }
     }
 
+    // how a cell of the points relates to the range
+    fn relate(&self, min_packed_value: &[u8], max_packed_value: &[u8]) -> Relation {
+        let mut crosses = false;
+        let bytes = self.bytes_per_dim;
+        for dim in 0..self.num_dims {
+            let offset = dim * bytes;
+            let end = offset + bytes;
+            if min_packed_value[offset..end] > self.upper_point[offset..end]
+                || max_packed_value[offset..end] < self.lower_point[offset..end]
+            {
+                return Relation::CellOutsideQuery;
+            }
+
+            crosses |= min_packed_value[offset..end] < self.lower_point[offset..end]
+                || max_packed_value[offset..end] > self.upper_point[offset..end];
+        }
+
+        if crosses {
+            Relation::CellCrossesQuery
+        } else {
+            Relation::CellInsideQuery
+        }
+    }
+
     fn build_matching_doc_set<R: LeafReader + ?Sized>(
         &self,
         reader: &R,
@@ -207,6 +365,10 @@ impl PointRangeWeight {
             let mut visitor = PointRangeIntersectVisitor::new(&mut result, self);
             values.intersect(&self.field, &mut visitor)?;
         }
+        // the cells left once cancelled were skipped
+        if let Some(ref timeout) = self.timeout {
+            timeout.check()?;
+        }
 
         Ok(result.build())
     }
@@ -217,62 +379,107 @@ impl<C: Codec> Weight<C> for PointRangeWeight {
         &self,
         leaf_reader_ctx: &LeafReaderContext<'_, C>,
     ) -> Result<Option<Box<dyn Scorer>>> {
+        match self.scorer_supplier(leaf_reader_ctx)? {
+            Some(supplier) => Ok(Some(supplier.get(usize::max_value())?)),
+            None => Ok(None),
+        }
+    }
+
+    fn scorer_supplier<'a>(
+        &'a self,
+        leaf_reader_ctx: &'a LeafReaderContext<'_, C>,
+    ) -> Result<Option<Box<dyn ScorerSupplier + 'a>>> {
         let leaf_reader = leaf_reader_ctx.reader;
-        if let Some(ref values) = leaf_reader.point_values() {
-            if let Some(field_info) = leaf_reader.field_info(&self.field) {
-                if field_info.point_dimension_count != self.num_dims as u32 {
-                    bail!(ErrorKind::IllegalArgument(format!(
-                        "field '{}' was indexed with num_dims={} but this query has num_dims={}",
-                        &self.field, field_info.point_dimension_count, self.num_dims
-                    )));
-                }
-                if self.bytes_per_dim as u32 != field_info.point_num_bytes {
-                    bail!(ErrorKind::IllegalArgument(format!(
-                        "field '{}' was indexed with bytes_per_dim={} but this query has \
-                         bytes_per_dim={}",
-                        &self.field, field_info.point_num_bytes, self.bytes_per_dim
-                    )));
-                }
+        let values = match leaf_reader.point_values() {
+            Some(values) => values,
+            None => return Ok(None),
+        };
+        let field_info = match leaf_reader.field_info(&self.field) {
+            Some(field_info) => field_info,
+            None => return Ok(None),
+        };
+        if field_info.point_dimension_count != self.num_dims as u32 {
+            bail!(ErrorKind::IllegalArgument(format!(
+                "field '{}' was indexed with num_dims={} but this query has num_dims={}",
+                &self.field, field_info.point_dimension_count, self.num_dims
+            )));
+        }
+        if self.bytes_per_dim as u32 != field_info.point_num_bytes {
+            bail!(ErrorKind::IllegalArgument(format!(
+                "field '{}' was indexed with bytes_per_dim={} but this query has bytes_per_dim={}",
+                &self.field, field_info.point_num_bytes, self.bytes_per_dim
+            )));
+        }
 
-                let mut all_docs_match = false;
-                if values.doc_count(&self.field)? == leaf_reader.max_doc() {
-                    let field_packed_lower = values.min_packed_value(&self.field)?;
-                    let field_packed_upper = values.max_packed_value(&self.field)?;
-
-                    all_docs_match = true;
-                    for i in 0..self.num_dims {
-                        let offset = i * self.bytes_per_dim;
-                        let end = offset + self.bytes_per_dim;
-                        if self.lower_point[offset..end] > field_packed_lower[offset..end]
-                            || self.upper_point[offset..end] < field_packed_upper[offset..end]
-                        {
-                            all_docs_match = false;
-                            break;
-                        }
-                    }
+        let mut all_docs_match = false;
+        if values.doc_count(&self.field)? == leaf_reader.max_doc() {
+            let field_packed_lower = values.min_packed_value(&self.field)?;
+            let field_packed_upper = values.max_packed_value(&self.field)?;
+
+            all_docs_match = true;
+            for i in 0..self.num_dims {
+                let offset = i * self.bytes_per_dim;
+                let end = offset + self.bytes_per_dim;
+                if self.lower_point[offset..end] > field_packed_lower[offset..end]
+                    || self.upper_point[offset..end] < field_packed_upper[offset..end]
+                {
+                    all_docs_match = false;
+                    break;
                 }
+            }
+        }
+
+        if all_docs_match {
+            let max_doc = leaf_reader.max_doc();
+            let iterator = PointDocIterEnum::All(AllDocsIterator::new(max_doc));
+            let scorer = ConstantScoreScorer::new(self.weight, iterator, max_doc as usize);
+            return Ok(Some(Box::new(CreatedScorerSupplier::new(Box::new(scorer)))));
+        }
+
+        // the points are only visited once the scorer is needed
+        let mut visitor = PointRangeEstimateVisitor { weight: self };
+        let cost = values.estimate_point_count(&self.field, &mut visitor)?;
+        Ok(Some(Box::new(PointRangeScorerSupplier {
+            weight: self,
+            reader: leaf_reader,
+            values,
+            cost: cost.max(0) as usize,
+        })))
+    }
 
-                let iterator = if all_docs_match {
-                    PointDocIterEnum::All(AllDocsIterator::new(leaf_reader.max_doc()))
+    fn count(&self, leaf_reader_ctx: &LeafReaderContext<'_, C>) -> Result<Option<usize>> {
+        let leaf_reader = leaf_reader_ctx.reader;
+        match leaf_reader.field_info(&self.field) {
+            Some(fi) if fi.point_dimension_count == 0 => return Ok(Some(0)),
+            Some(fi)
+                if fi.point_dimension_count != self.num_dims as u32
+                    || fi.point_num_bytes != self.bytes_per_dim as u32 =>
+            {
+                // the scorer tells the mismatch
+                return Ok(None);
+            }
+            Some(_) => {}
+            None => return Ok(Some(0)),
+        }
+        let values = match leaf_reader.point_values() {
+            Some(values) => values,
+            None => return Ok(Some(0)),
+        };
+        let min_packed_value = values.min_packed_value(&self.field)?;
+        let max_packed_value = values.max_packed_value(&self.field)?;
+        match self.relate(&min_packed_value, &max_packed_value) {
+            Relation::CellOutsideQuery => Ok(Some(0)),
+            Relation::CellInsideQuery if leaf_reader.num_docs() == leaf_reader.max_doc() => {
+                // every point matches, which tells the docs when each has one
+                let doc_count = values.doc_count(&self.field)?;
+                if values.size(&self.field)? == i64::from(doc_count) {
+                    Ok(Some(doc_count as usize))
                 } else {
-                    if let Some(iter) = self
-                        .build_matching_doc_set(leaf_reader, values)?
-                        .iterator()?
-                    {
-                        PointDocIterEnum::DocSet(iter)
-                    } else {
-                        PointDocIterEnum::None(EmptyDocIterator::default())
-                    }
-                };
-                let cost = iterator.cost();
-                return Ok(Some(Box::new(ConstantScoreScorer::new(
-                    self.weight,
-                    iterator,
-                    cost,
-                ))));
+                    Ok(None)
+                }
             }
+            _ => Ok(None),
         }
-        Ok(None)
     }
 
     fn query_type(&self) -> &'static str {
@@ -318,6 +525,7 @@ impl fmt::Display for PointRangeWeight {
 struct PointRangeIntersectVisitor<'a> {
     doc_id_set_builder: &'a mut DocIdSetBuilder,
     weight: &'a PointRangeWeight,
+    visited: usize,
 }
 
 impl<'a> PointRangeIntersectVisitor<'a> {
@@ -328,17 +536,30 @@ impl<'a> PointRangeIntersectVisitor<'a> {
         PointRangeIntersectVisitor {
             doc_id_set_builder,
             weight,
+            visited: 0,
+        }
+    }
+
+    fn check_timeout(&mut self) -> Result<()> {
+        if let Some(ref timeout) = self.weight.timeout {
+            if self.visited % DEFAULT_CHECK_INTERVAL == 0 {
+                timeout.check()?;
+            }
+            self.visited += 1;
         }
+        Ok(())
     }
 }
 
 impl<'a> IntersectVisitor for PointRangeIntersectVisitor<'a> {
     fn visit(&mut self, doc_id: DocId) -> Result<()> {
+        self.check_timeout()?;
         self.doc_id_set_builder.add_doc(doc_id);
         Ok(())
     }
 
     fn visit_by_packed_value(&mut self, doc_id: DocId, packed_value: &[u8]) -> Result<()> {
+        self.check_timeout()?;
         let bytes = self.weight.bytes_per_dim;
         for dim in 0..self.weight.num_dims {
             let offset = dim * bytes;
@@ -355,27 +576,13 @@ impl<'a> IntersectVisitor for PointRangeIntersectVisitor<'a> {
     }
 
     fn compare(&self, min_packed_value: &[u8], max_packed_value: &[u8]) -> Relation {
-        let mut crosses = false;
-        let bytes = self.weight.bytes_per_dim;
-        for dim in 0..self.weight.num_dims {
-            let offset = dim * bytes;
-            let end = offset + bytes;
-            if min_packed_value[offset..end] > self.weight.upper_point[offset..end]
-                || max_packed_value[offset..end] < self.weight.lower_point[offset..]
-            {
+        // skips the cells left, the cancellation is told once intersected
+        if let Some(ref timeout) = self.weight.timeout {
+            if timeout.is_cancelled() {
                 return Relation::CellOutsideQuery;
             }
-
-            let end = offset + bytes;
-            crosses |= min_packed_value[offset..end] < self.weight.lower_point[offset..end]
-                || max_packed_value[offset..end] > self.weight.upper_point[offset..end];
-        }
-
-        if crosses {
-            Relation::CellCrossesQuery
-        } else {
-            Relation::CellInsideQuery
         }
+        self.weight.relate(min_packed_value, max_packed_value)
     }
 
     fn grow(&mut self, count: usize) {
@@ -383,6 +590,57 @@ impl<'a> IntersectVisitor for PointRangeIntersectVisitor<'a> {
     }
 }
 
+/// Only compares the cells with the range, to estimate the number of
+/// matching points.
+struct PointRangeEstimateVisitor<'a> {
+    weight: &'a PointRangeWeight,
+}
+
+impl<'a> IntersectVisitor for PointRangeEstimateVisitor<'a> {
+    fn visit(&mut self, _doc_id: DocId) -> Result<()> {
+        Ok(())
+    }
+
+    fn visit_by_packed_value(&mut self, _doc_id: DocId, _packed_value: &[u8]) -> Result<()> {
+        Ok(())
+    }
+
+    fn compare(&self, min_packed_value: &[u8], max_packed_value: &[u8]) -> Relation {
+        self.weight.relate(min_packed_value, max_packed_value)
+    }
+}
+
+/// Collects the docs of the range once the scorer is needed.
+struct PointRangeScorerSupplier<'a, C: Codec> {
+    weight: &'a PointRangeWeight,
+    reader: &'a SearchLeafReader<C>,
+    values: Arc<CodecPointsReader<C>>,
+    cost: usize,
+}
+
+impl<'a, C: Codec> ScorerSupplier for PointRangeScorerSupplier<'a, C> {
+    fn get(self: Box<Self>, _lead_cost: usize) -> Result<Box<dyn Scorer>> {
+        let iterator = match self
+            .weight
+            .build_matching_doc_set(self.reader, &self.values)?
+            .iterator()?
+        {
+            Some(iter) => PointDocIterEnum::DocSet(iter),
+            None => PointDocIterEnum::None(EmptyDocIterator::default()),
+        };
+        let cost = iterator.cost();
+        Ok(Box::new(ConstantScoreScorer::new(
+            self.weight.weight,
+            iterator,
+            cost,
+        )))
+    }
+
+    fn cost(&self) -> usize {
+        self.cost
+    }
+}
+
 enum PointDocIterEnum {
     DocSet(DocIdSetDocIterEnum),
     All(AllDocsIterator),
@@ -462,3 +720,221 @@ impl DocIterator for PointDocIterEnum {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::doc::{Field, FieldType};
+    use core::index::tests::{open_test_reader, TestReader};
+    use core::index::IndexReader;
+    use core::search::searcher::{DefaultIndexSearcher, SearchPlanBuilder};
+    use core::search::NO_MORE_DOCS;
+    use core::util::tests::seeded_rng;
+    use core::util::VariantValue;
+
+    use rand::Rng;
+    use std::sync::Arc;
+    use std::{i32, i64};
+
+    fn point(name: &str, packed: Vec<u8>, num_dims: u32) -> Field {
+        let mut field_type = FieldType::default();
+        field_type
+            .set_dimensions(num_dims, packed.len() as u32 / num_dims)
+            .unwrap();
+        Field::new(
+            name.to_string(),
+            field_type,
+            Some(VariantValue::Binary(packed)),
+            None,
+        )
+    }
+
+    fn build_index(docs: Vec<Vec<Field>>) -> Arc<TestReader> {
+        open_test_reader(vec![docs])
+    }
+
+    fn matching_docs(reader: &Arc<TestReader>, query: &PointRangeQuery) -> Vec<DocId> {
+        let searcher = DefaultIndexSearcher::new(Arc::clone(reader));
+        let weight = searcher.create_weight(query, false).unwrap();
+        let mut docs = vec![];
+        for leaf in reader.leaves() {
+            if let Some(mut scorer) = weight.create_scorer(&leaf).unwrap() {
+                loop {
+                    let doc = scorer.next().unwrap();
+                    if doc == NO_MORE_DOCS {
+                        break;
+                    }
+                    docs.push(leaf.doc_base + doc);
+                }
+            }
+        }
+        docs
+    }
+
+    // the docs of `values` within the range, in doc id order
+    fn scan<T: PartialOrd>(values: &[Option<T>], lower: &T, upper: &T) -> Vec<DocId> {
+        values
+            .iter()
+            .enumerate()
+            .filter(|(_, v)| v.as_ref().map_or(false, |v| v >= lower && v <= upper))
+            .map(|(doc, _)| doc as DocId)
+            .collect()
+    }
+
+    #[test]
+    fn test_long_range_matches_scan() {
+        let mut rng = seeded_rng();
+        // every tenth doc has no value, some values are at the extremes of the type
+        let values: Vec<Option<i64>> = (0..100_000)
+            .map(|i| match i % 10 {
+                0 => None,
+                1 if i % 1000 == 1 => Some(i64::MIN),
+                2 if i % 1000 == 2 => Some(i64::MAX),
+                _ => Some(rng.gen::<i64>() % 5000),
+            })
+            .collect();
+        let docs = values
+            .iter()
+            .map(|v| match *v {
+                Some(v) => vec![point("value", LongPoint::pack(&[v]), 1)],
+                None => vec![],
+            })
+            .collect();
+        let reader = build_index(docs);
+
+        let mut ranges = vec![
+            (i64::MIN, i64::MAX),
+            (i64::MIN, -2500),
+            (2500, i64::MAX),
+            (i64::MIN, i64::MIN),
+            (i64::MAX, i64::MAX),
+            (0, 0),
+            (-4999, 4999),
+            (6000, 7000),
+            (10, -10),
+        ];
+        for _ in 0..20 {
+            let a = rng.gen::<i64>() % 6000;
+            let b = rng.gen::<i64>() % 6000;
+            ranges.push((a.min(b), a.max(b)));
+            // a degenerate range on an indexed value
+            let value = values[(a.abs() as usize * 7) % values.len()].unwrap_or(0);
+            ranges.push((value, value));
+        }
+        for &(lower, upper) in &ranges {
+            let query =
+                PointRangeQuery::new_long_range("value".to_string(), &[lower], &[upper]).unwrap();
+            assert_eq!(
+                matching_docs(&reader, &query),
+                scan(&values, &lower, &upper),
+                "{}",
+                query
+            );
+        }
+        let all =
+            PointRangeQuery::new_long_range("value".to_string(), &[i64::MIN], &[i64::MAX]).unwrap();
+        assert_eq!(matching_docs(&reader, &all).len(), 90_000);
+    }
+
+    #[test]
+    fn test_two_dimensional_box() {
+        let mut rng = seeded_rng();
+        // a 100 x 100 grid of points, and random points around it
+        let mut values = vec![];
+        for x in 0..100 {
+            for y in 0..100 {
+                values.push(Some((x, y)));
+            }
+        }
+        for _ in 0..10_000 {
+            values.push(Some((rng.gen::<i32>() % 200, rng.gen::<i32>() % 200)));
+        }
+        let docs = values
+            .iter()
+            .map(|v| {
+                let (x, y) = v.unwrap();
+                vec![point("box", IntPoint::pack(&[x, y]), 2)]
+            })
+            .collect();
+        let reader = build_index(docs);
+
+        let in_box = |lower: [i32; 2], upper: [i32; 2]| -> Vec<DocId> {
+            values
+                .iter()
+                .enumerate()
+                .filter(|(_, v)| {
+                    let (x, y) = v.unwrap();
+                    x >= lower[0] && x <= upper[0] && y >= lower[1] && y <= upper[1]
+                })
+                .map(|(doc, _)| doc as DocId)
+                .collect()
+        };
+        let mut boxes = vec![
+            ([50, 0], [50, 99]),
+            ([0, 50], [99, 50]),
+            ([10, 20], [30, 40]),
+            ([i32::MIN, i32::MIN], [i32::MAX, i32::MAX]),
+            ([i32::MIN, 0], [i32::MAX, 0]),
+            ([150, 150], [i32::MAX, i32::MAX]),
+            ([60, 60], [40, 80]),
+        ];
+        for _ in 0..20 {
+            let (a, b, c, d) = (
+                rng.gen::<i32>() % 250,
+                rng.gen::<i32>() % 250,
+                rng.gen::<i32>() % 250,
+                rng.gen::<i32>() % 250,
+            );
+            boxes.push(([a.min(b), c.min(d)], [a.max(b), c.max(d)]));
+        }
+        for &(lower, upper) in &boxes {
+            let query = PointRangeQuery::new_int_range("box".to_string(), &lower, &upper).unwrap();
+            assert_eq!(
+                matching_docs(&reader, &query),
+                in_box(lower, upper),
+                "{}",
+                query
+            );
+        }
+        // a column of the grid
+        let query = PointRangeQuery::new_int_range("box".to_string(), &[50, 0], &[50, 99]).unwrap();
+        assert!(matching_docs(&reader, &query).len() >= 100);
+    }
+
+    #[test]
+    fn test_typed_ranges() {
+        let docs = (0..100)
+            .map(|i| {
+                vec![
+                    point("float", FloatPoint::pack(&[i as f32 - 49.5]), 1),
+                    point("double", DoublePoint::pack(&[i as f64 / 10.0]), 1),
+                    point("binary", vec![i as u8, 255 - i as u8], 2),
+                ]
+            })
+            .collect();
+        let reader = build_index(docs);
+
+        let query =
+            PointRangeQuery::new_float_range("float".to_string(), &[-0.5], &[10.0]).unwrap();
+        assert_eq!(matching_docs(&reader, &query), (49..60).collect::<Vec<_>>());
+        let query =
+            PointRangeQuery::new_double_range("double".to_string(), &[2.0], &[2.0]).unwrap();
+        assert_eq!(matching_docs(&reader, &query), vec![20]);
+        let query = PointRangeQuery::new_binary_range(
+            "binary".to_string(),
+            &[&[10], &[0]],
+            &[&[250], &[200]],
+        )
+        .unwrap();
+        assert_eq!(
+            matching_docs(&reader, &query),
+            (55..100).collect::<Vec<_>>()
+        );
+
+        assert!(PointRangeQuery::new_long_range("value".to_string(), &[], &[]).is_err());
+        assert!(PointRangeQuery::new_long_range("value".to_string(), &[1, 2], &[3]).is_err());
+        assert!(
+            PointRangeQuery::new_binary_range("binary".to_string(), &[&[1, 2]], &[&[3]]).is_err()
+        );
+    }
+}
@@ -0,0 +1,175 @@
+// Copyright 2019 Zhizhesihai (Beijing) Technology Limited.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use core::codec::Codec;
+use core::search::multi_term_query::{AcceptStatus, MultiTermWeight, RewriteMethod, TermFilter};
+use core::search::searcher::SearchPlanBuilder;
+use core::search::term_query::TermQuery;
+use core::search::{Query, Weight};
+
+use error::Result;
+
+use std::fmt;
+
+pub const PREFIX: &str = "prefix";
+
+/// Matches the docs having a term starting with the prefix in the field, with
+/// a constant score.
+#[derive(Clone, Debug)]
+pub struct PrefixQuery {
+    field: String,
+    prefix: Vec<u8>,
+    rewrite_method: RewriteMethod,
+}
+
+impl PrefixQuery {
+    pub fn new(field: String, prefix: Vec<u8>) -> PrefixQuery {
+        PrefixQuery {
+            field,
+            prefix,
+            rewrite_method: RewriteMethod::default(),
+        }
+    }
+
+    pub fn with_rewrite_method(mut self, rewrite_method: RewriteMethod) -> PrefixQuery {
+        self.rewrite_method = rewrite_method;
+        self
+    }
+
+    pub fn field(&self) -> &str {
+        &self.field
+    }
+
+    pub fn prefix(&self) -> &[u8] {
+        &self.prefix
+    }
+}
+
+impl TermFilter for PrefixQuery {
+    fn field(&self) -> &str {
+        &self.field
+    }
+
+    fn lower_bound(&self) -> Option<&[u8]> {
+        if self.prefix.is_empty() {
+            None
+        } else {
+            Some(self.prefix.as_slice())
+        }
+    }
+
+    fn accept(&self, term: &[u8]) -> AcceptStatus {
+        if term.starts_with(&self.prefix) {
+            AcceptStatus::Yes
+        } else {
+            AcceptStatus::End
+        }
+    }
+}
+
+impl<C: Codec> Query<C> for PrefixQuery {
+    fn create_weight(
+        &self,
+        searcher: &dyn SearchPlanBuilder<C>,
+        _needs_scores: bool,
+    ) -> Result<Box<dyn Weight<C>>> {
+        let weight = MultiTermWeight::new(self.clone(), self.rewrite_method, PREFIX);
+        Ok(Box::new(
+            weight.with_timeout(searcher.query_timeout().cloned()),
+        ))
+    }
+
+    fn extract_terms(&self) -> Vec<TermQuery> {
+        vec![]
+    }
+
+    fn query_type(&self) -> &'static str {
+        PREFIX
+    }
+
+    fn as_any(&self) -> &::std::any::Any {
+        self
+    }
+}
+
+impl fmt::Display for PrefixQuery {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "PrefixQuery(field: {}, prefix: {})",
+            &self.field,
+            String::from_utf8_lossy(&self.prefix)
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::codec::CodecEnum;
+    use core::doc::{Field, FieldType};
+    use core::index::tests::open_test_reader;
+    use core::index::IndexOptions;
+    use core::search::searcher::{DefaultIndexSearcher, IndexSearcher};
+    use core::util::VariantValue;
+
+    fn keyword(value: &str) -> Field {
+        let mut field_type = FieldType::default();
+        field_type.tokenized = false;
+        field_type.index_options = IndexOptions::Docs;
+        Field::new(
+            "name".to_string(),
+            field_type,
+            Some(VariantValue::VString(value.to_string())),
+            None,
+        )
+    }
+
+    fn count<S: IndexSearcher<CodecEnum>>(searcher: &S, prefix: &str) -> i32 {
+        let query = PrefixQuery::new("name".to_string(), prefix.as_bytes().to_vec());
+        searcher.count(&query).unwrap()
+    }
+
+    #[test]
+    fn test_prefix_query() {
+        // enough terms sharing "apple" for the terms dictionary to split them
+        // into floor blocks
+        let mut docs: Vec<_> = (0..300)
+            .map(|i| vec![keyword(&format!("apple{:03}", i))])
+            .collect();
+        for name in &["app", "apricot", "banana", "cherry"] {
+            docs.push(vec![keyword(name)]);
+        }
+        let searcher = DefaultIndexSearcher::new(open_test_reader(vec![docs]));
+        assert_eq!(count(&searcher, "apple"), 300);
+        assert_eq!(count(&searcher, "apple1"), 100);
+        assert_eq!(count(&searcher, "apple29"), 10);
+        assert_eq!(count(&searcher, "apple299"), 1);
+        assert_eq!(count(&searcher, "app"), 301);
+        assert_eq!(count(&searcher, "ap"), 302);
+        assert_eq!(count(&searcher, ""), 304);
+        assert_eq!(count(&searcher, "cherry"), 1);
+
+        // no term starts with these
+        assert_eq!(count(&searcher, "apple3"), 0);
+        assert_eq!(count(&searcher, "aa"), 0);
+        assert_eq!(count(&searcher, "blueberry"), 0);
+        assert_eq!(count(&searcher, "zucchini"), 0);
+        assert_eq!(
+            searcher
+                .count(&PrefixQuery::new("missing".to_string(), b"a".to_vec()))
+                .unwrap(),
+            0
+        );
+    }
+}
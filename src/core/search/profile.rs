@@ -0,0 +1,680 @@
+// Copyright 2019 Zhizhesihai (Beijing) Technology Limited.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Profiles a search, see `IndexSearcher::search_profiled`.
+//!
+//! The weights and scorers of every query of the search are wrapped to time
+//! their calls with the monotonic clock, into a tree of breakdowns mirroring
+//! the query. The times of a query include the ones of its sub queries. There
+//! is no separate rewrite phase: the queries that rewrite themselves, e.g. to
+//! the terms they match, do it while building their weights or scorers, so
+//! the rewrite is part of the `create_weight` or `build_scorer` times.
+
+use core::codec::Codec;
+use core::index::LeafReaderContext;
+use core::search::collector::{Collector, ParallelLeafCollector, ScoreMode, SearchCollector};
+use core::search::explanation::Explanation;
+use core::search::{
+    BatchScorer, DocIterator, FeatureResult, Query, Scorer, ScorerSupplier, Weight,
+};
+use core::util::{DocId, IndexedContext};
+
+use error::Result;
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+
+/// What is timed for each query of a profiled search.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProfileTiming {
+    /// Building the weight, with the ones of the sub queries.
+    CreateWeight,
+    /// Building the scorer of a segment.
+    BuildScorer,
+    /// `next` and `approximate_next`.
+    NextDoc,
+    /// `advance`, `approximate_advance` and `slow_advance`.
+    Advance,
+    Score,
+    /// Verifying the docs of a two phase scorer.
+    Match,
+}
+
+const TIMINGS: [ProfileTiming; 6] = [
+    ProfileTiming::CreateWeight,
+    ProfileTiming::BuildScorer,
+    ProfileTiming::NextDoc,
+    ProfileTiming::Advance,
+    ProfileTiming::Score,
+    ProfileTiming::Match,
+];
+
+impl ProfileTiming {
+    pub fn values() -> &'static [ProfileTiming] {
+        &TIMINGS
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ProfileTiming::CreateWeight => "create_weight",
+            ProfileTiming::BuildScorer => "build_scorer",
+            ProfileTiming::NextDoc => "next_doc",
+            ProfileTiming::Advance => "advance",
+            ProfileTiming::Score => "score",
+            ProfileTiming::Match => "match",
+        }
+    }
+}
+
+/// The times and counts of calls of a query, shared by its weight and all
+/// its scorers, possibly on several threads.
+#[derive(Default)]
+pub struct ProfileBreakdown {
+    nanos: [AtomicU64; 6],
+    counts: [AtomicU64; 6],
+}
+
+impl ProfileBreakdown {
+    /// Adds a call of `timing` started at `start` and ending now.
+    pub fn record(&self, timing: ProfileTiming, start: Instant) {
+        self.record_time(timing, start);
+        self.counts[timing as usize].fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Adds the time of a call already counted, e.g. the scorer built from
+    /// the `ScorerSupplier` of a segment.
+    pub fn record_time(&self, timing: ProfileTiming, start: Instant) {
+        let nanos = start.elapsed().as_nanos() as u64;
+        self.nanos[timing as usize].fetch_add(nanos, Ordering::Relaxed);
+    }
+
+    pub fn time_nanos(&self, timing: ProfileTiming) -> u64 {
+        self.nanos[timing as usize].load(Ordering::Relaxed)
+    }
+
+    pub fn count(&self, timing: ProfileTiming) -> u64 {
+        self.counts[timing as usize].load(Ordering::Relaxed)
+    }
+}
+
+struct ProfileNode {
+    query_type: &'static str,
+    description: String,
+    breakdown: Arc<ProfileBreakdown>,
+    children: Vec<usize>,
+}
+
+/// Builds the tree of the breakdowns of the queries while their weights are
+/// created, the sub queries being the children of the query building their
+/// weights.
+#[derive(Default)]
+pub struct ProfileTree {
+    nodes: Vec<ProfileNode>,
+    roots: Vec<usize>,
+    stack: Vec<usize>,
+}
+
+impl ProfileTree {
+    /// Starts building the weight of `query`, returns its breakdown.
+    pub fn enter<C: Codec>(&mut self, query: &dyn Query<C>) -> Arc<ProfileBreakdown> {
+        let breakdown = Arc::new(ProfileBreakdown::default());
+        let node = self.nodes.len();
+        self.nodes.push(ProfileNode {
+            query_type: query.query_type(),
+            description: format!("{}", query),
+            breakdown: Arc::clone(&breakdown),
+            children: vec![],
+        });
+        match self.stack.last() {
+            Some(&parent) => self.nodes[parent].children.push(node),
+            None => self.roots.push(node),
+        }
+        self.stack.push(node);
+        breakdown
+    }
+
+    /// Ends building the weight of the last query entered.
+    pub fn exit(&mut self) {
+        self.stack.pop();
+    }
+
+    /// The profile of the last top-level query, `None` if no weight was built.
+    pub fn profile(&self) -> Option<QueryProfile> {
+        self.roots.last().map(|&root| self.node_profile(root))
+    }
+
+    fn node_profile(&self, node: usize) -> QueryProfile {
+        let node = &self.nodes[node];
+        let mut time_nanos = 0;
+        let mut breakdown = BTreeMap::new();
+        for &timing in ProfileTiming::values() {
+            let nanos = node.breakdown.time_nanos(timing);
+            time_nanos += nanos;
+            breakdown.insert(timing.name().to_string(), nanos);
+            breakdown.insert(
+                format!("{}_count", timing.name()),
+                node.breakdown.count(timing),
+            );
+        }
+        QueryProfile {
+            query_type: node.query_type.to_string(),
+            description: node.description.clone(),
+            time_nanos,
+            breakdown,
+            children: node
+                .children
+                .iter()
+                .map(|&child| self.node_profile(child))
+                .collect(),
+        }
+    }
+}
+
+/// The times spent in a query of a profiled search, and in its sub queries.
+#[derive(Serialize)]
+pub struct QueryProfile {
+    query_type: String,
+    description: String,
+    time_nanos: u64,
+    breakdown: BTreeMap<String, u64>,
+    children: Vec<QueryProfile>,
+}
+
+impl QueryProfile {
+    pub fn query_type(&self) -> &str {
+        &self.query_type
+    }
+
+    pub fn description(&self) -> &str {
+        &self.description
+    }
+
+    /// The sum of the times of the breakdown.
+    pub fn time_nanos(&self) -> u64 {
+        self.time_nanos
+    }
+
+    /// The nanoseconds spent in each `ProfileTiming`, by name, and the calls
+    /// of each, by name suffixed with `_count`.
+    pub fn breakdown(&self) -> &BTreeMap<String, u64> {
+        &self.breakdown
+    }
+
+    pub fn timing(&self, timing: ProfileTiming) -> u64 {
+        self.breakdown[timing.name()]
+    }
+
+    pub fn count(&self, timing: ProfileTiming) -> u64 {
+        self.breakdown[&format!("{}_count", timing.name())]
+    }
+
+    pub fn children(&self) -> &[QueryProfile] {
+        &self.children
+    }
+}
+
+/// The time spent collecting the hits of a profiled search.
+#[derive(Serialize)]
+pub struct CollectorProfile {
+    time_nanos: u64,
+    count: u64,
+}
+
+impl CollectorProfile {
+    pub fn time_nanos(&self) -> u64 {
+        self.time_nanos
+    }
+
+    /// The docs collected.
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+}
+
+/// The profile of a search, see `IndexSearcher::search_profiled`.
+#[derive(Serialize)]
+pub struct ProfileResult {
+    query: QueryProfile,
+    collector: CollectorProfile,
+}
+
+impl ProfileResult {
+    pub fn new(query: QueryProfile, collector: CollectorProfile) -> ProfileResult {
+        ProfileResult { query, collector }
+    }
+
+    pub fn query(&self) -> &QueryProfile {
+        &self.query
+    }
+
+    pub fn collector(&self) -> &CollectorProfile {
+        &self.collector
+    }
+
+    /// Renders the profile as JSON, the query tree under `query` and the
+    /// collection under `collector`.
+    pub fn to_json(&self) -> Result<String> {
+        Ok(::serde_json::to_string(self)?)
+    }
+}
+
+/// Times the scorers built by a weight, and how long building them takes.
+pub struct ProfileWeight<C: Codec> {
+    weight: Box<dyn Weight<C>>,
+    breakdown: Arc<ProfileBreakdown>,
+}
+
+impl<C: Codec> ProfileWeight<C> {
+    pub fn new(weight: Box<dyn Weight<C>>, breakdown: Arc<ProfileBreakdown>) -> ProfileWeight<C> {
+        ProfileWeight { weight, breakdown }
+    }
+}
+
+impl<C: Codec> Weight<C> for ProfileWeight<C> {
+    fn create_scorer(
+        &self,
+        leaf_reader: &LeafReaderContext<'_, C>,
+    ) -> Result<Option<Box<dyn Scorer>>> {
+        let start = Instant::now();
+        let scorer = self.weight.create_scorer(leaf_reader);
+        self.breakdown.record(ProfileTiming::BuildScorer, start);
+        Ok(scorer?.map(|scorer| {
+            Box::new(ProfileScorer::new(scorer, Arc::clone(&self.breakdown))) as Box<dyn Scorer>
+        }))
+    }
+
+    fn scorer_supplier<'a>(
+        &'a self,
+        leaf_reader: &'a LeafReaderContext<'_, C>,
+    ) -> Result<Option<Box<dyn ScorerSupplier + 'a>>> {
+        let start = Instant::now();
+        let supplier = self.weight.scorer_supplier(leaf_reader);
+        self.breakdown.record(ProfileTiming::BuildScorer, start);
+        Ok(supplier?.map(|supplier| {
+            Box::new(ProfileScorerSupplier {
+                supplier,
+                breakdown: Arc::clone(&self.breakdown),
+            }) as Box<dyn ScorerSupplier + 'a>
+        }))
+    }
+
+    fn count(&self, leaf_reader: &LeafReaderContext<'_, C>) -> Result<Option<usize>> {
+        self.weight.count(leaf_reader)
+    }
+
+    fn hash_code(&self) -> u32 {
+        self.weight.hash_code()
+    }
+
+    fn query_type(&self) -> &'static str {
+        self.weight.query_type()
+    }
+
+    fn actual_query_type(&self) -> &'static str {
+        self.weight.actual_query_type()
+    }
+
+    fn normalize(&mut self, norm: f32, boost: f32) {
+        self.weight.normalize(norm, boost)
+    }
+
+    fn value_for_normalization(&self) -> f32 {
+        self.weight.value_for_normalization()
+    }
+
+    fn needs_scores(&self) -> bool {
+        self.weight.needs_scores()
+    }
+
+    fn create_batch_scorer(&self) -> Option<Box<dyn BatchScorer>> {
+        self.weight.create_batch_scorer()
+    }
+
+    fn explain(&self, reader: &LeafReaderContext<'_, C>, doc: DocId) -> Result<Explanation> {
+        self.weight.explain(reader, doc)
+    }
+}
+
+impl<C: Codec> fmt::Display for ProfileWeight<C> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", &self.weight)
+    }
+}
+
+struct ProfileScorerSupplier<'a> {
+    supplier: Box<dyn ScorerSupplier + 'a>,
+    breakdown: Arc<ProfileBreakdown>,
+}
+
+impl<'a> ScorerSupplier for ProfileScorerSupplier<'a> {
+    fn get(self: Box<Self>, lead_cost: usize) -> Result<Box<dyn Scorer>> {
+        let start = Instant::now();
+        let scorer = self.supplier.get(lead_cost);
+        // counted when the supplier was created
+        self.breakdown
+            .record_time(ProfileTiming::BuildScorer, start);
+        Ok(Box::new(ProfileScorer::new(scorer?, self.breakdown)))
+    }
+
+    fn cost(&self) -> usize {
+        self.supplier.cost()
+    }
+}
+
+/// Times the calls of a scorer, keeping its iteration, two phase or not, and
+/// its cost.
+pub struct ProfileScorer {
+    scorer: Box<dyn Scorer>,
+    breakdown: Arc<ProfileBreakdown>,
+}
+
+impl ProfileScorer {
+    pub fn new(scorer: Box<dyn Scorer>, breakdown: Arc<ProfileBreakdown>) -> ProfileScorer {
+        ProfileScorer { scorer, breakdown }
+    }
+}
+
+impl Scorer for ProfileScorer {
+    fn score(&mut self) -> Result<f32> {
+        let start = Instant::now();
+        let score = self.scorer.score();
+        self.breakdown.record(ProfileTiming::Score, start);
+        score
+    }
+
+    fn support_two_phase(&self) -> bool {
+        self.scorer.support_two_phase()
+    }
+
+    fn score_context(&mut self) -> Result<IndexedContext> {
+        self.scorer.score_context()
+    }
+
+    fn score_feature(&mut self) -> Result<Vec<FeatureResult>> {
+        self.scorer.score_feature()
+    }
+
+    fn set_min_competitive_score(&mut self, min_score: f32) -> Result<()> {
+        self.scorer.set_min_competitive_score(min_score)
+    }
+
+    fn max_score(&self) -> f32 {
+        self.scorer.max_score()
+    }
+}
+
+impl DocIterator for ProfileScorer {
+    fn doc_id(&self) -> DocId {
+        self.scorer.doc_id()
+    }
+
+    fn next(&mut self) -> Result<DocId> {
+        let start = Instant::now();
+        let doc = self.scorer.next();
+        self.breakdown.record(ProfileTiming::NextDoc, start);
+        doc
+    }
+
+    fn advance(&mut self, target: DocId) -> Result<DocId> {
+        let start = Instant::now();
+        let doc = self.scorer.advance(target);
+        self.breakdown.record(ProfileTiming::Advance, start);
+        doc
+    }
+
+    fn slow_advance(&mut self, target: DocId) -> Result<DocId> {
+        let start = Instant::now();
+        let doc = self.scorer.slow_advance(target);
+        self.breakdown.record(ProfileTiming::Advance, start);
+        doc
+    }
+
+    fn cost(&self) -> usize {
+        self.scorer.cost()
+    }
+
+    fn matches(&mut self) -> Result<bool> {
+        let start = Instant::now();
+        let matches = self.scorer.matches();
+        self.breakdown.record(ProfileTiming::Match, start);
+        matches
+    }
+
+    fn match_cost(&self) -> f32 {
+        self.scorer.match_cost()
+    }
+
+    fn approximate_next(&mut self) -> Result<DocId> {
+        let start = Instant::now();
+        let doc = self.scorer.approximate_next();
+        self.breakdown.record(ProfileTiming::NextDoc, start);
+        doc
+    }
+
+    fn approximate_advance(&mut self, target: DocId) -> Result<DocId> {
+        let start = Instant::now();
+        let doc = self.scorer.approximate_advance(target);
+        self.breakdown.record(ProfileTiming::Advance, start);
+        doc
+    }
+}
+
+/// Times the collection of the hits by another collector.
+pub struct ProfileCollector<T> {
+    collector: T,
+    nanos: Arc<AtomicU64>,
+    count: Arc<AtomicU64>,
+}
+
+impl<T> ProfileCollector<T> {
+    pub fn new(collector: T) -> ProfileCollector<T> {
+        ProfileCollector {
+            collector,
+            nanos: Arc::new(AtomicU64::new(0)),
+            count: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// The collection so far, of all the leaves.
+    pub fn profile(&self) -> CollectorProfile {
+        CollectorProfile {
+            time_nanos: self.nanos.load(Ordering::Relaxed),
+            count: self.count.load(Ordering::Relaxed),
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.collector
+    }
+}
+
+impl<T: SearchCollector> SearchCollector for ProfileCollector<T> {
+    type LC = ProfileCollector<T::LC>;
+
+    fn set_next_reader<C: Codec>(&mut self, reader: &LeafReaderContext<'_, C>) -> Result<()> {
+        self.collector.set_next_reader(reader)
+    }
+
+    fn support_parallel(&self) -> bool {
+        self.collector.support_parallel()
+    }
+
+    fn leaf_collector<C: Codec>(
+        &mut self,
+        reader: &LeafReaderContext<'_, C>,
+    ) -> Result<ProfileCollector<T::LC>> {
+        Ok(ProfileCollector {
+            collector: self.collector.leaf_collector(reader)?,
+            nanos: Arc::clone(&self.nanos),
+            count: Arc::clone(&self.count),
+        })
+    }
+
+    fn finish_parallel(&mut self) -> Result<()> {
+        self.collector.finish_parallel()
+    }
+
+    fn min_competitive_score(&self) -> Option<f32> {
+        self.collector.min_competitive_score()
+    }
+}
+
+impl<T: ParallelLeafCollector> ParallelLeafCollector for ProfileCollector<T> {
+    fn finish_leaf(&mut self) -> Result<()> {
+        self.collector.finish_leaf()
+    }
+}
+
+impl<T: Collector> Collector for ProfileCollector<T> {
+    fn score_mode(&self) -> ScoreMode {
+        self.collector.score_mode()
+    }
+
+    fn collect<S: Scorer + ?Sized>(&mut self, doc: DocId, scorer: &mut S) -> Result<()> {
+        let start = Instant::now();
+        let result = self.collector.collect(doc, scorer);
+        let nanos = start.elapsed().as_nanos() as u64;
+        self.nanos.fetch_add(nanos, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::codec::CodecEnum;
+    use core::doc::{Field, Store, StringField};
+    use core::index::tests::{open_test_reader, TestReader};
+    use core::index::{IndexReader, Term};
+    use core::search::boolean_query::{BooleanQuery, BOOLEAN};
+    use core::search::collector::TopDocsCollector;
+    use core::search::searcher::{DefaultIndexSearcher, IndexSearcher};
+    use core::search::term_query::{TermQuery, TERM};
+
+    fn open_reader() -> Arc<TestReader> {
+        let docs: Vec<Vec<Field>> = [
+            vec!["a", "b"],
+            vec!["a", "c"],
+            vec!["a", "b", "d"],
+            vec!["a"],
+            vec!["b", "c"],
+            vec!["a", "c"],
+        ]
+        .iter()
+        .map(|tags| {
+            tags.iter()
+                .map(|tag| StringField::new("tag", tag, Store::No))
+                .collect()
+        })
+        .collect();
+        open_test_reader(vec![docs])
+    }
+
+    fn term(tag: &str) -> Box<dyn Query<CodecEnum>> {
+        let term = Term::new("tag".to_string(), tag.as_bytes().to_vec());
+        Box::new(TermQuery::new(term, 1.0, None))
+    }
+
+    // the times of a query include the ones of its sub queries
+    fn check_times(profile: &QueryProfile) {
+        let sum: u64 = ProfileTiming::values()
+            .iter()
+            .map(|&timing| profile.timing(timing))
+            .sum();
+        assert_eq!(profile.time_nanos(), sum);
+        assert_eq!(profile.count(ProfileTiming::CreateWeight), 1);
+
+        let children_time: u64 = profile.children().iter().map(|c| c.time_nanos()).sum();
+        assert!(children_time <= profile.time_nanos());
+        let children_create_weight: u64 = profile
+            .children()
+            .iter()
+            .map(|c| c.timing(ProfileTiming::CreateWeight))
+            .sum();
+        assert!(children_create_weight <= profile.timing(ProfileTiming::CreateWeight));
+        for child in profile.children() {
+            check_times(child);
+        }
+    }
+
+    #[test]
+    fn test_search_profiled() {
+        let reader = open_reader();
+        let leaves = reader.leaves();
+        let searcher = DefaultIndexSearcher::new(Arc::clone(&reader));
+
+        // a and (b or c) and not d
+        let b_or_c = BooleanQuery::build(vec![], vec![term("b"), term("c")], vec![]).unwrap();
+        let query = BooleanQuery::build_with_must_nots(
+            vec![term("a"), b_or_c],
+            vec![],
+            vec![],
+            vec![term("d")],
+        )
+        .unwrap();
+
+        let mut collector = TopDocsCollector::new(10);
+        let start = Instant::now();
+        let profile = searcher
+            .search_profiled(query.as_ref(), &mut collector)
+            .unwrap();
+        let elapsed = start.elapsed().as_nanos() as u64;
+        let top_docs = collector.top_docs();
+        assert_eq!(top_docs.total_hits(), 3);
+        assert_eq!(profile.collector().count(), 3);
+
+        // the tree mirrors the query: must, should then must not clauses
+        let root = profile.query();
+        assert_eq!(root.query_type(), BOOLEAN);
+        assert_eq!(root.description(), format!("{}", query));
+        let children: Vec<&str> = root.children().iter().map(|c| c.query_type()).collect();
+        assert_eq!(children, vec![TERM, BOOLEAN, TERM]);
+        let nested: Vec<&str> = root.children()[1]
+            .children()
+            .iter()
+            .map(|c| c.query_type())
+            .collect();
+        assert_eq!(nested, vec![TERM, TERM]);
+        assert!(root.children()[0].children().is_empty());
+        assert!(root.children()[2].children().is_empty());
+
+        check_times(root);
+        // the scores are computed while collecting, both are timed
+        assert!(root.time_nanos() <= elapsed);
+        assert!(profile.collector().time_nanos() <= elapsed);
+        assert_eq!(root.count(ProfileTiming::BuildScorer), leaves.len() as u64);
+        // every hit, then the end of the docs
+        let iterations = root.count(ProfileTiming::NextDoc) + root.count(ProfileTiming::Advance);
+        assert!(iterations >= 4);
+        assert!(root.count(ProfileTiming::Score) >= 3);
+
+        let json: ::serde_json::Value =
+            ::serde_json::from_str(&profile.to_json().unwrap()).unwrap();
+        assert_eq!(json["query"]["query_type"], BOOLEAN);
+        assert_eq!(json["query"]["children"].as_array().unwrap().len(), 3);
+        assert_eq!(
+            json["query"]["children"][1]["children"]
+                .as_array()
+                .unwrap()
+                .len(),
+            2
+        );
+        assert!(json["query"]["breakdown"]["next_doc"].is_u64());
+        assert!(json["query"]["breakdown"]["score_count"].as_u64().unwrap() >= 3);
+        assert_eq!(json["collector"]["count"], 3);
+    }
+}
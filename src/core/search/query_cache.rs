@@ -12,22 +12,25 @@
 // limitations under the License.
 
 use std::collections::hash_map::DefaultHasher;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fmt;
 use std::hash::{Hash, Hasher};
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::{Arc, RwLock};
+use std::mem;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::usize;
 
 use core::index::LeafReaderContext;
 use core::search::bulk_scorer::BulkScorer;
 use core::search::cache_policy::QueryCachingPolicy;
-use core::search::collector::Collector;
+use core::search::collector::{Collector, ScoreMode};
 use core::search::explanation::Explanation;
-use core::search::lru_cache::LRUCache;
 use core::search::match_all::ConstantScoreScorer;
 use core::search::{two_phase_next, DocIdSet, DocIterator, Scorer, Weight, NO_MORE_DOCS};
-use core::util::bit_set::{BitSet, FixedBitSet, ImmutableBitSet};
+use core::search::{CreatedScorerSupplier, ScorerSupplier};
+use core::util::bit_set::{bits2words, BitSet, FixedBitSet, ImmutableBitSet};
 use core::util::bit_util::UnsignedShift;
+use core::util::cache::LruCache;
 use core::util::doc_id_set::{BitDocIdSet, BitSetIterator, DocIdSetDocIterEnum, DocIdSetEnum};
 use core::util::external::deferred::Deferred;
 use core::util::{Bits, DocId};
@@ -115,168 +118,221 @@ impl<C: Codec> QueryCache<C> for NoCacheQueryCache {
 // @lucene.experimental
 //
 
-struct LeafCache {
-    _key: String,
-    leaf_cache: HashMap<String, CacheDocIdSetEnum>,
+struct CachedDocIdSet {
+    set: CacheDocIdSetEnum,
+    ram_bytes_used: usize,
 }
 
-impl LeafCache {
-    pub fn new(_key: String) -> LeafCache {
-        LeafCache {
-            _key,
-            leaf_cache: HashMap::new(),
-        }
-    }
+// the doc id sets cached for a query, by the core cache key of their segment
+#[derive(Default)]
+struct CachedSets {
+    sets: HashMap<String, CachedDocIdSet>,
+    ram_bytes_used: usize,
+    // set once the query is evicted, so that no set is added to it anymore
+    evicted: bool,
+}
 
-    pub fn get(&self, query_key: &str) -> Result<Option<CachedDocIdSetIterEnum>> {
-        match self.leaf_cache.get(query_key) {
-            Some(set) => set.iterator(),
-            None => Ok(None),
-        }
+impl CachedSets {
+    /// Returns the bytes used by the removed set.
+    fn remove(&mut self, core_key: &str) -> Option<usize> {
+        let removed = self.sets.remove(core_key)?;
+        self.ram_bytes_used -= removed.ram_bytes_used;
+        Some(removed.ram_bytes_used)
     }
+}
 
-    pub fn put_if_absent(&mut self, query_key: &str, set: CacheDocIdSetEnum) {
-        if !self.leaf_cache.contains_key(query_key) {
-            self.leaf_cache.insert(query_key.to_string(), set);
-        }
-    }
+type CachedQuery = Arc<Mutex<CachedSets>>;
+
+#[derive(Default)]
+struct CacheStats {
+    // the bytes of the cached sets, without the query keys
+    ram_bytes_used: AtomicUsize,
+    hit_count: AtomicUsize,
+    miss_count: AtomicUsize,
+    cache_count: AtomicUsize,
+    cache_size: AtomicUsize,
+    eviction_count: AtomicUsize,
+}
 
-    pub fn remove(&mut self, query_key: &str) {
-        self.leaf_cache.remove(query_key);
+impl CacheStats {
+    fn on_eviction(&self, num_sets: usize, ram_bytes_used: usize) {
+        self.ram_bytes_used
+            .fetch_sub(ram_bytes_used, Ordering::Relaxed);
+        self.cache_size.fetch_sub(num_sets, Ordering::Relaxed);
+        self.eviction_count.fetch_add(num_sets, Ordering::Relaxed);
     }
 }
 
 struct CacheData {
-    // maps queries that are contained in the cache to a singleton so that this
-    // cache does not store several copies of the same query
-    pub unique_queries: LRUCache<String, String>,
-    // The contract between this set and the per-leaf caches is that per-leaf caches
-    // are only allowed to store sub-sets of the queries that are contained in
-    // mostRecentlyUsedQueries. This is why write operations are performed under a lock
-    // pub most_recently_used_queries: HashSet<Query>,
-    pub cache: HashMap<String, LeafCache>,
-
-    max_size: usize,
-    min_size: i32,
-    min_size_ratio: f32,
+    // the cached queries weighing the bytes of their key and of their sets,
+    // in a single shard to evict the least recently used query of the cache
+    queries: LruCache<String, CachedQuery>,
+    // the cores that drop their sets once closed
+    cores: Mutex<HashSet<String>>,
+    stats: Arc<CacheStats>,
 }
 
 impl CacheData {
-    fn test<C: Codec>(&self, leaf_reader: &LeafReaderContext<'_, C>) -> Result<bool> {
-        if leaf_reader.reader.max_doc() < self.min_size {
-            Ok(false)
-        } else {
-            Ok(
-                leaf_reader.reader.max_doc() as f32 / leaf_reader.parent.max_doc() as f32
-                    >= self.min_size_ratio,
-            )
+    fn new(max_size: usize, max_ram_bytes_used: usize) -> CacheData {
+        let stats = Arc::new(CacheStats::default());
+        let evicted_stats = Arc::clone(&stats);
+        let queries = LruCache::with_limits(
+            max_size,
+            max_ram_bytes_used,
+            |key: &String, query: &CachedQuery| key.len() + query.lock().unwrap().ram_bytes_used,
+        )
+        .with_shards(1)
+        .with_eviction_listener(move |_, query: CachedQuery| {
+            let mut sets = query.lock().unwrap();
+            sets.evicted = true;
+            evicted_stats.on_eviction(sets.sets.len(), sets.ram_bytes_used);
+            sets.sets.clear();
+            sets.ram_bytes_used = 0;
+        });
+        CacheData {
+            queries,
+            cores: Mutex::new(HashSet::new()),
+            stats,
         }
     }
 
-    /// Whether evictions are required.
-    fn requires_eviction(&self) -> Result<bool> {
-        Ok(self.unique_queries.len() >= self.max_size)
-    }
-
-    fn get<C: Codec>(
-        &mut self,
+    /// Returns `None` if the query isn't cached for the core, and the iterator
+    /// of the cached set otherwise, `None` if it's empty.
+    fn get(
+        &self,
         query_key: &str,
-        leaf_reader: &LeafReaderContext<'_, C>,
-    ) -> Result<Option<CachedDocIdSetIterEnum>> {
-        if let Some(leaf_cache) = self.cache.get(leaf_reader.reader.core_cache_key()) {
-            if let Some(singleton) = self.unique_queries.get(&query_key.to_string()) {
-                // this get call moves the query to the most-recently-used position
-                return leaf_cache.get(singleton);
+        core_key: &str,
+    ) -> Result<Option<Option<CachedDocIdSetIterEnum>>> {
+        let mut cached = None;
+        // this get call moves the query to the most-recently-used position
+        if let Some(query) = self.queries.get(query_key) {
+            if let Some(cached_set) = query.lock()?.sets.get(core_key) {
+                cached = Some(cached_set.set.iterator()?);
             }
         }
-        Ok(None)
-    }
-
-    // return true if new LeafCache is added to process core reader drop listener
-    pub fn put_if_absent<C: Codec>(
-        &mut self,
-        query_key: &str,
-        leaf_reader: &LeafReaderContext<'_, C>,
-        set: CacheDocIdSetEnum,
-    ) -> Result<bool> {
-        self.evict_if_necessary()?;
-
-        let query_key = if self.unique_queries.contains_key(&query_key.to_string()) {
-            self.unique_queries
-                .get(&query_key.to_string())
-                .unwrap()
-                .to_string()
+        if cached.is_some() {
+            self.stats.hit_count.fetch_add(1, Ordering::Relaxed);
         } else {
-            self.unique_queries
-                .insert(query_key.to_string(), query_key.to_string());
-            query_key.to_string()
-        };
-
-        let key = leaf_reader.reader.core_cache_key();
-        let new_entry = if self.cache.contains_key(key) {
-            false
-        } else {
-            let leaf_cache = LeafCache::new(key.into());
-            self.cache.insert(key.into(), leaf_cache);
-            true
+            self.stats.miss_count.fetch_add(1, Ordering::Relaxed);
+        }
+        Ok(cached)
+    }
+
+    // returns true if the set is the first one of its core, which must then
+    // drop its sets once closed
+    fn put_if_absent(&self, query_key: &str, core_key: &str, set: CachedDocIdSet) -> Result<bool> {
+        let query_key = query_key.to_string();
+        let query = self
+            .queries
+            .compute_if_absent(query_key.clone(), |_| Ok(CachedQuery::default()))?;
+        let added = {
+            let mut sets = query.lock()?;
+            if sets.evicted || sets.sets.contains_key(core_key) {
+                false
+            } else {
+                sets.ram_bytes_used += set.ram_bytes_used;
+                self.stats
+                    .ram_bytes_used
+                    .fetch_add(set.ram_bytes_used, Ordering::Relaxed);
+                self.stats.cache_size.fetch_add(1, Ordering::Relaxed);
+                self.stats.cache_count.fetch_add(1, Ordering::Relaxed);
+                sets.sets.insert(core_key.to_string(), set);
+                true
+            }
         };
-
-        {
-            let leaf_cache = self.cache.get_mut(key).unwrap();
-            leaf_cache.put_if_absent(&query_key, set);
+        if !added {
+            return Ok(false);
         }
-
-        Ok(new_entry)
+        // evicts the least recently used queries if the cache got too heavy
+        self.queries.reweigh(&query_key);
+        Ok(self.cores.lock()?.insert(core_key.to_string()))
     }
 
-    fn evict_if_necessary(&mut self) -> Result<()> {
-        if self.requires_eviction()? {
-            loop {
-                if !self.requires_eviction()? {
-                    break;
-                }
-
-                if let Some(key) = self.unique_queries.remove_last() {
-                    self.on_eviction(&key);
-                } else {
-                    bail!(
-                        "Removal from the cache failed! This is probably due to a query which has \
-                         been modified after having been put into the cache or a badly \
-                         implemented clone()."
-                    );
+    // drops the sets cached for a core that was closed
+    fn clear_core_cache(&self, core_key: &str) {
+        if !self.cores.lock().unwrap().remove(core_key) {
+            return;
+        }
+        for query_key in self.queries.keys() {
+            if let Some(query) = self.queries.peek(&query_key) {
+                let removed = query.lock().unwrap().remove(core_key);
+                if let Some(ram_bytes_used) = removed {
+                    self.stats.on_eviction(1, ram_bytes_used);
+                    self.queries.reweigh(&query_key);
                 }
             }
         }
-
-        Ok(())
-    }
-
-    fn on_eviction(&mut self, query_key: &str) {
-        for leaf_cache in self.cache.values_mut() {
-            leaf_cache.remove(query_key);
-        }
     }
 }
 
+/// Caches the docs matched by the non-scoring weights per segment, see
+/// `CachingWrapperWeight`.
+///
+/// The least recently used queries are evicted to keep at most `max_size`
+/// queries, and the doc id sets under `max_ram_bytes_used` bytes. The sets of
+/// a segment are dropped with its core.
 pub struct LRUQueryCache {
-    cache_data: Arc<RwLock<CacheData>>,
+    cache_data: Arc<CacheData>,
+    min_size: i32,
+    min_size_ratio: f32,
 }
 
 impl LRUQueryCache {
     pub fn new(max_size: usize) -> LRUQueryCache {
-        // let max_size = 10;
-        let cache_data = CacheData {
-            unique_queries: LRUCache::with_capacity(max_size),
-            cache: HashMap::new(),
-            max_size,
-            min_size: 10000,
-            min_size_ratio: 0.03f32,
-        };
+        Self::with_ram_limit(max_size, usize::MAX)
+    }
 
+    pub fn with_ram_limit(max_size: usize, max_ram_bytes_used: usize) -> LRUQueryCache {
         LRUQueryCache {
-            cache_data: Arc::new(RwLock::new(cache_data)),
+            cache_data: Arc::new(CacheData::new(max_size, max_ram_bytes_used)),
+            min_size: 10000,
+            min_size_ratio: 0.03f32,
         }
     }
+
+    /// Only caches the segments with at least `min_size` docs and
+    /// `min_size_ratio` of the docs of the index, 10000 and 3% by default.
+    pub fn with_min_leaf_size(mut self, min_size: i32, min_size_ratio: f32) -> LRUQueryCache {
+        self.min_size = min_size;
+        self.min_size_ratio = min_size_ratio;
+        self
+    }
+
+    /// The number of lookups that found a cached doc id set.
+    pub fn hit_count(&self) -> Result<usize> {
+        Ok(self.cache_data.stats.hit_count.load(Ordering::Relaxed))
+    }
+
+    /// The number of lookups of a cacheable segment that found nothing.
+    pub fn miss_count(&self) -> Result<usize> {
+        Ok(self.cache_data.stats.miss_count.load(Ordering::Relaxed))
+    }
+
+    /// The number of doc id sets in the cache.
+    pub fn cache_size(&self) -> Result<usize> {
+        Ok(self.cache_data.stats.cache_size.load(Ordering::Relaxed))
+    }
+
+    /// The number of doc id sets that were ever cached.
+    pub fn cache_count(&self) -> Result<usize> {
+        Ok(self.cache_data.stats.cache_count.load(Ordering::Relaxed))
+    }
+
+    /// The number of doc id sets that were evicted, or dropped with their core.
+    pub fn eviction_count(&self) -> Result<usize> {
+        Ok(self.cache_data.stats.eviction_count.load(Ordering::Relaxed))
+    }
+
+    /// The bytes of the cached sets and of the keys of the cached queries.
+    pub fn ram_bytes_used(&self) -> Result<usize> {
+        let keys: usize = self.cache_data.queries.keys().iter().map(String::len).sum();
+        Ok(self.cache_data.stats.ram_bytes_used.load(Ordering::Relaxed) + keys)
+    }
+
+    /// The keys of the cached queries, the least recently used first.
+    pub fn cached_queries(&self) -> Result<Vec<String>> {
+        Ok(self.cache_data.queries.keys())
+    }
 }
 
 impl<C: Codec> QueryCache<C> for LRUQueryCache {
@@ -290,6 +346,8 @@ impl<C: Codec> QueryCache<C> for LRUQueryCache {
         } else {
             Box::new(CachingWrapperWeight::new(
                 Arc::clone(&self.cache_data),
+                self.min_size,
+                self.min_size_ratio,
                 weight,
                 policy,
             ))
@@ -298,7 +356,9 @@ impl<C: Codec> QueryCache<C> for LRUQueryCache {
 }
 
 pub struct CachingWrapperWeight<C: Codec> {
-    cache_data: Arc<RwLock<CacheData>>,
+    cache_data: Arc<CacheData>,
+    min_size: i32,
+    min_size_ratio: f32,
     weight: Box<dyn Weight<C>>,
     policy: Arc<QueryCachingPolicy<C>>,
     used: AtomicBool,
@@ -308,7 +368,9 @@ pub struct CachingWrapperWeight<C: Codec> {
 
 impl<C: Codec> CachingWrapperWeight<C> {
     fn new(
-        cache_data: Arc<RwLock<CacheData>>,
+        cache_data: Arc<CacheData>,
+        min_size: i32,
+        min_size_ratio: f32,
         weight: Box<dyn Weight<C>>,
         policy: Arc<QueryCachingPolicy<C>>,
     ) -> CachingWrapperWeight<C> {
@@ -317,6 +379,8 @@ impl<C: Codec> CachingWrapperWeight<C> {
         query_key.hash(&mut hasher);
         CachingWrapperWeight {
             cache_data,
+            min_size,
+            min_size_ratio,
             weight,
             policy,
             used: AtomicBool::new(false),
@@ -392,7 +456,14 @@ impl<C: Codec> CachingWrapperWeight<C> {
     }
 
     fn should_cache(&self, leaf_reader: &LeafReaderContext<'_, C>) -> Result<bool> {
-        self.cache_data.read()?.test(leaf_reader)
+        if leaf_reader.reader.max_doc() < self.min_size {
+            Ok(false)
+        } else {
+            Ok(
+                leaf_reader.reader.max_doc() as f32 / leaf_reader.parent.max_doc() as f32
+                    >= self.min_size_ratio,
+            )
+        }
     }
 
     fn cache(
@@ -408,18 +479,19 @@ impl<C: Codec> CachingWrapperWeight<C> {
                 let doc_id_set = self.cache_impl(&mut bulk_scorer, max_doc)?;
 
                 let iter = doc_id_set.iterator()?;
-                if self
-                    .cache_data
-                    .write()?
-                    .put_if_absent(query_key, leaf_reader, doc_id_set)?
-                {
-                    let key = leaf_reader.reader.core_cache_key().to_owned();
+                let cached = CachedDocIdSet {
+                    ram_bytes_used: doc_id_set.ram_bytes_used(max_doc),
+                    set: doc_id_set,
+                };
+                let core_key = leaf_reader.reader.core_cache_key();
+                if self.cache_data.put_if_absent(query_key, core_key, cached)? {
+                    let key = core_key.to_owned();
                     let cache_data = Arc::clone(&self.cache_data);
                     leaf_reader
                         .reader
                         .add_core_drop_listener(Deferred::new(move || {
                             let core_key = key;
-                            cache_data.write().unwrap().cache.remove(&core_key);
+                            cache_data.clear_core_cache(&core_key);
                         }))
                 }
 
@@ -447,19 +519,12 @@ impl<C: Codec> Weight<C> for CachingWrapperWeight<C> {
             return self.weight.create_scorer(leaf_reader);
         }
 
-        {
-            // If the lock is already busy, prefer using the uncached version than waiting
-            match self.cache_data.try_write() {
-                Ok(mut cache_data) => {
-                    if let Some(disi) = cache_data.get(&self.query_key, leaf_reader)? {
-                        let cost = disi.cost();
-                        return Ok(Some(Box::new(ConstantScoreScorer::new(0.0f32, disi, cost))));
-                    }
-                }
-                _ => {
-                    return self.weight.create_scorer(leaf_reader);
-                }
-            }
+        let core_key = leaf_reader.reader.core_cache_key();
+        if let Some(cached) = self.cache_data.get(&self.query_key, core_key)? {
+            return Ok(cached.map(|disi| {
+                let cost = disi.cost();
+                Box::new(ConstantScoreScorer::new(0.0f32, disi, cost)) as Box<dyn Scorer>
+            }));
         }
 
         if self.policy.should_cache(self)? {
@@ -475,6 +540,26 @@ impl<C: Codec> Weight<C> for CachingWrapperWeight<C> {
         }
     }
 
+    fn scorer_supplier<'a>(
+        &'a self,
+        leaf_reader: &'a LeafReaderContext<'_, C>,
+    ) -> Result<Option<Box<dyn ScorerSupplier + 'a>>> {
+        // the segments that are not cached keep the choice of the wrapped weight
+        if !self.should_cache(leaf_reader)? {
+            if !self.used.compare_and_swap(false, true, Ordering::AcqRel) {
+                self.policy.on_use(self)
+            }
+            return self.weight.scorer_supplier(leaf_reader);
+        }
+        Ok(self.create_scorer(leaf_reader)?.map(|scorer| {
+            Box::new(CreatedScorerSupplier::new(scorer)) as Box<dyn ScorerSupplier + 'a>
+        }))
+    }
+
+    fn count(&self, leaf_reader: &LeafReaderContext<'_, C>) -> Result<Option<usize>> {
+        self.weight.count(leaf_reader)
+    }
+
     fn hash_code(&self) -> u32 {
         self.hash_code
     }
@@ -543,8 +628,8 @@ pub struct BitSetLeafCollector {
 }
 
 impl Collector for BitSetLeafCollector {
-    fn needs_scores(&self) -> bool {
-        false
+    fn score_mode(&self) -> ScoreMode {
+        ScoreMode::CompleteNoScores
     }
 
     fn collect<S: Scorer + ?Sized>(&mut self, doc: DocId, _scorer: &mut S) -> Result<()> {
@@ -560,8 +645,8 @@ pub struct DocIdSetLeafCollector {
 }
 
 impl Collector for DocIdSetLeafCollector {
-    fn needs_scores(&self) -> bool {
-        false
+    fn score_mode(&self) -> ScoreMode {
+        ScoreMode::CompleteNoScores
     }
 
     fn collect<S: Scorer + ?Sized>(&mut self, doc: DocId, _scorer: &mut S) -> Result<()> {
@@ -588,13 +673,19 @@ static MAX_ARRAY_LENGTH: usize = 1 << 12;
 struct RoaringDocIdSet {
     doc_id_sets: Arc<[Option<DocIdSetEnum>]>,
     cardinality: usize,
+    ram_bytes_used: usize,
 }
 
 impl RoaringDocIdSet {
-    fn new(doc_id_sets: Vec<Option<DocIdSetEnum>>, cardinality: usize) -> RoaringDocIdSet {
+    fn new(
+        doc_id_sets: Vec<Option<DocIdSetEnum>>,
+        cardinality: usize,
+        ram_bytes_used: usize,
+    ) -> RoaringDocIdSet {
         RoaringDocIdSet {
             doc_id_sets: Arc::from(doc_id_sets.into_boxed_slice()),
             cardinality,
+            ram_bytes_used,
         }
     }
 }
@@ -602,6 +693,8 @@ impl RoaringDocIdSet {
 struct RoaringDocIdSetBuilder {
     doc_id_sets: Vec<Option<DocIdSetEnum>>,
     cardinality: usize,
+    // the bytes of the blocks flushed so far
+    ram_bytes_used: usize,
 
     max_doc: i32,
     last_doc_id: DocId,
@@ -623,6 +716,7 @@ impl RoaringDocIdSetBuilder {
         }
 
         RoaringDocIdSetBuilder {
+            ram_bytes_used: doc_id_sets.len() * mem::size_of::<Option<DocIdSetEnum>>(),
             doc_id_sets,
             cardinality: 0,
             max_doc,
@@ -646,6 +740,7 @@ impl RoaringDocIdSetBuilder {
             if current_block_cardinality > 0 {
                 let mut docs: Vec<u16> = vec![0u16; current_block_cardinality];
                 docs.copy_from_slice(&self.buffer[0..current_block_cardinality]);
+                self.ram_bytes_used += docs.len() * mem::size_of::<u16>();
 
                 self.doc_id_sets[current_block as usize] = Some(DocIdSetEnum::ShortArray(
                     ShortArrayDocIdSet::new(docs, current_block_cardinality),
@@ -684,6 +779,7 @@ impl RoaringDocIdSetBuilder {
                 );
 
                 let length = exclude_docs.len();
+                self.ram_bytes_used += length * mem::size_of::<u16>();
                 self.doc_id_sets[self.current_block as usize] =
                     Some(DocIdSetEnum::NotDocId(NotDocIdSet::new(
                         ShortArrayDocIdSet::new(exclude_docs, length),
@@ -692,6 +788,7 @@ impl RoaringDocIdSetBuilder {
             } else {
                 // Neither sparse nor super dense, use a fixed bit set
                 let dense_buf = self.dense_buffer.take().unwrap();
+                self.ram_bytes_used += dense_buf.bits.len() * mem::size_of::<i64>();
                 self.doc_id_sets[self.current_block as usize] =
                     Some(DocIdSetEnum::BitDocId(BitDocIdSet::new(
                         Arc::from(dense_buf),
@@ -710,7 +807,7 @@ impl RoaringDocIdSetBuilder {
     // NOTE: doc ids must be added in order.
     //
     pub fn add_doc(&mut self, doc_id: i32) -> Result<()> {
-        if doc_id < self.last_doc_id {
+        if doc_id <= self.last_doc_id {
             bail!(
                 "Doc ids must be added in-order, got {} which is <= lastDocID={}",
                 doc_id,
@@ -758,7 +855,7 @@ impl RoaringDocIdSetBuilder {
 
     pub fn build(mut self) -> RoaringDocIdSet {
         self.flush();
-        RoaringDocIdSet::new(self.doc_id_sets, self.cardinality)
+        RoaringDocIdSet::new(self.doc_id_sets, self.cardinality, self.ram_bytes_used)
     }
 }
 
@@ -839,7 +936,7 @@ impl DocIterator for RoaringDocIterator {
 
         if target_block != self.block {
             self.block = target_block;
-            if self.block as usize > self.doc_id_sets.len() {
+            if self.block as usize >= self.doc_id_sets.len() {
                 self.sub = None;
                 self.doc = NO_MORE_DOCS;
 
@@ -920,7 +1017,8 @@ impl DocIterator for ShortArrayDocIterator {
     fn next(&mut self) -> Result<DocId> {
         self.i += 1;
         if self.i as usize >= self.length {
-            Ok(NO_MORE_DOCS)
+            self.doc = NO_MORE_DOCS;
+            Ok(self.doc)
         } else {
             self.doc = i32::from(unsafe { *self.docs.as_ptr().offset(self.i as isize) });
             Ok(self.doc)
@@ -1038,6 +1136,15 @@ enum CacheDocIdSetEnum {
     Roaring(RoaringDocIdSet),
 }
 
+impl CacheDocIdSetEnum {
+    fn ram_bytes_used(&self, max_doc: i32) -> usize {
+        match self {
+            CacheDocIdSetEnum::Bit(_) => bits2words(max_doc as usize) * mem::size_of::<i64>(),
+            CacheDocIdSetEnum::Roaring(set) => set.ram_bytes_used,
+        }
+    }
+}
+
 impl DocIdSet for CacheDocIdSetEnum {
     type Iter = CachedDocIdSetIterEnum;
 
@@ -1130,3 +1237,331 @@ impl DocIterator for CachedDocIdSetIterEnum {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::codec::CodecEnum;
+    use core::doc::{Field, FieldType};
+    use core::index::index_writer_config::IndexWriterConfig;
+    use core::index::tests::{write_test_index, TestReader};
+    use core::index::{IndexOptions, IndexReader, Term};
+    use core::search::boolean_query::BooleanQuery;
+    use core::search::cache_policy::{
+        AlwaysCacheQueryCachingPolicy, UsageTrackingQueryCachingPolicy,
+    };
+    use core::search::collector::TopDocsCollector;
+    use core::search::match_all::ConstantScoreQuery;
+    use core::search::searcher::{DefaultIndexSearcher, DefaultSimilarityProducer, IndexSearcher};
+    use core::search::term_query::TermQuery;
+    use core::search::Query;
+    use core::util::tests::seeded_rng;
+    use core::util::VariantValue;
+
+    use rand::Rng;
+
+    type TestSearcher =
+        DefaultIndexSearcher<CodecEnum, TestReader, Arc<TestReader>, DefaultSimilarityProducer>;
+
+    const COLORS: [&str; 3] = ["red", "blue", "green"];
+
+    fn keyword(name: &str, value: &str) -> Field {
+        let mut field_type = FieldType::default();
+        field_type.tokenized = false;
+        field_type.index_options = IndexOptions::DocsAndFreqs;
+        Field::new(
+            name.to_string(),
+            field_type,
+            Some(VariantValue::VString(value.to_string())),
+            None,
+        )
+    }
+
+    // two segments of 20 docs with the three colors, 11 red, 12 blue and 13
+    // green docs are left after the deletions
+    fn open_reader() -> Arc<TestReader> {
+        let reader = write_test_index(IndexWriterConfig::default(), |writer| {
+            for segment in 0..2 {
+                for i in segment * 20..segment * 20 + 20 {
+                    let doc = vec![
+                        keyword("id", &i.to_string()),
+                        keyword("color", COLORS[i % 3]),
+                        keyword("tag", if i % 2 == 0 { "even" } else { "odd" }),
+                    ];
+                    writer.add_document(doc).unwrap();
+                }
+                writer.commit().unwrap();
+            }
+            for id in &["3", "6", "24", "31"] {
+                writer
+                    .delete_documents_by_term("id", id.as_bytes())
+                    .unwrap();
+            }
+            writer.commit().unwrap();
+        });
+        assert_eq!(reader.leaves().len(), 2);
+        assert!(reader.has_deletions());
+        reader
+    }
+
+    fn term_query(field: &str, value: &str) -> TermQuery {
+        TermQuery::new(
+            Term::new(field.to_string(), value.as_bytes().to_vec()),
+            1.0,
+            None,
+        )
+    }
+
+    fn cached_searcher<P>(
+        reader: &Arc<TestReader>,
+        cache: &Arc<LRUQueryCache>,
+        policy: P,
+    ) -> TestSearcher
+    where
+        P: QueryCachingPolicy<CodecEnum> + 'static,
+    {
+        let mut searcher = DefaultIndexSearcher::new(Arc::clone(reader));
+        let query_cache: Arc<dyn QueryCache<CodecEnum>> =
+            Arc::clone(cache) as Arc<dyn QueryCache<CodecEnum>>;
+        searcher.set_query_cache(query_cache);
+        searcher.set_query_cache_policy(Arc::new(policy));
+        searcher
+    }
+
+    fn hits(searcher: &TestSearcher, query: &dyn Query<CodecEnum>) -> Vec<(DocId, u32)> {
+        let mut collector = TopDocsCollector::new(100);
+        searcher.search(query, &mut collector).unwrap();
+        collector
+            .top_docs()
+            .score_docs()
+            .iter()
+            .map(|d| (d.doc_id(), d.score().to_bits()))
+            .collect()
+    }
+
+    // the colors of the cached queries, the least recently used first
+    fn cached_colors(cache: &LRUQueryCache) -> Vec<&'static str> {
+        cache
+            .cached_queries()
+            .unwrap()
+            .iter()
+            .map(|key| {
+                *COLORS
+                    .iter()
+                    .find(|color| key.contains(&format!("term: {},", color)))
+                    .unwrap()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_cached_filters() {
+        let reader = open_reader();
+        let cache = Arc::new(LRUQueryCache::new(10).with_min_leaf_size(0, 0.0));
+        let searcher = cached_searcher(&reader, &cache, AlwaysCacheQueryCachingPolicy::default());
+        let mut uncached = DefaultIndexSearcher::new(Arc::clone(&reader));
+        uncached.set_query_cache(Arc::new(NoCacheQueryCache::new()));
+
+        let query = BooleanQuery::build(
+            vec![Box::new(term_query("tag", "even"))],
+            vec![],
+            vec![Box::new(term_query("color", "red"))],
+        )
+        .unwrap();
+        let expected = hits(&uncached, query.as_ref());
+        // 0, 12, 18, 30 and 36 are red and even
+        assert_eq!(expected.len(), 5);
+
+        // the filter is cached for both segments
+        assert_eq!(hits(&searcher, query.as_ref()), expected);
+        assert_eq!(cache.hit_count().unwrap(), 0);
+        assert_eq!(cache.miss_count().unwrap(), 2);
+        assert_eq!(cache.cache_count().unwrap(), 2);
+        assert_eq!(cache.cache_size().unwrap(), 2);
+        assert!(cache.ram_bytes_used().unwrap() > 0);
+
+        assert_eq!(hits(&searcher, query.as_ref()), expected);
+        assert_eq!(cache.hit_count().unwrap(), 2);
+        assert_eq!(cache.miss_count().unwrap(), 2);
+        assert_eq!(cache.cache_count().unwrap(), 2);
+
+        // the deleted docs are filtered out of the cached sets
+        let query = ConstantScoreQuery::new(Box::new(term_query("color", "blue")));
+        assert_eq!(hits(&searcher, &query), hits(&uncached, &query));
+        assert_eq!(hits(&searcher, &query), hits(&uncached, &query));
+        assert_eq!(searcher.count(&query).unwrap(), 12);
+        assert_eq!(cached_colors(&cache), vec!["red", "blue"]);
+
+        // the sets of the segments are dropped with their cores
+        drop(searcher);
+        drop(uncached);
+        drop(reader);
+        assert_eq!(cache.cache_size().unwrap(), 0);
+        assert_eq!(cache.eviction_count().unwrap(), 4);
+        let keys: usize = cache
+            .cached_queries()
+            .unwrap()
+            .iter()
+            .map(String::len)
+            .sum();
+        assert_eq!(cache.ram_bytes_used().unwrap(), keys);
+    }
+
+    #[test]
+    fn test_eviction_order() {
+        let reader = open_reader();
+        let cache = Arc::new(LRUQueryCache::new(2).with_min_leaf_size(0, 0.0));
+        let searcher = cached_searcher(&reader, &cache, AlwaysCacheQueryCachingPolicy::default());
+        let count = |color: &str| searcher.count(&term_query("color", color)).unwrap();
+
+        assert_eq!(count("red"), 11);
+        assert_eq!(count("blue"), 12);
+        assert_eq!(cached_colors(&cache), vec!["red", "blue"]);
+        // "red" gets used again, "blue" is evicted instead
+        assert_eq!(count("red"), 11);
+        assert_eq!(cached_colors(&cache), vec!["blue", "red"]);
+        assert_eq!(count("green"), 13);
+        assert_eq!(cached_colors(&cache), vec!["red", "green"]);
+        assert_eq!(cache.eviction_count().unwrap(), 2);
+        assert_eq!(cache.cache_size().unwrap(), 4);
+        assert_eq!(count("blue"), 12);
+        assert_eq!(cached_colors(&cache), vec!["green", "blue"]);
+
+        // the cache is also kept under its byte limit
+        let cache = Arc::new(LRUQueryCache::new(10).with_min_leaf_size(0, 0.0));
+        let searcher = cached_searcher(&reader, &cache, AlwaysCacheQueryCachingPolicy::default());
+        searcher.count(&term_query("color", "green")).unwrap();
+        let limit = cache.ram_bytes_used().unwrap() + 8;
+        let cache = Arc::new(LRUQueryCache::with_ram_limit(10, limit).with_min_leaf_size(0, 0.0));
+        let searcher = cached_searcher(&reader, &cache, AlwaysCacheQueryCachingPolicy::default());
+        let count = |color: &str| searcher.count(&term_query("color", color)).unwrap();
+        assert_eq!(count("green"), 13);
+        assert_eq!(count("red"), 11);
+        assert_eq!(cached_colors(&cache), vec!["red"]);
+        assert!(cache.ram_bytes_used().unwrap() <= limit);
+    }
+
+    #[test]
+    fn test_caching_policy() {
+        let reader = open_reader();
+        let cache = Arc::new(LRUQueryCache::new(10).with_min_leaf_size(0, 0.0));
+        let policy = UsageTrackingQueryCachingPolicy::default().with_min_frequency(3);
+        let searcher = cached_searcher(&reader, &cache, policy);
+
+        for i in 0..3 {
+            assert_eq!(searcher.count(&term_query("color", "red")).unwrap(), 11);
+            let expected = if i < 2 { 0 } else { 2 };
+            assert_eq!(cache.cache_count().unwrap(), expected);
+        }
+        assert_eq!(cache.hit_count().unwrap(), 0);
+        assert_eq!(searcher.count(&term_query("color", "red")).unwrap(), 11);
+        assert_eq!(cache.hit_count().unwrap(), 2);
+
+        // the segments under the minimum size aren't even looked up
+        let cache = Arc::new(LRUQueryCache::new(10));
+        let searcher = cached_searcher(&reader, &cache, AlwaysCacheQueryCachingPolicy::default());
+        assert_eq!(searcher.count(&term_query("color", "red")).unwrap(), 11);
+        assert_eq!(cache.miss_count().unwrap(), 0);
+        assert_eq!(cache.cache_size().unwrap(), 0);
+    }
+
+    fn random_docs(rng: &mut impl Rng, max_doc: i32, density: f64) -> Vec<DocId> {
+        (0..max_doc)
+            .filter(|_| rng.gen::<f64>() < density)
+            .collect()
+    }
+
+    fn roaring(max_doc: i32, docs: &[DocId]) -> RoaringDocIdSet {
+        let mut builder = RoaringDocIdSetBuilder::new(max_doc);
+        for &doc in docs {
+            builder.add_doc(doc).unwrap();
+        }
+        builder.build()
+    }
+
+    fn bit_set(max_doc: i32, docs: &[DocId]) -> BitDocIdSet<FixedBitSet> {
+        let mut bits = FixedBitSet::new(max_doc as usize);
+        for &doc in docs {
+            bits.set(doc as usize);
+        }
+        BitDocIdSet::new(Arc::new(bits), docs.len())
+    }
+
+    #[test]
+    fn test_roaring_against_bit_set() {
+        let mut rng = seeded_rng();
+        // the last block is partial, 0.99 encodes the full blocks as their inverse
+        let max_doc = 3 * (1 << 16) + 1234;
+        for &density in &[0.00001, 0.001, 0.01, 0.1, 0.5, 0.9, 0.99] {
+            let docs = random_docs(&mut rng, max_doc, density);
+            let roaring = roaring(max_doc, &docs);
+            let bits = bit_set(max_doc, &docs);
+            if docs.is_empty() {
+                assert!(roaring.iterator().unwrap().is_none());
+                continue;
+            }
+
+            let mut expected = bits.iterator().unwrap().unwrap();
+            let mut actual = roaring.iterator().unwrap().unwrap();
+            assert_eq!(actual.cost(), docs.len());
+            loop {
+                let doc = expected.next().unwrap();
+                assert_eq!(actual.next().unwrap(), doc, "density {}", density);
+                if doc == NO_MORE_DOCS {
+                    break;
+                }
+            }
+
+            // advance by random steps, some of them skipping whole blocks
+            let mut expected = bits.iterator().unwrap().unwrap();
+            let mut actual = roaring.iterator().unwrap().unwrap();
+            let mut doc = -1;
+            while doc != NO_MORE_DOCS {
+                let step = if rng.gen::<bool>() {
+                    1 + (rng.gen::<u32>() % 100) as i32
+                } else {
+                    1 + (rng.gen::<u32>() % (1 << 16)) as i32
+                };
+                doc = if step == 1 {
+                    expected.next().unwrap()
+                } else {
+                    expected.advance(doc + step).unwrap()
+                };
+                let actual_doc = if step == 1 {
+                    actual.next().unwrap()
+                } else {
+                    actual.advance(actual.doc_id() + step).unwrap()
+                };
+                assert_eq!(actual_doc, doc, "density {}", density);
+            }
+        }
+    }
+
+    #[test]
+    fn test_roaring_ram_bytes_used() {
+        let mut rng = seeded_rng();
+        let max_doc = 1 << 20;
+        let bits_ram = CacheDocIdSetEnum::Bit(bit_set(max_doc, &[])).ram_bytes_used(max_doc);
+        for &density in &[0.0001, 0.001, 0.01] {
+            let docs = random_docs(&mut rng, max_doc, density);
+            let roaring = CacheDocIdSetEnum::Roaring(roaring(max_doc, &docs));
+            assert!(
+                roaring.ram_bytes_used(max_doc) * 4 < bits_ram,
+                "density {}",
+                density
+            );
+        }
+        // very dense blocks are stored as their missing docs
+        let docs: Vec<DocId> = (0..max_doc).filter(|d| d % 1000 != 0).collect();
+        let roaring = CacheDocIdSetEnum::Roaring(roaring(max_doc, &docs));
+        assert!(roaring.ram_bytes_used(max_doc) * 4 < bits_ram);
+    }
+
+    #[test]
+    fn test_roaring_out_of_order() {
+        let mut builder = RoaringDocIdSetBuilder::new(100);
+        builder.add_doc(3).unwrap();
+        assert!(builder.add_doc(3).is_err());
+        assert!(builder.add_doc(2).is_err());
+    }
+}
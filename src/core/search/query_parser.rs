@@ -0,0 +1,1304 @@
+// Copyright 2019 Zhizhesihai (Beijing) Technology Limited.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use core::analysis::Analyzer;
+use core::codec::Codec;
+use core::index::Term;
+use core::search::boolean_query::BooleanQuery;
+use core::search::boost::BoostQuery;
+use core::search::fuzzy_query::{FuzzyQuery, DEFAULT_MAX_EDITS};
+use core::search::fuzzy_query::{DEFAULT_MAX_EXPANSIONS, DEFAULT_PREFIX_LENGTH};
+use core::search::match_all::MatchAllDocsQuery;
+use core::search::phrase_query::PhraseQueryBuilder;
+use core::search::prefix_query::PrefixQuery;
+use core::search::term_query::TermQuery;
+use core::search::term_range_query::TermRangeQuery;
+use core::search::wildcard_query::{WildcardQuery, WILDCARD_ESCAPE};
+use core::search::wildcard_query::{WILDCARD_CHAR, WILDCARD_STRING};
+use core::search::{self, Query};
+
+use error::ErrorKind::IllegalArgument;
+use error::{Error, ErrorKind, Result};
+
+use std::sync::Arc;
+
+/// The most paths through the token graph of a text searched, beyond which
+/// the parsing fails.
+pub const MAX_GRAPH_PATHS: usize = 1024;
+
+/// How the clauses of a query are combined when no operator is given
+/// between them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Operator {
+    /// `a b` matches the docs matching `a` or `b`.
+    Or,
+    /// `a b` matches the docs matching both `a` and `b`.
+    And,
+}
+
+impl Default for Operator {
+    fn default() -> Self {
+        Operator::Or
+    }
+}
+
+/// Parses the query syntax of Lucene's classic query parser:
+///
+/// * `term`, `"a phrase"` and `"a phrase"~2` search the default field, `field:term`, `field:"a
+///   phrase"` and `field:(a group)` another one;
+/// * `+term` is required, `-term`, `!term` and `NOT term` are prohibited, `a AND b`, `a && b`, `a
+///   OR b` and `a || b` combine two clauses;
+/// * `te?m*` is a wildcard term, `term*` a prefix, `term~` and `term~1` are fuzzy terms and `*:*`
+///   matches all the docs;
+/// * `[a TO b]` is an inclusive term range, `{a TO b}` an exclusive one, `*` leaves a side open;
+/// * `^2.5` boosts a term, a phrase, a group or a range;
+/// * `\` escapes the next char, e.g. `a\:b` or `a\*`.
+///
+/// The terms and the phrases are analyzed with the analyzer of their field.
+/// The wildcard, prefix, fuzzy and range terms are not. A token graph, e.g.
+/// of synonyms of several words, matches any of its paths: "ny" mapped to
+/// "new york" is searched as `"new york" ny`.
+///
+/// An invalid query string fails with a `search::ErrorKind::QueryParse`
+/// error giving the char position of the failure and what was expected there.
+pub struct QueryParser {
+    default_field: String,
+    analyzer: Arc<dyn Analyzer>,
+    default_operator: Operator,
+    auto_generate_phrase_queries: bool,
+    enable_position_increments: bool,
+}
+
+impl QueryParser {
+    pub fn new(default_field: &str, analyzer: Arc<dyn Analyzer>) -> QueryParser {
+        QueryParser {
+            default_field: default_field.to_string(),
+            analyzer,
+            default_operator: Operator::default(),
+            auto_generate_phrase_queries: false,
+            enable_position_increments: true,
+        }
+    }
+
+    pub fn with_default_operator(mut self, operator: Operator) -> Self {
+        self.default_operator = operator;
+        self
+    }
+
+    /// Whether a word analyzed into terms at several positions, like "wi-fi",
+    /// is searched as a phrase rather than as a boolean query of its terms
+    /// combined with the default operator.
+    pub fn with_auto_generate_phrase_queries(mut self, auto_generate: bool) -> Self {
+        self.auto_generate_phrase_queries = auto_generate;
+        self
+    }
+
+    /// Whether the phrases keep the gaps left by the analyzer, e.g. by the
+    /// stop words it removes, true by default. "quick the fox" then matches
+    /// "quick brown fox" but not "quick fox". Without the increments the
+    /// terms of a phrase follow each other, so it is the other way around.
+    pub fn with_enable_position_increments(mut self, enable: bool) -> Self {
+        self.enable_position_increments = enable;
+        self
+    }
+
+    pub fn default_field(&self) -> &str {
+        &self.default_field
+    }
+
+    pub fn default_operator(&self) -> Operator {
+        self.default_operator
+    }
+
+    pub fn enable_position_increments(&self) -> bool {
+        self.enable_position_increments
+    }
+
+    pub fn parse<C: Codec>(&self, query: &str) -> Result<Box<dyn Query<C>>> {
+        let mut state = ParseState {
+            parser: self,
+            tokens: tokenize(query)?,
+            index: 0,
+            end: query.chars().count(),
+        };
+        match state.parse_query(&self.default_field, false)? {
+            Some(query) => Ok(query),
+            None => bail!(IllegalArgument(format!(
+                "no term left in '{}' after analysis",
+                query
+            ))),
+        }
+    }
+
+    // the terms of `text` in `field` with their positions and position lengths
+    fn analyze(&self, field: &str, text: &str) -> Result<Analyzed> {
+        let mut stream = self.analyzer.token_stream(field, text)?;
+        stream.reset()?;
+        let mut analyzed = Analyzed::default();
+        let mut position = -1;
+        while stream.increment_token()? {
+            position += stream.position_attribute_mut().get_position_increment() as i32;
+            let length = stream
+                .position_length_attribute()
+                .map_or(1, |attr| attr.get_position_length() as i32);
+            let term = stream.term_bytes_attribute().get_bytes_ref();
+            analyzed
+                .terms
+                .push((term.bytes().to_vec(), position, length));
+        }
+        stream.end()?;
+        Ok(analyzed)
+    }
+}
+
+// the analyzed terms with their positions and the number of positions each
+// one spans, more than one for a synonym of several words
+#[derive(Default)]
+struct Analyzed {
+    terms: Vec<(Vec<u8>, i32, i32)>,
+}
+
+impl Analyzed {
+    // whether the terms make a graph of several paths, some of them spanning
+    // several positions or being at the position of others but not all
+    fn is_graph(&self) -> bool {
+        self.terms.iter().any(|t| t.2 > 1)
+            || (!self.same_position() && self.terms.windows(2).any(|w| w[0].1 == w[1].1))
+    }
+
+    // whether the terms are all at the same position, e.g. synonyms
+    fn same_position(&self) -> bool {
+        self.terms.iter().all(|t| t.1 == self.terms[0].1)
+    }
+
+    // the terms with their positions
+    fn into_positions(self) -> Vec<(Vec<u8>, i32)> {
+        self.terms
+            .into_iter()
+            .map(|(term, position, _)| (term, position))
+            .collect()
+    }
+}
+
+const CLAUSE_START: &[&str] = &["term", "phrase", "\"(\"", "\"[\"", "\"{\""];
+const RANGE_BOUND: &[&str] = &["term", "phrase", "\"*\""];
+
+fn parse_error(position: usize, found: String, expected: &[&str]) -> Error {
+    let expected = expected.iter().map(|e| e.to_string()).collect();
+    ErrorKind::Search(search::ErrorKind::QueryParse(position, found, expected)).into()
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum TokenKind {
+    // the chars of a term, with whether each one was escaped
+    Word(Vec<(char, bool)>),
+    Phrase(String),
+    Plus,
+    // `-`, `!` or `NOT`
+    Minus,
+    And,
+    Or,
+    Colon,
+    LParen,
+    RParen,
+    LBracket,
+    RBracket,
+    LBrace,
+    RBrace,
+    // `^` and `~`, with the number following them
+    Caret(String),
+    Tilde(String),
+}
+
+#[derive(Debug)]
+struct Token {
+    kind: TokenKind,
+    // the position of the first char of the token
+    position: usize,
+    // the token as written in the query string
+    source: String,
+}
+
+fn is_word_end(c: char) -> bool {
+    c.is_whitespace()
+        || match c {
+            '(' | ')' | ':' | '^' | '[' | ']' | '"' | '{' | '}' | '~' => true,
+            _ => false,
+        }
+}
+
+fn unescaped(chars: &[(char, bool)]) -> String {
+    chars.iter().map(|&(c, _)| c).collect()
+}
+
+// whether `word` is `keyword` without escaped chars
+fn is_keyword(word: &[(char, bool)], keyword: &str) -> bool {
+    word.iter().all(|&(_, escaped)| !escaped) && unescaped(word) == keyword
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let start = i;
+        let kind = match chars[i] {
+            c if c.is_whitespace() => {
+                i += 1;
+                continue;
+            }
+            '+' => TokenKind::Plus,
+            '-' | '!' => TokenKind::Minus,
+            ':' => TokenKind::Colon,
+            '(' => TokenKind::LParen,
+            ')' => TokenKind::RParen,
+            '[' => TokenKind::LBracket,
+            ']' => TokenKind::RBracket,
+            '{' => TokenKind::LBrace,
+            '}' => TokenKind::RBrace,
+            c @ '^' | c @ '~' => {
+                let mut number = String::new();
+                while i + 1 < chars.len() && (chars[i + 1].is_ascii_digit() || chars[i + 1] == '.')
+                {
+                    i += 1;
+                    number.push(chars[i]);
+                }
+                if c == '^' {
+                    TokenKind::Caret(number)
+                } else {
+                    TokenKind::Tilde(number)
+                }
+            }
+            '"' => {
+                let mut phrase = String::new();
+                loop {
+                    i += 1;
+                    match chars.get(i) {
+                        None => {
+                            return Err(parse_error(
+                                chars.len(),
+                                "end of query".into(),
+                                &["closing quote"],
+                            ));
+                        }
+                        Some('"') => break,
+                        Some('\\') if i + 1 < chars.len() => {
+                            i += 1;
+                            phrase.push(chars[i]);
+                        }
+                        Some(&c) => phrase.push(c),
+                    }
+                }
+                TokenKind::Phrase(phrase)
+            }
+            _ => {
+                let mut word = Vec::new();
+                while i < chars.len() && !is_word_end(chars[i]) {
+                    if chars[i] == WILDCARD_ESCAPE {
+                        if i + 1 == chars.len() {
+                            return Err(parse_error(
+                                chars.len(),
+                                "end of query".into(),
+                                &["escaped char"],
+                            ));
+                        }
+                        word.push((chars[i + 1], true));
+                        i += 2;
+                    } else {
+                        word.push((chars[i], false));
+                        i += 1;
+                    }
+                }
+                i -= 1;
+                if is_keyword(&word, "AND") || is_keyword(&word, "&&") {
+                    TokenKind::And
+                } else if is_keyword(&word, "OR") || is_keyword(&word, "||") {
+                    TokenKind::Or
+                } else if is_keyword(&word, "NOT") {
+                    TokenKind::Minus
+                } else {
+                    TokenKind::Word(word)
+                }
+            }
+        };
+        i += 1;
+        tokens.push(Token {
+            kind,
+            position: start,
+            source: chars[start..i].iter().collect(),
+        });
+    }
+    Ok(tokens)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Occur {
+    Must,
+    Should,
+    MustNot,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Modifier {
+    Required,
+    Prohibited,
+}
+
+struct ParseState<'a> {
+    parser: &'a QueryParser,
+    tokens: Vec<Token>,
+    index: usize,
+    // the position of the end of the query string
+    end: usize,
+}
+
+impl<'a> ParseState<'a> {
+    fn peek(&self) -> Option<&TokenKind> {
+        self.tokens.get(self.index).map(|t| &t.kind)
+    }
+
+    fn position(&self) -> usize {
+        self.tokens
+            .get(self.index)
+            .map(|t| t.position)
+            .unwrap_or(self.end)
+    }
+
+    fn found(&self) -> String {
+        match self.tokens.get(self.index) {
+            Some(token) => format!("\"{}\"", token.source),
+            None => "end of query".into(),
+        }
+    }
+
+    // the error on the next token, or on the end of the query
+    fn unexpected(&self, expected: &[&str]) -> Error {
+        parse_error(self.position(), self.found(), expected)
+    }
+
+    fn next(&mut self, expected: &[&str]) -> Result<TokenKind> {
+        match self.tokens.get(self.index) {
+            Some(token) => {
+                self.index += 1;
+                Ok(token.kind.clone())
+            }
+            None => Err(self.unexpected(expected)),
+        }
+    }
+
+    // Query := [Modifier] Clause ([Conjunction] [Modifier] Clause)*
+    fn parse_query<C: Codec>(
+        &mut self,
+        field: &str,
+        nested: bool,
+    ) -> Result<Option<Box<dyn Query<C>>>> {
+        let mut clauses = Vec::new();
+        let mut first = true;
+        loop {
+            match self.peek() {
+                None if first => return Err(self.unexpected(CLAUSE_START)),
+                None => break,
+                Some(TokenKind::RParen) if nested && !first => break,
+                Some(TokenKind::RParen) if nested => return Err(self.unexpected(CLAUSE_START)),
+                _ => {}
+            }
+            let conjunction = match self.peek() {
+                Some(TokenKind::And) => Some(Operator::And),
+                Some(TokenKind::Or) => Some(Operator::Or),
+                _ => None,
+            };
+            if conjunction.is_some() {
+                if first {
+                    return Err(self.unexpected(&["\"+\"", "\"-\"", "term", "phrase", "\"(\""]));
+                }
+                self.index += 1;
+            }
+            let modifier = match self.peek() {
+                Some(TokenKind::Plus) => Some(Modifier::Required),
+                Some(TokenKind::Minus) => Some(Modifier::Prohibited),
+                _ => None,
+            };
+            if modifier.is_some() {
+                self.index += 1;
+            }
+            let query = self.parse_clause(field)?;
+            self.add_clause(&mut clauses, conjunction, modifier, query);
+            first = false;
+        }
+        combine(clauses)
+    }
+
+    // sets the occur of the new clause and of the previous one the way
+    // Lucene's classic query parser does
+    fn add_clause<C: Codec>(
+        &self,
+        clauses: &mut Vec<(Occur, Box<dyn Query<C>>)>,
+        conjunction: Option<Operator>,
+        modifier: Option<Modifier>,
+        query: Option<Box<dyn Query<C>>>,
+    ) {
+        let default_operator = self.parser.default_operator;
+        if let Some(last) = clauses.last_mut() {
+            if last.0 != Occur::MustNot {
+                match conjunction {
+                    Some(Operator::And) => last.0 = Occur::Must,
+                    Some(Operator::Or) if default_operator == Operator::And => {
+                        last.0 = Occur::Should
+                    }
+                    _ => {}
+                }
+            }
+        }
+        let prohibited = modifier == Some(Modifier::Prohibited);
+        let required = modifier == Some(Modifier::Required)
+            || (!prohibited
+                && match default_operator {
+                    Operator::Or => conjunction == Some(Operator::And),
+                    Operator::And => conjunction != Some(Operator::Or),
+                });
+        let occur = if prohibited {
+            Occur::MustNot
+        } else if required {
+            Occur::Must
+        } else {
+            Occur::Should
+        };
+        if let Some(query) = query {
+            clauses.push((occur, query));
+        }
+    }
+
+    // Clause := [field ":"] (Word | Phrase | Range | "(" Query ")" ["^" boost])
+    fn parse_clause<C: Codec>(&mut self, field: &str) -> Result<Option<Box<dyn Query<C>>>> {
+        let field_name = match self.peek() {
+            Some(TokenKind::Word(word))
+                if self.tokens.get(self.index + 1).map(|t| &t.kind) == Some(&TokenKind::Colon) =>
+            {
+                Some(unescaped(word))
+            }
+            _ => None,
+        };
+        let field = match field_name {
+            Some(name) => {
+                self.index += 2;
+                name
+            }
+            None => field.to_string(),
+        };
+        let position = self.position();
+        match self.next(CLAUSE_START)? {
+            TokenKind::LParen => {
+                let query = self.parse_query(&field, true)?;
+                if self.peek() != Some(&TokenKind::RParen) {
+                    return Err(self.unexpected(&["\")\""]));
+                }
+                self.index += 1;
+                let boost = self.parse_boost()?;
+                Ok(query.map(|q| BoostQuery::build(q, boost.unwrap_or(1.0))))
+            }
+            TokenKind::Word(word) => self.word_query(&field, &word),
+            TokenKind::Phrase(phrase) => self.phrase_query(&field, &phrase),
+            TokenKind::LBracket => self.range_query(&field, true),
+            TokenKind::LBrace => self.range_query(&field, false),
+            _ => Err(parse_error(
+                position,
+                format!("\"{}\"", self.tokens[self.index - 1].source),
+                CLAUSE_START,
+            )),
+        }
+    }
+
+    // an optional `^boost`
+    fn parse_boost(&mut self) -> Result<Option<f32>> {
+        let number = match self.peek() {
+            Some(TokenKind::Caret(number)) => number.clone(),
+            _ => return Ok(None),
+        };
+        let position = self.position() + 1;
+        self.index += 1;
+        match number.parse::<f32>() {
+            Ok(boost) => Ok(Some(boost)),
+            Err(_) if number.is_empty() => Err(self.unexpected(&["number"])),
+            Err(_) => Err(parse_error(
+                position,
+                format!("\"{}\"", number),
+                &["number"],
+            )),
+        }
+    }
+
+    // an optional `~` followed by an optional number
+    fn parse_slop(&mut self) -> Result<Option<Option<f32>>> {
+        let number = match self.peek() {
+            Some(TokenKind::Tilde(number)) => number.clone(),
+            _ => return Ok(None),
+        };
+        let position = self.position() + 1;
+        self.index += 1;
+        if number.is_empty() {
+            return Ok(Some(None));
+        }
+        match number.parse::<f32>() {
+            Ok(slop) => Ok(Some(Some(slop))),
+            Err(_) => Err(parse_error(
+                position,
+                format!("\"{}\"", number),
+                &["number"],
+            )),
+        }
+    }
+
+    // the boost and the slop following a term or a phrase, in any order
+    fn parse_term_suffix(&mut self) -> Result<(Option<f32>, Option<Option<f32>>)> {
+        let mut boost = self.parse_boost()?;
+        let slop = self.parse_slop()?;
+        if boost.is_none() {
+            boost = self.parse_boost()?;
+        }
+        Ok((boost, slop))
+    }
+
+    fn word_query<C: Codec>(
+        &mut self,
+        field: &str,
+        word: &[(char, bool)],
+    ) -> Result<Option<Box<dyn Query<C>>>> {
+        let (boost, slop) = self.parse_term_suffix()?;
+        let text = unescaped(word);
+        let wildcards = word
+            .iter()
+            .filter(|&&(c, escaped)| !escaped && (c == WILDCARD_STRING || c == WILDCARD_CHAR))
+            .count();
+        let query: Box<dyn Query<C>> = if field == "*" && is_keyword(word, "*") {
+            Box::new(MatchAllDocsQuery)
+        } else if wildcards == 1 && word.last() == Some(&(WILDCARD_STRING, false)) {
+            let prefix = &text[..text.len() - 1];
+            Box::new(PrefixQuery::new(
+                field.to_string(),
+                prefix.as_bytes().to_vec(),
+            ))
+        } else if wildcards > 0 {
+            let mut pattern = String::with_capacity(text.len());
+            for &(c, escaped) in word {
+                if escaped && (c == WILDCARD_STRING || c == WILDCARD_CHAR || c == WILDCARD_ESCAPE) {
+                    pattern.push(WILDCARD_ESCAPE);
+                }
+                pattern.push(c);
+            }
+            Box::new(WildcardQuery::new(field.to_string(), &pattern))
+        } else if let Some(similarity) = slop {
+            let term = Term::new(field.to_string(), text.as_bytes().to_vec());
+            let max_edits = fuzzy_edits(similarity, text.chars().count());
+            Box::new(FuzzyQuery::new(
+                term,
+                max_edits,
+                DEFAULT_PREFIX_LENGTH,
+                DEFAULT_MAX_EXPANSIONS,
+            )?)
+        } else {
+            match self.analyzed_word(field, &text)? {
+                Some(query) => query,
+                None => return Ok(None),
+            }
+        };
+        Ok(Some(BoostQuery::build(query, boost.unwrap_or(1.0))))
+    }
+
+    fn analyzed_word<C: Codec>(
+        &self,
+        field: &str,
+        text: &str,
+    ) -> Result<Option<Box<dyn Query<C>>>> {
+        let analyzed = self.parser.analyze(field, text)?;
+        let position_increments = self.parser.enable_position_increments;
+        if analyzed.is_graph() {
+            let query = if self.parser.auto_generate_phrase_queries {
+                paths_query(field, &analyzed.terms, 0, position_increments)?
+            } else {
+                graph_query(
+                    field,
+                    &analyzed,
+                    self.parser.default_operator,
+                    position_increments,
+                )?
+            };
+            return Ok(Some(query));
+        }
+        if analyzed.same_position() {
+            return synonyms_query(field, analyzed.into_positions());
+        }
+        let terms = analyzed.into_positions();
+        if self.parser.auto_generate_phrase_queries {
+            return phrase(field, terms, 0, position_increments).map(Some);
+        }
+        let queries: Vec<Box<dyn Query<C>>> = terms
+            .into_iter()
+            .map(|(term, _)| term_query(field, term))
+            .collect();
+        let query = match self.parser.default_operator {
+            Operator::Or => BooleanQuery::build(vec![], queries, vec![])?,
+            Operator::And => BooleanQuery::build(queries, vec![], vec![])?,
+        };
+        Ok(Some(query))
+    }
+
+    fn phrase_query<C: Codec>(
+        &mut self,
+        field: &str,
+        text: &str,
+    ) -> Result<Option<Box<dyn Query<C>>>> {
+        let (boost, slop) = self.parse_term_suffix()?;
+        let slop = slop.and_then(|s| s).unwrap_or(0.0) as i32;
+        let analyzed = self.parser.analyze(field, text)?;
+        let position_increments = self.parser.enable_position_increments;
+        let query = if analyzed.is_graph() {
+            paths_query(field, &analyzed.terms, slop, position_increments)?
+        } else if analyzed.same_position() {
+            match synonyms_query(field, analyzed.into_positions())? {
+                Some(query) => query,
+                None => return Ok(None),
+            }
+        } else {
+            phrase(field, analyzed.into_positions(), slop, position_increments)?
+        };
+        Ok(Some(BoostQuery::build(query, boost.unwrap_or(1.0))))
+    }
+
+    // Range := ("[" | "{") bound "TO" bound ("]" | "}") ["^" boost]
+    fn range_query<C: Codec>(
+        &mut self,
+        field: &str,
+        include_lower: bool,
+    ) -> Result<Option<Box<dyn Query<C>>>> {
+        let lower = self.range_bound()?;
+        match self.peek() {
+            Some(TokenKind::Word(word)) if is_keyword(word, "TO") => self.index += 1,
+            _ => return Err(self.unexpected(&["\"TO\""])),
+        }
+        let upper = self.range_bound()?;
+        let include_upper = match self.peek() {
+            Some(TokenKind::RBracket) => true,
+            Some(TokenKind::RBrace) => false,
+            _ => return Err(self.unexpected(&["\"]\"", "\"}\""])),
+        };
+        self.index += 1;
+        let boost = self.parse_boost()?;
+        let query = TermRangeQuery::new(
+            field.to_string(),
+            lower,
+            upper,
+            include_lower,
+            include_upper,
+        );
+        Ok(Some(BoostQuery::build(
+            Box::new(query),
+            boost.unwrap_or(1.0),
+        )))
+    }
+
+    // a range bound, `None` for an open one
+    fn range_bound(&mut self) -> Result<Option<Vec<u8>>> {
+        let bound = match self.peek() {
+            Some(TokenKind::Word(word)) if is_keyword(word, "*") => None,
+            Some(TokenKind::Word(word)) => Some(unescaped(word).into_bytes()),
+            Some(TokenKind::Phrase(phrase)) => Some(phrase.clone().into_bytes()),
+            _ => return Err(self.unexpected(RANGE_BOUND)),
+        };
+        self.index += 1;
+        Ok(bound)
+    }
+}
+
+fn combine<C: Codec>(
+    mut clauses: Vec<(Occur, Box<dyn Query<C>>)>,
+) -> Result<Option<Box<dyn Query<C>>>> {
+    if clauses.is_empty() {
+        return Ok(None);
+    }
+    if clauses.len() == 1 && clauses[0].0 != Occur::MustNot {
+        return Ok(Some(clauses.remove(0).1));
+    }
+    let mut musts = Vec::new();
+    let mut shoulds = Vec::new();
+    let mut must_nots = Vec::new();
+    for (occur, query) in clauses {
+        match occur {
+            Occur::Must => musts.push(query),
+            Occur::Should => shoulds.push(query),
+            Occur::MustNot => must_nots.push(query),
+        }
+    }
+    BooleanQuery::build_with_must_nots(musts, shoulds, vec![], must_nots).map(Some)
+}
+
+// the max edits of a fuzzy term given after its `~`, an edit distance or a
+// similarity below 1 as in old Lucene versions
+fn fuzzy_edits(similarity: Option<f32>, term_length: usize) -> u32 {
+    match similarity {
+        None => DEFAULT_MAX_EDITS,
+        Some(s) if s >= 1.0 => (s as u32).min(DEFAULT_MAX_EDITS),
+        Some(s) => (((1.0 - s) * term_length as f32) as u32).min(DEFAULT_MAX_EDITS),
+    }
+}
+
+fn term_query<C: Codec>(field: &str, term: Vec<u8>) -> Box<dyn Query<C>> {
+    Box::new(TermQuery::new(
+        Term::new(field.to_string(), term),
+        1.0,
+        None,
+    ))
+}
+
+// the terms analyzed at a single position match any of them
+fn synonyms_query<C: Codec>(
+    field: &str,
+    terms: Vec<(Vec<u8>, i32)>,
+) -> Result<Option<Box<dyn Query<C>>>> {
+    let queries: Vec<Box<dyn Query<C>>> = terms
+        .into_iter()
+        .map(|(term, _)| term_query(field, term))
+        .collect();
+    if queries.is_empty() {
+        return Ok(None);
+    }
+    BooleanQuery::build(vec![], queries, vec![]).map(Some)
+}
+
+// the phrase of the analyzed terms, at their positions with the increments,
+// else one after the other
+fn phrase<C: Codec>(
+    field: &str,
+    terms: Vec<(Vec<u8>, i32)>,
+    slop: i32,
+    position_increments: bool,
+) -> Result<Box<dyn Query<C>>> {
+    let mut builder = PhraseQueryBuilder::new();
+    builder.set_slop(slop);
+    for (i, (term, position)) in terms.into_iter().enumerate() {
+        let position = if position_increments {
+            position
+        } else {
+            i as i32
+        };
+        builder.add(Term::new(field.to_string(), term), position)?;
+    }
+    Ok(Box::new(builder.build()?))
+}
+
+// The query of a word analyzed into a token graph. The parts of the graph no
+// term skips over are combined with the operator, as the terms of a word at
+// several positions, each one matching any of its paths.
+fn graph_query<C: Codec>(
+    field: &str,
+    analyzed: &Analyzed,
+    operator: Operator,
+    position_increments: bool,
+) -> Result<Box<dyn Query<C>>> {
+    let terms = &analyzed.terms;
+    let mut queries = Vec::new();
+    let mut start = 0;
+    let mut end = terms[0].1 + terms[0].2;
+    for i in 1..=terms.len() {
+        if i == terms.len() || terms[i].1 >= end {
+            queries.push(paths_query(
+                field,
+                &terms[start..i],
+                0,
+                position_increments,
+            )?);
+            start = i;
+        }
+        if i < terms.len() {
+            end = end.max(terms[i].1 + terms[i].2);
+        }
+    }
+    if queries.len() == 1 {
+        return Ok(queries.remove(0));
+    }
+    match operator {
+        Operator::Or => BooleanQuery::build(vec![], queries, vec![]),
+        Operator::And => BooleanQuery::build(queries, vec![], vec![]),
+    }
+}
+
+// matches any path of a token graph, a term or a phrase of its terms
+fn paths_query<C: Codec>(
+    field: &str,
+    terms: &[(Vec<u8>, i32, i32)],
+    slop: i32,
+    position_increments: bool,
+) -> Result<Box<dyn Query<C>>> {
+    let mut paths = Vec::new();
+    add_paths(terms, terms[0].1, terms[0].1, &mut vec![], &mut paths)?;
+    let mut queries: Vec<Box<dyn Query<C>>> = Vec::with_capacity(paths.len());
+    for mut path in paths {
+        if path.len() == 1 {
+            queries.push(term_query(field, path.remove(0).0));
+        } else {
+            queries.push(phrase(field, path, slop, position_increments)?);
+        }
+    }
+    if queries.len() == 1 {
+        return Ok(queries.remove(0));
+    }
+    BooleanQuery::build(vec![], queries, vec![])
+}
+
+// adds the paths from `node` to the end of the graph to `paths`, after the
+// terms of `path`, the node being at `position` along the path. The nodes no
+// term leaves are holes, e.g. of stop words, skipped to the next one.
+fn add_paths(
+    terms: &[(Vec<u8>, i32, i32)],
+    node: i32,
+    position: i32,
+    path: &mut Vec<(Vec<u8>, i32)>,
+    paths: &mut Vec<Vec<(Vec<u8>, i32)>>,
+) -> Result<()> {
+    let next = match terms.iter().map(|t| t.1).find(|&p| p >= node) {
+        Some(next) => next,
+        None => {
+            if paths.len() == MAX_GRAPH_PATHS {
+                bail!(IllegalArgument(format!(
+                    "the token graph has more than {} paths",
+                    MAX_GRAPH_PATHS
+                )));
+            }
+            paths.push(path.clone());
+            return Ok(());
+        }
+    };
+    let position = position + (next - node);
+    for (term, _, length) in terms.iter().filter(|t| t.1 == next) {
+        path.push((term.clone(), position));
+        add_paths(terms, next + length, position + 1, path, paths)?;
+        path.pop();
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::analysis::standard_tokenizer::StandardTokenizer;
+    use core::analysis::{CustomAnalyzer, LowerCaseFilter, StandardAnalyzer};
+    use core::analysis::{SynonymGraphFilter, SynonymMap};
+    use core::codec::tests::TestCodec;
+    use core::doc::{Store, TextField};
+    use core::index::index_writer_config::IndexWriterConfig;
+    use core::index::tests::{open_test_reader_with, TestReader};
+    use core::search::collector::TopDocsCollector;
+    use core::search::phrase_query::PhraseQuery;
+    use core::search::searcher::{DefaultIndexSearcher, DefaultSimilarityProducer, IndexSearcher};
+    use core::util::DocId;
+
+    type TestSearcher =
+        DefaultIndexSearcher<TestCodec, TestReader, Arc<TestReader>, DefaultSimilarityProducer>;
+
+    fn parser() -> QueryParser {
+        QueryParser::new("body", Arc::new(StandardAnalyzer::new()))
+    }
+
+    // the query in the classic syntax, with the required clauses of boolean
+    // queries first, then the optional and the prohibited ones
+    fn syntax(query: &dyn Query<TestCodec>) -> String {
+        let any = query.as_any();
+        if let Some(q) = any.downcast_ref::<BooleanQuery<TestCodec>>() {
+            let mut clauses = vec![];
+            for c in q.must_queries() {
+                clauses.push(format!("+{}", nested(c.as_ref())));
+            }
+            for c in q.should_queries() {
+                clauses.push(nested(c.as_ref()));
+            }
+            for c in q.must_not_queries() {
+                clauses.push(format!("-{}", nested(c.as_ref())));
+            }
+            clauses.join(" ")
+        } else if let Some(q) = any.downcast_ref::<BoostQuery<TestCodec>>() {
+            format!("{}^{}", nested(q.query()), q.boost())
+        } else if let Some(q) = any.downcast_ref::<TermQuery>() {
+            format!("{}:{}", q.term.field(), q.term.text().unwrap())
+        } else if let Some(q) = any.downcast_ref::<PhraseQuery>() {
+            let mut words = vec![];
+            for (term, &position) in q.terms().iter().zip(q.positions()) {
+                while (words.len() as i32) < position {
+                    words.push("?".to_string());
+                }
+                words.push(term.text().unwrap());
+            }
+            let slop = if q.slop() > 0 {
+                format!("~{}", q.slop())
+            } else {
+                String::new()
+            };
+            format!("{}:\"{}\"{}", q.terms()[0].field(), words.join(" "), slop)
+        } else if let Some(q) = any.downcast_ref::<PrefixQuery>() {
+            format!("{}:{}*", q.field(), String::from_utf8_lossy(q.prefix()))
+        } else if let Some(q) = any.downcast_ref::<WildcardQuery>() {
+            format!("{}:{}", q.field(), q.pattern())
+        } else if let Some(q) = any.downcast_ref::<FuzzyQuery>() {
+            format!(
+                "{}:{}~{}",
+                q.term().field(),
+                q.term().text().unwrap(),
+                q.max_edits()
+            )
+        } else if let Some(q) = any.downcast_ref::<TermRangeQuery>() {
+            let bound = |b: Option<&[u8]>| {
+                b.map(|b| String::from_utf8_lossy(b).into_owned())
+                    .unwrap_or_else(|| "*".to_string())
+            };
+            format!(
+                "{}:{}{} TO {}{}",
+                q.field(),
+                if q.include_lower() { "[" } else { "{" },
+                bound(q.lower()),
+                bound(q.upper()),
+                if q.include_upper() { "]" } else { "}" },
+            )
+        } else if any.downcast_ref::<MatchAllDocsQuery>().is_some() {
+            "*:*".to_string()
+        } else {
+            panic!("unexpected query {}", query)
+        }
+    }
+
+    fn nested(query: &dyn Query<TestCodec>) -> String {
+        if query.as_any().is::<BooleanQuery<TestCodec>>() {
+            format!("({})", syntax(query))
+        } else {
+            syntax(query)
+        }
+    }
+
+    fn check(parser: &QueryParser, cases: &[(&str, &str)]) {
+        for &(query, expected) in cases {
+            match parser.parse::<TestCodec>(query) {
+                Ok(q) => assert_eq!(syntax(q.as_ref()), expected, "parsing {}", query),
+                Err(e) => panic!("parsing {}: {}", query, e),
+            }
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        check(
+            &parser(),
+            &[
+                ("fox", "body:fox"),
+                ("Fox", "body:fox"),
+                ("title:fox", "title:fox"),
+                ("quick fox", "body:quick body:fox"),
+                ("the fox", "body:fox"),
+                ("+quick fox", "+body:quick body:fox"),
+                ("+quick -fox", "+body:quick -body:fox"),
+                ("quick AND fox", "+body:quick +body:fox"),
+                ("quick && fox", "+body:quick +body:fox"),
+                ("quick OR fox", "body:quick body:fox"),
+                ("quick || fox", "body:quick body:fox"),
+                ("quick NOT fox", "body:quick -body:fox"),
+                ("quick !fox", "body:quick -body:fox"),
+                ("quick AND brown OR fox", "+body:quick +body:brown body:fox"),
+                ("-fox", "-body:fox"),
+                ("\"quick fox\"", "body:\"quick fox\""),
+                ("\"quick fox\"~2", "body:\"quick fox\"~2"),
+                ("\"the quick fox\"", "body:\"quick fox\""),
+                ("\"quick the fox\"", "body:\"quick ? fox\""),
+                ("\"Fox\"", "body:fox"),
+                ("\"Fox\"^2", "body:fox^2"),
+                ("\"quick fox\"~1^2", "body:\"quick fox\"~1^2"),
+                ("fox^2", "body:fox^2"),
+                ("fox^0.5", "body:fox^0.5"),
+                ("title:(quick fox)", "title:quick title:fox"),
+                ("title:(quick +fox)^3", "(+title:fox title:quick)^3"),
+                (
+                    "(quick fox) AND title:brown",
+                    "+(body:quick body:fox) +title:brown",
+                ),
+                (
+                    "title:(quick OR \"brown fox\"~1) -body:lazy",
+                    "(title:quick title:\"brown fox\"~1) -body:lazy",
+                ),
+                ("title:(quick body:fox)", "title:quick body:fox"),
+                ("qui*", "body:qui*"),
+                ("qu?ck", "body:qu?ck"),
+                ("q*k*", "body:q*k*"),
+                ("*:*", "*:*"),
+                ("title:*", "title:*"),
+                ("fox~", "body:fox~2"),
+                ("fox~1", "body:fox~1"),
+                ("fox~0.5", "body:fox~1"),
+                ("fox^2~1", "body:fox~1^2"),
+                ("year:[2000 TO 2020]", "year:[2000 TO 2020]"),
+                ("year:{2000 TO 2020]", "year:{2000 TO 2020]"),
+                ("year:[* TO 2020}", "year:[* TO 2020}"),
+                ("year:[2000 TO 2020]^2", "year:[2000 TO 2020]^2"),
+                ("name:[\"a b\" TO c]", "name:[a b TO c]"),
+                ("fox\\*", "body:fox"),
+                ("fox\\*bar*", "body:fox*bar*"),
+                ("f\\?o?", "body:f\\?o?"),
+                ("title\\:x:fox", "title:x:fox"),
+                ("\"say \\\"fox\\\"\"", "body:\"say fox\""),
+                ("wi-fi", "body:wi body:fi"),
+                (
+                    "title:(quick +brown) -fox \"exact phrase\"~2 AND year:[2000 TO 2020]",
+                    "+body:\"exact phrase\"~2 +year:[2000 TO 2020] (+title:brown title:quick) \
+                     -body:fox",
+                ),
+            ],
+        );
+    }
+
+    #[test]
+    fn test_parse_with_options() {
+        check(
+            &parser().with_default_operator(Operator::And),
+            &[
+                ("quick fox", "+body:quick +body:fox"),
+                ("quick OR fox", "body:quick body:fox"),
+                ("quick fox OR brown", "+body:quick body:fox body:brown"),
+                ("quick -fox", "+body:quick -body:fox"),
+                ("wi-fi", "+body:wi +body:fi"),
+            ],
+        );
+        check(
+            &parser().with_enable_position_increments(false),
+            &[
+                ("\"quick the fox\"", "body:\"quick fox\""),
+                ("\"quick the fox\"~1", "body:\"quick fox\"~1"),
+                ("quick the fox", "body:quick body:fox"),
+            ],
+        );
+        check(
+            &parser().with_auto_generate_phrase_queries(true),
+            &[
+                ("wi-fi", "body:\"wi fi\""),
+                ("wi-fi fox", "body:\"wi fi\" body:fox"),
+            ],
+        );
+
+        // only stop words
+        assert!(parser().parse::<TestCodec>("the").is_err());
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let clause = &["term", "phrase", "\"(\"", "\"[\"", "\"{\""][..];
+        let cases: &[(&str, usize, &str, &[&str])] = &[
+            ("", 0, "end of query", clause),
+            ("   ", 3, "end of query", clause),
+            ("(", 1, "end of query", clause),
+            ("()", 1, "\")\"", clause),
+            ("(fox", 4, "end of query", &["\")\""]),
+            ("title:(fox", 10, "end of query", &["\")\""]),
+            ("fox)", 3, "\")\"", clause),
+            (
+                "AND fox",
+                0,
+                "\"AND\"",
+                &["\"+\"", "\"-\"", "term", "phrase", "\"(\""],
+            ),
+            ("fox AND", 7, "end of query", clause),
+            ("fox AND OR brown", 8, "\"OR\"", clause),
+            ("fox +", 5, "end of query", clause),
+            ("title:", 6, "end of query", clause),
+            (":fox", 0, "\":\"", clause),
+            ("fox:^2", 4, "\"^2\"", clause),
+            ("fox^2^3", 5, "\"^3\"", clause),
+            ("fox^", 4, "end of query", &["number"]),
+            ("fox^x", 4, "\"x\"", &["number"]),
+            ("fox^1.2.3", 4, "\"1.2.3\"", &["number"]),
+            ("\"quick fox", 10, "end of query", &["closing quote"]),
+            ("fox\\", 4, "end of query", &["escaped char"]),
+            ("year:[2000 2020]", 11, "\"2020\"", &["\"TO\""]),
+            (
+                "year:[2000 TO 2020",
+                18,
+                "end of query",
+                &["\"]\"", "\"}\""],
+            ),
+            ("year:[2000 TO]", 13, "\"]\"", &["term", "phrase", "\"*\""]),
+            ("{a TO b", 7, "end of query", &["\"]\"", "\"}\""]),
+            ("[a TO b]~1", 8, "\"~1\"", clause),
+            ("东京 AND", 6, "end of query", clause),
+        ];
+        for &(query, position, found, expected) in cases {
+            match parser().parse::<TestCodec>(query) {
+                Err(Error(ErrorKind::Search(search::ErrorKind::QueryParse(p, f, e)), _)) => {
+                    assert_eq!((p, f.as_str()), (position, found), "parsing {}", query);
+                    assert_eq!(e, expected, "parsing {}", query);
+                }
+                Err(e) => panic!("parsing {}: unexpected error {}", query, e),
+                Ok(q) => panic!("parsing {}: unexpected query {}", query, q),
+            }
+        }
+    }
+
+    #[test]
+    fn test_search() {
+        let searcher = search_docs(
+            Arc::new(StandardAnalyzer::new()),
+            &[
+                "The quick brown fox",
+                "A lazy brown dog",
+                "Quick, lazy fox!",
+            ],
+        );
+
+        let parser = parser();
+        let count = |query: &str| {
+            let query = parser.parse::<TestCodec>(query).unwrap();
+            searcher.count(query.as_ref()).unwrap()
+        };
+        assert_eq!(count("fox"), 2);
+        assert_eq!(count("fox -lazy"), 1);
+        assert_eq!(count("-fox"), 0);
+        assert_eq!(count("brown AND NOT dog"), 1);
+        assert_eq!(count("+quick +fox"), 2);
+        assert_eq!(count("dog OR (quick -brown)"), 2);
+        assert_eq!(count("\"brown fox\""), 1);
+        assert_eq!(count("\"quick fox\"~1"), 2);
+        assert_eq!(count("qui*"), 2);
+        assert_eq!(count("br?wn"), 2);
+        assert_eq!(count("lazzy~1"), 2);
+        assert_eq!(count("body:[a TO c]"), 2);
+        assert_eq!(count("*:*"), 3);
+        assert_eq!(count("*:* -brown"), 1);
+    }
+
+    #[test]
+    fn test_position_increments() {
+        // the stop words are removed, their positions are kept
+        let searcher = search_docs(
+            Arc::new(StandardAnalyzer::new()),
+            &[
+                "the quick brown fox",
+                "the quick fox",
+                "quick the fox",
+                "quick brown red fox",
+            ],
+        );
+        let docs = |parser: &QueryParser, query: &str| {
+            let query = parser.parse::<TestCodec>(query).unwrap();
+            let mut collector = TopDocsCollector::new(10);
+            searcher.search(query.as_ref(), &mut collector).unwrap();
+            let mut docs: Vec<DocId> = collector
+                .top_docs()
+                .score_docs()
+                .iter()
+                .map(|d| d.doc_id())
+                .collect();
+            docs.sort();
+            docs
+        };
+
+        // "quick ? fox", any word may fill the gap of "the"
+        let parser = parser();
+        assert!(parser.enable_position_increments());
+        assert_eq!(docs(&parser, "\"quick the fox\""), vec![0, 2]);
+        assert_eq!(docs(&parser, "\"quick the fox\"~1"), vec![0, 1, 2, 3]);
+        assert_eq!(docs(&parser, "\"the quick fox\""), vec![1]);
+
+        // "quick fox", the gap is collapsed
+        let parser = parser.with_enable_position_increments(false);
+        assert!(!parser.enable_position_increments());
+        assert_eq!(docs(&parser, "\"quick the fox\""), vec![1]);
+        assert_eq!(docs(&parser, "\"quick the fox\"~1"), vec![0, 1, 2]);
+        assert_eq!(docs(&parser, "\"the quick fox\""), vec![1]);
+    }
+
+    fn synonym_analyzer() -> Arc<dyn Analyzer> {
+        let mut builder = SynonymMap::builder();
+        builder.add("ny", "new york", true).unwrap();
+        builder.add("fast", "quick", true).unwrap();
+        let map = Arc::new(builder.build().unwrap());
+        Arc::new(
+            CustomAnalyzer::builder()
+                .tokenizer(|text| Box::new(StandardTokenizer::new(text)))
+                .filter(|input| Box::new(LowerCaseFilter::new(input)))
+                .filter(move |input| Box::new(SynonymGraphFilter::new(input, Arc::clone(&map))))
+                .build()
+                .unwrap(),
+        )
+    }
+
+    fn search_docs(analyzer: Arc<dyn Analyzer>, bodies: &[&str]) -> TestSearcher {
+        let mut conf = IndexWriterConfig::default();
+        conf.analyzer = analyzer;
+        let docs = bodies
+            .iter()
+            .map(|body| vec![TextField::new("body", body, Store::No)])
+            .collect();
+        DefaultIndexSearcher::new(open_test_reader_with(conf, vec![docs]))
+    }
+
+    fn matching_docs(searcher: &TestSearcher, parser: &QueryParser, query: &str) -> Vec<DocId> {
+        let query = parser.parse::<TestCodec>(query).unwrap();
+        let mut collector = TopDocsCollector::new(10);
+        searcher.search(query.as_ref(), &mut collector).unwrap();
+        let mut docs: Vec<DocId> = collector
+            .top_docs()
+            .score_docs()
+            .iter()
+            .map(|d| d.doc_id())
+            .collect();
+        docs.sort();
+        docs
+    }
+
+    #[test]
+    fn test_synonym_graph() {
+        let parser = QueryParser::new("body", synonym_analyzer());
+        check(
+            &parser,
+            &[
+                ("fast", "body:quick body:fast"),
+                ("ny", "body:\"new york\" body:ny"),
+                ("ny pizza", "(body:\"new york\" body:ny) body:pizza"),
+                ("\"ny pizza\"", "body:\"new york pizza\" body:\"ny pizza\""),
+                ("\"fast car\"~1", "body:\"quick car\"~1 body:\"fast car\"~1"),
+            ],
+        );
+
+        // the synonyms are only expanded at query time
+        let searcher = search_docs(
+            Arc::new(StandardAnalyzer::new()),
+            &[
+                "pizza in new york",
+                "the best ny pizza",
+                "york is not new",
+                "new york pizza",
+                "a fast car",
+            ],
+        );
+        assert_eq!(matching_docs(&searcher, &parser, "ny"), vec![0, 1, 3]);
+        assert_eq!(
+            matching_docs(&searcher, &parser, "\"ny pizza\""),
+            vec![1, 3]
+        );
+        assert_eq!(matching_docs(&searcher, &parser, "\"fast car\""), vec![4]);
+
+        // at index time, the graph is flattened
+        let searcher = search_docs(synonym_analyzer(), &["NY pizza", "new york bagels"]);
+        let parser = parser();
+        assert_eq!(matching_docs(&searcher, &parser, "ny"), vec![0]);
+        assert_eq!(
+            matching_docs(&searcher, &parser, "\"new york\""),
+            vec![0, 1]
+        );
+        assert_eq!(
+            matching_docs(&searcher, &parser, "\"new york pizza\""),
+            vec![0]
+        );
+    }
+}
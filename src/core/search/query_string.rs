@@ -15,8 +15,10 @@ use error::{ErrorKind::IllegalArgument, Result};
 use std::option::Option::{None, Some};
 use std::result::Result::Ok;
 use std::str::Chars;
+use std::sync::Arc;
 use std::vec::Vec;
 
+use core::analysis::Analyzer;
 use core::codec::Codec;
 use core::index::Term;
 use core::search::boolean_query::BooleanQuery;
@@ -32,6 +34,7 @@ pub struct QueryStringQueryBuilder {
     minimum_should_match: i32,
     #[allow(dead_code)]
     boost: f32,
+    analyzer: Option<Arc<dyn Analyzer>>,
 }
 
 impl QueryStringQueryBuilder {
@@ -46,9 +49,21 @@ impl QueryStringQueryBuilder {
             fields,
             minimum_should_match,
             boost,
+            analyzer: None,
         }
     }
 
+    /// Analyzes the words and the phrases of the query string into the terms
+    /// of each field, as the field values were analyzed when indexed.
+    ///
+    /// A word analyzed into several terms matches any of them, a quoted phrase
+    /// matches its terms at the positions given by the analyzer. The words
+    /// analyzed into no term, like stop words, are ignored.
+    pub fn with_analyzer(mut self, analyzer: Arc<dyn Analyzer>) -> Self {
+        self.analyzer = Some(analyzer);
+        self
+    }
+
     pub fn build<C: Codec>(&self) -> Result<Box<dyn Query<C>>> {
         match self.parse_query(&mut self.query_string.chars(), None) {
             Ok(Some(q)) => Ok(q),
@@ -101,15 +116,16 @@ impl QueryStringQueryBuilder {
 
                     if !term_chars.is_empty() {
                         let term: String = term_chars.iter().cloned().collect();
-                        let query = self.build_field_query(term);
+                        let query = self.build_field_query(term, true);
                         match query {
-                            Ok(q) => {
+                            Ok(Some(q)) => {
                                 if is_option {
                                     shoulds.push(q);
                                 } else {
                                     musts.push(q);
                                 }
                             }
+                            Ok(None) => {}
                             Err(e) => {
                                 return Err(e);
                             }
@@ -143,15 +159,16 @@ impl QueryStringQueryBuilder {
                     }
                     if !term_chars.is_empty() {
                         let term: String = term_chars.iter().cloned().collect();
-                        let query_res = self.build_field_query(term);
+                        let query_res = self.build_field_query(term, false);
                         match query_res {
-                            Ok(q) => {
+                            Ok(Some(q)) => {
                                 if is_option {
                                     shoulds.push(q);
                                 } else {
                                     musts.push(q);
                                 }
                             }
+                            Ok(None) => {}
                             Err(e) => {
                                 return Err(e);
                             }
@@ -164,6 +181,9 @@ impl QueryStringQueryBuilder {
                 }
             }
         }
+        if musts.is_empty() && shoulds.is_empty() {
+            return Ok(None);
+        }
         let query: Box<dyn Query<C>> = if musts.len() + shoulds.len() == 1 {
             if !musts.is_empty() {
                 musts.remove(0)
@@ -176,26 +196,87 @@ impl QueryStringQueryBuilder {
         Ok(Some(query))
     }
 
-    fn term_query<C: Codec>(&self, term: String, field: String, boost: f32) -> Box<dyn Query<C>> {
-        Box::new(TermQuery::new(Term::new(field, term.into()), boost, None))
+    fn term_query<C: Codec>(&self, term: Vec<u8>, field: String, boost: f32) -> Box<dyn Query<C>> {
+        Box::new(TermQuery::new(Term::new(field, term), boost, None))
+    }
+
+    // the terms of `text` in `field` with their positions, `text` itself
+    // without analyzer
+    fn analyze(&self, field: &str, text: &str) -> Result<Vec<(Vec<u8>, i32)>> {
+        let analyzer = match self.analyzer {
+            Some(ref analyzer) => analyzer,
+            None => {
+                return Ok(vec![(text.as_bytes().to_vec(), 0)]);
+            }
+        };
+        let mut stream = analyzer.token_stream(field, text)?;
+        stream.reset()?;
+        let mut terms = Vec::new();
+        let mut position = -1;
+        while stream.increment_token()? {
+            position += stream.position_attribute_mut().get_position_increment() as i32;
+            let term = stream.term_bytes_attribute().get_bytes_ref();
+            terms.push((term.bytes().to_vec(), position));
+        }
+        stream.end()?;
+        Ok(terms)
+    }
+
+    // the query matching the analyzed terms of `text` in `field`
+    fn analyzed_query<C: Codec>(
+        &self,
+        text: &str,
+        field: &str,
+        boost: f32,
+        phrase: bool,
+    ) -> Result<Option<Box<dyn Query<C>>>> {
+        let mut terms = self.analyze(field, text)?;
+        let query = match terms.len() {
+            0 => None,
+            1 => Some(self.term_query(terms.remove(0).0, field.to_string(), boost)),
+            _ if phrase => {
+                let (terms, positions): (Vec<_>, Vec<_>) = terms
+                    .into_iter()
+                    .map(|(term, position)| (Term::new(field.to_string(), term), position))
+                    .unzip();
+                let query = PhraseQuery::new(terms, positions, 0, None, None)?;
+                Some(BoostQuery::build(Box::new(query), boost))
+            }
+            _ => {
+                let shoulds = terms
+                    .into_iter()
+                    .map(|(term, _)| self.term_query(term, field.to_string(), boost))
+                    .collect();
+                Some(BooleanQuery::build(Vec::new(), shoulds, vec![])?)
+            }
+        };
+        Ok(query)
     }
 
-    fn build_field_query<C: Codec>(&self, term_boost: String) -> Result<Box<dyn Query<C>>> {
+    fn build_field_query<C: Codec>(
+        &self,
+        term_boost: String,
+        quoted: bool,
+    ) -> Result<Option<Box<dyn Query<C>>>> {
         let mut queries = if term_boost.find('~').is_some() {
             self.field_phrase_query(&term_boost)?
         } else {
-            self.field_term_query(term_boost)?
+            self.field_term_query(term_boost, quoted)?
         };
 
-        let res = if queries.len() == 1 {
-            queries.remove(0)
-        } else {
-            BooleanQuery::build(Vec::new(), queries, vec![])?
+        let res = match queries.len() {
+            0 => None,
+            1 => Some(queries.remove(0)),
+            _ => Some(BooleanQuery::build(Vec::new(), queries, vec![])?),
         };
         Ok(res)
     }
 
-    fn field_term_query<C: Codec>(&self, query: String) -> Result<Vec<Box<dyn Query<C>>>> {
+    fn field_term_query<C: Codec>(
+        &self,
+        query: String,
+        quoted: bool,
+    ) -> Result<Vec<Box<dyn Query<C>>>> {
         let (term, boost) = if let Some(i) = query.find('^') {
             let (t, b) = query.split_at(i as usize);
             let boost_str: String = b.chars().skip(1).collect();
@@ -211,7 +292,9 @@ impl QueryStringQueryBuilder {
         };
         let mut queries = Vec::new();
         for fb in &self.fields {
-            queries.push(self.term_query(term.clone(), fb.0.clone(), fb.1 * boost));
+            if let Some(query) = self.analyzed_query(&term, &fb.0, fb.1 * boost, quoted)? {
+                queries.push(query);
+            }
         }
         Ok(queries)
     }
@@ -221,13 +304,31 @@ impl QueryStringQueryBuilder {
             let (t, s) = query.split_at(idx);
             let slop_str: String = s.chars().skip(1).collect();
             let slop = slop_str.parse::<i32>()?;
+            let mut queries = Vec::with_capacity(self.fields.len());
+            if self.analyzer.is_some() {
+                for fb in &self.fields {
+                    let terms = self.analyze(&fb.0, t)?;
+                    if terms.len() < 2 {
+                        queries.extend(self.analyzed_query(t, &fb.0, fb.1, false)?);
+                        continue;
+                    }
+                    let (terms, positions): (Vec<_>, Vec<_>) = terms
+                        .into_iter()
+                        .map(|(term, position)| (Term::new(fb.0.clone(), term), position))
+                        .unzip();
+                    queries.push(BoostQuery::build(
+                        Box::new(PhraseQuery::new(terms, positions, slop, None, None)?),
+                        fb.1,
+                    ));
+                }
+                return Ok(queries);
+            }
             let term_strs: Vec<&str> = t.split_whitespace().collect();
             if term_strs.len() < 2 {
                 bail!(IllegalArgument(
                     "phrase query terms size must not small than 2".into()
                 ));
             }
-            let mut queries = Vec::with_capacity(self.fields.len());
             for fb in &self.fields {
                 let terms: Vec<Term> = term_strs
                     .iter()
@@ -252,7 +353,18 @@ impl QueryStringQueryBuilder {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use core::analysis::{KeywordAnalyzer, PerFieldAnalyzerWrapper};
+    use core::analysis::{StandardAnalyzer, WhitespaceAnalyzer};
     use core::codec::tests::TestCodec;
+    use core::codec::CodecEnum;
+    use core::doc::{Store, TextField};
+    use core::index::index_writer_config::IndexWriterConfig;
+    use core::index::tests::open_test_reader_with;
+    use core::search::collector::TopDocsCollector;
+    use core::search::searcher::{DefaultIndexSearcher, IndexSearcher};
+    use core::util::DocId;
+
+    use std::collections::HashMap;
 
     #[test]
     fn test_query_string_query() {
@@ -405,4 +517,114 @@ mod tests {
         );
     }
 
+    // a title per doc, analyzed by StandardAnalyzer
+    fn search_titles(query_string: &str) -> Result<Vec<DocId>> {
+        let mut conf = IndexWriterConfig::default();
+        conf.analyzer = Arc::new(StandardAnalyzer::new());
+        let docs = ["The quick brown fox", "Quick foxes", "A FOX, quick!"]
+            .iter()
+            .map(|title| vec![TextField::new("title", title, Store::No)])
+            .collect();
+        let reader = open_test_reader_with(conf, vec![docs]);
+
+        let query: Box<dyn Query<CodecEnum>> = QueryStringQueryBuilder::new(
+            query_string.to_string(),
+            vec![("title".to_string(), 1.0)],
+            1,
+            1.0,
+        )
+        .with_analyzer(Arc::new(StandardAnalyzer::new()))
+        .build()?;
+        let searcher = DefaultIndexSearcher::new(reader);
+        let mut collector = TopDocsCollector::new(10);
+        searcher.search(query.as_ref(), &mut collector)?;
+        let mut docs: Vec<DocId> = collector
+            .top_docs()
+            .score_docs()
+            .iter()
+            .map(|d| d.doc_id())
+            .collect();
+        docs.sort();
+        Ok(docs)
+    }
+
+    #[test]
+    fn test_analyzed_query_string() {
+        assert_eq!(search_titles("Quick FOX").unwrap(), vec![0, 1, 2]);
+        assert_eq!(search_titles("+Quick +FOX").unwrap(), vec![0, 2]);
+        assert_eq!(search_titles("+the +FOX").unwrap(), vec![0, 2]);
+        assert_eq!(search_titles("\"the QUICK Brown\"").unwrap(), vec![0]);
+        assert!(search_titles("\"quick fox\"").unwrap().is_empty());
+        assert_eq!(search_titles("\"quick fox\"~1").unwrap(), vec![0]);
+        assert_eq!(search_titles("\"Fox quick\"").unwrap(), vec![2]);
+        // only stop words
+        assert!(search_titles("the a").is_err());
+    }
+
+    // "id" is not tokenized and "tags" is multi-valued
+    fn per_field_analyzer() -> Arc<dyn Analyzer> {
+        let mut fields: HashMap<String, Arc<dyn Analyzer>> = HashMap::new();
+        fields.insert("id".to_string(), Arc::new(KeywordAnalyzer::new()));
+        fields.insert("tags".to_string(), Arc::new(WhitespaceAnalyzer));
+        let mut analyzer =
+            PerFieldAnalyzerWrapper::with_analyzers(Arc::new(StandardAnalyzer::new()), fields);
+        analyzer.set_position_increment_gap(100);
+        Arc::new(analyzer)
+    }
+
+    fn search_tags(query_string: &str, field: &str) -> Vec<DocId> {
+        let analyzer = per_field_analyzer();
+        let mut conf = IndexWriterConfig::default();
+        conf.analyzer = Arc::clone(&analyzer);
+        let docs = vec![
+            ("Item A-1", vec!["rust search", "fast index"]),
+            ("item a-2", vec!["search fast"]),
+        ];
+        let docs = docs
+            .into_iter()
+            .map(|(id, tags)| {
+                let mut doc = vec![TextField::new("id", id, Store::No)];
+                for tag in tags {
+                    doc.push(TextField::new("tags", tag, Store::No));
+                }
+                doc
+            })
+            .collect();
+        let reader = open_test_reader_with(conf, vec![docs]);
+
+        let query: Box<dyn Query<CodecEnum>> = QueryStringQueryBuilder::new(
+            query_string.to_string(),
+            vec![(field.to_string(), 1.0)],
+            1,
+            1.0,
+        )
+        .with_analyzer(analyzer)
+        .build()
+        .unwrap();
+        let searcher = DefaultIndexSearcher::new(reader);
+        let mut collector = TopDocsCollector::new(10);
+        searcher.search(query.as_ref(), &mut collector).unwrap();
+        let mut docs: Vec<DocId> = collector
+            .top_docs()
+            .score_docs()
+            .iter()
+            .map(|d| d.doc_id())
+            .collect();
+        docs.sort();
+        docs
+    }
+
+    #[test]
+    fn test_per_field_analyzer() {
+        // the phrases don't match across the values of a field
+        assert_eq!(search_tags("\"search fast\"", "tags"), vec![1]);
+        assert_eq!(search_tags("\"search fast\"~10", "tags"), vec![1]);
+        assert_eq!(search_tags("\"rust search\"", "tags"), vec![0]);
+        assert_eq!(search_tags("\"fast index\"", "tags"), vec![0]);
+        assert_eq!(search_tags("search", "tags"), vec![0, 1]);
+
+        // the whole id is a single term
+        assert_eq!(search_tags("\"Item A-1\"", "id"), vec![0]);
+        assert!(search_tags("\"item a-1\"", "id").is_empty());
+    }
 }
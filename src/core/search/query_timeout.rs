@@ -0,0 +1,218 @@
+// Copyright 2019 Zhizhesihai (Beijing) Technology Limited.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use error::ErrorKind::Cancelled;
+use error::Result;
+
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// How many terms, points or docs are handled between two checks of a
+/// `QueryTimeout` by default.
+pub const DEFAULT_CHECK_INTERVAL: usize = 16;
+
+/// A count of elapsed ticks, e.g. milliseconds, only ever growing.
+pub trait Counter: Send + Sync {
+    fn get(&self) -> u64;
+}
+
+/// Counts the milliseconds elapsed since its creation.
+pub struct MillisCounter {
+    start: Instant,
+}
+
+impl Default for MillisCounter {
+    fn default() -> MillisCounter {
+        MillisCounter {
+            start: Instant::now(),
+        }
+    }
+}
+
+impl Counter for MillisCounter {
+    fn get(&self) -> u64 {
+        self.start.elapsed().as_millis() as u64
+    }
+}
+
+/// A counter only moving when told to, e.g. by a timer thread updating it
+/// instead of every search reading the clock.
+#[derive(Default)]
+pub struct ManualCounter {
+    ticks: AtomicU64,
+}
+
+impl ManualCounter {
+    pub fn add_and_get(&self, delta: u64) -> u64 {
+        self.ticks.fetch_add(delta, Ordering::AcqRel) + delta
+    }
+}
+
+impl Counter for ManualCounter {
+    fn get(&self) -> u64 {
+        self.ticks.load(Ordering::Acquire)
+    }
+}
+
+struct Deadline {
+    counter: Arc<dyn Counter>,
+    start: u64,
+    allowed: u64,
+}
+
+/// Cancels a running search, either explicitly with `cancel` or once the
+/// allowed ticks of a counter have elapsed.
+///
+/// The clones share the same state, so the searcher, the collectors and the
+/// weights of a search all see the cancellation. The collectors check it
+/// between the docs, see `TimeLimitingCollector`, and the queries enumerating
+/// many terms or points while building their scorers check it as they go,
+/// so that a search is also cut short before collecting anything.
+#[derive(Clone)]
+pub struct QueryTimeout {
+    cancelled: Arc<AtomicBool>,
+    deadline: Option<Arc<Deadline>>,
+}
+
+impl Default for QueryTimeout {
+    fn default() -> QueryTimeout {
+        QueryTimeout {
+            cancelled: Arc::new(AtomicBool::new(false)),
+            deadline: None,
+        }
+    }
+}
+
+impl QueryTimeout {
+    /// Only cancelled by `cancel`.
+    pub fn new() -> QueryTimeout {
+        Self::default()
+    }
+
+    /// Cancelled once `timeout` has elapsed from now, at the millisecond.
+    pub fn with_timeout(timeout: Duration) -> QueryTimeout {
+        Self::with_counter(
+            Arc::new(MillisCounter::default()),
+            timeout.as_millis() as u64,
+        )
+    }
+
+    /// Cancelled once `counter` has moved by `allowed` ticks from now.
+    pub fn with_counter(counter: Arc<dyn Counter>, allowed: u64) -> QueryTimeout {
+        let start = counter.get();
+        QueryTimeout {
+            cancelled: Arc::new(AtomicBool::new(false)),
+            deadline: Some(Arc::new(Deadline {
+                counter,
+                start,
+                allowed,
+            })),
+        }
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Release);
+    }
+
+    /// The ticks allowed to the search, `None` without deadline.
+    pub fn allowed(&self) -> Option<u64> {
+        self.deadline.as_ref().map(|deadline| deadline.allowed)
+    }
+
+    /// The ticks elapsed since the creation, 0 without deadline.
+    pub fn elapsed(&self) -> u64 {
+        match self.deadline {
+            Some(ref deadline) => deadline.counter.get().saturating_sub(deadline.start),
+            None => 0,
+        }
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        if self.cancelled.load(Ordering::Acquire) {
+            return true;
+        }
+        if let Some(ref deadline) = self.deadline {
+            if self.elapsed() >= deadline.allowed {
+                self.cancel();
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Fails with `Cancelled` once the search is cancelled.
+    pub fn check(&self) -> Result<()> {
+        if self.is_cancelled() {
+            match self.allowed() {
+                Some(allowed) => bail!(Cancelled(format!(
+                    "search timed out, {} ticks elapsed of {} allowed",
+                    self.elapsed(),
+                    allowed
+                ))),
+                None => bail!(Cancelled("search cancelled".to_string())),
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for QueryTimeout {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "QueryTimeout(cancelled: {}, elapsed: {}, allowed: {:?})",
+            self.cancelled.load(Ordering::Acquire),
+            self.elapsed(),
+            self.allowed()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use error::{Error, ErrorKind};
+
+    #[test]
+    fn test_query_timeout() {
+        let counter = Arc::new(ManualCounter::default());
+        counter.add_and_get(5);
+        let timeout = QueryTimeout::with_counter(Arc::clone(&counter) as Arc<dyn Counter>, 10);
+        assert_eq!(timeout.allowed(), Some(10));
+        assert!(!timeout.is_cancelled());
+
+        counter.add_and_get(9);
+        assert_eq!(timeout.elapsed(), 9);
+        assert!(timeout.check().is_ok());
+        // the clones share the cancellation
+        let clone = timeout.clone();
+        counter.add_and_get(1);
+        match clone.check() {
+            Err(Error(ErrorKind::Cancelled(_), _)) => {}
+            other => panic!("expected a cancellation, got {:?}", other),
+        }
+        assert!(timeout.is_cancelled());
+
+        let timeout = QueryTimeout::new();
+        assert_eq!(timeout.allowed(), None);
+        assert!(timeout.check().is_ok());
+        timeout.clone().cancel();
+        assert!(timeout.is_cancelled());
+        assert!(timeout.check().is_err());
+
+        assert!(QueryTimeout::with_timeout(Duration::from_millis(0)).is_cancelled());
+        assert!(!QueryTimeout::with_timeout(Duration::from_secs(60)).is_cancelled());
+    }
+}
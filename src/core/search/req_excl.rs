@@ -0,0 +1,171 @@
+// Copyright 2019 Zhizhesihai (Beijing) Technology Limited.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use core::search::{two_phase_next, DocIterator, Scorer, NO_MORE_DOCS};
+use core::util::DocId;
+use error::Result;
+
+/// A Scorer for queries with a required part and an excluded part, the docs
+/// matching the excluded part are skipped and the scores are those of the
+/// required part.
+///
+/// If the required part supports two phase iteration so does this scorer,
+/// its approximation is the one of the required part and the exclusion is
+/// checked before the required part verifies a doc.
+pub struct ReqExclScorer {
+    req_scorer: Box<dyn Scorer>,
+    excl_scorer: Box<dyn Scorer>,
+}
+
+impl ReqExclScorer {
+    pub fn new(req_scorer: Box<dyn Scorer>, excl_scorer: Box<dyn Scorer>) -> ReqExclScorer {
+        ReqExclScorer {
+            req_scorer,
+            excl_scorer,
+        }
+    }
+
+    fn is_excluded(&mut self, doc: DocId) -> Result<bool> {
+        let mut excl_doc = self.excl_scorer.doc_id();
+        if excl_doc < doc {
+            excl_doc = self.excl_scorer.advance(doc)?;
+        }
+        Ok(excl_doc == doc)
+    }
+
+    // moves the required scorer from `doc` to the first doc not excluded
+    fn to_non_excluded(&mut self, mut doc: DocId) -> Result<DocId> {
+        while doc != NO_MORE_DOCS && self.is_excluded(doc)? {
+            doc = self.req_scorer.next()?;
+        }
+        Ok(doc)
+    }
+}
+
+impl Scorer for ReqExclScorer {
+    fn score(&mut self) -> Result<f32> {
+        self.req_scorer.score()
+    }
+
+    fn support_two_phase(&self) -> bool {
+        self.req_scorer.support_two_phase()
+    }
+
+    fn set_min_competitive_score(&mut self, min_score: f32) -> Result<()> {
+        self.req_scorer.set_min_competitive_score(min_score)
+    }
+
+    fn max_score(&self) -> f32 {
+        self.req_scorer.max_score()
+    }
+}
+
+impl DocIterator for ReqExclScorer {
+    fn doc_id(&self) -> DocId {
+        self.req_scorer.doc_id()
+    }
+
+    fn next(&mut self) -> Result<DocId> {
+        if self.support_two_phase() {
+            self.approximate_next()?;
+            two_phase_next(self)
+        } else {
+            let doc = self.req_scorer.next()?;
+            self.to_non_excluded(doc)
+        }
+    }
+
+    fn advance(&mut self, target: DocId) -> Result<DocId> {
+        if self.support_two_phase() {
+            self.approximate_advance(target)?;
+            two_phase_next(self)
+        } else {
+            let doc = self.req_scorer.advance(target)?;
+            self.to_non_excluded(doc)
+        }
+    }
+
+    fn cost(&self) -> usize {
+        self.req_scorer.cost()
+    }
+
+    fn matches(&mut self) -> Result<bool> {
+        if !self.support_two_phase() {
+            return Ok(true);
+        }
+        let doc = self.req_scorer.doc_id();
+        Ok(!self.is_excluded(doc)? && self.req_scorer.matches()?)
+    }
+
+    fn match_cost(&self) -> f32 {
+        self.req_scorer.match_cost() + self.excl_scorer.match_cost()
+    }
+
+    fn approximate_next(&mut self) -> Result<DocId> {
+        self.req_scorer.approximate_next()
+    }
+
+    fn approximate_advance(&mut self, target: DocId) -> Result<DocId> {
+        self.req_scorer.approximate_advance(target)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::search::disjunction::*;
+    use core::search::tests::*;
+
+    #[test]
+    fn test_excluded_docs() {
+        let req: Box<dyn Scorer> = Box::new(create_mock_scorer(vec![1, 2, 3, 5, 8]));
+        let excl: Box<dyn Scorer> = Box::new(DisjunctionSumScorer::new(vec![
+            create_mock_scorer(vec![2, 4]),
+            create_mock_scorer(vec![3, 8]),
+        ]));
+        let mut scorer = ReqExclScorer::new(req, excl);
+
+        assert_eq!(scorer.doc_id(), -1);
+        assert_eq!(scorer.next().unwrap(), 1);
+        assert!((scorer.score().unwrap() - 1.0) < ::std::f32::EPSILON);
+        assert_eq!(scorer.next().unwrap(), 5);
+        assert!((scorer.score().unwrap() - 5.0) < ::std::f32::EPSILON);
+        assert_eq!(scorer.next().unwrap(), NO_MORE_DOCS);
+
+        let req: Box<dyn Scorer> = Box::new(create_mock_scorer(vec![1, 2, 3, 5, 8]));
+        let excl: Box<dyn Scorer> = Box::new(create_mock_scorer(vec![2, 3]));
+        let mut scorer = ReqExclScorer::new(req, excl);
+        assert_eq!(scorer.advance(2).unwrap(), 5);
+        assert_eq!(scorer.advance(6).unwrap(), 8);
+    }
+
+    #[test]
+    fn test_two_phase_required() {
+        let req: Box<dyn Scorer> = Box::new(create_mock_two_phase_scorer(
+            vec![1, 2, 3, 4, 5, 6],
+            vec![2, 6],
+        ));
+        let excl: Box<dyn Scorer> = Box::new(create_mock_scorer(vec![3, 4]));
+        let mut scorer = ReqExclScorer::new(req, excl);
+        assert!(scorer.support_two_phase());
+
+        assert_eq!(scorer.approximate_next().unwrap(), 1);
+        assert!(scorer.matches().unwrap());
+        assert_eq!(scorer.approximate_next().unwrap(), 2);
+        assert!(!scorer.matches().unwrap());
+        assert_eq!(scorer.approximate_advance(3).unwrap(), 3);
+        assert!(!scorer.matches().unwrap());
+        assert_eq!(scorer.next().unwrap(), 5);
+        assert_eq!(scorer.next().unwrap(), NO_MORE_DOCS);
+    }
+}
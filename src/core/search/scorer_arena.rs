@@ -0,0 +1,258 @@
+// Copyright 2019 Zhizhesihai (Beijing) Technology Limited.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use core::search::conjunction::ConjunctionScorer;
+use core::search::disjunction::DisjunctionSumScorer;
+use core::search::Scorer;
+
+use std::mem;
+use std::ops::{Deref, DerefMut};
+use std::slice;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// Free buffers kept by a pool for every element type, more are released.
+const MAX_FREE_BUFFERS: usize = 1024;
+
+/// The buffers of a `ScorerArena` for one element type, kept empty between uses.
+///
+/// Only pools of `Send` elements hand out buffers, so a pool can be shared by
+/// the threads of a searcher.
+pub struct VecPool<T> {
+    free: Mutex<Vec<Vec<T>>>,
+    allocated: AtomicUsize,
+    reused: AtomicUsize,
+}
+
+impl<T> Default for VecPool<T> {
+    fn default() -> Self {
+        VecPool {
+            free: Mutex::new(vec![]),
+            allocated: AtomicUsize::new(0),
+            reused: AtomicUsize::new(0),
+        }
+    }
+}
+
+impl<T: Send> VecPool<T> {
+    /// Takes a free buffer of the pool, or allocates one if there is none.
+    pub fn take(pool: &Arc<VecPool<T>>, capacity: usize) -> PooledVec<T> {
+        let free = pool.free.lock().unwrap().pop();
+        let vec = match free {
+            Some(mut vec) => {
+                pool.reused.fetch_add(1, Ordering::Relaxed);
+                vec.reserve(capacity);
+                vec
+            }
+            None => {
+                pool.allocated.fetch_add(1, Ordering::Relaxed);
+                Vec::with_capacity(capacity)
+            }
+        };
+        PooledVec {
+            vec,
+            pool: Some(Arc::clone(pool)),
+        }
+    }
+}
+
+impl<T> VecPool<T> {
+    fn give_back(&self, mut vec: Vec<T>) {
+        // drop the elements outside of the lock
+        vec.clear();
+        let mut free = self.free.lock().unwrap();
+        if free.len() < MAX_FREE_BUFFERS {
+            free.push(vec);
+        }
+    }
+
+    /// The buffers allocated because the pool had no free one.
+    pub fn allocated(&self) -> usize {
+        self.allocated.load(Ordering::Relaxed)
+    }
+
+    /// The buffers taken from the free ones of the pool.
+    pub fn reused(&self) -> usize {
+        self.reused.load(Ordering::Relaxed)
+    }
+}
+
+/// A `Vec` whose buffer goes back to its pool when it is dropped. Without a
+/// pool it is a plain `Vec`.
+pub struct PooledVec<T> {
+    vec: Vec<T>,
+    pool: Option<Arc<VecPool<T>>>,
+}
+
+impl<T> Default for PooledVec<T> {
+    fn default() -> Self {
+        Vec::new().into()
+    }
+}
+
+impl<T> From<Vec<T>> for PooledVec<T> {
+    fn from(vec: Vec<T>) -> Self {
+        PooledVec { vec, pool: None }
+    }
+}
+
+impl<T> Deref for PooledVec<T> {
+    type Target = Vec<T>;
+
+    fn deref(&self) -> &Vec<T> {
+        &self.vec
+    }
+}
+
+impl<T> DerefMut for PooledVec<T> {
+    fn deref_mut(&mut self) -> &mut Vec<T> {
+        &mut self.vec
+    }
+}
+
+impl<'a, T> IntoIterator for &'a PooledVec<T> {
+    type Item = &'a T;
+    type IntoIter = slice::Iter<'a, T>;
+
+    fn into_iter(self) -> slice::Iter<'a, T> {
+        self.vec.iter()
+    }
+}
+
+impl<'a, T> IntoIterator for &'a mut PooledVec<T> {
+    type Item = &'a mut T;
+    type IntoIter = slice::IterMut<'a, T>;
+
+    fn into_iter(self) -> slice::IterMut<'a, T> {
+        self.vec.iter_mut()
+    }
+}
+
+impl<T> Drop for PooledVec<T> {
+    fn drop(&mut self) {
+        if let Some(pool) = self.pool.take() {
+            pool.give_back(mem::replace(&mut self.vec, Vec::new()));
+        }
+    }
+}
+
+/// Recycles the clause lists of the boolean scorers between the leaves and the
+/// queries of a searcher, instead of allocating them again for every leaf.
+///
+/// The queue of a disjunction is allocated as usual: its wrappers point to
+/// each other, so they can't go through a pool shared by several threads.
+///
+/// The buffers of a scorer go back to the arena once the scorer is dropped,
+/// that is when its leaf has been collected. The arena is opt-in, see
+/// `DefaultIndexSearcher::set_scorer_arena`, the scorers are allocated as
+/// usual without it.
+#[derive(Default)]
+pub struct ScorerArena {
+    scorers: Arc<VecPool<Box<dyn Scorer>>>,
+    indexes: Arc<VecPool<usize>>,
+}
+
+impl ScorerArena {
+    pub fn new() -> ScorerArena {
+        Self::default()
+    }
+
+    /// A list for the scorers of `capacity` clauses.
+    pub fn scorers(&self, capacity: usize) -> PooledVec<Box<dyn Scorer>> {
+        VecPool::take(&self.scorers, capacity)
+    }
+
+    /// Builds a conjunction of `children` with the buffers of the arena.
+    pub fn conjunction(
+        &self,
+        children: PooledVec<Box<dyn Scorer>>,
+    ) -> ConjunctionScorer<Box<dyn Scorer>> {
+        let two_phase_children = VecPool::take(&self.indexes, children.len());
+        ConjunctionScorer::with_buffers(children, two_phase_children)
+    }
+
+    /// Builds a disjunction of `children` with the buffers of the arena.
+    pub fn disjunction_sum(
+        &self,
+        children: PooledVec<Box<dyn Scorer>>,
+    ) -> DisjunctionSumScorer<Box<dyn Scorer>> {
+        DisjunctionSumScorer::with_buffers(children, PooledVec::default(), PooledVec::default())
+    }
+
+    /// The buffers the arena allocated because it had no free one.
+    pub fn allocated(&self) -> usize {
+        self.scorers.allocated() + self.indexes.allocated()
+    }
+
+    /// The buffers the arena handed out again.
+    pub fn reused(&self) -> usize {
+        self.scorers.reused() + self.indexes.reused()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::search::tests::*;
+    use core::search::{DocIterator, NO_MORE_DOCS};
+    use core::util::DocId;
+
+    fn mock_scorers(arena: &ScorerArena, docs: &[&[DocId]]) -> PooledVec<Box<dyn Scorer>> {
+        let mut scorers = arena.scorers(docs.len());
+        for d in docs {
+            scorers.push(Box::new(create_mock_scorer(d.to_vec())));
+        }
+        scorers
+    }
+
+    #[test]
+    fn test_buffers_reused_between_scorers() {
+        let arena = ScorerArena::new();
+        for i in 0..3 {
+            let children = mock_scorers(&arena, &[&[1, 2, 3], &[2, 3], &[3, 4]]);
+            let mut conjunction = arena.conjunction(children);
+            assert_eq!(conjunction.next().unwrap(), 3);
+
+            let children = mock_scorers(&arena, &[&[1, 5], &[2, 5]]);
+            let mut disjunction = arena.disjunction_sum(children);
+            let mut docs = vec![];
+            loop {
+                let doc = disjunction.next().unwrap();
+                if doc == NO_MORE_DOCS {
+                    break;
+                }
+                docs.push(doc);
+            }
+            assert_eq!(docs, vec![1, 2, 5]);
+            // the clause list of the conjunction is still in use, so the
+            // disjunction allocated its own
+            if i == 0 {
+                assert_eq!(arena.allocated(), 3);
+                assert_eq!(arena.reused(), 0);
+            }
+        }
+        // the buffers went back when the scorers were dropped
+        assert_eq!(arena.allocated(), 3);
+        assert_eq!(arena.reused(), 2 * 3);
+    }
+
+    #[test]
+    fn test_pooled_vec_without_pool() {
+        let mut vec: PooledVec<i32> = vec![1, 2].into();
+        vec.push(3);
+        for v in &mut vec {
+            *v *= 2;
+        }
+        assert_eq!(vec.iter().sum::<i32>(), 12);
+    }
+}
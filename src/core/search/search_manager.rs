@@ -17,7 +17,7 @@ use core::{
         merge_policy::MergePolicy, merge_scheduler::MergeScheduler, IndexReader, IndexWriter,
         StandardDirectoryReader,
     },
-    search::searcher::IndexSearcher,
+    search::{facets::FacetsState, searcher::IndexSearcher},
     store::Directory,
     util::{ReferenceManager, ReferenceManagerBase, RefreshListener},
 };
@@ -51,10 +51,14 @@ use std::sync::Arc;
 /// since it penalizes the unlucky queries that need to refresh. It's better to use
 /// a separate background thread, that periodically calls {@link #maybeRefresh}. Finally,
 /// be sure to call {@link #close} once you are done
+///
+/// A `FacetsState` given with `with_facets` has the ordinal maps of its fields
+/// built on the reader of every new searcher.
 pub struct SearcherManager<C: Codec, T, SF: SearcherFactory<C>> {
     searcher_factory: SF,
     pub manager_base: ReferenceManagerBase<SF::Searcher>,
     refresh_listener: Option<T>,
+    facets: Option<Arc<FacetsState>>,
 }
 
 impl<C: Codec, T, SF: SearcherFactory<C>> SearcherManager<C, T, SF> {
@@ -79,19 +83,55 @@ impl<C: Codec, T, SF: SearcherFactory<C>> SearcherManager<C, T, SF> {
         searcher_factory: SF,
         refresh_listener: Option<T>,
     ) -> Result<Self>
+    where
+        D: Directory + Send + Sync + 'static,
+        MS: MergeScheduler,
+        MP: MergePolicy,
+    {
+        Self::build(reader, searcher_factory, refresh_listener, None)
+    }
+
+    pub fn with_facets<D, MS, MP>(
+        reader: StandardDirectoryReader<D, C, MS, MP>,
+        searcher_factory: SF,
+        refresh_listener: Option<T>,
+        facets: Arc<FacetsState>,
+    ) -> Result<Self>
+    where
+        D: Directory + Send + Sync + 'static,
+        MS: MergeScheduler,
+        MP: MergePolicy,
+    {
+        Self::build(reader, searcher_factory, refresh_listener, Some(facets))
+    }
+
+    fn build<D, MS, MP>(
+        reader: StandardDirectoryReader<D, C, MS, MP>,
+        searcher_factory: SF,
+        refresh_listener: Option<T>,
+        facets: Option<Arc<FacetsState>>,
+    ) -> Result<Self>
     where
         D: Directory + Send + Sync + 'static,
         MS: MergeScheduler,
         MP: MergePolicy,
     {
         let current = searcher_factory.new_searcher(Arc::new(reader))?;
+        if let Some(ref facets) = facets {
+            facets.refresh(current.reader())?;
+        }
         let manager_base = ReferenceManagerBase::new(Arc::new(current));
         Ok(SearcherManager {
             searcher_factory,
             manager_base,
             refresh_listener,
+            facets,
         })
     }
+
+    pub fn facets_state(&self) -> Option<&Arc<FacetsState>> {
+        self.facets.as_ref()
+    }
 }
 
 impl<C, T, SF, RL> ReferenceManager<SF::Searcher, RL> for SearcherManager<C, T, SF>
@@ -134,9 +174,11 @@ where
         //            unreachable!()
         //        }
         if let Some(reader) = reference_to_refresh.reader().refresh()? {
-            self.searcher_factory
-                .new_searcher(Arc::from(reader))
-                .map(|s| Some(Arc::new(s)))
+            let searcher = self.searcher_factory.new_searcher(Arc::from(reader))?;
+            if let Some(ref facets) = self.facets {
+                facets.refresh(searcher.reader())?;
+            }
+            Ok(Some(Arc::new(searcher)))
         } else {
             Ok(None)
         }
@@ -153,7 +195,181 @@ where
     }
 }
 
+/// Creates the searchers of a `SearcherManager`, which are shared by the
+/// search threads.
 pub trait SearcherFactory<C: Codec> {
-    type Searcher: IndexSearcher<C>;
-    fn new_searcher(&self, reader: Arc<IndexReader<Codec = C>>) -> Result<Self::Searcher>;
+    type Searcher: IndexSearcher<C> + Send + Sync;
+    fn new_searcher(
+        &self,
+        reader: Arc<dyn IndexReader<Codec = C> + Send + Sync>,
+    ) -> Result<Self::Searcher>;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::codec::CodecEnum;
+    use core::doc::{Field, FieldType};
+    use core::index::index_writer_config::IndexWriterConfig;
+    use core::index::{IndexOptions, Term};
+    use core::search::searcher::{DefaultIndexSearcher, DefaultSimilarityProducer};
+    use core::search::term_query::TermQuery;
+    use core::store::RAMDirectory;
+    use core::util::VariantValue;
+    use error::ErrorKind::AlreadyClosed;
+
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    struct Factory;
+
+    impl SearcherFactory<CodecEnum> for Factory {
+        type Searcher = DefaultIndexSearcher<
+            CodecEnum,
+            dyn IndexReader<Codec = CodecEnum> + Send + Sync,
+            Arc<dyn IndexReader<Codec = CodecEnum> + Send + Sync>,
+            DefaultSimilarityProducer,
+        >;
+
+        fn new_searcher(
+            &self,
+            reader: Arc<dyn IndexReader<Codec = CodecEnum> + Send + Sync>,
+        ) -> Result<Self::Searcher> {
+            Ok(DefaultIndexSearcher::new(reader))
+        }
+    }
+
+    // records whether two refreshes ever ran at once
+    #[derive(Default)]
+    struct Listener {
+        running: AtomicUsize,
+        overlapped: AtomicBool,
+        refreshed: AtomicUsize,
+    }
+
+    impl RefreshListener for Listener {
+        fn before_refresh(&self) -> Result<()> {
+            if self.running.fetch_add(1, Ordering::AcqRel) != 0 {
+                self.overlapped.store(true, Ordering::Release);
+            }
+            Ok(())
+        }
+
+        fn after_refresh(&self, refreshed: bool) -> Result<()> {
+            if refreshed {
+                self.refreshed.fetch_add(1, Ordering::AcqRel);
+            }
+            self.running.fetch_sub(1, Ordering::AcqRel);
+            Ok(())
+        }
+    }
+
+    fn keyword(name: &str, value: &str) -> Field {
+        let mut field_type = FieldType::default();
+        field_type.tokenized = false;
+        field_type.index_options = IndexOptions::DocsAndFreqs;
+        Field::new(
+            name.to_string(),
+            field_type,
+            Some(VariantValue::VString(value.to_string())),
+            None,
+        )
+    }
+
+    #[test]
+    fn test_concurrent_refresh_and_search() {
+        let writer = IndexWriter::new(
+            Arc::new(RAMDirectory::new()),
+            Arc::new(IndexWriterConfig::default()),
+        )
+        .unwrap();
+        writer.add_document(vec![keyword("type", "doc")]).unwrap();
+        let listener = Arc::new(Listener::default());
+        let manager = Arc::new(
+            SearcherManager::from_writer(
+                &writer,
+                true,
+                false,
+                Factory,
+                Some(Arc::clone(&listener)),
+            )
+            .unwrap(),
+        );
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let added = Arc::new(AtomicUsize::new(1));
+        let mut threads = vec![];
+        for _ in 0..2 {
+            let (writer, stop, added) = (writer.clone(), Arc::clone(&stop), Arc::clone(&added));
+            threads.push(thread::spawn(move || {
+                while !stop.load(Ordering::Acquire) {
+                    writer.add_document(vec![keyword("type", "doc")]).unwrap();
+                    added.fetch_add(1, Ordering::AcqRel);
+                    thread::sleep(Duration::from_millis(1));
+                }
+            }));
+        }
+        for _ in 0..2 {
+            let (manager, stop) = (Arc::clone(&manager), Arc::clone(&stop));
+            threads.push(thread::spawn(move || {
+                while !stop.load(Ordering::Acquire) {
+                    manager.maybe_refresh().unwrap();
+                    thread::sleep(Duration::from_millis(5));
+                }
+            }));
+        }
+        for _ in 0..4 {
+            let (manager, stop) = (Arc::clone(&manager), Arc::clone(&stop));
+            threads.push(thread::spawn(move || {
+                let query =
+                    TermQuery::new(Term::new("type".to_string(), b"doc".to_vec()), 1.0, None);
+                let mut last_seen = 0;
+                while !stop.load(Ordering::Acquire) {
+                    let searcher = manager.acquire().unwrap();
+                    let num_docs = searcher.reader().num_docs();
+                    assert!(num_docs >= last_seen);
+                    assert_eq!(searcher.count(&query).unwrap(), num_docs);
+                    last_seen = num_docs;
+                    manager.release(&searcher).unwrap();
+                }
+            }));
+        }
+
+        let start = Instant::now();
+        while start.elapsed() < Duration::from_secs(2) {
+            thread::sleep(Duration::from_millis(50));
+        }
+        stop.store(true, Ordering::Release);
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        assert!(!listener.overlapped.load(Ordering::Acquire));
+        assert!(listener.refreshed.load(Ordering::Acquire) > 0);
+
+        manager.maybe_refresh_blocking().unwrap();
+        let searcher = manager.acquire().unwrap();
+        assert_eq!(
+            searcher.reader().num_docs() as usize,
+            added.load(Ordering::Acquire)
+        );
+        manager.release(&searcher).unwrap();
+
+        manager.close().unwrap();
+        manager.close().unwrap();
+        match manager.acquire() {
+            Err(e) => match e.kind() {
+                AlreadyClosed(_) => {}
+                _ => panic!("expected AlreadyClosed, got {:?}", e),
+            },
+            Ok(_) => panic!("a closed manager returned a searcher"),
+        }
+        assert!(manager.maybe_refresh().is_err());
+        // the searcher acquired before the close is still usable
+        assert_eq!(
+            searcher.reader().num_docs() as usize,
+            added.load(Ordering::Acquire)
+        );
+        writer.close().unwrap();
+    }
 }
@@ -11,29 +11,39 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::cell::RefCell;
 use std::collections::HashMap;
 use std::fmt;
 use std::ops::Deref;
-use std::sync::{Arc, RwLock};
-
-use crossbeam::channel::{unbounded, Receiver, Sender};
+use std::sync::atomic::{AtomicBool, Ordering as AtomicOrdering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
 
 use core::codec::{Codec, CodecTermState};
 use core::index::LeafReaderContext;
-use core::index::{get_terms, IndexReader, SearchLeafReader};
-use core::index::{Term, TermContext, Terms};
+use core::index::{IndexReader, SearchLeafReader};
+use core::index::{Term, TermContext};
 use core::search::bm25_similarity::BM25Similarity;
 use core::search::bulk_scorer::BulkScorer;
 use core::search::cache_policy::{QueryCachingPolicy, UsageTrackingQueryCachingPolicy};
-use core::search::collector::{self, Collector, ParallelLeafCollector, SearchCollector};
+use core::search::collector::{self, Collector, ParallelLeafCollector, ScoreMode, SearchCollector};
+use core::search::collector::{LeafSender, ParallelChannel};
+use core::search::collector::{MinScoreCollector, TimeLimitingCollector, TopDocsCollector};
 use core::search::explanation::Explanation;
-use core::search::match_all::{ConstantScoreQuery, MatchAllDocsQuery};
+use core::search::norm_encoding::NORM_ENCODING_ATTRIBUTE;
+use core::search::norm_encoding::{NormEncoding, NormMismatchPolicy, NormRemap};
+use core::search::profile::{ProfileCollector, ProfileResult, ProfileTiming};
+use core::search::profile::{ProfileTree, ProfileWeight};
 use core::search::query_cache::{LRUQueryCache, QueryCache};
+use core::search::query_timeout::QueryTimeout;
+use core::search::scorer_arena::ScorerArena;
+use core::search::selectivity::{estimate_selectivity, SelectivityEstimate};
 use core::search::statistics::{CollectionStatistics, TermStatistics};
-use core::search::term_query::TermQuery;
+use core::search::top_docs::TopDocs;
 use core::search::{Query, Scorer, Weight, NO_MORE_DOCS};
 use core::search::{SimScorer, SimWeight, Similarity, SimilarityProducer};
 use core::util::bits::Bits;
+use core::util::cache::LruCache;
 use core::util::thread_pool::{DefaultContext, ThreadPool, ThreadPoolBuilder};
 use core::util::DocId;
 use core::util::KeyedContext;
@@ -115,6 +125,69 @@ impl SimScorer for NonScoringSimScorer {
     }
 }
 
+/// The most docs a slice of leaves searched by a single task may have, unless
+/// it's a single leaf.
+pub const MAX_DOCS_PER_SLICE: i32 = 250_000;
+
+/// The most leaves in a slice searched by a single task.
+pub const MAX_SEGMENTS_PER_SLICE: usize = 5;
+
+/// The number of term contexts a searcher keeps for the terms looked up
+/// again, unless set with `set_term_context_cache_size`.
+pub const DEFAULT_TERM_CONTEXT_CACHE_SIZE: usize = 10_000;
+
+/// Groups the leaves into the slices that are searched in parallel, as the
+/// ords of the leaves in each slice.
+///
+/// The largest leaves get a slice of their own, the others are grouped up to
+/// `MAX_DOCS_PER_SLICE` docs or `MAX_SEGMENTS_PER_SLICE` leaves. The slices
+/// only depend on the sizes of the leaves.
+pub fn slices<C: Codec>(leaves: &[LeafReaderContext<'_, C>]) -> Vec<Vec<usize>> {
+    let mut ords: Vec<usize> = (0..leaves.len()).collect();
+    // the largest leaves first, stable for the leaves of the same size
+    ords.sort_by(|&o1, &o2| {
+        leaves[o2]
+            .reader
+            .max_doc()
+            .cmp(&leaves[o1].reader.max_doc())
+    });
+
+    let mut slices = vec![];
+    let mut group = vec![];
+    let mut docs = 0;
+    for ord in ords {
+        let max_doc = leaves[ord].reader.max_doc();
+        if max_doc > MAX_DOCS_PER_SLICE {
+            slices.push(vec![ord]);
+            continue;
+        }
+        group.push(ord);
+        docs += max_doc;
+        if docs > MAX_DOCS_PER_SLICE || group.len() >= MAX_SEGMENTS_PER_SLICE {
+            slices.push(group);
+            group = vec![];
+            docs = 0;
+        }
+    }
+    if !group.is_empty() {
+        slices.push(group);
+    }
+    for slice in &mut slices {
+        slice.sort();
+    }
+    slices
+}
+
+// the score the scorers may skip the docs under, never for the collections of
+// every hit
+fn min_competitive_score<S: SearchCollector + ?Sized>(collector: &S) -> Option<f32> {
+    if collector.score_mode().is_exhaustive() {
+        None
+    } else {
+        collector.min_competitive_score()
+    }
+}
+
 pub trait IndexSearcher<C: Codec>: SearchPlanBuilder<C> {
     type Reader: IndexReader<Codec = C> + ?Sized;
     fn reader(&self) -> &Self::Reader;
@@ -127,9 +200,76 @@ pub trait IndexSearcher<C: Codec>: SearchPlanBuilder<C> {
     where
         S: SearchCollector + ?Sized;
 
-    fn count(&self, query: &dyn Query<C>) -> Result<i32>;
+    /// Counts the live docs matching `query`, without scoring them.
+    ///
+    /// The leaves whose count the weight tells, see `Weight::count`, aren't
+    /// searched, the others are searched as by `search`, in parallel when
+    /// the searcher has an executor.
+    fn count(&self, query: &dyn Query<C>) -> Result<usize>;
+
+    /// Finds the top `n` hits scoring at least `min_score`. Also returns the
+    /// number of hits that were dropped for scoring less.
+    ///
+    /// The threshold is passed to the scorers before collecting, so that the
+    /// ones able to skip non-competitive docs don't even score them.
+    fn search_with_min_score(
+        &self,
+        query: &dyn Query<C>,
+        n: usize,
+        min_score: f32,
+    ) -> Result<(TopDocs, usize)> {
+        let mut collector = MinScoreCollector::new(TopDocsCollector::new(n), min_score);
+        self.search_parallel(query, &mut collector)?;
+        let suppressed = collector.suppressed_hits();
+        Ok((collector.into_inner().top_docs(), suppressed))
+    }
+
+    /// Searches as `search` until `timeout` is cancelled, e.g. once its
+    /// deadline has passed. Both the collection, see `TimeLimitingCollector`,
+    /// and the enumeration of the terms or points matched by a query, see
+    /// `SearchPlanBuilder::query_timeout`, are cut short.
+    ///
+    /// Returns whether the search was cut short, in which case the results
+    /// of `collector` are partial: it only holds the hits collected before.
+    fn search_cancellable<S>(
+        &self,
+        query: &dyn Query<C>,
+        collector: &mut S,
+        timeout: &QueryTimeout,
+    ) -> Result<bool>
+    where
+        S: SearchCollector;
+
+    /// Searches for at most `timeout`, see `search_cancellable`.
+    fn search_with_timeout<S>(
+        &self,
+        query: &dyn Query<C>,
+        collector: &mut S,
+        timeout: Duration,
+    ) -> Result<bool>
+    where
+        S: SearchCollector,
+    {
+        self.search_cancellable(query, collector, &QueryTimeout::with_timeout(timeout))
+    }
+
+    /// Searches as `search`, timing the creation of the weights and scorers
+    /// of `query` and of its sub queries, their iteration and scoring, and
+    /// the collection, see `profile`.
+    ///
+    /// The hits are collected by `collector` as usual, the profile is
+    /// returned.
+    fn search_profiled<S>(&self, query: &dyn Query<C>, collector: &mut S) -> Result<ProfileResult>
+    where
+        S: SearchCollector;
 
     fn explain(&self, query: &dyn Query<C>, doc: DocId) -> Result<Explanation>;
+
+    /// Estimates how many docs `query` matches from the index statistics,
+    /// without creating any scorer, see `selectivity::estimate_selectivity`.
+    fn estimate_selectivity(&self, query: &dyn Query<C>) -> Result<SelectivityEstimate> {
+        estimate_selectivity(self.reader(), query)
+    }
 }
 
 pub trait SearchPlanBuilder<C: Codec> {
@@ -163,6 +303,21 @@ pub trait SearchPlanBuilder<C: Codec> {
     ) -> TermStatistics;
 
     fn collections_statistics(&self, field: &str) -> Result<CollectionStatistics>;
+
+    /// The segments searched, for the queries that look up the terms of a
+    /// field to build their weight.
+    fn leaves(&self) -> Vec<LeafReaderContext<'_, C>>;
+
+    /// Cancels the running search, for the queries that enumerate many terms
+    /// or points before matching any doc to check it as they go.
+    fn query_timeout(&self) -> Option<&QueryTimeout> {
+        None
+    }
+
+    /// The arena the boolean scorers take their buffers from, if any.
+    fn scorer_arena(&self) -> Option<&Arc<ScorerArena>> {
+        None
+    }
 }
 
 pub struct DefaultIndexSearcher<
@@ -176,8 +331,11 @@ pub struct DefaultIndexSearcher<
     query_cache: Arc<dyn QueryCache<C>>,
     cache_policy: Arc<dyn QueryCachingPolicy<C>>,
     collection_statistics: RwLock<HashMap<String, CollectionStatistics>>,
-    term_contexts: RwLock<HashMap<String, Arc<TermContext<CodecTermState<C>>>>>,
+    term_contexts: LruCache<String, Arc<TermContext<CodecTermState<C>>>>,
     thread_pool: Option<Arc<ThreadPool<DefaultContext>>>,
+    // norms of these fields were encoded by another similarity than the one searching them
+    norm_remaps: HashMap<String, NormRemap>,
+    scorer_arena: Option<Arc<ScorerArena>>,
 }
 
 impl<C: Codec, R: IndexReader<Codec = C> + ?Sized, IR: Deref<Target = R>>
@@ -202,9 +360,87 @@ where
             query_cache: Arc::new(LRUQueryCache::new(1000)),
             cache_policy: Arc::new(UsageTrackingQueryCachingPolicy::default()),
             collection_statistics: RwLock::new(HashMap::new()),
-            term_contexts: RwLock::new(HashMap::new()),
+            term_contexts: LruCache::with_max_entries(DEFAULT_TERM_CONTEXT_CACHE_SIZE),
             thread_pool: None,
+            norm_remaps: HashMap::new(),
+            scorer_arena: None,
+        }
+    }
+
+    /// Same as `with_similarity`, but also checks the norm encodings of the
+    /// indexed fields with `check_norm_encodings`.
+    pub fn with_similarity_checked(
+        reader: IR,
+        sim_producer: SP,
+        policy: NormMismatchPolicy,
+    ) -> Result<DefaultIndexSearcher<C, R, IR, SP>> {
+        let mut searcher = Self::with_similarity(reader, sim_producer);
+        searcher.check_norm_encodings(policy)?;
+        Ok(searcher)
+    }
+
+    /// Checks that the norms of the indexed fields were encoded at index time by
+    /// the similarity searching them, otherwise they would be silently decoded
+    /// into wrong field lengths. What happens on a mismatch depends on `policy`.
+    ///
+    /// Fields indexed before the encoding was recorded, and fields searched
+    /// by similarities not telling their encoding, aren't checked.
+    pub fn check_norm_encodings(&mut self, policy: NormMismatchPolicy) -> Result<()> {
+        let mut remaps: HashMap<String, NormRemap> = HashMap::new();
+        for leaf in self.reader.leaves() {
+            for fi in leaf.reader.field_infos().by_name.values() {
+                if !fi.has_norms() {
+                    continue;
+                }
+                let recorded = match fi.attribute(NORM_ENCODING_ATTRIBUTE) {
+                    Some(name) => name,
+                    None => continue,
+                };
+                let expected = match self.sim_producer.create(&fi.name).norm_encoding() {
+                    Some(encoding) => encoding,
+                    None => continue,
+                };
+                if recorded == expected.name {
+                    if remaps.contains_key(&fi.name) {
+                        bail!(ErrorKind::IllegalState(format!(
+                            "norms of field '{}' were encoded by several similarities",
+                            fi.name
+                        )));
+                    }
+                    continue;
+                }
+
+                let message = format!(
+                    "norms of field '{}' were encoded by {} but are decoded by {}",
+                    fi.name, recorded, expected.name
+                );
+                match policy {
+                    NormMismatchPolicy::Fail => bail!(ErrorKind::IllegalState(message)),
+                    NormMismatchPolicy::Ignore => warn!("{}, scores will be off", message),
+                    NormMismatchPolicy::Remap => {
+                        let source = match NormEncoding::for_name(&recorded) {
+                            Some(encoding) => encoding,
+                            None => bail!(ErrorKind::IllegalState(format!(
+                                "{}, and the encoding of {} is unknown",
+                                message, recorded
+                            ))),
+                        };
+                        if let Some(remap) = remaps.get(&fi.name) {
+                            if remap.source != source.name {
+                                bail!(ErrorKind::IllegalState(format!(
+                                    "norms of field '{}' were encoded by several similarities",
+                                    fi.name
+                                )));
+                            }
+                            continue;
+                        }
+                        remaps.insert(fi.name.clone(), expected.remap_from(&source));
+                    }
+                }
+            }
         }
+        self.norm_remaps = remaps;
+        Ok(())
     }
 
     pub fn with_thread_pool(&mut self, num_threads: usize) {
@@ -221,6 +457,16 @@ where
         self.thread_pool = Some(pool);
     }
 
+    /// Searches the slices of the leaves in parallel on the threads of `pool`,
+    /// see `slices`.
+    pub fn with_executor(
+        mut self,
+        pool: Arc<ThreadPool<DefaultContext>>,
+    ) -> DefaultIndexSearcher<C, R, IR, SP> {
+        self.set_thread_pool(pool);
+        self
+    }
+
     pub fn set_query_cache(&mut self, cache: Arc<dyn QueryCache<C>>) {
         self.query_cache = cache;
     }
@@ -229,6 +475,160 @@ where
         self.cache_policy = cache_policy;
     }
 
+    /// Recycles the buffers of the boolean scorers in `arena` instead of
+    /// allocating them for every leaf, see `ScorerArena`.
+    pub fn set_scorer_arena(&mut self, arena: Arc<ScorerArena>) {
+        self.scorer_arena = Some(arena);
+    }
+
+    /// Keeps at most `size` term contexts for the terms looked up again,
+    /// dropping the ones already cached.
+    pub fn set_term_context_cache_size(&mut self, size: usize) {
+        self.term_contexts = LruCache::with_max_entries(size);
+    }
+
+    // creates the weight of `query` with `plan` building the weights of its
+    // sub queries, cached if it doesn't need the scores
+    fn create_weight_with(
+        &self,
+        plan: &dyn SearchPlanBuilder<C>,
+        query: &dyn Query<C>,
+        needs_scores: bool,
+    ) -> Result<Box<dyn Weight<C>>> {
+        let mut weight = query.create_weight(plan, needs_scores)?;
+        if !needs_scores {
+            weight = self
+                .query_cache
+                .do_cache(weight, Arc::clone(&self.cache_policy));
+        }
+        Ok(weight)
+    }
+
+    // searches `leaves` with `weight`, the slices of the leaves in parallel if
+    // the searcher has an executor and the collector supports it
+    fn search_leaves<S>(
+        &self,
+        weight: &dyn Weight<C>,
+        leaves: &[LeafReaderContext<'_, C>],
+        collector: &mut S,
+    ) -> Result<()>
+    where
+        S: SearchCollector + ?Sized,
+    {
+        if collector.support_parallel() && leaves.len() > 1 {
+            if let Some(ref thread_pool) = self.thread_pool {
+                return self.search_slices(weight, leaves, collector, thread_pool);
+            }
+        }
+
+        for reader in leaves {
+            if let Some(mut scorer) = weight.create_scorer(reader)? {
+                // the hits of the previous leaves may already set a bound
+                if let Some(min_score) = min_competitive_score(collector) {
+                    scorer.set_min_competitive_score(min_score)?;
+                }
+                // some in running segment maybe wrong, just skip it!
+                // TODO maybe we should matching more specific error type
+                if let Err(e) = collector.set_next_reader(reader) {
+                    error!(
+                        "set next reader for leaf {} failed!, {:?}",
+                        reader.reader.name(),
+                        e
+                    );
+                    continue;
+                }
+                let live_docs = reader.reader.live_docs();
+
+                Self::do_search(&mut *scorer, collector, live_docs.as_ref())?;
+            }
+        }
+
+        Ok(())
+    }
+
+    // creates the scorers and the leaf collectors of every slice, and
+    // collects each slice in a task of `thread_pool`. The collector merges the
+    // hits of the leaves in `finish_parallel`, which returns once every task is
+    // done, so the first error is only returned then.
+    fn search_slices<S>(
+        &self,
+        weight: &dyn Weight<C>,
+        leaves: &[LeafReaderContext<'_, C>],
+        collector: &mut S,
+        thread_pool: &ThreadPool<DefaultContext>,
+    ) -> Result<()>
+    where
+        S: SearchCollector + ?Sized,
+    {
+        let failure: Arc<Mutex<Option<Error>>> = Arc::new(Mutex::new(None));
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let submitted =
+            self.submit_slices(weight, leaves, collector, thread_pool, &failure, &cancelled);
+        if submitted.is_err() {
+            cancelled.store(true, AtomicOrdering::Release);
+        }
+        let finished = collector.finish_parallel();
+        submitted?;
+        if let Some(e) = failure.lock()?.take() {
+            return Err(e);
+        }
+        finished
+    }
+
+    fn submit_slices<S>(
+        &self,
+        weight: &dyn Weight<C>,
+        leaves: &[LeafReaderContext<'_, C>],
+        collector: &mut S,
+        thread_pool: &ThreadPool<DefaultContext>,
+        failure: &Arc<Mutex<Option<Error>>>,
+        cancelled: &Arc<AtomicBool>,
+    ) -> Result<()>
+    where
+        S: SearchCollector + ?Sized,
+    {
+        for slice in slices(leaves) {
+            let mut tasks = Vec::with_capacity(slice.len());
+            for ord in slice {
+                let reader = &leaves[ord];
+                if let Some(mut scorer) = weight.create_scorer(reader)? {
+                    if let Some(min_score) = min_competitive_score(collector) {
+                        scorer.set_min_competitive_score(min_score)?;
+                    }
+                    let leaf_collector = collector.leaf_collector(reader)?;
+                    tasks.push((scorer, leaf_collector, reader.reader.live_docs()));
+                }
+            }
+            if tasks.is_empty() {
+                continue;
+            }
+
+            let failure = Arc::clone(failure);
+            let cancelled = Arc::clone(cancelled);
+            thread_pool.execute(move |_ctx| {
+                // the leaf collectors that are dropped unfinished don't hold
+                // the collector back
+                for (mut scorer, mut collector, live_docs) in tasks {
+                    if cancelled.load(AtomicOrdering::Acquire) {
+                        return;
+                    }
+                    let result =
+                        Self::do_search(scorer.as_mut(), &mut collector, live_docs.as_ref())
+                            .and_then(|_| collector.finish_leaf());
+                    if let Err(e) = result {
+                        cancelled.store(true, AtomicOrdering::Release);
+                        let mut failure = failure.lock().unwrap();
+                        if failure.is_none() {
+                            *failure = Some(e);
+                        }
+                        return;
+                    }
+                }
+            });
+        }
+        Ok(())
+    }
+
     fn do_search<S: Scorer + ?Sized, T: Collector + ?Sized, B: Bits + ?Sized>(
         scorer: &mut S,
         collector: &mut T,
@@ -268,114 +668,84 @@ where
     }
 
     /// Lower-level search API.
+    ///
+    /// The slices of the leaves are searched in parallel if the searcher has an
+    /// executor and the collector supports it.
     fn search<S>(&self, query: &dyn Query<C>, collector: &mut S) -> Result<()>
     where
         S: SearchCollector + ?Sized,
     {
-        let weight = self.create_weight(query, collector.needs_scores())?;
-
-        for reader in self.reader.leaves() {
-            if let Some(mut scorer) = weight.create_scorer(&reader)? {
-                // some in running segment maybe wrong, just skip it!
-                // TODO maybe we should matching more specific error type
-                if let Err(e) = collector.set_next_reader(&reader) {
-                    error!(
-                        "set next reader for leaf {} failed!, {:?}",
-                        reader.reader.name(),
-                        e
-                    );
-                    continue;
-                }
-                let live_docs = reader.reader.live_docs();
-
-                Self::do_search(&mut *scorer, collector, live_docs.as_ref())?;
-            }
-        }
-
-        Ok(())
+        self.reader.ensure_open()?;
+        let weight = self.create_weight(query, collector.score_mode().needs_scores())?;
+        self.search_leaves(weight.as_ref(), &self.reader.leaves(), collector)
     }
 
     fn search_parallel<S>(&self, query: &dyn Query<C>, collector: &mut S) -> Result<()>
     where
         S: SearchCollector + ?Sized,
     {
-        if collector.support_parallel() && self.reader.leaves().len() > 1 {
-            if let Some(ref thread_pool) = self.thread_pool {
-                let weight = self.create_weight(query, collector.needs_scores())?;
-
-                for (_ord, reader) in self.reader.leaves().iter().enumerate() {
-                    if let Some(scorer) = weight.create_scorer(reader)? {
-                        match collector.leaf_collector(reader) {
-                            Ok(leaf_collector) => {
-                                let live_docs = reader.reader.live_docs();
-                                thread_pool.execute(move |_ctx| {
-                                    let mut collector = leaf_collector;
-                                    let mut scorer = scorer;
-                                    if let Err(e) = Self::do_search(
-                                        scorer.as_mut(),
-                                        &mut collector,
-                                        live_docs.as_ref(),
-                                    ) {
-                                        error!(
-                                            "do search parallel failed by '{:?}', may return \
-                                             partial result",
-                                            e
-                                        );
-                                    }
-                                    if let Err(e) = collector.finish_leaf() {
-                                        error!(
-                                            "finish search parallel failed by '{:?}', may return \
-                                             partial result",
-                                            e
-                                        );
-                                    }
-                                })
-                            }
-                            Err(e) => {
-                                error!(
-                                    "create leaf collector for leaf {} failed with '{:?}'",
-                                    reader.reader.name(),
-                                    e
-                                );
-                            }
-                        }
-                    }
-                }
-                return collector.finish_parallel();
-            }
-        }
         self.search(query, collector)
     }
 
-    fn count(&self, query: &dyn Query<C>) -> Result<i32> {
-        let mut query = query;
-        loop {
-            if let Some(constant_query) = query.as_any().downcast_ref::<ConstantScoreQuery<C>>() {
-                query = constant_query.get_raw_query();
-            } else {
-                break;
-            }
+    fn search_cancellable<S>(
+        &self,
+        query: &dyn Query<C>,
+        collector: &mut S,
+        timeout: &QueryTimeout,
+    ) -> Result<bool>
+    where
+        S: SearchCollector,
+    {
+        self.reader.ensure_open()?;
+        let plan = SearchPlan::new(self).with_timeout(timeout);
+        let mut limited = TimeLimitingCollector::new(collector, timeout.clone());
+        let result = plan
+            .create_weight(query, limited.score_mode().needs_scores())
+            .and_then(|weight| {
+                self.search_leaves(weight.as_ref(), &self.reader.leaves(), &mut limited)
+            });
+        match result {
+            Ok(()) => Ok(false),
+            Err(Error(ErrorKind::Collector(collector::ErrorKind::TimeExceeded(..)), _)) => Ok(true),
+            Err(Error(ErrorKind::Cancelled(_), _)) if timeout.is_cancelled() => Ok(true),
+            Err(e) => Err(e),
         }
+    }
 
-        if let Some(_) = query.as_any().downcast_ref::<MatchAllDocsQuery>() {
-            return Ok(self.reader().num_docs());
-        } else if let Some(term_query) = query.as_any().downcast_ref::<TermQuery>() {
-            if !self.reader().has_deletions() {
-                let term = &term_query.term;
-                let mut count = 0;
-                for leaf in self.reader().leaves() {
-                    count += leaf.reader.doc_freq(term)?;
-                }
-                return Ok(count);
+    fn search_profiled<S>(&self, query: &dyn Query<C>, collector: &mut S) -> Result<ProfileResult>
+    where
+        S: SearchCollector,
+    {
+        self.reader.ensure_open()?;
+        let plan = SearchPlan::new(self).profiled();
+        let mut profiled = ProfileCollector::new(collector);
+        let weight = plan.create_weight(query, profiled.score_mode().needs_scores())?;
+        self.search_leaves(weight.as_ref(), &self.reader.leaves(), &mut profiled)?;
+        let query_profile = plan.tree.as_ref().unwrap().borrow().profile().unwrap();
+        Ok(ProfileResult::new(query_profile, profiled.profile()))
+    }
+
+    fn count(&self, query: &dyn Query<C>) -> Result<usize> {
+        self.reader.ensure_open()?;
+        let weight = self.create_weight(query, false)?;
+        let mut count = 0;
+        let mut uncounted = vec![];
+        for leaf in self.reader.leaves() {
+            match weight.count(&leaf)? {
+                Some(leaf_count) => count += leaf_count,
+                None => uncounted.push(leaf),
             }
         }
-
-        let mut collector = TotalHitCountCollector::new();
-        self.search_parallel(query, &mut collector)?;
-        Ok(collector.total_hits())
+        if !uncounted.is_empty() {
+            let mut collector = TotalHitCountCollector::new();
+            self.search_leaves(weight.as_ref(), &uncounted, &mut collector)?;
+            count += collector.total_hits() as usize;
+        }
+        Ok(count)
     }
 
     fn explain(&self, query: &dyn Query<C>, doc: DocId) -> Result<Explanation> {
+        self.reader.ensure_open()?;
         let reader = self.reader.leaf_reader_for_doc(doc);
         let live_docs = reader.reader.live_docs();
         if !live_docs.get((doc - reader.doc_base()) as usize)? {
@@ -414,13 +784,7 @@ where
         query: &dyn Query<C>,
         needs_scores: bool,
     ) -> Result<Box<dyn Weight<C>>> {
-        let mut weight = query.create_weight(self, needs_scores)?;
-        if !needs_scores {
-            weight = self
-                .query_cache
-                .do_cache(weight, Arc::clone(&self.cache_policy));
-        }
-        Ok(weight)
+        self.create_weight_with(self, query, needs_scores)
     }
 
     /// Creates a normalized weight for a top-level `Query`.
@@ -444,33 +808,23 @@ where
 
     fn similarity(&self, field: &str, needs_scores: bool) -> Box<dyn Similarity<C>> {
         if needs_scores {
-            self.sim_producer.create(field)
+            let mut similarity = self.sim_producer.create(field);
+            if let Some(remap) = self.norm_remaps.get(field) {
+                similarity.set_norm_remap(remap.clone());
+            }
+            similarity
         } else {
             Box::new(NonScoringSimilarity {})
         }
     }
 
     fn term_state(&self, term: &Term) -> Result<Arc<TermContext<CodecTermState<C>>>> {
-        let term_context: Arc<TermContext<CodecTermState<C>>>;
-        let mut builded = false;
         let term_key = format!("{}_{}", term.field, term.text()?);
-        if self.term_contexts.read().unwrap().contains_key(&term_key) {
-            builded = true;
-        }
-
-        if builded {
-            term_context = Arc::clone(self.term_contexts.read().unwrap().get(&term_key).unwrap());
-        } else {
+        self.term_contexts.compute_if_absent(term_key, |_| {
             let mut context = TermContext::new(&*self.reader);
             context.build(&*self.reader, &term)?;
-            term_context = Arc::new(context);
-            self.term_contexts
-                .write()
-                .unwrap()
-                .insert(term_key.clone(), Arc::clone(&term_context));
-        };
-
-        Ok(term_context)
+            Ok(Arc::new(context))
+        })
     }
 
     fn term_statistics(
@@ -493,38 +847,147 @@ where
             }
         }
         // slow path
-        let mut doc_count = 0i32;
-        let mut sum_total_term_freq = 0i64;
-        let mut sum_doc_freq = 0i64;
-        if let Some(terms) = get_terms(&*self.reader, field)? {
-            doc_count = terms.doc_count()?;
-            sum_total_term_freq = terms.sum_total_term_freq()?;
-            sum_doc_freq = terms.sum_doc_freq()?;
-        }
-        let stat = CollectionStatistics::new(
-            field.into(),
-            i64::from(self.reader.max_doc()),
-            i64::from(doc_count),
-            sum_total_term_freq,
-            sum_doc_freq,
-        );
+        let stat = self.reader.collection_statistics(field)?;
 
         let mut statistics = self.collection_statistics.write().unwrap();
         statistics.insert(field.into(), stat);
         Ok(statistics[field].clone())
     }
+
+    fn leaves(&self) -> Vec<LeafReaderContext<'_, C>> {
+        self.reader.leaves()
+    }
+
+    fn scorer_arena(&self) -> Option<&Arc<ScorerArena>> {
+        self.scorer_arena.as_ref()
+    }
+}
+
+// builds the weights of a search as its searcher does, telling them the
+// timeout of the search if any, and wrapping each to profile it in the tree
+// of the breakdowns of the queries when profiling
+struct SearchPlan<'a, C, R, IR, SP>
+where
+    C: Codec + 'a,
+    R: IndexReader<Codec = C> + ?Sized + 'a,
+    IR: Deref<Target = R> + 'a,
+    SP: SimilarityProducer<C> + 'a,
+{
+    searcher: &'a DefaultIndexSearcher<C, R, IR, SP>,
+    timeout: Option<&'a QueryTimeout>,
+    tree: Option<RefCell<ProfileTree>>,
 }
 
-struct TotalHitCountCollector {
+impl<'a, C, R, IR, SP> SearchPlan<'a, C, R, IR, SP>
+where
+    C: Codec + 'a,
+    R: IndexReader<Codec = C> + ?Sized + 'a,
+    IR: Deref<Target = R> + 'a,
+    SP: SimilarityProducer<C> + 'a,
+{
+    fn new(searcher: &'a DefaultIndexSearcher<C, R, IR, SP>) -> Self {
+        SearchPlan {
+            searcher,
+            timeout: None,
+            tree: None,
+        }
+    }
+
+    fn with_timeout(mut self, timeout: &'a QueryTimeout) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    fn profiled(mut self) -> Self {
+        self.tree = Some(RefCell::new(ProfileTree::default()));
+        self
+    }
+}
+
+impl<'a, C, R, IR, SP> SearchPlanBuilder<C> for SearchPlan<'a, C, R, IR, SP>
+where
+    C: Codec + 'a,
+    R: IndexReader<Codec = C> + ?Sized + 'a,
+    IR: Deref<Target = R> + 'a,
+    SP: SimilarityProducer<C> + 'a,
+{
+    fn num_docs(&self) -> i32 {
+        self.searcher.num_docs()
+    }
+
+    fn max_doc(&self) -> i32 {
+        self.searcher.max_doc()
+    }
+
+    fn create_weight(
+        &self,
+        query: &dyn Query<C>,
+        needs_scores: bool,
+    ) -> Result<Box<dyn Weight<C>>> {
+        let tree = match self.tree {
+            Some(ref tree) => tree,
+            None => return self.searcher.create_weight_with(self, query, needs_scores),
+        };
+        let breakdown = tree.borrow_mut().enter(query);
+        // the weights of the sub queries are created meanwhile, as children
+        let start = Instant::now();
+        let weight = self.searcher.create_weight_with(self, query, needs_scores);
+        breakdown.record(ProfileTiming::CreateWeight, start);
+        tree.borrow_mut().exit();
+        Ok(Box::new(ProfileWeight::new(weight?, breakdown)))
+    }
+
+    fn create_normalized_weight(
+        &self,
+        query: &dyn Query<C>,
+        needs_scores: bool,
+    ) -> Result<Box<dyn Weight<C>>> {
+        self.create_weight(query, needs_scores)
+    }
+
+    fn similarity(&self, field: &str, needs_scores: bool) -> Box<dyn Similarity<C>> {
+        self.searcher.similarity(field, needs_scores)
+    }
+
+    fn term_state(&self, term: &Term) -> Result<Arc<TermContext<CodecTermState<C>>>> {
+        self.searcher.term_state(term)
+    }
+
+    fn term_statistics(
+        &self,
+        term: Term,
+        context: &TermContext<CodecTermState<C>>,
+    ) -> TermStatistics {
+        self.searcher.term_statistics(term, context)
+    }
+
+    fn collections_statistics(&self, field: &str) -> Result<CollectionStatistics> {
+        self.searcher.collections_statistics(field)
+    }
+
+    fn leaves(&self) -> Vec<LeafReaderContext<'_, C>> {
+        self.searcher.leaves()
+    }
+
+    fn query_timeout(&self) -> Option<&QueryTimeout> {
+        self.timeout
+    }
+
+    fn scorer_arena(&self) -> Option<&Arc<ScorerArena>> {
+        self.searcher.scorer_arena()
+    }
+}
+
+pub(crate) struct TotalHitCountCollector {
     total_hits: i32,
-    channel: Option<(Sender<i32>, Receiver<i32>)>,
+    channel: ParallelChannel<i32>,
 }
 
 impl TotalHitCountCollector {
     pub fn new() -> Self {
         TotalHitCountCollector {
             total_hits: 0,
-            channel: None,
+            channel: ParallelChannel::default(),
         }
     }
 
@@ -547,33 +1010,23 @@ impl SearchCollector for TotalHitCountCollector {
         &mut self,
         _reader: &LeafReaderContext<'_, C>,
     ) -> Result<TotalHitsCountLeafCollector> {
-        if self.channel.is_none() {
-            self.channel = Some(unbounded());
-        }
         Ok(TotalHitsCountLeafCollector {
             count: 0,
-            sender: self.channel.as_ref().unwrap().0.clone(),
+            sender: self.channel.sender(),
         })
     }
 
     fn finish_parallel(&mut self) -> Result<()> {
-        let channel = self.channel.take();
-        // iff all the `weight.create_scorer(leaf_reader)` return None, the channel won't
-        // inited and thus stay None
-        if let Some((sender, receiver)) = channel {
-            drop(sender);
-            while let Ok(v) = receiver.recv() {
-                self.total_hits += v;
-            }
+        for count in self.channel.receive() {
+            self.total_hits += count;
         }
-
         Ok(())
     }
 }
 
 impl Collector for TotalHitCountCollector {
-    fn needs_scores(&self) -> bool {
-        false
+    fn score_mode(&self) -> ScoreMode {
+        ScoreMode::CompleteNoScores
     }
 
     fn collect<S: Scorer + ?Sized>(&mut self, _doc: i32, _scorer: &mut S) -> Result<()> {
@@ -582,14 +1035,14 @@ impl Collector for TotalHitCountCollector {
     }
 }
 
-struct TotalHitsCountLeafCollector {
+pub(crate) struct TotalHitsCountLeafCollector {
     count: i32,
-    sender: Sender<i32>,
+    sender: LeafSender<i32>,
 }
 
 impl Collector for TotalHitsCountLeafCollector {
-    fn needs_scores(&self) -> bool {
-        false
+    fn score_mode(&self) -> ScoreMode {
+        ScoreMode::CompleteNoScores
     }
 
     fn collect<S: Scorer + ?Sized>(&mut self, _doc: i32, _scorer: &mut S) -> Result<()> {
@@ -600,13 +1053,7 @@ impl Collector for TotalHitsCountLeafCollector {
 
 impl ParallelLeafCollector for TotalHitsCountLeafCollector {
     fn finish_leaf(&mut self) -> Result<()> {
-        self.sender.send(self.count).map_err(|e| {
-            ErrorKind::IllegalState(format!(
-                "channel unexpected closed before search complete with err: {:?}",
-                e
-            ))
-            .into()
-        })
+        self.sender.send(self.count)
     }
 }
 
@@ -617,12 +1064,35 @@ mod tests {
     use core::index::tests::*;
     use core::search::collector::top_docs::*;
     use core::search::collector::*;
+    use core::search::query_timeout::{Counter, ManualCounter};
     use core::search::term_query::TermQuery;
     use core::search::tests::*;
     use core::search::*;
     use core::util::DocId;
     use std::sync::atomic::Ordering;
 
+    use core::codec::CodecEnum;
+    use core::doc::{
+        Field, FieldType, FloatPoint, LongPoint, NumericDocValuesField, Store, StringField,
+    };
+    use core::index::index_writer_config::IndexWriterConfig;
+    use core::index::merge_policy::TieredMergePolicy;
+    use core::index::merge_scheduler::SerialMergeScheduler;
+    use core::index::{Fieldable, IndexOptions, IndexWriter, StandardDirectoryReader, Term};
+    use core::search::bm25_similarity::{BM25_LENGTH_NORM_ENCODING, BM25_NORM_ENCODING};
+    use core::search::boolean_query::BooleanQuery;
+    use core::search::classic_similarity::{ClassicSimilarityProducer, CLASSIC_NORM_ENCODING};
+    use core::search::fuzzy_query::FuzzyQuery;
+    use core::search::match_all::MatchAllDocsQuery;
+    use core::search::point_range::PointRangeQuery;
+    use core::search::prefix_query::PrefixQuery;
+    use core::search::sort::Sort;
+    use core::search::sort_field::{SimpleSortField, SortField, SortFieldType};
+    use core::search::top_docs::ScoreDocHit;
+    use core::store::tests::CountingDirectory;
+    use core::store::RAMDirectory;
+    use core::util::VariantValue;
+
     pub const MOCK_QUERY: &str = "mock";
 
     struct MockQuery {
@@ -705,4 +1175,944 @@ mod tests {
         assert!((score_docs[1].score() - 5f32) < ::std::f32::EPSILON);
         assert!((score_docs[2].score() - 5f32) < ::std::f32::EPSILON);
     }
+
+    // matches `docs` in every leaf, the docs scoring as their ids. The scorers
+    // record the thresholds they are given with their current doc, and tick
+    // `counter` when moving to a doc, for a search to time out after a number
+    // of docs
+    struct RecordingQuery {
+        docs: Vec<DocId>,
+        thresholds: Arc<RwLock<Vec<(DocId, f32)>>>,
+        counter: Option<Arc<ManualCounter>>,
+    }
+
+    impl RecordingQuery {
+        fn new(docs: Vec<DocId>) -> RecordingQuery {
+            RecordingQuery {
+                docs,
+                thresholds: Arc::new(RwLock::new(vec![])),
+                counter: None,
+            }
+        }
+
+        fn with_counter(mut self, counter: Arc<ManualCounter>) -> RecordingQuery {
+            self.counter = Some(counter);
+            self
+        }
+    }
+
+    impl<C: Codec> Query<C> for RecordingQuery {
+        fn create_weight(
+            &self,
+            _searcher: &dyn SearchPlanBuilder<C>,
+            _needs_scores: bool,
+        ) -> Result<Box<dyn Weight<C>>> {
+            Ok(Box::new(RecordingWeight {
+                docs: self.docs.clone(),
+                thresholds: Arc::clone(&self.thresholds),
+                counter: self.counter.clone(),
+            }))
+        }
+
+        fn extract_terms(&self) -> Vec<TermQuery> {
+            unimplemented!()
+        }
+
+        fn query_type(&self) -> &'static str {
+            MOCK_QUERY
+        }
+
+        fn as_any(&self) -> &::std::any::Any {
+            unreachable!()
+        }
+    }
+
+    impl fmt::Display for RecordingQuery {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "RecordingQuery")
+        }
+    }
+
+    struct RecordingWeight {
+        docs: Vec<DocId>,
+        thresholds: Arc<RwLock<Vec<(DocId, f32)>>>,
+        counter: Option<Arc<ManualCounter>>,
+    }
+
+    impl<C: Codec> Weight<C> for RecordingWeight {
+        fn create_scorer(
+            &self,
+            _reader: &LeafReaderContext<'_, C>,
+        ) -> Result<Option<Box<dyn Scorer>>> {
+            Ok(Some(Box::new(RecordingQueryScorer {
+                scorer: create_mock_scorer(self.docs.clone()),
+                thresholds: Arc::clone(&self.thresholds),
+                counter: self.counter.clone(),
+            })))
+        }
+
+        fn query_type(&self) -> &'static str {
+            MOCK_QUERY
+        }
+
+        fn normalize(&mut self, _norm: f32, _boost: f32) {}
+
+        fn value_for_normalization(&self) -> f32 {
+            0.0
+        }
+
+        fn needs_scores(&self) -> bool {
+            true
+        }
+
+        fn explain(&self, _reader: &LeafReaderContext<'_, C>, _doc: DocId) -> Result<Explanation> {
+            unimplemented!()
+        }
+    }
+
+    impl fmt::Display for RecordingWeight {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "RecordingWeight")
+        }
+    }
+
+    struct RecordingQueryScorer {
+        scorer: MockSimpleScorer<MockDocIterator>,
+        thresholds: Arc<RwLock<Vec<(DocId, f32)>>>,
+        counter: Option<Arc<ManualCounter>>,
+    }
+
+    impl RecordingQueryScorer {
+        fn tick(&self) {
+            if let Some(ref counter) = self.counter {
+                counter.add_and_get(1);
+            }
+        }
+    }
+
+    impl Scorer for RecordingQueryScorer {
+        fn score(&mut self) -> Result<f32> {
+            self.scorer.score()
+        }
+
+        fn set_min_competitive_score(&mut self, min_score: f32) -> Result<()> {
+            let doc = self.doc_id();
+            self.thresholds.write()?.push((doc, min_score));
+            Ok(())
+        }
+    }
+
+    impl DocIterator for RecordingQueryScorer {
+        fn doc_id(&self) -> DocId {
+            self.scorer.doc_id()
+        }
+
+        fn next(&mut self) -> Result<DocId> {
+            self.tick();
+            self.scorer.next()
+        }
+
+        fn advance(&mut self, target: DocId) -> Result<DocId> {
+            self.tick();
+            self.scorer.advance(target)
+        }
+
+        fn cost(&self) -> usize {
+            self.scorer.cost()
+        }
+    }
+
+    #[test]
+    fn test_search_with_min_score() {
+        let index_reader: Arc<dyn IndexReader<Codec = TestCodec>> =
+            Arc::new(MockIndexReader::new(vec![
+                MockLeafReader::new(0),
+                MockLeafReader::new(10),
+                MockLeafReader::new(20),
+            ]));
+        let searcher = DefaultIndexSearcher::new(index_reader);
+        let query = RecordingQuery::new(vec![1, 2, 3, 4, 5]);
+
+        let mut collector = TopDocsCollector::new(100);
+        searcher.search(&query, &mut collector).unwrap();
+        let top_docs = collector.top_docs();
+        let expected: Vec<(DocId, f32)> = top_docs
+            .score_docs()
+            .iter()
+            .filter(|d| d.score() >= 3.0)
+            .map(|d| (d.doc_id(), d.score()))
+            .collect();
+        assert!(query.thresholds.read().unwrap().is_empty());
+
+        let (top_docs, suppressed) = searcher.search_with_min_score(&query, 100, 3.0).unwrap();
+        let hits: Vec<(DocId, f32)> = top_docs
+            .score_docs()
+            .iter()
+            .map(|d| (d.doc_id(), d.score()))
+            .collect();
+        assert_eq!(hits, expected);
+        assert_eq!(top_docs.total_hits(), 9);
+        assert_eq!(suppressed, 6);
+        // every leaf scorer got the threshold before collecting
+        assert_eq!(*query.thresholds.read().unwrap(), vec![(-1, 3.0); 3]);
+    }
+
+    #[test]
+    fn test_min_competitive_score_per_leaf() {
+        let index_reader: Arc<dyn IndexReader<Codec = TestCodec>> =
+            Arc::new(MockIndexReader::new(vec![
+                MockLeafReader::new(0),
+                MockLeafReader::new(10),
+                MockLeafReader::new(20),
+            ]));
+        let searcher = DefaultIndexSearcher::new(index_reader);
+        let query = RecordingQuery::new(vec![1, 2, 3, 4, 5]);
+        let mut collector = TopDocsCollector::new(2).with_total_hits_threshold(0);
+        searcher.search(&query, &mut collector).unwrap();
+
+        // the scorers of the leaves after the first start with the threshold
+        // of the hits collected so far, before their first doc
+        let initial: Vec<f32> = query
+            .thresholds
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|t| t.0 == -1)
+            .map(|t| t.1)
+            .collect();
+        assert_eq!(
+            initial,
+            vec![FloatPoint::next_up(4.0), FloatPoint::next_up(5.0)]
+        );
+    }
+
+    // doc i has i + 1 values, so the docs score differently by their norms
+    fn index_tags(norm_encoding: NormEncoding) -> Arc<RAMDirectory> {
+        let dir = Arc::new(RAMDirectory::new());
+        let mut conf = IndexWriterConfig::default();
+        conf.norm_encoding = norm_encoding;
+        let writer = IndexWriter::new(Arc::clone(&dir), Arc::new(conf)).unwrap();
+        let mut field_type = FieldType::default();
+        field_type.tokenized = false;
+        field_type.omit_norms = false;
+        field_type.index_options = IndexOptions::DocsAndFreqs;
+        for i in 0..4 {
+            let doc: Vec<Field> = (0..=i)
+                .map(|j| {
+                    let value = if j == 0 {
+                        "x".to_string()
+                    } else {
+                        format!("y{}", j)
+                    };
+                    Field::new(
+                        "tag".to_string(),
+                        field_type.clone(),
+                        Some(VariantValue::VString(value)),
+                        None,
+                    )
+                })
+                .collect();
+            writer.add_document(doc).unwrap();
+        }
+        writer.commit().unwrap();
+        writer.close().unwrap();
+        dir
+    }
+
+    fn scores<SP: SimilarityProducer<CodecEnum>>(
+        searcher: &DefaultIndexSearcher<CodecEnum, TagReader, Arc<TagReader>, SP>,
+    ) -> Vec<(DocId, f32)> {
+        let query = TermQuery::new(Term::new("tag".to_string(), b"x".to_vec()), 1.0, None);
+        let mut collector = TopDocsCollector::new(10);
+        searcher.search(&query, &mut collector).unwrap();
+        collector
+            .top_docs()
+            .score_docs()
+            .iter()
+            .map(|d| (d.doc_id(), d.score()))
+            .collect()
+    }
+
+    type TagReader =
+        StandardDirectoryReader<RAMDirectory, CodecEnum, SerialMergeScheduler, TieredMergePolicy>;
+
+    #[test]
+    fn test_term_context_cache() {
+        let reader = Arc::new(TagReader::open(index_tags(BM25_NORM_ENCODING)).unwrap());
+        let mut searcher = DefaultIndexSearcher::new(Arc::clone(&reader));
+        let x = Term::new("tag".to_string(), b"x".to_vec());
+        let y1 = Term::new("tag".to_string(), b"y1".to_vec());
+
+        let context = searcher.term_state(&x).unwrap();
+        assert_eq!(context.doc_freq, 4);
+        assert!(Arc::ptr_eq(&context, &searcher.term_state(&x).unwrap()));
+        assert_eq!(searcher.term_contexts.hit_count(), 1);
+        assert_eq!(searcher.term_state(&y1).unwrap().doc_freq, 3);
+        assert_eq!(searcher.term_contexts.len(), 2);
+
+        // the least recently used contexts are dropped
+        searcher.set_term_context_cache_size(1);
+        let context = searcher.term_state(&x).unwrap();
+        searcher.term_state(&y1).unwrap();
+        assert_eq!(searcher.term_contexts.len(), 1);
+        assert_eq!(searcher.term_contexts.eviction_count(), 1);
+        assert!(!Arc::ptr_eq(&context, &searcher.term_state(&x).unwrap()));
+    }
+
+    #[test]
+    fn test_check_norm_encodings() {
+        let bm25_dir = index_tags(BM25_NORM_ENCODING);
+        let classic_dir = index_tags(CLASSIC_NORM_ENCODING);
+        let bm25_reader = Arc::new(TagReader::open(bm25_dir).unwrap());
+        let classic_reader = Arc::new(TagReader::open(classic_dir).unwrap());
+        let fi = bm25_reader.leaves()[0].reader.field_infos().by_name["tag"].clone();
+        assert_eq!(
+            fi.attribute(NORM_ENCODING_ATTRIBUTE),
+            Some(BM25_NORM_ENCODING.name.to_string())
+        );
+
+        let native = DefaultIndexSearcher::with_similarity_checked(
+            Arc::clone(&classic_reader),
+            ClassicSimilarityProducer,
+            NormMismatchPolicy::Fail,
+        )
+        .unwrap();
+        let expected = scores(&native);
+        assert_eq!(expected.len(), 4);
+        assert_eq!(expected[0].0, 0);
+
+        // the default similarity matches the default encoding
+        assert!(DefaultIndexSearcher::with_similarity_checked(
+            Arc::clone(&bm25_reader),
+            DefaultSimilarityProducer,
+            NormMismatchPolicy::Fail,
+        )
+        .is_ok());
+
+        let failed = DefaultIndexSearcher::with_similarity_checked(
+            Arc::clone(&bm25_reader),
+            ClassicSimilarityProducer,
+            NormMismatchPolicy::Fail,
+        );
+        match failed {
+            Err(e) => match e.kind() {
+                ErrorKind::IllegalState(msg) => {
+                    assert!(msg.contains("'tag'"), "{}", msg);
+                    assert!(msg.contains(BM25_NORM_ENCODING.name), "{}", msg);
+                }
+                _ => panic!("expected IllegalState, got {:?}", e),
+            },
+            Ok(_) => panic!("searched BM25 norms with ClassicSimilarity"),
+        }
+
+        let remapped = DefaultIndexSearcher::with_similarity_checked(
+            Arc::clone(&bm25_reader),
+            ClassicSimilarityProducer,
+            NormMismatchPolicy::Remap,
+        )
+        .unwrap();
+        assert_eq!(scores(&remapped), expected);
+
+        let query = TermQuery::new(Term::new("tag".to_string(), b"x".to_vec()), 1.0, None);
+        let explanation = remapped.explain(&query, 0).unwrap();
+        assert!(
+            explanation.to_string(0).contains(BM25_NORM_ENCODING.name),
+            "{}",
+            explanation.to_string(0)
+        );
+    }
+
+    #[test]
+    fn test_remap_norms_of_another_encoding() {
+        // the lengths are recorded exactly, but BM25Similarity decodes them
+        // as the lossy encoding of Lucene 6
+        let native_reader = Arc::new(TagReader::open(index_tags(BM25_NORM_ENCODING)).unwrap());
+        let exact_reader =
+            Arc::new(TagReader::open(index_tags(BM25_LENGTH_NORM_ENCODING)).unwrap());
+        let native = DefaultIndexSearcher::with_similarity_checked(
+            native_reader,
+            DefaultSimilarityProducer,
+            NormMismatchPolicy::Fail,
+        )
+        .unwrap();
+        let expected = scores(&native);
+        assert_eq!(expected.len(), 4);
+
+        assert!(DefaultIndexSearcher::with_similarity_checked(
+            Arc::clone(&exact_reader),
+            DefaultSimilarityProducer,
+            NormMismatchPolicy::Fail,
+        )
+        .is_err());
+
+        // the norms are misread as huge lengths
+        let ignored = DefaultIndexSearcher::with_similarity_checked(
+            Arc::clone(&exact_reader),
+            DefaultSimilarityProducer,
+            NormMismatchPolicy::Ignore,
+        )
+        .unwrap();
+        let ignored = scores(&ignored);
+        for (doc, score) in &expected {
+            let (_, misread) = ignored.iter().find(|d| d.0 == *doc).unwrap();
+            assert!(
+                *misread < score * 0.5,
+                "doc {}: {} vs {}",
+                doc,
+                misread,
+                score
+            );
+        }
+
+        // the remapped norms decode to the nearest lengths of the lossy
+        // encoding, the scores are the native ones up to one step of it
+        let remapped = DefaultIndexSearcher::with_similarity_checked(
+            exact_reader,
+            DefaultSimilarityProducer,
+            NormMismatchPolicy::Remap,
+        )
+        .unwrap();
+        let remapped = scores(&remapped);
+        let docs = |scores: &[(DocId, f32)]| scores.iter().map(|d| d.0).collect::<Vec<_>>();
+        assert_eq!(docs(&remapped), docs(&expected));
+        for ((_, score), (_, native_score)) in remapped.iter().zip(&expected) {
+            assert!(
+                (score - native_score).abs() < native_score * 0.2,
+                "{} vs {}",
+                score,
+                native_score
+            );
+        }
+        // the shortest and the longest lengths are exact in both encodings
+        assert_eq!(remapped[0], expected[0]);
+        assert_eq!(remapped[3], expected[3]);
+    }
+
+    // segment i has 5 + i docs, a doc matching "x" once to four times
+    fn index_segments() -> Arc<TagReader> {
+        let dir = Arc::new(RAMDirectory::new());
+        let writer =
+            IndexWriter::new(Arc::clone(&dir), Arc::new(IndexWriterConfig::default())).unwrap();
+        let mut field_type = FieldType::default();
+        field_type.omit_norms = false;
+        field_type.index_options = IndexOptions::DocsAndFreqs;
+        let mut doc = 0;
+        for segment in 0..8 {
+            for _ in 0..5 + segment {
+                let mut words = vec!["x"; doc % 4 + 1];
+                words.extend(vec!["y"; doc % 3]);
+                let fields: Vec<Box<dyn Fieldable>> = vec![
+                    Box::new(Field::new(
+                        "body".to_string(),
+                        field_type.clone(),
+                        Some(VariantValue::VString(words.join(" "))),
+                        None,
+                    )),
+                    Box::new(NumericDocValuesField::new("rank", (doc * 7 % 11) as i64)),
+                ];
+                writer.add_document(fields).unwrap();
+                doc += 1;
+            }
+            writer.commit().unwrap();
+        }
+        writer.close().unwrap();
+        Arc::new(TagReader::open(dir).unwrap())
+    }
+
+    fn score_hits(top_docs: &TopDocs) -> Vec<(DocId, u32)> {
+        top_docs
+            .score_docs()
+            .iter()
+            .map(|d| (d.doc_id(), d.score().to_bits()))
+            .collect()
+    }
+
+    fn field_hits(top_docs: &TopDocs) -> Vec<(DocId, u32, Vec<VariantValue>)> {
+        top_docs
+            .score_docs()
+            .iter()
+            .map(|hit| match hit {
+                ScoreDocHit::Field(f) => (f.doc, f.score.to_bits(), f.fields.clone()),
+                ScoreDocHit::Score(_) => panic!("expected field docs"),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_search_slices_in_parallel() {
+        let reader = index_segments();
+        let leaves = reader.leaves();
+        assert_eq!(leaves.len(), 8);
+        assert_eq!(slices(&leaves), vec![vec![3, 4, 5, 6, 7], vec![0, 1, 2]]);
+
+        let single = DefaultIndexSearcher::new(Arc::clone(&reader));
+        let pool = ThreadPoolBuilder::with_default_factory("search".into())
+            .thread_count(4)
+            .build();
+        let parallel = DefaultIndexSearcher::new(Arc::clone(&reader)).with_executor(Arc::new(pool));
+        let query = TermQuery::new(Term::new("body".to_string(), b"x".to_vec()), 1.0, None);
+
+        let mut expected = TopDocsCollector::new(12);
+        single.search(&query, &mut expected).unwrap();
+        let expected = expected.top_docs();
+        assert_eq!(expected.total_hits(), 68);
+        for _ in 0..5 {
+            let mut collector = TopDocsCollector::new(12);
+            parallel.search(&query, &mut collector).unwrap();
+            let top_docs = collector.top_docs();
+            assert_eq!(top_docs.total_hits(), expected.total_hits());
+            assert_eq!(score_hits(&top_docs), score_hits(&expected));
+        }
+
+        let rank = SimpleSortField::new("rank".to_string(), SortFieldType::Long, true);
+        let sort = Sort::new(vec![SortField::Simple(rank), SortField::new_score()]);
+        let mut expected = TopFieldCollector::new(sort.clone(), 15, true).unwrap();
+        single.search(&query, &mut expected).unwrap();
+        let expected = expected.top_docs();
+        for _ in 0..5 {
+            let mut collector = TopFieldCollector::new(sort.clone(), 15, true).unwrap();
+            parallel.search(&query, &mut collector).unwrap();
+            let top_docs = collector.top_docs();
+            assert_eq!(top_docs.total_hits(), expected.total_hits());
+            assert_eq!(field_hits(&top_docs), field_hits(&expected));
+        }
+    }
+
+    #[test]
+    fn test_search_with_scorer_arena() {
+        let reader = index_segments();
+        let plain = DefaultIndexSearcher::new(Arc::clone(&reader));
+        let arena = Arc::new(ScorerArena::new());
+        let mut pooled = DefaultIndexSearcher::new(Arc::clone(&reader));
+        pooled.set_scorer_arena(Arc::clone(&arena));
+        let pool = ThreadPoolBuilder::with_default_factory("search".into())
+            .thread_count(4)
+            .build();
+        let mut parallel =
+            DefaultIndexSearcher::new(Arc::clone(&reader)).with_executor(Arc::new(pool));
+        parallel.set_scorer_arena(Arc::clone(&arena));
+
+        let term = |text: &str| -> Box<dyn Query<CodecEnum>> {
+            Box::new(TermQuery::new(
+                Term::new("body".to_string(), text.as_bytes().to_vec()),
+                1.0,
+                None,
+            ))
+        };
+        let queries = vec![
+            BooleanQuery::build(vec![], vec![term("x"), term("y"), term("z")], vec![]).unwrap(),
+            BooleanQuery::build(vec![term("x"), term("y")], vec![], vec![]).unwrap(),
+            BooleanQuery::build(vec![term("x")], vec![term("y")], vec![]).unwrap(),
+            BooleanQuery::build_with_must_nots(
+                vec![term("x")],
+                vec![],
+                vec![],
+                vec![term("y"), term("z")],
+            )
+            .unwrap(),
+        ];
+        for query in &queries {
+            let mut expected = TopDocsCollector::new(100);
+            plain.search(query.as_ref(), &mut expected).unwrap();
+            let expected = expected.top_docs();
+            assert!(expected.total_hits() > 0);
+            for searcher in &[&pooled, &parallel] {
+                let mut collector = TopDocsCollector::new(100);
+                searcher.search(query.as_ref(), &mut collector).unwrap();
+                let top_docs = collector.top_docs();
+                assert_eq!(top_docs.total_hits(), expected.total_hits());
+                assert_eq!(score_hits(&top_docs), score_hits(&expected));
+            }
+        }
+        // the buffers of the first leaves were reused by the next ones
+        assert!(arena.reused() > arena.allocated());
+    }
+
+    // the scorers of the leaf starting at `failing_doc_base` fail to score
+    struct FailingQuery {
+        failing_doc_base: DocId,
+    }
+
+    impl<C: Codec> Query<C> for FailingQuery {
+        fn create_weight(
+            &self,
+            _searcher: &dyn SearchPlanBuilder<C>,
+            _needs_scores: bool,
+        ) -> Result<Box<dyn Weight<C>>> {
+            Ok(Box::new(FailingWeight {
+                failing_doc_base: self.failing_doc_base,
+            }))
+        }
+
+        fn extract_terms(&self) -> Vec<TermQuery> {
+            unimplemented!()
+        }
+
+        fn query_type(&self) -> &'static str {
+            MOCK_QUERY
+        }
+
+        fn as_any(&self) -> &::std::any::Any {
+            unreachable!()
+        }
+    }
+
+    impl fmt::Display for FailingQuery {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "FailingQuery")
+        }
+    }
+
+    struct FailingWeight {
+        failing_doc_base: DocId,
+    }
+
+    impl<C: Codec> Weight<C> for FailingWeight {
+        fn create_scorer(
+            &self,
+            reader: &LeafReaderContext<'_, C>,
+        ) -> Result<Option<Box<dyn Scorer>>> {
+            Ok(Some(Box::new(FailingScorer {
+                scorer: create_mock_scorer(vec![1, 2, 3]),
+                fails: reader.doc_base == self.failing_doc_base,
+            })))
+        }
+
+        fn query_type(&self) -> &'static str {
+            MOCK_QUERY
+        }
+
+        fn normalize(&mut self, _norm: f32, _boost: f32) {}
+
+        fn value_for_normalization(&self) -> f32 {
+            0.0
+        }
+
+        fn needs_scores(&self) -> bool {
+            true
+        }
+
+        fn explain(&self, _reader: &LeafReaderContext<'_, C>, _doc: DocId) -> Result<Explanation> {
+            unimplemented!()
+        }
+    }
+
+    impl fmt::Display for FailingWeight {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "FailingWeight")
+        }
+    }
+
+    struct FailingScorer {
+        scorer: MockSimpleScorer<MockDocIterator>,
+        fails: bool,
+    }
+
+    impl Scorer for FailingScorer {
+        fn score(&mut self) -> Result<f32> {
+            if self.fails {
+                bail!(ErrorKind::IllegalState("failed to score".into()));
+            }
+            self.scorer.score()
+        }
+    }
+
+    impl DocIterator for FailingScorer {
+        fn doc_id(&self) -> DocId {
+            self.scorer.doc_id()
+        }
+
+        fn next(&mut self) -> Result<DocId> {
+            self.scorer.next()
+        }
+
+        fn advance(&mut self, target: DocId) -> Result<DocId> {
+            self.scorer.advance(target)
+        }
+
+        fn cost(&self) -> usize {
+            self.scorer.cost()
+        }
+    }
+
+    #[test]
+    fn test_search_slices_error() {
+        let index_reader: Arc<dyn IndexReader<Codec = TestCodec>> =
+            Arc::new(MockIndexReader::new(vec![
+                MockLeafReader::new(0),
+                MockLeafReader::new(10),
+                MockLeafReader::new(20),
+            ]));
+        let mut searcher = DefaultIndexSearcher::new(index_reader);
+        searcher.with_thread_pool(2);
+
+        let mut collector = TopDocsCollector::new(10);
+        searcher
+            .search(
+                &FailingQuery {
+                    failing_doc_base: 40,
+                },
+                &mut collector,
+            )
+            .unwrap();
+        assert_eq!(collector.top_docs().total_hits(), 9);
+
+        let mut collector = TopDocsCollector::new(10);
+        let result = searcher.search(
+            &FailingQuery {
+                failing_doc_base: 10,
+            },
+            &mut collector,
+        );
+        match result {
+            Err(e) => match e.kind() {
+                ErrorKind::IllegalState(msg) => assert_eq!(msg, "failed to score"),
+                _ => panic!("expected IllegalState, got {:?}", e),
+            },
+            Ok(_) => panic!("the failure of a slice was lost"),
+        }
+    }
+
+    type CountingReader = StandardDirectoryReader<
+        CountingDirectory,
+        CodecEnum,
+        SerialMergeScheduler,
+        TieredMergePolicy,
+    >;
+
+    fn counting_writer(
+        dir: &Arc<CountingDirectory>,
+    ) -> IndexWriter<CountingDirectory, CodecEnum, SerialMergeScheduler, TieredMergePolicy> {
+        let mut config = IndexWriterConfig::default();
+        config.use_compound_file = false;
+        IndexWriter::new(Arc::clone(dir), Arc::new(config)).unwrap()
+    }
+
+    // segment s has the values s * 100 + i for i in 0..10, tagged by parity
+    fn index_values(dir: &Arc<CountingDirectory>) {
+        let writer = counting_writer(dir);
+        for segment in 0..2i64 {
+            for i in 0..10 {
+                let value = segment * 100 + i;
+                let tag = if i % 2 == 0 { "even" } else { "odd" };
+                let doc: Vec<Box<dyn Fieldable>> = vec![
+                    Box::new(StringField::new("id", &value.to_string(), Store::No)),
+                    Box::new(StringField::new("tag", tag, Store::No)),
+                    Box::new(LongPoint::new("value", &[value]).unwrap()),
+                ];
+                writer.add_document(doc).unwrap();
+            }
+            writer.commit().unwrap();
+        }
+        writer.close().unwrap();
+    }
+
+    fn count_queries() -> Vec<Box<dyn Query<CodecEnum>>> {
+        let tag = |value: &str| Term::new("tag".to_string(), value.as_bytes().to_vec());
+        let range = |lower: i64, upper: i64| {
+            PointRangeQuery::new_long_range("value".to_string(), &[lower], &[upper]).unwrap()
+        };
+        vec![
+            Box::new(TermQuery::new(tag("even"), 1.0, None)),
+            Box::new(TermQuery::new(tag("missing"), 1.0, None)),
+            Box::new(MatchAllDocsQuery),
+            // both segments are in the range
+            Box::new(range(0, 200)),
+            // the first segment is in the range, the second is out of it
+            Box::new(range(0, 50)),
+            // crossing both segments
+            Box::new(range(5, 104)),
+        ]
+    }
+
+    // the count and the bytes of postings and points read to count
+    fn count_reads<IS: IndexSearcher<CodecEnum>>(
+        searcher: &IS,
+        query: &dyn Query<CodecEnum>,
+        dir: &CountingDirectory,
+    ) -> (usize, usize) {
+        let before = dir.reads();
+        let hits = searcher.count(query).unwrap();
+        (hits, dir.reads() - before)
+    }
+
+    // the counts the weight of `query` tells for every leaf
+    fn weight_counts<IS: IndexSearcher<CodecEnum>>(
+        searcher: &IS,
+        query: &dyn Query<CodecEnum>,
+    ) -> Vec<Option<usize>> {
+        let weight = searcher.create_weight(query, false).unwrap();
+        searcher
+            .reader()
+            .leaves()
+            .iter()
+            .map(|leaf| weight.count(leaf).unwrap())
+            .collect()
+    }
+
+    fn exhaustive_hits<IS: IndexSearcher<CodecEnum>>(
+        searcher: &IS,
+        query: &dyn Query<CodecEnum>,
+    ) -> usize {
+        let mut collector = TopDocsCollector::new(100);
+        searcher.search(query, &mut collector).unwrap();
+        collector.top_docs().total_hits() as usize
+    }
+
+    #[test]
+    fn test_count_fast_path() {
+        // counts the bytes read from the postings and the points data files
+        let dir = Arc::new(CountingDirectory::counting_reads_of(&[".doc", ".dim"]));
+        index_values(&dir);
+
+        let reader = Arc::new(CountingReader::open(Arc::clone(&dir)).unwrap());
+        assert_eq!(reader.leaves().len(), 2);
+        let searcher = DefaultIndexSearcher::new(Arc::clone(&reader));
+        let queries = count_queries();
+        // the doc freqs tell the counts of the term queries
+        assert_eq!(
+            weight_counts(&searcher, queries[0].as_ref()),
+            vec![Some(5), Some(5)]
+        );
+        assert_eq!(
+            weight_counts(&searcher, queries[1].as_ref()),
+            vec![Some(0), Some(0)]
+        );
+        // without deletions, only the crossing range reads postings or points
+        let expected = [(10, 0), (0, 0), (20, 0), (20, 0), (10, 0)];
+        for (query, &expected) in queries.iter().zip(expected.iter()) {
+            assert_eq!(count_reads(&searcher, query.as_ref(), &dir), expected);
+        }
+        let (hits, range_reads) = count_reads(&searcher, queries[5].as_ref(), &dir);
+        assert_eq!(hits, 10);
+        assert!(range_reads > 0);
+        for query in &queries {
+            assert_eq!(
+                searcher.count(query.as_ref()).unwrap(),
+                exhaustive_hits(&searcher, query.as_ref())
+            );
+        }
+
+        // the doc freqs and the point counts of the first segment count the
+        // deleted docs, its matches are iterated
+        let writer = counting_writer(&dir);
+        writer.delete_documents_by_term("id", b"2").unwrap();
+        writer.delete_documents_by_term("id", b"7").unwrap();
+        writer.commit().unwrap();
+        writer.close().unwrap();
+        let reader = Arc::new(CountingReader::open(Arc::clone(&dir)).unwrap());
+        let searcher = DefaultIndexSearcher::new(Arc::clone(&reader));
+        assert_eq!(
+            weight_counts(&searcher, queries[0].as_ref()),
+            vec![None, Some(5)]
+        );
+        let (hits, term_reads) = count_reads(&searcher, queries[0].as_ref(), &dir);
+        assert_eq!(hits, 9);
+        assert!(term_reads > 0);
+        let (hits, range_reads) = count_reads(&searcher, queries[3].as_ref(), &dir);
+        assert_eq!(hits, 18);
+        assert!(range_reads > 0);
+        assert_eq!(count_reads(&searcher, queries[2].as_ref(), &dir), (18, 0));
+
+        let pool = ThreadPoolBuilder::with_default_factory("search".into())
+            .thread_count(2)
+            .build();
+        let parallel = DefaultIndexSearcher::new(Arc::clone(&reader)).with_executor(Arc::new(pool));
+        for query in &queries {
+            let expected = exhaustive_hits(&searcher, query.as_ref());
+            assert_eq!(searcher.count(query.as_ref()).unwrap(), expected);
+            assert_eq!(parallel.count(query.as_ref()).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn test_search_with_timeout() {
+        let index_reader: Arc<dyn IndexReader<Codec = TestCodec>> =
+            Arc::new(MockIndexReader::new(vec![
+                MockLeafReader::new(0),
+                MockLeafReader::new(10),
+                MockLeafReader::new(20),
+            ]));
+        let searcher = DefaultIndexSearcher::new(index_reader);
+
+        // three leaves of 200 docs, the counter moving by one per doc: the
+        // deadline is seen at the first check after 50 docs, before doc 64
+        let counter = Arc::new(ManualCounter::default());
+        let query = RecordingQuery::new((0..200).collect()).with_counter(Arc::clone(&counter));
+        let timeout = QueryTimeout::with_counter(Arc::clone(&counter) as Arc<dyn Counter>, 50);
+        let mut collector = TopDocsCollector::new(1000);
+        let timed_out = searcher
+            .search_cancellable(&query, &mut collector, &timeout)
+            .unwrap();
+        assert!(timed_out);
+        assert!(timeout.is_cancelled());
+
+        // the partial results hold the docs collected before the deadline, the
+        // first docs of the first leaf
+        let top_docs = collector.top_docs();
+        assert_eq!(top_docs.total_hits(), 64);
+        let mut docs: Vec<DocId> = top_docs.score_docs().iter().map(|d| d.doc_id()).collect();
+        docs.sort();
+        assert_eq!(docs, (0..64).collect::<Vec<_>>());
+
+        let query = RecordingQuery::new((0..20).collect());
+        let mut collector = TopDocsCollector::new(100);
+        let timed_out = searcher
+            .search_with_timeout(&query, &mut collector, Duration::from_secs(60))
+            .unwrap();
+        assert!(!timed_out);
+        assert_eq!(collector.top_docs().total_hits(), 60);
+    }
+
+    #[test]
+    fn test_cancelled_enumeration() {
+        let dir = Arc::new(CountingDirectory::new());
+        index_values(&dir);
+        let reader = Arc::new(CountingReader::open(Arc::clone(&dir)).unwrap());
+        let leaves = reader.leaves();
+        let searcher = DefaultIndexSearcher::new(Arc::clone(&reader));
+        let id = Term::new("id".to_string(), b"105".to_vec());
+        let queries: Vec<Box<dyn Query<CodecEnum>>> = vec![
+            Box::new(PrefixQuery::new("id".to_string(), b"1".to_vec())),
+            Box::new(FuzzyQuery::new(id, 1, 0, 50).unwrap()),
+            Box::new(PointRangeQuery::new_long_range("value".to_string(), &[5], &[104]).unwrap()),
+        ];
+        let cancelled = QueryTimeout::new();
+        cancelled.cancel();
+
+        for query in &queries {
+            let expected = exhaustive_hits(&searcher, query.as_ref());
+            assert!(expected > 0);
+            let mut collector = TopDocsCollector::new(100);
+            let timed_out = searcher
+                .search_cancellable(query.as_ref(), &mut collector, &QueryTimeout::new())
+                .unwrap();
+            assert!(!timed_out);
+            assert_eq!(collector.top_docs().total_hits() as usize, expected);
+
+            // the terms or the points are enumerated before any doc is collected,
+            // the enumeration itself stops, also when profiled
+            let plans = vec![
+                SearchPlan::new(&searcher).with_timeout(&cancelled),
+                SearchPlan::new(&searcher)
+                    .with_timeout(&cancelled)
+                    .profiled(),
+            ];
+            for plan in &plans {
+                let scorer = plan
+                    .create_weight(query.as_ref(), true)
+                    .and_then(|weight| weight.create_scorer(&leaves[0]).map(|_| ()));
+                match scorer {
+                    Err(Error(ErrorKind::Cancelled(_), _)) => {}
+                    other => panic!("expected {} to be cancelled, got {:?}", query, other),
+                }
+            }
+            let mut collector = TopDocsCollector::new(100);
+            let timed_out = searcher
+                .search_cancellable(query.as_ref(), &mut collector, &cancelled)
+                .unwrap();
+            assert!(timed_out);
+            assert_eq!(collector.top_docs().total_hits(), 0);
+        }
+    }
 }
@@ -0,0 +1,821 @@
+// Copyright 2019 Zhizhesihai (Beijing) Technology Limited.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use core::codec::Codec;
+use core::index::PointValues;
+use core::index::{IndexReader, LeafReaderContext, SearchLeafReader};
+use core::index::{SeekStatus, Term, TermIterator, Terms};
+use core::search::boolean_query::BooleanQuery;
+use core::search::boost::BoostQuery;
+use core::search::legacy_numeric_range::LegacyNumericRangeQuery;
+use core::search::match_all::{ConstantScoreQuery, MatchAllDocsQuery};
+use core::search::point_range::PointRangeQuery;
+use core::search::term_in_set::TermInSetQuery;
+use core::search::term_query::TermQuery;
+use core::search::Query;
+
+use error::Result;
+
+use std::cmp::{max, min};
+
+/// At most this many terms of a multi-term query are looked up per segment,
+/// the doc freqs of the other terms are extrapolated from them.
+pub const MAX_SAMPLED_TERMS: usize = 128;
+
+/// How far a `SelectivityEstimate` can be trusted, from the least to the
+/// most trustworthy.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SelectivityConfidence {
+    /// The query type isn't known, the bounds span all the docs.
+    Unknown,
+    /// Extrapolated from sampled terms, or interpolated over the value range
+    /// of the points.
+    Low,
+    /// Combined from the estimates of boolean clauses, assuming that they
+    /// match independent docs.
+    Medium,
+    /// Taken from the exact index statistics, which may still count deleted
+    /// or multi-valued docs.
+    High,
+    /// The number of matching docs.
+    Exact,
+}
+
+/// Estimate of the number of live docs a query matches.
+///
+/// Whatever the confidence, `min_docs <= matching docs <= max_docs` holds
+/// and `estimated_docs` lies within these bounds.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SelectivityEstimate {
+    pub estimated_docs: i32,
+    pub min_docs: i32,
+    pub max_docs: i32,
+    /// The number of live docs of the reader.
+    pub num_docs: i32,
+    pub confidence: SelectivityConfidence,
+    /// The boolean clause driving the estimate: the most selective required
+    /// clause of a conjunction, or the broadest clause of a disjunction.
+    pub dominant_clause: Option<String>,
+}
+
+impl SelectivityEstimate {
+    fn exact(docs: i32, num_docs: i32) -> SelectivityEstimate {
+        SelectivityEstimate {
+            estimated_docs: docs,
+            min_docs: docs,
+            max_docs: docs,
+            num_docs,
+            confidence: SelectivityConfidence::Exact,
+            dominant_clause: None,
+        }
+    }
+
+    fn unknown(num_docs: i32) -> SelectivityEstimate {
+        SelectivityEstimate {
+            estimated_docs: num_docs,
+            min_docs: 0,
+            max_docs: num_docs,
+            num_docs,
+            confidence: SelectivityConfidence::Unknown,
+            dominant_clause: None,
+        }
+    }
+
+    /// The estimated fraction of the live docs matching the query.
+    pub fn selectivity(&self) -> f64 {
+        if self.num_docs == 0 {
+            0.0
+        } else {
+            f64::from(self.estimated_docs) / f64::from(self.num_docs)
+        }
+    }
+
+    fn ratio(&self) -> f64 {
+        self.selectivity().min(1.0)
+    }
+}
+
+/// Sums the per segment estimates of a leaf query.
+struct LeafSum {
+    estimate: f64,
+    min_docs: i64,
+    max_docs: i64,
+    confidence: SelectivityConfidence,
+}
+
+impl LeafSum {
+    fn new() -> LeafSum {
+        LeafSum {
+            estimate: 0.0,
+            min_docs: 0,
+            max_docs: 0,
+            confidence: SelectivityConfidence::Exact,
+        }
+    }
+
+    /// Adds a segment whose `doc_freq` counts the matching docs, deleted ones
+    /// included.
+    fn add_doc_freq<C: Codec>(&mut self, reader: &SearchLeafReader<C>, doc_freq: i32) {
+        let deleted = reader.max_doc() - reader.num_docs();
+        if doc_freq > 0 && deleted > 0 {
+            self.confidence = min(self.confidence, SelectivityConfidence::High);
+        }
+        self.add(
+            reader,
+            f64::from(doc_freq),
+            max(doc_freq - deleted, 0),
+            doc_freq,
+            SelectivityConfidence::Exact,
+        );
+    }
+
+    /// Adds a segment where `estimate` docs of `min_docs..=max_docs` match,
+    /// all counts including the deleted docs.
+    fn add<C: Codec>(
+        &mut self,
+        reader: &SearchLeafReader<C>,
+        estimate: f64,
+        min_docs: i32,
+        max_docs: i32,
+        confidence: SelectivityConfidence,
+    ) {
+        let (live, max_doc) = (reader.num_docs(), reader.max_doc());
+        if max_doc == 0 {
+            return;
+        }
+        let max_docs = min(max_docs, live);
+        let min_docs = min(min_docs, max_docs);
+        let estimate = estimate * f64::from(live) / f64::from(max_doc);
+        self.estimate += estimate.max(f64::from(min_docs)).min(f64::from(max_docs));
+        self.min_docs += i64::from(min_docs);
+        self.max_docs += i64::from(max_docs);
+        self.confidence = min(self.confidence, confidence);
+    }
+
+    fn finish(self, num_docs: i32) -> SelectivityEstimate {
+        let max_docs = min(self.max_docs, i64::from(num_docs)) as i32;
+        let min_docs = min(self.min_docs, i64::from(max_docs)) as i32;
+        SelectivityEstimate {
+            estimated_docs: clamp(self.estimate, min_docs, max_docs),
+            min_docs,
+            max_docs,
+            num_docs,
+            confidence: self.confidence,
+            dominant_clause: None,
+        }
+    }
+}
+
+fn clamp(estimate: f64, min_docs: i32, max_docs: i32) -> i32 {
+    max(
+        min(estimate.round() as i64, i64::from(max_docs)),
+        i64::from(min_docs),
+    ) as i32
+}
+
+/// Estimates the number of docs of `reader` matching `query` from the terms
+/// dictionaries and the points indices only, no scorer is created.
+///
+/// The supported queries are estimated as follows, the others get
+/// `SelectivityConfidence::Unknown` bounds spanning all the docs:
+///
+/// * `TermQuery`: the doc freqs of the term, scaled by the live docs ratio of every segment.
+/// * `BooleanQuery`: the required clauses are combined as independent conjunctions, the product of
+///   their selectivities. Bounded above by the most selective clause. The should clauses, when they
+///   are required, as independent disjunctions, by inclusion-exclusion, bounded above by the sum of
+///   the clauses, capped at the number of docs.
+/// * `PointRangeQuery`: the docs having points for the field when the range contains all the points
+///   of a segment, none when it misses all of them, otherwise the fraction of the value range of
+///   the points it covers.
+/// * `TermInSetQuery`, `LegacyNumericRangeQuery`: the sum of the doc freqs of the terms, up to
+///   `MAX_SAMPLED_TERMS` terms per segment. The doc freqs of a term set larger than that are
+///   extrapolated from an evenly spaced sample of its terms, a term range hitting more terms is
+///   bounded by the docs having the field.
+pub fn estimate_selectivity<C: Codec, R: IndexReader<Codec = C> + ?Sized>(
+    reader: &R,
+    query: &dyn Query<C>,
+) -> Result<SelectivityEstimate> {
+    let leaves = reader.leaves();
+    estimate(&leaves, reader.num_docs(), query)
+}
+
+fn estimate<C: Codec>(
+    leaves: &[LeafReaderContext<'_, C>],
+    num_docs: i32,
+    query: &dyn Query<C>,
+) -> Result<SelectivityEstimate> {
+    let query = query.as_any();
+    if let Some(q) = query.downcast_ref::<ConstantScoreQuery<C>>() {
+        estimate(leaves, num_docs, q.get_raw_query())
+    } else if let Some(q) = query.downcast_ref::<BoostQuery<C>>() {
+        estimate(leaves, num_docs, q.query())
+    } else if query.downcast_ref::<MatchAllDocsQuery>().is_some() {
+        Ok(SelectivityEstimate::exact(num_docs, num_docs))
+    } else if let Some(q) = query.downcast_ref::<TermQuery>() {
+        estimate_term(leaves, num_docs, &q.term)
+    } else if let Some(q) = query.downcast_ref::<BooleanQuery<C>>() {
+        estimate_boolean(leaves, num_docs, q)
+    } else if let Some(q) = query.downcast_ref::<PointRangeQuery>() {
+        estimate_point_range(leaves, num_docs, q)
+    } else if let Some(q) = query.downcast_ref::<TermInSetQuery>() {
+        estimate_term_set(leaves, num_docs, q)
+    } else if let Some(q) = query.downcast_ref::<LegacyNumericRangeQuery>() {
+        estimate_term_ranges(leaves, num_docs, q.field(), q.ranges())
+    } else {
+        Ok(SelectivityEstimate::unknown(num_docs))
+    }
+}
+
+fn estimate_term<C: Codec>(
+    leaves: &[LeafReaderContext<'_, C>],
+    num_docs: i32,
+    term: &Term,
+) -> Result<SelectivityEstimate> {
+    let mut sum = LeafSum::new();
+    for leaf in leaves {
+        sum.add_doc_freq(leaf.reader, leaf.reader.doc_freq(term)?);
+    }
+    Ok(sum.finish(num_docs))
+}
+
+fn estimate_boolean<C: Codec>(
+    leaves: &[LeafReaderContext<'_, C>],
+    num_docs: i32,
+    query: &BooleanQuery<C>,
+) -> Result<SelectivityEstimate> {
+    let mut required = Vec::new();
+    for q in query.must_queries().iter().chain(query.filter_queries()) {
+        required.push((q.to_string(), estimate(leaves, num_docs, q.as_ref())?));
+    }
+    if query.minimum_should_match() > 0 && !query.should_queries().is_empty() {
+        let mut optional = Vec::with_capacity(query.should_queries().len());
+        for q in query.should_queries() {
+            optional.push((q.to_string(), estimate(leaves, num_docs, q.as_ref())?));
+        }
+        let msm = query.minimum_should_match() as usize;
+        let disjunction = disjunction(optional, msm, num_docs);
+        if required.is_empty() {
+            return Ok(disjunction);
+        }
+        let should_str: Vec<String> = query
+            .should_queries()
+            .iter()
+            .map(|q| q.to_string())
+            .collect();
+        required.push((format!("should: [{}]", should_str.join(", ")), disjunction));
+    }
+    Ok(conjunction(required, num_docs))
+}
+
+fn combined_confidence(clauses: &[(String, SelectivityEstimate)]) -> SelectivityConfidence {
+    let confidence = clauses
+        .iter()
+        .map(|(_, e)| e.confidence)
+        .min()
+        .unwrap_or(SelectivityConfidence::Exact);
+    if clauses.len() > 1 {
+        min(confidence, SelectivityConfidence::Medium)
+    } else {
+        confidence
+    }
+}
+
+fn conjunction(clauses: Vec<(String, SelectivityEstimate)>, num_docs: i32) -> SelectivityEstimate {
+    let confidence = combined_confidence(&clauses);
+    let n = i64::from(num_docs);
+    let max_docs = clauses
+        .iter()
+        .map(|(_, e)| e.max_docs)
+        .min()
+        .unwrap_or(num_docs);
+    let min_sum: i64 = clauses.iter().map(|(_, e)| i64::from(e.min_docs)).sum();
+    let min_docs = max(min_sum - (clauses.len() as i64 - 1) * n, 0) as i32;
+    let min_docs = min(min_docs, max_docs);
+    let ratio: f64 = clauses.iter().map(|(_, e)| e.ratio()).product();
+    let dominant_clause = clauses
+        .into_iter()
+        .min_by_key(|(_, e)| e.estimated_docs)
+        .map(|(clause, _)| clause);
+    SelectivityEstimate {
+        estimated_docs: clamp(ratio * f64::from(num_docs), min_docs, max_docs),
+        min_docs,
+        max_docs,
+        num_docs,
+        confidence,
+        dominant_clause,
+    }
+}
+
+// the docs matching at least `minimum_should_match` of the clauses
+fn disjunction(
+    clauses: Vec<(String, SelectivityEstimate)>,
+    minimum_should_match: usize,
+    num_docs: i32,
+) -> SelectivityEstimate {
+    let confidence = combined_confidence(&clauses);
+    let msm = max(minimum_should_match, 1);
+    if msm > clauses.len() {
+        return SelectivityEstimate::exact(0, num_docs);
+    }
+    let n = i64::from(num_docs);
+    // a matching doc is counted by at least `msm` clauses, and it is in at
+    // least one of any `len - msm + 1` clauses
+    let mut max_docs: Vec<i64> = clauses.iter().map(|(_, e)| i64::from(e.max_docs)).collect();
+    max_docs.sort();
+    let max_sum: i64 = max_docs.iter().sum();
+    let smallest_max_sum: i64 = max_docs[..clauses.len() - msm + 1].iter().sum();
+    let max_docs = min(min(max_sum / msm as i64, smallest_max_sum), n) as i32;
+    let min_docs = if msm == 1 {
+        clauses.iter().map(|(_, e)| e.min_docs).max().unwrap_or(0)
+    } else {
+        // every doc is counted by at most `msm - 1` clauses if it doesn't
+        // match, and by at most all of them if it does
+        let min_sum: i64 = clauses.iter().map(|(_, e)| i64::from(e.min_docs)).sum();
+        let excess = max(min_sum - (msm as i64 - 1) * n, 0);
+        let clauses_over_msm = (clauses.len() - msm + 1) as i64;
+        ((excess + clauses_over_msm - 1) / clauses_over_msm) as i32
+    };
+    let min_docs = min(min_docs, max_docs);
+    // match_ratios[i]: the probability that a doc matches exactly `i` of the
+    // clauses seen so far, `msm` standing for `msm` or more
+    let mut match_ratios = vec![0.0f64; msm + 1];
+    match_ratios[0] = 1.0;
+    for (_, e) in &clauses {
+        let ratio = e.ratio();
+        match_ratios[msm] += match_ratios[msm - 1] * ratio;
+        for i in (1..msm).rev() {
+            match_ratios[i] = match_ratios[i] * (1.0 - ratio) + match_ratios[i - 1] * ratio;
+        }
+        match_ratios[0] *= 1.0 - ratio;
+    }
+    let dominant_clause = clauses
+        .into_iter()
+        .max_by_key(|(_, e)| e.estimated_docs)
+        .map(|(clause, _)| clause);
+    SelectivityEstimate {
+        estimated_docs: clamp(match_ratios[msm] * f64::from(num_docs), min_docs, max_docs),
+        min_docs,
+        max_docs,
+        num_docs,
+        confidence,
+        dominant_clause,
+    }
+}
+
+/// The leading bytes of a dimension, as an unsigned integer preserving the
+/// byte order of the sortable encoding.
+fn dimension_value(packed: &[u8]) -> f64 {
+    let mut value = 0u64;
+    for i in 0..8 {
+        value = (value << 8) | u64::from(packed.get(i).cloned().unwrap_or(0));
+    }
+    value as f64
+}
+
+fn estimate_point_range<C: Codec>(
+    leaves: &[LeafReaderContext<'_, C>],
+    num_docs: i32,
+    query: &PointRangeQuery,
+) -> Result<SelectivityEstimate> {
+    let field = query.field();
+    let (num_dims, bytes_per_dim) = (query.num_dims(), query.bytes_per_dim());
+    let mut sum = LeafSum::new();
+    for leaf in leaves {
+        let reader = leaf.reader;
+        let field_info = match reader.field_info(field) {
+            Some(field_info) if field_info.point_dimension_count > 0 => field_info,
+            _ => continue,
+        };
+        let values = match reader.point_values() {
+            Some(values) => values,
+            None => continue,
+        };
+        if field_info.point_dimension_count as usize != num_dims
+            || field_info.point_num_bytes as usize != bytes_per_dim
+        {
+            sum.add(
+                reader,
+                f64::from(reader.max_doc()),
+                0,
+                reader.max_doc(),
+                SelectivityConfidence::Unknown,
+            );
+            continue;
+        }
+        let doc_count = values.doc_count(field)?;
+        if doc_count == 0 {
+            continue;
+        }
+        let field_lower = values.min_packed_value(field)?;
+        let field_upper = values.max_packed_value(field)?;
+
+        let mut contains_all = true;
+        let mut fraction = 1.0;
+        for i in 0..num_dims {
+            let range = i * bytes_per_dim..(i + 1) * bytes_per_dim;
+            let (lower, upper) = (
+                &query.lower_point()[range.clone()],
+                &query.upper_point()[range.clone()],
+            );
+            let (min_value, max_value) = (&field_lower[range.clone()], &field_upper[range]);
+            if upper < min_value || lower > max_value {
+                fraction = 0.0;
+                break;
+            }
+            if lower > min_value || upper < max_value {
+                contains_all = false;
+                let covered_lower = dimension_value(max(lower, min_value));
+                let covered_upper = dimension_value(min(upper, max_value));
+                let (min_value, max_value) =
+                    (dimension_value(min_value), dimension_value(max_value));
+                fraction *= (covered_upper - covered_lower + 1.0) / (max_value - min_value + 1.0);
+            }
+        }
+        if fraction == 0.0 {
+            // the range misses all the points of the segment
+        } else if contains_all {
+            sum.add_doc_freq(reader, doc_count);
+        } else {
+            sum.add(
+                reader,
+                f64::from(doc_count) * fraction.min(1.0),
+                0,
+                doc_count,
+                SelectivityConfidence::Low,
+            );
+        }
+    }
+    Ok(sum.finish(num_docs))
+}
+
+/// The number of docs having a term in the field, deleted ones included.
+fn docs_with_field<C: Codec>(reader: &SearchLeafReader<C>, terms: &impl Terms) -> Result<i32> {
+    Ok(match terms.doc_count()? {
+        -1 => reader.max_doc(),
+        doc_count => doc_count,
+    })
+}
+
+fn estimate_term_set<C: Codec>(
+    leaves: &[LeafReaderContext<'_, C>],
+    num_docs: i32,
+    query: &TermInSetQuery,
+) -> Result<SelectivityEstimate> {
+    let all_terms = query.terms();
+    let stride = (all_terms.len() + MAX_SAMPLED_TERMS - 1) / MAX_SAMPLED_TERMS;
+    let mut sum = LeafSum::new();
+    for leaf in leaves {
+        let reader = leaf.reader;
+        let terms = match reader.terms(query.field())? {
+            Some(terms) => terms,
+            None => continue,
+        };
+        let docs_with_field = docs_with_field(reader, &terms)?;
+        let deleted = reader.max_doc() - reader.num_docs();
+        let mut iter = terms.iterator()?;
+        let (mut doc_freq_sum, mut max_doc_freq, mut sampled) = (0i64, 0, 0);
+        for term in all_terms.iter().step_by(max(stride, 1)) {
+            sampled += 1;
+            if iter.seek_exact(term)? {
+                let doc_freq = iter.doc_freq()?;
+                doc_freq_sum += i64::from(doc_freq);
+                max_doc_freq = max(max_doc_freq, doc_freq);
+            }
+        }
+        if all_terms.len() == 1 {
+            sum.add_doc_freq(reader, max_doc_freq);
+        } else if sampled == all_terms.len() {
+            // a doc having several of the terms is counted several times
+            let max_docs = min(doc_freq_sum, i64::from(docs_with_field)) as i32;
+            sum.add(
+                reader,
+                doc_freq_sum as f64,
+                max(max_doc_freq - deleted, 0),
+                max_docs,
+                SelectivityConfidence::High,
+            );
+        } else {
+            let estimate = doc_freq_sum as f64 * all_terms.len() as f64 / sampled as f64;
+            sum.add(
+                reader,
+                estimate,
+                max(max_doc_freq - deleted, 0),
+                docs_with_field,
+                SelectivityConfidence::Low,
+            );
+        }
+    }
+    Ok(sum.finish(num_docs))
+}
+
+/// Estimates the docs having a term within any of the inclusive `ranges`.
+/// Seeking to the lower bound of a range prunes the ranges before the first
+/// or after the last term of the segment.
+fn estimate_term_ranges<C: Codec>(
+    leaves: &[LeafReaderContext<'_, C>],
+    num_docs: i32,
+    field: &str,
+    ranges: &[(Vec<u8>, Vec<u8>)],
+) -> Result<SelectivityEstimate> {
+    let mut sum = LeafSum::new();
+    for leaf in leaves {
+        let reader = leaf.reader;
+        let terms = match reader.terms(field)? {
+            Some(terms) => terms,
+            None => continue,
+        };
+        let docs_with_field = docs_with_field(reader, &terms)?;
+        let deleted = reader.max_doc() - reader.num_docs();
+        let mut iter = terms.iterator()?;
+        let (mut doc_freq_sum, mut max_doc_freq, mut visited) = (0i64, 0, 0);
+        let mut truncated = false;
+        'ranges: for (lower, upper) in ranges {
+            if iter.seek_ceil(lower)? == SeekStatus::End {
+                continue;
+            }
+            loop {
+                if iter.term()? > upper.as_slice() {
+                    break;
+                }
+                if visited == MAX_SAMPLED_TERMS {
+                    truncated = true;
+                    break 'ranges;
+                }
+                visited += 1;
+                let doc_freq = iter.doc_freq()?;
+                doc_freq_sum += i64::from(doc_freq);
+                max_doc_freq = max(max_doc_freq, doc_freq);
+                if iter.next()?.is_none() {
+                    break;
+                }
+            }
+        }
+        let min_docs = max(max_doc_freq - deleted, 0);
+        if truncated {
+            sum.add(
+                reader,
+                doc_freq_sum as f64,
+                min_docs,
+                docs_with_field,
+                SelectivityConfidence::Low,
+            );
+        } else if visited == 1 {
+            sum.add_doc_freq(reader, max_doc_freq);
+        } else {
+            let max_docs = min(doc_freq_sum, i64::from(docs_with_field)) as i32;
+            sum.add(
+                reader,
+                doc_freq_sum as f64,
+                min_docs,
+                max_docs,
+                SelectivityConfidence::High,
+            );
+        }
+    }
+    Ok(sum.finish(num_docs))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::codec::CodecEnum;
+    use core::doc::{Field, FieldType, LongPoint};
+    use core::index::index_writer_config::IndexWriterConfig;
+    use core::index::tests::{write_test_index, TestReader};
+    use core::index::{Fieldable, IndexOptions};
+    use core::search::field_exists::FieldExistsQuery;
+    use core::search::searcher::{DefaultIndexSearcher, IndexSearcher};
+    use core::util::VariantValue;
+
+    use std::sync::Arc;
+
+    fn keyword(name: &str, value: &str) -> Box<dyn Fieldable> {
+        let mut field_type = FieldType::default();
+        field_type.tokenized = false;
+        field_type.index_options = IndexOptions::Docs;
+        Box::new(Field::new(
+            name.to_string(),
+            field_type,
+            Some(VariantValue::VString(value.to_string())),
+            None,
+        ))
+    }
+
+    fn long_point(name: &str, value: i64) -> Box<dyn Fieldable> {
+        let mut field_type = FieldType::default();
+        field_type.set_dimensions(1, 8).unwrap();
+        Box::new(Field::new_bytes(
+            name.to_string(),
+            LongPoint::pack(&[value]),
+            field_type,
+        ))
+    }
+
+    // Two segments of 500 docs, "a" tags every other doc, "b" every fifth
+    // and "c" every tenth. With `deletes`, the "c" docs of the second
+    // segment are deleted.
+    fn build_index(deletes: bool) -> Arc<TestReader> {
+        write_test_index(IndexWriterConfig::default(), |writer| {
+            for i in 0..1000 {
+                let mut doc = vec![keyword("id", &i.to_string()), long_point("timestamp", i)];
+                for (tag, every) in &[("a", 2), ("b", 5), ("c", 10)] {
+                    if i % every == 0 {
+                        doc.push(keyword("tag", tag));
+                    }
+                }
+                writer.add_document(doc).unwrap();
+                if i == 499 {
+                    writer.commit().unwrap();
+                }
+            }
+            writer.commit().unwrap();
+            if deletes {
+                let ids: Vec<Term> = (500..1000)
+                    .filter(|i| i % 10 == 0)
+                    .map(|i| Term::new("id".to_string(), i.to_string().into_bytes()))
+                    .collect();
+                writer.delete_documents_by_terms(ids).unwrap();
+                writer.commit().unwrap();
+            }
+        })
+    }
+
+    fn term(field: &str, value: &str) -> Box<dyn Query<CodecEnum>> {
+        let term = Term::new(field.to_string(), value.as_bytes().to_vec());
+        Box::new(TermQuery::new(term, 1.0, None))
+    }
+
+    /// Checks the documented bounds against the actual count.
+    fn estimate_and_count(
+        reader: &Arc<TestReader>,
+        query: &dyn Query<CodecEnum>,
+    ) -> (SelectivityEstimate, i32) {
+        let searcher = DefaultIndexSearcher::new(Arc::clone(reader));
+        let estimate = searcher.estimate_selectivity(query).unwrap();
+        let count = searcher.count(query).unwrap() as i32;
+        assert!(
+            estimate.min_docs <= count && count <= estimate.max_docs,
+            "{}: {:?}, count {}",
+            query,
+            estimate,
+            count
+        );
+        assert!(estimate.min_docs <= estimate.estimated_docs);
+        assert!(estimate.estimated_docs <= estimate.max_docs);
+        assert_eq!(estimate.num_docs, reader.num_docs());
+        (estimate, count)
+    }
+
+    #[test]
+    fn test_term_estimates() {
+        let reader = build_index(false);
+        for tag in &["a", "b", "c", "d"] {
+            let (estimate, count) = estimate_and_count(&reader, term("tag", tag).as_ref());
+            assert_eq!(estimate.confidence, SelectivityConfidence::Exact);
+            assert_eq!(estimate.estimated_docs, count);
+        }
+
+        let reader = build_index(true);
+        assert_eq!(reader.num_docs(), 950);
+        let (estimate, count) = estimate_and_count(&reader, term("tag", "c").as_ref());
+        assert_eq!(count, 50);
+        assert_eq!(estimate.confidence, SelectivityConfidence::High);
+        assert_eq!((estimate.min_docs, estimate.max_docs), (50, 100));
+        let (estimate, _) = estimate_and_count(&reader, term("id", "5").as_ref());
+        assert_eq!(estimate.confidence, SelectivityConfidence::Exact);
+    }
+
+    #[test]
+    fn test_boolean_estimates() {
+        for deletes in &[false, true] {
+            let reader = build_index(*deletes);
+            let conjunction =
+                BooleanQuery::build(vec![term("tag", "a")], vec![], vec![term("tag", "b")])
+                    .unwrap();
+            let (estimate, count) = estimate_and_count(&reader, conjunction.as_ref());
+            assert_eq!(estimate.confidence, SelectivityConfidence::Medium);
+            if !*deletes {
+                // "a" and "b" are independent, until the deletes of "c" docs
+                assert!((estimate.estimated_docs - count).abs() <= count / 10);
+            }
+            let b = term("tag", "b").to_string();
+            assert_eq!(estimate.dominant_clause, Some(b.clone()));
+
+            let disjunction =
+                BooleanQuery::build(vec![], vec![term("tag", "a"), term("tag", "b")], vec![])
+                    .unwrap();
+            let (estimate, count) = estimate_and_count(&reader, disjunction.as_ref());
+            if !*deletes {
+                assert!((estimate.estimated_docs - count).abs() <= count / 10);
+            }
+            assert_eq!(estimate.dominant_clause, Some(term("tag", "a").to_string()));
+
+            // "c" docs all have "a", the independence assumption doesn't hold
+            // but the bounds do
+            let nested = BooleanQuery::build(
+                vec![term("tag", "c")],
+                vec![],
+                vec![disjunction, term("tag", "missing")],
+            )
+            .unwrap();
+            let (estimate, count) = estimate_and_count(&reader, nested.as_ref());
+            assert_eq!(count, 0);
+            assert_eq!(estimate.estimated_docs, 0);
+            assert_eq!(estimate.max_docs, 0);
+        }
+
+        let reader = build_index(false);
+        let shoulds = (0..20).map(|i| term("id", &i.to_string())).collect();
+        let disjunction = BooleanQuery::build(vec![], shoulds, vec![]).unwrap();
+        let (estimate, count) = estimate_and_count(&reader, disjunction.as_ref());
+        assert_eq!((count, estimate.max_docs), (20, 20));
+    }
+
+    #[test]
+    fn test_disjunction_with_minimum_should_match() {
+        let clauses = || {
+            vec![
+                ("a".to_string(), SelectivityEstimate::exact(600, 1000)),
+                ("b".to_string(), SelectivityEstimate::exact(700, 1000)),
+                ("c".to_string(), SelectivityEstimate::exact(100, 1000)),
+            ]
+        };
+        let any = disjunction(clauses(), 1, 1000);
+        assert_eq!((any.min_docs, any.max_docs), (700, 1000));
+        // 1 - 0.4 * 0.3 * 0.9
+        assert_eq!(any.estimated_docs, 892);
+
+        // "a" and "b" share at least 300 docs, any two clauses at least 200
+        // of them: 1400 docs counted over 1000 docs, each counted at most
+        // once without matching and twice when it does
+        let two = disjunction(clauses(), 2, 1000);
+        assert_eq!((two.min_docs, two.max_docs), (200, 700));
+        // 0.42 + 0.06 + 0.07 - 2 * 0.042
+        assert_eq!(two.estimated_docs, 466);
+        assert_eq!(two.dominant_clause, Some("b".to_string()));
+
+        let all = disjunction(clauses(), 3, 1000);
+        assert_eq!((all.min_docs, all.max_docs), (0, 100));
+        assert_eq!(all.estimated_docs, 42);
+
+        assert_eq!(disjunction(clauses(), 4, 1000).max_docs, 0);
+    }
+
+    #[test]
+    fn test_point_range_estimates() {
+        for deletes in &[false, true] {
+            let reader = build_index(*deletes);
+            let all = LongPoint::new_range_query("timestamp".into(), -10, 2000).unwrap();
+            let (estimate, count) = estimate_and_count(&reader, all.as_ref());
+            assert_eq!(estimate.estimated_docs, count);
+
+            let none = LongPoint::new_range_query("timestamp".into(), 2000, 3000).unwrap();
+            let (estimate, _) = estimate_and_count(&reader, none.as_ref());
+            assert_eq!(estimate.max_docs, 0);
+            assert_eq!(estimate.confidence, SelectivityConfidence::Exact);
+
+            let some = LongPoint::new_range_query("timestamp".into(), 250, 749).unwrap();
+            let (estimate, count) = estimate_and_count(&reader, some.as_ref());
+            assert_eq!(estimate.confidence, SelectivityConfidence::Low);
+            assert!((estimate.estimated_docs - count).abs() <= count / 10);
+        }
+    }
+
+    #[test]
+    fn test_term_set_estimates() {
+        let reader = build_index(false);
+        let ids = |n: usize| -> Vec<Vec<u8>> {
+            (0..n).map(|i| (i * 3).to_string().into_bytes()).collect()
+        };
+
+        let query = TermInSetQuery::new("id".into(), ids(100));
+        let (estimate, count) = estimate_and_count(&reader, &query);
+        assert_eq!(estimate.confidence, SelectivityConfidence::High);
+        assert_eq!(estimate.estimated_docs, count);
+
+        // more terms than sampled, every sampled term has one doc
+        let query = TermInSetQuery::new("id".into(), ids(300));
+        let (estimate, count) = estimate_and_count(&reader, &query);
+        assert_eq!(estimate.confidence, SelectivityConfidence::Low);
+        assert!((estimate.estimated_docs - count).abs() <= count / 10);
+    }
+
+    #[test]
+    fn test_unknown_query_spans_all_docs() {
+        let reader = build_index(true);
+        let query = FieldExistsQuery::new("tag".into());
+        let (estimate, _) = estimate_and_count(&reader, &query);
+        assert_eq!(estimate.confidence, SelectivityConfidence::Unknown);
+        assert_eq!((estimate.min_docs, estimate.max_docs), (0, 950));
+    }
+}
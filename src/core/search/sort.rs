@@ -13,6 +13,8 @@
 
 use core::search::sort_field::*;
 
+use error::Result;
+
 #[derive(Clone, Eq, PartialEq, Debug)]
 pub struct Sort {
     fields: Vec<SortField>,
@@ -23,6 +25,16 @@ impl Sort {
         Sort { fields }
     }
 
+    /// Parses the comma separated fields of a sort, see `SortField::parse`,
+    /// e.g. `category:asc:last,price:long:desc,_score`.
+    pub fn parse(spec: &str) -> Result<Sort> {
+        let fields = spec
+            .split(',')
+            .map(SortField::parse)
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Sort::new(fields))
+    }
+
     pub fn get_sort(&self) -> &[SortField] {
         &self.fields
     }
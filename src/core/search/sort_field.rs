@@ -24,6 +24,7 @@ use core::util::VariantValue;
 use error::ErrorKind::IllegalArgument;
 use error::Result;
 
+use std::cmp::Ordering;
 use std::sync::Arc;
 
 #[derive(PartialEq, Debug, Clone, Copy, Eq)]
@@ -54,6 +55,7 @@ pub enum SortedNumericSelectorType {
     Max,
 }
 
+/// Where the docs without a value sort with a string sort.
 #[derive(PartialEq, Debug, Clone, Copy, Eq)]
 pub enum SortFieldMissingValue {
     StringLast,
@@ -118,6 +120,84 @@ impl SortField {
         }
     }
 
+    pub fn string_missing(&self) -> SortFieldMissingValue {
+        match self {
+            SortField::Simple(s) => s.string_missing,
+            SortField::SortedNumeric(_) => SortFieldMissingValue::StringFirst,
+        }
+    }
+
+    /// Compares two sort values of this field in its natural order, i.e.
+    /// without reversing it.
+    pub fn compare_values(&self, value1: &VariantValue, value2: &VariantValue) -> Ordering {
+        match (value1, value2) {
+            (VariantValue::Binary(term1), VariantValue::Binary(term2))
+                if self.field_type() == SortFieldType::String =>
+            {
+                let missing_last = self.string_missing() == SortFieldMissingValue::StringLast;
+                compare_terms(term1, term2, missing_last)
+            }
+            _ => value1.cmp(value2),
+        }
+    }
+
+    /// Parses a sort field from `<field>[:<type>][:asc|desc][:<missing>]`,
+    /// e.g. `price:long:desc:0`.
+    ///
+    /// The type is `string`, `long`, `int`, `double` or `float`, `string` if
+    /// omitted. `_score` and `_doc` sort by score and by doc id, without a
+    /// type. The order is ascending by default, except for the score whose
+    /// higher values sort first. The docs without a value sort `first` or
+    /// `last` in the order of the sort, descending or not, or with a constant
+    /// of a numeric type.
+    pub fn parse(spec: &str) -> Result<SortField> {
+        let mut parts: Vec<&str> = spec.trim().split(':').collect();
+        let field = parts.remove(0);
+        if field.is_empty() {
+            bail!(IllegalArgument(format!("no field in sort '{}'", spec)));
+        }
+        let field_type = match field {
+            "_score" => SortFieldType::Score,
+            "_doc" => SortFieldType::Doc,
+            _ => match parts.first().and_then(|t| parse_sort_type(t)) {
+                Some(field_type) => {
+                    parts.remove(0);
+                    field_type
+                }
+                None => SortFieldType::String,
+            },
+        };
+        let descending = match parts.first() {
+            Some(&"asc") => Some(false),
+            Some(&"desc") => Some(true),
+            _ => None,
+        };
+        if descending.is_some() {
+            parts.remove(0);
+        }
+        // the natural order of the score is descending
+        let reverse = if field_type == SortFieldType::Score {
+            descending == Some(false)
+        } else {
+            descending == Some(true)
+        };
+        let name = match field_type {
+            SortFieldType::Score | SortFieldType::Doc => String::new(),
+            _ => field.to_string(),
+        };
+        let mut sort_field = SimpleSortField::new(name, field_type, reverse);
+        match parts.len() {
+            0 => {}
+            1 => sort_field.set_missing(parts[0])?,
+            _ => bail!(IllegalArgument(format!(
+                "unexpected '{}' in sort '{}'",
+                parts[1..].join(":"),
+                spec
+            ))),
+        }
+        Ok(SortField::Simple(sort_field))
+    }
+
     pub fn get_comparator(
         &self,
         num_hits: usize,
@@ -130,12 +210,24 @@ impl SortField {
     }
 }
 
+fn parse_sort_type(name: &str) -> Option<SortFieldType> {
+    match name {
+        "string" => Some(SortFieldType::String),
+        "long" => Some(SortFieldType::Long),
+        "int" => Some(SortFieldType::Int),
+        "double" => Some(SortFieldType::Double),
+        "float" => Some(SortFieldType::Float),
+        _ => None,
+    }
+}
+
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub struct SimpleSortField {
     field: String,
     field_type: SortFieldType,
     is_reverse: bool,
     missing_value: Option<VariantValue>,
+    string_missing: SortFieldMissingValue,
 }
 
 impl SimpleSortField {
@@ -145,16 +237,12 @@ impl SimpleSortField {
             field_type,
             is_reverse,
             missing_value: None,
+            string_missing: SortFieldMissingValue::StringFirst,
         }
     }
 
     pub fn new_score() -> SimpleSortField {
-        SimpleSortField {
-            field: String::new(),
-            field_type: SortFieldType::Score,
-            is_reverse: false,
-            missing_value: None,
-        }
+        Self::new(String::new(), SortFieldType::Score, false)
     }
 
     pub fn field(&self) -> &String {
@@ -173,6 +261,67 @@ impl SimpleSortField {
         self.missing_value = value;
     }
 
+    /// Sorts the docs without a value first or last, for a string sort.
+    pub fn set_string_missing(&mut self, missing: SortFieldMissingValue) {
+        self.string_missing = missing;
+    }
+
+    pub fn string_missing(&self) -> SortFieldMissingValue {
+        self.string_missing
+    }
+
+    // the missing values sort first or last in the order of the sort, i.e.
+    // as the lowest or highest value unless the order is reversed
+    fn set_missing(&mut self, missing: &str) -> Result<()> {
+        let field_type = self.field_type;
+        let invalid = move || {
+            IllegalArgument(format!(
+                "invalid missing value '{}' for a {:?} sort",
+                missing, field_type
+            ))
+        };
+        let first = match missing {
+            "first" => Some(true),
+            "last" => Some(false),
+            _ => None,
+        };
+        // the lowest value sorts first unless the order is reversed
+        let lowest = first.map(|first| first != self.is_reverse);
+        let value = match field_type {
+            SortFieldType::String => {
+                self.string_missing = match lowest {
+                    Some(true) => SortFieldMissingValue::StringFirst,
+                    Some(false) => SortFieldMissingValue::StringLast,
+                    None => bail!(invalid()),
+                };
+                return Ok(());
+            }
+            SortFieldType::Long => match lowest {
+                Some(true) => VariantValue::Long(i64::min_value()),
+                Some(false) => VariantValue::Long(i64::max_value()),
+                None => VariantValue::Long(missing.parse().map_err(|_| invalid())?),
+            },
+            SortFieldType::Int => match lowest {
+                Some(true) => VariantValue::Int(i32::min_value()),
+                Some(false) => VariantValue::Int(i32::max_value()),
+                None => VariantValue::Int(missing.parse().map_err(|_| invalid())?),
+            },
+            SortFieldType::Double => match lowest {
+                Some(true) => VariantValue::Double(::std::f64::NEG_INFINITY),
+                Some(false) => VariantValue::Double(::std::f64::INFINITY),
+                None => VariantValue::Double(missing.parse().map_err(|_| invalid())?),
+            },
+            SortFieldType::Float => match lowest {
+                Some(true) => VariantValue::Float(::std::f32::NEG_INFINITY),
+                Some(false) => VariantValue::Float(::std::f32::INFINITY),
+                None => VariantValue::Float(missing.parse().map_err(|_| invalid())?),
+            },
+            _ => bail!(invalid()),
+        };
+        self.missing_value = Some(value);
+        Ok(())
+    }
+
     pub fn get_comparator(
         &self,
         num_hits: usize,
@@ -181,9 +330,11 @@ impl SimpleSortField {
         match self.field_type {
             SortFieldType::Score => FieldComparatorEnum::Score(RelevanceComparator::new(num_hits)),
             SortFieldType::Doc => FieldComparatorEnum::Doc(DocComparator::new(num_hits)),
-            SortFieldType::String => {
-                unimplemented!();
-            }
+            SortFieldType::String => FieldComparatorEnum::TermOrdVal(TermOrdValComparator::new(
+                num_hits,
+                self.field.clone(),
+                self.string_missing == SortFieldMissingValue::StringLast,
+            )),
             _ => {
                 // debug_assert!(missing_value.is_some());
 
@@ -494,6 +645,96 @@ mod tests {
         assert_eq!(true, sort_field.is_reverse());
     }
 
+    #[test]
+    fn test_parse_sort_field() {
+        let field = SortField::parse("category").unwrap();
+        assert_eq!(field.field(), "category");
+        assert_eq!(field.field_type(), SortFieldType::String);
+        assert!(!field.is_reverse());
+        assert_eq!(field.string_missing(), SortFieldMissingValue::StringFirst);
+
+        let field = SortField::parse(" category:asc:last").unwrap();
+        assert_eq!(field.field_type(), SortFieldType::String);
+        assert_eq!(field.string_missing(), SortFieldMissingValue::StringLast);
+        // the empty term stands for the missing values
+        let missing = VariantValue::Binary(vec![]);
+        let value = VariantValue::Binary(b"a".to_vec());
+        assert_eq!(field.compare_values(&missing, &value), Ordering::Greater);
+
+        let field = SortField::parse("price:long:desc:-1").unwrap();
+        assert_eq!(field.field_type(), SortFieldType::Long);
+        assert!(field.is_reverse());
+        assert_eq!(field.missing_value(), Some(&VariantValue::Long(-1)));
+        // first in a descending order is the highest value
+        let field = SortField::parse("price:long:desc:first").unwrap();
+        assert_eq!(
+            field.missing_value(),
+            Some(&VariantValue::Long(i64::max_value()))
+        );
+        let field = SortField::parse("weight:float:last").unwrap();
+        assert!(!field.is_reverse());
+        assert_eq!(
+            field.missing_value(),
+            Some(&VariantValue::Float(::std::f32::INFINITY))
+        );
+
+        let score = SortField::parse("_score").unwrap();
+        assert_eq!(score.field_type(), SortFieldType::Score);
+        assert!(!score.is_reverse());
+        assert!(SortField::parse("_score:asc").unwrap().is_reverse());
+        assert!(SortField::parse("_doc:desc").unwrap().is_reverse());
+
+        for invalid in &[
+            "",
+            ":long",
+            "price:long:cheap",
+            "name:asc:0",
+            "_score:first",
+        ] {
+            assert!(SortField::parse(invalid).is_err(), "{}", invalid);
+        }
+        assert!(SortField::parse("price:long:desc:0:1").is_err());
+    }
+
+    #[test]
+    fn test_parse_missing_descending() {
+        for &(spec, missing_last) in &[
+            ("name:asc:last", true),
+            ("name:desc:last", true),
+            ("name:asc:first", false),
+            ("name:desc:first", false),
+            ("price:long:asc:last", true),
+            ("price:long:desc:last", true),
+            ("price:long:asc:first", false),
+            ("price:long:desc:first", false),
+        ] {
+            let field = SortField::parse(spec).unwrap();
+            let (missing, value) = if field.field_type() == SortFieldType::String {
+                // the empty term stands for the missing values
+                (
+                    VariantValue::Binary(vec![]),
+                    VariantValue::Binary(b"a".to_vec()),
+                )
+            } else {
+                (
+                    field.missing_value().unwrap().clone(),
+                    VariantValue::Long(5),
+                )
+            };
+            // the order of the hits reverses the descending fields
+            let mut cmp = field.compare_values(&missing, &value);
+            if field.is_reverse() {
+                cmp = cmp.reverse();
+            }
+            let expected = if missing_last {
+                Ordering::Greater
+            } else {
+                Ordering::Less
+            };
+            assert_eq!(cmp, expected, "{}", spec);
+        }
+    }
+
     #[test]
     fn test_sort_field_with_doc_type() {
         let sort_field = SortField::Simple(SimpleSortField::new(
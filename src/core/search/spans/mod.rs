@@ -17,3 +17,101 @@ pub mod span_boost;
 pub mod span_near;
 pub mod span_or;
 pub mod span_term;
+
+#[cfg(test)]
+pub mod tests {
+    use core::codec::CodecEnum;
+    use core::doc::{Field, FieldType};
+    use core::index::tests::{open_test_reader, TestReader};
+    use core::index::{IndexOptions, IndexReader, Term};
+    use core::search::searcher::DefaultIndexSearcher;
+    use core::search::spans::span::{
+        PostingsFlag, SpanQuery, SpanQueryEnum, SpanWeight, Spans, NO_MORE_POSITIONS,
+    };
+    use core::search::spans::span_term::SpanTermQuery;
+    use core::search::{DocIterator, NO_MORE_DOCS};
+    use core::util::{DocId, VariantValue};
+
+    use std::sync::Arc;
+
+    /// Two segments of docs whose spans are worked out by hand in the tests.
+    // positions:             0     1     2    3     4     5
+    pub const DOCS: &[&[&str]] = &[
+        &[
+            "quick brown fox quick brown fox",
+            "fox brown quick",
+            "quick red lazy brown",
+        ],
+        &["brown quick brown", "quick brown quick fox brown fox"],
+    ];
+
+    fn text(value: &str) -> Field {
+        let mut field_type = FieldType::default();
+        field_type.index_options = IndexOptions::DocsAndFreqsAndPositions;
+        Field::new(
+            "body".to_string(),
+            field_type,
+            Some(VariantValue::VString(value.to_string())),
+            None,
+        )
+    }
+
+    pub fn span_term(value: &str) -> SpanQueryEnum {
+        SpanQueryEnum::Term(SpanTermQuery::new(
+            Term::new("body".to_string(), value.as_bytes().to_vec()),
+            None,
+        ))
+    }
+
+    /// Indexes the docs in the body field, a segment per slice.
+    pub fn open_reader(segments: &[&[&str]]) -> Arc<TestReader> {
+        open_test_reader(
+            segments
+                .iter()
+                .map(|docs| docs.iter().map(|body| vec![text(body)]).collect())
+                .collect(),
+        )
+    }
+
+    /// Returns the `(doc, start, end)` spans of a query in all the segments,
+    /// moving to the docs with `next` or, if `two_phase`, with the
+    /// approximation of the spans then `matches`.
+    pub fn collect_spans<Q: SpanQuery<CodecEnum>>(
+        reader: &Arc<TestReader>,
+        query: &Q,
+        two_phase: bool,
+    ) -> Vec<(DocId, i32, i32)> {
+        let searcher = DefaultIndexSearcher::new(Arc::clone(reader));
+        let weight = query.span_weight(&searcher, false).unwrap();
+        let mut spans_list = vec![];
+        for leaf in reader.leaves() {
+            let mut spans = match weight.get_spans(&leaf, &PostingsFlag::Positions).unwrap() {
+                Some(spans) => spans,
+                None => continue,
+            };
+            loop {
+                let doc = if two_phase {
+                    spans.approximate_next().unwrap()
+                } else {
+                    spans.next().unwrap()
+                };
+                if doc == NO_MORE_DOCS {
+                    break;
+                }
+                if two_phase && !spans.matches().unwrap() {
+                    continue;
+                }
+                assert_eq!(spans.start_position(), -1);
+                loop {
+                    let start = spans.next_start_position().unwrap();
+                    if start == NO_MORE_POSITIONS {
+                        break;
+                    }
+                    assert_eq!(spans.start_position(), start);
+                    spans_list.push((doc + leaf.doc_base, start, spans.end_position()));
+                }
+            }
+        }
+        spans_list
+    }
+}
@@ -1057,3 +1057,147 @@ impl DocIterator for GapSpans {
         0
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::search::collector::TopDocsCollector;
+    use core::search::searcher::{DefaultIndexSearcher, IndexSearcher};
+    use core::search::spans::tests::*;
+
+    fn near(words: &[&str], slop: i32, in_order: bool) -> SpanNearQuery {
+        let clauses = words.iter().map(|w| span_term(w)).collect();
+        SpanNearQuery::new(clauses, slop, in_order).unwrap()
+    }
+
+    fn assert_spans(query: &SpanNearQuery, expected: &[(DocId, i32, i32)]) {
+        let reader = open_reader(DOCS);
+        assert_eq!(collect_spans(&reader, query, false), expected);
+        assert_eq!(collect_spans(&reader, query, true), expected);
+    }
+
+    #[test]
+    fn test_ordered_near() {
+        assert_spans(
+            &near(&["quick", "brown"], 0, true),
+            &[(0, 0, 2), (0, 3, 5), (3, 1, 3), (4, 0, 2)],
+        );
+        // "quick red lazy brown" and the second "quick" of doc 4 match with slop
+        assert_spans(
+            &near(&["quick", "brown"], 2, true),
+            &[
+                (0, 0, 2),
+                (0, 3, 5),
+                (2, 0, 4),
+                (3, 1, 3),
+                (4, 0, 2),
+                (4, 2, 5),
+            ],
+        );
+        assert_spans(
+            &near(&["brown", "quick"], 0, true),
+            &[(1, 1, 3), (3, 0, 2), (4, 1, 3)],
+        );
+    }
+
+    #[test]
+    fn test_unordered_near() {
+        // "brown quick" matches too, and overlaps "quick brown" in docs 3 and 4
+        assert_spans(
+            &near(&["quick", "brown"], 0, false),
+            &[
+                (0, 0, 2),
+                (0, 3, 5),
+                (1, 1, 3),
+                (3, 0, 2),
+                (3, 1, 3),
+                (4, 0, 2),
+                (4, 1, 3),
+            ],
+        );
+        // "brown fox quick" and "quick fox brown" match with slop
+        assert_spans(
+            &near(&["quick", "brown"], 2, false),
+            &[
+                (0, 0, 2),
+                (0, 1, 4),
+                (0, 3, 5),
+                (1, 1, 3),
+                (2, 0, 4),
+                (3, 0, 2),
+                (3, 1, 3),
+                (4, 0, 2),
+                (4, 1, 3),
+                (4, 2, 5),
+            ],
+        );
+    }
+
+    #[test]
+    fn test_overlapping_ordered_spans() {
+        // "quick brown quick fox brown fox" matches twice with slop 1:
+        // quick brown .. fox
+        //       quick .. brown fox
+        assert_spans(
+            &near(&["quick", "brown", "fox"], 1, true),
+            &[(0, 0, 3), (0, 3, 6), (4, 0, 4), (4, 2, 6)],
+        );
+        assert_spans(
+            &near(&["quick", "brown", "fox"], 0, true),
+            &[(0, 0, 3), (0, 3, 6)],
+        );
+    }
+
+    #[test]
+    fn test_approximation() {
+        // all the docs have "quick" and "brown", the positions are only
+        // checked by `matches`
+        let reader = open_reader(DOCS);
+        let searcher = DefaultIndexSearcher::new(Arc::clone(&reader));
+        let weight = near(&["quick", "brown"], 0, true)
+            .span_weight(&searcher, false)
+            .unwrap();
+        let mut approximated = vec![];
+        let mut matched = vec![];
+        for leaf in reader.leaves() {
+            let mut spans = weight
+                .get_spans(&leaf, &PostingsFlag::Positions)
+                .unwrap()
+                .unwrap();
+            assert!(spans.support_two_phase());
+            loop {
+                let doc = spans.approximate_next().unwrap();
+                if doc == NO_MORE_DOCS {
+                    break;
+                }
+                approximated.push(doc + leaf.doc_base);
+                if spans.matches().unwrap() {
+                    matched.push(doc + leaf.doc_base);
+                }
+            }
+        }
+        assert_eq!(approximated, vec![0, 1, 2, 3, 4]);
+        assert_eq!(matched, vec![0, 3, 4]);
+    }
+
+    #[test]
+    fn test_sloppy_freq_score() {
+        let reader = open_reader(&[&["quick brown lazy dog", "quick lazy dog brown"]]);
+        let searcher = DefaultIndexSearcher::new(reader);
+        let mut collector = TopDocsCollector::new(10);
+        searcher
+            .search(&near(&["quick", "brown"], 2, true), &mut collector)
+            .unwrap();
+        let top_docs = collector.top_docs();
+        let hits: Vec<_> = top_docs
+            .score_docs()
+            .iter()
+            .map(|hit| (hit.doc_id(), hit.score()))
+            .collect();
+        // the docs have the same length, the wider match scores lower
+        assert_eq!(hits.len(), 2);
+        assert_eq!(hits[0].0, 0);
+        assert_eq!(hits[1].0, 1);
+        assert!(hits[0].1 > hits[1].1);
+    }
+}
@@ -264,8 +264,13 @@ impl<P: PostingIterator> SpanOrSpans<P> {
             let mut cost = 0;
             for spans in &by_doc_queue {
                 let cost_weight = max(spans.cost(), 1) as f32;
-                sum_position_cost += spans.positions_cost() * cost_weight;
-                sum_approx_cost += spans.match_cost() * cost_weight;
+                // the spans with an approximation, e.g. near spans, have a
+                // match cost rather than a positions cost
+                if spans.support_two_phase() {
+                    sum_approx_cost += spans.match_cost() * cost_weight;
+                } else {
+                    sum_position_cost += spans.positions_cost() * cost_weight;
+                }
                 sum_weight += cost_weight;
                 cost += spans.cost();
             }
@@ -516,14 +521,19 @@ impl<P: PostingIterator> Eq for SpansElement<P> {}
 
 impl<P: PostingIterator> PartialEq for SpansElement<P> {
     fn eq(&self, other: &SpansElement<P>) -> bool {
-        self.spans().doc_id().eq(&other.spans().doc_id())
+        self.cmp(other) == Ordering::Equal
     }
 }
 
 impl<P: PostingIterator> Ord for SpansElement<P> {
     fn cmp(&self, other: &Self) -> Ordering {
-        // reversed order by doc id for BinaryHeap
-        other.spans().doc_id().cmp(&self.spans().doc_id())
+        // the spans of `by_position_queue` are all at the current doc, reversed
+        // order by start position then end position for BinaryHeap
+        let (spans1, spans2) = (self.spans(), other.spans());
+        spans2
+            .start_position()
+            .cmp(&spans1.start_position())
+            .then_with(|| spans2.end_position().cmp(&spans1.end_position()))
     }
 }
 
@@ -532,3 +542,91 @@ impl<P: PostingIterator> PartialOrd for SpansElement<P> {
         Some(self.cmp(other))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::search::spans::span_near::SpanNearQuery;
+    use core::search::spans::tests::*;
+
+    fn or(clauses: Vec<SpanQueryEnum>) -> SpanQueryEnum {
+        SpanQueryEnum::Or(SpanOrQuery::new(clauses).unwrap())
+    }
+
+    fn near(clauses: Vec<SpanQueryEnum>, in_order: bool) -> SpanQueryEnum {
+        SpanQueryEnum::Near(SpanNearQuery::new(clauses, 0, in_order).unwrap())
+    }
+
+    fn assert_spans(query: &SpanQueryEnum, expected: &[(DocId, i32, i32)]) {
+        let reader = open_reader(DOCS);
+        assert_eq!(collect_spans(&reader, query, false), expected);
+        assert_eq!(collect_spans(&reader, query, true), expected);
+    }
+
+    #[test]
+    fn test_span_or_positions() {
+        // the spans of the clauses are merged by position in every doc
+        assert_spans(
+            &or(vec![span_term("fox"), span_term("quick")]),
+            &[
+                (0, 0, 1),
+                (0, 2, 3),
+                (0, 3, 4),
+                (0, 5, 6),
+                (1, 0, 1),
+                (1, 2, 3),
+                (2, 0, 1),
+                (3, 1, 2),
+                (4, 0, 1),
+                (4, 2, 3),
+                (4, 3, 4),
+                (4, 5, 6),
+            ],
+        );
+        // a clause without any match in the segments
+        assert_spans(
+            &or(vec![span_term("red"), span_term("missing")]),
+            &[(2, 1, 2)],
+        );
+    }
+
+    #[test]
+    fn test_near_inside_or() {
+        // "quick brown" spans and "fox" spans, sorted by start then end
+        let quick_brown = near(vec![span_term("quick"), span_term("brown")], true);
+        assert_spans(
+            &or(vec![span_term("fox"), quick_brown]),
+            &[
+                (0, 0, 2),
+                (0, 2, 3),
+                (0, 3, 5),
+                (0, 5, 6),
+                (1, 0, 1),
+                (3, 1, 3),
+                (4, 0, 2),
+                (4, 3, 4),
+                (4, 5, 6),
+            ],
+        );
+    }
+
+    #[test]
+    fn test_or_inside_near() {
+        // "quick" followed by "brown" or "fox"
+        let brown_or_fox = or(vec![span_term("brown"), span_term("fox")]);
+        assert_spans(
+            &near(vec![span_term("quick"), brown_or_fox], true),
+            &[(0, 0, 2), (0, 3, 5), (3, 1, 3), (4, 0, 2), (4, 2, 4)],
+        );
+
+        // "brown quick" or "quick brown", each followed by "fox"
+        let quick_brown = or(vec![
+            near(vec![span_term("quick"), span_term("brown")], true),
+            near(vec![span_term("brown"), span_term("quick")], true),
+        ]);
+        assert_spans(
+            &near(vec![quick_brown, span_term("fox")], true),
+            &[(0, 0, 3), (0, 3, 6), (4, 1, 4)],
+        );
+    }
+}
@@ -0,0 +1,604 @@
+// Copyright 2019 Zhizhesihai (Beijing) Technology Limited.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Autocompletion of analyzed prefixes with the weighted suggestions of an
+//! FST, see `AnalyzingSuggester`.
+
+use core::analysis::Analyzer;
+use core::codec::codec_util;
+use core::store::{DataInput, DataOutput, Directory, IOContext};
+use core::util::fst::fst_builder::FstBuilder;
+use core::util::fst::util::TopNSearcher;
+use core::util::fst::{self, BytesReader, InputType, OutputFactory, FST};
+use core::util::fst::{ByteSequenceOutput, ByteSequenceOutputFactory};
+use core::util::fst::{PairOutput, PairOutputFactory, PositiveIntOutput, PositiveIntOutputFactory};
+use core::util::ints_ref::{to_ints_ref, IntsRefBuilder};
+use core::util::levenshtein::{LevenshteinAutomata, DEAD_STATE};
+
+use error::ErrorKind::IllegalArgument;
+use error::Result;
+
+use std::cmp::Ordering;
+use std::str;
+use std::sync::Arc;
+use std::u16;
+use std::u32;
+
+const CODEC_NAME: &str = "AnalyzingSuggester";
+const VERSION_START: i32 = 0;
+const VERSION_CURRENT: i32 = VERSION_START;
+
+/// Separates the tokens of an analyzed key.
+pub const SEP_LABEL: u8 = 0x1F;
+
+// ends the analyzed key in the FST inputs, it is followed by two bytes
+// telling apart the suggestions sharing the same analyzed key
+const END_BYTE: u8 = 0;
+
+/// Prefixes with less chars once analyzed are looked up without edits, as
+/// one edit would match most of the suggestions.
+pub const FUZZY_MIN_LENGTH: usize = 3;
+
+type SuggestOutputFactory = PairOutputFactory<PositiveIntOutputFactory, ByteSequenceOutputFactory>;
+type SuggestOutput = PairOutput<PositiveIntOutput, ByteSequenceOutput>;
+
+/// A suggestion given to `AnalyzingSuggester::build`, the `key` is analyzed
+/// to be matched by the lookups but is returned as is.
+#[derive(Debug, Clone)]
+pub struct SuggestInput {
+    pub key: String,
+    pub weight: u32,
+    pub payload: Vec<u8>,
+}
+
+impl SuggestInput {
+    pub fn new<S: Into<String>>(key: S, weight: u32) -> SuggestInput {
+        SuggestInput::with_payload(key, weight, Vec::new())
+    }
+
+    pub fn with_payload<S: Into<String>>(key: S, weight: u32, payload: Vec<u8>) -> SuggestInput {
+        SuggestInput {
+            key: key.into(),
+            weight,
+            payload,
+        }
+    }
+}
+
+/// A completion returned by `AnalyzingSuggester::lookup`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LookupResult {
+    pub key: String,
+    pub weight: u32,
+    pub payload: Vec<u8>,
+}
+
+/// Suggests the completions of a prefix by matching it against the analyzed
+/// form of the suggestions, so that e.g. with a lowercasing analyzer
+/// "new yo" completes to "New York".
+///
+/// The suggestions are compiled into an FST mapping the analyzed keys to the
+/// key and payload as given, paired with the cost `u32::MAX - weight`. The
+/// FST pushes the minimal cost of the suggestions below a node towards the
+/// root, so the best completions of a prefix are found by `TopNSearcher`
+/// from the node the prefix leads to, without visiting the others.
+///
+/// The analyzed tokens are joined with `SEP_LABEL`, and the analyzed keys
+/// must not contain a `0` byte, which ends them in the FST inputs.
+pub struct AnalyzingSuggester {
+    analyzer: Arc<dyn Analyzer>,
+    fst: Option<FST<SuggestOutputFactory>>,
+    count: u64,
+}
+
+impl AnalyzingSuggester {
+    pub fn new(analyzer: Arc<dyn Analyzer>) -> AnalyzingSuggester {
+        AnalyzingSuggester {
+            analyzer,
+            fst: None,
+            count: 0,
+        }
+    }
+
+    /// The number of suggestions built or loaded.
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// Replaces the suggestions with `inputs`.
+    pub fn build<I: IntoIterator<Item = SuggestInput>>(&mut self, inputs: I) -> Result<()> {
+        let mut entries = Vec::new();
+        for input in inputs {
+            let analyzed = self.analyze(&input.key)?;
+            if analyzed.contains(&END_BYTE) {
+                bail!(IllegalArgument(format!(
+                    "the analyzed form of suggestion {:?} contains the reserved byte 0x00",
+                    input.key
+                )));
+            }
+            let key = input.key.as_bytes();
+            if key.len() > u16::MAX as usize {
+                bail!(IllegalArgument(format!(
+                    "suggestion is too long: {} bytes, the max is {}",
+                    key.len(),
+                    u16::MAX
+                )));
+            }
+            let mut output = Vec::with_capacity(2 + key.len() + input.payload.len());
+            output.push((key.len() >> 8) as u8);
+            output.push(key.len() as u8);
+            output.extend_from_slice(key);
+            output.extend_from_slice(&input.payload);
+            entries.push((analyzed, encode_weight(input.weight), output));
+        }
+        // the inputs must be added in order, the suggestions sharing an
+        // analyzed key are numbered by cost
+        entries.sort();
+
+        let mut builder = FstBuilder::new(InputType::Byte1, output_factory());
+        builder.init();
+        let mut scratch = IntsRefBuilder::new();
+        let mut input = Vec::new();
+        let mut dedup = 0usize;
+        for (i, &(ref analyzed, cost, ref output)) in entries.iter().enumerate() {
+            if i > 0 && entries[i - 1].0 == *analyzed {
+                dedup += 1;
+                if dedup > u16::MAX as usize {
+                    bail!(IllegalArgument(format!(
+                        "more than {} suggestions share the analyzed form {:?}",
+                        u16::MAX as usize + 1,
+                        String::from_utf8_lossy(analyzed)
+                    )));
+                }
+            } else {
+                dedup = 0;
+            }
+            input.clear();
+            input.extend_from_slice(analyzed);
+            input.push(END_BYTE);
+            input.push((dedup >> 8) as u8);
+            input.push(dedup as u8);
+            builder.add(
+                to_ints_ref(&input, &mut scratch),
+                PairOutput::new(
+                    PositiveIntOutput::new(cost),
+                    ByteSequenceOutput::new(output.clone()),
+                ),
+            )?;
+        }
+        self.fst = builder.finish()?;
+        self.count = entries.len() as u64;
+        Ok(())
+    }
+
+    /// Returns the `num` completions of `prefix` with the highest weights, in
+    /// decreasing weight order, ties being broken by the smaller analyzed
+    /// key.
+    ///
+    /// With `fuzzy`, the completions may start with up to one edit (insertion,
+    /// deletion, substitution or transposition of a char) from the analyzed
+    /// prefix, unless it is shorter than `FUZZY_MIN_LENGTH` chars.
+    pub fn lookup(&self, prefix: &str, num: usize, fuzzy: bool) -> Result<Vec<LookupResult>> {
+        let fst = match self.fst {
+            Some(ref fst) => fst,
+            None => return Ok(Vec::new()),
+        };
+        if num == 0 {
+            return Ok(Vec::new());
+        }
+        let analyzed = self.analyze(prefix)?;
+        let mut reader = fst.bytes_reader();
+
+        let mut searcher = TopNSearcher::new(fst, num, by_cost);
+        match str::from_utf8(&analyzed) {
+            Ok(word) if fuzzy && word.chars().count() >= FUZZY_MIN_LENGTH => {
+                for path in Self::fuzzy_starts(fst, word, &mut reader)? {
+                    searcher.add_start(path.arc, path.output, true, path.input);
+                }
+            }
+            _ => {
+                if let Some(path) = Self::exact_start(fst, &analyzed, &mut reader)? {
+                    searcher.add_start(path.arc, path.output, true, path.input);
+                }
+            }
+        }
+        let mut results = Vec::with_capacity(num);
+        for (_, output) in searcher.search()? {
+            let bytes = output.output2.inner();
+            let key_len = ((bytes[0] as usize) << 8) | bytes[1] as usize;
+            let key = String::from_utf8(bytes[2..2 + key_len].to_vec())?;
+            results.push(LookupResult {
+                key,
+                weight: decode_weight(output.output1.get()),
+                payload: bytes[2 + key_len..].to_vec(),
+            });
+        }
+        Ok(results)
+    }
+
+    /// Writes the suggestions to the file `name` of `dir`.
+    pub fn store<D: Directory>(&self, dir: &D, name: &str) -> Result<()> {
+        let mut out = dir.create_output(name, &IOContext::Default)?;
+        codec_util::write_header(&mut out, CODEC_NAME, VERSION_CURRENT)?;
+        out.write_vlong(self.count as i64)?;
+        match self.fst {
+            Some(ref fst) => {
+                out.write_byte(1)?;
+                fst.save(&mut out)?;
+            }
+            None => out.write_byte(0)?,
+        }
+        codec_util::write_footer(&mut out)
+    }
+
+    /// Replaces the suggestions with the ones written by `store` to the file
+    /// `name` of `dir`.
+    pub fn load<D: Directory>(&mut self, dir: &D, name: &str) -> Result<()> {
+        let mut input = dir.open_checksum_input(name, &IOContext::READ_ONCE)?;
+        codec_util::check_header(&mut input, CODEC_NAME, VERSION_START, VERSION_CURRENT)?;
+        let count = input.read_vlong()? as u64;
+        let fst = if input.read_byte()? == 1 {
+            Some(FST::from_input(&mut input, output_factory())?)
+        } else {
+            None
+        };
+        codec_util::check_footer(&mut input)?;
+        self.fst = fst;
+        self.count = count;
+        Ok(())
+    }
+
+    fn analyze(&self, text: &str) -> Result<Vec<u8>> {
+        let mut stream = self.analyzer.token_stream("", text)?;
+        stream.reset()?;
+        let mut analyzed = Vec::new();
+        while stream.increment_token()? {
+            if !analyzed.is_empty() {
+                analyzed.push(SEP_LABEL);
+            }
+            analyzed.extend_from_slice(stream.term_bytes_attribute().get_bytes_ref().bytes());
+        }
+        stream.end()?;
+        Ok(analyzed)
+    }
+
+    // the path leading to the node of the analyzed prefix
+    fn exact_start(
+        fst: &FST<SuggestOutputFactory>,
+        analyzed: &[u8],
+        reader: &mut dyn BytesReader,
+    ) -> Result<Option<Path>> {
+        let mut path = Path::new(fst);
+        for &label in analyzed {
+            match fst.find_target_arc(i32::from(label), &path.arc, reader)? {
+                Some(arc) => path = path.follow(fst, arc),
+                None => return Ok(None),
+            }
+        }
+        Ok(Some(path))
+    }
+
+    // the paths leading to the nodes whose input is within one edit of the
+    // analyzed prefix, none of them being below another one so that every
+    // completion is reached once
+    fn fuzzy_starts(
+        fst: &FST<SuggestOutputFactory>,
+        word: &str,
+        reader: &mut dyn BytesReader,
+    ) -> Result<Vec<Path>> {
+        let dfa = LevenshteinAutomata::new(word, true).to_automaton(1)?;
+        let mut starts = Vec::new();
+        // the paths with the automaton state of their input, and the bytes of
+        // the char being read
+        let mut stack = vec![(Path::new(fst), dfa.start(), Vec::new())];
+        while let Some((path, state, pending)) = stack.pop() {
+            if pending.is_empty() && dfa.distance(state).is_some() {
+                starts.push(path);
+                continue;
+            }
+            let mut arc = fst.read_first_target_arc(&path.arc, reader)?;
+            loop {
+                if arc.label != fst::END_LABEL && arc.label != i32::from(END_BYTE) {
+                    let mut bytes = pending.clone();
+                    bytes.push(arc.label as u8);
+                    if bytes.len() < utf8_len(bytes[0]) {
+                        stack.push((path.follow(fst, arc.clone()), state, bytes));
+                    } else if let Some(c) =
+                        str::from_utf8(&bytes).ok().and_then(|s| s.chars().next())
+                    {
+                        let next = dfa.step(state, c);
+                        if next != DEAD_STATE {
+                            stack.push((path.follow(fst, arc.clone()), next, Vec::new()));
+                        }
+                    }
+                }
+                if arc.is_last() {
+                    break;
+                }
+                fst.read_next_arc(&mut arc, reader)?;
+            }
+        }
+        Ok(starts)
+    }
+}
+
+fn output_factory() -> SuggestOutputFactory {
+    PairOutputFactory::new(
+        PositiveIntOutputFactory::new(),
+        ByteSequenceOutputFactory::new(),
+    )
+}
+
+fn by_cost(a: &SuggestOutput, b: &SuggestOutput) -> Ordering {
+    a.output1.get().cmp(&b.output1.get())
+}
+
+fn encode_weight(weight: u32) -> u64 {
+    u64::from(u32::MAX - weight)
+}
+
+fn decode_weight(cost: u64) -> u32 {
+    u32::MAX - cost as u32
+}
+
+// the length of the UTF-8 sequence starting with `b`
+fn utf8_len(b: u8) -> usize {
+    if b < 0x80 {
+        1
+    } else if b < 0xE0 {
+        2
+    } else if b < 0xF0 {
+        3
+    } else {
+        4
+    }
+}
+
+// a path from the root of the FST
+struct Path {
+    // the last arc of the path
+    arc: fst::Arc<SuggestOutput>,
+    output: SuggestOutput,
+    input: Vec<u8>,
+}
+
+impl Path {
+    fn new(fst: &FST<SuggestOutputFactory>) -> Path {
+        Path {
+            arc: fst.root_arc(),
+            output: fst.outputs().empty(),
+            input: Vec::new(),
+        }
+    }
+
+    fn follow(&self, fst: &FST<SuggestOutputFactory>, arc: fst::Arc<SuggestOutput>) -> Path {
+        let output = match arc.output {
+            Some(ref out) => fst.outputs().add(&self.output, out),
+            None => self.output.clone(),
+        };
+        let mut input = self.input.clone();
+        input.push(arc.label as u8);
+        Path { arc, output, input }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::analysis::standard_tokenizer::StandardTokenizer;
+    use core::analysis::{CustomAnalyzer, LowerCaseFilter};
+    use core::store::RAMDirectory;
+
+    const WORDS: [&str; 10] = [
+        "alpha", "bravo", "charlie", "delta", "echo", "foxtrot", "golf", "hotel", "india", "juliet",
+    ];
+
+    fn analyzer() -> Arc<dyn Analyzer> {
+        Arc::new(
+            CustomAnalyzer::builder()
+                .tokenizer(|text| Box::new(StandardTokenizer::new(text)))
+                .filter(|input| Box::new(LowerCaseFilter::new(input)))
+                .build()
+                .unwrap(),
+        )
+    }
+
+    fn capitalize(word: &str) -> String {
+        let mut capitalized = word[..1].to_uppercase();
+        capitalized.push_str(&word[1..]);
+        capitalized
+    }
+
+    // 10k distinct capitalized phrases of four words with distinct weights
+    fn inputs() -> Vec<SuggestInput> {
+        (0..10_000u32)
+            .map(|i| {
+                let phrase = [i % 10, i / 10 % 10, i / 100 % 10, i / 1000]
+                    .iter()
+                    .map(|&w| capitalize(WORDS[w as usize]))
+                    .collect::<Vec<_>>()
+                    .join(" ");
+                let weight = (i * 7919) % 100_003;
+                SuggestInput::with_payload(phrase, weight, i.to_string().into_bytes())
+            })
+            .collect()
+    }
+
+    // the analyzed form of the phrases built by `inputs`
+    fn analyzed(key: &str) -> String {
+        key.to_lowercase().replace(' ', "\u{1F}")
+    }
+
+    fn brute_force<F: Fn(&str) -> bool>(
+        inputs: &[SuggestInput],
+        num: usize,
+        matches: F,
+    ) -> Vec<LookupResult> {
+        let mut expected: Vec<_> = inputs
+            .iter()
+            .filter(|input| matches(&analyzed(&input.key)))
+            .map(|input| LookupResult {
+                key: input.key.clone(),
+                weight: input.weight,
+                payload: input.payload.clone(),
+            })
+            .collect();
+        expected.sort_by(|a, b| b.weight.cmp(&a.weight));
+        expected.truncate(num);
+        expected
+    }
+
+    fn build() -> (AnalyzingSuggester, Vec<SuggestInput>) {
+        let inputs = inputs();
+        let mut suggester = AnalyzingSuggester::new(analyzer());
+        suggester.build(inputs.clone()).unwrap();
+        assert_eq!(suggester.count(), 10_000);
+        (suggester, inputs)
+    }
+
+    #[test]
+    fn test_exact_lookup() {
+        let (suggester, inputs) = build();
+
+        for prefix in &[
+            "a",
+            "alpha",
+            "alpha br",
+            "Alpha Bravo C",
+            "hotel india j",
+            "kilo",
+        ] {
+            for &num in &[1, 5, 30] {
+                let matched = analyzed(prefix);
+                let expected = brute_force(&inputs, num, |s| s.starts_with(&matched));
+                assert_eq!(suggester.lookup(prefix, num, false).unwrap(), expected);
+            }
+        }
+        let top = suggester.lookup("alpha br", 10, false).unwrap();
+        assert_eq!(top.len(), 10);
+        assert!(top.windows(2).all(|w| w[0].weight > w[1].weight));
+        assert!(top.iter().all(|r| r.key.starts_with("Alpha Bravo ")));
+
+        // the empty prefix completes to the best suggestions
+        let expected = brute_force(&inputs, 3, |_| true);
+        assert_eq!(suggester.lookup("", 3, false).unwrap(), expected);
+        assert!(suggester.lookup("alpha", 0, false).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_case_folded_lookup() {
+        let (suggester, _) = build();
+
+        let lowercased = suggester.lookup("golf hotel", 5, false).unwrap();
+        assert_eq!(lowercased.len(), 5);
+        assert_eq!(
+            suggester.lookup("GOLF HOTEL", 5, false).unwrap(),
+            lowercased
+        );
+        assert_eq!(
+            suggester.lookup("Golf  Hotel", 5, false).unwrap(),
+            lowercased
+        );
+        for result in &lowercased {
+            assert!(result.key.starts_with("Golf Hotel "));
+            let i: u32 = String::from_utf8(result.payload.clone())
+                .unwrap()
+                .parse()
+                .unwrap();
+            assert_eq!(result.weight, (i * 7919) % 100_003);
+        }
+    }
+
+    #[test]
+    fn test_fuzzy_lookup() {
+        let (suggester, inputs) = build();
+
+        for prefix in &["alpah br", "delat", "hotle india", "golf hotel"] {
+            let dfa = LevenshteinAutomata::new(&analyzed(prefix), true)
+                .to_automaton(1)
+                .unwrap();
+            // some prefix of the suggestion is within one edit
+            let expected = brute_force(&inputs, 10, |s| {
+                s.char_indices()
+                    .map(|(i, _)| i)
+                    .chain(Some(s.len()))
+                    .any(|i| dfa.run(&s[..i]).is_some())
+            });
+            assert_eq!(expected.len(), 10);
+            assert_eq!(suggester.lookup(prefix, 10, true).unwrap(), expected);
+        }
+        assert!(suggester.lookup("alpah br", 10, false).unwrap().is_empty());
+
+        // the short prefixes are not fuzzy
+        assert_eq!(
+            suggester.lookup("ju", 10, true).unwrap(),
+            suggester.lookup("ju", 10, false).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_store_and_load() {
+        let (suggester, _) = build();
+        let dir = RAMDirectory::new();
+        suggester.store(&dir, "suggest.fst").unwrap();
+
+        let mut loaded = AnalyzingSuggester::new(analyzer());
+        loaded.load(&dir, "suggest.fst").unwrap();
+        assert_eq!(loaded.count(), 10_000);
+        for prefix in &["echo f", "india", "bravo charlie delta e"] {
+            assert_eq!(
+                loaded.lookup(prefix, 7, false).unwrap(),
+                suggester.lookup(prefix, 7, false).unwrap()
+            );
+        }
+        assert_eq!(
+            loaded.lookup("fxotrot", 4, true).unwrap(),
+            suggester.lookup("fxotrot", 4, true).unwrap()
+        );
+
+        let empty = AnalyzingSuggester::new(analyzer());
+        empty.store(&dir, "empty.fst").unwrap();
+        loaded.load(&dir, "empty.fst").unwrap();
+        assert_eq!(loaded.count(), 0);
+        assert!(loaded.lookup("alpha", 5, false).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_shared_analyzed_form() {
+        let mut suggester = AnalyzingSuggester::new(analyzer());
+        suggester
+            .build(vec![
+                SuggestInput::new("New York", 10),
+                SuggestInput::new("new york", 30),
+                SuggestInput::new("NEW YORK", 20),
+                SuggestInput::new("Newark", 40),
+            ])
+            .unwrap();
+
+        let keys: Vec<_> = suggester
+            .lookup("new y", 5, false)
+            .unwrap()
+            .into_iter()
+            .map(|r| (r.key, r.weight))
+            .collect();
+        assert_eq!(
+            keys,
+            vec![
+                ("new york".to_string(), 30),
+                ("NEW YORK".to_string(), 20),
+                ("New York".to_string(), 10),
+            ]
+        );
+        assert_eq!(suggester.lookup("new", 5, false).unwrap()[0].key, "Newark");
+    }
+}
@@ -0,0 +1,527 @@
+// Copyright 2019 Zhizhesihai (Beijing) Technology Limited.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use core::codec::Codec;
+use core::index::NO_MORE_ORDS;
+use core::index::{DocValuesType, LeafReader, LeafReaderContext, SearchLeafReader};
+use core::index::{SortedDocValues, SortedDocValuesRef, SortedSetDocValues, SortedSetDocValuesRef};
+use core::index::{Term, TermIterator, Terms};
+use core::search::explanation::Explanation;
+use core::search::match_all::ConstantScoreScorer;
+use core::search::posting_iterator::PostingIteratorFlags;
+use core::search::searcher::SearchPlanBuilder;
+use core::search::term_query::TermQuery;
+use core::search::{DocIdSet, DocIterator, Query, Scorer, Weight, NO_MORE_DOCS};
+use core::util::bit_set::{BitSet, FixedBitSet};
+use core::util::{Bits, DocId, DocIdSetBuilder};
+
+use error::ErrorKind::IllegalState;
+use error::Result;
+
+use std::fmt;
+
+pub const TERM_IN_SET: &str = "term_in_set";
+
+/// Below this many terms the postings of the terms are always unioned.
+pub const DEFAULT_DOC_VALUES_THRESHOLD: usize = 16;
+
+/// How a `TermInSetQuery` finds the matching docs of a segment.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TermInSetStrategy {
+    /// Unions the postings lists of the terms.
+    Postings,
+    /// Scans the sorted (set) doc values of the field, checking the ords of
+    /// every doc against the ords of the terms.
+    DocValues,
+}
+
+/// Matches the docs having any of the terms in the field, with a constant score.
+///
+/// When the field has sorted (set) doc values and there are at least
+/// `doc_values_threshold` terms, the matching docs of a segment are found by
+/// scanning the doc values if the postings to union (the sum of the doc freqs
+/// of the terms) are more than the docs having the field.
+#[derive(Clone, Debug)]
+pub struct TermInSetQuery {
+    field: String,
+    // sorted and deduplicated
+    terms: Vec<Vec<u8>>,
+    doc_values_threshold: usize,
+    strategy: Option<TermInSetStrategy>,
+}
+
+impl TermInSetQuery {
+    pub fn new(field: String, mut terms: Vec<Vec<u8>>) -> TermInSetQuery {
+        terms.sort();
+        terms.dedup();
+        TermInSetQuery {
+            field,
+            terms,
+            doc_values_threshold: DEFAULT_DOC_VALUES_THRESHOLD,
+            strategy: None,
+        }
+    }
+
+    pub fn with_doc_values_threshold(mut self, threshold: usize) -> TermInSetQuery {
+        self.doc_values_threshold = threshold;
+        self
+    }
+
+    /// Forces the strategy of every segment instead of choosing the cheaper one.
+    pub fn with_strategy(mut self, strategy: TermInSetStrategy) -> TermInSetQuery {
+        self.strategy = Some(strategy);
+        self
+    }
+
+    pub fn field(&self) -> &str {
+        &self.field
+    }
+
+    pub fn terms(&self) -> &[Vec<u8>] {
+        &self.terms
+    }
+
+    /// Returns the strategy used for the segment of `reader`.
+    pub fn strategy<C: Codec>(&self, reader: &SearchLeafReader<C>) -> Result<TermInSetStrategy> {
+        if let Some(strategy) = self.strategy {
+            return Ok(strategy);
+        }
+        let has_doc_values = match reader.field_info(&self.field).map(|fi| fi.doc_values_type) {
+            Some(DocValuesType::Sorted) | Some(DocValuesType::SortedSet) => true,
+            _ => false,
+        };
+        if !has_doc_values || self.terms.len() < self.doc_values_threshold {
+            return Ok(TermInSetStrategy::Postings);
+        }
+        let terms = match reader.terms(&self.field)? {
+            Some(terms) => terms,
+            None => return Ok(TermInSetStrategy::DocValues),
+        };
+        let docs_with_field = match terms.doc_count()? {
+            -1 => i64::from(reader.max_doc()),
+            doc_count => i64::from(doc_count),
+        };
+        let mut postings_cost = 0i64;
+        let mut iter = terms.iterator()?;
+        for term in &self.terms {
+            if iter.seek_exact(term)? {
+                postings_cost += i64::from(iter.doc_freq()?);
+                if postings_cost > docs_with_field {
+                    return Ok(TermInSetStrategy::DocValues);
+                }
+            }
+        }
+        Ok(TermInSetStrategy::Postings)
+    }
+}
+
+impl<C: Codec> Query<C> for TermInSetQuery {
+    fn create_weight(
+        &self,
+        _searcher: &dyn SearchPlanBuilder<C>,
+        _needs_scores: bool,
+    ) -> Result<Box<dyn Weight<C>>> {
+        Ok(Box::new(TermInSetWeight {
+            query: self.clone(),
+            weight: 0f32,
+            norm: 1f32,
+        }))
+    }
+
+    fn extract_terms(&self) -> Vec<TermQuery> {
+        self.terms
+            .iter()
+            .map(|t| TermQuery::new(Term::new(self.field.clone(), t.clone()), 1.0, None))
+            .collect()
+    }
+
+    fn query_type(&self) -> &'static str {
+        TERM_IN_SET
+    }
+
+    fn as_any(&self) -> &::std::any::Any {
+        self
+    }
+}
+
+impl fmt::Display for TermInSetQuery {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let terms: Vec<String> = self
+            .terms
+            .iter()
+            .map(|t| String::from_utf8_lossy(t).into_owned())
+            .collect();
+        write!(
+            f,
+            "TermInSetQuery(field: {}, terms: [{}])",
+            &self.field,
+            terms.join(", ")
+        )
+    }
+}
+
+struct TermInSetWeight {
+    query: TermInSetQuery,
+    weight: f32,
+    norm: f32,
+}
+
+impl TermInSetWeight {
+    fn postings_scorer<C: Codec>(
+        &self,
+        reader: &SearchLeafReader<C>,
+    ) -> Result<Option<Box<dyn Scorer>>> {
+        let terms = match reader.terms(&self.query.field)? {
+            Some(terms) => terms,
+            None => return Ok(None),
+        };
+        let mut result = DocIdSetBuilder::from_terms(reader.max_doc(), &terms)?;
+        let mut iter = terms.iterator()?;
+        for term in &self.query.terms {
+            if iter.seek_exact(term)? {
+                let mut postings = iter.postings_with_flags(PostingIteratorFlags::NONE)?;
+                result.add(&mut postings)?;
+            }
+        }
+        match result.build().iterator()? {
+            Some(iterator) => {
+                let cost = iterator.cost();
+                Ok(Some(Box::new(ConstantScoreScorer::new(
+                    self.weight,
+                    iterator,
+                    cost,
+                ))))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn doc_values_scorer<C: Codec>(
+        &self,
+        reader: &SearchLeafReader<C>,
+    ) -> Result<Option<Box<dyn Scorer>>> {
+        let doc_values_type = match reader.field_info(&self.query.field) {
+            Some(fi) => fi.doc_values_type,
+            None => return Ok(None),
+        };
+        let mut ords = vec![];
+        let (values, value_count) = match doc_values_type {
+            DocValuesType::Sorted => {
+                let values = reader.get_sorted_doc_values(&self.query.field)?;
+                for term in &self.query.terms {
+                    let ord = values.lookup_term(term)?;
+                    if ord >= 0 {
+                        ords.push(i64::from(ord));
+                    }
+                }
+                let value_count = values.get_value_count();
+                (TermValues::Sorted(values), value_count)
+            }
+            DocValuesType::SortedSet => {
+                let values = reader.get_sorted_set_doc_values(&self.query.field)?;
+                for term in &self.query.terms {
+                    let ord = values.lookup_term(term)?;
+                    if ord >= 0 {
+                        ords.push(ord);
+                    }
+                }
+                let value_count = values.get_value_count();
+                (TermValues::SortedSet(values), value_count)
+            }
+            _ => bail!(IllegalState(format!(
+                "field '{}' has no sorted doc values to match the terms with",
+                self.query.field
+            ))),
+        };
+        if ords.is_empty() {
+            return Ok(None);
+        }
+        let max_doc = reader.max_doc();
+        let iterator = DocValuesTermsIterator {
+            values,
+            ords: OrdSet::new(ords, value_count),
+            doc: -1,
+            max_doc,
+        };
+        Ok(Some(Box::new(ConstantScoreScorer::new(
+            self.weight,
+            iterator,
+            max_doc as usize,
+        ))))
+    }
+}
+
+impl<C: Codec> Weight<C> for TermInSetWeight {
+    fn create_scorer(
+        &self,
+        leaf_reader_ctx: &LeafReaderContext<'_, C>,
+    ) -> Result<Option<Box<dyn Scorer>>> {
+        if self.query.terms.is_empty() {
+            return Ok(None);
+        }
+        let leaf_reader = leaf_reader_ctx.reader;
+        match self.query.strategy(leaf_reader)? {
+            TermInSetStrategy::Postings => self.postings_scorer(leaf_reader),
+            TermInSetStrategy::DocValues => self.doc_values_scorer(leaf_reader),
+        }
+    }
+
+    fn query_type(&self) -> &'static str {
+        TERM_IN_SET
+    }
+
+    fn normalize(&mut self, norm: f32, boost: f32) {
+        self.weight = norm * boost;
+        self.norm = norm;
+    }
+
+    fn value_for_normalization(&self) -> f32 {
+        self.weight * self.weight
+    }
+
+    fn needs_scores(&self) -> bool {
+        false
+    }
+
+    fn explain(&self, reader: &LeafReaderContext<'_, C>, doc: DocId) -> Result<Explanation> {
+        let matched = match self.create_scorer(reader)? {
+            Some(mut scorer) => scorer.advance(doc)? == doc,
+            None => false,
+        };
+        if matched {
+            Ok(Explanation::new(
+                true,
+                self.weight,
+                format!("{}, product of:", self),
+                vec![
+                    Explanation::new(true, self.weight, "boost".to_string(), vec![]),
+                    Explanation::new(true, self.norm, "queryNorm".to_string(), vec![]),
+                ],
+            ))
+        } else {
+            Ok(Explanation::new(
+                false,
+                0f32,
+                format!("{} doesn't match id {}", self, doc),
+                vec![],
+            ))
+        }
+    }
+}
+
+impl fmt::Display for TermInSetWeight {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "TermInSetWeight({})", &self.query)
+    }
+}
+
+enum TermValues {
+    Sorted(SortedDocValuesRef),
+    SortedSet(SortedSetDocValuesRef),
+}
+
+/// The segment ords of the terms of the query.
+enum OrdSet {
+    Sorted(Vec<i64>),
+    Bits(FixedBitSet),
+}
+
+impl OrdSet {
+    fn new(mut ords: Vec<i64>, value_count: usize) -> OrdSet {
+        // a bit set is no larger than the sorted ords when there are less
+        // than 64 values per ord
+        if value_count <= ords.len() * 64 {
+            let mut bits = FixedBitSet::new(value_count);
+            for ord in ords {
+                bits.set(ord as usize);
+            }
+            OrdSet::Bits(bits)
+        } else {
+            ords.sort();
+            OrdSet::Sorted(ords)
+        }
+    }
+
+    fn contains(&self, ord: i64) -> Result<bool> {
+        match *self {
+            OrdSet::Sorted(ref ords) => Ok(ords.binary_search(&ord).is_ok()),
+            OrdSet::Bits(ref bits) => bits.get(ord as usize),
+        }
+    }
+}
+
+/// Iterates the docs having one of the ords in their doc values.
+struct DocValuesTermsIterator {
+    values: TermValues,
+    ords: OrdSet,
+    doc: DocId,
+    max_doc: DocId,
+}
+
+impl DocValuesTermsIterator {
+    fn matches_doc(&self, doc: DocId) -> Result<bool> {
+        match self.values {
+            TermValues::Sorted(ref values) => {
+                let ord = values.get_ord(doc)?;
+                Ok(ord >= 0 && self.ords.contains(i64::from(ord))?)
+            }
+            TermValues::SortedSet(ref values) => {
+                let mut ctx = values.set_document(doc)?;
+                loop {
+                    let ord = values.next_ord(&mut ctx)?;
+                    if ord == NO_MORE_ORDS {
+                        return Ok(false);
+                    }
+                    if self.ords.contains(ord)? {
+                        return Ok(true);
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl DocIterator for DocValuesTermsIterator {
+    fn doc_id(&self) -> DocId {
+        self.doc
+    }
+
+    fn next(&mut self) -> Result<DocId> {
+        let target = self.doc + 1;
+        self.advance(target)
+    }
+
+    fn advance(&mut self, target: DocId) -> Result<DocId> {
+        let mut doc = target;
+        while doc < self.max_doc {
+            if self.matches_doc(doc)? {
+                self.doc = doc;
+                return Ok(doc);
+            }
+            doc += 1;
+        }
+        self.doc = NO_MORE_DOCS;
+        Ok(NO_MORE_DOCS)
+    }
+
+    fn cost(&self) -> usize {
+        self.max_doc as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::doc::{Field, FieldType, SortedSetDocValuesField};
+    use core::index::tests::{open_test_reader, TestReader};
+    use core::index::{Fieldable, IndexOptions, IndexReader};
+    use core::search::collector::top_docs::TopDocsCollector;
+    use core::search::searcher::{DefaultIndexSearcher, IndexSearcher};
+    use core::util::VariantValue;
+
+    use std::sync::Arc;
+
+    const NUM_DOCS: usize = 2000;
+
+    // every doc is in "c0" and in "c{i % 100}"
+    fn build_index() -> Arc<TestReader> {
+        let mut field_type = FieldType::default();
+        field_type.tokenized = false;
+        field_type.index_options = IndexOptions::DocsAndFreqs;
+        let docs = (0..NUM_DOCS)
+            .map(|i| {
+                let mut doc: Vec<Box<dyn Fieldable>> = vec![];
+                for country in &["c0".to_string(), format!("c{}", i % 100)] {
+                    doc.push(Box::new(Field::new(
+                        "country".to_string(),
+                        field_type.clone(),
+                        Some(VariantValue::VString(country.clone())),
+                        None,
+                    )));
+                    doc.push(Box::new(SortedSetDocValuesField::new(
+                        "country",
+                        country.as_bytes(),
+                    )));
+                }
+                doc
+            })
+            .collect();
+        open_test_reader(vec![docs])
+    }
+
+    fn countries(range: ::std::ops::Range<usize>) -> Vec<Vec<u8>> {
+        range.map(|i| format!("c{}", i).into_bytes()).collect()
+    }
+
+    fn matching_docs(reader: &Arc<TestReader>, query: &TermInSetQuery) -> Vec<DocId> {
+        let searcher = DefaultIndexSearcher::new(Arc::clone(reader));
+        let mut collector = TopDocsCollector::new(NUM_DOCS);
+        searcher.search(query, &mut collector).unwrap();
+        let mut docs: Vec<DocId> = collector
+            .top_docs()
+            .score_docs()
+            .iter()
+            .map(|d| d.doc_id())
+            .collect();
+        docs.sort();
+        docs
+    }
+
+    #[test]
+    fn test_strategies_match_the_same_docs() {
+        let reader = build_index();
+        let mut terms = countries(1..40);
+        terms.push(b"missing".to_vec());
+        for terms in vec![terms, countries(0..20), countries(95..120)] {
+            let query = TermInSetQuery::new("country".to_string(), terms);
+            let postings = matching_docs(
+                &reader,
+                &query.clone().with_strategy(TermInSetStrategy::Postings),
+            );
+            let doc_values = matching_docs(
+                &reader,
+                &query.clone().with_strategy(TermInSetStrategy::DocValues),
+            );
+            assert!(!postings.is_empty());
+            assert_eq!(postings, doc_values);
+            assert_eq!(matching_docs(&reader, &query), postings);
+        }
+
+        let query = TermInSetQuery::new("country".to_string(), countries(1..40));
+        assert_eq!(matching_docs(&reader, &query).len(), NUM_DOCS / 100 * 39);
+        let query = TermInSetQuery::new("country".to_string(), countries(200..220))
+            .with_strategy(TermInSetStrategy::DocValues);
+        assert!(matching_docs(&reader, &query).is_empty());
+    }
+
+    #[test]
+    fn test_planner_picks_cheaper_strategy() {
+        let reader = build_index();
+        let leaves = reader.leaves();
+        let leaf = leaves[0].reader;
+
+        // 20 rare countries: 400 postings against 2000 docs with the field
+        let query = TermInSetQuery::new("country".to_string(), countries(1..21));
+        assert_eq!(query.strategy(leaf).unwrap(), TermInSetStrategy::Postings);
+
+        // "c0" is in every doc, so the postings outnumber the docs
+        let query = TermInSetQuery::new("country".to_string(), countries(0..20));
+        assert_eq!(query.strategy(leaf).unwrap(), TermInSetStrategy::DocValues);
+
+        // too few terms to consider the doc values
+        let query = TermInSetQuery::new("country".to_string(), countries(0..5));
+        assert_eq!(query.strategy(leaf).unwrap(), TermInSetStrategy::Postings);
+        let query = query.with_doc_values_threshold(4);
+        assert_eq!(query.strategy(leaf).unwrap(), TermInSetStrategy::DocValues);
+    }
+}
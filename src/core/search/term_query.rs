@@ -149,7 +149,6 @@ impl<C: Codec> Weight<C> for TermWeight<C> {
         &self,
         reader_context: &LeafReaderContext<'_, C>,
     ) -> Result<Option<Box<dyn Scorer>>> {
-        let _norms = reader_context.reader.norm_values(&self.term.field);
         let sim_scorer = self.sim_weight.sim_scorer(reader_context.reader)?;
 
         let flags = if self.needs_scores {
@@ -167,12 +166,25 @@ impl<C: Codec> Weight<C> for TermWeight<C> {
         }
     }
 
+    fn count(&self, reader_context: &LeafReaderContext<'_, C>) -> Result<Option<usize>> {
+        let reader = reader_context.reader;
+        if reader.num_docs() != reader.max_doc() {
+            // the doc freq counts the deleted docs
+            return Ok(None);
+        }
+        if !self.term_states.contains_key(&reader_context.doc_base) {
+            return Ok(Some(0));
+        }
+        Ok(Some(reader.doc_freq(&self.term)? as usize))
+    }
+
     fn query_type(&self) -> &'static str {
         TERM
     }
 
     fn normalize(&mut self, norm: f32, boost: f32) {
-        self.sim_weight.normalize(norm, boost)
+        // the boost of the enclosing queries multiplies the one of the term
+        self.sim_weight.normalize(norm, boost * self.boost)
     }
 
     fn value_for_normalization(&self) -> f32 {
@@ -233,3 +245,92 @@ impl<C: Codec> fmt::Display for TermWeight<C> {
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::doc::{Field, FieldType};
+    use core::index::tests::open_test_reader;
+    use core::index::{IndexOptions, IndexReader};
+    use core::search::collector::TopDocsCollector;
+    use core::search::searcher::{DefaultIndexSearcher, IndexSearcher};
+    use core::search::NO_MORE_DOCS;
+    use core::util::VariantValue;
+
+    use std::sync::Arc;
+
+    fn text(value: &str) -> Field {
+        let mut field_type = FieldType::default();
+        field_type.index_options = IndexOptions::DocsAndFreqs;
+        Field::new(
+            "body".to_string(),
+            field_type,
+            Some(VariantValue::VString(value.to_string())),
+            None,
+        )
+    }
+
+    // the lengths are powers of 4, their norms are decoded without loss
+    fn bm25(doc_freq: f32, doc_count: f32, avg_dl: f32, freq: f32, dl: f32) -> f32 {
+        let (k1, b) = (1.2f32, 0.75f32);
+        let idf = (1.0 + (doc_count - doc_freq + 0.5) / (doc_freq + 0.5)).ln();
+        idf * (k1 + 1.0) * freq / (freq + k1 * (1.0 - b + b * dl / avg_dl))
+    }
+
+    #[test]
+    fn test_term_query_bm25_scores() {
+        let long_doc = format!("apple{}", " pear".repeat(15));
+        let docs = [
+            "apple",
+            "apple apple apple pear",
+            "pear pear pear pear",
+            long_doc.as_str(),
+        ]
+        .iter()
+        .map(|body| vec![text(body)])
+        .collect();
+        let reader = open_test_reader(vec![docs]);
+        assert_eq!(reader.leaves().len(), 1);
+        let searcher = DefaultIndexSearcher::new(Arc::clone(&reader));
+        let query = TermQuery::new(Term::new("body".to_string(), b"apple".to_vec()), 1.0, None);
+        let mut collector = TopDocsCollector::new(10);
+        searcher.search(&query, &mut collector).unwrap();
+        let top_docs = collector.top_docs();
+        assert_eq!(top_docs.total_hits(), 3);
+
+        // 4 docs of 1 + 4 + 4 + 16 terms, "apple" is in 3 of them
+        let avg_dl = 25.0 / 4.0;
+        let expected = [
+            (1, bm25(3.0, 4.0, avg_dl, 3.0, 4.0)),
+            (0, bm25(3.0, 4.0, avg_dl, 1.0, 1.0)),
+            (3, bm25(3.0, 4.0, avg_dl, 1.0, 16.0)),
+        ];
+        let hits = top_docs.score_docs();
+        assert_eq!(hits.len(), expected.len());
+        for (hit, &(doc, score)) in hits.iter().zip(expected.iter()) {
+            assert_eq!(hit.doc_id(), doc);
+            assert!(
+                (hit.score() - score).abs() < 1e-5,
+                "doc {} scored {}, expected {}",
+                doc,
+                hit.score(),
+                score
+            );
+            let explanation = searcher.explain(&query, doc).unwrap();
+            assert!((explanation.value() - hit.score()).abs() < 1e-5);
+        }
+
+        // the scorer iterates the postings of the term
+        let weight = searcher.create_weight(&query, true).unwrap();
+        let leaves = reader.leaves();
+        let mut scorer = weight.create_scorer(&leaves[0]).unwrap().unwrap();
+        assert_eq!(scorer.cost(), 3);
+        assert_eq!(scorer.next().unwrap(), 0);
+        assert_eq!(scorer.advance(2).unwrap(), 3);
+        assert!((scorer.score().unwrap() - expected[2].1).abs() < 1e-5);
+        assert_eq!(scorer.next().unwrap(), NO_MORE_DOCS);
+
+        let missing = TermQuery::new(Term::new("body".to_string(), b"kiwi".to_vec()), 1.0, None);
+        assert_eq!(searcher.count(&missing).unwrap(), 0);
+    }
+}
@@ -0,0 +1,211 @@
+// Copyright 2019 Zhizhesihai (Beijing) Technology Limited.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use core::codec::Codec;
+use core::search::multi_term_query::{AcceptStatus, MultiTermWeight, RewriteMethod, TermFilter};
+use core::search::searcher::SearchPlanBuilder;
+use core::search::term_query::TermQuery;
+use core::search::{Query, Weight};
+
+use error::Result;
+
+use std::cmp::Ordering;
+use std::fmt;
+
+pub const TERM_RANGE: &str = "term_range";
+
+/// Matches the docs having a term between the bounds in the field, with a
+/// constant score. The terms are compared as bytes, a missing bound leaves that
+/// side of the range open.
+#[derive(Clone, Debug)]
+pub struct TermRangeQuery {
+    field: String,
+    lower: Option<Vec<u8>>,
+    upper: Option<Vec<u8>>,
+    include_lower: bool,
+    include_upper: bool,
+    rewrite_method: RewriteMethod,
+}
+
+impl TermRangeQuery {
+    pub fn new(
+        field: String,
+        lower: Option<Vec<u8>>,
+        upper: Option<Vec<u8>>,
+        include_lower: bool,
+        include_upper: bool,
+    ) -> TermRangeQuery {
+        TermRangeQuery {
+            field,
+            lower,
+            upper,
+            include_lower,
+            include_upper,
+            rewrite_method: RewriteMethod::default(),
+        }
+    }
+
+    pub fn with_rewrite_method(mut self, rewrite_method: RewriteMethod) -> TermRangeQuery {
+        self.rewrite_method = rewrite_method;
+        self
+    }
+
+    pub fn field(&self) -> &str {
+        &self.field
+    }
+
+    pub fn lower(&self) -> Option<&[u8]> {
+        self.lower.as_ref().map(Vec::as_slice)
+    }
+
+    pub fn upper(&self) -> Option<&[u8]> {
+        self.upper.as_ref().map(Vec::as_slice)
+    }
+
+    pub fn include_lower(&self) -> bool {
+        self.include_lower
+    }
+
+    pub fn include_upper(&self) -> bool {
+        self.include_upper
+    }
+}
+
+impl TermFilter for TermRangeQuery {
+    fn field(&self) -> &str {
+        &self.field
+    }
+
+    fn lower_bound(&self) -> Option<&[u8]> {
+        self.lower()
+    }
+
+    fn accept(&self, term: &[u8]) -> AcceptStatus {
+        if !self.include_lower && self.lower() == Some(term) {
+            return AcceptStatus::No;
+        }
+        if let Some(ref upper) = self.upper {
+            match term.cmp(upper.as_slice()) {
+                Ordering::Greater => return AcceptStatus::End,
+                Ordering::Equal if !self.include_upper => return AcceptStatus::End,
+                _ => {}
+            }
+        }
+        AcceptStatus::Yes
+    }
+}
+
+impl<C: Codec> Query<C> for TermRangeQuery {
+    fn create_weight(
+        &self,
+        searcher: &dyn SearchPlanBuilder<C>,
+        _needs_scores: bool,
+    ) -> Result<Box<dyn Weight<C>>> {
+        let weight = MultiTermWeight::new(self.clone(), self.rewrite_method, TERM_RANGE);
+        Ok(Box::new(
+            weight.with_timeout(searcher.query_timeout().cloned()),
+        ))
+    }
+
+    fn extract_terms(&self) -> Vec<TermQuery> {
+        vec![]
+    }
+
+    fn query_type(&self) -> &'static str {
+        TERM_RANGE
+    }
+
+    fn as_any(&self) -> &::std::any::Any {
+        self
+    }
+}
+
+impl fmt::Display for TermRangeQuery {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let bound = |b: &Option<Vec<u8>>| {
+            b.as_ref()
+                .map(|b| String::from_utf8_lossy(b).into_owned())
+                .unwrap_or_else(|| "*".to_string())
+        };
+        write!(
+            f,
+            "TermRangeQuery(field: {}, {}{} TO {}{})",
+            &self.field,
+            if self.include_lower { "[" } else { "{" },
+            bound(&self.lower),
+            bound(&self.upper),
+            if self.include_upper { "]" } else { "}" },
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::doc::{Field, FieldType};
+    use core::index::tests::open_test_reader;
+    use core::index::IndexOptions;
+    use core::search::searcher::{DefaultIndexSearcher, IndexSearcher};
+    use core::util::VariantValue;
+
+    fn range(lower: Option<&str>, upper: Option<&str>, inclusive: (bool, bool)) -> TermRangeQuery {
+        TermRangeQuery::new(
+            "id".to_string(),
+            lower.map(|l| l.as_bytes().to_vec()),
+            upper.map(|u| u.as_bytes().to_vec()),
+            inclusive.0,
+            inclusive.1,
+        )
+    }
+
+    #[test]
+    fn test_term_range_query() {
+        let mut field_type = FieldType::default();
+        field_type.tokenized = false;
+        field_type.index_options = IndexOptions::Docs;
+        // "p000" to "p199", one per doc
+        let docs = (0..200)
+            .map(|i| {
+                vec![Field::new(
+                    "id".to_string(),
+                    field_type.clone(),
+                    Some(VariantValue::VString(format!("p{:03}", i))),
+                    None,
+                )]
+            })
+            .collect();
+        let searcher = DefaultIndexSearcher::new(open_test_reader(vec![docs]));
+        let cases = [
+            (Some("p010"), Some("p020"), (true, true), 11),
+            (Some("p010"), Some("p020"), (false, true), 10),
+            (Some("p010"), Some("p020"), (true, false), 10),
+            (Some("p010"), Some("p020"), (false, false), 9),
+            (None, Some("p005"), (true, true), 6),
+            (Some("p195"), None, (true, true), 5),
+            (None, None, (true, true), 200),
+            (Some("p0"), Some("p1"), (true, true), 100),
+            (Some("p0505"), Some("p07"), (true, false), 19),
+            // no terms in the range
+            (Some("a"), Some("b"), (true, true), 0),
+            (Some("p010"), Some("p010"), (false, true), 0),
+            (Some("p0101"), Some("p0109"), (true, true), 0),
+            (Some("q"), None, (true, true), 0),
+        ];
+        for &(lower, upper, inclusive, count) in &cases {
+            let query = range(lower, upper, inclusive);
+            assert_eq!(searcher.count(&query).unwrap(), count, "{}", query);
+            let query = query.with_rewrite_method(RewriteMethod::Boolean);
+            assert_eq!(searcher.count(&query).unwrap(), count, "{}", query);
+        }
+    }
+}
@@ -15,21 +15,30 @@ use core::search::posting_iterator::PostingIterator;
 use core::search::DocIterator;
 use core::search::Scorer;
 use core::search::SimScorer;
+use core::search::NO_MORE_DOCS;
 use core::util::DocId;
 use error::Result;
 
+use std::f32;
+
 pub struct TermScorer<T: PostingIterator> {
     sim_scorer: Box<dyn SimScorer>,
     postings_iterator: T,
     boost: f32,
+    max_score: f32,
+    // the postings are skipped to the end once it is over the max score
+    min_competitive_score: f32,
 }
 
 impl<T: PostingIterator> TermScorer<T> {
     pub fn new(sim_scorer: Box<dyn SimScorer>, postings_iterator: T, boost: f32) -> Self {
+        let max_score = sim_scorer.max_score();
         TermScorer {
             sim_scorer,
             postings_iterator,
             boost,
+            max_score,
+            min_competitive_score: f32::NEG_INFINITY,
         }
     }
 
@@ -49,6 +58,15 @@ impl<T: PostingIterator> Scorer for TermScorer<T> {
         self.boost;
         Ok(self.sim_scorer.score(doc_id, freq as f32)?)
     }
+
+    fn set_min_competitive_score(&mut self, min_score: f32) -> Result<()> {
+        self.min_competitive_score = min_score;
+        Ok(())
+    }
+
+    fn max_score(&self) -> f32 {
+        self.max_score
+    }
 }
 
 impl<T: PostingIterator> DocIterator for TermScorer<T> {
@@ -57,10 +75,16 @@ impl<T: PostingIterator> DocIterator for TermScorer<T> {
     }
 
     fn next(&mut self) -> Result<DocId> {
+        if self.max_score < self.min_competitive_score {
+            return self.postings_iterator.advance(NO_MORE_DOCS);
+        }
         self.postings_iterator.next()
     }
 
     fn advance(&mut self, target: DocId) -> Result<DocId> {
+        if self.max_score < self.min_competitive_score {
+            return self.postings_iterator.advance(NO_MORE_DOCS);
+        }
         self.postings_iterator.advance(target)
     }
 
@@ -45,23 +45,26 @@ impl ScoreDoc {
     }
 }
 
+/// Orders the better hits first: higher scores, then lower doc ids.
 impl Ord for ScoreDoc {
     fn cmp(&self, other: &Self) -> Ordering {
-        self.score.partial_cmp(&other.score).unwrap()
+        other
+            .score
+            .partial_cmp(&self.score)
+            .unwrap()
+            .then_with(|| self.doc.cmp(&other.doc))
     }
 }
 
 impl PartialOrd for ScoreDoc {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        self.score
-            .partial_cmp(&other.score)
-            .map(|ord| ord.reverse())
+        Some(self.cmp(other))
     }
 }
 
 impl PartialEq for ScoreDoc {
     fn eq(&self, other: &Self) -> bool {
-        self == other
+        self.cmp(other) == Ordering::Equal
     }
 }
 
@@ -112,7 +115,7 @@ impl Eq for FieldDoc {}
 
 impl PartialEq for FieldDoc {
     fn eq(&self, other: &Self) -> bool {
-        self == other
+        self.doc == other.doc && self.fields == other.fields
     }
 }
 
@@ -130,6 +133,22 @@ impl PartialOrd for FieldDoc {
     }
 }
 
+/// How the `total_hits` of `TopDocs` relates to the number of matching docs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TotalHitsRelation {
+    /// The total hits are the number of matching docs.
+    EqualTo,
+    /// The total hits are a lower bound, the hits over the total hits
+    /// threshold of the collector may not have been counted.
+    GreaterThanOrEqualTo,
+}
+
+impl Default for TotalHitsRelation {
+    fn default() -> Self {
+        TotalHitsRelation::EqualTo
+    }
+}
+
 #[derive(Clone, Debug)]
 pub enum ScoreDocHit {
     Score(ScoreDoc),
@@ -207,6 +226,9 @@ pub struct TopScoreDocs {
     /// The total number of hits for the query.
     pub total_hits: usize,
 
+    /// Whether `total_hits` is exact or a lower bound.
+    pub total_hits_relation: TotalHitsRelation,
+
     /// The top hits for the query.
     pub score_docs: Vec<ScoreDocHit>,
 
@@ -218,6 +240,7 @@ impl TopScoreDocs {
     pub fn new(total_hits: usize, score_docs: Vec<ScoreDocHit>) -> TopScoreDocs {
         TopScoreDocs {
             total_hits,
+            total_hits_relation: TotalHitsRelation::EqualTo,
             score_docs,
             max_score: f32::NAN,
         }
@@ -231,11 +254,29 @@ impl TopScoreDocs {
 #[derive(Clone)]
 pub struct TopFieldDocs {
     pub total_hits: usize,
+    pub total_hits_relation: TotalHitsRelation,
     pub score_docs: Vec<ScoreDocHit>,
     pub max_score: f32,
     pub fields: Vec<SortField>,
 }
 
+impl TopFieldDocs {
+    pub fn new(
+        total_hits: usize,
+        score_docs: Vec<ScoreDocHit>,
+        fields: Vec<SortField>,
+        max_score: f32,
+    ) -> TopFieldDocs {
+        TopFieldDocs {
+            total_hits,
+            total_hits_relation: TotalHitsRelation::EqualTo,
+            score_docs,
+            max_score,
+            fields,
+        }
+    }
+}
+
 pub struct CollapseTopFieldDocs {
     /// The total number of hits for the query.
     pub total_hits: usize,
@@ -255,8 +296,9 @@ pub struct CollapseTopFieldDocs {
     /// The field used for collapsing
     pub field: String,
 
-    /// The collapse value for each top doc
-    pub collapse_values: Vec<VariantValue>,
+    /// The collapse value for each top doc, `None` for the group of the docs
+    /// without a value
+    pub collapse_values: Vec<Option<VariantValue>>,
 }
 
 impl CollapseTopFieldDocs {
@@ -266,7 +308,7 @@ impl CollapseTopFieldDocs {
         total_groups: usize,
         score_docs: Vec<ScoreDocHit>,
         sort_fields: Vec<SortField>,
-        collapse_values: Vec<VariantValue>,
+        collapse_values: Vec<Option<VariantValue>>,
         max_score: f32,
     ) -> CollapseTopFieldDocs {
         CollapseTopFieldDocs {
@@ -300,6 +342,14 @@ impl TopDocs {
         }
     }
 
+    pub fn total_hits_relation(&self) -> TotalHitsRelation {
+        match *self {
+            TopDocs::Score(ref s) => s.total_hits_relation,
+            TopDocs::Field(ref f) => f.total_hits_relation,
+            TopDocs::Collapse(_) => TotalHitsRelation::EqualTo,
+        }
+    }
+
     pub fn total_groups(&self) -> usize {
         match *self {
             TopDocs::Score(ref s) => s.total_hits,
@@ -0,0 +1,367 @@
+// Copyright 2019 Zhizhesihai (Beijing) Technology Limited.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use core::search::scorer_arena::PooledVec;
+use core::search::{DocIterator, Scorer, NO_MORE_DOCS};
+use core::util::DocId;
+use error::Result;
+
+use std::cmp::{Ordering, Reverse};
+use std::collections::BinaryHeap;
+use std::f32;
+
+/// A disjunction summing the scores of its clauses, which skips the docs that
+/// can't reach the minimum competitive score given the maximum scores of the
+/// clauses, as the MaxScore flavour of WAND does.
+///
+/// The clauses are sorted by maximum score. Once the maximum scores of the
+/// first clauses sum up to less than the minimum competitive score, a doc
+/// only matching them can't compete: the other clauses, the essential ones,
+/// lead the iteration, and the first ones are only advanced to the docs the
+/// essential ones match, as long as the doc may still compete.
+///
+/// The maximum scores are those of whole postings lists, the postings format
+/// doesn't record the maximum scores of its blocks.
+pub struct WANDScorer<T: Scorer> {
+    // sorted by maximum score
+    clauses: PooledVec<T>,
+    // the sums of the maximum scores of the clauses before each one, and of
+    // all of them at the end, rounded up
+    max_score_sums: Vec<f64>,
+    // the clauses before it aren't essential
+    first_essential: usize,
+    // the essential clauses after the current doc, by doc id
+    essential: BinaryHeap<Reverse<(DocId, usize)>>,
+    // the essential clauses on the current doc
+    lead: Vec<usize>,
+    // the clauses matching the current doc, with their scores
+    matching: Vec<(usize, f32)>,
+    doc: DocId,
+    score: f32,
+    min_competitive_score: f32,
+    cost: usize,
+}
+
+impl<T: Scorer> WANDScorer<T> {
+    /// Whether the disjunction of `clauses` can skip the docs that don't
+    /// compete, which needs a finite maximum score for every clause.
+    pub fn can_skip(clauses: &[T]) -> bool {
+        clauses.iter().all(|c| {
+            let max_score = c.max_score();
+            max_score.is_finite() && max_score >= 0.0 && !c.support_two_phase()
+        })
+    }
+
+    /// Creates the disjunction of `clauses`, which have to pass `can_skip`.
+    pub fn new(mut clauses: PooledVec<T>) -> WANDScorer<T> {
+        assert!(clauses.len() > 1);
+        debug_assert!(Self::can_skip(&clauses));
+
+        clauses.sort_by(|a, b| {
+            a.max_score()
+                .partial_cmp(&b.max_score())
+                .unwrap_or(Ordering::Equal)
+        });
+        // the scores are summed as f32, so a sum may be a little over the sum
+        // of the maximum scores
+        let slack = 1.0 + f64::from(f32::EPSILON) * clauses.len() as f64;
+        let mut max_score_sums = Vec::with_capacity(clauses.len() + 1);
+        let mut sum = 0f64;
+        max_score_sums.push(0.0);
+        for clause in clauses.iter() {
+            sum += f64::from(clause.max_score());
+            max_score_sums.push(sum * slack);
+        }
+        let cost = clauses.iter().map(|c| c.cost()).sum();
+        let essential = clauses
+            .iter()
+            .enumerate()
+            .map(|(i, c)| Reverse((c.doc_id(), i)))
+            .collect();
+        let len = clauses.len();
+        WANDScorer {
+            clauses,
+            max_score_sums,
+            first_essential: 0,
+            essential,
+            lead: Vec::with_capacity(len),
+            matching: Vec::with_capacity(len),
+            doc: -1,
+            score: 0.0,
+            min_competitive_score: f32::NEG_INFINITY,
+            cost,
+        }
+    }
+
+    // scores `doc`, the essential clauses on it are the lead, returns whether
+    // it competes
+    fn score_doc(&mut self, doc: DocId) -> Result<bool> {
+        let min_score = f64::from(self.min_competitive_score);
+        self.matching.clear();
+        let mut score = 0f64;
+        for &i in &self.lead {
+            let clause_score = self.clauses[i].score()?;
+            self.matching.push((i, clause_score));
+            score += f64::from(clause_score);
+        }
+        // the other clauses, the ones that may add the most first
+        for i in (0..self.first_essential).rev() {
+            if score + self.max_score_sums[i + 1] < min_score {
+                return Ok(false);
+            }
+            let mut clause_doc = self.clauses[i].doc_id();
+            if clause_doc < doc {
+                clause_doc = self.clauses[i].advance(doc)?;
+            }
+            if clause_doc == doc {
+                let clause_score = self.clauses[i].score()?;
+                self.matching.push((i, clause_score));
+                score += f64::from(clause_score);
+            }
+        }
+        // summed in the order of the clauses, so that the score of a doc
+        // doesn't depend on the minimum competitive score
+        self.matching.sort_unstable_by_key(|m| m.0);
+        self.score = self.matching.iter().map(|m| m.1).sum();
+        Ok(self.score >= self.min_competitive_score)
+    }
+}
+
+impl<T: Scorer> Scorer for WANDScorer<T> {
+    fn score(&mut self) -> Result<f32> {
+        Ok(self.score)
+    }
+
+    fn max_score(&self) -> f32 {
+        self.max_score_sums[self.clauses.len()] as f32
+    }
+
+    fn set_min_competitive_score(&mut self, min_score: f32) -> Result<()> {
+        if min_score <= self.min_competitive_score {
+            return Ok(());
+        }
+        self.min_competitive_score = min_score;
+        let min_score = f64::from(min_score);
+        let mut first_essential = self.first_essential;
+        while first_essential < self.clauses.len()
+            && self.max_score_sums[first_essential + 1] < min_score
+        {
+            first_essential += 1;
+        }
+        if first_essential > self.first_essential {
+            // the clauses that aren't essential anymore stay where they are,
+            // they are advanced on demand
+            self.first_essential = first_essential;
+            let essential: Vec<_> = self
+                .essential
+                .drain()
+                .filter(|e| (e.0).1 >= first_essential)
+                .collect();
+            self.essential = essential.into();
+            self.lead.retain(|&i| i >= first_essential);
+        }
+        Ok(())
+    }
+}
+
+impl<T: Scorer> DocIterator for WANDScorer<T> {
+    fn doc_id(&self) -> DocId {
+        self.doc
+    }
+
+    fn next(&mut self) -> Result<DocId> {
+        if self.doc == NO_MORE_DOCS {
+            return Ok(NO_MORE_DOCS);
+        }
+        let target = self.doc + 1;
+        self.advance(target)
+    }
+
+    fn advance(&mut self, target: DocId) -> Result<DocId> {
+        let mut target = target;
+        loop {
+            for i in self.lead.drain(..) {
+                let doc = self.clauses[i].advance(target)?;
+                self.essential.push(Reverse((doc, i)));
+            }
+            while let Some(mut top) = self.essential.peek_mut() {
+                if (top.0).0 >= target {
+                    break;
+                }
+                let i = (top.0).1;
+                (top.0).0 = self.clauses[i].advance(target)?;
+            }
+            let doc = match self.essential.peek() {
+                Some(top) => (top.0).0,
+                None => NO_MORE_DOCS,
+            };
+            if doc == NO_MORE_DOCS {
+                self.doc = NO_MORE_DOCS;
+                return Ok(NO_MORE_DOCS);
+            }
+            while let Some(&Reverse((clause_doc, i))) = self.essential.peek() {
+                if clause_doc != doc {
+                    break;
+                }
+                self.essential.pop();
+                self.lead.push(i);
+            }
+            if self.score_doc(doc)? {
+                self.doc = doc;
+                return Ok(doc);
+            }
+            target = doc + 1;
+        }
+    }
+
+    fn cost(&self) -> usize {
+        self.cost
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::search::disjunction::DisjunctionSumScorer;
+    use core::search::tests::*;
+    use core::util::tests::seeded_rng;
+
+    use rand::Rng;
+
+    // a clause scoring `score` on all its docs
+    struct ConstantScorer {
+        iterator: MockDocIterator,
+        score: f32,
+    }
+
+    impl Scorer for ConstantScorer {
+        fn score(&mut self) -> Result<f32> {
+            Ok(self.score)
+        }
+
+        fn max_score(&self) -> f32 {
+            self.score
+        }
+    }
+
+    impl DocIterator for ConstantScorer {
+        fn doc_id(&self) -> DocId {
+            self.iterator.doc_id()
+        }
+
+        fn next(&mut self) -> Result<DocId> {
+            self.iterator.next()
+        }
+
+        fn advance(&mut self, target: DocId) -> Result<DocId> {
+            self.iterator.advance(target)
+        }
+
+        fn cost(&self) -> usize {
+            self.iterator.cost()
+        }
+    }
+
+    fn clauses(docs: &[Vec<DocId>], scores: &[f32]) -> Vec<ConstantScorer> {
+        docs.iter()
+            .zip(scores)
+            .map(|(docs, &score)| ConstantScorer {
+                iterator: create_mock_doc_iterator(docs.clone()),
+                score,
+            })
+            .collect()
+    }
+
+    fn wand_scorer(docs: &[Vec<DocId>], scores: &[f32]) -> WANDScorer<ConstantScorer> {
+        WANDScorer::new(clauses(docs, scores).into())
+    }
+
+    fn hits(scorer: &mut impl Scorer) -> Vec<(DocId, f32)> {
+        let mut hits = vec![];
+        loop {
+            let doc = scorer.next().unwrap();
+            if doc == NO_MORE_DOCS {
+                return hits;
+            }
+            hits.push((doc, scorer.score().unwrap()));
+        }
+    }
+
+    #[test]
+    fn test_wand_without_min_competitive_score() {
+        let docs = vec![vec![1, 2, 3, 4, 5], vec![2, 5], vec![2, 3, 4, 5]];
+        let scores = [1.0, 2.0, 4.0];
+        let mut wand = wand_scorer(&docs, &scores);
+        let mut disjunction = DisjunctionSumScorer::new(clauses(&docs, &scores));
+        let expected = hits(&mut disjunction);
+        assert_eq!(
+            expected,
+            vec![(1, 1.0), (2, 7.0), (3, 5.0), (4, 5.0), (5, 7.0)]
+        );
+        assert_eq!(hits(&mut wand), expected);
+        assert!(wand.max_score() >= 7.0);
+    }
+
+    #[test]
+    fn test_wand_skips_the_docs_that_dont_compete() {
+        let docs = vec![vec![1, 2, 3, 4, 5, 6], vec![2, 5], vec![3, 4, 5]];
+        let mut wand = wand_scorer(&docs, &[1.0, 2.0, 4.0]);
+        assert_eq!(wand.next().unwrap(), 1);
+        // only the docs of the last clause may score 4 or more
+        wand.set_min_competitive_score(4.0).unwrap();
+        assert_eq!(hits(&mut wand), vec![(3, 5.0), (4, 5.0), (5, 7.0)]);
+
+        let mut wand = wand_scorer(&docs, &[1.0, 2.0, 4.0]);
+        assert_eq!(wand.next().unwrap(), 1);
+        // no doc can score more than 7
+        wand.set_min_competitive_score(7.5).unwrap();
+        assert_eq!(wand.next().unwrap(), NO_MORE_DOCS);
+    }
+
+    #[test]
+    fn test_wand_against_disjunction() {
+        let mut rng = seeded_rng();
+        for _ in 0..50 {
+            let num_clauses = rng.gen_range(2, 8);
+            let mut docs = vec![];
+            let mut scores = vec![];
+            for _ in 0..num_clauses {
+                let density = rng.gen_range(0.01, 0.5);
+                docs.push((0..1000).filter(|_| rng.gen::<f64>() < density).collect());
+                // quarters, so that the sums are exact whatever their order
+                scores.push(rng.gen_range(0, 40) as f32 / 4.0);
+            }
+            let mut disjunction = DisjunctionSumScorer::new(clauses(&docs, &scores));
+            let all_hits = hits(&mut disjunction);
+
+            // the minimum competitive score grows as the hits are collected,
+            // the docs skipped in between are the ones that don't compete
+            let mut wand = wand_scorer(&docs, &scores);
+            let mut min_score = f32::NEG_INFINITY;
+            let mut expected = all_hits.iter();
+            loop {
+                let doc = wand.next().unwrap();
+                let hit = expected.by_ref().find(|h| h.1 >= min_score).cloned();
+                if doc == NO_MORE_DOCS {
+                    assert_eq!(hit, None);
+                    break;
+                }
+                assert_eq!(hit, Some((doc, wand.score().unwrap())));
+                if rng.gen_range(0, 5) == 0 {
+                    min_score = min_score.max(wand.score().unwrap());
+                    wand.set_min_competitive_score(min_score).unwrap();
+                }
+            }
+        }
+    }
+}
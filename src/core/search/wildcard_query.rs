@@ -0,0 +1,253 @@
+// Copyright 2019 Zhizhesihai (Beijing) Technology Limited.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use core::codec::Codec;
+use core::search::multi_term_query::{AcceptStatus, MultiTermWeight, RewriteMethod, TermFilter};
+use core::search::searcher::SearchPlanBuilder;
+use core::search::term_query::TermQuery;
+use core::search::{Query, Weight};
+
+use error::Result;
+
+use std::fmt;
+use std::str;
+
+pub const WILDCARD: &str = "wildcard";
+
+pub const WILDCARD_STRING: char = '*';
+pub const WILDCARD_CHAR: char = '?';
+pub const WILDCARD_ESCAPE: char = '\\';
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum PatternChar {
+    Literal(char),
+    // `?`, any single char
+    AnyChar,
+    // `*`, any sequence of chars, possibly empty
+    AnyString,
+}
+
+/// Matches the docs having a term matching a wildcard pattern in the field,
+/// with a constant score.
+///
+/// `*` matches any sequence of chars and `?` any single char, a char escaped
+/// with `\` matches itself. The chars before the first wildcard must prefix
+/// the terms, so patterns starting with a wildcard enumerate all the terms
+/// of the field.
+#[derive(Clone, Debug)]
+pub struct WildcardQuery {
+    field: String,
+    pattern: String,
+    chars: Vec<PatternChar>,
+    // the utf-8 bytes of the literal chars before the first wildcard
+    prefix: Vec<u8>,
+    rewrite_method: RewriteMethod,
+}
+
+impl WildcardQuery {
+    pub fn new(field: String, pattern: &str) -> WildcardQuery {
+        let mut chars = Vec::with_capacity(pattern.len());
+        let mut iter = pattern.chars();
+        while let Some(c) = iter.next() {
+            chars.push(match c {
+                WILDCARD_STRING => PatternChar::AnyString,
+                WILDCARD_CHAR => PatternChar::AnyChar,
+                // a trailing escape matches itself
+                WILDCARD_ESCAPE => PatternChar::Literal(iter.next().unwrap_or(WILDCARD_ESCAPE)),
+                c => PatternChar::Literal(c),
+            });
+        }
+        let prefix: String = chars
+            .iter()
+            .take_while(|c| match c {
+                PatternChar::Literal(_) => true,
+                _ => false,
+            })
+            .map(|c| match c {
+                PatternChar::Literal(c) => *c,
+                _ => unreachable!(),
+            })
+            .collect();
+        WildcardQuery {
+            field,
+            pattern: pattern.to_string(),
+            chars,
+            prefix: prefix.into_bytes(),
+            rewrite_method: RewriteMethod::default(),
+        }
+    }
+
+    pub fn with_rewrite_method(mut self, rewrite_method: RewriteMethod) -> WildcardQuery {
+        self.rewrite_method = rewrite_method;
+        self
+    }
+
+    pub fn field(&self) -> &str {
+        &self.field
+    }
+
+    pub fn pattern(&self) -> &str {
+        &self.pattern
+    }
+
+    fn matches(&self, term: &[char]) -> bool {
+        let pattern = &self.chars;
+        let (mut p, mut t) = (0, 0);
+        // the last `*` seen and the term char it matches up to
+        let mut backtrack: Option<(usize, usize)> = None;
+        while t < term.len() {
+            match pattern.get(p) {
+                Some(PatternChar::AnyString) => {
+                    backtrack = Some((p, t));
+                    p += 1;
+                    continue;
+                }
+                Some(PatternChar::AnyChar) => {
+                    p += 1;
+                    t += 1;
+                    continue;
+                }
+                Some(PatternChar::Literal(c)) if *c == term[t] => {
+                    p += 1;
+                    t += 1;
+                    continue;
+                }
+                _ => {}
+            }
+            // let the last `*` match one more char
+            match backtrack {
+                Some((star, matched)) => {
+                    backtrack = Some((star, matched + 1));
+                    p = star + 1;
+                    t = matched + 1;
+                }
+                None => return false,
+            }
+        }
+        pattern[p..].iter().all(|c| *c == PatternChar::AnyString)
+    }
+}
+
+impl TermFilter for WildcardQuery {
+    fn field(&self) -> &str {
+        &self.field
+    }
+
+    fn lower_bound(&self) -> Option<&[u8]> {
+        if self.prefix.is_empty() {
+            None
+        } else {
+            Some(self.prefix.as_slice())
+        }
+    }
+
+    fn accept(&self, term: &[u8]) -> AcceptStatus {
+        if !term.starts_with(&self.prefix) {
+            return AcceptStatus::End;
+        }
+        match str::from_utf8(term) {
+            Ok(term) if self.matches(&term.chars().collect::<Vec<_>>()) => AcceptStatus::Yes,
+            _ => AcceptStatus::No,
+        }
+    }
+}
+
+impl<C: Codec> Query<C> for WildcardQuery {
+    fn create_weight(
+        &self,
+        searcher: &dyn SearchPlanBuilder<C>,
+        _needs_scores: bool,
+    ) -> Result<Box<dyn Weight<C>>> {
+        let weight = MultiTermWeight::new(self.clone(), self.rewrite_method, WILDCARD);
+        Ok(Box::new(
+            weight.with_timeout(searcher.query_timeout().cloned()),
+        ))
+    }
+
+    fn extract_terms(&self) -> Vec<TermQuery> {
+        vec![]
+    }
+
+    fn query_type(&self) -> &'static str {
+        WILDCARD
+    }
+
+    fn as_any(&self) -> &::std::any::Any {
+        self
+    }
+}
+
+impl fmt::Display for WildcardQuery {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "WildcardQuery(field: {}, pattern: {})",
+            &self.field, &self.pattern
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::doc::{Store, StringField};
+    use core::index::tests::open_test_reader;
+    use core::search::searcher::{DefaultIndexSearcher, IndexSearcher};
+
+    fn accepts(pattern: &str, term: &str) -> bool {
+        let query = WildcardQuery::new("name".to_string(), pattern);
+        query.accept(term.as_bytes()) == AcceptStatus::Yes
+    }
+
+    #[test]
+    fn test_pattern() {
+        assert!(accepts("ap*e", "apple"));
+        assert!(accepts("ap*e", "ape"));
+        assert!(!accepts("ap*e", "apples"));
+        assert!(accepts("a?ple", "apple"));
+        assert!(!accepts("a?ple", "aple"));
+        assert!(accepts("*p*l*", "apple"));
+        assert!(accepts("*", ""));
+        assert!(accepts("*a*a*", "banana"));
+        assert!(accepts("é?é", "été"));
+        assert!(accepts("a\\*b", "a*b"));
+        assert!(!accepts("a\\*b", "axb"));
+
+        let query = WildcardQuery::new("name".to_string(), "ap?le*");
+        assert_eq!(query.lower_bound(), Some(&b"ap"[..]));
+        assert_eq!(query.accept(b"banana"), AcceptStatus::End);
+        assert_eq!(
+            WildcardQuery::new("name".to_string(), "*pple").lower_bound(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_wildcard_query() {
+        let docs = ["apple", "ample", "apply", "maple", "banana"]
+            .iter()
+            .map(|name| vec![StringField::new("name", name, Store::No)])
+            .collect();
+        let searcher = DefaultIndexSearcher::new(open_test_reader(vec![docs]));
+        let count = |pattern: &str| {
+            searcher
+                .count(&WildcardQuery::new("name".to_string(), pattern))
+                .unwrap()
+        };
+        assert_eq!(count("a?ple"), 2);
+        assert_eq!(count("ap*"), 2);
+        assert_eq!(count("*ple"), 3);
+        assert_eq!(count("*a*a*"), 1);
+        assert_eq!(count("b?"), 0);
+    }
+}
@@ -0,0 +1,66 @@
+// Copyright 2019 Zhizhesihai (Beijing) Technology Limited.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use core::store::{DataOutput, IndexOutput};
+use error::Result;
+
+use crc::{crc32, Hasher32};
+use std::io::{self, Write};
+
+/// Wraps another `IndexOutput` and maintains a running CRC32 checksum of all
+/// the bytes written through it, so that `codec_util::write_footer` can be used
+/// on outputs that don't compute a checksum themselves.
+pub struct BufferedChecksumIndexOutput<O: IndexOutput> {
+    output: O,
+    digest: crc32::Digest,
+}
+
+impl<O: IndexOutput> BufferedChecksumIndexOutput<O> {
+    pub fn new(output: O) -> BufferedChecksumIndexOutput<O> {
+        let digest = crc32::Digest::new_with_initial(crc32::IEEE, 0u32);
+        BufferedChecksumIndexOutput { output, digest }
+    }
+
+    /// Returns the wrapped output.
+    pub fn into_inner(self) -> O {
+        self.output
+    }
+}
+
+impl<O: IndexOutput> Write for BufferedChecksumIndexOutput<O> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let length = self.output.write(buf)?;
+        self.digest.write(&buf[0..length]);
+        Ok(length)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.output.flush()
+    }
+}
+
+impl<O: IndexOutput> DataOutput for BufferedChecksumIndexOutput<O> {}
+
+impl<O: IndexOutput> IndexOutput for BufferedChecksumIndexOutput<O> {
+    fn name(&self) -> &str {
+        self.output.name()
+    }
+
+    fn file_pointer(&self) -> i64 {
+        self.output.file_pointer()
+    }
+
+    fn checksum(&self) -> Result<i64> {
+        Ok(i64::from(self.digest.sum32()))
+    }
+}
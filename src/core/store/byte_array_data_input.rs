@@ -11,10 +11,12 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use core::store::{DataInput, DataOutput};
+use core::store::{decode_string, read_string_length, DataInput, DataOutput};
+use error::ErrorKind::{CorruptIndex, IllegalArgument, UnexpectedEOF};
 use error::Result;
 use std::cmp::min;
 use std::io::{self, Read, Write};
+use std::str;
 use std::sync::Arc;
 
 pub struct ByteArrayRef(Arc<Vec<u8>>);
@@ -79,10 +81,42 @@ impl<T: AsRef<[u8]>> ByteArrayDataInput<T> {
         }
         Ok(&self.bytes.as_ref()[pos..pos + len])
     }
+
+    // corrupt lengths read from the bytes themselves must not make us read
+    // past the end, single bytes aren't checked to keep `read_byte` cheap
+    fn ensure_remaining(&self, count: usize) -> Result<()> {
+        let length = self.length();
+        if self.pos > length || count > length - self.pos {
+            bail!(UnexpectedEOF(format!(
+                "read past EOF: pos={}, count={}, length={}",
+                self.pos, count, length
+            )));
+        }
+        Ok(())
+    }
+
+    /// Returns an input over the `len` bytes at `offset`, sharing the bytes
+    /// of this input without copying them. The view has its own position,
+    /// starting at 0, and `rewind` moves it back to the start of the view.
+    pub fn slice_view(&self, offset: usize, len: usize) -> Result<ByteArrayDataInput<&[u8]>> {
+        let limit = self.length();
+        if offset > limit || len > limit - offset {
+            bail!(IllegalArgument(format!(
+                "slice ({}, {}) is beyond the {} bytes of the input",
+                offset,
+                offset + len,
+                limit
+            )));
+        }
+        Ok(ByteArrayDataInput::new(
+            &self.bytes.as_ref()[offset..offset + len],
+        ))
+    }
 }
 
 impl<T: AsRef<[u8]>> DataInput for ByteArrayDataInput<T> {
     fn skip_bytes(&mut self, count: usize) -> Result<()> {
+        self.ensure_remaining(count)?;
         self.pos += count;
         Ok(())
     }
@@ -94,10 +128,29 @@ impl<T: AsRef<[u8]>> DataInput for ByteArrayDataInput<T> {
     }
 
     fn read_bytes(&mut self, b: &mut [u8], offset: usize, len: usize) -> Result<()> {
+        self.ensure_remaining(len)?;
         b[offset..offset + len].copy_from_slice(&self.bytes.as_ref()[self.pos..self.pos + len]);
         self.pos += len;
         Ok(())
     }
+
+    // checks the bytes in place, only the valid strings are copied
+    fn read_string(&mut self) -> Result<String> {
+        let length = read_string_length(self)?;
+        let remaining = self.length().saturating_sub(self.pos);
+        if length > remaining {
+            bail!(CorruptIndex(format!(
+                "string of {} bytes is longer than the {} bytes left",
+                length, remaining
+            )));
+        }
+        let bytes = &self.bytes.as_ref()[self.pos..self.pos + length];
+        self.pos += length;
+        match str::from_utf8(bytes) {
+            Ok(s) => Ok(s.to_owned()),
+            Err(_) => decode_string(bytes.to_vec()),
+        }
+    }
 }
 
 impl<T: AsRef<[u8]>> Read for ByteArrayDataInput<T> {
@@ -151,3 +204,66 @@ where
 }
 
 impl<T> DataOutput for ByteArrayDataOutput<T> where T: AsMut<[u8]> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_slice_view() {
+        let bytes: Vec<u8> = (0..32).collect();
+        let mut input = ByteArrayDataInput::new(&bytes[..]);
+        input.skip_bytes(4).unwrap();
+
+        let mut first = input.slice_view(8, 8).unwrap();
+        let mut second = input.slice_view(12, 20).unwrap();
+        assert_eq!(first.length(), 8);
+        assert_eq!(second.length(), 20);
+        assert_eq!(first.position(), 0);
+
+        // the views and the input move independently
+        assert_eq!(first.read_byte().unwrap(), 8);
+        assert_eq!(first.read_byte().unwrap(), 9);
+        assert_eq!(second.read_byte().unwrap(), 12);
+        assert_eq!(input.read_byte().unwrap(), 4);
+        assert_eq!(first.position(), 2);
+        assert_eq!(second.position(), 1);
+        assert_eq!(input.position(), 5);
+
+        // rewinding goes back to the start of the view, not of the input
+        let mut b = [0u8; 6];
+        first.read_bytes(&mut b, 0, 6).unwrap();
+        assert_eq!(b, [10, 11, 12, 13, 14, 15]);
+        assert!(first.eof());
+        first.rewind();
+        assert_eq!(first.read_byte().unwrap(), 8);
+        second.rewind();
+        assert_eq!(second.read_byte().unwrap(), 12);
+        assert_eq!(input.position(), 5);
+
+        // views of views are relative to the view
+        let mut nested = second.slice_view(16, 4).unwrap();
+        assert_eq!(nested.read_byte().unwrap(), 28);
+        assert_eq!(second.position(), 1);
+
+        assert!(input.slice_view(32, 0).unwrap().eof());
+        assert!(input.slice_view(30, 3).is_err());
+        assert!(input.slice_view(33, 0).is_err());
+        assert!(input.slice_view(8, usize::max_value()).is_err());
+    }
+
+    #[test]
+    fn test_read_past_eof() {
+        let bytes = [1u8, 2, 3, 0x80];
+        let mut input = ByteArrayDataInput::new(&bytes[..]);
+        assert!(input.skip_bytes(5).is_err());
+        assert_eq!(input.position(), 0);
+        let mut b = [0u8; 8];
+        assert!(input.read_bytes(&mut b, 0, 5).is_err());
+        input.read_bytes(&mut b, 0, 3).unwrap();
+        assert!(input.skip_bytes(2).is_err());
+        input.set_position(10);
+        assert!(input.skip_bytes(0).is_err());
+        assert!(input.read_bytes(&mut b, 0, 1).is_err());
+    }
+}
@@ -12,6 +12,7 @@
 // limitations under the License.
 
 use core::util::bit_util::ZigZagEncoding;
+use core::util::string_util::modified_utf8_to_string;
 use error::ErrorKind::*;
 use error::Result;
 
@@ -65,38 +66,46 @@ pub trait DataInput: Read {
             | (i32::from(self.read_byte()?) & 0xff))
     }
 
+    /// Reads an int stored in variable-length format, 1 to 5 bytes.
+    ///
+    /// Encodings longer than 5 bytes, overflowing 32 bits or ending with an
+    /// unnecessary zero byte are corrupt.
     fn read_vint(&mut self) -> Result<i32> {
-        let mut b = (self.read_byte()?) as i8;
-        if b >= 0 {
-            return Ok(i32::from(b));
-        }
-
-        let mut i = i32::from(b) & 0x7f;
-        b = self.read_byte()? as i8;
-        i |= (i32::from(b) & 0x7f) << 7;
-        if b >= 0 {
-            return Ok(i);
+        let mut i = 0i32;
+        for shift in (0..28).step_by(7) {
+            let b = self.read_byte()?;
+            i |= i32::from(b & 0x7f) << shift;
+            if b & 0x80 == 0 {
+                if b == 0 && shift > 0 {
+                    bail!(CorruptIndex("vInt ends with a zero byte".to_owned()));
+                }
+                return Ok(i);
+            }
         }
-
-        b = self.read_byte()? as i8;
-        i |= (i32::from(b) & 0x7f) << 14;
-        if b >= 0 {
-            return Ok(i);
+        // the 5th byte holds the 4 highest bits
+        let b = self.read_byte()?;
+        if b & 0xf0 != 0 {
+            bail!(CorruptIndex(format!(
+                "vInt overflows 32 bits or is longer than 5 bytes, last byte: {:#x}",
+                b
+            )));
         }
-
-        b = self.read_byte()? as i8;
-        i |= (i32::from(b) & 0x7f) << 21;
-        if b >= 0 {
-            return Ok(i);
+        if b == 0 {
+            bail!(CorruptIndex("vInt ends with a zero byte".to_owned()));
         }
+        Ok(i | (i32::from(b) << 28))
+    }
 
-        b = self.read_byte()? as i8;
-        i |= (i32::from(b) & 0x0f) << 28;
-
-        if (b as u8 & 0xf0) != 0 {
-            bail!(IllegalState("Invalid vInt detected".to_owned()));
+    /// Reads a vInt that must be in `[0, max]`, for lengths and counts
+    /// with a known sane upper bound.
+    fn read_vint_bounded(&mut self, max: i32) -> Result<i32> {
+        let i = self.read_vint()?;
+        if i < 0 || i > max {
+            bail!(CorruptIndex(format!(
+                "vInt {} is out of bounds [0, {}]",
+                i, max
+            )));
         }
-
         Ok(i)
     }
 
@@ -112,131 +121,104 @@ pub trait DataInput: Read {
         self.read_vlong_ex(false)
     }
 
+    /// Reads a long stored in variable-length format, 1 to 9 bytes, or 10
+    /// bytes for the negative longs when `negative_allowed`.
+    ///
+    /// Longer encodings or ones ending with an unnecessary zero byte are
+    /// corrupt.
     fn read_vlong_ex(&mut self, negative_allowed: bool) -> Result<i64> {
-        const ERR_MSG: &str = "Invalid vLong detected";
-
-        let mut b = self.read_byte()? as i8;
-        if b >= 0 {
-            return Ok(i64::from(b));
-        }
-
-        let mut i = i64::from(b) & 0x7f_i64;
-
-        b = self.read_byte()? as i8;
-        i |= (i64::from(b) & 0x7f_i64) << 7;
-        if b >= 0 {
-            return Ok(i);
-        }
-
-        b = self.read_byte()? as i8;
-        i |= (i64::from(b) & 0x7f_i64) << 14;
-        if b >= 0 {
-            return Ok(i);
-        }
-
-        b = self.read_byte()? as i8;
-        i |= (i64::from(b) & 0x7f_i64) << 21;
-        if b >= 0 {
-            return Ok(i);
-        }
-
-        b = self.read_byte()? as i8;
-        i |= (i64::from(b) & 0x7f_i64) << 28;
-        if b >= 0 {
-            return Ok(i);
-        }
-
-        b = self.read_byte()? as i8;
-        i |= (i64::from(b) & 0x7f_i64) << 35;
-        if b >= 0 {
-            return Ok(i);
-        }
-
-        b = self.read_byte()? as i8;
-        i |= (i64::from(b) & 0x7f_i64) << 42;
-        if b >= 0 {
-            return Ok(i);
-        }
-
-        b = self.read_byte()? as i8;
-        i |= (i64::from(b) & 0x7f_i64) << 49;
-        if b >= 0 {
-            return Ok(i);
-        }
-
-        b = self.read_byte()? as i8;
-        i |= (i64::from(b) & 0x7f_i64) << 56;
-        if b >= 0 {
-            return Ok(i);
+        let mut i = 0i64;
+        for shift in (0..9).map(|k| k * 7) {
+            let b = self.read_byte()?;
+            i |= i64::from(b & 0x7f) << shift;
+            if b & 0x80 == 0 {
+                if b == 0 && shift > 0 {
+                    bail!(CorruptIndex("vLong ends with a zero byte".to_owned()));
+                }
+                return Ok(i);
+            }
         }
-
         if negative_allowed {
-            b = self.read_byte()? as i8;
-            if b == 0 || b == 1 {
-                i |= (i64::from(b) & 0x7f_i64) << 63;
-                return Ok(i);
-            } else {
-                bail!(IllegalState(ERR_MSG.to_owned()));
+            // only the sign bit is left
+            let b = self.read_byte()?;
+            if b == 1 {
+                return Ok(i | (1i64 << 63));
             }
+            bail!(CorruptIndex(format!(
+                "vLong overflows 64 bits or is not canonical, last byte: {:#x}",
+                b
+            )));
         }
-        bail!(IllegalState(ERR_MSG.to_owned()))
+        bail!(CorruptIndex(
+            "vLong overflows 63 bits or is longer than 9 bytes".to_owned()
+        ))
+    }
+
+    /// Reads a vLong that must be in `[0, max]`, for lengths and counts
+    /// with a known sane upper bound.
+    fn read_vlong_bounded(&mut self, max: i64) -> Result<i64> {
+        let i = self.read_vlong()?;
+        if i > max {
+            bail!(CorruptIndex(format!(
+                "vLong {} is out of bounds [0, {}]",
+                i, max
+            )));
+        }
+        Ok(i)
     }
 
+    /// Reads a long written by `DataOutput::write_zlong`, a zig-zag encoded
+    /// vLong, 1 to 10 bytes.
     fn read_zlong(&mut self) -> Result<i64> {
         Ok(self.read_vlong_ex(true)?.decode())
     }
 
+    /// Reads a string written by `DataOutput::write_string`, a vInt length
+    /// followed by the UTF-8 bytes, see `decode_string`.
     fn read_string(&mut self) -> Result<String> {
-        const ERR_MSG: &str = "Invalid String detected";
-        let length = self.read_vint()?;
-        if length < 0 {
-            bail!(IllegalState(ERR_MSG.to_owned()));
+        let length = read_string_length(self)?;
+        // grows the buffer as the bytes are read, so that a corrupt length
+        // fails at the end of the input instead of allocating up to 2GB
+        let mut buffer = Vec::with_capacity(length.min(STRING_CHUNK_SIZE));
+        while buffer.len() < length {
+            let offset = buffer.len();
+            let chunk = (length - offset).min(STRING_CHUNK_SIZE);
+            buffer.resize(offset + chunk, 0);
+            self.read_bytes(&mut buffer, offset, chunk)?;
         }
-
-        let length = length as usize;
-
-        let mut buffer = Vec::with_capacity(length);
-
-        unsafe {
-            buffer.set_len(length);
-        };
-
-        self.read_bytes(&mut buffer, 0, length)?;
-        Ok(String::from_utf8(buffer)?)
+        decode_string(buffer)
     }
 
+    /// Reads a map written by `DataOutput::write_map_of_strings`, a vInt
+    /// count followed by the keys and values in any order.
     fn read_map_of_strings(&mut self) -> Result<HashMap<String, String>> {
         let count = self.read_vint()?;
         if count < 0 {
-            bail!(IllegalState("Invalid StringMap detected".to_owned()));
+            bail!(CorruptIndex(format!("invalid map size: {}", count)));
         }
 
         let mut map = HashMap::new();
-        if count != 0 {
-            for _ in 0..count {
-                let k = self.read_string()?;
-                let v = self.read_string()?;
-                map.insert(k, v);
-            }
+        for _ in 0..count {
+            let k = self.read_string()?;
+            let v = self.read_string()?;
+            map.insert(k, v);
         }
-
         Ok(map)
     }
 
+    /// Reads a set written by `DataOutput::write_set_of_strings`, a vInt
+    /// count followed by the strings in any order.
     fn read_set_of_strings(&mut self) -> Result<HashSet<String>> {
         let count = self.read_vint()?;
         if count < 0 {
-            bail!(IllegalState("Invalid StringSet detected".to_owned()));
+            bail!(CorruptIndex(format!("invalid set size: {}", count)));
         }
 
-        let mut hash_set = HashSet::new();
-        if count != 0 {
-            for _ in 0..count {
-                hash_set.insert(self.read_string()?);
-            }
+        let mut set = HashSet::new();
+        for _ in 0..count {
+            set.insert(self.read_string()?);
         }
-
-        Ok(hash_set)
+        Ok(set)
     }
 
     fn skip_bytes(&mut self, count: usize) -> Result<()> {
@@ -254,3 +236,242 @@ pub trait DataInput: Read {
 }
 
 impl<'a> DataInput for &'a [u8] {}
+
+/// Reads the vInt length of a string, see `DataInput::read_string`.
+// the most bytes of a string read at once by `DataInput::read_string`
+const STRING_CHUNK_SIZE: usize = 8192;
+
+pub fn read_string_length<I: DataInput + ?Sized>(input: &mut I) -> Result<usize> {
+    let length = input.read_vint()?;
+    if length < 0 {
+        bail!(CorruptIndex(format!("invalid string length: {}", length)));
+    }
+    Ok(length as usize)
+}
+
+/// Converts the bytes of a string read from an index. Lucene writes UTF-8,
+/// but the modified UTF-8 of Java is accepted as well, so that NUL encoded in
+/// two bytes and the supplementary characters encoded as surrogate pairs are
+/// still read.
+pub fn decode_string(bytes: Vec<u8>) -> Result<String> {
+    match String::from_utf8(bytes) {
+        Ok(s) => Ok(s),
+        Err(e) => match modified_utf8_to_string(e.as_bytes()) {
+            Some(s) => Ok(s),
+            None => bail!(CorruptIndex(format!(
+                "invalid UTF-8 string: {}",
+                e.utf8_error()
+            ))),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::store::{ByteArrayDataInput, DataOutput, GrowableByteArrayDataOutput};
+
+    fn assert_corrupt<T: ::std::fmt::Debug>(res: Result<T>) {
+        match res {
+            Err(ref e) => match e.kind() {
+                CorruptIndex(_) => {}
+                _ => panic!("expected CorruptIndex, got {:?}", e),
+            },
+            Ok(v) => panic!("expected CorruptIndex, got {:?}", v),
+        }
+    }
+
+    #[test]
+    fn test_round_trip_varints() {
+        let ints = [
+            0,
+            1,
+            127,
+            128,
+            16_383,
+            16_384,
+            i32::max_value(),
+            -1,
+            i32::min_value(),
+        ];
+        let longs = [
+            0,
+            1,
+            127,
+            128,
+            1 << 35,
+            (1 << 56) - 1,
+            1 << 56,
+            i64::max_value(),
+        ];
+        let mut out = GrowableByteArrayDataOutput::new(0);
+        for &i in &ints {
+            out.write_vint(i).unwrap();
+        }
+        for &l in &longs {
+            out.write_vlong(l).unwrap();
+            out.write_zlong(-l).unwrap();
+        }
+        out.write_zlong(i64::min_value()).unwrap();
+
+        let mut input = &out.bytes[..out.position()];
+        for &i in &ints {
+            assert_eq!(input.read_vint().unwrap(), i);
+        }
+        for &l in &longs {
+            assert_eq!(input.read_vlong().unwrap(), l);
+            assert_eq!(input.read_zlong().unwrap(), -l);
+        }
+        assert_eq!(input.read_zlong().unwrap(), i64::min_value());
+        assert!(input.is_empty());
+    }
+
+    #[test]
+    fn test_corrupt_vint() {
+        // longer than 5 bytes
+        assert_corrupt((&[0xffu8, 0xff, 0xff, 0xff, 0xff, 0x01][..]).read_vint());
+        // overflows 32 bits
+        assert_corrupt((&[0xffu8, 0xff, 0xff, 0xff, 0x1f][..]).read_vint());
+        // unnecessary trailing zero bytes
+        assert_corrupt((&[0x80u8, 0x00][..]).read_vint());
+        assert_corrupt((&[0x81u8, 0x80, 0x80, 0x80, 0x00][..]).read_vint());
+        // truncated
+        assert!((&[0x80u8, 0x80][..]).read_vint().is_err());
+
+        assert_eq!((&[0x00u8][..]).read_vint().unwrap(), 0);
+        assert_eq!(
+            (&[0xffu8, 0xff, 0xff, 0xff, 0x0f][..]).read_vint().unwrap(),
+            -1
+        );
+    }
+
+    #[test]
+    fn test_corrupt_vlong() {
+        let mut over_length = vec![0xffu8; 9];
+        over_length.push(0x01);
+        assert_corrupt((&over_length[..]).read_vlong());
+        assert_eq!((&over_length[..]).read_zlong().unwrap(), i64::min_value());
+        over_length[9] = 0x02;
+        assert_corrupt((&over_length[..]).read_zlong());
+        over_length[9] = 0x00;
+        assert_corrupt((&over_length[..]).read_zlong());
+        assert_corrupt((&[0xffu8, 0x80, 0x00][..]).read_vlong());
+    }
+
+    #[test]
+    fn test_bounded_varints() {
+        assert_eq!((&[0x7fu8][..]).read_vint_bounded(127).unwrap(), 127);
+        assert_corrupt((&[0x80u8, 0x01][..]).read_vint_bounded(127));
+        assert_corrupt((&[0xffu8, 0xff, 0xff, 0xff, 0x0f][..]).read_vint_bounded(100));
+        assert_eq!((&[0x80u8, 0x01][..]).read_vlong_bounded(128).unwrap(), 128);
+        assert_corrupt((&[0x81u8, 0x01][..]).read_vlong_bounded(128));
+    }
+
+    // the bytes of zLongs, strings, maps and sets in the format of Lucene's
+    // DataOutput, encoded by hand. The UTF-8 and modified UTF-8 bytes of the
+    // strings are the ones of Java's `String.getBytes` and `writeUTF`.
+    const ZLONG_ENCODINGS: &[(i64, &[u8])] = &[
+        (0, &[0x00]),
+        (-1, &[0x01]),
+        (1, &[0x02]),
+        (-64, &[0x7f]),
+        (64, &[0x80, 0x01]),
+        (
+            i64::max_value(),
+            &[0xfe, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01],
+        ),
+        (
+            i64::min_value(),
+            &[0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01],
+        ),
+    ];
+
+    const STRING_ENCODINGS: &[(&str, &[u8])] = &[
+        ("", &[0x00]),
+        ("lucene", &[0x06, b'l', b'u', b'c', b'e', b'n', b'e']),
+        ("\u{e9}t\u{e9}", &[0x05, 0xc3, 0xa9, b't', 0xc3, 0xa9]),
+        ("\u{1F600}", &[0x04, 0xf0, 0x9f, 0x98, 0x80]),
+    ];
+
+    fn written<F: FnOnce(&mut GrowableByteArrayDataOutput) -> Result<()>>(f: F) -> Vec<u8> {
+        let mut out = GrowableByteArrayDataOutput::new(0);
+        f(&mut out).unwrap();
+        out.bytes[..out.position()].to_vec()
+    }
+
+    #[test]
+    fn test_zlong_encodings() {
+        for &(l, bytes) in ZLONG_ENCODINGS {
+            assert_eq!(written(|out| out.write_zlong(l)), bytes, "{}", l);
+            let mut input = bytes;
+            assert_eq!(input.read_zlong().unwrap(), l);
+            assert!(input.is_empty());
+            assert_eq!(ByteArrayDataInput::new(bytes).read_zlong().unwrap(), l);
+        }
+    }
+
+    #[test]
+    fn test_string_encodings() {
+        for &(s, bytes) in STRING_ENCODINGS {
+            assert_eq!(written(|out| out.write_string(s)), bytes, "{}", s);
+            let mut input = bytes;
+            assert_eq!(input.read_string().unwrap(), s);
+            assert!(input.is_empty());
+            let mut input = ByteArrayDataInput::new(bytes);
+            assert_eq!(input.read_string().unwrap(), s);
+            assert!(input.eof());
+        }
+
+        // NUL and U+1F600 in the modified UTF-8 of Java
+        let modified: &[u8] = &[0x08, 0xc0, 0x80, 0xed, 0xa0, 0xbd, 0xed, 0xb8, 0x80];
+        assert_eq!((&modified[..]).read_string().unwrap(), "\u{0}\u{1F600}");
+        let mut input = ByteArrayDataInput::new(modified);
+        assert_eq!(input.read_string().unwrap(), "\u{0}\u{1F600}");
+        assert!(input.eof());
+
+        // invalid bytes, an unpaired surrogate and a negative length
+        assert_corrupt((&[0x01u8, 0xff][..]).read_string());
+        assert_corrupt((&[0x03u8, 0xed, 0xa0, 0xbd][..]).read_string());
+        assert_corrupt(ByteArrayDataInput::new(&[0x03u8, 0xed, 0xa0, 0xbd][..]).read_string());
+        assert_corrupt((&[0xffu8, 0xff, 0xff, 0xff, 0x0f][..]).read_string());
+        // truncated
+        assert!((&[0x05u8, b'a'][..]).read_string().is_err());
+        assert_corrupt(ByteArrayDataInput::new(&[0x05u8, b'a'][..]).read_string());
+        // a corrupt length isn't allocated before reading the bytes
+        let huge: &[u8] = &[0xfeu8, 0xff, 0xff, 0xff, 0x07, b'a'];
+        assert!((&huge[..]).read_string().is_err());
+        assert_corrupt(ByteArrayDataInput::new(huge).read_string());
+    }
+
+    #[test]
+    fn test_map_and_set_encodings() {
+        let mut map = HashMap::new();
+        map.insert("a".to_string(), "1".to_string());
+        map.insert("bb".to_string(), "".to_string());
+        let sorted: &[u8] = &[0x02, 0x01, b'a', 0x01, b'1', 0x02, b'b', b'b', 0x00];
+        assert_eq!(written(|out| out.write_map_of_strings(&map)), sorted);
+        assert_eq!((&sorted[..]).read_map_of_strings().unwrap(), map);
+        // Java writes the entries in the order of its map
+        let unsorted: &[u8] = &[0x02, 0x02, b'b', b'b', 0x00, 0x01, b'a', 0x01, b'1'];
+        assert_eq!((&unsorted[..]).read_map_of_strings().unwrap(), map);
+        assert_eq!(
+            ByteArrayDataInput::new(unsorted)
+                .read_map_of_strings()
+                .unwrap(),
+            map
+        );
+
+        let set: HashSet<String> = ["x", "yz"].iter().map(|s| s.to_string()).collect();
+        let bytes: &[u8] = &[0x02, 0x01, b'x', 0x02, b'y', b'z'];
+        assert_eq!(written(|out| out.write_set_of_strings(&set)), bytes);
+        assert_eq!((&bytes[..]).read_set_of_strings().unwrap(), set);
+
+        assert_eq!(
+            written(|out| out.write_map_of_strings(&HashMap::new())),
+            vec![0x00]
+        );
+        assert!((&[0x00u8][..]).read_set_of_strings().unwrap().is_empty());
+        assert_corrupt((&[0xffu8, 0xff, 0xff, 0xff, 0x0f][..]).read_map_of_strings());
+        assert_corrupt((&[0xffu8, 0xff, 0xff, 0xff, 0x0f][..]).read_set_of_strings());
+    }
+}
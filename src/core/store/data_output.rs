@@ -82,17 +82,28 @@ pub trait DataOutput: Write {
         self._write_signed_vlong(i)
     }
 
+    /// Writes a long zig-zag encoded as a vLong, so that the longs close to
+    /// zero take few bytes whatever their sign, 1 to 10 bytes.
     fn write_zlong(&mut self, i: i64) -> Result<()> {
         self._write_signed_vlong(i.encode())
     }
 
+    /// Writes a string as the vInt length of its UTF-8 bytes followed by them.
     fn write_string(&mut self, s: &str) -> Result<()> {
         let s = s.as_bytes();
+        if s.len() > i32::max_value() as usize {
+            bail!(IllegalArgument(format!(
+                "string of {} bytes is too long to be written",
+                s.len()
+            )));
+        }
         self.write_vint(s.len() as i32)?;
         self.write_bytes(s, 0, s.len())?;
         Ok(())
     }
 
+    /// Writes a map as its vInt size followed by the keys and values, the keys
+    /// sorted so that the same map is always written the same way.
     fn write_map_of_strings(&mut self, map: &HashMap<String, String>) -> Result<()> {
         self.write_vint(map.len() as i32)?;
 
@@ -105,6 +116,7 @@ pub trait DataOutput: Write {
         Ok(())
     }
 
+    /// Writes a set as its vInt size followed by the sorted strings.
     fn write_set_of_strings(&mut self, set: &HashSet<String>) -> Result<()> {
         self.write_vint(set.len() as i32)?;
 
@@ -21,7 +21,7 @@ use std::sync::RwLock;
 use core::index::segment_file_name;
 use core::store::LockFactory;
 use core::store::{Directory, IOContext};
-use core::store::{FSIndexOutput, IndexInput, MmapIndexInput};
+use core::store::{FSIndexInput, FSIndexOutput, IndexInput};
 use core::util::to_base36;
 use error::ErrorKind::IllegalState;
 use error::Result;
@@ -153,11 +153,10 @@ impl<LF: LockFactory> Directory for FSDirectory<LF> {
         FSIndexOutput::new(&path)
     }
 
-    fn open_input(&self, name: &str, _ctx: &IOContext) -> Result<Box<dyn IndexInput>> {
+    fn open_input(&self, name: &str, ctx: &IOContext) -> Result<Box<dyn IndexInput>> {
         self.ensure_can_read(name)?;
         let path = self.directory.as_path().join(name);
-        // hack logic, we don'e implement FsIndexInput yes, so just us MmapIndexInput instead
-        Ok(Box::new(MmapIndexInput::new(path)?))
+        Ok(Box::new(FSIndexInput::open(path, ctx)?))
     }
 
     fn obtain_lock(&self, name: &str) -> Result<Self::LK> {
@@ -0,0 +1,483 @@
+// Copyright 2019 Zhizhesihai (Beijing) Technology Limited.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use core::store::{DataInput, IOContext, IndexInput, RandomAccessInput};
+use error::ErrorKind::{IllegalArgument, UnexpectedEOF};
+use error::Result;
+
+use byteorder::{BigEndian, ByteOrder};
+
+use std::cmp::min;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+/// Default buffer size, small as most reads are random accesses for searches.
+pub const BUFFER_SIZE: usize = 1024;
+
+/// Buffer size for merges, which read the files sequentially.
+pub const MERGE_BUFFER_SIZE: usize = 64 * 1024;
+
+/// `random_access_slice`s smaller than this are read without a buffer.
+pub const RANDOM_ACCESS_BUFFER_THRESHOLD: i64 = 16 * 1024;
+
+/// A buffered `IndexInput` reading a file with positional reads, so that
+/// clones and slices share the file handle.
+///
+/// The buffer size depends on the `IOContext` the file is opened with, and on
+/// unix merge inputs also hint the OS to read ahead aggressively.
+pub struct FSIndexInput {
+    name: String,
+    file: Arc<File>,
+    // the part of the file read by this input
+    offset: u64,
+    length: u64,
+    buffer: Vec<u8>,
+    buffer_size: usize,
+    // position of `buffer[0]`, relative to `offset`
+    buffer_start: u64,
+    buffer_pos: usize,
+}
+
+impl FSIndexInput {
+    pub fn open<P: AsRef<Path>>(path: P, context: &IOContext) -> Result<FSIndexInput> {
+        let path = path.as_ref();
+        let file = File::open(path)?;
+        let length = file.metadata()?.len();
+        if context.is_merge() {
+            advise_sequential(&file);
+        }
+        Ok(FSIndexInput::new(
+            path.display().to_string(),
+            Arc::new(file),
+            0,
+            length,
+            Self::buffer_size_for(context),
+        ))
+    }
+
+    fn new(
+        name: String,
+        file: Arc<File>,
+        offset: u64,
+        length: u64,
+        buffer_size: usize,
+    ) -> FSIndexInput {
+        FSIndexInput {
+            name,
+            file,
+            offset,
+            length,
+            buffer: Vec::with_capacity(buffer_size),
+            buffer_size,
+            buffer_start: 0,
+            buffer_pos: 0,
+        }
+    }
+
+    /// Returns the buffer size of inputs opened with `context`.
+    pub fn buffer_size_for(context: &IOContext) -> usize {
+        if context.is_merge() {
+            MERGE_BUFFER_SIZE
+        } else {
+            BUFFER_SIZE
+        }
+    }
+
+    pub fn buffer_size(&self) -> usize {
+        self.buffer_size
+    }
+
+    fn position(&self) -> u64 {
+        self.buffer_start + self.buffer_pos as u64
+    }
+
+    fn refill(&mut self) -> io::Result<()> {
+        let pos = self.position();
+        let len = min(self.buffer_size as u64, self.length - pos) as usize;
+        self.buffer.resize(len, 0);
+        let read = read_at(&self.file, &mut self.buffer, self.offset + pos)?;
+        self.buffer.truncate(read);
+        self.buffer_start = pos;
+        self.buffer_pos = 0;
+        Ok(())
+    }
+}
+
+impl Read for FSIndexInput {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.buffer_pos == self.buffer.len() {
+            let pos = self.position();
+            if pos >= self.length {
+                return Ok(0);
+            }
+            if buf.len() >= self.buffer_size {
+                // no need to copy big reads through the buffer
+                let len = min(buf.len() as u64, self.length - pos) as usize;
+                let read = read_at(&self.file, &mut buf[..len], self.offset + pos)?;
+                self.buffer.clear();
+                self.buffer_start = pos + read as u64;
+                self.buffer_pos = 0;
+                return Ok(read);
+            }
+            self.refill()?;
+        }
+        let len = min(buf.len(), self.buffer.len() - self.buffer_pos);
+        buf[..len].copy_from_slice(&self.buffer[self.buffer_pos..self.buffer_pos + len]);
+        self.buffer_pos += len;
+        Ok(len)
+    }
+}
+
+impl DataInput for FSIndexInput {
+    fn read_byte(&mut self) -> Result<u8> {
+        if self.buffer_pos < self.buffer.len() {
+            self.buffer_pos += 1;
+            return Ok(self.buffer[self.buffer_pos - 1]);
+        }
+        let mut byte = [0u8; 1];
+        self.read_bytes(&mut byte, 0, 1)?;
+        Ok(byte[0])
+    }
+
+    fn read_bytes(&mut self, b: &mut [u8], offset: usize, length: usize) -> Result<()> {
+        let end = offset + length;
+        if b.len() < end {
+            bail!(IllegalArgument(format!(
+                "Buffer too small: writing [{}, {}) to [0, {})",
+                offset,
+                end,
+                b.len()
+            )));
+        }
+        // a single read may stop at the end of the buffer
+        if let Err(e) = self.read_exact(&mut b[offset..end]) {
+            bail!(UnexpectedEOF(format!(
+                "Reached EOF when {} bytes are expected in {}: {}",
+                length, self.name, e
+            )));
+        }
+        Ok(())
+    }
+}
+
+impl IndexInput for FSIndexInput {
+    fn clone(&self) -> Result<Box<dyn IndexInput>> {
+        let mut input = FSIndexInput::new(
+            self.name.clone(),
+            Arc::clone(&self.file),
+            self.offset,
+            self.length,
+            self.buffer_size,
+        );
+        input.buffer_start = self.position();
+        Ok(Box::new(input))
+    }
+
+    fn file_pointer(&self) -> i64 {
+        self.position() as i64
+    }
+
+    fn seek(&mut self, pos: i64) -> Result<()> {
+        if pos < 0 || pos as u64 > self.length {
+            bail!(UnexpectedEOF(format!(
+                "seek to {} is out of bounds of {} with length {}",
+                pos, self.name, self.length
+            )));
+        }
+        let pos = pos as u64;
+        if pos >= self.buffer_start && pos <= self.buffer_start + self.buffer.len() as u64 {
+            self.buffer_pos = (pos - self.buffer_start) as usize;
+        } else {
+            self.buffer.clear();
+            self.buffer_start = pos;
+            self.buffer_pos = 0;
+        }
+        Ok(())
+    }
+
+    fn len(&self) -> u64 {
+        self.length
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn random_access_slice(&self, offset: i64, length: i64) -> Result<Box<dyn RandomAccessInput>> {
+        Ok(Box::new(self.fs_random_access_slice(offset, length)?))
+    }
+
+    fn slice(&self, description: &str, offset: i64, length: i64) -> Result<Box<dyn IndexInput>> {
+        self.check_slice(offset, length)?;
+        Ok(Box::new(FSIndexInput::new(
+            format!("{} [slice={}]", self.name, description),
+            Arc::clone(&self.file),
+            self.offset + offset as u64,
+            length as u64,
+            self.buffer_size,
+        )))
+    }
+
+    fn is_buffered(&self) -> bool {
+        true
+    }
+}
+
+impl FSIndexInput {
+    fn fs_random_access_slice(&self, offset: i64, length: i64) -> Result<FSRandomAccessInput> {
+        self.check_slice(offset, length)?;
+        let buffer = if length < RANDOM_ACCESS_BUFFER_THRESHOLD {
+            None
+        } else {
+            Some(Mutex::new((0, Vec::with_capacity(self.buffer_size))))
+        };
+        Ok(FSRandomAccessInput {
+            file: Arc::clone(&self.file),
+            offset: self.offset + offset as u64,
+            length: length as u64,
+            buffer_size: self.buffer_size,
+            buffer,
+        })
+    }
+
+    fn check_slice(&self, offset: i64, length: i64) -> Result<()> {
+        if offset < 0 || length < 0 || (offset + length) as u64 > self.length {
+            bail!(IllegalArgument(format!(
+                "slice [{}, {}) is out of bounds of {} with length {}",
+                offset,
+                offset + length,
+                self.name,
+                self.length
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Random access over a part of a file, returned by
+/// `FSIndexInput::random_access_slice`.
+pub struct FSRandomAccessInput {
+    file: Arc<File>,
+    offset: u64,
+    length: u64,
+    buffer_size: usize,
+    // start and content of the buffered window, `None` for small slices that
+    // are read directly
+    buffer: Option<Mutex<(u64, Vec<u8>)>>,
+}
+
+impl FSRandomAccessInput {
+    fn read_at(&self, pos: i64, buf: &mut [u8]) -> Result<()> {
+        if pos < 0 || pos as u64 + buf.len() as u64 > self.length {
+            bail!(UnexpectedEOF(format!(
+                "read of {} bytes at {} is out of bounds of slice with length {}",
+                buf.len(),
+                pos,
+                self.length
+            )));
+        }
+        let pos = pos as u64;
+        match self.buffer {
+            Some(ref buffer) => {
+                let mut buffer = buffer.lock()?;
+                let (ref mut start, ref mut bytes) = *buffer;
+                if pos < *start || pos + buf.len() as u64 > *start + bytes.len() as u64 {
+                    let len = min(self.buffer_size as u64, self.length - pos) as usize;
+                    bytes.resize(len, 0);
+                    read_fully_at(&self.file, bytes, self.offset + pos)?;
+                    *start = pos;
+                }
+                let from = (pos - *start) as usize;
+                buf.copy_from_slice(&bytes[from..from + buf.len()]);
+                Ok(())
+            }
+            None => read_fully_at(&self.file, buf, self.offset + pos),
+        }
+    }
+
+    pub fn is_buffered(&self) -> bool {
+        self.buffer.is_some()
+    }
+}
+
+impl RandomAccessInput for FSRandomAccessInput {
+    fn read_byte(&self, pos: i64) -> Result<u8> {
+        let mut buf = [0u8; 1];
+        self.read_at(pos, &mut buf)?;
+        Ok(buf[0])
+    }
+
+    fn read_short(&self, pos: i64) -> Result<i16> {
+        let mut buf = [0u8; 2];
+        self.read_at(pos, &mut buf)?;
+        Ok(BigEndian::read_i16(&buf))
+    }
+
+    fn read_int(&self, pos: i64) -> Result<i32> {
+        let mut buf = [0u8; 4];
+        self.read_at(pos, &mut buf)?;
+        Ok(BigEndian::read_i32(&buf))
+    }
+
+    fn read_long(&self, pos: i64) -> Result<i64> {
+        let mut buf = [0u8; 8];
+        self.read_at(pos, &mut buf)?;
+        Ok(BigEndian::read_i64(&buf))
+    }
+}
+
+#[cfg(unix)]
+fn read_at(file: &File, buf: &mut [u8], pos: u64) -> io::Result<usize> {
+    use std::os::unix::fs::FileExt;
+    file.read_at(buf, pos)
+}
+
+#[cfg(windows)]
+fn read_at(file: &File, buf: &mut [u8], pos: u64) -> io::Result<usize> {
+    use std::os::windows::fs::FileExt;
+    file.seek_read(buf, pos)
+}
+
+fn read_fully_at(file: &File, buf: &mut [u8], pos: u64) -> Result<()> {
+    let mut done = 0;
+    while done < buf.len() {
+        let read = read_at(file, &mut buf[done..], pos + done as u64)?;
+        if read == 0 {
+            bail!(UnexpectedEOF(format!(
+                "Reached EOF when {} bytes are expected at {}",
+                buf.len(),
+                pos
+            )));
+        }
+        done += read;
+    }
+    Ok(())
+}
+
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "freebsd"))]
+fn advise_sequential(file: &File) {
+    use std::os::unix::io::AsRawFd;
+    // only a hint, the read ahead of the OS works without it
+    let res = unsafe { libc::posix_fadvise(file.as_raw_fd(), 0, 0, libc::POSIX_FADV_SEQUENTIAL) };
+    if res != 0 {
+        debug!("posix_fadvise failed with {}", res);
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android", target_os = "freebsd")))]
+fn advise_sequential(_file: &File) {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::store::MergeInfo;
+
+    use std::fs;
+    use std::io::Write;
+
+    fn write_file(name: &str, len: usize) -> ::std::path::PathBuf {
+        let path = ::std::env::temp_dir().join(name);
+        let data: Vec<u8> = (0..len).map(|i| (i % 251) as u8).collect();
+        fs::File::create(&path).unwrap().write_all(&data).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_buffer_size_by_context() {
+        let path = write_file("rucene_test_fs_index_input_context", 100);
+        let merge = IOContext::Merge(MergeInfo::new(1, 100, false, None));
+        let input = FSIndexInput::open(&path, &merge).unwrap();
+        assert_eq!(input.buffer_size(), MERGE_BUFFER_SIZE);
+        for ctx in &[IOContext::READ, IOContext::READ_ONCE, IOContext::Default] {
+            let input = FSIndexInput::open(&path, ctx).unwrap();
+            assert_eq!(input.buffer_size(), BUFFER_SIZE);
+        }
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_read_across_buffers() {
+        let len = BUFFER_SIZE * 5 + 17;
+        let path = write_file("rucene_test_fs_index_input_read", len);
+        let mut input = FSIndexInput::open(&path, &IOContext::READ).unwrap();
+        assert_eq!(input.len(), len as u64);
+        for i in 0..BUFFER_SIZE + 10 {
+            assert_eq!(input.read_byte().unwrap(), (i % 251) as u8);
+        }
+        // spans a refill
+        let mut bytes = vec![0u8; BUFFER_SIZE - 5];
+        input.read_bytes(&mut bytes, 0, BUFFER_SIZE - 5).unwrap();
+        let start = BUFFER_SIZE + 10;
+        assert_eq!(bytes[0], (start % 251) as u8);
+        assert_eq!(
+            bytes[BUFFER_SIZE - 6],
+            ((start + BUFFER_SIZE - 6) % 251) as u8
+        );
+        // bypasses the buffer
+        let mut bytes = vec![0u8; BUFFER_SIZE * 2];
+        input.read_bytes(&mut bytes, 0, BUFFER_SIZE * 2).unwrap();
+        assert_eq!(bytes[0], ((BUFFER_SIZE * 2 + 5) % 251) as u8);
+
+        input.seek(3).unwrap();
+        assert_eq!(input.file_pointer(), 3);
+        assert_eq!(input.read_byte().unwrap(), 3);
+        input.seek(len as i64 - 1).unwrap();
+        assert_eq!(input.read_byte().unwrap(), ((len - 1) % 251) as u8);
+        assert!(input.read_byte().is_err());
+        assert!(input.seek(len as i64 + 1).is_err());
+
+        let mut slice = input.slice("test", 300, 600).unwrap();
+        assert_eq!(slice.len(), 600);
+        assert_eq!(slice.read_byte().unwrap(), (300 % 251) as u8);
+        slice.seek(599).unwrap();
+        assert_eq!(slice.read_byte().unwrap(), (899 % 251) as u8);
+        assert!(slice.read_byte().is_err());
+        let mut clone = slice.clone().unwrap();
+        clone.seek(1).unwrap();
+        assert_eq!(clone.read_byte().unwrap(), (301 % 251) as u8);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_random_access_slice() {
+        let len = RANDOM_ACCESS_BUFFER_THRESHOLD as usize * 2;
+        let path = write_file("rucene_test_fs_index_input_random", len);
+        let input = FSIndexInput::open(&path, &IOContext::READ).unwrap();
+        let mut expected = [0u8; 8];
+        for (i, b) in expected.iter_mut().enumerate() {
+            *b = ((100 + i) % 251) as u8;
+        }
+
+        let small = input.fs_random_access_slice(90, 1000).unwrap();
+        assert!(!small.is_buffered());
+        assert_eq!(small.read_long(10).unwrap(), BigEndian::read_i64(&expected));
+        assert_eq!(small.read_int(10).unwrap(), BigEndian::read_i32(&expected));
+        assert_eq!(
+            small.read_short(10).unwrap(),
+            BigEndian::read_i16(&expected)
+        );
+        assert!(small.read_int(998).is_err());
+
+        let large = input
+            .fs_random_access_slice(90, RANDOM_ACCESS_BUFFER_THRESHOLD)
+            .unwrap();
+        assert!(large.is_buffered());
+        assert_eq!(large.read_long(10).unwrap(), BigEndian::read_i64(&expected));
+        let pos = RANDOM_ACCESS_BUFFER_THRESHOLD - 1;
+        assert_eq!(large.read_byte(pos).unwrap(), ((90 + pos) % 251) as u8);
+        assert_eq!(large.read_byte(0).unwrap(), 90);
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -36,4 +36,13 @@ pub trait IndexInput: DataInput + Send + Sync {
     fn is_buffered(&self) -> bool {
         false
     }
+
+    /// Returns the `length` bytes at `offset` without copying them, if the
+    /// input holds its whole content in memory. The position is not changed.
+    ///
+    /// The bytes stay valid as long as this input or one of its clones is
+    /// open, `None` is returned for inputs that can't guarantee that.
+    fn bytes_view(&self, _offset: i64, _length: i64) -> Option<&[u8]> {
+        None
+    }
 }
@@ -13,13 +13,21 @@
 
 use core::store::Directory;
 
-use error::{ErrorKind::AlreadyClosed, Result};
+use error::{
+    ErrorKind::{AlreadyClosed, LockObtainFailed},
+    Result,
+};
+
+use fs2::FileExt;
 
 use std::collections::HashSet;
 use std::fs;
-use std::path::PathBuf;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::sync::Mutex;
+use std::time::SystemTime;
 
 ///  An inter process mutex lock.
 /// Typical use might look like:<pre class="prettyprint">
@@ -87,6 +95,7 @@ pub struct NativeFSLock {
     channel: fs::File,
     real_path: PathBuf,
     lock_held: Arc<Mutex<HashSet<PathBuf>>>,
+    closed: AtomicBool,
 }
 
 impl NativeFSLock {
@@ -101,15 +110,21 @@ impl NativeFSLock {
             channel,
             real_path,
             lock_held,
+            closed: AtomicBool::new(false),
         }
     }
 }
 
 impl Lock for NativeFSLock {
     fn close(&self) -> Result<()> {
+        if self.closed.swap(true, Ordering::AcqRel) {
+            return Ok(());
+        }
         // NOTE: we don't validate, as unlike SimpleFSLockFactory, we can't break others locks
         // first release the lock, then the channel
+        let unlocked = self.channel.unlock();
         let remove = self.lock_held.lock()?.remove(&self.real_path);
+        unlocked?;
         if !remove {
             bail!(AlreadyClosed(format!(
                 "Lock path was cleared but never marked as held: {:?}",
@@ -121,6 +136,13 @@ impl Lock for NativeFSLock {
     }
 
     fn ensure_valid(&self) -> Result<()> {
+        if self.closed.load(Ordering::Acquire) {
+            bail!(AlreadyClosed(format!(
+                "Lock instance already released: {:?}",
+                self.real_path
+            )));
+        }
+
         if !self.lock_held.lock()?.contains(&self.real_path) {
             bail!(AlreadyClosed(
                 "Lock path unexpectedly cleared from map".into()
@@ -140,6 +162,21 @@ impl Lock for NativeFSLock {
     }
 }
 
+impl Drop for NativeFSLock {
+    fn drop(&mut self) {
+        if let Err(e) = self.close() {
+            error!("failed to release lock {:?}: {:?}", self.real_path, e);
+        }
+    }
+}
+
+/// Implements `LockFactory` using native OS file locks (`flock` on unix,
+/// `LockFileEx` on windows).
+///
+/// The OS releases the locks when the process exits, so a lock file left
+/// behind by a crashed process doesn't prevent obtaining the lock again.
+/// Locks obtained in this process are tracked as well, because some platforms
+/// don't exclude locking the same file twice from one process.
 pub struct NativeFSLockFactory {
     pub lock_held: Arc<Mutex<HashSet<PathBuf>>>,
 }
@@ -155,20 +192,236 @@ impl Default for NativeFSLockFactory {
 impl LockFactory for NativeFSLockFactory {
     type LK = NativeFSLock;
     fn obtain_lock<D: Directory>(&self, dir: &D, lock_name: &str) -> Result<Self::LK> {
-        let mut real_path = dir.resolve(lock_name);
-        real_path.pop();
-        let _ = fs::create_dir(&real_path);
+        let lock_path = dir.resolve(lock_name);
+        create_lock_dir(&lock_path)?;
 
-        real_path = dir.resolve(lock_name);
-        let channel = fs::File::create(&real_path)?;
+        // never truncate or delete the lock file, it may be locked by others
+        fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .open(&lock_path)?;
+        // resolve symlinks, so that all the paths of one file share the lock
+        let real_path = lock_path.canonicalize()?;
 
-        self.lock_held.lock()?.insert(real_path.clone());
+        if !self.lock_held.lock()?.insert(real_path.clone()) {
+            bail!(LockObtainFailed(format!(
+                "Lock held by this process: {:?}",
+                real_path
+            )));
+        }
 
-        Ok(NativeFSLock::new(
-            Mutex::new(lock_name.to_string()),
-            channel,
-            real_path,
-            Arc::clone(&self.lock_held),
-        ))
+        let locked = fs::OpenOptions::new()
+            .write(true)
+            .open(&real_path)
+            .and_then(|channel| channel.try_lock_exclusive().map(|_| channel));
+        match locked {
+            Ok(channel) => Ok(NativeFSLock::new(
+                Mutex::new(lock_name.to_string()),
+                channel,
+                real_path,
+                Arc::clone(&self.lock_held),
+            )),
+            Err(e) => {
+                self.lock_held.lock()?.remove(&real_path);
+                bail!(LockObtainFailed(format!(
+                    "Lock held by another program: {:?}: {}",
+                    real_path, e
+                )))
+            }
+        }
+    }
+}
+
+/// A lock obtained by `SimpleFSLockFactory`, released by deleting its lock file.
+pub struct SimpleFSLock {
+    path: PathBuf,
+    // modified time of the lock file when it was created
+    creation_time: SystemTime,
+    closed: AtomicBool,
+}
+
+impl Lock for SimpleFSLock {
+    fn close(&self) -> Result<()> {
+        if self.closed.swap(true, Ordering::AcqRel) {
+            return Ok(());
+        }
+        // don't delete the lock file of someone else
+        self.check_valid()?;
+        if let Err(e) = fs::remove_file(&self.path) {
+            bail!(AlreadyClosed(format!(
+                "Unable to remove lock file {:?}: {}",
+                self.path, e
+            )));
+        }
+        Ok(())
+    }
+
+    fn ensure_valid(&self) -> Result<()> {
+        if self.closed.load(Ordering::Acquire) {
+            bail!(AlreadyClosed(format!(
+                "Lock instance already released: {:?}",
+                self.path
+            )));
+        }
+        self.check_valid()
+    }
+}
+
+impl SimpleFSLock {
+    fn check_valid(&self) -> Result<()> {
+        let modified = fs::metadata(&self.path).and_then(|m| m.modified());
+        match modified {
+            Ok(time) if time == self.creation_time => Ok(()),
+            Ok(_) => bail!(AlreadyClosed(format!(
+                "Underlying file changed by an external force: {:?}",
+                self.path
+            ))),
+            Err(e) => bail!(AlreadyClosed(format!(
+                "Unable to access lock file {:?}: {}",
+                self.path, e
+            ))),
+        }
+    }
+}
+
+impl Drop for SimpleFSLock {
+    fn drop(&mut self) {
+        if let Err(e) = self.close() {
+            error!("failed to release lock {:?}: {:?}", self.path, e);
+        }
+    }
+}
+
+/// Implements `LockFactory` by atomically creating a lock file, and deleting
+/// it on release.
+///
+/// This works on filesystems without native locks (e.g. some network
+/// filesystems), but a lock file left behind by a crashed process must be
+/// removed by hand before the lock can be obtained again.
+#[derive(Default)]
+pub struct SimpleFSLockFactory;
+
+impl LockFactory for SimpleFSLockFactory {
+    type LK = SimpleFSLock;
+    fn obtain_lock<D: Directory>(&self, dir: &D, lock_name: &str) -> Result<Self::LK> {
+        let path = dir.resolve(lock_name);
+        create_lock_dir(&path)?;
+
+        match fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)
+        {
+            Ok(file) => {
+                let creation_time = file.metadata()?.modified()?;
+                Ok(SimpleFSLock {
+                    path,
+                    creation_time,
+                    closed: AtomicBool::new(false),
+                })
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                bail!(LockObtainFailed(format!(
+                    "Lock held by another program: {:?}. If no other process is using the index, \
+                     the lock file is stale and may be removed",
+                    path
+                )))
+            }
+            Err(e) => bail!(LockObtainFailed(format!(
+                "Failed to create lock file {:?}: {}",
+                path, e
+            ))),
+        }
+    }
+}
+
+fn create_lock_dir(lock_path: &Path) -> Result<()> {
+    if let Some(dir) = lock_path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::store::FSDirectory;
+    use error::ErrorKind;
+
+    fn test_dir(name: &str) -> PathBuf {
+        let path = ::std::env::temp_dir().join(name);
+        let _ = fs::remove_dir_all(&path);
+        path
+    }
+
+    fn assert_obtain_failed<L>(res: Result<L>) {
+        match res {
+            Err(e) => match e.kind() {
+                ErrorKind::LockObtainFailed(_) => {}
+                _ => panic!("expected LockObtainFailed, got {:?}", e),
+            },
+            Ok(_) => panic!("lock obtained twice"),
+        }
+    }
+
+    #[test]
+    fn test_native_lock_release_on_drop() {
+        let path = test_dir("rucene_test_native_fs_lock");
+        let dir = FSDirectory::new(&path, NativeFSLockFactory::default()).unwrap();
+        // another factory only sees the OS lock
+        let other = FSDirectory::new(&path, NativeFSLockFactory::default()).unwrap();
+        {
+            let lock = dir.obtain_lock("write.lock").unwrap();
+            lock.ensure_valid().unwrap();
+            assert_obtain_failed(dir.obtain_lock("write.lock"));
+            assert_obtain_failed(other.obtain_lock("write.lock"));
+            // other lock names are independent
+            assert!(dir.obtain_lock("other.lock").is_ok());
+        }
+        let lock = other.obtain_lock("write.lock").unwrap();
+        lock.close().unwrap();
+        // closing is idempotent, but the lock is no longer valid
+        lock.close().unwrap();
+        assert!(lock.ensure_valid().is_err());
+        assert!(dir.obtain_lock("write.lock").is_ok());
+        fs::remove_dir_all(&path).unwrap();
+    }
+
+    #[test]
+    fn test_native_lock_ignores_stale_lock_file() {
+        let path = test_dir("rucene_test_native_fs_lock_stale");
+        let dir = FSDirectory::new(&path, NativeFSLockFactory::default()).unwrap();
+        // left behind by a crashed process
+        fs::File::create(path.join("write.lock")).unwrap();
+        let lock = dir.obtain_lock("write.lock").unwrap();
+        lock.ensure_valid().unwrap();
+        drop(lock);
+        // the lock file isn't deleted, as others may have locked it meanwhile
+        assert!(path.join("write.lock").exists());
+        fs::remove_dir_all(&path).unwrap();
+    }
+
+    #[test]
+    fn test_simple_lock() {
+        let path = test_dir("rucene_test_simple_fs_lock");
+        let dir = FSDirectory::new(&path, SimpleFSLockFactory::default()).unwrap();
+        let lock_file = path.join("write.lock");
+        {
+            let lock = dir.obtain_lock("write.lock").unwrap();
+            lock.ensure_valid().unwrap();
+            assert!(lock_file.exists());
+            assert_obtain_failed(dir.obtain_lock("write.lock"));
+        }
+        assert!(!lock_file.exists());
+
+        // a stale lock file must be removed by hand
+        fs::File::create(&lock_file).unwrap();
+        assert_obtain_failed(dir.obtain_lock("write.lock"));
+        fs::remove_file(&lock_file).unwrap();
+
+        let lock = dir.obtain_lock("write.lock").unwrap();
+        fs::remove_file(&lock_file).unwrap();
+        assert!(lock.ensure_valid().is_err());
+        fs::remove_dir_all(&path).unwrap();
     }
 }
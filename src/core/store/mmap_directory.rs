@@ -21,8 +21,9 @@ use memmap::Mmap;
 
 use core::store::fs_index_output::FSIndexOutput;
 use core::store::lock::LockFactory;
+use core::store::{ChunkedMmapIndexInput, IndexInput, MmapIndexInput, ReadOnlySource};
 use core::store::{Directory, FSDirectory, IOContext};
-use core::store::{IndexInput, MmapIndexInput, ReadOnlySource};
+use error::ErrorKind::IllegalArgument;
 use error::Result;
 
 #[derive(Default, Clone, Debug)]
@@ -105,25 +106,47 @@ impl MmapCache {
     }
 }
 
+/// Default max chunk size, files bigger than this are mapped in multiple chunks.
+#[cfg(target_pointer_width = "64")]
+pub const DEFAULT_MAX_CHUNK_SIZE: u32 = 1 << 30;
+#[cfg(not(target_pointer_width = "64"))]
+pub const DEFAULT_MAX_CHUNK_SIZE: u32 = 1 << 28;
+
 pub struct MmapDirectory<LF: LockFactory> {
     directory: FSDirectory<LF>,
     pub preload: bool,
     mmap_cache: Arc<Mutex<MmapCache>>,
+    chunk_size_power: u32,
 }
 
 impl<LF: LockFactory> MmapDirectory<LF> {
+    /// Create a new `MmapDirectory` for the named location.
+    ///
+    /// `max_chunk_size` is the max size of a single mapping, it will be rounded
+    /// down to a power of two. Files bigger than it are mapped as several chunks,
+    /// which makes it possible to handle files that are larger than the address
+    /// space available for one mapping.
     pub fn new<T: AsRef<Path>>(
         directory: &T,
         lock_factory: LF,
-        _max_chunk_size: u32,
+        max_chunk_size: u32,
     ) -> Result<MmapDirectory<LF>> {
+        if max_chunk_size == 0 {
+            bail!(IllegalArgument("max_chunk_size must be positive".into()));
+        }
+        let chunk_size_power = 31 - max_chunk_size.leading_zeros();
         let directory = FSDirectory::new(directory, lock_factory)?;
         Ok(MmapDirectory {
             directory,
             preload: false,
             mmap_cache: Arc::new(Mutex::new(MmapCache::default())),
+            chunk_size_power,
         })
     }
+
+    pub fn max_chunk_size(&self) -> u32 {
+        1 << self.chunk_size_power
+    }
 }
 
 impl<LF: LockFactory> Directory for MmapDirectory<LF> {
@@ -145,13 +168,18 @@ impl<LF: LockFactory> Directory for MmapDirectory<LF> {
 
     fn open_input(&self, name: &str, _ctx: &IOContext) -> Result<Box<dyn IndexInput>> {
         let full_path = self.directory.resolve(name);
-        let mut mmap_cache = self.mmap_cache.lock()?;
-        let boxed = mmap_cache
-            .get_mmap(&full_path)?
-            .map(ReadOnlySource::from)
-            .map(MmapIndexInput::from)
-            .unwrap();
-        Ok(Box::new(boxed))
+        let file_len = self.directory.file_length(name)? as u64;
+        if file_len > 0 && file_len <= u64::from(self.max_chunk_size()) {
+            let mut mmap_cache = self.mmap_cache.lock()?;
+            if let Some(mmap) = mmap_cache.get_mmap(&full_path)? {
+                return Ok(Box::new(MmapIndexInput::from(ReadOnlySource::from(mmap))));
+            }
+        }
+        // empty files can't be mapped, and big files are mapped chunk by chunk
+        Ok(Box::new(ChunkedMmapIndexInput::open(
+            &full_path,
+            self.chunk_size_power,
+        )?))
     }
 
     fn obtain_lock(&self, name: &str) -> Result<Self::LK> {
@@ -197,3 +225,70 @@ impl<LF: LockFactory> fmt::Display for MmapDirectory<LF> {
         write!(f, "MmapDirectory({})", self.directory)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::store::{DataInput, DataOutput, NativeFSLockFactory};
+
+    fn write_test_file(dir: &MmapDirectory<NativeFSLockFactory>, name: &str, count: i64) {
+        let mut output = dir.create_output(name, &IOContext::Default).unwrap();
+        output.write_byte(b'a').unwrap();
+        for i in 0..count {
+            output.write_long(i * 31).unwrap();
+            output.write_vint(i as i32).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_chunked_mmap_directory() {
+        let path = ::std::env::temp_dir().join("rucene_test_chunked_mmap_directory");
+        let dir = MmapDirectory::new(&path, NativeFSLockFactory::default(), 1000).unwrap();
+        assert_eq!(dir.max_chunk_size(), 512);
+        write_test_file(&dir, "chunked", 1000);
+
+        let mut input = dir.open_input("chunked", &IOContext::READ).unwrap();
+        assert_eq!(input.name(), "ChunkedMmapIndexInput");
+        assert_eq!(input.read_byte().unwrap(), b'a');
+        for i in 0..1000 {
+            assert_eq!(input.read_long().unwrap(), i * 31);
+            assert_eq!(input.read_vint().unwrap(), i as i32);
+        }
+        assert!(input.read_byte().is_err());
+
+        // slices that cross chunk boundaries, and slices that fit in one chunk
+        let mut slice = input.slice("cross", 505, 26).unwrap();
+        let mut clone = slice.clone().unwrap();
+        assert_eq!(slice.read_long().unwrap(), clone.read_long().unwrap());
+        let small = input.slice("small", 1, 100).unwrap();
+        assert_eq!(small.name(), "MmapIndexInput");
+
+        let random = input.random_access_slice(1, 9 * 100).unwrap();
+        let mut fp = 0;
+        for i in 0..100i64 {
+            assert_eq!(random.read_long(fp).unwrap(), i * 31);
+            fp += if i < 128 { 9 } else { 10 };
+        }
+        assert!(random.read_long(9 * 100 - 4).is_err());
+
+        ::std::fs::remove_dir_all(&path).unwrap();
+    }
+
+    #[test]
+    fn test_mmap_directory_empty_file() {
+        let path = ::std::env::temp_dir().join("rucene_test_mmap_directory_empty_file");
+        let dir = MmapDirectory::new(&path, NativeFSLockFactory::default(), 1 << 20).unwrap();
+        write_test_file(&dir, "single", 10);
+        dir.create_output("empty", &IOContext::Default).unwrap();
+
+        let input = dir.open_input("single", &IOContext::READ).unwrap();
+        assert_eq!(input.name(), "MmapIndexInput");
+        assert_eq!(input.len(), 91);
+
+        let mut input = dir.open_input("empty", &IOContext::READ).unwrap();
+        assert!(input.is_empty());
+        assert!(input.read_byte().is_err());
+
+        ::std::fs::remove_dir_all(&path).unwrap();
+    }
+}
@@ -11,8 +11,9 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use byteorder::{BigEndian, ByteOrder};
 use core::store::{DataInput, IndexInput, RandomAccessInput};
-use error::ErrorKind::{IllegalArgument, IllegalState};
+use error::ErrorKind::{IllegalArgument, IllegalState, UnexpectedEOF};
 use error::Result;
 use memmap::{Mmap, MmapOptions};
 use std::fmt::Debug;
@@ -201,6 +202,23 @@ impl MmapIndexInput {
         }
     }
 
+    /// Make sure there are at least `count` bytes left after the current position
+    /// and return the current position.
+    #[inline]
+    fn ensure_remaining(&self, count: usize) -> Result<usize> {
+        let pos = self.position as usize;
+        if pos + count > self.slice.len() {
+            bail!(UnexpectedEOF(format!(
+                "read past EOF: pos={}, count={}, length={}, {}",
+                pos,
+                count,
+                self.slice.len(),
+                self.description
+            )));
+        }
+        Ok(pos)
+    }
+
     fn slice_impl(&self, description: &str, offset: i64, length: i64) -> Result<Self> {
         let total_len = self.len() as i64;
         if offset < 0 || length < 0 || offset + length > total_len {
@@ -267,9 +285,61 @@ impl IndexInput for MmapIndexInput {
     fn name(&self) -> &str {
         "MmapIndexInput" // hard-coded
     }
+
+    fn bytes_view(&self, offset: i64, length: i64) -> Option<&[u8]> {
+        if offset < 0 || length < 0 || (offset + length) as u64 > self.len() {
+            return None;
+        }
+        Some(&self.slice[offset as usize..(offset + length) as usize])
+    }
 }
 
-impl DataInput for MmapIndexInput {}
+impl DataInput for MmapIndexInput {
+    fn read_byte(&mut self) -> Result<u8> {
+        let pos = self.ensure_remaining(1)?;
+        self.position += 1;
+        Ok(self.slice[pos])
+    }
+
+    fn read_bytes(&mut self, b: &mut [u8], offset: usize, length: usize) -> Result<()> {
+        if b.len() < offset + length {
+            bail!(IllegalArgument(format!(
+                "Buffer too small: writing [{}, {}) to [0, {})",
+                offset,
+                offset + length,
+                b.len()
+            )));
+        }
+        let pos = self.ensure_remaining(length)?;
+        b[offset..offset + length].copy_from_slice(&self.slice[pos..pos + length]);
+        self.position += length as u64;
+        Ok(())
+    }
+
+    fn read_short(&mut self) -> Result<i16> {
+        let pos = self.ensure_remaining(2)?;
+        self.position += 2;
+        Ok(BigEndian::read_i16(&self.slice[pos..pos + 2]))
+    }
+
+    fn read_int(&mut self) -> Result<i32> {
+        let pos = self.ensure_remaining(4)?;
+        self.position += 4;
+        Ok(BigEndian::read_i32(&self.slice[pos..pos + 4]))
+    }
+
+    fn read_long(&mut self) -> Result<i64> {
+        let pos = self.ensure_remaining(8)?;
+        self.position += 8;
+        Ok(BigEndian::read_i64(&self.slice[pos..pos + 8]))
+    }
+
+    fn skip_bytes(&mut self, count: usize) -> Result<()> {
+        self.ensure_remaining(count)?;
+        self.position += count as u64;
+        Ok(())
+    }
+}
 
 impl Read for MmapIndexInput {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
@@ -315,6 +385,282 @@ impl RandomAccessInput for MmapIndexInput {
     }
 }
 
+/// `IndexInput` over a file that is mapped as a sequence of fixed-size chunks.
+///
+/// This is used for files that are bigger than the max chunk size of a
+/// `MmapDirectory` (e.g. files larger than 4GB on platforms that can't map
+/// them at once), and for empty files that can't be mapped at all. All clones
+/// and slices share the underlying mappings, which are only unmapped once the
+/// last of them is dropped.
+pub struct ChunkedMmapIndexInput {
+    chunks: Arc<Vec<ReadOnlySource>>,
+    chunk_size_power: u32,
+    chunk_size_mask: u64,
+    // offset of this input from the beginning of the file
+    offset: u64,
+    length: u64,
+    position: u64,
+    description: String,
+}
+
+impl ChunkedMmapIndexInput {
+    pub fn open(path: &Path, chunk_size_power: u32) -> Result<ChunkedMmapIndexInput> {
+        debug_assert!(chunk_size_power < 64);
+        let file_len = File::open(path)?.metadata()?.len();
+        let chunk_size = 1u64 << chunk_size_power;
+        let num_chunks = (file_len + chunk_size - 1) >> chunk_size_power;
+
+        let mut chunks = Vec::with_capacity(num_chunks as usize);
+        let mut chunk_start = 0u64;
+        while chunk_start < file_len {
+            let chunk_len = ::std::cmp::min(chunk_size, file_len - chunk_start);
+            let mmap = MmapIndexInput::mmap(path, chunk_start as usize, chunk_len as usize)?
+                .ok_or_else(|| IllegalState(format!("file {:?} was truncated", path)))?;
+            chunks.push(ReadOnlySource::from(mmap));
+            chunk_start += chunk_len;
+        }
+
+        Ok(ChunkedMmapIndexInput {
+            chunks: Arc::new(chunks),
+            chunk_size_power,
+            chunk_size_mask: chunk_size - 1,
+            offset: 0,
+            length: file_len,
+            position: 0,
+            description: format!("ChunkedMmapIndexInput({:?})", path),
+        })
+    }
+
+    /// Returns the chunk holding the byte at `pos` and the offset of that byte in the chunk.
+    #[inline]
+    fn locate(&self, pos: u64) -> (&[u8], usize) {
+        let abs = self.offset + pos;
+        let chunk = &self.chunks[(abs >> self.chunk_size_power) as usize];
+        (chunk.as_slice(), (abs & self.chunk_size_mask) as usize)
+    }
+
+    fn check_range(&self, pos: u64, count: usize) -> Result<()> {
+        if pos + count as u64 > self.length {
+            bail!(UnexpectedEOF(format!(
+                "read past EOF: pos={}, count={}, length={}, {}",
+                pos, count, self.length, self.description
+            )));
+        }
+        Ok(())
+    }
+
+    fn copy_at(&self, pos: u64, b: &mut [u8]) {
+        let mut copied = 0;
+        while copied < b.len() {
+            let (chunk, off) = self.locate(pos + copied as u64);
+            let n = ::std::cmp::min(chunk.len() - off, b.len() - copied);
+            b[copied..copied + n].copy_from_slice(&chunk[off..off + n]);
+            copied += n;
+        }
+    }
+
+    fn read_at<T, F>(&self, pos: u64, f: F) -> Result<T>
+    where
+        F: Fn(&[u8]) -> T,
+    {
+        self.check_range(pos, ::std::mem::size_of::<T>())?;
+        let size = ::std::mem::size_of::<T>();
+        let (chunk, off) = self.locate(pos);
+        if off + size <= chunk.len() {
+            Ok(f(&chunk[off..off + size]))
+        } else {
+            // the value crosses a chunk boundary
+            let mut buf = [0u8; 8];
+            self.copy_at(pos, &mut buf[..size]);
+            Ok(f(&buf[..size]))
+        }
+    }
+
+    fn slice_impl(&self, description: &str, offset: i64, length: i64) -> Result<SlicedInput> {
+        if offset < 0 || length < 0 || (offset + length) as u64 > self.length {
+            bail!(IllegalArgument(format!(
+                "Illegal (offset, length) slice: ({}, {}) for file of length: {}",
+                offset, length, self.length
+            )));
+        }
+        let start = self.offset + offset as u64;
+        let length = length as u64;
+        let first_chunk = start >> self.chunk_size_power;
+        if length > 0 && first_chunk == (start + length - 1) >> self.chunk_size_power {
+            // the slice fits in a single chunk, no need for the chunk arithmetic
+            let source =
+                self.chunks[first_chunk as usize].range(start & self.chunk_size_mask, length)?;
+            let mut input = MmapIndexInput::from(source);
+            input.description = description.to_string();
+            Ok(SlicedInput::Single(input))
+        } else {
+            Ok(SlicedInput::Chunked(ChunkedMmapIndexInput {
+                chunks: Arc::clone(&self.chunks),
+                chunk_size_power: self.chunk_size_power,
+                chunk_size_mask: self.chunk_size_mask,
+                offset: start,
+                length,
+                position: 0,
+                description: description.to_string(),
+            }))
+        }
+    }
+}
+
+enum SlicedInput {
+    Single(MmapIndexInput),
+    Chunked(ChunkedMmapIndexInput),
+}
+
+impl Clone for ChunkedMmapIndexInput {
+    fn clone(&self) -> Self {
+        ChunkedMmapIndexInput {
+            chunks: Arc::clone(&self.chunks),
+            chunk_size_power: self.chunk_size_power,
+            chunk_size_mask: self.chunk_size_mask,
+            offset: self.offset,
+            length: self.length,
+            position: self.position,
+            description: self.description.clone(),
+        }
+    }
+}
+
+impl IndexInput for ChunkedMmapIndexInput {
+    fn clone(&self) -> Result<Box<dyn IndexInput>> {
+        Ok(Box::new(Clone::clone(self)))
+    }
+
+    fn file_pointer(&self) -> i64 {
+        self.position as i64
+    }
+
+    fn seek(&mut self, pos: i64) -> Result<()> {
+        if pos < 0 || pos as u64 > self.length {
+            bail!(IllegalArgument(format!(
+                "invalid position, expecting 0 < pos < {}, got: {}",
+                self.length, pos
+            )));
+        }
+        self.position = pos as u64;
+        Ok(())
+    }
+
+    fn len(&self) -> u64 {
+        self.length
+    }
+
+    fn name(&self) -> &str {
+        "ChunkedMmapIndexInput" // hard-coded
+    }
+
+    fn random_access_slice(&self, offset: i64, length: i64) -> Result<Box<dyn RandomAccessInput>> {
+        match self.slice_impl("RandomAccessSlice", offset, length)? {
+            SlicedInput::Single(input) => Ok(Box::new(input)),
+            SlicedInput::Chunked(input) => Ok(Box::new(input)),
+        }
+    }
+
+    fn slice(&self, description: &str, offset: i64, length: i64) -> Result<Box<dyn IndexInput>> {
+        match self.slice_impl(description, offset, length)? {
+            SlicedInput::Single(input) => Ok(Box::new(input)),
+            SlicedInput::Chunked(input) => Ok(Box::new(input)),
+        }
+    }
+}
+
+impl DataInput for ChunkedMmapIndexInput {
+    fn read_byte(&mut self) -> Result<u8> {
+        self.check_range(self.position, 1)?;
+        let b = {
+            let (chunk, off) = self.locate(self.position);
+            chunk[off]
+        };
+        self.position += 1;
+        Ok(b)
+    }
+
+    fn read_bytes(&mut self, b: &mut [u8], offset: usize, length: usize) -> Result<()> {
+        if b.len() < offset + length {
+            bail!(IllegalArgument(format!(
+                "Buffer too small: writing [{}, {}) to [0, {})",
+                offset,
+                offset + length,
+                b.len()
+            )));
+        }
+        self.check_range(self.position, length)?;
+        self.copy_at(self.position, &mut b[offset..offset + length]);
+        self.position += length as u64;
+        Ok(())
+    }
+
+    fn read_short(&mut self) -> Result<i16> {
+        let v = self.read_at(self.position, BigEndian::read_i16)?;
+        self.position += 2;
+        Ok(v)
+    }
+
+    fn read_int(&mut self) -> Result<i32> {
+        let v = self.read_at(self.position, BigEndian::read_i32)?;
+        self.position += 4;
+        Ok(v)
+    }
+
+    fn read_long(&mut self) -> Result<i64> {
+        let v = self.read_at(self.position, BigEndian::read_i64)?;
+        self.position += 8;
+        Ok(v)
+    }
+
+    fn skip_bytes(&mut self, count: usize) -> Result<()> {
+        self.check_range(self.position, count)?;
+        self.position += count as u64;
+        Ok(())
+    }
+}
+
+impl Read for ChunkedMmapIndexInput {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let count = ::std::cmp::min(buf.len() as u64, self.length - self.position) as usize;
+        self.copy_at(self.position, &mut buf[..count]);
+        self.position += count as u64;
+        Ok(count)
+    }
+}
+
+impl RandomAccessInput for ChunkedMmapIndexInput {
+    fn read_byte(&self, pos: i64) -> Result<u8> {
+        if pos < 0 {
+            bail!(IllegalArgument(format!("invalid position: {}", pos)));
+        }
+        self.check_range(pos as u64, 1)?;
+        let (chunk, off) = self.locate(pos as u64);
+        Ok(chunk[off])
+    }
+
+    fn read_short(&self, pos: i64) -> Result<i16> {
+        if pos < 0 {
+            bail!(IllegalArgument(format!("invalid position: {}", pos)));
+        }
+        self.read_at(pos as u64, BigEndian::read_i16)
+    }
+
+    fn read_int(&self, pos: i64) -> Result<i32> {
+        if pos < 0 {
+            bail!(IllegalArgument(format!("invalid position: {}", pos)));
+        }
+        self.read_at(pos as u64, BigEndian::read_i32)
+    }
+
+    fn read_long(&self, pos: i64) -> Result<i64> {
+        if pos < 0 {
+            bail!(IllegalArgument(format!("invalid position: {}", pos)));
+        }
+        self.read_at(pos as u64, BigEndian::read_i64)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -31,6 +31,10 @@ mod buffered_checksum_index_input;
 
 pub use self::buffered_checksum_index_input::*;
 
+mod buffered_checksum_index_output;
+
+pub use self::buffered_checksum_index_output::*;
+
 mod mmap_index_input;
 
 pub use self::mmap_index_input::*;
@@ -47,6 +51,10 @@ mod fs_index_output;
 
 pub use self::fs_index_output::*;
 
+mod fs_index_input;
+
+pub use self::fs_index_input::*;
+
 mod byte_array_data_input;
 
 pub use self::byte_array_data_input::*;
@@ -74,9 +82,17 @@ mod tracking_directory_wrapper;
 
 pub use self::tracking_directory_wrapper::*;
 
+mod partition_directory;
+
+pub use self::partition_directory::*;
+
 mod ram_output;
 pub use self::ram_output::*;
 
+mod ram_directory;
+
+pub use self::ram_directory::*;
+
 mod rate_limiter;
 pub use self::rate_limiter::*;
 
@@ -118,3 +134,249 @@ impl MergeInfo {
         }
     }
 }
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use error::Result;
+
+    use std::collections::HashSet;
+    use std::fmt;
+    use std::io::{self, Read};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    /// A `RAMDirectory` counting the inputs opened and not yet dropped, clones
+    /// and slices included, and the bytes read from them.
+    pub struct CountingDirectory {
+        dir: RAMDirectory,
+        open_inputs: Arc<AtomicUsize>,
+        reads: Arc<AtomicUsize>,
+        read_extensions: Vec<&'static str>,
+    }
+
+    impl CountingDirectory {
+        pub fn new() -> CountingDirectory {
+            CountingDirectory::counting_reads_of(&[])
+        }
+
+        /// Only counts the bytes read from the files with one of `extensions`,
+        /// or from every file when `extensions` is empty.
+        pub fn counting_reads_of(extensions: &[&'static str]) -> CountingDirectory {
+            CountingDirectory {
+                dir: RAMDirectory::new(),
+                open_inputs: Arc::new(AtomicUsize::new(0)),
+                reads: Arc::new(AtomicUsize::new(0)),
+                read_extensions: extensions.to_vec(),
+            }
+        }
+
+        pub fn open_inputs(&self) -> usize {
+            self.open_inputs.load(Ordering::Acquire)
+        }
+
+        pub fn reads(&self) -> usize {
+            self.reads.load(Ordering::Acquire)
+        }
+
+        fn counts_reads_of(&self, name: &str) -> bool {
+            self.read_extensions.is_empty()
+                || self.read_extensions.iter().any(|ext| name.ends_with(ext))
+        }
+    }
+
+    impl fmt::Display for CountingDirectory {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "CountingDirectory({})", self.dir)
+        }
+    }
+
+    pub struct CountingInput {
+        input: Box<dyn IndexInput>,
+        open_inputs: Arc<AtomicUsize>,
+        // `None` when the reads of the file aren't counted
+        reads: Option<Arc<AtomicUsize>>,
+    }
+
+    impl CountingInput {
+        fn wrap(
+            input: Box<dyn IndexInput>,
+            open_inputs: &Arc<AtomicUsize>,
+            reads: &Option<Arc<AtomicUsize>>,
+        ) -> Box<dyn IndexInput> {
+            open_inputs.fetch_add(1, Ordering::AcqRel);
+            Box::new(CountingInput {
+                input,
+                open_inputs: Arc::clone(open_inputs),
+                reads: reads.clone(),
+            })
+        }
+
+        fn count_read(&self, len: usize) {
+            if let Some(ref reads) = self.reads {
+                reads.fetch_add(len, Ordering::AcqRel);
+            }
+        }
+    }
+
+    impl Drop for CountingInput {
+        fn drop(&mut self) {
+            self.open_inputs.fetch_sub(1, Ordering::AcqRel);
+        }
+    }
+
+    impl Read for CountingInput {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let read = self.input.read(buf)?;
+            self.count_read(read);
+            Ok(read)
+        }
+    }
+
+    impl DataInput for CountingInput {
+        fn read_byte(&mut self) -> Result<u8> {
+            self.count_read(1);
+            self.input.read_byte()
+        }
+
+        fn read_bytes(&mut self, b: &mut [u8], offset: usize, length: usize) -> Result<()> {
+            self.count_read(length);
+            self.input.read_bytes(b, offset, length)
+        }
+    }
+
+    impl IndexInput for CountingInput {
+        fn clone(&self) -> Result<Box<dyn IndexInput>> {
+            Ok(CountingInput::wrap(
+                self.input.clone()?,
+                &self.open_inputs,
+                &self.reads,
+            ))
+        }
+
+        fn file_pointer(&self) -> i64 {
+            self.input.file_pointer()
+        }
+
+        fn seek(&mut self, pos: i64) -> Result<()> {
+            self.input.seek(pos)
+        }
+
+        fn len(&self) -> u64 {
+            self.input.len()
+        }
+
+        fn name(&self) -> &str {
+            self.input.name()
+        }
+
+        fn random_access_slice(
+            &self,
+            offset: i64,
+            length: i64,
+        ) -> Result<Box<dyn RandomAccessInput>> {
+            let input = self.input.random_access_slice(offset, length)?;
+            match self.reads {
+                Some(ref reads) => Ok(Box::new(CountingRandomAccessInput {
+                    input,
+                    reads: Arc::clone(reads),
+                })),
+                None => Ok(input),
+            }
+        }
+
+        fn slice(
+            &self,
+            description: &str,
+            offset: i64,
+            length: i64,
+        ) -> Result<Box<dyn IndexInput>> {
+            let slice = self.input.slice(description, offset, length)?;
+            Ok(CountingInput::wrap(slice, &self.open_inputs, &self.reads))
+        }
+    }
+
+    struct CountingRandomAccessInput {
+        input: Box<dyn RandomAccessInput>,
+        reads: Arc<AtomicUsize>,
+    }
+
+    impl RandomAccessInput for CountingRandomAccessInput {
+        fn read_byte(&self, pos: i64) -> Result<u8> {
+            self.reads.fetch_add(1, Ordering::AcqRel);
+            self.input.read_byte(pos)
+        }
+
+        fn read_short(&self, pos: i64) -> Result<i16> {
+            self.reads.fetch_add(2, Ordering::AcqRel);
+            self.input.read_short(pos)
+        }
+
+        fn read_int(&self, pos: i64) -> Result<i32> {
+            self.reads.fetch_add(4, Ordering::AcqRel);
+            self.input.read_int(pos)
+        }
+
+        fn read_long(&self, pos: i64) -> Result<i64> {
+            self.reads.fetch_add(8, Ordering::AcqRel);
+            self.input.read_long(pos)
+        }
+    }
+
+    impl Directory for CountingDirectory {
+        type LK = RAMLock;
+        type IndexOutput = RAMIndexOutput;
+        type TempOutput = RAMIndexOutput;
+
+        fn list_all(&self) -> Result<Vec<String>> {
+            self.dir.list_all()
+        }
+
+        fn file_length(&self, name: &str) -> Result<i64> {
+            self.dir.file_length(name)
+        }
+
+        fn create_output(&self, name: &str, ctx: &IOContext) -> Result<Self::IndexOutput> {
+            self.dir.create_output(name, ctx)
+        }
+
+        fn open_input(&self, name: &str, ctx: &IOContext) -> Result<Box<dyn IndexInput>> {
+            let input = self.dir.open_input(name, ctx)?;
+            let reads = if self.counts_reads_of(name) {
+                Some(Arc::clone(&self.reads))
+            } else {
+                None
+            };
+            Ok(CountingInput::wrap(input, &self.open_inputs, &reads))
+        }
+
+        fn obtain_lock(&self, name: &str) -> Result<Self::LK> {
+            self.dir.obtain_lock(name)
+        }
+
+        fn create_temp_output(
+            &self,
+            prefix: &str,
+            suffix: &str,
+            ctx: &IOContext,
+        ) -> Result<Self::TempOutput> {
+            self.dir.create_temp_output(prefix, suffix, ctx)
+        }
+
+        fn delete_file(&self, name: &str) -> Result<()> {
+            self.dir.delete_file(name)
+        }
+
+        fn sync(&self, names: &HashSet<String>) -> Result<()> {
+            self.dir.sync(names)
+        }
+
+        fn sync_meta_data(&self) -> Result<()> {
+            self.dir.sync_meta_data()
+        }
+
+        fn rename(&self, source: &str, dest: &str) -> Result<()> {
+            self.dir.rename(source, dest)
+        }
+    }
+}
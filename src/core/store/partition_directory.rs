@@ -0,0 +1,399 @@
+// Copyright 2019 Zhizhesihai (Beijing) Technology Limited.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use core::index::{INDEX_FILE_PENDING_SEGMENTS, INDEX_FILE_SEGMENTS};
+use core::store::{DataOutput, Directory, IOContext, IndexInput, IndexOutput};
+use error::ErrorKind::IllegalArgument;
+use error::Result;
+
+use std::collections::HashSet;
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+/// Separates the partition name from the file names of the partition.
+const PARTITION_SEPARATOR: char = '-';
+
+/// Name of a logical index stored along with other ones in a single `Directory`.
+///
+/// All the files of the partition, including its `segments_N` files and its
+/// write lock, are named `<name>-<file>`, so the indexes don't see each other.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct IndexPartition {
+    name: String,
+    prefix: String,
+}
+
+impl IndexPartition {
+    /// Partition names are made of ASCII letters and digits only, so that no
+    /// partition file can be taken for a file of another partition or of an
+    /// index owning the whole directory.
+    pub fn new<S: Into<String>>(name: S) -> Result<IndexPartition> {
+        let name = name.into();
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric()) {
+            bail!(IllegalArgument(format!(
+                "invalid partition name '{}': only ASCII letters and digits are allowed",
+                name
+            )));
+        }
+        if name.starts_with(INDEX_FILE_SEGMENTS) || name.starts_with(INDEX_FILE_PENDING_SEGMENTS) {
+            bail!(IllegalArgument(format!(
+                "invalid partition name '{}': would be taken for a segments file",
+                name
+            )));
+        }
+        let prefix = format!("{}{}", name, PARTITION_SEPARATOR);
+        Ok(IndexPartition { name, prefix })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the name under which the partition file `name` is stored.
+    pub fn file_name(&self, name: &str) -> String {
+        let mut file_name = String::with_capacity(self.prefix.len() + name.len());
+        file_name.push_str(&self.prefix);
+        file_name.push_str(name);
+        file_name
+    }
+
+    /// Returns the partition file name of the stored file `file_name`, or None
+    /// if it belongs to another partition.
+    pub fn strip<'a>(&self, file_name: &'a str) -> Option<&'a str> {
+        if file_name.starts_with(&self.prefix) {
+            Some(&file_name[self.prefix.len()..])
+        } else {
+            None
+        }
+    }
+}
+
+impl fmt::Display for IndexPartition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name)
+    }
+}
+
+/// A view of the files of one `IndexPartition` of the wrapped directory.
+///
+/// Writers, readers and the index file deleter opened on this directory only
+/// see the partition's files, so commits and garbage collection of the
+/// partitions are isolated, and every partition has its own write lock.
+pub struct PartitionDirectory<D: Directory> {
+    directory: Arc<D>,
+    partition: IndexPartition,
+}
+
+impl<D: Directory> PartitionDirectory<D> {
+    pub fn new(directory: Arc<D>, partition: IndexPartition) -> PartitionDirectory<D> {
+        PartitionDirectory {
+            directory,
+            partition,
+        }
+    }
+
+    pub fn partition(&self) -> &IndexPartition {
+        &self.partition
+    }
+
+    pub fn directory(&self) -> &Arc<D> {
+        &self.directory
+    }
+}
+
+impl<D: Directory> Directory for PartitionDirectory<D> {
+    type LK = D::LK;
+    type IndexOutput = PartitionIndexOutput<D::IndexOutput>;
+    type TempOutput = PartitionIndexOutput<D::TempOutput>;
+
+    fn list_all(&self) -> Result<Vec<String>> {
+        Ok(self
+            .directory
+            .list_all()?
+            .iter()
+            .filter_map(|name| self.partition.strip(name))
+            .map(str::to_string)
+            .collect())
+    }
+
+    fn file_length(&self, name: &str) -> Result<i64> {
+        self.directory.file_length(&self.partition.file_name(name))
+    }
+
+    fn create_output(&self, name: &str, context: &IOContext) -> Result<Self::IndexOutput> {
+        let output = self
+            .directory
+            .create_output(&self.partition.file_name(name), context)?;
+        Ok(PartitionIndexOutput::new(output, name.to_string()))
+    }
+
+    fn open_input(&self, name: &str, ctx: &IOContext) -> Result<Box<dyn IndexInput>> {
+        self.directory
+            .open_input(&self.partition.file_name(name), ctx)
+    }
+
+    fn obtain_lock(&self, name: &str) -> Result<Self::LK> {
+        self.directory.obtain_lock(&self.partition.file_name(name))
+    }
+
+    fn create_temp_output(
+        &self,
+        prefix: &str,
+        suffix: &str,
+        ctx: &IOContext,
+    ) -> Result<Self::TempOutput> {
+        let output =
+            self.directory
+                .create_temp_output(&self.partition.file_name(prefix), suffix, ctx)?;
+        let name = match self.partition.strip(output.name()) {
+            Some(name) => name.to_string(),
+            None => output.name().to_string(),
+        };
+        Ok(PartitionIndexOutput::new(output, name))
+    }
+
+    fn delete_file(&self, name: &str) -> Result<()> {
+        self.directory.delete_file(&self.partition.file_name(name))
+    }
+
+    fn sync(&self, name: &HashSet<String>) -> Result<()> {
+        let names = name.iter().map(|n| self.partition.file_name(n)).collect();
+        self.directory.sync(&names)
+    }
+
+    fn sync_meta_data(&self) -> Result<()> {
+        self.directory.sync_meta_data()
+    }
+
+    fn rename(&self, source: &str, dest: &str) -> Result<()> {
+        self.directory.rename(
+            &self.partition.file_name(source),
+            &self.partition.file_name(dest),
+        )
+    }
+
+    fn resolve(&self, name: &str) -> PathBuf {
+        self.directory.resolve(&self.partition.file_name(name))
+    }
+}
+
+impl<D: Directory> fmt::Display for PartitionDirectory<D> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "PartitionDirectory({}, {})",
+            self.partition,
+            self.directory.as_ref()
+        )
+    }
+}
+
+/// Output of a `PartitionDirectory`, named after the partition file instead of
+/// the stored file.
+pub struct PartitionIndexOutput<O: IndexOutput> {
+    delegate: O,
+    name: String,
+}
+
+impl<O: IndexOutput> PartitionIndexOutput<O> {
+    fn new(delegate: O, name: String) -> Self {
+        PartitionIndexOutput { delegate, name }
+    }
+}
+
+impl<O: IndexOutput> IndexOutput for PartitionIndexOutput<O> {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn file_pointer(&self) -> i64 {
+        self.delegate.file_pointer()
+    }
+
+    fn checksum(&self) -> Result<i64> {
+        self.delegate.checksum()
+    }
+}
+
+impl<O: IndexOutput> DataOutput for PartitionIndexOutput<O> {}
+
+impl<O: IndexOutput> io::Write for PartitionIndexOutput<O> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.delegate.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.delegate.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::codec::CodecEnum;
+    use core::doc::{Field, FieldType};
+    use core::index::index_writer_config::IndexWriterConfig;
+    use core::index::merge_policy::TieredMergePolicy;
+    use core::index::merge_scheduler::SerialMergeScheduler;
+    use core::index::{get_last_commit_generation, IndexOptions, IndexReader, IndexWriter};
+    use core::index::{StandardDirectoryReader, Term};
+    use core::search::searcher::{DefaultIndexSearcher, IndexSearcher};
+    use core::search::term_query::TermQuery;
+    use core::store::{DataInput, RAMDirectory};
+    use core::util::VariantValue;
+    use error::ErrorKind::LockObtainFailed;
+
+    type Reader<D> = StandardDirectoryReader<D, CodecEnum, SerialMergeScheduler, TieredMergePolicy>;
+
+    fn tenant_doc(tenant: &str) -> Vec<Field> {
+        let mut field_type = FieldType::default();
+        field_type.tokenized = false;
+        field_type.omit_norms = true;
+        field_type.index_options = IndexOptions::Docs;
+        vec![Field::new(
+            "tenant".to_string(),
+            field_type,
+            Some(VariantValue::VString(tenant.to_string())),
+            None,
+        )]
+    }
+
+    // returns the number of docs of the index and the number of docs of `tenant`
+    fn count<D: Directory + Send + Sync + 'static>(dir: &Arc<D>, tenant: &str) -> (i32, i32) {
+        let reader = Reader::open(Arc::clone(dir)).unwrap();
+        let num_docs = reader.num_docs();
+        let searcher = DefaultIndexSearcher::new(Arc::new(reader));
+        let query = TermQuery::new(
+            Term::new("tenant".to_string(), tenant.as_bytes().to_vec()),
+            1.0,
+            None,
+        );
+        (num_docs, searcher.count(&query).unwrap())
+    }
+
+    #[test]
+    fn test_partition_names() {
+        assert!(IndexPartition::new("tenant1").is_ok());
+        assert!(IndexPartition::new("").is_err());
+        assert!(IndexPartition::new("a-b").is_err());
+        assert!(IndexPartition::new("_0").is_err());
+        assert!(IndexPartition::new("segments1").is_err());
+
+        let partition = IndexPartition::new("a").unwrap();
+        assert_eq!(partition.file_name("segments_1"), "a-segments_1");
+        assert_eq!(partition.strip("a-_0.cfs"), Some("_0.cfs"));
+        assert_eq!(partition.strip("ab-_0.cfs"), None);
+        assert_eq!(partition.strip("_0.cfs"), None);
+    }
+
+    #[test]
+    fn test_partition_directory_isolation() {
+        let dir = Arc::new(RAMDirectory::new());
+        let a = PartitionDirectory::new(Arc::clone(&dir), IndexPartition::new("a").unwrap());
+        let b = PartitionDirectory::new(Arc::clone(&dir), IndexPartition::new("b").unwrap());
+
+        for (part, data) in &[(&a, 1u8), (&b, 2u8)] {
+            let mut output = part.create_output("_0.cfs", &IOContext::Default).unwrap();
+            assert_eq!(output.name(), "_0.cfs");
+            output.write_byte(*data).unwrap();
+        }
+        assert_eq!(a.list_all().unwrap(), vec!["_0.cfs".to_string()]);
+        assert_eq!(
+            dir.list_all().unwrap(),
+            vec!["a-_0.cfs".to_string(), "b-_0.cfs".to_string()]
+        );
+        let mut input = b.open_input("_0.cfs", &IOContext::READ).unwrap();
+        assert_eq!(input.read_byte().unwrap(), 2);
+
+        a.delete_file("_0.cfs").unwrap();
+        assert!(a.list_all().unwrap().is_empty());
+        assert_eq!(b.list_all().unwrap(), vec!["_0.cfs".to_string()]);
+
+        let temp = a
+            .create_temp_output("_1", "sort", &IOContext::Default)
+            .unwrap();
+        let temp_name = temp.name().to_string();
+        drop(temp);
+        assert!(temp_name.starts_with("_1_sort"), "{}", temp_name);
+        assert_eq!(a.list_all().unwrap(), vec![temp_name.clone()]);
+        a.delete_file(&temp_name).unwrap();
+
+        // the write lock is per partition
+        let _lock = a.obtain_lock("write.lock").unwrap();
+        let _other = b.obtain_lock("write.lock").unwrap();
+        match a.obtain_lock("write.lock") {
+            Err(e) => match e.kind() {
+                LockObtainFailed(_) => {}
+                _ => panic!("expected LockObtainFailed, got {:?}", e),
+            },
+            Ok(_) => panic!("obtained the write lock of a partition twice"),
+        }
+    }
+
+    #[test]
+    fn test_partitioned_indexes() {
+        let dir = Arc::new(RAMDirectory::new());
+        let a = Arc::new(PartitionDirectory::new(
+            Arc::clone(&dir),
+            IndexPartition::new("a").unwrap(),
+        ));
+        let b = Arc::new(PartitionDirectory::new(
+            Arc::clone(&dir),
+            IndexPartition::new("b").unwrap(),
+        ));
+        let conf = Arc::new(IndexWriterConfig::default());
+
+        let writer_a = IndexWriter::new(Arc::clone(&a), Arc::clone(&conf)).unwrap();
+        let writer_b = IndexWriter::new(Arc::clone(&b), Arc::clone(&conf)).unwrap();
+        for _ in 0..3 {
+            writer_a.add_document(tenant_doc("a")).unwrap();
+        }
+        writer_b.add_document(tenant_doc("b")).unwrap();
+        writer_a.commit().unwrap();
+        writer_b.commit().unwrap();
+
+        assert_eq!(count(&a, "a"), (3, 3));
+        assert_eq!(count(&a, "b"), (3, 0));
+        assert_eq!(count(&b, "b"), (1, 1));
+        assert_eq!(count(&b, "a"), (1, 0));
+
+        // committing again in one partition drops its previous commit only
+        let b_files = b.list_all().unwrap();
+        let a_generation = get_last_commit_generation(&a.list_all().unwrap()).unwrap();
+        writer_a.add_document(tenant_doc("a")).unwrap();
+        writer_a.commit().unwrap();
+        let a_files = a.list_all().unwrap();
+        assert_eq!(
+            get_last_commit_generation(&a_files).unwrap(),
+            a_generation + 1
+        );
+        assert!(!a_files.contains(&format!("segments_{}", a_generation)));
+        assert_eq!(b.list_all().unwrap(), b_files);
+        assert_eq!(count(&a, "a"), (4, 4));
+        assert_eq!(count(&b, "b"), (1, 1));
+
+        writer_a.close().unwrap();
+        writer_b.close().unwrap();
+
+        // an index owning the whole directory leaves the partitions alone
+        let writer = IndexWriter::new(Arc::clone(&dir), conf).unwrap();
+        writer.add_document(tenant_doc("c")).unwrap();
+        writer.commit().unwrap();
+        writer.close().unwrap();
+        assert_eq!(count(&dir, "c"), (1, 1));
+        assert_eq!(b.list_all().unwrap(), b_files);
+        assert_eq!(count(&a, "a"), (4, 4));
+    }
+}
@@ -0,0 +1,634 @@
+// Copyright 2019 Zhizhesihai (Beijing) Technology Limited.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use core::index::index_writer::INDEX_WRITE_LOCK_NAME;
+use core::index::segment_file_name;
+use core::store::{DataInput, DataOutput, Directory, IOContext, IndexInput, IndexOutput};
+use core::store::{Lock, RandomAccessInput};
+use core::util::to_base36;
+use error::ErrorKind::{
+    AlreadyClosed, IllegalArgument, IllegalState, LockObtainFailed, UnexpectedEOF,
+};
+use error::Result;
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+
+use byteorder::{BigEndian, ByteOrder};
+use flate2::Crc;
+
+#[derive(Default)]
+struct RAMFiles {
+    // files that are completely written and visible to readers
+    files: HashMap<String, Arc<Vec<u8>>>,
+    // files that have an open `RAMIndexOutput`
+    writing: HashSet<String>,
+}
+
+/// A memory-resident `Directory` implementation.
+///
+/// Files only become visible once the `IndexOutput` writing them is dropped,
+/// opening a file that is still being written fails, and `rename` is atomic.
+/// This is mostly useful for unit tests and small ephemeral indexes, the whole
+/// index is held in the heap.
+pub struct RAMDirectory {
+    inner: Arc<RwLock<RAMFiles>>,
+    locks: Arc<Mutex<HashSet<String>>>,
+    next_temp_file_counter: AtomicUsize,
+}
+
+impl Default for RAMDirectory {
+    fn default() -> Self {
+        RAMDirectory {
+            inner: Arc::new(RwLock::new(RAMFiles::default())),
+            locks: Arc::new(Mutex::new(HashSet::new())),
+            next_temp_file_counter: AtomicUsize::new(0),
+        }
+    }
+}
+
+impl RAMDirectory {
+    pub fn new() -> RAMDirectory {
+        Self::default()
+    }
+
+    /// Creates a new `RAMDirectory` holding a copy of all the files of the given
+    /// directory, so that an index on disk can be loaded into memory once and then
+    /// be used by repeated test runs.
+    pub fn from_directory<D: Directory>(dir: &D, ctx: &IOContext) -> Result<RAMDirectory> {
+        let ram_dir = RAMDirectory::new();
+        ram_dir.copy_all_from(dir, ctx)?;
+        Ok(ram_dir)
+    }
+
+    /// Copies all the files of the given directory into this directory, existing
+    /// files with the same name are replaced. Lock files are not copied.
+    pub fn copy_all_from<D: Directory>(&self, dir: &D, ctx: &IOContext) -> Result<()> {
+        for name in dir.list_all()? {
+            // also skips the write locks of index partitions
+            if name.ends_with(INDEX_WRITE_LOCK_NAME) {
+                continue;
+            }
+            let mut input = dir.open_input(&name, ctx)?;
+            let length = input.len() as usize;
+            let mut data = vec![0u8; length];
+            input.read_bytes(&mut data, 0, length)?;
+
+            let mut inner = self.inner.write()?;
+            if inner.writing.contains(&name) {
+                bail!(IllegalState(format!(
+                    "file '{}' is still being written",
+                    name
+                )));
+            }
+            inner.files.insert(name, Arc::new(data));
+        }
+        Ok(())
+    }
+
+    /// Returns the total size in bytes of all the files in this directory.
+    pub fn ram_bytes_used(&self) -> Result<u64> {
+        Ok(self
+            .inner
+            .read()?
+            .files
+            .values()
+            .map(|f| f.len() as u64)
+            .sum())
+    }
+
+    fn new_output(&self, name: String) -> Result<RAMIndexOutput> {
+        let mut inner = self.inner.write()?;
+        if inner.files.contains_key(&name) || inner.writing.contains(&name) {
+            bail!(IllegalArgument(format!("file '{}' already exists", name)));
+        }
+        inner.writing.insert(name.clone());
+        Ok(RAMIndexOutput {
+            name,
+            buffer: Vec::new(),
+            crc: Crc::new(),
+            directory: Arc::clone(&self.inner),
+        })
+    }
+}
+
+impl Directory for RAMDirectory {
+    type LK = RAMLock;
+    type IndexOutput = RAMIndexOutput;
+    type TempOutput = RAMIndexOutput;
+
+    fn list_all(&self) -> Result<Vec<String>> {
+        let mut names: Vec<String> = self.inner.read()?.files.keys().cloned().collect();
+        names.sort();
+        Ok(names)
+    }
+
+    fn file_length(&self, name: &str) -> Result<i64> {
+        let inner = self.inner.read()?;
+        match inner.files.get(name) {
+            Some(data) => Ok(data.len() as i64),
+            None => bail!(IllegalArgument(format!("file '{}' not found", name))),
+        }
+    }
+
+    fn create_output(&self, name: &str, _ctx: &IOContext) -> Result<Self::IndexOutput> {
+        self.new_output(name.to_string())
+    }
+
+    fn open_input(&self, name: &str, _ctx: &IOContext) -> Result<Box<dyn IndexInput>> {
+        let inner = self.inner.read()?;
+        if inner.writing.contains(name) {
+            bail!(IllegalState(format!(
+                "file '{}' is still being written",
+                name
+            )));
+        }
+        match inner.files.get(name) {
+            Some(data) => Ok(Box::new(RAMIndexInput::new(name, Arc::clone(data)))),
+            None => bail!(IllegalArgument(format!("file '{}' not found", name))),
+        }
+    }
+
+    fn obtain_lock(&self, name: &str) -> Result<Self::LK> {
+        if !self.locks.lock()?.insert(name.to_string()) {
+            bail!(LockObtainFailed(format!(
+                "lock instance already obtained: {}",
+                name
+            )));
+        }
+        Ok(RAMLock {
+            name: name.to_string(),
+            locks: Arc::clone(&self.locks),
+        })
+    }
+
+    fn create_temp_output(
+        &self,
+        prefix: &str,
+        suffix: &str,
+        _ctx: &IOContext,
+    ) -> Result<Self::TempOutput> {
+        loop {
+            let name = segment_file_name(
+                prefix,
+                &format!(
+                    "{}_{}",
+                    suffix,
+                    to_base36(self.next_temp_file_counter.fetch_add(1, Ordering::AcqRel) as u64)
+                ),
+                "tmp",
+            );
+            {
+                let inner = self.inner.read()?;
+                if inner.files.contains_key(&name) || inner.writing.contains(&name) {
+                    continue;
+                }
+            }
+            return self.new_output(name);
+        }
+    }
+
+    fn delete_file(&self, name: &str) -> Result<()> {
+        let mut inner = self.inner.write()?;
+        if inner.writing.contains(name) {
+            bail!(IllegalState(format!(
+                "file '{}' is still being written",
+                name
+            )));
+        }
+        if inner.files.remove(name).is_none() {
+            bail!(IllegalArgument(format!("file '{}' not found", name)));
+        }
+        Ok(())
+    }
+
+    fn sync(&self, names: &HashSet<String>) -> Result<()> {
+        // nothing to make durable, but all the files must be completely written
+        let inner = self.inner.read()?;
+        for name in names {
+            if inner.writing.contains(name) {
+                bail!(IllegalState(format!(
+                    "file '{}' is still being written",
+                    name
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    fn sync_meta_data(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn rename(&self, source: &str, dest: &str) -> Result<()> {
+        let mut inner = self.inner.write()?;
+        if inner.writing.contains(source) || inner.writing.contains(dest) {
+            bail!(IllegalState(format!(
+                "can't rename '{}' to '{}' while it is being written",
+                source, dest
+            )));
+        }
+        match inner.files.remove(source) {
+            Some(data) => {
+                inner.files.insert(dest.to_string(), data);
+                Ok(())
+            }
+            None => bail!(IllegalArgument(format!("file '{}' not found", source))),
+        }
+    }
+}
+
+impl fmt::Display for RAMDirectory {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "RAMDirectory@{:p}", self.inner.as_ref())
+    }
+}
+
+/// `Lock` for `RAMDirectory`, only guards against other obtainers in the same directory.
+pub struct RAMLock {
+    name: String,
+    locks: Arc<Mutex<HashSet<String>>>,
+}
+
+impl Lock for RAMLock {
+    fn close(&self) -> Result<()> {
+        if !self.locks.lock()?.remove(&self.name) {
+            bail!(AlreadyClosed(format!(
+                "lock instance was already released: {}",
+                self.name
+            )));
+        }
+        Ok(())
+    }
+
+    fn ensure_valid(&self) -> Result<()> {
+        if !self.locks.lock()?.contains(&self.name) {
+            bail!(AlreadyClosed(format!(
+                "lock instance was already released: {}",
+                self.name
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// `IndexOutput` of `RAMDirectory`, the file is published to the directory when the
+/// output is dropped.
+pub struct RAMIndexOutput {
+    name: String,
+    buffer: Vec<u8>,
+    crc: Crc,
+    directory: Arc<RwLock<RAMFiles>>,
+}
+
+impl Drop for RAMIndexOutput {
+    fn drop(&mut self) {
+        let data = ::std::mem::replace(&mut self.buffer, Vec::new());
+        match self.directory.write() {
+            Ok(mut inner) => {
+                inner.writing.remove(&self.name);
+                inner.files.insert(self.name.clone(), Arc::new(data));
+            }
+            Err(e) => error!("Oops, failed to publish {}, errmsg: {}", self.name, e),
+        }
+    }
+}
+
+impl Write for RAMIndexOutput {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        self.crc.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl DataOutput for RAMIndexOutput {}
+
+impl IndexOutput for RAMIndexOutput {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn file_pointer(&self) -> i64 {
+        self.buffer.len() as i64
+    }
+
+    fn checksum(&self) -> Result<i64> {
+        Ok((self.crc.sum() as i64) & 0xffff_ffffi64)
+    }
+}
+
+/// `IndexInput` over a file of `RAMDirectory`, clones and slices share the file data.
+pub struct RAMIndexInput {
+    data: Arc<Vec<u8>>,
+    start: usize,
+    end: usize,
+    // relative to `start`
+    position: usize,
+    description: String,
+}
+
+impl RAMIndexInput {
+    fn new(name: &str, data: Arc<Vec<u8>>) -> RAMIndexInput {
+        let end = data.len();
+        RAMIndexInput {
+            data,
+            start: 0,
+            end,
+            position: 0,
+            description: name.to_string(),
+        }
+    }
+
+    fn slice_impl(&self, description: &str, offset: i64, length: i64) -> Result<RAMIndexInput> {
+        if offset < 0 || length < 0 || (offset + length) as u64 > self.len() {
+            bail!(IllegalArgument(format!(
+                "slice() {} out of bounds: offset={},length={},fileLength={}: {}",
+                description,
+                offset,
+                length,
+                self.len(),
+                self.description
+            )));
+        }
+        let start = self.start + offset as usize;
+        Ok(RAMIndexInput {
+            data: Arc::clone(&self.data),
+            start,
+            end: start + length as usize,
+            position: 0,
+            description: format!("{} [slice={}]", self.description, description),
+        })
+    }
+
+    /// Returns the absolute offset in `data` of the next `count` bytes.
+    fn ensure_remaining(&self, count: usize) -> Result<usize> {
+        let pos = self.start + self.position;
+        if count > self.end - pos {
+            bail!(UnexpectedEOF(format!(
+                "read past EOF: {}, pos={}, count={}, len={}",
+                self.description,
+                self.position,
+                count,
+                self.len()
+            )));
+        }
+        Ok(pos)
+    }
+
+    fn check_index(&self, pos: i64, count: usize) -> Result<usize> {
+        if pos < 0 || pos as u64 + count as u64 > self.len() {
+            bail!(IllegalArgument(format!(
+                "invalid position, expecting 0 <= pos <= {}, got: {}",
+                self.len().saturating_sub(count as u64),
+                pos
+            )));
+        }
+        Ok(self.start + pos as usize)
+    }
+}
+
+impl Clone for RAMIndexInput {
+    fn clone(&self) -> Self {
+        RAMIndexInput {
+            data: Arc::clone(&self.data),
+            start: self.start,
+            end: self.end,
+            position: self.position,
+            description: self.description.clone(),
+        }
+    }
+}
+
+impl IndexInput for RAMIndexInput {
+    fn clone(&self) -> Result<Box<dyn IndexInput>> {
+        Ok(Box::new(Clone::clone(self)))
+    }
+
+    fn file_pointer(&self) -> i64 {
+        self.position as i64
+    }
+
+    fn seek(&mut self, pos: i64) -> Result<()> {
+        if pos < 0 || pos as u64 > self.len() {
+            bail!(IllegalArgument(format!(
+                "invalid position, expecting 0 <= pos <= {}, got: {}",
+                self.len(),
+                pos
+            )));
+        }
+        self.position = pos as usize;
+        Ok(())
+    }
+
+    fn len(&self) -> u64 {
+        (self.end - self.start) as u64
+    }
+
+    fn name(&self) -> &str {
+        "RAMIndexInput"
+    }
+
+    fn random_access_slice(&self, offset: i64, length: i64) -> Result<Box<dyn RandomAccessInput>> {
+        Ok(Box::new(self.slice_impl(
+            "RandomAccessSlice",
+            offset,
+            length,
+        )?))
+    }
+
+    fn slice(&self, description: &str, offset: i64, length: i64) -> Result<Box<dyn IndexInput>> {
+        Ok(Box::new(self.slice_impl(description, offset, length)?))
+    }
+
+    fn bytes_view(&self, offset: i64, length: i64) -> Option<&[u8]> {
+        if offset < 0 || length < 0 || (offset + length) as u64 > self.len() {
+            return None;
+        }
+        let start = self.start + offset as usize;
+        Some(&self.data[start..start + length as usize])
+    }
+}
+
+impl DataInput for RAMIndexInput {
+    fn read_byte(&mut self) -> Result<u8> {
+        let pos = self.ensure_remaining(1)?;
+        self.position += 1;
+        Ok(self.data[pos])
+    }
+
+    fn read_bytes(&mut self, b: &mut [u8], offset: usize, length: usize) -> Result<()> {
+        if b.len() < offset + length {
+            bail!(IllegalArgument(format!(
+                "Buffer too small: writing [{}, {}) to [0, {})",
+                offset,
+                offset + length,
+                b.len()
+            )));
+        }
+        let pos = self.ensure_remaining(length)?;
+        b[offset..offset + length].copy_from_slice(&self.data[pos..pos + length]);
+        self.position += length;
+        Ok(())
+    }
+
+    fn read_short(&mut self) -> Result<i16> {
+        let pos = self.ensure_remaining(2)?;
+        self.position += 2;
+        Ok(BigEndian::read_i16(&self.data[pos..pos + 2]))
+    }
+
+    fn read_int(&mut self) -> Result<i32> {
+        let pos = self.ensure_remaining(4)?;
+        self.position += 4;
+        Ok(BigEndian::read_i32(&self.data[pos..pos + 4]))
+    }
+
+    fn read_long(&mut self) -> Result<i64> {
+        let pos = self.ensure_remaining(8)?;
+        self.position += 8;
+        Ok(BigEndian::read_i64(&self.data[pos..pos + 8]))
+    }
+
+    fn skip_bytes(&mut self, count: usize) -> Result<()> {
+        self.ensure_remaining(count)?;
+        self.position += count;
+        Ok(())
+    }
+}
+
+impl Read for RAMIndexInput {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let pos = self.start + self.position;
+        let count = buf.len().min(self.end - pos);
+        buf[..count].copy_from_slice(&self.data[pos..pos + count]);
+        self.position += count;
+        Ok(count)
+    }
+}
+
+impl RandomAccessInput for RAMIndexInput {
+    fn read_byte(&self, pos: i64) -> Result<u8> {
+        let pos = self.check_index(pos, 1)?;
+        Ok(self.data[pos])
+    }
+
+    fn read_short(&self, pos: i64) -> Result<i16> {
+        let pos = self.check_index(pos, 2)?;
+        Ok(BigEndian::read_i16(&self.data[pos..pos + 2]))
+    }
+
+    fn read_int(&self, pos: i64) -> Result<i32> {
+        let pos = self.check_index(pos, 4)?;
+        Ok(BigEndian::read_i32(&self.data[pos..pos + 4]))
+    }
+
+    fn read_long(&self, pos: i64) -> Result<i64> {
+        let pos = self.check_index(pos, 8)?;
+        Ok(BigEndian::read_i64(&self.data[pos..pos + 8]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ram_directory_write_read() {
+        let dir = RAMDirectory::new();
+        {
+            let mut output = dir.create_output("test.bin", &IOContext::Default).unwrap();
+            output.write_int(0x0102_0304).unwrap();
+            output.write_long(-5).unwrap();
+            output.write_vint(300).unwrap();
+            output.write_string("hello").unwrap();
+            assert_eq!(output.file_pointer(), 20);
+
+            // not visible until the output is dropped
+            assert!(dir.open_input("test.bin", &IOContext::READ).is_err());
+            assert!(dir.list_all().unwrap().is_empty());
+        }
+        assert_eq!(dir.list_all().unwrap(), vec!["test.bin".to_string()]);
+        assert_eq!(dir.file_length("test.bin").unwrap(), 20);
+
+        let mut input = dir.open_input("test.bin", &IOContext::READ).unwrap();
+        assert_eq!(input.len(), 20);
+        assert_eq!(input.read_int().unwrap(), 0x0102_0304);
+        let mut cloned = input.as_ref().clone().unwrap();
+        assert_eq!(input.read_long().unwrap(), -5);
+        assert_eq!(input.read_vint().unwrap(), 300);
+        assert_eq!(input.read_string().unwrap(), "hello");
+        assert!(input.read_byte().is_err());
+
+        assert_eq!(cloned.file_pointer(), 4);
+        assert_eq!(cloned.read_long().unwrap(), -5);
+
+        let slice = input.random_access_slice(4, 8).unwrap();
+        assert_eq!(slice.read_long(0).unwrap(), -5);
+        assert!(slice.read_int(6).is_err());
+
+        let mut slice = input.slice("vint", 12, 8).unwrap();
+        assert_eq!(slice.read_vint().unwrap(), 300);
+        assert!(input.slice("oob", 12, 9).is_err());
+    }
+
+    #[test]
+    fn test_ram_directory_file_ops() {
+        let dir = RAMDirectory::new();
+        {
+            let mut output = dir.create_output("a", &IOContext::Default).unwrap();
+            output.write_byte(1).unwrap();
+            assert!(dir.create_output("a", &IOContext::Default).is_err());
+            assert!(dir.rename("a", "b").is_err());
+        }
+        assert!(dir.create_output("a", &IOContext::Default).is_err());
+
+        dir.rename("a", "b").unwrap();
+        assert_eq!(dir.list_all().unwrap(), vec!["b".to_string()]);
+        assert!(dir.open_input("a", &IOContext::READ).is_err());
+        assert_eq!(dir.file_length("b").unwrap(), 1);
+
+        dir.delete_file("b").unwrap();
+        assert!(dir.delete_file("b").is_err());
+        assert!(dir.list_all().unwrap().is_empty());
+
+        let lock = dir.obtain_lock("write.lock").unwrap();
+        assert!(dir.obtain_lock("write.lock").is_err());
+        lock.ensure_valid().unwrap();
+        lock.close().unwrap();
+        assert!(lock.ensure_valid().is_err());
+        assert!(dir.obtain_lock("write.lock").is_ok());
+    }
+
+    #[test]
+    fn test_ram_directory_copy() {
+        let source = RAMDirectory::new();
+        {
+            let mut output = source.create_output("seg_1", &IOContext::Default).unwrap();
+            output.write_bytes(&[1u8, 2, 3], 0, 3).unwrap();
+        }
+        let dir = RAMDirectory::from_directory(&source, &IOContext::READ).unwrap();
+        let mut input = dir.open_input("seg_1", &IOContext::READ).unwrap();
+        let mut buf = [0u8; 3];
+        input.read_bytes(&mut buf, 0, 3).unwrap();
+        assert_eq!(buf, [1u8, 2, 3]);
+        assert_eq!(dir.ram_bytes_used().unwrap(), 3);
+    }
+}
@@ -11,10 +11,13 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use error::ErrorKind::IllegalArgument;
 use error::Result;
 
-use std::sync::Arc;
-use std::time::{Duration, SystemTime};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
 
 /// Abstract base class to rate limit IO.  Typically implementations are
 /// shared across multiple IndexInputs or IndexOutputs (for example
@@ -24,7 +27,7 @@ use std::time::{Duration, SystemTime};
 
 pub trait RateLimiter: Sync + Send {
     /// Sets an updated MB per second rate limit.
-    fn set_mb_per_sec(&self, mb_per_sec: f64);
+    fn set_mb_per_sec(&self, mb_per_sec: f64) -> Result<()>;
 
     /// The current MB per second rate limit.
     fn mb_per_sec(&self) -> f64;
@@ -40,8 +43,8 @@ pub trait RateLimiter: Sync + Send {
 }
 
 impl RateLimiter for Arc<RateLimiter> {
-    fn set_mb_per_sec(&self, mb_per_sec: f64) {
-        (**self).set_mb_per_sec(mb_per_sec);
+    fn set_mb_per_sec(&self, mb_per_sec: f64) -> Result<()> {
+        (**self).set_mb_per_sec(mb_per_sec)
     }
 
     fn mb_per_sec(&self) -> f64 {
@@ -58,36 +61,147 @@ impl RateLimiter for Arc<RateLimiter> {
 }
 
 /// Simple class to rate limit IO.
+///
+/// The rate can be changed at runtime and is shared by all the inputs and
+/// outputs holding this limiter.
 pub struct SimpleRateLimiter {
-    _mb_per_sec: f64,
-    _min_pause_check_bytes: u64,
-    _last_ns: SystemTime,
+    // bits of the f64 rate, so that it can be changed without locking
+    mb_per_sec: AtomicU64,
+    min_pause_check_bytes: AtomicU64,
+    last_time: Mutex<Instant>,
+    total_paused_nanos: AtomicU64,
 }
 
+const MIN_PAUSE_CHECK_MSEC: f64 = 5.0;
+
 impl SimpleRateLimiter {
-    pub fn new(mb_per_sec: f64) -> Self {
-        SimpleRateLimiter {
-            _mb_per_sec: mb_per_sec,
-            _min_pause_check_bytes: 0,
-            _last_ns: SystemTime::now(),
-        }
+    pub fn new(mb_per_sec: f64) -> Result<Self> {
+        let limiter = SimpleRateLimiter {
+            mb_per_sec: AtomicU64::new(0),
+            min_pause_check_bytes: AtomicU64::new(0),
+            last_time: Mutex::new(Instant::now()),
+            total_paused_nanos: AtomicU64::new(0),
+        };
+        limiter.set_mb_per_sec(mb_per_sec)?;
+        Ok(limiter)
+    }
+
+    /// Total time spent pausing in `#pause` since this limiter was created.
+    pub fn total_paused(&self) -> Duration {
+        Duration::from_nanos(self.total_paused_nanos.load(Ordering::Acquire))
     }
 }
 
 impl RateLimiter for SimpleRateLimiter {
-    fn set_mb_per_sec(&self, _mb_per_sec: f64) {
-        unimplemented!()
+    fn set_mb_per_sec(&self, mb_per_sec: f64) -> Result<()> {
+        if mb_per_sec.is_nan() || mb_per_sec <= 0.0 {
+            bail!(IllegalArgument(format!(
+                "mb_per_sec must be positive; got: {}",
+                mb_per_sec
+            )));
+        }
+        self.mb_per_sec
+            .store(mb_per_sec.to_bits(), Ordering::Release);
+        let check_bytes = MIN_PAUSE_CHECK_MSEC / 1000.0 * mb_per_sec * 1024.0 * 1024.0;
+        // an infinite rate never needs to pause
+        let check_bytes = if check_bytes.is_infinite() {
+            u64::max_value()
+        } else {
+            check_bytes as u64
+        };
+        self.min_pause_check_bytes
+            .store(check_bytes, Ordering::Release);
+        Ok(())
     }
 
     fn mb_per_sec(&self) -> f64 {
-        unimplemented!()
+        f64::from_bits(self.mb_per_sec.load(Ordering::Acquire))
     }
 
-    fn pause(&self, _bytes: u64) -> Result<Duration> {
-        unimplemented!()
+    fn pause(&self, bytes: u64) -> Result<Duration> {
+        let start = Instant::now();
+        let seconds_to_pause = bytes as f64 / 1024.0 / 1024.0 / self.mb_per_sec();
+        let target = {
+            let mut last_time = self.last_time.lock()?;
+            // the rate is instantaneous, time spent without writing is not credited
+            let target = *last_time + Duration::from_nanos((seconds_to_pause * 1e9) as u64);
+            if target <= start {
+                *last_time = start;
+                return Ok(Duration::default());
+            }
+            *last_time = target;
+            target
+        };
+
+        // loop because sleep doesn't always sleep long enough
+        loop {
+            let now = Instant::now();
+            if now >= target {
+                break;
+            }
+            thread::sleep(target - now);
+        }
+        let paused = start.elapsed();
+        let nanos = paused.as_secs() * 1_000_000_000 + u64::from(paused.subsec_nanos());
+        self.total_paused_nanos.fetch_add(nanos, Ordering::AcqRel);
+        Ok(paused)
     }
 
     fn min_pause_check_bytes(&self) -> u64 {
-        unimplemented!()
+        self.min_pause_check_bytes.load(Ordering::Acquire)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::store::{DataOutput, IndexOutput, RAMOutputStream, RateLimitIndexOutput};
+
+    #[test]
+    fn test_simple_rate_limiter_throttles_output() {
+        let limiter = Arc::new(SimpleRateLimiter::new(64.0).unwrap());
+        let mut output = RateLimitIndexOutput::new(
+            Arc::clone(&limiter),
+            RAMOutputStream::with_chunk_size(1 << 20, false),
+        );
+
+        let total = 4 * 1024 * 1024;
+        let buf = vec![7u8; 16 * 1024];
+        let start = Instant::now();
+        for _ in 0..total / buf.len() {
+            output.write_bytes(&buf, 0, buf.len()).unwrap();
+        }
+        let elapsed = start.elapsed();
+        assert_eq!(output.file_pointer(), total as i64);
+
+        // 4MB at 64MB/s take 62.5ms, the bytes after the last pause check
+        // are not paused for
+        assert!(elapsed >= Duration::from_millis(50), "{:?}", elapsed);
+
+        // the pauses never sleep less than the rate requires
+        let paused = limiter.total_paused();
+        assert!(paused >= Duration::from_millis(50), "{:?}", paused);
+        assert!(paused <= elapsed, "{:?} > {:?}", paused, elapsed);
+    }
+
+    #[test]
+    fn test_simple_rate_limiter_set_rate() {
+        let limiter = SimpleRateLimiter::new(8.0).unwrap();
+        assert_eq!(limiter.mb_per_sec(), 8.0);
+        let check_bytes = limiter.min_pause_check_bytes();
+        limiter.set_mb_per_sec(16.0).unwrap();
+        assert_eq!(limiter.mb_per_sec(), 16.0);
+        assert_eq!(limiter.min_pause_check_bytes(), check_bytes * 2);
+
+        limiter.set_mb_per_sec(::std::f64::INFINITY).unwrap();
+        assert_eq!(limiter.min_pause_check_bytes(), u64::max_value());
+        assert_eq!(limiter.pause(1 << 30).unwrap(), Duration::default());
+
+        // invalid rates are rejected and leave the rate as it was
+        assert!(limiter.set_mb_per_sec(0.0).is_err());
+        assert!(limiter.set_mb_per_sec(-1.0).is_err());
+        assert!(limiter.set_mb_per_sec(::std::f64::NAN).is_err());
+        assert_eq!(limiter.mb_per_sec(), ::std::f64::INFINITY);
+        assert!(SimpleRateLimiter::new(0.0).is_err());
     }
 }
@@ -203,6 +203,27 @@ impl FixedBitSet {
         self.bits[end_word] ^= end_mask;
     }
 
+    /// Returns the index of the last set bit before or at the index specified,
+    /// -1 if there is none.
+    pub fn prev_set_bit(&self, index: usize) -> i32 {
+        debug_assert!(index < self.num_bits);
+        let mut i = index >> 6;
+        let sub_index = index & 0x3fusize;
+        // skip all the bits to the left of index
+        let word = self.bits[i] << (63 - sub_index);
+        if word != 0 {
+            return ((i << 6) + sub_index) as i32 - word.leading_zeros() as i32;
+        }
+        while i > 0 {
+            i -= 1;
+            let word = self.bits[i];
+            if word != 0 {
+                return ((i << 6) + 63) as i32 - word.leading_zeros() as i32;
+            }
+        }
+        -1
+    }
+
     /// returns true if the sets have any elements in common
     pub fn intersects(&self, other: &FixedBitSet) -> bool {
         // Depends on the ghost bits being clear!
@@ -219,6 +240,25 @@ impl FixedBitSet {
         self.do_or(&other.bits, other.num_words);
     }
 
+    /// this = this AND other
+    pub fn set_and(&mut self, other: &FixedBitSet) {
+        let pos = self.num_words.min(other.num_words);
+        for i in 0..pos {
+            self.bits[i] &= other.bits[i];
+        }
+        for word in &mut self.bits[pos..self.num_words] {
+            *word = 0;
+        }
+    }
+
+    /// this = this AND NOT other
+    pub fn set_and_not(&mut self, other: &FixedBitSet) {
+        let pos = self.num_words.min(other.num_words);
+        for i in 0..pos {
+            self.bits[i] &= !other.bits[i];
+        }
+    }
+
     fn do_or(&mut self, other_arr: &[i64], other_num_words: usize) {
         assert!(other_num_words <= self.num_words);
         let this_arr = &mut self.bits;
@@ -364,9 +404,387 @@ impl Bits for FixedBitSet {
     }
 }
 
+/// A bit set that only allocates the 64 bit words holding set bits, for sets
+/// of a low density. The bits are grouped in blocks of 4096, each block has
+/// an index word whose i-th bit tells whether its i-th word is allocated, and
+/// the allocated words of the block are stored in order.
+pub struct SparseFixedBitSet {
+    indices: Vec<u64>,
+    bits: Vec<Vec<u64>>,
+    length: usize,
+}
+
+impl SparseFixedBitSet {
+    pub fn new(length: usize) -> SparseFixedBitSet {
+        let block_count = (length + 4095) >> 12;
+        SparseFixedBitSet {
+            indices: vec![0; block_count],
+            bits: vec![Vec::new(); block_count],
+            length,
+        }
+    }
+
+    /// Returns the index of the last set bit before or at the index specified,
+    /// -1 if there is none.
+    pub fn prev_set_bit(&self, index: usize) -> i32 {
+        debug_assert!(index < self.length);
+        let i4096 = index >> 12;
+        let block_index = self.indices[i4096];
+        let i64 = (index >> 6) & 0x3f;
+        if block_index & (1u64 << i64) != 0 {
+            let o = (block_index & ((1u64 << i64) - 1)).count_ones() as usize;
+            let word = self.bits[i4096][o] << (63 - (index & 0x3f));
+            if word != 0 {
+                return index as i32 - word.leading_zeros() as i32;
+            }
+        }
+        let indices_before = block_index & ((1u64 << i64) - 1);
+        if indices_before != 0 {
+            return Self::last_set_bit(i4096, indices_before, &self.bits[i4096]);
+        }
+        for block in (0..i4096).rev() {
+            if self.indices[block] != 0 {
+                return Self::last_set_bit(block, self.indices[block], &self.bits[block]);
+            }
+        }
+        -1
+    }
+
+    // the last set bit of a block, `block_index` covering a prefix of `words`
+    fn last_set_bit(i4096: usize, block_index: u64, words: &[u64]) -> i32 {
+        let i64 = 63 - block_index.leading_zeros() as usize;
+        let word = words[block_index.count_ones() as usize - 1];
+        ((i4096 << 12) | (i64 << 6) | (63 - word.leading_zeros() as usize)) as i32
+    }
+
+    // this word &= mask, the words becoming empty are released
+    fn and_word(&mut self, word_index: usize, mask: u64) {
+        let i4096 = word_index >> 6;
+        let i64 = word_index & 0x3f;
+        let block_index = self.indices[i4096];
+        if block_index & (1u64 << i64) == 0 {
+            return;
+        }
+        let o = (block_index & ((1u64 << i64) - 1)).count_ones() as usize;
+        self.bits[i4096][o] &= mask;
+        if self.bits[i4096][o] == 0 {
+            self.bits[i4096].remove(o);
+            self.indices[i4096] &= !(1u64 << i64);
+        }
+    }
+}
+
+impl ImmutableBitSet for SparseFixedBitSet {
+    fn cardinality(&self) -> usize {
+        self.bits
+            .iter()
+            .flat_map(|words| words.iter())
+            .map(|w| w.count_ones() as usize)
+            .sum()
+    }
+
+    fn next_set_bit(&self, index: usize) -> i32 {
+        debug_assert!(index < self.length);
+        let i4096 = index >> 12;
+        let block_index = self.indices[i4096];
+        let i64 = (index >> 6) & 0x3f;
+        let mut o = (block_index & ((1u64 << i64) - 1)).count_ones() as usize;
+        if block_index & (1u64 << i64) != 0 {
+            let word = self.bits[i4096][o] >> (index & 0x3f);
+            if word != 0 {
+                return (index + word.trailing_zeros() as usize) as i32;
+            }
+            o += 1;
+        }
+        let indices_after = block_index >> i64 >> 1;
+        if indices_after != 0 {
+            let next_i64 = i64 + 1 + indices_after.trailing_zeros() as usize;
+            let word = self.bits[i4096][o];
+            return ((i4096 << 12) | (next_i64 << 6) | word.trailing_zeros() as usize) as i32;
+        }
+        for block in i4096 + 1..self.indices.len() {
+            let block_index = self.indices[block];
+            if block_index != 0 {
+                let i64 = block_index.trailing_zeros() as usize;
+                let word = self.bits[block][0];
+                return ((block << 12) | (i64 << 6) | word.trailing_zeros() as usize) as i32;
+            }
+        }
+        NO_MORE_DOCS
+    }
+}
+
+impl BitSet for SparseFixedBitSet {
+    fn set(&mut self, index: usize) {
+        debug_assert!(index < self.length);
+        let i4096 = index >> 12;
+        let block_index = self.indices[i4096];
+        let i64 = (index >> 6) & 0x3f;
+        let o = (block_index & ((1u64 << i64) - 1)).count_ones() as usize;
+        if block_index & (1u64 << i64) != 0 {
+            self.bits[i4096][o] |= 1u64 << (index & 0x3f);
+        } else {
+            self.indices[i4096] |= 1u64 << i64;
+            self.bits[i4096].insert(o, 1u64 << (index & 0x3f));
+        }
+    }
+
+    fn clear(&mut self, index: usize) {
+        debug_assert!(index < self.length);
+        self.and_word(index >> 6, !(1u64 << (index & 0x3f)));
+    }
+
+    fn clear_batch(&mut self, start_index: usize, end_index: usize) {
+        debug_assert!(start_index < self.length);
+        debug_assert!(end_index <= self.length);
+        if end_index <= start_index {
+            return;
+        }
+        let start_word = start_index >> 6;
+        let end_word = (end_index - 1) >> 6;
+        // the bits to keep in the first and the last words
+        let start_mask = !(u64::max_value() << (start_index & 0x3f));
+        let end_mask = match end_index & 0x3f {
+            0 => 0,
+            bits => u64::max_value() << bits,
+        };
+
+        if start_word == end_word {
+            self.and_word(start_word, start_mask | end_mask);
+            return;
+        }
+        self.and_word(start_word, start_mask);
+        for word in start_word + 1..end_word {
+            self.and_word(word, 0);
+        }
+        self.and_word(end_word, end_mask);
+    }
+}
+
+impl Bits for SparseFixedBitSet {
+    fn get_with_ctx(&self, ctx: BitsContext, index: usize) -> Result<(bool, BitsContext)> {
+        debug_assert!(index < self.length);
+        let i4096 = index >> 12;
+        let block_index = self.indices[i4096];
+        let i64 = (index >> 6) & 0x3f;
+        if block_index & (1u64 << i64) == 0 {
+            return Ok((false, ctx));
+        }
+        let o = (block_index & ((1u64 << i64) - 1)).count_ones() as usize;
+        Ok((self.bits[i4096][o] & (1u64 << (index & 0x3f)) != 0, ctx))
+    }
+
+    fn len(&self) -> usize {
+        self.length
+    }
+
+    fn as_bit_set(&self) -> &BitSet {
+        self
+    }
+
+    fn as_bit_set_mut(&mut self) -> &mut BitSet {
+        self
+    }
+
+    fn clone(&self) -> BitsRef {
+        Arc::new(SparseFixedBitSet {
+            indices: self.indices.clone(),
+            bits: self.bits.clone(),
+            length: self.length,
+        })
+    }
+}
+
 /// returns the number of 64 bit words it would take to hold numBits
 pub fn bits2words(num_bits: usize) -> usize {
     let num_bits = num_bits as i32;
     // I.e.: get the word-offset of the last bit and add one (make sure to use >> so 0 returns 0!)
     (((num_bits - 1) >> 6) + 1) as usize
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::util::doc_id_set::BitSetIterator;
+    use core::util::tests::seeded_rng;
+
+    use rand::Rng;
+    use std::collections::BTreeSet;
+
+    // checks every read of `bits` against the `expected` model
+    fn check<T: ImmutableBitSet>(bits: &T, expected: &BTreeSet<usize>, len: usize) {
+        for i in 0..len {
+            assert_eq!(bits.get(i).unwrap(), expected.contains(&i), "bit {}", i);
+        }
+        assert_eq!(bits.cardinality(), expected.len());
+        for i in 0..len {
+            let next = expected
+                .range(i..)
+                .next()
+                .map_or(NO_MORE_DOCS, |&d| d as i32);
+            assert_eq!(bits.next_set_bit(i), next, "next from {}", i);
+        }
+    }
+
+    fn check_iterator<T: ImmutableBitSet>(
+        rng: &mut impl Rng,
+        bits: Arc<T>,
+        expected: &BTreeSet<usize>,
+    ) {
+        let len = bits.len();
+        let mut iter = BitSetIterator::new(Arc::clone(&bits), expected.len()).unwrap();
+        assert_eq!(iter.doc_id(), -1);
+        for &doc in expected {
+            assert_eq!(iter.next().unwrap(), doc as i32);
+        }
+        assert_eq!(iter.next().unwrap(), NO_MORE_DOCS);
+
+        // advance to random targets, and to the word boundaries
+        let mut targets: Vec<usize> = (0..50).map(|_| rng.gen_range(0, len)).collect();
+        targets.extend(
+            [63, 64, 65, 127, 128, 4095, 4096, 4097]
+                .iter()
+                .filter(|&&t| t < len),
+        );
+        targets.sort();
+        let mut iter = BitSetIterator::new(bits, expected.len()).unwrap();
+        for target in targets {
+            if (target as i32) <= iter.doc_id() {
+                continue;
+            }
+            let next = expected
+                .range(target..)
+                .next()
+                .map_or(NO_MORE_DOCS, |&d| d as i32);
+            assert_eq!(iter.advance(target as i32).unwrap(), next);
+            if next == NO_MORE_DOCS {
+                break;
+            }
+        }
+        let mut iter = BitSetIterator::new(Arc::new(FixedBitSet::new(len)), 0).unwrap();
+        assert_eq!(iter.advance(len as i32).unwrap(), NO_MORE_DOCS);
+    }
+
+    // applies the same random updates to `bits` and to the model
+    fn random_updates<T: BitSet>(
+        rng: &mut impl Rng,
+        bits: &mut T,
+        expected: &mut BTreeSet<usize>,
+        len: usize,
+    ) {
+        for _ in 0..200 {
+            let i = rng.gen_range(0, len);
+            match rng.gen_range(0, 8) {
+                0 => {
+                    bits.clear(i);
+                    expected.remove(&i);
+                }
+                1 => {
+                    let end = (i + rng.gen_range(0, 300)).min(len);
+                    bits.clear_batch(i, end);
+                    for j in i..end {
+                        expected.remove(&j);
+                    }
+                }
+                2 => {
+                    let end = (i + rng.gen_range(0, 100)).min(len);
+                    bits.batch_set(i, end);
+                    expected.extend(i..end);
+                }
+                _ => {
+                    bits.set(i);
+                    expected.insert(i);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_fixed_bit_set() {
+        let mut rng = seeded_rng();
+        for &len in &[1, 63, 64, 65, 1000, 5000] {
+            let mut bits = FixedBitSet::new(len);
+            let mut expected = BTreeSet::new();
+            random_updates(&mut rng, &mut bits, &mut expected, len);
+            check(&bits, &expected, len);
+            for i in 0..len {
+                let prev = expected.range(..=i).next_back().map_or(-1, |&d| d as i32);
+                assert_eq!(bits.prev_set_bit(i), prev, "prev from {}", i);
+            }
+            check_iterator(&mut rng, Arc::new(bits), &expected);
+        }
+    }
+
+    #[test]
+    fn test_sparse_fixed_bit_set() {
+        let mut rng = seeded_rng();
+        for &len in &[1, 63, 64, 65, 4096, 4097, 20_000] {
+            let mut bits = SparseFixedBitSet::new(len);
+            let mut expected = BTreeSet::new();
+            check(&bits, &expected, len);
+            random_updates(&mut rng, &mut bits, &mut expected, len);
+            check(&bits, &expected, len);
+            for i in 0..len {
+                let prev = expected.range(..=i).next_back().map_or(-1, |&d| d as i32);
+                assert_eq!(bits.prev_set_bit(i), prev, "prev from {}", i);
+            }
+            check_iterator(&mut rng, Arc::new(bits), &expected);
+        }
+    }
+
+    #[test]
+    fn test_word_boundaries() {
+        let len = 3 * 4096;
+        let set = [0, 63, 64, 127, 4095, 4096, 8191, len - 1];
+        let mut fixed = FixedBitSet::new(len);
+        let mut sparse = SparseFixedBitSet::new(len);
+        for &i in &set {
+            fixed.set(i);
+            sparse.set(i);
+        }
+        let expected: BTreeSet<usize> = set.iter().cloned().collect();
+        check(&fixed, &expected, len);
+        check(&sparse, &expected, len);
+
+        sparse.clear_batch(63, 4097);
+        fixed.clear_batch(63, 4097);
+        let expected: BTreeSet<usize> = [0, 8191, len - 1].iter().cloned().collect();
+        check(&fixed, &expected, len);
+        check(&sparse, &expected, len);
+        assert_eq!(sparse.prev_set_bit(8190), 0);
+        assert_eq!(sparse.next_set_bit(1), 8191);
+    }
+
+    #[test]
+    fn test_fixed_bit_set_operations() {
+        let mut rng = seeded_rng();
+        let len = 1000;
+        let mut a = FixedBitSet::new(len);
+        let mut b = FixedBitSet::new(len / 2);
+        let mut expected_a = BTreeSet::new();
+        let mut expected_b = BTreeSet::new();
+        random_updates(&mut rng, &mut a, &mut expected_a, len);
+        random_updates(&mut rng, &mut b, &mut expected_b, len / 2);
+
+        let mut or = FixedBitSet::copy_from(a.bits.clone(), len).unwrap();
+        or.set_or(&b);
+        check(&or, &expected_a.union(&expected_b).cloned().collect(), len);
+
+        let mut and = FixedBitSet::copy_from(a.bits.clone(), len).unwrap();
+        and.set_and(&b);
+        check(
+            &and,
+            &expected_a.intersection(&expected_b).cloned().collect(),
+            len,
+        );
+        assert_eq!(a.intersects(&b), and.cardinality() > 0);
+
+        let mut and_not = FixedBitSet::copy_from(a.bits.clone(), len).unwrap();
+        and_not.set_and_not(&b);
+        check(
+            &and_not,
+            &expected_a.difference(&expected_b).cloned().collect(),
+            len,
+        );
+    }
+}
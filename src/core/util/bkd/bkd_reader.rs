@@ -404,6 +404,100 @@ impl BKDReader {
         Ok(())
     }
 
+    /// Estimates the number of points `intersect` would visit with `visitor`,
+    /// from the index only: the cells inside the query count as full leaves,
+    /// the leaves crossing it as half full.
+    pub fn estimate_point_count(&self, visitor: &mut impl IntersectVisitor) -> Result<i64> {
+        let mut state = self.create_intersect_state(visitor)?;
+        self.estimate_point_count_with_state(
+            &mut state,
+            &self.min_packed_value,
+            &self.max_packed_value,
+        )
+    }
+
+    fn estimate_point_count_with_state<'a, IV: IntersectVisitor + 'a>(
+        &self,
+        state: &mut IntersectState<'a, IV>,
+        cell_min_packed: &[u8],
+        cell_max_packed: &[u8],
+    ) -> Result<i64> {
+        let r = state.visitor.compare(cell_min_packed, cell_max_packed);
+        if r == Relation::CellOutsideQuery {
+            Ok(0)
+        } else if r == Relation::CellInsideQuery {
+            Ok(self.max_points_in_leaf_node as i64 * self.count_leaves(state)?)
+        } else if state.index_tree.is_leaf_node() {
+            if state.index_tree.node_exists() {
+                Ok((self.max_points_in_leaf_node as i64 + 1) / 2)
+            } else {
+                Ok(0)
+            }
+        } else {
+            // same recursion as `intersect_with_state`
+            let split_dim = state.index_tree.split_dim() as usize;
+            let split_packed_value_idx = state.index_tree.split_packed_value_index();
+            let mut split_dim_value = state.index_tree.split_dim_value();
+            let dim_start = split_dim * self.bytes_per_dim;
+            let dim_end = dim_start + self.bytes_per_dim;
+
+            state.index_tree.set_split_packed_value(
+                split_packed_value_idx,
+                0,
+                &cell_max_packed[0..self.packed_bytes_length],
+            );
+            state.index_tree.set_split_packed_value(
+                split_packed_value_idx,
+                dim_start,
+                &split_dim_value,
+            );
+            let mut split_packed_value = state.index_tree.split_packed_value();
+
+            state.index_tree.push_left()?;
+            let left =
+                self.estimate_point_count_with_state(state, cell_min_packed, &split_packed_value)?;
+            state.index_tree.pop();
+
+            split_dim_value[0..self.bytes_per_dim]
+                .copy_from_slice(&split_packed_value[dim_start..dim_end]);
+
+            split_packed_value[0..self.packed_bytes_length]
+                .copy_from_slice(&cell_min_packed[0..self.packed_bytes_length]);
+            split_packed_value[dim_start..dim_end]
+                .copy_from_slice(&split_dim_value[0..self.bytes_per_dim]);
+            state
+                .index_tree
+                .set_split_packed_value(split_packed_value_idx, 0, &split_packed_value);
+            state.index_tree.set_split_dim_value(&split_dim_value);
+
+            state.index_tree.push_right()?;
+            let right =
+                self.estimate_point_count_with_state(state, &split_packed_value, cell_max_packed)?;
+            state.index_tree.pop();
+
+            Ok(left + right)
+        }
+    }
+
+    // the number of leaves under the current node of the index
+    fn count_leaves<'a, IV: IntersectVisitor + 'a>(
+        &self,
+        state: &mut IntersectState<'a, IV>,
+    ) -> Result<i64> {
+        if state.index_tree.is_leaf_node() {
+            Ok(if state.index_tree.node_exists() { 1 } else { 0 })
+        } else {
+            state.index_tree.push_left()?;
+            let left = self.count_leaves(state)?;
+            state.index_tree.pop();
+
+            state.index_tree.push_right()?;
+            let right = self.count_leaves(state)?;
+            state.index_tree.pop();
+            Ok(left + right)
+        }
+    }
+
     /// Visits all docIDs and packed values in a single leaf block
     pub fn visit_leaf_block_values<'a, IV: IntersectVisitor + 'a>(
         &self,
@@ -14,7 +14,7 @@
 use core::codec::codec_util;
 use core::codec::MutablePointsReader;
 use core::codec::{INT_BYTES, LONG_BYTES};
-use core::index::{IntersectVisitor, LiveDocsDocMap, Relation};
+use core::index::{IntersectVisitor, LiveDocsDocMap, Relation, MAX_NUM_BYTES};
 use core::store::{
     DataOutput, Directory, GrowableByteArrayDataOutput, IndexOutput, RAMOutputStream,
     TrackingDirectoryWrapper,
@@ -24,7 +24,8 @@ use core::util::bit_util::UnsignedShift;
 use core::util::bkd::{
     bkd_reader::{MergeReader, StubIntersectVisitor},
     BKDReader, DocIdsWriter, HeapPointWriter, LongBitSet, MutablePointsReaderUtils,
-    OfflinePointWriter, PointReader, PointReaderEnum, PointType, PointWriter, PointWriterEnum,
+    OfflinePointReader, OfflinePointWriter, PointReader, PointReaderEnum, PointType, PointWriter,
+    PointWriterEnum,
 };
 use core::util::offline_sorter::{BufferSize, MAX_TEMP_FILES};
 use core::util::sorter::{check_range, MSBRadixSorter, MSBSorter, Sorter};
@@ -32,7 +33,7 @@ use core::util::string_util::bytes_subtract;
 use core::util::DocId;
 
 use std::borrow::Cow;
-use std::cmp::Ordering;
+use std::cmp::{Ordering, Reverse};
 use std::collections::BinaryHeap;
 use std::sync::Arc;
 
@@ -52,6 +53,10 @@ pub const DEFAULT_MAX_POINTS_IN_LEAF_NODE: i32 = 1024;
 pub const DEFAULT_MAX_MB_SORT_IN_HEAP: f32 = 1024.0f32;
 pub const MAX_DIMS: i32 = 8;
 
+// all the temp files are created through it, so that they can be deleted
+// when the writer fails
+type TempDir<D> = TrackingDirectoryWrapper<D, Arc<D>>;
+
 pub struct PathSlice<W: PointWriter> {
     writer: W,
     start: i64,
@@ -82,7 +87,9 @@ impl<'a, D: Directory, O: IndexOutput> OneDimIntersectVisitor<'a, D, O> {
 
 impl<'a, D: Directory, O: IndexOutput> IntersectVisitor for OneDimIntersectVisitor<'a, D, O> {
     fn visit(&mut self, _doc_id: DocId) -> Result<()> {
-        bail!(IllegalState("".into()))
+        bail!(IllegalState(
+            "AddPointsVisitor adds the points by their packed values".into()
+        ))
     }
 
     fn visit_by_packed_value(&mut self, doc_id: DocId, packed_value: &[u8]) -> Result<()> {
@@ -317,7 +324,7 @@ pub struct BKDWriter<D: Directory> {
     num_dims: usize,
     bytes_per_dim: usize,
     packed_bytes_length: usize,
-    temp_dir: TrackingDirectoryWrapper<D, Arc<D>>,
+    temp_dir: Arc<TempDir<D>>,
     temp_file_name_prefix: String,
     _max_mb_sort_in_heap: f64,
     scratch_diff: Vec<u8>,
@@ -329,7 +336,7 @@ pub struct BKDWriter<D: Directory> {
 
     docs_seen: FixedBitSet,
 
-    offline_point_writer: Option<OfflinePointWriter<D>>,
+    offline_point_writer: Option<OfflinePointWriter<TempDir<D>>>,
     heap_point_writer: Option<HeapPointWriter>,
 
     temp_input: Option<String>,
@@ -343,7 +350,7 @@ pub struct BKDWriter<D: Directory> {
     single_value_per_doc: bool,
 
     _offline_sorter_buffer_mb: BufferSize,
-    _offline_sorter_max_temp_files: i32,
+    offline_sorter_max_temp_files: i32,
     max_doc: i32,
 
     min_packed_value: Vec<u8>,
@@ -365,6 +372,7 @@ impl<D: Directory> BKDWriter<D> {
     ) -> Result<BKDWriter<D>> {
         Self::verify_params(
             num_dims,
+            bytes_per_dim,
             max_points_in_leaf_node,
             max_mb_sort_in_heap,
             total_point_count,
@@ -425,7 +433,7 @@ impl<D: Directory> BKDWriter<D> {
             num_dims,
             bytes_per_dim,
             packed_bytes_length,
-            temp_dir: TrackingDirectoryWrapper::new(temp_dir),
+            temp_dir: Arc::new(TrackingDirectoryWrapper::new(temp_dir)),
             temp_file_name_prefix: temp_file_name_prefix.to_string(),
             _max_mb_sort_in_heap: max_mb_sort_in_heap,
             scratch_diff: vec![0u8; bytes_per_dim],
@@ -443,7 +451,7 @@ impl<D: Directory> BKDWriter<D> {
             long_ords,
             single_value_per_doc,
             _offline_sorter_buffer_mb,
-            _offline_sorter_max_temp_files: MAX_TEMP_FILES,
+            offline_sorter_max_temp_files: MAX_TEMP_FILES,
             max_doc,
             min_packed_value: vec![0u8; packed_bytes_length],
             max_packed_value: vec![0u8; packed_bytes_length],
@@ -459,7 +467,7 @@ impl<D: Directory> BKDWriter<D> {
             )));
         }
 
-        if self.point_count > self.max_points_sort_in_heap as i64 {
+        if self.point_count >= self.max_points_sort_in_heap as i64 {
             if self.offline_point_writer.is_none() {
                 self.spill_to_offline()?;
             }
@@ -521,12 +529,13 @@ impl<D: Directory> BKDWriter<D> {
         if self.num_dims == 1 {
             self.write_field_1_dim(out, field_name, reader)
         } else {
-            unimplemented!()
+            self.write_field_n_dim(out, field_name, reader)
         }
     }
 
     pub fn verify_params(
         num_dims: usize,
+        bytes_per_dim: usize,
         max_points_in_leaf_node: i32,
         max_mb_sort_in_heap: f64,
         total_point_count: i64,
@@ -539,6 +548,12 @@ impl<D: Directory> BKDWriter<D> {
                 MAX_DIMS
             )));
         }
+        if bytes_per_dim < 1 || bytes_per_dim > MAX_NUM_BYTES as usize {
+            bail!(IllegalArgument(format!(
+                "bytes_per_dim must be 1 .. {}",
+                MAX_NUM_BYTES
+            )));
+        }
         if max_points_in_leaf_node <= 0 {
             bail!(IllegalArgument(
                 "max_points_in_leaf_node must be > 0".into()
@@ -560,7 +575,17 @@ impl<D: Directory> BKDWriter<D> {
         Ok(())
     }
 
+    /// Writes the tree of the added points to `out`, and returns the file
+    /// pointer of its index. The temp files are deleted, also on error.
     pub fn finish(&mut self, out: &mut impl IndexOutput) -> Result<i64> {
+        let res = self.finish_tree(out);
+        if res.is_err() {
+            self.delete_temp_files();
+        }
+        res
+    }
+
+    fn finish_tree(&mut self, out: &mut impl IndexOutput) -> Result<i64> {
         // TODO: specialize the 1D case?  it's much faster at indexing time (no partitioning on
         // recurse...)
 
@@ -611,7 +636,7 @@ impl<D: Directory> BKDWriter<D> {
         debug_assert!(self.point_count / num_leaves as i64 <= self.max_points_in_leaf_node as i64);
 
         // Sort all docs once by each dimension:
-        let mut sorted_point_writers: Vec<PathSlice<PointWriterEnum<D>>> = vec![];
+        let mut sorted_point_writers: Vec<PathSlice<PointWriterEnum<TempDir<D>>>> = vec![];
         // This is only used on exception; on normal code paths we close all files we opened:
         let mut to_close_heroically = vec![];
         let mut _success = false;
@@ -667,9 +692,10 @@ impl<D: Directory> BKDWriter<D> {
         Ok(index_fp)
     }
 
-    /// More efficient bulk-add for incoming {@link BKDReader}s.  This does a merge sort
-    /// of the already sorted values and currently only works when numDims==1.  This
-    /// returns -1 if all documents containing dimensional values were deleted.
+    /// More efficient bulk-add for incoming {@link BKDReader}s.  In the 1D case this
+    /// does a merge sort of the already sorted values, otherwise the live points are
+    /// added and sorted again, offline if they don't fit in heap.  This returns -1 if
+    /// all documents containing dimensional values were deleted.
     pub fn merge(
         &mut self,
         output: &mut impl IndexOutput,
@@ -678,6 +704,29 @@ impl<D: Directory> BKDWriter<D> {
     ) -> Result<i64> {
         debug_assert!(doc_maps.is_empty() || readers.len() == doc_maps.len());
 
+        if self.num_dims > 1 {
+            let packed_bytes_length = self.packed_bytes_length;
+            for i in 0..readers.len() {
+                let doc_map = if doc_maps.is_empty() {
+                    None
+                } else {
+                    Some(doc_maps[i])
+                };
+                let mut visitor = StubIntersectVisitor::default();
+                let mut reader = MergeReader::new(readers[i], doc_map, &mut visitor)?;
+                while reader.next()? {
+                    self.add(
+                        &reader.state.scratch_packed_value[..packed_bytes_length],
+                        reader.doc_id,
+                    )?;
+                }
+            }
+            if self.point_count == 0 {
+                return Ok(-1);
+            }
+            return self.finish(output);
+        }
+
         let mut stub_visitors = vec![StubIntersectVisitor::default(); readers.len()];
         let mut sub_vps: Vec<*mut StubIntersectVisitor> = stub_visitors
             .iter_mut()
@@ -729,20 +778,19 @@ impl<D: Directory> BKDWriter<D> {
         // For each .add we just append to this input file, then in .finish we sort this input and
         // resursively build the tree:
         self.offline_point_writer = Some(OfflinePointWriter::prefix_new(
-            Arc::clone(&self.temp_dir.directory),
+            Arc::clone(&self.temp_dir),
             &self.temp_file_name_prefix,
             self.packed_bytes_length,
             self.long_ords,
             "spill",
             0,
             self.single_value_per_doc,
-        ));
+        )?);
 
         let input = self
             .offline_point_writer
             .as_ref()
             .unwrap()
-            .output()
             .name()
             .to_string();
         self.temp_input = Some(input);
@@ -794,6 +842,24 @@ impl<D: Directory> BKDWriter<D> {
         one_dim_writer.finish()
     }
 
+    // The points are added as they come, and sorted by each dimension in
+    // `finish`, offline if they don't fit in heap.
+    fn write_field_n_dim(
+        &mut self,
+        out: &mut impl IndexOutput,
+        field_name: &str,
+        reader: impl MutablePointsReader,
+    ) -> Result<i64> {
+        {
+            let mut visitor = AddPointsVisitor { writer: self };
+            reader.intersect(field_name, &mut visitor)?;
+        }
+        if self.point_count == 0 {
+            return Ok(-1);
+        }
+        self.finish(out)
+    }
+
     fn rotate_to_tree(
         &self,
         node_id: i32,
@@ -1363,7 +1429,7 @@ impl<D: Directory> BKDWriter<D> {
         &mut self,
         node_id: i32,
         leaf_node_offset: i32,
-        slices: &mut Vec<PathSlice<PointWriterEnum<D>>>,
+        slices: &mut Vec<PathSlice<PointWriterEnum<TempDir<D>>>>,
         ord_bitset: &mut Option<LongBitSet>,
         out: &mut impl IndexOutput,
         min_packed_value: &mut Vec<u8>,
@@ -1383,6 +1449,7 @@ impl<D: Directory> BKDWriter<D> {
         {
             // Special case for 1D, to cutover to heap once we recurse deeply enough:
             let p = self.switch_to_heap(&mut slices[0], to_close_heroically)?;
+            slices[0].writer.destory()?;
             slices[0] = p;
         }
 
@@ -1400,6 +1467,7 @@ impl<D: Directory> BKDWriter<D> {
                     // such that we started offline, but then kept splitting
                     // only in one dimension, and so never had to rewrite into heap writer
                     let p = self.switch_to_heap(&mut slices[dim], to_close_heroically)?;
+                    slices[dim].writer.destory()?;
                     slices[dim] = p;
                 }
 
@@ -1427,9 +1495,9 @@ impl<D: Directory> BKDWriter<D> {
                     let mut cardinality = 1;
                     let mut previous = self.scratch1[offset + prefix];
 
-                    for _i in 1..source.count {
+                    for i in 1..source.count {
                         heap_source
-                            .read_packed_value(source.start as usize + 1, &mut self.scratch2);
+                            .read_packed_value((source.start + i) as usize, &mut self.scratch2);
                         let b = self.scratch2[offset + prefix];
                         debug_assert!(previous <= b);
                         if b != previous {
@@ -1505,18 +1573,12 @@ impl<D: Directory> BKDWriter<D> {
         } else {
             // Inner node: partition/recurse
             let split_dim = if self.num_dims > 1 {
-                let min_packed_value = (&self.min_packed_value) as (*const Vec<u8>);
-                let max_packed_value = (&self.max_packed_value) as (*const Vec<u8>);
-                self.split(
-                    unsafe { &(*min_packed_value) },
-                    unsafe { &(*max_packed_value) },
-                    parent_splits,
-                )
+                self.split(min_packed_value, max_packed_value, parent_splits)
             } else {
                 0
             };
 
-            let source = (&slices[split_dim]) as (*const PathSlice<PointWriterEnum<D>>);
+            let source = (&slices[split_dim]) as (*const PathSlice<PointWriterEnum<TempDir<D>>>);
             let source = unsafe { &(*source) };
             debug_assert!((node_id as usize) < split_packed_values.len());
 
@@ -1585,9 +1647,10 @@ impl<D: Directory> BKDWriter<D> {
                     to_close_heroically,
                 )?;
 
-                let mut left_point_writer = self.point_writer(left_count, &format!("left{}", dim));
+                let mut left_point_writer =
+                    self.point_writer(left_count, &format!("left{}", dim))?;
                 let mut right_point_writer =
-                    self.point_writer(source.count - left_count, &format!("right{}", dim));
+                    self.point_writer(source.count - left_count, &format!("right{}", dim))?;
                 let next_right_count = reader.split(
                     source.count,
                     ord_bitset.as_mut().unwrap(),
@@ -1595,6 +1658,8 @@ impl<D: Directory> BKDWriter<D> {
                     &mut right_point_writer,
                     dim as i32 == dim_to_clear,
                 )?;
+                left_point_writer.close()?;
+                right_point_writer.close()?;
 
                 if right_count != next_right_count {
                     bail!(IllegalState(format!(
@@ -1623,12 +1688,10 @@ impl<D: Directory> BKDWriter<D> {
                 to_close_heroically,
             )?;
 
-            for dim in 0..self.num_dims as usize {
-                // Don't destroy the dim we split on because we just re-used what our caller above
-                // gave us for that dim:
-                if dim != split_dim as usize {
-                    left_slices[dim].writer.destory()?;
-                }
+            // the dim we split on shares the file of our caller, which is
+            // deleted once its last writer is destroyed
+            for slice in &mut left_slices {
+                slice.writer.destory()?;
             }
 
             // TODO: we could "tail recurse" here?  have our parent discard its refs as we recurse
@@ -1647,12 +1710,10 @@ impl<D: Directory> BKDWriter<D> {
                 to_close_heroically,
             )?;
 
-            for dim in 0..self.num_dims as usize {
-                // Don't destroy the dim we split on because we just re-used what our caller above
-                // gave us for that dim:
-                if dim != split_dim as usize {
-                    right_slices[dim].writer.destory()?;
-                }
+            // the dim we split on shares the file of our caller, which is
+            // deleted once its last writer is destroyed
+            for slice in &mut right_slices {
+                slice.writer.destory()?;
             }
 
             parent_splits[split_dim as usize] -= 1;
@@ -1665,7 +1726,7 @@ impl<D: Directory> BKDWriter<D> {
         &self,
         right_count: i64,
         split_dim: i32,
-        source: &PathSlice<PointWriterEnum<D>>,
+        source: &PathSlice<PointWriterEnum<TempDir<D>>>,
         ord_bitset: &mut Option<LongBitSet>,
     ) -> Result<Vec<u8>> {
         // Now we mark ords that fall into the right half, so we can partition on all other dims
@@ -1696,25 +1757,25 @@ impl<D: Directory> BKDWriter<D> {
         Ok(scratch)
     }
 
-    fn point_writer(&self, count: i64, desc: &str) -> PointWriterEnum<D> {
+    fn point_writer(&self, count: i64, desc: &str) -> Result<PointWriterEnum<TempDir<D>>> {
         if count < self.max_points_sort_in_heap as i64 {
-            PointWriterEnum::Heap(HeapPointWriter::new(
+            Ok(PointWriterEnum::Heap(HeapPointWriter::new(
                 count as usize,
                 count as usize,
                 self.packed_bytes_length,
                 self.long_ords,
                 self.single_value_per_doc,
-            ))
+            )))
         } else {
-            PointWriterEnum::Offline(OfflinePointWriter::prefix_new(
-                Arc::clone(&self.temp_dir.directory),
+            Ok(PointWriterEnum::Offline(OfflinePointWriter::prefix_new(
+                Arc::clone(&self.temp_dir),
                 &self.temp_file_name_prefix,
                 self.packed_bytes_length,
                 self.long_ords,
                 desc,
                 count,
                 self.single_value_per_doc,
-            ))
+            )?))
         }
     }
 
@@ -1769,7 +1830,7 @@ impl<D: Directory> BKDWriter<D> {
         &self,
         source: &mut PathSlice<W>,
         to_close_heroically: &mut Vec<PointReaderEnum>,
-    ) -> Result<PathSlice<PointWriterEnum<D>>> {
+    ) -> Result<PathSlice<PointWriterEnum<TempDir<D>>>> {
         let count = source.count;
         // Not inside the try because we don't want to close it here:
         let reader = source.writer.shared_point_reader(
@@ -1806,10 +1867,10 @@ impl<D: Directory> BKDWriter<D> {
 
         let mut msb_sorter = MSBRadixSorter::new(max_length, intro_sorter);
 
-        msb_sorter.sort(0, self.point_count as i32);
+        msb_sorter.sort(0, writer.next_write as i32);
     }
 
-    fn sort(&mut self, dim: i32) -> Result<PointWriterEnum<D>> {
+    fn sort(&mut self, dim: i32) -> Result<PointWriterEnum<TempDir<D>>> {
         debug_assert!(dim >= 0 && dim < self.num_dims as i32);
 
         if self.heap_point_writer.is_some() {
@@ -1830,21 +1891,174 @@ impl<D: Directory> BKDWriter<D> {
             return Ok(PointWriterEnum::Heap(sorted));
         } else {
             // Offline sort:
-            unimplemented!()
+            debug_assert!(self.temp_input.is_some());
+            let temp_input = self.temp_input.clone().unwrap();
+            let mut runs = self.write_sorted_runs(&temp_input, dim)?;
+            // merge the runs, at most max_temp_files at a time
+            let max_temp_files = self.offline_sorter_max_temp_files.max(2) as usize;
+            while runs.len() > max_temp_files {
+                let merged = self.merge_sorted_runs(&mut runs[..max_temp_files], dim)?;
+                runs.drain(..max_temp_files);
+                runs.push(merged);
+            }
+            let sorted = self.merge_sorted_runs(&mut runs, dim)?;
+            Ok(PointWriterEnum::Offline(sorted))
         }
     }
+
+    // Radix sorts the points of `temp_input` by `dim` in runs of
+    // `max_points_sort_in_heap` points, each written to its own temp file.
+    fn write_sorted_runs(
+        &mut self,
+        temp_input: &str,
+        dim: i32,
+    ) -> Result<Vec<OfflinePointWriter<TempDir<D>>>> {
+        let mut reader = OfflinePointReader::new(
+            self.temp_dir.as_ref(),
+            temp_input,
+            self.packed_bytes_length as i32,
+            0,
+            self.point_count as usize,
+            self.long_ords,
+            self.single_value_per_doc,
+        )?;
+        let mut runs = vec![];
+        let mut left = self.point_count;
+        while left > 0 {
+            let count = left.min(self.max_points_sort_in_heap as i64) as usize;
+            let mut heap = HeapPointWriter::new(
+                count,
+                count,
+                self.packed_bytes_length,
+                self.long_ords,
+                self.single_value_per_doc,
+            );
+            for _ in 0..count {
+                if !reader.next()? {
+                    bail!(IllegalState(format!(
+                        "temp file {} has less than {} points",
+                        temp_input, self.point_count
+                    )));
+                }
+                heap.append(reader.packed_value(), reader.ord(), reader.doc_id())?;
+            }
+            self.sort_heap_point_writer(&mut heap, dim);
+
+            let mut run = OfflinePointWriter::prefix_new(
+                Arc::clone(&self.temp_dir),
+                &self.temp_file_name_prefix,
+                self.packed_bytes_length,
+                self.long_ords,
+                &format!("sort{}", dim),
+                count as i64,
+                self.single_value_per_doc,
+            )?;
+            let mut heap_reader = heap.point_reader(0, count)?;
+            while heap_reader.next()? {
+                run.append(
+                    heap_reader.packed_value(),
+                    heap_reader.ord(),
+                    heap_reader.doc_id(),
+                )?;
+            }
+            run.close()?;
+            runs.push(run);
+            left -= count as i64;
+        }
+        Ok(runs)
+    }
+
+    // Merges sorted runs into a new one, the merged runs are deleted.
+    fn merge_sorted_runs(
+        &self,
+        runs: &mut [OfflinePointWriter<TempDir<D>>],
+        dim: i32,
+    ) -> Result<OfflinePointWriter<TempDir<D>>> {
+        let count: i64 = runs.iter().map(|r| r.count()).sum();
+        let mut merged = OfflinePointWriter::prefix_new(
+            Arc::clone(&self.temp_dir),
+            &self.temp_file_name_prefix,
+            self.packed_bytes_length,
+            self.long_ords,
+            &format!("sort{}", dim),
+            count,
+            self.single_value_per_doc,
+        )?;
+
+        // the points are ordered by their bytes in `dim`, then by doc id and
+        // by ord, so equal points come out in the same order from any run
+        let offset = dim as usize * self.bytes_per_dim;
+        let sort_key = |reader: &PointReaderEnum| {
+            let mut key = Vec::with_capacity(self.bytes_per_dim + 12);
+            key.extend_from_slice(&reader.packed_value()[offset..offset + self.bytes_per_dim]);
+            key.extend_from_slice(&(reader.doc_id() as u32).to_be_bytes());
+            key.extend_from_slice(&(reader.ord() as u64).to_be_bytes());
+            key
+        };
+        let mut readers = Vec::with_capacity(runs.len());
+        let mut queue = BinaryHeap::with_capacity(runs.len());
+        for (i, run) in runs.iter().enumerate() {
+            let mut reader = run.point_reader(0, run.count() as usize)?;
+            if reader.next()? {
+                queue.push(Reverse((sort_key(&reader), i)));
+            }
+            readers.push(reader);
+        }
+        while let Some(Reverse((_, i))) = queue.pop() {
+            {
+                let reader = &readers[i];
+                merged.append(reader.packed_value(), reader.ord(), reader.doc_id())?;
+            }
+            if readers[i].next()? {
+                queue.push(Reverse((sort_key(&readers[i]), i)));
+            }
+        }
+        merged.close()?;
+        drop(readers);
+
+        for run in runs.iter_mut() {
+            run.destory()?;
+        }
+        Ok(merged)
+    }
+
+    // Deletes the temp files left by an error, their outputs are released
+    // first.
+    fn delete_temp_files(&mut self) {
+        self.offline_point_writer = None;
+        self.temp_input = None;
+        for name in self.temp_dir.create_files() {
+            let _ = self.temp_dir.delete_file(&name);
+        }
+    }
+}
+
+// adds the points of a `MutablePointsReader` to a `BKDWriter`
+struct AddPointsVisitor<'a, D: Directory> {
+    writer: &'a mut BKDWriter<D>,
+}
+
+impl<'a, D: Directory> IntersectVisitor for AddPointsVisitor<'a, D> {
+    fn visit(&mut self, _doc_id: DocId) -> Result<()> {
+        bail!(IllegalState(
+            "AddPointsVisitor adds the points by their packed values".into()
+        ))
+    }
+
+    fn visit_by_packed_value(&mut self, doc_id: DocId, packed_value: &[u8]) -> Result<()> {
+        self.writer.add(packed_value, doc_id)
+    }
+
+    fn compare(&self, _min_packed_value: &[u8], _max_packed_value: &[u8]) -> Relation {
+        Relation::CellCrossesQuery
+    }
 }
 
 impl<D: Directory> Drop for BKDWriter<D> {
     fn drop(&mut self) {
-        if self.temp_input.is_some() {
-            // NOTE: this should only happen on exception, e.g. caller calls close w/o calling
-            // finish:
-            match self.temp_dir.delete_file(self.temp_input.as_ref().unwrap()) {
-                _ => {}
-            }
-            self.temp_input = None;
-        }
+        // NOTE: there are temp files left only on error, e.g. the caller drops
+        // the writer w/o calling finish
+        self.delete_temp_files();
     }
 }
 
@@ -2006,3 +2220,321 @@ impl<D: Directory> Sorter for BKDWriterMSBIntroSorter<D> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    extern crate rand;
+
+    use super::*;
+    use core::store::{IOContext, RAMDirectory};
+    use core::util::packed::{
+        PackedLongValuesBuilder, PackedLongValuesBuilderType, DEFAULT_PAGE_SIZE,
+    };
+    use core::util::packed_misc::COMPACT;
+    use core::util::tests::seeded_rng;
+
+    use self::rand::Rng;
+
+    const NUM_DIMS: usize = 2;
+    const BYTES_PER_DIM: usize = 4;
+    const MAX_POINTS_IN_LEAF_NODE: i32 = 64;
+
+    // collects the docs of the points in a box
+    struct BoxVisitor {
+        min: Vec<u8>,
+        max: Vec<u8>,
+        docs: Vec<DocId>,
+    }
+
+    impl BoxVisitor {
+        fn new(min: Vec<u8>, max: Vec<u8>) -> BoxVisitor {
+            BoxVisitor {
+                min,
+                max,
+                docs: vec![],
+            }
+        }
+
+        fn contains(&self, packed_value: &[u8]) -> bool {
+            (0..NUM_DIMS).all(|dim| {
+                let range = dim * BYTES_PER_DIM..(dim + 1) * BYTES_PER_DIM;
+                packed_value[range.clone()] >= self.min[range.clone()]
+                    && packed_value[range.clone()] <= self.max[range]
+            })
+        }
+    }
+
+    impl IntersectVisitor for BoxVisitor {
+        fn visit(&mut self, doc_id: DocId) -> Result<()> {
+            self.docs.push(doc_id);
+            Ok(())
+        }
+
+        fn visit_by_packed_value(&mut self, doc_id: DocId, packed_value: &[u8]) -> Result<()> {
+            if self.contains(packed_value) {
+                self.docs.push(doc_id);
+            }
+            Ok(())
+        }
+
+        fn compare(&self, min_packed_value: &[u8], max_packed_value: &[u8]) -> Relation {
+            let mut crosses = false;
+            for dim in 0..NUM_DIMS {
+                let range = dim * BYTES_PER_DIM..(dim + 1) * BYTES_PER_DIM;
+                if max_packed_value[range.clone()] < self.min[range.clone()]
+                    || min_packed_value[range.clone()] > self.max[range.clone()]
+                {
+                    return Relation::CellOutsideQuery;
+                }
+                crosses |= min_packed_value[range.clone()] < self.min[range.clone()]
+                    || max_packed_value[range.clone()] > self.max[range];
+            }
+            if crosses {
+                Relation::CellCrossesQuery
+            } else {
+                Relation::CellInsideQuery
+            }
+        }
+    }
+
+    fn random_point(rng: &mut impl Rng) -> Vec<u8> {
+        // a small range so that there are duplicate values
+        (0..NUM_DIMS)
+            .flat_map(|_| rng.gen_range(0u32, 100_000).to_be_bytes().to_vec())
+            .collect()
+    }
+
+    fn new_writer(
+        dir: &Arc<RAMDirectory>,
+        max_doc: usize,
+        max_mb_sort_in_heap: f64,
+        total_point_count: usize,
+        single_value_per_doc: bool,
+    ) -> BKDWriter<RAMDirectory> {
+        BKDWriter::new(
+            max_doc as i32,
+            Arc::clone(dir),
+            "_0",
+            NUM_DIMS,
+            BYTES_PER_DIM,
+            MAX_POINTS_IN_LEAF_NODE,
+            max_mb_sort_in_heap,
+            total_point_count as i64,
+            single_value_per_doc,
+        )
+        .unwrap()
+    }
+
+    fn finish(dir: &Arc<RAMDirectory>, name: &str, writer: &mut BKDWriter<RAMDirectory>) -> i64 {
+        let mut out = dir.create_output(name, &IOContext::Default).unwrap();
+        writer.finish(&mut out).unwrap()
+    }
+
+    fn open_reader(dir: &Arc<RAMDirectory>, name: &str, index_fp: i64) -> BKDReader {
+        let mut input = dir.open_input(name, &IOContext::READ).unwrap();
+        input.seek(index_fp).unwrap();
+        BKDReader::new(Arc::from(input)).unwrap()
+    }
+
+    // compares the docs found in random boxes with the points of each doc
+    fn assert_intersect(reader: &BKDReader, points: &[(DocId, Vec<u8>)]) {
+        let mut rng = seeded_rng();
+        for _ in 0..20 {
+            let (mut min, mut max) = (vec![], vec![]);
+            for _ in 0..NUM_DIMS {
+                let a = rng.gen_range(0u32, 100_000);
+                let b = rng.gen_range(0u32, 100_000);
+                min.extend_from_slice(&a.min(b).to_be_bytes());
+                max.extend_from_slice(&a.max(b).to_be_bytes());
+            }
+            let mut visitor = BoxVisitor::new(min, max);
+            reader.intersect(&mut visitor).unwrap();
+            let mut actual = visitor.docs.clone();
+            actual.sort();
+            actual.dedup();
+
+            let mut expected: Vec<DocId> = points
+                .iter()
+                .filter(|(_, p)| visitor.contains(p))
+                .map(|(doc, _)| *doc)
+                .collect();
+            expected.sort();
+            expected.dedup();
+            assert_eq!(actual, expected);
+        }
+    }
+
+    // indexes two points per doc, sorted offline in at least `min_runs` runs
+    fn check_offline_sort(num_docs: usize, max_mb_sort_in_heap: f64, min_runs: usize) {
+        let mut rng = seeded_rng();
+        let points: Vec<(DocId, Vec<u8>)> = (0..2 * num_docs)
+            .map(|i| ((i / 2) as DocId, random_point(&mut rng)))
+            .collect();
+
+        let dir = Arc::new(RAMDirectory::new());
+        let mut writer = new_writer(&dir, num_docs, max_mb_sort_in_heap, points.len(), false);
+        assert!((writer.max_points_sort_in_heap as usize) * min_runs < points.len());
+        for (doc, point) in &points {
+            writer.add(point, *doc).unwrap();
+        }
+        assert!(writer.temp_input.is_some());
+        let index_fp = finish(&dir, "points", &mut writer);
+        // all the temp files are gone
+        assert_eq!(dir.list_all().unwrap(), vec!["points".to_string()]);
+
+        let reader = open_reader(&dir, "points", index_fp);
+        assert_eq!(reader.point_count, points.len() as i64);
+        assert_eq!(reader.doc_count, num_docs as i32);
+        assert_intersect(&reader, &points);
+    }
+
+    #[test]
+    fn test_offline_sort() {
+        // a heap budget of ~800 points, so that the points are sorted in more
+        // runs than merged at once
+        check_offline_sort(10_000, 0.05, 10);
+    }
+
+    #[test]
+    fn test_offline_sort_large() {
+        // 200K points under a heap budget of 1MB, ~16K points per run
+        check_offline_sort(100_000, 1.0, 8);
+    }
+
+    #[test]
+    fn test_merge() {
+        let mut rng = seeded_rng();
+        let num_docs = 3_000;
+        let points: Vec<(DocId, Vec<u8>)> = (0..num_docs)
+            .map(|i| (i as DocId, random_point(&mut rng)))
+            .collect();
+
+        let dir = Arc::new(RAMDirectory::new());
+        let mut readers = vec![];
+        for (i, half) in points.chunks(num_docs / 2).enumerate() {
+            let mut writer = new_writer(&dir, num_docs, 0.05, half.len(), true);
+            for (doc, point) in half {
+                writer.add(point, *doc).unwrap();
+            }
+            let name = format!("points{}", i);
+            let index_fp = finish(&dir, &name, &mut writer);
+            readers.push(open_reader(&dir, &name, index_fp));
+        }
+
+        let mut writer = new_writer(&dir, num_docs, 0.05, num_docs, true);
+        let index_fp = {
+            let mut out = dir.create_output("merged", &IOContext::Default).unwrap();
+            writer
+                .merge(&mut out, vec![], readers.iter().collect())
+                .unwrap()
+        };
+        let reader = open_reader(&dir, "merged", index_fp);
+        assert_eq!(reader.point_count, num_docs as i64);
+        assert_intersect(&reader, &points);
+    }
+
+    // maps the docs of a segment past the ones of the previous segments,
+    // dropping the docs whose number is a multiple of 3
+    fn deletion_doc_map(max_doc: usize, doc_base: DocId) -> LiveDocsDocMap {
+        let mut live_docs = FixedBitSet::new(max_doc);
+        let mut builder = PackedLongValuesBuilder::new(
+            DEFAULT_PAGE_SIZE,
+            COMPACT,
+            PackedLongValuesBuilderType::Monotonic,
+        );
+        let mut deleted = 0;
+        for doc in 0..max_doc {
+            builder.add((doc - deleted) as i64);
+            if doc % 3 == 0 {
+                deleted += 1;
+            } else {
+                live_docs.set(doc);
+            }
+        }
+        LiveDocsDocMap::new(Arc::new(live_docs), builder.build(), doc_base)
+    }
+
+    #[test]
+    fn test_merge_with_doc_maps() {
+        let mut rng = seeded_rng();
+        let max_doc = 1_500;
+        let dir = Arc::new(RAMDirectory::new());
+        let mut readers = vec![];
+        let mut doc_maps = vec![];
+        let mut merged_points = vec![];
+        let mut doc_base = 0;
+        for i in 0..2 {
+            let mut writer = new_writer(&dir, max_doc, 0.05, max_doc, true);
+            for doc in 0..max_doc {
+                let point = random_point(&mut rng);
+                writer.add(&point, doc as DocId).unwrap();
+                if doc % 3 != 0 {
+                    merged_points.push((doc_base + (doc - doc / 3 - 1) as DocId, point));
+                }
+            }
+            let name = format!("points{}", i);
+            let index_fp = finish(&dir, &name, &mut writer);
+            readers.push(open_reader(&dir, &name, index_fp));
+            doc_maps.push(deletion_doc_map(max_doc, doc_base));
+            doc_base += (max_doc - (max_doc + 2) / 3) as DocId;
+        }
+
+        let num_docs = doc_base as usize;
+        let mut writer = new_writer(&dir, num_docs, 0.05, num_docs, true);
+        let index_fp = {
+            let mut out = dir.create_output("merged", &IOContext::Default).unwrap();
+            writer
+                .merge(
+                    &mut out,
+                    doc_maps.iter().collect(),
+                    readers.iter().collect(),
+                )
+                .unwrap()
+        };
+        let reader = open_reader(&dir, "merged", index_fp);
+        assert_eq!(reader.point_count, merged_points.len() as i64);
+        assert_eq!(reader.doc_count, num_docs as i32);
+        assert_intersect(&reader, &merged_points);
+    }
+
+    #[test]
+    fn test_temp_files_deleted_on_error() {
+        let mut rng = seeded_rng();
+        let dir = Arc::new(RAMDirectory::new());
+        {
+            let mut writer = new_writer(&dir, 4_000, 0.05, 3_000, true);
+            for doc in 0..3_000 {
+                writer.add(&random_point(&mut rng), doc).unwrap();
+            }
+            assert!(writer.temp_input.is_some());
+            // one point more than announced
+            assert!(writer.add(&random_point(&mut rng), 3_000).is_err());
+        }
+        assert!(dir.list_all().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_verify_params() {
+        let dir = Arc::new(RAMDirectory::new());
+        let new = |num_dims, bytes_per_dim| {
+            BKDWriter::new(
+                10,
+                Arc::clone(&dir),
+                "_0",
+                num_dims,
+                bytes_per_dim,
+                MAX_POINTS_IN_LEAF_NODE,
+                1.0,
+                10,
+                true,
+            )
+            .is_ok()
+        };
+        assert!(new(1, 1));
+        assert!(new(8, 16));
+        assert!(!new(0, 4));
+        assert!(!new(9, 4));
+        assert!(!new(2, 0));
+        assert!(!new(2, 17));
+    }
+}
@@ -168,7 +168,7 @@ impl PointReader for OfflinePointReader {
         }
 
         self.doc_id = self.input.read_int()?;
-        if self.single_value_per_doc {
+        if !self.single_value_per_doc {
             if self.long_ords {
                 self.ord = self.input.read_long()?;
             } else {
@@ -202,7 +202,7 @@ impl PointReader for OfflinePointReader {
             );
         }
 
-        let mut fp = self.input.file_pointer() + self.packed_value.capacity() as i64;
+        let mut fp = self.input.file_pointer() + self.packed_value.len() as i64;
         if !self.single_value_per_doc {
             fp += INT_BYTES as i64;
         }
@@ -238,9 +238,9 @@ impl PointReader for OfflinePointReader {
         // We specialize the offline -> offline split since the default impl
         // is somewhat wasteful otherwise (e.g. decoding docID when we don't
         // need to)
-        let packed_bytes_length = self.packed_value.capacity();
+        let packed_bytes_length = self.packed_value.len();
         let mut bytes_per_doc = packed_bytes_length + INT_BYTES as usize;
-        if self.single_value_per_doc {
+        if !self.single_value_per_doc {
             if self.long_ords {
                 bytes_per_doc += LONG_BYTES as usize;
             } else {
@@ -285,7 +285,7 @@ impl PointReader for OfflinePointReader {
         }
 
         right.set_count(right_count as i64);
-        left.set_count((count_start - right_count) as i64);
+        left.set_count((count - right_count) as i64);
 
         Ok(right_count)
     }
@@ -305,6 +305,9 @@ pub struct OfflinePointWriter<D: Directory> {
     closed: bool,
     temp_file_name_prefix: String,
     desc: String,
+    // shared with the writers returned by `clone`, the last one destroyed
+    // deletes the file, `None` once destroyed
+    handle: Option<Arc<()>>,
 }
 
 impl<D: Directory> OfflinePointWriter<D> {
@@ -330,6 +333,7 @@ impl<D: Directory> OfflinePointWriter<D> {
             closed: false,
             temp_file_name_prefix: "".to_string(),
             desc: "".to_string(),
+            handle: Some(Arc::new(())),
         }
     }
 
@@ -347,18 +351,16 @@ impl<D: Directory> OfflinePointWriter<D> {
         desc: &str,
         expected_count: i64,
         single_value_per_doc: bool,
-    ) -> OfflinePointWriter<D> {
-        let output = temp_dir
-            .create_temp_output(
-                temp_file_name_prefix,
-                &format!("bkd_{}", desc),
-                &IOContext::Default,
-            )
-            .unwrap();
+    ) -> Result<OfflinePointWriter<D>> {
+        let output = temp_dir.create_temp_output(
+            temp_file_name_prefix,
+            &format!("bkd_{}", desc),
+            &IOContext::Default,
+        )?;
 
         let name = output.name().to_string();
 
-        OfflinePointWriter {
+        Ok(OfflinePointWriter {
             temp_dir,
             output: Some(output),
             name,
@@ -369,10 +371,19 @@ impl<D: Directory> OfflinePointWriter<D> {
             shared_reader: None,
             next_shared_read: 0,
             expected_count,
-            closed: true,
+            closed: false,
             temp_file_name_prefix: temp_file_name_prefix.to_string(),
             desc: desc.to_string(),
-        }
+            handle: Some(Arc::new(())),
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn count(&self) -> i64 {
+        self.count
     }
 }
 
@@ -400,12 +411,18 @@ impl<D: Directory> PointWriter for OfflinePointWriter<D> {
     }
 
     fn destory(&mut self) -> Result<()> {
-        if self.shared_reader.is_some() {
-            debug_assert!(self.next_shared_read == self.count);
-            self.shared_reader = None;
+        debug_assert!(self.next_shared_read <= self.count);
+        self.shared_reader = None;
+        // an output left open by an error must be released to delete the file
+        self.output = None;
+
+        match self.handle.take() {
+            // the clones still reading the file delete it when destroyed
+            Some(handle) if Arc::strong_count(&handle) == 1 => {
+                self.temp_dir.delete_file(&self.name)
+            }
+            _ => Ok(()),
         }
-
-        self.temp_dir.delete_file(&self.name)
     }
 
     fn point_reader(&self, start: usize, length: usize) -> Result<PointReaderEnum> {
@@ -431,19 +448,21 @@ impl<D: Directory> PointWriter for OfflinePointWriter<D> {
         _to_close: &mut Vec<PointReaderEnum>,
     ) -> Result<&mut PointReaderEnum> {
         if self.shared_reader.is_none() {
-            debug_assert!(start == 0 && length as i64 <= self.count);
+            // the slices of a writer shared by `clone` start anywhere in the file
+            debug_assert!((start + length) as i64 <= self.count);
             let shared_reader = PointReaderEnum::Offline(OfflinePointReader::new(
                 self.temp_dir.as_ref(),
                 &self.name,
                 self.packed_bytes_length as i32,
-                0,
-                self.count as usize,
+                start,
+                self.count as usize - start,
                 self.long_ords,
                 self.single_value_per_doc,
             )?);
 
             //_to_close.push(shared_reader);
             self.shared_reader = Some(shared_reader);
+            self.next_shared_read = start as i64;
 
         // debug_assert!(self.shared_reader.as_ref().unwrap().as_ref().is_checksum);
         } else {
@@ -471,40 +490,35 @@ impl<D: Directory> PointWriter for OfflinePointWriter<D> {
     fn close(&mut self) -> Result<()> {
         if !self.closed {
             debug_assert!(self.shared_reader.is_none());
-            let output = self.output.as_mut().unwrap();
-            codec_util::write_footer(output)?;
+            if let Some(mut output) = self.output.take() {
+                codec_util::write_footer(&mut output)?;
+            }
             self.closed = true;
         }
 
         Ok(())
     }
 
+    /// Returns a writer reading the same file, which must be closed. The
+    /// file is deleted by the last of them to be destroyed, so each must be
+    /// destroyed with `destory`.
     fn clone(&self) -> Self {
-        let temp_dir = Arc::clone(&self.temp_dir);
-        let output = temp_dir
-            .create_temp_output(
-                &self.temp_file_name_prefix,
-                &format!("bkd_{}", self.desc),
-                &IOContext::Default,
-            )
-            .unwrap();
-
-        let name = output.name().to_string();
-
+        debug_assert!(self.closed);
         OfflinePointWriter {
-            temp_dir,
-            output: Some(output),
-            name,
+            temp_dir: Arc::clone(&self.temp_dir),
+            output: None,
+            name: self.name.clone(),
             packed_bytes_length: self.packed_bytes_length,
             single_value_per_doc: self.single_value_per_doc,
             count: self.count,
             long_ords: self.long_ords,
             shared_reader: None,
-            next_shared_read: self.next_shared_read,
+            next_shared_read: 0,
             expected_count: self.expected_count,
-            closed: self.closed,
+            closed: true,
             temp_file_name_prefix: self.temp_file_name_prefix.clone(),
             desc: self.desc.clone(),
+            handle: self.handle.clone(),
         }
     }
 }
@@ -13,6 +13,9 @@
 
 use core::util::{byte_ref::BytesRef, fill_slice, Count, Counter};
 
+use std::cmp::min;
+use std::mem;
+
 /// Class that Posting and PostingVector use to write byte
 /// streams into shared fixed-size byte[] arrays.  The idea
 /// is to allocate slices of increasing lengths For
@@ -222,55 +225,36 @@ impl ByteBlockPool {
     }
 
     pub fn read_bytes(&self, offset: usize, bytes: &mut [u8], off: usize, length: usize) {
-        if length == 0 {
-            return;
-        }
-
         let mut bytes_offset = off;
         let mut bytes_length = length;
         let mut buffer_index = offset >> Self::BYTE_BLOCK_SHIFT;
         let mut pos = offset & Self::BYTE_BLOCK_MASK;
-        let overflow = (pos + length) as isize - Self::BYTE_BLOCK_SIZE as isize;
-        loop {
-            if overflow <= 0 {
-                bytes[bytes_offset..bytes_offset + bytes_length]
-                    .copy_from_slice(&self.buffers[buffer_index][pos..pos + bytes_length]);
-                break;
-            } else {
-                let bytes_copy = length - overflow as usize;
-                bytes[bytes_offset..bytes_offset + bytes_copy]
-                    .copy_from_slice(&self.buffers[buffer_index][pos..pos + bytes_copy]);
-                pos = 0;
-                bytes_length -= bytes_copy;
-                bytes_offset += bytes_copy;
-                buffer_index += 1;
-            }
+        while bytes_length > 0 {
+            let bytes_copy = min(bytes_length, Self::BYTE_BLOCK_SIZE - pos);
+            bytes[bytes_offset..bytes_offset + bytes_copy]
+                .copy_from_slice(&self.buffers[buffer_index][pos..pos + bytes_copy]);
+            pos = 0;
+            bytes_length -= bytes_copy;
+            bytes_offset += bytes_copy;
+            buffer_index += 1;
         }
     }
 
+    /// Appends the bytes to the pool, spanning over the buffers if needed.
     pub fn append(&mut self, bytes: &BytesRef) {
         let mut length = bytes.len();
-        if length == 0 {
-            return;
-        }
         let mut offset = 0;
-        loop {
-            let start = self.byte_upto;
-            if length + self.byte_upto <= Self::BYTE_BLOCK_SIZE {
-                self.current_buffer()[start..start + bytes.len()]
-                    .copy_from_slice(&bytes.bytes()[offset..offset + length]);
-                self.byte_upto += length;
-                break;
-            } else {
-                let bytes_copy = Self::BYTE_BLOCK_SIZE - self.byte_upto;
-                if bytes_copy > 0 {
-                    self.current_buffer()[start..start + bytes_copy].copy_from_slice(bytes.bytes());
-                    debug_assert!(bytes_copy > 0);
-                    offset += bytes_copy;
-                    length -= bytes_copy;
-                }
+        while length > 0 {
+            if self.byte_upto == Self::BYTE_BLOCK_SIZE {
                 self.next_buffer();
             }
+            let start = self.byte_upto;
+            let bytes_copy = min(length, Self::BYTE_BLOCK_SIZE - start);
+            self.current_buffer()[start..start + bytes_copy]
+                .copy_from_slice(&bytes.bytes()[offset..offset + bytes_copy]);
+            self.byte_upto += bytes_copy;
+            offset += bytes_copy;
+            length -= bytes_copy;
         }
     }
 
@@ -374,3 +358,165 @@ impl ByteBlockAllocator for DirectTrackingAllocator {
         })
     }
 }
+
+/// A `ByteBlockAllocator` that keeps up to `max_buffered_blocks` recycled
+/// blocks for later use, so that a pool which is reset between flushes does
+/// not give its blocks away. The blocks are zero filled when handed out again
+/// since the slices rely on it.
+pub struct RecyclingByteBlockAllocator {
+    block_size: usize,
+    free_blocks: Vec<Vec<u8>>,
+    max_buffered_blocks: usize,
+    pub bytes_used: Counter,
+}
+
+impl RecyclingByteBlockAllocator {
+    pub const DEFAULT_BUFFERED_BLOCKS: usize = 64;
+
+    pub fn new(block_size: usize, max_buffered_blocks: usize, bytes_used: Counter) -> Self {
+        RecyclingByteBlockAllocator {
+            block_size,
+            free_blocks: Vec::with_capacity(max_buffered_blocks),
+            max_buffered_blocks,
+            bytes_used,
+        }
+    }
+
+    /// Returns the number of currently buffered blocks.
+    pub fn num_buffered_blocks(&self) -> usize {
+        self.free_blocks.len()
+    }
+
+    /// Removes up to `num` buffered blocks, returning the number removed.
+    pub fn free_blocks(&mut self, num: usize) -> usize {
+        let num = min(num, self.free_blocks.len());
+        let new_len = self.free_blocks.len() - num;
+        self.free_blocks.truncate(new_len);
+        self.bytes_used.add_get(-((num * self.block_size) as i64));
+        num
+    }
+}
+
+impl Default for RecyclingByteBlockAllocator {
+    fn default() -> Self {
+        Self::new(
+            ByteBlockPool::BYTE_BLOCK_SIZE,
+            Self::DEFAULT_BUFFERED_BLOCKS,
+            Counter::default(),
+        )
+    }
+}
+
+impl ByteBlockAllocator for RecyclingByteBlockAllocator {
+    fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    fn recycle_byte_blocks(&mut self, blocks: &mut [Vec<u8>], start: usize, end: usize) {
+        for block in &mut blocks[start..end] {
+            let block = mem::replace(block, vec![]);
+            if self.free_blocks.len() < self.max_buffered_blocks {
+                self.free_blocks.push(block);
+            } else {
+                self.bytes_used.add_get(-(self.block_size as i64));
+            }
+        }
+    }
+
+    fn byte_block(&mut self) -> Vec<u8> {
+        match self.free_blocks.pop() {
+            Some(mut block) => {
+                fill_slice(&mut block, 0);
+                block
+            }
+            None => {
+                self.bytes_used.add_get(self.block_size as i64);
+                vec![0u8; self.block_size]
+            }
+        }
+    }
+
+    unsafe fn copy_unsafe(&self) -> Box<dyn ByteBlockAllocator> {
+        Box::new(RecyclingByteBlockAllocator::new(
+            self.block_size,
+            self.max_buffered_blocks,
+            self.bytes_used.shallow_copy(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::util::tests::seeded_rng;
+
+    use rand::Rng;
+
+    #[test]
+    fn test_append_and_read_bytes() {
+        let mut rng = seeded_rng();
+        let mut pool = ByteBlockPool::new(Box::new(DirectAllocator::default()));
+        let mut expected = Vec::new();
+        while expected.len() < 3 * ByteBlockPool::BYTE_BLOCK_SIZE + 17 {
+            let len = rng.gen_range(0, ByteBlockPool::BYTE_BLOCK_SIZE / 3);
+            let bytes: Vec<u8> = (0..len).map(|_| rng.gen()).collect();
+            pool.append(&BytesRef::new(&bytes));
+            expected.extend_from_slice(&bytes);
+        }
+        assert_eq!(pool.byte_offset as usize + pool.byte_upto, expected.len());
+
+        for _ in 0..1000 {
+            let offset = rng.gen_range(0, expected.len());
+            let len = rng.gen_range(0, min(expected.len() - offset, 3 * 1024 * 32) + 1);
+            let mut bytes = vec![0u8; len + 2];
+            pool.read_bytes(offset, &mut bytes, 1, len);
+            assert_eq!(&bytes[1..=len], &expected[offset..offset + len]);
+            assert_eq!(pool.read_byte(offset), expected[offset]);
+        }
+    }
+
+    #[test]
+    fn test_recycling_allocator() {
+        let counter = Counter::default();
+        let allocator =
+            RecyclingByteBlockAllocator::new(ByteBlockPool::BYTE_BLOCK_SIZE, 8, unsafe {
+                counter.shallow_copy()
+            });
+        let mut pool = ByteBlockPool::new(Box::new(allocator));
+        let bytes = vec![7u8; 5 * ByteBlockPool::BYTE_BLOCK_SIZE];
+        for _ in 0..3 {
+            pool.append(&BytesRef::new(&bytes));
+            assert_eq!(pool.buffer_upto, 4);
+            // no block is allocated again once the pool was reset
+            assert_eq!(counter.get(), 5 * ByteBlockPool::BYTE_BLOCK_SIZE as i64);
+
+            pool.reset(false, false);
+            assert_eq!(pool.buffer_upto, -1);
+            assert!(pool.buffers.iter().all(|b| b.is_empty()));
+        }
+
+        // the recycled blocks are zero filled
+        pool.next_buffer();
+        assert!(pool.current_buffer().iter().all(|&b| b == 0));
+    }
+
+    #[test]
+    fn test_reset_reuse_first() {
+        let mut pool = ByteBlockPool::new(Box::new(DirectAllocator::default()));
+        let bytes = vec![3u8; ByteBlockPool::BYTE_BLOCK_SIZE + 10];
+        pool.append(&BytesRef::new(&bytes));
+        assert_eq!(pool.buffer_upto, 1);
+
+        pool.reset(true, true);
+        assert_eq!(pool.buffer_upto, 0);
+        assert_eq!(pool.byte_upto, 0);
+        assert_eq!(pool.byte_offset, 0);
+        assert!(pool.buffers[0].iter().all(|&b| b == 0));
+        assert!(pool.buffers[1].is_empty());
+
+        pool.append(&BytesRef::new(b"abc"));
+        let mut read = [0u8; 3];
+        pool.read_bytes(0, &mut read, 0, 3);
+        assert_eq!(&read, b"abc");
+    }
+}
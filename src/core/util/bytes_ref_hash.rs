@@ -324,8 +324,7 @@ impl BytesRefHash {
                     } else {
                         (
                             (self.byte_pool().buffers[bytes_idx][start] & 0x7f) as usize
-                                + ((self.byte_pool().buffers[bytes_idx][start + 1] & 0xff) << 7)
-                                    as usize,
+                                + ((self.byte_pool().buffers[bytes_idx][start + 1] as usize) << 7),
                             start + 2,
                         )
                     };
@@ -367,7 +366,7 @@ impl BytesRefHash {
         }
 
         if self.ids.is_empty() {
-            self.ids = vec![0i32; self.hash_size];
+            self.ids = vec![-1i32; self.hash_size];
             self.bytes_start_array
                 .bytes_used_mut()
                 .add_get(4 * self.hash_size as i64);
@@ -530,3 +529,94 @@ impl BytesStartArray for DirectByteStartArray {
         &mut self.bytes_used
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::util::byte_block_pool::DirectAllocator;
+    use core::util::tests::seeded_rng;
+
+    use rand::Rng;
+    use std::collections::HashMap;
+
+    fn random_term<R: Rng>(rng: &mut R) -> Vec<u8> {
+        // mostly short terms, some longer than 127 bytes to use 2 length bytes
+        let len = if rng.gen_range(0, 20) == 0 {
+            rng.gen_range(128, 1000)
+        } else {
+            rng.gen_range(0, 12)
+        };
+        (0..len).map(|_| rng.gen_range(b'a', b'g')).collect()
+    }
+
+    fn check_terms(hash: &BytesRefHash, terms: &HashMap<Vec<u8>, i32>) {
+        assert_eq!(hash.len(), terms.len());
+        for (term, &id) in terms {
+            assert_eq!(hash.get(id as usize).bytes(), term.as_slice());
+        }
+    }
+
+    #[test]
+    fn test_add_and_sort() {
+        let mut rng = seeded_rng();
+        let mut pool = ByteBlockPool::new(Box::new(DirectAllocator::default()));
+        let mut hash = BytesRefHash::with_pool(&mut pool);
+
+        for round in 0..2 {
+            let mut terms: HashMap<Vec<u8>, i32> = HashMap::new();
+            for _ in 0..200_000 {
+                let term = random_term(&mut rng);
+                let id = hash.add(&BytesRef::new(&term));
+                match terms.get(&term) {
+                    Some(&existing) => assert_eq!(id, -(existing + 1)),
+                    None => {
+                        // ids are given in the order the terms were added
+                        assert_eq!(id, terms.len() as i32);
+                        terms.insert(term, id);
+                    }
+                }
+            }
+            // the ids are stable across the rehashes
+            check_terms(&hash, &terms);
+            for (term, &id) in &terms {
+                assert_eq!(hash.add(&BytesRef::new(term)), -(id + 1));
+            }
+
+            hash.sort();
+            let mut sorted: Vec<&Vec<u8>> = terms.keys().collect();
+            sorted.sort();
+            for (i, term) in sorted.iter().enumerate() {
+                let id = hash.ids[i];
+                assert_eq!(terms[*term], id);
+                assert_eq!(hash.get(id as usize).bytes(), term.as_slice());
+            }
+
+            // reuse the hash for the next flush, recycling the pool
+            hash.clear(round == 0);
+            assert_eq!(hash.len(), 0);
+        }
+    }
+
+    #[test]
+    fn test_add_by_pool_offset() {
+        let mut pool = ByteBlockPool::new(Box::new(DirectAllocator::default()));
+        let mut hash = BytesRefHash::with_pool(&mut pool);
+        let mut offsets = Vec::new();
+        for i in 0..1000 {
+            let term = format!("term{}", i);
+            let id = hash.add(&BytesRef::new(term.as_bytes()));
+            assert_eq!(id, i);
+            offsets.push(hash.byte_start(id as usize));
+        }
+
+        let mut other_pool = ByteBlockPool::new(Box::new(DirectAllocator::default()));
+        let mut vectors = BytesRefHash::with_pool(&mut other_pool);
+        for (i, &offset) in offsets.iter().enumerate().rev() {
+            assert_eq!(vectors.add_by_pool_offset(offset), 999 - i as i32);
+        }
+        for (i, &offset) in offsets.iter().enumerate() {
+            assert_eq!(vectors.add_by_pool_offset(offset), -(1000 - i as i32));
+            assert_eq!(vectors.byte_start(999 - i), offset);
+        }
+    }
+}
@@ -0,0 +1,598 @@
+// Copyright 2019 Zhizhesihai (Beijing) Technology Limited.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::borrow::Borrow;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::mem;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::usize;
+
+use core::search::lru_cache::LRUCache;
+use error::Result;
+
+/// The number of shards of a cache, unless fewer entries fit in it.
+pub const DEFAULT_SHARDS: usize = 16;
+
+/// Returns the weight of an entry, usually an estimate of the bytes it uses.
+pub type Weigher<K, V> = dyn Fn(&K, &V) -> usize + Send + Sync;
+
+/// Called with each entry evicted from a cache.
+pub type EvictionListener<K, V> = dyn Fn(K, V) + Send + Sync;
+
+// the entries of a shard with their weights, the least recently used last
+struct Shard<K, V> {
+    entries: LRUCache<K, (V, usize)>,
+    weight: usize,
+}
+
+impl<K: Clone + Hash + Eq, V> Shard<K, V> {
+    fn new() -> Shard<K, V> {
+        Shard {
+            entries: LRUCache::new(usize::MAX),
+            weight: 0,
+        }
+    }
+
+    fn remove<Q: ?Sized>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq,
+    {
+        let (value, weight) = self.entries.remove(key)?;
+        self.weight -= weight;
+        Some(value)
+    }
+
+    // returns the previous value of the key and the entries evicted to make
+    // room for the new one, an entry weighing more than the whole shard is
+    // evicted right away
+    fn put(
+        &mut self,
+        key: K,
+        value: V,
+        weight: usize,
+        max_weight: usize,
+        max_entries: usize,
+    ) -> (Option<V>, Vec<(K, V)>) {
+        let previous = self.remove(&key);
+        if weight > max_weight || max_entries == 0 {
+            return (previous, vec![(key, value)]);
+        }
+        let mut evicted = self.evict(max_weight - weight, max_entries - 1);
+        self.entries.insert(key, (value, weight));
+        self.weight += weight;
+        (previous, evicted)
+    }
+
+    // evicts the least recently used entries until they weigh at most
+    // `max_weight` and there is at most `max_entries` of them
+    fn evict(&mut self, max_weight: usize, max_entries: usize) -> Vec<(K, V)> {
+        let mut evicted = Vec::new();
+        while self.weight > max_weight || self.entries.len() > max_entries {
+            match self.entries.pop_last() {
+                Some((k, (v, w))) => {
+                    self.weight -= w;
+                    evicted.push((k, v));
+                }
+                None => break,
+            }
+        }
+        evicted
+    }
+}
+
+/// A bounded cache that may be shared by threads, evicting the least
+/// recently used entries once full.
+///
+/// The cache is bounded by its number of entries, by the sum of the weights
+/// given by a `Weigher` to its entries, or both. The entries are spread over
+/// shards by the hash of their keys, each one with its own lock and an equal
+/// part of the capacity, so that the least recently used entry of the shard
+/// is evicted rather than the one of the whole cache.
+///
+/// No lock is held while calling the weigher, the eviction listener or
+/// computing a value.
+pub struct LruCache<K, V> {
+    shards: Vec<Mutex<Shard<K, V>>>,
+    max_weight: usize,
+    max_entries: usize,
+    weigher: Box<Weigher<K, V>>,
+    eviction_listener: Option<Box<EvictionListener<K, V>>>,
+    hit_count: AtomicUsize,
+    miss_count: AtomicUsize,
+    eviction_count: AtomicUsize,
+}
+
+impl<K: Clone + Hash + Eq, V: Clone> LruCache<K, V> {
+    /// Creates a cache holding at most `max_entries` entries.
+    pub fn with_max_entries(max_entries: usize) -> LruCache<K, V> {
+        Self::with_limits(max_entries, max_entries, |_, _| 1)
+    }
+
+    /// Creates a cache whose entries weigh at most `max_weight` according to
+    /// `weigher`, an entry weighing more than a shard is never kept.
+    pub fn with_max_weight<W>(max_weight: usize, weigher: W) -> LruCache<K, V>
+    where
+        W: Fn(&K, &V) -> usize + Send + Sync + 'static,
+    {
+        Self::with_limits(usize::MAX, max_weight, weigher)
+    }
+
+    /// Creates a cache holding at most `max_entries` entries, which weigh at
+    /// most `max_weight` according to `weigher`.
+    pub fn with_limits<W>(max_entries: usize, max_weight: usize, weigher: W) -> LruCache<K, V>
+    where
+        W: Fn(&K, &V) -> usize + Send + Sync + 'static,
+    {
+        let mut cache = LruCache {
+            shards: Vec::new(),
+            max_weight,
+            max_entries,
+            weigher: Box::new(weigher),
+            eviction_listener: None,
+            hit_count: AtomicUsize::new(0),
+            miss_count: AtomicUsize::new(0),
+            eviction_count: AtomicUsize::new(0),
+        };
+        cache.reset_shards(DEFAULT_SHARDS);
+        cache
+    }
+
+    /// Spreads the entries over `num_shards` shards instead of
+    /// `DEFAULT_SHARDS`, a single shard evicts in the exact LRU order.
+    pub fn with_shards(mut self, num_shards: usize) -> LruCache<K, V> {
+        self.reset_shards(num_shards);
+        self
+    }
+
+    /// Calls `listener` with the entries evicted to make room for others, or
+    /// weighing more than a shard, but not with the ones replaced by `put` or
+    /// removed by `invalidate` and `invalidate_all`.
+    pub fn with_eviction_listener<L>(mut self, listener: L) -> LruCache<K, V>
+    where
+        L: Fn(K, V) + Send + Sync + 'static,
+    {
+        self.eviction_listener = Some(Box::new(listener));
+        self
+    }
+
+    fn reset_shards(&mut self, num_shards: usize) {
+        // every shard has room for some entries
+        let num_shards = num_shards.min(self.max_weight).min(self.max_entries).max(1);
+        self.shards = (0..num_shards).map(|_| Mutex::new(Shard::new())).collect();
+    }
+
+    // the weight each shard is bounded by, their sum isn't over `max_weight`
+    fn shard_max_weight(&self) -> usize {
+        self.max_weight / self.shards.len()
+    }
+
+    fn shard_max_entries(&self) -> usize {
+        self.max_entries / self.shards.len()
+    }
+
+    fn shard<Q: Hash + ?Sized>(&self, key: &Q) -> &Mutex<Shard<K, V>> {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        &self.shards[(hasher.finish() % self.shards.len() as u64) as usize]
+    }
+
+    // must be called once the lock of the shard is released
+    fn on_eviction(&self, evicted: Vec<(K, V)>) {
+        self.eviction_count
+            .fetch_add(evicted.len(), Ordering::Relaxed);
+        if let Some(ref listener) = self.eviction_listener {
+            for (key, value) in evicted {
+                listener(key, value);
+            }
+        }
+    }
+
+    /// Returns the value of `key` and marks it as the most recently used.
+    pub fn get<Q: ?Sized>(&self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq,
+    {
+        let value = self
+            .shard(key)
+            .lock()
+            .unwrap()
+            .entries
+            .get(key)
+            .map(|e| e.0.clone());
+        if value.is_some() {
+            self.hit_count.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.miss_count.fetch_add(1, Ordering::Relaxed);
+        }
+        value
+    }
+
+    /// Returns the value of `key`, without marking it as used nor counting
+    /// the lookup.
+    pub fn peek<Q: ?Sized>(&self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq,
+    {
+        self.shard(key)
+            .lock()
+            .unwrap()
+            .entries
+            .peek(key)
+            .map(|e| e.0.clone())
+    }
+
+    /// Associates `value` with `key`, and returns the previous value of it.
+    pub fn put(&self, key: K, value: V) -> Option<V> {
+        let weight = (self.weigher)(&key, &value);
+        let (previous, evicted) = self.shard(&key).lock().unwrap().put(
+            key,
+            value,
+            weight,
+            self.shard_max_weight(),
+            self.shard_max_entries(),
+        );
+        self.on_eviction(evicted);
+        previous
+    }
+
+    /// Returns the value of `key`, computing it with `f` and caching it if
+    /// there was none. The lock isn't held while computing, so that several
+    /// threads missing the same key may compute it, the first value cached is
+    /// returned to all of them.
+    pub fn compute_if_absent<F>(&self, key: K, f: F) -> Result<V>
+    where
+        F: FnOnce(&K) -> Result<V>,
+    {
+        if let Some(value) = self.get(&key) {
+            return Ok(value);
+        }
+        let value = f(&key)?;
+        let weight = (self.weigher)(&key, &value);
+        let evicted = {
+            let mut shard = self.shard(&key).lock().unwrap();
+            if let Some(cached) = shard.entries.get(&key) {
+                return Ok(cached.0.clone());
+            }
+            shard
+                .put(
+                    key,
+                    value.clone(),
+                    weight,
+                    self.shard_max_weight(),
+                    self.shard_max_entries(),
+                )
+                .1
+        };
+        self.on_eviction(evicted);
+        Ok(value)
+    }
+
+    /// Weighs the entry of `key` again after its value was modified in place,
+    /// evicting the least recently used entries if it got heavier. The entry
+    /// isn't marked as used.
+    pub fn reweigh(&self, key: &K) {
+        let value = match self.peek(key) {
+            Some(value) => value,
+            None => return,
+        };
+        let weight = (self.weigher)(key, &value);
+        let max_weight = self.shard_max_weight();
+        let evicted = {
+            let mut shard = self.shard(key).lock().unwrap();
+            let previous = match shard.entries.peek_mut(key) {
+                Some(entry) => mem::replace(&mut entry.1, weight),
+                None => return,
+            };
+            shard.weight = shard.weight - previous + weight;
+            if weight > max_weight {
+                // evicted right away, as if it was put
+                let value = shard.remove(key).unwrap();
+                vec![(key.clone(), value)]
+            } else {
+                let max_entries = self.shard_max_entries();
+                shard.evict(max_weight, max_entries)
+            }
+        };
+        self.on_eviction(evicted);
+    }
+
+    /// Removes `key` from the cache and returns its value.
+    pub fn invalidate<Q: ?Sized>(&self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq,
+    {
+        self.shard(key).lock().unwrap().remove(key)
+    }
+
+    /// Removes all the entries of the cache.
+    pub fn invalidate_all(&self) {
+        for shard in &self.shards {
+            // the entries are dropped once the lock is released
+            let _entries = mem::replace(&mut *shard.lock().unwrap(), Shard::new());
+        }
+    }
+
+    /// The keys of the cache, the least recently used first in each shard.
+    pub fn keys(&self) -> Vec<K> {
+        let mut keys = Vec::new();
+        for shard in &self.shards {
+            let mut shard_keys = shard.lock().unwrap().entries.keys();
+            shard_keys.reverse();
+            keys.extend(shard_keys);
+        }
+        keys
+    }
+
+    /// The number of entries in the cache.
+    pub fn len(&self) -> usize {
+        self.shards
+            .iter()
+            .map(|s| s.lock().unwrap().entries.len())
+            .sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The sum of the weights of the entries, their number if the cache is
+    /// bounded by entries.
+    pub fn weight(&self) -> usize {
+        self.shards.iter().map(|s| s.lock().unwrap().weight).sum()
+    }
+
+    pub fn max_weight(&self) -> usize {
+        self.max_weight
+    }
+
+    pub fn max_entries(&self) -> usize {
+        self.max_entries
+    }
+
+    /// The number of lookups that found a value.
+    pub fn hit_count(&self) -> usize {
+        self.hit_count.load(Ordering::Relaxed)
+    }
+
+    /// The number of lookups that found nothing.
+    pub fn miss_count(&self) -> usize {
+        self.miss_count.load(Ordering::Relaxed)
+    }
+
+    /// The number of entries evicted to make room for others, or weighing
+    /// more than a shard.
+    pub fn eviction_count(&self) -> usize {
+        self.eviction_count.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::util::tests::seeded_rng;
+
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn test_max_entries() {
+        let cache = LruCache::with_max_entries(3).with_shards(1);
+        assert_eq!(cache.put("a", 1), None);
+        cache.put("b", 2);
+        cache.put("c", 3);
+        assert_eq!(cache.put("a", 10), Some(1));
+        assert_eq!(cache.eviction_count(), 0);
+
+        // "b" is the least recently used
+        cache.put("d", 4);
+        assert_eq!(cache.len(), 3);
+        assert_eq!(cache.eviction_count(), 1);
+        assert_eq!(cache.get("b"), None);
+        assert_eq!(cache.get("c"), Some(3));
+        cache.put("e", 5);
+        assert_eq!(cache.get("a"), None);
+        assert_eq!(cache.get("c"), Some(3));
+        assert_eq!(cache.get("d"), Some(4));
+        assert_eq!(cache.hit_count(), 3);
+        assert_eq!(cache.miss_count(), 2);
+
+        assert_eq!(cache.invalidate("d"), Some(4));
+        assert_eq!(cache.invalidate("d"), None);
+        assert_eq!(cache.len(), 2);
+        cache.invalidate_all();
+        assert!(cache.is_empty());
+        assert_eq!(cache.weight(), 0);
+        assert_eq!(cache.eviction_count(), 2);
+    }
+
+    #[test]
+    fn test_max_weight() {
+        let cache = LruCache::with_max_weight(10, |_: &u32, v: &Vec<u8>| v.len()).with_shards(1);
+        cache.put(1, vec![0; 4]);
+        cache.put(2, vec![0; 4]);
+        assert_eq!(cache.weight(), 8);
+        // both are evicted to make room
+        cache.put(3, vec![0; 9]);
+        assert_eq!(cache.weight(), 9);
+        assert_eq!(cache.len(), 1);
+        assert_eq!(cache.eviction_count(), 2);
+
+        // replacing an entry updates the weight
+        cache.put(3, vec![0; 2]);
+        assert_eq!(cache.weight(), 2);
+        assert_eq!(cache.eviction_count(), 2);
+
+        // an entry heavier than the cache isn't kept
+        cache.put(4, vec![0; 11]);
+        assert_eq!(cache.get(&4), None);
+        assert_eq!(cache.get(&3), Some(vec![0; 2]));
+        assert_eq!(cache.weight(), 2);
+        assert_eq!(cache.eviction_count(), 3);
+
+        // the shards split the weight
+        let cache = LruCache::with_max_weight(10, |_: &u32, v: &Vec<u8>| v.len()).with_shards(2);
+        cache.put(1, vec![0; 6]);
+        assert_eq!(cache.get(&1), None);
+        assert_eq!(cache.shards.len(), 2);
+
+        // there is never more shards than entries
+        let cache: LruCache<u32, u32> = LruCache::with_max_entries(3);
+        assert_eq!(cache.shards.len(), 3);
+        let cache: LruCache<u32, u32> = LruCache::with_max_entries(0);
+        cache.put(1, 1);
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn test_eviction_listener() {
+        let evicted = Arc::new(Mutex::new(vec![]));
+        let listened = Arc::clone(&evicted);
+        let cache =
+            LruCache::with_limits(2, 10, |_: &&str, v: &Arc<Mutex<usize>>| *v.lock().unwrap())
+                .with_shards(1)
+                .with_eviction_listener(move |k, _| listened.lock().unwrap().push(k));
+        let a = Arc::new(Mutex::new(2));
+        cache.put("a", Arc::clone(&a));
+        cache.put("b", Arc::new(Mutex::new(2)));
+        assert_eq!(cache.keys(), vec!["a", "b"]);
+
+        // bounded by the number of entries
+        cache.put("c", Arc::new(Mutex::new(2)));
+        assert_eq!(*evicted.lock().unwrap(), vec!["a"]);
+        assert_eq!(cache.keys(), vec!["b", "c"]);
+        // replaced and invalidated entries aren't evicted
+        cache.put("c", Arc::new(Mutex::new(3)));
+        cache.invalidate("b");
+        assert_eq!(cache.eviction_count(), 1);
+
+        // an entry modified in place is weighed again, without being used
+        cache.put("a", Arc::clone(&a));
+        assert_eq!(cache.keys(), vec!["c", "a"]);
+        *a.lock().unwrap() = 8;
+        cache.reweigh(&"a");
+        assert_eq!(cache.keys(), vec!["a"]);
+        assert_eq!(cache.weight(), 8);
+        *a.lock().unwrap() = 11;
+        cache.reweigh(&"a");
+        assert!(cache.is_empty());
+        assert_eq!(cache.weight(), 0);
+        assert_eq!(*evicted.lock().unwrap(), vec!["a", "c", "a"]);
+        assert_eq!(cache.eviction_count(), 3);
+    }
+
+    #[test]
+    fn test_compute_if_absent() {
+        let cache = LruCache::with_max_entries(10);
+        let value = cache.compute_if_absent(1, |k| Ok(k * 10)).unwrap();
+        assert_eq!(value, 10);
+        let value = cache
+            .compute_if_absent(1, |_| -> Result<i32> { panic!("already cached") })
+            .unwrap();
+        assert_eq!(value, 10);
+        assert_eq!(cache.hit_count(), 1);
+        assert_eq!(cache.miss_count(), 1);
+
+        // errors aren't cached
+        assert!(cache
+            .compute_if_absent(2, |_| bail!("failed to compute"))
+            .is_err());
+        assert_eq!(cache.get(&2), None);
+
+        // the cache isn't locked while computing, the first value cached wins
+        let value = cache
+            .compute_if_absent(3, |k| {
+                assert_eq!(cache.get(k), None);
+                cache.put(*k, 30);
+                Ok(31)
+            })
+            .unwrap();
+        assert_eq!(value, 30);
+        assert_eq!(cache.get(&3), Some(30));
+    }
+
+    #[test]
+    fn test_concurrent_access() {
+        const THREADS: usize = 8;
+        const OPS: usize = 20000;
+        const MAX_WEIGHT: usize = 1000;
+
+        let cache = Arc::new(LruCache::with_max_weight(
+            MAX_WEIGHT,
+            |_: &u32, v: &Vec<u8>| v.len(),
+        ));
+        // the threads draw their seeds from the seeded generator
+        let mut rng = seeded_rng();
+        let handles: Vec<_> = (0..THREADS)
+            .map(|_| {
+                let cache = Arc::clone(&cache);
+                let mut rng = StdRng::from_rng(&mut rng).unwrap();
+                thread::spawn(move || {
+                    let mut lookups = 0;
+                    for _ in 0..OPS {
+                        let key = rng.gen_range(0u32, 500);
+                        match rng.gen_range(0, 10) {
+                            0..=3 => {
+                                lookups += 1;
+                                if let Some(v) = cache.get(&key) {
+                                    assert_eq!(v, vec![key as u8; v.len()]);
+                                }
+                            }
+                            4..=6 => {
+                                cache.put(key, vec![key as u8; rng.gen_range(1, 40)]);
+                            }
+                            7..=8 => {
+                                lookups += 1;
+                                let len = rng.gen_range(1, 40);
+                                let v = cache
+                                    .compute_if_absent(key, |k| Ok(vec![*k as u8; len]))
+                                    .unwrap();
+                                assert_eq!(v, vec![key as u8; v.len()]);
+                            }
+                            _ => {
+                                cache.invalidate(&key);
+                            }
+                        }
+                        assert!(cache.weight() <= MAX_WEIGHT);
+                    }
+                    lookups
+                })
+            })
+            .collect();
+        let lookups: usize = handles.into_iter().map(|h| h.join().unwrap()).sum();
+
+        assert_eq!(cache.hit_count() + cache.miss_count(), lookups);
+        assert!(cache.eviction_count() > 0);
+        assert!(cache.weight() <= MAX_WEIGHT);
+        let mut len = 0;
+        for shard in &cache.shards {
+            let mut shard = shard.lock().unwrap();
+            assert!(shard.weight <= cache.shard_max_weight());
+            len += shard.entries.len();
+            let mut weight = 0;
+            while let Some((_, (v, w))) = shard.entries.pop_last() {
+                assert_eq!(v.len(), w);
+                weight += w;
+            }
+            assert_eq!(weight, shard.weight);
+        }
+        assert!(len > 0);
+    }
+}
@@ -100,7 +100,7 @@ impl DocIdSetBuilder {
     /// `DocIterator`, you should rather use `RoaringDocIdSet.Builder`.
     pub fn add(&mut self, iter: &mut DocIterator) -> Result<()> {
         if let Some(ref mut bit_set) = self.bit_set {
-            bit_set.or(iter)?;
+            return bit_set.or(iter);
         } else {
             let cost = min(i32::max_value() as usize, iter.cost());
             self.grow(cost);
@@ -160,14 +160,14 @@ impl DocIdSetBuilder {
             return;
         }
         let current_idx = self.buffers.len() - 1;
-        if self.buffers[current_idx].length >= num_docs + self.buffers[current_idx].array.len() {
+        let length = self.buffers[current_idx].length;
+        let capacity = self.buffers[current_idx].array.len();
+        if capacity - length >= num_docs {
             // current buffer is large enough
             return;
         }
-        if self.buffers[current_idx].length
-            < (self.buffers[current_idx].array.len() - self.buffers[current_idx].array.len())
-                >> 3usize
-        {
+        if length < capacity - (capacity >> 3usize) {
+            // current buffer is less than 7/8 full, resize rather than waste space
             let cap = self.additional_capacity(num_docs);
             self.grow_buffer(current_idx, cap);
         } else {
@@ -336,3 +336,72 @@ impl Buffer {
         Buffer { array, length }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::search::DocIdSet;
+    use core::util::doc_id_set::IntArrayDocIterator;
+    use core::util::tests::seeded_rng;
+
+    use rand::Rng;
+    use std::collections::BTreeSet;
+
+    fn collect(builder: &mut DocIdSetBuilder) -> Vec<DocId> {
+        let mut iter = builder.build().iterator().unwrap().unwrap();
+        let mut docs = Vec::new();
+        loop {
+            let doc = iter.next().unwrap();
+            if doc == NO_MORE_DOCS {
+                return docs;
+            }
+            docs.push(doc);
+        }
+    }
+
+    #[test]
+    fn test_sparse_and_dense() {
+        let mut rng = seeded_rng();
+        let max_doc = 100_000;
+        // under and over the threshold of max_doc / 128 documents
+        for &count in &[0, 1, 100, 700, 5_000, 50_000] {
+            let mut builder = DocIdSetBuilder::with_max_doc(max_doc);
+            let mut expected = BTreeSet::new();
+            for _ in 0..count {
+                let doc = rng.gen_range(0, max_doc);
+                builder.grow(1);
+                builder.add_doc(doc);
+                expected.insert(doc);
+            }
+            if count <= 100 {
+                assert!(builder.bit_set.is_none());
+            } else if count >= 5_000 {
+                assert!(builder.bit_set.is_some());
+            }
+            let docs = collect(&mut builder);
+            assert_eq!(docs, expected.into_iter().collect::<Vec<_>>());
+        }
+    }
+
+    #[test]
+    fn test_add_iterators() {
+        let mut rng = seeded_rng();
+        let max_doc = 10_000;
+        let mut builder = DocIdSetBuilder::with_max_doc(max_doc);
+        let mut expected = BTreeSet::new();
+        for _ in 0..20 {
+            let docs: BTreeSet<DocId> = (0..rng.gen_range(0, 100))
+                .map(|_| rng.gen_range(0, max_doc))
+                .collect();
+            expected.extend(docs.iter().cloned());
+            let mut docs: Vec<DocId> = docs.into_iter().collect();
+            let len = docs.len();
+            docs.push(NO_MORE_DOCS);
+            builder
+                .add(&mut IntArrayDocIterator::new(Arc::new(docs), len))
+                .unwrap();
+        }
+        let docs = collect(&mut builder);
+        assert_eq!(docs, expected.into_iter().collect::<Vec<_>>());
+    }
+}
@@ -394,6 +394,8 @@ pub struct StoreBytesReader {
     pub block_index: usize,
     pub next_read: usize,
     pub reversed: bool,
+    // whether a reversed read consumed the byte at position 0
+    before_start: bool,
 }
 
 unsafe impl Send for StoreBytesReader {}
@@ -412,6 +414,7 @@ impl StoreBytesReader {
             block_index: 0,
             next_read: 0,
             reversed,
+            before_start: false,
         }
     }
 
@@ -427,6 +430,7 @@ impl StoreBytesReader {
             block_index: 0,
             next_read: 0,
             reversed,
+            before_start: false,
         }
     }
 }
@@ -439,6 +443,7 @@ impl BytesReader for StoreBytesReader {
     fn set_position(&mut self, pos: usize) {
         self.block_index = pos >> self.block_bits;
         self.next_read = pos & self.block_mask;
+        self.before_start = false;
         debug_assert_eq!(pos, self.block_index * self.block_size + self.next_read);
     }
 
@@ -449,12 +454,14 @@ impl BytesReader for StoreBytesReader {
 
 impl DataInput for StoreBytesReader {
     fn read_byte(&mut self) -> Result<u8> {
-        let b = unsafe {
-            *(*((*self.blocks.as_ref())
-                .as_ptr()
-                .offset(self.block_index as isize)))
-            .as_ptr()
-            .offset(self.next_read as isize)
+        let b = match self
+            .blocks
+            .as_ref()
+            .get(self.block_index)
+            .and_then(|block| block.get(self.next_read))
+        {
+            Some(&b) if !self.before_start => b,
+            _ => bail!(self.out_of_bounds(1)),
         };
 
         if self.reversed {
@@ -462,6 +469,8 @@ impl DataInput for StoreBytesReader {
                 if self.block_index > 0 {
                     self.block_index -= 1;
                     self.next_read = self.block_size - 1;
+                } else {
+                    self.before_start = true;
                 }
             } else {
                 self.next_read -= 1;
@@ -479,23 +488,33 @@ impl DataInput for StoreBytesReader {
     }
 
     fn read_bytes(&mut self, b: &mut [u8], offset: usize, len: usize) -> Result<()> {
-        unsafe {
-            let ptr = b.as_mut_ptr().offset(offset as isize);
-            for i in 0..len {
-                *ptr.offset(i as isize) = self.read_byte()?;
-            }
+        for v in &mut b[offset..offset + len] {
+            *v = self.read_byte()?;
         }
 
         Ok(())
     }
 
     fn skip_bytes(&mut self, count: usize) -> Result<()> {
+        if count == 0 {
+            return Ok(());
+        }
         let pos = self.position();
 
         if self.reversed {
-            debug_assert!(pos > count);
-            self.set_position(pos - count);
+            if self.before_start || pos >= self.bytes_len() || count > pos + 1 {
+                bail!(self.out_of_bounds(count));
+            }
+            if count == pos + 1 {
+                self.set_position(0);
+                self.before_start = true;
+            } else {
+                self.set_position(pos - count);
+            }
         } else {
+            if pos + count > self.bytes_len() {
+                bail!(self.out_of_bounds(count));
+            }
             self.set_position(pos + count);
         }
 
@@ -539,15 +558,16 @@ impl IndexInput for StoreBytesReader {
 
 impl Read for StoreBytesReader {
     fn read(&mut self, b: &mut [u8]) -> io::Result<usize> {
-        let left = b.len();
-
-        for i in b.iter_mut().take(left) {
-            if let Ok(byte) = self.read_byte() {
-                *i = byte;
+        let mut read = 0;
+        for v in b.iter_mut() {
+            match self.read_byte() {
+                Ok(byte) => *v = byte,
+                Err(_) => break,
             }
+            read += 1;
         }
 
-        Ok(b.len() - left)
+        Ok(read)
     }
 }
 
@@ -564,6 +584,9 @@ impl BytesStore {
 mod tests {
     use super::*;
     use core::util::fst::tests::*;
+    use core::util::tests::seeded_rng;
+
+    use rand::Rng;
 
     fn create_test_bytes_store() -> Result<BytesStore> {
         let mut outputs = TestBufferedDataIO::default();
@@ -611,5 +634,41 @@ mod tests {
         let mut b = vec![0; 5];
         reverse_reader.read_bytes(b.as_mut_slice(), 0, 5).unwrap();
         assert_eq!(b.as_slice(), [5, 4, 3, 2, 1]);
+        assert!(reverse_reader.read_byte().is_err());
+        assert!(reverse_reader.skip_bytes(1).is_err());
+    }
+
+    #[test]
+    fn test_out_of_bounds() {
+        let store = create_test_bytes_store().unwrap();
+        let mut forward_reader = store.get_forward_reader();
+        forward_reader.set_position(7);
+        let mut b = vec![0; 3];
+        assert!(forward_reader.read_bytes(&mut b, 0, 3).is_err());
+        forward_reader.set_position(9);
+        assert!(forward_reader.read_byte().is_err());
+        assert!(forward_reader.skip_bytes(1).is_err());
+        forward_reader.skip_bytes(0).unwrap();
+
+        let mut reverse_reader = store.get_reverse_reader();
+        reverse_reader.set_position(2);
+        assert!(reverse_reader.skip_bytes(4).is_err());
+        reverse_reader.set_position(2);
+        reverse_reader.skip_bytes(3).unwrap();
+        assert!(reverse_reader.read_byte().is_err());
+        reverse_reader.set_position(12);
+        assert!(reverse_reader.read_byte().is_err());
+    }
+
+    #[test]
+    fn test_random_reads() {
+        let mut rng = seeded_rng();
+        let bytes: Vec<u8> = (0..1000).map(|_| rng.gen::<u8>()).collect();
+        let mut outputs = TestBufferedDataIO::default();
+        outputs.write_bytes(&bytes, 0, bytes.len()).unwrap();
+        let store = BytesStore::new(&mut outputs, bytes.len(), 64).unwrap();
+
+        check_random_reads(&mut rng, &mut store.get_forward_reader(), &bytes);
+        check_random_reads(&mut rng, &mut store.get_reverse_reader(), &bytes);
     }
 }
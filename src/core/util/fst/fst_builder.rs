@@ -21,11 +21,12 @@ use core::util::bit_util::BitsRequired;
 use core::util::fst::bytes_store::StoreBytesReader;
 use core::util::fst::fst_reader::{CompiledAddress, InputType};
 use core::util::fst::{BytesReader, Output, OutputFactory, FST};
-use core::util::ints_ref::{IntsRef, IntsRefBuilder};
+use core::util::ints_ref::{to_ints_ref, IntsRef, IntsRefBuilder};
 use core::util::packed::{PagedGrowableWriter, PagedMutableWriter};
 use core::util::packed_misc::COMPACT;
 use core::util::LongValues;
 
+use error::ErrorKind::IllegalArgument;
 use error::Result;
 
 /// Builds a minimal FST (maps an IntsRef term to an arbitrary
@@ -58,6 +59,8 @@ pub struct FstBuilder<F: OutputFactory> {
     do_share_non_singleton_nodes: bool,
     share_max_tail_length: u32,
     last_input: IntsRefBuilder,
+    // the ints of the inputs given as bytes to `add_bytes`
+    scratch_input: IntsRefBuilder,
     // NOTE: cutting this over to ArrayList instead loses ~6%
     // in build performance on 9.8M Wikipedia terms; so we
     // left this as an array:
@@ -116,6 +119,7 @@ impl<F: OutputFactory> FstBuilder<F> {
             do_share_non_singleton_nodes,
             share_max_tail_length,
             last_input: IntsRefBuilder::new(),
+            scratch_input: IntsRefBuilder::new(),
             frontier: Vec::with_capacity(10),
             last_frozen_node: 0,
             reused_bytes_per_arc: Vec::with_capacity(4),
@@ -295,17 +299,22 @@ impl<F: OutputFactory> FstBuilder<F> {
 
     /// Add the next input/output pair.  The provided input
     /// must be sorted after the previous one according to
-    /// `IntsRef#compareTo`.  It's also OK to add the same
-    /// input twice in a row with different outputs, as long
-    /// as `OutputFactory` implements the `OutputFactory#merge`
-    /// method. Note that input is fully consumed after this
-    /// method is returned (so caller is free to reuse), but
+    /// `IntsRef#compareTo`, an error is returned for the inputs
+    /// out of order or added twice. Note that input is fully
+    /// consumed after this method is returned (so caller is free
+    /// to reuse), but
     /// output is not.  So if your outputs are changeable (eg
     /// `ByteSequenceOutputs`) then you cannot reuse across
     /// calls.
     pub fn add(&mut self, input: IntsRef, output: F::Value) -> Result<()> {
         debug_assert!(self.inited);
-        assert!(self.last_input.length == 0 || input > self.last_input.get());
+        if self.last_input.length > 0 && input <= self.last_input.get() {
+            bail!(IllegalArgument(format!(
+                "inputs must be added in sorted order without duplicates, got {:?} after {:?}",
+                &input.ints()[input.offset..input.offset + input.length],
+                &self.last_input.ints()[..self.last_input.length]
+            )));
+        }
         let mut output = output;
 
         if self.frontier.len() < input.length + 1 {
@@ -402,6 +411,13 @@ impl<F: OutputFactory> FstBuilder<F> {
         Ok(())
     }
 
+    /// Adds the input of the labels `input`, see `add`. This is the way to
+    /// add the terms to an FST of `InputType::Byte1`.
+    pub fn add_bytes(&mut self, input: &[u8], output: F::Value) -> Result<()> {
+        let input = to_ints_ref(input, &mut self.scratch_input);
+        self.add(input, output)
+    }
+
     // Returns final FST. NOTE: this will return None if nothing is accepted by the fst
     pub fn finish(&mut self) -> Result<Option<FST<F>>> {
         debug_assert!(self.inited);
@@ -827,3 +843,157 @@ impl<F: OutputFactory> UnCompiledNode<F> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::util::fst::tests::TestBufferedDataIO;
+    use core::util::fst::{
+        ByteSequenceOutput, ByteSequenceOutputFactory, PositiveIntOutput, PositiveIntOutputFactory,
+    };
+
+    fn save<F: OutputFactory>(fst: &FST<F>) -> TestBufferedDataIO {
+        let mut io = TestBufferedDataIO::default();
+        fst.save(&mut io).unwrap();
+        io
+    }
+
+    // the FSA of `terms` with (or without) suffix sharing and fixed array arcs
+    fn build_fsa(terms: &[Vec<u8>], share_suffix: bool, array_arcs: bool) -> Vec<u8> {
+        let mut builder = FstBuilder::build(
+            InputType::Byte1,
+            0,
+            0,
+            share_suffix,
+            true,
+            i32::max_value() as u32,
+            PositiveIntOutputFactory::new(),
+            array_arcs,
+            15,
+        );
+        builder.init();
+        for term in terms {
+            builder.add_bytes(term, PositiveIntOutput::new(0)).unwrap();
+        }
+        save(&builder.finish().unwrap().unwrap()).bytes
+    }
+
+    #[test]
+    fn test_round_trip_one_million_terms() {
+        let term = |i: u32| format!("{:07}", i).into_bytes();
+        let mut builder = FstBuilder::new(InputType::Byte1, PositiveIntOutputFactory::new());
+        builder.init();
+        for i in 0..1_000_000u32 {
+            builder
+                .add_bytes(&term(i * 3), PositiveIntOutput::new(u64::from(i)))
+                .unwrap();
+        }
+        assert_eq!(builder.term_count(), 1_000_000);
+        let built = builder.finish().unwrap().unwrap();
+
+        let mut io = save(&built);
+        let fst = FST::from_input(&mut io, PositiveIntOutputFactory::new()).unwrap();
+        for i in 0..1_000_000u32 {
+            let key = term(i * 3);
+            assert_eq!(
+                fst.get(&key).unwrap(),
+                Some(PositiveIntOutput::new(u64::from(i)))
+            );
+            assert_eq!(fst.get(&term(i * 3 + 1)).unwrap(), None);
+            if i % 1000 == 0 {
+                assert_eq!(
+                    built.get(&key).unwrap().map(|o| o.get()),
+                    Some(u64::from(i))
+                );
+                // the prefixes and the extensions of the keys are absent
+                assert_eq!(fst.get(&key[..6]).unwrap(), None);
+                let mut longer = key.clone();
+                longer.push(b'0');
+                assert_eq!(fst.get(&longer).unwrap(), None);
+            }
+        }
+        assert_eq!(fst.get(b"").unwrap(), None);
+        assert_eq!(fst.get(b"3000000").unwrap(), None);
+    }
+
+    #[test]
+    fn test_fixed_array_arcs() {
+        // every byte is a label of the root node
+        let terms: Vec<Vec<u8>> = (0..=255u8)
+            .flat_map(|b| vec![vec![b, b'x'], vec![b, b'y', b'z']])
+            .collect();
+        let mut builder = FstBuilder::new(InputType::Byte1, ByteSequenceOutputFactory::new());
+        builder.init();
+        for (i, term) in terms.iter().enumerate() {
+            let output = ByteSequenceOutput::new(vec![(i % 7) as u8 + 1, i as u8]);
+            builder.add_bytes(term, output).unwrap();
+        }
+        let mut io = save(&builder.finish().unwrap().unwrap());
+        let fst = FST::from_input(&mut io, ByteSequenceOutputFactory::new()).unwrap();
+
+        let mut reader = fst.bytes_reader();
+        let root = fst
+            .read_first_target_arc(&fst.root_arc(), &mut reader)
+            .unwrap();
+        assert!(root.bytes_per_arc > 0);
+        assert_eq!(root.num_arcs, 256);
+        for (i, term) in terms.iter().enumerate() {
+            let output = fst.get(term).unwrap().unwrap();
+            assert_eq!(output.inner(), &[(i % 7) as u8 + 1, i as u8]);
+        }
+        assert_eq!(fst.get(&[7u8, b'y']).unwrap(), None);
+        assert_eq!(fst.get(&[7u8, b'z']).unwrap(), None);
+
+        // the fixed arrays trade space for the binary search of the labels
+        let sizes: Vec<usize> = [true, false]
+            .iter()
+            .map(|&array_arcs| build_fsa(&terms, true, array_arcs).len())
+            .collect();
+        assert!(sizes[0] > sizes[1]);
+    }
+
+    #[test]
+    fn test_suffix_sharing() {
+        let mut terms: Vec<Vec<u8>> = (0..10_000u32)
+            .flat_map(|i| {
+                vec![
+                    format!("{:04}ing", i).into_bytes(),
+                    format!("{:04}ed", i).into_bytes(),
+                ]
+            })
+            .collect();
+        terms.sort();
+
+        let shared = build_fsa(&terms, true, true);
+        let unshared = build_fsa(&terms, false, true);
+        assert!(shared.len() * 4 < unshared.len());
+        // the encoding is deterministic
+        assert_eq!(build_fsa(&terms, true, true), shared);
+
+        let mut io = TestBufferedDataIO::default();
+        io.bytes = shared;
+        let fst = FST::from_input(&mut io, PositiveIntOutputFactory::new()).unwrap();
+        for term in &terms {
+            assert_eq!(fst.get(term).unwrap(), Some(PositiveIntOutput::new(0)));
+        }
+        assert_eq!(fst.get(b"0042in").unwrap(), None);
+        assert_eq!(fst.get(b"10000ed").unwrap(), None);
+    }
+
+    #[test]
+    fn test_unsorted_inputs() {
+        let mut builder = FstBuilder::new(InputType::Byte1, PositiveIntOutputFactory::new());
+        builder.init();
+        builder.add_bytes(b"b", PositiveIntOutput::new(1)).unwrap();
+        builder.add_bytes(b"bc", PositiveIntOutput::new(2)).unwrap();
+        assert!(builder.add_bytes(b"bc", PositiveIntOutput::new(3)).is_err());
+        assert!(builder.add_bytes(b"b", PositiveIntOutput::new(3)).is_err());
+        assert!(builder.add_bytes(b"a", PositiveIntOutput::new(3)).is_err());
+        builder.add_bytes(b"c", PositiveIntOutput::new(4)).unwrap();
+
+        let fst = builder.finish().unwrap().unwrap();
+        assert_eq!(fst.get(b"bc").unwrap(), Some(PositiveIntOutput::new(2)));
+        assert_eq!(fst.get(b"c").unwrap(), Some(PositiveIntOutput::new(4)));
+        assert_eq!(fst.get(b"a").unwrap(), None);
+    }
+}
@@ -1018,6 +1018,14 @@ pub enum FSTBytesReader {
     BytesStore(StoreBytesReader),
 }
 
+impl FSTBytesReader {
+    /// A reader over no bytes, e.g. for a terms enum whose FST is not
+    /// loaded yet, all the reads fail with `CorruptIndex`.
+    pub fn empty() -> FSTBytesReader {
+        FSTBytesReader::Directional(DirectionalBytesReader::empty())
+    }
+}
+
 impl BytesReader for FSTBytesReader {
     fn position(&self) -> usize {
         match *self {
@@ -1110,4 +1118,50 @@ mod test {
             }
         }
     }
+
+    fn build_words(words: &[&str]) -> FST<ByteSequenceOutputFactory> {
+        let mut builder = FstBuilder::new(InputType::Byte1, ByteSequenceOutputFactory {});
+        builder.init();
+        for (i, word) in words.iter().enumerate() {
+            let output = ByteSequenceOutput::new(vec![i as u8 + 1]);
+            builder.add_bytes(word.as_bytes(), output).unwrap();
+        }
+        builder.finish().unwrap().unwrap()
+    }
+
+    #[test]
+    fn test_truncated_fst() {
+        let words = ["cat", "dag", "dbg", "dcg", "ddg", "deg", "dog", "dogs"];
+        let fst = build_words(&words);
+        let mut saved: Vec<u8> = Vec::new();
+        fst.save(&mut saved).unwrap();
+
+        let mut reader = DirectionalBytesReader::new(&saved, false);
+        let loaded = FST::from_input(&mut reader, ByteSequenceOutputFactory {}).unwrap();
+        for (i, word) in words.iter().enumerate() {
+            assert_eq!(
+                loaded.get(word.as_bytes()).unwrap(),
+                Some(ByteSequenceOutput::new(vec![i as u8 + 1]))
+            );
+        }
+
+        // the saved bytes cut anywhere can't be loaded
+        for len in 0..saved.len() {
+            let mut reader = DirectionalBytesReader::new(&saved[..len], false);
+            assert!(FST::from_input(&mut reader, ByteSequenceOutputFactory {}).is_err());
+        }
+
+        // the nodes are read backward from the start node, the lookups in
+        // an FST missing its last bytes fail
+        assert!(loaded.use_bytes_array);
+        for cut in 1..loaded.bytes_array.len() {
+            let mut reader = DirectionalBytesReader::new(&saved, false);
+            let mut truncated = FST::from_input(&mut reader, ByteSequenceOutputFactory {}).unwrap();
+            let len = truncated.bytes_array.len() - cut;
+            truncated.bytes_array.truncate(len);
+            for word in &words {
+                assert!(truncated.get(word.as_bytes()).is_err());
+            }
+        }
+    }
 }
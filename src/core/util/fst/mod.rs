@@ -11,12 +11,13 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::cmp::min;
 use std::fmt::Debug;
 use std::hash::Hash;
 use std::io;
 
 use core::store::{DataInput, DataOutput};
-use error::Result;
+use error::{ErrorKind, Result};
 
 // pub mod builder;
 pub mod bytes_output;
@@ -26,6 +27,11 @@ pub mod fst_builder;
 pub mod fst_iteartor;
 pub mod fst_reader;
 pub use self::fst_reader::*;
+pub mod int_output;
+pub use self::int_output::{PositiveIntOutput, PositiveIntOutputFactory};
+pub mod pair_output;
+pub use self::pair_output::{PairOutput, PairOutputFactory};
+pub mod util;
 
 pub trait Output: Clone + Eq + Hash + Debug {
     type Value;
@@ -106,13 +112,19 @@ pub trait BytesReader: DataInput {
     fn reversed(&self) -> bool;
 }
 
+/// Reads the bytes of a slice forward, or backward from the position to the
+/// start of the slice for the `reversed` readers, as the nodes of an FST are.
+///
+/// The reads out of the slice fail with `CorruptIndex`, as they only happen
+/// with corrupted FSTs.
 pub struct DirectionalBytesReader {
     bytes: *const [u8],
-    pos: usize,
+    // the next position to read, -1 once a reversed reader read the first byte
+    pos: isize,
     pub reversed: bool,
 }
 
-impl<'a> DirectionalBytesReader {
+impl DirectionalBytesReader {
     pub fn new(bytes: &[u8], reversed: bool) -> DirectionalBytesReader {
         DirectionalBytesReader {
             bytes: bytes as *const [u8],
@@ -121,18 +133,47 @@ impl<'a> DirectionalBytesReader {
         }
     }
 
+    /// A reader of no bytes, all the reads fail.
+    pub fn empty() -> DirectionalBytesReader {
+        DirectionalBytesReader::new(&[], false)
+    }
+
     fn bytes_slice(&self) -> &[u8] {
         unsafe { &(*self.bytes) }
     }
+
+    // the number of bytes that can be read from the position
+    fn available(&self) -> usize {
+        let len = self.bytes_slice().len() as isize;
+        if self.pos < 0 || self.pos >= len {
+            0
+        } else if self.reversed {
+            self.pos as usize + 1
+        } else {
+            (len - self.pos) as usize
+        }
+    }
+
+    fn check_available(&self, count: usize) -> Result<()> {
+        if count > self.available() {
+            bail!(ErrorKind::CorruptIndex(format!(
+                "reading {} bytes at position {} is out of the {} bytes of the FST",
+                count,
+                self.pos,
+                self.bytes_slice().len()
+            )));
+        }
+        Ok(())
+    }
 }
 
 impl BytesReader for DirectionalBytesReader {
     fn position(&self) -> usize {
-        self.pos
+        self.pos as usize
     }
 
     fn set_position(&mut self, pos: usize) {
-        self.pos = pos
+        self.pos = pos as isize
     }
 
     fn reversed(&self) -> bool {
@@ -142,37 +183,60 @@ impl BytesReader for DirectionalBytesReader {
 
 impl io::Read for DirectionalBytesReader {
     fn read(&mut self, b: &mut [u8]) -> io::Result<usize> {
-        let mut len = b.len();
-        if self.reversed {
-            if len > self.pos {
-                len = self.pos
-            }
-            for v in b.iter_mut().take(len) {
-                *v = self.bytes_slice()[self.pos];
+        let len = min(b.len(), self.available());
+        for v in b.iter_mut().take(len) {
+            *v = self.bytes_slice()[self.pos as usize];
+            if self.reversed {
                 self.pos -= 1;
+            } else {
+                self.pos += 1;
             }
-        } else {
-            let available = self.bytes_slice().len() - self.pos;
-            if available < len {
-                len = available;
-            }
-            b[..len].clone_from_slice(&self.bytes_slice()[self.pos..len]);
-
-            self.pos += len;
         }
-
         Ok(len)
     }
 }
 
 impl DataInput for DirectionalBytesReader {
-    fn skip_bytes(&mut self, count: usize) -> Result<()> {
+    fn read_byte(&mut self) -> Result<u8> {
+        self.check_available(1)?;
+        let b = self.bytes_slice()[self.pos as usize];
+        if self.reversed {
+            self.pos -= 1;
+        } else {
+            self.pos += 1;
+        }
+        Ok(b)
+    }
+
+    fn read_bytes(&mut self, b: &mut [u8], offset: usize, len: usize) -> Result<()> {
+        if len == 0 {
+            return Ok(());
+        }
+        self.check_available(len)?;
+        let pos = self.pos as usize;
+        let target = &mut b[offset..offset + len];
         if self.reversed {
-            self.pos -= count;
+            for (i, v) in target.iter_mut().enumerate() {
+                *v = self.bytes_slice()[pos - i];
+            }
+            self.pos -= len as isize;
         } else {
-            self.pos += count;
+            target.copy_from_slice(&self.bytes_slice()[pos..pos + len]);
+            self.pos += len as isize;
         }
+        Ok(())
+    }
 
+    fn skip_bytes(&mut self, count: usize) -> Result<()> {
+        if count == 0 {
+            return Ok(());
+        }
+        self.check_available(count)?;
+        if self.reversed {
+            self.pos -= count as isize;
+        } else {
+            self.pos += count as isize;
+        }
         Ok(())
     }
 }
@@ -180,9 +244,80 @@ impl DataInput for DirectionalBytesReader {
 #[cfg(test)]
 pub mod tests {
     use super::*;
+    use core::util::tests::seeded_rng;
+
+    use rand::Rng;
     use std::io;
     use std::io::{Read, Write};
 
+    /// Checks random reads and skips of `reader` against `bytes`, the reads
+    /// out of the bytes must fail rather than panic.
+    pub fn check_random_reads<R: BytesReader>(
+        rng: &mut impl Rng,
+        reader: &mut R,
+        bytes: &[u8],
+    ) {
+        let len = bytes.len() as isize;
+        for _ in 0..1000 {
+            let mut pos = (rng.gen::<usize>() % (bytes.len() + 3)) as isize;
+            reader.set_position(pos as usize);
+            for _ in 0..10 {
+                let op = rng.gen::<u8>() % 3;
+                let count = if op == 0 {
+                    1
+                } else {
+                    rng.gen::<usize>() % 20
+                };
+                let in_bounds = count == 0
+                    || if reader.reversed() {
+                        pos < len && count as isize <= pos + 1
+                    } else {
+                        pos >= 0 && pos + count as isize <= len
+                    };
+                let expected: Vec<u8> = if !in_bounds {
+                    vec![]
+                } else if reader.reversed() {
+                    (0..count).map(|i| bytes[pos as usize - i]).collect()
+                } else {
+                    bytes[pos as usize..pos as usize + count].to_vec()
+                };
+
+                let result = match op {
+                    0 => reader.read_byte().map(|b| assert_eq!(b, expected[0])),
+                    1 => {
+                        let mut b = vec![0u8; count + 1];
+                        reader
+                            .read_bytes(&mut b, 1, count)
+                            .map(|_| assert_eq!(&b[1..], expected.as_slice()))
+                    }
+                    _ => reader.skip_bytes(count),
+                };
+                assert_eq!(result.is_ok(), in_bounds, "{} bytes at {}", count, pos);
+                if !in_bounds {
+                    break;
+                }
+                if reader.reversed() {
+                    pos -= count as isize;
+                } else {
+                    pos += count as isize;
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_directional_random_reads() {
+        let mut rng = seeded_rng();
+        let bytes: Vec<u8> = (0..300).map(|_| rng.gen::<u8>()).collect();
+        check_random_reads(&mut rng, &mut DirectionalBytesReader::new(&bytes, false), &bytes);
+        check_random_reads(&mut rng, &mut DirectionalBytesReader::new(&bytes, true), &bytes);
+
+        let mut empty = DirectionalBytesReader::empty();
+        assert!(empty.read_byte().is_err());
+        assert!(empty.skip_bytes(1).is_err());
+        empty.read_bytes(&mut [], 0, 0).unwrap();
+    }
+
     #[derive(Default)]
     pub struct TestBufferedDataIO {
         pub bytes: Vec<u8>,
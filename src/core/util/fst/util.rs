@@ -0,0 +1,295 @@
+// Copyright 2019 Zhizhesihai (Beijing) Technology Limited.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::cmp::Ordering;
+
+use core::util::fst::{Arc, Output, OutputFactory, END_LABEL, FST};
+use error::Result;
+
+/// Returns the `top_n` paths from the target node of `from_arc` to a final
+/// node with the minimal outputs according to `comparator`, the best first,
+/// see `TopNSearcher`. The outputs returned are `prior_output` plus the
+/// outputs of the path, the empty path is only returned with `allow_empty`
+/// if `from_arc` is final.
+pub fn shortest_paths<F, C>(
+    fst: &FST<F>,
+    from_arc: &Arc<F::Value>,
+    prior_output: F::Value,
+    comparator: C,
+    top_n: usize,
+    allow_empty: bool,
+) -> Result<Vec<(Vec<u8>, F::Value)>>
+where
+    F: OutputFactory,
+    C: Fn(&F::Value, &F::Value) -> Ordering,
+{
+    let mut searcher = TopNSearcher::new(fst, top_n, comparator);
+    searcher.add_start(from_arc.clone(), prior_output, allow_empty, Vec::new());
+    searcher.search()
+}
+
+/// Finds the `top_n` paths with the minimal outputs from a set of start
+/// nodes to the final nodes, the paths with equal outputs being ordered by
+/// their inputs. The inputs are returned as bytes, so the FST is expected to
+/// be of `InputType::Byte1`.
+///
+/// The paths are expanded best first and at most `top_n` of them are queued,
+/// which is exact as long as the output of a path is the minimal output of
+/// its completions: the `comparator` must never order an output before the
+/// one of its prefix, and the common output of two paths must be the minimal
+/// one, as with `PositiveIntOutputFactory` where the builder pushes the
+/// smallest output of the paths going through a node to the arcs leading to
+/// it. The FSTs have no cycles, so no cycle is checked.
+pub struct TopNSearcher<'a, F: OutputFactory + 'a, C> {
+    fst: &'a FST<F>,
+    top_n: usize,
+    comparator: C,
+    // the paths to expand, the best at the end
+    queue: Vec<FSTPath<F::Value>>,
+}
+
+// a path from a start node, with the last arc of it
+struct FSTPath<T: Output> {
+    arc: Arc<T>,
+    output: T,
+    input: Vec<u8>,
+    // whether the last arc was a final one, completing the path
+    complete: bool,
+    // whether the path may be completed right away
+    allow_empty: bool,
+}
+
+impl<'a, F, C> TopNSearcher<'a, F, C>
+where
+    F: OutputFactory + 'a,
+    C: Fn(&F::Value, &F::Value) -> Ordering,
+{
+    pub fn new(fst: &'a FST<F>, top_n: usize, comparator: C) -> TopNSearcher<'a, F, C> {
+        TopNSearcher {
+            fst,
+            top_n,
+            comparator,
+            queue: Vec::new(),
+        }
+    }
+
+    /// Searches the completions of the paths leading to the target node of
+    /// `arc`, whose output is `output` and input is `input`. The start node
+    /// is returned as a path itself if `allow_empty` and it is final.
+    pub fn add_start(
+        &mut self,
+        arc: Arc<F::Value>,
+        output: F::Value,
+        allow_empty: bool,
+        input: Vec<u8>,
+    ) {
+        self.push(FSTPath {
+            arc,
+            output,
+            input,
+            complete: false,
+            allow_empty,
+        });
+        self.prune(self.top_n);
+    }
+
+    /// Returns the inputs and outputs of the `top_n` best paths, the best
+    /// first.
+    pub fn search(mut self) -> Result<Vec<(Vec<u8>, F::Value)>> {
+        let mut results = Vec::with_capacity(self.top_n);
+        let mut reader = self.fst.bytes_reader();
+        while results.len() < self.top_n {
+            let path = match self.queue.pop() {
+                Some(path) => path,
+                None => break,
+            };
+            if path.complete {
+                results.push((path.input, path.output));
+                continue;
+            }
+
+            let mut arc = self.fst.read_first_target_arc(&path.arc, &mut reader)?;
+            loop {
+                if arc.label != END_LABEL || path.allow_empty {
+                    let output = match arc.output {
+                        Some(ref out) => self.fst.outputs().add(&path.output, out),
+                        None => path.output.clone(),
+                    };
+                    let mut input = path.input.clone();
+                    if arc.label != END_LABEL {
+                        input.push(arc.label as u8);
+                    }
+                    self.push(FSTPath {
+                        complete: arc.label == END_LABEL,
+                        arc: arc.clone(),
+                        output,
+                        input,
+                        allow_empty: true,
+                    });
+                }
+                if arc.is_last() {
+                    break;
+                }
+                self.fst.read_next_arc(&mut arc, &mut reader)?;
+            }
+            // every queued path leads to a distinct completion as good as
+            // itself, so the paths after the remaining ones are useless
+            let remaining = self.top_n - results.len();
+            self.prune(remaining);
+        }
+        Ok(results)
+    }
+
+    // the order of the paths, the better ones first
+    fn compare(&self, a: &FSTPath<F::Value>, b: &FSTPath<F::Value>) -> Ordering {
+        (self.comparator)(&a.output, &b.output)
+            .then_with(|| a.input.cmp(&b.input))
+            .then_with(|| b.complete.cmp(&a.complete))
+    }
+
+    fn push(&mut self, path: FSTPath<F::Value>) {
+        let pos = match self
+            .queue
+            .binary_search_by(|other| self.compare(&path, other))
+        {
+            Ok(pos) | Err(pos) => pos,
+        };
+        self.queue.insert(pos, path);
+    }
+
+    fn prune(&mut self, size: usize) {
+        if self.queue.len() > size {
+            let excess = self.queue.len() - size;
+            self.queue.drain(..excess);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::util::fst::fst_builder::FstBuilder;
+    use core::util::fst::{InputType, PositiveIntOutput, PositiveIntOutputFactory};
+
+    fn build(entries: &[(&str, u64)]) -> FST<PositiveIntOutputFactory> {
+        let mut entries = entries.to_vec();
+        entries.sort();
+        let mut builder = FstBuilder::new(InputType::Byte1, PositiveIntOutputFactory::new());
+        builder.init();
+        for &(input, output) in &entries {
+            builder
+                .add_bytes(input.as_bytes(), PositiveIntOutput::new(output))
+                .unwrap();
+        }
+        builder.finish().unwrap().unwrap()
+    }
+
+    // the entries starting with `prefix` sorted by output then input
+    fn exhaustive(entries: &[(&str, u64)], prefix: &str, top_n: usize) -> Vec<(Vec<u8>, u64)> {
+        let mut expected: Vec<_> = entries
+            .iter()
+            .filter(|e| e.0.starts_with(prefix))
+            .map(|e| (e.1, e.0[prefix.len()..].as_bytes().to_vec()))
+            .collect();
+        expected.sort();
+        expected
+            .into_iter()
+            .take(top_n)
+            .map(|(output, input)| (input, output))
+            .collect()
+    }
+
+    fn by_output(a: &PositiveIntOutput, b: &PositiveIntOutput) -> Ordering {
+        a.get().cmp(&b.get())
+    }
+
+    fn paths(
+        fst: &FST<PositiveIntOutputFactory>,
+        prefix: &str,
+        top_n: usize,
+        allow_empty: bool,
+    ) -> Vec<(Vec<u8>, u64)> {
+        let mut arc = fst.root_arc();
+        let mut output = PositiveIntOutput::new(0);
+        let mut reader = fst.bytes_reader();
+        for &b in prefix.as_bytes() {
+            arc = match fst
+                .find_target_arc(i32::from(b), &arc, &mut reader)
+                .unwrap()
+            {
+                Some(arc) => arc,
+                None => return Vec::new(),
+            };
+            if let Some(ref out) = arc.output {
+                output = output.cat(out);
+            }
+        }
+        shortest_paths(fst, &arc, output, by_output, top_n, allow_empty)
+            .unwrap()
+            .into_iter()
+            .map(|(input, output)| (input, output.get()))
+            .collect()
+    }
+
+    #[test]
+    fn test_shortest_paths() {
+        let mut entries = vec![
+            ("cat", 12),
+            ("cats", 3),
+            ("dog", 7),
+            ("dogs", 7),
+            ("doge", 1),
+            ("dot", 7),
+            ("do", 20),
+            ("zebra", 0),
+            ("a", 5),
+        ];
+        let words: Vec<String> = (0..500u64).map(|i| format!("w{}x{}", i % 17, i)).collect();
+        for (i, word) in words.iter().enumerate() {
+            entries.push((word.as_str(), (i as u64 * 31) % 50));
+        }
+        let fst = build(&entries);
+
+        for prefix in &["", "d", "do", "dog", "ca", "w", "w1", "w3x", "q"] {
+            for &top_n in &[1, 2, 3, 10, 1000] {
+                assert_eq!(
+                    paths(&fst, prefix, top_n, true),
+                    exhaustive(&entries, prefix, top_n),
+                    "prefix {} top {}",
+                    prefix,
+                    top_n
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_empty_path() {
+        let entries = [("do", 1), ("dog", 5), ("dot", 5), ("dots", 2)];
+        let fst = build(&entries);
+
+        let with_empty = paths(&fst, "do", 10, true);
+        assert_eq!(
+            with_empty,
+            vec![
+                (vec![], 1),
+                (b"ts".to_vec(), 2),
+                (b"g".to_vec(), 5),
+                (b"t".to_vec(), 5),
+            ]
+        );
+        let without_empty = paths(&fst, "do", 10, false);
+        assert_eq!(without_empty, with_empty[1..].to_vec());
+        assert!(paths(&fst, "do", 0, true).is_empty());
+    }
+}
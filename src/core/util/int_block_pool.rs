@@ -30,6 +30,8 @@ const NEXT_LEVEL_ARRAY: [usize; 10] = [1, 2, 3, 4, 5, 6, 7, 8, 9, 9];
 /// An array holding the level sizes for byte slices.
 const LEVEL_SIZE_ARRAY: [usize; 10] = [2, 4, 8, 16, 32, 64, 128, 256, 512, 1024];
 
+const FIRST_LEVEL_SIZE: usize = LEVEL_SIZE_ARRAY[0];
+
 /// Class that Posting and PostingVector use to write byte
 /// streams into shared fixed-size byte[] arrays.  The idea
 /// is to allocate slices of increasing lengths For
@@ -159,11 +161,14 @@ impl IntBlockPool {
         upto
     }
 
-    /// Creates a new byte slice with the given starting size and
-    /// returns the slices offset in the pool.
-    pub fn alloc_slice(&mut self, slice: &mut [usize], slice_offset: usize) -> usize {
-        let level = slice[slice_offset];
-        let new_level = NEXT_LEVEL_ARRAY[level - 1];
+    /// Allocates the slice following the one whose end is at `upto` in the
+    /// buffer `buffer_index`, writing the forwarding address of the new slice
+    /// in place of the level of the old one, and returns the offset of the
+    /// new slice in the current buffer.
+    pub fn alloc_slice(&mut self, buffer_index: usize, upto: usize) -> usize {
+        // the level is stored plus one since 0 means within a slice
+        let level = self.buffers[buffer_index][upto] as usize - 1;
+        let new_level = NEXT_LEVEL_ARRAY[level];
         let new_size = LEVEL_SIZE_ARRAY[new_level];
 
         // Maybe allocate another block
@@ -172,19 +177,126 @@ impl IntBlockPool {
         }
 
         let new_upto = self.int_upto;
-        let offset = (new_upto as isize + self.int_offset) as usize;
+        let offset = new_upto as isize + self.int_offset;
         self.int_upto += new_size;
 
         // Write forwarding address at end of last slice:
-        slice[slice_offset] = offset;
+        self.buffers[buffer_index][upto] = offset as i32;
 
         // Write new level
-        self.buffers[self.buffer_upto as usize][self.int_upto - 1] = new_level as i32;
+        self.buffers[self.buffer_upto as usize][self.int_upto - 1] = new_level as i32 + 1;
 
         new_upto
     }
 }
 
+/// Writes ints into the slices of an `IntBlockPool`, a slice being continued
+/// in a larger one allocated once its end is reached. The offsets are
+/// absolute offsets in the pool.
+#[derive(Default)]
+pub struct IntSliceWriter {
+    offset: usize,
+}
+
+impl IntSliceWriter {
+    /// Continues writing at the given offset of a slice.
+    pub fn reset(&mut self, slice_offset: usize) {
+        self.offset = slice_offset;
+    }
+
+    pub fn write_int(&mut self, pool: &mut IntBlockPool, value: i32) {
+        let mut buffer_index = self.offset >> INT_BLOCK_SHIFT;
+        let mut relative_offset = self.offset & INT_BLOCK_MASK;
+        if pool.buffers[buffer_index][relative_offset] != 0 {
+            // End of slice; allocate a new one
+            relative_offset = pool.alloc_slice(buffer_index, relative_offset);
+            buffer_index = pool.buffer_upto as usize;
+            self.offset = (relative_offset as isize + pool.int_offset) as usize;
+        }
+        pool.buffers[buffer_index][relative_offset] = value;
+        self.offset += 1;
+    }
+
+    /// Starts a new slice and returns its offset.
+    pub fn start_new_slice(&mut self, pool: &mut IntBlockPool) -> usize {
+        let upto = pool.new_slice(FIRST_LEVEL_SIZE);
+        self.offset = (upto as isize + pool.int_offset) as usize;
+        self.offset
+    }
+
+    /// Returns the offset the next int will be written at, which is the end
+    /// offset of the slice for `IntSliceReader::reset`.
+    pub fn current_offset(&self) -> usize {
+        self.offset
+    }
+}
+
+/// Reads the ints written by an `IntSliceWriter`, following the forwarding
+/// addresses from a slice to the next.
+#[derive(Default)]
+pub struct IntSliceReader {
+    upto: usize,
+    buffer_upto: usize,
+    buffer_offset: usize,
+    limit: usize,
+    level: usize,
+    end: usize,
+}
+
+impl IntSliceReader {
+    /// Starts reading the slice from `start_offset` up to `end_offset`.
+    pub fn reset(&mut self, start_offset: usize, end_offset: usize) {
+        debug_assert!(start_offset <= end_offset);
+        self.buffer_upto = start_offset >> INT_BLOCK_SHIFT;
+        self.buffer_offset = self.buffer_upto * INT_BLOCK_SIZE;
+        self.end = end_offset;
+        self.level = 0;
+        self.upto = start_offset & INT_BLOCK_MASK;
+
+        self.limit = if start_offset + FIRST_LEVEL_SIZE >= end_offset {
+            // There is only this one slice to read
+            end_offset - self.buffer_offset
+        } else {
+            self.upto + FIRST_LEVEL_SIZE - 1
+        };
+    }
+
+    /// Returns true if all the ints of the slice were read.
+    pub fn end_of_slice(&self) -> bool {
+        debug_assert!(self.upto + self.buffer_offset <= self.end);
+        self.upto + self.buffer_offset == self.end
+    }
+
+    pub fn read_int(&mut self, pool: &IntBlockPool) -> i32 {
+        debug_assert!(!self.end_of_slice());
+        if self.upto == self.limit {
+            self.next_slice(pool);
+        }
+        let value = pool.buffers[self.buffer_upto][self.upto];
+        self.upto += 1;
+        value
+    }
+
+    fn next_slice(&mut self, pool: &IntBlockPool) {
+        let next_index = pool.buffers[self.buffer_upto][self.limit] as usize;
+        self.level = NEXT_LEVEL_ARRAY[self.level];
+        let new_size = LEVEL_SIZE_ARRAY[self.level];
+
+        self.buffer_upto = next_index >> INT_BLOCK_SHIFT;
+        self.buffer_offset = self.buffer_upto * INT_BLOCK_SIZE;
+        self.upto = next_index & INT_BLOCK_MASK;
+
+        self.limit = if next_index + new_size >= self.end {
+            // We are advancing to the final slice
+            self.end - self.buffer_offset
+        } else {
+            // This is not the final slice (subtract 1 for the forwarding
+            // address at the end of this new slice)
+            self.upto + new_size - 1
+        };
+    }
+}
+
 /// Abstract class for allocating and freeing byte blocks
 pub trait IntAllocator {
     fn block_size(&self) -> usize;
@@ -227,3 +339,71 @@ impl IntAllocator for DirectIntAllocator {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::util::tests::seeded_rng;
+
+    use rand::Rng;
+
+    // writes interleaved streams of ints as the postings do, then reads them
+    fn check_streams(pool: &mut IntBlockPool, num_streams: usize) {
+        let mut rng = seeded_rng();
+        let mut writer = IntSliceWriter::default();
+        let mut starts = Vec::with_capacity(num_streams);
+        let mut ends = Vec::with_capacity(num_streams);
+        let mut values = vec![Vec::new(); num_streams];
+        for _ in 0..num_streams {
+            let start = writer.start_new_slice(pool);
+            starts.push(start);
+            ends.push(start);
+        }
+        for _ in 0..num_streams * 500 {
+            let stream = rng.gen_range(0, num_streams);
+            let value: i32 = rng.gen();
+            writer.reset(ends[stream]);
+            writer.write_int(pool, value);
+            ends[stream] = writer.current_offset();
+            values[stream].push(value);
+        }
+
+        let mut reader = IntSliceReader::default();
+        for stream in 0..num_streams {
+            reader.reset(starts[stream], ends[stream]);
+            for &value in &values[stream] {
+                assert!(!reader.end_of_slice());
+                assert_eq!(reader.read_int(pool), value);
+            }
+            assert!(reader.end_of_slice());
+        }
+    }
+
+    #[test]
+    fn test_single_slice() {
+        let mut pool = IntBlockPool::new(Box::new(DirectIntAllocator::default()));
+        let mut writer = IntSliceWriter::default();
+        let start = writer.start_new_slice(&mut pool);
+        let count = 3 * INT_BLOCK_SIZE;
+        for i in 0..count {
+            writer.write_int(&mut pool, i as i32);
+        }
+
+        let mut reader = IntSliceReader::default();
+        reader.reset(start, writer.current_offset());
+        for i in 0..count {
+            assert_eq!(reader.read_int(&pool), i as i32);
+        }
+        assert!(reader.end_of_slice());
+    }
+
+    #[test]
+    fn test_interleaved_slices() {
+        let mut pool = IntBlockPool::new(Box::new(DirectIntAllocator::default()));
+        for &num_streams in &[1, 2, 10, 100] {
+            check_streams(&mut pool, num_streams);
+            // the slices rely on the buffers being zero filled
+            pool.reset(true, true);
+        }
+    }
+}
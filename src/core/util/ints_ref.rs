@@ -118,7 +118,7 @@ impl Ord for IntsRef {
                 return c;
             }
         }
-        (self.length - other.length).cmp(&0)
+        self.length.cmp(&other.length)
     }
 }
 
@@ -0,0 +1,366 @@
+// Copyright 2019 Zhizhesihai (Beijing) Technology Limited.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Helpers for the legacy (pre-points) trie encoding of numeric values.
+//!
+//! Legacy numeric fields index every value as several terms, one for each
+//! precision step. Each term is prefix coded: the first byte holds the
+//! shift of the value, followed by the remaining bits of the value, 7 bits
+//! per byte so that the terms sort in the same order as the numbers.
+
+use error::ErrorKind::{IllegalArgument, IllegalState};
+use error::Result;
+
+/// The default precision step used by legacy long and double fields.
+pub const PRECISION_STEP_DEFAULT: i32 = 16;
+
+/// The default precision step used by legacy int and float fields.
+pub const PRECISION_STEP_DEFAULT_32: i32 = 8;
+
+/// Longs are stored at lower precision by shifting off lower bits. The shift
+/// is stored as `SHIFT_START_LONG + shift` in the first byte.
+pub const SHIFT_START_LONG: u8 = 0x20;
+
+/// The maximum term length (used for byte buffer allocation) of a prefix coded long.
+pub const BUF_SIZE_LONG: usize = 63 / 7 + 2;
+
+/// Integers are stored at lower precision by shifting off lower bits. The shift
+/// is stored as `SHIFT_START_INT + shift` in the first byte.
+pub const SHIFT_START_INT: u8 = 0x60;
+
+/// The maximum term length (used for byte buffer allocation) of a prefix coded int.
+pub const BUF_SIZE_INT: usize = 31 / 7 + 2;
+
+/// Returns prefix coded bits after reducing the precision by `shift` bits.
+pub fn long_to_prefix_coded(val: i64, shift: u32) -> Vec<u8> {
+    assert!(
+        shift < 64,
+        "Illegal shift value, must be 0..63; got shift={}",
+        shift
+    );
+    // i / 7 is the same as (i * 37) >> 8 for i in 0..63
+    let mut n_chars = ((((63 - shift) * 37) >> 8) + 1) as usize;
+    let mut bytes = vec![0u8; n_chars + 1];
+    bytes[0] = SHIFT_START_LONG + shift as u8;
+    let mut sortable_bits = ((val as u64) ^ 0x8000_0000_0000_0000) >> shift;
+    while n_chars > 0 {
+        // Store 7 bits per byte for compatibility with UTF-8 encoding of terms
+        bytes[n_chars] = (sortable_bits & 0x7f) as u8;
+        sortable_bits >>= 7;
+        n_chars -= 1;
+    }
+    bytes
+}
+
+/// Returns prefix coded bits after reducing the precision by `shift` bits.
+pub fn int_to_prefix_coded(val: i32, shift: u32) -> Vec<u8> {
+    assert!(
+        shift < 32,
+        "Illegal shift value, must be 0..31; got shift={}",
+        shift
+    );
+    // i / 7 is the same as (i * 37) >> 8 for i in 0..31
+    let mut n_chars = ((((31 - shift) * 37) >> 8) + 1) as usize;
+    let mut bytes = vec![0u8; n_chars + 1];
+    bytes[0] = SHIFT_START_INT + shift as u8;
+    let mut sortable_bits = ((val as u32) ^ 0x8000_0000) >> shift;
+    while n_chars > 0 {
+        bytes[n_chars] = (sortable_bits & 0x7f) as u8;
+        sortable_bits >>= 7;
+        n_chars -= 1;
+    }
+    bytes
+}
+
+/// Returns the shift value from a prefix encoded long.
+pub fn prefix_coded_long_shift(val: &[u8]) -> Result<u32> {
+    if val.is_empty() {
+        bail!(IllegalArgument("empty prefix coded long".into()));
+    }
+    let shift = i32::from(val[0]) - i32::from(SHIFT_START_LONG);
+    if shift < 0 || shift > 63 {
+        bail!(IllegalState(format!(
+            "Invalid shift value ({}) in prefix coded bytes (is encoded value really a LONG?)",
+            shift
+        )));
+    }
+    Ok(shift as u32)
+}
+
+/// Returns the shift value from a prefix encoded int.
+pub fn prefix_coded_int_shift(val: &[u8]) -> Result<u32> {
+    if val.is_empty() {
+        bail!(IllegalArgument("empty prefix coded int".into()));
+    }
+    let shift = i32::from(val[0]) - i32::from(SHIFT_START_INT);
+    if shift < 0 || shift > 31 {
+        bail!(IllegalState(format!(
+            "Invalid shift value ({}) in prefix coded bytes (is encoded value really an INT?)",
+            shift
+        )));
+    }
+    Ok(shift as u32)
+}
+
+/// Returns a long from prefix coded bytes. Rightmost bits will be zero for lower
+/// precision codes.
+pub fn prefix_coded_to_long(val: &[u8]) -> Result<i64> {
+    let shift = prefix_coded_long_shift(val)?;
+    let mut sortable_bits = 0u64;
+    for (i, &b) in val.iter().enumerate().skip(1) {
+        if b & 0x80 != 0 {
+            bail!(IllegalState(format!(
+                "Invalid prefix coded numerical value representation (byte {:x} at position {} is \
+                 invalid)",
+                b, i
+            )));
+        }
+        sortable_bits = (sortable_bits << 7) | u64::from(b);
+    }
+    Ok(((sortable_bits << shift) ^ 0x8000_0000_0000_0000) as i64)
+}
+
+/// Returns an int from prefix coded bytes. Rightmost bits will be zero for lower
+/// precision codes.
+pub fn prefix_coded_to_int(val: &[u8]) -> Result<i32> {
+    let shift = prefix_coded_int_shift(val)?;
+    let mut sortable_bits = 0u32;
+    for (i, &b) in val.iter().enumerate().skip(1) {
+        if b & 0x80 != 0 {
+            bail!(IllegalState(format!(
+                "Invalid prefix coded numerical value representation (byte {:x} at position {} is \
+                 invalid)",
+                b, i
+            )));
+        }
+        sortable_bits = (sortable_bits << 7) | u32::from(b);
+    }
+    Ok(((sortable_bits << shift) ^ 0x8000_0000) as i32)
+}
+
+/// Returns the size in bits (32 or 64) of the value encoded in the prefix coded
+/// `term`, or `None` if the term is not a legacy numeric term.
+pub fn prefix_coded_value_size(term: &[u8]) -> Option<u32> {
+    match term.first() {
+        Some(&b) if b >= SHIFT_START_LONG && b < SHIFT_START_LONG + 64 => Some(64),
+        Some(&b) if b >= SHIFT_START_INT && b < SHIFT_START_INT + 32 => Some(32),
+        _ => None,
+    }
+}
+
+/// Splits a long range recursively. The callback is called for every sub range
+/// with the prefix coded lower and upper bound (both inclusive).
+///
+/// This method is used by `LegacyNumericRangeQuery`.
+pub fn split_long_range<F>(precision_step: i32, min_bound: i64, max_bound: i64, mut f: F)
+where
+    F: FnMut(Vec<u8>, Vec<u8>),
+{
+    split_range(
+        64,
+        precision_step,
+        min_bound,
+        max_bound,
+        &mut |min: i64, max: i64, shift: u32| {
+            f(
+                long_to_prefix_coded(min, shift),
+                long_to_prefix_coded(max, shift),
+            )
+        },
+    );
+}
+
+/// Splits an int range recursively. The callback is called for every sub range
+/// with the prefix coded lower and upper bound (both inclusive).
+///
+/// This method is used by `LegacyNumericRangeQuery`.
+pub fn split_int_range<F>(precision_step: i32, min_bound: i32, max_bound: i32, mut f: F)
+where
+    F: FnMut(Vec<u8>, Vec<u8>),
+{
+    split_range(
+        32,
+        precision_step,
+        i64::from(min_bound),
+        i64::from(max_bound),
+        &mut |min: i64, max: i64, shift: u32| {
+            f(
+                int_to_prefix_coded(min as i32, shift),
+                int_to_prefix_coded(max as i32, shift),
+            )
+        },
+    );
+}
+
+fn split_range(
+    val_size: i32,
+    precision_step: i32,
+    mut min_bound: i64,
+    mut max_bound: i64,
+    add_range: &mut dyn FnMut(i64, i64, u32),
+) {
+    assert!(precision_step >= 1, "precision_step must be >= 1");
+    if min_bound > max_bound {
+        return;
+    }
+
+    let mut shift = 0i32;
+    loop {
+        // calculate new bounds for inner precision
+        let next_shift = shift.saturating_add(precision_step);
+        let diff = 1i64.wrapping_shl(next_shift as u32);
+        let mask = 1i64
+            .wrapping_shl(precision_step as u32)
+            .wrapping_sub(1)
+            .wrapping_shl(shift as u32);
+        let has_lower = (min_bound & mask) != 0;
+        let has_upper = (max_bound & mask) != mask;
+        let next_min_bound = (if has_lower {
+            min_bound.wrapping_add(diff)
+        } else {
+            min_bound
+        }) & !mask;
+        let next_max_bound = (if has_upper {
+            max_bound.wrapping_sub(diff)
+        } else {
+            max_bound
+        }) & !mask;
+        let lower_wrapped = next_min_bound < min_bound;
+        let upper_wrapped = next_max_bound > max_bound;
+
+        if next_shift >= val_size
+            || next_min_bound > next_max_bound
+            || lower_wrapped
+            || upper_wrapped
+        {
+            // We are in the lowest precision or the next precision is not available.
+            add_split_range(add_range, min_bound, max_bound, shift as u32);
+            break;
+        }
+
+        if has_lower {
+            add_split_range(add_range, min_bound, min_bound | mask, shift as u32);
+        }
+        if has_upper {
+            add_split_range(add_range, max_bound & !mask, max_bound, shift as u32);
+        }
+
+        // recurse to next precision
+        min_bound = next_min_bound;
+        max_bound = next_max_bound;
+        shift = next_shift;
+    }
+}
+
+fn add_split_range(
+    add_range: &mut dyn FnMut(i64, i64, u32),
+    min_bound: i64,
+    max_bound: i64,
+    shift: u32,
+) {
+    // for the max bound set all lower bits (that were shifted away), the prefix
+    // encoding will remove the bits anyway
+    add_range(
+        min_bound,
+        max_bound | (1i64 << shift).wrapping_sub(1),
+        shift,
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_long_prefix_coded_round_trip() {
+        let values = [
+            i64::min_value(),
+            -1_234_567_890_123,
+            -1,
+            0,
+            1,
+            42,
+            9_876_543_210,
+            i64::max_value(),
+        ];
+        for &v in &values {
+            let bytes = long_to_prefix_coded(v, 0);
+            assert_eq!(bytes.len(), BUF_SIZE_LONG);
+            assert_eq!(prefix_coded_long_shift(&bytes).unwrap(), 0);
+            assert_eq!(prefix_coded_to_long(&bytes).unwrap(), v);
+            assert_eq!(prefix_coded_value_size(&bytes), Some(64));
+
+            let bytes = long_to_prefix_coded(v, 16);
+            assert_eq!(prefix_coded_long_shift(&bytes).unwrap(), 16);
+            assert_eq!(prefix_coded_to_long(&bytes).unwrap(), v & !0xffff);
+        }
+
+        // the encoded terms sort in the same order as the values
+        for pair in values.windows(2) {
+            assert!(long_to_prefix_coded(pair[0], 0) < long_to_prefix_coded(pair[1], 0));
+        }
+    }
+
+    #[test]
+    fn test_int_prefix_coded_round_trip() {
+        let values = [i32::min_value(), -65_536, -1, 0, 1, 255, i32::max_value()];
+        for &v in &values {
+            let bytes = int_to_prefix_coded(v, 0);
+            assert_eq!(bytes.len(), BUF_SIZE_INT);
+            assert_eq!(prefix_coded_to_int(&bytes).unwrap(), v);
+            assert_eq!(prefix_coded_value_size(&bytes), Some(32));
+            assert!(prefix_coded_long_shift(&bytes).is_err());
+        }
+        for pair in values.windows(2) {
+            assert!(int_to_prefix_coded(pair[0], 8) <= int_to_prefix_coded(pair[1], 8));
+        }
+    }
+
+    #[test]
+    fn test_split_long_range() {
+        let (lower, upper) = (-5000i64, 9_999_999i64);
+        let mut ranges = vec![];
+        split_long_range(8, lower, upper, |min, max| ranges.push((min, max)));
+        assert!(!ranges.is_empty());
+
+        // every value in the range is covered by exactly one sub range,
+        // values outside the range are not covered at all
+        let covered = |v: i64| {
+            ranges
+                .iter()
+                .filter(|&&(ref min, ref max)| {
+                    let shift = prefix_coded_long_shift(min).unwrap();
+                    let term = long_to_prefix_coded(v, shift);
+                    &term >= min && &term <= max
+                })
+                .count()
+        };
+        for &v in &[lower, lower + 1, 0, 255, 256, 65_535, upper - 1, upper] {
+            assert_eq!(covered(v), 1, "value {}", v);
+        }
+        for &v in &[lower - 1, upper + 1, i64::min_value(), i64::max_value()] {
+            assert_eq!(covered(v), 0, "value {}", v);
+        }
+    }
+
+    #[test]
+    fn test_split_int_range_full() {
+        let mut ranges = vec![];
+        split_int_range(4, i32::min_value(), i32::max_value(), |min, max| {
+            ranges.push((min, max))
+        });
+        // the full range is covered by a single range at the lowest precision
+        assert_eq!(ranges.len(), 1);
+        assert_eq!(prefix_coded_int_shift(&ranges[0].0).unwrap(), 28);
+    }
+}
@@ -0,0 +1,476 @@
+// Copyright 2019 Zhizhesihai (Beijing) Technology Limited.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use error::ErrorKind::IllegalArgument;
+use error::Result;
+
+use std::cmp::min;
+use std::collections::HashMap;
+
+/// The largest edit distance `LevenshteinAutomata` builds automata for.
+pub const MAXIMUM_SUPPORTED_DISTANCE: u32 = 2;
+
+/// The state without any accepted continuation.
+pub const DEAD_STATE: usize = 0;
+
+const START_STATE: usize = 1;
+
+/// Builds the deterministic automata accepting the strings within an edit
+/// distance of a word.
+///
+/// The edits are insertions, deletions and substitutions of unicode code
+/// points, and optionally transpositions of two adjacent code points, which
+/// count as a single edit as in Lucene.
+///
+/// A state of an automaton for distance `n` is parametrized by the distances
+/// between the input read so far and every prefix of the word, capped at
+/// `n + 1`, so that the number of states only depends on the length of the
+/// word and `n`. The code points that are not in the word all behave the same
+/// and share a transition.
+pub struct LevenshteinAutomata {
+    word: Vec<char>,
+    // the distinct code points of the word, sorted
+    alphabet: Vec<char>,
+    transpositions: bool,
+}
+
+impl LevenshteinAutomata {
+    pub fn new(word: &str, transpositions: bool) -> LevenshteinAutomata {
+        let word: Vec<char> = word.chars().collect();
+        let mut alphabet = word.clone();
+        alphabet.sort();
+        alphabet.dedup();
+        LevenshteinAutomata {
+            word,
+            alphabet,
+            transpositions,
+        }
+    }
+
+    /// Builds the automaton accepting the strings within `n` edits of the word.
+    pub fn to_automaton(&self, n: u32) -> Result<LevenshteinDfa> {
+        if n > MAXIMUM_SUPPORTED_DISTANCE {
+            bail!(IllegalArgument(format!(
+                "max edits must be at most {}, got {}",
+                MAXIMUM_SUPPORTED_DISTANCE, n
+            )));
+        }
+        let n = n as u8;
+        let classes = self.alphabet.len() + 1;
+        let start = self.start_state(n);
+
+        // the dead state goes to itself
+        let mut transitions = vec![DEAD_STATE; classes];
+        let mut distances = vec![None];
+        let mut ids = HashMap::new();
+        let mut pending = vec![];
+        ids.insert(start.clone(), START_STATE);
+        distances.push(start.distance(self.word.len(), n));
+        pending.push(start);
+
+        let mut next_state = 0;
+        while next_state < pending.len() {
+            let state = pending[next_state].clone();
+            next_state += 1;
+            for class in 0..classes {
+                let c = self.alphabet.get(class).cloned();
+                let target = self.step(&state, c, n);
+                let id = if target.is_dead(n) {
+                    DEAD_STATE
+                } else if let Some(&id) = ids.get(&target) {
+                    id
+                } else {
+                    let id = ids.len() + 1;
+                    ids.insert(target.clone(), id);
+                    distances.push(target.distance(self.word.len(), n));
+                    pending.push(target);
+                    id
+                };
+                transitions.push(id);
+            }
+        }
+
+        Ok(LevenshteinDfa {
+            alphabet: self.alphabet.clone(),
+            transitions,
+            distances,
+        })
+    }
+
+    fn start_state(&self, n: u8) -> ParametricState {
+        let len = self.word.len();
+        ParametricState {
+            distances: (0..=len).map(|j| min(j, n as usize + 1) as u8).collect(),
+            transposed: vec![n + 1; len + 1],
+        }
+    }
+
+    // reads `c`, `None` standing for any code point not in the word
+    fn step(&self, state: &ParametricState, c: Option<char>, n: u8) -> ParametricState {
+        let len = self.word.len();
+        let cap = n + 1;
+        let is_c = |j: usize| c.map_or(false, |c| self.word[j] == c);
+        let mut distances = Vec::with_capacity(len + 1);
+        distances.push(min(state.distances[0] + 1, cap));
+        for j in 1..=len {
+            let cost = if is_c(j - 1) { 0 } else { 1 };
+            let mut d = min(
+                min(state.distances[j] + 1, distances[j - 1] + 1),
+                state.distances[j - 1] + cost,
+            );
+            if self.transpositions && j >= 2 && is_c(j - 2) {
+                d = min(d, state.transposed[j] + 1);
+            }
+            distances.push(min(d, cap));
+        }
+        // the distances to swap with the next code point, which has to be the
+        // code point before the one just read in the word
+        let mut transposed = vec![cap; len + 1];
+        if self.transpositions {
+            for j in 2..=len {
+                if is_c(j - 1) && state.distances[j - 2] < n {
+                    transposed[j] = state.distances[j - 2];
+                }
+            }
+        }
+        ParametricState {
+            distances,
+            transposed,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct ParametricState {
+    // the distances between the input and each prefix of the word
+    distances: Vec<u8>,
+    // the distances before the last code point, where the next one can be
+    // swapped with it
+    transposed: Vec<u8>,
+}
+
+impl ParametricState {
+    // no distance can decrease once they are all over `n`
+    fn is_dead(&self, n: u8) -> bool {
+        self.distances.iter().all(|&d| d > n)
+    }
+
+    fn distance(&self, len: usize, n: u8) -> Option<u32> {
+        let d = self.distances[len];
+        if d <= n {
+            Some(u32::from(d))
+        } else {
+            None
+        }
+    }
+}
+
+/// A deterministic automaton built by `LevenshteinAutomata`.
+pub struct LevenshteinDfa {
+    alphabet: Vec<char>,
+    // the targets of the states for every code point of the alphabet, then for
+    // the code points not in it
+    transitions: Vec<usize>,
+    // the edit distance to the word of the accepted states
+    distances: Vec<Option<u32>>,
+}
+
+impl LevenshteinDfa {
+    pub fn start(&self) -> usize {
+        START_STATE
+    }
+
+    pub fn step(&self, state: usize, c: char) -> usize {
+        let class = match self.alphabet.binary_search(&c) {
+            Ok(class) => class,
+            Err(_) => self.alphabet.len(),
+        };
+        self.transitions[state * (self.alphabet.len() + 1) + class]
+    }
+
+    /// Returns the edit distance to the word if `state` is accepted.
+    pub fn distance(&self, state: usize) -> Option<u32> {
+        self.distances[state]
+    }
+
+    /// Returns the edit distance between `text` and the word, `None` if it is
+    /// more than the distance of the automaton.
+    pub fn run(&self, text: &str) -> Option<u32> {
+        let mut state = self.start();
+        for c in text.chars() {
+            state = self.step(state, c);
+            if state == DEAD_STATE {
+                return None;
+            }
+        }
+        self.distance(state)
+    }
+
+    pub fn num_states(&self) -> usize {
+        self.distances.len()
+    }
+
+    /// Runs the automaton on `text`, a string of a sorted enumeration, and
+    /// tells where the enumeration may find the next accepted string.
+    pub fn intersect(&self, text: &str) -> DfaMatch {
+        let chars: Vec<char> = text.chars().collect();
+        let mut states = Vec::with_capacity(chars.len() + 1);
+        states.push(self.start());
+        for &c in &chars {
+            let state = self.step(states[states.len() - 1], c);
+            if state == DEAD_STATE {
+                break;
+            }
+            states.push(state);
+        }
+        if states.len() > chars.len() {
+            return match self.distance(states[chars.len()]) {
+                Some(edits) => DfaMatch::Accepted(edits),
+                None => DfaMatch::Rejected,
+            };
+        }
+        // the code point at `states.len() - 1` leads to the dead state, the next
+        // string with a live prefix changes it or one of the code points before
+        for i in (0..states.len()).rev() {
+            if let Some(c) = self.next_live_char(states[i], chars[i]) {
+                let mut next: String = chars[..i].iter().collect();
+                next.push(c);
+                return DfaMatch::SeekTo(next);
+            }
+        }
+        DfaMatch::Exhausted
+    }
+
+    // the smallest code point after `after` leading `state` to a live state
+    fn next_live_char(&self, state: usize, after: char) -> Option<char> {
+        let classes = self.alphabet.len() + 1;
+        let targets = &self.transitions[state * classes..(state + 1) * classes];
+        let in_alphabet = self
+            .alphabet
+            .iter()
+            .zip(targets)
+            .find(|&(&c, &target)| c > after && target != DEAD_STATE)
+            .map(|(&c, _)| c);
+        let mut other = None;
+        if targets[self.alphabet.len()] != DEAD_STATE {
+            other = next_char(after);
+            while let Some(c) = other {
+                if self.alphabet.binary_search(&c).is_err() {
+                    break;
+                }
+                other = next_char(c);
+            }
+        }
+        match (in_alphabet, other) {
+            (Some(a), Some(b)) => Some(min(a, b)),
+            (a, b) => a.or(b),
+        }
+    }
+}
+
+/// The outcome of `LevenshteinDfa::intersect` for a string of a sorted
+/// enumeration.
+#[derive(Debug, PartialEq, Eq)]
+pub enum DfaMatch {
+    /// The string is accepted, with its edit distance to the word.
+    Accepted(u32),
+    /// The string is rejected, the next one may be accepted.
+    Rejected,
+    /// The string is rejected, and so are the ones before this one.
+    SeekTo(String),
+    /// The string is rejected, and so are all the ones after it.
+    Exhausted,
+}
+
+// the code point after `c`, skipping the surrogates
+fn next_char(c: char) -> Option<char> {
+    match c as u32 {
+        0xD7FF => Some('\u{E000}'),
+        0x10FFFF => None,
+        u => char::from_u32(u + 1),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the optimal string alignment distance
+    fn edit_distance(a: &str, b: &str, transpositions: bool) -> u32 {
+        let a: Vec<char> = a.chars().collect();
+        let b: Vec<char> = b.chars().collect();
+        let mut d = vec![vec![0u32; b.len() + 1]; a.len() + 1];
+        for i in 0..=a.len() {
+            for j in 0..=b.len() {
+                d[i][j] = if i == 0 || j == 0 {
+                    (i + j) as u32
+                } else {
+                    let cost = if a[i - 1] == b[j - 1] { 0 } else { 1 };
+                    let mut v = min(
+                        min(d[i - 1][j] + 1, d[i][j - 1] + 1),
+                        d[i - 1][j - 1] + cost,
+                    );
+                    if transpositions
+                        && i > 1
+                        && j > 1
+                        && a[i - 1] == b[j - 2]
+                        && a[i - 2] == b[j - 1]
+                    {
+                        v = min(v, d[i - 2][j - 2] + 1);
+                    }
+                    v
+                };
+            }
+        }
+        d[a.len()][b.len()]
+    }
+
+    #[test]
+    fn test_matches_edit_distance() {
+        let words = [
+            "",
+            "a",
+            "ab",
+            "ba",
+            "abc",
+            "acb",
+            "bca",
+            "abcd",
+            "abdc",
+            "badc",
+            "aabb",
+            "abab",
+            "lucene",
+            "lucnee",
+            "ulcene",
+            "lucen",
+            "lucenes",
+            "luecne",
+            "lcene",
+            "café",
+            "cafe",
+            "caffè",
+            "naïve",
+            "日本語",
+            "日語本",
+        ];
+        for &transpositions in &[true, false] {
+            for word in words.iter() {
+                let automata = LevenshteinAutomata::new(word, transpositions);
+                for n in 0..=MAXIMUM_SUPPORTED_DISTANCE {
+                    let dfa = automata.to_automaton(n).unwrap();
+                    for text in words.iter() {
+                        let distance = edit_distance(text, word, transpositions);
+                        let expected = if distance <= n { Some(distance) } else { None };
+                        assert_eq!(
+                            dfa.run(text),
+                            expected,
+                            "{:?} against {:?}, n={}, transpositions={}",
+                            text,
+                            word,
+                            n,
+                            transpositions
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_distances_over_code_points() {
+        let dfa = LevenshteinAutomata::new("café", true)
+            .to_automaton(1)
+            .unwrap();
+        assert_eq!(dfa.run("cafe"), Some(1));
+        assert_eq!(dfa.run("caf"), Some(1));
+        assert_eq!(dfa.run("cfaé"), Some(1));
+        assert_eq!(dfa.run("cafée"), Some(1));
+        assert_eq!(dfa.run("cafés"), Some(1));
+        assert_eq!(dfa.run("caffe"), None);
+
+        // the states don't depend on the code points outside of the word
+        let dfa = LevenshteinAutomata::new("abc", false)
+            .to_automaton(2)
+            .unwrap();
+        let other = LevenshteinAutomata::new("xyz", false)
+            .to_automaton(2)
+            .unwrap();
+        assert_eq!(dfa.num_states(), other.num_states());
+
+        assert!(LevenshteinAutomata::new("abc", true)
+            .to_automaton(MAXIMUM_SUPPORTED_DISTANCE + 1)
+            .is_err());
+    }
+
+    #[test]
+    fn test_intersect_sorted_strings() {
+        let mut strings: Vec<String> = [
+            "",
+            "a",
+            "abc",
+            "abd",
+            "abdc",
+            "acb",
+            "b",
+            "bab",
+            "bac",
+            "bbc",
+            "bc",
+            "c",
+            "cab",
+            "xabc",
+            "xbc",
+            "yy",
+            "zabc",
+            "ábc",
+            "日本語",
+        ]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+        strings.sort();
+        for word in &["abc", "bc", "日本"] {
+            for n in 0..=MAXIMUM_SUPPORTED_DISTANCE {
+                let dfa = LevenshteinAutomata::new(word, true)
+                    .to_automaton(n)
+                    .unwrap();
+                let expected: Vec<(&str, u32)> = strings
+                    .iter()
+                    .filter_map(|s| dfa.run(s).map(|edits| (s.as_str(), edits)))
+                    .collect();
+
+                // enumerates the strings seeking over the rejected ones
+                let mut accepted = vec![];
+                let mut i = 0;
+                while i < strings.len() {
+                    match dfa.intersect(&strings[i]) {
+                        DfaMatch::Accepted(edits) => {
+                            accepted.push((strings[i].as_str(), edits));
+                            i += 1;
+                        }
+                        DfaMatch::Rejected => i += 1,
+                        DfaMatch::SeekTo(next) => {
+                            assert!(next > strings[i]);
+                            i = match strings.binary_search(&next) {
+                                Ok(i) | Err(i) => i,
+                            };
+                        }
+                        DfaMatch::Exhausted => break,
+                    }
+                }
+                assert_eq!(accepted, expected, "{:?}, n={}", word, n);
+            }
+        }
+    }
+}
@@ -76,14 +76,18 @@ pub mod bit_util;
 pub mod bkd;
 pub mod byte_block_pool;
 pub mod bytes_ref_hash;
+pub mod cache;
 pub mod doc_id_set;
 pub mod external;
 pub mod fst;
 pub mod int_block_pool;
 pub mod ints_ref;
 pub mod io;
+pub mod legacy_numeric_utils;
+pub mod levenshtein;
 pub mod math;
 pub mod offline_sorter;
+pub mod priority_queue;
 pub mod selector;
 pub mod small_float;
 pub mod sorter;
@@ -120,3 +124,28 @@ impl<T> Deref for DerefWrapper<T> {
         &self.0
     }
 }
+
+#[cfg(test)]
+pub mod tests {
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    use std::env;
+
+    /// The environment variable fixing the seed of `seeded_rng`.
+    pub const TEST_SEED_VAR: &str = "RUCENE_TEST_SEED";
+
+    /// Returns a random generator for a randomized test, seeded from
+    /// `RUCENE_TEST_SEED` if it is set and randomly otherwise. The seed is
+    /// printed, so the output of a failed test tells how to reproduce it.
+    pub fn seeded_rng() -> StdRng {
+        let seed = env::var(TEST_SEED_VAR)
+            .ok()
+            .and_then(|seed| seed.parse().ok())
+            .unwrap_or_else(rand::random::<u64>);
+        println!("random test seed: {}={}", TEST_SEED_VAR, seed);
+        let mut bytes = [0u8; 32];
+        bytes[..8].copy_from_slice(&seed.to_le_bytes());
+        StdRng::from_seed(bytes)
+    }
+}
@@ -132,3 +132,75 @@ impl NumericDocValues for MixinMonotonicLongValues {
         LongValues::get64_with_ctx(self, ctx, i64::from(doc_id))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::store::{Directory, IOContext, RAMDirectory};
+    use core::util::packed::DirectMonotonicWriter;
+    use core::util::tests::seeded_rng;
+
+    use rand::Rng;
+
+    fn check(values: &[i64], block_shift: i32) {
+        let dir = RAMDirectory::new();
+        {
+            let mut meta = dir.create_output("meta", &IOContext::Default).unwrap();
+            let mut data = dir.create_output("data", &IOContext::Default).unwrap();
+            let mut writer = DirectMonotonicWriter::get_instance(
+                &mut meta,
+                &mut data,
+                values.len() as i64,
+                block_shift,
+            )
+            .unwrap();
+            for &v in values {
+                writer.add(v).unwrap();
+            }
+            writer.finish().unwrap();
+        }
+
+        let mut meta_in = dir.open_input("meta", &IOContext::Default).unwrap();
+        let meta =
+            DirectMonotonicReader::load_meta(meta_in.as_mut(), values.len() as i64, block_shift)
+                .unwrap();
+        let data = dir.open_input("data", &IOContext::Default).unwrap();
+        let slice = data.random_access_slice(0, data.len() as i64).unwrap();
+        let reader = DirectMonotonicReader::get_instance(&meta, &Arc::from(slice)).unwrap();
+        for (i, &v) in values.iter().enumerate() {
+            assert_eq!(reader.get64(i as i64).unwrap(), v, "index {}", i);
+        }
+    }
+
+    #[test]
+    fn test_monotonic() {
+        let mut rng = seeded_rng();
+        let mut values = Vec::with_capacity(10_000);
+        let mut value = i64::from(rng.gen::<u32>());
+        for i in 0..10_000 {
+            // regular runs, flat runs and jumps
+            value += match (i / 700) % 3 {
+                0 => 3,
+                1 => 0,
+                _ => i64::from(rng.gen::<u16>()),
+            };
+            values.push(value);
+        }
+        for &block_shift in &[3, 8, 16] {
+            check(&values, block_shift);
+            check(&values[..1], block_shift);
+            check(&values[..100], block_shift);
+        }
+    }
+
+    #[test]
+    fn test_out_of_order() {
+        let dir = RAMDirectory::new();
+        let mut meta = dir.create_output("meta", &IOContext::Default).unwrap();
+        let mut data = dir.create_output("data", &IOContext::Default).unwrap();
+        let mut writer = DirectMonotonicWriter::get_instance(&mut meta, &mut data, 2, 4).unwrap();
+        writer.add(10).unwrap();
+        assert!(writer.add(9).is_err());
+        assert!(DirectMonotonicWriter::get_instance(&mut meta, &mut data, 2, 2).is_err());
+    }
+}
@@ -815,3 +815,59 @@ impl NumericDocValues for DirectPackedReader64 {
         LongValues::get64_with_ctx(self, ctx, i64::from(doc_id))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::store::{Directory, IOContext, RAMDirectory};
+    use core::util::packed::packed_misc::{get_reader_no_header, Format, Reader, VERSION_CURRENT};
+    use core::util::packed::{DirectWriter, SUPPORTED_BITS_PER_VALUE};
+    use core::util::tests::seeded_rng;
+
+    use rand::Rng;
+
+    #[test]
+    fn test_direct_reader_equals_heap_reader() {
+        let mut rng = seeded_rng();
+        let dir = RAMDirectory::new();
+        for &bits_per_value in SUPPORTED_BITS_PER_VALUE {
+            let count = rng.gen_range(1, 1001);
+            let values: Vec<i64> = (0..count)
+                .map(|_| (rng.gen::<u64>() >> (64 - bits_per_value)) as i64)
+                .collect();
+            let name = format!("packed_{}", bits_per_value);
+            {
+                let mut out = dir.create_output(&name, &IOContext::Default).unwrap();
+                let mut writer =
+                    DirectWriter::get_instance(&mut out, count as i64, bits_per_value).unwrap();
+                for &v in &values {
+                    writer.add(v).unwrap();
+                }
+                writer.finish().unwrap();
+            }
+
+            let mut input = dir.open_input(&name, &IOContext::Default).unwrap();
+            let slice = input.random_access_slice(0, input.len() as i64).unwrap();
+            let direct = DirectReader::get_instance(Arc::from(slice), bits_per_value, 0).unwrap();
+            let heap = get_reader_no_header(
+                input.as_mut(),
+                Format::Packed,
+                VERSION_CURRENT,
+                count,
+                bits_per_value,
+            )
+            .unwrap();
+            for (i, &v) in values.iter().enumerate() {
+                assert_eq!(direct.get64(i as i64).unwrap(), v, "bpv {}", bits_per_value);
+                assert_eq!(heap.get(i), v, "bpv {}", bits_per_value);
+            }
+        }
+    }
+
+    #[test]
+    fn test_unsupported_bits_per_value() {
+        let dir = RAMDirectory::new();
+        let mut out = dir.create_output("packed", &IOContext::Default).unwrap();
+        assert!(DirectWriter::get_instance(&mut out, 10, 3).is_err());
+    }
+}
@@ -73,6 +73,11 @@ impl PackedLongValues {
 
     pub fn ram_bytes_used_estimate(&self) -> usize {
         let mut size = self.values.capacity() * 16; // fat pointer
+        size += self
+            .values
+            .iter()
+            .map(|v| v.ram_bytes_used_estimate())
+            .sum::<usize>();
         match self.builder_type {
             PackedLongValuesBuilderType::Monotonic => {
                 size += self.mins.capacity() * 8;
@@ -416,6 +421,11 @@ impl PackedLongValuesBuilder {
 
     pub fn ram_bytes_used_estimate(&self) -> usize {
         let mut size = self.values.capacity() * 16; // fat pointer
+        size += self
+            .values
+            .iter()
+            .map(|v| v.ram_bytes_used_estimate())
+            .sum::<usize>();
         match self.builder_type {
             PackedLongValuesBuilderType::Monotonic => {
                 size += self.mins.capacity() * 8;
@@ -426,6 +436,52 @@ impl PackedLongValuesBuilder {
             }
             PackedLongValuesBuilderType::Default => {}
         }
-        size
+        size + self.pending.capacity() * 8
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::util::packed::packed_misc::COMPACT;
+
+    // small deltas above a large base with rare large outliers
+    fn value(index: usize) -> i64 {
+        if index % 999_983 == 0 {
+            1 << 40
+        } else {
+            1_000_000 + (index % 5) as i64
+        }
+    }
+
+    #[test]
+    fn test_delta_values() {
+        let size = 10_000_000;
+        let mut builder = PackedLongValuesBuilder::new(
+            DEFAULT_PAGE_SIZE,
+            COMPACT,
+            PackedLongValuesBuilderType::Delta,
+        );
+        for i in 0..size {
+            builder.add(value(i));
+        }
+        assert_eq!(builder.size(), size as i64);
+        assert!(builder.ram_bytes_used_estimate() < size);
+
+        let values = builder.build();
+        assert_eq!(values.size(), size as i64);
+        let mut count = 0;
+        for (i, v) in values.iterator().enumerate() {
+            assert_eq!(v, value(i));
+            count += 1;
+        }
+        assert_eq!(count, size);
+        for i in (0..size).step_by(997) {
+            assert_eq!(values.get64(i as i64).unwrap(), value(i));
+        }
+        // 3 bits per value except for the pages with an outlier
+        let estimate = values.ram_bytes_used_estimate();
+        assert!(estimate > size * 3 / 8);
+        assert!(estimate < size * 3 / 8 + size / 8);
     }
 }
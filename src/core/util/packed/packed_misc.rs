@@ -734,6 +734,18 @@ pub trait Mutable: Reader {
     fn get_format(&self) -> Format {
         Format::Packed
     }
+
+    /// Returns an estimate of the bytes used by the values, the blocks they
+    /// are packed in being rounded up to whole longs.
+    fn ram_bytes_used_estimate(&self) -> usize {
+        let bits_per_value = self.get_bits_per_value();
+        if bits_per_value == 0 {
+            return 0;
+        }
+        self.get_format()
+            .long_count(VERSION_CURRENT, self.size() as i32, bits_per_value) as usize
+            * 8
+    }
 }
 
 pub struct PackedIntsNullMutable {
@@ -3232,3 +3244,102 @@ impl BlockPackedReaderIterator {
         Ok(i)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::store::ByteArrayDataInput;
+    use core::util::tests::seeded_rng;
+
+    use rand::Rng;
+
+    fn random_values(rng: &mut impl Rng, count: usize, bits_per_value: i32) -> Vec<i64> {
+        (0..count)
+            .map(|_| (rng.gen::<u64>() >> (64 - bits_per_value)) as i64)
+            .collect()
+    }
+
+    fn write(format: Format, bits_per_value: i32, values: &[i64]) -> Vec<u8> {
+        let mut bytes: Vec<u8> = Vec::new();
+        let mut writer =
+            get_writer_no_header(format, values.len(), bits_per_value, DEFAULT_BUFFER_SIZE);
+        for &v in values {
+            writer.add(v, &mut bytes).unwrap();
+        }
+        writer.finish(&mut bytes).unwrap();
+        bytes
+    }
+
+    fn check_reader(rng: &mut impl Rng, reader: &impl Reader, values: &[i64]) {
+        assert_eq!(reader.size(), values.len());
+        for (i, &v) in values.iter().enumerate() {
+            assert_eq!(reader.get(i), v, "index {}", i);
+        }
+
+        let mut buffer = vec![0i64; 100];
+        for _ in 0..100 {
+            let start = rng.gen_range(0, values.len());
+            let len = rng.gen_range(1, buffer.len() + 1);
+            let end = min(start + len, values.len());
+            let mut index = start;
+            while index < end {
+                let read = reader.bulk_get(index, &mut buffer, end - index);
+                assert!(read > 0 && read <= end - index);
+                assert_eq!(&buffer[..read], &values[index..index + read]);
+                index += read;
+            }
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut rng = seeded_rng();
+        for bits_per_value in 1..=64 {
+            for &format in &[Format::Packed, Format::PackedSingleBlock] {
+                if !format.is_supported(bits_per_value) {
+                    continue;
+                }
+                let count = rng.gen_range(1, 1001);
+                let values = random_values(&mut rng, count, bits_per_value);
+                let bytes = write(format, bits_per_value, &values);
+                assert_eq!(
+                    bytes.len() as i64,
+                    format.byte_count(VERSION_CURRENT, count as i32, bits_per_value)
+                );
+
+                let mut input = ByteArrayDataInput::new(&bytes);
+                let reader = get_reader_no_header(
+                    &mut input,
+                    format,
+                    VERSION_CURRENT,
+                    count,
+                    bits_per_value,
+                )
+                .unwrap();
+                check_reader(&mut rng, &reader, &values);
+
+                let mut mutable = get_mutable_by_format(count, bits_per_value, format);
+                for (i, &v) in values.iter().enumerate() {
+                    mutable.set(i, v);
+                }
+                check_reader(&mut rng, &mutable, &values);
+            }
+        }
+    }
+
+    #[test]
+    fn test_round_trip_with_header() {
+        let mut rng = seeded_rng();
+        let values = random_values(&mut rng, 500, 13);
+        let mut bytes: Vec<u8> = Vec::new();
+        let mut writer = get_writer_no_header(Format::Packed, values.len(), 13, 100);
+        writer.write_header(&mut bytes).unwrap();
+        for &v in &values {
+            writer.add(v, &mut bytes).unwrap();
+        }
+        writer.finish(&mut bytes).unwrap();
+
+        let reader = get_reader(&mut ByteArrayDataInput::new(&bytes)).unwrap();
+        check_reader(&mut rng, &reader, &values);
+    }
+}
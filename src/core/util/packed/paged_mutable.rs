@@ -66,6 +66,15 @@ impl PagedMutableBase {
     fn page_index(&self, index: usize) -> usize {
         index >> self.page_shift
     }
+
+    /// Returns an estimate of the bytes used by the values of all the pages.
+    pub fn ram_bytes_used_estimate(&self) -> usize {
+        self.sub_mutables
+            .iter()
+            .map(|m| m.ram_bytes_used_estimate())
+            .sum::<usize>()
+            + self.sub_mutables.capacity() * ::std::mem::size_of::<PagedMutableEnum>()
+    }
 }
 
 pub trait PagedMutableWriter: LongValues + Sized {
@@ -107,13 +116,12 @@ pub trait PagedMutableWriter: LongValues + Sized {
     /// instance and copying values one by one.
     fn resize(&self, new_size: usize) -> Self {
         let mut copy = self.new_unfilled_copy(new_size);
-        let num_common_pages = min(
-            copy.paged_mutable_base().sub_mutables.capacity(),
-            self.paged_mutable_base().sub_mutables.len(),
-        );
+        // the capacity of the pages of the copy may exceed its page count
+        let num_pages = num_blocks(new_size, self.paged_mutable_base().page_size());
+        let num_common_pages = min(num_pages, self.paged_mutable_base().sub_mutables.len());
         let mut copy_buffer = [0i64; 1024];
-        for i in 0..copy.paged_mutable_base().sub_mutables.capacity() {
-            let value_count = if i == copy.paged_mutable_base().sub_mutables.capacity() - 1 {
+        for i in 0..num_pages {
+            let value_count = if i == num_pages - 1 {
                 self.paged_mutable_base().last_page_size(new_size)
             } else {
                 self.paged_mutable_base().page_size()
@@ -287,6 +295,10 @@ impl PagedMutableHugeWriter {
     pub fn size(&self) -> usize {
         self.base.size
     }
+
+    pub fn ram_bytes_used_estimate(&self) -> usize {
+        self.base.ram_bytes_used_estimate()
+    }
 }
 
 impl PagedMutableWriter for PagedMutableHugeWriter {
@@ -391,6 +403,10 @@ impl PagedGrowableWriter {
     pub fn size(&self) -> usize {
         self.base.size
     }
+
+    pub fn ram_bytes_used_estimate(&self) -> usize {
+        self.base.ram_bytes_used_estimate()
+    }
 }
 
 impl PagedMutableWriter for PagedGrowableWriter {
@@ -446,3 +462,95 @@ impl NumericDocValues for PagedGrowableWriter {
         Ok((self.base.sub_mutables[page_index].get(index_in_page), None))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::util::packed::packed_misc::COMPACT;
+
+    const SIZE: usize = 10_000_000;
+
+    // small values with rare large outliers
+    fn value(index: usize) -> i64 {
+        if index % 1_000_003 == 0 {
+            1 << 40
+        } else {
+            (index % 7) as i64
+        }
+    }
+
+    #[test]
+    fn test_paged_growable_writer() {
+        let page_size = 1 << 16;
+        let mut writer = PagedGrowableWriter::new(SIZE, page_size, 1, COMPACT);
+        for i in 0..SIZE {
+            writer.set(i, value(i));
+        }
+        for i in 0..SIZE {
+            assert_eq!(writer.get64(i as i64).unwrap(), value(i));
+        }
+
+        // only the pages holding an outlier need 41 bits per value
+        let outlier_pages = (0..SIZE)
+            .step_by(1_000_003)
+            .map(|i| i / page_size)
+            .collect::<Vec<_>>();
+        let mut optimum = 0;
+        for (page, m) in writer.base.sub_mutables.iter().enumerate() {
+            let bits_per_value = if outlier_pages.contains(&page) { 41 } else { 3 };
+            assert_eq!(m.get_bits_per_value(), bits_per_value);
+            optimum += (m.size() * bits_per_value as usize + 63) / 64 * 8;
+        }
+        let estimate = writer.ram_bytes_used_estimate();
+        assert!(estimate >= optimum);
+        assert!(estimate < optimum + 64 * 1024);
+        assert!(estimate < SIZE);
+
+        let grown = writer.grow();
+        assert!(grown.size() > SIZE);
+        for i in 0..SIZE {
+            assert_eq!(grown.get64(i as i64).unwrap(), value(i));
+        }
+        for i in SIZE..grown.size() {
+            assert_eq!(grown.get64(i as i64).unwrap(), 0);
+        }
+
+        let shrunk = grown.resize(SIZE / 3);
+        assert_eq!(
+            shrunk.base.sub_mutables.len(),
+            num_blocks(SIZE / 3, page_size)
+        );
+        for i in 0..SIZE / 3 {
+            assert_eq!(shrunk.get64(i as i64).unwrap(), value(i));
+        }
+    }
+
+    #[test]
+    fn test_paged_mutable_resize() {
+        let page_size = 1 << 10;
+        let size = 100_000;
+        let mut writer = PagedMutableHugeWriter::new(size, page_size, 41, COMPACT);
+        for i in 0..size {
+            writer.set(i, value(i * 13));
+        }
+        for &new_size in &[
+            1,
+            page_size - 1,
+            page_size,
+            page_size + 1,
+            size,
+            size * 2 + 7,
+        ] {
+            let copy = writer.resize(new_size);
+            assert_eq!(copy.size(), new_size);
+            assert_eq!(
+                copy.base.sub_mutables.len(),
+                num_blocks(new_size, page_size)
+            );
+            for i in 0..new_size {
+                let expected = if i < size { value(i * 13) } else { 0 };
+                assert_eq!(copy.get64(i as i64).unwrap(), expected);
+            }
+        }
+    }
+}
@@ -0,0 +1,337 @@
+// Copyright 2019 Zhizhesihai (Beijing) Technology Limited.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::ops::DerefMut;
+
+/// A priority queue of at most `max_size` elements, the least element
+/// according to `less_than` being at the top. Unlike `BinaryHeap` it can
+/// replace the top with a new element once full, and let the top be modified
+/// in place and moved down with `update_top`.
+///
+/// The queue may be pre-filled with sentinel elements that are less than any
+/// actual element, so that the top is always there and an element simply
+/// replaces it if it compares greater, without checking the size first.
+///
+/// The elements are stored in `H`, a plain `Vec` unless the queue is created
+/// with `with_heap` to reuse a buffer.
+pub struct PriorityQueue<T, F: Fn(&T, &T) -> bool, H: DerefMut<Target = Vec<T>> = Vec<T>> {
+    heap: H,
+    max_size: usize,
+    less_than: F,
+}
+
+impl<T, F: Fn(&T, &T) -> bool> PriorityQueue<T, F> {
+    pub fn new(max_size: usize, less_than: F) -> PriorityQueue<T, F> {
+        PriorityQueue::with_heap(Vec::with_capacity(max_size), max_size, less_than)
+    }
+
+    /// Creates a full queue of `max_size` sentinels created by `sentinel`,
+    /// which must be less than or equal to the elements added later.
+    pub fn with_sentinels<S: FnMut() -> T>(
+        max_size: usize,
+        less_than: F,
+        mut sentinel: S,
+    ) -> PriorityQueue<T, F> {
+        let mut queue = PriorityQueue::new(max_size, less_than);
+        for _ in 0..max_size {
+            queue.heap.push(sentinel());
+        }
+        // the sentinels are all equal, no need to heapify them
+        queue
+    }
+}
+
+impl<T, F: Fn(&T, &T) -> bool, H: DerefMut<Target = Vec<T>>> PriorityQueue<T, F, H> {
+    /// Creates a queue storing its elements in the empty buffer `heap`.
+    pub fn with_heap(heap: H, max_size: usize, less_than: F) -> PriorityQueue<T, F, H> {
+        debug_assert!(heap.is_empty());
+        PriorityQueue {
+            heap,
+            max_size,
+            less_than,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.heap.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.heap.is_empty()
+    }
+
+    pub fn max_size(&self) -> usize {
+        self.max_size
+    }
+
+    /// Adds an element to the queue, which must not be full.
+    pub fn add(&mut self, element: T) -> &mut T {
+        assert!(self.heap.len() < self.max_size, "the queue is full");
+        self.heap.push(element);
+        let last = self.heap.len() - 1;
+        self.up_heap(last);
+        &mut self.heap[0]
+    }
+
+    /// Adds an element to the queue if it isn't full, else replaces the top
+    /// if the element isn't less than it. Returns the element that doesn't
+    /// fit in the queue, either the previous top or `element` itself.
+    pub fn insert_with_overflow(&mut self, element: T) -> Option<T> {
+        if self.heap.len() < self.max_size {
+            self.add(element);
+            None
+        } else if !self.heap.is_empty() && !(self.less_than)(&element, &self.heap[0]) {
+            Some(self.replace_top(element))
+        } else {
+            Some(element)
+        }
+    }
+
+    /// Returns the least element, the queue must not be empty.
+    pub fn top(&self) -> &T {
+        &self.heap[0]
+    }
+
+    /// Returns the least element to modify it in place, `update_top` must be
+    /// called afterwards to restore the order of the queue.
+    pub fn top_mut(&mut self) -> &mut T {
+        &mut self.heap[0]
+    }
+
+    /// Returns the least element, or `None` if the queue is empty.
+    pub fn peek(&self) -> Option<&T> {
+        self.heap.first()
+    }
+
+    /// Removes and returns the least element.
+    pub fn pop(&mut self) -> Option<T> {
+        if self.heap.is_empty() {
+            return None;
+        }
+        let result = self.heap.swap_remove(0);
+        if !self.heap.is_empty() {
+            self.down_heap();
+        }
+        Some(result)
+    }
+
+    /// Moves the top down to its place after it was modified in place, and
+    /// returns the new top. This is much faster than a pop followed by an add.
+    pub fn update_top(&mut self) -> &mut T {
+        self.down_heap();
+        &mut self.heap[0]
+    }
+
+    /// Replaces the top with `element`, returning the previous top.
+    pub fn replace_top(&mut self, mut element: T) -> T {
+        ::std::mem::swap(&mut self.heap[0], &mut element);
+        self.down_heap();
+        element
+    }
+
+    pub fn clear(&mut self) {
+        self.heap.clear();
+    }
+
+    /// Iterates over the elements in no particular order.
+    pub fn iter(&self) -> ::std::slice::Iter<T> {
+        self.heap.iter()
+    }
+
+    /// Returns the elements in heap order: the children of the element at
+    /// `i` are at `2 * i + 1` and `2 * i + 2`, and are not less than it.
+    pub fn as_slice(&self) -> &[T] {
+        &self.heap
+    }
+
+    /// Returns the elements from the greatest to the least, emptying the queue.
+    pub fn drain_sorted(&mut self) -> Vec<T> {
+        let mut sorted = Vec::with_capacity(self.heap.len());
+        while let Some(element) = self.pop() {
+            sorted.push(element);
+        }
+        sorted.reverse();
+        sorted
+    }
+
+    fn up_heap(&mut self, mut i: usize) {
+        while i > 0 {
+            let parent = (i - 1) >> 1;
+            if !(self.less_than)(&self.heap[i], &self.heap[parent]) {
+                break;
+            }
+            self.heap.swap(i, parent);
+            i = parent;
+        }
+    }
+
+    fn down_heap(&mut self) {
+        let size = self.heap.len();
+        let mut i = 0;
+        loop {
+            let mut child = (i << 1) + 1;
+            if child >= size {
+                break;
+            }
+            if child + 1 < size && (self.less_than)(&self.heap[child + 1], &self.heap[child]) {
+                child += 1;
+            }
+            if !(self.less_than)(&self.heap[child], &self.heap[i]) {
+                break;
+            }
+            self.heap.swap(i, child);
+            i = child;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::util::tests::seeded_rng;
+
+    use rand::Rng;
+
+    fn less_than(a: &i32, b: &i32) -> bool {
+        a < b
+    }
+
+    #[test]
+    fn test_pop_in_order() {
+        let mut rng = seeded_rng();
+        let mut values: Vec<i32> = (0..1000).map(|_| rng.gen_range(-100, 100)).collect();
+        let mut queue = PriorityQueue::new(values.len(), less_than);
+        for &v in &values {
+            queue.add(v);
+        }
+        assert_eq!(queue.len(), values.len());
+        values.sort();
+        for &v in &values {
+            assert_eq!(*queue.top(), v);
+            assert_eq!(queue.pop(), Some(v));
+        }
+        assert!(queue.is_empty());
+        assert_eq!(queue.pop(), None);
+        assert_eq!(queue.peek(), None);
+    }
+
+    #[test]
+    fn test_insert_with_overflow() {
+        let mut rng = seeded_rng();
+        let values: Vec<i32> = (0..1000).map(|_| rng.gen_range(0, 50)).collect();
+        let mut queue = PriorityQueue::new(10, less_than);
+        let mut overflown = Vec::new();
+        for &v in &values {
+            if let Some(o) = queue.insert_with_overflow(v) {
+                // the overflow is never greater than what is kept
+                assert!(queue.iter().all(|&kept| o <= kept));
+                overflown.push(o);
+            }
+        }
+        assert_eq!(queue.len(), 10);
+        assert_eq!(overflown.len(), values.len() - 10);
+
+        let mut sorted = values.clone();
+        sorted.sort_by(|a, b| b.cmp(a));
+        assert_eq!(queue.drain_sorted(), sorted[..10].to_vec());
+
+        // equal elements replace the top, less ones are rejected
+        let mut queue = PriorityQueue::new(2, less_than);
+        assert_eq!(queue.insert_with_overflow(5), None);
+        assert_eq!(queue.insert_with_overflow(7), None);
+        assert_eq!(queue.insert_with_overflow(4), Some(4));
+        assert_eq!(queue.insert_with_overflow(5), Some(5));
+        assert_eq!(*queue.top(), 5);
+        assert_eq!(queue.insert_with_overflow(6), Some(5));
+        assert_eq!(*queue.top(), 6);
+
+        // nothing fits in an empty queue
+        let mut queue = PriorityQueue::new(0, less_than);
+        assert_eq!(queue.insert_with_overflow(1), Some(1));
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn test_update_top() {
+        let mut queue = PriorityQueue::new(5, less_than);
+        for v in &[3, 1, 4, 1, 5] {
+            queue.add(*v);
+        }
+        *queue.top_mut() = 10;
+        assert_eq!(*queue.update_top(), 1);
+        *queue.top_mut() = 2;
+        assert_eq!(*queue.update_top(), 2);
+        assert_eq!(queue.replace_top(9), 2);
+        assert_eq!(queue.drain_sorted(), vec![10, 9, 5, 4, 3]);
+    }
+
+    #[test]
+    fn test_sentinels() {
+        #[derive(Debug, PartialEq)]
+        struct Hit {
+            doc: i32,
+            score: f32,
+        }
+
+        // the worst hits at the top, the hits with equal scores by doc id
+        fn worse(a: &Hit, b: &Hit) -> bool {
+            if a.score == b.score {
+                a.doc > b.doc
+            } else {
+                a.score < b.score
+            }
+        }
+
+        let mut queue = PriorityQueue::with_sentinels(3, worse, || Hit {
+            doc: i32::max_value(),
+            score: ::std::f32::NEG_INFINITY,
+        });
+        assert_eq!(queue.len(), 3);
+
+        // the hot path replaces the top without checking the size
+        let scores = [1.0, 3.0, 2.0, 3.0, 0.5, 2.5];
+        for (doc, &score) in scores.iter().enumerate() {
+            if score <= queue.top().score {
+                continue;
+            }
+            {
+                let top = queue.top_mut();
+                top.doc = doc as i32;
+                top.score = score;
+            }
+            queue.update_top();
+        }
+
+        let hits = queue.drain_sorted();
+        assert_eq!(
+            hits,
+            vec![
+                Hit { doc: 1, score: 3.0 },
+                Hit { doc: 3, score: 3.0 },
+                Hit { doc: 5, score: 2.5 },
+            ]
+        );
+
+        // sentinels are left when less hits than the size are collected
+        let mut queue = PriorityQueue::with_sentinels(3, worse, || Hit {
+            doc: i32::max_value(),
+            score: ::std::f32::NEG_INFINITY,
+        });
+        queue.top_mut().doc = 0;
+        queue.top_mut().score = 1.0;
+        queue.update_top();
+        let hits = queue.drain_sorted();
+        assert_eq!(hits[0], Hit { doc: 0, score: 1.0 });
+        assert!(hits[1..].iter().all(|h| h.doc == i32::max_value()));
+    }
+}
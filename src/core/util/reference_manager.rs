@@ -13,7 +13,7 @@
 
 use std::{
     mem,
-    sync::{Arc, Mutex, MutexGuard},
+    sync::{Arc, Mutex, MutexGuard, RwLock},
 };
 
 use error::{
@@ -24,7 +24,8 @@ use error::{
 pub struct ReferenceManagerBase<T: ?Sized> {
     lock: Mutex<()>,
     refresh_lock: Mutex<()>,
-    current: Option<Arc<T>>,
+    // swapped under `lock`, read by `acquire` concurrently
+    current: RwLock<Option<Arc<T>>>,
 }
 
 impl<T: ?Sized> ReferenceManagerBase<T> {
@@ -32,11 +33,12 @@ impl<T: ?Sized> ReferenceManagerBase<T> {
         Self {
             lock: Mutex::new(()),
             refresh_lock: Mutex::new(()),
-            current: Some(current),
+            current: RwLock::new(Some(current)),
         }
     }
+
     fn ensure_open(&self) -> Result<()> {
-        if self.current.is_none() {
+        if self.current.read()?.is_none() {
             bail!(AlreadyClosed("this ReferenceManager is closed".into()));
         }
         Ok(())
@@ -54,14 +56,11 @@ pub trait ReferenceManager<T: ?Sized, RL: RefreshListener> {
     fn refresh_listener(&self) -> Option<&RL>;
 
     fn _swap_reference(&self, new_reference: Option<Arc<T>>, _l: &MutexGuard<()>) -> Result<()> {
-        // let _l = base.lock.lock().unwrap();
-        // we use base.lock to make sure this operation is safe
-        let base_ptr =
-            self.base() as *const ReferenceManagerBase<T> as *mut ReferenceManagerBase<T>;
-        unsafe {
-            if let Some(old_ref) = mem::replace(&mut (*base_ptr).current, new_reference) {
-                self.release(old_ref.as_ref())?;
-            }
+        let old_ref = mem::replace(&mut *self.base().current.write()?, new_reference);
+        // the old reference is dropped once the threads that acquired it
+        // released it
+        if let Some(old_ref) = old_ref {
+            self.release(old_ref.as_ref())?;
         }
         Ok(())
     }
@@ -80,19 +79,25 @@ pub trait ReferenceManager<T: ?Sized, RL: RefreshListener> {
     /// Obtain the current reference. You must match every call to acquire with one
     /// call to `release`; it's best to do so in a finally clause, and set the
     /// reference to None to prevent accidental usage after it has been released.
+    ///
+    /// Fails with `AlreadyClosed` once the manager is closed, a closed reference
+    /// is never returned.
     fn acquire(&self) -> Result<Arc<T>> {
         loop {
-            if let Some(ref cur) = self.base().current {
+            let current = self.base().current.read()?;
+            if let Some(ref cur) = *current {
                 if self.try_inc_ref(cur)? {
                     return Ok(Arc::clone(cur));
                 }
-            // TODO double check for ref count
+                if self.ref_count(cur) == 0 {
+                    bail!(IllegalState(
+                        "the current reference has been released - this is likely a bug when the \
+                         reference count is modified outside of the ReferenceManager"
+                            .into()
+                    ));
+                }
             } else {
-                bail!(IllegalState(
-                    "this ReferenceManager is closed - this is likely a bug when the reference \
-                     count is modified outside of the ReferenceManager"
-                        .into()
-                ));
+                bail!(AlreadyClosed("this ReferenceManager is closed".into()));
             }
         }
     }
@@ -111,8 +116,8 @@ pub trait ReferenceManager<T: ?Sized, RL: RefreshListener> {
     /// resource on a closed {@link ReferenceManager} will throw an
     /// {@link AlreadyClosedException}.
     fn close(&self) -> Result<()> {
-        let _l = self.base().lock.lock().unwrap();
-        if self.base().current.is_some() {
+        let _l = self.base().lock.lock()?;
+        if self.base().current.read()?.is_some() {
             // make sure we can call this more than once
             // closeable javadoc says:
             // if this is already closed then invoking this method has no effect.
@@ -166,8 +171,13 @@ pub trait ReferenceManager<T: ?Sized, RL: RefreshListener> {
         Ok(())
     }
 
-    fn _do_swap(&self, reference: Arc<T>, l: &MutexGuard<()>) -> Result<()> {
-        let res = self._swap_reference(Some(Arc::clone(&reference)), l);
+    fn _do_swap(&self, reference: Arc<T>, _refresh_lock: &MutexGuard<()>) -> Result<()> {
+        // the manager may have been closed while refreshing, it must stay so
+        let l = self.base().lock.lock()?;
+        let res = self
+            .base()
+            .ensure_open()
+            .and_then(|_| self._swap_reference(Some(Arc::clone(&reference)), &l));
         if res.is_err() {
             let _ = self.release(reference.as_ref());
         }
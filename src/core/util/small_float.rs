@@ -11,8 +11,17 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+/// Floating point numbers and lengths encoded in a single byte, to store
+/// the norms of the fields.
 pub struct SmallFloat;
+
 impl SmallFloat {
+    /// `long_to_int4(i32::max_value())`, the largest int4 an `i32` encodes to.
+    pub const MAX_INT4: i32 = 231;
+
+    /// The number of small values `int_to_byte4` encodes as they are.
+    pub const NUM_FREE_VALUES: i32 = 255 - Self::MAX_INT4;
+
     pub fn float_to_byte315(f: f32) -> u8 {
         let bits = f.to_bits() as i32;
         let small_float = (bits >> (24 - 3)) as i32;
@@ -34,13 +43,72 @@ impl SmallFloat {
             f32::from_bits(bits)
         }
     }
+
+    /// Encodes a non-negative long into an int keeping its 4 most significant
+    /// bits, like a float with 3 bits of mantissa and an implicit leading bit.
+    /// The values under 8 are encoded exactly, the order of the values is
+    /// preserved and the encoding of `i64::max_value()` fits in a byte.
+    pub fn long_to_int4(i: i64) -> i32 {
+        debug_assert!(i >= 0, "only supports positive values, got {}", i);
+        let num_bits = 64 - i.leading_zeros() as i32;
+        if num_bits < 4 {
+            // subnormal value
+            i as i32
+        } else {
+            // normal value
+            let shift = num_bits - 4;
+            // only keep the 4 most significant bits, the first being implicit
+            let mut encoded = ((i as u64) >> shift) as i32 & 0x07;
+            // encode the shift, adding 1 because 0 is reserved for subnormal values
+            encoded |= (shift + 1) << 3;
+            encoded
+        }
+    }
+
+    /// Decodes an int encoded with `long_to_int4`.
+    pub fn int4_to_long(i: i32) -> i64 {
+        let bits = i64::from(i & 0x07);
+        let shift = (i >> 3) - 1;
+        if shift == -1 {
+            // subnormal value
+            bits
+        } else {
+            // normal value
+            (bits | 0x08) << shift
+        }
+    }
+
+    /// Encodes a non-negative int, e.g. the length of a field, into a byte.
+    /// The values under `NUM_FREE_VALUES` are encoded exactly, the bigger
+    /// ones with `long_to_int4`, so that the order is preserved and the
+    /// relative error of the decoded values is at most 1/8.
+    pub fn int_to_byte4(i: i32) -> u8 {
+        debug_assert!(i >= 0, "only supports positive values, got {}", i);
+        if i < Self::NUM_FREE_VALUES {
+            i as u8
+        } else {
+            (Self::NUM_FREE_VALUES + Self::long_to_int4(i64::from(i - Self::NUM_FREE_VALUES))) as u8
+        }
+    }
+
+    /// Decodes a byte encoded with `int_to_byte4`.
+    pub fn byte4_to_int(b: u8) -> i32 {
+        let i = i32::from(b);
+        if i < Self::NUM_FREE_VALUES {
+            i
+        } else {
+            (i64::from(Self::NUM_FREE_VALUES) + Self::int4_to_long(i - Self::NUM_FREE_VALUES))
+                as i32
+        }
+    }
 }
 
 #[cfg(test)]
 pub mod tests {
-    extern crate rand;
-
     use super::*;
+    use core::util::tests::seeded_rng;
+
+    use rand::Rng;
 
     fn origin_byte_to_float(b: u8) -> f32 {
         if b == 0 {
@@ -75,6 +143,7 @@ pub mod tests {
 
     #[test]
     fn test_float_to_byte315() {
+        let mut rng = seeded_rng();
         let min_value = 1.4e-45f32;
         let positive_infinity = 1.0f32 / 0.0f32;
         let negative_infinity = -1.0f32 / 0.0f32;
@@ -94,7 +163,7 @@ pub mod tests {
 
         let num = 100_000;
         for _ in 0..num {
-            let m: u32 = rand::random::<u32>();
+            let m: u32 = rng.gen::<u32>();
             let f = f32::from_bits(m);
             if f.is_nan() {
                 continue;
@@ -114,4 +183,83 @@ pub mod tests {
         }
     }
 
+    // the lengths decoded by Lucene's SmallFloat.byte4ToInt for every byte
+    const BYTE4_TABLE: [i32; 256] = [
+        0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23, 24,
+        25, 26, 27, 28, 29, 30, 31, 32, 33, 34, 35, 36, 37, 38, 39, 40, 42, 44, 46, 48, 50, 52, 54,
+        56, 60, 64, 68, 72, 76, 80, 84, 88, 96, 104, 112, 120, 128, 136, 144, 152, 168, 184, 200,
+        216, 232, 248, 264, 280, 312, 344, 376, 408, 440, 472, 504, 536, 600, 664, 728, 792, 856,
+        920, 984, 1048, 1176, 1304, 1432, 1560, 1688, 1816, 1944, 2072, 2328, 2584, 2840, 3096,
+        3352, 3608, 3864, 4120, 4632, 5144, 5656, 6168, 6680, 7192, 7704, 8216, 9240, 10264, 11288,
+        12312, 13336, 14360, 15384, 16408, 18456, 20504, 22552, 24600, 26648, 28696, 30744, 32792,
+        36888, 40984, 45080, 49176, 53272, 57368, 61464, 65560, 73752, 81944, 90136, 98328, 106520,
+        114712, 122904, 131096, 147480, 163864, 180248, 196632, 213016, 229400, 245784, 262168,
+        294936, 327704, 360472, 393240, 426008, 458776, 491544, 524312, 589848, 655384, 720920,
+        786456, 851992, 917528, 983064, 1048600, 1179672, 1310744, 1441816, 1572888, 1703960,
+        1835032, 1966104, 2097176, 2359320, 2621464, 2883608, 3145752, 3407896, 3670040, 3932184,
+        4194328, 4718616, 5242904, 5767192, 6291480, 6815768, 7340056, 7864344, 8388632, 9437208,
+        10485784, 11534360, 12582936, 13631512, 14680088, 15728664, 16777240, 18874392, 20971544,
+        23068696, 25165848, 27263000, 29360152, 31457304, 33554456, 37748760, 41943064, 46137368,
+        50331672, 54525976, 58720280, 62914584, 67108888, 75497496, 83886104, 92274712, 100663320,
+        109051928, 117440536, 125829144, 134217752, 150994968, 167772184, 184549400, 201326616,
+        218103832, 234881048, 251658264, 268435480, 301989912, 335544344, 369098776, 402653208,
+        436207640, 469762072, 503316504, 536870936, 603979800, 671088664, 738197528, 805306392,
+        872415256, 939524120, 1006632984, 1073741848, 1207959576, 1342177304, 1476395032,
+        1610612760, 1744830488, 1879048216, 2013265944,
+    ];
+
+    #[test]
+    fn test_byte4_reference_values() {
+        assert_eq!(
+            SmallFloat::MAX_INT4,
+            SmallFloat::long_to_int4(i64::from(i32::max_value()))
+        );
+        for (b, &expected) in BYTE4_TABLE.iter().enumerate() {
+            assert_eq!(SmallFloat::byte4_to_int(b as u8), expected);
+            assert_eq!(SmallFloat::int_to_byte4(expected), b as u8);
+        }
+        assert_eq!(SmallFloat::int_to_byte4(i32::max_value()), 255);
+    }
+
+    #[test]
+    fn test_int_to_byte4() {
+        let mut rng = seeded_rng();
+        // encoding is monotonic and decoding rounds down to the code points
+        let mut previous = 0u8;
+        let mut i = 0i32;
+        while i >= 0 {
+            let b = SmallFloat::int_to_byte4(i);
+            assert!(b >= previous);
+            let decoded = SmallFloat::byte4_to_int(b);
+            assert!(decoded <= i);
+            if b < 255 {
+                assert!(SmallFloat::byte4_to_int(b + 1) > i);
+            }
+            if i < SmallFloat::NUM_FREE_VALUES {
+                assert_eq!(decoded, i);
+            }
+            previous = b;
+            i = i
+                .checked_add(1 + i / 97 + rng.gen::<u16>() as i32 % 3)
+                .unwrap_or(-1);
+        }
+    }
+
+    #[test]
+    fn test_long_to_int4() {
+        let mut rng = seeded_rng();
+        for i in 0..=SmallFloat::MAX_INT4 {
+            let decoded = SmallFloat::int4_to_long(i);
+            assert_eq!(SmallFloat::long_to_int4(decoded), i);
+            if i > 0 {
+                assert!(decoded > SmallFloat::int4_to_long(i - 1));
+            }
+        }
+        for _ in 0..10_000 {
+            let l = (rng.gen::<u64>() >> 1) as i64;
+            let decoded = SmallFloat::int4_to_long(SmallFloat::long_to_int4(l));
+            assert!(decoded <= l);
+            assert!(l - decoded <= l / 8);
+        }
+    }
 }
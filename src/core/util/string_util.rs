@@ -80,6 +80,62 @@ pub fn sort_key_length(prior_term: &[u8], current_term: &[u8]) -> usize {
     current_term.len().min(1 + prior_term.len())
 }
 
+/// Decodes the modified UTF-8 of Java, where NUL is encoded in two bytes and
+/// the supplementary characters as two 3-byte surrogates, which `String`
+/// rejects. The 4-byte sequences of UTF-8 are accepted too. Returns `None` if
+/// the bytes are invalid or leave a surrogate unpaired.
+pub fn modified_utf8_to_string(bytes: &[u8]) -> Option<String> {
+    let mut units: Vec<u16> = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let b = bytes[i];
+        if b < 0x80 {
+            units.push(u16::from(b));
+            i += 1;
+        } else if b & 0xe0 == 0xc0 {
+            let c = (u16::from(b & 0x1f) << 6) | continuation_bits(bytes, i + 1)?;
+            // only NUL may have an overlong encoding
+            if c != 0 && c < 0x80 {
+                return None;
+            }
+            units.push(c);
+            i += 2;
+        } else if b & 0xf0 == 0xe0 {
+            let c = (u16::from(b & 0x0f) << 12)
+                | (continuation_bits(bytes, i + 1)? << 6)
+                | continuation_bits(bytes, i + 2)?;
+            if c < 0x800 {
+                return None;
+            }
+            units.push(c);
+            i += 3;
+        } else if b & 0xf8 == 0xf0 {
+            let c = (u32::from(b & 0x07) << 18)
+                | (u32::from(continuation_bits(bytes, i + 1)?) << 12)
+                | (u32::from(continuation_bits(bytes, i + 2)?) << 6)
+                | u32::from(continuation_bits(bytes, i + 3)?);
+            if c < 0x1_0000 || c > 0x10_ffff {
+                return None;
+            }
+            let c = c - 0x1_0000;
+            units.push(0xd800 + (c >> 10) as u16);
+            units.push(0xdc00 + (c & 0x3ff) as u16);
+            i += 4;
+        } else {
+            return None;
+        }
+    }
+    String::from_utf16(&units).ok()
+}
+
+// the low 6 bits of the continuation byte at `i`
+fn continuation_bits(bytes: &[u8], i: usize) -> Option<u16> {
+    match bytes.get(i) {
+        Some(&b) if b & 0xc0 == 0x80 => Some(u16::from(b & 0x3f)),
+        _ => None,
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -90,4 +146,31 @@ mod tests {
         let strv = id2str(&v[..]);
         assert_eq!("4161047F", strv);
     }
+
+    #[test]
+    fn test_modified_utf8_to_string() {
+        assert_eq!(modified_utf8_to_string(b"abc"), Some("abc".to_string()));
+        assert_eq!(
+            modified_utf8_to_string(&[0x61, 0xc0, 0x80, 0x62]),
+            Some("a\u{0}b".to_string())
+        );
+        // U+1F600 as a surrogate pair, then as a 4-byte sequence
+        let pair = [0xed, 0xa0, 0xbd, 0xed, 0xb8, 0x80];
+        assert_eq!(
+            modified_utf8_to_string(&pair),
+            Some("\u{1F600}".to_string())
+        );
+        assert_eq!(
+            modified_utf8_to_string(&[0xf0, 0x9f, 0x98, 0x80, 0xc3, 0xa9]),
+            Some("\u{1F600}\u{e9}".to_string())
+        );
+
+        // unpaired surrogates, overlong encodings and truncated sequences
+        assert_eq!(modified_utf8_to_string(&pair[..3]), None);
+        assert_eq!(modified_utf8_to_string(&pair[3..]), None);
+        assert_eq!(modified_utf8_to_string(&[0xc1, 0x81]), None);
+        assert_eq!(modified_utf8_to_string(&[0xe0, 0x81, 0x81]), None);
+        assert_eq!(modified_utf8_to_string(&[0xe2, 0x82]), None);
+        assert_eq!(modified_utf8_to_string(&[0xff]), None);
+    }
 }
@@ -50,6 +50,16 @@ error_chain! {
             display("Corrupt Index: {}", errmsg)
         }
 
+        IndexFormatTooOld(errmsg: String) {
+            description(errmsg)
+            display("Index format too old: {}", errmsg)
+        }
+
+        IndexFormatTooNew(errmsg: String) {
+            description(errmsg)
+            display("Index format too new: {}", errmsg)
+        }
+
         UnsupportedOperation(errmsg: Cow<'static, str>) {
             description(errmsg),
             display("Unsupported Operation: {}", errmsg)
@@ -60,6 +70,16 @@ error_chain! {
             display("Already Closed: {}", errmsg)
         }
 
+        LockObtainFailed(errmsg: String) {
+            description(errmsg)
+            display("Lock obtain failed: {}", errmsg)
+        }
+
+        Cancelled(errmsg: String) {
+            description(errmsg)
+            display("Cancelled: {}", errmsg)
+        }
+
         IOError(errmsg: String) {
             description(errmsg)
             display("IO Error: {}", errmsg)
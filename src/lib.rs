@@ -41,6 +41,8 @@ extern crate crc;
 extern crate crossbeam;
 extern crate fasthash;
 extern crate flate2;
+extern crate fs2;
+extern crate libc;
 extern crate memmap;
 extern crate num_cpus;
 extern crate num_traits;
@@ -0,0 +1,93 @@
+// Copyright 2019 Zhizhesihai (Beijing) Technology Limited.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Checks that slicing the bytes of an input doesn't allocate. It has its own
+//! test binary, because it replaces the global allocator.
+
+extern crate rucene;
+
+use rucene::core::store::{
+    ByteArrayDataInput, DataInput, DataOutput, Directory, IOContext, RAMDirectory,
+};
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+
+// counts the allocations of the current thread, so that the tests running
+// concurrently don't disturb each other
+struct CountingAllocator;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = Cell::new(0);
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let _ = ALLOCATIONS.try_with(|c| c.set(c.get() + 1));
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let _ = ALLOCATIONS.try_with(|c| c.set(c.get() + 1));
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+fn allocations() -> usize {
+    ALLOCATIONS.with(|c| c.get())
+}
+
+#[test]
+fn test_slice_view_does_not_allocate() {
+    let bytes: Vec<u8> = (0..64).collect();
+    let input = ByteArrayDataInput::new(&bytes[..]);
+
+    let before = allocations();
+    let mut suffixes = input.slice_view(8, 16).unwrap();
+    let mut stats = input.slice_view(24, 8).unwrap();
+    assert_eq!(suffixes.read_byte().unwrap(), 8);
+    assert_eq!(stats.read_byte().unwrap(), 24);
+    suffixes.skip_bytes(4).unwrap();
+    assert_eq!(stats.read_byte().unwrap(), 25);
+    suffixes.rewind();
+    assert_eq!(suffixes.read_byte().unwrap(), 8);
+    let mut nested = stats.slice_view(4, 4).unwrap();
+    assert_eq!(nested.read_byte().unwrap(), 28);
+    assert_eq!(allocations(), before);
+}
+
+#[test]
+fn test_bytes_view_does_not_allocate() {
+    let dir = RAMDirectory::new();
+    {
+        let mut out = dir.create_output("_0.tim", &IOContext::Default).unwrap();
+        let bytes: Vec<u8> = (0..64).collect();
+        out.write_bytes(&bytes, 0, bytes.len()).unwrap();
+    }
+    let input = dir.open_input("_0.tim", &IOContext::READ).unwrap();
+
+    let before = allocations();
+    let view = input.bytes_view(16, 8).unwrap();
+    assert_eq!(view, &[16, 17, 18, 19, 20, 21, 22, 23]);
+    let mut reader = ByteArrayDataInput::new(view);
+    assert_eq!(reader.read_byte().unwrap(), 16);
+    assert!(input.bytes_view(60, 8).is_none());
+    assert_eq!(allocations(), before);
+}